@@ -0,0 +1,135 @@
+//! Tail the index or audit journal of a liveion node, writing its lines to stdout.
+//!
+//! ```sh
+//! cargo run --example journal_tail -- \
+//!     --url http://localhost:7777 --journal audit --token <admin token> --state audit.pos
+//! ```
+//!
+//! The generation and offset reached are kept in `--state`, a restarted tailer goes on
+//! from there. A new generation means the journal was compacted or rotated, it is read
+//! again from offset 0.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use api::recorder::{
+    JOURNAL_DEFAULT_LIMIT_BYTES, JOURNAL_GENERATION_HEADER, JOURNAL_MAX_LIMIT_BYTES,
+    JOURNAL_NEXT_OFFSET_HEADER, JOURNAL_SIZE_HEADER,
+};
+use clap::Parser;
+
+#[derive(Parser)]
+struct Args {
+    /// liveion to tail
+    #[arg(long, default_value = "http://localhost:7777")]
+    url: String,
+    /// `index` or `audit`
+    #[arg(long, default_value = "audit")]
+    journal: String,
+    /// Admin token, when the node has auth configured
+    #[arg(long)]
+    token: Option<String>,
+    /// File keeping the generation and offset reached
+    #[arg(long)]
+    state: Option<PathBuf>,
+    /// Wait between reads once the end of the journal is reached
+    #[arg(long, default_value_t = 1000)]
+    interval_ms: u64,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Position {
+    generation: u64,
+    offset: u64,
+}
+
+impl Position {
+    /// `{generation} {offset}` as written by [`Position::save`]
+    fn load(path: &Path) -> Option<Self> {
+        let state = std::fs::read_to_string(path).ok()?;
+        let (generation, offset) = state.trim().split_once(' ')?;
+        Some(Self {
+            generation: generation.parse().ok()?,
+            offset: offset.parse().ok()?,
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, format!("{} {}\n", self.generation, self.offset))?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+fn header(res: &reqwest::Response, name: &str) -> Result<u64> {
+    res.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .with_context(|| format!("response without {name}"))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let url = format!(
+        "{}{}",
+        args.url.trim_end_matches('/'),
+        api::path::recorder_journal(&args.journal)
+    );
+    let client = reqwest::Client::new();
+    let mut position = args
+        .state
+        .as_deref()
+        .and_then(Position::load)
+        .unwrap_or_default();
+    let mut limit = JOURNAL_DEFAULT_LIMIT_BYTES;
+    let mut stdout = std::io::stdout().lock();
+    loop {
+        let mut req = client
+            .get(&url)
+            .query(&[("offset", position.offset), ("limit_bytes", limit)]);
+        if let Some(token) = &args.token {
+            req = req.bearer_auth(token);
+        }
+        let res = req.send().await?.error_for_status()?;
+        let generation = header(&res, JOURNAL_GENERATION_HEADER)?;
+        let next_offset = header(&res, JOURNAL_NEXT_OFFSET_HEADER)?;
+        let size = header(&res, JOURNAL_SIZE_HEADER)?;
+        let data = res.bytes().await?;
+
+        if generation != position.generation {
+            // What was read from the old offset is part of another file
+            if position.offset != 0 {
+                eprintln!("{} was replaced, reading it again from 0", args.journal);
+                position = Position {
+                    generation,
+                    offset: 0,
+                };
+                continue;
+            }
+            position.generation = generation;
+        }
+        stdout.write_all(&data)?;
+        stdout.flush()?;
+        position.offset = next_offset;
+        if let Some(path) = &args.state {
+            position.save(path)?;
+        }
+
+        if data.is_empty() && next_offset < size {
+            // The next line is longer than the limit
+            if limit >= JOURNAL_MAX_LIMIT_BYTES {
+                bail!("line at offset {next_offset} is longer than {limit} bytes");
+            }
+            limit = (limit * 2).min(JOURNAL_MAX_LIMIT_BYTES);
+            continue;
+        }
+        if next_offset >= size {
+            tokio::time::sleep(Duration::from_millis(args.interval_ms)).await;
+        }
+    }
+}
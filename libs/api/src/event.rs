@@ -13,6 +13,20 @@ pub enum Event {
         r#type: StreamEventType,
         stream: Stream,
     },
+    Recording {
+        r#type: RecordingEventType,
+        recording: crate::recorder::RecordingIndexEntry,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum RecordingEventType {
+    /// Reconciliation found the recording's objects gone from storage
+    RecordingMissing,
+    /// The recording got no media for `recorder.stall_timeout_seconds` while its
+    /// publisher stayed connected
+    RecordingStalled,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -0,0 +1,143 @@
+//! Extractors shared by the HTTP routes of liveion, liveman and livevod
+
+use axum::extract::rejection::PathRejection;
+use axum::extract::{FromRef, FromRequestParts, Path};
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+
+use crate::recorder::{NameRules, RecorderError};
+
+/// [`Path`] whose `stream` and `record` parameters follow the [`NameRules`] of the
+/// router's state. A name breaking one is refused with 400 and the rule in a
+/// [`RecorderError::Validation`] before the handler runs
+#[derive(Debug, Clone)]
+pub struct NamedPath<T>(pub T);
+
+pub enum NamedPathRejection {
+    Path(PathRejection),
+    Name(RecorderError),
+}
+
+impl IntoResponse for NamedPathRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Path(rejection) => rejection.into_response(),
+            Self::Name(err) => (StatusCode::BAD_REQUEST, axum::Json(err)).into_response(),
+        }
+    }
+}
+
+impl<T, S> FromRequestParts<S> for NamedPath<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+    NameRules: FromRef<S>,
+{
+    type Rejection = NamedPathRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let rules = NameRules::from_ref(state);
+        let Path(params) = Path::<Vec<(String, String)>>::from_request_parts(parts, state)
+            .await
+            .map_err(NamedPathRejection::Path)?;
+        for (name, value) in &params {
+            let checked = match name.as_str() {
+                "stream" => rules.check_stream(value),
+                "record" => rules.check_record(value),
+                _ => continue,
+            };
+            checked.map_err(|rule| NamedPathRejection::Name(rule.error(name)))?;
+        }
+        let Path(value) = Path::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(NamedPathRejection::Path)?;
+        Ok(Self(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::Request;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn record(NamedPath((stream, record)): NamedPath<(String, String)>) -> String {
+        format!("{stream}:{record}")
+    }
+
+    async fn get_status(rules: NameRules, uri: &str) -> (StatusCode, String) {
+        let app = Router::new()
+            .route("/api/record/{stream}/{record}", get(record))
+            .with_state(rules);
+        let res = app
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// `name` as a single path segment, dots encoded so no client resolves them
+    fn encode(name: &str) -> String {
+        name.bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'~' => {
+                    (b as char).to_string()
+                }
+                b => format!("%{b:02X}"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_named_path() {
+        let rules = NameRules::default();
+        let (status, body) = get_status(rules, "/api/record/cam/1760486400").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "cam:1760486400");
+
+        for (name, rule) in crate::recorder::tests::malformed_names() {
+            if name.is_empty() {
+                // Does not match the route at all
+                continue;
+            }
+            let (status, body) =
+                get_status(rules, &format!("/api/record/{}/1", encode(&name))).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{name:?}");
+            let err: RecorderError = serde_json::from_str(&body).unwrap();
+            let RecorderError::Validation { field, message } = err else {
+                panic!("{name:?}: {body}");
+            };
+            assert_eq!(field.as_deref(), Some("stream"));
+            let expected = match rule {
+                crate::recorder::NameRule::EmptySegment => "separator",
+                rule => rule.as_str(),
+            };
+            assert!(message.contains(expected), "{name:?}: {message}");
+
+            let (status, body) =
+                get_status(rules, &format!("/api/record/cam/{}", encode(&name))).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{name:?}");
+            assert!(body.contains("\"record\""), "{name:?}: {body}");
+        }
+
+        // An encoded `/` is a separator, allowed in streams with tenancy only
+        let tenanted = NameRules::with_separators();
+        let (status, body) = get_status(tenanted, "/api/record/acme%2Fcam/1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "acme/cam:1");
+        let (status, _) = get_status(rules, "/api/record/acme%2Fcam/1").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = get_status(tenanted, "/api/record/cam/1%2F2").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
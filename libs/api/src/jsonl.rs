@@ -0,0 +1,197 @@
+//! JSON Lines (`application/x-ndjson`) bodies of large listings.
+//!
+//! Instead of one JSON array built in memory, a listing requested with
+//! `Accept: application/x-ndjson` or `?format=jsonl` sends one object per line as it
+//! reads them. A listing that fails midway ends with an [`ErrorLine`] so a reader can
+//! tell it from a complete one.
+
+use serde::{Deserialize, Serialize};
+
+pub const CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// `format` query value selecting JSON Lines
+pub const FORMAT: &str = "jsonl";
+
+/// Buffered lines are sent once they reach this size
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Whether a request asked for JSON Lines, by its `Accept` header or `format` query
+pub fn requested(accept: Option<&str>, format: Option<&str>) -> bool {
+    format.is_some_and(|f| f.eq_ignore_ascii_case(FORMAT))
+        || accept.is_some_and(|accept| {
+            accept.split(',').any(|media| {
+                media
+                    .split(';')
+                    .next()
+                    .is_some_and(|m| m.trim().eq_ignore_ascii_case(CONTENT_TYPE))
+            })
+        })
+}
+
+/// `?format=` of the listings that can answer with JSON Lines
+#[derive(Debug, Clone, Default, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct FormatQuery {
+    /// `jsonl` for one object per line, like `Accept: application/x-ndjson`
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+/// Last line of a listing that failed midway
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorLine {
+    pub error: String,
+}
+
+/// Turns items into chunks of lines: the first line goes out alone so clients see data
+/// right away, later ones in chunks of up to 64 KiB
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+    started: bool,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the line of `item`, returns a chunk when one is ready to send
+    pub fn push<T: Serialize>(&mut self, item: &T) -> serde_json::Result<Option<Vec<u8>>> {
+        let len = self.buf.len();
+        if let Err(e) = serde_json::to_writer(&mut self.buf, item) {
+            self.buf.truncate(len);
+            return Err(e);
+        }
+        self.buf.push(b'\n');
+        if !self.started || self.buf.len() >= CHUNK_BYTES {
+            self.started = true;
+            return Ok(Some(std::mem::take(&mut self.buf)));
+        }
+        Ok(None)
+    }
+
+    /// The buffered lines followed by the error line ending the listing
+    pub fn error(mut self, error: impl ToString) -> Vec<u8> {
+        let line = ErrorLine {
+            error: error.to_string(),
+        };
+        serde_json::to_writer(&mut self.buf, &line).expect("a string serializes");
+        self.buf.push(b'\n');
+        self.buf
+    }
+
+    /// The lines still buffered
+    pub fn finish(self) -> Option<Vec<u8>> {
+        (!self.buf.is_empty()).then_some(self.buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::{DEFAULT_PRIORITY, RecordingIndexEntry, RecordingStatus};
+
+    fn entry(i: usize) -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            uuid: format!("0192d0a8-0000-7000-8000-{i:012}"),
+            record: (1_700_000_000 + i).to_string(),
+            stream: "cam".to_string(),
+            record_dir: format!("cam/{}", 1_700_000_000 + i),
+            mpd_path: format!("cam/{}/manifest.mpd", 1_700_000_000 + i),
+            start_ts: 1_700_000_000_000_000 + i as i64,
+            end_ts: None,
+            duration_ms: Some(60_000),
+            status: RecordingStatus::Completed,
+            node_alias: Some("edge-1".to_string()),
+            updated_at: i as i64,
+            note: (i % 3 == 0).then(|| format!("note {i}, with \"quotes\"\nand a newline")),
+            labels: vec!["night".to_string()],
+            continues: None,
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant: None,
+            size: None,
+            captions: Vec::new(),
+            trigger: None,
+            imported: false,
+            seq: 0,
+        }
+    }
+
+    fn encode(entries: &[RecordingIndexEntry]) -> Vec<Vec<u8>> {
+        let mut encoder = Encoder::new();
+        let mut chunks = Vec::new();
+        for entry in entries {
+            chunks.extend(encoder.push(entry).unwrap());
+        }
+        chunks.extend(encoder.finish());
+        chunks
+    }
+
+    #[test]
+    fn test_requested() {
+        assert!(requested(Some("application/x-ndjson"), None));
+        assert!(requested(
+            Some("text/html, application/x-ndjson;q=0.9"),
+            None
+        ));
+        assert!(requested(None, Some("jsonl")));
+        assert!(!requested(Some("application/json"), None));
+        assert!(!requested(None, Some("json")));
+        assert!(!requested(None, None));
+    }
+
+    #[test]
+    fn test_streamed_equals_buffered() {
+        let entries: Vec<RecordingIndexEntry> = (0..50_000).map(entry).collect();
+        let buffered = serde_json::to_value(&entries).unwrap();
+
+        let chunks = encode(&entries);
+        // The first entry is sent by itself, the rest in bounded chunks
+        assert_eq!(
+            chunks[0],
+            [serde_json::to_vec(&entries[0]).unwrap(), b"\n".to_vec()].concat()
+        );
+        assert!(chunks.len() > 2);
+        let longest_line = entries
+            .iter()
+            .map(|e| serde_json::to_vec(e).unwrap().len() + 1)
+            .max()
+            .unwrap();
+        assert!(chunks.iter().all(|c| c.len() < CHUNK_BYTES + longest_line));
+
+        let body = chunks.concat();
+        let streamed: Vec<serde_json::Value> = body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(serde_json::Value::Array(streamed), buffered);
+    }
+
+    #[test]
+    fn test_error_ends_the_listing() {
+        let mut encoder = Encoder::new();
+        encoder.push(&entry(0)).unwrap();
+        encoder.push(&entry(1)).unwrap();
+        let tail = encoder.error("database is locked");
+        let lines: Vec<&[u8]> = tail.split(|b| *b == b'\n').collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].is_empty());
+        assert_eq!(
+            serde_json::from_slice::<ErrorLine>(lines[1]).unwrap(),
+            ErrorLine {
+                error: "database is locked".to_string()
+            }
+        );
+    }
+}
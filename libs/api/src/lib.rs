@@ -1,4 +1,7 @@
 pub mod event;
+#[cfg(feature = "axum")]
+pub mod extract;
+pub mod jsonl;
 pub mod path;
 pub mod recorder;
 pub mod request;
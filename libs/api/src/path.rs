@@ -1,5 +1,7 @@
 pub const METRICS: &str = "/metrics";
 pub const METRICS_JSON: &str = "/metrics/json";
+pub const OPENAPI: &str = "/api/openapi.json";
+pub const SWAGGER_UI: &str = "/swagger-ui";
 
 pub fn whip(stream: &str) -> String {
     format!("/whip/{stream}")
@@ -42,6 +44,26 @@ pub fn record(stream: &str) -> String {
     format!("/api/record/{stream}")
 }
 
+pub fn record_entry(stream: &str, record: &str) -> String {
+    format!("/api/record/{stream}/{record}")
+}
+
+pub fn record_restore(stream: &str, record: &str) -> String {
+    format!("/api/record/{stream}/{record}/restore")
+}
+
+pub fn record_captions(stream: &str, record: &str, lang: &str) -> String {
+    format!("/api/record/{stream}/{record}/captions/{lang}")
+}
+
+pub fn record_by_id(uuid: &str) -> String {
+    format!("/api/record/by-id/{uuid}")
+}
+
+pub fn record_repair(stream: &str, record: &str) -> String {
+    format!("/api/record/repair/{stream}/{record}")
+}
+
 pub fn recordings() -> &'static str {
     "/api/recordings"
 }
@@ -53,3 +75,99 @@ pub fn recordings_ack() -> &'static str {
 pub fn recordings_delete() -> &'static str {
     "/api/recordings"
 }
+
+pub fn recordings_orphaned() -> &'static str {
+    "/api/recordings/orphaned"
+}
+
+pub fn recordings_orphaned_archive() -> &'static str {
+    "/api/recordings/orphaned/archive"
+}
+
+pub fn recordings_orphaned_delete() -> &'static str {
+    "/api/recordings/orphaned/delete"
+}
+
+pub fn recorder_events() -> &'static str {
+    "/api/recorder/events"
+}
+
+pub fn recorder_ingest() -> &'static str {
+    "/api/recorder/ingest"
+}
+
+pub fn recorder_uploads() -> &'static str {
+    "/api/recorder/uploads"
+}
+
+pub fn recorder_lease() -> &'static str {
+    "/api/recorder/lease"
+}
+
+pub fn recorder_leases() -> &'static str {
+    "/api/recorder/leases"
+}
+
+pub fn recorder_capabilities() -> &'static str {
+    "/api/recorder/capabilities"
+}
+
+pub fn recorder_trigger() -> &'static str {
+    "/api/recorder/trigger"
+}
+
+pub fn recorder_reconcile() -> &'static str {
+    "/api/recorder/reconcile"
+}
+
+pub fn recorder_rename_stream() -> &'static str {
+    "/api/recorder/rename-stream"
+}
+
+pub fn recorder_import() -> &'static str {
+    "/api/recorder/import"
+}
+
+pub fn recorder_ws() -> &'static str {
+    "/api/ws/recorder"
+}
+
+pub fn recorder_index_restore() -> &'static str {
+    "/api/recorder/index/restore"
+}
+
+pub fn recorder_audit() -> &'static str {
+    "/api/recorder/audit"
+}
+
+pub fn recorder_journal(journal: &str) -> String {
+    format!("/api/recorder/journal/{journal}")
+}
+
+pub fn recorder_stats() -> &'static str {
+    "/api/recorder/stats"
+}
+
+pub fn recorder_stream_stats(stream: &str) -> String {
+    format!("/api/recorder/stats/{stream}")
+}
+
+pub fn recorder_health() -> &'static str {
+    "/api/recorder/health"
+}
+
+pub fn recorder_stream_health(stream: &str) -> String {
+    format!("/api/recorder/health/{stream}")
+}
+
+pub fn recorder_verify(stream: &str, record: &str) -> String {
+    format!("/api/recorder/verify/{stream}/{record}")
+}
+
+pub fn storage_chaos() -> &'static str {
+    "/api/debug/storage/chaos"
+}
+
+pub fn storage_diagnose() -> &'static str {
+    "/api/storage/diagnose"
+}
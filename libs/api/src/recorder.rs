@@ -3,9 +3,13 @@ use std::str::FromStr;
 
 /// Recording session information
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct RecordingSession {
     /// Session UUID (optional for backward compatibility)
     pub id: Option<String>,
+    /// Stable id, see [`RecordingIndexEntry::uuid`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
     /// Stream name
     pub stream: String,
     /// Recording start timestamp (microseconds since epoch)
@@ -18,97 +22,2070 @@ pub struct RecordingSession {
     pub mpd_path: String,
     /// Recording status
     pub status: RecordingStatus,
+    /// Operator note attached after the fact
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Operator labels attached after the fact
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    /// Record id of the previous part when this session was split at the duration limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continues: Option<String>,
+    /// Track formats, see [`RecordingIndexEntry::media_info`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub media_info: Vec<MediaInfo>,
+    /// Retention class, see [`RecordingIndexEntry::retention_class`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_class: Option<RetentionClass>,
+    /// Priority, see [`RecordingIndexEntry::priority`]
+    #[serde(default = "default_priority")]
+    pub priority: u8,
+    /// When the recording was moved to the trash, see [`RecordingIndexEntry::trashed_at`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trashed_at: Option<i64>,
+    /// Cascade source, see [`RecordingIndexEntry::source`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Tenant, see [`RecordingIndexEntry::tenant`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+/// Recording entry persisted in the liveion index (index.json)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RecordingIndexEntry {
+    /// Stable id of the recording (UUIDv7, so ids sort by creation), kept when its
+    /// stream is renamed. Empty only for entries archived by a node predating it
+    #[serde(default)]
+    pub uuid: String,
+    pub record: String,
+    pub stream: String,
+    pub record_dir: String,
+    pub mpd_path: String,
+    pub start_ts: i64,
+    pub end_ts: Option<i64>,
+    pub duration_ms: Option<i32>,
+    pub status: RecordingStatus,
+    pub node_alias: Option<String>,
+    pub updated_at: i64,
+    /// User-editable note, see [`UpdateRecordingRequest`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// User-editable labels, see [`UpdateRecordingRequest`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    /// Record id this entry continues after a max-duration split
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continues: Option<String>,
+    /// Track formats, one snapshot per renegotiation, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub media_info: Vec<MediaInfo>,
+    /// Retention class the recording's objects are tagged with, `None` keeps them forever
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_class: Option<RetentionClass>,
+    /// Higher priorities are uploaded first, see [`DEFAULT_PRIORITY`]
+    #[serde(default = "default_priority")]
+    pub priority: u8,
+    /// When the recording was moved to the trash (UNIX microseconds), its objects are
+    /// deleted once the trash retention passes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trashed_at: Option<i64>,
+    /// Status to go back to when a trashed recording is restored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trashed_from: Option<RecordingStatus>,
+    /// The wall clock stepped while recording, `end_ts` is off by the step and
+    /// `duration_ms` (measured on a monotonic clock) is the one to trust
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub clock_skew_detected: bool,
+    /// Why the manifest of an interrupted recording could not be repaired
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repair_error: Option<String>,
+    /// WHEP URL the stream was cascade-pulled from, `None` for local publishers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Storage destinations holding the recording's objects, by name. Empty when not
+    /// tracked, readers then try every destination they know
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replicas: Vec<String>,
+    /// Tenant the node's `recorder.tenancy` derived from the stream name, the first
+    /// segment of the recording's object keys. `None` without tenancy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Segments stored once the recording finished, `None` while it runs and for
+    /// recordings of nodes predating it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<RecordingSize>,
+    /// Languages with a caption sidecar, `captions_{lang}.vtt` in `record_dir`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub captions: Vec<String>,
+    /// Events that started the recording, `None` unless started by a trigger
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trigger: Option<RecordingTrigger>,
+    /// Recorded by another system and added by `POST /api/recorder/import`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub imported: bool,
+    /// Position of the entry's last write in its node's index journal. Increases with
+    /// every line the node appends, survives restarts and compactions, but has gaps.
+    /// 0 for entries written before it and for entries built outside an index
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub seq: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+impl RecordingIndexEntry {
+    pub fn key(&self) -> String {
+        format!("{}/{}", self.stream, self.record)
+    }
+
+    /// When the recording ended, UNIX microseconds and never before `start_ts`.
+    ///
+    /// `start_ts + duration_ms` when the wall clock stepped, otherwise `end_ts` with
+    /// that as the fallback for entries that only have a duration.
+    pub fn effective_end_ts(&self) -> Option<i64> {
+        let from_duration = self
+            .duration_ms
+            .map(|d| self.start_ts.saturating_add(i64::from(d.max(0)) * 1000));
+        let end = if self.clock_skew_detected {
+            from_duration.or(self.end_ts)
+        } else {
+            self.end_ts.or(from_duration)
+        };
+        end.map(|end| end.max(self.start_ts))
+    }
+
+    pub fn is_trashed(&self) -> bool {
+        matches!(self.status, RecordingStatus::Trashed)
+    }
+}
+
+/// Track formats of a recording from `since_ts` on, read from its init segments
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MediaInfo {
+    /// When this format was first seen, UNIX microseconds
+    pub since_ts: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video: Option<VideoInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct VideoInfo {
+    /// RFC 6381 codec string, e.g. `avc1.42E01E`
+    pub codec: String,
+    pub width: u32,
+    pub height: u32,
+    /// Frames per second measured over the first segment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub framerate: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AudioInfo {
+    pub codec: String,
+    pub channels: u16,
+    pub sample_rate: u32,
+}
+
+impl VideoInfo {
+    /// Same codec and resolution, the measured framerate is not a format change
+    pub fn same_format(&self, other: &VideoInfo) -> bool {
+        self.codec == other.codec && self.width == other.width && self.height == other.height
+    }
+}
+
+impl MediaInfo {
+    /// Whether `next` only fills in tracks missing here rather than renegotiating one
+    pub fn completed_by(&self, next: &MediaInfo) -> bool {
+        let video = match (&self.video, &next.video) {
+            (Some(a), Some(b)) => a.same_format(b),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        let audio = match (&self.audio, &next.audio) {
+            (Some(a), Some(b)) => a == b,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        video && audio
+    }
+}
+
+/// Per-recording file next to the manifest, one [`SegmentTiming`] line per video segment
+pub const SEGMENTS_FILENAME: &str = "segments.jsonl";
+
+/// Where a video segment sits on the wall clock and in the media timeline.
+///
+/// The manifest only has media time, which drifts from the wall clock over long
+/// recordings and jumps where segments were dropped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentTiming {
+    /// File name relative to the recording directory
+    pub file: String,
+    /// Segment number, as in the manifest's `$Number$`
+    pub seq: u32,
+    /// Wall clock time of the segment's first sample, UNIX microseconds
+    pub start_ts: i64,
+    /// Media time of the segment's first sample from the start of the recording
+    pub offset_ms: u64,
+    pub duration_ms: u64,
+    pub bytes: u64,
+}
+
+/// Archive file holding the acked entries of the index at `index_path`,
+/// `index.archive.json` next to `index.json`
+pub fn index_archive_path(index_path: &std::path::Path) -> std::path::PathBuf {
+    let stem = index_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match index_path.extension() {
+        Some(ext) => format!("{stem}.archive.{}", ext.to_string_lossy()),
+        None => format!("{stem}.archive"),
+    };
+    index_path.with_file_name(name)
+}
+
+/// Append `next` to a recording's formats, merging it into the latest snapshot when
+/// it only adds a track or a measured framerate. Returns whether anything changed.
+pub fn push_media_info(list: &mut Vec<MediaInfo>, next: MediaInfo) -> bool {
+    match list.last_mut() {
+        Some(last) if *last == next => false,
+        Some(last) if last.completed_by(&next) => {
+            let since_ts = last.since_ts;
+            *last = MediaInfo { since_ts, ..next };
+            true
+        }
+        _ => {
+            list.push(next);
+            true
+        }
+    }
+}
+
+/// Object tag key carrying the retention class, matched by storage lifecycle rules
+pub const RETENTION_TAG: &str = "retention";
+
+/// How long a finished recording is kept: `<n>d`, `<n>w`, `<n>y` or `forever`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(try_from = "String", into = "String")]
+pub struct RetentionClass(String);
+
+impl RetentionClass {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Retention period, `None` for `forever`. A year counts as 365 days.
+    pub fn period(&self) -> Option<std::time::Duration> {
+        if self.0 == "forever" {
+            return None;
+        }
+        let (count, unit) = self.0.split_at(self.0.len() - 1);
+        let days = match unit {
+            "d" => 1,
+            "w" => 7,
+            _ => 365,
+        };
+        let count: u64 = count.parse().ok()?;
+        Some(std::time::Duration::from_secs(count * days * 86_400))
+    }
+
+    /// Whether a recording that ended at `end_ts` is past its period at `now`, both in
+    /// UNIX microseconds
+    pub fn expired(&self, end_ts: i64, now: i64) -> bool {
+        self.period().is_some_and(|period| {
+            let period = i64::try_from(period.as_micros()).unwrap_or(i64::MAX);
+            end_ts.saturating_add(period) <= now
+        })
+    }
+
+    /// Tag set in `x-amz-tagging` form
+    pub fn tagging(&self) -> String {
+        format!("{RETENTION_TAG}={}", self.0)
+    }
+}
+
+impl FromStr for RetentionClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "forever" {
+            return Ok(Self(s.to_string()));
+        }
+        let valid = s.len() >= 2
+            && s.ends_with(['d', 'w', 'y'])
+            && s[..s.len() - 1].bytes().all(|b| b.is_ascii_digit())
+            && s[..s.len() - 1].parse::<u32>().is_ok_and(|n| n > 0);
+        if !valid {
+            return Err(format!(
+                "invalid retention class '{s}', expected e.g. 30d, 12w, 1y or forever"
+            ));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for RetentionClass {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<RetentionClass> for String {
+    fn from(class: RetentionClass) -> Self {
+        class.0
+    }
+}
+
+impl std::fmt::Display for RetentionClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Priority of recordings no rule or start request assigns one. Higher values are
+/// more important, so streams can be ranked both above and below the default.
+pub const DEFAULT_PRIORITY: u8 = 100;
+
+pub fn default_priority() -> u8 {
+    DEFAULT_PRIORITY
+}
+
+/// Maximum length of a recording note in bytes
+pub const MAX_NOTE_LEN: usize = 4096;
+/// Maximum length of a single recording label in bytes
+pub const MAX_LABEL_LEN: usize = 64;
+/// Maximum number of labels on a recording
+pub const MAX_LABELS: usize = 32;
+
+/// Request body for `PATCH /api/record/{stream}/{record}`
+///
+/// Only user-editable fields are accepted; system fields such as `status`
+/// or timestamps are rejected as unknown fields.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct UpdateRecordingRequest {
+    /// Replace the note, an empty string clears it
+    #[serde(default)]
+    pub note: Option<String>,
+    /// Label additions and removals
+    #[serde(default)]
+    pub labels: Option<LabelsPatch>,
+    /// Move the recording to another retention class, its objects are re-tagged
+    #[serde(default)]
+    pub retention_class: Option<RetentionClass>,
+    /// Change the priority, queued uploads are reordered
+    #[serde(default)]
+    pub priority: Option<u8>,
+    /// Only patch the entry while its `updated_at` is this one, see
+    /// [`RecorderError::VersionMismatch`]
+    #[serde(default)]
+    pub if_match: Option<i64>,
+}
+
+/// Label changes applied by [`UpdateRecordingRequest`], removals run after additions
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct LabelsPatch {
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+impl UpdateRecordingRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.note.is_none()
+            && self.labels.is_none()
+            && self.retention_class.is_none()
+            && self.priority.is_none()
+        {
+            return Err("empty patch".to_string());
+        }
+        if let Some(note) = self.note.as_ref()
+            && note.len() > MAX_NOTE_LEN
+        {
+            return Err(format!("note exceeds {MAX_NOTE_LEN} bytes"));
+        }
+        if let Some(labels) = self.labels.as_ref() {
+            for label in labels.add.iter().chain(labels.remove.iter()) {
+                if label.trim().is_empty() {
+                    return Err("label must not be empty".to_string());
+                }
+                if label.len() > MAX_LABEL_LEN {
+                    return Err(format!("label exceeds {MAX_LABEL_LEN} bytes"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the patch to an entry, returning an error when labels overflow
+    pub fn apply(&self, entry: &mut RecordingIndexEntry) -> Result<(), String> {
+        let mut labels = entry.labels.clone();
+        if let Some(patch) = self.labels.as_ref() {
+            for label in &patch.add {
+                let label = label.trim();
+                if !labels.iter().any(|l| l == label) {
+                    labels.push(label.to_string());
+                }
+            }
+            labels.retain(|l| !patch.remove.iter().any(|r| r.trim() == l));
+        }
+        if labels.len() > MAX_LABELS {
+            return Err(format!(
+                "recording cannot have more than {MAX_LABELS} labels"
+            ));
+        }
+        entry.labels = labels;
+        if let Some(note) = self.note.as_ref() {
+            entry.note = if note.is_empty() {
+                None
+            } else {
+                Some(note.clone())
+            };
+        }
+        if let Some(class) = self.retention_class.as_ref() {
+            entry.retention_class = Some(class.clone());
+        }
+        if let Some(priority) = self.priority {
+            entry.priority = priority;
+        }
+        Ok(())
+    }
 }
 
 /// Recording status
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum RecordingStatus {
+    /// Recording is currently active
+    Active,
+    /// Recording completed successfully
+    Completed,
+    /// Recording failed
+    Failed,
+    /// Recording was acknowledged by manager
+    Acked,
+    /// Recording finished but its objects are gone from storage
+    Missing,
+    /// Recording was cut short by a shutdown that could not finalize it in time
+    Interrupted,
+    /// Recording was deleted and can be restored until the trash retention passes
+    Trashed,
+}
+
+impl std::fmt::Display for RecordingStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordingStatus::Active => write!(f, "Active"),
+            RecordingStatus::Completed => write!(f, "Completed"),
+            RecordingStatus::Failed => write!(f, "Failed"),
+            RecordingStatus::Acked => write!(f, "Acked"),
+            RecordingStatus::Missing => write!(f, "Missing"),
+            RecordingStatus::Interrupted => write!(f, "Interrupted"),
+            RecordingStatus::Trashed => write!(f, "Trashed"),
+        }
+    }
+}
+
+impl FromStr for RecordingStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Active" => Ok(RecordingStatus::Active),
+            "Completed" => Ok(RecordingStatus::Completed),
+            "Failed" => Ok(RecordingStatus::Failed),
+            "Acked" => Ok(RecordingStatus::Acked),
+            "Missing" => Ok(RecordingStatus::Missing),
+            "Interrupted" => Ok(RecordingStatus::Interrupted),
+            "Trashed" => Ok(RecordingStatus::Trashed),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Request to pull recording sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema, utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct PullRecordingsRequest {
+    /// Stream name filter (None for all streams)
+    pub stream: Option<String>,
+    /// Only get sessions updated since this timestamp
+    pub since_ts: Option<i64>,
+    /// Maximum number of sessions to return
+    pub limit: u32,
+    /// Sort order by `updated_at`, `asc` for sync cursors, `desc` for newest first
+    #[serde(default)]
+    pub order: ListOrder,
+    /// Cursor from the previous page's `next_cursor`, must be used with the same order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// List trashed recordings too
+    #[serde(default)]
+    pub include_trashed: bool,
+}
+
+impl PullRecordingsRequest {
+    /// Decode `cursor`, rejecting cursors issued for the other order
+    pub fn parsed_cursor(&self) -> Result<Option<ListCursor>, String> {
+        self.cursor
+            .as_deref()
+            .map(|c| ListCursor::decode(c, self.order))
+            .transpose()
+    }
+}
+
+/// Error code returned when playback is refused for a [`RecordingStatus::Missing`] entry
+pub const RECORDING_MISSING_CODE: &str = "recording_missing";
+
+/// Error code returned when previews are requested for a recording without video
+pub const PREVIEW_AUDIO_ONLY_CODE: &str = "audio_only";
+
+/// Error code returned when a transcode names a profile livevod is not configured with
+pub const TRANSCODE_UNKNOWN_PROFILE_CODE: &str = "unknown_profile";
+
+/// Error body of the recorder routes, tagged by `error`. Callers decide from the variant
+/// whether a request is worth retrying, see [`RecorderError::is_retryable`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum RecorderError {
+    /// No such recording on the node, retrying does not help
+    NotFound {
+        stream: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        record: Option<String>,
+        message: String,
+    },
+    /// The request conflicts with the node's state, such as an existing rename target
+    Conflict { message: String },
+    /// Another process holds the index lock, retry after `retry_after_seconds`
+    IndexBusy {
+        retry_after_seconds: u64,
+        message: String,
+    },
+    /// The index or storage is not initialized or failing, retry later
+    StorageUnavailable { message: String },
+    /// The recording's status does not allow the operation, e.g. trashing an active one
+    InvalidTransition {
+        stream: String,
+        record: String,
+        message: String,
+    },
+    /// Malformed request, `field` names the offending input when known
+    Validation {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        field: Option<String>,
+        message: String,
+    },
+    /// The node already records `max` streams, retry once one of them stops
+    RecordingLimit {
+        active: usize,
+        max: usize,
+        message: String,
+    },
+    /// The node lacks the feature, see [`RecorderCapabilities`]
+    Unsupported {
+        feature: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        node: Option<String>,
+        message: String,
+    },
+    /// `if_match` named another version of the recording than `current`, which changed
+    /// since the caller read it. Refresh and retry with `current.updated_at`
+    VersionMismatch {
+        stream: String,
+        record: String,
+        if_match: i64,
+        current: Box<RecordingIndexEntry>,
+        message: String,
+    },
+}
+
+impl RecorderError {
+    /// HTTP status the error is returned with
+    pub fn status_code(&self) -> u16 {
+        match self {
+            Self::NotFound { .. } => 404,
+            Self::Conflict { .. }
+            | Self::InvalidTransition { .. }
+            | Self::VersionMismatch { .. } => 409,
+            Self::IndexBusy { .. } | Self::StorageUnavailable { .. } => 503,
+            Self::Validation { .. } => 400,
+            Self::RecordingLimit { .. } => 429,
+            Self::Unsupported { .. } => 501,
+        }
+    }
+
+    /// Whether the same request may succeed later
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::IndexBusy { .. } | Self::StorageUnavailable { .. } | Self::RecordingLimit { .. }
+        )
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::NotFound { message, .. }
+            | Self::Conflict { message }
+            | Self::IndexBusy { message, .. }
+            | Self::StorageUnavailable { message }
+            | Self::InvalidTransition { message, .. }
+            | Self::Validation { message, .. }
+            | Self::RecordingLimit { message, .. }
+            | Self::Unsupported { message, .. }
+            | Self::VersionMismatch { message, .. } => message,
+        }
+    }
+
+    /// `stream/record` not found
+    pub fn not_found(stream: &str, record: &str) -> Self {
+        Self::NotFound {
+            stream: stream.to_string(),
+            record: Some(record.to_string()),
+            message: format!("recording {stream}/{record} not found"),
+        }
+    }
+
+    /// `if_match` is not the version of `current`
+    pub fn version_mismatch(if_match: i64, current: RecordingIndexEntry) -> Self {
+        Self::VersionMismatch {
+            stream: current.stream.clone(),
+            record: current.record.clone(),
+            if_match,
+            message: format!(
+                "recording {} is at version {}, not {if_match}",
+                current.key(),
+                current.updated_at
+            ),
+            current: Box::new(current),
+        }
+    }
+
+    pub fn validation(field: Option<&str>, message: impl ToString) -> Self {
+        Self::Validation {
+            field: field.map(str::to_string),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for RecorderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for RecorderError {}
+
+/// Request body for `POST /api/recorder/reconcile`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReconcileRequest {
+    /// Also check this many segments per recording, spread over its timeline
+    #[serde(default)]
+    pub sample_segments: Option<usize>,
+}
+
+/// Progress of the index/storage reconciliation job
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReconcileStatus {
+    pub running: bool,
+    /// Finished recordings checked by the current or last run
+    pub checked: usize,
+    /// Recordings marked missing by the current or last run
+    pub missing: usize,
+    /// Start of the current or last run, UNIX microseconds
+    pub started_at: Option<i64>,
+    /// End of the last run, UNIX microseconds
+    pub finished_at: Option<i64>,
+}
+
+/// Free space of the recorder's upload spool, see `upload.min_free_bytes` and
+/// `upload.min_free_inodes`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DiskStatus {
+    /// Bytes free in `local_dir` at the last check, `None` before the first one
+    pub free_bytes: Option<u64>,
+    pub min_free_bytes: u64,
+    /// Inodes free in `local_dir` at the last check, `None` before the first one or on
+    /// filesystems that don't report them
+    #[serde(default)]
+    pub free_inodes: Option<u64>,
+    #[serde(default)]
+    pub min_free_inodes: u64,
+    /// New uploads are refused and segments dropped until space is freed
+    pub guarded: bool,
+}
+
+/// Uploads held back while liveman is unreachable, see `upload.suspend_after_failures`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UploadSuspension {
+    /// UNIX microseconds
+    pub since: i64,
+    /// Probes of liveman since, all failed
+    pub probes: u32,
+    /// Error of the last failed request or probe
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Upload lane of an object, see `upload.small_object_bytes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum UploadLane {
+    /// Objects below `small_object_bytes`: manifests, init segments, short segments
+    Small,
+    Large,
+}
+
+impl UploadLane {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UploadLane::Small => "small",
+            UploadLane::Large => "large",
+        }
+    }
+}
+
+/// Uploads of one lane of the queue
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UploadLaneStatus {
+    pub lane: UploadLane,
+    /// Objects of the lane being uploaded right now
+    pub uploading: usize,
+    /// Most uploads the lane runs at once
+    pub concurrency: usize,
+}
+
+/// Upload queue of a node, `GET /api/recorder/uploads`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UploadQueueStatus {
+    /// Queued objects, dead-lettered ones included
+    pub pending: usize,
+    /// Objects given up after `upload.max_retries`
+    pub dead_lettered: usize,
+    /// Objects being uploaded right now
+    pub uploading: usize,
+    /// Uploads by lane, the small lane is left out when `small_object_bytes` is 0
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lanes: Vec<UploadLaneStatus>,
+    /// Connection failures to liveman in a row
+    pub consecutive_failures: u32,
+    /// Set while the queue is suspended
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suspended: Option<UploadSuspension>,
+    /// Upload lag of the node's streams
+    #[serde(default)]
+    pub lag: UploadLag,
+}
+
+/// Time from a file's last write to its upload being stored and verified, over
+/// `upload.lag_window_seconds`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UploadLag {
+    /// Uploads completed within the window
+    pub samples: u64,
+    /// Median lag of those uploads, `None` without any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p50_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p99_ms: Option<u64>,
+    /// Age of the oldest file still queued, dead-lettered ones aside
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oldest_pending_ms: Option<u64>,
+    /// Largest lag of the window, or the age of the oldest pending file when larger
+    pub worst_ms: u64,
+}
+
+impl UploadLag {
+    /// Fold in the lag of another node: samples add up, the rest takes the larger value,
+    /// so a cluster's percentiles are an upper bound of the nodes' own
+    pub fn merge(&mut self, other: &UploadLag) {
+        self.samples += other.samples;
+        self.p50_ms = self.p50_ms.max(other.p50_ms);
+        self.p99_ms = self.p99_ms.max(other.p99_ms);
+        self.oldest_pending_ms = self.oldest_pending_ms.max(other.oldest_pending_ms);
+        self.worst_ms = self.worst_ms.max(other.worst_ms);
+    }
+}
+
+/// Where the recorder's startup gate stands, see `recorder.startup`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum StartupState {
+    /// Objects wait for the first successful connection check
+    #[default]
+    Waiting,
+    Ready,
+    /// `max_wait_seconds` passed without one, objects are written as usual
+    TimedOut,
+}
+
+/// Startup gate of the recorder's storage writes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct StartupStatus {
+    pub state: StartupState,
+    /// `storage` when recordings are written to storage directly, `liveman` with uploads
+    pub target: String,
+    /// Connection checks so far
+    pub attempts: u32,
+    /// Error of the last failed check
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Start of the wait, UNIX microseconds
+    pub since: i64,
+    /// End of the wait, UNIX microseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<i64>,
+}
+
+/// Recordings running on a node against its `max_concurrent_recordings`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RecordingCapacity {
+    pub active: usize,
+    /// 0 is unlimited
+    pub max: usize,
+}
+
+impl RecordingCapacity {
+    /// Whether new recordings are refused or preempt others
+    pub fn at_limit(&self) -> bool {
+        self.max > 0 && self.active >= self.max
+    }
+}
+
+/// Media segments a recording stored, init segments and manifests not counted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RecordingSize {
+    pub segments: u64,
+    pub bytes: u64,
+}
+
+/// Triggers of a recording started by `POST /api/recorder/trigger` or MQTT
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RecordingTrigger {
+    /// Source of the first trigger, `http` or `mqtt:{topic}`
+    pub source: String,
+    /// First trigger, UNIX microseconds
+    pub first_at: i64,
+    /// Latest trigger, each one pushing the stop back by its post-roll
+    pub last_at: i64,
+    pub count: u32,
+    /// Media recorded from before the first trigger
+    pub pre_roll_ms: u64,
+}
+
+/// Recorder statistics of a node, a stream or a cluster, see `GET /api/recorder/stats`
+///
+/// Computed from the recordings in the index plus the running ones, a deleted
+/// recording drops out of every figure
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RecorderStats {
+    /// Recordings in the index, running and trashed ones included
+    pub recordings: u64,
+    /// Recordings running now
+    pub active: u64,
+    /// Recorded time, the running recordings' so far included
+    pub recorded_ms: u64,
+    /// Media segments stored, see [`RecordingSize`]
+    pub segments: u64,
+    pub bytes: u64,
+    /// `bytes / segments`, 0 without segments
+    pub avg_segment_bytes: u64,
+    /// Rate the running recordings store media at, bits per second
+    pub write_bitrate_bps: u64,
+    /// When the figures were computed, UNIX microseconds
+    pub computed_at: i64,
+}
+
+impl RecorderStats {
+    /// Add the figures of `other`, e.g. another node's
+    pub fn add(&mut self, other: &RecorderStats) {
+        self.recordings += other.recordings;
+        self.active += other.active;
+        self.recorded_ms += other.recorded_ms;
+        self.segments += other.segments;
+        self.bytes += other.bytes;
+        self.avg_segment_bytes = self.bytes.checked_div(self.segments).unwrap_or(0);
+        self.write_bitrate_bps += other.write_bitrate_bps;
+        self.computed_at = self.computed_at.max(other.computed_at);
+    }
+
+    /// Hours recorded, from [`Self::recorded_ms`]
+    pub fn recorded_hours(&self) -> f64 {
+        self.recorded_ms as f64 / 3_600_000.0
+    }
+}
+
+/// Response of liveman's `GET /api/recorder/stats`: the nodes' stats as of their last
+/// record sync, and their sum
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ClusterRecorderStats {
+    pub total: RecorderStats,
+    /// By node alias, nodes not synced yet or predating the stats are absent
+    pub nodes: std::collections::BTreeMap<String, RecorderStats>,
+}
+
+/// Verdict of a recording integrity rollup, worst last
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    #[default]
+    Ok,
+    Degraded,
+    Failing,
+}
+
+impl HealthStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Degraded => "degraded",
+            Self::Failing => "failing",
+        }
+    }
+}
+
+/// Signal a [`HealthReason`] comes from, see `recorder.health`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum HealthReasonKind {
+    /// The oldest queued upload waits longer than the threshold, `value` in seconds
+    UploadLag,
+    /// Uploads given up after `upload.max_retries`
+    DeadLettered,
+    /// Uploads whose stored object differed from the local file
+    UploadMismatch,
+    /// Finished recordings whose objects are gone from storage
+    Missing,
+    /// Recordings failed or cut short by a shutdown within the window
+    Interrupted,
+    /// The disk space guard refuses new uploads, `value` in free bytes
+    DiskGuard,
+    /// Free space of the upload spool is running low, `value` in free bytes
+    DiskLow,
+    /// A recording gets no media while its publisher stays connected, `value` in
+    /// seconds since its last sample
+    Stalled,
+}
+
+/// A condition that made a rollup worse than `ok`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HealthReason {
+    pub kind: HealthReasonKind,
+    pub status: HealthStatus,
+    /// Stream the condition is about, absent for the node's disk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+    /// Measured figure: a count, seconds or bytes depending on `kind`
+    pub value: u64,
+    /// Threshold `value` crossed for `status`
+    pub threshold: u64,
+    pub message: String,
+}
+
+/// Recording integrity of a stream or of the node, see `GET /api/recorder/health`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RecorderHealth {
+    /// Worst status of the reasons, `ok` without any
+    pub status: HealthStatus,
+    /// Worst first
+    #[serde(default)]
+    pub reasons: Vec<HealthReason>,
+    /// When the rollup was computed, UNIX microseconds
+    pub computed_at: i64,
+    /// Upload lag of the stream or of the node, `None` without uploads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_lag: Option<UploadLag>,
+}
+
+/// A recording getting no media while its publisher stays connected, see
+/// `recorder.stall_timeout_seconds`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RecordingStall {
+    pub record_dir: String,
+    /// Last media sample of the recording, UNIX microseconds
+    pub since_ts: i64,
+}
+
+/// When the node's index and upload queue writes reach the disk, see
+/// `recorder.durability`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DurabilityStatus {
+    /// `strict`, `batched` or `relaxed`
+    pub mode: String,
+    /// Longest a write waits for its sync, 0 when each write is synced right away
+    pub flush_interval_ms: u64,
+    /// Files and directories written but not synced yet
+    pub unsynced: usize,
+}
+
+/// Response of liveman's `GET /api/recorder/health`: the nodes' rollups as of their
+/// last record sync, and the worst status among them
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ClusterRecorderHealth {
+    pub status: HealthStatus,
+    /// By node alias, nodes not synced yet or predating the rollup are absent
+    pub nodes: std::collections::BTreeMap<String, RecorderHealth>,
+    /// Upload lag of the nodes that upload, see [`UploadLag::merge`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_lag: Option<UploadLag>,
+}
+
+/// Request body for `POST /api/recorder/lease`, acquiring, renewing or releasing the
+/// recording lease of a stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RecordingLeaseRequest {
+    pub stream: String,
+    pub node_alias: String,
+    /// How long the lease holds without another request from its holder
+    pub ttl_seconds: u64,
+    /// Give the lease up, once the holder stopped recording
+    #[serde(default)]
+    pub release: bool,
+}
+
+/// The one node allowed to record a stream, until `expires_at`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RecordingLease {
+    pub stream: String,
+    /// Node alias
+    pub holder: String,
+    /// When the holder acquired it, UNIX microseconds
+    pub acquired_at: i64,
+    /// UNIX microseconds, another node may acquire it afterwards
+    pub expires_at: i64,
+}
+
+/// Response of `POST /api/recorder/lease`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RecordingLeaseResponse {
+    /// Whether the requesting node holds the lease now
+    pub granted: bool,
+    /// The current lease, another node's when not `granted`, absent once released
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease: Option<RecordingLease>,
+}
+
+/// Request body for `POST /api/recorder/trigger`, an event worth recording happened
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TriggerRequest {
+    pub stream: String,
+    /// Media to keep from before the trigger, `recorder.triggers.pre_roll_ms` when
+    /// absent and at most `recorder.triggers.buffer_ms`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_roll_ms: Option<u64>,
+    /// Quiet time after the trigger before the recording stops,
+    /// `recorder.triggers.post_roll_ms` when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_roll_ms: Option<u64>,
+}
+
+/// Response of `POST /api/recorder/trigger`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TriggerResponse {
+    pub stream: String,
+    pub record: String,
+    /// Whether this trigger started the recording, otherwise it was running already
+    pub started: bool,
+    /// Media recorded from before the trigger, 0 unless it `started` the recording
+    pub pre_roll_ms: u64,
+    /// When the recording stops without another trigger, UNIX microseconds. `None`
+    /// for a recording not started by a trigger, which is left running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_at: Option<i64>,
+}
+
+/// Names of [`RecorderCapabilities::features`]
+pub mod capability {
+    /// `POST`/`DELETE /api/record/{stream}`
+    pub const RECORD: &str = "record";
+    /// `note` and `labels` through `PATCH /api/record/{stream}/{record}`
+    pub const LABELS: &str = "labels";
+    /// `PUT /api/record/{stream}/{record}/captions/{lang}`
+    pub const CAPTIONS: &str = "captions";
+    /// Deletes to the trash and `POST /api/record/{stream}/{record}/restore`
+    pub const TRASH: &str = "trash";
+    pub const REPAIR: &str = "repair";
+    pub const RENAME_STREAM: &str = "rename_stream";
+    pub const VERIFY: &str = "verify";
+    pub const RECONCILE: &str = "reconcile";
+    pub const INDEX_RESTORE: &str = "index_restore";
+    /// `POST /api/recorder/import`
+    pub const IMPORT: &str = "import";
+    /// `POST /api/recorder/trigger`
+    pub const TRIGGERS: &str = "triggers";
+    /// Triggers from MQTT topics, built with `trigger-mqtt` and configured
+    pub const TRIGGER_MQTT: &str = "trigger_mqtt";
+    /// Large files uploaded in parts through liveman
+    pub const MULTIPART_UPLOAD: &str = "multipart_upload";
+    /// Index transitions pushed to liveman as they happen
+    pub const PUSH_SYNC: &str = "push_sync";
+    /// Auto-recording under a lease from liveman
+    pub const LEASE: &str = "lease";
+}
+
+/// How a node's recordings reach storage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum UploadMode {
+    /// Written to storage by the node
+    #[default]
+    Direct,
+    /// Spooled locally and uploaded through URLs presigned by liveman
+    Presign,
+    /// Spooled locally and streamed to storage through liveman
+    Proxy,
+}
+
+/// Recorder features of a node, `GET /api/recorder/capabilities`.
+///
+/// Clients check a feature here before calling its routes, which a node without it
+/// answers with 404. A feature's version only grows when its requests or responses
+/// change incompatibly.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RecorderCapabilities {
+    /// liveion version of the node
+    pub version: String,
+    /// Supported features by [`capability`] name, with the version of their API
+    pub features: std::collections::BTreeMap<String, u32>,
+    pub upload_mode: UploadMode,
+    /// Format of the node's recordings index, `jsonl`
+    pub index: String,
+}
+
+impl RecorderCapabilities {
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.contains_key(feature)
+    }
+
+    /// [`RecorderError::Unsupported`] unless the node supports `feature`
+    pub fn require(&self, feature: &str, node: &str) -> Result<(), RecorderError> {
+        if self.supports(feature) {
+            return Ok(());
+        }
+        Err(RecorderError::Unsupported {
+            feature: feature.to_string(),
+            node: Some(node.to_string()),
+            message: format!("node {node} does not support {feature}"),
+        })
+    }
+}
+
+/// Longest stream or record name in UTF-8 bytes, leaving room for the namespace and
+/// file name within S3's key limit
+pub const MAX_NAME_LENGTH: usize = 255;
+
+/// Rule a stream or record name breaks, see [`NameRules`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameRule {
+    Empty,
+    /// More than [`MAX_NAME_LENGTH`] bytes
+    TooLong,
+    ControlCharacter,
+    Backslash,
+    /// Contains `..`, or is `.` or has a `.` segment
+    DotSegment,
+    /// Contains `/` where names are single segments
+    Separator,
+    /// Starts or ends with `/`, or contains `//`
+    EmptySegment,
+}
+
+impl NameRule {
+    /// Code of the rule in error messages
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Empty => "empty",
+            Self::TooLong => "too_long",
+            Self::ControlCharacter => "control_character",
+            Self::Backslash => "backslash",
+            Self::DotSegment => "dot_segment",
+            Self::Separator => "separator",
+            Self::EmptySegment => "empty_segment",
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Self::Empty => "must not be empty",
+            Self::TooLong => "must be at most 255 bytes",
+            Self::ControlCharacter => "must not contain control characters",
+            Self::Backslash => "must not contain `\\`",
+            Self::DotSegment => "must not contain `..` or a `.` segment",
+            Self::Separator => "must not contain `/` without recorder tenancy",
+            Self::EmptySegment => "must not start or end with `/` or contain `//`",
+        }
+    }
+
+    /// Validation error of `field`, named after the rule
+    pub fn error(&self, field: &str) -> RecorderError {
+        RecorderError::validation(
+            Some(field),
+            format!(
+                "{field} name breaks rule {}: {}",
+                self.as_str(),
+                self.describe()
+            ),
+        )
+    }
+}
+
+impl std::fmt::Display for NameRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.as_str(), self.describe())
+    }
+}
+
+/// Naming rules of streams and records, the same where a recording is created and at
+/// every route taking a `{stream}` or `{record}`, so a name that records can be played
+/// back. Names become index keys, file paths and object keys
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NameRules {
+    /// Stream names may hold `/`-separated segments, with recorder tenancy
+    pub separators: bool,
+}
+
+impl NameRules {
+    /// Rules of a node with recorder tenancy, or of a service that serves several nodes
+    pub fn with_separators() -> Self {
+        Self { separators: true }
+    }
+
+    pub fn check_stream(&self, name: &str) -> Result<(), NameRule> {
+        check_name(name, self.separators)
+    }
+
+    /// Record names are always a single segment
+    pub fn check_record(&self, name: &str) -> Result<(), NameRule> {
+        check_name(name, false)
+    }
+}
+
+fn check_name(name: &str, separators: bool) -> Result<(), NameRule> {
+    if name.is_empty() {
+        return Err(NameRule::Empty);
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(NameRule::TooLong);
+    }
+    if name.chars().any(char::is_control) {
+        return Err(NameRule::ControlCharacter);
+    }
+    if name.contains('\\') {
+        return Err(NameRule::Backslash);
+    }
+    if name.contains("..") || name.split('/').any(|segment| segment == ".") {
+        return Err(NameRule::DotSegment);
+    }
+    if name.contains('/') {
+        if !separators {
+            return Err(NameRule::Separator);
+        }
+        if name.split('/').any(str::is_empty) {
+            return Err(NameRule::EmptySegment);
+        }
+    }
+    Ok(())
+}
+
+/// Request body for `POST /api/recorder/rename-stream`
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum RecordingStatus {
-    /// Recording is currently active
-    Active,
-    /// Recording completed successfully
-    Completed,
-    /// Recording failed or was interrupted
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RenameStreamRequest {
+    pub from: String,
+    pub to: String,
+    /// Also move the objects to the new stream's prefix, otherwise only the index is rewritten
+    #[serde(default)]
+    pub copy_objects: bool,
+}
+
+impl RenameStreamRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        for name in [&self.from, &self.to] {
+            if name.trim().is_empty() {
+                return Err(format!("invalid stream name: {name:?}"));
+            }
+            if let Err(rule) = NameRules::default().check_stream(name) {
+                return Err(format!("invalid stream name {name:?}: {rule}"));
+            }
+        }
+        if self.from == self.to {
+            return Err("from and to must differ".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of a stream rename on one node
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RenameStreamResponse {
+    /// Index entries moved to the new stream
+    pub renamed: usize,
+    /// Objects copied to the new prefix and removed from the old one
+    pub moved_objects: usize,
+    /// Recordings outside `{from}/` (custom `base_dir`) whose objects were left in place
+    pub kept_in_place: usize,
+}
+
+/// Request body for `POST /api/recorder/index/restore`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RestoreIndexRequest {
+    /// Backup object to install, e.g. `_index/edge-1/1760486400000000.jsonl`, the latest
+    /// backup of this node when unset
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Replace a local index that has changes newer than the backup
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Outcome of installing an index backup
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RestoreIndexResponse {
+    /// Backup object that was installed
+    pub key: String,
+    /// Entries in the installed index
+    pub entries: usize,
+    /// Entries the replaced index had
+    pub replaced: usize,
+}
+
+/// Request body for `POST /api/recorder/import`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ImportRecordingsRequest {
+    /// Storage prefix listed for recording dirs, e.g. `legacy/` or `cam/`
+    pub prefix: String,
+}
+
+impl ImportRecordingsRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if self
+            .prefix
+            .split('/')
+            .any(|segment| segment == "." || segment == "..")
+        {
+            return Err(format!("invalid prefix: {:?}", self.prefix));
+        }
+        Ok(())
+    }
+}
+
+/// Recording dir left out of an import
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ImportSkip {
+    /// Manifest found in the dir
+    pub mpd_path: String,
+    /// `{stream}/{record}` when the path names one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub reason: String,
+}
+
+/// Outcome of `POST /api/recorder/import`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ImportRecordingsResponse {
+    /// Nothing was added, `imported` lists what would be
+    pub dry_run: bool,
+    /// `{stream}/{record}` of the entries added
+    pub imported: Vec<String>,
+    /// Collisions with existing entries and dirs that can't be indexed
+    pub skipped: Vec<ImportSkip>,
+}
+
+/// `dry_run` query of the destructive recorder endpoints
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct DryRunQuery {
+    /// Report what would be affected without changing anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Destructive operation on recordings or the index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOperation {
+    /// Recording moved to the trash
+    Trash,
+    /// Recording deleted with `permanent=true`
+    Purge,
+    /// Acked entries deleted by liveman
+    DeleteAcked,
+    RenameStream,
+    RestoreIndex,
+    /// Recording deleted by the sweep past its retention class
+    Expire,
+    /// Recording deleted by the sweep over `retention.max_bytes`
+    Quota,
+    /// Recording purged from the trash after `trash_retention_days`
+    EmptyTrash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Succeeded,
+    /// Refused before anything changed, e.g. a recording still being written
+    Rejected,
+    /// Failed part way, some of the keys may be affected
     Failed,
-    /// Recording was acknowledged by manager
-    Acked,
 }
 
-impl std::fmt::Display for RecordingStatus {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+/// Line of the audit log of destructive operations
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuditRecord {
+    /// UNIX microseconds
+    pub ts: i64,
+    pub operation: AuditOperation,
+    /// Token subject of the request, `*` for static tokens and without auth, or
+    /// `system/retention`
+    pub actor: String,
+    /// `{stream}/{record}` of the recordings affected, the backup key of a restore
+    pub keys: Vec<String>,
+    /// Objects deleted or moved in storage
+    pub objects: u64,
+    /// Bytes of the objects deleted, 0 when not measured
+    pub bytes: u64,
+    pub outcome: AuditOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// What a destructive request would affect, returned with `dry_run=true`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DryRunResponse {
+    pub operation: AuditOperation,
+    /// `{stream}/{record}` of the recordings that would be affected
+    pub keys: Vec<String>,
+    /// Objects that would be deleted or moved in storage
+    pub objects: u64,
+    /// Bytes of the objects that would be deleted
+    pub bytes: u64,
+}
+
+/// Query of `GET /api/recorder/audit`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct AuditQuery {
+    /// Only records after this UNIX microsecond timestamp
+    #[serde(default)]
+    pub since_ts: Option<i64>,
+}
+
+/// Journal read by `GET /api/recorder/journal/{journal}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum JournalKind {
+    /// The index log, `index.json`
+    Index,
+    /// The audit log, `index.audit.json`
+    Audit,
+}
+
+/// Bytes of a journal read returns when the request doesn't say
+pub const JOURNAL_DEFAULT_LIMIT_BYTES: u64 = 1024 * 1024;
+
+/// Most bytes of a journal one read returns
+pub const JOURNAL_MAX_LIMIT_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Response header of a journal read carrying the generation of the file read, which
+/// changes whenever the file is replaced, e.g. compacted or rotated
+pub const JOURNAL_GENERATION_HEADER: &str = "x-journal-generation";
+
+/// Response header of a journal read carrying the offset to read from next
+pub const JOURNAL_NEXT_OFFSET_HEADER: &str = "x-journal-next-offset";
+
+/// Response header of a journal read carrying the size of the file when it was read
+pub const JOURNAL_SIZE_HEADER: &str = "x-journal-size";
+
+/// Query of `GET /api/recorder/journal/{journal}`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct JournalQuery {
+    /// Byte offset to read from, the `x-journal-next-offset` of the previous read
+    #[serde(default)]
+    pub offset: u64,
+    /// Most bytes to return, 1 MiB by default and at most 16 MiB. Only complete lines
+    /// are returned, so it must exceed the longest line
+    #[serde(default)]
+    pub limit_bytes: Option<u64>,
+}
+
+impl JournalQuery {
+    /// `limit_bytes` with its default, capped
+    pub fn limit(&self) -> u64 {
+        self.limit_bytes
+            .unwrap_or(JOURNAL_DEFAULT_LIMIT_BYTES)
+            .clamp(1, JOURNAL_MAX_LIMIT_BYTES)
+    }
+}
+
+/// Audit records, oldest first
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuditLogResponse {
+    pub records: Vec<AuditRecord>,
+}
+
+/// Query of `GET /api/recorder/verify/{stream}/{record}`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct VerifyRecordingQuery {
+    /// Also compare SHA-256 of the objects present on both sides with equal size
+    #[serde(default)]
+    pub checksum: bool,
+}
+
+/// Object whose local and remote sizes differ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SizeMismatch {
+    pub path: String,
+    pub local_size: u64,
+    pub remote_size: u64,
+}
+
+/// Local files of a recording compared with the objects under its prefix in storage
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct VerifyRecordingResponse {
+    pub stream: String,
+    pub record: String,
+    pub record_dir: String,
+    /// Every local file is stored remotely with the same size (and checksum, if compared)
+    pub complete: bool,
+    /// The recording is still active or has queued uploads, the diff will change
+    pub provisional: bool,
+    /// Uploads of the recording still queued on this node
+    pub pending_uploads: usize,
+    pub local_files: usize,
+    pub remote_objects: usize,
+    /// Paths relative to `record_dir`, sorted
+    pub local_only: Vec<String>,
+    /// Remote objects without a local copy, e.g. after local retention pruned it
+    pub remote_only: Vec<String>,
+    pub size_mismatch: Vec<SizeMismatch>,
+    /// Objects whose SHA-256 differs, `None` unless checksums were requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum_mismatch: Option<Vec<String>>,
+    /// When the comparison ran, UNIX microseconds
+    pub checked_at: i64,
+}
+
+/// Caption track stored by `PUT /api/record/{stream}/{record}/captions/{lang}`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CaptionsResponse {
+    pub stream: String,
+    pub record: String,
+    pub lang: String,
+    /// Key of the WebVTT sidecar
+    pub path: String,
+    pub cues: usize,
+    /// Whether an earlier track of the same language was replaced
+    pub replaced: bool,
+    /// Every caption language of the recording, this one included
+    pub captions: Vec<String>,
+}
+
+/// Manifest of an interrupted recording rebuilt from the segments actually stored
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RepairRecordingResponse {
+    pub stream: String,
+    pub record: String,
+    pub mpd_path: String,
+    /// Segments in the repaired manifest, all tracks
+    pub segments: usize,
+    /// Segments the manifest was missing before the repair
+    pub added_segments: usize,
+    /// Length of the longest track, also written to the index entry
+    pub duration_ms: i32,
+}
+
+/// Kind of index transition carried by a recorder event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RecorderEventKind {
+    /// A new recording entry was added to the index
+    Created,
+    /// The entry status changed (completed, failed, acked, missing)
+    Status,
+    /// Other entry fields changed, also used for entries replayed on resume
+    Updated,
+    /// All pending uploads of a finished recording completed
+    Uploaded,
+    /// The entry was removed from the index
+    Deleted,
+}
+
+impl RecorderEventKind {
+    pub fn as_str(&self) -> &'static str {
         match self {
-            RecordingStatus::Active => write!(f, "Active"),
-            RecordingStatus::Completed => write!(f, "Completed"),
-            RecordingStatus::Failed => write!(f, "Failed"),
-            RecordingStatus::Acked => write!(f, "Acked"),
+            RecorderEventKind::Created => "created",
+            RecorderEventKind::Status => "status",
+            RecorderEventKind::Updated => "updated",
+            RecorderEventKind::Uploaded => "uploaded",
+            RecorderEventKind::Deleted => "deleted",
         }
     }
 }
 
-impl FromStr for RecordingStatus {
+impl FromStr for RecorderEventKind {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "Active" => Ok(RecordingStatus::Active),
-            "Completed" => Ok(RecordingStatus::Completed),
-            "Failed" => Ok(RecordingStatus::Failed),
-            "Acked" => Ok(RecordingStatus::Acked),
+            "created" => Ok(RecorderEventKind::Created),
+            "status" => Ok(RecorderEventKind::Status),
+            "updated" => Ok(RecorderEventKind::Updated),
+            "uploaded" => Ok(RecorderEventKind::Uploaded),
+            "deleted" => Ok(RecorderEventKind::Deleted),
             _ => Err(()),
         }
     }
 }
 
-/// Request to pull recording sessions
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct PullRecordingsRequest {
-    /// Stream name filter (None for all streams)
+/// Query of `GET /api/recorder/events`, filters applied by the node
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct EventsQuery {
+    /// Only events of streams matching this glob, e.g. `cam-*`
+    #[serde(default)]
     pub stream: Option<String>,
-    /// Only get sessions updated since this timestamp
-    pub since_ts: Option<i64>,
-    /// Maximum number of sessions to return
-    pub limit: u32,
+    /// Only events of these kinds, comma separated, e.g. `created,status`
+    #[serde(default)]
+    pub types: Option<String>,
+    /// Name labelling the subscriber's metrics, the token's subject by default
+    #[serde(default)]
+    pub subscriber: Option<String>,
+}
+
+impl EventsQuery {
+    /// Kinds listed in `types`, `None` for every kind
+    pub fn kinds(&self) -> Result<Option<Vec<RecorderEventKind>>, String> {
+        let Some(types) = self.types.as_deref() else {
+            return Ok(None);
+        };
+        types
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(|t| t.parse().map_err(|_| format!("unknown event type '{t}'")))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some)
+    }
+}
+
+/// SSE event name telling a subscriber it was sent too slowly to keep up
+pub const EVENTS_LAGGED: &str = "lagged";
+
+/// Data of a `lagged` event: events were dropped for the subscriber, which resynchronizes
+/// from the index, e.g. by reconnecting with `Last-Event-ID` or pulling the recordings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct EventsLagged {
+    /// Events dropped since the last one sent
+    pub missed: u64,
+    /// Id of the last event sent before them
+    pub last_event_id: Option<i64>,
+}
+
+/// One index transition served on the recorder events stream.
+///
+/// `id` is sent as the SSE event id; for entry changes it is the entry's `updated_at`,
+/// so a client resuming with `Last-Event-ID` gets every entry changed after it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RecorderEvent {
+    pub id: i64,
+    pub kind: RecorderEventKind,
+    pub entry: RecordingIndexEntry,
+}
+
+/// Batch of index transitions pushed by a liveion node to `POST /api/recorder/ingest`
+///
+/// The request is authenticated with the token liveman has configured for `node_alias`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct IngestRecordingsRequest {
+    pub node_alias: String,
+    pub events: Vec<RecorderEvent>,
+}
+
+/// Outcome of an ingest batch
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct IngestRecordingsResponse {
+    /// Transitions written to the catalog
+    pub applied: usize,
+    /// Duplicates, transitions older than the catalog row, and deletions
+    pub skipped: usize,
 }
 
 /// Response containing recording sessions
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct PullRecordingsResponse {
     /// Recording sessions
     pub sessions: Vec<RecordingSession>,
     /// Timestamp of the newest session (for next pull)
     pub last_ts: Option<i64>,
+    /// Cursor for the next page in the requested order, absent on the last page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// Recordings running on the node, absent from older nodes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<RecordingCapacity>,
+    /// The node's [`RecorderStats`], absent from older nodes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<RecorderStats>,
+    /// The node's [`RecorderHealth`], absent from older nodes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<RecorderHealth>,
+}
+
+/// Sort order for recording listings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ListOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl ListOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ListOrder::Asc => "asc",
+            ListOrder::Desc => "desc",
+        }
+    }
+}
+
+/// Position of the last entry of a page, ordered by `(updated_at, key)`.
+///
+/// Encoded as `order:updated_at:key`; the order is part of the cursor so a
+/// page fetched newest-first can't be continued oldest-first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListCursor {
+    pub order: ListOrder,
+    pub updated_at: i64,
+    pub key: String,
+}
+
+impl ListCursor {
+    pub fn encode(&self) -> String {
+        format!("{}:{}:{}", self.order.as_str(), self.updated_at, self.key)
+    }
+
+    pub fn decode(value: &str, order: ListOrder) -> Result<Self, String> {
+        let mut parts = value.splitn(3, ':');
+        let (Some(cursor_order), Some(updated_at), Some(key)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err("malformed cursor".to_string());
+        };
+        let cursor_order = match cursor_order {
+            "asc" => ListOrder::Asc,
+            "desc" => ListOrder::Desc,
+            _ => return Err("malformed cursor".to_string()),
+        };
+        let updated_at = updated_at
+            .parse::<i64>()
+            .map_err(|_| "malformed cursor".to_string())?;
+        if cursor_order != order {
+            return Err(format!(
+                "cursor was issued for order={} but order={} was requested",
+                cursor_order.as_str(),
+                order.as_str()
+            ));
+        }
+        Ok(Self {
+            order,
+            updated_at,
+            key: key.to_string(),
+        })
+    }
+
+    /// Whether an entry comes strictly after this cursor in its order
+    fn admits(&self, updated_at: i64, key: &str) -> bool {
+        let pos = (updated_at, key).cmp(&(self.updated_at, self.key.as_str()));
+        match self.order {
+            ListOrder::Asc => pos.is_gt(),
+            ListOrder::Desc => pos.is_lt(),
+        }
+    }
+}
+
+/// Response header carrying the next page cursor on listings that return plain arrays
+pub const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+/// Response header of `GET /api/recordings` carrying the journal generation the listing
+/// was read at: the node's latest [`RecordingIndexEntry::seq`]
+pub const INDEX_SEQ_HEADER: &str = "x-index-seq";
+
+/// Order entries by `(updated_at, key)`, skip up to `cursor` and keep `limit` entries.
+///
+/// Returns the page and the cursor of the next page, if there is one.
+pub fn page_entries(
+    rows: Vec<RecordingIndexEntry>,
+    order: ListOrder,
+    cursor: Option<&ListCursor>,
+    limit: usize,
+) -> (Vec<RecordingIndexEntry>, Option<ListCursor>) {
+    page_by(rows, order, cursor, limit, |r| (r.updated_at, r.key()))
+}
+
+/// [`page_entries`] for any row type, `position` returns its `(updated_at, key)`
+pub fn page_by<T>(
+    rows: Vec<T>,
+    order: ListOrder,
+    cursor: Option<&ListCursor>,
+    limit: usize,
+    position: impl Fn(&T) -> (i64, String),
+) -> (Vec<T>, Option<ListCursor>) {
+    let mut rows: Vec<((i64, String), T)> = rows
+        .into_iter()
+        .map(|r| (position(&r), r))
+        .filter(|((updated_at, key), _)| cursor.is_none_or(|c| c.admits(*updated_at, key)))
+        .collect();
+    rows.sort_by(|a, b| a.0.cmp(&b.0));
+    if order == ListOrder::Desc {
+        rows.reverse();
+    }
+
+    let has_more = rows.len() > limit;
+    rows.truncate(limit);
+    let next = if has_more {
+        rows.last().map(|((updated_at, key), _)| ListCursor {
+            order,
+            updated_at: *updated_at,
+            key: key.clone(),
+        })
+    } else {
+        None
+    };
+    (rows.into_iter().map(|(_, r)| r).collect(), next)
 }
 
 /// Recording key for ack/delete operations
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct RecordingKey {
     pub stream: String,
     pub record: String,
+    /// Only act on the entry while its `updated_at` is this one. One mismatch fails the
+    /// whole request with [`RecorderError::VersionMismatch`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub if_match: Option<i64>,
 }
 
-/// Request to acknowledge recordings in index
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl RecordingKey {
+    /// Recording an object key belongs to, `[{namespace}/]{stream}/{record}/{file}` with
+    /// a numeric record id. `None` for keys of any other shape, such as `_shared/` objects,
+    /// custom `base_dir` layouts or keys with `.` and `..` segments
+    pub fn from_path(path: &str) -> Option<Self> {
+        let segments: Vec<&str> = path.split('/').collect();
+        if segments
+            .iter()
+            .any(|s| s.is_empty() || *s == "." || *s == "..")
+        {
+            return None;
+        }
+        let [.., stream, record, _file] = segments.as_slice() else {
+            return None;
+        };
+        if !record.bytes().all(|b| b.is_ascii_digit()) || stream.starts_with('_') {
+            return None;
+        }
+        Some(Self {
+            stream: stream.to_string(),
+            record: record.to_string(),
+            if_match: None,
+        })
+    }
+}
+
+/// Keys listed in [`AckRecordingsResponse::sample`] at most
+pub const ACK_SAMPLE_LEN: usize = 20;
+
+/// Request to acknowledge recordings in index, by key and/or by filter
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct AckRecordingsRequest {
+    #[serde(default)]
     pub records: Vec<RecordingKey>,
+    /// Also ack every unacked entry matching, expanded on the node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<AckFilter>,
+}
+
+impl AckRecordingsRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(filter) = self.filter.as_ref()
+            && !filter.is_narrowing()
+        {
+            return Err("ack filter needs stream, updated_before_ts or status".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Entries an [`AckRecordingsRequest`] acks without listing them. Conditions are
+/// combined, trashed entries are never matched
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AckFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+    /// Only entries last updated before this timestamp (UNIX microseconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_before_ts: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<RecordingStatus>,
+}
+
+impl AckFilter {
+    /// Whether any condition is set: an empty filter would ack the whole index
+    pub fn is_narrowing(&self) -> bool {
+        self.stream.as_ref().is_some_and(|s| !s.is_empty())
+            || self.updated_before_ts.is_some()
+            || self.status.is_some()
+    }
+
+    pub fn matches(&self, entry: &RecordingIndexEntry) -> bool {
+        self.is_narrowing()
+            && !entry.is_trashed()
+            && self.stream.as_ref().is_none_or(|s| s == &entry.stream)
+            && self
+                .updated_before_ts
+                .is_none_or(|ts| entry.updated_at < ts)
+            && self.status.as_ref().is_none_or(|s| s == &entry.status)
+    }
 }
 
 /// Response for ack
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct AckRecordingsResponse {
     pub acked: usize,
+    /// Highest `updated_at` the acked entries had before the ack, a sync cursor may
+    /// advance to it without skipping entries it has not seen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_updated_at: Option<i64>,
+    /// Up to [`ACK_SAMPLE_LEN`] acked keys (`{stream}/{record}`), sorted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sample: Vec<String>,
 }
 
 /// Request to delete recordings from index (only acked entries are removed)
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct DeleteRecordingsRequest {
     pub records: Vec<RecordingKey>,
 }
 
 /// Response for delete
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct DeleteRecordingsResponse {
     pub deleted: usize,
 }
 
+/// Query of `DELETE /api/record/{stream}/{record}`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct DeleteRecordingQuery {
+    /// Delete the objects and the entry right away instead of moving it to the trash
+    #[serde(default)]
+    pub permanent: bool,
+    /// Report what would be affected without changing anything
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Only delete the recording while its `updated_at` is this one
+    #[serde(default)]
+    pub if_match: Option<i64>,
+}
+
+/// Query of `POST /api/record/{stream}/{record}/restore`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct RestoreRecordingQuery {
+    /// Only restore the recording while its `updated_at` is this one
+    #[serde(default)]
+    pub if_match: Option<i64>,
+}
+
 /// Response containing recording sessions
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RecordingSessionResponse {
@@ -159,13 +2136,21 @@ pub struct PullSegmentsResponse {
 
 /// Request body to start recording a stream (Live777 node)
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct StartRecordRequest {
     /// Optional base directory for storing recordings, e.g. "web-0/2025/05/05"
     pub base_dir: Option<String>,
+    /// Retention class overriding the matching auto-record rule
+    #[serde(default)]
+    pub retention_class: Option<RetentionClass>,
+    /// Priority overriding the matching auto-record rule
+    #[serde(default)]
+    pub priority: Option<u8>,
 }
 
 /// Response body after starting recording
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct StartRecordResponse {
     pub id: String,
     #[serde(default)]
@@ -174,4 +2159,507 @@ pub struct StartRecordResponse {
     pub record_dir: String,
     /// Absolute path (within storage) to the MPD manifest for this session
     pub mpd_path: String,
+    /// Retention class the recording was started with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_class: Option<RetentionClass>,
+    /// Priority the recording was started with
+    #[serde(default = "default_priority")]
+    pub priority: u8,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn entry_at(record: &str, updated_at: i64) -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            uuid: String::new(),
+            record: record.to_string(),
+            updated_at,
+            ..entry()
+        }
+    }
+
+    fn records(rows: &[RecordingIndexEntry]) -> Vec<&str> {
+        rows.iter().map(|r| r.record.as_str()).collect()
+    }
+
+    fn walk(order: ListOrder, limit: usize) -> Vec<String> {
+        // Two entries share updated_at=20 to exercise the key tie-breaker
+        let rows = vec![
+            entry_at("a", 10),
+            entry_at("b", 20),
+            entry_at("c", 20),
+            entry_at("d", 30),
+            entry_at("e", 40),
+        ];
+        let mut seen = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let parsed = cursor
+                .as_deref()
+                .map(|c| ListCursor::decode(c, order).unwrap());
+            let (page, next) = page_entries(rows.clone(), order, parsed.as_ref(), limit);
+            assert!(page.len() <= limit);
+            seen.extend(records(&page).into_iter().map(str::to_string));
+            match next {
+                Some(next) => cursor = Some(next.encode()),
+                None => break,
+            }
+        }
+        seen
+    }
+
+    #[test]
+    fn paging_visits_every_entry_once_in_both_orders() {
+        for limit in 1..=5 {
+            assert_eq!(walk(ListOrder::Asc, limit), ["a", "b", "c", "d", "e"]);
+            assert_eq!(walk(ListOrder::Desc, limit), ["e", "d", "c", "b", "a"]);
+        }
+    }
+
+    #[test]
+    fn desc_first_page_is_newest() {
+        let rows = vec![entry_at("a", 10), entry_at("b", 30), entry_at("c", 20)];
+        let (page, next) = page_entries(rows, ListOrder::Desc, None, 2);
+        assert_eq!(records(&page), ["b", "c"]);
+        assert_eq!(next.unwrap().encode(), "desc:20:camera01/c");
+    }
+
+    #[test]
+    fn mixing_orders_across_pages_is_rejected() {
+        let rows = vec![entry_at("a", 10), entry_at("b", 20)];
+        let (_, next) = page_entries(rows, ListOrder::Asc, None, 1);
+        let req = PullRecordingsRequest {
+            stream: None,
+            since_ts: None,
+            limit: 1,
+            order: ListOrder::Desc,
+            cursor: Some(next.unwrap().encode()),
+            include_trashed: false,
+        };
+        assert!(req.parsed_cursor().is_err());
+        assert!(ListCursor::decode("sideways:1:k", ListOrder::Asc).is_err());
+        assert!(ListCursor::decode("asc:notanumber:k", ListOrder::Asc).is_err());
+    }
+
+    #[test]
+    fn order_defaults_to_asc() {
+        let req: PullRecordingsRequest = serde_html_form::from_str("limit=10").unwrap();
+        assert_eq!(req.order, ListOrder::Asc);
+        assert_eq!(req.parsed_cursor(), Ok(None));
+    }
+
+    fn entry() -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            uuid: String::new(),
+            record: "1718200000".to_string(),
+            stream: "camera01".to_string(),
+            record_dir: "camera01/1718200000".to_string(),
+            mpd_path: "camera01/1718200000/manifest.mpd".to_string(),
+            start_ts: 1_718_200_000_000_000,
+            end_ts: None,
+            duration_ms: None,
+            status: RecordingStatus::Completed,
+            node_alias: None,
+            updated_at: 0,
+            note: None,
+            labels: vec!["night".to_string()],
+            continues: None,
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant: None,
+            size: None,
+            captions: Vec::new(),
+            trigger: None,
+            imported: false,
+            seq: 0,
+        }
+    }
+
+    #[test]
+    fn test_update_recording_apply() {
+        let mut e = entry();
+        let patch = UpdateRecordingRequest {
+            note: Some("false alarm".to_string()),
+            labels: Some(LabelsPatch {
+                add: vec!["ticket-42".to_string(), "night".to_string()],
+                remove: vec!["night".to_string()],
+            }),
+            retention_class: None,
+            priority: None,
+            if_match: None,
+        };
+        patch.validate().unwrap();
+        patch.apply(&mut e).unwrap();
+        assert_eq!(e.note.as_deref(), Some("false alarm"));
+        assert_eq!(e.labels, vec!["ticket-42".to_string()]);
+
+        let clear = UpdateRecordingRequest {
+            note: Some(String::new()),
+            labels: None,
+            retention_class: None,
+            priority: None,
+            if_match: None,
+        };
+        clear.apply(&mut e).unwrap();
+        assert!(e.note.is_none());
+    }
+
+    #[test]
+    fn test_update_recording_validate() {
+        assert!(UpdateRecordingRequest::default().validate().is_err());
+        let long = UpdateRecordingRequest {
+            note: Some("x".repeat(MAX_NOTE_LEN + 1)),
+            labels: None,
+            retention_class: None,
+            priority: None,
+            if_match: None,
+        };
+        assert!(long.validate().is_err());
+        let blank = UpdateRecordingRequest {
+            note: None,
+            labels: Some(LabelsPatch {
+                add: vec![" ".to_string()],
+                remove: vec![],
+            }),
+            retention_class: None,
+            priority: None,
+            if_match: None,
+        };
+        assert!(blank.validate().is_err());
+    }
+
+    #[test]
+    fn test_rename_stream_validate() {
+        let req = |from: &str, to: &str| RenameStreamRequest {
+            from: from.to_string(),
+            to: to.to_string(),
+            copy_objects: false,
+        };
+        assert!(req("cam", "lobby").validate().is_ok());
+        assert!(req("cam", "cam").validate().is_err());
+        assert!(req("cam", "").validate().is_err());
+        assert!(req("cam", "a/b").validate().is_err());
+        assert!(req("..", "lobby").validate().is_err());
+        assert!(req("cam", "lob\nby").validate().is_err());
+    }
+
+    /// Malformed names that must fail with each rule, with and without separators
+    pub(crate) fn malformed_names() -> Vec<(String, NameRule)> {
+        vec![
+            (String::new(), NameRule::Empty),
+            ("a".repeat(MAX_NAME_LENGTH + 1), NameRule::TooLong),
+            ("x".repeat(10_000), NameRule::TooLong),
+            ("cam\n1".to_string(), NameRule::ControlCharacter),
+            ("cam\u{0}".to_string(), NameRule::ControlCharacter),
+            ("\u{7f}".to_string(), NameRule::ControlCharacter),
+            ("cam\\1".to_string(), NameRule::Backslash),
+            ("..".to_string(), NameRule::DotSegment),
+            (".".to_string(), NameRule::DotSegment),
+            ("a..b".to_string(), NameRule::DotSegment),
+            ("../etc".to_string(), NameRule::DotSegment),
+            ("a/./b".to_string(), NameRule::DotSegment),
+            ("/cam".to_string(), NameRule::EmptySegment),
+            ("cam/".to_string(), NameRule::EmptySegment),
+            ("acme//cam".to_string(), NameRule::EmptySegment),
+        ]
+    }
+
+    #[test]
+    fn test_name_rules() {
+        let single = NameRules::default();
+        let tenanted = NameRules::with_separators();
+        for name in [
+            "cam",
+            "a.b",
+            "é-cam_1 ~",
+            "1760486400",
+            &"a".repeat(MAX_NAME_LENGTH),
+        ] {
+            assert_eq!(single.check_stream(name), Ok(()), "{name:?}");
+            assert_eq!(single.check_record(name), Ok(()), "{name:?}");
+            assert_eq!(tenanted.check_stream(name), Ok(()), "{name:?}");
+        }
+
+        assert_eq!(tenanted.check_stream("acme/cam"), Ok(()));
+        assert_eq!(single.check_stream("acme/cam"), Err(NameRule::Separator));
+        assert_eq!(tenanted.check_record("acme/cam"), Err(NameRule::Separator));
+
+        for (name, rule) in malformed_names() {
+            let expected = match rule {
+                NameRule::EmptySegment => NameRule::Separator,
+                rule => rule,
+            };
+            assert_eq!(single.check_stream(&name), Err(expected), "{name:?}");
+            assert_eq!(single.check_record(&name), Err(expected), "{name:?}");
+            assert_eq!(tenanted.check_stream(&name), Err(rule), "{name:?}");
+        }
+
+        let RecorderError::Validation { field, message } = NameRule::Separator.error("stream")
+        else {
+            panic!("not a validation error");
+        };
+        assert_eq!(field.as_deref(), Some("stream"));
+        assert!(message.contains("separator"), "{message}");
+    }
+
+    #[test]
+    fn test_ack_filter() {
+        let req = |filter: AckFilter| AckRecordingsRequest {
+            records: Vec::new(),
+            filter: Some(filter),
+        };
+        assert!(AckRecordingsRequest::default().validate().is_ok());
+        assert!(req(AckFilter::default()).validate().is_err());
+        let empty_stream = AckFilter {
+            stream: Some(String::new()),
+            ..Default::default()
+        };
+        assert!(req(empty_stream.clone()).validate().is_err());
+        assert!(!empty_stream.matches(&entry()));
+
+        let before = AckFilter {
+            updated_before_ts: Some(20),
+            ..Default::default()
+        };
+        assert!(req(before.clone()).validate().is_ok());
+        assert!(before.matches(&entry_at("a", 10)));
+        assert!(!before.matches(&entry_at("b", 20)));
+        let trashed = RecordingIndexEntry {
+            uuid: String::new(),
+            status: RecordingStatus::Trashed,
+            ..entry_at("c", 10)
+        };
+        assert!(!before.matches(&trashed));
+
+        let filter = AckFilter {
+            stream: Some("camera01".to_string()),
+            status: Some(RecordingStatus::Completed),
+            ..before
+        };
+        assert!(filter.matches(&entry_at("a", 10)));
+        let active = RecordingIndexEntry {
+            uuid: String::new(),
+            status: RecordingStatus::Active,
+            ..entry_at("a", 10)
+        };
+        assert!(!filter.matches(&active));
+        let other = RecordingIndexEntry {
+            uuid: String::new(),
+            stream: "camera02".to_string(),
+            ..entry_at("a", 10)
+        };
+        assert!(!filter.matches(&other));
+    }
+
+    #[test]
+    fn test_push_media_info() {
+        let video = |width, framerate| VideoInfo {
+            codec: "avc1.42E01F".to_string(),
+            width,
+            height: 720,
+            framerate,
+        };
+        let audio = AudioInfo {
+            codec: "opus".to_string(),
+            channels: 2,
+            sample_rate: 48_000,
+        };
+        let mut list = Vec::new();
+
+        // Audio starts first, video and then its framerate complete the same snapshot
+        assert!(push_media_info(
+            &mut list,
+            MediaInfo {
+                since_ts: 1,
+                video: None,
+                audio: Some(audio.clone()),
+            }
+        ));
+        for (since_ts, framerate) in [(2, None), (3, Some(30.0))] {
+            assert!(push_media_info(
+                &mut list,
+                MediaInfo {
+                    since_ts,
+                    video: Some(video(1280, framerate)),
+                    audio: Some(audio.clone()),
+                }
+            ));
+        }
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].since_ts, 1);
+        assert_eq!(list[0].video, Some(video(1280, Some(30.0))));
+        let same = list[0].clone();
+        assert!(!push_media_info(&mut list, same));
+
+        // A resolution change is a renegotiation
+        assert!(push_media_info(
+            &mut list,
+            MediaInfo {
+                since_ts: 4,
+                video: Some(video(640, Some(30.0))),
+                audio: Some(audio),
+            }
+        ));
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[1].since_ts, 4);
+    }
+
+    #[test]
+    fn test_retention_class() {
+        let class: RetentionClass = "30d".parse().unwrap();
+        assert_eq!(class.tagging(), "retention=30d");
+        assert_eq!(
+            class.period(),
+            Some(std::time::Duration::from_secs(30 * 86_400))
+        );
+        let end = 1_000_000;
+        let day = 86_400 * 1_000_000;
+        assert!(!class.expired(end, end + 29 * day));
+        assert!(class.expired(end, end + 30 * day));
+
+        let forever: RetentionClass = "forever".parse().unwrap();
+        assert_eq!(forever.period(), None);
+        assert!(!forever.expired(0, i64::MAX));
+        assert_eq!(
+            "2w".parse::<RetentionClass>().unwrap().period(),
+            Some(std::time::Duration::from_secs(14 * 86_400))
+        );
+        assert!("1y".parse::<RetentionClass>().is_ok());
+
+        for invalid in ["", "d", "0d", "30", "-1d", "30m", "1.5y", "Forever"] {
+            assert!(invalid.parse::<RetentionClass>().is_err(), "{invalid}");
+        }
+        assert!(RetentionClass::try_from("never".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_effective_end_ts() {
+        let mut e = entry();
+        assert_eq!(e.effective_end_ts(), None);
+        e.duration_ms = Some(60_000);
+        assert_eq!(e.effective_end_ts(), Some(e.start_ts + 60_000_000));
+        // Written before end_ts was clamped: an NTP step put it before the start
+        e.end_ts = Some(e.start_ts - 3_600_000_000);
+        assert_eq!(e.effective_end_ts(), Some(e.start_ts));
+        e.clock_skew_detected = true;
+        assert_eq!(e.effective_end_ts(), Some(e.start_ts + 60_000_000));
+    }
+
+    #[test]
+    fn test_upload_lag_merge() {
+        // Nodes predating upload lag report none
+        let old: RecorderHealth =
+            serde_json::from_str(r#"{"status":"ok","reasons":[],"computed_at":1}"#).unwrap();
+        assert_eq!(old.upload_lag, None);
+
+        let mut cluster = UploadLag::default();
+        cluster.merge(&UploadLag {
+            samples: 10,
+            p50_ms: Some(4_000),
+            p99_ms: Some(30_000),
+            oldest_pending_ms: None,
+            worst_ms: 31_000,
+        });
+        cluster.merge(&UploadLag {
+            samples: 5,
+            p50_ms: Some(6_000),
+            p99_ms: Some(9_000),
+            oldest_pending_ms: Some(400_000),
+            worst_ms: 400_000,
+        });
+        assert_eq!(
+            cluster,
+            UploadLag {
+                samples: 15,
+                p50_ms: Some(6_000),
+                p99_ms: Some(30_000),
+                oldest_pending_ms: Some(400_000),
+                worst_ms: 400_000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_recording_key_from_path() {
+        let key = RecordingKey::from_path("cam/1700000000/v_seg_0001.m4s").unwrap();
+        assert_eq!(
+            (key.stream.as_str(), key.record.as_str()),
+            ("cam", "1700000000")
+        );
+        let key = RecordingKey::from_path("edge-1/cam/1700000000/manifest.mpd").unwrap();
+        assert_eq!(key.stream, "cam");
+
+        for path in [
+            "",
+            "cam/1700000000",
+            "cam/latest/manifest.mpd",
+            "_shared/init/ab12.mp4",
+            "/cam/1700000000/manifest.mpd",
+            "cam//1700000000/manifest.mpd",
+            "other/../cam/1700000000/manifest.mpd",
+            "cam/1700000000/../../other/1700000000/manifest.mpd",
+            "cam/./1700000000/manifest.mpd",
+        ] {
+            assert!(RecordingKey::from_path(path).is_none(), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_recorder_error_retry() {
+        let busy = RecorderError::IndexBusy {
+            retry_after_seconds: 1,
+            message: "index locked".to_string(),
+        };
+        assert_eq!((busy.status_code(), busy.is_retryable()), (503, true));
+        let missing = RecorderError::not_found("cam", "1700000000");
+        assert_eq!(
+            (missing.status_code(), missing.is_retryable()),
+            (404, false)
+        );
+        assert_eq!(missing.to_string(), "recording cam/1700000000 not found");
+        let invalid = RecorderError::validation(Some("cursor"), "bad cursor");
+        assert_eq!(
+            (invalid.status_code(), invalid.is_retryable()),
+            (400, false)
+        );
+        // Not retried as is: the caller refreshes to the current entry first
+        let stale = RecorderError::version_mismatch(1, entry());
+        assert_eq!((stale.status_code(), stale.is_retryable()), (409, false));
+        let body = serde_json::to_value(&stale).unwrap();
+        assert_eq!(body["error"], "version_mismatch");
+        assert_eq!(body["current"]["updated_at"], entry().updated_at);
+        assert_eq!(
+            serde_json::from_value::<RecorderError>(body).unwrap(),
+            stale
+        );
+    }
+
+    #[test]
+    fn test_capabilities_require() {
+        let caps = RecorderCapabilities {
+            features: [(capability::RECORD.to_string(), 1)].into(),
+            ..Default::default()
+        };
+        assert!(caps.require(capability::RECORD, "edge-1").is_ok());
+        let err = caps.require(capability::TRASH, "edge-1").unwrap_err();
+        assert_eq!((err.status_code(), err.is_retryable()), (501, false));
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            serde_json::json!({
+                "error": "unsupported",
+                "feature": "trash",
+                "node": "edge-1",
+                "message": "node edge-1 does not support trash",
+            })
+        );
+    }
 }
@@ -14,6 +14,35 @@ pub struct Stream {
     pub publish: PubSub,
     pub subscribe: PubSub,
     pub codecs: Vec<Codec>,
+    /// Recording state, absent when the node is built without the recorder
+    #[serde(default, flatten, skip_serializing_if = "Option::is_none")]
+    pub recording: Option<StreamRecording>,
+}
+
+/// Whether a stream is being recorded, flattened into [`Stream`]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamRecording {
+    pub recording: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_id: Option<String>,
+    /// Start of the current recording, UNIX microseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recording_since_ts: Option<i64>,
+    /// Media segments stored for the current recording so far
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segments_written: Option<u64>,
+}
+
+impl StreamRecording {
+    pub fn idle() -> Self {
+        Self {
+            recording: false,
+            record_id: None,
+            recording_since_ts: None,
+            segments_written: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
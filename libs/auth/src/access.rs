@@ -29,7 +29,15 @@ pub async fn access_middleware(request: Request, next: Next) -> Response {
             (id, &Method::POST, path) if path == api::path::cascade(&id) => {
                 Access::from(claims.mode).x
             }
-            (id, _, _) if id == ANY_ID => true,
+            (id, _, _) if id == ANY_ID && claims.streams.is_none() => true,
+            // The presign handler checks the path against the claim
+            (_, &Method::POST, "/api/storage/presign") if claims.streams.is_some() => true,
+            // So do the handlers of objects proxied through liveman
+            (_, _, path)
+                if path.starts_with("/api/storage/objects/") && claims.streams.is_some() =>
+            {
+                true
+            }
             (id, &Method::POST, path) if path == "/token" && id == ANY_ID => {
                 Access::from(claims.mode).r
                     && Access::from(claims.mode).w
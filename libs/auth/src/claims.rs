@@ -1,12 +1,78 @@
 use std::fmt::Display;
 
+use api::recorder::RecordingKey;
 use serde::{Deserialize, Serialize};
 
+use crate::ANY_ID;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub id: String,
     pub exp: u64,
     pub mode: Mode,
+    /// Stream prefixes recordings are limited to, `None` for the stream `id` names
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub streams: Option<Vec<String>>,
+    /// Tenants recordings are limited to, the first segment of their object keys with
+    /// the recorder's tenancy. `None` for every tenant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenants: Option<Vec<String>>,
+}
+
+impl Claims {
+    /// Whether the token may see recordings of `stream`
+    pub fn allows_stream(&self, stream: &str) -> bool {
+        match self.streams {
+            Some(ref prefixes) => prefixes.iter().any(|prefix| prefix_matches(prefix, stream)),
+            // A tenant's token sees every stream of its tenants
+            None if self.tenants.is_some() => true,
+            None => self.id == ANY_ID || self.id == stream,
+        }
+    }
+
+    /// Whether the token may see recordings of `tenant`, `None` for recordings made
+    /// without tenancy
+    pub fn allows_tenant(&self, tenant: Option<&str>) -> bool {
+        match self.tenants {
+            Some(ref tenants) => {
+                tenant.is_some_and(|tenant| tenants.iter().any(|t| t == ANY_ID || t == tenant))
+            }
+            None => true,
+        }
+    }
+
+    /// Whether the token may see a recording of `stream` in `tenant`
+    pub fn allows_recording(&self, stream: &str, tenant: Option<&str>) -> bool {
+        self.allows_tenant(tenant) && self.allows_stream(stream)
+    }
+
+    /// Whether the token may access the object `path`, attributed to its stream with
+    /// [`RecordingKey::from_path`] and to its tenant by its first segment. Objects that
+    /// don't parse are denied unless the token has access to every stream
+    pub fn allows_object(&self, path: &str) -> bool {
+        if self.id == ANY_ID && self.streams.is_none() && self.tenants.is_none() {
+            return true;
+        }
+        let tenant = self.tenants.as_ref().and(path.split('/').next());
+        RecordingKey::from_path(path).is_some_and(|key| self.allows_recording(&key.stream, tenant))
+    }
+}
+
+/// `prefix` only matches at a boundary: `cam` matches `cam` and `cam-2` but not
+/// `camera`, `cam-` matches `cam-2` but not `cam`. `*` matches every stream
+fn prefix_matches(prefix: &str, stream: &str) -> bool {
+    if prefix == ANY_ID {
+        return true;
+    }
+    let Some(rest) = stream.strip_prefix(prefix) else {
+        return false;
+    };
+    if prefix.is_empty() {
+        return false;
+    }
+    rest.is_empty()
+        || !prefix.ends_with(|c: char| c.is_ascii_alphanumeric())
+        || rest.starts_with(|c: char| !c.is_ascii_alphanumeric())
 }
 
 impl Display for Claims {
@@ -106,4 +172,93 @@ mod tests {
         assert!(!access.x);
         assert_eq!(format!("{access}"), "---");
     }
+
+    fn tenant(streams: &[&str]) -> Claims {
+        Claims {
+            id: "tenant-a".to_string(),
+            exp: 0,
+            mode: 4,
+            streams: Some(streams.iter().map(|s| s.to_string()).collect()),
+            tenants: None,
+        }
+    }
+
+    #[test]
+    fn test_stream_prefixes() {
+        let claims = tenant(&["lobby", "tenant-a-"]);
+        assert!(claims.allows_stream("lobby"));
+        assert!(claims.allows_stream("lobby-2"));
+        assert!(claims.allows_stream("tenant-a-cam1"));
+        // Prefixes stop at a boundary
+        assert!(!claims.allows_stream("lobbyist"));
+        assert!(!claims.allows_stream("tenant-a"));
+        assert!(!claims.allows_stream("tenant-ab-cam1"));
+        assert!(!claims.allows_stream("Lobby"));
+        assert!(!tenant(&[""]).allows_stream("lobby"));
+        assert!(!tenant(&[]).allows_stream("lobby"));
+        assert!(tenant(&["*"]).allows_stream("lobby"));
+
+        // Without the claim a token sees the stream it was issued for
+        let mut claims = tenant(&[]);
+        claims.streams = None;
+        assert!(claims.allows_stream("tenant-a"));
+        assert!(!claims.allows_stream("tenant-a-cam1"));
+        claims.id = ANY_ID.to_string();
+        assert!(claims.allows_stream("tenant-a-cam1"));
+        assert!(claims.allows_object("custom/layout/v_seg_0001.m4s"));
+    }
+
+    #[test]
+    fn test_object_paths() {
+        let claims = tenant(&["lobby"]);
+        assert!(claims.allows_object("lobby/1700000000/manifest.mpd"));
+        assert!(claims.allows_object("edge-1/lobby/1700000000/v_seg_0001.m4s"));
+        assert!(!claims.allows_object("office/1700000000/manifest.mpd"));
+        // The namespace is not the stream
+        assert!(!claims.allows_object("lobby/office/1700000000/manifest.mpd"));
+        // Paths that don't parse into a recording are denied
+        for path in [
+            "lobby/manifest.mpd",
+            "lobby/latest/manifest.mpd",
+            "lobby/1700000000/../../office/1700000000/manifest.mpd",
+            "_shared/init/ab12.mp4",
+            "index.json",
+        ] {
+            assert!(!claims.allows_object(path), "{path}");
+        }
+        // Even with every stream granted by prefix
+        assert!(!tenant(&["*"]).allows_object("index.json"));
+    }
+
+    #[test]
+    fn test_tenants() {
+        let claims = Claims {
+            id: "acme".to_string(),
+            exp: 0,
+            mode: 4,
+            streams: None,
+            tenants: Some(vec!["acme".to_string()]),
+        };
+        assert!(claims.allows_recording("acme-cam1", Some("acme")));
+        assert!(!claims.allows_recording("globex-cam1", Some("globex")));
+        assert!(!claims.allows_recording("lobby", None));
+        assert!(claims.allows_object("acme/edge-1/acme-cam1/1700000000/manifest.mpd"));
+        assert!(claims.allows_object("acme/acme-cam1/1700000000/v_seg_0001.m4s"));
+        for path in [
+            "globex/edge-1/globex-cam1/1700000000/manifest.mpd",
+            "globex/acme/1700000000/manifest.mpd",
+            "acme-cam1/1700000000/manifest.mpd",
+            "acme/index.json",
+        ] {
+            assert!(!claims.allows_object(path), "{path}");
+        }
+
+        // Streams narrow the tenant down further
+        let claims = Claims {
+            streams: Some(vec!["acme-lobby".to_string()]),
+            ..claims
+        };
+        assert!(claims.allows_object("acme/acme-lobby/1700000000/manifest.mpd"));
+        assert!(!claims.allows_object("acme/acme-cam1/1700000000/manifest.mpd"));
+    }
 }
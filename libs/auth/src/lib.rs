@@ -1,6 +1,7 @@
 use std::collections::HashSet;
 
 use anyhow::{Error, anyhow};
+use config_loader::Secret;
 use headers::authorization::{Bearer, Credentials};
 use http::{StatusCode, header};
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
@@ -41,12 +42,33 @@ pub struct AuthState {
 }
 
 impl AuthState {
-    pub fn new(secret: String, tokens: Vec<String>) -> Self {
+    pub fn new(secret: &Secret, tokens: &[Secret]) -> Self {
         Self {
-            tokens: tokens.into_iter().collect(),
-            decoding: DecodingKey::from_secret(secret.as_bytes()),
+            tokens: tokens
+                .iter()
+                .map(|token| token.expose_secret().to_string())
+                .collect(),
+            decoding: DecodingKey::from_secret(secret.expose_secret().as_bytes()),
         }
     }
+
+    /// Claims of the request's bearer token: static tokens have access to everything,
+    /// anything else must be a valid JWT
+    pub fn claims(&self, headers: &header::HeaderMap) -> Option<Claims> {
+        let bearer = Bearer::decode(headers.get(header::AUTHORIZATION)?)?;
+        if self.tokens.contains(bearer.token()) {
+            return Some(Claims {
+                id: ANY_ID.to_string(),
+                exp: 0,
+                mode: 7,
+                streams: None,
+                tenants: None,
+            });
+        }
+        decode::<Claims>(bearer.token(), &self.decoding, &Validation::default())
+            .ok()
+            .map(|token_data| token_data.claims)
+    }
 }
 
 pub async fn validate_middleware(
@@ -60,32 +82,19 @@ pub async fn validate_middleware(
                 id: ANY_ID.to_string(),
                 exp: 0,
                 mode: 7,
+                streams: None,
+                tenants: None,
             });
             return true;
         }
 
-        if let Some(auth_header) = request.headers().get(header::AUTHORIZATION) {
-            match Bearer::decode(auth_header) {
-                Some(bearer) if state.tokens.contains(bearer.token()) => {
-                    request.extensions_mut().insert(Claims {
-                        id: ANY_ID.to_string(),
-                        exp: 0,
-                        mode: 7,
-                    });
-                    return true;
-                }
-                Some(bearer) => {
-                    if let Ok(token_data) =
-                        decode::<Claims>(bearer.token(), &state.decoding, &Validation::default())
-                    {
-                        request.extensions_mut().insert(token_data.claims);
-                        return true;
-                    }
-                }
-                _ => (),
+        match state.claims(request.headers()) {
+            Some(claims) => {
+                request.extensions_mut().insert(claims);
+                true
             }
-        };
-        false
+            None => false,
+        }
     };
 
     if closure() {
@@ -0,0 +1,98 @@
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// What `Debug` prints in place of a secret
+const MASK: &str = "***";
+
+/// A credential of a config: secret keys, tokens, passwords.
+///
+/// `Debug` prints `***` instead of the value, so a config logged with `{:?}` never
+/// carries it. The value comes out of [`Secret::expose_secret`] only, where it is sent
+/// or compared. Serialized as is: [`crate::effective_config`] redacts it by its key.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+
+    /// Unset, which some configs take as "no auth" and `Debug` shows as is
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Redacted(&self.0).fmt(f)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self)
+    }
+}
+
+/// A secret held as a plain string, for hand-written `Debug` impls of types that are no
+/// config, e.g. `.field("token", &Redacted(&self.token))`
+pub struct Redacted<'a>(pub &'a str);
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            f.write_str("\"\"")
+        } else {
+            f.write_str(MASK)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_hides_value() {
+        let secret = Secret::from("hunter2");
+        assert_eq!(format!("{secret:?}"), "***");
+        assert_eq!(format!("{:?}", Some(&secret)), "Some(***)");
+        assert_eq!(format!("{:?}", Secret::default()), "\"\"");
+        assert_eq!(secret.expose_secret(), "hunter2");
+
+        // Read and written like the string it holds
+        let parsed: Vec<Secret> = toml::Value::try_from(["a", "b"])
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(parsed, [Secret::from("a"), Secret::from("b")]);
+        assert_eq!(
+            toml::Value::try_from(&secret).unwrap().as_str(),
+            Some("hunter2")
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! Caption sidecars, WebVTT files listed next to the media of a manifest.
+//!
+//! Captions arrive as WebVTT or SRT and are stored as WebVTT, the format DASH players
+//! and HLS both take as a side-loaded text track. A manifest references each language
+//! through a text adaptation set whose single representation's `<BaseURL>` is the
+//! sidecar, relative to the manifest.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::mpd::{AdaptationSet, Mpd, Representation};
+use crate::xml::{Element, Node};
+
+/// Captions that can't be stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptionError {
+    /// Neither a `WEBVTT` header nor an SRT cue
+    UnknownFormat,
+    NoCues,
+    /// A cue timing line that doesn't parse, 1-based line number
+    Timing {
+        line: usize,
+    },
+    EndBeforeStart {
+        line: usize,
+    },
+    /// A cue ending after the recording
+    BeyondDuration {
+        end: Duration,
+        duration: Duration,
+    },
+}
+
+impl fmt::Display for CaptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat => write!(f, "captions are neither WebVTT nor SRT"),
+            Self::NoCues => write!(f, "captions have no cue"),
+            Self::Timing { line } => write!(f, "malformed cue timing on line {line}"),
+            Self::EndBeforeStart { line } => write!(f, "cue on line {line} ends before it starts"),
+            Self::BeyondDuration { end, duration } => write!(
+                f,
+                "cue ends at {:.3}s, after the recording's {:.3}s",
+                end.as_secs_f64(),
+                duration.as_secs_f64()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CaptionError {}
+
+/// Captions converted to WebVTT
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captions {
+    pub vtt: String,
+    pub cues: usize,
+    /// End of the last cue
+    pub end: Duration,
+}
+
+impl Captions {
+    /// Refuse captions running past a recording of `duration`
+    pub fn check_duration(&self, duration: Duration) -> Result<(), CaptionError> {
+        if self.end > duration {
+            return Err(CaptionError::BeyondDuration {
+                end: self.end,
+                duration,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Name of the sidecar of `lang`, next to the manifest
+pub fn captions_file(lang: &str) -> String {
+    format!("captions_{lang}.vtt")
+}
+
+/// `input` as WebVTT: a WebVTT file is kept as it is once its cue timings check out,
+/// SRT is converted cue by cue
+pub fn to_webvtt(input: &str) -> Result<Captions, CaptionError> {
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+    let input = input.replace("\r\n", "\n").replace('\r', "\n");
+    let first = input.lines().next().unwrap_or("");
+    if first == "WEBVTT" || first.starts_with("WEBVTT ") || first.starts_with("WEBVTT\t") {
+        check_webvtt(input)
+    } else {
+        from_srt(&input)
+    }
+}
+
+fn check_webvtt(vtt: String) -> Result<Captions, CaptionError> {
+    let mut cues = 0;
+    let mut end = Duration::ZERO;
+    for (i, line) in vtt.lines().enumerate() {
+        if !line.contains("-->") {
+            continue;
+        }
+        let (start, stop) = cue_timing(line, '.').ok_or(CaptionError::Timing { line: i + 1 })?;
+        if stop < start {
+            return Err(CaptionError::EndBeforeStart { line: i + 1 });
+        }
+        cues += 1;
+        end = end.max(stop);
+    }
+    if cues == 0 {
+        return Err(CaptionError::NoCues);
+    }
+    Ok(Captions { vtt, cues, end })
+}
+
+fn from_srt(srt: &str) -> Result<Captions, CaptionError> {
+    let mut vtt = String::from("WEBVTT\n");
+    let mut cues = 0;
+    let mut end = Duration::ZERO;
+    let lines: Vec<&str> = srt.lines().collect();
+    let mut i = 0;
+    while i < lines.len() {
+        if lines[i].trim().is_empty() {
+            i += 1;
+            continue;
+        }
+        // Optional counter before the timing
+        let timing_at = if lines[i].contains("-->") { i } else { i + 1 };
+        let Some(timing) = lines.get(timing_at).filter(|l| l.contains("-->")) else {
+            return Err(if cues == 0 {
+                CaptionError::UnknownFormat
+            } else {
+                CaptionError::Timing { line: i + 1 }
+            });
+        };
+        let (start, stop) = cue_timing(timing, ',').ok_or(CaptionError::Timing {
+            line: timing_at + 1,
+        })?;
+        if stop < start {
+            return Err(CaptionError::EndBeforeStart {
+                line: timing_at + 1,
+            });
+        }
+        vtt.push('\n');
+        vtt.push_str(&format!("{} --> {}\n", timestamp(start), timestamp(stop)));
+        i = timing_at + 1;
+        while let Some(text) = lines.get(i).filter(|l| !l.trim().is_empty()) {
+            vtt.push_str(text);
+            vtt.push('\n');
+            i += 1;
+        }
+        cues += 1;
+        end = end.max(stop);
+    }
+    if cues == 0 {
+        return Err(CaptionError::NoCues);
+    }
+    Ok(Captions { vtt, cues, end })
+}
+
+/// Start and end of a `start --> end [settings]` line whose milliseconds follow
+/// `separator`
+fn cue_timing(line: &str, separator: char) -> Option<(Duration, Duration)> {
+    let (start, rest) = line.split_once("-->")?;
+    let stop = rest.split_whitespace().next()?;
+    Some((
+        parse_timestamp(start.trim(), separator)?,
+        parse_timestamp(stop, separator)?,
+    ))
+}
+
+/// `[hh:]mm:ss{separator}ttt`
+fn parse_timestamp(s: &str, separator: char) -> Option<Duration> {
+    let (clock, millis) = s.split_once(separator)?;
+    if millis.len() != 3 {
+        return None;
+    }
+    let parts: Vec<&str> = clock.split(':').collect();
+    let (hours, minutes, seconds) = match parts.as_slice() {
+        [h, m, s] => (*h, *m, *s),
+        [m, s] => ("0", *m, *s),
+        _ => return None,
+    };
+    if minutes.len() != 2 || seconds.len() != 2 {
+        return None;
+    }
+    let number = |v: &str| -> Option<u64> {
+        v.bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| v.parse().ok())
+            .flatten()
+    };
+    let (minutes, seconds) = (number(minutes)?, number(seconds)?);
+    if minutes > 59 || seconds > 59 {
+        return None;
+    }
+    let secs = number(hours)? * 3_600 + minutes * 60 + seconds;
+    Some(Duration::from_secs(secs) + Duration::from_millis(number(millis)?))
+}
+
+/// `hh:mm:ss.ttt`
+fn timestamp(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3_600,
+        secs / 60 % 60,
+        secs % 60,
+        d.subsec_millis()
+    )
+}
+
+impl Mpd {
+    /// Reference the sidecar of `lang` from the first period, replacing the text set
+    /// of the same language
+    pub fn set_captions(&mut self, lang: &str) {
+        let Some(period) = self.periods.first_mut() else {
+            return;
+        };
+        let next_id = period
+            .adaptation_sets
+            .iter()
+            .filter_map(|set| set.id()?.parse::<u32>().ok())
+            .max()
+            .map_or(0, |id| id + 1);
+        let existing = period
+            .adaptation_sets
+            .iter()
+            .position(|set| set.content_type() == Some("text") && set.lang() == Some(lang));
+        let id = existing
+            .and_then(|at| period.adaptation_sets[at].id()?.parse().ok())
+            .unwrap_or(next_id);
+
+        let mut set = AdaptationSet::new(id, "text");
+        set.raw.set_attr("mimeType", "text/vtt");
+        set.raw.set_attr("lang", lang);
+        let mut role = Element::new("Role");
+        role.set_attr("schemeIdUri", "urn:mpeg:dash:role:2011");
+        role.set_attr("value", "subtitle");
+        set.raw.children.push((0, Node::Element(role)));
+        let mut representation = Representation::default();
+        representation
+            .raw
+            .set_attr("id", format!("captions_{lang}"));
+        representation.raw.set_attr("bandwidth", 256);
+        representation.set_base_url(&captions_file(lang));
+        set.representations.push(representation);
+
+        match existing {
+            Some(at) => period.adaptation_sets[at] = set,
+            None => period.adaptation_sets.push(set),
+        }
+    }
+
+    /// Languages of the text sets, in manifest order
+    pub fn caption_languages(&self) -> Vec<String> {
+        self.adaptation_sets()
+            .filter(|set| set.content_type() == Some("text"))
+            .filter_map(|set| set.lang().map(str::to_string))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpd::tests::RECORDER;
+
+    const SRT: &str = "1\r\n00:00:01,000 --> 00:00:04,500\r\nHello\r\nworld\r\n\r\n\
+                       2\r\n00:00:05,250 --> 00:00:07,000\r\n<i>Bye</i>\r\n";
+
+    #[test]
+    fn test_srt_to_webvtt() {
+        let captions = to_webvtt(SRT).unwrap();
+        assert_eq!(
+            captions.vtt,
+            "WEBVTT\n\n\
+             00:00:01.000 --> 00:00:04.500\nHello\nworld\n\n\
+             00:00:05.250 --> 00:00:07.000\n<i>Bye</i>\n"
+        );
+        assert_eq!(captions.cues, 2);
+        assert_eq!(captions.end, Duration::from_millis(7_000));
+        // Converted captions read back as WebVTT
+        assert_eq!(to_webvtt(&captions.vtt).unwrap().end, captions.end);
+
+        assert!(captions.check_duration(Duration::from_secs(25)).is_ok());
+        assert_eq!(
+            captions.check_duration(Duration::from_secs(6)),
+            Err(CaptionError::BeyondDuration {
+                end: Duration::from_secs(7),
+                duration: Duration::from_secs(6),
+            })
+        );
+    }
+
+    #[test]
+    fn test_webvtt_timings_checked() {
+        let vtt = "WEBVTT - demo\n\nNOTE kept\n\nintro\n00:01.000 --> 00:02.000 align:start\nHi\n\n\
+                   01:00:00.000 --> 01:00:01.500\nLater\n";
+        let captions = to_webvtt(vtt).unwrap();
+        assert_eq!(captions.vtt, vtt);
+        assert_eq!(captions.cues, 2);
+        assert_eq!(captions.end, Duration::from_millis(3_601_500));
+
+        assert_eq!(
+            to_webvtt("WEBVTT\n\n00:01.000 --> 00:61.000\nHi\n"),
+            Err(CaptionError::Timing { line: 3 })
+        );
+        assert_eq!(
+            to_webvtt("WEBVTT\n\n00:02.000 --> 00:01.000\nHi\n"),
+            Err(CaptionError::EndBeforeStart { line: 3 })
+        );
+        assert_eq!(to_webvtt("WEBVTT\n"), Err(CaptionError::NoCues));
+        assert_eq!(to_webvtt(""), Err(CaptionError::NoCues));
+        assert_eq!(
+            to_webvtt("hello\nworld\n"),
+            Err(CaptionError::UnknownFormat)
+        );
+        assert_eq!(
+            to_webvtt("1\n00:00:01.000 --> 00:00:02,000\nHi\n"),
+            Err(CaptionError::Timing { line: 2 })
+        );
+    }
+
+    #[test]
+    fn test_set_captions() {
+        let mut mpd: Mpd = RECORDER.parse().unwrap();
+        mpd.set_captions("en");
+        mpd.set_captions("pt-BR");
+        // Replacing a language keeps its set
+        mpd.set_captions("en");
+        let written = mpd.to_string();
+        assert!(written.contains(
+            "<AdaptationSet id=\"2\" contentType=\"text\" mimeType=\"text/vtt\" lang=\"en\">\n\
+             \x20           <Role schemeIdUri=\"urn:mpeg:dash:role:2011\" value=\"subtitle\" />\n\
+             \x20           <Representation id=\"captions_en\" bandwidth=\"256\">\n\
+             \x20               <BaseURL>captions_en.vtt</BaseURL>\n"
+        ));
+
+        let mpd: Mpd = written.parse().unwrap();
+        assert_eq!(mpd.caption_languages(), ["en", "pt-BR"]);
+        let set = mpd.adaptation_sets().nth(3).unwrap();
+        assert_eq!(set.id(), Some("3"));
+        assert_eq!(
+            set.representations[0].base_url().as_deref(),
+            Some("captions_pt-BR.vtt")
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! Cut a static manifest down to a time window.
+//!
+//! Each timeline keeps the segments overlapping the window, clipped on its own
+//! timescale so audio and video stay aligned on the window rather than on segments. A
+//! window starting mid-segment plays from the segment containing it, and
+//! `presentationTimeOffset` moves the player's start to the requested instant.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::mpd::{Mpd, S, to_ticks};
+
+#[derive(Debug, PartialEq)]
+pub enum ClipError {
+    /// `from_ms` is not before `to_ms`
+    EmptyWindow,
+    /// The window ends after the recording
+    OutOfRange { duration_ms: u64 },
+    /// The manifest has no segment timeline to clip
+    NoSegments,
+}
+
+impl fmt::Display for ClipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyWindow => write!(f, "from_ms must be before to_ms"),
+            Self::OutOfRange { duration_ms } => {
+                write!(f, "window outside the recording of {duration_ms} ms")
+            }
+            Self::NoSegments => write!(f, "manifest has no segments"),
+        }
+    }
+}
+
+impl std::error::Error for ClipError {}
+
+impl Mpd {
+    /// Keep the segments overlapping `from_ms..to_ms` of the presentation, and set the
+    /// presentation and first period durations to the window
+    pub fn clip(&mut self, from_ms: u64, to_ms: u64) -> Result<(), ClipError> {
+        if from_ms >= to_ms {
+            return Err(ClipError::EmptyWindow);
+        }
+        let duration_ms = self
+            .templates_mut()
+            .map(|template| template.end_ms())
+            .max()
+            .unwrap_or(0);
+        if duration_ms == 0 {
+            return Err(ClipError::NoSegments);
+        }
+        if to_ms > duration_ms {
+            return Err(ClipError::OutOfRange { duration_ms });
+        }
+
+        for template in self.templates_mut() {
+            let segments = template.segments();
+            let from = to_ticks(from_ms, template.timescale());
+            let to = to_ticks(to_ms, template.timescale());
+            let first = segments
+                .iter()
+                .position(|(t, d)| t + d > from)
+                .unwrap_or(segments.len());
+            let Some(timeline) = template.timeline.as_mut() else {
+                continue;
+            };
+            timeline.entries = segments[first..]
+                .iter()
+                .take_while(|(t, _)| *t < to)
+                .map(|&(t, d)| S::new(t, d))
+                .collect();
+            template.set_start_number(template.start_number() + first as u64);
+            template.set_presentation_time_offset(from);
+        }
+
+        let window = Duration::from_millis(to_ms - from_ms);
+        self.set_media_presentation_duration(window);
+        if let Some(period) = self.periods.first_mut() {
+            period.set_duration(window);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpd::tests::{FFMPEG, PACKAGER};
+
+    #[test]
+    fn test_clip_keeps_what_it_does_not_model() {
+        let mut mpd: Mpd = PACKAGER.parse().unwrap();
+        mpd.clip(2_100, 4_000).unwrap();
+        let set = mpd.adaptation_sets().next().unwrap();
+        let template = set.segment_template.as_ref().unwrap();
+        // 2.1s falls in the second segment, kept whole
+        assert_eq!(template.segments(), [(60060, 60060)]);
+        assert_eq!(template.start_number(), 2);
+        assert_eq!(template.presentation_time_offset(), 63_000);
+        assert_eq!(
+            mpd.media_presentation_duration(),
+            Some(Duration::from_millis(1_900))
+        );
+        assert_eq!(
+            mpd.periods[0].duration(),
+            Some(Duration::from_millis(1_900))
+        );
+
+        let written = mpd.to_string();
+        assert_eq!(written.matches("<ContentProtection").count(), 2);
+        assert!(written.contains(r#"<Role schemeIdUri="urn:mpeg:dash:role:2011" value="main" />"#));
+        assert!(written.contains("<UTCTiming"));
+    }
+
+    #[test]
+    fn test_clip_tracks_on_their_own_timescale() {
+        let mut mpd: Mpd = FFMPEG.parse().unwrap();
+        mpd.clip(4_500, 8_000).unwrap();
+        let mut templates = mpd.templates_mut();
+        let video = templates.next().unwrap();
+        assert_eq!(video.segments(), [(51200, 51200)]);
+        assert_eq!(video.presentation_time_offset(), 57_600);
+        // Entries without `t` come out with it
+        let audio = templates.next().unwrap();
+        assert_eq!(audio.segments(), [(192512, 191488)]);
+        assert!(audio.timeline.as_ref().unwrap().entries[0].t().is_some());
+
+        let mut mpd: Mpd = FFMPEG.parse().unwrap();
+        assert_eq!(mpd.clip(3_000, 3_000), Err(ClipError::EmptyWindow));
+        assert_eq!(
+            mpd.clip(0, 10_001),
+            Err(ClipError::OutOfRange {
+                duration_ms: 10_000
+            })
+        );
+        let mut empty: Mpd = "<MPD><Period /></MPD>".parse().unwrap();
+        assert_eq!(empty.clip(0, 1_000), Err(ClipError::NoSegments));
+    }
+}
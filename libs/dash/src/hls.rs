@@ -0,0 +1,243 @@
+//! HLS media playlists of a manifest's segment timelines.
+//!
+//! Recordings are fragmented MP4, which HLS plays as it is (RFC 8216 section 3.3):
+//! converting is listing the same init and media segments in a playlist per
+//! representation, nothing is remuxed.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::mpd::{AdaptationSet, Mpd};
+
+/// One `#EXTINF` entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaSegment {
+    pub duration: Duration,
+    /// Relative to the manifest, as the DASH template expands it
+    pub uri: String,
+}
+
+/// A media playlist, written out with [`fmt::Display`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaPlaylist {
+    /// `#EXT-X-MAP`, the init segment
+    pub map: Option<String>,
+    /// Number of the first segment
+    pub media_sequence: u64,
+    pub segments: Vec<MediaSegment>,
+    /// No segments will be added, `#EXT-X-ENDLIST` and a `VOD` playlist type
+    pub ended: bool,
+}
+
+impl MediaPlaylist {
+    /// `#EXT-X-TARGETDURATION`, the longest segment rounded up to whole seconds
+    pub fn target_duration(&self) -> u64 {
+        self.segments
+            .iter()
+            .map(|s| s.duration.as_secs_f64().ceil() as u64)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl fmt::Display for MediaPlaylist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "#EXTM3U")?;
+        writeln!(f, "#EXT-X-VERSION:7")?;
+        writeln!(f, "#EXT-X-TARGETDURATION:{}", self.target_duration())?;
+        writeln!(f, "#EXT-X-MEDIA-SEQUENCE:{}", self.media_sequence)?;
+        if self.ended {
+            writeln!(f, "#EXT-X-PLAYLIST-TYPE:VOD")?;
+        }
+        if let Some(map) = &self.map {
+            writeln!(f, "#EXT-X-MAP:URI=\"{map}\"")?;
+        }
+        for segment in &self.segments {
+            writeln!(f, "#EXTINF:{:.3},", segment.duration.as_secs_f64())?;
+            writeln!(f, "{}", segment.uri)?;
+        }
+        if self.ended {
+            writeln!(f, "#EXT-X-ENDLIST")?;
+        }
+        Ok(())
+    }
+}
+
+/// A representation of the manifest as an HLS rendition
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendition {
+    /// `video`, `audio` or `text`, see [`crate::AdaptationSet::content_type`]
+    pub content_type: Option<String>,
+    /// `LANGUAGE` of a subtitles rendition
+    pub language: Option<String>,
+    pub representation_id: Option<String>,
+    pub bandwidth: Option<u64>,
+    pub codecs: Option<String>,
+    pub playlist: MediaPlaylist,
+}
+
+impl Mpd {
+    /// A playlist per representation with a segment timeline, in manifest order.
+    /// Representations addressed otherwise, `SegmentBase` or `SegmentList`, are left out,
+    /// except caption sidecars which become a subtitles rendition of one segment
+    pub fn hls_renditions(&self) -> Vec<Rendition> {
+        let ended = self.mpd_type() != Some("dynamic");
+        let mut renditions = Vec::new();
+        for set in self.adaptation_sets() {
+            if set.content_type() == Some("text") {
+                renditions.extend(self.subtitles(set, ended));
+                continue;
+            }
+            for representation in &set.representations {
+                let Some(template) = set.template_of(representation) else {
+                    continue;
+                };
+                if template.timeline.is_none() || template.media().is_none() {
+                    continue;
+                }
+                let timescale = template.timescale() as f64;
+                let start_number = template.start_number();
+                let segments = template
+                    .segments()
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(i, (t, d))| {
+                        let number = start_number + i as u64;
+                        Some(MediaSegment {
+                            duration: Duration::from_secs_f64(d as f64 / timescale),
+                            uri: template.media_url(representation, number, t)?,
+                        })
+                    })
+                    .collect();
+                renditions.push(Rendition {
+                    content_type: set.content_type().map(str::to_string),
+                    language: set.lang().map(str::to_string),
+                    representation_id: representation.id().map(str::to_string),
+                    bandwidth: representation.bandwidth(),
+                    codecs: representation.codecs().map(str::to_string),
+                    playlist: MediaPlaylist {
+                        map: template.initialization_url(representation),
+                        media_sequence: start_number,
+                        segments,
+                        ended,
+                    },
+                });
+            }
+        }
+        renditions
+    }
+
+    /// The sidecars of a text set, each lasting the whole presentation
+    fn subtitles(&self, set: &AdaptationSet, ended: bool) -> Vec<Rendition> {
+        let duration = self.media_presentation_duration().unwrap_or_default();
+        set.representations
+            .iter()
+            .filter_map(|representation| {
+                Some(Rendition {
+                    content_type: Some("text".to_string()),
+                    language: set.lang().map(str::to_string),
+                    representation_id: representation.id().map(str::to_string),
+                    bandwidth: representation.bandwidth(),
+                    codecs: representation.codecs().map(str::to_string),
+                    playlist: MediaPlaylist {
+                        map: None,
+                        media_sequence: 0,
+                        segments: vec![MediaSegment {
+                            duration,
+                            uri: representation.base_url()?,
+                        }],
+                        ended,
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpd::tests::{PACKAGER, RECORDER};
+
+    #[test]
+    fn test_recorder_manifest_as_hls() {
+        let mpd: Mpd = RECORDER.parse().unwrap();
+        let renditions = mpd.hls_renditions();
+        assert_eq!(renditions.len(), 2);
+        let video = &renditions[0];
+        assert_eq!(video.content_type.as_deref(), Some("video"));
+        assert_eq!(video.bandwidth, Some(1_500_000));
+        assert_eq!(video.codecs.as_deref(), Some("avc1.42c01f"));
+        assert_eq!(
+            video.playlist.to_string(),
+            "#EXTM3U\n\
+             #EXT-X-VERSION:7\n\
+             #EXT-X-TARGETDURATION:10\n\
+             #EXT-X-MEDIA-SEQUENCE:1\n\
+             #EXT-X-PLAYLIST-TYPE:VOD\n\
+             #EXT-X-MAP:URI=\"../../_shared/init/3f2a.mp4\"\n\
+             #EXTINF:10.000,\n\
+             v_seg_0001.m4s\n\
+             #EXTINF:10.000,\n\
+             v_seg_0002.m4s\n\
+             #EXTINF:5.000,\n\
+             v_seg_0003.m4s\n\
+             #EXT-X-ENDLIST\n"
+        );
+        assert_eq!(renditions[1].content_type.as_deref(), Some("audio"));
+        assert_eq!(renditions[1].playlist.map.as_deref(), Some("a_init.m4s"));
+    }
+
+    #[test]
+    fn test_shared_template_and_live_manifest() {
+        let mpd: Mpd = PACKAGER.parse().unwrap();
+        let renditions = mpd.hls_renditions();
+        assert_eq!(renditions.len(), 2);
+        let small = &renditions[1];
+        assert_eq!(small.representation_id.as_deref(), Some("video-180p"));
+        let uris: Vec<&str> = small
+            .playlist
+            .segments
+            .iter()
+            .map(|s| s.uri.as_str())
+            .collect();
+        assert_eq!(
+            uris,
+            [
+                "video-180p/0.m4s",
+                "video-180p/60060.m4s",
+                "video-180p/120120.m4s"
+            ]
+        );
+        // 2.002s rounds up
+        assert_eq!(small.playlist.target_duration(), 3);
+
+        let live = PACKAGER.replace(r#"type="static""#, r#"type="dynamic""#);
+        let mpd: Mpd = live.parse().unwrap();
+        let playlist = mpd.hls_renditions()[0].playlist.to_string();
+        assert!(!playlist.contains("#EXT-X-ENDLIST"));
+        assert!(!playlist.contains("VOD"));
+    }
+
+    #[test]
+    fn test_captions_as_subtitles_rendition() {
+        let mut mpd: Mpd = RECORDER.parse().unwrap();
+        mpd.set_captions("de");
+        let renditions = mpd.hls_renditions();
+        assert_eq!(renditions.len(), 3);
+        let subtitles = &renditions[2];
+        assert_eq!(subtitles.content_type.as_deref(), Some("text"));
+        assert_eq!(subtitles.language.as_deref(), Some("de"));
+        assert_eq!(
+            subtitles.playlist.to_string(),
+            "#EXTM3U\n\
+             #EXT-X-VERSION:7\n\
+             #EXT-X-TARGETDURATION:25\n\
+             #EXT-X-MEDIA-SEQUENCE:0\n\
+             #EXT-X-PLAYLIST-TYPE:VOD\n\
+             #EXTINF:25.000,\n\
+             captions_de.vtt\n\
+             #EXT-X-ENDLIST\n"
+        );
+    }
+}
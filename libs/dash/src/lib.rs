@@ -0,0 +1,20 @@
+//! DASH manifests, parsed into typed structures, modified and written back.
+//!
+//! Shared by the recorder, which writes and repairs the manifests of its recordings,
+//! and livevod, which clips them and serves them as HLS. Attributes and elements the
+//! types don't model survive the round trip, so manifests from other encoders can be
+//! rewritten too.
+
+pub mod captions;
+pub mod clip;
+pub mod hls;
+pub mod mpd;
+pub mod xml;
+
+pub use captions::{CaptionError, Captions, captions_file, to_webvtt};
+pub use clip::ClipError;
+pub use hls::{MediaPlaylist, MediaSegment, Rendition};
+pub use mpd::{
+    AdaptationSet, Error, Mpd, Period, Raw, Representation, S, SegmentTemplate, SegmentTimeline,
+    format_duration, parse_duration, to_ms, to_ticks,
+};
@@ -0,0 +1,865 @@
+//! Typed view of a DASH manifest.
+//!
+//! Each type models the attributes and children the recorder and livevod work with.
+//! Everything else an encoder wrote is kept as parsed in [`Raw`]: attributes in their
+//! order, unmodeled children at their place relative to the modeled ones, so parsing,
+//! changing a few values and writing the manifest back keeps what we don't understand.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::xml::{self, Element, Node};
+
+/// A manifest that isn't XML, or whose root isn't `<MPD>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    Xml(xml::ParseError),
+    NotMpd(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Xml(e) => write!(f, "{e}"),
+            Self::NotMpd(root) => write!(f, "root element is <{root}>, not <MPD>"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<xml::ParseError> for Error {
+    fn from(e: xml::ParseError) -> Self {
+        Self::Xml(e)
+    }
+}
+
+/// Attributes and unmodeled children of an element, as parsed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Raw {
+    /// Every attribute in document order, modeled ones included
+    pub attrs: Vec<(String, String)>,
+    /// Children without a typed field, each after as many modeled children as
+    /// preceded it in the source
+    pub children: Vec<(usize, Node)>,
+}
+
+impl Raw {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn parsed<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.attr(name)?.trim().parse().ok()
+    }
+
+    /// Set `name` in place, appended after the other attributes when missing
+    pub fn set_attr(&mut self, name: &str, value: impl ToString) {
+        let value = value.to_string();
+        match self.attrs.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value,
+            None => self.attrs.push((name.to_string(), value)),
+        }
+    }
+
+    pub fn remove_attr(&mut self, name: &str) -> Option<String> {
+        let pos = self.attrs.iter().position(|(n, _)| n == name)?;
+        Some(self.attrs.remove(pos).1)
+    }
+
+    /// Split `element` into its children named in `modeled` and the rest
+    fn split(element: Element, modeled: &[&str]) -> (Self, Vec<Element>) {
+        let mut raw = Self {
+            attrs: element.attrs,
+            children: Vec::new(),
+        };
+        let mut typed = Vec::new();
+        for child in element.children {
+            match child {
+                Node::Element(e) if modeled.contains(&e.name.as_str()) => typed.push(e),
+                node => raw.children.push((typed.len(), node)),
+            }
+        }
+        (raw, typed)
+    }
+
+    /// `name` element with the attributes, the `modeled` children and the unmodeled
+    /// ones back at their place
+    fn join(&self, name: &str, modeled: Vec<Element>) -> Element {
+        let mut element = Element::new(name);
+        element.attrs = self.attrs.clone();
+        let mut extra = self.children.iter().peekable();
+        for (i, child) in modeled.into_iter().enumerate() {
+            while let Some((_, node)) = extra.next_if(|(at, _)| *at <= i) {
+                element.children.push(node.clone());
+            }
+            element.children.push(Node::Element(child));
+        }
+        element.children.extend(extra.map(|(_, node)| node.clone()));
+        element
+    }
+
+    /// Text of the first unmodeled `<BaseURL>`
+    fn base_url(&self) -> Option<String> {
+        self.children.iter().find_map(|(_, node)| match node {
+            Node::Element(e) if e.name == "BaseURL" => Some(e.text()),
+            _ => None,
+        })
+    }
+
+    /// Replace every `<BaseURL>` with one holding `url`, before the modeled children
+    fn set_base_url(&mut self, url: &str) {
+        let at = self
+            .children
+            .iter()
+            .position(|(_, node)| matches!(node, Node::Element(e) if e.name == "BaseURL"))
+            .unwrap_or_else(|| self.children.iter().take_while(|(at, _)| *at == 0).count());
+        self.children
+            .retain(|(_, node)| !matches!(node, Node::Element(e) if e.name == "BaseURL"));
+        let mut base = Element::new("BaseURL");
+        base.children.push(Node::Text(url.to_string()));
+        self.children
+            .insert(at.min(self.children.len()), (0, Node::Element(base)));
+    }
+}
+
+/// `xs:duration` of the `PnDTnHnMn.nS` forms manifests use, years and months refused
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let rest = s.trim().strip_prefix('P')?;
+    let (days, time) = match rest.split_once('T') {
+        Some((days, time)) => (days, time),
+        None => (rest, ""),
+    };
+    let mut secs = 0f64;
+    if !days.is_empty() {
+        secs += days.strip_suffix('D')?.parse::<f64>().ok()? * 86_400.0;
+    }
+    let mut time = time;
+    for (unit, scale) in [('H', 3_600.0), ('M', 60.0), ('S', 1.0)] {
+        if let Some((value, rest)) = time.split_once(unit) {
+            secs += value.parse::<f64>().ok()? * scale;
+            time = rest;
+        }
+    }
+    if !time.is_empty() || !secs.is_finite() || secs < 0.0 {
+        return None;
+    }
+    Some(Duration::from_secs_f64(secs))
+}
+
+/// `duration` as `PT{seconds}S` with milliseconds, the form the recorder writes
+pub fn format_duration(duration: Duration) -> String {
+    format!("PT{:.3}S", duration.as_secs_f64())
+}
+
+/// `<MPD>`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Mpd {
+    pub periods: Vec<Period>,
+    pub raw: Raw,
+}
+
+impl FromStr for Mpd {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let root = xml::parse(s)?;
+        if root.name != "MPD" {
+            return Err(Error::NotMpd(root.name));
+        }
+        Ok(Self::from_element(root))
+    }
+}
+
+impl fmt::Display for Mpd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        self.to_element().write(&mut out, 0);
+        f.write_str(&out)
+    }
+}
+
+impl Mpd {
+    fn from_element(element: Element) -> Self {
+        let (raw, typed) = Raw::split(element, &["Period"]);
+        Self {
+            periods: typed.into_iter().map(Period::from_element).collect(),
+            raw,
+        }
+    }
+
+    fn to_element(&self) -> Element {
+        self.raw
+            .join("MPD", self.periods.iter().map(Period::to_element).collect())
+    }
+
+    /// Static manifest of the ISO base media file format live profile, the one the
+    /// recorder writes: fragmented MP4 segments addressed through a `SegmentTemplate`
+    pub fn new_static() -> Self {
+        let mut mpd = Self::default();
+        for (name, value) in [
+            ("xmlns:xsi", "http://www.w3.org/2001/XMLSchema-instance"),
+            ("xmlns", "urn:mpeg:dash:schema:mpd:2011"),
+            ("xmlns:xlink", "http://www.w3.org/1999/xlink"),
+            (
+                "xsi:schemaLocation",
+                "urn:mpeg:DASH:schema:MPD:2011 http://standards.iso.org/ittf/PubliclyAvailableStandards/MPEG-DASH_schema_files/DASH-MPD.xsd",
+            ),
+            ("profiles", "urn:mpeg:dash:profile:isoff-live:2011"),
+            ("type", "static"),
+        ] {
+            mpd.raw.set_attr(name, value);
+        }
+        mpd
+    }
+
+    /// `static` or `dynamic`
+    pub fn mpd_type(&self) -> Option<&str> {
+        self.raw.attr("type")
+    }
+
+    /// `availabilityStartTime` as written, an `xs:dateTime`
+    pub fn availability_start_time(&self) -> Option<&str> {
+        self.raw.attr("availabilityStartTime")
+    }
+
+    pub fn media_presentation_duration(&self) -> Option<Duration> {
+        parse_duration(self.raw.attr("mediaPresentationDuration")?)
+    }
+
+    pub fn set_media_presentation_duration(&mut self, duration: Duration) {
+        self.raw
+            .set_attr("mediaPresentationDuration", format_duration(duration));
+    }
+
+    pub fn max_segment_duration(&self) -> Option<Duration> {
+        parse_duration(self.raw.attr("maxSegmentDuration")?)
+    }
+
+    pub fn set_max_segment_duration(&mut self, duration: Duration) {
+        self.raw
+            .set_attr("maxSegmentDuration", format_duration(duration));
+    }
+
+    pub fn min_buffer_time(&self) -> Option<Duration> {
+        parse_duration(self.raw.attr("minBufferTime")?)
+    }
+
+    pub fn set_min_buffer_time(&mut self, duration: Duration) {
+        self.raw
+            .set_attr("minBufferTime", format_duration(duration));
+    }
+
+    pub fn base_url(&self) -> Option<String> {
+        self.raw.base_url()
+    }
+
+    pub fn set_base_url(&mut self, url: &str) {
+        self.raw.set_base_url(url)
+    }
+
+    /// Every adaptation set of every period
+    pub fn adaptation_sets(&self) -> impl Iterator<Item = &AdaptationSet> {
+        self.periods.iter().flat_map(|p| p.adaptation_sets.iter())
+    }
+
+    /// Every segment template, of adaptation sets and of representations
+    pub fn templates_mut(&mut self) -> impl Iterator<Item = &mut SegmentTemplate> {
+        self.periods
+            .iter_mut()
+            .flat_map(|p| p.adaptation_sets.iter_mut())
+            .flat_map(|set| {
+                set.segment_template.iter_mut().chain(
+                    set.representations
+                        .iter_mut()
+                        .filter_map(|r| r.segment_template.as_mut()),
+                )
+            })
+    }
+}
+
+/// `<Period>`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Period {
+    pub adaptation_sets: Vec<AdaptationSet>,
+    pub raw: Raw,
+}
+
+impl Period {
+    fn from_element(element: Element) -> Self {
+        let (raw, typed) = Raw::split(element, &["AdaptationSet"]);
+        Self {
+            adaptation_sets: typed.into_iter().map(AdaptationSet::from_element).collect(),
+            raw,
+        }
+    }
+
+    fn to_element(&self) -> Element {
+        self.raw.join(
+            "Period",
+            self.adaptation_sets
+                .iter()
+                .map(AdaptationSet::to_element)
+                .collect(),
+        )
+    }
+
+    pub fn new(id: &str, start: Duration) -> Self {
+        let mut period = Self::default();
+        period.raw.set_attr("id", id);
+        period.raw.set_attr("start", format_duration(start));
+        period
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.raw.attr("id")
+    }
+
+    pub fn start(&self) -> Option<Duration> {
+        parse_duration(self.raw.attr("start")?)
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        parse_duration(self.raw.attr("duration")?)
+    }
+
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.raw.set_attr("duration", format_duration(duration));
+    }
+
+    pub fn base_url(&self) -> Option<String> {
+        self.raw.base_url()
+    }
+
+    /// Resolve the period's segments against `url` instead of the manifest's location
+    pub fn set_base_url(&mut self, url: &str) {
+        self.raw.set_base_url(url)
+    }
+}
+
+/// `<AdaptationSet>`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdaptationSet {
+    /// Shared by the representations without one of their own
+    pub segment_template: Option<SegmentTemplate>,
+    pub representations: Vec<Representation>,
+    pub raw: Raw,
+}
+
+impl AdaptationSet {
+    fn from_element(element: Element) -> Self {
+        let (raw, typed) = Raw::split(element, &["SegmentTemplate", "Representation"]);
+        let mut set = Self {
+            raw,
+            ..Default::default()
+        };
+        for child in typed {
+            match child.name.as_str() {
+                "SegmentTemplate" => {
+                    set.segment_template = Some(SegmentTemplate::from_element(child))
+                }
+                _ => set
+                    .representations
+                    .push(Representation::from_element(child)),
+            }
+        }
+        set
+    }
+
+    fn to_element(&self) -> Element {
+        let typed = self
+            .segment_template
+            .iter()
+            .map(SegmentTemplate::to_element)
+            .chain(self.representations.iter().map(Representation::to_element))
+            .collect();
+        self.raw.join("AdaptationSet", typed)
+    }
+
+    pub fn new(id: u32, content_type: &str) -> Self {
+        let mut set = Self::default();
+        set.raw.set_attr("id", id);
+        set.raw.set_attr("contentType", content_type);
+        set
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.raw.attr("id")
+    }
+
+    /// `contentType`, else the type of the `mimeType` of the set or its first
+    /// representation, `video` or `audio` for the manifests we write
+    pub fn content_type(&self) -> Option<&str> {
+        self.raw.attr("contentType").or_else(|| {
+            let mime = self
+                .raw
+                .attr("mimeType")
+                .or_else(|| self.representations.first()?.mime_type())?;
+            mime.split_once('/').map(|(kind, _)| kind)
+        })
+    }
+
+    /// Language of the set's tracks, set on caption sets
+    pub fn lang(&self) -> Option<&str> {
+        self.raw.attr("lang")
+    }
+
+    /// The template segments of `representation` follow
+    pub fn template_of<'a>(
+        &'a self,
+        representation: &'a Representation,
+    ) -> Option<&'a SegmentTemplate> {
+        representation
+            .segment_template
+            .as_ref()
+            .or(self.segment_template.as_ref())
+    }
+}
+
+/// `<Representation>`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Representation {
+    pub segment_template: Option<SegmentTemplate>,
+    pub raw: Raw,
+}
+
+impl Representation {
+    fn from_element(element: Element) -> Self {
+        let (raw, typed) = Raw::split(element, &["SegmentTemplate"]);
+        Self {
+            segment_template: typed.into_iter().next().map(SegmentTemplate::from_element),
+            raw,
+        }
+    }
+
+    fn to_element(&self) -> Element {
+        self.raw.join(
+            "Representation",
+            self.segment_template
+                .iter()
+                .map(SegmentTemplate::to_element)
+                .collect(),
+        )
+    }
+
+    pub fn new(id: u32, mime_type: &str, codecs: &str, bandwidth: u64) -> Self {
+        let mut representation = Self::default();
+        representation.raw.set_attr("id", id);
+        representation.raw.set_attr("mimeType", mime_type);
+        representation.raw.set_attr("codecs", codecs);
+        representation.raw.set_attr("bandwidth", bandwidth);
+        representation
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.raw.attr("id")
+    }
+
+    pub fn mime_type(&self) -> Option<&str> {
+        self.raw.attr("mimeType")
+    }
+
+    pub fn codecs(&self) -> Option<&str> {
+        self.raw.attr("codecs")
+    }
+
+    /// Bits per second
+    pub fn bandwidth(&self) -> Option<u64> {
+        self.raw.parsed("bandwidth")
+    }
+
+    pub fn width(&self) -> Option<u32> {
+        self.raw.parsed("width")
+    }
+
+    pub fn height(&self) -> Option<u32> {
+        self.raw.parsed("height")
+    }
+
+    /// Where the representation's single file is, the sidecar of a caption track
+    pub fn base_url(&self) -> Option<String> {
+        self.raw.base_url()
+    }
+
+    pub fn set_base_url(&mut self, url: &str) {
+        self.raw.set_base_url(url)
+    }
+}
+
+/// `<SegmentTemplate>`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SegmentTemplate {
+    pub timeline: Option<SegmentTimeline>,
+    pub raw: Raw,
+}
+
+impl SegmentTemplate {
+    fn from_element(element: Element) -> Self {
+        let (raw, typed) = Raw::split(element, &["SegmentTimeline"]);
+        Self {
+            timeline: typed.into_iter().next().map(SegmentTimeline::from_element),
+            raw,
+        }
+    }
+
+    fn to_element(&self) -> Element {
+        self.raw.join(
+            "SegmentTemplate",
+            self.timeline
+                .iter()
+                .map(SegmentTimeline::to_element)
+                .collect(),
+        )
+    }
+
+    /// Template starting at segment 1 with an empty timeline
+    pub fn new(timescale: u64, initialization: &str, media: &str) -> Self {
+        let mut template = Self {
+            timeline: Some(SegmentTimeline::default()),
+            raw: Raw::default(),
+        };
+        template.raw.set_attr("timescale", timescale);
+        template.raw.set_attr("initialization", initialization);
+        template.raw.set_attr("media", media);
+        template.raw.set_attr("startNumber", 1);
+        template
+    }
+
+    /// Ticks per second, 1 when missing or zero
+    pub fn timescale(&self) -> u64 {
+        self.raw.parsed("timescale").filter(|t| *t > 0).unwrap_or(1)
+    }
+
+    pub fn initialization(&self) -> Option<&str> {
+        self.raw.attr("initialization")
+    }
+
+    pub fn media(&self) -> Option<&str> {
+        self.raw.attr("media")
+    }
+
+    pub fn start_number(&self) -> u64 {
+        self.raw.parsed("startNumber").unwrap_or(1)
+    }
+
+    pub fn set_start_number(&mut self, number: u64) {
+        self.raw.set_attr("startNumber", number);
+    }
+
+    pub fn presentation_time_offset(&self) -> u64 {
+        self.raw.parsed("presentationTimeOffset").unwrap_or(0)
+    }
+
+    pub fn set_presentation_time_offset(&mut self, ticks: u64) {
+        self.raw.set_attr("presentationTimeOffset", ticks);
+    }
+
+    /// `(t, d)` in ticks of every segment, `r` repeats expanded
+    pub fn segments(&self) -> Vec<(u64, u64)> {
+        self.timeline
+            .as_ref()
+            .map(SegmentTimeline::segments)
+            .unwrap_or_default()
+    }
+
+    /// End of the last segment in milliseconds
+    pub fn end_ms(&self) -> u64 {
+        self.segments()
+            .last()
+            .map_or(0, |(t, d)| to_ms(t + d, self.timescale()))
+    }
+
+    /// `media` with the `$Number$`, `$Time$`, `$RepresentationID$` and `$Bandwidth$`
+    /// identifiers of one segment substituted, `None` without `media`
+    pub fn media_url(
+        &self,
+        representation: &Representation,
+        number: u64,
+        time: u64,
+    ) -> Option<String> {
+        Some(substitute(self.media()?, representation, number, time))
+    }
+
+    pub fn initialization_url(&self, representation: &Representation) -> Option<String> {
+        Some(substitute(self.initialization()?, representation, 0, 0))
+    }
+}
+
+/// `<SegmentTimeline>`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SegmentTimeline {
+    pub entries: Vec<S>,
+    pub raw: Raw,
+}
+
+impl SegmentTimeline {
+    fn from_element(element: Element) -> Self {
+        let (raw, typed) = Raw::split(element, &["S"]);
+        Self {
+            entries: typed
+                .into_iter()
+                .map(|e| S {
+                    raw: Raw::split(e, &[]).0,
+                })
+                .collect(),
+            raw,
+        }
+    }
+
+    fn to_element(&self) -> Element {
+        self.raw.join(
+            "SegmentTimeline",
+            self.entries
+                .iter()
+                .map(|s| s.raw.join("S", Vec::new()))
+                .collect(),
+        )
+    }
+
+    /// `(t, d)` of every segment, `r` repeats expanded. A missing `t` continues from
+    /// the previous entry, a negative `r` counts as no repeat
+    pub fn segments(&self) -> Vec<(u64, u64)> {
+        let mut segments = Vec::with_capacity(self.entries.len());
+        let mut next = 0u64;
+        for entry in &self.entries {
+            let t = entry.t().unwrap_or(next);
+            let d = entry.d();
+            for i in 0..=entry.r() {
+                segments.push((t + i * d, d));
+            }
+            next = t + (entry.r() + 1) * d;
+        }
+        segments
+    }
+
+    /// Append a segment of `d` ticks starting at `t`. Each segment gets its own entry
+    /// with an explicit `t`, players resync on it rather than accumulate a gap
+    pub fn append(&mut self, t: u64, d: u64) {
+        self.entries.push(S::new(t, d));
+    }
+}
+
+/// `<S>` entry of a segment timeline
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct S {
+    pub raw: Raw,
+}
+
+impl S {
+    pub fn new(t: u64, d: u64) -> Self {
+        let mut s = Self::default();
+        s.raw.set_attr("t", t);
+        s.raw.set_attr("d", d);
+        s
+    }
+
+    pub fn t(&self) -> Option<u64> {
+        self.raw.parsed("t")
+    }
+
+    pub fn d(&self) -> u64 {
+        self.raw.parsed("d").unwrap_or(0)
+    }
+
+    pub fn r(&self) -> u64 {
+        self.raw.parsed::<i64>("r").map_or(0, |r| r.max(0) as u64)
+    }
+}
+
+pub fn to_ticks(ms: u64, timescale: u64) -> u64 {
+    (ms as u128 * timescale as u128 / 1000) as u64
+}
+
+pub fn to_ms(ticks: u64, timescale: u64) -> u64 {
+    (ticks as u128 * 1000 / timescale.max(1) as u128) as u64
+}
+
+/// `template` with the identifiers of ISO/IEC 23009-1 5.3.9.4.4 substituted, each
+/// with an optional `%0Nd` width
+fn substitute(template: &str, representation: &Representation, number: u64, time: u64) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(begin) = rest.find('$') {
+        out.push_str(&rest[..begin]);
+        let after = &rest[begin + 1..];
+        let Some(end) = after.find('$') else {
+            out.push_str(&rest[begin..]);
+            return out;
+        };
+        let identifier = &after[..end];
+        let (name, format) = identifier.split_once('%').unwrap_or((identifier, ""));
+        let value = match name {
+            "" => Some("$".to_string()),
+            "RepresentationID" => representation.id().map(str::to_string),
+            "Number" => Some(number.to_string()),
+            "Time" => Some(time.to_string()),
+            "Bandwidth" => representation.bandwidth().map(|b| b.to_string()),
+            _ => None,
+        };
+        match value {
+            Some(value) => {
+                let width = format
+                    .strip_prefix('0')
+                    .and_then(|f| f.strip_suffix('d'))
+                    .and_then(|w| w.parse::<usize>().ok())
+                    .unwrap_or(0);
+                out.push_str(&format!("{value:0>width$}"));
+            }
+            None => out.push_str(&rest[begin..begin + end + 2]),
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const RECORDER: &str = include_str!("../fixtures/recorder.mpd");
+    pub(crate) const FFMPEG: &str = include_str!("../fixtures/ffmpeg.mpd");
+    pub(crate) const PACKAGER: &str = include_str!("../fixtures/packager.mpd");
+
+    #[test]
+    fn test_round_trip_keeps_every_element_and_attribute() {
+        for fixture in [RECORDER, FFMPEG, PACKAGER] {
+            let mpd: Mpd = fixture.parse().unwrap();
+            let written = mpd.to_string();
+            // Element for element, attributes in their order, unmodeled ones included
+            assert_eq!(xml::parse(&written).unwrap(), xml::parse(fixture).unwrap());
+            let again: Mpd = written.parse().unwrap();
+            assert_eq!(again, mpd);
+            assert_eq!(again.to_string(), written);
+        }
+
+        let written = PACKAGER.parse::<Mpd>().unwrap().to_string();
+        assert!(written.contains(
+            r#"<ContentProtection value="cenc" schemeIdUri="urn:mpeg:dash:mp4protection:2011" cenc:default_KID="6e5a1d26-2757-47d7-8046-eaa5d1d34b5a" />"#
+        ));
+        assert!(written.contains("<cenc:pssh>AAAAW3Bzc2g"));
+        // After the period, where it was
+        assert!(written.ends_with(
+            "    </Period>\n    <UTCTiming schemeIdUri=\"urn:mpeg:dash:utc:http-xsdate:2014\" value=\"https://time.akamai.com/?iso&amp;ms\" />\n</MPD>\n"
+        ));
+    }
+
+    #[test]
+    fn test_typed_access() {
+        let mpd: Mpd = FFMPEG.parse().unwrap();
+        assert_eq!(mpd.mpd_type(), Some("static"));
+        assert_eq!(
+            mpd.media_presentation_duration(),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(mpd.min_buffer_time(), Some(Duration::from_secs(8)));
+        let period = &mpd.periods[0];
+        assert_eq!(period.id(), Some("0"));
+        assert_eq!(period.start(), Some(Duration::ZERO));
+
+        let sets: Vec<&AdaptationSet> = mpd.adaptation_sets().collect();
+        assert_eq!(sets.len(), 2);
+        let video = &sets[0].representations[0];
+        assert_eq!(sets[0].content_type(), Some("video"));
+        assert_eq!(video.codecs(), Some("avc1.64001f"));
+        assert_eq!((video.width(), video.height()), (Some(1280), Some(720)));
+        let template = sets[0].template_of(video).unwrap();
+        assert_eq!(template.timescale(), 12800);
+        // `r` repeats and entries without `t` continue the timeline
+        assert_eq!(
+            template.segments(),
+            [(0, 51200), (51200, 51200), (102400, 25600)]
+        );
+        assert_eq!(template.end_ms(), 10_000);
+        assert_eq!(
+            template.media_url(video, 2, 51200).as_deref(),
+            Some("chunk-stream0-00002.m4s")
+        );
+        // The audio representation keeps its channel configuration
+        assert_eq!(sets[1].representations[0].raw.children.len(), 1);
+
+        // Shared by the representations, `$Time$` addressed
+        let mpd: Mpd = PACKAGER.parse().unwrap();
+        let set = mpd.adaptation_sets().next().unwrap();
+        let small = &set.representations[1];
+        let template = set.template_of(small).unwrap();
+        assert_eq!(
+            template.media_url(small, 1, 60060).as_deref(),
+            Some("video-180p/60060.m4s")
+        );
+        assert_eq!(
+            template.initialization_url(small).as_deref(),
+            Some("video-180p/init.mp4")
+        );
+
+        assert_eq!(
+            "<Manifest />".parse::<Mpd>(),
+            Err(Error::NotMpd("Manifest".to_string()))
+        );
+        assert!(matches!("<MPD>".parse::<Mpd>(), Err(Error::Xml(_))));
+    }
+
+    #[test]
+    fn test_modify_in_place() {
+        let mut mpd: Mpd = RECORDER.parse().unwrap();
+        mpd.set_media_presentation_duration(Duration::from_millis(27_500));
+        mpd.periods[0].set_base_url("../../object/cam/1700000000/");
+        for template in mpd.templates_mut() {
+            let (t, d) = *template.segments().last().unwrap();
+            template.timeline.as_mut().unwrap().append(t + d, d);
+        }
+        let written = mpd.to_string();
+        assert!(written.contains(
+            r#"type="static" mediaPresentationDuration="PT27.500S" maxSegmentDuration="PT10.000S""#
+        ));
+        assert!(written.contains(
+            "<Period id=\"0\" start=\"PT0.0S\">\n        <BaseURL>../../object/cam/1700000000/</BaseURL>\n        <AdaptationSet"
+        ));
+        assert!(written.contains(
+            "<S t=\"1800000\" d=\"450000\" />\n                        <S t=\"2250000\" d=\"450000\" />"
+        ));
+        assert!(written.contains(r#"<S t="1200000" d="240000" />"#));
+
+        // A second BaseURL replaces the first
+        let mut mpd: Mpd = written.parse().unwrap();
+        assert_eq!(
+            mpd.periods[0].base_url().as_deref(),
+            Some("../../object/cam/1700000000/")
+        );
+        mpd.periods[0].set_base_url("https://cdn.example.com/a&b/");
+        let written = mpd.to_string();
+        assert_eq!(written.matches("<BaseURL>").count(), 1);
+        assert!(written.contains("<BaseURL>https://cdn.example.com/a&amp;b/</BaseURL>"));
+        let mpd: Mpd = written.parse().unwrap();
+        assert_eq!(
+            mpd.periods[0].base_url().as_deref(),
+            Some("https://cdn.example.com/a&b/")
+        );
+    }
+
+    #[test]
+    fn test_durations() {
+        for (s, secs) in [
+            ("PT0.0S", 0.0),
+            ("PT25.000S", 25.0),
+            ("PT2S", 2.0),
+            ("PT1H2M3.5S", 3723.5),
+            ("P1DT1S", 86_401.0),
+            ("PT90M", 5400.0),
+        ] {
+            assert_eq!(
+                parse_duration(s),
+                Some(Duration::from_secs_f64(secs)),
+                "{s}"
+            );
+        }
+        for s in ["", "T1S", "P1Y", "PT1S2", "PT-1S", "PTxS"] {
+            assert_eq!(parse_duration(s), None, "{s}");
+        }
+        assert_eq!(format_duration(Duration::from_millis(5_500)), "PT5.500S");
+    }
+}
@@ -0,0 +1,382 @@
+//! Just enough XML for DASH manifests: elements, attributes, text and comments.
+//!
+//! Attribute order, namespaced names and comments survive a round trip. Whitespace
+//! between elements doesn't, [`Element::write`] indents on its own.
+
+use std::fmt;
+
+/// Deeper nesting than any manifest has, refused rather than recursed into
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+    Comment(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Element {
+    pub name: String,
+    /// In document order
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+/// Why a document didn't parse, `offset` in bytes into the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid XML at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Element {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Set `name` in place, appended after the other attributes when missing
+    pub fn set_attr(&mut self, name: &str, value: impl ToString) {
+        let value = value.to_string();
+        match self.attrs.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value,
+            None => self.attrs.push((name.to_string(), value)),
+        }
+    }
+
+    pub fn remove_attr(&mut self, name: &str) -> Option<String> {
+        let pos = self.attrs.iter().position(|(n, _)| n == name)?;
+        Some(self.attrs.remove(pos).1)
+    }
+
+    /// Text content of the element itself, child elements left out
+    pub fn text(&self) -> String {
+        self.children
+            .iter()
+            .filter_map(|child| match child {
+                Node::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Append the element to `out`, indented four spaces per `depth`
+    pub fn write(&self, out: &mut String, depth: usize) {
+        let indent = "    ".repeat(depth);
+        out.push_str(&indent);
+        out.push('<');
+        out.push_str(&self.name);
+        for (name, value) in &self.attrs {
+            out.push(' ');
+            out.push_str(name);
+            out.push_str("=\"");
+            escape(out, value, true);
+            out.push('"');
+        }
+        if self.children.is_empty() {
+            out.push_str(" />\n");
+            return;
+        }
+        if self.children.iter().all(|c| matches!(c, Node::Text(_))) {
+            out.push('>');
+            escape(out, &self.text(), false);
+        } else {
+            out.push_str(">\n");
+            for child in &self.children {
+                match child {
+                    Node::Element(element) => element.write(out, depth + 1),
+                    Node::Text(text) => {
+                        out.push_str(&indent);
+                        out.push_str("    ");
+                        escape(out, text, false);
+                        out.push('\n');
+                    }
+                    Node::Comment(comment) => {
+                        out.push_str(&indent);
+                        out.push_str("    <!--");
+                        out.push_str(comment);
+                        out.push_str("-->\n");
+                    }
+                }
+            }
+            out.push_str(&indent);
+        }
+        out.push_str("</");
+        out.push_str(&self.name);
+        out.push_str(">\n");
+    }
+}
+
+fn escape(out: &mut String, s: &str, attr: bool) {
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' if attr => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// The root element of `input`. The prolog, a DOCTYPE without internal subset, and
+/// comments or processing instructions outside the root are skipped
+pub fn parse(input: &str) -> Result<Element, ParseError> {
+    let mut parser = Parser {
+        s: input,
+        pos: input.strip_prefix('\u{feff}').map_or(0, |_| 3),
+    };
+    parser.skip_misc()?;
+    if !parser.rest().starts_with('<') {
+        return Err(parser.error("expected the root element"));
+    }
+    let root = parser.element(0)?;
+    parser.skip_misc()?;
+    if !parser.rest().is_empty() {
+        return Err(parser.error("content after the root element"));
+    }
+    Ok(root)
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn error(&self, message: &'static str) -> ParseError {
+        ParseError {
+            offset: self.pos,
+            message,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Advance past `end`, returning what came before it
+    fn until(&mut self, end: &str, message: &'static str) -> Result<&'a str, ParseError> {
+        let rest = self.rest();
+        let len = rest.find(end).ok_or_else(|| self.error(message))?;
+        self.pos += len + end.len();
+        Ok(&rest[..len])
+    }
+
+    fn expect(&mut self, token: &str, message: &'static str) -> Result<(), ParseError> {
+        if !self.rest().starts_with(token) {
+            return Err(self.error(message));
+        }
+        self.pos += token.len();
+        Ok(())
+    }
+
+    fn skip_misc(&mut self) -> Result<(), ParseError> {
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("<?") {
+                self.until("?>", "unterminated processing instruction")?;
+            } else if rest.starts_with("<!--") {
+                self.until("-->", "unterminated comment")?;
+            } else if rest.starts_with("<!DOCTYPE") {
+                self.until(">", "unterminated DOCTYPE")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, ParseError> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '=' | '<'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected a name"));
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    fn element(&mut self, depth: usize) -> Result<Element, ParseError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("elements nested too deep"));
+        }
+        self.expect("<", "expected an element")?;
+        let mut element = Element::new(self.name()?);
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                return Ok(element);
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                break;
+            }
+            let name = self.name()?;
+            self.skip_whitespace();
+            self.expect("=", "expected `=` after the attribute name")?;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(q @ ('"' | '\'')) => q,
+                _ => return Err(self.error("expected a quoted attribute value")),
+            };
+            self.pos += 1;
+            let start = self.pos;
+            let raw = self.until(&quote.to_string(), "unterminated attribute value")?;
+            let value = unescape(raw).ok_or(ParseError {
+                offset: start,
+                message: "invalid character reference",
+            })?;
+            if element.attr(name).is_some() {
+                return Err(self.error("duplicate attribute"));
+            }
+            element.attrs.push((name.to_string(), value));
+        }
+
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("</") {
+                self.pos += 2;
+                if self.name()? != element.name {
+                    return Err(self.error("mismatched closing tag"));
+                }
+                self.skip_whitespace();
+                self.expect(">", "expected `>`")?;
+                return Ok(element);
+            } else if rest.starts_with("<!--") {
+                self.pos += 4;
+                let comment = self.until("-->", "unterminated comment")?;
+                element.children.push(Node::Comment(comment.to_string()));
+            } else if rest.starts_with("<![CDATA[") {
+                self.pos += 9;
+                let text = self.until("]]>", "unterminated CDATA section")?;
+                element.children.push(Node::Text(text.to_string()));
+            } else if rest.starts_with("<?") {
+                self.until("?>", "unterminated processing instruction")?;
+            } else if rest.starts_with('<') {
+                let child = self.element(depth + 1)?;
+                element.children.push(Node::Element(child));
+            } else if rest.is_empty() {
+                return Err(self.error("unclosed element"));
+            } else {
+                let start = self.pos;
+                let len = rest.find('<').unwrap_or(rest.len());
+                self.pos += len;
+                let raw = &rest[..len];
+                if !raw.trim().is_empty() {
+                    let text = unescape(raw.trim()).ok_or(ParseError {
+                        offset: start,
+                        message: "invalid character reference",
+                    })?;
+                    element.children.push(Node::Text(text));
+                }
+            }
+        }
+    }
+}
+
+/// `s` with its entity and character references resolved, `None` for an unknown one
+fn unescape(s: &str) -> Option<String> {
+    if !s.contains('&') {
+        return Some(s.to_string());
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        let end = rest[amp..].find(';')? + amp;
+        let c = match &rest[amp + 1..end] {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            reference => {
+                let code = match reference.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => reference.strip_prefix('#')?.parse().ok()?,
+                };
+                char::from_u32(code)?
+            }
+        };
+        out.push(c);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_write() {
+        let root = parse(
+            "\u{feff}<?xml version=\"1.0\"?>\n<!-- generated -->\n<A x='1' ns:y=\"a&amp;b\">\n  <B/>\n  <!-- keep -->\n  <C z=\"&#x3C;\">t &lt; 2</C><![CDATA[<raw>]]></A>\n",
+        )
+        .unwrap();
+        assert_eq!(root.name, "A");
+        assert_eq!(root.attr("ns:y"), Some("a&b"));
+        assert_eq!(root.children.len(), 4);
+        let Node::Element(c) = &root.children[2] else {
+            panic!("{root:?}");
+        };
+        assert_eq!(c.attr("z"), Some("<"));
+        assert_eq!(c.text(), "t < 2");
+
+        let mut out = String::new();
+        root.write(&mut out, 0);
+        assert_eq!(
+            out,
+            "<A x=\"1\" ns:y=\"a&amp;b\">\n    <B />\n    <!-- keep -->\n    <C z=\"&lt;\">t &lt; 2</C>\n    &lt;raw&gt;\n</A>\n"
+        );
+        assert_eq!(parse(&out).unwrap(), root);
+    }
+
+    #[test]
+    fn test_reject_malformed() {
+        for (input, message) in [
+            ("", "expected the root element"),
+            ("<A>", "unclosed element"),
+            ("<A></B>", "mismatched closing tag"),
+            ("<A x=1/>", "expected a quoted attribute value"),
+            ("<A x=\"1\" x=\"2\"/>", "duplicate attribute"),
+            ("<A x=\"&bogus;\"/>", "invalid character reference"),
+            ("<A/><B/>", "content after the root element"),
+        ] {
+            assert_eq!(parse(input).unwrap_err().message, message, "{input}");
+        }
+        let deep = "<A>".repeat(MAX_DEPTH + 2);
+        assert_eq!(
+            parse(&deep).unwrap_err().message,
+            "elements nested too deep"
+        );
+    }
+}
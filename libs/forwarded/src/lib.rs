@@ -0,0 +1,414 @@
+//! External origin of requests arriving through reverse proxies.
+//!
+//! `Forwarded` (RFC 7239), `X-Forwarded-Proto`, `X-Forwarded-Host` and
+//! `X-Forwarded-Prefix` are only honored when the connecting peer is a trusted
+//! proxy. Anyone else could use them to point generated links and redirects at a
+//! host of their choosing, so they are ignored for untrusted peers.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use http::HeaderMap;
+use http::header::{FORWARDED, HOST};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PREFIX: &str = "x-forwarded-prefix";
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Address range in CIDR notation, a bare address matches only itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // Peers of dual-stack listeners show up as IPv4-mapped IPv6 addresses
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => mask_eq(&net.octets(), &ip.octets(), self.prefix),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => mask_eq(&net.octets(), &ip.octets(), self.prefix),
+            _ => false,
+        }
+    }
+}
+
+fn mask_eq(net: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let full = usize::from(prefix / 8);
+    if net[..full] != ip[..full] {
+        return false;
+    }
+    let rest = prefix % 8;
+    if rest == 0 {
+        return true;
+    }
+    let mask = 0xffu8 << (8 - rest);
+    net[full] & mask == ip[full] & mask
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address in {s:?}"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("invalid prefix length in {s:?}"))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl Serialize for Cidr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Peers whose forwarding headers are honored, empty trusts nobody
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TrustedProxies(Vec<Cidr>);
+
+impl TrustedProxies {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Scheme, host and path prefix clients use to reach this service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    pub proto: String,
+    pub host: String,
+    /// Path the proxy strips before forwarding, empty or starting with `/` without a trailing one
+    pub prefix: String,
+}
+
+impl Origin {
+    /// Origin of a request from `peer` received on a listener speaking `proto`.
+    ///
+    /// Forwarding headers of trusted peers override the listener's scheme and the
+    /// `Host` header, a `Forwarded` header wins over the `X-Forwarded-*` ones.
+    /// Malformed values are ignored. `None` without any usable host.
+    pub fn from_request(
+        headers: &HeaderMap,
+        peer: IpAddr,
+        trusted: &TrustedProxies,
+        proto: &str,
+    ) -> Option<Self> {
+        let mut origin = Self {
+            proto: proto.to_string(),
+            host: header(headers, HOST.as_str())
+                .filter(|h| valid_host(h))
+                .unwrap_or_default(),
+            prefix: String::new(),
+        };
+        if trusted.contains(peer) {
+            let (fwd_proto, fwd_host) = match header(headers, FORWARDED.as_str()) {
+                Some(value) => parse_forwarded(&value),
+                None => (
+                    header(headers, X_FORWARDED_PROTO).map(first_value),
+                    header(headers, X_FORWARDED_HOST).map(first_value),
+                ),
+            };
+            if let Some(proto) = fwd_proto
+                .map(|p| p.to_ascii_lowercase())
+                .filter(|p| p == "http" || p == "https")
+            {
+                origin.proto = proto;
+            }
+            if let Some(host) = fwd_host.filter(|h| valid_host(h)) {
+                origin.host = host;
+            }
+            if let Some(prefix) = header(headers, X_FORWARDED_PREFIX)
+                .map(first_value)
+                .and_then(|p| normalize_prefix(&p))
+            {
+                origin.prefix = prefix;
+            }
+        }
+        (!origin.host.is_empty()).then_some(origin)
+    }
+
+    /// Absolute URL of `path`, which starts with `/`, as seen by the client
+    pub fn url(&self, path: &str) -> String {
+        format!("{}://{}{}{}", self.proto, self.host, self.prefix, path)
+    }
+}
+
+/// Make a configured URL absolute: a path is resolved against `origin`, anything
+/// else is returned unchanged, as is a path when the origin is unknown.
+pub fn absolute(origin: Option<&Origin>, url: &str) -> String {
+    match origin {
+        Some(origin) if url.starts_with('/') && !url.starts_with("//") => origin.url(url),
+        _ => url.to_string(),
+    }
+}
+
+/// Address of the client behind `peer`: the last `X-Forwarded-For` entry not added by a
+/// trusted proxy, `peer` itself when it is not trusted or the header is missing
+pub fn client_ip(headers: &HeaderMap, peer: IpAddr, trusted: &TrustedProxies) -> IpAddr {
+    let mut client = peer;
+    let Some(chain) = header(headers, X_FORWARDED_FOR) else {
+        return client;
+    };
+    for hop in chain.rsplit(',') {
+        if !trusted.contains(client) {
+            break;
+        }
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) => client = ip,
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Proxies append to lists, the first value is the one the client sent to the outermost proxy
+fn first_value(value: String) -> String {
+    value
+        .split(',')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+/// `proto` and `host` of the first element of a `Forwarded` header
+fn parse_forwarded(value: &str) -> (Option<String>, Option<String>) {
+    let first = split_unquoted(value, ',').into_iter().next().unwrap_or("");
+    let (mut proto, mut host) = (None, None);
+    for pair in split_unquoted(first, ';') {
+        let Some((key, val)) = pair.split_once('=') else {
+            continue;
+        };
+        let val = val.trim().trim_matches('"').to_string();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "proto" => proto = Some(val),
+            "host" => host = Some(val),
+            _ => {}
+        }
+    }
+    (proto, host)
+}
+
+fn split_unquoted(value: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(&value[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// Hostname or address with an optional port, nothing that could add a path or userinfo
+fn valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host.len() <= 255
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':' | '[' | ']'))
+}
+
+fn normalize_prefix(prefix: &str) -> Option<String> {
+    let prefix = prefix.trim_end_matches('/');
+    if prefix.is_empty() {
+        return Some(String::new());
+    }
+    let valid = prefix.starts_with('/')
+        && !prefix.starts_with("//")
+        && prefix.split('/').skip(1).all(|segment| {
+            !segment.is_empty()
+                && segment != "."
+                && segment != ".."
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~'))
+        });
+    valid.then(|| prefix.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn trusted() -> TrustedProxies {
+        TrustedProxies(vec!["10.0.0.0/8".parse().unwrap(), "::1".parse().unwrap()])
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, HeaderValue::from_static("vod.internal:8899"));
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn origin(headers: &HeaderMap, peer: &str) -> Origin {
+        Origin::from_request(headers, peer.parse().unwrap(), &trusted(), "http").unwrap()
+    }
+
+    #[test]
+    fn test_cidr_contains() {
+        let net: Cidr = "192.168.1.0/23".parse().unwrap();
+        assert!(net.contains("192.168.0.7".parse().unwrap()));
+        assert!(!net.contains("192.168.2.1".parse().unwrap()));
+        assert!(net.contains("::ffff:192.168.1.1".parse().unwrap()));
+        assert!(
+            "0.0.0.0/0"
+                .parse::<Cidr>()
+                .unwrap()
+                .contains("8.8.8.8".parse().unwrap())
+        );
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_trusted_peer_uses_forwarded_headers() {
+        let headers = headers(&[
+            (X_FORWARDED_PROTO, "https"),
+            (X_FORWARDED_HOST, "vod.example.com, proxy.internal"),
+            (X_FORWARDED_PREFIX, "/vod/"),
+        ]);
+        let origin = origin(&headers, "10.1.2.3");
+        assert_eq!(
+            origin.url("/s3/bucket/a.m4s"),
+            "https://vod.example.com/vod/s3/bucket/a.m4s"
+        );
+    }
+
+    #[test]
+    fn test_untrusted_peer_is_ignored() {
+        let headers = headers(&[
+            (X_FORWARDED_PROTO, "https"),
+            (X_FORWARDED_HOST, "evil.example"),
+            (X_FORWARDED_PREFIX, "/phish"),
+            ("forwarded", "host=evil.example;proto=https"),
+        ]);
+        let origin = origin(&headers, "203.0.113.9");
+        assert_eq!(origin.url("/a"), "http://vod.internal:8899/a");
+    }
+
+    #[test]
+    fn test_rfc7239_forwarded_wins() {
+        let headers = headers(&[
+            (
+                "forwarded",
+                r#"for="[2001:db8::1]:4711";proto=HTTPS;host="vod.example.com", for=10.0.0.2"#,
+            ),
+            (X_FORWARDED_HOST, "other.example.com"),
+        ]);
+        let origin = origin(&headers, "::1");
+        assert_eq!(origin.proto, "https");
+        assert_eq!(origin.host, "vod.example.com");
+    }
+
+    #[test]
+    fn test_malformed_values_are_ignored() {
+        let headers = headers(&[
+            (X_FORWARDED_PROTO, "javascript"),
+            (X_FORWARDED_HOST, "evil.example/path@"),
+            (X_FORWARDED_PREFIX, "//evil.example"),
+        ]);
+        let origin = origin(&headers, "10.0.0.1");
+        assert_eq!(origin.url("/a"), "http://vod.internal:8899/a");
+
+        assert_eq!(normalize_prefix("/"), Some(String::new()));
+        assert_eq!(normalize_prefix("/a/../b"), None);
+        assert_eq!(normalize_prefix("vod"), None);
+    }
+
+    #[test]
+    fn test_absolute() {
+        let origin = origin(&headers(&[]), "203.0.113.9");
+        assert_eq!(
+            absolute(Some(&origin), "/s3"),
+            "http://vod.internal:8899/s3"
+        );
+        assert_eq!(
+            absolute(Some(&origin), "https://cdn.example.com"),
+            "https://cdn.example.com"
+        );
+        assert_eq!(absolute(None, "/s3"), "/s3");
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let ip = |pairs: &[(&'static str, &str)], peer: &str| {
+            client_ip(&headers(pairs), peer.parse().unwrap(), &trusted()).to_string()
+        };
+        let chain = [(X_FORWARDED_FOR, "198.51.100.1, 203.0.113.9, 10.0.0.2")];
+        // The last hop a trusted proxy did not add, a spoofed first entry is ignored
+        assert_eq!(ip(&chain, "10.0.0.1"), "203.0.113.9");
+        assert_eq!(ip(&chain, "192.0.2.7"), "192.0.2.7");
+        assert_eq!(ip(&[], "10.0.0.1"), "10.0.0.1");
+        assert_eq!(ip(&[(X_FORWARDED_FOR, "bogus")], "::1"), "::1");
+    }
+
+    #[test]
+    fn test_trusted_proxies_config() {
+        #[derive(serde::Deserialize)]
+        struct Config {
+            trusted_proxies: TrustedProxies,
+        }
+        let cfg: Config =
+            toml::from_str(r#"trusted_proxies = ["10.0.0.0/8", "127.0.0.1"]"#).unwrap();
+        assert!(cfg.trusted_proxies.contains("127.0.0.1".parse().unwrap()));
+        assert!(!cfg.trusted_proxies.contains("127.0.0.2".parse().unwrap()));
+        assert!(toml::from_str::<Config>(r#"trusted_proxies = ["nope"]"#).is_err());
+    }
+}
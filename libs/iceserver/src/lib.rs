@@ -4,10 +4,11 @@ pub mod cloudflare;
 #[cfg(feature = "coturn")]
 pub mod coturn;
 
+use config_loader::Redacted;
 use serde::{Deserialize, Serialize};
 use webrtc::{Error, ice, ice_transport::ice_server::RTCIceServer};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct IceServer {
     #[serde(default)]
     pub urls: Vec<String>,
@@ -17,6 +18,16 @@ pub struct IceServer {
     pub credential: String,
 }
 
+impl std::fmt::Debug for IceServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IceServer")
+            .field("urls", &self.urls)
+            .field("username", &self.username)
+            .field("credential", &Redacted(&self.credential))
+            .finish()
+    }
+}
+
 // from https://github.com/webrtc-rs/webrtc/blob/71157ba2153a891a8cfd819f3cf1441a7a0808d8/webrtc/src/ice_transport/ice_server.rs
 impl IceServer {
     pub(crate) fn parse_url(&self, url_str: &str) -> webrtc::error::Result<ice::url::Url> {
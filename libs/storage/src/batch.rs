@@ -0,0 +1,369 @@
+//! Deleting many objects at once.
+//!
+//! Backends with a multi-object delete (S3's `DeleteObjects`) get one request per
+//! chunk of keys, the others get single deletes with a bounded number in flight. A
+//! chunk the backend rejects is retried key by key, so a partial failure reports the
+//! keys left behind instead of failing the whole batch.
+
+use std::future::Future;
+
+use opendal::Operator;
+use tokio::task::JoinSet;
+
+/// Options of [`delete_batch`]
+#[derive(Debug, Clone)]
+pub struct DeleteBatchOptions {
+    /// Single deletes in flight when the backend has no batch delete
+    pub concurrency: usize,
+    /// Report progress after this many keys were handled, 0 only reports the end
+    pub progress_every: usize,
+}
+
+impl Default for DeleteBatchOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 16,
+            progress_every: 1000,
+        }
+    }
+}
+
+/// Keys handled so far, passed to the progress callback of [`delete_batch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeleteProgress {
+    pub deleted: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+/// A key [`delete_batch`] could not delete
+#[derive(Debug, Clone)]
+pub struct FailedDelete {
+    pub key: String,
+    pub error: String,
+}
+
+#[derive(Debug, Default)]
+pub struct DeleteBatchReport {
+    pub deleted: usize,
+    pub failed: Vec<FailedDelete>,
+}
+
+impl DeleteBatchReport {
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Error of a batch that left objects behind
+#[derive(Debug)]
+pub struct PartialDeleteError {
+    pub deleted: usize,
+    pub failed: Vec<FailedDelete>,
+}
+
+impl std::fmt::Display for PartialDeleteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} objects deleted, {} failed",
+            self.deleted,
+            self.failed.len()
+        )?;
+        if let Some(first) = self.failed.first() {
+            write!(f, ", first '{}': {}", first.key, first.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PartialDeleteError {}
+
+impl From<DeleteBatchReport> for PartialDeleteError {
+    fn from(report: DeleteBatchReport) -> Self {
+        Self {
+            deleted: report.deleted,
+            failed: report.failed,
+        }
+    }
+}
+
+struct Tracker<F> {
+    report: DeleteBatchReport,
+    total: usize,
+    every: usize,
+    next: usize,
+    progress: F,
+}
+
+impl<F: FnMut(DeleteProgress)> Tracker<F> {
+    fn handled(&self) -> usize {
+        self.report.deleted + self.report.failed.len()
+    }
+
+    fn deleted(&mut self, count: usize) {
+        self.report.deleted += count;
+        self.report_progress();
+    }
+
+    fn failed(&mut self, key: String, error: String) {
+        self.report.failed.push(FailedDelete { key, error });
+        self.report_progress();
+    }
+
+    fn report_progress(&mut self) {
+        if self.every == 0 {
+            return;
+        }
+        let handled = self.handled();
+        if handled >= self.next && handled < self.total {
+            self.next = (handled / self.every + 1) * self.every;
+            (self.progress)(self.progress());
+        }
+    }
+
+    fn progress(&self) -> DeleteProgress {
+        DeleteProgress {
+            deleted: self.report.deleted,
+            failed: self.report.failed.len(),
+            total: self.total,
+        }
+    }
+
+    fn finish(mut self) -> DeleteBatchReport {
+        let progress = self.progress();
+        (self.progress)(progress);
+        self.report
+    }
+}
+
+/// Delete `keys`, calling `progress` every [`DeleteBatchOptions::progress_every`] keys
+/// and once at the end.
+///
+/// Never fails as a whole: keys that could not be deleted are listed in the report.
+/// Deleting a missing key succeeds.
+pub async fn delete_batch<F>(
+    operator: &Operator,
+    keys: Vec<String>,
+    options: &DeleteBatchOptions,
+    progress: F,
+) -> DeleteBatchReport
+where
+    F: FnMut(DeleteProgress),
+{
+    let mut tracker = Tracker {
+        report: DeleteBatchReport::default(),
+        total: keys.len(),
+        every: options.progress_every,
+        next: options.progress_every,
+        progress,
+    };
+
+    let batch_size = operator
+        .info()
+        .full_capability()
+        .delete_max_size
+        .unwrap_or(1);
+    if batch_size > 1 {
+        for chunk in keys.chunks(batch_size) {
+            match operator.delete_iter(chunk.iter().map(String::as_str)).await {
+                Ok(()) => tracker.deleted(chunk.len()),
+                Err(e) => {
+                    tracing::debug!(
+                        "batch delete of {} keys failed, retrying one by one: {}",
+                        chunk.len(),
+                        e
+                    );
+                    delete_each(
+                        operator,
+                        chunk.to_vec(),
+                        options.concurrency,
+                        &mut tracker,
+                        delete_one,
+                    )
+                    .await;
+                }
+            }
+        }
+    } else {
+        delete_each(
+            operator,
+            keys,
+            options.concurrency,
+            &mut tracker,
+            delete_one,
+        )
+        .await;
+    }
+    tracker.finish()
+}
+
+async fn delete_one(operator: Operator, key: String) -> opendal::Result<()> {
+    operator.delete(&key).await
+}
+
+/// Run `delete` for every key with at most `concurrency` of them in flight
+async fn delete_each<F, P, Fut>(
+    operator: &Operator,
+    keys: Vec<String>,
+    concurrency: usize,
+    tracker: &mut Tracker<P>,
+    delete: F,
+) where
+    F: Fn(Operator, String) -> Fut,
+    Fut: Future<Output = opendal::Result<()>> + Send + 'static,
+    P: FnMut(DeleteProgress),
+{
+    let concurrency = concurrency.max(1);
+    let mut keys = keys.into_iter();
+    let mut tasks = JoinSet::new();
+    loop {
+        while tasks.len() < concurrency {
+            let Some(key) = keys.next() else { break };
+            let deleting = delete(operator.clone(), key.clone());
+            tasks.spawn(async move { (key, deleting.await) });
+        }
+        let Some(joined) = tasks.join_next().await else {
+            break;
+        };
+        match joined {
+            Ok((_, Ok(()))) => tracker.deleted(1),
+            Ok((key, Err(e))) => tracker.failed(key, e.to_string()),
+            // The task only awaits the delete, it panics with it
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use opendal::services;
+
+    use super::*;
+
+    const KEYS: usize = 10_000;
+    /// Round trip of a single delete to a remote backend
+    const DELETE_LATENCY: Duration = Duration::from_millis(5);
+
+    async fn memory_with(keys: usize) -> (Operator, Vec<String>) {
+        let operator = Operator::new(services::Memory::default()).unwrap().finish();
+        let keys: Vec<String> = (0..keys)
+            .map(|i| format!("cam/1/v_seg_{i:05}.m4s"))
+            .collect();
+        for key in &keys {
+            operator.write(key, "segment").await.unwrap();
+        }
+        (operator, keys)
+    }
+
+    async fn remaining(operator: &Operator) -> usize {
+        operator
+            .list_with("cam/")
+            .recursive(true)
+            .await
+            .unwrap()
+            .iter()
+            .filter(|e| !e.metadata().is_dir())
+            .count()
+    }
+
+    async fn slow_delete(operator: Operator, key: String) -> opendal::Result<()> {
+        tokio::time::sleep(DELETE_LATENCY).await;
+        operator.delete(&key).await
+    }
+
+    /// Virtual time deleting every key with `concurrency` deletes in flight takes
+    async fn timed(concurrency: usize) -> Duration {
+        let (operator, keys) = memory_with(KEYS).await;
+        let mut tracker = Tracker {
+            report: DeleteBatchReport::default(),
+            total: keys.len(),
+            every: 0,
+            next: 0,
+            progress: |_: DeleteProgress| {},
+        };
+        let started = tokio::time::Instant::now();
+        delete_each(&operator, keys, concurrency, &mut tracker, slow_delete).await;
+        let elapsed = started.elapsed();
+        assert_eq!(tracker.report.deleted, KEYS);
+        assert_eq!(remaining(&operator).await, 0);
+        elapsed
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_deletes_beat_sequential() {
+        let sequential = timed(1).await;
+        let concurrent = timed(DeleteBatchOptions::default().concurrency).await;
+        println!("{KEYS} deletes: sequential {sequential:?}, concurrent {concurrent:?}");
+        assert_eq!(sequential, DELETE_LATENCY * KEYS as u32);
+        assert!(concurrent * 10 < sequential);
+    }
+
+    #[tokio::test]
+    async fn test_delete_batch_reports_progress() {
+        let (operator, keys) = memory_with(KEYS).await;
+        let mut reported = Vec::new();
+        let report = delete_batch(
+            &operator,
+            keys,
+            &DeleteBatchOptions::default(),
+            |progress| reported.push(progress),
+        )
+        .await;
+
+        assert!(report.is_complete());
+        assert_eq!(report.deleted, KEYS);
+        assert_eq!(remaining(&operator).await, 0);
+        // Every 1000 keys, then the final count
+        assert_eq!(reported.len(), KEYS / 1000);
+        assert!(reported.windows(2).all(|w| w[0].deleted < w[1].deleted));
+        assert_eq!(
+            reported.last(),
+            Some(&DeleteProgress {
+                deleted: KEYS,
+                failed: 0,
+                total: KEYS,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_batch_lists_failed_keys() {
+        let (operator, keys) = memory_with(10).await;
+        let mut tracker = Tracker {
+            report: DeleteBatchReport::default(),
+            total: keys.len(),
+            every: 0,
+            next: 0,
+            progress: |_: DeleteProgress| {},
+        };
+        delete_each(
+            &operator,
+            keys,
+            4,
+            &mut tracker,
+            |operator, key| async move {
+                if key.ends_with("3.m4s") || key.ends_with("7.m4s") {
+                    return Err(opendal::Error::new(
+                        opendal::ErrorKind::PermissionDenied,
+                        "access denied",
+                    ));
+                }
+                operator.delete(&key).await
+            },
+        )
+        .await;
+
+        let report = tracker.finish();
+        assert_eq!(report.deleted, 8);
+        let mut failed: Vec<&str> = report.failed.iter().map(|f| f.key.as_str()).collect();
+        failed.sort();
+        assert_eq!(failed, ["cam/1/v_seg_00003.m4s", "cam/1/v_seg_00007.m4s"]);
+        assert_eq!(remaining(&operator).await, 2);
+
+        let err = PartialDeleteError::from(report);
+        assert!(err.to_string().starts_with("8 objects deleted, 2 failed"));
+    }
+}
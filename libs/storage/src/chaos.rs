@@ -0,0 +1,180 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use opendal::raw::{
+    Access, Layer, LayeredAccess, OpList, OpPresign, OpRead, OpStat, OpWrite, RpDelete, RpList,
+    RpPresign, RpRead, RpStat, RpWrite,
+};
+use opendal::{Error, ErrorKind};
+use serde::{Deserialize, Serialize};
+
+/// Whether chaos settings are honored by this build, release builds need the `chaos` feature
+pub const CHAOS_AVAILABLE: bool = cfg!(any(debug_assertions, feature = "chaos"));
+
+/// Injected failures and latency for one operation type
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct FaultConfig {
+    /// Fraction of operations failing with a temporary error, from 0.0 to 1.0
+    #[serde(default)]
+    pub failure_rate: f64,
+    /// Delay added before every operation
+    #[serde(default)]
+    pub latency_ms: u64,
+}
+
+/// Storage failure injection settings, for testing degradation without breaking a real bucket
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChaosConfig {
+    /// Seed of the failure sequence, the same seed replays the same decisions
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub read: FaultConfig,
+    #[serde(default)]
+    pub write: FaultConfig,
+    #[serde(default)]
+    pub stat: FaultConfig,
+    #[serde(default)]
+    pub presign: FaultConfig,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ChaosOp {
+    Read,
+    Write,
+    Stat,
+    Presign,
+}
+
+#[derive(Debug)]
+struct ChaosState {
+    config: RwLock<ChaosConfig>,
+    rng: AtomicU64,
+}
+
+/// OpenDAL layer injecting failures and latency, settings can be changed at runtime.
+///
+/// Clones share their settings, so a clone kept by a debug endpoint controls every
+/// operator the layer was applied to.
+#[derive(Debug, Clone)]
+pub struct ChaosLayer {
+    state: Arc<ChaosState>,
+}
+
+impl ChaosLayer {
+    pub fn new(config: ChaosConfig) -> Self {
+        Self {
+            state: Arc::new(ChaosState {
+                rng: AtomicU64::new(config.seed),
+                config: RwLock::new(config),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> ChaosConfig {
+        self.state.config.read().unwrap().clone()
+    }
+
+    /// Replace the settings and restart the failure sequence from the new seed
+    pub fn set_config(&self, config: ChaosConfig) {
+        let mut current = self.state.config.write().unwrap();
+        self.state.rng.store(config.seed, Ordering::Relaxed);
+        *current = config;
+    }
+
+    /// Next value of a splitmix64 sequence mapped to [0, 1)
+    fn next_unit(&self) -> f64 {
+        let mut z = self
+            .state
+            .rng
+            .fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+            .wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    async fn inject(&self, op: ChaosOp, path: &str) -> opendal::Result<()> {
+        let fault = {
+            let config = self.state.config.read().unwrap();
+            match op {
+                ChaosOp::Read => config.read,
+                ChaosOp::Write => config.write,
+                ChaosOp::Stat => config.stat,
+                ChaosOp::Presign => config.presign,
+            }
+        };
+        if fault.latency_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(fault.latency_ms)).await;
+        }
+        if fault.failure_rate > 0.0 && self.next_unit() < fault.failure_rate {
+            tracing::debug!("storage chaos: injected {:?} failure for {}", op, path);
+            return Err(Error::new(
+                ErrorKind::Unexpected,
+                format!("storage chaos: injected {op:?} failure"),
+            )
+            .with_context("path", path)
+            .set_temporary());
+        }
+        Ok(())
+    }
+}
+
+impl<A: Access> Layer<A> for ChaosLayer {
+    type LayeredAccess = ChaosAccessor<A>;
+
+    fn layer(&self, inner: A) -> Self::LayeredAccess {
+        ChaosAccessor {
+            inner,
+            chaos: self.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ChaosAccessor<A: Access> {
+    inner: A,
+    chaos: ChaosLayer,
+}
+
+impl<A: Access> LayeredAccess for ChaosAccessor<A> {
+    type Inner = A;
+    type Reader = A::Reader;
+    type Writer = A::Writer;
+    type Lister = A::Lister;
+    type Deleter = A::Deleter;
+
+    fn inner(&self) -> &Self::Inner {
+        &self.inner
+    }
+
+    async fn read(&self, path: &str, args: OpRead) -> opendal::Result<(RpRead, Self::Reader)> {
+        self.chaos.inject(ChaosOp::Read, path).await?;
+        self.inner.read(path, args).await
+    }
+
+    async fn write(&self, path: &str, args: OpWrite) -> opendal::Result<(RpWrite, Self::Writer)> {
+        self.chaos.inject(ChaosOp::Write, path).await?;
+        self.inner.write(path, args).await
+    }
+
+    async fn stat(&self, path: &str, args: OpStat) -> opendal::Result<RpStat> {
+        self.chaos.inject(ChaosOp::Stat, path).await?;
+        self.inner.stat(path, args).await
+    }
+
+    async fn presign(&self, path: &str, args: OpPresign) -> opendal::Result<RpPresign> {
+        self.chaos.inject(ChaosOp::Presign, path).await?;
+        self.inner.presign(path, args).await
+    }
+
+    async fn list(&self, path: &str, args: OpList) -> opendal::Result<(RpList, Self::Lister)> {
+        self.inner.list(path, args).await
+    }
+
+    async fn delete(&self) -> opendal::Result<(RpDelete, Self::Deleter)> {
+        self.inner.delete().await
+    }
+}
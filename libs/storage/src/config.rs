@@ -1,3 +1,4 @@
+use config_loader::Secret;
 use serde::{Deserialize, Serialize};
 
 /// Unified storage configuration for Live777 components
@@ -19,27 +20,58 @@ pub enum StorageConfig {
         /// AWS region
         #[serde(default)]
         region: Option<String>,
-        /// Custom endpoint for S3-compatible services
+        /// Custom endpoint for S3-compatible services, a list enables health-checked failover
         #[serde(default)]
-        endpoint: Option<String>,
+        endpoint: Option<S3Endpoint>,
         /// Access key ID
         #[serde(default)]
         access_key_id: Option<String>,
         /// Secret access key
         #[serde(default)]
-        secret_access_key: Option<String>,
+        secret_access_key: Option<Secret>,
         /// Session token for temporary credentials
         #[serde(default)]
-        session_token: Option<String>,
+        session_token: Option<Secret>,
         /// Disable config/credential auto-loading
         #[serde(default)]
         disable_config_load: bool,
         /// Enable virtual host style addressing
         #[serde(default)]
         enable_virtual_host_style: bool,
+        /// Base URL clients reach the endpoint at, presigned URLs handed out are rewritten
+        /// to it. A path such as `/s3` is resolved against the request's external origin.
+        #[serde(default)]
+        public_endpoint: Option<String>,
     },
 }
 
+impl StorageConfig {
+    /// `presigned` pointed at `public_endpoint`, `None` when no rewrite is configured.
+    ///
+    /// Only the scheme and authority are replaced and the public path is prepended,
+    /// the signature still covers the original host, so the proxy serving the
+    /// public endpoint has to forward requests with the internal `Host`.
+    pub fn public_url(&self, presigned: &str) -> Option<String> {
+        let Self::S3 {
+            public_endpoint: Some(base),
+            ..
+        } = self
+        else {
+            return None;
+        };
+        let path_and_query = match presigned.split_once("://") {
+            Some((_, rest)) => rest.find(['/', '?']).map_or("/", |i| &rest[i..]),
+            None => presigned,
+        };
+        let path_and_query = if path_and_query.starts_with('?') {
+            format!("/{path_and_query}")
+        } else {
+            path_and_query.to_string()
+        };
+        Some(format!("{}{}", base.trim_end_matches('/'), path_and_query))
+    }
+}
+
 impl Default for StorageConfig {
     fn default() -> Self {
         Self::Fs {
@@ -48,6 +80,35 @@ impl Default for StorageConfig {
     }
 }
 
+/// One endpoint, or several in priority order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum S3Endpoint {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl S3Endpoint {
+    pub fn urls(&self) -> Vec<&str> {
+        match self {
+            Self::Single(url) => vec![url.as_str()],
+            Self::Multiple(urls) => urls.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+impl From<&str> for S3Endpoint {
+    fn from(url: &str) -> Self {
+        Self::Single(url.to_string())
+    }
+}
+
+impl From<String> for S3Endpoint {
+    fn from(url: String) -> Self {
+        Self::Single(url)
+    }
+}
+
 fn default_s3_root() -> String {
     "/".to_string()
 }
@@ -0,0 +1,494 @@
+//! Staged storage diagnostics, telling which operation fails when storage breaks.
+//!
+//! Stages run in order: resolve the endpoint, connect to it (TCP, then TLS for `https`),
+//! list the root, write, read and delete a probe object, and fetch a presigned URL. Every
+//! stage runs under its own timeout, and once one fails the later stages are skipped.
+
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use opendal::Operator;
+use serde::{Deserialize, Serialize};
+
+use crate::StorageConfig;
+
+/// Directory of the probe objects, each run writes its own
+pub const PROBE_PREFIX: &str = "_diagnose/";
+
+const PROBE_BODY: &[u8] = b"live777 storage probe";
+
+/// `[recorder.diagnose]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnoseConfig {
+    /// Skip the write, read and delete probes, for credentials without write access
+    #[serde(default)]
+    pub read_only: bool,
+    /// How long one stage may take before it fails
+    #[serde(default = "default_stage_timeout_ms")]
+    pub stage_timeout_ms: u64,
+}
+
+impl Default for DiagnoseConfig {
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            stage_timeout_ms: default_stage_timeout_ms(),
+        }
+    }
+}
+
+fn default_stage_timeout_ms() -> u64 {
+    5_000
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// DNS lookup of the endpoint host
+    Resolve,
+    /// TCP connect, and the TLS handshake for `https` endpoints
+    Connect,
+    /// List the root, which checks the credentials and that the bucket exists
+    List,
+    Write,
+    Read,
+    Delete,
+    /// Presign a read of the probe and fetch it, checking the signature is accepted
+    Presign,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Resolve => "resolve",
+            Self::Connect => "connect",
+            Self::List => "list",
+            Self::Write => "write",
+            Self::Read => "read",
+            Self::Delete => "delete",
+            Self::Presign => "presign",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageStatus {
+    Ok,
+    Failed,
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageReport {
+    pub stage: Stage,
+    pub status: StageStatus,
+    /// Time the stage took, `None` when skipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// Underlying error of a failed stage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Why a stage was skipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiagnoseReport {
+    /// No stage failed
+    pub ok: bool,
+    /// Endpoint the network stages went to, `None` for filesystem storage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    pub stages: Vec<StageReport>,
+}
+
+/// Run the stages against `operator`, built from `config`. `selected_endpoint` is the
+/// endpoint a failover operator currently routes to.
+pub async fn diagnose(
+    config: &StorageConfig,
+    operator: &Operator,
+    selected_endpoint: Option<&str>,
+    options: &DiagnoseConfig,
+) -> DiagnoseReport {
+    let endpoint = endpoint_url(config, selected_endpoint);
+    let timeout = Duration::from_millis(options.stage_timeout_ms.max(1));
+    let mut run = Run {
+        timeout,
+        stages: Vec::new(),
+        failed: None,
+    };
+    let probe = format!(
+        "{PROBE_PREFIX}probe-{}",
+        chrono::Utc::now().timestamp_micros()
+    );
+
+    match endpoint.as_deref() {
+        Some(url) => {
+            let addrs = run.stage(Stage::Resolve, resolve(url)).await;
+            run.stage(
+                Stage::Connect,
+                connect(url, addrs.unwrap_or_default(), timeout),
+            )
+            .await;
+        }
+        None => {
+            run.skip(Stage::Resolve, "not used by filesystem storage");
+            run.skip(Stage::Connect, "not used by filesystem storage");
+        }
+    }
+    run.stage(Stage::List, async {
+        operator.check().await.map_err(|e| e.to_string())
+    })
+    .await;
+
+    if options.read_only {
+        for stage in [Stage::Write, Stage::Read, Stage::Delete] {
+            run.skip(stage, "read_only is set");
+        }
+    } else {
+        run.stage(Stage::Write, async {
+            operator
+                .write(&probe, PROBE_BODY)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+        .await;
+        run.stage(Stage::Read, async {
+            let body = operator.read(&probe).await.map_err(|e| e.to_string())?;
+            if body.to_vec() != PROBE_BODY {
+                return Err(format!(
+                    "read back {} bytes that differ from the {} written",
+                    body.len(),
+                    PROBE_BODY.len()
+                ));
+            }
+            Ok(())
+        })
+        .await;
+        run.stage(Stage::Delete, async {
+            operator.delete(&probe).await.map_err(|e| e.to_string())
+        })
+        .await;
+    }
+
+    match config {
+        StorageConfig::S3 { .. } => {
+            run.stage(Stage::Presign, presign(operator, &probe, timeout))
+                .await;
+        }
+        StorageConfig::Fs { .. } => run.skip(Stage::Presign, "filesystem storage cannot presign"),
+    }
+
+    DiagnoseReport {
+        ok: run.failed.is_none(),
+        endpoint,
+        stages: run.stages,
+    }
+}
+
+struct Run {
+    timeout: Duration,
+    stages: Vec<StageReport>,
+    failed: Option<Stage>,
+}
+
+impl Run {
+    /// Run `work` unless an earlier stage failed, its value when it succeeds in time
+    async fn stage<T>(
+        &mut self,
+        stage: Stage,
+        work: impl Future<Output = Result<T, String>>,
+    ) -> Option<T> {
+        if let Some(failed) = self.failed {
+            self.skip(stage, &format!("{failed} failed"));
+            return None;
+        }
+        let started = Instant::now();
+        let result = tokio::time::timeout(self.timeout, work).await;
+        let latency_ms = Some(started.elapsed().as_millis() as u64);
+        let (value, error) = match result {
+            Ok(Ok(value)) => (Some(value), None),
+            Ok(Err(e)) => (None, Some(e)),
+            Err(_) => (
+                None,
+                Some(format!("timed out after {} ms", self.timeout.as_millis())),
+            ),
+        };
+        if error.is_some() {
+            self.failed = Some(stage);
+        }
+        self.stages.push(StageReport {
+            stage,
+            status: if error.is_some() {
+                StageStatus::Failed
+            } else {
+                StageStatus::Ok
+            },
+            latency_ms,
+            error,
+            reason: None,
+        });
+        value
+    }
+
+    fn skip(&mut self, stage: Stage, reason: &str) {
+        self.stages.push(StageReport {
+            stage,
+            status: StageStatus::Skipped,
+            latency_ms: None,
+            error: None,
+            reason: Some(reason.to_string()),
+        });
+    }
+}
+
+/// Endpoint the S3 operator talks to, AWS's regional one when none is configured
+fn endpoint_url(config: &StorageConfig, selected: Option<&str>) -> Option<String> {
+    let StorageConfig::S3 {
+        endpoint, region, ..
+    } = config
+    else {
+        return None;
+    };
+    if let Some(url) = selected.or_else(|| endpoint.as_ref()?.urls().first().copied()) {
+        return Some(url.to_string());
+    }
+    let region = region.as_deref().unwrap_or("us-east-1");
+    Some(format!("https://s3.{region}.amazonaws.com"))
+}
+
+async fn resolve(endpoint: &str) -> Result<Vec<SocketAddr>, String> {
+    let url = url::Url::parse(endpoint).map_err(|e| format!("invalid endpoint: {e}"))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| "endpoint has no host".to_string())?
+        .trim_matches(['[', ']']);
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| "endpoint has no port".to_string())?;
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| e.to_string())?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("{host} resolved to no address"));
+    }
+    Ok(addrs)
+}
+
+async fn connect(endpoint: &str, addrs: Vec<SocketAddr>, timeout: Duration) -> Result<(), String> {
+    tokio::net::TcpStream::connect(&addrs[..])
+        .await
+        .map_err(|e| e.to_string())?;
+    if endpoint.starts_with("https://") {
+        // Any answer, an error status included, means the handshake went through
+        client(timeout)?
+            .head(endpoint)
+            .send()
+            .await
+            .map_err(|e| error_chain(&e))?;
+    }
+    Ok(())
+}
+
+async fn presign(operator: &Operator, probe: &str, timeout: Duration) -> Result<(), String> {
+    let presigned = operator
+        .presign_read(probe, Duration::from_secs(60))
+        .await
+        .map_err(|e| e.to_string())?;
+    let response = client(timeout)?
+        .get(presigned.uri().to_string())
+        .send()
+        .await
+        .map_err(|e| error_chain(&e.without_url()))?;
+    let status = response.status();
+    // The probe is gone by now, or was never written when read-only
+    if status.is_success() || status == reqwest::StatusCode::NOT_FOUND {
+        return Ok(());
+    }
+    let body = response.text().await.unwrap_or_default();
+    match xml_code(&body) {
+        Some(code) => Err(format!("presigned GET answered {status}: {code}")),
+        None => Err(format!("presigned GET answered {status}")),
+    }
+}
+
+fn client(timeout: Duration) -> Result<reqwest::Client, String> {
+    reqwest::Client::builder()
+        .no_proxy()
+        .timeout(timeout)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// `e` with its sources, reqwest keeps the TLS or connect error a few levels down
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+/// `<Code>` of an S3 error document
+fn xml_code(body: &str) -> Option<&str> {
+    let start = body.find("<Code>")? + "<Code>".len();
+    let len = body[start..].find("</Code>")?;
+    Some(&body[start..start + len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statuses(report: &DiagnoseReport) -> Vec<(Stage, StageStatus)> {
+        report.stages.iter().map(|s| (s.stage, s.status)).collect()
+    }
+
+    fn s3(endpoint: &str) -> StorageConfig {
+        StorageConfig::S3 {
+            bucket: "recordings".to_string(),
+            root: "/".to_string(),
+            region: Some("us-east-1".to_string()),
+            endpoint: Some(endpoint.into()),
+            access_key_id: Some("minioadmin".to_string()),
+            secret_access_key: Some("minioadmin".into()),
+            session_token: None,
+            disable_config_load: true,
+            enable_virtual_host_style: false,
+            public_endpoint: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fs_probes_and_cleans_up() {
+        let root = std::env::temp_dir().join(format!("live777-diagnose-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let config = StorageConfig::Fs {
+            root: root.to_string_lossy().into_owned(),
+        };
+        let operator = crate::create_operator(&config).unwrap();
+        let report = diagnose(&config, &operator, None, &DiagnoseConfig::default()).await;
+        assert!(report.ok, "{report:?}");
+        assert_eq!(report.endpoint, None);
+        assert_eq!(
+            statuses(&report),
+            [
+                (Stage::Resolve, StageStatus::Skipped),
+                (Stage::Connect, StageStatus::Skipped),
+                (Stage::List, StageStatus::Ok),
+                (Stage::Write, StageStatus::Ok),
+                (Stage::Read, StageStatus::Ok),
+                (Stage::Delete, StageStatus::Ok),
+                (Stage::Presign, StageStatus::Skipped),
+            ]
+        );
+        assert!(report.stages[2].latency_ms.is_some());
+        let probes = root.join(PROBE_PREFIX);
+        assert!(!probes.exists() || probes.read_dir().unwrap().next().is_none());
+
+        let read_only = DiagnoseConfig {
+            read_only: true,
+            ..Default::default()
+        };
+        let report = diagnose(&config, &operator, None, &read_only).await;
+        assert!(report.ok);
+        assert_eq!(report.stages[3].status, StageStatus::Skipped);
+        assert_eq!(report.stages[3].reason.as_deref(), Some("read_only is set"));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_stages_after_a_failure_are_skipped() {
+        // Bound then dropped, nothing listens there
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = s3(&format!("http://{addr}"));
+        let operator = crate::create_operator(&config).unwrap();
+        let report = diagnose(&config, &operator, None, &DiagnoseConfig::default()).await;
+        assert!(!report.ok);
+        assert_eq!(report.endpoint, Some(format!("http://{addr}")));
+        assert_eq!(
+            statuses(&report),
+            [
+                (Stage::Resolve, StageStatus::Ok),
+                (Stage::Connect, StageStatus::Failed),
+                (Stage::List, StageStatus::Skipped),
+                (Stage::Write, StageStatus::Skipped),
+                (Stage::Read, StageStatus::Skipped),
+                (Stage::Delete, StageStatus::Skipped),
+                (Stage::Presign, StageStatus::Skipped),
+            ]
+        );
+        assert!(report.stages[1].error.is_some());
+        assert_eq!(report.stages[6].reason.as_deref(), Some("connect failed"));
+    }
+
+    #[tokio::test]
+    async fn test_stage_timeout() {
+        // Accepts connections and never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+        let config = s3(&format!("http://{addr}"));
+        let operator = crate::create_operator(&config).unwrap();
+        let options = DiagnoseConfig {
+            stage_timeout_ms: 200,
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let report = diagnose(&config, &operator, None, &options).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let list = &report.stages[2];
+        assert_eq!(
+            (list.stage, list.status),
+            (Stage::List, StageStatus::Failed)
+        );
+        assert_eq!(list.error.as_deref(), Some("timed out after 200 ms"));
+        assert_eq!(report.stages[3].status, StageStatus::Skipped);
+    }
+
+    #[test]
+    fn test_endpoint_url() {
+        assert_eq!(
+            endpoint_url(&s3("http://minio:9000"), Some("http://minio-2:9000")).as_deref(),
+            Some("http://minio-2:9000")
+        );
+        assert_eq!(
+            endpoint_url(&s3("http://minio:9000"), None).as_deref(),
+            Some("http://minio:9000")
+        );
+        let mut aws = s3("");
+        if let StorageConfig::S3 {
+            endpoint, region, ..
+        } = &mut aws
+        {
+            *endpoint = None;
+            *region = Some("eu-west-1".to_string());
+        }
+        assert_eq!(
+            endpoint_url(&aws, None).as_deref(),
+            Some("https://s3.eu-west-1.amazonaws.com")
+        );
+        assert_eq!(
+            xml_code("<Error><Code>SignatureDoesNotMatch</Code></Error>"),
+            Some("SignatureDoesNotMatch")
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use opendal::Operator;
+use serde::Serialize;
+
+/// Interval between health probes of configured endpoints
+pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Endpoint {
+    url: Option<String>,
+    operator: Operator,
+    healthy: AtomicBool,
+}
+
+/// Health of one configured endpoint, as reported by storage status endpoints
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EndpointStatus {
+    /// Endpoint URL, `None` for backends without an explicit endpoint
+    pub endpoint: Option<String>,
+    pub healthy: bool,
+    pub selected: bool,
+}
+
+/// Operator set that routes to the first healthy endpoint.
+///
+/// Callers fetch [`FailoverOperator::current`] per operation (or per small batch) so
+/// that reads, writes and presigned URLs follow the selection. With a single endpoint
+/// this is a plain operator and no probing happens.
+#[derive(Clone)]
+pub struct FailoverOperator {
+    endpoints: Arc<Vec<Endpoint>>,
+    selected: Arc<AtomicUsize>,
+}
+
+impl From<Operator> for FailoverOperator {
+    fn from(operator: Operator) -> Self {
+        Self::new(vec![(None, operator)])
+    }
+}
+
+impl FailoverOperator {
+    /// Build from `(endpoint, operator)` pairs in priority order; must not be empty
+    pub fn new(operators: Vec<(Option<String>, Operator)>) -> Self {
+        assert!(!operators.is_empty(), "at least one operator is required");
+        let endpoints = operators
+            .into_iter()
+            .map(|(url, operator)| Endpoint {
+                url,
+                operator,
+                healthy: AtomicBool::new(true),
+            })
+            .collect();
+        Self {
+            endpoints: Arc::new(endpoints),
+            selected: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Apply an OpenDAL layer to the operator of every endpoint
+    pub fn layer<L>(self, layer: L) -> Self
+    where
+        L: opendal::raw::Layer<opendal::raw::Accessor> + Clone,
+    {
+        let endpoints = self
+            .endpoints
+            .iter()
+            .map(|e| Endpoint {
+                url: e.url.clone(),
+                operator: e.operator.clone().layer(layer.clone()),
+                healthy: AtomicBool::new(e.healthy.load(Ordering::Relaxed)),
+            })
+            .collect();
+        Self {
+            endpoints: Arc::new(endpoints),
+            selected: self.selected,
+        }
+    }
+
+    /// Operator of the currently selected endpoint
+    pub fn current(&self) -> Operator {
+        self.endpoints[self.selected_index()].operator.clone()
+    }
+
+    /// URL of the currently selected endpoint
+    pub fn selected_endpoint(&self) -> Option<String> {
+        self.endpoints[self.selected_index()].url.clone()
+    }
+
+    pub fn is_failover(&self) -> bool {
+        self.endpoints.len() > 1
+    }
+
+    pub fn status(&self) -> Vec<EndpointStatus> {
+        let selected = self.selected_index();
+        self.endpoints
+            .iter()
+            .enumerate()
+            .map(|(i, e)| EndpointStatus {
+                endpoint: e.url.clone(),
+                healthy: e.healthy.load(Ordering::Relaxed),
+                selected: i == selected,
+            })
+            .collect()
+    }
+
+    /// Probe every endpoint once and select the first healthy one.
+    ///
+    /// When all endpoints are down the selection is left unchanged.
+    pub async fn probe(&self) {
+        for endpoint in self.endpoints.iter() {
+            let healthy = endpoint.operator.check().await.is_ok();
+            let was_healthy = endpoint.healthy.swap(healthy, Ordering::Relaxed);
+            if was_healthy != healthy {
+                tracing::warn!(
+                    "storage endpoint {:?} is now {}",
+                    endpoint.url,
+                    if healthy { "healthy" } else { "unhealthy" }
+                );
+            }
+        }
+        self.reselect();
+    }
+
+    /// Spawn the background probe loop, a no-op for single-endpoint configs. Abort the
+    /// returned task to stop probing an operator that was replaced
+    pub fn spawn_probe(&self, interval: Duration) -> Option<tokio::task::JoinHandle<()>> {
+        if !self.is_failover() {
+            return None;
+        }
+        let this = self.clone();
+        Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                ticker.tick().await;
+                this.probe().await;
+            }
+        }))
+    }
+
+    fn selected_index(&self) -> usize {
+        self.selected.load(Ordering::Relaxed)
+    }
+
+    fn reselect(&self) {
+        let Some(next) = self
+            .endpoints
+            .iter()
+            .position(|e| e.healthy.load(Ordering::Relaxed))
+        else {
+            return;
+        };
+        let previous = self.selected.swap(next, Ordering::Relaxed);
+        if previous != next {
+            tracing::warn!(
+                "storage failover: switched from {:?} to {:?}",
+                self.endpoints[previous].url,
+                self.endpoints[next].url
+            );
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_healthy(&self, index: usize, healthy: bool) {
+        self.endpoints[index]
+            .healthy
+            .store(healthy, Ordering::Relaxed);
+        self.reselect();
+    }
+}
+
+impl std::fmt::Debug for FailoverOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FailoverOperator")
+            .field("status", &self.status())
+            .finish()
+    }
+}
@@ -0,0 +1,238 @@
+//! Notices telling livevod which objects were rewritten.
+//!
+//! Recording objects are written once, except manifests and `segments.jsonl` which a
+//! finalization or repair writes again. Whoever sees the rewrite, liveion writing to
+//! storage itself or liveman once a node's uploads finished, POSTs the keys to livevod's
+//! [`INVALIDATE_PATH`] so its caches drop them instead of serving the stale version
+//! until they expire.
+//!
+//! Notices are signed with a secret shared with livevod, an HMAC-SHA256 of the
+//! timestamp and body in [`SIGNATURE_HEADER`], `t={unix seconds},v1={hex}`.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::Duration;
+
+use config_loader::Secret;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::sigv4::{constant_time_eq, hex, hmac_sha256};
+
+pub const INVALIDATE_PATH: &str = "/api/internal/invalidate";
+
+pub const SIGNATURE_HEADER: &str = "x-live777-signature";
+
+/// Signatures further from the receiver's clock are refused, so a captured notice
+/// cannot be replayed later
+pub const SIGNATURE_TOLERANCE: Duration = Duration::from_secs(300);
+
+/// Body of [`INVALIDATE_PATH`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InvalidateRequest {
+    pub keys: Vec<String>,
+}
+
+/// `[invalidate]` of the sender, off without `urls`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvalidateConfig {
+    /// livevod base URLs, e.g. http://127.0.0.1:8899
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// livevod's `internal.secret`
+    #[serde(default)]
+    pub secret: Secret,
+    /// Keys rewritten within this long of each other go out in one notice
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u64,
+}
+
+impl Default for InvalidateConfig {
+    fn default() -> Self {
+        Self {
+            urls: Vec::new(),
+            secret: Secret::default(),
+            debounce_ms: default_debounce_ms(),
+        }
+    }
+}
+
+fn default_debounce_ms() -> u64 {
+    500
+}
+
+/// Value of [`SIGNATURE_HEADER`] for `body` sent at `timestamp`, UNIX seconds
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    format!("t={timestamp},v1={}", signature(secret, timestamp, body))
+}
+
+fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut message = format!("{timestamp}.").into_bytes();
+    message.extend_from_slice(body);
+    hex(&hmac_sha256(secret.as_bytes(), &message))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    Missing,
+    Malformed,
+    /// More than [`SIGNATURE_TOLERANCE`] from `now`
+    Skewed,
+    Mismatch,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "notice is not signed"),
+            Self::Malformed => write!(f, "malformed signature"),
+            Self::Skewed => write!(f, "signature time too far from the server's"),
+            Self::Mismatch => write!(f, "signature does not match"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Check `header`, the [`SIGNATURE_HEADER`] of a notice, against `body` at `now`
+pub fn verify(
+    secret: &str,
+    header: Option<&str>,
+    body: &[u8],
+    now: i64,
+) -> Result<(), SignatureError> {
+    let header = header.ok_or(SignatureError::Missing)?;
+    let mut timestamp = None;
+    let mut given = None;
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", t)) => timestamp = t.parse::<i64>().ok(),
+            Some(("v1", v)) => given = Some(v),
+            _ => {}
+        }
+    }
+    let (Some(timestamp), Some(given)) = (timestamp, given) else {
+        return Err(SignatureError::Malformed);
+    };
+    if now.abs_diff(timestamp) > SIGNATURE_TOLERANCE.as_secs() {
+        return Err(SignatureError::Skewed);
+    }
+    let expected = signature(secret, timestamp, body);
+    if !constant_time_eq(expected.as_bytes(), given.as_bytes()) {
+        return Err(SignatureError::Mismatch);
+    }
+    Ok(())
+}
+
+/// Batches rewritten keys and sends them to every configured livevod.
+///
+/// The first key of a batch waits [`InvalidateConfig::debounce_ms`] for the others, a
+/// manifest rewritten per segment goes out once per batch. Failed notices are only
+/// logged, the receiver's cache TTL still bounds how long it serves a stale object.
+#[derive(Clone)]
+pub struct Invalidator {
+    tx: mpsc::UnboundedSender<String>,
+}
+
+impl Invalidator {
+    /// Start sending, `None` when `cfg` has no URL
+    pub fn spawn(cfg: InvalidateConfig) -> Option<Self> {
+        if cfg.urls.iter().all(|url| url.trim().is_empty()) {
+            return None;
+        }
+        if cfg.secret.is_empty() {
+            tracing::warn!("invalidation notices are sent without a secret, livevod refuses them");
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run(cfg, rx));
+        Some(Self { tx })
+    }
+
+    pub fn notify(&self, key: &str) {
+        let _ = self.tx.send(crate::normalize_key(key).into_owned());
+    }
+}
+
+async fn run(cfg: InvalidateConfig, mut rx: mpsc::UnboundedReceiver<String>) {
+    let client = reqwest::Client::new();
+    let debounce = Duration::from_millis(cfg.debounce_ms);
+    while let Some(first) = rx.recv().await {
+        let mut keys = BTreeSet::from([first]);
+        let deadline = tokio::time::Instant::now() + debounce;
+        while let Ok(Some(key)) = tokio::time::timeout_at(deadline, rx.recv()).await {
+            keys.insert(key);
+        }
+        let req = InvalidateRequest {
+            keys: keys.into_iter().collect(),
+        };
+        let Ok(body) = serde_json::to_vec(&req) else {
+            continue;
+        };
+        for url in cfg.urls.iter().filter(|url| !url.trim().is_empty()) {
+            if let Err(e) = send(&client, cfg.secret.expose_secret(), url, &body).await {
+                tracing::warn!(
+                    "invalidating {} keys on {} failed: {:#}",
+                    req.keys.len(),
+                    url,
+                    e
+                );
+            }
+        }
+    }
+}
+
+async fn send(
+    client: &reqwest::Client,
+    secret: &str,
+    url: &str,
+    body: &[u8],
+) -> anyhow::Result<()> {
+    let url = format!("{}{}", url.trim_end_matches('/'), INVALIDATE_PATH);
+    let signature = sign(secret, chrono::Utc::now().timestamp(), body);
+    let resp = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .timeout(Duration::from_secs(10))
+        .body(body.to_vec())
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        anyhow::bail!("livevod answered {}", resp.status());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let body = br#"{"keys":["cam/1/manifest.mpd"]}"#;
+        let header = sign("s3cret", 1_700_000_000, body);
+        assert!(header.starts_with("t=1700000000,v1="));
+        assert_eq!(verify("s3cret", Some(&header), body, 1_700_000_100), Ok(()));
+
+        assert_eq!(
+            verify("other", Some(&header), body, 1_700_000_000),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify("s3cret", Some(&header), b"{\"keys\":[]}", 1_700_000_000),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify("s3cret", Some(&header), body, 1_700_000_301),
+            Err(SignatureError::Skewed)
+        );
+        assert_eq!(
+            verify("s3cret", None, body, 1_700_000_000),
+            Err(SignatureError::Missing)
+        );
+        assert_eq!(
+            verify("s3cret", Some("v1=abc"), body, 1_700_000_000),
+            Err(SignatureError::Malformed)
+        );
+    }
+}
@@ -1,10 +1,33 @@
+pub mod batch;
+pub mod chaos;
 pub mod config;
+pub mod diagnose;
+pub mod failover;
+pub mod invalidate;
 pub mod operator;
 pub mod path;
+pub mod sigv4;
 
 #[cfg(test)]
 mod tests;
 
-pub use config::StorageConfig;
-pub use operator::{create_operator, init_operator, test_connection};
-pub use path::{generate_path, get_directory, validate_path};
+pub use batch::{
+    DeleteBatchOptions, DeleteBatchReport, DeleteProgress, FailedDelete, PartialDeleteError,
+    delete_batch,
+};
+pub use chaos::{CHAOS_AVAILABLE, ChaosConfig, ChaosLayer, FaultConfig};
+pub use config::{S3Endpoint, StorageConfig};
+pub use diagnose::{DiagnoseConfig, DiagnoseReport, Stage, StageReport, StageStatus, diagnose};
+pub use failover::{EndpointStatus, FailoverOperator};
+pub use invalidate::{InvalidateConfig, InvalidateRequest, Invalidator};
+pub use operator::{
+    create_failover_operator, create_operator, delete_prefix, delete_prefix_with,
+    init_failover_operator, init_operator, test_connection,
+};
+pub use path::{
+    DEFAULT_SEGMENT_PATTERN, KeyError, MAX_KEY_LENGTH, SHARED_PREFIX, SegmentPattern, check_key,
+    content_type_for, decode_key_from_path, encode_key_for_url, generate_path, get_directory,
+    is_shared, normalize_key, record_dir, relative_to, resolve_relative, shared_init_key,
+    validate_path,
+};
+pub use sigv4::{PresignedRequest, S3Signer, complete_multipart_document, tagging_document};
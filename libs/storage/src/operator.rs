@@ -1,4 +1,7 @@
+use crate::batch::{DeleteBatchOptions, DeleteProgress, PartialDeleteError, delete_batch};
 use crate::config::StorageConfig;
+use crate::failover::{DEFAULT_PROBE_INTERVAL, FailoverOperator};
+use crate::path::{SHARED_PREFIX, is_shared};
 use anyhow::Result;
 use opendal::Operator;
 use opendal::services;
@@ -13,67 +16,95 @@ pub fn create_operator(config: &StorageConfig) -> Result<Operator> {
             let builder = services::Fs::default().root(root);
             Ok(Operator::new(builder)?.finish())
         }
+        StorageConfig::S3 { endpoint, .. } => {
+            // Multi-endpoint configs go through create_failover_operator, plain callers get the primary
+            let primary = endpoint.as_ref().and_then(|e| e.urls().first().copied());
+            build_s3(config, primary)
+        }
+    }
+}
+
+/// Create one operator per configured endpoint, routed to the first healthy one
+pub fn create_failover_operator(config: &StorageConfig) -> Result<FailoverOperator> {
+    match config {
         StorageConfig::S3 {
-            bucket,
-            root,
-            region,
-            endpoint,
-            access_key_id,
-            secret_access_key,
-            session_token,
-            disable_config_load,
-            enable_virtual_host_style,
-        } => {
-            tracing::info!(
-                "Configuring S3 storage with bucket: {}, region: {:?}",
-                bucket,
-                region
-            );
+            endpoint: Some(endpoint),
+            ..
+        } if endpoint.urls().len() > 1 => {
+            let mut operators = Vec::new();
+            for url in endpoint.urls() {
+                operators.push((Some(url.to_string()), build_s3(config, Some(url))?));
+            }
+            Ok(FailoverOperator::new(operators))
+        }
+        _ => Ok(FailoverOperator::from(create_operator(config)?)),
+    }
+}
 
-            let mut builder = services::S3::default()
-                .bucket(bucket)
-                .root(root.trim_start_matches('/'));
+fn build_s3(config: &StorageConfig, endpoint: Option<&str>) -> Result<Operator> {
+    let StorageConfig::S3 {
+        bucket,
+        root,
+        region,
+        access_key_id,
+        secret_access_key,
+        session_token,
+        disable_config_load,
+        enable_virtual_host_style,
+        ..
+    } = config
+    else {
+        anyhow::bail!("not an s3 storage config");
+    };
 
-            if let Some(region) = region {
-                builder = builder.region(region);
-                tracing::debug!("S3 region set to: {}", region);
-            }
+    tracing::info!(
+        "Configuring S3 storage with bucket: {}, region: {:?}",
+        bucket,
+        region
+    );
 
-            if let Some(endpoint) = endpoint {
-                builder = builder.endpoint(endpoint);
-                tracing::debug!("S3 endpoint set to: {}", endpoint);
-            }
+    let mut builder = services::S3::default()
+        .bucket(bucket)
+        .root(root.trim_start_matches('/'));
 
-            if let Some(access_key_id) = access_key_id {
-                builder = builder.access_key_id(access_key_id);
-                tracing::debug!("S3 access key configured");
-            }
+    if let Some(region) = region {
+        builder = builder.region(region);
+        tracing::debug!("S3 region set to: {}", region);
+    }
 
-            if let Some(secret_access_key) = secret_access_key {
-                builder = builder.secret_access_key(secret_access_key);
-                tracing::debug!("S3 secret key configured");
-            }
+    if let Some(endpoint) = endpoint {
+        builder = builder.endpoint(endpoint);
+        tracing::debug!("S3 endpoint set to: {}", endpoint);
+    }
 
-            if let Some(session_token) = session_token {
-                builder = builder.session_token(session_token);
-                tracing::debug!("S3 session token configured");
-            }
+    if let Some(access_key_id) = access_key_id {
+        builder = builder.access_key_id(access_key_id);
+        tracing::debug!("S3 access key configured");
+    }
 
-            if *disable_config_load {
-                builder = builder.disable_config_load();
-                tracing::debug!("S3 config load disabled");
-            }
+    if let Some(secret_access_key) = secret_access_key {
+        builder = builder.secret_access_key(secret_access_key.expose_secret());
+        tracing::debug!("S3 secret key configured");
+    }
 
-            if *enable_virtual_host_style {
-                builder = builder.enable_virtual_host_style();
-                tracing::debug!("S3 virtual host style enabled");
-            }
+    if let Some(session_token) = session_token {
+        builder = builder.session_token(session_token.expose_secret());
+        tracing::debug!("S3 session token configured");
+    }
 
-            let op = Operator::new(builder)?.finish();
-            tracing::debug!("S3 storage operator created successfully");
-            Ok(op)
-        }
+    if *disable_config_load {
+        builder = builder.disable_config_load();
+        tracing::debug!("S3 config load disabled");
+    }
+
+    if *enable_virtual_host_style {
+        builder = builder.enable_virtual_host_style();
+        tracing::debug!("S3 virtual host style enabled");
     }
+
+    let op = Operator::new(builder)?.finish();
+    tracing::debug!("S3 storage operator created successfully");
+    Ok(op)
 }
 
 /// Test storage connection
@@ -102,3 +133,75 @@ pub async fn init_operator(config: &StorageConfig) -> Result<Operator> {
 
     Ok(operator)
 }
+
+/// Initialize a failover operator, probe all endpoints and keep probing in the background
+pub async fn init_failover_operator(config: &StorageConfig) -> Result<FailoverOperator> {
+    let operator = create_failover_operator(config)?;
+    if !operator.is_failover() {
+        // Same behavior as init_operator for single endpoints
+        if let Err(e) = test_connection(&operator.current()).await {
+            tracing::warn!(
+                "Storage backend initialized but connection test failed: {}, continuing anyway",
+                e
+            );
+        }
+        return Ok(operator);
+    }
+
+    operator.probe().await;
+    tracing::info!(
+        "Storage failover initialized, selected endpoint: {:?}",
+        operator.selected_endpoint()
+    );
+    operator.spawn_probe(DEFAULT_PROBE_INTERVAL);
+    Ok(operator)
+}
+
+/// Delete every object under `prefix`, e.g. one recording directory.
+///
+/// Shared objects (see [`SHARED_PREFIX`]) may be referenced by any recording and are
+/// never deleted: a shared prefix is refused and shared objects under a broader one
+/// are kept. Returns how many objects were deleted, a [`PartialDeleteError`] listing
+/// the keys left behind when some could not be.
+pub async fn delete_prefix(operator: &Operator, prefix: &str) -> Result<usize> {
+    delete_prefix_with(operator, prefix, &DeleteBatchOptions::default(), |_| {}).await
+}
+
+/// [`delete_prefix`] reporting progress as described in [`delete_batch`]
+pub async fn delete_prefix_with<F>(
+    operator: &Operator,
+    prefix: &str,
+    options: &DeleteBatchOptions,
+    progress: F,
+) -> Result<usize>
+where
+    F: FnMut(DeleteProgress),
+{
+    let prefix = prefix.trim_matches('/');
+    anyhow::ensure!(!prefix.is_empty(), "refusing to delete the storage root");
+    anyhow::ensure!(
+        !is_shared(&format!("{prefix}/")),
+        "refusing to delete shared objects under '{prefix}'"
+    );
+
+    let keys: Vec<String> = operator
+        .list_with(&format!("{prefix}/"))
+        .recursive(true)
+        .await?
+        .into_iter()
+        .filter(|entry| !entry.metadata().is_dir() && !is_shared(entry.path()))
+        .map(|entry| entry.path().to_string())
+        .collect();
+    let report = delete_batch(operator, keys, options, progress).await;
+    if !report.is_complete() {
+        return Err(PartialDeleteError::from(report).into());
+    }
+    let deleted = report.deleted;
+    tracing::debug!(
+        "Deleted {} objects under '{}', kept {}",
+        deleted,
+        prefix,
+        SHARED_PREFIX
+    );
+    Ok(deleted)
+}
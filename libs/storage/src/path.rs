@@ -1,15 +1,44 @@
+use std::borrow::Cow;
+use std::fmt;
 use std::path::Path;
 
+use unicode_normalization::{IsNormalized, UnicodeNormalization, is_nfc_quick};
+
+use crate::sigv4::{uri_decode, uri_encode};
+
 /// Generate storage path based on stream name and UNIX timestamp
-/// Format: {stream}/{timestamp_seconds}/{filename}
-pub fn generate_path(stream: &str, timestamp_micros: i64, filename: &str) -> String {
+/// Format: [{namespace}/]{stream}/{timestamp_seconds}/{filename}
+pub fn generate_path(
+    namespace: Option<&str>,
+    stream: &str,
+    timestamp_micros: i64,
+    filename: &str,
+) -> String {
     let timestamp_seconds = if timestamp_micros < 0 {
         0
     } else {
         timestamp_micros / 1_000_000
     };
 
-    format!("{}/{}/{}", stream, timestamp_seconds, filename)
+    format!(
+        "{}/{}",
+        record_dir(namespace, stream, timestamp_seconds),
+        filename
+    )
+}
+
+/// Default directory of a recording, `[{namespace}/]{stream}/{record_id}`.
+///
+/// The namespace keeps nodes that record the same stream name into one bucket apart.
+pub fn record_dir(namespace: Option<&str>, stream: &str, record_id: i64) -> String {
+    let dir = match namespace
+        .map(|ns| ns.trim_matches('/'))
+        .filter(|ns| !ns.is_empty())
+    {
+        Some(ns) => format!("{ns}/{stream}/{record_id}"),
+        None => format!("{stream}/{record_id}"),
+    };
+    normalize_key(&dir).into_owned()
 }
 
 /// Extract directory path from full storage path
@@ -17,9 +46,234 @@ pub fn get_directory(path: &str) -> Option<&str> {
     Path::new(path).parent()?.to_str()
 }
 
-/// Validate storage path format
+/// Longest object key S3 accepts, in UTF-8 bytes rather than characters
+pub const MAX_KEY_LENGTH: usize = 1024;
+
+/// Why [`check_key`] refuses an object key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyError {
+    Empty,
+    /// Starts with `/` or contains `..`
+    NotRelative,
+    /// More than [`MAX_KEY_LENGTH`] bytes
+    TooLong {
+        len: usize,
+    },
+    /// S3 stores control characters but listings return them escaped or not at all
+    ControlCharacter(char),
+    /// A URL path that isn't valid percent-encoded UTF-8
+    InvalidEncoding,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "object key is empty"),
+            Self::NotRelative => write!(f, "object key must be relative and without `..`"),
+            Self::TooLong { len } => write!(
+                f,
+                "object key is {len} bytes, longer than the {MAX_KEY_LENGTH} bytes S3 accepts"
+            ),
+            Self::ControlCharacter(c) => {
+                write!(f, "object key contains control character {:?}", c)
+            }
+            Self::InvalidEncoding => write!(f, "object key is not valid percent-encoded UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+/// Check `key` against the constraints S3 puts on object keys
+pub fn check_key(key: &str) -> Result<(), KeyError> {
+    if key.is_empty() {
+        return Err(KeyError::Empty);
+    }
+    if key.contains("..") || key.starts_with('/') {
+        return Err(KeyError::NotRelative);
+    }
+    if key.len() > MAX_KEY_LENGTH {
+        return Err(KeyError::TooLong { len: key.len() });
+    }
+    match key.chars().find(|c| c.is_control()) {
+        Some(c) => Err(KeyError::ControlCharacter(c)),
+        None => Ok(()),
+    }
+}
+
+/// Validate storage path format, see [`check_key`]
 pub fn validate_path(path: &str) -> bool {
-    !path.is_empty() && !path.contains("..") && !path.starts_with('/')
+    check_key(path).is_ok()
+}
+
+/// `key` in Unicode NFC, the form every object key is stored in.
+///
+/// A stream name typed on macOS arrives decomposed (`e` + U+0301) where the same name
+/// from elsewhere is composed (`é`), storage would hold them as different keys.
+pub fn normalize_key(key: &str) -> Cow<'_, str> {
+    match is_nfc_quick(key.chars()) {
+        IsNormalized::Yes => Cow::Borrowed(key),
+        _ => Cow::Owned(key.nfc().collect()),
+    }
+}
+
+/// `key` as a URL path, every byte but unreserved characters and `/` percent-encoded.
+///
+/// Use it wherever a key goes into a URL, manifest `BaseURL`s and redirect `Location`s
+/// included: a stream named `50%` or `a#b` is not a valid path otherwise, and `+` or a
+/// space is read back differently depending on the decoder.
+pub fn encode_key_for_url(key: &str) -> String {
+    uri_encode(&normalize_key(key), false)
+}
+
+/// The key of the raw URL path `path`, the inverse of [`encode_key_for_url`].
+///
+/// Decodes exactly once, so an object named `%41` stays reachable as `%2541`, then
+/// normalizes and checks the key like it was stored.
+pub fn decode_key_from_path(path: &str) -> Result<String, KeyError> {
+    let decoded = uri_decode(path.trim_start_matches('/')).ok_or(KeyError::InvalidEncoding)?;
+    let key = normalize_key(&decoded).into_owned();
+    check_key(&key)?;
+    Ok(key)
+}
+
+/// Objects shared between recordings, never deleted with a recording
+pub const SHARED_PREFIX: &str = "_shared/";
+
+/// Content-addressed key of a deduplicated init segment
+pub fn shared_init_key(sha256_hex: &str) -> String {
+    format!("{SHARED_PREFIX}init/{sha256_hex}.mp4")
+}
+
+/// Whether `path` is a shared object, see [`SHARED_PREFIX`]
+pub fn is_shared(path: &str) -> bool {
+    path.trim_start_matches('/').starts_with(SHARED_PREFIX)
+}
+
+/// Reference to the object `key` from a manifest stored in `dir`, as used in MPD attributes
+pub fn relative_to(dir: &str, key: &str) -> String {
+    let depth = dir.split('/').filter(|s| !s.is_empty()).count();
+    format!("{}{}", "../".repeat(depth), key)
+}
+
+/// Resolve a manifest `reference` against the manifest's `dir`, the inverse of [`relative_to`]
+pub fn resolve_relative(dir: &str, reference: &str) -> String {
+    let mut parts: Vec<&str> = dir.split('/').filter(|s| !s.is_empty()).collect();
+    for part in reference.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Default [`SegmentPattern`], `v_seg_0001.m4s`, `a_seg_0001.m4s` and on
+pub const DEFAULT_SEGMENT_PATTERN: &str = "seg_%04d.m4s";
+
+/// File name of a track's media segments after its `v_`/`a_` prefix, printf-style with
+/// the segment number as the one `%d` or zero-padded `%0Nd`, e.g. `segment_%06d.m4s`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentPattern {
+    prefix: String,
+    suffix: String,
+    width: usize,
+}
+
+impl SegmentPattern {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        if pattern.contains(['/', '$']) {
+            return Err(format!("{pattern} can't contain '/' or '$'"));
+        }
+        let Some(begin) = pattern.find('%') else {
+            return Err(format!("{pattern} has no %d for the segment number"));
+        };
+        let rest = &pattern[begin + 1..];
+        let Some(end) = rest.find('d') else {
+            return Err(format!("{pattern} has no %d for the segment number"));
+        };
+        let spec = &rest[..end];
+        let width = if spec.is_empty() {
+            0
+        } else {
+            spec.strip_prefix('0')
+                .and_then(|w| w.parse::<usize>().ok())
+                .filter(|w| (1..=10).contains(w))
+                .ok_or_else(|| {
+                    format!("unsupported conversion %{spec}d in {pattern}, use %d or %0Nd")
+                })?
+        };
+        let suffix = &rest[end + 1..];
+        if suffix.contains('%') {
+            return Err(format!("{pattern} has more than one conversion"));
+        }
+        if !suffix.ends_with(".m4s") && !suffix.ends_with(".mp4") {
+            return Err(format!("{pattern} must end in .m4s or .mp4"));
+        }
+        Ok(Self {
+            prefix: pattern[..begin].to_string(),
+            suffix: suffix.to_string(),
+            width,
+        })
+    }
+
+    /// Name of segment `number` of the track with file name prefix `track`
+    pub fn filename(&self, track: &str, number: u32) -> String {
+        format!(
+            "{track}{}{number:0width$}{}",
+            self.prefix,
+            self.suffix,
+            width = self.width
+        )
+    }
+
+    /// DASH `SegmentTemplate` media attribute expanding to [`Self::filename`]
+    pub fn template(&self, track: &str) -> String {
+        let number = match self.width {
+            0 => "$Number$".to_string(),
+            width => format!("$Number%0{width}d$"),
+        };
+        format!("{track}{}{number}{}", self.prefix, self.suffix)
+    }
+}
+
+impl Default for SegmentPattern {
+    fn default() -> Self {
+        Self::parse(DEFAULT_SEGMENT_PATTERN).expect("default segment pattern")
+    }
+}
+
+/// Content type for a stored object, derived from its file name.
+///
+/// Audio tracks are told apart by their `a_`/`audio_` file name prefix, which every
+/// [`SegmentPattern`] keeps.
+pub fn content_type_for(path: &str) -> &'static str {
+    let name = Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(path);
+    let ext = Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+    match ext.to_ascii_lowercase().as_str() {
+        "mpd" => "application/dash+xml",
+        "m4s" | "mp4" => {
+            if name.starts_with("a_") || name.starts_with("audio_") {
+                "audio/mp4"
+            } else {
+                "video/mp4"
+            }
+        }
+        "jpg" | "jpeg" => "image/jpeg",
+        "json" => "application/json",
+        "jsonl" => "application/x-ndjson",
+        "vtt" => "text/vtt",
+        _ => "application/octet-stream",
+    }
 }
 
 #[cfg(test)]
@@ -30,8 +284,11 @@ mod tests {
     fn test_generate_path() {
         // 2024-01-15 12:00:00 UTC
         let timestamp = 1_705_320_000_000_000_i64;
-        let path = generate_path("camera01", timestamp, "segment_001.m4s");
+        let path = generate_path(None, "camera01", timestamp, "segment_001.m4s");
         assert_eq!(path, "camera01/1705320000/segment_001.m4s");
+        let path = generate_path(Some("edge-1"), "camera01", timestamp, "segment_001.m4s");
+        assert_eq!(path, "edge-1/camera01/1705320000/segment_001.m4s");
+        assert_eq!(record_dir(Some(""), "camera01", 1), "camera01/1");
     }
 
     #[test]
@@ -46,5 +303,150 @@ mod tests {
         assert!(!validate_path("../camera01/segment.m4s"));
         assert!(!validate_path("/absolute/path"));
         assert!(!validate_path(""));
+        assert!(!validate_path("camera01/1705320000/seg\n.m4s"));
+    }
+
+    #[test]
+    fn test_check_key_length_in_bytes() {
+        // 3 bytes per character
+        let stream = "摄像头".repeat(56);
+        let dir = record_dir(Some("edge-1"), &stream, 1_705_320_000);
+        let key = format!("{dir}/v_seg_0001.m4s");
+        assert_eq!(key.chars().count(), 201);
+        assert_eq!(key.len(), 537);
+        assert_eq!(check_key(&key), Ok(()));
+
+        // Exactly at the limit, then one byte over it with far fewer characters
+        let at_limit = format!("{}/{}", "é".repeat(505), "seg_00001.m4s");
+        assert_eq!(at_limit.len(), MAX_KEY_LENGTH);
+        assert_eq!(check_key(&at_limit), Ok(()));
+        let over = format!("{}/{}", "é".repeat(505), "seg_000001.m4s");
+        assert_eq!(over.chars().count(), 520);
+        assert_eq!(check_key(&over), Err(KeyError::TooLong { len: 1025 }));
+        assert!(!validate_path(&over));
+
+        // A character is never split to fit
+        let emoji = format!("cam/{}", "📷".repeat(255));
+        assert_eq!(emoji.len(), 1024);
+        assert_eq!(check_key(&emoji), Ok(()));
+        assert!(check_key(&format!("{emoji}a")).is_err());
+
+        assert_eq!(
+            check_key("cam/1705320000/\u{7f}.m4s"),
+            Err(KeyError::ControlCharacter('\u{7f}'))
+        );
+        assert_eq!(check_key("cam/../x"), Err(KeyError::NotRelative));
+    }
+
+    #[test]
+    fn test_key_url_round_trip() {
+        for stream in ["📷 lobby", "50%", "a+b", "door#2", "café", "%41"] {
+            let key = format!("{}/v_seg_0001.m4s", record_dir(None, stream, 1_705_320_000));
+            let url = encode_key_for_url(&key);
+            assert!(
+                url.bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-._~/%".contains(&b)),
+                "{url}"
+            );
+            assert_eq!(decode_key_from_path(&url), Ok(key.clone()));
+            assert_eq!(decode_key_from_path(&format!("/{url}")), Ok(key));
+        }
+        assert_eq!(encode_key_for_url("a b/50%/x+y#z"), "a%20b/50%25/x%2By%23z");
+        // Decoded once: `%2541` is the key `%41`, never `A`
+        assert_eq!(decode_key_from_path("cam/%2541").unwrap(), "cam/%41");
+
+        // Composed and decomposed spellings are the same key
+        let decomposed = "cafe\u{301}";
+        assert_eq!(normalize_key(decomposed), "caf\u{e9}");
+        assert!(matches!(normalize_key("caf\u{e9}"), Cow::Borrowed(_)));
+        assert_eq!(record_dir(None, decomposed, 1), "caf\u{e9}/1");
+        assert_eq!(
+            decode_key_from_path("cafe%CC%81/1/manifest.mpd").unwrap(),
+            "caf\u{e9}/1/manifest.mpd"
+        );
+
+        assert_eq!(
+            decode_key_from_path("cam/%zz"),
+            Err(KeyError::InvalidEncoding)
+        );
+        assert_eq!(
+            decode_key_from_path("cam/%FF"),
+            Err(KeyError::InvalidEncoding)
+        );
+        assert_eq!(
+            decode_key_from_path("cam/%2E%2E/x"),
+            Err(KeyError::NotRelative)
+        );
+        assert_eq!(
+            decode_key_from_path("cam/%0A.m4s"),
+            Err(KeyError::ControlCharacter('\n'))
+        );
+    }
+
+    #[test]
+    fn test_shared_references() {
+        let key = shared_init_key("ab12");
+        assert_eq!(key, "_shared/init/ab12.mp4");
+        assert!(is_shared(&key));
+        assert!(!is_shared("cam/_shared/init/ab12.mp4"));
+
+        let reference = relative_to("cam/1705320000", &key);
+        assert_eq!(reference, "../../_shared/init/ab12.mp4");
+        assert_eq!(resolve_relative("cam/1705320000", &reference), key);
+        assert_eq!(
+            resolve_relative("cam/1705320000", "v_init.m4s"),
+            "cam/1705320000/v_init.m4s"
+        );
+    }
+
+    #[test]
+    fn test_segment_pattern() {
+        let default = SegmentPattern::default();
+        assert_eq!(default.filename("v_", 7), "v_seg_0007.m4s");
+        assert_eq!(default.template("a_"), "a_seg_$Number%04d$.m4s");
+
+        let padded = SegmentPattern::parse("segment_%06d.m4s").unwrap();
+        assert_eq!(padded.filename("v_", 19), "v_segment_000019.m4s");
+        assert_eq!(padded.template("v_"), "v_segment_$Number%06d$.m4s");
+
+        let plain = SegmentPattern::parse("%d.mp4").unwrap();
+        assert_eq!(plain.filename("a_", 2), "a_2.mp4");
+        assert_eq!(plain.template("a_"), "a_$Number$.mp4");
+
+        for bad in [
+            "seg.m4s",
+            "seg_%5d.m4s",
+            "seg_%04d_%d.m4s",
+            "seg_%04d.ts",
+            "dir/seg_%04d.m4s",
+            "seg_$%04d.m4s",
+        ] {
+            assert!(SegmentPattern::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_content_type_for() {
+        assert_eq!(
+            content_type_for("cam/1/manifest.mpd"),
+            "application/dash+xml"
+        );
+        assert_eq!(content_type_for("cam/1/v_init.m4s"), "video/mp4");
+        assert_eq!(content_type_for("cam/1/v_seg_0001.m4s"), "video/mp4");
+        assert_eq!(content_type_for("cam/1/a_seg_0001.m4s"), "audio/mp4");
+        assert_eq!(content_type_for("cam/1/v_segment_000001.m4s"), "video/mp4");
+        assert_eq!(content_type_for("cam/1/a_segment_12.mp4"), "audio/mp4");
+        assert_eq!(content_type_for("audio_init.mp4"), "audio/mp4");
+        assert_eq!(content_type_for("cam/1/thumb.JPG"), "image/jpeg");
+        assert_eq!(content_type_for("cam/1/previews.vtt"), "text/vtt");
+        assert_eq!(content_type_for("cam/1/meta.json"), "application/json");
+        assert_eq!(
+            content_type_for("cam/1/segments.jsonl"),
+            "application/x-ndjson"
+        );
+        assert_eq!(
+            content_type_for("cam/1/data.bin"),
+            "application/octet-stream"
+        );
     }
 }
@@ -1,4 +1,4 @@
-use crate::{StorageConfig, create_operator};
+use crate::{StorageConfig, create_operator, delete_prefix, shared_init_key};
 
 #[tokio::test]
 async fn test_fs_storage_config() {
@@ -28,12 +28,13 @@ async fn test_s3_storage_config() {
         bucket: "test-bucket".to_string(),
         root: "/test".to_string(),
         region: Some("us-east-1".to_string()),
-        endpoint: Some("http://localhost:9000".to_string()),
+        endpoint: Some("http://localhost:9000".into()),
         access_key_id: Some("minioadmin".to_string()),
-        secret_access_key: Some("minioadmin".to_string()),
+        secret_access_key: Some("minioadmin".into()),
         session_token: None,
         disable_config_load: true,
         enable_virtual_host_style: false,
+        public_endpoint: None,
     };
 
     let result = create_operator(&config);
@@ -48,10 +49,11 @@ fn test_storage_config_serialization() {
         region: Some("us-west-2".to_string()),
         endpoint: None,
         access_key_id: Some("AKIA...".to_string()),
-        secret_access_key: Some("secret...".to_string()),
+        secret_access_key: Some("secret...".into()),
         session_token: None,
         disable_config_load: false,
         enable_virtual_host_style: true,
+        public_endpoint: None,
     };
 
     let serialized = toml::to_string(&config).expect("Failed to serialize config");
@@ -105,3 +107,233 @@ enable_virtual_host_style = true
     assert_eq!(region, Some("us-east-1".to_string()));
     assert!(enable_virtual_host_style);
 }
+
+#[test]
+fn test_s3_endpoint_list_parsing() {
+    let toml_str = r#"
+type = "s3"
+bucket = "test-bucket"
+endpoint = ["http://gw-a:9000", "http://gw-b:9000"]
+"#;
+    let config: StorageConfig = toml::from_str(toml_str).expect("Failed to parse TOML config");
+    let StorageConfig::S3 { endpoint, .. } = config else {
+        panic!("Expected S3 variant");
+    };
+    let endpoint = endpoint.expect("endpoint should be set");
+    assert_eq!(
+        endpoint.urls(),
+        vec!["http://gw-a:9000", "http://gw-b:9000"]
+    );
+}
+
+#[tokio::test]
+async fn test_failover_operator_per_endpoint() {
+    let config = StorageConfig::S3 {
+        bucket: "test-bucket".to_string(),
+        root: "/test".to_string(),
+        region: Some("us-east-1".to_string()),
+        endpoint: Some(crate::S3Endpoint::Multiple(vec![
+            "http://gw-a:9000".to_string(),
+            "http://gw-b:9000".to_string(),
+        ])),
+        access_key_id: Some("minioadmin".to_string()),
+        secret_access_key: Some("minioadmin".into()),
+        session_token: None,
+        disable_config_load: true,
+        enable_virtual_host_style: false,
+        public_endpoint: None,
+    };
+
+    let op = crate::create_failover_operator(&config).expect("failover operator");
+    assert!(op.is_failover());
+    assert_eq!(op.selected_endpoint().as_deref(), Some("http://gw-a:9000"));
+
+    op.set_healthy(0, false);
+    assert_eq!(op.selected_endpoint().as_deref(), Some("http://gw-b:9000"));
+
+    // Nothing healthy: keep the last selection rather than flapping
+    op.set_healthy(1, false);
+    assert_eq!(op.selected_endpoint().as_deref(), Some("http://gw-b:9000"));
+
+    op.set_healthy(0, true);
+    let status = op.status();
+    assert!(status[0].selected && status[0].healthy);
+    assert!(!status[1].selected && !status[1].healthy);
+}
+
+#[tokio::test]
+async fn test_single_endpoint_is_not_failover() {
+    let config = StorageConfig::Fs {
+        root: std::env::temp_dir().to_string_lossy().into_owned(),
+    };
+    let op = crate::create_failover_operator(&config).expect("fs operator");
+    assert!(!op.is_failover());
+    assert_eq!(op.selected_endpoint(), None);
+}
+
+fn chaos_fs_operator(name: &str, chaos: &crate::ChaosLayer) -> opendal::Operator {
+    let root = std::env::temp_dir().join(format!("storage-chaos-{}-{}", name, std::process::id()));
+    let config = StorageConfig::Fs {
+        root: root.to_string_lossy().into_owned(),
+    };
+    create_operator(&config)
+        .expect("fs operator")
+        .layer(chaos.clone())
+}
+
+/// Retry like the uploader does, the attempt count is generous so a 20% rate can't exhaust it
+async fn with_retries<T, F, Fut>(mut op: F) -> (opendal::Result<T>, usize)
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = opendal::Result<T>>,
+{
+    let mut failures = 0;
+    loop {
+        match op().await {
+            Err(e) if e.is_temporary() && failures < 20 => failures += 1,
+            result => return (result, failures),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_chaos_seed_is_reproducible() {
+    let config = crate::ChaosConfig {
+        seed: 42,
+        write: crate::FaultConfig {
+            failure_rate: 0.5,
+            latency_ms: 0,
+        },
+        ..Default::default()
+    };
+    let mut runs = Vec::new();
+    for run in 0..2 {
+        let chaos = crate::ChaosLayer::new(config.clone());
+        let op = chaos_fs_operator(&format!("seed-{run}"), &chaos);
+        let mut pattern = Vec::new();
+        for i in 0..32 {
+            pattern.push(op.write(&format!("obj_{i}"), vec![1u8]).await.is_ok());
+        }
+        runs.push(pattern);
+    }
+    assert_eq!(runs[0], runs[1]);
+    assert!(runs[0].contains(&true) && runs[0].contains(&false));
+}
+
+#[tokio::test]
+async fn test_chaos_runtime_update() {
+    let chaos = crate::ChaosLayer::new(crate::ChaosConfig {
+        write: crate::FaultConfig {
+            failure_rate: 1.0,
+            latency_ms: 0,
+        },
+        ..Default::default()
+    });
+    let op = chaos_fs_operator("update", &chaos);
+    assert!(op.write("a", vec![1u8]).await.is_err());
+
+    chaos.set_config(crate::ChaosConfig::default());
+    assert!(op.write("a", vec![1u8]).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_chaos_write_failures_upload_and_playback_without_loss() {
+    let chaos = crate::ChaosLayer::new(crate::ChaosConfig {
+        seed: 7,
+        write: crate::FaultConfig {
+            failure_rate: 0.2,
+            latency_ms: 1,
+        },
+        ..Default::default()
+    });
+    let op = chaos_fs_operator("loss", &chaos);
+
+    // Upload queue: every segment eventually lands despite injected write failures
+    let mut total_failures = 0;
+    for i in 0..50 {
+        let path = format!("cam/1/v_seg_{i:04}.m4s");
+        let body = format!("segment {i}").into_bytes();
+        let (result, failures) = with_retries(|| op.write(&path, body.clone())).await;
+        result.expect("write should eventually succeed");
+        total_failures += failures;
+    }
+    assert!(
+        total_failures > 0,
+        "20% failure rate should inject failures"
+    );
+
+    // Playback: reads with the same rate injected still return every segment intact
+    let mut read_config = chaos.config();
+    read_config.read = read_config.write;
+    chaos.set_config(read_config);
+    for i in 0..50 {
+        let path = format!("cam/1/v_seg_{i:04}.m4s");
+        let (result, _) = with_retries(|| op.read(&path)).await;
+        let bytes = result.expect("read should eventually succeed");
+        assert_eq!(bytes.to_vec(), format!("segment {i}").into_bytes());
+    }
+}
+
+#[tokio::test]
+async fn test_delete_prefix_keeps_shared_objects() {
+    let root = std::env::temp_dir().join(format!("live777-delete-prefix-{}", std::process::id()));
+    let op = create_operator(&StorageConfig::Fs {
+        root: root.to_string_lossy().into_owned(),
+    })
+    .unwrap();
+    let shared = shared_init_key("ab12");
+    for path in [
+        "cam/1705320000/manifest.mpd",
+        "cam/1705320000/v_seg_0001.m4s",
+        "cam/1705320001/manifest.mpd",
+        shared.as_str(),
+    ] {
+        op.write(path, "x").await.unwrap();
+    }
+
+    assert_eq!(delete_prefix(&op, "cam/1705320000").await.unwrap(), 2);
+    assert!(!op.exists("cam/1705320000/manifest.mpd").await.unwrap());
+    assert!(op.exists("cam/1705320001/manifest.mpd").await.unwrap());
+
+    assert!(delete_prefix(&op, "_shared").await.is_err());
+    assert!(delete_prefix(&op, "_shared/init").await.is_err());
+    assert!(delete_prefix(&op, "/").await.is_err());
+    assert!(op.exists(&shared).await.unwrap());
+
+    let _ = std::fs::remove_dir_all(root);
+}
+
+#[test]
+fn test_public_url_rewrite() {
+    let config: StorageConfig = toml::from_str(
+        r#"
+        type = "s3"
+        bucket = "recordings"
+        endpoint = "http://minio.internal:9000"
+        public_endpoint = "/s3/"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config
+            .public_url("http://minio.internal:9000/recordings/cam/1/a.m4s?X-Amz-Signature=abc")
+            .as_deref(),
+        Some("/s3/recordings/cam/1/a.m4s?X-Amz-Signature=abc")
+    );
+
+    let config: StorageConfig = toml::from_str(
+        r#"
+        type = "s3"
+        bucket = "recordings"
+        public_endpoint = "https://media.example.com"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config
+            .public_url("https://recordings.s3.amazonaws.com?list-type=2")
+            .as_deref(),
+        Some("https://media.example.com/?list-type=2")
+    );
+    assert_eq!(StorageConfig::default().public_url("http://x/y"), None);
+}
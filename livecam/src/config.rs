@@ -1,4 +1,5 @@
 use base64::{Engine as _, engine::general_purpose};
+use config_loader::Redacted;
 use serde::{Deserialize, Serialize};
 use std::{env, net::SocketAddr, str::FromStr};
 use tracing::{info, warn};
@@ -49,7 +50,7 @@ fn default_rtp_port() -> u16 {
     5004
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AuthConfig {
     pub username: String,
     pub password_hash: String,
@@ -57,6 +58,16 @@ pub struct AuthConfig {
     pub jwt_secret: String,
 }
 
+impl std::fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("username", &self.username)
+            .field("password_hash", &Redacted(&self.password_hash))
+            .field("jwt_secret", &Redacted(&self.jwt_secret))
+            .finish()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Log {
     #[serde(default = "default_log_level")]
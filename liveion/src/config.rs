@@ -1,5 +1,8 @@
 use std::{env, net::SocketAddr, str::FromStr};
 
+#[cfg(feature = "recorder")]
+use api::recorder::RetentionClass;
+use config_loader::Secret;
 use iceserver::{IceServer, default_ice_servers};
 use serde::{Deserialize, Serialize};
 
@@ -62,14 +65,17 @@ pub struct Http {
     pub listen: SocketAddr,
     #[serde(default)]
     pub cors: bool,
+    /// Serve Swagger UI for `/api/openapi.json` at `/swagger-ui`
+    #[serde(default)]
+    pub swagger_ui: bool,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Auth {
     #[serde(default)]
-    pub secret: String,
+    pub secret: Secret,
     #[serde(default)]
-    pub tokens: Vec<String>,
+    pub tokens: Vec<Secret>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -98,6 +104,7 @@ impl Default for Http {
         Self {
             listen: default_http_listen(),
             cors: Default::default(),
+            swagger_ui: Default::default(),
         }
     }
 }
@@ -128,6 +135,20 @@ impl Config {
                 .map_err(|e| anyhow::anyhow!(format!("ice_server error : {}", e)))?;
         }
 
+        #[cfg(feature = "recorder")]
+        for schedule in &self.recorder.schedules {
+            crate::recorder::schedule::Schedule::compile(schedule)
+                .map_err(|e| anyhow::anyhow!("recorder schedule error: {}", e))?;
+        }
+
+        #[cfg(feature = "recorder")]
+        crate::recorder::tenancy::Tenancy::new(&self.recorder.tenancy)
+            .map_err(|e| anyhow::anyhow!("recorder tenancy error: {}", e))?;
+
+        #[cfg(feature = "recorder")]
+        storage::SegmentPattern::parse(&self.recorder.segment_pattern)
+            .map_err(|e| anyhow::anyhow!("recorder segment_pattern error: {}", e))?;
+
         #[cfg(feature = "source")]
         for source in &self.stream.sources {
             source
@@ -153,6 +174,19 @@ pub struct RecorderConfig {
     #[serde(default)]
     pub node_alias: Option<String>,
 
+    /// Prefix of generated object keys, `{key_namespace}/{stream}/{record_id}`, so nodes
+    /// recording the same stream name into one bucket stay apart. Defaults to `node_alias`
+    #[serde(default)]
+    pub key_namespace: Option<String>,
+
+    /// Set to false to keep the plain `{stream}/{record_id}` layout, e.g. on a single node
+    #[serde(default = "default_namespace_keys")]
+    pub namespace_keys: bool,
+
+    /// Tenant of each stream, derived from its name, prefixing its object keys
+    #[serde(default)]
+    pub tenancy: TenancyConfig,
+
     /// Optional path for recorder index file (index.json)
     #[serde(default)]
     pub index_path: Option<String>,
@@ -161,9 +195,137 @@ pub struct RecorderConfig {
     #[serde(default = "default_max_recording_seconds")]
     pub max_recording_seconds: u64,
 
+    /// Split recordings into linked parts after this many minutes, takes precedence over
+    /// `max_recording_seconds` when set
+    #[serde(default)]
+    pub max_recording_duration_minutes: Option<u64>,
+
+    /// Auto-record rules with per-rule overrides, matched in order before `auto_streams`
+    #[serde(default)]
+    pub rules: Vec<RecordingRule>,
+
+    /// Cron-like recording windows per stream pattern, evaluated in local time
+    #[serde(default)]
+    pub schedules: Vec<RecordingSchedule>,
+
+    /// After a schedule reload, keep in-flight scheduled recordings that fall outside
+    /// their new windows for this many seconds before stopping them
+    #[serde(default = "default_schedule_grace_seconds")]
+    pub schedule_grace_seconds: u64,
+
+    /// On shutdown, wait this long for recordings to flush their last segment and
+    /// manifest before marking the unfinished ones interrupted
+    #[serde(default = "default_shutdown_deadline_seconds")]
+    pub shutdown_deadline_seconds: u64,
+
+    /// Keep a recording open this long after its publisher goes away, a publisher or
+    /// cascade pull coming back within it continues the same recording. Longer gaps
+    /// start a new recording linked to the previous one (0 ends it right away)
+    #[serde(default = "default_reconnect_grace_seconds")]
+    pub reconnect_grace_seconds: u64,
+
+    /// What a stream published again before its last recording is finalized gets,
+    /// overridden per rule
+    #[serde(default)]
+    pub republish: RepublishMode,
+
+    /// With `republish = "resume"`, how long a recording waits for its publisher to come
+    /// back after it left. Longer absences start a new recording
+    #[serde(default = "default_resume_max_gap_seconds")]
+    pub resume_max_gap_seconds: u64,
+
+    /// A recording that gets no media this long while its publisher stays connected is
+    /// stalled, reported and handled per `stall_action` (0 turns the watchdog off)
+    #[serde(default = "default_stall_timeout_seconds")]
+    pub stall_timeout_seconds: u64,
+
+    /// What a stalled recording gets
+    #[serde(default)]
+    pub stall_action: StallAction,
+
+    /// Store byte-identical init segments once under `_shared/init/{sha256}.mp4` and
+    /// reference them from the manifests
+    #[serde(default)]
+    pub dedup_init_segments: bool,
+
+    /// Names of media segments after their track's `v_`/`a_` prefix, printf-style with
+    /// the segment number as `%d` or zero-padded `%0Nd`. Only recordings started
+    /// afterwards are named after a changed pattern, their manifests carry it
+    #[serde(default = "default_segment_pattern")]
+    pub segment_pattern: String,
+
+    /// Streams recorded at once on this node, further starts are refused or preempt a
+    /// recording of lower priority depending on `recording_limit_mode` (0 is unlimited)
+    #[serde(default)]
+    pub max_concurrent_recordings: usize,
+
+    /// What a start does once `max_concurrent_recordings` is reached
+    #[serde(default)]
+    pub recording_limit_mode: RecordingLimitMode,
+
     /// Async upload configuration
     #[serde(default)]
     pub upload: UploadConfig,
+
+    /// Wait for storage, or liveman with uploads, before writing recordings out
+    #[serde(default)]
+    pub startup: StartupConfig,
+
+    /// Index/storage reconciliation of finished recordings
+    #[serde(default)]
+    pub reconcile: ReconcileConfig,
+
+    /// Push index transitions to liveman as they happen
+    #[serde(default)]
+    pub push: PushConfig,
+
+    /// Auto-record a stream only while holding its lease from liveman
+    #[serde(default)]
+    pub lease: LeaseConfig,
+
+    /// Recordings started by `POST /api/recorder/trigger` or MQTT events
+    #[serde(default)]
+    pub triggers: TriggerConfig,
+
+    /// livevod instances told about manifests this node rewrites in storage
+    #[serde(default)]
+    pub invalidate: storage::InvalidateConfig,
+
+    /// Retention classes of recordings and the optional index sweep
+    #[serde(default)]
+    pub retention: RetentionConfig,
+
+    /// Periodic copies of the index in storage
+    #[serde(default)]
+    pub backup: BackupConfig,
+
+    /// Log of destructive operations next to the index
+    #[serde(default)]
+    pub audit: AuditConfig,
+
+    /// Locks keeping other processes off the index file
+    #[serde(default)]
+    pub index_lock: IndexLockConfig,
+
+    /// How often the index and the upload queue are synced to disk
+    #[serde(default)]
+    pub durability: DurabilityConfig,
+
+    /// Threads of the recorder's own runtime, kept apart from the media path
+    #[serde(default)]
+    pub runtime: RecorderRuntimeConfig,
+
+    /// Thresholds of the recording integrity rollup, `GET /api/recorder/health`
+    #[serde(default)]
+    pub health: HealthConfig,
+
+    /// Storage failure injection, honored only in debug builds or with the `chaos` feature
+    #[serde(default)]
+    pub chaos: Option<storage::ChaosConfig>,
+
+    /// Stages of `POST /api/storage/diagnose`, served when uploads are disabled
+    #[serde(default)]
+    pub diagnose: storage::DiagnoseConfig,
 }
 
 #[cfg(feature = "recorder")]
@@ -171,6 +333,51 @@ fn default_max_recording_seconds() -> u64 {
     86_400
 }
 
+#[cfg(feature = "recorder")]
+fn default_shutdown_deadline_seconds() -> u64 {
+    10
+}
+
+#[cfg(feature = "recorder")]
+fn default_reconnect_grace_seconds() -> u64 {
+    10
+}
+
+#[cfg(feature = "recorder")]
+fn default_resume_max_gap_seconds() -> u64 {
+    30
+}
+
+#[cfg(feature = "recorder")]
+fn default_stall_timeout_seconds() -> u64 {
+    60
+}
+
+#[cfg(feature = "recorder")]
+fn default_segment_pattern() -> String {
+    storage::DEFAULT_SEGMENT_PATTERN.to_string()
+}
+
+#[cfg(feature = "recorder")]
+fn default_namespace_keys() -> bool {
+    true
+}
+
+#[cfg(feature = "recorder")]
+impl RecorderConfig {
+    /// Namespace of generated object keys, `None` for the plain layout
+    pub fn key_namespace(&self) -> Option<String> {
+        if !self.namespace_keys {
+            return None;
+        }
+        self.key_namespace
+            .as_ref()
+            .or(self.node_alias.as_ref())
+            .map(|ns| ns.trim_matches('/').to_string())
+            .filter(|ns| !ns.is_empty())
+    }
+}
+
 #[cfg(feature = "recorder")]
 impl Default for RecorderConfig {
     fn default() -> Self {
@@ -178,13 +385,733 @@ impl Default for RecorderConfig {
             auto_streams: vec![],
             storage: Default::default(),
             node_alias: None,
+            key_namespace: None,
+            namespace_keys: default_namespace_keys(),
+            tenancy: Default::default(),
             index_path: None,
             max_recording_seconds: default_max_recording_seconds(),
+            max_recording_duration_minutes: None,
+            rules: vec![],
+            schedules: vec![],
+            schedule_grace_seconds: default_schedule_grace_seconds(),
+            shutdown_deadline_seconds: default_shutdown_deadline_seconds(),
+            reconnect_grace_seconds: default_reconnect_grace_seconds(),
+            republish: Default::default(),
+            resume_max_gap_seconds: default_resume_max_gap_seconds(),
+            stall_timeout_seconds: default_stall_timeout_seconds(),
+            stall_action: Default::default(),
+            dedup_init_segments: false,
+            segment_pattern: default_segment_pattern(),
+            max_concurrent_recordings: 0,
+            recording_limit_mode: Default::default(),
             upload: Default::default(),
+            startup: Default::default(),
+            reconcile: Default::default(),
+            push: Default::default(),
+            lease: Default::default(),
+            triggers: Default::default(),
+            invalidate: Default::default(),
+            retention: Default::default(),
+            backup: Default::default(),
+            audit: Default::default(),
+            index_lock: Default::default(),
+            durability: Default::default(),
+            runtime: Default::default(),
+            health: Default::default(),
+            chaos: None,
+            diagnose: Default::default(),
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingLimitMode {
+    /// Refuse the new recording
+    #[default]
+    Reject,
+    /// Stop the active recording of lowest priority, if lower than the new one's
+    Preempt,
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StallAction {
+    /// Keep the recording open, samples coming back continue it
+    #[default]
+    Wait,
+    /// Finalize the recording as `Interrupted`
+    Interrupt,
+    /// Finalize it as `Interrupted`, the stream's next sample starts a new recording
+    Restart,
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepublishMode {
+    /// Start the new recording once the last one is finalized
+    #[default]
+    Queue,
+    /// Start the new recording right away, next to the one being finalized
+    ForceNew,
+    /// Keep the last recording open for `resume_max_gap_seconds`, a publisher back by
+    /// then continues it in a new Period
+    Resume,
+}
+
+/// Several customers on one node: recordings of `{tenant}{separator}...` streams are
+/// stored under `{tenant}/`, so presigning and playback can be scoped to a tenant
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TenancyConfig {
+    /// The tenant is the stream name up to the first separator, `acme-cam1` is `acme`
+    /// with `-`
+    #[serde(default)]
+    pub tenant_separator: Option<String>,
+    /// Regex matched against the stream name instead of `tenant_separator`, its
+    /// `tenant` group or else its first group is the tenant
+    #[serde(default)]
+    pub tenant_pattern: Option<String>,
+    /// Tenant of streams that don't match, those are not recorded without one
+    #[serde(default)]
+    pub default_tenant: Option<String>,
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingRule {
+    /// Stream name patterns matched by this rule, supports wildcards
+    pub streams: Vec<String>,
+    /// Override of the global split limit for matching streams (0 disables splitting)
+    #[serde(default)]
+    pub max_recording_duration_minutes: Option<u64>,
+    /// Retention class of matching streams' recordings, e.g. `30d`, `1y` or `forever`
+    #[serde(default)]
+    pub retention_class: Option<RetentionClass>,
+    /// Priority of matching streams' recordings, higher values are uploaded first and
+    /// deleted last by the byte quota (default: 100)
+    #[serde(default)]
+    pub priority: Option<u8>,
+    /// Override of `republish` for matching streams
+    #[serde(default)]
+    pub republish: Option<RepublishMode>,
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    /// Class of recordings no rule or start request assigns one, unset keeps them forever
+    #[serde(default)]
+    pub default_class: Option<RetentionClass>,
+    /// Delete finished recordings past their class from storage and the index. Leave it
+    /// off when storage lifecycle rules expire the tagged objects.
+    #[serde(default)]
+    pub sweep: bool,
+    /// Minutes between sweeps
+    #[serde(default = "default_retention_sweep_interval_minutes")]
+    pub sweep_interval_minutes: u64,
+    /// Days a deleted recording stays in the trash before its objects are deleted
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u64,
+    /// With `sweep`, bytes indexed recordings may take in storage (0 disables the quota).
+    /// Finished recordings over it are deleted lowest priority first, oldest first.
+    #[serde(default)]
+    pub max_bytes: u64,
+}
+
+#[cfg(feature = "recorder")]
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            default_class: None,
+            sweep: false,
+            sweep_interval_minutes: default_retention_sweep_interval_minutes(),
+            trash_retention_days: default_trash_retention_days(),
+            max_bytes: 0,
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_retention_sweep_interval_minutes() -> u64 {
+    60
+}
+
+#[cfg(feature = "recorder")]
+fn default_trash_retention_days() -> u64 {
+    7
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupConfig {
+    /// Upload the index to `_index/{node_alias}/{timestamp}.jsonl` in storage
+    #[serde(default)]
+    pub enabled: bool,
+    /// Minutes between backups, unchanged indexes are not uploaded again
+    #[serde(default = "default_backup_interval_minutes")]
+    pub interval_minutes: u64,
+    /// Backups kept per node, older ones are deleted after each upload
+    #[serde(default = "default_backup_keep")]
+    pub keep: usize,
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexLockMode {
+    /// `flock` on lock files next to the index
+    #[default]
+    Flock,
+    /// A lease file renewed by a heartbeat, for network filesystems where flock is
+    /// unreliable
+    Lease,
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexLockConfig {
+    #[serde(default)]
+    pub mode: IndexLockMode,
+    /// Give up on a lock held by someone else after this many seconds
+    #[serde(default = "default_index_lock_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// With `lease`, a lease not renewed for this many seconds is taken over
+    #[serde(default = "default_index_lease_ttl_seconds")]
+    pub lease_ttl_seconds: u64,
+}
+
+/// When writes to the index and the upload queue reach the disk
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DurabilityMode {
+    /// Every index write is synced before it returns, the queue is rewritten and synced
+    /// on every change
+    #[default]
+    Strict,
+    /// Syncs and queue rewrites are coalesced to one per `flush_interval_ms`, a crash
+    /// loses at most that window. Rewritten files are synced before they are renamed
+    Batched,
+    /// Nothing is synced, the OS writes back when it sees fit. A power loss may lose
+    /// whatever it had not written yet
+    Relaxed,
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DurabilityConfig {
+    #[serde(default)]
+    pub mode: DurabilityMode,
+    /// With `batched` or `relaxed`, the longest a write waits for its sync or queue
+    /// rewrite
+    #[serde(default = "default_durability_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+}
+
+#[cfg(feature = "recorder")]
+impl Default for DurabilityConfig {
+    fn default() -> Self {
+        Self {
+            mode: DurabilityMode::default(),
+            flush_interval_ms: default_durability_flush_interval_ms(),
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_durability_flush_interval_ms() -> u64 {
+    1000
+}
+
+/// The dedicated runtime index file IO and uploads run on, so a hung storage endpoint
+/// or a slow disk ties up its threads and not the ones forwarding media
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecorderRuntimeConfig {
+    /// Async worker threads, 0 runs the recorder on the main runtime as before
+    #[serde(default = "default_runtime_worker_threads")]
+    pub worker_threads: usize,
+    /// Threads for blocking file IO, tasks beyond them wait for one to free up
+    #[serde(default = "default_runtime_max_blocking_threads")]
+    pub max_blocking_threads: usize,
+}
+
+#[cfg(feature = "recorder")]
+impl Default for RecorderRuntimeConfig {
+    fn default() -> Self {
+        Self {
+            worker_threads: default_runtime_worker_threads(),
+            max_blocking_threads: default_runtime_max_blocking_threads(),
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_runtime_worker_threads() -> usize {
+    2
+}
+
+#[cfg(feature = "recorder")]
+fn default_runtime_max_blocking_threads() -> usize {
+    16
+}
+
+/// A figure's thresholds for `degraded` and `failing`, 0 turns a level off
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthThreshold {
+    #[serde(default)]
+    pub degraded: u64,
+    #[serde(default)]
+    pub failing: u64,
+}
+
+/// When the integrity rollup turns `degraded` or `failing`. A full disk always fails
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthConfig {
+    /// Age of the oldest queued upload, seconds since its file was written
+    #[serde(default = "default_health_upload_lag_seconds")]
+    pub upload_lag_seconds: HealthThreshold,
+    /// Uploads given up after `upload.max_retries`
+    #[serde(default = "default_health_dead_lettered")]
+    pub dead_lettered: HealthThreshold,
+    /// Uploads whose last attempt stored something other than the local file
+    #[serde(default = "default_health_upload_mismatches")]
+    pub upload_mismatches: HealthThreshold,
+    /// Finished recordings whose objects are gone from storage
+    #[serde(default = "default_health_missing")]
+    pub missing: HealthThreshold,
+    /// Recordings failed or interrupted within `window_seconds`
+    #[serde(default = "default_health_interrupted")]
+    pub interrupted: HealthThreshold,
+    /// Seconds a running recording got no media for, see `stall_timeout_seconds`
+    #[serde(default = "default_health_stalled_seconds")]
+    pub stalled_seconds: HealthThreshold,
+    /// How far back failed and interrupted recordings count
+    #[serde(default = "default_health_window_seconds")]
+    pub window_seconds: u64,
+    /// `degraded` while free space is less than this many percent above
+    /// `upload.min_free_bytes`, 0 turns it off
+    #[serde(default = "default_health_disk_headroom_percent")]
+    pub disk_headroom_percent: u64,
+}
+
+#[cfg(feature = "recorder")]
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            upload_lag_seconds: default_health_upload_lag_seconds(),
+            dead_lettered: default_health_dead_lettered(),
+            upload_mismatches: default_health_upload_mismatches(),
+            missing: default_health_missing(),
+            interrupted: default_health_interrupted(),
+            stalled_seconds: default_health_stalled_seconds(),
+            window_seconds: default_health_window_seconds(),
+            disk_headroom_percent: default_health_disk_headroom_percent(),
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_health_upload_lag_seconds() -> HealthThreshold {
+    HealthThreshold {
+        degraded: 300,
+        failing: 1_800,
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_health_dead_lettered() -> HealthThreshold {
+    HealthThreshold {
+        degraded: 0,
+        failing: 1,
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_health_upload_mismatches() -> HealthThreshold {
+    HealthThreshold {
+        degraded: 1,
+        failing: 5,
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_health_missing() -> HealthThreshold {
+    HealthThreshold {
+        degraded: 0,
+        failing: 1,
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_health_interrupted() -> HealthThreshold {
+    HealthThreshold {
+        degraded: 1,
+        failing: 3,
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_health_stalled_seconds() -> HealthThreshold {
+    HealthThreshold {
+        degraded: 1,
+        failing: 600,
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_health_window_seconds() -> u64 {
+    86_400
+}
+
+#[cfg(feature = "recorder")]
+fn default_health_disk_headroom_percent() -> u64 {
+    100
+}
+
+#[cfg(feature = "recorder")]
+impl Default for IndexLockConfig {
+    fn default() -> Self {
+        Self {
+            mode: IndexLockMode::default(),
+            timeout_seconds: default_index_lock_timeout_seconds(),
+            lease_ttl_seconds: default_index_lease_ttl_seconds(),
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_index_lock_timeout_seconds() -> u64 {
+    10
+}
+
+#[cfg(feature = "recorder")]
+fn default_index_lease_ttl_seconds() -> u64 {
+    30
+}
+
+#[cfg(feature = "recorder")]
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_minutes: default_backup_interval_minutes(),
+            keep: default_backup_keep(),
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_backup_interval_minutes() -> u64 {
+    60
+}
+
+#[cfg(feature = "recorder")]
+fn default_backup_keep() -> usize {
+    24
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// Size of `index.audit.json` past which it is rotated, 0 never rotates
+    #[serde(default = "default_audit_max_bytes")]
+    pub max_bytes: u64,
+    /// Rotated files kept, `index.audit.1.json` being the newest
+    #[serde(default = "default_audit_keep")]
+    pub keep: usize,
+}
+
+#[cfg(feature = "recorder")]
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: default_audit_max_bytes(),
+            keep: default_audit_keep(),
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_audit_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+#[cfg(feature = "recorder")]
+fn default_audit_keep() -> usize {
+    5
+}
+
+#[cfg(feature = "recorder")]
+fn default_schedule_grace_seconds() -> u64 {
+    300
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingSchedule {
+    /// Stream name patterns recorded by this schedule, supports wildcards
+    pub streams: Vec<String>,
+    /// Cron expression (`minute hour day-of-month month day-of-week`) opening a window
+    pub start: String,
+    /// Cron expression closing the window, exclusive with `duration_minutes`
+    #[serde(default)]
+    pub stop: Option<String>,
+    /// Window length after each start, exclusive with `stop`
+    #[serde(default)]
+    pub duration_minutes: Option<u64>,
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconcileConfig {
+    /// Run reconciliation every this many minutes (0 only runs it on demand)
+    #[serde(default)]
+    pub interval_minutes: u64,
+    /// Segments checked per recording in addition to its manifest
+    #[serde(default)]
+    pub sample_segments: usize,
+    /// Maximum storage HEAD requests per second
+    #[serde(default = "default_reconcile_heads_per_second")]
+    pub max_heads_per_second: u32,
+    /// Maximum objects read per second when verifying a recording with checksums
+    #[serde(default = "default_checksum_reads_per_second")]
+    pub max_checksum_reads_per_second: u32,
+}
+
+/// Startup gate of storage writes: recordings start right away, their objects wait
+/// until storage (or liveman with uploads) answers
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StartupConfig {
+    /// Give up waiting after this long and write as usual, failures retried or
+    /// logged (0 disables the gate)
+    #[serde(default = "default_startup_max_wait_seconds")]
+    pub max_wait_seconds: u64,
+    /// Interval between connection checks while waiting
+    #[serde(default = "default_startup_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+#[cfg(feature = "recorder")]
+impl Default for StartupConfig {
+    fn default() -> Self {
+        Self {
+            max_wait_seconds: default_startup_max_wait_seconds(),
+            poll_interval_ms: default_startup_poll_interval_ms(),
         }
     }
 }
 
+#[cfg(feature = "recorder")]
+fn default_startup_max_wait_seconds() -> u64 {
+    120
+}
+
+#[cfg(feature = "recorder")]
+fn default_startup_poll_interval_ms() -> u64 {
+    2_000
+}
+
+#[cfg(feature = "recorder")]
+impl Default for ReconcileConfig {
+    fn default() -> Self {
+        Self {
+            interval_minutes: 0,
+            sample_segments: 0,
+            max_heads_per_second: default_reconcile_heads_per_second(),
+            max_checksum_reads_per_second: default_checksum_reads_per_second(),
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_reconcile_heads_per_second() -> u32 {
+    10
+}
+
+#[cfg(feature = "recorder")]
+fn default_checksum_reads_per_second() -> u32 {
+    5
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushConfig {
+    /// POST index transitions to liveman, pull sync stays the backfill path
+    #[serde(default)]
+    pub enabled: bool,
+    /// Liveman base URL, e.g. http://127.0.0.1:8888
+    #[serde(default)]
+    pub liveman_url: String,
+    /// Token liveman has configured for this node (`[[nodes]] token`)
+    #[serde(default)]
+    pub token: Secret,
+    /// Maximum transitions per request
+    #[serde(default = "default_push_batch_size")]
+    pub batch_size: usize,
+    /// Delay between flushes, and between retries while liveman is unreachable
+    #[serde(default = "default_push_interval_ms")]
+    pub interval_ms: u64,
+    /// Undelivered transitions kept while liveman is unreachable, the oldest are
+    /// dropped beyond this and left to pull sync
+    #[serde(default = "default_push_max_queue")]
+    pub max_queue: usize,
+}
+
+#[cfg(feature = "recorder")]
+impl Default for PushConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            liveman_url: String::new(),
+            token: Secret::default(),
+            batch_size: default_push_batch_size(),
+            interval_ms: default_push_interval_ms(),
+            max_queue: default_push_max_queue(),
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_push_batch_size() -> usize {
+    100
+}
+
+#[cfg(feature = "recorder")]
+fn default_push_interval_ms() -> u64 {
+    500
+}
+
+#[cfg(feature = "recorder")]
+fn default_push_max_queue() -> usize {
+    10_000
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaseConfig {
+    /// Ask liveman before auto-recording, so a stream published on several nodes is
+    /// recorded by one of them
+    #[serde(default)]
+    pub enabled: bool,
+    /// Liveman base URL, e.g. http://127.0.0.1:8888
+    #[serde(default)]
+    pub liveman_url: String,
+    /// Token liveman has configured for this node (`[[nodes]] token`)
+    #[serde(default)]
+    pub token: Secret,
+    /// Lease lifetime, renewed every third of it while recording. Another node takes
+    /// over this long after the holder stopped renewing
+    #[serde(default = "default_lease_ttl_seconds")]
+    pub ttl_seconds: u64,
+}
+
+#[cfg(feature = "recorder")]
+impl Default for LeaseConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            liveman_url: String::new(),
+            token: Secret::default(),
+            ttl_seconds: default_lease_ttl_seconds(),
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_lease_ttl_seconds() -> u64 {
+    30
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriggerConfig {
+    /// Streams whose recent media is kept in memory while published, so a recording
+    /// started by a trigger begins before it
+    #[serde(default)]
+    pub streams: Vec<String>,
+    /// Media kept per stream of `streams`, the longest pre-roll a trigger gets
+    #[serde(default = "default_trigger_buffer_ms")]
+    pub buffer_ms: u64,
+    /// Pre-roll of triggers not asking for one
+    #[serde(default = "default_trigger_pre_roll_ms")]
+    pub pre_roll_ms: u64,
+    /// Quiet time after the last trigger before the recording stops, for triggers not
+    /// asking for one
+    #[serde(default = "default_trigger_post_roll_ms")]
+    pub post_roll_ms: u64,
+    /// Subscribe to an MQTT broker for triggers, needs the `trigger-mqtt` feature
+    #[serde(default)]
+    pub mqtt: Option<TriggerMqttConfig>,
+}
+
+#[cfg(feature = "recorder")]
+impl Default for TriggerConfig {
+    fn default() -> Self {
+        Self {
+            streams: Vec::new(),
+            buffer_ms: default_trigger_buffer_ms(),
+            pre_roll_ms: default_trigger_pre_roll_ms(),
+            post_roll_ms: default_trigger_post_roll_ms(),
+            mqtt: None,
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_trigger_buffer_ms() -> u64 {
+    10_000
+}
+
+#[cfg(feature = "recorder")]
+fn default_trigger_pre_roll_ms() -> u64 {
+    5_000
+}
+
+#[cfg(feature = "recorder")]
+fn default_trigger_post_roll_ms() -> u64 {
+    30_000
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriggerMqttConfig {
+    /// Broker URL with the client id, e.g. mqtt://127.0.0.1:1883?client_id=live777-edge-1
+    pub url: String,
+    #[serde(default)]
+    pub topics: Vec<TriggerTopic>,
+}
+
+/// Messages on `topic` trigger the recording of `stream`
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriggerTopic {
+    /// Topic filter, `+` and a trailing `#` as wildcards, e.g. `cameras/+/motion`
+    pub topic: String,
+    /// Stream name, `{1}` standing for the level the first wildcard matched, and so
+    /// on, e.g. `{1}`
+    pub stream: String,
+}
+
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadTransport {
+    /// Send each object to storage with a URL liveman presigns
+    #[default]
+    Presign,
+    /// Stream each object to liveman's `PUT /api/storage/objects/{path}`, which writes
+    /// it to storage. No presigned URL leaves liveman, multipart uploads are not used
+    Proxy,
+}
+
 #[cfg(feature = "recorder")]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UploadConfig {
@@ -196,22 +1123,89 @@ pub struct UploadConfig {
     pub liveman_url: String,
     /// Liveman bearer token for presign API
     #[serde(default)]
-    pub liveman_token: String,
+    pub liveman_token: Secret,
+    /// How objects reach storage: PUT to URLs liveman presigns, or streamed through
+    /// liveman where nodes can't reach storage themselves
+    #[serde(default)]
+    pub mode: UploadTransport,
     /// Queue file path for pending uploads
     #[serde(default = "default_upload_queue_path")]
     pub queue_path: String,
     /// Local spool directory for recordings before upload
     #[serde(default = "default_upload_local_dir")]
     pub local_dir: String,
+    /// Files queued for upload, owned by the uploader. Finished files are hard linked
+    /// here from `local_dir`, or copied when it is on another filesystem
+    #[serde(default = "default_upload_staging_dir")]
+    pub staging_dir: String,
+    /// Keep recordings in `local_dir` for this many minutes regardless of upload
+    /// progress (0 moves them to the staging dir right away)
+    #[serde(default)]
+    pub local_retention_minutes: u64,
     /// Presigned URL TTL seconds
     #[serde(default = "default_presign_ttl_seconds")]
     pub presign_ttl_seconds: u64,
+    /// Slowest upload rate expected, URLs are presigned for as long as their transfer
+    /// takes at this rate when that outlasts `presign_ttl_seconds` (0 disables)
+    #[serde(default)]
+    pub min_upload_bytes_per_second: u64,
+    /// Upload files of at least this size in parts, retries resume after the last
+    /// uploaded part. Liveman needs static S3 credentials to sign them (0 disables)
+    #[serde(default)]
+    pub multipart_threshold_bytes: u64,
+    /// Size of each part of a multipart upload, at least 5 MiB
+    #[serde(default = "default_multipart_part_bytes")]
+    pub multipart_part_bytes: u64,
     /// Upload loop interval in milliseconds
     #[serde(default = "default_upload_interval_ms")]
     pub interval_ms: u64,
-    /// Maximum concurrent uploads
+    /// Maximum concurrent uploads of objects of at least `small_object_bytes`, of
+    /// all objects when it is 0
     #[serde(default = "default_upload_concurrency")]
     pub concurrency: usize,
+    /// Objects smaller than this upload in a lane of their own, so manifests and init
+    /// segments never wait behind large segments (0 puts all objects in one lane)
+    #[serde(default = "default_upload_small_object_bytes")]
+    pub small_object_bytes: u64,
+    /// Maximum concurrent uploads of objects below `small_object_bytes`
+    #[serde(default = "default_upload_small_concurrency")]
+    pub small_concurrency: usize,
+    /// Refuse new files and drop segments while `local_dir` has less free space
+    /// (0 disables the guard)
+    #[serde(default)]
+    pub min_free_bytes: u64,
+    /// Same as `min_free_bytes` for free inodes, which small segments can run out of
+    /// first (0 disables the guard)
+    #[serde(default)]
+    pub min_free_inodes: u64,
+    /// Upload a recording's manifest at most this often while it is being recorded,
+    /// newer versions replace the queued one meanwhile. The final manifest is uploaded
+    /// right away (0 uploads every version)
+    #[serde(default)]
+    pub mpd_upload_interval_ms: u64,
+    /// Give an upload up after this many failed retries, until its object is queued
+    /// again (0 retries forever)
+    #[serde(default)]
+    pub max_retries: u32,
+    /// Suspend the queue after this many connection failures to liveman in a row,
+    /// probing `/api/storage/ping` until it answers instead of retrying every entry
+    /// (0 never suspends)
+    #[serde(default = "default_suspend_after_failures")]
+    pub suspend_after_failures: u32,
+    /// Interval between probes of liveman while suspended
+    #[serde(default = "default_suspend_probe_interval_ms")]
+    pub suspend_probe_interval_ms: u64,
+    /// Check the size of each uploaded object against its local file before deleting
+    /// the file, a mismatch is retried like a failed upload
+    #[serde(default = "default_verify_size")]
+    pub verify_size: bool,
+    /// Also compare the SHA-256 of each uploaded object with its local file, reading
+    /// the object back
+    #[serde(default)]
+    pub verify_checksum: bool,
+    /// Window of the upload lag percentiles and worst lag, see `recorder_upload_lag_seconds`
+    #[serde(default = "default_upload_lag_window_seconds")]
+    pub lag_window_seconds: u64,
 }
 
 #[cfg(feature = "recorder")]
@@ -220,12 +1214,29 @@ impl Default for UploadConfig {
         Self {
             enabled: false,
             liveman_url: String::new(),
-            liveman_token: String::new(),
+            liveman_token: Secret::default(),
+            mode: UploadTransport::default(),
             queue_path: default_upload_queue_path(),
             local_dir: default_upload_local_dir(),
+            staging_dir: default_upload_staging_dir(),
+            local_retention_minutes: 0,
             presign_ttl_seconds: default_presign_ttl_seconds(),
+            min_upload_bytes_per_second: 0,
+            multipart_threshold_bytes: 0,
+            multipart_part_bytes: default_multipart_part_bytes(),
             interval_ms: default_upload_interval_ms(),
             concurrency: default_upload_concurrency(),
+            small_object_bytes: default_upload_small_object_bytes(),
+            small_concurrency: default_upload_small_concurrency(),
+            min_free_bytes: 0,
+            min_free_inodes: 0,
+            mpd_upload_interval_ms: 0,
+            max_retries: 0,
+            suspend_after_failures: default_suspend_after_failures(),
+            suspend_probe_interval_ms: default_suspend_probe_interval_ms(),
+            verify_size: default_verify_size(),
+            verify_checksum: false,
+            lag_window_seconds: default_upload_lag_window_seconds(),
         }
     }
 }
@@ -240,11 +1251,21 @@ fn default_upload_local_dir() -> String {
     "./recordings".to_string()
 }
 
+#[cfg(feature = "recorder")]
+fn default_upload_staging_dir() -> String {
+    "./recordings/.staging".to_string()
+}
+
 #[cfg(feature = "recorder")]
 fn default_presign_ttl_seconds() -> u64 {
     300
 }
 
+#[cfg(feature = "recorder")]
+fn default_multipart_part_bytes() -> u64 {
+    16 << 20
+}
+
 #[cfg(feature = "recorder")]
 fn default_upload_interval_ms() -> u64 {
     2_000
@@ -254,6 +1275,37 @@ fn default_upload_interval_ms() -> u64 {
 fn default_upload_concurrency() -> usize {
     2
 }
+
+#[cfg(feature = "recorder")]
+fn default_upload_small_object_bytes() -> u64 {
+    1 << 20
+}
+
+#[cfg(feature = "recorder")]
+fn default_upload_small_concurrency() -> usize {
+    4
+}
+
+#[cfg(feature = "recorder")]
+fn default_suspend_after_failures() -> u32 {
+    10
+}
+
+#[cfg(feature = "recorder")]
+fn default_suspend_probe_interval_ms() -> u64 {
+    30_000
+}
+
+#[cfg(feature = "recorder")]
+fn default_verify_size() -> bool {
+    true
+}
+
+#[cfg(feature = "recorder")]
+fn default_upload_lag_window_seconds() -> u64 {
+    3_600
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct StreamConfig {
     #[serde(default)]
@@ -38,6 +38,8 @@ impl From<crate::forward::message::ForwardInfo> for api::response::Stream {
                     fmtp: media_code.fmtp,
                 })
                 .collect(),
+            // Filled in by the stream routes from the recorder
+            recording: None,
         }
     }
 }
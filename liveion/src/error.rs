@@ -6,6 +6,10 @@ pub enum AppError {
     StreamNotFound(String),
     StreamAlreadyExists(String),
     SessionNotFound(String),
+    RecordingNotFound(String),
+    BadRequest(String),
+    /// Recorder failure returned as a typed body liveman can act on
+    Recorder(api::recorder::RecorderError),
     Throw(String),
     InternalServerError(anyhow::Error),
 }
@@ -32,6 +36,24 @@ impl AppError {
         AppError::SessionNotFound(t.to_string())
     }
 
+    pub fn recording_not_found<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::RecordingNotFound(t.to_string())
+    }
+
+    pub fn bad_request<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::BadRequest(t.to_string())
+    }
+
+    pub fn recorder(e: api::recorder::RecorderError) -> Self {
+        AppError::Recorder(e)
+    }
+
     pub fn throw<T>(t: T) -> Self
     where
         T: ToString,
@@ -46,6 +68,24 @@ impl IntoResponse for AppError {
             AppError::StreamNotFound(err) => (StatusCode::NOT_FOUND, err).into_response(),
             AppError::StreamAlreadyExists(err) => (StatusCode::CONFLICT, err).into_response(),
             AppError::SessionNotFound(err) => (StatusCode::NOT_FOUND, err).into_response(),
+            AppError::RecordingNotFound(err) => (StatusCode::NOT_FOUND, err).into_response(),
+            AppError::BadRequest(err) => (StatusCode::BAD_REQUEST, err).into_response(),
+            AppError::Recorder(err) => {
+                let status = StatusCode::from_u16(err.status_code())
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                let mut response = (status, axum::Json(&err)).into_response();
+                if let api::recorder::RecorderError::IndexBusy {
+                    retry_after_seconds,
+                    ..
+                } = err
+                {
+                    response.headers_mut().insert(
+                        http::header::RETRY_AFTER,
+                        http::HeaderValue::from(retry_after_seconds),
+                    );
+                }
+                response
+            }
             AppError::InternalServerError(err) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
             }
@@ -1,6 +1,6 @@
 use crate::forward::message;
 
-use super::{Event, Stream, StreamEventType};
+use super::{Event, RecordingEventType, Stream, StreamEventType};
 
 impl From<StreamEventType> for api::event::StreamEventType {
     fn from(value: StreamEventType) -> Self {
@@ -11,6 +11,15 @@ impl From<StreamEventType> for api::event::StreamEventType {
     }
 }
 
+impl From<RecordingEventType> for api::event::RecordingEventType {
+    fn from(value: RecordingEventType) -> Self {
+        match value {
+            RecordingEventType::Missing => api::event::RecordingEventType::RecordingMissing,
+            RecordingEventType::Stalled => api::event::RecordingEventType::RecordingStalled,
+        }
+    }
+}
+
 impl From<Stream> for api::event::Stream {
     fn from(value: Stream) -> Self {
         Self {
@@ -68,6 +77,10 @@ impl Event {
                 stream: stream_evnet.stream.into(),
             },
             Event::Forward(forward_event) => forward_event.into(),
+            Event::Recording(recording_event) => api::event::Event::Recording {
+                r#type: recording_event.r#type.into(),
+                recording: recording_event.recording,
+            },
         }
     }
 }
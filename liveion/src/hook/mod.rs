@@ -11,6 +11,19 @@ use crate::forward::message::ForwardEvent;
 pub enum Event {
     Stream(StreamEvent),
     Forward(ForwardEvent),
+    Recording(RecordingEvent),
+}
+
+#[derive(Clone, Debug)]
+pub struct RecordingEvent {
+    pub r#type: RecordingEventType,
+    pub recording: api::recorder::RecordingIndexEntry,
+}
+
+#[derive(Clone, Debug)]
+pub enum RecordingEventType {
+    Missing,
+    Stalled,
 }
 
 #[derive(Clone, Debug)]
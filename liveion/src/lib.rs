@@ -89,13 +89,24 @@ where
             })
             .layer(middleware::from_fn(access_middleware))
             .layer(middleware::from_fn_with_state(
-                AuthState::new(cfg.auth.secret, cfg.auth.tokens),
+                AuthState::new(&cfg.auth.secret, &cfg.auth.tokens),
                 validate_middleware,
             )),
     );
 
     let app = app
         .route(path::METRICS, get(metrics))
+        .route(path::OPENAPI, get(crate::route::openapi::openapi));
+    let app = if cfg.http.swagger_ui {
+        app.merge(
+            utoipa_swagger_ui::SwaggerUi::new(path::SWAGGER_UI)
+                .config(utoipa_swagger_ui::Config::from(path::OPENAPI)),
+        )
+    } else {
+        app
+    };
+
+    let app = app
         .with_state(app_state.clone())
         .layer(if cfg.http.cors {
             CorsLayer::permissive()
@@ -164,6 +175,8 @@ where
 
     #[cfg(feature = "source")]
     let stream_manager = app_state.stream_manager.clone();
+    #[cfg(feature = "recorder")]
+    let recorder_deadline = std::time::Duration::from_secs(cfg.recorder.shutdown_deadline_seconds);
 
     axum::serve(listener, app)
         .with_graceful_shutdown(async move {
@@ -177,6 +190,9 @@ where
                     tracing::error!("Failed to stop sources: {}", e);
                 }
             }
+
+            #[cfg(feature = "recorder")]
+            crate::recorder::shutdown(recorder_deadline).await;
         })
         .await
         .unwrap_or_else(|e| error!("Application error: {e}"));
@@ -215,6 +231,72 @@ pub fn metrics_register() {
     metrics::REGISTRY
         .register(Box::new(metrics::REFORWARD.clone()))
         .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDINGS_MISSING.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_DISK_FREE_BYTES.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_DISK_FREE_INODES.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_DISK_GUARD.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_UPLOADS_COALESCED.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_UPLOADS_DEAD_LETTERED.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_UPLOADS_SUSPENDED.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_UPLOAD_SUSPENSIONS.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_UPLOADS_VERIFIED.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_UPLOAD_MISMATCHES.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_UPLOADS_IN_FLIGHT.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_UPLOAD_LANE_CONCURRENCY.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_EVENT_SUBSCRIBERS.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_EVENTS_DELIVERED.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_EVENTS_DROPPED.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_UPLOAD_LAG.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_UPLOAD_LAG_WORST.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_TRIGGERS.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_STARTS_REJECTED.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDINGS_PREEMPTED.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDINGS_STALLED.clone()))
+        .unwrap();
+    metrics::REGISTRY
+        .register(Box::new(metrics::RECORDER_STALLED.clone()))
+        .unwrap();
 }
 
 async fn metrics() -> String {
@@ -1,11 +1,142 @@
 use lazy_static::lazy_static;
-use prometheus::{Gauge, Registry, TextEncoder};
+use prometheus::{
+    Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
 
 lazy_static! {
     pub static ref STREAM: Gauge = Gauge::new("stream", "stream number").unwrap();
     pub static ref PUBLISH: Gauge = Gauge::new("publish", "publish number").unwrap();
     pub static ref SUBSCRIBE: Gauge = Gauge::new("subscribe", "subscribe number").unwrap();
     pub static ref REFORWARD: Gauge = Gauge::new("reforward", "reforward number").unwrap();
+    pub static ref RECORDINGS_MISSING: IntCounter = IntCounter::new(
+        "recordings_missing_total",
+        "finished recordings whose objects were found missing in storage"
+    )
+    .unwrap();
+    pub static ref RECORDER_DISK_FREE_BYTES: IntGauge = IntGauge::new(
+        "recorder_disk_free_bytes",
+        "bytes free in the recorder's upload local_dir"
+    )
+    .unwrap();
+    pub static ref RECORDER_DISK_FREE_INODES: IntGauge = IntGauge::new(
+        "recorder_disk_free_inodes",
+        "inodes free in the recorder's upload local_dir"
+    )
+    .unwrap();
+    pub static ref RECORDER_DISK_GUARD: IntGauge = IntGauge::new(
+        "recorder_disk_guard",
+        "1 while uploads are refused and segments dropped for lack of disk space"
+    )
+    .unwrap();
+    pub static ref RECORDER_UPLOADS_COALESCED: IntCounter = IntCounter::new(
+        "recorder_uploads_coalesced_total",
+        "queued uploads replaced by a newer version of the same object"
+    )
+    .unwrap();
+    pub static ref RECORDER_UPLOADS_DEAD_LETTERED: IntCounter = IntCounter::new(
+        "recorder_uploads_dead_lettered_total",
+        "uploads given up after max_retries"
+    )
+    .unwrap();
+    pub static ref RECORDER_UPLOADS_SUSPENDED: IntGauge = IntGauge::new(
+        "recorder_uploads_suspended",
+        "1 while the upload queue waits for liveman to answer again"
+    )
+    .unwrap();
+    pub static ref RECORDER_UPLOAD_SUSPENSIONS: IntCounter = IntCounter::new(
+        "recorder_upload_suspensions_total",
+        "times the upload queue was suspended after connection failures to liveman"
+    )
+    .unwrap();
+    pub static ref RECORDER_UPLOADS_VERIFIED: IntCounter = IntCounter::new(
+        "recorder_uploads_verified_total",
+        "uploaded objects found to match their local file"
+    )
+    .unwrap();
+    pub static ref RECORDER_UPLOAD_MISMATCHES: IntCounter = IntCounter::new(
+        "recorder_upload_mismatches_total",
+        "uploads storage accepted but stored with another size or checksum than the local file"
+    )
+    .unwrap();
+    pub static ref RECORDER_UPLOADS_IN_FLIGHT: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "recorder_uploads_in_flight",
+            "uploads running in the lane, small objects or large ones"
+        ),
+        &["lane"]
+    )
+    .unwrap();
+    pub static ref RECORDER_UPLOAD_LANE_CONCURRENCY: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "recorder_upload_lane_concurrency",
+            "most uploads the lane runs at once"
+        ),
+        &["lane"]
+    )
+    .unwrap();
+    pub static ref RECORDER_EVENT_SUBSCRIBERS: IntGauge = IntGauge::new(
+        "recorder_event_subscribers",
+        "connections following the recorder events stream"
+    )
+    .unwrap();
+    pub static ref RECORDER_EVENTS_DELIVERED: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "recorder_events_delivered_total",
+            "recorder events sent to the subscriber"
+        ),
+        &["subscriber"]
+    )
+    .unwrap();
+    pub static ref RECORDER_EVENTS_DROPPED: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "recorder_events_dropped_total",
+            "recorder events dropped for a subscriber too slow to keep up"
+        ),
+        &["subscriber"]
+    )
+    .unwrap();
+    pub static ref RECORDER_UPLOAD_LAG: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "recorder_upload_lag_seconds",
+            "time from a file's last write to its upload being stored and verified"
+        )
+        .buckets(vec![
+            1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0
+        ]),
+        &["stream"]
+    )
+    .unwrap();
+    pub static ref RECORDER_UPLOAD_LAG_WORST: IntGauge = IntGauge::new(
+        "recorder_upload_lag_worst_seconds",
+        "worst upload lag of the lag window, a file still queued counts with its age"
+    )
+    .unwrap();
+    pub static ref RECORDER_TRIGGERS: IntCounter = IntCounter::new(
+        "recorder_triggers_total",
+        "recording triggers received over HTTP or MQTT"
+    )
+    .unwrap();
+    pub static ref RECORDER_STARTS_REJECTED: IntCounter = IntCounter::new(
+        "recorder_starts_rejected_total",
+        "recording starts refused at max_concurrent_recordings"
+    )
+    .unwrap();
+    pub static ref RECORDINGS_PREEMPTED: IntCounter = IntCounter::new(
+        "recordings_preempted_total",
+        "recordings stopped to make room for one of higher priority"
+    )
+    .unwrap();
+    pub static ref RECORDINGS_STALLED: IntCounter = IntCounter::new(
+        "recordings_stalled_total",
+        "recordings that got no media for stall_timeout_seconds while published"
+    )
+    .unwrap();
+    pub static ref RECORDER_STALLED: IntGauge = IntGauge::new(
+        "recorder_recordings_stalled",
+        "recordings stalled right now"
+    )
+    .unwrap();
     pub static ref REGISTRY: Registry =
         Registry::new_custom(Some("live777".to_string()), None).unwrap();
     pub static ref ENCODER: TextEncoder = TextEncoder::new();
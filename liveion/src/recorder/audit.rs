@@ -0,0 +1,288 @@
+//! Append-only log of destructive operations on recordings and the index.
+//!
+//! Each trash, purge, delete, rename, restore and retention deletion appends one JSON
+//! line to `index.audit.json` next to the index, under the same write lock as the index
+//! and never compacted. Past `max_bytes` the file is rotated to `index.audit.1.json`,
+//! `index.audit.2.json` and so on, the oldest beyond `keep` is dropped. Each rotation
+//! starts a generation of its [`Journal`].
+
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use api::recorder::{AuditOperation, AuditOutcome, AuditRecord, DryRunResponse};
+use chrono::Utc;
+use tokio::sync::Mutex;
+
+use super::index::lock_file;
+use super::journal::{Journal, JournalChunk};
+use super::lock::LockOptions;
+
+/// Actor of the deletions the retention sweep and the trash purge make on their own
+pub const SYSTEM_RETENTION: &str = "system/retention";
+
+/// Actor of `live777 --restore-index`
+pub const SYSTEM_CLI: &str = "system/cli";
+
+/// What a `dry_run` request would affect, or why it would be refused
+pub enum DryRun {
+    Affected(DryRunResponse),
+    NotFound(String),
+    Conflict(String),
+}
+
+/// Audit file of the index at `index_path`, `index.audit.json` next to `index.json`
+pub fn audit_path(index_path: &Path) -> PathBuf {
+    rotated_path(index_path, None)
+}
+
+/// `index.audit.{n}.json` for the `n`th rotated file, `index.audit.json` for `None`
+fn rotated_path(index_path: &Path, n: Option<usize>) -> PathBuf {
+    let stem = index_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut name = format!("{stem}.audit");
+    if let Some(n) = n {
+        name.push_str(&format!(".{n}"));
+    }
+    if let Some(ext) = index_path.extension() {
+        name.push_str(&format!(".{}", ext.to_string_lossy()));
+    }
+    index_path.with_file_name(name)
+}
+
+/// A record of `operation` by `actor` on `keys` that succeeded
+pub fn record(operation: AuditOperation, actor: &str, keys: Vec<String>) -> AuditRecord {
+    AuditRecord {
+        ts: Utc::now().timestamp_micros(),
+        operation,
+        actor: actor.to_string(),
+        keys,
+        objects: 0,
+        bytes: 0,
+        outcome: AuditOutcome::Succeeded,
+        reason: None,
+    }
+}
+
+pub struct AuditLog {
+    index_path: PathBuf,
+    /// Size past which the file is rotated, 0 never rotates
+    max_bytes: u64,
+    /// Rotated files kept
+    keep: usize,
+    lock: LockOptions,
+    write_lock: Mutex<()>,
+    /// Generations of `index.audit.json`, one per rotation
+    journal: Arc<Journal>,
+}
+
+impl AuditLog {
+    pub fn new(index_path: PathBuf, max_bytes: u64, keep: usize) -> Self {
+        Self {
+            journal: Arc::new(Journal::new(audit_path(&index_path))),
+            index_path,
+            max_bytes,
+            keep,
+            lock: LockOptions::default(),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// Lock the file for appends like the index instead of with the defaults
+    pub fn with_lock_options(mut self, lock: LockOptions) -> Self {
+        self.lock = lock;
+        self
+    }
+
+    /// Append `record`, rotating first when the file has reached `max_bytes`
+    pub async fn append(&self, record: AuditRecord) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        let line = serde_json::to_string(&record)?;
+        let index_path = self.index_path.clone();
+        let (max_bytes, keep, lock) = (self.max_bytes, self.keep, self.lock);
+        let journal = self.journal.clone();
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let path = audit_path(&index_path);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let _lock = lock_file(&path, lock)?;
+            let size = std::fs::metadata(&path).map_or(0, |m| m.len());
+            if max_bytes > 0 && size >= max_bytes {
+                journal.replace(|| rotate(&index_path, keep))?;
+            }
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)?;
+            writeln!(file, "{}", line)?;
+            file.sync_data()?;
+            Ok(())
+        })
+        .await?
+    }
+
+    /// [`Self::append`], failures logged: the operation already happened and is not
+    /// undone for want of a trace
+    pub async fn log(&self, record: AuditRecord) {
+        if let Err(e) = self.append(record).await {
+            tracing::error!("[audit] appending to the audit log failed: {:#}", e);
+        }
+    }
+
+    /// Complete lines of `index.audit.json` from `offset`, up to `limit` bytes, see
+    /// [`super::journal`]. Rotated files are not read
+    pub async fn read_journal(&self, offset: u64, limit: u64) -> Result<JournalChunk> {
+        let journal = self.journal.clone();
+        Ok(tokio::task::spawn_blocking(move || journal.read(offset, limit)).await??)
+    }
+
+    /// Records after `since_ts` (UNIX microseconds), every record kept when `None`,
+    /// oldest first
+    pub async fn since(&self, since_ts: Option<i64>) -> Result<Vec<AuditRecord>> {
+        let index_path = self.index_path.clone();
+        let keep = self.keep;
+        tokio::task::spawn_blocking(move || -> Result<Vec<AuditRecord>> {
+            let mut records = Vec::new();
+            let files = (1..=keep)
+                .rev()
+                .map(Some)
+                .chain(std::iter::once(None))
+                .map(|n| rotated_path(&index_path, n));
+            for path in files {
+                let file = match std::fs::File::open(&path) {
+                    Ok(file) => file,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                for line in std::io::BufReader::new(file).lines() {
+                    let line = line?;
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    let record: AuditRecord = serde_json::from_str(line).with_context(|| {
+                        format!("Failed to parse audit line in {}", path.display())
+                    })?;
+                    if since_ts.is_none_or(|since| record.ts > since) {
+                        records.push(record);
+                    }
+                }
+            }
+            Ok(records)
+        })
+        .await?
+    }
+}
+
+/// Shift `index.audit.{n}.json` to `n + 1` and the live file to 1, dropping the file
+/// past `keep`. Callers hold the file lock.
+fn rotate(index_path: &Path, keep: usize) -> Result<()> {
+    if keep == 0 {
+        std::fs::remove_file(audit_path(index_path))?;
+        return Ok(());
+    }
+    match std::fs::remove_file(rotated_path(index_path, Some(keep))) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    for n in (1..keep).rev() {
+        let from = rotated_path(index_path, Some(n));
+        if from.exists() {
+            std::fs::rename(&from, rotated_path(index_path, Some(n + 1)))?;
+        }
+    }
+    std::fs::rename(audit_path(index_path), rotated_path(index_path, Some(1)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_paths() {
+        let index = Path::new("/data/recordings/index.json");
+        assert_eq!(
+            audit_path(index),
+            PathBuf::from("/data/recordings/index.audit.json")
+        );
+        assert_eq!(
+            rotated_path(index, Some(2)),
+            PathBuf::from("/data/recordings/index.audit.2.json")
+        );
+        assert_eq!(audit_path(Path::new("index")), PathBuf::from("index.audit"));
+    }
+
+    #[tokio::test]
+    async fn test_append_rotates_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let index_path = dir.path().join("index.json");
+        let at = |i: i64| AuditRecord {
+            ts: i,
+            ..record(AuditOperation::Trash, "*", vec![format!("cam/{i}")])
+        };
+        let line_len = serde_json::to_string(&at(0)).unwrap().len() as u64 + 1;
+        // Two lines per file, two rotated files
+        let log = AuditLog::new(index_path.clone(), line_len * 2, 2);
+        for i in 0..7 {
+            log.append(at(i)).await.unwrap();
+        }
+        assert!(rotated_path(&index_path, Some(2)).exists());
+        assert!(!rotated_path(&index_path, Some(3)).exists());
+
+        // The first two records were rotated out with the oldest file
+        let keys: Vec<String> = log
+            .since(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .flat_map(|r| r.keys)
+            .collect();
+        assert_eq!(keys, ["cam/2", "cam/3", "cam/4", "cam/5", "cam/6"]);
+
+        let since: Vec<i64> = log
+            .since(Some(4))
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.ts)
+            .collect();
+        assert_eq!(since, [5, 6]);
+    }
+
+    #[tokio::test]
+    async fn test_rotation_starts_a_journal_generation() {
+        let dir = tempfile::tempdir().unwrap();
+        let at = |i: i64| AuditRecord {
+            ts: i,
+            ..record(AuditOperation::Purge, "*", vec![format!("cam/{i}")])
+        };
+        let line_len = serde_json::to_string(&at(0)).unwrap().len() as u64 + 1;
+        let log = AuditLog::new(dir.path().join("index.json"), line_len * 2, 1);
+        log.append(at(0)).await.unwrap();
+        log.append(at(1)).await.unwrap();
+        let tailed = log.read_journal(0, 1 << 20).await.unwrap();
+        assert_eq!(tailed.next_offset(), line_len * 2);
+
+        // Rotated before the third record is appended
+        log.append(at(2)).await.unwrap();
+        let stale = log
+            .read_journal(tailed.next_offset(), 1 << 20)
+            .await
+            .unwrap();
+        assert_eq!(stale.generation, tailed.generation + 1);
+        assert!(stale.data.is_empty());
+        let restarted = log.read_journal(0, 1 << 20).await.unwrap();
+        let ts: Vec<i64> = std::str::from_utf8(&restarted.data)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<AuditRecord>(line).unwrap().ts)
+            .collect();
+        assert_eq!(ts, [2]);
+    }
+}
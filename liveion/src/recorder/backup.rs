@@ -0,0 +1,521 @@
+//! Copies of the index in storage, so losing the local file does not orphan recordings
+//! whose objects are still in the bucket.
+//!
+//! Backups live under `_index/{node}/{timestamp}.jsonl` in the format of a compacted
+//! index. Without any backup, [`rebuild`] recovers best-effort entries from the manifests.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use api::recorder::{
+    AuditOperation, DEFAULT_PRIORITY, DryRunResponse, RecordingIndexEntry, RecordingStatus,
+    RestoreIndexResponse,
+};
+use chrono::Utc;
+use opendal::Operator;
+use storage::FailoverOperator;
+use tokio::time::{self, MissedTickBehavior};
+
+use super::audit::DryRun;
+use super::index::RecordingsIndex;
+use super::tenancy::Tenancy;
+
+/// Storage prefix of index backups, next to the recordings but outside any stream
+pub const BACKUP_PREFIX: &str = "_index/";
+
+const MANIFEST_NAME: &str = "manifest.mpd";
+
+/// Outcome of [`IndexBackup::restore`]
+pub enum RestoreOutcome {
+    Restored(RestoreIndexResponse),
+    NotFound(String),
+    Conflict(String),
+}
+
+/// A backup read and checked against the local index, or why it can't be installed
+enum Loaded {
+    Backup(String, Vec<RecordingIndexEntry>),
+    NotFound(String),
+    Conflict(String),
+}
+
+pub struct IndexBackup {
+    index: Arc<RecordingsIndex>,
+    operator: FailoverOperator,
+    node: String,
+    keep: usize,
+    /// Entry count and last update of the last uploaded index
+    last: Mutex<Option<(usize, i64)>>,
+}
+
+impl IndexBackup {
+    pub fn new(
+        index: Arc<RecordingsIndex>,
+        operator: FailoverOperator,
+        node: String,
+        keep: usize,
+    ) -> Self {
+        Self {
+            index,
+            operator,
+            node,
+            keep: keep.max(1),
+            last: Mutex::new(None),
+        }
+    }
+
+    fn prefix(&self) -> String {
+        format!("{BACKUP_PREFIX}{}/", self.node)
+    }
+
+    pub async fn run(self: Arc<Self>, interval: Duration) {
+        let mut ticker = time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            match self.backup().await {
+                Ok(Some(key)) => tracing::info!("[backup] index uploaded to {}", key),
+                Ok(None) => tracing::debug!("[backup] index unchanged, skipped"),
+                Err(e) => tracing::warn!("[backup] index backup failed: {}", e),
+            }
+        }
+    }
+
+    /// Upload the index unless it is unchanged since the last upload, then drop the
+    /// backups beyond `keep`. Returns the key written.
+    pub async fn backup(&self) -> Result<Option<String>> {
+        let entries = self.index.snapshot().await?;
+        let fingerprint = (
+            entries.len(),
+            entries.iter().map(|e| e.updated_at).max().unwrap_or(0),
+        );
+        if *self.last.lock().unwrap() == Some(fingerprint) {
+            return Ok(None);
+        }
+
+        let mut body = String::new();
+        for entry in &entries {
+            body.push_str(&serde_json::to_string(entry)?);
+            body.push('\n');
+        }
+        // Microseconds keep keys unique and in chronological order
+        let key = format!("{}{}.jsonl", self.prefix(), Utc::now().timestamp_micros());
+        self.operator.current().write(&key, body).await?;
+        *self.last.lock().unwrap() = Some(fingerprint);
+
+        let keys = self.list().await?;
+        let stale = keys.len().saturating_sub(self.keep);
+        for old in &keys[..stale] {
+            if let Err(e) = self.operator.current().delete(old).await {
+                tracing::warn!("[backup] failed to delete old backup {}: {}", old, e);
+            }
+        }
+        Ok(Some(key))
+    }
+
+    /// Backup keys of this node, oldest first
+    pub async fn list(&self) -> Result<Vec<String>> {
+        let mut keys: Vec<String> = self
+            .operator
+            .current()
+            .list(&self.prefix())
+            .await?
+            .into_iter()
+            .filter(|e| !e.metadata().is_dir() && e.path().ends_with(".jsonl"))
+            .map(|e| e.path().to_string())
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Install the backup at `key`, the latest one of this node when `None`.
+    ///
+    /// A local index with changes newer than the backup is only replaced with `force`.
+    pub async fn restore(&self, key: Option<&str>, force: bool) -> Result<RestoreOutcome> {
+        let (key, entries) = match self.load(key, force).await? {
+            Loaded::Backup(key, entries) => (key, entries),
+            Loaded::NotFound(reason) => return Ok(RestoreOutcome::NotFound(reason)),
+            Loaded::Conflict(reason) => return Ok(RestoreOutcome::Conflict(reason)),
+        };
+        let count = entries.len();
+        let replaced = self.index.replace_all(entries).await?;
+        tracing::warn!(
+            "[backup] index restored from {} ({} entries, {} replaced)",
+            key,
+            count,
+            replaced
+        );
+        Ok(RestoreOutcome::Restored(RestoreIndexResponse {
+            key,
+            entries: count,
+            replaced,
+        }))
+    }
+
+    /// Local entries [`Self::restore`] would drop or roll back, without restoring
+    pub async fn preview(&self, key: Option<&str>, force: bool) -> Result<DryRun> {
+        let entries = match self.load(key, force).await? {
+            Loaded::Backup(_, entries) => entries,
+            Loaded::NotFound(reason) => return Ok(DryRun::NotFound(reason)),
+            Loaded::Conflict(reason) => return Ok(DryRun::Conflict(reason)),
+        };
+        let backup: HashMap<String, i64> =
+            entries.iter().map(|e| (e.key(), e.updated_at)).collect();
+        let keys = self
+            .index
+            .snapshot()
+            .await?
+            .into_iter()
+            .filter(|e| backup.get(&e.key()) != Some(&e.updated_at))
+            .map(|e| e.key())
+            .collect();
+        Ok(DryRun::Affected(DryRunResponse {
+            operation: AuditOperation::RestoreIndex,
+            keys,
+            objects: 0,
+            bytes: 0,
+        }))
+    }
+
+    /// Read the backup at `key`, the latest one when `None`, refusing to replace newer
+    /// local changes without `force`
+    async fn load(&self, key: Option<&str>, force: bool) -> Result<Loaded> {
+        let key = match key {
+            Some(key) if !key.starts_with(BACKUP_PREFIX) || key.contains("..") => {
+                return Ok(Loaded::NotFound(format!("{key} is not an index backup")));
+            }
+            Some(key) => key.to_string(),
+            None => match self.list().await?.pop() {
+                Some(key) => key,
+                None => {
+                    return Ok(Loaded::NotFound(format!(
+                        "no backups under {}",
+                        self.prefix()
+                    )));
+                }
+            },
+        };
+        let content = match self.operator.current().read(&key).await {
+            Ok(content) => content.to_vec(),
+            Err(e) if e.kind() == opendal::ErrorKind::NotFound => {
+                return Ok(Loaded::NotFound(format!("{key} does not exist")));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let entries = parse(&String::from_utf8_lossy(&content))
+            .with_context(|| format!("backup {key} is not a valid index"))?;
+
+        let backup_updated_at = entries.iter().map(|e| e.updated_at).max().unwrap_or(0);
+        let local_updated_at = self.index.last_updated_at().await;
+        if !force && local_updated_at > backup_updated_at {
+            return Ok(Loaded::Conflict(format!(
+                "local index has changes newer than {key}, restore with force to replace it"
+            )));
+        }
+        Ok(Loaded::Backup(key, entries))
+    }
+}
+
+/// Entries of a backup, every line must parse
+fn parse(content: &str) -> Result<Vec<RecordingIndexEntry>> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).with_context(|| format!("invalid entry on line {}", i + 1))
+        })
+        .collect()
+}
+
+/// Best-effort entries for the manifests under `namespace` (the storage root when
+/// `None`) that have no index entry, for when the index is lost without a backup.
+///
+/// Only the canonical `{stream}/{record_id}/manifest.mpd` layout is recognized, under
+/// `{tenant}/` with tenancy. Start and duration come from the record id and the
+/// manifest; notes, labels, retention classes and media info are lost.
+pub async fn rebuild(
+    index: &RecordingsIndex,
+    operator: &Operator,
+    namespace: Option<&str>,
+    tenancy: &Tenancy,
+    node_alias: Option<String>,
+) -> Result<usize> {
+    let prefix = namespace.map(|ns| format!("{ns}/")).unwrap_or_default();
+    let mut added = 0;
+    let listed = if tenancy.is_enabled() { "" } else { &prefix };
+    for object in operator.list_with(listed).recursive(true).await? {
+        let path = object.path();
+        let (tenant, rel) = if tenancy.is_enabled() {
+            match path.split_once('/') {
+                Some((tenant, rel)) => (Some(tenant), rel),
+                None => continue,
+            }
+        } else {
+            (None, path)
+        };
+        let Some((stream, record)) = rel.strip_prefix(&prefix).and_then(recording_of) else {
+            continue;
+        };
+        // Only where this node's tenancy would have put the stream
+        if tenancy.tenant_of(stream).ok().flatten().as_deref() != tenant {
+            continue;
+        }
+        if index.contains(stream, record).await {
+            continue;
+        }
+        let Ok(start_secs) = record.parse::<i64>() else {
+            continue;
+        };
+        let duration_ms = match operator.read(path).await {
+            Ok(mpd) => media_duration_ms(&String::from_utf8_lossy(&mpd.to_vec())),
+            Err(e) => {
+                tracing::warn!("[backup] failed to read {}: {}", path, e);
+                None
+            }
+        };
+        let start_ts = start_secs * 1_000_000;
+        let record_dir = path.trim_end_matches(MANIFEST_NAME).trim_end_matches('/');
+        index
+            .upsert(RecordingIndexEntry {
+                uuid: String::new(),
+                record: record.to_string(),
+                stream: stream.to_string(),
+                record_dir: record_dir.to_string(),
+                mpd_path: path.to_string(),
+                start_ts,
+                end_ts: duration_ms.map(|ms| start_ts + ms as i64 * 1_000),
+                duration_ms,
+                status: RecordingStatus::Completed,
+                node_alias: node_alias.clone(),
+                updated_at: Utc::now().timestamp_micros(),
+                note: None,
+                labels: Vec::new(),
+                continues: None,
+                media_info: Vec::new(),
+                retention_class: None,
+                trashed_at: None,
+                trashed_from: None,
+                repair_error: None,
+                source: None,
+                replicas: Vec::new(),
+                clock_skew_detected: false,
+                priority: DEFAULT_PRIORITY,
+                tenant: tenant.map(str::to_string),
+                size: None,
+                captions: Vec::new(),
+                trigger: None,
+                imported: false,
+                seq: 0,
+            })
+            .await?;
+        added += 1;
+    }
+    Ok(added)
+}
+
+/// `(stream, record)` of `{stream}/{record_id}/manifest.mpd`, relative to the namespace
+fn recording_of(rel: &str) -> Option<(&str, &str)> {
+    let mut parts = rel.split('/');
+    let (stream, record, name) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some()
+        || name != MANIFEST_NAME
+        || stream.starts_with('_')
+        || record.len() != 10
+        || !record.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    Some((stream, record))
+}
+
+/// `mediaPresentationDuration` in the `PT{seconds}S` form the segmenter writes
+fn media_duration_ms(mpd: &str) -> Option<i32> {
+    let attr = "mediaPresentationDuration=\"";
+    let value = &mpd[mpd.find(attr)? + attr.len()..];
+    let value = &value[..value.find('"')?];
+    let secs: f64 = value.strip_prefix("PT")?.strip_suffix('S')?.parse().ok()?;
+    Some((secs * 1000.0).round() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::StorageConfig;
+
+    fn entry(record: &str, updated_at: i64) -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            uuid: String::new(),
+            record: record.to_string(),
+            stream: "cam".to_string(),
+            record_dir: format!("edge-1/cam/{record}"),
+            mpd_path: format!("edge-1/cam/{record}/manifest.mpd"),
+            start_ts: 0,
+            end_ts: Some(1_000_000),
+            duration_ms: Some(1_000),
+            status: RecordingStatus::Completed,
+            node_alias: Some("edge-1".to_string()),
+            updated_at,
+            note: None,
+            labels: Vec::new(),
+            continues: None,
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant: None,
+            size: None,
+            captions: Vec::new(),
+            trigger: None,
+            imported: false,
+            seq: 0,
+        }
+    }
+
+    async fn setup(dir: &std::path::Path) -> (Arc<RecordingsIndex>, FailoverOperator) {
+        let storage = StorageConfig::Fs {
+            root: dir.join("storage").to_string_lossy().into_owned(),
+        };
+        let operator = storage::create_failover_operator(&storage).unwrap();
+        let index = Arc::new(RecordingsIndex::load(dir.join("index.json")).await.unwrap());
+        (index, operator)
+    }
+
+    #[tokio::test]
+    async fn test_backup_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let (index, operator) = setup(dir.path()).await;
+        let backup = IndexBackup::new(index.clone(), operator, "edge-1".to_string(), 2);
+
+        index.upsert(entry("1700000001", 1)).await.unwrap();
+        let first = backup.backup().await.unwrap().unwrap();
+        assert!(first.starts_with("_index/edge-1/"));
+        // Unchanged index is not uploaded again
+        assert!(backup.backup().await.unwrap().is_none());
+
+        index.upsert(entry("1700000002", 2)).await.unwrap();
+        backup.backup().await.unwrap().unwrap();
+        index.upsert(entry("1700000003", 3)).await.unwrap();
+        let last = backup.backup().await.unwrap().unwrap();
+
+        let keys = backup.list().await.unwrap();
+        assert_eq!(keys.len(), 2);
+        assert!(!keys.contains(&first));
+        assert_eq!(keys.last(), Some(&last));
+    }
+
+    #[tokio::test]
+    async fn test_restore_over_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let (index, operator) = setup(dir.path()).await;
+        let backup = IndexBackup::new(index.clone(), operator.clone(), "edge-1".to_string(), 5);
+
+        assert!(matches!(
+            backup.restore(None, false).await.unwrap(),
+            RestoreOutcome::NotFound(_)
+        ));
+        index.upsert(entry("1700000001", 1)).await.unwrap();
+        index.upsert(entry("1700000002", 2)).await.unwrap();
+        let key = backup.backup().await.unwrap().unwrap();
+
+        // The local index moved on after the backup
+        index.upsert(entry("1700000003", 3)).await.unwrap();
+        assert!(matches!(
+            backup.restore(None, false).await.unwrap(),
+            RestoreOutcome::Conflict(_)
+        ));
+        assert_eq!(index.snapshot().await.unwrap().len(), 3);
+
+        let DryRun::Affected(preview) = backup.preview(Some(&key), true).await.unwrap() else {
+            panic!("backup not found");
+        };
+        assert_eq!(preview.keys, ["cam/1700000003"]);
+        assert_eq!(index.snapshot().await.unwrap().len(), 3);
+
+        let RestoreOutcome::Restored(resp) = backup.restore(Some(&key), true).await.unwrap() else {
+            panic!("backup not restored");
+        };
+        assert_eq!(resp.key, key);
+        assert_eq!(resp.entries, 2);
+        assert_eq!(resp.replaced, 3);
+        assert!(!index.contains("cam", "1700000003").await);
+        // The restored index is what a restart loads
+        let reloaded = RecordingsIndex::load(dir.path().join("index.json"))
+            .await
+            .unwrap();
+        assert_eq!(reloaded.snapshot().await.unwrap().len(), 2);
+
+        // Restoring the same backup again is not a conflict
+        assert!(matches!(
+            backup.restore(None, false).await.unwrap(),
+            RestoreOutcome::Restored(_)
+        ));
+
+        let current = operator.current();
+        current
+            .write("_index/edge-1/9999999999999999.jsonl", "not json\n")
+            .await
+            .unwrap();
+        assert!(backup.restore(None, true).await.is_err());
+        assert_eq!(index.snapshot().await.unwrap().len(), 2);
+        assert!(matches!(
+            backup
+                .restore(Some("edge-1/cam/1700000001/manifest.mpd"), true)
+                .await
+                .unwrap(),
+            RestoreOutcome::NotFound(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_from_manifests() {
+        let dir = tempfile::tempdir().unwrap();
+        let (index, operator) = setup(dir.path()).await;
+        let current = operator.current();
+        let mpd = "<MPD mediaPresentationDuration=\"PT12.500S\"></MPD>";
+        current
+            .write("edge-1/cam/1700000001/manifest.mpd", mpd)
+            .await
+            .unwrap();
+        current
+            .write("edge-1/cam/1700000002/manifest.mpd", mpd)
+            .await
+            .unwrap();
+        current
+            .write("edge-1/cam/custom/manifest.mpd", mpd)
+            .await
+            .unwrap();
+        current
+            .write("edge-2/cam/1700000003/manifest.mpd", mpd)
+            .await
+            .unwrap();
+        index.upsert(entry("1700000002", 1)).await.unwrap();
+
+        let added = rebuild(
+            &index,
+            &current,
+            Some("edge-1"),
+            &Tenancy::default(),
+            Some("edge-1".to_string()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(added, 1);
+        let rebuilt = index.get("cam", "1700000001").await.unwrap();
+        assert_eq!(rebuilt.record_dir, "edge-1/cam/1700000001");
+        assert_eq!(rebuilt.start_ts, 1_700_000_001_000_000);
+        assert_eq!(rebuilt.duration_ms, Some(12_500));
+        assert_eq!(rebuilt.end_ts, Some(1_700_000_013_500_000));
+        // The existing entry is left alone
+        assert_eq!(index.get("cam", "1700000002").await.unwrap().updated_at, 1);
+        assert!(!index.contains("cam", "1700000003").await);
+    }
+}
@@ -0,0 +1,739 @@
+//! `live777 bench-recorder`: what a host sustains recording, before it is sized.
+//!
+//! Synthetic streams run through the real segmenter, index and uploader for a while
+//! and the rates they achieved are reported. Uploads go in `proxy` mode to a sink served
+//! in process, which writes them to a directory, keeps them in memory or drops them, so
+//! no storage or liveman is needed. The media is H.264 built from a seeded generator:
+//! runs with the same options write the same bytes, whatever version runs them.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use api::recorder::{DEFAULT_PRIORITY, RecordingSize, RecordingStatus};
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use chrono::Utc;
+use opendal::Operator;
+use serde::Serialize;
+use tokio_stream::StreamExt;
+
+use super::index::{self, RecordingIndexEntry, RecordingsIndex};
+use super::segmenter::{PENDING_WRITES, Segmenter};
+use super::uploader::UploadManager;
+use crate::config::{UploadConfig, UploadTransport};
+
+const TIMESCALE: u32 = 90_000;
+/// Baseline profile 3.0, as the segmenter tests use
+const SPS: &[u8] = &[0x67, 0x42, 0xE0, 0x1E, 0x8D, 0x68, 0x50];
+const PPS: &[u8] = &[0x68, 0xCE, 0x3C, 0x80];
+/// Distinct delta frames each stream cycles through, generated once up front so the
+/// generator costs nothing while the bench runs
+const DELTA_VARIANTS: usize = 16;
+/// How long uploads may take to drain after the streams stopped
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where uploaded objects end up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum BenchSink {
+    /// Files under `<dir>/storage`
+    Fs,
+    /// Kept in memory until the bench ends
+    Memory,
+    /// Counted and dropped
+    Null,
+}
+
+#[derive(Debug, Clone, Serialize, clap::Args)]
+pub struct BenchOptions {
+    /// Synthetic streams recorded at once
+    #[arg(long, default_value_t = 4)]
+    pub streams: usize,
+    /// Video bitrate of each stream
+    #[arg(long, default_value_t = 4_000)]
+    pub bitrate_kbps: u64,
+    #[arg(long, default_value_t = 30)]
+    pub fps: u32,
+    /// Interval between keyframes, segments end at the first keyframe past 10 seconds
+    #[arg(long, default_value_t = 2_000)]
+    pub keyframe_interval_ms: u64,
+    /// How long the streams are recorded
+    #[arg(long, default_value_t = 60)]
+    pub duration_secs: u64,
+    #[arg(long, value_enum, default_value_t = BenchSink::Null)]
+    pub sink: BenchSink,
+    /// Concurrent uploads, as `upload.concurrency`
+    #[arg(long, default_value_t = 4)]
+    pub upload_concurrency: usize,
+    /// Seed of the synthetic media, runs with the same seed write the same bytes
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Working directory for the spool, staging, index and `fs` sink. A temporary one
+    /// removed afterwards without it
+    #[arg(long)]
+    pub dir: Option<PathBuf>,
+    /// Print the report as JSON instead of a table
+    #[arg(long)]
+    #[serde(skip)]
+    pub json: bool,
+}
+
+/// What a bench run achieved
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    pub version: String,
+    pub options: BenchOptions,
+    pub elapsed_ms: u64,
+    pub frames: u64,
+    /// Latest a frame was handed to its segmenter after it was due, the host fell behind
+    /// real time when this grows towards the frame interval
+    pub max_frame_lag_ms: f64,
+    pub segments: u64,
+    pub segments_per_second: f64,
+    pub segment_bytes: u64,
+    pub index_appends: u64,
+    pub index_append_p50_ms: f64,
+    pub index_append_p99_ms: f64,
+    pub index_append_max_ms: f64,
+    /// Bytes the sink received, manifests and init segments included
+    pub uploaded_bytes: u64,
+    pub uploaded_objects: u64,
+    pub upload_bytes_per_second: f64,
+    /// Uploads still queued once the drain timed out
+    pub upload_backlog: usize,
+    /// Of one core, averaged over the run. `None` where it can't be read
+    pub cpu_percent: Option<f64>,
+    /// Of the spool, staging and index, sampled each second
+    pub disk_peak_bytes: u64,
+    pub disk_end_bytes: u64,
+}
+
+impl BenchReport {
+    pub fn table(&self) -> String {
+        let o = &self.options;
+        let rows = [
+            ("version", self.version.clone()),
+            (
+                "streams",
+                format!(
+                    "{} x {} kbps, {} fps, keyframe every {} ms, seed {}",
+                    o.streams, o.bitrate_kbps, o.fps, o.keyframe_interval_ms, o.seed
+                ),
+            ),
+            ("sink", format!("{:?}", o.sink).to_lowercase()),
+            ("elapsed", format!("{:.1} s", self.elapsed_ms as f64 / 1e3)),
+            ("frames", self.frames.to_string()),
+            ("max frame lag", format!("{:.1} ms", self.max_frame_lag_ms)),
+            (
+                "segments",
+                format!(
+                    "{} ({:.2}/s, {})",
+                    self.segments,
+                    self.segments_per_second,
+                    human_bytes(self.segment_bytes)
+                ),
+            ),
+            (
+                "index appends",
+                format!(
+                    "{} (p50 {:.2} ms, p99 {:.2} ms, max {:.2} ms)",
+                    self.index_appends,
+                    self.index_append_p50_ms,
+                    self.index_append_p99_ms,
+                    self.index_append_max_ms
+                ),
+            ),
+            (
+                "uploaded",
+                format!(
+                    "{} objects, {} ({}/s)",
+                    self.uploaded_objects,
+                    human_bytes(self.uploaded_bytes),
+                    human_bytes(self.upload_bytes_per_second as u64)
+                ),
+            ),
+            ("upload backlog", self.upload_backlog.to_string()),
+            (
+                "cpu",
+                self.cpu_percent
+                    .map_or("n/a".to_string(), |cpu| format!("{cpu:.1} %")),
+            ),
+            (
+                "disk",
+                format!(
+                    "peak {}, end {}",
+                    human_bytes(self.disk_peak_bytes),
+                    human_bytes(self.disk_end_bytes)
+                ),
+            ),
+        ];
+        let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        rows.iter()
+            .map(|(name, value)| format!("{name:<width$}  {value}\n"))
+            .collect()
+    }
+}
+
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// H.264 frames of one synthetic stream
+struct SyntheticSource {
+    keyframe: Bytes,
+    deltas: Vec<Bytes>,
+    gop: u64,
+}
+
+impl SyntheticSource {
+    /// Frames near `bitrate_kbps`, keyframes four times the size of delta frames
+    fn new(opts: &BenchOptions, stream: usize) -> Self {
+        let fps = opts.fps.max(1) as u64;
+        let gop = (opts.keyframe_interval_ms * fps / 1_000).max(1);
+        let per_frame = opts.bitrate_kbps * 1_000 / 8 / fps;
+        let delta_len = (per_frame * gop / (gop + 3)).max(16) as usize;
+        let mut rng =
+            XorShift::new(opts.seed ^ (stream as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+        let mut keyframe = Vec::with_capacity(delta_len * 4 + 32);
+        for nal in [SPS, PPS] {
+            keyframe.extend_from_slice(&[0, 0, 0, 1]);
+            keyframe.extend_from_slice(nal);
+        }
+        keyframe.extend_from_slice(&[0, 0, 0, 1, 0x65, 0x88, 0x84]);
+        rng.fill(&mut keyframe, delta_len * 4);
+        let deltas = (0..DELTA_VARIANTS)
+            .map(|_| {
+                let mut frame = Vec::with_capacity(delta_len + 8);
+                frame.extend_from_slice(&[0, 0, 0, 1, 0x41, 0x9A, 0x02]);
+                rng.fill(&mut frame, delta_len);
+                Bytes::from(frame)
+            })
+            .collect();
+        Self {
+            keyframe: Bytes::from(keyframe),
+            deltas,
+            gop,
+        }
+    }
+
+    fn frame(&self, n: u64) -> Bytes {
+        match n % self.gop {
+            0 => self.keyframe.clone(),
+            i => self.deltas[(n / self.gop + i) as usize % DELTA_VARIANTS].clone(),
+        }
+    }
+}
+
+/// Seeded xorshift64*, the media must not depend on a generator's version
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Append `len` bytes, none of them zero so no start code shows up in a payload
+    fn fill(&mut self, out: &mut Vec<u8>, len: usize) {
+        out.extend((0..len).map(|_| (self.next() % 255 + 1) as u8));
+    }
+}
+
+/// Stands in for liveman's `/api/storage/objects/{path}`
+struct Sink {
+    operator: Option<Operator>,
+    sizes: Mutex<HashMap<String, u64>>,
+    bytes: AtomicU64,
+    objects: AtomicU64,
+}
+
+async fn sink_put(
+    State(sink): State<Arc<Sink>>,
+    axum::extract::Path(key): axum::extract::Path<String>,
+    body: Body,
+) -> Response {
+    let mut stream = body.into_data_stream();
+    let mut writer = match &sink.operator {
+        Some(operator) => match operator.writer(&key).await {
+            Ok(writer) => Some(writer),
+            Err(e) => return (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+        },
+        None => None,
+    };
+    let mut len = 0u64;
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        };
+        len += chunk.len() as u64;
+        if let Some(writer) = writer.as_mut()
+            && let Err(e) = writer.write(chunk).await
+        {
+            return (StatusCode::BAD_GATEWAY, e.to_string()).into_response();
+        }
+    }
+    if let Some(mut writer) = writer
+        && let Err(e) = writer.close().await
+    {
+        return (StatusCode::BAD_GATEWAY, e.to_string()).into_response();
+    }
+    sink.bytes.fetch_add(len, Ordering::Relaxed);
+    sink.objects.fetch_add(1, Ordering::Relaxed);
+    sink.sizes.lock().unwrap().insert(key, len);
+    StatusCode::OK.into_response()
+}
+
+async fn sink_head(
+    State(sink): State<Arc<Sink>>,
+    axum::extract::Path(key): axum::extract::Path<String>,
+) -> Response {
+    match sink.sizes.lock().unwrap().get(&key) {
+        Some(len) => {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_LENGTH, (*len).into());
+            (StatusCode::OK, headers).into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Serve `sink` on a free local port, returns its base URL
+async fn serve_sink(sink: Arc<Sink>) -> Result<String> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let base = format!("http://{}", listener.local_addr()?);
+    let app = axum::Router::new()
+        .route("/api/storage/ping", axum::routing::get(|| async { "pong" }))
+        .route(
+            "/api/storage/objects/{*path}",
+            axum::routing::put(sink_put).head(sink_head),
+        )
+        .with_state(sink);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("[bench] sink stopped: {}", e);
+        }
+    });
+    Ok(base)
+}
+
+/// CPU time of this process so far
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+    // SAFETY: `usage` is written by `getrusage` before it is read
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+    let micros = |t: libc::timeval| t.tv_sec as u64 * 1_000_000 + t.tv_usec as u64;
+    Some(Duration::from_micros(
+        micros(usage.ru_utime) + micros(usage.ru_stime),
+    ))
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
+
+/// Bytes the recorder keeps under `dir`: spool, staging and index, the `fs` sink aside
+fn local_usage(dir: &Path) -> u64 {
+    disk_usage(dir).saturating_sub(disk_usage(&dir.join("storage")))
+}
+
+/// Bytes of the files under `dir`
+fn disk_usage(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => disk_usage(&entry.path()),
+            Ok(_) => entry.metadata().map(|m| m.len()).unwrap_or(0),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+pub(super) fn bench_entry(stream: &str, record_dir: &str, start_ts: i64) -> RecordingIndexEntry {
+    RecordingIndexEntry {
+        uuid: index::new_recording_uuid(),
+        record: start_ts.to_string(),
+        stream: stream.to_string(),
+        record_dir: record_dir.to_string(),
+        mpd_path: format!("{record_dir}/manifest.mpd"),
+        start_ts,
+        end_ts: None,
+        duration_ms: None,
+        status: RecordingStatus::Active,
+        node_alias: None,
+        updated_at: Utc::now().timestamp_micros(),
+        note: None,
+        labels: Vec::new(),
+        continues: None,
+        media_info: Vec::new(),
+        retention_class: None,
+        trashed_at: None,
+        trashed_from: None,
+        repair_error: None,
+        source: None,
+        replicas: Vec::new(),
+        clock_skew_detected: false,
+        priority: DEFAULT_PRIORITY,
+        tenant: None,
+        size: None,
+        captions: Vec::new(),
+        trigger: None,
+        imported: false,
+        seq: 0,
+    }
+}
+
+/// Upsert `entry` into `index`, recording how long it took
+async fn timed_upsert(
+    index: &RecordingsIndex,
+    entry: RecordingIndexEntry,
+    appends: &Mutex<Vec<Duration>>,
+) -> Result<()> {
+    let started = Instant::now();
+    index.upsert(entry).await?;
+    appends.lock().unwrap().push(started.elapsed());
+    Ok(())
+}
+
+/// What one stream got through
+struct StreamRun {
+    frames: u64,
+    max_lag: Duration,
+    segments: u64,
+    bytes: u64,
+}
+
+/// Record stream `n` for `opts.duration_secs`, frames paced in real time
+async fn run_stream(
+    opts: &BenchOptions,
+    n: usize,
+    uploader: Arc<UploadManager>,
+    index: Arc<RecordingsIndex>,
+    appends: Arc<Mutex<Vec<Duration>>>,
+) -> Result<StreamRun> {
+    let stream = format!("bench-{n}");
+    let start_ts = Utc::now().timestamp_micros();
+    let record_dir = storage::record_dir(None, &stream, start_ts / 1_000_000);
+    // Nothing is written through it, uploads carry every object
+    let operator = Operator::new(opendal::services::Memory::default())?.finish();
+    let mut segmenter = Segmenter::new(
+        operator.into(),
+        stream.clone(),
+        record_dir.clone(),
+        Some(uploader.clone()),
+        Some(uploader.local_dir()),
+    )
+    .await?;
+    let segments = segmenter.segments_written();
+    let bytes = segmenter.bytes_written();
+
+    let mut entry = bench_entry(&stream, &record_dir, start_ts);
+    timed_upsert(&index, entry.clone(), &appends).await?;
+
+    let source = SyntheticSource::new(opts, n);
+    let fps = opts.fps.max(1);
+    let interval = Duration::from_secs(1) / fps;
+    let total = opts.duration_secs * fps as u64;
+    let started = tokio::time::Instant::now();
+    let mut max_lag = Duration::ZERO;
+    let mut indexed = 0;
+    for frame in 0..total {
+        let due = started + interval * frame as u32;
+        tokio::time::sleep_until(due).await;
+        max_lag = max_lag.max(due.elapsed());
+        segmenter
+            .push_h264(source.frame(frame), TIMESCALE / fps)
+            .await?;
+        // One append per segment, as a recording's size is kept current
+        let written = segments.load(Ordering::Relaxed);
+        if written > indexed {
+            indexed = written;
+            entry.size = Some(RecordingSize {
+                segments: written,
+                bytes: bytes.load(Ordering::Relaxed),
+            });
+            entry.updated_at = Utc::now().timestamp_micros();
+            timed_upsert(&index, entry.clone(), &appends).await?;
+        }
+    }
+    segmenter.flush().await?;
+
+    let segments = segments.load(Ordering::Relaxed);
+    let bytes = bytes.load(Ordering::Relaxed);
+    entry.status = RecordingStatus::Completed;
+    entry.end_ts = Some(Utc::now().timestamp_micros());
+    entry.duration_ms = Some(started.elapsed().as_millis() as u64);
+    entry.size = Some(RecordingSize { segments, bytes });
+    entry.updated_at = Utc::now().timestamp_micros();
+    timed_upsert(&index, entry, &appends).await?;
+    Ok(StreamRun {
+        frames: total,
+        max_lag,
+        segments,
+        bytes,
+    })
+}
+
+fn percentile_ms(sorted: &[Duration], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1].as_secs_f64() * 1e3
+}
+
+/// Run the bench in `dir`
+async fn run_in(opts: &BenchOptions, dir: &Path) -> Result<BenchReport> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let operator = match opts.sink {
+        BenchSink::Fs => Some(
+            Operator::new(
+                opendal::services::Fs::default().root(&dir.join("storage").display().to_string()),
+            )?
+            .finish(),
+        ),
+        BenchSink::Memory => Some(Operator::new(opendal::services::Memory::default())?.finish()),
+        BenchSink::Null => None,
+    };
+    let sink = Arc::new(Sink {
+        operator,
+        sizes: Mutex::new(HashMap::new()),
+        bytes: AtomicU64::new(0),
+        objects: AtomicU64::new(0),
+    });
+    let base = serve_sink(sink.clone()).await?;
+
+    let path = |name: &str| dir.join(name).display().to_string();
+    let uploader = Arc::new(
+        UploadManager::load(UploadConfig {
+            enabled: true,
+            liveman_url: base,
+            mode: UploadTransport::Proxy,
+            queue_path: path("upload_queue.jsonl"),
+            local_dir: path("spool"),
+            staging_dir: path("staging"),
+            interval_ms: 500,
+            concurrency: opts.upload_concurrency.max(1),
+            ..Default::default()
+        })
+        .await?,
+    );
+    let upload_loop = tokio::spawn(uploader.clone().run());
+    let index = Arc::new(RecordingsIndex::load(dir.join("index.jsonl")).await?);
+    let compactor = tokio::spawn(index.clone().run_compactor());
+    let appends = Arc::new(Mutex::new(Vec::new()));
+
+    let disk_peak = Arc::new(AtomicU64::new(0));
+    let sampler = {
+        let dir = dir.to_path_buf();
+        let disk_peak = disk_peak.clone();
+        tokio::spawn(async move {
+            loop {
+                let dir = dir.clone();
+                let used = tokio::task::spawn_blocking(move || local_usage(&dir))
+                    .await
+                    .unwrap_or(0);
+                disk_peak.fetch_max(used, Ordering::Relaxed);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        })
+    };
+
+    let cpu_start = cpu_time();
+    let started = Instant::now();
+    let runs = (0..opts.streams.max(1)).map(|n| {
+        let opts = opts.clone();
+        let (uploader, index, appends) = (uploader.clone(), index.clone(), appends.clone());
+        tokio::spawn(async move { run_stream(&opts, n, uploader, index, appends).await })
+    });
+    let mut streams = Vec::new();
+    for run in runs.collect::<Vec<_>>() {
+        streams.push(run.await??);
+    }
+
+    // Uploads still queued count as backlog, not as throughput
+    PENDING_WRITES.wait_idle().await;
+    let drain_until = Instant::now() + DRAIN_TIMEOUT;
+    while !uploader.queued().await.is_empty() && Instant::now() < drain_until {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let elapsed = started.elapsed();
+    let cpu_percent = cpu_start
+        .zip(cpu_time())
+        .map(|(start, end)| (end - start).as_secs_f64() / elapsed.as_secs_f64() * 100.0);
+    let upload_backlog = uploader.queued().await.len();
+
+    upload_loop.abort();
+    sampler.abort();
+    index.close().await;
+    compactor.abort();
+    let disk_end = local_usage(dir);
+    disk_peak.fetch_max(disk_end, Ordering::Relaxed);
+
+    let mut appends = std::mem::take(&mut *appends.lock().unwrap());
+    appends.sort();
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    let segments = streams.iter().map(|s| s.segments).sum::<u64>();
+    let uploaded_bytes = sink.bytes.load(Ordering::Relaxed);
+    Ok(BenchReport {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        options: opts.clone(),
+        elapsed_ms: elapsed.as_millis() as u64,
+        frames: streams.iter().map(|s| s.frames).sum(),
+        max_frame_lag_ms: streams
+            .iter()
+            .map(|s| s.max_lag)
+            .max()
+            .unwrap_or_default()
+            .as_secs_f64()
+            * 1e3,
+        segments,
+        segments_per_second: segments as f64 / seconds,
+        segment_bytes: streams.iter().map(|s| s.bytes).sum(),
+        index_appends: appends.len() as u64,
+        index_append_p50_ms: percentile_ms(&appends, 0.5),
+        index_append_p99_ms: percentile_ms(&appends, 0.99),
+        index_append_max_ms: percentile_ms(&appends, 1.0),
+        uploaded_bytes,
+        uploaded_objects: sink.objects.load(Ordering::Relaxed),
+        upload_bytes_per_second: uploaded_bytes as f64 / seconds,
+        upload_backlog,
+        cpu_percent,
+        disk_peak_bytes: disk_peak.load(Ordering::Relaxed),
+        disk_end_bytes: disk_end,
+    })
+}
+
+/// Run the bench as `opts` ask. A temporary working directory is removed afterwards,
+/// one given with `--dir` is kept for inspection
+pub async fn bench_recorder(opts: &BenchOptions) -> Result<BenchReport> {
+    match &opts.dir {
+        Some(dir) => run_in(opts, dir).await,
+        None => {
+            let dir = std::env::temp_dir().join(format!("live777-bench-{}", std::process::id()));
+            let report = run_in(opts, &dir).await;
+            if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+                tracing::warn!("[bench] failed to remove {}: {}", dir.display(), e);
+            }
+            report
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(dir: &Path) -> BenchOptions {
+        BenchOptions {
+            streams: 2,
+            bitrate_kbps: 800,
+            fps: 30,
+            keyframe_interval_ms: 1_000,
+            duration_secs: 11,
+            sink: BenchSink::Memory,
+            upload_concurrency: 2,
+            seed: 7,
+            dir: Some(dir.to_path_buf()),
+            json: false,
+        }
+    }
+
+    #[test]
+    fn test_source_is_deterministic() {
+        let opts = options(Path::new("."));
+        let frames = |opts: &BenchOptions, stream| {
+            let source = SyntheticSource::new(opts, stream);
+            (0..90).map(|n| source.frame(n)).collect::<Vec<_>>()
+        };
+        let first = frames(&opts, 0);
+        assert_eq!(first, frames(&opts, 0));
+        // Streams and seeds differ from each other
+        assert_ne!(first, frames(&opts, 1));
+        assert_ne!(
+            first,
+            frames(
+                &BenchOptions {
+                    seed: 8,
+                    ..opts.clone()
+                },
+                0
+            )
+        );
+
+        // A keyframe every second, 800 kbps on average
+        let source = SyntheticSource::new(&opts, 0);
+        assert_eq!(source.gop, 30);
+        assert_eq!(first[0], first[30]);
+        assert_eq!(&first[0][4..5], &[0x67]);
+        assert_eq!(&first[1][4..5], &[0x41]);
+        let bytes: usize = first[..30].iter().map(Bytes::len).sum();
+        assert!((95_000..105_000).contains(&bytes), "{bytes}");
+        // No start code within a payload
+        assert!(!first[1][4..].windows(3).any(|w| w == [0, 0, 1]));
+    }
+
+    #[tokio::test]
+    async fn test_bench_runs_through_the_pipeline() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = options(dir.path());
+        let report = bench_recorder(&opts).await.unwrap();
+
+        assert_eq!(report.frames, 2 * 11 * 30);
+        // One segment past 10 seconds and the one flushed, per stream
+        assert_eq!(report.segments, 4);
+        // The start, one per segment and the end, per stream
+        assert_eq!(report.index_appends, 2 * (1 + 1 + 1));
+        assert!(report.index_append_p99_ms <= report.index_append_max_ms);
+        assert_eq!(report.upload_backlog, 0);
+        assert!(report.uploaded_bytes > report.segment_bytes);
+        assert!(report.disk_peak_bytes > 0);
+        assert!(report.table().contains("index appends"));
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["options"]["sink"], "memory");
+
+        // The index holds both recordings, completed
+        let index = RecordingsIndex::load(dir.path().join("index.jsonl"))
+            .await
+            .unwrap();
+        for n in 0..2 {
+            let entries = index.entries_of(&format!("bench-{n}")).await;
+            assert_eq!(entries.len(), 1);
+            assert!(matches!(entries[0].status, RecordingStatus::Completed));
+            assert_eq!(entries[0].size.as_ref().unwrap().segments, 2);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! What `GET /api/recorder/capabilities` reports, from compile-time features and the
+//! recorder config.
+
+use api::recorder::{RecorderCapabilities, UploadMode, capability};
+
+use crate::config::{RecorderConfig, UploadTransport};
+
+/// Format of the index, a log of JSON lines at `index_path`
+const INDEX_FORMAT: &str = "jsonl";
+
+pub fn capabilities(cfg: &RecorderConfig) -> RecorderCapabilities {
+    let mut features = vec![
+        capability::RECORD,
+        capability::LABELS,
+        capability::CAPTIONS,
+        capability::TRASH,
+        capability::REPAIR,
+        capability::RENAME_STREAM,
+        capability::VERIFY,
+        capability::RECONCILE,
+        capability::INDEX_RESTORE,
+        capability::IMPORT,
+        capability::TRIGGERS,
+    ];
+    if cfg!(feature = "trigger-mqtt") && cfg.triggers.mqtt.is_some() {
+        features.push(capability::TRIGGER_MQTT);
+    }
+    if cfg.upload.enabled
+        && cfg.upload.mode == UploadTransport::Presign
+        && cfg.upload.multipart_threshold_bytes > 0
+    {
+        features.push(capability::MULTIPART_UPLOAD);
+    }
+    if cfg.push.enabled {
+        features.push(capability::PUSH_SYNC);
+    }
+    if cfg.lease.enabled {
+        features.push(capability::LEASE);
+    }
+    RecorderCapabilities {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        features: features
+            .into_iter()
+            .map(|feature| (feature.to_string(), 1))
+            .collect(),
+        upload_mode: match (cfg.upload.enabled, cfg.upload.mode) {
+            (false, _) => UploadMode::Direct,
+            (true, UploadTransport::Presign) => UploadMode::Presign,
+            (true, UploadTransport::Proxy) => UploadMode::Proxy,
+        },
+        index: INDEX_FORMAT.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clients of mixed-version clusters parse this, renaming a feature breaks them
+    #[test]
+    fn test_capabilities_snapshot() {
+        let mut cfg = RecorderConfig::default();
+        cfg.upload.enabled = true;
+        cfg.upload.multipart_threshold_bytes = 64 << 20;
+        let mut caps = capabilities(&cfg);
+        caps.version = "0.0.0".to_string();
+        assert_eq!(
+            serde_json::to_string_pretty(&caps).unwrap(),
+            r#"{
+  "version": "0.0.0",
+  "features": {
+    "captions": 1,
+    "import": 1,
+    "index_restore": 1,
+    "labels": 1,
+    "multipart_upload": 1,
+    "reconcile": 1,
+    "record": 1,
+    "rename_stream": 1,
+    "repair": 1,
+    "trash": 1,
+    "triggers": 1,
+    "verify": 1
+  },
+  "upload_mode": "presign",
+  "index": "jsonl"
+}"#
+        );
+
+        let caps = capabilities(&RecorderConfig::default());
+        assert_eq!(caps.upload_mode, UploadMode::Direct);
+        assert!(!caps.supports(capability::MULTIPART_UPLOAD));
+        assert!(!caps.supports(capability::PUSH_SYNC));
+
+        // Proxied uploads send whole objects
+        cfg.upload.mode = UploadTransport::Proxy;
+        let caps = capabilities(&cfg);
+        assert_eq!(caps.upload_mode, UploadMode::Proxy);
+        assert!(!caps.supports(capability::MULTIPART_UPLOAD));
+    }
+}
@@ -0,0 +1,326 @@
+//! Caption sidecars of recordings, one WebVTT file per language in the record dir.
+//!
+//! Captions of a running recording are listed in the manifest the segmenter writes
+//! with its next segment. A finished recording has its stored manifest rewritten, the
+//! way a repair does, so players pick the new track up without a new recording.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use api::recorder::{CaptionsResponse, RecordingIndexEntry, RecordingStatus};
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use opendal::ErrorKind;
+use storage::FailoverOperator;
+
+use super::index::RecordingsIndex;
+use super::uploader::UploadManager;
+
+/// Languages of the recordings being written, by record dir
+static ACTIVE: Lazy<Mutex<HashMap<String, BTreeSet<String>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Caption languages the segmenter lists in the manifest under `record_dir`
+pub(crate) fn active_languages(record_dir: &str) -> Vec<String> {
+    ACTIVE
+        .lock()
+        .unwrap()
+        .get(record_dir)
+        .map(|langs| langs.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// The recording under `record_dir` stopped, its manifest is final
+pub(crate) fn forget(record_dir: &str) {
+    ACTIVE.lock().unwrap().remove(record_dir);
+}
+
+/// BCP 47 tags such as `en` or `zh-Hans-CN`, which also keeps them safe in object keys
+pub fn valid_lang(lang: &str) -> bool {
+    (1..=35).contains(&lang.len())
+        && lang
+            .split('-')
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_alphanumeric()))
+}
+
+/// Outcome of [`Captioner::store`]
+pub enum CaptionsOutcome {
+    Stored(CaptionsResponse),
+    NotFound,
+    /// Not WebVTT or SRT, or cues beyond the recording
+    Invalid(String),
+}
+
+pub struct Captioner {
+    index: Arc<RecordingsIndex>,
+    operator: FailoverOperator,
+    uploader: Option<Arc<UploadManager>>,
+    /// Directories holding local copies of the objects, later roots win
+    local_roots: Vec<PathBuf>,
+}
+
+impl Captioner {
+    pub fn new(
+        index: Arc<RecordingsIndex>,
+        operator: FailoverOperator,
+        uploader: Option<Arc<UploadManager>>,
+        local_roots: Vec<PathBuf>,
+    ) -> Self {
+        Self {
+            index,
+            operator,
+            uploader,
+            local_roots,
+        }
+    }
+
+    /// Store `body`, WebVTT or SRT, as the `lang` captions of `stream/record`.
+    ///
+    /// `elapsed` is how long the recording has been running when it is recorded by this
+    /// node, cues may not end after it, nor after `duration_ms` of a finished recording.
+    pub async fn store(
+        &self,
+        stream: &str,
+        record: &str,
+        lang: &str,
+        body: &str,
+        elapsed: Option<Duration>,
+    ) -> Result<CaptionsOutcome> {
+        let Some(entry) = self.index.get(stream, record).await else {
+            return Ok(CaptionsOutcome::NotFound);
+        };
+        let captions = match dash::to_webvtt(body) {
+            Ok(captions) => captions,
+            Err(e) => return Ok(CaptionsOutcome::Invalid(e.to_string())),
+        };
+        if let Some(duration) = recorded_duration(&entry, elapsed)
+            && let Err(e) = captions.check_duration(duration)
+        {
+            return Ok(CaptionsOutcome::Invalid(e.to_string()));
+        }
+
+        let path = format!("{}/{}", entry.record_dir, dash::captions_file(lang));
+        let replaced = entry.captions.iter().any(|l| l == lang);
+        self.write(&entry, &path, captions.vtt.into_bytes()).await?;
+
+        if matches!(entry.status, RecordingStatus::Active) {
+            ACTIVE
+                .lock()
+                .unwrap()
+                .entry(entry.record_dir.clone())
+                .or_default()
+                .insert(lang.to_string());
+        } else if !replaced {
+            self.add_to_manifest(&entry, lang).await?;
+        }
+        let captions_of = self
+            .index
+            .add_captions(stream, record, lang)
+            .await?
+            .map(|e| e.captions)
+            .unwrap_or_default();
+        tracing::info!(
+            "[recorder] stored {} captions of {}, {} cues",
+            lang,
+            entry.key(),
+            captions.cues
+        );
+        Ok(CaptionsOutcome::Stored(CaptionsResponse {
+            stream: stream.to_string(),
+            record: record.to_string(),
+            lang: lang.to_string(),
+            path,
+            cues: captions.cues,
+            replaced,
+            captions: captions_of,
+        }))
+    }
+
+    /// Reference `lang` from the stored manifest of a finished recording
+    async fn add_to_manifest(&self, entry: &RecordingIndexEntry, lang: &str) -> Result<()> {
+        let Some(data) = self.read(&entry.mpd_path).await? else {
+            anyhow::bail!("manifest {} is missing", entry.mpd_path);
+        };
+        let mut mpd: dash::Mpd = String::from_utf8_lossy(&data).parse()?;
+        mpd.set_captions(lang);
+        self.write(entry, &entry.mpd_path, mpd.to_string().into_bytes())
+            .await
+    }
+
+    /// Contents of `key` from the newest local copy, then storage. `None` if absent
+    async fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        for root in self.local_roots.iter().rev() {
+            match tokio::fs::read(root.join(key)).await {
+                Ok(data) => return Ok(Some(data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        match self.operator.current().read(key).await {
+            Ok(data) => Ok(Some(data.to_vec())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Queue `data` for upload as `key` of `entry` like the segmenter's files, or write
+    /// it to storage when uploads are disabled
+    async fn write(&self, entry: &RecordingIndexEntry, key: &str, data: Vec<u8>) -> Result<()> {
+        let Some(uploader) = self.uploader.as_ref() else {
+            self.operator
+                .current()
+                .write_with(key, data)
+                .content_type(storage::content_type_for(key))
+                .await?;
+            crate::recorder::invalidate(key).await;
+            return Ok(());
+        };
+        let local_path = PathBuf::from(uploader.local_dir()).join(key);
+        if let Some(parent) = local_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&local_path, data).await?;
+        if key == entry.mpd_path {
+            uploader.upload_final_manifest(key.to_string());
+        }
+        uploader
+            .stage(
+                key.to_string(),
+                &local_path,
+                &entry.stream,
+                entry.retention_class.as_ref().map(|c| c.tagging()),
+                entry.priority,
+            )
+            .await
+    }
+}
+
+/// How long `entry` lasts so far, `None` when unknown
+fn recorded_duration(entry: &RecordingIndexEntry, elapsed: Option<Duration>) -> Option<Duration> {
+    if matches!(entry.status, RecordingStatus::Active) {
+        return elapsed.or_else(|| {
+            let micros = Utc::now().timestamp_micros() - entry.start_ts;
+            Some(Duration::from_micros(u64::try_from(micros).ok()?))
+        });
+    }
+    let duration_ms = u64::try_from(entry.duration_ms?).ok()?;
+    Some(Duration::from_millis(duration_ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::recorder::DEFAULT_PRIORITY;
+    use opendal::Operator;
+    use opendal::services::Fs;
+
+    const RECORD_DIR: &str = "cam/1700000000";
+    const SRT: &str =
+        "1\n00:00:01,000 --> 00:00:04,000\nHello\n\n2\n00:00:20,000 --> 00:00:24,500\nBye\n";
+
+    async fn setup(root: &std::path::Path) -> (Arc<RecordingsIndex>, Captioner) {
+        let dir = root.join("bucket").join(RECORD_DIR);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("manifest.mpd"),
+            include_str!("../../../libs/dash/fixtures/recorder.mpd"),
+        )
+        .unwrap();
+        let index = RecordingsIndex::load(root.join("index.json"))
+            .await
+            .unwrap();
+        index
+            .upsert(RecordingIndexEntry {
+                uuid: String::new(),
+                record: "1700000000".to_string(),
+                stream: "cam".to_string(),
+                record_dir: RECORD_DIR.to_string(),
+                mpd_path: format!("{RECORD_DIR}/manifest.mpd"),
+                start_ts: 1_700_000_000_000_000,
+                end_ts: Some(1_700_000_025_000_000),
+                duration_ms: Some(25_000),
+                status: RecordingStatus::Completed,
+                node_alias: None,
+                updated_at: 1,
+                note: None,
+                labels: Vec::new(),
+                continues: None,
+                media_info: Vec::new(),
+                retention_class: None,
+                trashed_at: None,
+                trashed_from: None,
+                repair_error: None,
+                source: None,
+                replicas: Vec::new(),
+                clock_skew_detected: false,
+                priority: DEFAULT_PRIORITY,
+                tenant: None,
+                size: None,
+                captions: Vec::new(),
+                trigger: None,
+                imported: false,
+                seq: 0,
+            })
+            .await
+            .unwrap();
+        let index = Arc::new(index);
+        let op = Operator::new(Fs::default().root(root.join("bucket").to_str().unwrap()))
+            .unwrap()
+            .finish();
+        let captioner = Captioner::new(index.clone(), op.into(), None, Vec::new());
+        (index, captioner)
+    }
+
+    async fn store(captioner: &Captioner, lang: &str, body: &str) -> Option<CaptionsResponse> {
+        match captioner
+            .store("cam", "1700000000", lang, body, None)
+            .await
+            .unwrap()
+        {
+            CaptionsOutcome::Stored(resp) => Some(resp),
+            CaptionsOutcome::Invalid(_) => None,
+            CaptionsOutcome::NotFound => panic!("recording not found"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_captions_of_finished_recording() {
+        let dir = tempfile::tempdir().unwrap();
+        let (index, captioner) = setup(dir.path()).await;
+        let bucket = dir.path().join("bucket");
+
+        let resp = store(&captioner, "en", SRT).await.unwrap();
+        assert_eq!(resp.path, format!("{RECORD_DIR}/captions_en.vtt"));
+        assert_eq!((resp.cues, resp.replaced), (2, false));
+        let vtt = std::fs::read_to_string(bucket.join(&resp.path)).unwrap();
+        assert!(vtt.starts_with("WEBVTT\n\n00:00:01.000 --> 00:00:04.000\nHello\n"));
+
+        // A second language and a replacement, listed once in the manifest
+        assert!(store(&captioner, "fr", SRT).await.is_some());
+        let vtt = "WEBVTT\n\n00:02.000 --> 00:03.000\nHi\n";
+        assert!(store(&captioner, "en", vtt).await.unwrap().replaced);
+        let mpd = std::fs::read_to_string(bucket.join(RECORD_DIR).join("manifest.mpd")).unwrap();
+        let mpd: dash::Mpd = mpd.parse().unwrap();
+        assert_eq!(mpd.caption_languages(), ["en", "fr"]);
+        let entry = index.get("cam", "1700000000").await.unwrap();
+        assert_eq!(entry.captions, ["en", "fr"]);
+
+        // Cues past the recording's 25s are refused
+        let late = "1\n00:00:20,000 --> 00:00:26,000\nLate\n";
+        assert!(store(&captioner, "de", late).await.is_none());
+        assert!(!bucket.join(RECORD_DIR).join("captions_de.vtt").exists());
+    }
+
+    #[test]
+    fn test_valid_lang() {
+        for lang in ["en", "pt-BR", "zh-Hans-CN"] {
+            assert!(valid_lang(lang), "{lang}");
+        }
+        for lang in ["", "en_US", "../x", "en-", "en.vtt", &"a".repeat(36)] {
+            assert!(!valid_lang(lang), "{lang}");
+        }
+    }
+}
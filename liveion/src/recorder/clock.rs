@@ -0,0 +1,156 @@
+//! Time sources of recording sessions.
+//!
+//! Wall-clock time only stamps `start_ts`/`end_ts`, durations are measured on a
+//! monotonic clock so an NTP step mid-recording cannot turn them negative.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+
+/// Wall-clock and monotonic durations further apart than this flag the entry
+pub const CLOCK_SKEW_THRESHOLD: Duration = Duration::from_secs(2);
+
+pub trait Clock: Send + Sync {
+    /// Wall-clock time, UNIX microseconds
+    fn wall_micros(&self) -> i64;
+    /// Monotonic time since a fixed, arbitrary origin
+    fn monotonic(&self) -> Duration;
+}
+
+pub struct SystemClock;
+
+static ORIGIN: Lazy<Instant> = Lazy::new(Instant::now);
+
+impl Clock for SystemClock {
+    fn wall_micros(&self) -> i64 {
+        Utc::now().timestamp_micros()
+    }
+
+    fn monotonic(&self) -> Duration {
+        ORIGIN.elapsed()
+    }
+}
+
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// End of a session that started at wall-clock `start_ts` and monotonic `started`
+#[derive(Debug, Clone, Copy)]
+pub struct SessionEnd {
+    /// Wall-clock end, never before `start_ts`
+    pub end_ts: i64,
+    /// Monotonic duration
+    pub duration_ms: i32,
+    /// Wall-clock and monotonic durations disagree by more than [`CLOCK_SKEW_THRESHOLD`]
+    pub clock_skew_detected: bool,
+}
+
+impl SessionEnd {
+    pub fn measure(clock: &dyn Clock, start_ts: i64, started: Duration) -> Self {
+        let elapsed = clock.monotonic().saturating_sub(started);
+        let wall_end = clock.wall_micros();
+        let elapsed_micros = i64::try_from(elapsed.as_micros()).unwrap_or(i64::MAX);
+        let skew = wall_end.saturating_sub(start_ts).abs_diff(elapsed_micros);
+        Self {
+            end_ts: wall_end.max(start_ts),
+            duration_ms: elapsed.as_millis().min(i32::MAX as u128) as i32,
+            clock_skew_detected: skew > CLOCK_SKEW_THRESHOLD.as_micros() as u64,
+        }
+    }
+}
+
+/// Clock the tests step by hand
+#[cfg(test)]
+pub(crate) mod manual {
+    use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+    use super::*;
+
+    #[derive(Default)]
+    pub struct ManualClock {
+        wall: AtomicI64,
+        monotonic: AtomicU64,
+    }
+
+    impl ManualClock {
+        pub fn new(wall_micros: i64) -> Arc<Self> {
+            let clock = Self::default();
+            clock.wall.store(wall_micros, Ordering::SeqCst);
+            Arc::new(clock)
+        }
+
+        /// Let `elapsed` pass on both clocks
+        pub fn advance(&self, elapsed: Duration) {
+            self.monotonic
+                .fetch_add(elapsed.as_micros() as u64, Ordering::SeqCst);
+            self.wall
+                .fetch_add(elapsed.as_micros() as i64, Ordering::SeqCst);
+        }
+
+        /// Step the wall clock only, as NTP does
+        pub fn step_wall(&self, micros: i64) {
+            self.wall.fetch_add(micros, Ordering::SeqCst);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn wall_micros(&self) -> i64 {
+            self.wall.load(Ordering::SeqCst)
+        }
+
+        fn monotonic(&self) -> Duration {
+            Duration::from_micros(self.monotonic.load(Ordering::SeqCst))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::manual::ManualClock;
+    use super::*;
+
+    const START: i64 = 1_700_000_000_000_000;
+
+    #[test]
+    fn test_steady_clock() {
+        let clock = ManualClock::new(START);
+        let started = clock.monotonic();
+        clock.advance(Duration::from_secs(90));
+        clock.step_wall(500_000);
+
+        let end = SessionEnd::measure(clock.as_ref(), START, started);
+        assert_eq!(end.duration_ms, 90_000);
+        assert_eq!(end.end_ts, START + 90_500_000);
+        // Small corrections stay below the threshold
+        assert!(!end.clock_skew_detected);
+    }
+
+    #[test]
+    fn test_wall_clock_stepped_back() {
+        let clock = ManualClock::new(START);
+        let started = clock.monotonic();
+        clock.advance(Duration::from_secs(10));
+        clock.step_wall(-3_600_000_000);
+
+        let end = SessionEnd::measure(clock.as_ref(), START, started);
+        assert_eq!(end.duration_ms, 10_000);
+        assert_eq!(end.end_ts, START);
+        assert!(end.clock_skew_detected);
+    }
+
+    #[test]
+    fn test_wall_clock_stepped_forward() {
+        let clock = ManualClock::new(START);
+        let started = clock.monotonic();
+        clock.advance(Duration::from_secs(10));
+        clock.step_wall(86_400_000_000);
+
+        let end = SessionEnd::measure(clock.as_ref(), START, started);
+        assert_eq!(end.duration_ms, 10_000);
+        assert_eq!(end.end_ts, START + 86_410_000_000);
+        assert!(end.clock_skew_detected);
+    }
+}
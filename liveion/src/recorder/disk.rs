@@ -0,0 +1,143 @@
+//! Free space of the filesystems the uploader writes to.
+//!
+//! Below `upload.min_free_bytes` or `upload.min_free_inodes` the uploader stops taking
+//! new files and the segmenter drops segments instead of filling the disk, until space
+//! is freed.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Free space of a filesystem as seen by this process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Available {
+    pub bytes: u64,
+    /// `None` where the filesystem allocates inodes on demand or doesn't report them
+    pub inodes: Option<u64>,
+}
+
+pub trait FreeSpace: Send + Sync {
+    /// Bytes and inodes available to this process on the filesystem holding `path`
+    fn available(&self, path: &Path) -> io::Result<Available>;
+}
+
+/// `statvfs` on unix, `GetDiskFreeSpaceEx` on windows
+pub struct Statvfs;
+
+impl FreeSpace for Statvfs {
+    fn available(&self, path: &Path) -> io::Result<Available> {
+        Ok(Available {
+            bytes: fs2::available_space(path)?,
+            inodes: free_inodes(path)?,
+        })
+    }
+}
+
+#[cfg(unix)]
+fn free_inodes(path: &Path) -> io::Result<Option<u64>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: `path` is NUL terminated and `stat` is written by `statvfs` before it is read
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // btrfs and friends report no inode table at all
+    #[allow(clippy::unnecessary_cast)] // `fsfilcnt_t` is 32 bits on some targets
+    let favail = stat.f_favail as u64;
+    Ok((stat.f_files > 0).then_some(favail))
+}
+
+#[cfg(not(unix))]
+fn free_inodes(_path: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+pub fn system() -> Arc<dyn FreeSpace> {
+    Arc::new(Statvfs)
+}
+
+/// Refusal of the uploader to take a file while the disk is nearly full, out of bytes
+/// or out of inodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskFull {
+    Bytes {
+        available: u64,
+        min_free_bytes: u64,
+    },
+    Inodes {
+        available: u64,
+        min_free_inodes: u64,
+    },
+}
+
+impl fmt::Display for DiskFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bytes {
+                available,
+                min_free_bytes,
+            } => write!(
+                f,
+                "{} bytes free, below min_free_bytes {}",
+                available, min_free_bytes
+            ),
+            Self::Inodes {
+                available,
+                min_free_inodes,
+            } => write!(
+                f,
+                "{} inodes free, below min_free_inodes {}",
+                available, min_free_inodes
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DiskFull {}
+
+/// Free space the tests set by hand
+#[cfg(test)]
+pub(crate) mod manual {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+
+    /// Inodes of a filesystem that doesn't report them
+    const NO_INODES: u64 = u64::MAX;
+
+    pub struct ManualFreeSpace {
+        bytes: AtomicU64,
+        inodes: AtomicU64,
+    }
+
+    impl ManualFreeSpace {
+        pub fn new(bytes: u64) -> Arc<Self> {
+            Arc::new(Self {
+                bytes: AtomicU64::new(bytes),
+                inodes: AtomicU64::new(NO_INODES),
+            })
+        }
+
+        pub fn set(&self, bytes: u64) {
+            self.bytes.store(bytes, Ordering::SeqCst);
+        }
+
+        pub fn set_inodes(&self, inodes: u64) {
+            self.inodes.store(inodes, Ordering::SeqCst);
+        }
+    }
+
+    impl FreeSpace for ManualFreeSpace {
+        fn available(&self, _path: &Path) -> io::Result<Available> {
+            let inodes = self.inodes.load(Ordering::SeqCst);
+            Ok(Available {
+                bytes: self.bytes.load(Ordering::SeqCst),
+                inodes: (inodes != NO_INODES).then_some(inodes),
+            })
+        }
+    }
+}
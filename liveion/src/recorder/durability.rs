@@ -0,0 +1,232 @@
+//! `recorder.durability`: when writes to the index and the upload queue reach the disk.
+//!
+//! Every index append used to `sync_data` the log and its directory, and every queue
+//! change rewrote the queue file. On eMMC that wears the flash and adds latency to each
+//! write. `batched` leaves the appends to a flusher that syncs the files written in the
+//! last `flush_interval_ms` at once, and coalesces queue rewrites the same way. Files
+//! renamed over others are still synced before the rename, so a crash leaves the old
+//! file or the whole new one, never a new name on missing data. `relaxed` syncs nothing.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Weak};
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+
+use crate::config::{DurabilityConfig, DurabilityMode};
+
+#[derive(Default)]
+struct Pending {
+    /// Appended to since the last flush
+    files: HashSet<PathBuf>,
+    /// Gained or changed an entry since the last flush
+    dirs: HashSet<PathBuf>,
+}
+
+pub struct Durability {
+    mode: DurabilityMode,
+    interval: Duration,
+    pending: Mutex<Pending>,
+}
+
+impl Default for Durability {
+    fn default() -> Self {
+        Self::new(&DurabilityConfig::default())
+    }
+}
+
+impl Durability {
+    pub fn new(cfg: &DurabilityConfig) -> Self {
+        Self {
+            mode: cfg.mode,
+            interval: Duration::from_millis(cfg.flush_interval_ms.max(1)),
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Longest a write waits for its sync, or a queue change for its rewrite
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Whether queue changes are written right away rather than by a flusher
+    pub fn writes_through(&self) -> bool {
+        self.mode == DurabilityMode::Strict
+    }
+
+    /// `file` at `path` was appended to. Synced now when strict, by the next
+    /// [`Self::flush`] when batched
+    pub fn appended(&self, file: &File, path: &Path) -> std::io::Result<()> {
+        match self.mode {
+            DurabilityMode::Strict => {
+                file.sync_data()?;
+                sync_parent_dir(path);
+            }
+            DurabilityMode::Batched => {
+                let mut pending = self.pending.lock().unwrap();
+                pending.files.insert(path.to_path_buf());
+                if let Some(dir) = path.parent() {
+                    pending.dirs.insert(dir.to_path_buf());
+                }
+            }
+            DurabilityMode::Relaxed => {}
+        }
+        Ok(())
+    }
+
+    /// Whether a file is synced before it is renamed over another
+    pub fn syncs_before_rename(&self) -> bool {
+        self.mode != DurabilityMode::Relaxed
+    }
+
+    /// `file` is complete and about to be renamed over another. Its data, and that of
+    /// every append before it which the rename may supersede, reaches the disk before
+    /// the rename can, unless relaxed
+    pub fn before_rename(&self, file: &File) -> std::io::Result<()> {
+        match self.mode {
+            DurabilityMode::Strict => file.sync_data(),
+            DurabilityMode::Batched => {
+                self.flush()?;
+                file.sync_data()
+            }
+            DurabilityMode::Relaxed => Ok(()),
+        }
+    }
+
+    /// A file was renamed to `path`. Its directory is synced now when strict, by the
+    /// next [`Self::flush`] when batched: until then a crash may bring the old file back
+    pub fn renamed(&self, path: &Path) {
+        match self.mode {
+            DurabilityMode::Strict => sync_parent_dir(path),
+            DurabilityMode::Batched => {
+                if let Some(dir) = path.parent() {
+                    self.pending.lock().unwrap().dirs.insert(dir.to_path_buf());
+                }
+            }
+            DurabilityMode::Relaxed => {}
+        }
+    }
+
+    /// Files and directories written since the last flush
+    pub fn unsynced(&self) -> usize {
+        let pending = self.pending.lock().unwrap();
+        pending.files.len() + pending.dirs.len()
+    }
+
+    pub fn status(&self) -> api::recorder::DurabilityStatus {
+        let (mode, flush_interval_ms) = match self.mode {
+            DurabilityMode::Strict => ("strict", 0),
+            DurabilityMode::Batched => ("batched", self.interval.as_millis() as u64),
+            DurabilityMode::Relaxed => ("relaxed", self.interval.as_millis() as u64),
+        };
+        api::recorder::DurabilityStatus {
+            mode: mode.to_string(),
+            flush_interval_ms,
+            unsynced: self.unsynced(),
+        }
+    }
+
+    /// Sync what was written since the last flush, returns how many files and
+    /// directories. Blocks on the disk
+    pub fn flush(&self) -> std::io::Result<usize> {
+        let Pending { files, dirs } = std::mem::take(&mut *self.pending.lock().unwrap());
+        let mut failed = None;
+        for path in &files {
+            match File::open(path).and_then(|file| file.sync_data()) {
+                Ok(()) => {}
+                // Renamed away meanwhile, its replacement was synced before the rename
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    // Tried again by the next flush
+                    self.pending.lock().unwrap().files.insert(path.clone());
+                    failed = Some(e);
+                }
+            }
+        }
+        // After the files, so a name never reaches the disk before its data
+        for dir in &dirs {
+            sync_dir(dir);
+        }
+        match failed {
+            Some(e) => Err(e),
+            None => Ok(files.len() + dirs.len()),
+        }
+    }
+
+    /// Flush every `flush_interval_ms` with a blocking task on `runtime` until the
+    /// last owner drops it. Nothing to do unless batched
+    pub async fn run_flusher(this: Weak<Self>, runtime: Handle) {
+        let Some(interval) = this
+            .upgrade()
+            .filter(|d| d.mode == DurabilityMode::Batched)
+            .map(|d| d.interval)
+        else {
+            return;
+        };
+        loop {
+            tokio::time::sleep(interval).await;
+            let Some(durability) = this.upgrade() else {
+                return;
+            };
+            if durability.unsynced() == 0 {
+                continue;
+            }
+            match runtime.spawn_blocking(move || durability.flush()).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => tracing::warn!("[recorder] index flush failed: {}", e),
+                Err(e) => tracing::warn!("[recorder] index flush panicked: {}", e),
+            }
+        }
+    }
+}
+
+/// Sync the directory holding `path`, so a file created or renamed there stays
+fn sync_parent_dir(path: &Path) {
+    if let Some(parent) = path.parent() {
+        sync_dir(parent);
+    }
+}
+
+fn sync_dir(dir: &Path) {
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn durability(mode: DurabilityMode) -> Durability {
+        Durability::new(&DurabilityConfig {
+            mode,
+            flush_interval_ms: 60_000,
+        })
+    }
+
+    #[test]
+    fn test_pending_by_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let file = File::create(&path).unwrap();
+
+        for mode in [DurabilityMode::Strict, DurabilityMode::Relaxed] {
+            let durability = durability(mode);
+            durability.appended(&file, &path).unwrap();
+            durability.renamed(&path);
+            assert_eq!(durability.unsynced(), 0, "{mode:?}");
+        }
+
+        let batched = durability(DurabilityMode::Batched);
+        batched.appended(&file, &path).unwrap();
+        batched.appended(&file, &path).unwrap();
+        assert_eq!(batched.unsynced(), 2);
+        assert_eq!(batched.status().unsynced, 2);
+        // A file renamed away since its append was synced before the rename
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(batched.flush().unwrap(), 2);
+        assert_eq!(batched.unsynced(), 0);
+    }
+}
@@ -0,0 +1,432 @@
+//! Fanout of index transitions to the subscribers of `/api/recorder/events`.
+//!
+//! The index publishes on a bounded broadcast and never waits for a subscriber. Each
+//! subscriber has a forwarder moving the events its filter admits into a buffer of its
+//! own, without waiting either: while the buffer is full events are dropped and counted,
+//! and once there is room again the subscriber is sent a `lagged` event before anything
+//! else, telling it to resynchronize from the index. A stalled client so only costs its
+//! own events, neither index writes nor the other subscribers.
+
+use std::sync::Arc;
+
+use api::recorder::{EventsLagged, EventsQuery, RecorderError, RecorderEvent, RecorderEventKind};
+use glob::Pattern;
+use prometheus::IntCounter;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use super::index::RecordingsIndex;
+use crate::metrics;
+
+/// Events buffered per subscriber, dropped past it
+const SUBSCRIBER_CAPACITY: usize = 64;
+
+/// Longest `subscriber` name, which labels metrics
+const MAX_SUBSCRIBER_LEN: usize = 64;
+
+/// What a subscriber is sent
+#[derive(Debug)]
+pub enum Delivery {
+    Event(RecorderEvent),
+    /// Events were dropped since the last delivery
+    Lagged(EventsLagged),
+}
+
+/// Events a subscriber asked for
+#[derive(Debug, Default)]
+pub struct EventFilter {
+    stream: Option<Pattern>,
+    kinds: Option<Vec<RecorderEventKind>>,
+}
+
+impl EventFilter {
+    /// Filter of `query`, refusing a malformed glob or an unknown event type
+    pub fn new(query: &EventsQuery) -> Result<Self, RecorderError> {
+        let stream = query
+            .stream
+            .as_deref()
+            .map(Pattern::new)
+            .transpose()
+            .map_err(|e| RecorderError::validation(Some("stream"), e.to_string()))?;
+        let kinds = query
+            .kinds()
+            .map_err(|e| RecorderError::validation(Some("types"), e))?;
+        Ok(Self { stream, kinds })
+    }
+
+    pub fn admits(&self, event: &RecorderEvent) -> bool {
+        self.stream
+            .as_ref()
+            .is_none_or(|pattern| pattern.matches(&event.entry.stream))
+            && self
+                .kinds
+                .as_ref()
+                .is_none_or(|kinds| kinds.contains(&event.kind))
+    }
+}
+
+/// Label of a subscriber's metrics: the `subscriber` it asked for, or `actor`
+pub fn subscriber_label(requested: Option<&str>, actor: &str) -> Result<String, RecorderError> {
+    let Some(name) = requested else {
+        return Ok(actor.to_string());
+    };
+    if name.is_empty()
+        || name.len() > MAX_SUBSCRIBER_LEN
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':' | '@'))
+    {
+        return Err(RecorderError::validation(
+            Some("subscriber"),
+            format!(
+                "at most {MAX_SUBSCRIBER_LEN} ASCII letters, digits and any of '-_.:@' expected"
+            ),
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// A subscriber's end of the fanout
+pub struct Subscription {
+    pub events: mpsc::Receiver<Delivery>,
+    /// Counts the events the connection sent on
+    pub delivered: IntCounter,
+}
+
+/// Subscribe `subscriber` to the transitions of `index` that `filter` admits, replaying
+/// the entries changed after `last_event_id` first
+pub fn subscribe(
+    index: Arc<RecordingsIndex>,
+    last_event_id: Option<i64>,
+    filter: EventFilter,
+    subscriber: &str,
+) -> Subscription {
+    // Subscribe before reading the replay so no transition falls in between
+    let live = index.subscribe();
+    let (send, events) = mpsc::channel(SUBSCRIBER_CAPACITY);
+    let forwarder = Forwarder {
+        filter,
+        missed: 0,
+        last_event_id,
+        dropped: metrics::RECORDER_EVENTS_DROPPED.with_label_values(&[subscriber]),
+    };
+    tokio::spawn(forwarder.run(index, live, send));
+    Subscription {
+        events,
+        delivered: metrics::RECORDER_EVENTS_DELIVERED.with_label_values(&[subscriber]),
+    }
+}
+
+struct Forwarder {
+    filter: EventFilter,
+    /// Events dropped since the last delivery
+    missed: u64,
+    /// Id of the last event handed over
+    last_event_id: Option<i64>,
+    dropped: IntCounter,
+}
+
+impl Forwarder {
+    async fn run(
+        mut self,
+        index: Arc<RecordingsIndex>,
+        mut live: broadcast::Receiver<RecorderEvent>,
+        send: mpsc::Sender<Delivery>,
+    ) {
+        let _subscribed = Subscribed::new();
+        let mut replayed_until = None;
+        if let Some(since) = self.last_event_id
+            && !self.replay(&index, since, &send, &mut replayed_until).await
+        {
+            return;
+        }
+        loop {
+            tokio::select! {
+                received = live.recv() => match received {
+                    Ok(event) => {
+                        // Replays bring entries only, deletions and uploads are never
+                        // among them
+                        let replayed = !matches!(
+                            event.kind,
+                            RecorderEventKind::Deleted | RecorderEventKind::Uploaded
+                        ) && replayed_until.is_some_and(|until| event.id <= until);
+                        if !replayed && self.filter.admits(&event) && !self.offer(&send, event) {
+                            return;
+                        }
+                    }
+                    // The forwarder itself fell behind the broadcast
+                    Err(broadcast::error::RecvError::Lagged(missed)) => self.drop_events(missed),
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+                // Room again after drops, the subscriber learns of them without waiting
+                // for the next event
+                permit = send.reserve(), if self.missed > 0 => match permit {
+                    Ok(permit) => permit.send(Delivery::Lagged(self.lagged())),
+                    Err(_) => return,
+                },
+                _ = send.closed() => return,
+            }
+        }
+    }
+
+    /// Send the entries changed after `since` as `updated`, waiting for the subscriber
+    /// like it asked to. False once it is gone
+    async fn replay(
+        &mut self,
+        index: &RecordingsIndex,
+        since: i64,
+        send: &mpsc::Sender<Delivery>,
+        replayed_until: &mut Option<i64>,
+    ) -> bool {
+        for entry in index.changed_since(since.max(0) as u64).await {
+            let id = entry.seq as i64;
+            *replayed_until = Some(replayed_until.map_or(id, |until| until.max(id)));
+            let event = RecorderEvent {
+                id,
+                kind: RecorderEventKind::Updated,
+                entry,
+            };
+            if !self.filter.admits(&event) {
+                continue;
+            }
+            if send.send(Delivery::Event(event)).await.is_err() {
+                return false;
+            }
+            self.last_event_id = Some(id);
+        }
+        true
+    }
+
+    /// Hand `event` over without waiting, dropping it when the buffer is full. False once
+    /// the subscriber is gone
+    fn offer(&mut self, send: &mpsc::Sender<Delivery>, event: RecorderEvent) -> bool {
+        if self.missed > 0 {
+            match send.try_reserve() {
+                Ok(permit) => permit.send(Delivery::Lagged(self.lagged())),
+                Err(TrySendError::Full(())) => {
+                    self.drop_events(1);
+                    return true;
+                }
+                Err(TrySendError::Closed(())) => return false,
+            }
+        }
+        let id = event.id;
+        match send.try_send(Delivery::Event(event)) {
+            Ok(()) => {
+                self.last_event_id = Some(id);
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                self.drop_events(1);
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// The `lagged` event for the events dropped so far, which it accounts for
+    fn lagged(&mut self) -> EventsLagged {
+        EventsLagged {
+            missed: std::mem::take(&mut self.missed),
+            last_event_id: self.last_event_id,
+        }
+    }
+
+    fn drop_events(&mut self, n: u64) {
+        self.missed += n;
+        self.dropped.inc_by(n);
+    }
+}
+
+/// Counts a subscriber in `recorder_event_subscribers` while it lives
+struct Subscribed;
+
+impl Subscribed {
+    fn new() -> Self {
+        metrics::RECORDER_EVENT_SUBSCRIBERS.inc();
+        Self
+    }
+}
+
+impl Drop for Subscribed {
+    fn drop(&mut self) {
+        metrics::RECORDER_EVENT_SUBSCRIBERS.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DurabilityConfig, DurabilityMode};
+    use crate::recorder::bench::bench_entry;
+    use crate::recorder::durability::Durability;
+    use std::time::{Duration, Instant};
+
+    async fn index(dir: &std::path::Path) -> Arc<RecordingsIndex> {
+        let durability = Durability::new(&DurabilityConfig {
+            mode: DurabilityMode::Relaxed,
+            flush_interval_ms: 60_000,
+        });
+        Arc::new(
+            RecordingsIndex::load(dir.join("index.json"))
+                .await
+                .unwrap()
+                .with_durability(Arc::new(durability)),
+        )
+    }
+
+    /// p99 of `writes` upserts of new recordings of `stream`
+    async fn write(index: &RecordingsIndex, stream: &str, writes: usize) -> Duration {
+        let mut latencies = Vec::with_capacity(writes);
+        for i in 0..writes {
+            let entry = bench_entry(stream, &format!("{stream}/{i}"), i as i64);
+            let started = Instant::now();
+            index.upsert(entry).await.unwrap();
+            latencies.push(started.elapsed());
+            tokio::task::yield_now().await;
+        }
+        latencies.sort();
+        latencies[writes * 99 / 100]
+    }
+
+    async fn next(subscription: &mut Subscription) -> Delivery {
+        tokio::time::timeout(Duration::from_secs(5), subscription.events.recv())
+            .await
+            .expect("delivery")
+            .expect("subscribed")
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_stalled_subscriber_costs_only_its_own_events() {
+        const WRITES: usize = 500;
+        let dir = tempfile::tempdir().unwrap();
+        let index = index(dir.path()).await;
+        let baseline = write(&index, "warmup", WRITES).await;
+
+        // Never read until the writes are done
+        let mut stalled = subscribe(index.clone(), None, EventFilter::default(), "test-stalled");
+        let mut fast = subscribe(index.clone(), None, EventFilter::default(), "test-fast");
+        let reader = tokio::spawn(async move {
+            let mut ids = Vec::with_capacity(WRITES);
+            while ids.len() < WRITES {
+                match next(&mut fast).await {
+                    Delivery::Event(event) => ids.push(event.id),
+                    Delivery::Lagged(lagged) => panic!("fast subscriber lagged: {lagged:?}"),
+                }
+            }
+            ids
+        });
+        let p99 = write(&index, "cam", WRITES).await;
+        assert!(
+            p99 < baseline * 5 + Duration::from_millis(20),
+            "p99 write {p99:?} with a stalled subscriber, {baseline:?} without"
+        );
+        let ids = reader.await.unwrap();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]), "{ids:?}");
+
+        // The stalled subscriber has its buffer, then learns what it missed
+        for id in &ids[..SUBSCRIBER_CAPACITY] {
+            match next(&mut stalled).await {
+                Delivery::Event(event) => assert_eq!(event.id, *id),
+                other => panic!("expected event {id}, got {other:?}"),
+            }
+        }
+        let missed = (WRITES - SUBSCRIBER_CAPACITY) as u64;
+        match next(&mut stalled).await {
+            Delivery::Lagged(lagged) => assert_eq!(
+                lagged,
+                EventsLagged {
+                    missed,
+                    last_event_id: Some(ids[SUBSCRIBER_CAPACITY - 1]),
+                }
+            ),
+            other => panic!("expected lagged, got {other:?}"),
+        }
+        let dropped = metrics::RECORDER_EVENTS_DROPPED.with_label_values(&["test-stalled"]);
+        assert_eq!(dropped.get(), missed);
+
+        // And follows again from there
+        index
+            .upsert(bench_entry("cam", "cam/next", 1))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next(&mut stalled).await,
+            Delivery::Event(event) if event.entry.record_dir == "cam/next"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_filters_apply_to_live_and_replayed_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = index(dir.path()).await;
+        let filter = |stream: Option<&str>, types: Option<&str>| {
+            EventFilter::new(&EventsQuery {
+                stream: stream.map(str::to_string),
+                types: types.map(str::to_string),
+                subscriber: None,
+            })
+            .unwrap()
+        };
+        let mut created = subscribe(
+            index.clone(),
+            None,
+            filter(Some("cam-*"), Some("created")),
+            "test-created",
+        );
+
+        let cam = bench_entry("cam-1", "cam-1/1", 1);
+        index.upsert(cam.clone()).await.unwrap();
+        index
+            .upsert(bench_entry("gate", "gate/1", 1))
+            .await
+            .unwrap();
+        index.upsert(cam).await.unwrap();
+        index
+            .upsert(bench_entry("cam-2", "cam-2/1", 1))
+            .await
+            .unwrap();
+        for stream in ["cam-1", "cam-2"] {
+            match next(&mut created).await {
+                Delivery::Event(event) => {
+                    assert_eq!(event.kind, RecorderEventKind::Created);
+                    assert_eq!(event.entry.stream, stream);
+                }
+                other => panic!("expected {stream} created, got {other:?}"),
+            }
+        }
+        assert!(created.events.try_recv().is_err());
+
+        // Replays are `updated` entries, filtered alike
+        let mut replayed = subscribe(index.clone(), Some(0), filter(Some("cam-*"), None), "test");
+        let mut streams = Vec::new();
+        for _ in 0..2 {
+            match next(&mut replayed).await {
+                Delivery::Event(event) => {
+                    assert_eq!(event.kind, RecorderEventKind::Updated);
+                    streams.push(event.entry.stream);
+                }
+                other => panic!("expected a replayed entry, got {other:?}"),
+            }
+        }
+        assert_eq!(streams, ["cam-1", "cam-2"]);
+        assert!(replayed.events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_malformed_filters_are_refused() {
+        let query = |stream: &str, types: &str| EventsQuery {
+            stream: Some(stream.to_string()),
+            types: Some(types.to_string()),
+            subscriber: None,
+        };
+        assert!(EventFilter::new(&query("cam-*", "created, status")).is_ok());
+        assert!(EventFilter::new(&query("cam-[", "created")).is_err());
+        assert!(EventFilter::new(&query("cam-*", "segment")).is_err());
+
+        assert_eq!(subscriber_label(None, "*").unwrap(), "*");
+        assert_eq!(subscriber_label(Some("dash-1"), "*").unwrap(), "dash-1");
+        assert!(subscriber_label(Some("a b"), "*").is_err());
+        assert!(subscriber_label(Some(&"x".repeat(65)), "*").is_err());
+    }
+}
@@ -0,0 +1,596 @@
+//! Recording integrity rollup of `GET /api/recorder/health`, per stream and for the node.
+//!
+//! Weighs what the index, the upload queue, the stall watchdog and the disk guard say against
+//! `recorder.health`: each condition past a threshold is a reason, the worst reason
+//! is the status. Queued uploads belong to the stream whose record dir holds them, the
+//! disk belongs to every stream.
+
+use std::collections::HashMap;
+
+use api::recorder::{
+    DiskStatus, HealthReason, HealthReasonKind, HealthStatus, RecorderHealth, RecordingIndexEntry,
+    RecordingStatus,
+};
+
+use super::uploader::QueuedUpload;
+use crate::config::{HealthConfig, HealthThreshold};
+
+/// Level `value` reaches in `threshold`
+fn level(value: u64, threshold: HealthThreshold) -> HealthStatus {
+    if threshold.failing > 0 && value >= threshold.failing {
+        HealthStatus::Failing
+    } else if threshold.degraded > 0 && value >= threshold.degraded {
+        HealthStatus::Degraded
+    } else {
+        HealthStatus::Ok
+    }
+}
+
+/// A reason for `value` of `stream`, `None` while it stays below both levels
+fn reason(
+    kind: HealthReasonKind,
+    stream: Option<&str>,
+    value: u64,
+    threshold: HealthThreshold,
+    message: impl FnOnce() -> String,
+) -> Option<HealthReason> {
+    let status = level(value, threshold);
+    let threshold = match status {
+        HealthStatus::Ok => return None,
+        HealthStatus::Degraded => threshold.degraded,
+        HealthStatus::Failing => threshold.failing,
+    };
+    Some(HealthReason {
+        kind,
+        status,
+        stream: stream.map(str::to_string),
+        value,
+        threshold,
+        message: message(),
+    })
+}
+
+#[derive(Default)]
+struct StreamSignals {
+    /// Oldest modification time of a queued, not dead-lettered file
+    oldest_queued: Option<i64>,
+    dead_lettered: u64,
+    mismatched: u64,
+    missing: u64,
+    interrupted: u64,
+    /// Last sample of the running recording, while it is stalled
+    stalled_since: Option<i64>,
+}
+
+/// Stream whose record dir holds `object_key`
+fn stream_of<'a>(dirs: &HashMap<&str, &'a str>, object_key: &str) -> Option<&'a str> {
+    let mut dir = object_key;
+    while let Some((parent, _)) = dir.rsplit_once('/') {
+        if let Some(stream) = dirs.get(parent) {
+            return Some(stream);
+        }
+        dir = parent;
+    }
+    None
+}
+
+/// Rollup of `stream`, or of the node with every stream's reasons. `stalled` holds the
+/// streams whose recording is stalled with its last sample, it and `now` in UNIX
+/// microseconds
+pub fn rollup(
+    cfg: &HealthConfig,
+    stream: Option<&str>,
+    entries: &[RecordingIndexEntry],
+    queued: &[QueuedUpload],
+    stalled: &[(String, i64)],
+    disk: Option<&DiskStatus>,
+    now: i64,
+) -> RecorderHealth {
+    let window_start = now - (cfg.window_seconds as i64).saturating_mul(1_000_000);
+    let mut signals: HashMap<&str, StreamSignals> = HashMap::new();
+    let mut dirs = HashMap::new();
+    for entry in entries {
+        if stream.is_some_and(|s| s != entry.stream) {
+            continue;
+        }
+        dirs.insert(
+            entry.record_dir.trim_end_matches('/'),
+            entry.stream.as_str(),
+        );
+        let s = signals.entry(entry.stream.as_str()).or_default();
+        match entry.status {
+            RecordingStatus::Missing => s.missing += 1,
+            RecordingStatus::Failed | RecordingStatus::Interrupted
+                if entry.end_ts.unwrap_or(entry.start_ts) >= window_start =>
+            {
+                s.interrupted += 1
+            }
+            _ => {}
+        }
+    }
+    for upload in queued {
+        let Some(stream) = stream_of(&dirs, &upload.object_key) else {
+            continue;
+        };
+        let s = signals.entry(stream).or_default();
+        if upload.dead_lettered {
+            s.dead_lettered += 1;
+        } else if let Some(at) = upload.modified_at {
+            s.oldest_queued = Some(s.oldest_queued.map_or(at, |oldest| oldest.min(at)));
+        }
+        if upload.mismatched {
+            s.mismatched += 1;
+        }
+    }
+    for (stalled_stream, since) in stalled {
+        if stream.is_some_and(|s| s != stalled_stream) {
+            continue;
+        }
+        signals
+            .entry(stalled_stream.as_str())
+            .or_default()
+            .stalled_since = Some(*since);
+    }
+
+    let mut reasons = Vec::new();
+    for (stream, s) in &signals {
+        let stream = Some(*stream);
+        let lag = s
+            .oldest_queued
+            .map_or(0, |at| (now.saturating_sub(at) / 1_000_000).max(0) as u64);
+        // At least a second, the watchdog only reports stalls past its timeout
+        let stalled = s.stalled_since.map_or(0, |since| {
+            (now.saturating_sub(since) / 1_000_000).max(1) as u64
+        });
+        reasons.extend(
+            [
+                reason(
+                    HealthReasonKind::UploadLag,
+                    stream,
+                    lag,
+                    cfg.upload_lag_seconds,
+                    || format!("oldest queued upload written {lag}s ago"),
+                ),
+                reason(
+                    HealthReasonKind::DeadLettered,
+                    stream,
+                    s.dead_lettered,
+                    cfg.dead_lettered,
+                    || format!("{} uploads given up", s.dead_lettered),
+                ),
+                reason(
+                    HealthReasonKind::UploadMismatch,
+                    stream,
+                    s.mismatched,
+                    cfg.upload_mismatches,
+                    || format!("{} uploads stored something else", s.mismatched),
+                ),
+                reason(
+                    HealthReasonKind::Missing,
+                    stream,
+                    s.missing,
+                    cfg.missing,
+                    || format!("{} recordings missing from storage", s.missing),
+                ),
+                reason(
+                    HealthReasonKind::Interrupted,
+                    stream,
+                    s.interrupted,
+                    cfg.interrupted,
+                    || {
+                        format!(
+                            "{} recordings failed or interrupted in the last {}s",
+                            s.interrupted, cfg.window_seconds
+                        )
+                    },
+                ),
+                reason(
+                    HealthReasonKind::Stalled,
+                    stream,
+                    stalled,
+                    cfg.stalled_seconds,
+                    || format!("recording got no media for {stalled}s"),
+                ),
+            ]
+            .into_iter()
+            .flatten(),
+        );
+    }
+    reasons.extend(disk.and_then(|disk| disk_reason(cfg, disk)));
+    // Worst first, then by stream for a stable order
+    reasons.sort_by(|a, b| {
+        b.status
+            .cmp(&a.status)
+            .then_with(|| a.stream.cmp(&b.stream))
+    });
+
+    RecorderHealth {
+        status: reasons
+            .iter()
+            .map(|r| r.status)
+            .max()
+            .unwrap_or(HealthStatus::Ok),
+        reasons,
+        computed_at: now,
+        upload_lag: None,
+    }
+}
+
+fn disk_reason(cfg: &HealthConfig, disk: &DiskStatus) -> Option<HealthReason> {
+    if disk.guarded {
+        return Some(HealthReason {
+            kind: HealthReasonKind::DiskGuard,
+            status: HealthStatus::Failing,
+            stream: None,
+            value: disk.free_bytes.unwrap_or_default(),
+            threshold: disk.min_free_bytes,
+            message: "disk space guard refuses new uploads".to_string(),
+        });
+    }
+    let free = disk.free_bytes?;
+    if cfg.disk_headroom_percent == 0 || disk.min_free_bytes == 0 {
+        return None;
+    }
+    let low = disk
+        .min_free_bytes
+        .saturating_mul(100 + cfg.disk_headroom_percent)
+        / 100;
+    (free < low).then(|| HealthReason {
+        kind: HealthReasonKind::DiskLow,
+        status: HealthStatus::Degraded,
+        stream: None,
+        value: free,
+        threshold: low,
+        message: format!(
+            "{free} bytes free, guard engages below {}",
+            disk.min_free_bytes
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::recorder::DEFAULT_PRIORITY;
+
+    const NOW: i64 = 1_760_486_400_000_000;
+    const SECOND: i64 = 1_000_000;
+
+    fn entry(stream: &str, record: &str, status: RecordingStatus) -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            uuid: String::new(),
+            record: record.to_string(),
+            stream: stream.to_string(),
+            record_dir: format!("{stream}/{record}"),
+            mpd_path: format!("{stream}/{record}/manifest.mpd"),
+            start_ts: NOW - 600 * SECOND,
+            end_ts: Some(NOW - 60 * SECOND),
+            duration_ms: None,
+            status,
+            node_alias: None,
+            updated_at: NOW,
+            note: None,
+            labels: Vec::new(),
+            continues: None,
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant: None,
+            size: None,
+            captions: Vec::new(),
+            trigger: None,
+            imported: false,
+            seq: 0,
+        }
+    }
+
+    fn upload(key: &str, age_seconds: i64) -> QueuedUpload {
+        QueuedUpload {
+            object_key: key.to_string(),
+            modified_at: Some(NOW - age_seconds * SECOND),
+            dead_lettered: false,
+            mismatched: false,
+        }
+    }
+
+    fn disk(free_bytes: u64, guarded: bool) -> DiskStatus {
+        DiskStatus {
+            free_bytes: Some(free_bytes),
+            min_free_bytes: 1_000,
+            free_inodes: None,
+            min_free_inodes: 0,
+            guarded,
+        }
+    }
+
+    fn kinds(health: &RecorderHealth) -> Vec<(HealthReasonKind, HealthStatus)> {
+        health.reasons.iter().map(|r| (r.kind, r.status)).collect()
+    }
+
+    /// The rollup of `entries` and `queued` for the stream `cam`
+    fn cam(entries: &[RecordingIndexEntry], queued: &[QueuedUpload]) -> RecorderHealth {
+        rollup(
+            &HealthConfig::default(),
+            Some("cam"),
+            entries,
+            queued,
+            &[],
+            None,
+            NOW,
+        )
+    }
+
+    #[test]
+    fn test_healthy() {
+        let entries = [
+            entry("cam", "1760486000", RecordingStatus::Completed),
+            entry("cam", "1760486300", RecordingStatus::Active),
+        ];
+        let queued = [upload("cam/1760486300/v_seg_0001.m4s", 2)];
+        let health = rollup(
+            &HealthConfig::default(),
+            None,
+            &entries,
+            &queued,
+            &[],
+            Some(&disk(10_000, false)),
+            NOW,
+        );
+        assert_eq!(health.status, HealthStatus::Ok);
+        assert!(health.reasons.is_empty());
+        assert_eq!(health.computed_at, NOW);
+    }
+
+    #[test]
+    fn test_upload_lag() {
+        let entries = [entry("cam", "1760486300", RecordingStatus::Active)];
+        let degraded = cam(&entries, &[upload("cam/1760486300/v_seg_0001.m4s", 400)]);
+        assert_eq!(degraded.status, HealthStatus::Degraded);
+        assert_eq!(
+            kinds(&degraded),
+            [(HealthReasonKind::UploadLag, HealthStatus::Degraded)]
+        );
+        assert_eq!(degraded.reasons[0].value, 400);
+        assert_eq!(degraded.reasons[0].threshold, 300);
+        assert_eq!(degraded.reasons[0].stream.as_deref(), Some("cam"));
+
+        let failing = cam(
+            &entries,
+            &[
+                upload("cam/1760486300/v_seg_0002.m4s", 10),
+                upload("cam/1760486300/v_seg_0001.m4s", 2_000),
+            ],
+        );
+        assert_eq!(
+            kinds(&failing),
+            [(HealthReasonKind::UploadLag, HealthStatus::Failing)]
+        );
+        assert_eq!(failing.reasons[0].value, 2_000);
+    }
+
+    #[test]
+    fn test_dead_lettered() {
+        let entries = [entry("cam", "1760486000", RecordingStatus::Completed)];
+        let mut dead = upload("cam/1760486000/v_seg_0001.m4s", 5_000);
+        dead.dead_lettered = true;
+        // A dead-lettered upload fails the stream, its age is no lag: nothing retries it
+        let health = cam(&entries, &[dead]);
+        assert_eq!(health.status, HealthStatus::Failing);
+        assert_eq!(
+            kinds(&health),
+            [(HealthReasonKind::DeadLettered, HealthStatus::Failing)]
+        );
+    }
+
+    #[test]
+    fn test_upload_mismatches() {
+        let entries = [entry("cam", "1760486000", RecordingStatus::Completed)];
+        let mismatched = |seq: usize| QueuedUpload {
+            mismatched: true,
+            ..upload(&format!("cam/1760486000/v_seg_{seq:04}.m4s"), 1)
+        };
+        let one: Vec<_> = (0..1).map(mismatched).collect();
+        assert_eq!(
+            kinds(&cam(&entries, &one)),
+            [(HealthReasonKind::UploadMismatch, HealthStatus::Degraded)]
+        );
+        let five: Vec<_> = (0..5).map(mismatched).collect();
+        assert_eq!(
+            kinds(&cam(&entries, &five)),
+            [(HealthReasonKind::UploadMismatch, HealthStatus::Failing)]
+        );
+    }
+
+    #[test]
+    fn test_missing() {
+        let entries = [
+            entry("cam", "1760486000", RecordingStatus::Completed),
+            entry("cam", "1760400000", RecordingStatus::Missing),
+        ];
+        let health = cam(&entries, &[]);
+        assert_eq!(health.status, HealthStatus::Failing);
+        assert_eq!(
+            kinds(&health),
+            [(HealthReasonKind::Missing, HealthStatus::Failing)]
+        );
+        assert_eq!(health.reasons[0].value, 1);
+    }
+
+    #[test]
+    fn test_interrupted_within_window() {
+        let mut old = entry("cam", "1760300000", RecordingStatus::Interrupted);
+        old.end_ts = Some(NOW - 2 * 86_400 * SECOND);
+        let mut entries = vec![
+            old,
+            entry("cam", "1760486000", RecordingStatus::Interrupted),
+        ];
+        assert_eq!(
+            kinds(&cam(&entries, &[])),
+            [(HealthReasonKind::Interrupted, HealthStatus::Degraded)]
+        );
+        entries.push(entry("cam", "1760486100", RecordingStatus::Failed));
+        entries.push(entry("cam", "1760486200", RecordingStatus::Interrupted));
+        let health = cam(&entries, &[]);
+        assert_eq!(
+            kinds(&health),
+            [(HealthReasonKind::Interrupted, HealthStatus::Failing)]
+        );
+        // The one before the window does not count
+        assert_eq!(health.reasons[0].value, 3);
+    }
+
+    #[test]
+    fn test_stalled() {
+        let entries = [entry("cam", "1760486300", RecordingStatus::Active)];
+        let stalled = |seconds: i64| {
+            rollup(
+                &HealthConfig::default(),
+                Some("cam"),
+                &entries,
+                &[],
+                &[("cam".to_string(), NOW - seconds * SECOND)],
+                None,
+                NOW,
+            )
+        };
+        let health = stalled(90);
+        assert_eq!(
+            kinds(&health),
+            [(HealthReasonKind::Stalled, HealthStatus::Degraded)]
+        );
+        assert_eq!(health.reasons[0].value, 90);
+        assert_eq!(
+            kinds(&stalled(700)),
+            [(HealthReasonKind::Stalled, HealthStatus::Failing)]
+        );
+
+        // Another stream's stall is not this one's
+        let other = rollup(
+            &HealthConfig::default(),
+            Some("cam"),
+            &entries,
+            &[],
+            &[("gate".to_string(), NOW - 90 * SECOND)],
+            None,
+            NOW,
+        );
+        assert_eq!(other.status, HealthStatus::Ok);
+    }
+
+    #[test]
+    fn test_disk() {
+        let cfg = HealthConfig::default();
+        let node = |disk: DiskStatus| rollup(&cfg, None, &[], &[], &[], Some(&disk), NOW);
+
+        let low = node(disk(1_500, false));
+        assert_eq!(
+            kinds(&low),
+            [(HealthReasonKind::DiskLow, HealthStatus::Degraded)]
+        );
+        assert_eq!(low.reasons[0].threshold, 2_000);
+        assert_eq!(low.reasons[0].stream, None);
+
+        let guarded = node(disk(900, true));
+        assert_eq!(
+            kinds(&guarded),
+            [(HealthReasonKind::DiskGuard, HealthStatus::Failing)]
+        );
+
+        // Before the first check nothing is known
+        let unknown = DiskStatus {
+            free_bytes: None,
+            ..disk(0, false)
+        };
+        assert_eq!(node(unknown).status, HealthStatus::Ok);
+
+        // The disk is every stream's
+        let entries = [entry("cam", "1760486000", RecordingStatus::Completed)];
+        let stream = rollup(
+            &cfg,
+            Some("cam"),
+            &entries,
+            &[],
+            &[],
+            Some(&disk(900, true)),
+            NOW,
+        );
+        assert_eq!(stream.status, HealthStatus::Failing);
+    }
+
+    #[test]
+    fn test_node_rollup_lists_every_stream_worst_first() {
+        let entries = [
+            entry("cam", "1760486000", RecordingStatus::Interrupted),
+            entry("gate", "1760486000", RecordingStatus::Missing),
+            entry("lobby", "1760486000", RecordingStatus::Completed),
+        ];
+        let health = rollup(
+            &HealthConfig::default(),
+            None,
+            &entries,
+            &[upload("lobby/1760486000/v_seg_0001.m4s", 400)],
+            &[],
+            Some(&disk(1_500, false)),
+            NOW,
+        );
+        assert_eq!(health.status, HealthStatus::Failing);
+        let reasons: Vec<_> = health
+            .reasons
+            .iter()
+            .map(|r| (r.kind, r.stream.as_deref()))
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                (HealthReasonKind::Missing, Some("gate")),
+                (HealthReasonKind::DiskLow, None),
+                (HealthReasonKind::Interrupted, Some("cam")),
+                (HealthReasonKind::UploadLag, Some("lobby")),
+            ]
+        );
+
+        // A stream's rollup leaves out the others
+        let lobby = rollup(
+            &HealthConfig::default(),
+            Some("lobby"),
+            &entries,
+            &[upload("lobby/1760486000/v_seg_0001.m4s", 400)],
+            &[],
+            None,
+            NOW,
+        );
+        assert_eq!(
+            kinds(&lobby),
+            [(HealthReasonKind::UploadLag, HealthStatus::Degraded)]
+        );
+    }
+
+    #[test]
+    fn test_levels_turned_off() {
+        let cfg = HealthConfig {
+            missing: HealthThreshold {
+                degraded: 0,
+                failing: 0,
+            },
+            disk_headroom_percent: 0,
+            ..Default::default()
+        };
+        let entries = [entry("cam", "1760400000", RecordingStatus::Missing)];
+        let health = rollup(
+            &cfg,
+            None,
+            &entries,
+            &[],
+            &[],
+            Some(&disk(1_500, false)),
+            NOW,
+        );
+        assert_eq!(health.status, HealthStatus::Ok);
+    }
+}
@@ -0,0 +1,382 @@
+//! Index recordings another system wrote to storage in the recorder's layout.
+//!
+//! A dir under the listed prefix is a recording when it holds an MPD and its path is
+//! `[{namespace}/]{stream}/{record}/` with a numeric record id, the UNIX second the
+//! recording started. The entry is built from the manifest: `availabilityStartTime`
+//! when set overrides the start taken from the record id, the duration is the
+//! `mediaPresentationDuration` or the end of the longest track. Imported entries are
+//! finished recordings like any other, played back, trashed and expired the same way.
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::Result;
+use api::recorder::{
+    DEFAULT_PRIORITY, ImportRecordingsResponse, ImportSkip, RecordingIndexEntry, RecordingKey,
+    RecordingSize, RecordingStatus,
+};
+use chrono::{DateTime, Utc};
+use opendal::ErrorKind;
+use storage::FailoverOperator;
+use tokio::sync::Mutex;
+
+use super::index::RecordingsIndex;
+use super::tenancy::Tenancy;
+
+const MANIFEST: &str = "manifest.mpd";
+
+/// Manifest and media objects of one dir found under the prefix
+#[derive(Default)]
+struct Listed {
+    manifests: Vec<String>,
+    size: RecordingSize,
+}
+
+pub struct Importer {
+    index: Arc<RecordingsIndex>,
+    operator: FailoverOperator,
+    node_alias: Option<String>,
+    /// Record dirs of a tenant's streams start with `{tenant}/`
+    tenancy: Tenancy,
+    running: Mutex<()>,
+}
+
+impl Importer {
+    pub fn new(
+        index: Arc<RecordingsIndex>,
+        operator: FailoverOperator,
+        node_alias: Option<String>,
+        tenancy: Tenancy,
+    ) -> Self {
+        Self {
+            index,
+            operator,
+            node_alias,
+            tenancy,
+            running: Mutex::new(()),
+        }
+    }
+
+    /// Add the recordings under `prefix` the index doesn't know yet, or only report
+    /// them with `dry_run`. Imports run one at a time so two of them can't both add
+    /// the same dir.
+    pub async fn import(&self, prefix: &str, dry_run: bool) -> Result<ImportRecordingsResponse> {
+        let _guard = self.running.lock().await;
+        let mut resp = ImportRecordingsResponse {
+            dry_run,
+            ..Default::default()
+        };
+        let existing = self.index.snapshot().await?;
+        let mut keys: HashSet<String> = existing.iter().map(|e| e.key()).collect();
+        let dirs: HashSet<&str> = existing.iter().map(|e| e.record_dir.as_str()).collect();
+
+        for (dir, listed) in self.list(prefix).await? {
+            // The recorder's own name first, then any other manifest in the dir
+            let Some(mpd_path) = listed
+                .manifests
+                .iter()
+                .find(|path| path.ends_with(&format!("/{MANIFEST}")))
+                .or(listed.manifests.first())
+                .cloned()
+            else {
+                continue;
+            };
+            let key =
+                RecordingKey::from_path(&mpd_path).map(|k| format!("{}/{}", k.stream, k.record));
+            let skip = |reason: String| ImportSkip {
+                mpd_path: mpd_path.clone(),
+                key: key.clone(),
+                reason,
+            };
+            // Checked against the entries added by this run too, a key can be found
+            // under two namespaces
+            if key.as_ref().is_some_and(|key| keys.contains(key)) {
+                resp.skipped.push(skip("already in the index".to_string()));
+                continue;
+            }
+            if dirs.contains(dir.as_str()) {
+                resp.skipped
+                    .push(skip("dir belongs to another recording".to_string()));
+                continue;
+            }
+            match self.entry(&dir, &mpd_path, listed.size).await? {
+                Ok(entry) => {
+                    keys.insert(entry.key());
+                    resp.imported.push(entry.key());
+                    if !dry_run {
+                        self.index.upsert(entry).await?;
+                    }
+                }
+                Err(reason) => resp.skipped.push(skip(reason)),
+            }
+        }
+        if !dry_run && !resp.imported.is_empty() {
+            tracing::info!(
+                "[recorder] imported {} recordings under {:?}, {} skipped",
+                resp.imported.len(),
+                prefix,
+                resp.skipped.len()
+            );
+        }
+        Ok(resp)
+    }
+
+    /// Dirs under `prefix` holding an MPD, with the size of their media segments
+    async fn list(&self, prefix: &str) -> Result<BTreeMap<String, Listed>> {
+        let operator = self.operator.current();
+        let prefix = match prefix.trim_matches('/') {
+            "" => "/".to_string(),
+            prefix => format!("{prefix}/"),
+        };
+        let mut dirs: BTreeMap<String, Listed> = BTreeMap::new();
+        for object in operator.list_with(&prefix).recursive(true).await? {
+            if object.metadata().is_dir() || storage::is_shared(object.path()) {
+                continue;
+            }
+            let path = object.path();
+            let Some((dir, name)) = path.rsplit_once('/') else {
+                continue;
+            };
+            let listed = dirs.entry(dir.to_string()).or_default();
+            if name.ends_with(".mpd") {
+                listed.manifests.push(path.to_string());
+            } else if is_media_segment(name) {
+                listed.size.segments += 1;
+                listed.size.bytes += object.metadata().content_length();
+            }
+        }
+        dirs.retain(|_, listed| !listed.manifests.is_empty());
+        Ok(dirs)
+    }
+
+    /// Index entry of the recording in `dir`, or why it can't be imported
+    async fn entry(
+        &self,
+        dir: &str,
+        mpd_path: &str,
+        size: RecordingSize,
+    ) -> Result<Result<RecordingIndexEntry, String>> {
+        let Some(key) = RecordingKey::from_path(mpd_path) else {
+            return Ok(Err(
+                "path is not [{namespace}/]{stream}/{record}/ with a numeric record".to_string(),
+            ));
+        };
+        let tenant = match self.tenancy.tenant_of(&key.stream) {
+            Ok(tenant) => tenant,
+            Err(e) => return Ok(Err(e)),
+        };
+        if let Some(tenant) = tenant.as_deref()
+            && !dir.starts_with(&format!("{tenant}/"))
+        {
+            return Ok(Err(format!("dir is outside the prefix of tenant {tenant}")));
+        }
+        let data = match self.operator.current().read(mpd_path).await {
+            Ok(data) => data.to_vec(),
+            // Deleted since the listing
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Err("manifest is gone".to_string()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mpd: dash::Mpd = match String::from_utf8_lossy(&data).parse() {
+            Ok(mpd) => mpd,
+            Err(e) => return Ok(Err(format!("manifest does not parse: {e}"))),
+        };
+        if mpd.mpd_type() == Some("dynamic") {
+            return Ok(Err(
+                "manifest is dynamic, the recording may still be written".to_string(),
+            ));
+        }
+        let Some(start_ts) = start_ts(&mpd, &key.record) else {
+            return Ok(Err(
+                "no start time in the manifest or the record id".to_string()
+            ));
+        };
+        let Some(duration_ms) = duration_ms(&mpd) else {
+            return Ok(Err("manifest has no duration".to_string()));
+        };
+        let now = Utc::now().timestamp_micros();
+        Ok(Ok(RecordingIndexEntry {
+            uuid: String::new(),
+            record: key.record,
+            stream: key.stream,
+            record_dir: dir.to_string(),
+            mpd_path: mpd_path.to_string(),
+            start_ts,
+            end_ts: Some(start_ts.saturating_add(i64::from(duration_ms) * 1000)),
+            duration_ms: Some(duration_ms),
+            status: RecordingStatus::Completed,
+            node_alias: self.node_alias.clone(),
+            updated_at: now,
+            note: None,
+            labels: Vec::new(),
+            continues: None,
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant,
+            size: Some(size),
+            captions: mpd.caption_languages(),
+            trigger: None,
+            imported: true,
+            seq: 0,
+        }))
+    }
+}
+
+/// Media segments, not init segments, manifests or sidecars
+fn is_media_segment(name: &str) -> bool {
+    (name.ends_with(".m4s") || name.ends_with(".mp4")) && !name.contains("init")
+}
+
+/// `availabilityStartTime`, else the record id as UNIX seconds, in UNIX microseconds
+fn start_ts(mpd: &dash::Mpd, record: &str) -> Option<i64> {
+    if let Some(start) = mpd
+        .availability_start_time()
+        .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+    {
+        return Some(start.timestamp_micros());
+    }
+    record.parse::<i64>().ok()?.checked_mul(1_000_000)
+}
+
+/// `mediaPresentationDuration`, else the end of the longest track
+fn duration_ms(mpd: &dash::Mpd) -> Option<i32> {
+    let ms = match mpd.media_presentation_duration() {
+        Some(duration) => duration.as_millis() as u64,
+        None => mpd
+            .adaptation_sets()
+            .flat_map(|set| {
+                set.representations
+                    .iter()
+                    .filter_map(|rep| set.template_of(rep))
+                    .map(|template| template.end_ms())
+            })
+            .max()?,
+    };
+    i32::try_from(ms).ok().filter(|ms| *ms > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opendal::Operator;
+    use opendal::services::Fs;
+
+    const RECORDER_MPD: &str = include_str!("../../../libs/dash/fixtures/recorder.mpd");
+
+    fn write(bucket: &std::path::Path, key: &str, data: &[u8]) {
+        let path = bucket.join(key);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, data).unwrap();
+    }
+
+    async fn setup(root: &std::path::Path) -> (Arc<RecordingsIndex>, Importer) {
+        let bucket = root.join("bucket");
+        // Two recordings of the other system, one with its start in the manifest
+        for key in ["legacy/cam/1600000000", "legacy/door/1600000100"] {
+            write(
+                &bucket,
+                &format!("{key}/manifest.mpd"),
+                RECORDER_MPD.as_bytes(),
+            );
+            write(&bucket, &format!("{key}/v_seg_0001.m4s"), &[0; 100]);
+            write(&bucket, &format!("{key}/a_seg_0001.m4s"), &[0; 20]);
+            write(&bucket, &format!("{key}/a_init.m4s"), &[0; 5]);
+        }
+        let with_start = RECORDER_MPD.replace(
+            "type=\"static\"",
+            "type=\"static\" availabilityStartTime=\"2020-09-13T12:30:00Z\"",
+        );
+        write(
+            &bucket,
+            "legacy/door/1600000100/manifest.mpd",
+            with_start.as_bytes(),
+        );
+        // Not a recording dir, and a manifest still being written
+        write(&bucket, "legacy/exports/all.mpd", RECORDER_MPD.as_bytes());
+        write(
+            &bucket,
+            "legacy/live/1600000200/manifest.mpd",
+            RECORDER_MPD
+                .replace("type=\"static\"", "type=\"dynamic\"")
+                .as_bytes(),
+        );
+
+        let index = Arc::new(
+            RecordingsIndex::load(root.join("index.json"))
+                .await
+                .unwrap(),
+        );
+        let op = Operator::new(Fs::default().root(bucket.to_str().unwrap()))
+            .unwrap()
+            .finish();
+        let importer = Importer::new(
+            index.clone(),
+            op.into(),
+            Some("edge-1".to_string()),
+            Tenancy::default(),
+        );
+        (index, importer)
+    }
+
+    #[tokio::test]
+    async fn test_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let (index, importer) = setup(dir.path()).await;
+
+        let preview = importer.import("legacy/", true).await.unwrap();
+        assert!(preview.dry_run);
+        assert_eq!(preview.imported, ["cam/1600000000", "door/1600000100"]);
+        let reasons: Vec<(&str, &str)> = preview
+            .skipped
+            .iter()
+            .map(|s| (s.mpd_path.as_str(), s.reason.split(',').next().unwrap()))
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                (
+                    "legacy/exports/all.mpd",
+                    "path is not [{namespace}/]{stream}/{record}/ with a numeric record"
+                ),
+                ("legacy/live/1600000200/manifest.mpd", "manifest is dynamic"),
+            ]
+        );
+        assert!(index.snapshot().await.unwrap().is_empty());
+
+        let resp = importer.import("legacy/", false).await.unwrap();
+        assert_eq!(resp.imported, preview.imported);
+        let cam = index.get("cam", "1600000000").await.unwrap();
+        assert!(cam.imported);
+        assert!(matches!(cam.status, RecordingStatus::Completed));
+        assert_eq!(cam.record_dir, "legacy/cam/1600000000");
+        assert_eq!(cam.mpd_path, "legacy/cam/1600000000/manifest.mpd");
+        assert_eq!(cam.start_ts, 1_600_000_000_000_000);
+        assert_eq!(cam.end_ts, Some(1_600_000_025_000_000));
+        assert_eq!(cam.duration_ms, Some(25_000));
+        assert_eq!(cam.node_alias.as_deref(), Some("edge-1"));
+        let size = cam.size.unwrap();
+        assert_eq!((size.segments, size.bytes), (2, 120));
+        let door = index.get("door", "1600000100").await.unwrap();
+        assert_eq!(door.start_ts, 1_600_000_200_000_000);
+
+        // A second run collides with what the first one added
+        let again = importer.import("legacy/", false).await.unwrap();
+        assert!(again.imported.is_empty());
+        assert_eq!(
+            again.skipped[0],
+            ImportSkip {
+                mpd_path: "legacy/cam/1600000000/manifest.mpd".to_string(),
+                key: Some("cam/1600000000".to_string()),
+                reason: "already in the index".to_string(),
+            }
+        );
+    }
+}
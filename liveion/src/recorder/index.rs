@@ -1,47 +1,188 @@
-use std::collections::HashMap;
-use std::io::Write;
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
 
 use anyhow::{Context, Result};
+pub use api::recorder::RecordingIndexEntry;
 use api::recorder::{
-    AckRecordingsRequest, DeleteRecordingsRequest, RecordingKey, RecordingSession, RecordingStatus,
+    ACK_SAMPLE_LEN, AckRecordingsRequest, AckRecordingsResponse, DeleteRecordingsRequest,
+    ListCursor, ListOrder, MediaInfo, RecorderError, RecorderEvent, RecorderEventKind,
+    RecordingKey, RecordingSession, RecordingSize, RecordingStatus, RecordingTrigger,
+    UpdateRecordingRequest, index_archive_path, page_entries, push_media_info,
 };
 use chrono::Utc;
-use fs2::FileExt;
-use serde::{Deserialize, Serialize};
-use tokio::sync::{Mutex, RwLock};
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct RecordingIndexEntry {
-    pub record: String,
-    pub stream: String,
-    pub record_dir: String,
-    pub mpd_path: String,
-    pub start_ts: i64,
-    pub end_ts: Option<i64>,
-    pub duration_ms: Option<i32>,
-    pub status: RecordingStatus,
-    pub node_alias: Option<String>,
-    pub updated_at: i64,
+use tokio::runtime::Handle;
+use tokio::sync::{Mutex, Notify, RwLock, broadcast};
+
+use super::clock::SessionEnd;
+use super::durability::Durability;
+use super::journal::{Journal, JournalChunk};
+use super::lock::{self, LockOptions};
+use crate::config::IndexLockMode;
+
+/// Buffered transitions per events subscriber before it is reported as lagged
+const EVENTS_CAPACITY: usize = 256;
+
+/// Entries appended to the log between two compactions
+const COMPACT_EVERY: usize = 200;
+
+/// Least time between two background compactions, the triggers meanwhile coalesce
+const COMPACT_COOLDOWN: Duration = Duration::from_secs(5);
+
+/// Outcome of [`RecordingsIndex::update_metadata`]
+pub enum MetadataUpdate {
+    Updated(RecordingIndexEntry),
+    NotFound,
+    Rejected(String),
+    /// `if_match` was not the entry's `updated_at`, the current entry is left as is
+    Stale(RecordingIndexEntry),
+}
+
+/// Outcome of moving an entry in or out of the trash, or purging it
+pub enum TrashUpdate {
+    Updated(RecordingIndexEntry),
+    NotFound,
+    Conflict(String),
+    /// `if_match` was not the entry's `updated_at`, the current entry is left as is
+    Stale(RecordingIndexEntry),
 }
 
-impl RecordingIndexEntry {
-    pub fn key(&self) -> String {
-        format!("{}/{}", self.stream, self.record)
+/// `updated_at` of an entry changed now. Later than the one it had even within the same
+/// microsecond, so each version of an entry has its own and `if_match` tells them apart
+fn next_version(updated_at: i64) -> i64 {
+    Utc::now().timestamp_micros().max(updated_at + 1)
+}
+
+/// Id of a new recording, see [`RecordingIndexEntry::uuid`]
+pub fn new_recording_uuid() -> String {
+    uuid::Uuid::now_v7().to_string()
+}
+
+/// Resident entries by key, and the key of each by uuid.
+///
+/// Entries are only added and removed through [`Entries::insert`] and
+/// [`Entries::remove`] so both maps stay in step; the mutable accessors must not change
+/// an entry's stream, record or uuid.
+#[derive(Default)]
+struct Entries {
+    by_key: HashMap<String, RecordingIndexEntry>,
+    by_uuid: HashMap<String, String>,
+}
+
+impl Entries {
+    /// Insert or replace the entry under its key, one without a uuid gets a new one
+    fn insert(&mut self, mut entry: RecordingIndexEntry) -> Option<RecordingIndexEntry> {
+        if entry.uuid.is_empty() {
+            entry.uuid = new_recording_uuid();
+        }
+        let key = entry.key();
+        let old = self.remove(&key);
+        self.by_uuid.insert(entry.uuid.clone(), key.clone());
+        self.by_key.insert(key, entry);
+        old
+    }
+
+    fn remove(&mut self, key: &str) -> Option<RecordingIndexEntry> {
+        let entry = self.by_key.remove(key)?;
+        if self.by_uuid.get(&entry.uuid).is_some_and(|k| k == key) {
+            self.by_uuid.remove(&entry.uuid);
+        }
+        Some(entry)
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut RecordingIndexEntry> {
+        self.by_key.get_mut(key)
+    }
+
+    fn values_mut(&mut self) -> impl Iterator<Item = &mut RecordingIndexEntry> {
+        self.by_key.values_mut()
+    }
+
+    fn get_by_uuid(&self, uuid: &str) -> Option<&RecordingIndexEntry> {
+        self.by_uuid.get(uuid).and_then(|key| self.by_key.get(key))
     }
 }
 
+impl std::ops::Deref for Entries {
+    type Target = HashMap<String, RecordingIndexEntry>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.by_key
+    }
+}
+
+impl FromIterator<RecordingIndexEntry> for Entries {
+    fn from_iter<I: IntoIterator<Item = RecordingIndexEntry>>(iter: I) -> Self {
+        let mut entries = Self::default();
+        for entry in iter {
+            entries.insert(entry);
+        }
+        entries
+    }
+}
+
+/// Index of the recordings on this node.
+///
+/// Entries liveman has acked are kept out of memory: they live in an archive file next
+/// to the index (see [`index_archive_path`]) and are read back only to delete them, for
+/// retention and for snapshots. Every other lookup sees the resident entries alone.
 pub struct RecordingsIndex {
     path: PathBuf,
-    entries: RwLock<HashMap<String, RecordingIndexEntry>>,
+    archive_path: PathBuf,
+    /// Entries that are not acked
+    entries: RwLock<Entries>,
+    /// Latest `updated_at` in the archive
+    archived_updated_at: AtomicI64,
+    /// Latest [`RecordingIndexEntry::seq`] handed out, in the log, the archive or neither
+    /// when its write failed
+    seq: AtomicU64,
     write_lock: Mutex<()>,
     write_count: AtomicUsize,
+    /// Set when the log grew by [`COMPACT_EVERY`] entries, see [`Self::run_compactor`]
+    compaction_needed: Notify,
+    /// Held by a background compaction while it runs
+    compacting: Mutex<()>,
+    /// Times the log was rewritten, a background compaction whose snapshot predates a
+    /// rewrite is dropped
+    rewrites: AtomicU64,
+    closed: AtomicBool,
+    events: broadcast::Sender<RecorderEvent>,
+    lock: LockOptions,
+    /// Runs the blocking file IO, see [`super::runtime`]
+    runtime: Handle,
+    /// When appends and rewrites reach the disk, see [`super::durability`]
+    durability: Arc<Durability>,
+    /// Generations of the log, one per rewrite
+    journal: Arc<Journal>,
 }
 
 impl RecordingsIndex {
     pub async fn load(path: PathBuf) -> Result<Self> {
+        // A crash between an append and its sync can leave part of a line behind
+        {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || -> Result<()> {
+                for path in [index_archive_path(&path), path] {
+                    let cut = drop_torn_tail(&path)?;
+                    if cut > 0 {
+                        tracing::warn!(
+                            "[recorder] dropped {} bytes of a partly written line from {}",
+                            cut,
+                            path.display()
+                        );
+                    }
+                }
+                Ok(())
+            })
+            .await??;
+        }
         let mut entries = HashMap::new();
+        // Superseded lines count too, as does the mark rewrites leave
+        let mut seq = read_seq(&seq_path_for(&path)).await;
         if let Ok(content) = tokio::fs::read_to_string(&path).await {
             let trimmed = content.trim();
             if !trimmed.is_empty() {
@@ -51,6 +192,7 @@ impl RecordingsIndex {
                             format!("Failed to parse index file: {}", path.display())
                         })?;
                     for entry in parsed {
+                        seq = seq.max(entry.seq);
                         entries.insert(entry.key(), entry);
                     }
                 } else {
@@ -63,36 +205,170 @@ impl RecordingsIndex {
                             serde_json::from_str(line).with_context(|| {
                                 format!("Failed to parse index line in {}", path.display())
                             })?;
+                        seq = seq.max(entry.seq);
                         entries.insert(entry.key(), entry);
                     }
                 }
             }
         }
 
-        Ok(Self {
+        // An entry acked since the last compaction is newer in the archive than in the log
+        let archive_path = index_archive_path(&path);
+        let (mut entries, archived_updated_at, seq) = {
+            let archive_path = archive_path.clone();
+            tokio::task::spawn_blocking(move || -> Result<_> {
+                let mut updated_at = 0;
+                let mut seq = seq;
+                for_each_archived(&archive_path, |archived| {
+                    updated_at = updated_at.max(archived.updated_at);
+                    seq = seq.max(archived.seq);
+                    let key = archived.key();
+                    if entries
+                        .get(&key)
+                        .is_some_and(|e| e.updated_at <= archived.updated_at)
+                    {
+                        entries.remove(&key);
+                    }
+                })?;
+                Ok((entries, updated_at, seq))
+            })
+            .await??
+        };
+        // Acked entries still in the log were written before the archive existed
+        let spilled: Vec<RecordingIndexEntry> = {
+            let keys: Vec<String> = entries
+                .iter()
+                .filter(|(_, e)| matches!(e.status, RecordingStatus::Acked))
+                .map(|(k, _)| k.clone())
+                .collect();
+            keys.iter().filter_map(|k| entries.remove(k)).collect()
+        };
+
+        // Entries written before recordings had a uuid get one now, the log keeps it from
+        // the next compaction on
+        let entries: Entries = entries.into_values().collect();
+
+        let index = Self {
+            journal: Arc::new(Journal::new(path.clone())),
             path,
+            archive_path,
             entries: RwLock::new(entries),
+            archived_updated_at: AtomicI64::new(archived_updated_at),
+            seq: AtomicU64::new(seq),
             write_lock: Mutex::new(()),
             write_count: AtomicUsize::new(0),
-        })
+            compaction_needed: Notify::new(),
+            compacting: Mutex::new(()),
+            rewrites: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            lock: LockOptions::default(),
+            runtime: Handle::current(),
+            durability: Arc::default(),
+        };
+        if !spilled.is_empty() {
+            index.archive(spilled).await?;
+            index.compact().await?;
+        }
+        Ok(index)
     }
 
-    pub async fn upsert(&self, entry: RecordingIndexEntry) -> Result<()> {
-        let to_append = entry.clone();
-        {
+    /// Lock the index file for writes as configured instead of with the defaults
+    pub fn with_lock_options(mut self, lock: LockOptions) -> Self {
+        self.lock = lock;
+        self
+    }
+
+    /// Run file IO on `runtime` instead of the runtime the index was loaded on
+    pub fn with_runtime(mut self, runtime: Handle) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Sync writes as `durability` says instead of each one right away
+    pub fn with_durability(mut self, durability: Arc<Durability>) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Insert or replace an entry. One without a uuid keeps the uuid of the entry it
+    /// replaces, or gets a new one
+    pub async fn upsert(&self, mut entry: RecordingIndexEntry) -> Result<()> {
+        let existed = {
             let mut map = self.entries.write().await;
-            map.insert(entry.key(), entry);
-        }
-        self.append_entries_and_maybe_compact(vec![to_append]).await
+            if entry.uuid.is_empty() {
+                entry.uuid = map
+                    .get(&entry.key())
+                    .map_or_else(new_recording_uuid, |existing| existing.uuid.clone());
+            }
+            map.insert(entry.clone()).is_some()
+        };
+        let entry = self.append_entry(entry).await?;
+        let kind = if existed {
+            RecorderEventKind::Updated
+        } else {
+            RecorderEventKind::Created
+        };
+        self.publish(kind, entry);
+        Ok(())
     }
 
+    /// Subscribe to index transitions published after this call
+    pub fn subscribe(&self) -> broadcast::Receiver<RecorderEvent> {
+        self.events.subscribe()
+    }
+
+    /// Entries written after journal position `seq`, in journal order, for resuming an
+    /// events stream. Acks are not replayed, the acking side already knows about them
+    pub async fn changed_since(&self, seq: u64) -> Vec<RecordingIndexEntry> {
+        let mut rows: Vec<RecordingIndexEntry> = {
+            let map = self.entries.read().await;
+            map.values().filter(|e| e.seq > seq).cloned().collect()
+        };
+        rows.sort_by_key(|e| e.seq);
+        rows
+    }
+
+    /// Latest journal position handed out, 0 for an index that never wrote a line
+    pub fn seq(&self) -> u64 {
+        self.seq.load(Ordering::Acquire)
+    }
+
+    /// Next journal position. Callers hold `write_lock`, so the log's lines follow it
+    fn next_seq(&self) -> u64 {
+        self.seq.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Look up the entry whose recording lives under `record_dir`
+    pub async fn find_by_dir(&self, record_dir: &str) -> Option<RecordingIndexEntry> {
+        let map = self.entries.read().await;
+        map.values().find(|e| e.record_dir == record_dir).cloned()
+    }
+
+    /// Publish an upload-complete transition for a finished recording
+    pub fn notify_uploaded(&self, entry: RecordingIndexEntry) {
+        self.publish(RecorderEventKind::Uploaded, entry);
+    }
+
+    fn publish(&self, kind: RecorderEventKind, entry: RecordingIndexEntry) {
+        // Deletions and uploads write no line, they carry the journal position they
+        // happened at so ids keep moving forward for Last-Event-ID resumes.
+        let id = match kind {
+            RecorderEventKind::Deleted | RecorderEventKind::Uploaded => self.seq(),
+            _ => entry.seq,
+        } as i64;
+        // No subscribers is not an error
+        let _ = self.events.send(RecorderEvent { id, kind, entry });
+    }
+
+    /// Record how a recording ended, with what it stored when known
     pub async fn update_status(
         &self,
         stream: &str,
         record: &str,
         status: RecordingStatus,
-        end_ts: Option<i64>,
-        duration_ms: Option<i32>,
+        end: SessionEnd,
+        size: Option<RecordingSize>,
     ) -> Result<()> {
         let mut updated: Option<RecordingIndexEntry> = None;
         {
@@ -100,24 +376,386 @@ impl RecordingsIndex {
             let key = format!("{}/{}", stream, record);
             if let Some(entry) = map.get_mut(&key) {
                 entry.status = status;
-                entry.end_ts = end_ts;
-                entry.duration_ms = duration_ms;
-                entry.updated_at = Utc::now().timestamp_micros();
+                entry.end_ts = Some(end.end_ts);
+                entry.duration_ms = Some(end.duration_ms);
+                entry.clock_skew_detected = end.clock_skew_detected;
+                entry.size = size;
+                entry.updated_at = next_version(entry.updated_at);
                 updated = Some(entry.clone());
             }
         }
         if let Some(entry) = updated {
-            self.append_entries_and_maybe_compact(vec![entry]).await?;
+            let entry = self.append_entry(entry).await?;
+            self.publish(RecorderEventKind::Status, entry);
         }
         Ok(())
     }
 
+    /// Finished entries ordered by key and strictly after `after`, for resumable scans.
+    /// Acked entries are not scanned
+    pub async fn finished_after(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Vec<RecordingIndexEntry> {
+        let mut rows: Vec<RecordingIndexEntry> = {
+            let map = self.entries.read().await;
+            map.values()
+                .filter(|e| {
+                    matches!(
+                        e.status,
+                        RecordingStatus::Completed
+                            | RecordingStatus::Failed
+                            | RecordingStatus::Interrupted
+                    )
+                })
+                .filter(|e| after.is_none_or(|after| e.key().as_str() > after))
+                .cloned()
+                .collect()
+        };
+        rows.sort_by_key(|e| e.key());
+        rows.truncate(limit);
+        rows
+    }
+
+    /// Mark an entry whose objects are gone from storage, `None` if it no longer exists
+    pub async fn mark_missing(
+        &self,
+        stream: &str,
+        record: &str,
+    ) -> Result<Option<RecordingIndexEntry>> {
+        let updated = {
+            let mut map = self.entries.write().await;
+            let key = format!("{}/{}", stream, record);
+            let Some(entry) = map.get_mut(&key) else {
+                return Ok(None);
+            };
+            entry.status = RecordingStatus::Missing;
+            entry.updated_at = next_version(entry.updated_at);
+            entry.clone()
+        };
+        let updated = self.append_entry(updated).await?;
+        self.publish(RecorderEventKind::Status, updated.clone());
+        Ok(Some(updated))
+    }
+
+    /// Mark the entries `node_alias` left active as interrupted. Called before any
+    /// recording starts, so an active entry is one a crash kept from being finalized.
+    pub async fn interrupt_active(
+        &self,
+        node_alias: Option<&str>,
+    ) -> Result<Vec<RecordingIndexEntry>> {
+        let updated: Vec<RecordingIndexEntry> = {
+            let mut map = self.entries.write().await;
+            let now = Utc::now().timestamp_micros();
+            map.values_mut()
+                .filter(|e| matches!(e.status, RecordingStatus::Active))
+                .filter(|e| e.node_alias.as_deref() == node_alias)
+                .map(|entry| {
+                    entry.status = RecordingStatus::Interrupted;
+                    entry.updated_at = now.max(entry.updated_at + 1);
+                    entry.clone()
+                })
+                .collect()
+        };
+        if updated.is_empty() {
+            return Ok(updated);
+        }
+        let updated = self.append_entries_and_maybe_compact(updated).await?;
+        for entry in &updated {
+            self.publish(RecorderEventKind::Status, entry.clone());
+        }
+        Ok(updated)
+    }
+
+    /// Record the outcome of a manifest repair: the rebuilt duration, or why the
+    /// recording cannot be repaired. `None` if the entry no longer exists
+    pub async fn record_repair(
+        &self,
+        stream: &str,
+        record: &str,
+        outcome: std::result::Result<i32, String>,
+    ) -> Result<Option<RecordingIndexEntry>> {
+        let updated = {
+            let mut map = self.entries.write().await;
+            let Some(entry) = map.get_mut(&format!("{}/{}", stream, record)) else {
+                return Ok(None);
+            };
+            match outcome {
+                Ok(duration_ms) => {
+                    entry.duration_ms = Some(duration_ms);
+                    entry.repair_error = None;
+                }
+                Err(reason) => entry.repair_error = Some(reason),
+            }
+            entry.updated_at = next_version(entry.updated_at);
+            entry.clone()
+        };
+        let updated = self.append_entry(updated).await?;
+        self.publish(RecorderEventKind::Updated, updated.clone());
+        Ok(Some(updated))
+    }
+
+    /// Move a finished entry to the trash, its objects stay until the trash is emptied.
+    /// Trashing an entry twice keeps the first `trashed_at`. With `if_match`, only while
+    /// the entry's `updated_at` is that one.
+    pub async fn trash(
+        &self,
+        stream: &str,
+        record: &str,
+        if_match: Option<i64>,
+    ) -> Result<TrashUpdate> {
+        let updated = {
+            let mut map = self.entries.write().await;
+            let Some(entry) = map.get_mut(&format!("{}/{}", stream, record)) else {
+                return Ok(TrashUpdate::NotFound);
+            };
+            if if_match.is_some_and(|version| version != entry.updated_at) {
+                return Ok(TrashUpdate::Stale(entry.clone()));
+            }
+            match entry.status {
+                RecordingStatus::Active => {
+                    return Ok(TrashUpdate::Conflict(format!(
+                        "recording {} is still being written",
+                        entry.key()
+                    )));
+                }
+                RecordingStatus::Trashed => return Ok(TrashUpdate::Updated(entry.clone())),
+                _ => {}
+            }
+            let now = next_version(entry.updated_at);
+            entry.trashed_from = Some(std::mem::replace(
+                &mut entry.status,
+                RecordingStatus::Trashed,
+            ));
+            entry.trashed_at = Some(now);
+            entry.updated_at = now;
+            entry.clone()
+        };
+        let updated = self.append_entry(updated).await?;
+        self.publish(RecorderEventKind::Status, updated.clone());
+        Ok(TrashUpdate::Updated(updated))
+    }
+
+    /// Take an entry out of the trash, back to the status it had before. With
+    /// `if_match`, only while the entry's `updated_at` is that one.
+    pub async fn restore(
+        &self,
+        stream: &str,
+        record: &str,
+        if_match: Option<i64>,
+    ) -> Result<TrashUpdate> {
+        let updated = {
+            let mut map = self.entries.write().await;
+            let Some(entry) = map.get_mut(&format!("{}/{}", stream, record)) else {
+                return Ok(TrashUpdate::NotFound);
+            };
+            if if_match.is_some_and(|version| version != entry.updated_at) {
+                return Ok(TrashUpdate::Stale(entry.clone()));
+            }
+            if !entry.is_trashed() {
+                return Ok(TrashUpdate::Conflict(format!(
+                    "recording {} is not in the trash",
+                    entry.key()
+                )));
+            }
+            entry.status = entry
+                .trashed_from
+                .take()
+                .unwrap_or(RecordingStatus::Completed);
+            entry.trashed_at = None;
+            entry.updated_at = next_version(entry.updated_at);
+            entry.clone()
+        };
+        let updated = self.append_entry(updated).await?;
+        self.publish(RecorderEventKind::Status, updated.clone());
+        Ok(TrashUpdate::Updated(updated))
+    }
+
+    /// Entries moved to the trash at or before `before` (UNIX microseconds)
+    pub async fn trashed_before(&self, before: i64) -> Vec<RecordingIndexEntry> {
+        let map = self.entries.read().await;
+        map.values()
+            .filter(|e| e.is_trashed() && e.trashed_at.is_some_and(|at| at <= before))
+            .cloned()
+            .collect()
+    }
+
+    /// Apply a user metadata patch. Last write wins, unless the patch has `if_match`:
+    /// then it only applies while the entry's `updated_at` is that one.
+    pub async fn update_metadata(
+        &self,
+        stream: &str,
+        record: &str,
+        patch: &UpdateRecordingRequest,
+    ) -> Result<MetadataUpdate> {
+        let updated = {
+            let mut map = self.entries.write().await;
+            let key = format!("{}/{}", stream, record);
+            let Some(entry) = map.get_mut(&key) else {
+                return Ok(MetadataUpdate::NotFound);
+            };
+            if patch
+                .if_match
+                .is_some_and(|version| version != entry.updated_at)
+            {
+                return Ok(MetadataUpdate::Stale(entry.clone()));
+            }
+            let mut candidate = entry.clone();
+            if let Err(reason) = patch.apply(&mut candidate) {
+                return Ok(MetadataUpdate::Rejected(reason));
+            }
+            candidate.updated_at = next_version(entry.updated_at);
+            *entry = candidate.clone();
+            candidate
+        };
+        let updated = self.append_entry(updated).await?;
+        self.publish(RecorderEventKind::Updated, updated.clone());
+        Ok(MetadataUpdate::Updated(updated))
+    }
+
+    /// Add a format snapshot to the recording under `record_dir`, see [`push_media_info`].
+    /// Returns `false` if no entry lives there yet.
+    pub async fn record_media_info(&self, record_dir: &str, info: MediaInfo) -> Result<bool> {
+        let updated = {
+            let mut map = self.entries.write().await;
+            let Some(entry) = map.values_mut().find(|e| e.record_dir == record_dir) else {
+                return Ok(false);
+            };
+            if !push_media_info(&mut entry.media_info, info) {
+                return Ok(true);
+            }
+            entry.updated_at = next_version(entry.updated_at);
+            entry.clone()
+        };
+        let updated = self.append_entry(updated).await?;
+        self.publish(RecorderEventKind::Updated, updated);
+        Ok(true)
+    }
+
+    /// Add `lang` to the caption languages of `stream/record`, `None` if not indexed
+    pub async fn add_captions(
+        &self,
+        stream: &str,
+        record: &str,
+        lang: &str,
+    ) -> Result<Option<RecordingIndexEntry>> {
+        let updated = {
+            let mut map = self.entries.write().await;
+            let Some(entry) = map.get_mut(&format!("{}/{}", stream, record)) else {
+                return Ok(None);
+            };
+            if entry.captions.iter().any(|l| l == lang) {
+                return Ok(Some(entry.clone()));
+            }
+            entry.captions.push(lang.to_string());
+            entry.captions.sort();
+            entry.updated_at = next_version(entry.updated_at);
+            entry.clone()
+        };
+        let updated = self.append_entry(updated).await?;
+        self.publish(RecorderEventKind::Updated, updated.clone());
+        Ok(Some(updated))
+    }
+
+    /// Record the triggers of `stream/record`, see `recorder.triggers`
+    pub async fn set_trigger(
+        &self,
+        stream: &str,
+        record: &str,
+        trigger: RecordingTrigger,
+    ) -> Result<Option<RecordingIndexEntry>> {
+        let updated = {
+            let mut map = self.entries.write().await;
+            let Some(entry) = map.get_mut(&format!("{}/{}", stream, record)) else {
+                return Ok(None);
+            };
+            entry.trigger = Some(trigger);
+            entry.updated_at = next_version(entry.updated_at);
+            entry.clone()
+        };
+        let updated = self.append_entry(updated).await?;
+        self.publish(RecorderEventKind::Updated, updated.clone());
+        Ok(Some(updated))
+    }
+
+    /// Entries of one stream ordered by record
+    pub async fn entries_of(&self, stream: &str) -> Vec<RecordingIndexEntry> {
+        let mut rows: Vec<RecordingIndexEntry> = {
+            let map = self.entries.read().await;
+            map.values()
+                .filter(|e| e.stream == stream)
+                .cloned()
+                .collect()
+        };
+        rows.sort_by(|a, b| a.record.cmp(&b.record));
+        rows
+    }
+
+    pub async fn get(&self, stream: &str, record: &str) -> Option<RecordingIndexEntry> {
+        let map = self.entries.read().await;
+        map.get(&format!("{}/{}", stream, record)).cloned()
+    }
+
+    /// Look up a resident entry by [`RecordingIndexEntry::uuid`]
+    pub async fn get_by_uuid(&self, uuid: &str) -> Option<RecordingIndexEntry> {
+        let map = self.entries.read().await;
+        map.get_by_uuid(uuid).cloned()
+    }
+
+    pub async fn contains(&self, stream: &str, record: &str) -> bool {
+        let map = self.entries.read().await;
+        map.contains_key(&format!("{}/{}", stream, record))
+    }
+
+    /// Move an entry to stream `to` with new object locations, `None` if it no longer exists.
+    ///
+    /// Fails when `to` already has a recording with the same id. The index is compacted
+    /// right away so the old key is not restored from the log on the next load.
+    pub async fn rename_entry(
+        &self,
+        stream: &str,
+        record: &str,
+        to: &str,
+        record_dir: String,
+        mpd_path: String,
+    ) -> Result<Option<RecordingIndexEntry>> {
+        let guard = self.write_lock.lock().await;
+        let (old, renamed) = {
+            let mut map = self.entries.write().await;
+            let target = format!("{}/{}", to, record);
+            if map.contains_key(&target) {
+                anyhow::bail!("recording {} already exists", target);
+            }
+            let Some(old) = map.remove(&format!("{}/{}", stream, record)) else {
+                return Ok(None);
+            };
+            let mut renamed = old.clone();
+            renamed.stream = to.to_string();
+            renamed.record_dir = record_dir;
+            renamed.mpd_path = mpd_path;
+            renamed.updated_at = Utc::now().timestamp_micros();
+            renamed.seq = self.next_seq();
+            // Same uuid under the new key
+            map.insert(renamed.clone());
+            (old, renamed)
+        };
+        self.compact().await?;
+        drop(guard);
+        self.publish(RecorderEventKind::Deleted, old);
+        self.publish(RecorderEventKind::Created, renamed.clone());
+        Ok(Some(renamed))
+    }
+
     pub async fn list_sessions(
         &self,
         stream: Option<String>,
         since_ts: Option<i64>,
         limit: u32,
-    ) -> (Vec<RecordingSession>, Option<i64>) {
+        order: ListOrder,
+        cursor: Option<&ListCursor>,
+        include_trashed: bool,
+    ) -> (Vec<RecordingSession>, Option<i64>, Option<ListCursor>) {
         let limit = if limit == 0 { 100 } else { limit } as usize;
         let mut rows: Vec<RecordingIndexEntry> = {
             let map = self.entries.read().await;
@@ -133,165 +771,776 @@ impl RecordingsIndex {
         }
 
         rows.retain(|r| !matches!(r.status, RecordingStatus::Acked));
-        rows.sort_by(|a, b| a.updated_at.cmp(&b.updated_at));
-        if rows.len() > limit {
-            rows.truncate(limit);
+        if !include_trashed {
+            rows.retain(|r| !r.is_trashed());
         }
+        let (rows, next_cursor) = page_entries(rows, order, cursor, limit);
 
         let last_ts = rows.iter().map(|r| r.updated_at).max();
         let sessions = rows
             .into_iter()
             .map(|r| RecordingSession {
                 id: Some(r.record.clone()),
+                uuid: Some(r.uuid),
                 stream: r.stream,
                 start_ts: r.start_ts,
                 end_ts: r.end_ts,
                 duration_ms: r.duration_ms,
                 mpd_path: r.mpd_path,
                 status: r.status,
+                note: r.note,
+                labels: r.labels,
+                continues: r.continues,
+                media_info: r.media_info,
+                retention_class: r.retention_class,
+                priority: r.priority,
+                trashed_at: r.trashed_at,
+                source: r.source,
+                tenant: r.tenant,
             })
             .collect();
 
-        (sessions, last_ts)
+        (sessions, last_ts, next_cursor)
     }
 
-    pub async fn ack(&self, req: AckRecordingsRequest) -> Result<usize> {
-        let mut acked = 0usize;
-        let records = req.records;
+    /// Mark entries acked and move them from memory to the archive: the listed keys
+    /// and the resident entries the filter matches, expanded under the write lock. A
+    /// listed key whose `if_match` is stale fails the request, nothing is acked
+    pub async fn ack(&self, req: AckRecordingsRequest) -> Result<AckRecordingsResponse> {
+        let guard = self.write_lock.lock().await;
+        // (acked entry, `updated_at` of the resident one it replaces)
+        let acked: Vec<(RecordingIndexEntry, i64)> = {
+            let map = self.entries.read().await;
+            version_mismatch(&req.records, |key| map.get(key))?;
+            let now = Utc::now().timestamp_micros();
+            let mut selected: HashMap<&String, &RecordingIndexEntry> = req
+                .records
+                .iter()
+                .filter_map(|RecordingKey { stream, record, .. }| {
+                    map.get_key_value(&format!("{}/{}", stream, record))
+                })
+                .collect();
+            if let Some(filter) = req.filter.as_ref() {
+                selected.extend(map.iter().filter(|(_, entry)| filter.matches(entry)));
+            }
+            selected
+                .into_values()
+                // Trashed entries stay on the node until the trash is emptied
+                .filter(|entry| !entry.is_trashed())
+                .map(|entry| {
+                    let mut acked = entry.clone();
+                    acked.status = RecordingStatus::Acked;
+                    acked.updated_at = now.max(entry.updated_at + 1);
+                    acked.seq = self.next_seq();
+                    (acked, entry.updated_at)
+                })
+                .collect()
+        };
+        if acked.is_empty() {
+            return Ok(AckRecordingsResponse::default());
+        }
+
+        // The archive line is newer than the entry's last line in the log, which the
+        // next compaction drops
+        self.archive(acked.iter().map(|(entry, _)| entry.clone()).collect())
+            .await?;
         {
             let mut map = self.entries.write().await;
-            for RecordingKey { stream, record } in &records {
-                let key = format!("{}/{}", stream, record);
-                if let Some(entry) = map.get_mut(&key) {
-                    entry.status = RecordingStatus::Acked;
-                    entry.updated_at = Utc::now().timestamp_micros();
-                    acked += 1;
+            for (entry, updated_at) in &acked {
+                // Unless it changed meanwhile, then the resident entry is the newer one
+                if map
+                    .get(&entry.key())
+                    .is_some_and(|e| e.updated_at == *updated_at)
+                {
+                    map.remove(&entry.key());
                 }
             }
         }
+        drop(guard);
+        let mut sample: Vec<String> = acked.iter().map(|(entry, _)| entry.key()).collect();
+        sample.sort();
+        sample.truncate(ACK_SAMPLE_LEN);
+        let resp = AckRecordingsResponse {
+            acked: acked.len(),
+            max_updated_at: acked.iter().map(|(_, updated_at)| *updated_at).max(),
+            sample,
+        };
+        for (entry, _) in acked {
+            self.publish(RecorderEventKind::Status, entry);
+        }
+        Ok(resp)
+    }
 
-        if acked > 0 {
-            let entries = {
-                let map = self.entries.read().await;
-                records
-                    .iter()
-                    .filter_map(|key| map.get(&format!("{}/{}", key.stream, key.record)).cloned())
-                    .collect::<Vec<_>>()
-            };
-            if !entries.is_empty() {
-                self.append_entries_and_maybe_compact(entries).await?;
+    /// Delete acked entries, reading the archive back to find them. Returns the keys
+    /// deleted. A listed key whose `if_match` is stale fails the request, nothing is
+    /// deleted
+    pub async fn delete_acked(&self, req: DeleteRecordingsRequest) -> Result<Vec<String>> {
+        let keys = request_keys(&req);
+        let removed = {
+            let _guard = self.write_lock.lock().await;
+            if req.records.iter().any(|key| key.if_match.is_some()) {
+                // Archived entries only change under the write lock
+                let listed = keys.clone();
+                let archived: HashMap<String, RecordingIndexEntry> = self
+                    .archived(move |entry| listed.contains(&entry.key()))
+                    .await?
+                    .into_iter()
+                    .map(|entry| (entry.key(), entry))
+                    .collect();
+                version_mismatch(&req.records, |key| archived.get(key))?;
             }
+            self.rewrite_archive(move |entry| !keys.contains(&entry.key()))
+                .await?
+        };
+        let mut deleted: Vec<String> = removed.iter().map(|e| e.key()).collect();
+        deleted.sort();
+        for entry in removed {
+            self.publish(RecorderEventKind::Deleted, entry);
         }
+        Ok(deleted)
+    }
 
+    /// Keys [`Self::delete_acked`] would delete
+    pub async fn acked_keys(&self, req: &DeleteRecordingsRequest) -> Result<Vec<String>> {
+        let keys = request_keys(req);
+        let mut acked: Vec<String> = self
+            .archived(move |entry| keys.contains(&entry.key()))
+            .await?
+            .iter()
+            .map(|e| e.key())
+            .collect();
+        acked.sort();
         Ok(acked)
     }
 
-    pub async fn delete_acked(&self, req: DeleteRecordingsRequest) -> Result<usize> {
-        let mut removed = 0usize;
-        {
-            let mut map = self.entries.write().await;
-            for RecordingKey { stream, record } in req.records {
-                let key = format!("{}/{}", stream, record);
-                if let Some(entry) = map.get(&key)
-                    && matches!(entry.status, RecordingStatus::Acked)
-                {
-                    map.remove(&key);
-                    removed += 1;
+    /// Finished recordings whose retention class expired by `now` (UNIX microseconds),
+    /// acked ones included
+    pub async fn expired(&self, now: i64) -> Result<Vec<RecordingIndexEntry>> {
+        let expired = move |e: &RecordingIndexEntry| {
+            !matches!(e.status, RecordingStatus::Active)
+                && match (&e.retention_class, e.effective_end_ts()) {
+                    (Some(class), Some(end_ts)) => class.expired(end_ts, now),
+                    _ => false,
                 }
+        };
+        let mut entries: Vec<RecordingIndexEntry> = {
+            let map = self.entries.read().await;
+            map.values().filter(|e| expired(e)).cloned().collect()
+        };
+        entries.extend(self.archived(expired).await?);
+        Ok(entries)
+    }
+
+    /// Every entry, acked ones included, lowest priority first and oldest first within
+    /// a priority
+    pub async fn in_deletion_order(&self) -> Result<Vec<RecordingIndexEntry>> {
+        let mut entries = self.archived(|_| true).await?;
+        entries.extend(self.entries.read().await.values().cloned());
+        entries.sort_by(|a, b| {
+            a.priority
+                .cmp(&b.priority)
+                .then(a.start_ts.cmp(&b.start_ts))
+                .then_with(|| a.key().cmp(&b.key()))
+        });
+        Ok(entries)
+    }
+
+    /// Drop an entry regardless of its status, publishing `deleted`
+    pub async fn remove(&self, stream: &str, record: &str) -> Result<bool> {
+        let key = format!("{}/{}", stream, record);
+        let removed = {
+            let mut map = self.entries.write().await;
+            map.remove(&key)
+        };
+        let entry = match removed {
+            Some(entry) => {
+                let _guard = self.write_lock.lock().await;
+                self.compact().await?;
+                entry
             }
-        }
+            None => {
+                let _guard = self.write_lock.lock().await;
+                let removed = self.rewrite_archive(move |e| e.key() != key).await?;
+                let Some(entry) = removed.into_iter().next() else {
+                    return Ok(false);
+                };
+                entry
+            }
+        };
+        self.publish(RecorderEventKind::Deleted, entry);
+        Ok(true)
+    }
 
-        if removed > 0 {
-            self.compact().await?;
-        }
+    /// Archived entries matching `keep` that have no newer resident copy
+    async fn archived(
+        &self,
+        keep: impl Fn(&RecordingIndexEntry) -> bool + Send + 'static,
+    ) -> Result<Vec<RecordingIndexEntry>> {
+        let path = self.archive_path.clone();
+        let matched = self
+            .runtime
+            .spawn_blocking(move || -> Result<_> {
+                let mut matched = HashMap::new();
+                for_each_archived(&path, |entry| {
+                    // Later lines win, including over an earlier line that matched
+                    if keep(&entry) {
+                        matched.insert(entry.key(), entry);
+                    } else {
+                        matched.remove(&entry.key());
+                    }
+                })?;
+                Ok(matched)
+            })
+            .await??;
+        let map = self.entries.read().await;
+        Ok(matched
+            .into_iter()
+            .filter(|(key, _)| !map.contains_key(key))
+            .map(|(_, entry)| entry)
+            .collect())
+    }
 
-        Ok(removed)
+    /// Append acked entries to the archive. Callers hold `write_lock`.
+    async fn archive(&self, entries: Vec<RecordingIndexEntry>) -> Result<()> {
+        let updated_at = entries.iter().map(|e| e.updated_at).max().unwrap_or(0);
+        let path = self.path.clone();
+        let archive_path = self.archive_path.clone();
+        let lock = self.lock;
+        let durability = self.durability.clone();
+        let lines: Vec<String> = entries
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.runtime
+            .spawn_blocking(move || -> Result<()> {
+                if let Some(parent) = archive_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let _lock = lock_file(&path, lock)?;
+                let mut file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&archive_path)?;
+                for line in lines {
+                    writeln!(file, "{}", line)?;
+                }
+                durability.appended(&file, &archive_path)?;
+                Ok(())
+            })
+            .await??;
+        self.archived_updated_at
+            .fetch_max(updated_at, Ordering::Relaxed);
+        Ok(())
     }
 
+    /// Rewrite the archive with the entries matching `keep`, one line each, returning
+    /// the others. Callers hold `write_lock`.
+    async fn rewrite_archive(
+        &self,
+        keep: impl Fn(&RecordingIndexEntry) -> bool + Send + 'static,
+    ) -> Result<Vec<RecordingIndexEntry>> {
+        let resident: HashSet<String> = self.entries.read().await.keys().cloned().collect();
+        let path = self.path.clone();
+        let archive_path = self.archive_path.clone();
+        let lock = self.lock;
+        let seq = self.seq();
+        let durability = self.durability.clone();
+        self.runtime
+            .spawn_blocking(move || -> Result<Vec<RecordingIndexEntry>> {
+                let mut archived = HashMap::new();
+                for_each_archived(&archive_path, |entry| {
+                    archived.insert(entry.key(), entry);
+                })?;
+                // Stale copies of entries that are resident again are dropped, not returned
+                archived.retain(|key, _| !resident.contains(key));
+                let (kept, removed): (Vec<_>, Vec<_>) =
+                    archived.into_values().partition(|entry| keep(entry));
+                if removed.is_empty() {
+                    return Ok(removed);
+                }
+                let _lock = lock_file(&path, lock)?;
+                write_seq(&path, seq, &durability)?;
+                write_lines(&archive_path, kept, &durability)?;
+                Ok(removed)
+            })
+            .await?
+    }
+
+    /// Append entries cloned from resident ones, each with the next journal position.
+    /// Returns them stamped, the resident entries get the same `seq` unless they
+    /// changed meanwhile: the append of that change stamps them
     async fn append_entries_and_maybe_compact(
         &self,
-        entries: Vec<RecordingIndexEntry>,
-    ) -> Result<()> {
+        mut entries: Vec<RecordingIndexEntry>,
+    ) -> Result<Vec<RecordingIndexEntry>> {
         if entries.is_empty() {
-            return Ok(());
+            return Ok(entries);
         }
         let _guard = self.write_lock.lock().await;
+        for entry in &mut entries {
+            entry.seq = self.next_seq();
+        }
         self.append_entries(entries.clone()).await?;
+        {
+            let mut map = self.entries.write().await;
+            for entry in &entries {
+                if let Some(resident) = map.get_mut(&entry.key())
+                    && resident.updated_at == entry.updated_at
+                {
+                    resident.seq = resident.seq.max(entry.seq);
+                }
+            }
+        }
 
-        let count = self.write_count.fetch_add(entries.len(), Ordering::Relaxed) + entries.len();
-        if count.is_multiple_of(200) {
-            self.compact().await?;
+        // The compactor rewrites the log, the append doesn't wait for it
+        let before = self.write_count.fetch_add(entries.len(), Ordering::Relaxed);
+        if (before + entries.len()) / COMPACT_EVERY > before / COMPACT_EVERY {
+            self.compaction_needed.notify_one();
+        }
+        Ok(entries)
+    }
+
+    async fn append_entry(&self, entry: RecordingIndexEntry) -> Result<RecordingIndexEntry> {
+        let mut stamped = self.append_entries_and_maybe_compact(vec![entry]).await?;
+        Ok(stamped.remove(0))
+    }
+
+    /// Compact the log whenever appends ask for it, at most once per
+    /// [`COMPACT_COOLDOWN`], until [`Self::close`]
+    pub async fn run_compactor(self: Arc<Self>) {
+        loop {
+            self.compaction_needed.notified().await;
+            {
+                let _compacting = self.compacting.lock().await;
+                if self.closed.load(Ordering::Acquire) {
+                    return;
+                }
+                let started = std::time::Instant::now();
+                match self.compact_in_background().await {
+                    Ok(true) => {
+                        tracing::debug!("[recorder] index compacted in {:?}", started.elapsed())
+                    }
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!("[recorder] index compaction failed: {:#}", e),
+                }
+            }
+            tokio::time::sleep(COMPACT_COOLDOWN).await;
+        }
+    }
+
+    /// Stop the compactor, waiting for a compaction in flight to finish. Appends still
+    /// work, the log is compacted on the next load
+    pub async fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.compaction_needed.notify_one();
+        let _compacting = self.compacting.lock().await;
+        let durability = self.durability.clone();
+        match self
+            .runtime
+            .spawn_blocking(move || durability.flush())
+            .await
+        {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => tracing::warn!("[recorder] index flush on close failed: {}", e),
+            Err(e) => tracing::warn!("[recorder] index flush on close panicked: {}", e),
         }
-        Ok(())
+    }
+
+    /// Rewrite the log without holding appends back for the rewrite: write a snapshot of
+    /// the resident entries to a side file, then under the write lock replay the lines
+    /// appended meanwhile onto it and swap it in. `false` when the log was rewritten
+    /// in between and the snapshot is dropped.
+    async fn compact_in_background(&self) -> Result<bool> {
+        // Every line up to `offset` is reflected in the entries cloned afterwards,
+        // lines after it are replayed over them
+        let (offset, rewrites) = {
+            let _guard = self.write_lock.lock().await;
+            let path = self.path.clone();
+            let lock = self.lock;
+            let offset = self
+                .runtime
+                .spawn_blocking(move || -> Result<u64> {
+                    let _lock = lock_file(&path, lock)?;
+                    log_len(&path)
+                })
+                .await??;
+            (offset, self.rewrites.load(Ordering::Acquire))
+        };
+        let entries: Vec<RecordingIndexEntry> =
+            self.entries.read().await.values().cloned().collect();
+        let snapshot_path = snapshot_path_for(&self.path);
+        {
+            let snapshot_path = snapshot_path.clone();
+            self.runtime
+                .spawn_blocking(move || -> Result<()> {
+                    let mut entries = entries;
+                    entries.sort_by(|a, b| a.stream.cmp(&b.stream).then(a.record.cmp(&b.record)));
+                    write_unrenamed(&snapshot_path, entries).map(drop)
+                })
+                .await??;
+        }
+
+        let _guard = self.write_lock.lock().await;
+        if self.rewrites.load(Ordering::Acquire) != rewrites {
+            let _ = tokio::fs::remove_file(&snapshot_path).await;
+            return Ok(false);
+        }
+        let path = self.path.clone();
+        let lock = self.lock;
+        let durability = self.durability.clone();
+        let journal = self.journal.clone();
+        let swapped = self
+            .runtime
+            .spawn_blocking(move || -> Result<bool> {
+                let _lock = lock_file(&path, lock)?;
+                let len = log_len(&path)?;
+                // Rewritten by another process meanwhile
+                if len < offset {
+                    let _ = std::fs::remove_file(&snapshot_path);
+                    return Ok(false);
+                }
+                let mut snapshot = std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&snapshot_path)?;
+                if len > offset {
+                    let mut log = std::fs::File::open(&path)?;
+                    log.seek(SeekFrom::Start(offset))?;
+                    std::io::copy(&mut log, &mut snapshot)?;
+                }
+                durability.before_rename(&snapshot)?;
+                journal.replace(|| replace_with(&snapshot_path, &path, &durability))?;
+                Ok(true)
+            })
+            .await??;
+        if swapped {
+            self.rewrites.fetch_add(1, Ordering::AcqRel);
+        }
+        Ok(swapped)
     }
 
     async fn append_entries(&self, entries: Vec<RecordingIndexEntry>) -> Result<()> {
         let path = self.path.clone();
+        let lock = self.lock;
+        let durability = self.durability.clone();
         let lines: Vec<String> = entries
             .into_iter()
             .map(|entry| serde_json::to_string(&entry))
             .collect::<Result<Vec<_>, _>>()?;
-        tokio::task::spawn_blocking(move || -> Result<()> {
-            if let Some(parent) = path.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
-            let _lock = lock_file(&path)?;
-            let mut file = std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&path)?;
-            for line in lines {
-                writeln!(file, "{}", line)?;
-            }
-            file.sync_data()?;
-            sync_parent_dir(&path)?;
-            Ok(())
-        })
-        .await??;
+        self.runtime
+            .spawn_blocking(move || -> Result<()> {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let _lock = lock_file(&path, lock)?;
+                let mut file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)?;
+                for line in lines {
+                    writeln!(file, "{}", line)?;
+                }
+                durability.appended(&file, &path)?;
+                Ok(())
+            })
+            .await??;
         Ok(())
     }
 
-    async fn compact(&self) -> Result<()> {
-        let entries = {
-            let map = self.entries.read().await;
-            let mut values: Vec<RecordingIndexEntry> = map.values().cloned().collect();
-            values.sort_by(|a, b| a.stream.cmp(&b.stream).then(a.record.cmp(&b.record)));
-            values
+    /// Complete lines of the log from `offset`, up to `limit` bytes, see
+    /// [`super::journal`]. Neither waits for writes nor holds them back
+    pub async fn read_journal(&self, offset: u64, limit: u64) -> Result<JournalChunk> {
+        let journal = self.journal.clone();
+        Ok(self
+            .runtime
+            .spawn_blocking(move || journal.read(offset, limit))
+            .await??)
+    }
+
+    /// All entries, acked ones included, ordered by stream and record
+    pub async fn snapshot(&self) -> Result<Vec<RecordingIndexEntry>> {
+        let mut values = self.archived(|_| true).await?;
+        values.extend(self.entries.read().await.values().cloned());
+        values.sort_by(|a, b| a.stream.cmp(&b.stream).then(a.record.cmp(&b.record)));
+        Ok(values)
+    }
+
+    /// Entries held in memory, acked ones are archived
+    #[cfg(test)]
+    pub async fn resident_len(&self) -> usize {
+        self.entries.read().await.len()
+    }
+
+    /// Replace every entry, e.g. with a restored backup, without publishing events.
+    /// Returns the number of entries replaced.
+    pub async fn replace_all(&self, entries: Vec<RecordingIndexEntry>) -> Result<usize> {
+        let _guard = self.write_lock.lock().await;
+        let archived = self.archived(|_| true).await?.len();
+        let (acked, resident): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|e| matches!(e.status, RecordingStatus::Acked));
+        let archived_updated_at = acked.iter().map(|e| e.updated_at).max().unwrap_or(0);
+        // A restored entry keeps its position, later writes go past every one
+        let seq = acked.iter().chain(&resident).map(|e| e.seq).max();
+        self.seq.fetch_max(seq.unwrap_or(0), Ordering::AcqRel);
+        let replaced = {
+            let mut map = self.entries.write().await;
+            let replaced = map.len();
+            *map = resident.into_iter().collect();
+            replaced
         };
+        write_archive(
+            &self.path,
+            &self.archive_path,
+            self.lock,
+            &self.runtime,
+            &self.durability,
+            acked,
+        )
+        .await?;
+        self.archived_updated_at
+            .store(archived_updated_at, Ordering::Relaxed);
+        self.compact().await?;
+        Ok(replaced + archived)
+    }
+
+    /// Latest `updated_at` of any entry, 0 for an empty index
+    pub async fn last_updated_at(&self) -> i64 {
+        let map = self.entries.read().await;
+        map.values()
+            .map(|e| e.updated_at)
+            .max()
+            .unwrap_or(0)
+            .max(self.archived_updated_at.load(Ordering::Relaxed))
+    }
+
+    /// Rewrite the log with one line per resident entry, the archive is left as is
+    async fn compact(&self) -> Result<()> {
+        let mut entries: Vec<RecordingIndexEntry> =
+            self.entries.read().await.values().cloned().collect();
+        entries.sort_by(|a, b| a.stream.cmp(&b.stream).then(a.record.cmp(&b.record)));
         self.compact_with_entries(entries).await
     }
 
     async fn compact_with_entries(&self, entries: Vec<RecordingIndexEntry>) -> Result<()> {
         let path = self.path.clone();
-        tokio::task::spawn_blocking(move || -> Result<()> {
-            if let Some(parent) = path.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
-            let _lock = lock_file(&path)?;
-            let tmp_path = tmp_path_for(&path);
-            let mut file = std::fs::OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(&tmp_path)?;
-            for entry in entries {
-                let line = serde_json::to_string(&entry)?;
-                writeln!(file, "{}", line)?;
-            }
-            file.sync_data()?;
-            if std::fs::metadata(&path).is_ok() {
-                let _ = std::fs::remove_file(&path);
-            }
-            std::fs::rename(&tmp_path, &path)
-                .with_context(|| format!("Failed to replace index file {}", path.display()))?;
-            sync_parent_dir(&path)?;
-            Ok(())
-        })
-        .await??;
+        let lock = self.lock;
+        let seq = self.seq();
+        let durability = self.durability.clone();
+        let journal = self.journal.clone();
+        self.runtime
+            .spawn_blocking(move || -> Result<()> {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let _lock = lock_file(&path, lock)?;
+                write_seq(&path, seq, &durability)?;
+                let tmp_path = tmp_path_for(&path);
+                let file = write_unrenamed(&tmp_path, entries)?;
+                durability.before_rename(&file)?;
+                journal.replace(|| replace_with(&tmp_path, &path, &durability))
+            })
+            .await??;
+        self.rewrites.fetch_add(1, Ordering::AcqRel);
         Ok(())
     }
 }
 
+fn request_keys(req: &DeleteRecordingsRequest) -> HashSet<String> {
+    req.records
+        .iter()
+        .map(|RecordingKey { stream, record, .. }| format!("{}/{}", stream, record))
+        .collect()
+}
+
+/// First listed key whose `if_match` is not the `updated_at` of its entry in `entries`,
+/// as the error failing the whole request. Keys without an entry there are not checked
+fn version_mismatch<'a>(
+    keys: &[RecordingKey],
+    entry: impl Fn(&str) -> Option<&'a RecordingIndexEntry>,
+) -> Result<()> {
+    for key in keys {
+        let Some(if_match) = key.if_match else {
+            continue;
+        };
+        if let Some(current) = entry(&format!("{}/{}", key.stream, key.record))
+            && current.updated_at != if_match
+        {
+            return Err(RecorderError::version_mismatch(if_match, current.clone()).into());
+        }
+    }
+    Ok(())
+}
+
+/// Replace the archive with `entries`, one line each
+async fn write_archive(
+    path: &Path,
+    archive_path: &Path,
+    lock: LockOptions,
+    runtime: &Handle,
+    durability: &Arc<Durability>,
+    entries: Vec<RecordingIndexEntry>,
+) -> Result<()> {
+    let path = path.to_path_buf();
+    let archive_path = archive_path.to_path_buf();
+    let durability = durability.clone();
+    runtime
+        .spawn_blocking(move || -> Result<()> {
+            let _lock = lock_file(&path, lock)?;
+            write_lines(&archive_path, entries, &durability)
+        })
+        .await?
+}
+
+/// Replace `path` with one JSON line per entry, through a temporary file
+fn write_lines(
+    path: &Path,
+    entries: Vec<RecordingIndexEntry>,
+    durability: &Durability,
+) -> Result<()> {
+    let tmp_path = tmp_path_for(path);
+    let file = write_unrenamed(&tmp_path, entries)?;
+    durability.before_rename(&file)?;
+    replace_with(&tmp_path, path, durability)
+}
+
+/// Write one JSON line per entry to `path`, returning the file unsynced
+fn write_unrenamed(path: &Path, entries: Vec<RecordingIndexEntry>) -> Result<std::fs::File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::io::BufWriter::new(
+        std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?,
+    );
+    for entry in entries {
+        let line = serde_json::to_string(&entry)?;
+        writeln!(file, "{}", line)?;
+    }
+    Ok(file.into_inner().map_err(|e| e.into_error())?)
+}
+
+/// Keep journal position `seq` next to the log at `path`. Written before a rewrite
+/// that may drop the line holding it, e.g. of a removed entry, so a reload never hands
+/// it out again
+fn write_seq(path: &Path, seq: u64, durability: &Durability) -> Result<()> {
+    let seq_path = seq_path_for(path);
+    let tmp_path = tmp_path_for(&seq_path);
+    let mut file = std::fs::File::create(&tmp_path)?;
+    writeln!(file, "{}", seq)?;
+    durability.before_rename(&file)?;
+    replace_with(&tmp_path, &seq_path, durability)
+}
+
+/// Journal position [`write_seq`] left, 0 when there is none or it can't be read
+async fn read_seq(seq_path: &Path) -> u64 {
+    tokio::fs::read_to_string(seq_path)
+        .await
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Move `from` over `path`, synced by the caller as `durability` says
+fn replace_with(from: &Path, path: &Path, durability: &Durability) -> Result<()> {
+    if std::fs::metadata(path).is_ok() {
+        let _ = std::fs::remove_file(path);
+    }
+    std::fs::rename(from, path)
+        .with_context(|| format!("Failed to replace index file {}", path.display()))?;
+    durability.renamed(path);
+    Ok(())
+}
+
+/// Cut a partial last line off the log at `path`, returning the bytes cut. Lines are
+/// only complete with their newline, a log written as one JSON array is left as is
+fn drop_torn_tail(path: &Path) -> Result<u64> {
+    const BLOCK: u64 = 8192;
+    let mut file = match std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+    {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let len = file.metadata()?.len();
+    let mut head = [0; 64];
+    let n = file.read(&mut head)?;
+    if len == 0 || head[..n].iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'[') {
+        return Ok(0);
+    }
+    // Scan back from the end for the newline of the last complete line
+    let mut keep = 0;
+    let mut end = len;
+    let mut buf = vec![0; BLOCK as usize];
+    while end > 0 {
+        let start = end.saturating_sub(BLOCK);
+        let block = &mut buf[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(block)?;
+        if let Some(i) = block.iter().rposition(|b| *b == b'\n') {
+            keep = start + i as u64 + 1;
+            break;
+        }
+        end = start;
+    }
+    if keep == len {
+        return Ok(0);
+    }
+    file.set_len(keep)?;
+    file.sync_data()?;
+    Ok(len - keep)
+}
+
+/// Length of the log at `path`, 0 when there is none yet
+fn log_len(path: &Path) -> Result<u64> {
+    match std::fs::metadata(path) {
+        Ok(meta) => Ok(meta.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Call `f` with each line of the archive at `path`, oldest first. A missing archive
+/// has no lines.
+fn for_each_archived(path: &Path, mut f: impl FnMut(RecordingIndexEntry)) -> Result<()> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let entry: RecordingIndexEntry = serde_json::from_str(line)
+            .with_context(|| format!("Failed to parse archive line in {}", path.display()))?;
+        f(entry);
+    }
+    Ok(())
+}
+
+/// Side file of a background compaction, apart from the temporary file of
+/// [`write_lines`] which a compaction under the write lock may be writing meanwhile
+fn snapshot_path_for(path: &Path) -> PathBuf {
+    let mut snapshot = tmp_path_for(path).into_os_string();
+    snapshot.push(".compacting");
+    PathBuf::from(snapshot)
+}
+
+/// High-water mark of the journal positions, see [`write_seq`]
+fn seq_path_for(path: &Path) -> PathBuf {
+    let mut seq = path.as_os_str().to_os_string();
+    seq.push(".seq");
+    PathBuf::from(seq)
+}
+
 fn tmp_path_for(path: &Path) -> PathBuf {
     let mut tmp = path.to_path_buf();
     if let Some(ext) = path.extension() {
@@ -304,35 +1553,754 @@ fn tmp_path_for(path: &Path) -> PathBuf {
     tmp
 }
 
-fn lock_file(path: &Path) -> Result<std::fs::File> {
-    let lock_path = lock_path_for(path);
-    if let Some(parent) = lock_path.parent() {
-        std::fs::create_dir_all(parent)?;
+/// Write lock of the index file, with a lease the owner writes alone and needs none
+pub(super) fn lock_file(path: &Path, lock: LockOptions) -> Result<Option<std::fs::File>> {
+    match lock.mode {
+        IndexLockMode::Flock => {
+            lock::lock_exclusive(&lock::lock_path_for(path), lock.timeout).map(Some)
+        }
+        IndexLockMode::Lease => Ok(None),
     }
-    let file = std::fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(&lock_path)?;
-    file.lock_exclusive()?;
-    Ok(file)
 }
 
-fn lock_path_for(path: &Path) -> PathBuf {
-    let mut lock_path = path.to_path_buf();
-    let name = path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("index.json");
-    lock_path.set_file_name(format!("{}.lock", name));
-    lock_path
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::recorder::{AckFilter, DEFAULT_PRIORITY};
 
-fn sync_parent_dir(path: &Path) -> Result<()> {
-    if let Some(parent) = path.parent()
-        && let Ok(dir) = std::fs::File::open(parent)
-    {
-        let _ = dir.sync_all();
+    fn entry(record: usize, status: RecordingStatus) -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            uuid: String::new(),
+            record: record.to_string(),
+            stream: "cam".to_string(),
+            record_dir: format!("cam/{record}"),
+            mpd_path: format!("cam/{record}/manifest.mpd"),
+            start_ts: record as i64 * 1_000_000,
+            end_ts: Some(record as i64 * 1_000_000 + 60_000_000),
+            duration_ms: Some(60_000),
+            status,
+            node_alias: None,
+            updated_at: record as i64,
+            note: None,
+            labels: Vec::new(),
+            continues: None,
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant: None,
+            size: None,
+            captions: Vec::new(),
+            trigger: None,
+            imported: false,
+            seq: 0,
+        }
+    }
+
+    fn keys(records: impl IntoIterator<Item = usize>) -> Vec<RecordingKey> {
+        records
+            .into_iter()
+            .map(|record| RecordingKey {
+                stream: "cam".to_string(),
+                record: record.to_string(),
+                if_match: None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_acked_entries_stay_on_disk() {
+        const ACKED: usize = 100_000;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+
+        // An index written before the archive existed, acked entries in the log
+        let mut log = String::new();
+        for record in 0..ACKED + 10 {
+            let status = if record < ACKED {
+                RecordingStatus::Acked
+            } else {
+                RecordingStatus::Completed
+            };
+            log.push_str(&serde_json::to_string(&entry(record, status)).unwrap());
+            log.push('\n');
+        }
+        std::fs::write(&path, log).unwrap();
+
+        let index = RecordingsIndex::load(path.clone()).await.unwrap();
+        assert_eq!(index.resident_len().await, 10);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 10);
+        assert_eq!(index.last_updated_at().await, (ACKED + 9) as i64);
+        assert!(index.get("cam", "0").await.is_none());
+
+        // Acking moves the entry out of memory
+        let acked = index
+            .ack(AckRecordingsRequest {
+                records: keys([ACKED, ACKED + 1]),
+                filter: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(acked.acked, 2);
+        assert_eq!(index.resident_len().await, 8);
+
+        let req = DeleteRecordingsRequest {
+            records: keys([0, 1, ACKED, ACKED + 2]),
+        };
+        let acked = index.acked_keys(&req).await.unwrap();
+        let deleted = index.delete_acked(req).await.unwrap();
+        // The last one is not acked
+        assert_eq!(deleted.len(), 3);
+        assert_eq!(acked, deleted);
+        assert_eq!(index.resident_len().await, 8);
+        assert_eq!(index.snapshot().await.unwrap().len(), ACKED + 10 - 3);
+
+        let reloaded = RecordingsIndex::load(path).await.unwrap();
+        assert_eq!(reloaded.resident_len().await, 8);
+        let snapshot = reloaded.snapshot().await.unwrap();
+        assert_eq!(snapshot.len(), ACKED + 10 - 3);
+        assert!(
+            snapshot
+                .iter()
+                .all(|e| e.record != "0" && e.record != ACKED.to_string())
+        );
+        let acked = snapshot
+            .iter()
+            .find(|e| e.record == (ACKED + 1).to_string())
+            .unwrap();
+        assert!(matches!(acked.status, RecordingStatus::Acked));
+    }
+
+    #[tokio::test]
+    async fn test_ack_survives_log_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        let index = RecordingsIndex::load(path.clone()).await.unwrap();
+        index
+            .upsert(entry(1, RecordingStatus::Completed))
+            .await
+            .unwrap();
+        index
+            .ack(AckRecordingsRequest {
+                records: keys([1]),
+                filter: None,
+            })
+            .await
+            .unwrap();
+
+        // Not compacted yet: the log still holds the completed line
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .contains("Completed")
+        );
+        let reloaded = RecordingsIndex::load(path).await.unwrap();
+        assert_eq!(reloaded.resident_len().await, 0);
+        assert_eq!(reloaded.expired(i64::MAX).await.unwrap().len(), 0);
+        assert_eq!(reloaded.in_deletion_order().await.unwrap().len(), 1);
+        assert!(reloaded.remove("cam", "1").await.unwrap());
+        assert!(reloaded.snapshot().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ack_by_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = RecordingsIndex::load(dir.path().join("index.json"))
+            .await
+            .unwrap();
+        for record in 1..=30 {
+            let mut entry = entry(record, RecordingStatus::Completed);
+            match record {
+                5 => entry.status = RecordingStatus::Trashed,
+                6 => entry.stream = "lobby".to_string(),
+                _ => {}
+            }
+            index.upsert(entry).await.unwrap();
+        }
+
+        let resp = index
+            .ack(AckRecordingsRequest {
+                records: keys([28]),
+                filter: Some(AckFilter {
+                    stream: Some("cam".to_string()),
+                    updated_before_ts: Some(25),
+                    status: None,
+                }),
+            })
+            .await
+            .unwrap();
+        // 1..=24 and 28, but not the trashed one nor the other stream
+        assert_eq!(resp.acked, 23);
+        assert_eq!(resp.max_updated_at, Some(28));
+        assert_eq!(resp.sample.len(), ACK_SAMPLE_LEN);
+        assert_eq!(resp.sample[0], "cam/1");
+        assert_eq!(index.resident_len().await, 7);
+        assert!(index.get("cam", "5").await.is_some());
+        assert!(index.get("lobby", "6").await.is_some());
+        assert!(index.get("cam", "25").await.is_some());
+
+        let resp = index
+            .ack(AckRecordingsRequest {
+                records: Vec::new(),
+                filter: Some(AckFilter {
+                    updated_before_ts: Some(25),
+                    ..Default::default()
+                }),
+            })
+            .await
+            .unwrap();
+        assert_eq!(resp.acked, 1);
+        assert_eq!(resp.sample, ["lobby/6"]);
+    }
+
+    /// A patch and a delete of the version both read: exactly one applies, the other
+    /// gets the entry the winner left
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_if_match_patch_delete_race() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = Arc::new(
+            RecordingsIndex::load(dir.path().join("index.json"))
+                .await
+                .unwrap(),
+        );
+        for record in 1..=50 {
+            index
+                .upsert(entry(record, RecordingStatus::Completed))
+                .await
+                .unwrap();
+            let version = index
+                .get("cam", &record.to_string())
+                .await
+                .unwrap()
+                .updated_at;
+            let patch = UpdateRecordingRequest {
+                note: Some("reviewed".to_string()),
+                if_match: Some(version),
+                ..Default::default()
+            };
+            let patched = tokio::spawn({
+                let index = index.clone();
+                async move {
+                    index
+                        .update_metadata("cam", &record.to_string(), &patch)
+                        .await
+                        .unwrap()
+                }
+            });
+            let trashed = tokio::spawn({
+                let index = index.clone();
+                async move {
+                    index
+                        .trash("cam", &record.to_string(), Some(version))
+                        .await
+                        .unwrap()
+                }
+            });
+            let (patched, trashed) = tokio::join!(patched, trashed);
+            let current = index.get("cam", &record.to_string()).await.unwrap();
+            match (patched.unwrap(), trashed.unwrap()) {
+                (MetadataUpdate::Updated(won), TrashUpdate::Stale(seen)) => {
+                    assert_eq!(won, current);
+                    assert_eq!(seen.updated_at, current.updated_at);
+                    assert!(!current.is_trashed());
+                }
+                (MetadataUpdate::Stale(seen), TrashUpdate::Updated(won)) => {
+                    assert_eq!(won, current);
+                    assert_eq!(seen.updated_at, current.updated_at);
+                    assert!(current.note.is_none());
+                }
+                _ => panic!("not exactly one of the patch and the delete applied"),
+            }
+            assert!(current.updated_at > version);
+        }
+
+        // Retried with the version it was refused with, the loser applies
+        let current = index.get("cam", "1").await.unwrap();
+        let retried = if current.is_trashed() {
+            let patch = UpdateRecordingRequest {
+                note: Some("reviewed".to_string()),
+                if_match: Some(current.updated_at),
+                ..Default::default()
+            };
+            matches!(
+                index.update_metadata("cam", "1", &patch).await.unwrap(),
+                MetadataUpdate::Updated(_)
+            )
+        } else {
+            matches!(
+                index
+                    .trash("cam", "1", Some(current.updated_at))
+                    .await
+                    .unwrap(),
+                TrashUpdate::Updated(_)
+            )
+        };
+        assert!(retried);
+        assert!(matches!(
+            index
+                .restore("cam", "1", Some(current.updated_at))
+                .await
+                .unwrap(),
+            TrashUpdate::Stale(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_if_match_ack_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = RecordingsIndex::load(dir.path().join("index.json"))
+            .await
+            .unwrap();
+        for record in 1..=3 {
+            index
+                .upsert(entry(record, RecordingStatus::Completed))
+                .await
+                .unwrap();
+        }
+        let mut records = keys([1, 2]);
+        records[0].if_match = Some(1);
+        records[1].if_match = Some(1);
+        // One stale key fails the whole ack
+        let err = index
+            .ack(AckRecordingsRequest {
+                records: records.clone(),
+                filter: None,
+            })
+            .await
+            .unwrap_err();
+        let Some(RecorderError::VersionMismatch {
+            record, current, ..
+        }) = err.downcast_ref::<RecorderError>()
+        else {
+            panic!("not a version mismatch: {err:#}");
+        };
+        assert_eq!((record.as_str(), current.updated_at), ("2", 2));
+        assert_eq!(index.resident_len().await, 3);
+
+        records[1].if_match = Some(2);
+        let acked = index
+            .ack(AckRecordingsRequest {
+                records,
+                filter: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(acked.acked, 2);
+
+        // Archived entries are at the version the ack gave them
+        let mut records = keys([1, 2]);
+        records[1].if_match = Some(2);
+        let err = index
+            .delete_acked(DeleteRecordingsRequest {
+                records: records.clone(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RecorderError>().unwrap().status_code(),
+            409
+        );
+        assert_eq!(index.snapshot().await.unwrap().len(), 3);
+        let archived = index.in_deletion_order().await.unwrap();
+        let acked = archived.iter().find(|e| e.record == "2").unwrap();
+        records[1].if_match = Some(acked.updated_at);
+        let deleted = index
+            .delete_acked(DeleteRecordingsRequest { records })
+            .await
+            .unwrap();
+        assert_eq!(deleted, ["cam/1", "cam/2"]);
+    }
+
+    #[tokio::test]
+    async fn test_uuid_survives_rename_and_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        // A log from before recordings had a uuid
+        std::fs::write(
+            &path,
+            serde_json::to_string(&entry(1, RecordingStatus::Completed))
+                .unwrap()
+                .replace(r#""uuid":"","#, "")
+                + "\n",
+        )
+        .unwrap();
+
+        let index = RecordingsIndex::load(path.clone()).await.unwrap();
+        let legacy = index.get("cam", "1").await.unwrap();
+        assert!(uuid::Uuid::parse_str(&legacy.uuid).is_ok());
+        index
+            .upsert(entry(2, RecordingStatus::Completed))
+            .await
+            .unwrap();
+        let uuid = index.get("cam", "2").await.unwrap().uuid;
+        // Updates without a uuid keep the one the entry has
+        index
+            .upsert(entry(2, RecordingStatus::Failed))
+            .await
+            .unwrap();
+        assert_eq!(index.get("cam", "2").await.unwrap().uuid, uuid);
+
+        let renamed = index
+            .rename_entry(
+                "cam",
+                "2",
+                "lobby",
+                "lobby/2".to_string(),
+                "lobby/2/manifest.mpd".to_string(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(renamed.uuid, uuid);
+        let found = index.get_by_uuid(&uuid).await.unwrap();
+        assert_eq!(found.key(), "lobby/2");
+        assert!(index.get_by_uuid(&legacy.uuid).await.is_some());
+
+        // Renaming compacted the log, the assigned uuid is persisted with it
+        let reloaded = RecordingsIndex::load(path).await.unwrap();
+        assert_eq!(reloaded.get("cam", "1").await.unwrap().uuid, legacy.uuid);
+        assert_eq!(reloaded.get_by_uuid(&uuid).await.unwrap().key(), "lobby/2");
+
+        reloaded.remove("lobby", "2").await.unwrap();
+        assert!(reloaded.get_by_uuid(&uuid).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_compaction_off_the_append_path() {
+        const RESIDENT: usize = 50_000;
+        const APPENDS: usize = 2 * COMPACT_EVERY;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        // Two lines per entry, as the log looks after a while without compaction
+        let mut log = String::new();
+        for status in [RecordingStatus::Active, RecordingStatus::Completed] {
+            for record in 0..RESIDENT {
+                let mut e = entry(record, status.clone());
+                e.uuid = format!("uuid-{record}");
+                log.push_str(&serde_json::to_string(&e).unwrap());
+                log.push('\n');
+            }
+        }
+        std::fs::write(&path, &log).unwrap();
+        let index = Arc::new(RecordingsIndex::load(path.clone()).await.unwrap());
+
+        // What an append crossing the threshold paid when compaction ran inline
+        let started = std::time::Instant::now();
+        {
+            let _guard = index.write_lock.lock().await;
+            index.compact().await.unwrap();
+        }
+        let inline = started.elapsed();
+        std::fs::write(&path, &log).unwrap();
+        let rewrites = index.rewrites.load(Ordering::Acquire);
+
+        let compactor = tokio::spawn(index.clone().run_compactor());
+        let mut latencies = Vec::with_capacity(APPENDS);
+        for record in 0..APPENDS {
+            let mut e = entry(record, RecordingStatus::Failed);
+            e.updated_at = RESIDENT as i64 + record as i64;
+            let started = std::time::Instant::now();
+            index.upsert(e).await.unwrap();
+            latencies.push(started.elapsed());
+        }
+        latencies.sort();
+        let p99 = latencies[APPENDS * 99 / 100];
+        assert!(
+            p99 < inline,
+            "p99 append {p99:?}, inline compaction {inline:?}"
+        );
+
+        tokio::time::timeout(Duration::from_secs(30), async {
+            while index.rewrites.load(Ordering::Acquire) == rewrites {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("background compaction");
+        index.close().await;
+        compactor.await.unwrap();
+        assert!(!snapshot_path_for(&path).exists());
+
+        // One line per entry plus the appends replayed after the snapshot
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert!(lines < RESIDENT + APPENDS, "{lines} lines");
+        let reloaded = RecordingsIndex::load(path).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(reloaded.snapshot().await.unwrap()).unwrap(),
+            serde_json::to_value(index.snapshot().await.unwrap()).unwrap()
+        );
+        let updated = reloaded
+            .get("cam", &(APPENDS - 1).to_string())
+            .await
+            .unwrap();
+        assert_eq!(updated.status, RecordingStatus::Failed);
+        assert_eq!(updated.uuid, format!("uuid-{}", APPENDS - 1));
+    }
+
+    /// Journal positions only grow: across appends, acks, a removal dropping the line
+    /// of the latest one, compactions and reloads
+    #[tokio::test]
+    async fn test_seq_survives_reload_and_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        let log_seqs = |path: &Path| -> Vec<u64> {
+            std::fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    serde_json::from_str::<RecordingIndexEntry>(line)
+                        .unwrap()
+                        .seq
+                })
+                .collect()
+        };
+
+        let index = RecordingsIndex::load(path.clone()).await.unwrap();
+        assert_eq!(index.seq(), 0);
+        let mut events = index.subscribe();
+        for record in 1..=3 {
+            index
+                .upsert(entry(record, RecordingStatus::Completed))
+                .await
+                .unwrap();
+        }
+        let ids: Vec<i64> = (0..3).map(|_| events.try_recv().unwrap().id).collect();
+        assert_eq!(ids, [1, 2, 3]);
+        index.record_repair("cam", "1", Ok(1_000)).await.unwrap();
+        assert_eq!(index.get("cam", "1").await.unwrap().seq, 4);
+        assert_eq!(log_seqs(&path), [1, 2, 3, 4]);
+        index
+            .ack(AckRecordingsRequest {
+                records: keys([2]),
+                filter: None,
+            })
+            .await
+            .unwrap();
+        index
+            .upsert(entry(4, RecordingStatus::Completed))
+            .await
+            .unwrap();
+        assert_eq!(index.seq(), 6);
+        // Compacted without the line of the latest position
+        index.remove("cam", "4").await.unwrap();
+        assert_eq!(log_seqs(&path), [4, 3]);
+        drop(index);
+
+        let reloaded = RecordingsIndex::load(path.clone()).await.unwrap();
+        assert_eq!(reloaded.seq(), 6);
+        assert_eq!(reloaded.get("cam", "3").await.unwrap().seq, 3);
+        let changed: Vec<String> = reloaded
+            .changed_since(3)
+            .await
+            .iter()
+            .map(|e| e.key())
+            .collect();
+        assert_eq!(changed, ["cam/1"]);
+        let acked = reloaded.snapshot().await.unwrap();
+        assert_eq!(acked.iter().find(|e| e.record == "2").unwrap().seq, 5);
+
+        reloaded
+            .upsert(entry(5, RecordingStatus::Completed))
+            .await
+            .unwrap();
+        assert_eq!(reloaded.get("cam", "5").await.unwrap().seq, 7);
+        {
+            let _guard = reloaded.write_lock.lock().await;
+            reloaded.compact().await.unwrap();
+        }
+        assert_eq!(log_seqs(&path), [4, 3, 7]);
+        assert_eq!(RecordingsIndex::load(path).await.unwrap().seq(), 7);
+    }
+
+    fn batched() -> Arc<Durability> {
+        Arc::new(Durability::new(&crate::config::DurabilityConfig {
+            mode: crate::config::DurabilityMode::Batched,
+            flush_interval_ms: 60_000,
+        }))
+    }
+
+    /// A kill between batched appends and their flush, modelled by cutting the log
+    /// anywhere past the last flush: the flushed entries load, no cut line is half
+    /// read, and appends after the reload start lines of their own
+    #[tokio::test]
+    async fn test_batched_crash_loses_at_most_the_window() {
+        const FLUSHED: usize = 20;
+        const WINDOW: usize = 10;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        let durability = batched();
+        let index = RecordingsIndex::load(path.clone())
+            .await
+            .unwrap()
+            .with_durability(durability.clone());
+        for record in 0..FLUSHED {
+            index
+                .upsert(entry(record, RecordingStatus::Completed))
+                .await
+                .unwrap();
+        }
+        assert!(durability.unsynced() > 0);
+        // The flusher's tick
+        durability.flush().unwrap();
+        assert_eq!(durability.unsynced(), 0);
+        let flushed = std::fs::read(&path).unwrap();
+        for record in FLUSHED..FLUSHED + WINDOW {
+            index
+                .upsert(entry(record, RecordingStatus::Completed))
+                .await
+                .unwrap();
+        }
+        assert!(durability.unsynced() > 0);
+        let written = std::fs::read(&path).unwrap();
+        assert!(written.starts_with(&flushed));
+        drop(index);
+
+        // Whatever part of the window reached the disk, or the window's length in zeros
+        // from a filesystem that grew the file before writing its data
+        let mut crashes: Vec<Vec<u8>> = (flushed.len()..=written.len())
+            .step_by(37)
+            .map(|at| written[..at].to_vec())
+            .collect();
+        crashes.push(written.clone());
+        let mut zeroed = written[..flushed.len() + 100].to_vec();
+        zeroed.resize(written.len(), 0);
+        crashes.push(zeroed);
+        let crashed = dir.path().join("crashed.json");
+        for crash in crashes {
+            std::fs::write(&crashed, &crash).unwrap();
+            let reloaded = RecordingsIndex::load(crashed.clone()).await.unwrap();
+            let len = reloaded.resident_len().await;
+            assert!((FLUSHED..=FLUSHED + WINDOW).contains(&len), "{len} entries");
+            for record in 0..FLUSHED {
+                assert!(reloaded.get("cam", &record.to_string()).await.is_some());
+            }
+            reloaded
+                .upsert(entry(FLUSHED + WINDOW, RecordingStatus::Completed))
+                .await
+                .unwrap();
+            drop(reloaded);
+            let again = RecordingsIndex::load(crashed.clone()).await.unwrap();
+            assert_eq!(again.resident_len().await, len + 1);
+        }
+    }
+
+    /// A batched rewrite syncs the appends it supersedes before its rename
+    #[tokio::test]
+    async fn test_batched_rewrite_flushes_appends_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        let durability = batched();
+        let index = RecordingsIndex::load(path.clone())
+            .await
+            .unwrap()
+            .with_durability(durability.clone());
+        for record in 0..3 {
+            index
+                .upsert(entry(record, RecordingStatus::Completed))
+                .await
+                .unwrap();
+        }
+        assert_eq!(durability.unsynced(), 2);
+        {
+            let _guard = index.write_lock.lock().await;
+            index.compact().await.unwrap();
+        }
+        // Only the directory entry of the renamed log waits for the next flush
+        assert_eq!(durability.unsynced(), 1);
+        assert_eq!(
+            RecordingsIndex::load(path)
+                .await
+                .unwrap()
+                .resident_len()
+                .await,
+            3
+        );
+    }
+
+    /// A tail of the log follows appends, and starts over from 0 after a compaction
+    /// with the entries as they are
+    #[tokio::test]
+    async fn test_journal_tail_across_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = RecordingsIndex::load(dir.path().join("index.json"))
+            .await
+            .unwrap();
+        let lines = |data: &[u8]| -> Vec<(String, u64)> {
+            std::str::from_utf8(data)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    let e: RecordingIndexEntry = serde_json::from_str(line).unwrap();
+                    (e.record, e.seq)
+                })
+                .collect()
+        };
+        let empty = index.read_journal(0, 1 << 20).await.unwrap();
+        assert_eq!((empty.size, empty.next_offset()), (0, 0));
+
+        for record in 1..=3 {
+            index
+                .upsert(entry(record, RecordingStatus::Active))
+                .await
+                .unwrap();
+        }
+        let first = index.read_journal(0, 1 << 20).await.unwrap();
+        assert_eq!(first.generation, empty.generation);
+        assert_eq!(
+            lines(&first.data),
+            [("1".into(), 1), ("2".into(), 2), ("3".into(), 3)]
+        );
+        assert_eq!(first.next_offset(), first.size);
+        // A limit within the second line returns the first alone
+        let line_len = first.data.iter().position(|b| *b == b'\n').unwrap() as u64 + 1;
+        let one = index.read_journal(0, line_len + 1).await.unwrap();
+        assert_eq!(one.next_offset(), line_len);
+
+        index
+            .upsert(entry(1, RecordingStatus::Completed))
+            .await
+            .unwrap();
+        let next = index
+            .read_journal(first.next_offset(), 1 << 20)
+            .await
+            .unwrap();
+        assert_eq!(next.generation, first.generation);
+        assert_eq!(lines(&next.data), [("1".into(), 4)]);
+
+        // The compacted log is shorter than the offset the tail reached
+        index.compact().await.unwrap();
+        let stale = index
+            .read_journal(next.next_offset(), 1 << 20)
+            .await
+            .unwrap();
+        assert_ne!(stale.generation, next.generation);
+        assert!(stale.data.is_empty());
+        let restarted = index.read_journal(0, 1 << 20).await.unwrap();
+        assert_eq!(restarted.generation, stale.generation);
+        assert_eq!(
+            lines(&restarted.data),
+            [("1".into(), 4), ("2".into(), 2), ("3".into(), 3)]
+        );
+
+        // So does a background compaction, with the appends it replays
+        index
+            .upsert(entry(4, RecordingStatus::Active))
+            .await
+            .unwrap();
+        let tail = index
+            .read_journal(restarted.next_offset(), 1 << 20)
+            .await
+            .unwrap();
+        assert_eq!(lines(&tail.data), [("4".into(), 5)]);
+        assert!(index.compact_in_background().await.unwrap());
+        let after = index.read_journal(0, 1 << 20).await.unwrap();
+        assert_ne!(after.generation, tail.generation);
+        let records: Vec<String> = lines(&after.data).into_iter().map(|(r, _)| r).collect();
+        assert_eq!(records, ["1", "2", "3", "4"]);
     }
-    Ok(())
 }
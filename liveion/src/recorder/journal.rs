@@ -0,0 +1,150 @@
+//! Reading the index log and the audit log from an offset, for tailers on other hosts.
+//!
+//! Both files are only appended to until they are replaced: a compaction renames a
+//! shorter log over the index log, a rotation moves the audit log away. A [`Journal`]
+//! counts these replacements as generations, so a tailer reading on from its offset
+//! learns when the file is no longer the one the offset was into and starts over at 0.
+//! Generations start at the UNIX microseconds the node started at, a restart may drop a
+//! torn last line too.
+//!
+//! A read holds the generation only while it opens the file, appends never take it and
+//! a replacement only for its rename.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use chrono::Utc;
+
+/// Complete lines of a journal from an offset
+#[derive(Debug)]
+pub struct JournalChunk {
+    /// Generation of the file read
+    pub generation: u64,
+    /// Offset read from
+    pub offset: u64,
+    /// Size of the file when it was opened, 0 when there is none yet
+    pub size: u64,
+    /// Whole lines from `offset`, newlines included
+    pub data: Vec<u8>,
+}
+
+impl JournalChunk {
+    /// Offset to read from next
+    pub fn next_offset(&self) -> u64 {
+        self.offset + self.data.len() as u64
+    }
+}
+
+pub struct Journal {
+    path: PathBuf,
+    generation: RwLock<u64>,
+}
+
+impl Journal {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            generation: RwLock::new(Utc::now().timestamp_micros().max(0) as u64),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn generation(&self) -> u64 {
+        *self.generation.read().unwrap()
+    }
+
+    /// Replace the file with `replace`, e.g. rename another over it. A read opens the
+    /// file before or after, never in between, and sees the next generation after
+    pub fn replace<T>(&self, replace: impl FnOnce() -> T) -> T {
+        let mut generation = self.generation.write().unwrap();
+        let replaced = replace();
+        *generation += 1;
+        replaced
+    }
+
+    /// Up to `limit` bytes from `offset`, cut after the last newline: a line still being
+    /// appended, or longer than `limit`, is left for a later read. Nothing past the end
+    /// of the file. Blocks on the disk
+    pub fn read(&self, offset: u64, limit: u64) -> std::io::Result<JournalChunk> {
+        let (generation, file) = {
+            let generation = self.generation.read().unwrap();
+            match File::open(&self.path) {
+                Ok(file) => (*generation, Some(file)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (*generation, None),
+                Err(e) => return Err(e),
+            }
+        };
+        let mut chunk = JournalChunk {
+            generation,
+            offset,
+            size: 0,
+            data: Vec::new(),
+        };
+        let Some(mut file) = file else {
+            return Ok(chunk);
+        };
+        chunk.size = file.metadata()?.len();
+        if offset >= chunk.size {
+            return Ok(chunk);
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        file.take(limit.min(chunk.size - offset))
+            .read_to_end(&mut chunk.data)?;
+        let complete = chunk
+            .data
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |i| i + 1);
+        chunk.data.truncate(complete);
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete_lines_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::new(dir.path().join("log"));
+        let missing = journal.read(0, 1024).unwrap();
+        assert_eq!((missing.size, missing.data.len()), (0, 0));
+
+        std::fs::write(journal.path(), "one\ntwo\nthr").unwrap();
+        let chunk = journal.read(0, 1024).unwrap();
+        assert_eq!(chunk.data, b"one\ntwo\n");
+        assert_eq!(chunk.size, 11);
+        assert_eq!(chunk.next_offset(), 8);
+        // The limit cuts within the second line
+        assert_eq!(journal.read(0, 6).unwrap().data, b"one\n");
+        // A line longer than the limit returns nothing, as does the end
+        let stuck = journal.read(4, 2).unwrap();
+        assert!(stuck.data.is_empty());
+        assert_eq!(stuck.next_offset(), 4);
+        assert!(journal.read(8, 1024).unwrap().data.is_empty());
+        assert!(journal.read(100, 1024).unwrap().data.is_empty());
+    }
+
+    #[test]
+    fn test_replace_starts_a_generation() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::new(dir.path().join("log"));
+        std::fs::write(journal.path(), "one\ntwo\n").unwrap();
+        let before = journal.read(0, 1024).unwrap();
+
+        let tmp = dir.path().join("log.tmp");
+        std::fs::write(&tmp, "two\n").unwrap();
+        journal
+            .replace(|| std::fs::rename(&tmp, journal.path()))
+            .unwrap();
+        let after = journal.read(0, 1024).unwrap();
+        assert_eq!(after.generation, before.generation + 1);
+        assert_eq!(after.data, b"two\n");
+    }
+}
@@ -0,0 +1,158 @@
+//! Upload lag, the SLO of recordings reaching storage: from the last write of a file
+//! in `local_dir`, its modification time when it was staged, to its upload being stored
+//! and verified.
+//!
+//! Every upload is observed in `recorder_upload_lag_seconds` by stream, and kept for
+//! `upload.lag_window_seconds` for the p50 and p99 of the status and health APIs. A file
+//! still queued lags by its age already, so the worst lag is the larger of the window's
+//! and the oldest pending file's, exported as `recorder_upload_lag_worst_seconds`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use api::recorder::UploadLag;
+
+use crate::metrics;
+
+/// Samples kept per stream, the oldest are dropped first past it
+const MAX_SAMPLES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    /// When the upload completed, UNIX milliseconds
+    at: i64,
+    lag_ms: u64,
+}
+
+pub struct UploadLags {
+    window_ms: i64,
+    /// By stream, `""` for entries queued before uploads knew their stream
+    streams: Mutex<HashMap<String, VecDeque<Sample>>>,
+}
+
+impl UploadLags {
+    pub fn new(window_seconds: u64) -> Self {
+        Self {
+            window_ms: (window_seconds.max(1) as i64).saturating_mul(1_000),
+            streams: Mutex::default(),
+        }
+    }
+
+    /// A file of `stream` last written at `modified_at` was uploaded at `uploaded_at`,
+    /// both UNIX milliseconds. Returns its lag
+    pub fn record(&self, stream: Option<&str>, modified_at: i64, uploaded_at: i64) -> u64 {
+        let lag_ms = uploaded_at.saturating_sub(modified_at).max(0) as u64;
+        let stream = stream.unwrap_or_default();
+        metrics::RECORDER_UPLOAD_LAG
+            .with_label_values(&[stream])
+            .observe(lag_ms as f64 / 1_000.0);
+        let mut streams = self.streams.lock().unwrap();
+        let samples = streams.entry(stream.to_string()).or_default();
+        if samples.len() >= MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(Sample {
+            at: uploaded_at,
+            lag_ms,
+        });
+        lag_ms
+    }
+
+    /// Lag of `stream`, or of every stream, over the window up to `now`.
+    /// `oldest_pending` is the modification time of its oldest queued file, all in UNIX
+    /// milliseconds
+    pub fn summary(
+        &self,
+        stream: Option<&str>,
+        oldest_pending: Option<i64>,
+        now: i64,
+    ) -> UploadLag {
+        let since = now - self.window_ms;
+        let mut lags: Vec<u64> = {
+            let mut streams = self.streams.lock().unwrap();
+            streams.retain(|_, samples| {
+                // Completion order, up to uploads running side by side
+                while samples.front().is_some_and(|sample| sample.at < since) {
+                    samples.pop_front();
+                }
+                !samples.is_empty()
+            });
+            streams
+                .iter()
+                .filter(|(s, _)| stream.is_none_or(|stream| stream == s.as_str()))
+                .flat_map(|(_, samples)| samples.iter().map(|sample| sample.lag_ms))
+                .collect()
+        };
+        lags.sort_unstable();
+        let oldest_pending_ms = oldest_pending.map(|at| now.saturating_sub(at).max(0) as u64);
+        UploadLag {
+            samples: lags.len() as u64,
+            p50_ms: percentile(&lags, 50),
+            p99_ms: percentile(&lags, 99),
+            oldest_pending_ms,
+            worst_ms: lags
+                .last()
+                .copied()
+                .unwrap_or_default()
+                .max(oldest_pending_ms.unwrap_or_default()),
+        }
+    }
+}
+
+/// Nearest-rank percentile `p` of `sorted`
+fn percentile(sorted: &[u64], p: usize) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_and_window() {
+        let lags = UploadLags::new(60);
+        // 100 uploads of cam lagging 1..=100 s, completed over the last 50 s
+        for i in 1..=100 {
+            let uploaded_at = 1_000_000 + i * 500;
+            lags.record(Some("cam"), uploaded_at - i * 1_000, uploaded_at);
+        }
+        lags.record(Some("gate"), 1_000_000, 1_030_000);
+        let now = 1_050_000;
+
+        let cam = lags.summary(Some("cam"), None, now);
+        assert_eq!(cam.samples, 100);
+        assert_eq!(cam.p50_ms, Some(50_000));
+        assert_eq!(cam.p99_ms, Some(99_000));
+        assert_eq!(cam.worst_ms, 100_000);
+
+        // A file queued for longer than any upload took is the worst lag
+        let gate = lags.summary(Some("gate"), Some(now - 120_000), now);
+        assert_eq!(gate.samples, 1);
+        assert_eq!(gate.p50_ms, Some(30_000));
+        assert_eq!(gate.oldest_pending_ms, Some(120_000));
+        assert_eq!(gate.worst_ms, 120_000);
+
+        let node = lags.summary(None, None, now);
+        assert_eq!(node.samples, 101);
+        assert_eq!(node.worst_ms, 100_000);
+
+        // Uploads older than the window are forgotten
+        let later = lags.summary(Some("cam"), None, 1_000_000 + 60_000 + 25_500);
+        assert_eq!(later.samples, 50);
+        assert_eq!(later.p50_ms, Some(75_000));
+        let empty = lags.summary(Some("cam"), None, now + 3_600_000);
+        assert_eq!(empty, UploadLag::default());
+    }
+
+    #[test]
+    fn test_lag_is_never_negative() {
+        let lags = UploadLags::new(60);
+        // A file whose clock ran ahead of the upload
+        assert_eq!(lags.record(None, 2_000, 1_000), 0);
+        assert_eq!(lags.summary(None, None, 1_000).p99_ms, Some(0));
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use api::recorder::{RecordingLeaseRequest, RecordingLeaseResponse};
+use http::header;
+use reqwest::Client;
+
+use crate::config::LeaseConfig;
+
+/// Shortest wait between two requests for a refused lease
+const MIN_RETRY: Duration = Duration::from_secs(1);
+
+/// Asks liveman's `POST /api/recorder/lease` which node records a stream
+pub struct LeaseClient {
+    cfg: LeaseConfig,
+    node_alias: String,
+    client: Client,
+}
+
+impl LeaseClient {
+    pub fn new(cfg: LeaseConfig, node_alias: String) -> Self {
+        Self {
+            cfg,
+            node_alias,
+            client: Client::new(),
+        }
+    }
+
+    fn ttl(&self) -> Duration {
+        Duration::from_secs(self.cfg.ttl_seconds.max(3))
+    }
+
+    /// Between renewals of a held lease, and retries while liveman is unreachable
+    pub fn renew_interval(&self) -> Duration {
+        self.ttl() / 3
+    }
+
+    /// Acquire or renew the lease of `stream`
+    pub async fn acquire(&self, stream: &str) -> Result<RecordingLeaseResponse> {
+        self.send(stream, false).await
+    }
+
+    pub async fn release(&self, stream: &str) -> Result<()> {
+        self.send(stream, true).await.map(|_| ())
+    }
+
+    async fn send(&self, stream: &str, release: bool) -> Result<RecordingLeaseResponse> {
+        let url = format!(
+            "{}{}",
+            self.cfg.liveman_url.trim_end_matches('/'),
+            api::path::recorder_lease()
+        );
+        let req = RecordingLeaseRequest {
+            stream: stream.to_string(),
+            node_alias: self.node_alias.clone(),
+            ttl_seconds: self.ttl().as_secs(),
+            release,
+        };
+        let resp = self
+            .client
+            .post(url)
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", self.cfg.token.expose_secret()),
+            )
+            .timeout(Duration::from_secs(10))
+            .json(&req)
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("lease request failed: {}", resp.status());
+        }
+        Ok(resp.json().await?)
+    }
+
+    /// When to ask again for a lease refused with `resp` at `now`, UNIX microseconds:
+    /// right after the holder's lease expires unless renewed
+    pub fn retry_after(&self, resp: &RecordingLeaseResponse, now: i64) -> Duration {
+        match &resp.lease {
+            Some(lease) => {
+                let left = u64::try_from(lease.expires_at - now).unwrap_or(0);
+                Duration::from_micros(left).max(MIN_RETRY)
+            }
+            None => MIN_RETRY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::recorder::RecordingLease;
+
+    #[test]
+    fn test_retry_after_holder_expires() {
+        let client = LeaseClient::new(
+            LeaseConfig {
+                enabled: true,
+                ttl_seconds: 1,
+                ..Default::default()
+            },
+            "edge-2".to_string(),
+        );
+        // Too short a TTL would expire between two renewals
+        assert_eq!(client.renew_interval(), Duration::from_secs(1));
+
+        let now = 1_700_000_000_000_000;
+        let refused = RecordingLeaseResponse {
+            granted: false,
+            lease: Some(RecordingLease {
+                stream: "cam".to_string(),
+                holder: "edge-1".to_string(),
+                acquired_at: now - 20_000_000,
+                expires_at: now + 12_500_000,
+            }),
+        };
+        assert_eq!(
+            client.retry_after(&refused, now),
+            Duration::from_millis(12_500)
+        );
+        assert_eq!(client.retry_after(&refused, now + 60_000_000), MIN_RETRY);
+    }
+}
@@ -0,0 +1,183 @@
+//! `max_concurrent_recordings`: what a start does once the node records that many streams.
+//!
+//! The decision is taken under the task map's lock, so concurrent stream-up events
+//! never push the node past its limit.
+
+use api::recorder::RecordingCapacity;
+
+use crate::config::{RecorderConfig, RecordingLimitMode};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordingLimit {
+    /// 0 is unlimited
+    pub max: usize,
+    pub preempt: bool,
+}
+
+/// Outcome of a recording start against the limit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Admission {
+    Admit,
+    /// The node is full of recordings at least as important as the new one
+    Reject,
+    /// Stop the recording of this stream to make room
+    Preempt(String),
+}
+
+/// A recording already running, as the limit sees it
+#[derive(Debug, Clone, Copy)]
+pub struct Active<'a> {
+    pub stream: &'a str,
+    pub priority: u8,
+    pub start_ts_micros: i64,
+}
+
+impl RecordingLimit {
+    pub fn from_config(cfg: &RecorderConfig) -> Self {
+        Self {
+            max: cfg.max_concurrent_recordings,
+            preempt: cfg.recording_limit_mode == RecordingLimitMode::Preempt,
+        }
+    }
+
+    pub fn capacity(&self, active: usize) -> RecordingCapacity {
+        RecordingCapacity {
+            active,
+            max: self.max,
+        }
+    }
+
+    /// Whether a recording of `priority` may start next to `active`.
+    ///
+    /// Preemption stops the lowest priority recording, the most recent one among equals
+    /// as it has the least footage to lose. A recording is never preempted for one of
+    /// the same or a lower priority.
+    pub fn admit<'a>(
+        &self,
+        active: impl ExactSizeIterator<Item = Active<'a>>,
+        priority: u8,
+    ) -> Admission {
+        if self.max == 0 || active.len() < self.max {
+            return Admission::Admit;
+        }
+        if !self.preempt {
+            return Admission::Reject;
+        }
+        active
+            .min_by_key(|a| (a.priority, std::cmp::Reverse(a.start_ts_micros)))
+            .filter(|victim| victim.priority < priority)
+            .map_or(Admission::Reject, |victim| {
+                Admission::Preempt(victim.stream.to_string())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Recordings of a node, stream to priority and start
+    #[derive(Default)]
+    struct Node {
+        recordings: HashMap<String, (u8, i64)>,
+        rejected: usize,
+        preempted: Vec<String>,
+    }
+
+    impl Node {
+        fn stream_up(&mut self, limit: &RecordingLimit, stream: String, priority: u8, ts: i64) {
+            let active = self
+                .recordings
+                .iter()
+                .map(|(stream, (priority, ts))| Active {
+                    stream,
+                    priority: *priority,
+                    start_ts_micros: *ts,
+                });
+            match limit.admit(active, priority) {
+                Admission::Admit => {}
+                Admission::Reject => {
+                    self.rejected += 1;
+                    return;
+                }
+                Admission::Preempt(victim) => {
+                    self.recordings.remove(&victim);
+                    self.preempted.push(victim);
+                }
+            }
+            self.recordings.insert(stream, (priority, ts));
+        }
+    }
+
+    #[test]
+    fn test_unlimited_admits_everything() {
+        let mut node = Node::default();
+        for i in 0..500 {
+            node.stream_up(&RecordingLimit::default(), format!("cam-{i}"), 100, i);
+        }
+        assert_eq!(node.recordings.len(), 500);
+        assert_eq!(node.rejected, 0);
+    }
+
+    #[test]
+    fn test_reject_mode_caps_recordings() {
+        let limit = RecordingLimit {
+            max: 50,
+            preempt: false,
+        };
+        let mut node = Node::default();
+        for i in 0..500 {
+            // Even important streams are refused once the node is full
+            let priority = if i % 7 == 0 { 255 } else { 100 };
+            node.stream_up(&limit, format!("cam-{i}"), priority, i);
+        }
+        assert_eq!(node.recordings.len(), 50);
+        assert_eq!(node.rejected, 450);
+        assert!(node.preempted.is_empty());
+        assert!((0..50).all(|i| node.recordings.contains_key(&format!("cam-{i}"))));
+        assert!(limit.capacity(node.recordings.len()).at_limit());
+
+        // A stopped recording frees a slot
+        node.recordings.remove("cam-0");
+        node.stream_up(&limit, "late".to_string(), 100, 500);
+        assert!(node.recordings.contains_key("late"));
+    }
+
+    #[test]
+    fn test_preempt_mode_stops_lowest_priority() {
+        let limit = RecordingLimit {
+            max: 50,
+            preempt: true,
+        };
+        let mut node = Node::default();
+        // 50 low priority streams fill the node, the later equals are refused
+        for i in 0..100 {
+            node.stream_up(&limit, format!("low-{i}"), 10, i);
+        }
+        assert_eq!(node.rejected, 50);
+        assert!(node.preempted.is_empty());
+
+        // Each important stream displaces the newest low priority recording
+        for i in 0..20 {
+            node.stream_up(&limit, format!("gate-{i}"), 200, 100 + i);
+        }
+        assert_eq!(node.recordings.len(), 50);
+        assert_eq!(node.preempted.len(), 20);
+        assert_eq!(node.preempted[0], "low-49");
+        assert_eq!(node.preempted[19], "low-30");
+        assert!((0..30).all(|i| node.recordings.contains_key(&format!("low-{i}"))));
+
+        // Middle priorities displace the low ones but never the important ones
+        for i in 0..100 {
+            node.stream_up(&limit, format!("lobby-{i}"), 100, 200 + i);
+        }
+        let gates = node.recordings.keys().filter(|s| s.starts_with("gate-"));
+        assert_eq!(gates.count(), 20);
+        let lobbies = node.recordings.keys().filter(|s| s.starts_with("lobby-"));
+        assert_eq!(lobbies.count(), 30);
+        assert_eq!(node.preempted.len(), 50);
+        assert_eq!(node.rejected, 50 + 70);
+    }
+}
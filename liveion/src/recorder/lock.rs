@@ -0,0 +1,450 @@
+//! Locks on the index file.
+//!
+//! `{index}.owner` is held for the life of the process so two processes never share an
+//! index, `{index}.lock` serializes each write. Both record their holder, so a lock that
+//! cannot be acquired in time is reported with the PID and host holding it. On network
+//! filesystems where flock is unreliable the ownership lock is a lease file renewed by a
+//! heartbeat instead, and writes rely on it alone.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::config::{IndexLockConfig, IndexLockMode};
+
+/// Delay between attempts on a busy lock
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A lock another process kept holding past the wait, writes may succeed once it lets go
+#[derive(Debug)]
+pub struct LockTimeout {
+    pub path: PathBuf,
+    pub waited: Duration,
+    pub holder: Option<LockHolder>,
+}
+
+impl std::fmt::Display for LockTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "timed out after {}s waiting for lock {} held by {}, \
+             restart with --force-unlock if that process is gone",
+            self.waited.as_secs(),
+            self.path.display(),
+            describe(self.holder.clone())
+        )
+    }
+}
+
+impl std::error::Error for LockTimeout {}
+
+/// Process recorded in a lock file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub hostname: String,
+    /// Last renewal of a lease, UNIX seconds
+    #[serde(default)]
+    pub heartbeat: i64,
+}
+
+impl LockHolder {
+    fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            hostname: hostname(),
+            heartbeat: Utc::now().timestamp(),
+        }
+    }
+
+    fn read(path: &Path) -> Option<Self> {
+        serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+    }
+
+    fn is_current(&self) -> bool {
+        self.pid == std::process::id() && self.hostname == hostname()
+    }
+
+    /// `Some(true)` when the process is gone, `None` when that cannot be told from here
+    fn is_dead(&self) -> Option<bool> {
+        if self.hostname != hostname() {
+            return None;
+        }
+        // A previous process whose PID was reused by this one
+        if self.pid == std::process::id() {
+            return Some(true);
+        }
+        pid_alive(self.pid).map(|alive| !alive)
+    }
+}
+
+impl std::fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pid {} on {}", self.pid, self.hostname)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn pid_alive(pid: u32) -> Option<bool> {
+    Some(Path::new("/proc").join(pid.to_string()).exists())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pid_alive(_pid: u32) -> Option<bool> {
+    None
+}
+
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+fn describe(holder: Option<LockHolder>) -> String {
+    holder.map_or_else(|| "an unknown holder".to_string(), |h| h.to_string())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LockOptions {
+    pub mode: IndexLockMode,
+    pub timeout: Duration,
+    pub lease_ttl: Duration,
+}
+
+impl Default for LockOptions {
+    fn default() -> Self {
+        Self::from(&IndexLockConfig::default())
+    }
+}
+
+impl From<&IndexLockConfig> for LockOptions {
+    fn from(cfg: &IndexLockConfig) -> Self {
+        Self {
+            mode: cfg.mode,
+            timeout: Duration::from_secs(cfg.timeout_seconds.max(1)),
+            lease_ttl: Duration::from_secs(cfg.lease_ttl_seconds.max(3)),
+        }
+    }
+}
+
+pub fn lock_path_for(index_path: &Path) -> PathBuf {
+    sibling(index_path, "lock")
+}
+
+pub fn owner_path_for(index_path: &Path) -> PathBuf {
+    sibling(index_path, "owner")
+}
+
+fn sibling(index_path: &Path, suffix: &str) -> PathBuf {
+    let name = index_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("index.json");
+    index_path.with_file_name(format!("{name}.{suffix}"))
+}
+
+/// Exclusive flock on `path`, waiting at most `timeout` for the current holder
+pub fn lock_exclusive(path: &Path, timeout: Duration) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("open lock {}", path.display()))?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        match file.try_lock_exclusive() {
+            Ok(()) => break,
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                if Instant::now() >= deadline {
+                    let mut content = Vec::new();
+                    let _ = file.read_to_end(&mut content);
+                    return Err(LockTimeout {
+                        path: path.to_path_buf(),
+                        waited: timeout,
+                        holder: serde_json::from_slice(&content).ok(),
+                    }
+                    .into());
+                }
+                std::thread::sleep(RETRY_INTERVAL);
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("lock {}", path.display()));
+            }
+        }
+    }
+    file.set_len(0)?;
+    file.rewind()?;
+    serde_json::to_writer(&mut file, &LockHolder::current())?;
+    Ok(file)
+}
+
+/// Ownership of an index, released on drop
+pub struct IndexOwner {
+    path: PathBuf,
+    mode: IndexLockMode,
+    _file: Option<File>,
+    heartbeat: Option<JoinHandle<()>>,
+}
+
+impl IndexOwner {
+    /// Take ownership of the index at `index_path`, waiting at most `opts.timeout`
+    pub async fn acquire(index_path: &Path, opts: LockOptions) -> Result<Self> {
+        let path = owner_path_for(index_path);
+        match opts.mode {
+            IndexLockMode::Flock => {
+                let lock = path.clone();
+                let file = tokio::task::spawn_blocking(move || lock_exclusive(&lock, opts.timeout))
+                    .await??;
+                Ok(Self {
+                    path,
+                    mode: opts.mode,
+                    _file: Some(file),
+                    heartbeat: None,
+                })
+            }
+            IndexLockMode::Lease => {
+                acquire_lease(&path, opts).await?;
+                let heartbeat = tokio::spawn(renew_lease(path.clone(), opts.lease_ttl / 3));
+                Ok(Self {
+                    path,
+                    mode: opts.mode,
+                    _file: None,
+                    heartbeat: Some(heartbeat),
+                })
+            }
+        }
+    }
+}
+
+impl Drop for IndexOwner {
+    fn drop(&mut self) {
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.abort();
+        }
+        if self.mode == IndexLockMode::Lease
+            && LockHolder::read(&self.path).is_some_and(|h| h.is_current())
+        {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+async fn acquire_lease(path: &Path, opts: LockOptions) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let deadline = Instant::now() + opts.timeout;
+    loop {
+        let holder = LockHolder::read(path);
+        let free = holder.as_ref().is_none_or(|h| {
+            h.is_current()
+                || h.is_dead() == Some(true)
+                || Utc::now().timestamp().saturating_sub(h.heartbeat)
+                    > opts.lease_ttl.as_secs() as i64
+        });
+        if free {
+            write_lease(path).await?;
+            // Another process may have taken the stale lease at the same time, the last
+            // writer wins and the others see it here
+            tokio::time::sleep(RETRY_INTERVAL).await;
+            if LockHolder::read(path).is_some_and(|h| h.is_current()) {
+                return Ok(());
+            }
+        } else if Instant::now() >= deadline {
+            bail!(
+                "timed out after {}s waiting for lease {} held by {}, renewed {}s ago",
+                opts.timeout.as_secs(),
+                path.display(),
+                describe(holder.clone()),
+                holder.map_or(0, |h| Utc::now().timestamp() - h.heartbeat)
+            );
+        } else {
+            tokio::time::sleep(RETRY_INTERVAL).await;
+        }
+    }
+}
+
+async fn write_lease(path: &Path) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+    tokio::fs::write(&tmp, serde_json::to_vec(&LockHolder::current())?).await?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("write lease {}", path.display()))
+}
+
+async fn renew_lease(path: PathBuf, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        if !LockHolder::read(&path).is_some_and(|h| h.is_current()) {
+            tracing::error!(
+                "[recorder] index lease {} was taken over by {}",
+                path.display(),
+                describe(LockHolder::read(&path))
+            );
+            return;
+        }
+        if let Err(e) = write_lease(&path).await {
+            tracing::warn!("[recorder] renewing index lease failed: {:#}", e);
+        }
+    }
+}
+
+/// Remove the lock files of the index at `index_path` whose holder is verified dead on
+/// this host, for `--force-unlock`. Returns the removed paths.
+pub fn force_unlock(index_path: &Path) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for path in [owner_path_for(index_path), lock_path_for(index_path)] {
+        if !path.exists() {
+            continue;
+        }
+        match LockHolder::read(&path) {
+            Some(holder) => match holder.is_dead() {
+                Some(true) => {}
+                Some(false) => bail!("{} is held by {}, still running", path.display(), holder),
+                None => bail!(
+                    "{} is held by {}, which cannot be verified from this host",
+                    path.display(),
+                    holder
+                ),
+            },
+            // No holder recorded, only remove it when nobody holds it
+            None => {
+                let file = File::open(&path)?;
+                if file.try_lock_exclusive().is_err() {
+                    bail!("{} is held by an unknown process", path.display());
+                }
+            }
+        }
+        std::fs::remove_file(&path).with_context(|| format!("remove {}", path.display()))?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// No process has this PID, above the Linux maximum
+    const DEAD_PID: u32 = 0x7fff_fff0;
+
+    fn opts(mode: IndexLockMode) -> LockOptions {
+        LockOptions {
+            mode,
+            timeout: Duration::from_secs(1),
+            lease_ttl: Duration::from_secs(30),
+        }
+    }
+
+    fn write_holder(path: &Path, pid: u32, hostname: &str, heartbeat: i64) {
+        let holder = LockHolder {
+            pid,
+            hostname: hostname.to_string(),
+            heartbeat,
+        };
+        std::fs::write(path, serde_json::to_vec(&holder).unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stale_flock_names_holder() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = dir.path().join("index.json");
+        let owner = owner_path_for(&index);
+        // A killed process whose lock the filesystem never released
+        let stuck = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&owner)
+            .unwrap();
+        stuck.lock_exclusive().unwrap();
+        write_holder(&owner, DEAD_PID, &hostname(), 0);
+
+        let err = IndexOwner::acquire(&index, opts(IndexLockMode::Flock))
+            .await
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains(&owner.display().to_string()), "{err}");
+        assert!(
+            err.contains(&format!("pid {DEAD_PID} on {}", hostname())),
+            "{err}"
+        );
+
+        assert_eq!(force_unlock(&index).unwrap(), vec![owner.clone()]);
+        let _owner = IndexOwner::acquire(&index, opts(IndexLockMode::Flock))
+            .await
+            .unwrap();
+        assert!(LockHolder::read(&owner).unwrap().is_current());
+        drop(stuck);
+    }
+
+    #[test]
+    fn test_force_unlock_spares_live_and_remote_holders() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = dir.path().join("index.json");
+        let lock = lock_path_for(&index);
+
+        write_holder(&lock, 1, &hostname(), 0);
+        let err = force_unlock(&index).unwrap_err().to_string();
+        assert!(err.contains("pid 1 on"), "{err}");
+        assert!(lock.exists());
+
+        write_holder(&lock, DEAD_PID, "other-host", 0);
+        let err = force_unlock(&index).unwrap_err().to_string();
+        assert!(err.contains("other-host"), "{err}");
+        assert!(lock.exists());
+
+        write_holder(&lock, DEAD_PID, &hostname(), 0);
+        assert_eq!(force_unlock(&index).unwrap(), vec![lock.clone()]);
+        assert!(!lock.exists());
+    }
+
+    #[tokio::test]
+    async fn test_lease_takes_over_stale_holder() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = dir.path().join("index.json");
+        let owner = owner_path_for(&index);
+
+        // Renewed just now by another node
+        write_holder(&owner, DEAD_PID, "other-host", Utc::now().timestamp());
+        let err = IndexOwner::acquire(&index, opts(IndexLockMode::Lease))
+            .await
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            err.contains(&format!("pid {DEAD_PID} on other-host")),
+            "{err}"
+        );
+
+        // Not renewed for longer than the lease
+        write_holder(&owner, DEAD_PID, "other-host", Utc::now().timestamp() - 60);
+        let lease = IndexOwner::acquire(&index, opts(IndexLockMode::Lease))
+            .await
+            .unwrap();
+        assert!(LockHolder::read(&owner).unwrap().is_current());
+        drop(lease);
+        assert!(!owner.exists());
+    }
+}
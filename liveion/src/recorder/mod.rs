@@ -1,51 +1,207 @@
 use glob::Pattern;
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::Duration;
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, broadcast};
 use tokio::time::{self, MissedTickBehavior};
 
-use opendal::Operator;
 #[cfg(feature = "recorder")]
-use storage::init_operator;
+use storage::init_failover_operator;
+use storage::{
+    ChaosConfig, ChaosLayer, DiagnoseConfig, DiagnoseReport, FailoverOperator, Invalidator,
+    SegmentPattern, StorageConfig,
+};
 
-use crate::hook::{Event, StreamEventType};
+use crate::forward::message::{ForwardEvent, ForwardEventType};
+use crate::hook::{Event, RecordingEvent, RecordingEventType, StreamEventType};
 use crate::stream::manager::Manager;
 use api::recorder::{
-    AckRecordingsRequest, AckRecordingsResponse, DeleteRecordingsRequest, DeleteRecordingsResponse,
-    PullRecordingsRequest, PullRecordingsResponse, RecordingStatus,
+    AckRecordingsRequest, AckRecordingsResponse, AuditOperation, AuditOutcome, AuditRecord,
+    DeleteRecordingsRequest, DeleteRecordingsResponse, DryRunResponse, ImportRecordingsResponse,
+    JournalKind, ListCursor, MediaInfo, PullRecordingsRequest, PullRecordingsResponse,
+    ReconcileStatus, RecorderStats, RecordingSize, RecordingStatus, RenameStreamRequest,
+    RetentionClass, UpdateRecordingRequest, VerifyRecordingResponse,
 };
+use api::response::StreamRecording;
 use chrono::Utc;
 
 #[cfg(feature = "recorder")]
-use crate::config::RecorderConfig;
+use crate::config::{HealthConfig, RecorderConfig, RepublishMode, StallAction};
 
+mod audit;
+mod backup;
+mod bench;
+mod capabilities;
+mod captions;
+mod clock;
+mod disk;
+mod durability;
+mod events;
+mod health;
+mod import;
 mod index;
+mod journal;
+mod lag;
+mod lease;
+mod limit;
+mod lock;
 mod pli_backoff;
+mod probe;
+mod push;
+mod reconcile;
+mod rename;
+mod repair;
+mod republish;
+mod retention;
+mod runtime;
+pub mod schedule;
 mod segmenter;
+mod shutdown;
+mod staging;
+mod stall;
+mod startup;
+mod stats;
 mod task;
+pub mod tenancy;
+mod trigger;
 mod uploader;
-use task::RecordingTask;
+mod verify;
+use task::{ParkedRecording, RecordingTask};
 pub mod codec;
 mod fmp4;
+use audit::AuditLog;
+pub use audit::DryRun;
+use backup::IndexBackup;
+pub use backup::RestoreOutcome;
+pub use bench::{BenchOptions, BenchReport, BenchSink, bench_recorder};
+pub use capabilities::capabilities;
+use captions::Captioner;
+pub use captions::{CaptionsOutcome, valid_lang};
+use durability::Durability;
+pub use events::{Delivery, EventFilter, Subscription, subscriber_label};
+use import::Importer;
+pub use index::{MetadataUpdate, TrashUpdate};
 use index::{RecordingIndexEntry, RecordingsIndex};
+use lease::LeaseClient;
+use limit::{Active, Admission, RecordingLimit};
+pub use lock::LockTimeout;
+use lock::{IndexOwner, LockOptions};
+use reconcile::Reconciler;
+pub use rename::RenameOutcome;
+use rename::StreamRenamer;
+pub use repair::RepairOutcome;
+use repair::Repairer;
+use republish::{Decision, Finalized, RepublishPolicy, Sessions};
+use retention::{Retention, RetentionPolicy};
+use startup::StartupGate;
+use stats::StatsCache;
+use tenancy::Tenancy;
+use trigger::Triggers;
 use uploader::UploadManager;
+use verify::Verifier;
 
 static TASKS: Lazy<RwLock<HashMap<String, RecordingTask>>> =
     Lazy::new(|| RwLock::new(HashMap::new()));
 
-static STORAGE: Lazy<RwLock<Option<Operator>>> = Lazy::new(|| RwLock::new(None));
+static STORAGE: Lazy<RwLock<Option<FailoverOperator>>> = Lazy::new(|| RwLock::new(None));
 static INDEX: Lazy<RwLock<Option<Arc<RecordingsIndex>>>> = Lazy::new(|| RwLock::new(None));
+/// Held as long as [`INDEX`] is, keeps other processes off the index file
+static INDEX_OWNER: Lazy<RwLock<Option<IndexOwner>>> = Lazy::new(|| RwLock::new(None));
 static NODE_ALIAS: Lazy<RwLock<Option<String>>> = Lazy::new(|| RwLock::new(None));
+/// Prefix of generated record dirs, see [`RecorderConfig::key_namespace`]
+static KEY_NAMESPACE: Lazy<RwLock<Option<String>>> = Lazy::new(|| RwLock::new(None));
+/// `recorder.tenancy`, applied to recordings started afterwards
+static TENANCY: Lazy<RwLock<Tenancy>> = Lazy::new(|| RwLock::new(Tenancy::default()));
 static UPLOADER: Lazy<RwLock<Option<Arc<UploadManager>>>> = Lazy::new(|| RwLock::new(None));
+/// `recorder.durability`, shared by the index and the upload queue
+static DURABILITY: Lazy<RwLock<Option<Arc<Durability>>>> = Lazy::new(|| RwLock::new(None));
+static RECONCILER: Lazy<RwLock<Option<Arc<Reconciler>>>> = Lazy::new(|| RwLock::new(None));
+static RENAMER: Lazy<RwLock<Option<Arc<StreamRenamer>>>> = Lazy::new(|| RwLock::new(None));
+static IMPORTER: Lazy<RwLock<Option<Arc<Importer>>>> = Lazy::new(|| RwLock::new(None));
+static RETENTION: Lazy<RwLock<Option<Arc<Retention>>>> = Lazy::new(|| RwLock::new(None));
+static VERIFIER: Lazy<RwLock<Option<Arc<Verifier>>>> = Lazy::new(|| RwLock::new(None));
+static REPAIRER: Lazy<RwLock<Option<Arc<Repairer>>>> = Lazy::new(|| RwLock::new(None));
+static CAPTIONER: Lazy<RwLock<Option<Arc<Captioner>>>> = Lazy::new(|| RwLock::new(None));
+static BACKUP: Lazy<RwLock<Option<Arc<IndexBackup>>>> = Lazy::new(|| RwLock::new(None));
+static AUDIT: Lazy<RwLock<Option<Arc<AuditLog>>>> = Lazy::new(|| RwLock::new(None));
+static STARTUP: Lazy<RwLock<Option<Arc<StartupGate>>>> = Lazy::new(|| RwLock::new(None));
+static STATS: Lazy<tokio::sync::Mutex<StatsCache>> =
+    Lazy::new(|| tokio::sync::Mutex::new(StatsCache::default()));
+static RETENTION_POLICY: Lazy<RwLock<RetentionPolicy>> =
+    Lazy::new(|| RwLock::new(RetentionPolicy::default()));
+/// Set once shutdown begins, no new recording is started afterwards
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+/// `recorder.dedup_init_segments`, applied to recordings started afterwards
+static DEDUP_INIT_SEGMENTS: AtomicBool = AtomicBool::new(false);
+/// `recorder.upload.verify_size`, for objects written to storage directly
+static VERIFY_SIZE: AtomicBool = AtomicBool::new(true);
+/// Whether `TENANCY` is enabled, stream names may hold `/` then
+static NAME_SEPARATORS: AtomicBool = AtomicBool::new(false);
+/// `recorder.segment_pattern`, applied to recordings started afterwards
+static SEGMENT_PATTERN: Lazy<RwLock<SegmentPattern>> =
+    Lazy::new(|| RwLock::new(SegmentPattern::default()));
+/// `recorder.reconnect_grace_seconds`, applied to recordings started afterwards
+static RECONNECT_GRACE_SECONDS: AtomicU64 = AtomicU64::new(0);
+/// `recorder.stall_timeout_seconds`, applied to recordings started afterwards
+static STALL_TIMEOUT_SECONDS: AtomicU64 = AtomicU64::new(0);
+/// `recorder.stall_action`, applied to recordings stalling afterwards
+static STALL_ACTION: Lazy<RwLock<StallAction>> = Lazy::new(|| RwLock::new(StallAction::Wait));
+static RECORDING_LIMIT: Lazy<RwLock<RecordingLimit>> =
+    Lazy::new(|| RwLock::new(RecordingLimit::default()));
+/// Recordings finalized after their publisher stayed away past the reconnect grace, the
+/// stream's next publisher continues them
+static RESUMABLE: Lazy<RwLock<HashMap<String, RecordingInfo>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+/// Streams whose recordings are being finalized or parked, see [`republish`]
+static SESSIONS: Lazy<RwLock<Sessions<ParkedRecording>>> =
+    Lazy::new(|| RwLock::new(Sessions::default()));
+static REPUBLISH_POLICY: Lazy<RwLock<RepublishPolicy>> =
+    Lazy::new(|| RwLock::new(RepublishPolicy::default()));
+/// `recorder.health`, the thresholds of the integrity rollup
+static HEALTH: Lazy<RwLock<HealthConfig>> = Lazy::new(|| RwLock::new(HealthConfig::default()));
+static CHAOS: Lazy<RwLock<Option<ChaosLayer>>> = Lazy::new(|| RwLock::new(None));
+/// Storage config and `recorder.diagnose` when recordings are written to storage directly
+static DIAGNOSE: Lazy<RwLock<Option<(StorageConfig, DiagnoseConfig)>>> =
+    Lazy::new(|| RwLock::new(None));
+static SCHEDULER: Lazy<RwLock<SchedulerState>> =
+    Lazy::new(|| RwLock::new(SchedulerState::default()));
+static INVALIDATOR: Lazy<RwLock<Option<Invalidator>>> = Lazy::new(|| RwLock::new(None));
+static LEASES: Lazy<RwLock<Option<Arc<LeaseClient>>>> = Lazy::new(|| RwLock::new(None));
+/// Per stream, the task renewing its lease or waiting for another node's to expire
+static LEASE_TASKS: Lazy<RwLock<HashMap<String, tokio::task::JoinHandle<()>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+static TRIGGERS: Lazy<RwLock<Option<Arc<Triggers>>>> = Lazy::new(|| RwLock::new(None));
+
+/// Interval between schedule evaluations
+const SCHEDULE_TICK: Duration = Duration::from_secs(15);
+
+#[derive(Default)]
+struct SchedulerState {
+    schedules: Vec<schedule::Schedule>,
+    /// Streams whose recording was started by a schedule, only these are stopped by it
+    started: HashSet<String>,
+    /// Scheduled recordings outside their windows keep running until then after a reload
+    grace_until: Option<chrono::DateTime<Utc>>,
+}
 
 #[derive(Clone, Debug)]
 pub struct RecordingInfo {
     pub record_dir: String,
     pub record_id: i64,
     pub start_ts_micros: i64,
+    /// Monotonic reading of the task's clock at `start_ts_micros`
+    pub started: Duration,
+    /// Class the recording's uploads are tagged with
+    pub retention_class: Option<RetentionClass>,
+    /// Upload order and quota deletion order, higher goes first and is deleted last
+    pub priority: u8,
+    /// WHEP URL of the cascade pull being recorded, `None` for local publishers
+    pub source: Option<String>,
+    /// Tenant of the stream, see [`tenancy`]
+    pub tenant: Option<String>,
 }
 
 /// Initialize recorder event listener.
@@ -61,8 +217,23 @@ pub async fn init(manager: Arc<Manager>, cfg: RecorderConfig) {
                 "[recorder] initializing storage operator with config: {:?}",
                 cfg.storage
             );
-            match init_operator(&cfg.storage).await {
+            match init_failover_operator(&cfg.storage).await {
                 Ok(op) => {
+                    let op = match cfg.chaos.clone() {
+                        Some(chaos) if storage::CHAOS_AVAILABLE => {
+                            tracing::warn!("[recorder] storage chaos enabled: {:?}", chaos);
+                            let layer = ChaosLayer::new(chaos);
+                            *CHAOS.write().await = Some(layer.clone());
+                            op.layer(layer)
+                        }
+                        Some(_) => {
+                            tracing::warn!(
+                                "[recorder] storage chaos ignored, build without the chaos feature"
+                            );
+                            op
+                        }
+                        None => op,
+                    };
                     *storage_writer = Some(op);
                     tracing::info!("[recorder] storage backend initialized successfully");
                 }
@@ -80,22 +251,87 @@ pub async fn init(manager: Arc<Manager>, cfg: RecorderConfig) {
         let mut alias = NODE_ALIAS.write().await;
         *alias = cfg.node_alias.clone();
     }
+    *KEY_NAMESPACE.write().await = cfg.key_namespace();
+    match Tenancy::new(&cfg.tenancy) {
+        Ok(tenancy) => {
+            NAME_SEPARATORS.store(tenancy.is_enabled(), Ordering::Release);
+            *TENANCY.write().await = tenancy;
+        }
+        Err(e) => tracing::error!("[recorder] invalid tenancy, left unchanged: {}", e),
+    }
+    DEDUP_INIT_SEGMENTS.store(cfg.dedup_init_segments, Ordering::Release);
+    VERIFY_SIZE.store(cfg.upload.verify_size, Ordering::Release);
+    match SegmentPattern::parse(&cfg.segment_pattern) {
+        Ok(pattern) => *SEGMENT_PATTERN.write().await = pattern,
+        Err(e) => tracing::error!("[recorder] invalid segment_pattern, left unchanged: {}", e),
+    }
+    RECONNECT_GRACE_SECONDS.store(cfg.reconnect_grace_seconds, Ordering::Release);
+    STALL_TIMEOUT_SECONDS.store(cfg.stall_timeout_seconds, Ordering::Release);
+    *STALL_ACTION.write().await = cfg.stall_action;
+    *RECORDING_LIMIT.write().await = RecordingLimit::from_config(&cfg);
+    *RETENTION_POLICY.write().await = RetentionPolicy::from_config(&cfg);
+    *REPUBLISH_POLICY.write().await = RepublishPolicy::from_config(&cfg);
+    *HEALTH.write().await = cfg.health.clone();
+    let durability = {
+        let mut durability = DURABILITY.write().await;
+        durability
+            .get_or_insert_with(|| {
+                let durability = Arc::new(Durability::new(&cfg.durability));
+                let runtime = runtime::handle(&cfg.runtime);
+                runtime.spawn(Durability::run_flusher(
+                    Arc::downgrade(&durability),
+                    runtime.clone(),
+                ));
+                durability
+            })
+            .clone()
+    };
 
     if let Some(index_path) = resolve_index_path(&cfg) {
         let mut index_writer = INDEX.write().await;
         if index_writer.is_none() {
-            match RecordingsIndex::load(index_path).await {
-                Ok(idx) => {
-                    *index_writer = Some(Arc::new(idx));
+            match open_index(&cfg, index_path, durability.clone()).await {
+                Ok((idx, owner)) => {
+                    let idx = Arc::new(idx);
+                    runtime::handle(&cfg.runtime).spawn(idx.clone().run_compactor());
+                    *index_writer = Some(idx);
+                    *INDEX_OWNER.write().await = Some(owner);
                     tracing::info!("[recorder] index.json initialized");
                 }
                 Err(e) => {
-                    tracing::error!("[recorder] failed to load index.json: {}", e);
+                    tracing::error!("[recorder] failed to load index.json: {:#}", e);
                 }
             }
         }
     }
 
+    init_audit(&cfg).await;
+    init_reconciler(manager.clone(), &cfg).await;
+    init_renamer(&cfg).await;
+    init_importer(&cfg).await;
+    init_pusher(&cfg).await;
+    init_leases(&cfg).await;
+    init_triggers(manager.clone(), &cfg).await;
+    *INVALIDATOR.write().await = Invalidator::spawn(cfg.invalidate.clone());
+
+    if !cfg.upload.enabled {
+        *DIAGNOSE.write().await = Some((cfg.storage.clone(), cfg.diagnose.clone()));
+        if cfg.startup.max_wait_seconds > 0
+            && let Some(op) = STORAGE.read().await.clone()
+        {
+            let gate = Arc::new(StartupGate::new("storage"));
+            *STARTUP.write().await = Some(gate.clone());
+            let startup = cfg.startup.clone();
+            tokio::spawn(async move {
+                gate.run(&startup, || {
+                    let op = op.current();
+                    async move { storage::test_connection(&op).await }
+                })
+                .await
+            });
+        }
+    }
+
     if cfg.upload.enabled {
         if cfg.upload.liveman_url.trim().is_empty() {
             tracing::warn!("[recorder] upload enabled but liveman_url is empty");
@@ -104,8 +340,28 @@ pub async fn init(manager: Arc<Manager>, cfg: RecorderConfig) {
             if uploader_guard.is_none() {
                 match UploadManager::load(cfg.upload.clone()).await {
                     Ok(manager) => {
-                        let manager = Arc::new(manager);
-                        tokio::spawn(manager.clone().run());
+                        let runtime = runtime::handle(&cfg.runtime);
+                        let manager = Arc::new(
+                            manager
+                                .with_runtime(runtime.clone())
+                                .with_durability(durability.clone()),
+                        );
+                        if cfg.startup.max_wait_seconds > 0 {
+                            let gate = Arc::new(StartupGate::new("liveman"));
+                            *STARTUP.write().await = Some(gate.clone());
+                            let (startup, manager) = (cfg.startup.clone(), manager.clone());
+                            runtime.spawn(async move {
+                                gate.run(&startup, || manager.ping()).await;
+                                manager.run().await
+                            });
+                        } else {
+                            runtime.spawn(manager.clone().run());
+                        }
+                        tokio::spawn(publish_uploaded(manager.subscribe_drained()));
+                        runtime.spawn(manager.clone().run_queue_flusher());
+                        if cfg.upload.local_retention_minutes > 0 {
+                            runtime.spawn(manager.clone().prune_loop());
+                        }
                         *uploader_guard = Some(manager);
                         tracing::info!("[recorder] uploader initialized");
                     }
@@ -117,293 +373,2140 @@ pub async fn init(manager: Arc<Manager>, cfg: RecorderConfig) {
         }
     }
 
+    init_retention(&cfg).await;
+    init_verifier(&cfg).await;
+    init_repairer(&cfg).await;
+    init_backup(&cfg).await;
+
+    SCHEDULER.write().await.schedules = compile_schedules(&cfg);
+    tokio::spawn(schedule_loop(manager.clone()));
+
     let cfg = Arc::new(cfg);
     let cfg_for_events = cfg.clone();
     let mut recv = manager.subscribe_event();
     tokio::spawn(async move {
         while let Ok(event) = recv.recv().await {
-            if let Event::Stream(stream_event) = event {
-                match stream_event.r#type {
+            match event {
+                Event::Stream(stream_event) => match stream_event.r#type {
                     StreamEventType::Up => {
-                        let stream_name = stream_event.stream.stream;
-                        if should_record(&cfg_for_events.auto_streams, &stream_name)
-                            && let Err(e) =
-                                start(manager_clone.clone(), stream_name.clone(), None).await
-                        {
-                            tracing::error!("[recorder] start failed: {}", e);
-                        }
+                        on_publish(&manager_clone, &cfg_for_events, stream_event.stream.stream)
+                            .await;
                     }
                     StreamEventType::Down => {
                         let stream_name = stream_event.stream.stream;
-                        let task_opt = {
-                            let mut map = TASKS.write().await;
-                            map.remove(&stream_name)
-                        };
-
-                        if let Some(task) = task_opt {
-                            let info = task.info.clone();
-                            let outcome = task.stop().await;
-                            update_index_on_stop(&stream_name, &info, outcome).await;
+                        RESUMABLE.write().await.remove(&stream_name);
+                        release_lease(&stream_name).await;
+                        if let Some(triggers) = TRIGGERS.read().await.clone() {
+                            triggers.forget(&stream_name).await;
+                        }
+                        // Finalized off the event loop, a publisher coming back meanwhile
+                        // is handled per the stream's republish mode
+                        if let Some(task) = take_task(&stream_name, None).await {
+                            let park = REPUBLISH_POLICY.read().await.mode_for(&stream_name)
+                                == RepublishMode::Resume;
                             tracing::info!("[recorder] stop recording task for {}", stream_name);
+                            tokio::spawn(end_recording(stream_name, task, park));
                         }
                     }
+                },
+                // A WHIP publisher or cascade pull into a stream that already exists
+                Event::Forward(ForwardEvent {
+                    r#type: ForwardEventType::PublishUp,
+                    stream_info,
+                    ..
+                }) => {
+                    on_publish(&manager_clone, &cfg_for_events, stream_info.id).await;
                 }
+                Event::Forward(_) => {}
             }
         }
     });
 
-    if cfg.max_recording_seconds > 0 {
-        let manager_for_rotation = manager.clone();
+    if shortest_split_limit(&cfg) > 0 {
         let cfg_for_rotation = cfg.clone();
         tokio::spawn(async move {
-            rotation_loop(manager_for_rotation, cfg_for_rotation).await;
+            rotation_loop(cfg_for_rotation).await;
         });
     } else {
-        tracing::info!("[recorder] max_recording_seconds is 0, automatic rotation disabled");
+        tracing::info!("[recorder] max recording duration is 0, automatic splitting disabled");
     }
 }
 
-/// Entry point for starting recording manually or automatically
-pub async fn start(
-    manager: Arc<Manager>,
-    stream: String,
-    base_dir: Option<String>,
-) -> anyhow::Result<RecordingInfo> {
-    let mut map = TASKS.write().await;
-    if let Some(existing) = map.get(&stream) {
-        tracing::info!("[recorder] stream {} is already recording", stream);
-        return Ok(existing.info.clone());
+/// Decide whether a stream that just got a publisher is recorded.
+///
+/// A recording still waiting out the reconnect grace goes on by itself. One being
+/// finalized or parked is handled per the stream's [`RepublishMode`], waiting for it
+/// off the event loop. Local publishers and cascade pulls are treated alike.
+async fn on_publish(manager: &Arc<Manager>, cfg: &Arc<RecorderConfig>, stream: String) {
+    if let Some(triggers) = TRIGGERS.read().await.clone() {
+        triggers.watch(&stream).await;
     }
-    let uploader = { UPLOADER.read().await.clone() };
-    let local_dir = uploader.as_ref().map(|u| u.local_dir());
-    let task = RecordingTask::spawn(manager, &stream, base_dir, uploader, local_dir).await?;
-    let info = task.info.clone();
-    map.insert(stream.clone(), task);
-
-    tracing::info!("[recorder] spawn recording task for {}", stream);
-    update_index_on_start(&stream, &info).await;
-    Ok(info)
-}
-
-/// Check whether a stream is currently being recorded on this node
-pub async fn is_recording(stream: &str) -> bool {
-    let map = TASKS.read().await;
-    map.contains_key(stream)
-}
-
-// Query by stream id only
-
-fn should_record(patterns: &[String], stream: &str) -> bool {
-    for p in patterns {
-        if let Ok(pat) = Pattern::new(p)
-            && pat.matches(stream)
-        {
-            return true;
+    if TASKS.read().await.contains_key(&stream) {
+        return;
+    }
+    match republish(&stream).await {
+        Decision::Start => record_publisher(manager, cfg, stream).await,
+        Decision::Resume(parked) => resume_parked(manager, stream, parked).await,
+        Decision::Wait(finalized) => {
+            tracing::info!(
+                "[recorder] {} published again while its last recording is finalizing",
+                stream
+            );
+            tokio::spawn(wait_finalized(
+                manager.clone(),
+                cfg.clone(),
+                stream,
+                finalized,
+            ));
         }
     }
-    false
 }
 
-/// Stop recording for a given stream if running
-pub async fn stop(stream: String) -> anyhow::Result<()> {
-    let task_opt = {
-        let mut map = TASKS.write().await;
-        map.remove(&stream)
+async fn republish(stream: &str) -> Decision<ParkedRecording> {
+    let (mode, max_gap) = {
+        let policy = REPUBLISH_POLICY.read().await;
+        (policy.mode_for(stream), policy.max_gap())
     };
+    SESSIONS
+        .write()
+        .await
+        .republish(stream, mode, clock::system().monotonic(), max_gap)
+}
 
-    if let Some(task) = task_opt {
-        let info = task.info.clone();
-        let outcome = task.stop().await;
-        update_index_on_stop(&stream, &info, outcome).await;
-        tracing::info!("[recorder] stopped recording task for {}", stream);
-    } else {
-        tracing::info!("[recorder] no recording task found for {}", stream);
+/// Carry out [`on_publish`] for `stream` once its recordings are finalized
+async fn wait_finalized(
+    manager: Arc<Manager>,
+    cfg: Arc<RecorderConfig>,
+    stream: String,
+    mut finalized: Finalized,
+) {
+    loop {
+        finalized.wait().await;
+        if !has_publisher(&manager, &stream).await || TASKS.read().await.contains_key(&stream) {
+            return;
+        }
+        match republish(&stream).await {
+            Decision::Start => return record_publisher(&manager, &cfg, stream).await,
+            Decision::Resume(parked) => return resume_parked(&manager, stream, parked).await,
+            Decision::Wait(next) => finalized = next,
+        }
     }
-    Ok(())
 }
 
-async fn update_index_on_start(stream: &str, info: &RecordingInfo) {
-    let index_opt = get_index().await;
-    if index_opt.is_none() {
+/// Record a stream that just got a publisher and has no recording pending.
+///
+/// One whose publisher stayed away past the reconnect grace is continued in a new
+/// recording, other streams go through the auto-record rules and schedules.
+async fn record_publisher(manager: &Arc<Manager>, cfg: &RecorderConfig, stream: String) {
+    if resume(manager, &stream).await {
         return;
     }
+    if should_auto_record(cfg, &stream) {
+        if let Some(leases) = LEASES.read().await.clone() {
+            let task = tokio::spawn(record_with_lease(manager.clone(), leases, stream.clone()));
+            if let Some(previous) = LEASE_TASKS.write().await.insert(stream, task) {
+                previous.abort();
+            }
+        } else if let Err(e) = start(manager.clone(), stream, None, None, None).await {
+            tracing::error!("[recorder] start failed: {}", e);
+        }
+    } else {
+        apply_schedule(manager, &stream, Utc::now()).await;
+    }
+}
 
-    let record = record_key(info);
-    let mpd_path = format!("{}/manifest.mpd", info.record_dir);
-    let entry = RecordingIndexEntry {
-        record,
-        stream: stream.to_string(),
-        record_dir: info.record_dir.clone(),
-        mpd_path,
-        start_ts: info.start_ts_micros,
-        end_ts: None,
-        duration_ms: None,
-        status: RecordingStatus::Active,
-        node_alias: NODE_ALIAS.read().await.clone(),
-        updated_at: Utc::now().timestamp_micros(),
+/// Continue the recording of `stream` lost to a publisher gap, returns whether there
+/// was one
+async fn resume(manager: &Arc<Manager>, stream: &str) -> bool {
+    let Some(previous) = RESUMABLE.write().await.remove(stream) else {
+        return false;
     };
+    match start_recording(
+        manager.clone(),
+        stream.to_string(),
+        None,
+        previous.retention_class.clone(),
+        Some(previous.priority),
+        Some(record_key(&previous)),
+        None,
+    )
+    .await
+    {
+        Ok(next) => tracing::info!(
+            "[recorder] stream {} continues in {} after a publisher gap",
+            stream,
+            next.record_dir
+        ),
+        Err(e) => tracing::error!("[recorder] resuming {} failed: {}", stream, e),
+    }
+    true
+}
 
-    if let Some(index) = index_opt
-        && let Err(e) = index.upsert(entry).await
+/// Continue `parked` with the stream's new publisher, in a new Period of the same
+/// recording. A publisher with another codec gets a new recording linked to it.
+async fn resume_parked(manager: &Arc<Manager>, stream: String, parked: ParkedRecording) {
+    let retention_class = parked.info.retention_class.clone();
+    let priority = parked.info.priority;
+    let continues = record_key(&parked.info);
+    if let Err(e) = start_recording(
+        manager.clone(),
+        stream.clone(),
+        None,
+        retention_class,
+        Some(priority),
+        Some(continues),
+        Some(parked),
+    )
+    .await
     {
-        tracing::error!("[recorder] index.json upsert failed: {}", e);
+        tracing::error!("[recorder] resuming {} failed: {}", stream, e);
     }
 }
 
-async fn update_index_on_stop(
-    stream: &str,
-    info: &RecordingInfo,
-    outcome: task::RecordingStopOutcome,
-) {
-    if let Some(index) = get_index().await {
-        let record = record_key(info);
-        if let Err(e) = index
-            .update_status(
-                stream,
-                &record,
-                outcome.status,
-                Some(outcome.end_ts),
-                Some(outcome.duration_ms),
-            )
-            .await
-        {
-            tracing::error!("[recorder] index.json update failed: {}", e);
+/// Finalize a recording whose publisher did not come back within the reconnect grace,
+/// or came back with another codec.
+///
+/// The stream's next publisher continues it in a new recording, right away when one is
+/// already up. Under `republish = "resume"` it is parked for that publisher instead.
+async fn on_publisher_lost(manager: Arc<Manager>, stream: String, record_dir: String) {
+    let Some(task) = take_task(&stream, Some(&record_dir)).await else {
+        return;
+    };
+    if REPUBLISH_POLICY.read().await.mode_for(&stream) == RepublishMode::Resume {
+        end_recording(stream.clone(), task, true).await;
+    } else {
+        let info = task.info.clone();
+        let outcome = task.stop().await;
+        update_index_on_stop(&stream, &info, outcome).await;
+        tracing::info!(
+            "[recorder] publisher of {} gone, finalized {}",
+            stream,
+            info.record_dir
+        );
+        // Resumable before a publisher waiting for the finalizing looks
+        RESUMABLE.write().await.insert(stream.clone(), info);
+        SESSIONS.write().await.finalized(&stream);
+    }
+    // A publisher that came up meanwhile found the task still there
+    if has_publisher(&manager, &stream).await && !is_recording(&stream).await {
+        let parked = SESSIONS.write().await.unpark(&stream);
+        match parked {
+            Some(parked) => resume_parked(&manager, stream, parked).await,
+            None => {
+                resume(&manager, &stream).await;
+            }
         }
     }
 }
 
-async fn get_index() -> Option<Arc<RecordingsIndex>> {
-    let index = INDEX.read().await;
-    index.clone()
+/// Report the recording in `record_dir` of `stream` stalled, `quiet` without a sample,
+/// and return what it gets per `recorder.stall_action`
+async fn on_stalled(
+    manager: &Arc<Manager>,
+    stream: &str,
+    record_dir: &str,
+    quiet: Duration,
+) -> StallAction {
+    let action = *STALL_ACTION.read().await;
+    tracing::warn!(
+        "[recorder] {} got no media for {:?} while published, stall action {:?}",
+        stream,
+        quiet,
+        action
+    );
+    // The webhook carries the index entry, looked up off the RTP path
+    let manager = manager.clone();
+    let record_dir = record_dir.to_string();
+    tokio::spawn(async move {
+        let Some(index) = get_index().await else {
+            return;
+        };
+        match index.find_by_dir(&record_dir).await {
+            Some(recording) => manager.send_event(Event::Recording(RecordingEvent {
+                r#type: RecordingEventType::Stalled,
+                recording,
+            })),
+            None => tracing::debug!("[recorder] stalled {} is not indexed", record_dir),
+        }
+    });
+    action
 }
 
-pub async fn pull_recordings(req: PullRecordingsRequest) -> anyhow::Result<PullRecordingsResponse> {
-    let Some(index) = get_index().await else {
-        return Ok(PullRecordingsResponse {
-            sessions: Vec::new(),
-            last_ts: None,
-        });
+/// Finalize a recording the stall watchdog ended as `Interrupted`. With `restart` the
+/// stream's next sample starts a new recording whose `continues` names it
+async fn on_stall_ended(manager: Arc<Manager>, stream: String, record_dir: String, restart: bool) {
+    let Some(task) = take_task(&stream, Some(&record_dir)).await else {
+        return;
     };
-
-    let (sessions, last_ts) = index
-        .list_sessions(req.stream, req.since_ts, req.limit)
-        .await;
-
-    Ok(PullRecordingsResponse { sessions, last_ts })
+    let info = task.info.clone();
+    let mut outcome = task.stop().await;
+    outcome.status = RecordingStatus::Interrupted;
+    update_index_on_stop(&stream, &info, outcome).await;
+    SESSIONS.write().await.finalized(&stream);
+    tracing::warn!(
+        "[recorder] finalized stalled recording {} as interrupted",
+        info.record_dir
+    );
+    if !restart || !next_media(&manager, &stream).await || is_recording(&stream).await {
+        return;
+    }
+    match start_recording(
+        manager,
+        stream.clone(),
+        None,
+        info.retention_class.clone(),
+        Some(info.priority),
+        Some(record_key(&info)),
+        None,
+    )
+    .await
+    {
+        Ok(next) => tracing::info!(
+            "[recorder] media of {} is back, recording {}",
+            stream,
+            next.record_dir
+        ),
+        Err(e) => tracing::error!("[recorder] restarting {} failed: {}", stream, e),
+    }
 }
 
-pub async fn ack_recordings(req: AckRecordingsRequest) -> anyhow::Result<AckRecordingsResponse> {
-    let Some(index) = get_index().await else {
-        return Ok(AckRecordingsResponse { acked: 0 });
+/// Wait for the next packet of `stream`'s publisher, false once it is gone
+async fn next_media(manager: &Manager, stream: &str) -> bool {
+    let Some(forward) = manager.get_forward(stream).await else {
+        return false;
     };
-
-    let acked = index.ack(req).await?;
-    Ok(AckRecordingsResponse { acked })
+    let video = forward.subscribe_video_rtp().await;
+    let audio = forward.subscribe_audio_rtp().await;
+    if video.is_none() && audio.is_none() {
+        return false;
+    }
+    tokio::select! {
+        got = next_packet(video) => got,
+        got = next_packet(audio) => got,
+    }
 }
 
-pub async fn delete_recordings(
-    req: DeleteRecordingsRequest,
-) -> anyhow::Result<DeleteRecordingsResponse> {
-    let Some(index) = get_index().await else {
-        return Ok(DeleteRecordingsResponse { deleted: 0 });
-    };
-
-    let deleted = index.delete_acked(req).await?;
-    Ok(DeleteRecordingsResponse { deleted })
+/// Whether a packet, or a lag of them, arrived before `rx` closed. Never without one
+async fn next_packet<T: Clone>(rx: Option<broadcast::Receiver<T>>) -> bool {
+    match rx {
+        Some(mut rx) => !matches!(rx.recv().await, Err(broadcast::error::RecvError::Closed)),
+        None => std::future::pending().await,
+    }
 }
 
-fn record_key(info: &RecordingInfo) -> String {
-    if info.record_id > 0 {
-        return info.record_id.to_string();
+/// Take the task of `stream` out of [`TASKS`], only while it writes to `record_dir`
+/// when given. It counts as finalizing until [`end_recording`] is done with it.
+async fn take_task(stream: &str, record_dir: Option<&str>) -> Option<RecordingTask> {
+    let mut map = TASKS.write().await;
+    if let Some(dir) = record_dir
+        && map.get(stream)?.info.record_dir != dir
+    {
+        return None;
     }
+    let task = map.remove(stream)?;
+    SESSIONS.write().await.finalizing(stream);
+    Some(task)
+}
 
-    let ts = (info.start_ts_micros / 1_000_000).max(0);
-    ts.to_string()
+/// Stop a task taken by [`take_task`] and index its end. With `park` a recording that
+/// ended cleanly is kept open for the stream's next publisher instead.
+async fn end_recording(stream: String, task: RecordingTask, park: bool) {
+    let info = task.info.clone();
+    if park {
+        match task.park().await {
+            Ok(parked) => park_recording(stream.clone(), parked).await,
+            Err(outcome) => update_index_on_stop(&stream, &info, outcome).await,
+        }
+    } else {
+        let outcome = task.stop().await;
+        update_index_on_stop(&stream, &info, outcome).await;
+    }
+    SESSIONS.write().await.finalized(&stream);
 }
 
-fn resolve_index_path(cfg: &RecorderConfig) -> Option<PathBuf> {
-    if let Some(path) = cfg.index_path.as_ref()
-        && !path.trim().is_empty()
-    {
-        return Some(PathBuf::from(path));
+/// Keep `parked` open for `recorder.resume_max_gap_seconds`, it is finalized as it
+/// was unless a publisher continues it by then. It stays active in the index meanwhile.
+async fn park_recording(stream: String, parked: ParkedRecording) {
+    let since = clock::system().monotonic();
+    let max_gap = REPUBLISH_POLICY.read().await.max_gap();
+    tracing::info!(
+        "[recorder] publisher of {} gone, keeping {} open for {:?}",
+        stream,
+        parked.info.record_dir,
+        max_gap
+    );
+    let displaced = SESSIONS.write().await.park(&stream, parked, since);
+    if let Some(displaced) = displaced {
+        finalize_parked(stream.clone(), displaced).await;
     }
+    tokio::spawn(async move {
+        time::sleep(max_gap).await;
+        let expired = SESSIONS.write().await.expire(&stream, since);
+        if let Some(parked) = expired {
+            finalize_parked(stream, parked).await;
+        }
+    });
+}
 
-    Some(PathBuf::from("./recordings/index.json"))
+/// Index the end of a parked recording no publisher continued
+async fn finalize_parked(stream: String, parked: ParkedRecording) {
+    tracing::info!(
+        "[recorder] finalized {}, the publisher of {} did not come back",
+        parked.info.record_dir,
+        stream
+    );
+    update_index_on_stop(&stream, &parked.info, parked.outcome).await;
 }
 
-#[cfg(feature = "recorder")]
-async fn rotation_loop(manager: Arc<Manager>, cfg: Arc<RecorderConfig>) {
-    let max_seconds = cfg.max_recording_seconds;
-    if max_seconds == 0 {
-        return;
+/// Record id of a new recording of `stream`, past every id it had before
+async fn next_record_id(stream: &str) -> i64 {
+    SESSIONS
+        .write()
+        .await
+        .next_record_id(stream, Utc::now().timestamp())
+}
+
+async fn has_publisher(manager: &Manager, stream: &str) -> bool {
+    match manager.get_forward(stream).await {
+        Some(forward) => forward.info().await.publish_session_info.is_some(),
+        None => false,
     }
+}
 
-    let interval_secs = rotation_check_interval(max_seconds);
-    let mut ticker = time::interval(Duration::from_secs(interval_secs));
-    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+/// Auto-record `stream` once liveman grants this node its lease.
+///
+/// Refused, the lease is asked for again as soon as the holder's expires, for as long
+/// as the stream is published here and not recording. Granted, it is renewed until the
+/// stream goes down; a lease lost to another node meanwhile stops the recording.
+async fn record_with_lease(manager: Arc<Manager>, leases: Arc<LeaseClient>, stream: String) {
+    loop {
+        let wait = match leases.acquire(&stream).await {
+            Ok(resp) if resp.granted => {
+                let expires_at = resp.lease.map_or(0, |lease| lease.expires_at);
+                if let Err(e) = start(manager.clone(), stream.clone(), None, None, None).await {
+                    tracing::error!("[recorder] start failed: {}", e);
+                    if let Err(e) = leases.release(&stream).await {
+                        tracing::warn!("[recorder] releasing lease of {} failed: {:#}", stream, e);
+                    }
+                    return;
+                }
+                if !hold_lease(&manager, &leases, &stream, expires_at).await {
+                    return;
+                }
+                if let Err(e) = stop(stream.clone()).await {
+                    tracing::error!("[recorder] stop failed: {}", e);
+                }
+                leases.renew_interval()
+            }
+            Ok(resp) => {
+                if let Some(lease) = &resp.lease {
+                    tracing::info!(
+                        "[recorder] not recording {}, {} holds its lease",
+                        stream,
+                        lease.holder
+                    );
+                }
+                leases.retry_after(&resp, Utc::now().timestamp_micros())
+            }
+            Err(e) => {
+                tracing::warn!("[recorder] lease of {} unavailable: {:#}", stream, e);
+                leases.renew_interval()
+            }
+        };
+        time::sleep(wait).await;
+        if !has_publisher(&manager, &stream).await || is_recording(&stream).await {
+            return;
+        }
+    }
+}
 
+/// Renew the lease of `stream` while it is published here, returns true once the
+/// lease was lost: taken by another node, or expired while liveman was unreachable
+async fn hold_lease(
+    manager: &Manager,
+    leases: &LeaseClient,
+    stream: &str,
+    mut expires_at: i64,
+) -> bool {
+    let mut ticker = time::interval(leases.renew_interval());
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker.tick().await;
     loop {
         ticker.tick().await;
-        if let Err(e) = enforce_max_duration(manager.clone(), max_seconds).await {
-            tracing::error!("[recorder] duration rotation failed: {}", e);
+        if !has_publisher(manager, stream).await && !is_recording(stream).await {
+            if let Err(e) = leases.release(stream).await {
+                tracing::warn!("[recorder] releasing lease of {} failed: {:#}", stream, e);
+            }
+            return false;
+        }
+        match leases.acquire(stream).await {
+            Ok(resp) if resp.granted => {
+                expires_at = resp.lease.map_or(expires_at, |lease| lease.expires_at);
+            }
+            Ok(resp) => {
+                tracing::warn!(
+                    "[recorder] lease of {} taken by {:?}, stopping the recording",
+                    stream,
+                    resp.lease.map(|lease| lease.holder)
+                );
+                return true;
+            }
+            Err(e) if Utc::now().timestamp_micros() >= expires_at => {
+                tracing::warn!(
+                    "[recorder] lease of {} expired unrenewed, stopping the recording: {:#}",
+                    stream,
+                    e
+                );
+                return true;
+            }
+            Err(e) => tracing::warn!("[recorder] renewing lease of {} failed: {:#}", stream, e),
         }
     }
 }
 
-#[cfg(feature = "recorder")]
-async fn enforce_max_duration(manager: Arc<Manager>, max_seconds: u64) -> anyhow::Result<()> {
-    let max_duration = Duration::from_secs(max_seconds);
-    let candidates: Vec<(String, Option<String>)> = {
-        let map = TASKS.read().await;
-        map.iter()
-            .filter_map(|(stream, task)| {
-                if task.has_exceeded(max_duration) {
-                    Some((stream.clone(), task.next_rotation_base_dir()))
-                } else {
-                    None
-                }
-            })
-            .collect()
+/// Stop renewing or waiting for the lease of `stream`, and give it up
+async fn release_lease(stream: &str) {
+    let Some(task) = LEASE_TASKS.write().await.remove(stream) else {
+        return;
     };
-
-    if candidates.is_empty() {
-        return Ok(());
+    task.abort();
+    if let Some(leases) = LEASES.read().await.clone() {
+        let stream = stream.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = leases.release(&stream).await {
+                tracing::warn!("[recorder] releasing lease of {} failed: {:#}", stream, e);
+            }
+        });
     }
+}
 
-    tracing::info!(
-        "[recorder] rotating {} streams after {} seconds",
-        candidates.len(),
-        max_seconds
-    );
+/// Entry point for starting recording manually or automatically.
+///
+/// Without a `retention_class` or `priority` the auto-record rules and the defaults
+/// apply.
+pub async fn start(
+    manager: Arc<Manager>,
+    stream: String,
+    base_dir: Option<String>,
+    retention_class: Option<RetentionClass>,
+    priority: Option<u8>,
+) -> anyhow::Result<RecordingInfo> {
+    start_recording(
+        manager,
+        stream,
+        base_dir,
+        retention_class,
+        priority,
+        None,
+        None,
+    )
+    .await
+}
 
-    for (stream, _) in &candidates {
-        if let Err(e) = stop(stream.clone()).await {
-            tracing::error!(
-                "[recorder] failed to stop stream {} during rotation: {}",
-                stream,
-                e
-            );
-        }
+/// [`start`], the new recording indexed as continuing the record `continues`. A
+/// `parked` recording is continued instead when the publisher fits it, and finalized
+/// otherwise.
+async fn start_recording(
+    manager: Arc<Manager>,
+    stream: String,
+    base_dir: Option<String>,
+    retention_class: Option<RetentionClass>,
+    priority: Option<u8>,
+    continues: Option<String>,
+    parked: Option<ParkedRecording>,
+) -> anyhow::Result<RecordingInfo> {
+    let mut parked = parked;
+    let started = start_task(
+        manager,
+        stream.clone(),
+        base_dir,
+        retention_class,
+        priority,
+        continues,
+        &mut parked,
+    )
+    .await;
+    if let Some(parked) = parked {
+        finalize_parked(stream, parked).await;
     }
+    started
+}
 
-    for (stream, base_dir) in candidates {
-        if let Err(e) = start(manager.clone(), stream.clone(), base_dir).await {
-            tracing::error!(
-                "[recorder] failed to restart stream {} during rotation: {}",
-                stream,
-                e
-            );
-        } else {
-            tracing::info!(
-                "[recorder] restarted recording for stream {} after reaching max duration",
-                stream
-            );
-        }
+async fn start_task(
+    manager: Arc<Manager>,
+    stream: String,
+    base_dir: Option<String>,
+    retention_class: Option<RetentionClass>,
+    priority: Option<u8>,
+    continues: Option<String>,
+    parked: &mut Option<ParkedRecording>,
+) -> anyhow::Result<RecordingInfo> {
+    if SHUTTING_DOWN.load(Ordering::Acquire) {
+        anyhow::bail!("recorder is shutting down");
     }
+    if let Err(rule) = name_rules().check_stream(&stream) {
+        tracing::warn!("[recorder] not recording {:?}: {}", stream, rule);
+        return Err(rule.error("stream").into());
+    }
+    let mut map = TASKS.write().await;
+    if let Some(existing) = map.get(&stream) {
+        tracing::info!("[recorder] stream {} is already recording", stream);
+        return Ok(existing.info.clone());
+    }
+    let tenant = TENANCY.read().await.tenant_of(&stream).map_err(|e| {
+        tracing::warn!("[recorder] not recording {}: {}", stream, e);
+        anyhow::Error::from(api::recorder::RecorderError::validation(Some("stream"), e))
+    })?;
+    // A custom base_dir stays inside its tenant's prefix too
+    let base_dir = match (tenant.as_deref(), base_dir) {
+        (Some(tenant), Some(dir))
+            if !dir
+                .trim_start_matches('/')
+                .starts_with(&format!("{tenant}/")) =>
+        {
+            Some(format!("{tenant}/{}", dir.trim_start_matches('/')))
+        }
+        (_, dir) => dir,
+    };
+    let uploader = { UPLOADER.read().await.clone() };
+    let (retention_class, priority) = {
+        let policy = RETENTION_POLICY.read().await;
+        (
+            retention_class.or_else(|| policy.class_for(&stream)),
+            priority.unwrap_or_else(|| policy.priority_for(&stream)),
+        )
+    };
+    let limit = *RECORDING_LIMIT.read().await;
+    let active = map.iter().map(|(stream, task)| Active {
+        stream,
+        priority: task.info.priority,
+        start_ts_micros: task.info.start_ts_micros,
+    });
+    match limit.admit(active, priority) {
+        Admission::Admit => {}
+        Admission::Reject => {
+            crate::metrics::RECORDER_STARTS_REJECTED.inc();
+            tracing::warn!(
+                "[recorder] not recording {}, {} recordings are running",
+                stream,
+                map.len()
+            );
+            return Err(api::recorder::RecorderError::RecordingLimit {
+                active: map.len(),
+                max: limit.max,
+                message: format!(
+                    "cannot record {stream}: max_concurrent_recordings {} reached",
+                    limit.max
+                ),
+            }
+            .into());
+        }
+        Admission::Preempt(victim) => {
+            if let Some(task) = map.remove(&victim) {
+                SESSIONS.write().await.finalizing(&victim);
+                crate::metrics::RECORDINGS_PREEMPTED.inc();
+                tracing::warn!(
+                    "[recorder] stopping {} (priority {}) to record {} (priority {})",
+                    victim,
+                    task.info.priority,
+                    stream,
+                    priority
+                );
+                // Finalized in the background, the new recording does not wait for it
+                tokio::spawn(end_recording(victim, task, false));
+            }
+        }
+    }
+    let task = RecordingTask::spawn(
+        manager,
+        &stream,
+        base_dir,
+        uploader,
+        retention_class,
+        priority,
+        tenant,
+        clock::system(),
+        parked,
+    )
+    .await?;
+    let info = task.info.clone();
+    let resumed = task.resumed();
+    map.insert(stream.clone(), task);
+
+    if resumed {
+        tracing::info!(
+            "[recorder] stream {} continues recording {}",
+            stream,
+            info.record_dir
+        );
+    } else {
+        tracing::info!("[recorder] spawn recording task for {}", stream);
+        update_index_on_start(&stream, &info, continues).await;
+    }
+    Ok(info)
+}
+
+/// Recordings running against `max_concurrent_recordings`
+pub async fn recording_capacity() -> api::recorder::RecordingCapacity {
+    let limit = *RECORDING_LIMIT.read().await;
+    limit.capacity(TASKS.read().await.len())
+}
+
+/// Stall of the recording of `stream`, `None` while it gets media or is not recording
+pub async fn stall_status(stream: &str) -> Option<api::recorder::RecordingStall> {
+    let map = TASKS.read().await;
+    let task = map.get(stream)?;
+    Some(api::recorder::RecordingStall {
+        record_dir: task.info.record_dir.clone(),
+        since_ts: task.stalled_since()?,
+    })
+}
+
+/// When index and upload queue writes reach the disk, `None` before the recorder is
+/// initialized
+pub async fn durability_status() -> Option<api::recorder::DurabilityStatus> {
+    DURABILITY.read().await.as_ref().map(|d| d.status())
+}
+
+/// Naming rules of the streams this node records, checked by [`start`] and by every
+/// route taking a `{stream}` or `{record}`
+pub fn name_rules() -> api::recorder::NameRules {
+    api::recorder::NameRules {
+        separators: NAME_SEPARATORS.load(Ordering::Acquire),
+    }
+}
+
+/// Check whether a stream is currently being recorded on this node
+pub async fn is_recording(stream: &str) -> bool {
+    let map = TASKS.read().await;
+    map.contains_key(stream)
+}
+
+/// Recording state of each of `streams` for the stream listing
+pub async fn stream_recordings(streams: &[String]) -> HashMap<String, StreamRecording> {
+    let map = TASKS.read().await;
+    streams
+        .iter()
+        .map(|stream| {
+            let recording = match map.get(stream) {
+                Some(task) => StreamRecording {
+                    recording: true,
+                    // Same id `POST /api/record/{stream}` answered with
+                    record_id: Some(if task.info.record_id > 0 {
+                        task.info.record_id.to_string()
+                    } else {
+                        (task.info.start_ts_micros / 1_000_000).max(0).to_string()
+                    }),
+                    recording_since_ts: Some(task.info.start_ts_micros),
+                    segments_written: Some(task.segments_written()),
+                },
+                None => StreamRecording::idle(),
+            };
+            (stream.clone(), recording)
+        })
+        .collect()
+}
+
+/// Recorder statistics of `stream`, or of the node, at most [`stats::STATS_TTL`] old
+pub async fn recorder_stats(stream: Option<&str>) -> anyhow::Result<RecorderStats> {
+    let mut cache = STATS.lock().await;
+    let computed = match cache.fresh(std::time::Instant::now()) {
+        Some(computed) => computed.clone(),
+        None => {
+            let entries = match get_index().await {
+                Some(index) => index.snapshot().await?,
+                None => Vec::new(),
+            };
+            let live: Vec<stats::Live> = TASKS
+                .read()
+                .await
+                .values()
+                .map(|task| stats::Live {
+                    stream: task.stream.clone(),
+                    elapsed: task.elapsed(),
+                    written: task.written(),
+                })
+                .collect();
+            cache.compute(
+                &entries,
+                &live,
+                std::time::Instant::now(),
+                Utc::now().timestamp_micros(),
+            )
+        }
+    };
+    Ok(match stream {
+        Some(stream) => computed.stream(stream),
+        None => computed.node,
+    })
+}
+
+/// Integrity rollup of `stream`, or of the node, against `recorder.health`
+pub async fn recorder_health(
+    stream: Option<&str>,
+) -> anyhow::Result<api::recorder::RecorderHealth> {
+    let entries = match get_index().await {
+        Some(index) => index.snapshot().await?,
+        None => Vec::new(),
+    };
+    let uploader = UPLOADER.read().await.clone();
+    let (queued, disk, upload_lag) = match uploader {
+        Some(uploader) => (
+            uploader.queued().await,
+            Some(uploader.disk_status()),
+            Some(uploader.upload_lag(stream).await),
+        ),
+        None => (Vec::new(), None, None),
+    };
+    let stalled: Vec<(String, i64)> = TASKS
+        .read()
+        .await
+        .values()
+        .filter_map(|task| Some((task.stream.clone(), task.stalled_since()?)))
+        .collect();
+    let mut health = health::rollup(
+        &*HEALTH.read().await,
+        stream,
+        &entries,
+        &queued,
+        &stalled,
+        disk.as_ref(),
+        Utc::now().timestamp_micros(),
+    );
+    health.upload_lag = upload_lag;
+    Ok(health)
+}
+
+/// Free space of the upload spool, `None` without uploads
+pub async fn disk_status() -> Option<api::recorder::DiskStatus> {
+    UPLOADER
+        .read()
+        .await
+        .as_ref()
+        .map(|uploader| uploader.disk_status())
+}
+
+/// Upload lag of `stream`, `None` without uploads
+pub async fn upload_lag(stream: &str) -> Option<api::recorder::UploadLag> {
+    let uploader = UPLOADER.read().await.clone()?;
+    Some(uploader.upload_lag(Some(stream)).await)
+}
+
+/// Upload queue and its suspension, `None` without uploads
+pub async fn upload_queue_status() -> Option<api::recorder::UploadQueueStatus> {
+    let uploader = UPLOADER.read().await.clone()?;
+    Some(uploader.queue_status().await)
+}
+
+/// Startup gate of the storage writes, `None` when `recorder.startup` is disabled
+pub async fn startup_status() -> Option<api::recorder::StartupStatus> {
+    STARTUP.read().await.as_ref().map(|gate| gate.status())
+}
+
+/// Resolve once the startup gate lets storage writes through
+async fn startup_ready() {
+    let gate = STARTUP.read().await.clone();
+    if let Some(gate) = gate {
+        gate.wait().await;
+    }
+}
+
+/// Tell livevod `key` was rewritten in storage, with `[recorder.invalidate]`. Objects
+/// uploaded through liveman are announced by liveman once the upload finished
+pub(crate) async fn invalidate(key: &str) {
+    if let Some(invalidator) = INVALIDATOR.read().await.as_ref() {
+        invalidator.notify(key);
+    }
+}
+
+/// Run the storage diagnostics, `None` when uploads go through liveman or storage is
+/// not initialized
+pub async fn diagnose_storage() -> Option<DiagnoseReport> {
+    let (config, options) = DIAGNOSE.read().await.clone()?;
+    let storage = STORAGE.read().await.clone()?;
+    let selected = storage.selected_endpoint();
+    let report =
+        storage::diagnose(&config, &storage.current(), selected.as_deref(), &options).await;
+    if !report.ok {
+        tracing::warn!("[recorder] storage diagnose failed: {:?}", report.stages);
+    }
+    Some(report)
+}
+
+/// Current storage chaos settings, `None` when failure injection is not enabled
+pub async fn chaos_config() -> Option<ChaosConfig> {
+    CHAOS.read().await.as_ref().map(|layer| layer.config())
+}
+
+/// Replace storage chaos settings at runtime, returns false when failure injection is not enabled
+pub async fn set_chaos_config(config: ChaosConfig) -> bool {
+    match CHAOS.read().await.as_ref() {
+        Some(layer) => {
+            tracing::warn!("[recorder] storage chaos updated: {:?}", config);
+            layer.set_config(config);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Push index transitions to liveman when `[recorder.push]` is enabled
+async fn init_pusher(cfg: &RecorderConfig) {
+    if !cfg.push.enabled {
+        return;
+    }
+    let Some(index) = get_index().await else {
+        tracing::warn!("[recorder] index push enabled but the index is unavailable");
+        return;
+    };
+    let Some(node_alias) = cfg.node_alias.clone() else {
+        tracing::warn!("[recorder] index push enabled but node_alias is not set");
+        return;
+    };
+    if cfg.push.liveman_url.trim().is_empty() {
+        tracing::warn!("[recorder] index push enabled but liveman_url is empty");
+        return;
+    }
+    let pusher = push::IndexPusher::new(cfg.push.clone(), node_alias);
+    tokio::spawn(pusher.run(index.subscribe()));
+    tracing::info!("[recorder] index push to {} enabled", cfg.push.liveman_url);
+}
+
+/// Gate auto-recording on liveman's leases when `[recorder.lease]` is enabled
+async fn init_leases(cfg: &RecorderConfig) {
+    if !cfg.lease.enabled {
+        return;
+    }
+    let Some(node_alias) = cfg.node_alias.clone() else {
+        tracing::warn!("[recorder] recording lease enabled but node_alias is not set");
+        return;
+    };
+    if cfg.lease.liveman_url.trim().is_empty() {
+        tracing::warn!("[recorder] recording lease enabled but liveman_url is empty");
+        return;
+    }
+    *LEASES.write().await = Some(Arc::new(LeaseClient::new(cfg.lease.clone(), node_alias)));
+    tracing::info!(
+        "[recorder] auto-recording leased from {}",
+        cfg.lease.liveman_url
+    );
+}
+
+/// Buffer the streams of `[recorder.triggers]` for pre-roll, stop the recordings
+/// triggers started after their post-roll and subscribe to the MQTT topics
+async fn init_triggers(manager: Arc<Manager>, cfg: &RecorderConfig) {
+    let triggers = Arc::new(Triggers::new(cfg.triggers.clone(), manager));
+    tokio::spawn(triggers.clone().run());
+    #[cfg(feature = "trigger-mqtt")]
+    if let Some(mqtt) = cfg.triggers.mqtt.clone() {
+        tracing::info!("[recorder] triggers subscribed to {}", mqtt.url);
+        tokio::spawn(trigger::subscribe(triggers.clone(), mqtt));
+    }
+    #[cfg(not(feature = "trigger-mqtt"))]
+    if cfg.triggers.mqtt.is_some() {
+        tracing::warn!("[recorder] triggers.mqtt ignored, build without the trigger-mqtt feature");
+    }
+    *TRIGGERS.write().await = Some(triggers);
+}
+
+/// Record `stream` for an event, see `recorder.triggers`. `None` before the recorder is
+/// initialized
+pub async fn trigger(
+    req: api::recorder::TriggerRequest,
+) -> Option<anyhow::Result<api::recorder::TriggerResponse>> {
+    let triggers = TRIGGERS.read().await.clone()?;
+    Some(
+        triggers
+            .fire(
+                &req.stream,
+                "http".to_string(),
+                req.pre_roll_ms,
+                req.post_roll_ms,
+            )
+            .await,
+    )
+}
+
+/// Pre-roll a trigger left for the recording of `stream` being started
+async fn take_pre_roll(stream: &str) -> trigger::PreRoll {
+    match TRIGGERS.read().await.as_ref() {
+        Some(triggers) => triggers.take_pre_roll(stream),
+        None => trigger::PreRoll::default(),
+    }
+}
+
+async fn init_reconciler(manager: Arc<Manager>, cfg: &RecorderConfig) {
+    let (Some(index), Some(operator), Some(index_path)) = (
+        get_index().await,
+        STORAGE.read().await.clone(),
+        resolve_index_path(cfg),
+    ) else {
+        return;
+    };
+    let mut checkpoint_path = index_path.into_os_string();
+    checkpoint_path.push(".reconcile");
+    let reconciler = Arc::new(Reconciler::new(
+        index,
+        operator,
+        manager,
+        PathBuf::from(checkpoint_path),
+        cfg.reconcile.max_heads_per_second,
+    ));
+    reconciler.resume().await;
+
+    if cfg.reconcile.interval_minutes > 0 {
+        let periodic = reconciler.clone();
+        let interval = Duration::from_secs(cfg.reconcile.interval_minutes.saturating_mul(60));
+        let sample_segments = cfg.reconcile.sample_segments;
+        tokio::spawn(async move {
+            let mut ticker = time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            // The first tick completes immediately, leave startup to resume()
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                periodic.start(sample_segments);
+            }
+        });
+    }
+    *RECONCILER.write().await = Some(reconciler);
+}
+
+/// Start an index/storage reconciliation run, `None` when storage or the index is unavailable
+pub async fn reconcile(sample_segments: usize) -> Option<(bool, ReconcileStatus)> {
+    let reconciler = RECONCILER.read().await.clone()?;
+    let started = reconciler.start(sample_segments);
+    Some((started, reconciler.status()))
+}
+
+/// Progress of the current or last reconciliation run
+pub async fn reconcile_status() -> Option<ReconcileStatus> {
+    let reconciler = RECONCILER.read().await.clone()?;
+    Some(reconciler.status())
+}
+
+async fn init_renamer(cfg: &RecorderConfig) {
+    let (Some(index), Some(operator), Some(index_path)) = (
+        get_index().await,
+        STORAGE.read().await.clone(),
+        resolve_index_path(cfg),
+    ) else {
+        return;
+    };
+    let mut journal_path = index_path.into_os_string();
+    journal_path.push(".rename");
+    let renamer = Arc::new(StreamRenamer::new(
+        index,
+        operator,
+        PathBuf::from(journal_path),
+        cfg.key_namespace(),
+        Tenancy::new(&cfg.tenancy).unwrap_or_default(),
+    ));
+    renamer.resume().await;
+    *RENAMER.write().await = Some(renamer);
+}
+
+async fn init_importer(cfg: &RecorderConfig) {
+    let (Some(index), Some(operator)) = (get_index().await, STORAGE.read().await.clone()) else {
+        return;
+    };
+    *IMPORTER.write().await = Some(Arc::new(Importer::new(
+        index,
+        operator,
+        cfg.node_alias.clone(),
+        Tenancy::new(&cfg.tenancy).unwrap_or_default(),
+    )));
+}
+
+async fn init_audit(cfg: &RecorderConfig) {
+    let (Some(_), Some(index_path)) = (get_index().await, resolve_index_path(cfg)) else {
+        return;
+    };
+    *AUDIT.write().await = Some(Arc::new(open_audit(cfg, index_path)));
+}
+
+fn open_audit(cfg: &RecorderConfig, index_path: PathBuf) -> AuditLog {
+    AuditLog::new(index_path, cfg.audit.max_bytes, cfg.audit.keep)
+        .with_lock_options(LockOptions::from(&cfg.index_lock))
+}
+
+/// Append to the audit log, if the index is initialized
+async fn audit(record: AuditRecord) {
+    if let Some(audit) = AUDIT.read().await.clone() {
+        audit.log(record).await;
+    }
+}
+
+/// [`audit::record`] with the outcome of an operation refused with `reason`
+fn rejected(
+    operation: AuditOperation,
+    actor: &str,
+    keys: Vec<String>,
+    reason: &str,
+) -> AuditRecord {
+    AuditRecord {
+        outcome: AuditOutcome::Rejected,
+        reason: Some(reason.to_string()),
+        ..audit::record(operation, actor, keys)
+    }
+}
+
+/// [`audit::record`] with the outcome of an operation that failed part way
+fn failed(
+    operation: AuditOperation,
+    actor: &str,
+    keys: Vec<String>,
+    error: &anyhow::Error,
+) -> AuditRecord {
+    AuditRecord {
+        outcome: AuditOutcome::Failed,
+        reason: Some(format!("{error:#}")),
+        ..audit::record(operation, actor, keys)
+    }
+}
+
+/// Audit records after `since_ts` (UNIX microseconds), `None` without an index
+pub async fn audit_log(since_ts: Option<i64>) -> Option<anyhow::Result<Vec<AuditRecord>>> {
+    let audit = AUDIT.read().await.clone()?;
+    Some(audit.since(since_ts).await)
+}
+
+/// Complete lines of the `kind` journal from `offset`, up to `limit` bytes. `None` when
+/// the index is not initialized
+pub async fn read_journal(
+    kind: JournalKind,
+    offset: u64,
+    limit: u64,
+) -> Option<anyhow::Result<journal::JournalChunk>> {
+    Some(match kind {
+        JournalKind::Index => get_index().await?.read_journal(offset, limit).await,
+        JournalKind::Audit => {
+            let audit = AUDIT.read().await.clone()?;
+            audit.read_journal(offset, limit).await
+        }
+    })
+}
+
+async fn init_retention(cfg: &RecorderConfig) {
+    let (Some(index), Some(operator)) = (get_index().await, STORAGE.read().await.clone()) else {
+        return;
+    };
+    let uploader = UPLOADER.read().await.clone();
+    let mut retention = Retention::new(index, operator, cfg.storage.clone(), uploader)
+        .with_quota(cfg.retention.max_bytes);
+    if let Some(audit) = AUDIT.read().await.clone() {
+        retention = retention.with_audit(audit);
+    }
+    let retention = Arc::new(retention);
+    if cfg.retention.sweep {
+        let interval = Duration::from_secs(
+            cfg.retention
+                .sweep_interval_minutes
+                .max(1)
+                .saturating_mul(60),
+        );
+        tokio::spawn(retention.clone().sweep_loop(interval));
+        tracing::info!(
+            "[recorder] retention sweep every {} minutes",
+            cfg.retention.sweep_interval_minutes.max(1)
+        );
+    }
+    tokio::spawn(retention.clone().trash_loop(Duration::from_secs(
+        cfg.retention.trash_retention_days.saturating_mul(86_400),
+    )));
+    *RETENTION.write().await = Some(retention);
+}
+
+async fn init_verifier(cfg: &RecorderConfig) {
+    let (Some(index), Some(operator), Some(uploader)) = (
+        get_index().await,
+        STORAGE.read().await.clone(),
+        UPLOADER.read().await.clone(),
+    ) else {
+        return;
+    };
+    *VERIFIER.write().await = Some(Arc::new(Verifier::new(
+        index,
+        operator,
+        uploader,
+        cfg.reconcile.max_checksum_reads_per_second,
+    )));
+}
+
+async fn init_repairer(cfg: &RecorderConfig) {
+    let (Some(index), Some(operator)) = (get_index().await, STORAGE.read().await.clone()) else {
+        return;
+    };
+    let uploader = UPLOADER.read().await.clone();
+    let local_roots = match uploader.as_ref() {
+        Some(uploader) => vec![
+            PathBuf::from(uploader.local_dir()),
+            PathBuf::from(uploader.staging_dir()),
+        ],
+        None => Vec::new(),
+    };
+    *CAPTIONER.write().await = Some(Arc::new(Captioner::new(
+        index.clone(),
+        operator.clone(),
+        uploader,
+        local_roots.clone(),
+    )));
+    let repairer = Arc::new(Repairer::new(index.clone(), operator, local_roots));
+    tokio::spawn(repairer.clone().run(index.subscribe()));
+    *REPAIRER.write().await = Some(repairer);
+
+    // Nothing is recording yet, entries still active were cut off by a crash
+    match index.interrupt_active(cfg.node_alias.as_deref()).await {
+        Ok(interrupted) if !interrupted.is_empty() => tracing::warn!(
+            "[recorder] {} recordings left active by the last run marked interrupted",
+            interrupted.len()
+        ),
+        Ok(_) => {}
+        Err(e) => tracing::error!("[recorder] index.json update failed: {}", e),
+    }
+}
+
+async fn init_backup(cfg: &RecorderConfig) {
+    let (Some(index), Some(operator)) = (get_index().await, STORAGE.read().await.clone()) else {
+        return;
+    };
+    let backup = Arc::new(IndexBackup::new(
+        index,
+        operator,
+        backup_node(cfg),
+        cfg.backup.keep,
+    ));
+    if cfg.backup.enabled {
+        let interval = Duration::from_secs(cfg.backup.interval_minutes.max(1).saturating_mul(60));
+        tokio::spawn(backup.clone().run(interval));
+        tracing::info!(
+            "[recorder] index backup every {} minutes",
+            cfg.backup.interval_minutes.max(1)
+        );
+    }
+    *BACKUP.write().await = Some(backup);
+}
+
+/// Directory of this node's backups under `_index/`
+fn backup_node(cfg: &RecorderConfig) -> String {
+    cfg.node_alias
+        .as_deref()
+        .map(|alias| alias.trim_matches('/'))
+        .filter(|alias| !alias.is_empty())
+        .unwrap_or("default")
+        .to_string()
+}
+
+/// Install an index backup from storage on behalf of `actor`, `None` when storage or
+/// the index is unavailable
+pub async fn restore_index(
+    key: Option<&str>,
+    force: bool,
+    actor: &str,
+) -> Option<anyhow::Result<RestoreOutcome>> {
+    let backup = BACKUP.read().await.clone()?;
+    let keys: Vec<String> = key.map(str::to_string).into_iter().collect();
+    // Entries of running recordings would be overwritten under them
+    if !TASKS.read().await.is_empty() {
+        let reason = "recordings are running, stop them before restoring the index";
+        audit(rejected(AuditOperation::RestoreIndex, actor, keys, reason)).await;
+        return Some(Ok(RestoreOutcome::Conflict(reason.to_string())));
+    }
+    let outcome = backup.restore(key, force).await;
+    let record = match &outcome {
+        Ok(RestoreOutcome::Restored(resp)) => {
+            audit::record(AuditOperation::RestoreIndex, actor, vec![resp.key.clone()])
+        }
+        Ok(RestoreOutcome::NotFound(reason) | RestoreOutcome::Conflict(reason)) => {
+            rejected(AuditOperation::RestoreIndex, actor, keys, reason)
+        }
+        Err(e) => failed(AuditOperation::RestoreIndex, actor, keys, e),
+    };
+    audit(record).await;
+    Some(outcome)
+}
+
+/// Local entries [`restore_index`] would drop or roll back
+pub async fn preview_restore_index(
+    key: Option<&str>,
+    force: bool,
+) -> Option<anyhow::Result<DryRun>> {
+    let backup = BACKUP.read().await.clone()?;
+    if !TASKS.read().await.is_empty() {
+        return Some(Ok(DryRun::Conflict(
+            "recordings are running, stop them before restoring the index".to_string(),
+        )));
+    }
+    Some(backup.preview(key, force).await)
+}
+
+/// Install an index backup before the server starts, for `--restore-index`
+pub async fn restore_index_offline(
+    cfg: &RecorderConfig,
+    key: Option<&str>,
+    force: bool,
+) -> anyhow::Result<RestoreOutcome> {
+    let index_path = resolve_index_path(cfg).unwrap_or_default();
+    // Strict, nothing flushes a batch before the command exits
+    let (index, _owner) = open_index(cfg, index_path.clone(), Arc::default()).await?;
+    let operator = init_failover_operator(&cfg.storage).await?;
+    let backup = IndexBackup::new(Arc::new(index), operator, backup_node(cfg), cfg.backup.keep);
+    let outcome = backup.restore(key, force).await?;
+    if let RestoreOutcome::Restored(resp) = &outcome {
+        open_audit(cfg, index_path)
+            .log(audit::record(
+                AuditOperation::RestoreIndex,
+                audit::SYSTEM_CLI,
+                vec![resp.key.clone()],
+            ))
+            .await;
+    }
+    Ok(outcome)
+}
+
+/// Add best-effort entries for manifests in storage missing from the index, for
+/// `--rebuild-index`. Returns the number of entries added.
+pub async fn rebuild_index_offline(cfg: &RecorderConfig) -> anyhow::Result<usize> {
+    let index_path = resolve_index_path(cfg).unwrap_or_default();
+    // Strict, nothing flushes a batch before the command exits
+    let (index, _owner) = open_index(cfg, index_path, Arc::default()).await?;
+    let operator = init_failover_operator(&cfg.storage).await?;
+    backup::rebuild(
+        &index,
+        &operator.current(),
+        cfg.key_namespace().as_deref(),
+        &Tenancy::new(&cfg.tenancy).map_err(anyhow::Error::msg)?,
+        cfg.node_alias.clone(),
+    )
+    .await
+}
+
+/// Remove index locks left behind by a process verified dead on this host, for
+/// `--force-unlock`. Returns the removed lock files.
+pub fn force_unlock(cfg: &RecorderConfig) -> anyhow::Result<Vec<PathBuf>> {
+    match resolve_index_path(cfg) {
+        Some(index_path) => lock::force_unlock(&index_path),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Take ownership of the index file, then load it
+async fn open_index(
+    cfg: &RecorderConfig,
+    index_path: PathBuf,
+    durability: Arc<Durability>,
+) -> anyhow::Result<(RecordingsIndex, IndexOwner)> {
+    let lock = LockOptions::from(&cfg.index_lock);
+    let owner = IndexOwner::acquire(&index_path, lock).await?;
+    let index = RecordingsIndex::load(index_path)
+        .await?
+        .with_lock_options(lock)
+        .with_runtime(runtime::handle(&cfg.runtime))
+        .with_durability(durability);
+    Ok((index, owner))
+}
+
+/// Compare the local files of a recording with its objects in storage.
+///
+/// `None` when this node keeps no local copies (uploads disabled), `Ok(None)` when the
+/// recording is not in the index.
+pub async fn verify_recording(
+    stream: &str,
+    record: &str,
+    checksum: bool,
+) -> Option<anyhow::Result<Option<VerifyRecordingResponse>>> {
+    let verifier = VERIFIER.read().await.clone()?;
+    Some(verifier.verify(stream, record, checksum).await)
+}
+
+/// Rebuild the manifest of a finished recording from the segments present.
+///
+/// `None` when the index or storage is not initialized.
+pub async fn repair_recording(stream: &str, record: &str) -> Option<anyhow::Result<RepairOutcome>> {
+    let repairer = REPAIRER.read().await.clone()?;
+    Some(repairer.repair(stream, record).await)
+}
+
+/// Index the recordings another system stored under `prefix`, only listing them with
+/// `dry_run`.
+///
+/// `None` when the index or storage is not initialized.
+pub async fn import_recordings(
+    prefix: &str,
+    dry_run: bool,
+) -> Option<anyhow::Result<ImportRecordingsResponse>> {
+    let importer = IMPORTER.read().await.clone()?;
+    Some(importer.import(prefix, dry_run).await)
+}
+
+/// Store the `lang` captions of a recording, WebVTT or SRT.
+///
+/// `None` when the index or storage is not initialized.
+pub async fn put_captions(
+    stream: &str,
+    record: &str,
+    lang: &str,
+    body: &str,
+) -> Option<anyhow::Result<CaptionsOutcome>> {
+    let captioner = CAPTIONER.read().await.clone()?;
+    let elapsed = TASKS
+        .read()
+        .await
+        .get(stream)
+        .filter(|task| record_key(&task.info) == record)
+        .map(|task| task.elapsed());
+    Some(captioner.store(stream, record, lang, body, elapsed).await)
+}
+
+/// Move the recordings of `req.from` to `req.to` on behalf of `actor`, `None` when the
+/// index has no storage
+pub async fn rename_stream(
+    req: RenameStreamRequest,
+    actor: &str,
+) -> Option<anyhow::Result<RenameOutcome>> {
+    let Some(renamer) = RENAMER.read().await.clone() else {
+        // Without an index this node has no recordings to rename
+        return get_index()
+            .await
+            .is_none()
+            .then(|| Ok(RenameOutcome::Renamed(Default::default())));
+    };
+    let keys: Vec<String> = match get_index().await {
+        Some(index) => index
+            .entries_of(&req.from)
+            .await
+            .iter()
+            .map(|e| e.key())
+            .collect(),
+        None => Vec::new(),
+    };
+    if is_recording(&req.from).await {
+        let reason = format!("{} is being recorded, stop it first", req.from);
+        audit(rejected(AuditOperation::RenameStream, actor, keys, &reason)).await;
+        return Some(Ok(RenameOutcome::Conflict(reason)));
+    }
+    let outcome = renamer.rename(req).await;
+    let record = match &outcome {
+        Ok(RenameOutcome::Renamed(resp)) => AuditRecord {
+            objects: resp.moved_objects as u64,
+            ..audit::record(AuditOperation::RenameStream, actor, keys)
+        },
+        Ok(RenameOutcome::Conflict(reason)) => {
+            rejected(AuditOperation::RenameStream, actor, keys, reason)
+        }
+        Err(e) => failed(AuditOperation::RenameStream, actor, keys, e),
+    };
+    audit(record).await;
+    Some(outcome)
+}
+
+/// Recordings and objects [`rename_stream`] would move
+pub async fn preview_rename_stream(req: &RenameStreamRequest) -> Option<anyhow::Result<DryRun>> {
+    let Some(renamer) = RENAMER.read().await.clone() else {
+        return get_index().await.is_none().then(|| {
+            Ok(DryRun::Affected(DryRunResponse {
+                operation: AuditOperation::RenameStream,
+                keys: Vec::new(),
+                objects: 0,
+                bytes: 0,
+            }))
+        });
+    };
+    if is_recording(&req.from).await {
+        return Some(Ok(DryRun::Conflict(format!(
+            "{} is being recorded, stop it first",
+            req.from
+        ))));
+    }
+    Some(renamer.preview(req).await)
+}
+
+fn compile_schedules(cfg: &RecorderConfig) -> Vec<schedule::Schedule> {
+    cfg.schedules
+        .iter()
+        .filter_map(|entry| match schedule::Schedule::compile(entry) {
+            Ok(compiled) => Some(compiled),
+            Err(e) => {
+                tracing::error!("[recorder] ignoring schedule {:?}: {}", entry.streams, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Replace recording schedules after a config reload.
+///
+/// Scheduled recordings that fall outside their new windows are stopped only after
+/// `schedule_grace_seconds`, recordings inside them keep running untouched.
+pub async fn reload_schedules(cfg: &RecorderConfig) {
+    let schedules = compile_schedules(cfg);
+    let mut state = SCHEDULER.write().await;
+    tracing::info!("[recorder] reloaded {} schedules", schedules.len());
+    state.schedules = schedules;
+    state.grace_until =
+        Some(Utc::now() + chrono::Duration::seconds(cfg.schedule_grace_seconds as i64));
+}
+
+/// Next scheduled start/stop of a stream, `None` when no schedule applies to it
+pub async fn schedule_status(stream: &str) -> Option<schedule::ScheduleStatus> {
+    let state = SCHEDULER.read().await;
+    schedule::status(&state.schedules, stream, &chrono::Local, Utc::now())
+}
+
+async fn schedule_loop(manager: Arc<Manager>) {
+    let mut ticker = time::interval(SCHEDULE_TICK);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        ticker.tick().await;
+        let now = Utc::now();
+        let mut streams: HashSet<String> = manager
+            .info(Vec::new())
+            .await
+            .into_iter()
+            .filter(|info| info.publish_session_info.is_some() || info.has_virtual_publisher)
+            .map(|info| info.id)
+            .collect();
+        streams.extend(SCHEDULER.read().await.started.iter().cloned());
+        for stream in streams {
+            apply_schedule(&manager, &stream, now).await;
+        }
+    }
+}
+
+/// Start or stop a stream's recording according to its schedule windows
+async fn apply_schedule(manager: &Arc<Manager>, stream: &str, now: chrono::DateTime<Utc>) {
+    let (in_window, started, in_grace) = {
+        let state = SCHEDULER.read().await;
+        if state.schedules.is_empty() && state.started.is_empty() {
+            return;
+        }
+        let in_window = schedule::status(&state.schedules, stream, &chrono::Local, now)
+            .is_some_and(|status| status.in_window);
+        (
+            in_window,
+            state.started.contains(stream),
+            state.grace_until.is_some_and(|until| now < until),
+        )
+    };
+    let recording = is_recording(stream).await;
+
+    if in_window && !recording {
+        match start(manager.clone(), stream.to_string(), None, None, None).await {
+            Ok(_) => {
+                tracing::info!("[recorder] schedule window opened for {}", stream);
+                SCHEDULER.write().await.started.insert(stream.to_string());
+            }
+            Err(e) => tracing::error!("[recorder] scheduled start failed: {}", e),
+        }
+    } else if started && !recording {
+        // Stopped manually or by the stream going down
+        SCHEDULER.write().await.started.remove(stream);
+    } else if started && !in_window && !in_grace {
+        tracing::info!("[recorder] schedule window closed for {}", stream);
+        SCHEDULER.write().await.started.remove(stream);
+        if let Err(e) = stop(stream.to_string()).await {
+            tracing::error!("[recorder] scheduled stop failed: {}", e);
+        }
+    }
+}
 
+// Query by stream id only
+
+#[cfg(feature = "recorder")]
+fn should_auto_record(cfg: &RecorderConfig, stream: &str) -> bool {
+    cfg.rules
+        .iter()
+        .any(|rule| should_record(&rule.streams, stream))
+        || should_record(&cfg.auto_streams, stream)
+}
+
+fn should_record(patterns: &[String], stream: &str) -> bool {
+    for p in patterns {
+        if let Ok(pat) = Pattern::new(p)
+            && pat.matches(stream)
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// Stop recording for a given stream if running
+pub async fn stop(stream: String) -> anyhow::Result<()> {
+    RESUMABLE.write().await.remove(&stream);
+    let parked = SESSIONS.write().await.unpark(&stream);
+    if let Some(parked) = parked {
+        finalize_parked(stream.clone(), parked).await;
+    }
+
+    if let Some(task) = take_task(&stream, None).await {
+        end_recording(stream.clone(), task, false).await;
+        tracing::info!("[recorder] stopped recording task for {}", stream);
+    } else {
+        tracing::info!("[recorder] no recording task found for {}", stream);
+    }
     Ok(())
 }
 
+/// Finalize every running recording before the process exits.
+///
+/// Recordings still unfinished after `deadline` are marked interrupted. The index is
+/// released afterwards, so the next start does not wait for an expired lease.
+pub async fn shutdown(deadline: Duration) {
+    SHUTTING_DOWN.store(true, Ordering::Release);
+    let tasks: Vec<RecordingTask> = TASKS.write().await.drain().map(|(_, t)| t).collect();
+    if !tasks.is_empty() {
+        tracing::info!(
+            "[recorder] finalizing {} recordings before exit (deadline {:?})",
+            tasks.len(),
+            deadline
+        );
+        let interrupted = shutdown::finalize_tasks(tasks, get_index().await, deadline).await;
+        if interrupted > 0 {
+            tracing::warn!(
+                "[recorder] {} recordings missed the shutdown deadline and were marked interrupted",
+                interrupted
+            );
+        }
+    }
+    let parked = SESSIONS.write().await.drain_parked();
+    for (stream, parked) in parked {
+        finalize_parked(stream, parked).await;
+    }
+    if let Some(uploader) = UPLOADER.read().await.clone()
+        && let Err(e) = uploader.flush_queue().await
+    {
+        tracing::warn!("[recorder] failed to write the upload queue: {:#}", e);
+    }
+    if let Some(index) = get_index().await {
+        index.close().await;
+    }
+    INDEX_OWNER.write().await.take();
+}
+
+async fn update_index_on_start(stream: &str, info: &RecordingInfo, continues: Option<String>) {
+    let index_opt = get_index().await;
+    if index_opt.is_none() {
+        return;
+    }
+
+    let record = record_key(info);
+    let mpd_path = format!("{}/manifest.mpd", info.record_dir);
+    let entry = RecordingIndexEntry {
+        uuid: index::new_recording_uuid(),
+        record,
+        stream: stream.to_string(),
+        record_dir: info.record_dir.clone(),
+        mpd_path,
+        start_ts: info.start_ts_micros,
+        end_ts: None,
+        duration_ms: None,
+        status: RecordingStatus::Active,
+        node_alias: NODE_ALIAS.read().await.clone(),
+        updated_at: Utc::now().timestamp_micros(),
+        note: None,
+        labels: Vec::new(),
+        continues,
+        media_info: Vec::new(),
+        retention_class: info.retention_class.clone(),
+        trashed_at: None,
+        trashed_from: None,
+        repair_error: None,
+        source: info.source.clone(),
+        replicas: Vec::new(),
+        clock_skew_detected: false,
+        priority: info.priority,
+        tenant: info.tenant.clone(),
+        size: None,
+        captions: Vec::new(),
+        trigger: None,
+        imported: false,
+        seq: 0,
+    };
+
+    if let Some(index) = index_opt
+        && let Err(e) = index.upsert(entry).await
+    {
+        tracing::error!("[recorder] index.json upsert failed: {}", e);
+    }
+}
+
+async fn update_index_on_stop(
+    stream: &str,
+    info: &RecordingInfo,
+    outcome: task::RecordingStopOutcome,
+) {
+    if outcome.clock_skew_detected {
+        tracing::warn!(
+            "[recorder] wall clock stepped during {}, duration {} ms taken from the monotonic clock",
+            info.record_dir,
+            outcome.duration_ms
+        );
+    }
+    if let Some(index) = get_index().await {
+        let record = record_key(info);
+        if let Err(e) = index
+            .update_status(
+                stream,
+                &record,
+                outcome.status.clone(),
+                outcome.session_end(),
+                Some(outcome.size),
+            )
+            .await
+        {
+            tracing::error!("[recorder] index.json update failed: {}", e);
+        }
+    }
+    captions::forget(&info.record_dir);
+    if let Some(retention) = RETENTION.read().await.clone() {
+        retention
+            .on_finished(&info.record_dir, info.retention_class.as_ref())
+            .await;
+    }
+}
+
+/// Finalize the previous part and index the new one after a max-duration split
+async fn on_split(
+    stream: String,
+    next_prefix: String,
+    size: RecordingSize,
+    media: Option<MediaInfo>,
+) {
+    let advanced = {
+        let mut map = TASKS.write().await;
+        map.get_mut(&stream).map(|task| {
+            let (previous, outcome) = task.advance(next_prefix, size);
+            (previous, outcome, task.info.clone())
+        })
+    };
+
+    let Some((previous, outcome, next)) = advanced else {
+        tracing::warn!(
+            "[recorder] split finished for {} but its task is gone",
+            stream
+        );
+        return;
+    };
+
+    SESSIONS
+        .write()
+        .await
+        .issued(&stream, next.record_id, Utc::now().timestamp());
+    update_index_on_stop(&stream, &previous, outcome).await;
+    update_index_on_start(&stream, &next, Some(record_key(&previous))).await;
+    if let Some(triggers) = TRIGGERS.read().await.clone() {
+        triggers
+            .on_split(&stream, &record_key(&previous), &record_key(&next))
+            .await;
+    }
+    if let Some(media) = media {
+        on_media_info(stream.clone(), next.record_dir.clone(), media).await;
+    }
+    tracing::info!(
+        "[recorder] stream {} continues in {} after {}",
+        stream,
+        next.record_dir,
+        previous.record_dir
+    );
+}
+
+/// Add track formats reported by the segmenter to the recording under `record_dir`
+async fn on_media_info(stream: String, record_dir: String, media: MediaInfo) {
+    let Some(index) = get_index().await else {
+        return;
+    };
+    match index.record_media_info(&record_dir, media).await {
+        Ok(true) => {}
+        Ok(false) => tracing::warn!(
+            "[recorder] media info of {} dropped, {} is not indexed",
+            stream,
+            record_dir
+        ),
+        Err(e) => tracing::error!("[recorder] index.json media info update failed: {}", e),
+    }
+}
+
+/// Turn drained upload directories into `uploaded` events for finished recordings
+async fn publish_uploaded(mut drained: broadcast::Receiver<String>) {
+    loop {
+        let dir = match drained.recv().await {
+            Ok(dir) => dir,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let Some(index) = get_index().await else {
+            continue;
+        };
+        // The queue also drains between segments of an active recording
+        if let Some(entry) = index.find_by_dir(&dir).await
+            && !matches!(entry.status, RecordingStatus::Active)
+        {
+            index.notify_uploaded(entry);
+        }
+    }
+}
+
+/// Stream the index transitions `filter` admits, replaying entries changed after
+/// `last_event_id` first. See [`events`] for what a subscriber too slow to keep up gets.
+///
+/// Event ids are journal positions, see [`RecordingIndexEntry::seq`]. Replayed entries
+/// are sent as `updated` with their current state; deletions that happened while
+/// disconnected cannot be replayed.
+pub async fn subscribe_events(
+    last_event_id: Option<i64>,
+    filter: EventFilter,
+    subscriber: &str,
+) -> anyhow::Result<Subscription> {
+    let Some(index) = get_index().await else {
+        return Err(anyhow::anyhow!("recorder index not initialized"));
+    };
+    Ok(events::subscribe(index, last_event_id, filter, subscriber))
+}
+
+async fn get_index() -> Option<Arc<RecordingsIndex>> {
+    let index = INDEX.read().await;
+    index.clone()
+}
+
+/// Latest journal position of the index, `None` without one
+pub async fn index_seq() -> Option<u64> {
+    Some(get_index().await?.seq())
+}
+
+pub async fn pull_recordings(
+    req: PullRecordingsRequest,
+    cursor: Option<ListCursor>,
+) -> anyhow::Result<PullRecordingsResponse> {
+    let Some(index) = get_index().await else {
+        return Ok(PullRecordingsResponse {
+            sessions: Vec::new(),
+            last_ts: None,
+            next_cursor: None,
+            capacity: Some(recording_capacity().await),
+            stats: node_stats().await,
+            health: node_health().await,
+        });
+    };
+
+    let (sessions, last_ts, next_cursor) = index
+        .list_sessions(
+            req.stream,
+            req.since_ts,
+            req.limit,
+            req.order,
+            cursor.as_ref(),
+            req.include_trashed,
+        )
+        .await;
+
+    Ok(PullRecordingsResponse {
+        sessions,
+        last_ts,
+        next_cursor: next_cursor.map(|c| c.encode()),
+        capacity: Some(recording_capacity().await),
+        stats: node_stats().await,
+        health: node_health().await,
+    })
+}
+
+/// The node's stats for liveman's sync, which must not fail on their account
+async fn node_stats() -> Option<RecorderStats> {
+    recorder_stats(None)
+        .await
+        .inspect_err(|e| tracing::warn!("[recorder] stats failed: {:#}", e))
+        .ok()
+}
+
+/// The node's integrity rollup for liveman's sync, like [`node_stats`]
+async fn node_health() -> Option<api::recorder::RecorderHealth> {
+    recorder_health(None)
+        .await
+        .inspect_err(|e| tracing::warn!("[recorder] health rollup failed: {:#}", e))
+        .ok()
+}
+
+pub async fn ack_recordings(req: AckRecordingsRequest) -> anyhow::Result<AckRecordingsResponse> {
+    let Some(index) = get_index().await else {
+        return Ok(AckRecordingsResponse::default());
+    };
+
+    let filter = req.filter.clone();
+    let resp = index.ack(req).await?;
+    if let Some(filter) = filter {
+        tracing::info!(
+            "[recorder] acked {} entries by filter {:?}, sample {:?}",
+            resp.acked,
+            filter,
+            resp.sample
+        );
+    }
+    Ok(resp)
+}
+
+/// Delete acked entries on behalf of `actor`
+pub async fn delete_recordings(
+    req: DeleteRecordingsRequest,
+    actor: &str,
+) -> anyhow::Result<DeleteRecordingsResponse> {
+    let Some(index) = get_index().await else {
+        return Ok(DeleteRecordingsResponse { deleted: 0 });
+    };
+
+    let requested: Vec<String> = req
+        .records
+        .iter()
+        .map(|k| format!("{}/{}", k.stream, k.record))
+        .collect();
+    let keys = match index.delete_acked(req).await {
+        Ok(keys) => keys,
+        Err(e) => {
+            let record = match e.downcast_ref::<api::recorder::RecorderError>() {
+                Some(stale) => rejected(
+                    AuditOperation::DeleteAcked,
+                    actor,
+                    requested,
+                    stale.message(),
+                ),
+                None => failed(AuditOperation::DeleteAcked, actor, requested, &e),
+            };
+            audit(record).await;
+            return Err(e);
+        }
+    };
+    let deleted = keys.len();
+    if deleted > 0 {
+        audit(audit::record(AuditOperation::DeleteAcked, actor, keys)).await;
+    }
+    Ok(DeleteRecordingsResponse { deleted })
+}
+
+/// Acked entries [`delete_recordings`] would delete
+pub async fn preview_delete_recordings(
+    req: &DeleteRecordingsRequest,
+) -> anyhow::Result<DryRunResponse> {
+    let keys = match get_index().await {
+        Some(index) => index.acked_keys(req).await?,
+        None => Vec::new(),
+    };
+    Ok(DryRunResponse {
+        operation: AuditOperation::DeleteAcked,
+        keys,
+        objects: 0,
+        bytes: 0,
+    })
+}
+
+/// Move a finished recording to the trash on behalf of `actor`, see
+/// [`RecordingsIndex::trash`]
+pub async fn trash_recording(
+    stream: &str,
+    record: &str,
+    actor: &str,
+    if_match: Option<i64>,
+) -> anyhow::Result<TrashUpdate> {
+    let Some(index) = get_index().await else {
+        return Ok(TrashUpdate::NotFound);
+    };
+    let keys = vec![format!("{stream}/{record}")];
+    let update = index.trash(stream, record, if_match).await;
+    match &update {
+        Ok(TrashUpdate::Updated(_)) => {
+            audit(audit::record(AuditOperation::Trash, actor, keys)).await
+        }
+        Ok(TrashUpdate::Conflict(reason)) => {
+            audit(rejected(AuditOperation::Trash, actor, keys, reason)).await
+        }
+        Ok(TrashUpdate::Stale(current)) => {
+            let reason = format!("version {} changed", current.updated_at);
+            audit(rejected(AuditOperation::Trash, actor, keys, &reason)).await
+        }
+        Ok(TrashUpdate::NotFound) => {}
+        Err(e) => audit(failed(AuditOperation::Trash, actor, keys, e)).await,
+    }
+    update
+}
+
+/// What [`trash_recording`] or, with `permanent`, [`purge_recording`] would affect.
+///
+/// `None` for `permanent` when the index or storage is not initialized.
+pub async fn preview_delete_recording(
+    stream: &str,
+    record: &str,
+    permanent: bool,
+) -> Option<anyhow::Result<DryRun>> {
+    if permanent {
+        let retention = RETENTION.read().await.clone()?;
+        return Some(retention.preview_purge(stream, record).await);
+    }
+    let entry = match get_index().await {
+        Some(index) => index.get(stream, record).await,
+        None => None,
+    };
+    let Some(entry) = entry else {
+        return Some(Ok(DryRun::NotFound(format!(
+            "recording {stream}/{record} not found"
+        ))));
+    };
+    if matches!(entry.status, RecordingStatus::Active) {
+        return Some(Ok(DryRun::Conflict(format!(
+            "recording {} is still being written",
+            entry.key()
+        ))));
+    }
+    Some(Ok(DryRun::Affected(DryRunResponse {
+        operation: AuditOperation::Trash,
+        keys: vec![entry.key()],
+        objects: 0,
+        bytes: 0,
+    })))
+}
+
+/// Take a recording out of the trash before it is purged
+pub async fn restore_recording(
+    stream: &str,
+    record: &str,
+    if_match: Option<i64>,
+) -> anyhow::Result<TrashUpdate> {
+    let Some(index) = get_index().await else {
+        return Ok(TrashUpdate::NotFound);
+    };
+    index.restore(stream, record, if_match).await
+}
+
+/// Delete a finished recording's objects and index entry right away, skipping the trash,
+/// on behalf of `actor`.
+///
+/// `None` when the index or storage is not initialized.
+pub async fn purge_recording(
+    stream: &str,
+    record: &str,
+    actor: &str,
+    if_match: Option<i64>,
+) -> Option<anyhow::Result<TrashUpdate>> {
+    let retention = RETENTION.read().await.clone()?;
+    Some(
+        retention
+            .purge_recording(stream, record, actor, if_match)
+            .await,
+    )
+}
+
+/// Index entry of the recording with `uuid`, `None` when it is not in the index or
+/// was acked
+pub async fn recording_by_uuid(uuid: &str) -> Option<RecordingIndexEntry> {
+    get_index().await?.get_by_uuid(uuid).await
+}
+
+/// Apply a metadata patch to an index entry
+pub async fn update_recording(
+    stream: &str,
+    record: &str,
+    patch: UpdateRecordingRequest,
+) -> anyhow::Result<MetadataUpdate> {
+    let Some(index) = get_index().await else {
+        return Ok(MetadataUpdate::NotFound);
+    };
+
+    let update = index.update_metadata(stream, record, &patch).await?;
+    if let (MetadataUpdate::Updated(entry), Some(class)) = (&update, patch.retention_class)
+        && let Some(retention) = RETENTION.read().await.clone()
+    {
+        retention.reclassify(&entry.record_dir, class).await;
+    }
+    if let (MetadataUpdate::Updated(entry), Some(priority)) = (&update, patch.priority)
+        && let Some(uploader) = UPLOADER.read().await.clone()
+    {
+        uploader
+            .reprioritize_pending(&entry.record_dir, priority)
+            .await;
+    }
+    Ok(update)
+}
+
+fn record_key(info: &RecordingInfo) -> String {
+    if info.record_id > 0 {
+        return info.record_id.to_string();
+    }
+
+    let ts = (info.start_ts_micros / 1_000_000).max(0);
+    ts.to_string()
+}
+
+fn resolve_index_path(cfg: &RecorderConfig) -> Option<PathBuf> {
+    if let Some(path) = cfg.index_path.as_ref()
+        && !path.trim().is_empty()
+    {
+        return Some(PathBuf::from(path));
+    }
+
+    Some(PathBuf::from("./recordings/index.json"))
+}
+
+#[cfg(feature = "recorder")]
+async fn rotation_loop(cfg: Arc<RecorderConfig>) {
+    let shortest = shortest_split_limit(&cfg);
+    if shortest == 0 {
+        return;
+    }
+
+    let interval_secs = rotation_check_interval(shortest);
+    let mut ticker = time::interval(Duration::from_secs(interval_secs));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        ticker.tick().await;
+        enforce_max_duration(&cfg).await;
+    }
+}
+
+#[cfg(feature = "recorder")]
+async fn enforce_max_duration(cfg: &RecorderConfig) {
+    let mut map = TASKS.write().await;
+    for (stream, task) in map.iter_mut() {
+        let limit = max_duration_secs(cfg, stream);
+        if limit == 0 || !task.has_exceeded(Duration::from_secs(limit)) {
+            continue;
+        }
+        if let Some(next_prefix) = task.request_split() {
+            tracing::info!(
+                "[recorder] stream {} reached {} seconds, splitting into {} at next keyframe",
+                stream,
+                limit,
+                next_prefix
+            );
+        }
+    }
+}
+
+/// Split limit in seconds for a stream: first matching rule, then the global setting
+#[cfg(feature = "recorder")]
+fn max_duration_secs(cfg: &RecorderConfig, stream: &str) -> u64 {
+    cfg.rules
+        .iter()
+        .filter(|rule| should_record(&rule.streams, stream))
+        .find_map(|rule| rule.max_recording_duration_minutes)
+        .or(cfg.max_recording_duration_minutes)
+        .map(|minutes| minutes.saturating_mul(60))
+        .unwrap_or(cfg.max_recording_seconds)
+}
+
+#[cfg(feature = "recorder")]
+fn shortest_split_limit(cfg: &RecorderConfig) -> u64 {
+    let global = cfg
+        .max_recording_duration_minutes
+        .map(|minutes| minutes.saturating_mul(60))
+        .unwrap_or(cfg.max_recording_seconds);
+    cfg.rules
+        .iter()
+        .filter_map(|rule| rule.max_recording_duration_minutes)
+        .map(|minutes| minutes.saturating_mul(60))
+        .chain(std::iter::once(global))
+        .filter(|secs| *secs > 0)
+        .min()
+        .unwrap_or(0)
+}
+
 #[cfg(feature = "recorder")]
 fn rotation_check_interval(max_seconds: u64) -> u64 {
     let quarter = max_seconds / 4;
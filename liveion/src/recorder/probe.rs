@@ -0,0 +1,343 @@
+//! Read track parameters back from the init segments and media fragments the recorder
+//! writes.
+
+use anyhow::{Result, anyhow, bail};
+
+/// Parameters of the single sample entry in an init segment's `stsd`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SampleEntry {
+    Video {
+        fourcc: String,
+        width: u32,
+        height: u32,
+    },
+    Audio {
+        fourcc: String,
+        channels: u16,
+        sample_rate: u32,
+    },
+}
+
+/// Parse `moov/trak/mdia/minf/stbl/stsd` of an init segment
+pub fn probe_init_segment(init: &[u8]) -> Result<SampleEntry> {
+    let stsd = find_path(
+        init,
+        &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"],
+    )?;
+    // FullBox header and entry_count precede the first sample entry
+    let entries = stsd.get(8..).ok_or_else(|| anyhow!("stsd too short"))?;
+    let (fourcc, entry) = boxes(entries)
+        .next()
+        .ok_or_else(|| anyhow!("stsd has no sample entry"))??;
+    let fourcc = String::from_utf8_lossy(fourcc).into_owned();
+
+    match fourcc.as_str() {
+        "avc1" | "avc3" | "hev1" | "hvc1" | "vp08" | "vp09" | "av01" => {
+            // reserved(6) data_reference_index(2) pre_defined/reserved(16) width(2) height(2)
+            let width = read_u16(entry, 24)?;
+            let height = read_u16(entry, 26)?;
+            if width == 0 || height == 0 {
+                bail!("{fourcc} sample entry has no dimensions");
+            }
+            Ok(SampleEntry::Video {
+                fourcc,
+                width: width as u32,
+                height: height as u32,
+            })
+        }
+        "Opus" | "mp4a" => {
+            // reserved(6) data_reference_index(2) reserved(8) channelcount(2)
+            // samplesize(2) pre_defined(2) reserved(2) samplerate(16.16)
+            let channels = read_u16(entry, 16)?;
+            let sample_rate = read_u32(entry, 24)? >> 16;
+            if channels == 0 || sample_rate == 0 {
+                bail!("{fourcc} sample entry has no channel layout");
+            }
+            Ok(SampleEntry::Audio {
+                fourcc,
+                channels,
+                sample_rate,
+            })
+        }
+        other => bail!("unsupported sample entry {other}"),
+    }
+}
+
+/// Time span of a media segment in its track's timescale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentTiming {
+    pub start: u64,
+    pub duration: u64,
+}
+
+/// Time span of a media segment from its `sidx`, or from the `tfdt` and `trun` sample
+/// durations of its fragments. Values of a `sidx` on another timescale are converted to
+/// `timescale`.
+///
+/// Fails on a truncated segment, so a partially written file is never mistaken for a
+/// shorter one.
+pub fn probe_fragment(data: &[u8], timescale: u32) -> Result<FragmentTiming> {
+    let mut sidx = None;
+    let mut span: Option<(u64, u64)> = None;
+    for b in boxes(data) {
+        let (typ, payload) = b?;
+        match typ {
+            b"sidx" if sidx.is_none() => sidx = Some(parse_sidx(payload, timescale)?),
+            b"moof" => {
+                let traf = find_path(payload, &[b"traf"])?;
+                let start = parse_tfdt(find_path(traf, &[b"tfdt"])?)?;
+                let default_duration = parse_tfhd_default_duration(find_path(traf, &[b"tfhd"])?)?;
+                let end = start + trun_duration(find_path(traf, &[b"trun"])?, default_duration)?;
+                span = Some(match span {
+                    Some((first, last)) => (first.min(start), last.max(end)),
+                    None => (start, end),
+                });
+            }
+            _ => {}
+        }
+    }
+    if let Some(timing) = sidx {
+        return Ok(timing);
+    }
+    let (start, end) = span.ok_or_else(|| anyhow!("no moof box found"))?;
+    Ok(FragmentTiming {
+        start,
+        duration: end - start,
+    })
+}
+
+fn parse_sidx(sidx: &[u8], timescale: u32) -> Result<FragmentTiming> {
+    let version = *sidx.first().ok_or_else(|| anyhow!("sidx too short"))?;
+    let sidx_timescale = read_u32(sidx, 8)?;
+    if sidx_timescale == 0 {
+        bail!("sidx has no timescale");
+    }
+    let (start, at) = match version {
+        0 => (read_u32(sidx, 12)? as u64, 20),
+        _ => (read_u64(sidx, 12)?, 28),
+    };
+    let count = read_u16(sidx, at + 2)? as usize;
+    let mut duration = 0u64;
+    for i in 0..count {
+        duration += read_u32(sidx, at + 4 + i * 12 + 4)? as u64;
+    }
+    let rescale = |ticks: u64| (ticks as u128 * timescale as u128 / sidx_timescale as u128) as u64;
+    Ok(FragmentTiming {
+        start: rescale(start),
+        duration: rescale(duration),
+    })
+}
+
+fn parse_tfdt(tfdt: &[u8]) -> Result<u64> {
+    match tfdt.first() {
+        Some(1) => read_u64(tfdt, 4),
+        Some(_) => Ok(read_u32(tfdt, 4)? as u64),
+        None => bail!("tfdt too short"),
+    }
+}
+
+/// `default_sample_duration` of a `tfhd`, if present
+fn parse_tfhd_default_duration(tfhd: &[u8]) -> Result<Option<u32>> {
+    let flags = read_u32(tfhd, 0)? & 0x00FF_FFFF;
+    if flags & 0x08 == 0 {
+        return Ok(None);
+    }
+    // track_ID, then base_data_offset and sample_description_index when flagged
+    let mut at = 8;
+    if flags & 0x01 != 0 {
+        at += 8;
+    }
+    if flags & 0x02 != 0 {
+        at += 4;
+    }
+    Ok(Some(read_u32(tfhd, at)?))
+}
+
+/// Sum of the sample durations of a `trun`
+fn trun_duration(trun: &[u8], default_duration: Option<u32>) -> Result<u64> {
+    let flags = read_u32(trun, 0)? & 0x00FF_FFFF;
+    let count = read_u32(trun, 4)? as u64;
+    if flags & 0x100 == 0 {
+        let default = default_duration.ok_or_else(|| anyhow!("trun has no sample durations"))?;
+        return Ok(count * default as u64);
+    }
+    let mut at = 8;
+    if flags & 0x01 != 0 {
+        at += 4;
+    }
+    if flags & 0x04 != 0 {
+        at += 4;
+    }
+    let per_sample = 4 * [0x100, 0x200, 0x400, 0x800]
+        .iter()
+        .filter(|f| flags & *f != 0)
+        .count();
+    let mut duration = 0u64;
+    for i in 0..count as usize {
+        duration += read_u32(trun, at + i * per_sample)? as u64;
+    }
+    Ok(duration)
+}
+
+fn find_path<'a>(mut data: &'a [u8], path: &[&[u8; 4]]) -> Result<&'a [u8]> {
+    for name in path {
+        data = boxes(data)
+            .find_map(|b| match b {
+                Ok((typ, payload)) if typ == *name => Some(Ok(payload)),
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            })
+            .ok_or_else(|| anyhow!("{} box not found", String::from_utf8_lossy(*name)))??;
+    }
+    Ok(data)
+}
+
+/// Iterate over `(type, payload)` of the boxes laid out back to back in `data`
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = Result<(&[u8], &[u8])>> {
+    std::iter::from_fn(move || {
+        if data.is_empty() {
+            return None;
+        }
+        match split_box(data) {
+            Ok((typ, payload, rest)) => {
+                data = rest;
+                Some(Ok((typ, payload)))
+            }
+            Err(e) => {
+                data = &[];
+                Some(Err(e))
+            }
+        }
+    })
+}
+
+/// Split the first box off `data` into its type, payload and the remaining bytes
+fn split_box(data: &[u8]) -> Result<(&[u8], &[u8], &[u8])> {
+    let size = read_u32(data, 0)? as u64;
+    let typ = data
+        .get(4..8)
+        .ok_or_else(|| anyhow!("truncated box header"))?;
+    let (header, size) = match size {
+        0 => (8, data.len() as u64),
+        1 => (16, read_u64(data, 8)?),
+        n => (8, n),
+    };
+    if size < header as u64 || size > data.len() as u64 {
+        bail!("box size {size} out of bounds");
+    }
+    let (current, rest) = data.split_at(size as usize);
+    Ok((typ, &current[header..], rest))
+}
+
+fn read_u16(data: &[u8], at: usize) -> Result<u16> {
+    let bytes = data
+        .get(at..at + 2)
+        .ok_or_else(|| anyhow!("truncated at byte {at}"))?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32> {
+    let bytes = data
+        .get(at..at + 4)
+        .ok_or_else(|| anyhow!("truncated at byte {at}"))?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u64(data: &[u8], at: usize) -> Result<u64> {
+    let bytes = data
+        .get(at..at + 8)
+        .ok_or_else(|| anyhow!("truncated at byte {at}"))?;
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(bytes);
+    Ok(u64::from_be_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::fmp4::Fmp4Writer;
+
+    #[test]
+    fn test_probe_video_and_audio_init() {
+        let video = Fmp4Writer::new(
+            90_000,
+            1,
+            1920,
+            1080,
+            "avc1.64002A".to_string(),
+            vec![vec![0x67, 0x64, 0x00, 0x2A], vec![0x68, 0xCE]],
+        );
+        assert_eq!(
+            probe_init_segment(&video.build_init_segment()).unwrap(),
+            SampleEntry::Video {
+                fourcc: "avc1".to_string(),
+                width: 1920,
+                height: 1080,
+            }
+        );
+
+        let audio = Fmp4Writer::new_audio(48_000, 2, 2, 48_000, "opus".to_string(), vec![]);
+        assert_eq!(
+            probe_init_segment(&audio.build_init_segment()).unwrap(),
+            SampleEntry::Audio {
+                fourcc: "Opus".to_string(),
+                channels: 2,
+                sample_rate: 48_000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_probe_fragment() {
+        use crate::recorder::fmp4::Mp4Sample;
+
+        let video = Fmp4Writer::new(90_000, 1, 640, 360, "avc1.42E01E".to_string(), vec![]);
+        let samples: Vec<Mp4Sample> = (0..5)
+            .map(|i| Mp4Sample {
+                duration: 3_000,
+                is_sync: i == 0,
+                bytes: bytes::Bytes::from_static(&[0, 0, 0, 1, 0x65]),
+            })
+            .collect();
+        let fragment = video.build_fragment(7, 180_000, &samples);
+        assert_eq!(
+            probe_fragment(&fragment, 90_000).unwrap(),
+            FragmentTiming {
+                start: 180_000,
+                duration: 15_000,
+            }
+        );
+        // A segment cut off inside its mdat is not a shorter segment
+        assert!(probe_fragment(&fragment[..fragment.len() - 2], 90_000).is_err());
+        assert!(probe_fragment(&fragment[..24], 90_000).is_err());
+
+        // A sidx on a millisecond timescale wins over the fragment headers
+        let mut sidx = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        sidx.extend_from_slice(&1_000u32.to_be_bytes());
+        sidx.extend_from_slice(&2_000u32.to_be_bytes());
+        sidx.extend_from_slice(&0u32.to_be_bytes());
+        sidx.extend_from_slice(&[0, 0, 0, 1]);
+        sidx.extend_from_slice(&0u32.to_be_bytes());
+        sidx.extend_from_slice(&500u32.to_be_bytes());
+        sidx.extend_from_slice(&0x9000_0000u32.to_be_bytes());
+        let mut indexed = ((sidx.len() + 8) as u32).to_be_bytes().to_vec();
+        indexed.extend_from_slice(b"sidx");
+        indexed.extend_from_slice(&sidx);
+        indexed.extend_from_slice(&fragment);
+        assert_eq!(
+            probe_fragment(&indexed, 90_000).unwrap(),
+            FragmentTiming {
+                start: 180_000,
+                duration: 45_000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_probe_rejects_truncated_init() {
+        let video = Fmp4Writer::new(90_000, 1, 640, 360, "vp09.00.10.08".to_string(), vec![]);
+        let init = video.build_init_segment();
+        assert!(probe_init_segment(&init[..init.len() / 2]).is_err());
+        assert!(probe_init_segment(&[]).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use anyhow::Result;
+use api::recorder::{IngestRecordingsRequest, IngestRecordingsResponse, RecorderEvent};
+use http::header;
+use reqwest::Client;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+use crate::config::PushConfig;
+
+/// Undelivered transitions, oldest first, bounded by dropping the oldest
+struct PushQueue {
+    events: VecDeque<RecorderEvent>,
+    max: usize,
+    dropped: u64,
+}
+
+impl PushQueue {
+    fn new(max: usize) -> Self {
+        Self {
+            events: VecDeque::new(),
+            max: max.max(1),
+            dropped: 0,
+        }
+    }
+
+    fn push(&mut self, event: RecorderEvent) {
+        if self.events.len() >= self.max {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+    }
+
+    /// Oldest `n` transitions, removed only once delivered
+    fn batch(&self, n: usize) -> Vec<RecorderEvent> {
+        self.events.iter().take(n.max(1)).cloned().collect()
+    }
+
+    fn delivered(&mut self, n: usize) {
+        self.events.drain(..n.min(self.events.len()));
+    }
+
+    fn take_dropped(&mut self) -> u64 {
+        std::mem::take(&mut self.dropped)
+    }
+}
+
+/// Pushes index transitions to liveman's `POST /api/recorder/ingest`
+pub struct IndexPusher {
+    cfg: PushConfig,
+    node_alias: String,
+    client: Client,
+    queue: PushQueue,
+    reachable: bool,
+}
+
+impl IndexPusher {
+    pub fn new(cfg: PushConfig, node_alias: String) -> Self {
+        let queue = PushQueue::new(cfg.max_queue);
+        Self {
+            cfg,
+            node_alias,
+            client: Client::new(),
+            queue,
+            reachable: true,
+        }
+    }
+
+    /// Forward transitions from `events` until the index goes away.
+    ///
+    /// Full batches are sent right away while liveman is reachable, the rest on the
+    /// next tick. Failed batches stay queued and are retried every tick, so everything
+    /// queued is flushed once liveman is back.
+    pub async fn run(mut self, mut events: broadcast::Receiver<RecorderEvent>) {
+        let mut ticker = tokio::time::interval(Duration::from_millis(self.cfg.interval_ms.max(50)));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => {
+                        self.queue.push(event);
+                        if self.reachable && self.queue.events.len() >= self.cfg.batch_size {
+                            self.flush().await;
+                        }
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("[recorder] index push lagged, {} transitions left to pull sync", n);
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+                _ = ticker.tick() => self.flush().await,
+            }
+        }
+    }
+
+    /// Send queued transitions batch by batch, stopping at the first failure
+    async fn flush(&mut self) {
+        let dropped = self.queue.take_dropped();
+        if dropped > 0 {
+            warn!(
+                "[recorder] index push queue full, dropped {} transitions, pull sync will backfill",
+                dropped
+            );
+        }
+        while !self.queue.events.is_empty() {
+            let batch = self.queue.batch(self.cfg.batch_size);
+            let len = batch.len();
+            match self.send(batch).await {
+                Ok(resp) => {
+                    self.queue.delivered(len);
+                    debug!(
+                        "[recorder] index push delivered {} transitions ({} applied, {} skipped)",
+                        len, resp.applied, resp.skipped
+                    );
+                    if !self.reachable {
+                        self.reachable = true;
+                        info!("[recorder] index push reconnected to liveman");
+                    }
+                }
+                Err(e) => {
+                    if self.reachable {
+                        self.reachable = false;
+                        warn!(
+                            "[recorder] index push failed, queueing {} transitions: {:#}",
+                            self.queue.events.len(),
+                            e
+                        );
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn send(&self, events: Vec<RecorderEvent>) -> Result<IngestRecordingsResponse> {
+        let url = format!(
+            "{}{}",
+            self.cfg.liveman_url.trim_end_matches('/'),
+            api::path::recorder_ingest()
+        );
+        let req = IngestRecordingsRequest {
+            node_alias: self.node_alias.clone(),
+            events,
+        };
+        let resp = self
+            .client
+            .post(url)
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", self.cfg.token.expose_secret()),
+            )
+            .timeout(Duration::from_secs(10))
+            .json(&req)
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("ingest failed: {}", resp.status());
+        }
+        Ok(resp.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::recorder::{
+        DEFAULT_PRIORITY, RecorderEventKind, RecordingIndexEntry, RecordingStatus,
+    };
+
+    fn event(id: i64) -> RecorderEvent {
+        RecorderEvent {
+            id,
+            kind: RecorderEventKind::Status,
+            entry: RecordingIndexEntry {
+                uuid: String::new(),
+                record: id.to_string(),
+                stream: "cam".to_string(),
+                record_dir: format!("cam/{id}"),
+                mpd_path: format!("cam/{id}/manifest.mpd"),
+                start_ts: id,
+                end_ts: None,
+                duration_ms: None,
+                status: RecordingStatus::Completed,
+                node_alias: Some("edge-1".to_string()),
+                updated_at: id,
+                note: None,
+                labels: Vec::new(),
+                continues: None,
+                media_info: Vec::new(),
+                retention_class: None,
+                trashed_at: None,
+                trashed_from: None,
+                repair_error: None,
+                source: None,
+                replicas: Vec::new(),
+                clock_skew_detected: false,
+                priority: DEFAULT_PRIORITY,
+                tenant: None,
+                size: None,
+                captions: Vec::new(),
+                trigger: None,
+                imported: false,
+                seq: 0,
+            },
+        }
+    }
+
+    fn ids(queue: &PushQueue) -> Vec<i64> {
+        queue.events.iter().map(|e| e.id).collect()
+    }
+
+    #[test]
+    fn test_queue_drops_oldest_when_full() {
+        let mut queue = PushQueue::new(3);
+        for id in 1..=5 {
+            queue.push(event(id));
+        }
+        assert_eq!(ids(&queue), vec![3, 4, 5]);
+        assert_eq!(queue.take_dropped(), 2);
+        assert_eq!(queue.take_dropped(), 0);
+    }
+
+    #[test]
+    fn test_queue_keeps_batch_until_delivered() {
+        let mut queue = PushQueue::new(10);
+        for id in 1..=5 {
+            queue.push(event(id));
+        }
+        let batch = queue.batch(2);
+        assert_eq!(batch.iter().map(|e| e.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(queue.events.len(), 5);
+
+        // Arrivals during delivery stay behind the batch
+        queue.push(event(6));
+        queue.delivered(batch.len());
+        assert_eq!(ids(&queue), vec![3, 4, 5, 6]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_flush_keeps_queue() {
+        let cfg = PushConfig {
+            enabled: true,
+            // Nothing listens on the discard port
+            liveman_url: "http://127.0.0.1:9".to_string(),
+            ..Default::default()
+        };
+        let mut pusher = IndexPusher::new(cfg, "edge-1".to_string());
+        pusher.queue.push(event(1));
+        pusher.queue.push(event(2));
+
+        pusher.flush().await;
+        assert!(!pusher.reachable);
+        assert_eq!(ids(&pusher.queue), vec![1, 2]);
+    }
+}
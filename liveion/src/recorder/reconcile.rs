@@ -0,0 +1,328 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use api::recorder::{ReconcileStatus, RecordingIndexEntry};
+use chrono::Utc;
+use opendal::{ErrorKind, Operator};
+use serde::{Deserialize, Serialize};
+use storage::FailoverOperator;
+use tokio::time::{self, Interval, MissedTickBehavior};
+
+use crate::hook::{Event, RecordingEvent, RecordingEventType};
+use crate::metrics;
+use crate::stream::manager::Manager;
+
+use super::index::RecordingsIndex;
+
+/// Entries loaded from the index per batch, the checkpoint is saved after each batch
+const BATCH_SIZE: usize = 50;
+
+/// Progress persisted between batches so a restart resumes where the run stopped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Checkpoint {
+    after: Option<String>,
+    sample_segments: usize,
+    checked: usize,
+    missing: usize,
+    started_at: i64,
+}
+
+/// Checks that finished recordings still have their objects in storage
+pub struct Reconciler {
+    index: Arc<RecordingsIndex>,
+    operator: FailoverOperator,
+    manager: Arc<Manager>,
+    checkpoint_path: PathBuf,
+    max_heads_per_second: u32,
+    status: Mutex<ReconcileStatus>,
+}
+
+impl Reconciler {
+    pub fn new(
+        index: Arc<RecordingsIndex>,
+        operator: FailoverOperator,
+        manager: Arc<Manager>,
+        checkpoint_path: PathBuf,
+        max_heads_per_second: u32,
+    ) -> Self {
+        Self {
+            index,
+            operator,
+            manager,
+            checkpoint_path,
+            max_heads_per_second: max_heads_per_second.max(1),
+            status: Mutex::new(ReconcileStatus::default()),
+        }
+    }
+
+    pub fn status(&self) -> ReconcileStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Start a run in the background, returns false when one is already running
+    pub fn start(self: &Arc<Self>, sample_segments: usize) -> bool {
+        let started_at = Utc::now().timestamp_micros();
+        let checkpoint = Checkpoint {
+            after: None,
+            sample_segments,
+            checked: 0,
+            missing: 0,
+            started_at,
+        };
+        self.spawn(checkpoint)
+    }
+
+    /// Continue a run interrupted by a restart, if any
+    pub async fn resume(self: &Arc<Self>) {
+        let Ok(content) = tokio::fs::read_to_string(&self.checkpoint_path).await else {
+            return;
+        };
+        match serde_json::from_str::<Checkpoint>(&content) {
+            Ok(checkpoint) => {
+                tracing::info!(
+                    "[reconcile] resuming after {:?} ({} checked)",
+                    checkpoint.after,
+                    checkpoint.checked
+                );
+                self.spawn(checkpoint);
+            }
+            Err(e) => {
+                tracing::warn!("[reconcile] discarding unreadable checkpoint: {}", e);
+                let _ = tokio::fs::remove_file(&self.checkpoint_path).await;
+            }
+        }
+    }
+
+    fn spawn(self: &Arc<Self>, checkpoint: Checkpoint) -> bool {
+        {
+            let mut status = self.status.lock().unwrap();
+            if status.running {
+                return false;
+            }
+            *status = ReconcileStatus {
+                running: true,
+                checked: checkpoint.checked,
+                missing: checkpoint.missing,
+                started_at: Some(checkpoint.started_at),
+                finished_at: None,
+            };
+        }
+        let this = self.clone();
+        tokio::spawn(async move { this.run(checkpoint).await });
+        true
+    }
+
+    async fn run(&self, mut checkpoint: Checkpoint) {
+        let mut limiter = time::interval(Duration::from_secs_f64(
+            1.0 / self.max_heads_per_second as f64,
+        ));
+        limiter.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            let batch = self
+                .index
+                .finished_after(checkpoint.after.as_deref(), BATCH_SIZE)
+                .await;
+            if batch.is_empty() {
+                break;
+            }
+            for entry in batch {
+                match self
+                    .objects_present(&entry, checkpoint.sample_segments, &mut limiter)
+                    .await
+                {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        if self.mark_missing(&entry).await {
+                            checkpoint.missing += 1;
+                        }
+                    }
+                    // Storage trouble is not evidence of deletion, leave the entry alone
+                    Err(e) => tracing::warn!("[reconcile] check of {} failed: {}", entry.key(), e),
+                }
+                checkpoint.checked += 1;
+                checkpoint.after = Some(entry.key());
+                let mut status = self.status.lock().unwrap();
+                status.checked = checkpoint.checked;
+                status.missing = checkpoint.missing;
+            }
+            if let Err(e) = self.save_checkpoint(&checkpoint).await {
+                tracing::warn!("[reconcile] failed to save checkpoint: {}", e);
+            }
+        }
+
+        let _ = tokio::fs::remove_file(&self.checkpoint_path).await;
+        tracing::info!(
+            "[reconcile] finished: {} checked, {} missing",
+            checkpoint.checked,
+            checkpoint.missing
+        );
+        let mut status = self.status.lock().unwrap();
+        status.running = false;
+        status.finished_at = Some(Utc::now().timestamp_micros());
+    }
+
+    async fn objects_present(
+        &self,
+        entry: &RecordingIndexEntry,
+        sample_segments: usize,
+        limiter: &mut Interval,
+    ) -> Result<bool> {
+        let operator = self.operator.current();
+        limiter.tick().await;
+        if !exists(&operator, &entry.mpd_path).await? {
+            return Ok(false);
+        }
+        if sample_segments == 0 {
+            return Ok(true);
+        }
+
+        let mpd = operator.read(&entry.mpd_path).await?.to_vec();
+        let mpd = String::from_utf8_lossy(&mpd);
+        for path in sample_segment_paths(&mpd, &entry.record_dir, sample_segments) {
+            limiter.tick().await;
+            if !exists(&operator, &path).await? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    async fn mark_missing(&self, entry: &RecordingIndexEntry) -> bool {
+        match self.index.mark_missing(&entry.stream, &entry.record).await {
+            Ok(Some(updated)) => {
+                tracing::warn!("[reconcile] objects of {} are missing", updated.key());
+                metrics::RECORDINGS_MISSING.inc();
+                self.manager.send_event(Event::Recording(RecordingEvent {
+                    r#type: RecordingEventType::Missing,
+                    recording: updated,
+                }));
+                true
+            }
+            Ok(None) => false,
+            Err(e) => {
+                tracing::error!("[reconcile] failed to mark {} missing: {}", entry.key(), e);
+                false
+            }
+        }
+    }
+
+    async fn save_checkpoint(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let tmp = self.checkpoint_path.with_extension("tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(checkpoint)?).await?;
+        tokio::fs::rename(&tmp, &self.checkpoint_path).await?;
+        Ok(())
+    }
+}
+
+async fn exists(operator: &Operator, path: &str) -> opendal::Result<bool> {
+    match operator.stat(path).await {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn attr<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let needle = format!(" {name}=\"");
+    let start = element.find(&needle)? + needle.len();
+    let len = element[start..].find('"')?;
+    Some(&element[start..start + len])
+}
+
+/// Paths of `n` segments spread over the first representation of a manifest
+fn sample_segment_paths(mpd: &str, record_dir: &str, n: usize) -> Vec<String> {
+    let Some(begin) = mpd.find("<SegmentTemplate") else {
+        return Vec::new();
+    };
+    let block = &mpd[begin..];
+    let block = &block[..block.find("</SegmentTemplate>").unwrap_or(block.len())];
+    let Some(media) = attr(block, "media") else {
+        return Vec::new();
+    };
+    let start_number: u64 = attr(block, "startNumber")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1);
+
+    let mut count = 0u64;
+    for (pos, _) in block.match_indices("<S ") {
+        let element = &block[pos..];
+        let element = &element[..element.find('>').unwrap_or(element.len())];
+        let repeat: u64 = attr(element, "r").and_then(|r| r.parse().ok()).unwrap_or(0);
+        count += 1 + repeat;
+    }
+    if count == 0 || n == 0 {
+        return Vec::new();
+    }
+
+    let n = (n as u64).min(count);
+    (0..n)
+        .map(|i| start_number + i * count / n)
+        .map(|number| format!("{}/{}", record_dir, expand_number(media, number)))
+        .collect()
+}
+
+/// Expand `$Number$` or `$Number%0Nd$` in a DASH segment template
+fn expand_number(template: &str, number: u64) -> String {
+    let Some(begin) = template.find("$Number") else {
+        return template.to_string();
+    };
+    let rest = &template[begin + "$Number".len()..];
+    let Some(end) = rest.find('$') else {
+        return template.to_string();
+    };
+    let width: usize = rest[..end]
+        .strip_prefix("%0")
+        .and_then(|f| f.strip_suffix('d'))
+        .and_then(|w| w.parse().ok())
+        .unwrap_or(0);
+    format!(
+        "{}{:0width$}{}",
+        &template[..begin],
+        number,
+        &rest[end + 1..],
+        width = width
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MPD: &str = r#"<MPD><Period>
+        <AdaptationSet id="0" contentType="video">
+            <Representation id="0" mimeType="video/mp4">
+                <SegmentTemplate timescale="90000" initialization="v_init.m4s" media="v_seg_$Number%04d$.m4s" startNumber="1">
+                    <SegmentTimeline>
+                        <S t="0" d="90000" />
+                        <S t="90000" d="90000" r="2" />
+                        <S t="360000" d="90000" />
+                    </SegmentTimeline>
+                </SegmentTemplate>
+            </Representation>
+        </AdaptationSet>
+    </Period></MPD>"#;
+
+    #[test]
+    fn test_sample_segment_paths() {
+        assert_eq!(
+            sample_segment_paths(MPD, "cam/1700000000", 2),
+            vec![
+                "cam/1700000000/v_seg_0001.m4s".to_string(),
+                "cam/1700000000/v_seg_0003.m4s".to_string(),
+            ]
+        );
+        assert_eq!(sample_segment_paths(MPD, "cam/1", 10).len(), 5);
+        assert!(sample_segment_paths(MPD, "cam/1", 0).is_empty());
+        assert!(sample_segment_paths("<MPD></MPD>", "cam/1", 3).is_empty());
+    }
+
+    #[test]
+    fn test_expand_number() {
+        assert_eq!(expand_number("a_seg_$Number%04d$.m4s", 7), "a_seg_0007.m4s");
+        assert_eq!(expand_number("seg_$Number$.m4s", 12), "seg_12.m4s");
+        assert_eq!(expand_number("static.m4s", 1), "static.m4s");
+    }
+}
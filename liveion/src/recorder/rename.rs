@@ -0,0 +1,647 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use api::recorder::{AuditOperation, DryRunResponse, RenameStreamRequest, RenameStreamResponse};
+use opendal::Operator;
+use serde::{Deserialize, Serialize};
+use storage::FailoverOperator;
+use tokio::sync::Mutex;
+
+use super::audit::DryRun;
+use super::index::RecordingsIndex;
+use super::tenancy::Tenancy;
+
+/// Rename in progress, persisted after each recording so a restart finishes it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Journal {
+    from: String,
+    to: String,
+    copy_objects: bool,
+    /// Old record dirs whose entries already moved but whose objects are not deleted yet
+    #[serde(default)]
+    pending_delete: Vec<String>,
+    #[serde(default)]
+    progress: RenameStreamResponse,
+}
+
+/// Outcome of [`StreamRenamer::rename`]
+pub enum RenameOutcome {
+    Renamed(RenameStreamResponse),
+    Conflict(String),
+}
+
+/// Moves the historical recordings of a stream to another stream name
+pub struct StreamRenamer {
+    index: Arc<RecordingsIndex>,
+    operator: FailoverOperator,
+    journal_path: PathBuf,
+    /// Key namespace of generated record dirs, those move to `{namespace}/{to}/`
+    namespace: Option<String>,
+    /// Record dirs of a tenant's streams start with `{tenant}/`, renames stay in the tenant
+    tenancy: Tenancy,
+    running: Mutex<()>,
+}
+
+impl StreamRenamer {
+    pub fn new(
+        index: Arc<RecordingsIndex>,
+        operator: FailoverOperator,
+        journal_path: PathBuf,
+        namespace: Option<String>,
+        tenancy: Tenancy,
+    ) -> Self {
+        Self {
+            index,
+            operator,
+            journal_path,
+            namespace,
+            tenancy,
+            running: Mutex::new(()),
+        }
+    }
+
+    /// Rename `req.from` to `req.to`.
+    ///
+    /// Only one rename runs at a time. An unfinished rename has to be retried with
+    /// the same request before another one is accepted, retrying skips the collision
+    /// checks for objects it copied itself.
+    pub async fn rename(&self, req: RenameStreamRequest) -> Result<RenameOutcome> {
+        let Ok(_guard) = self.running.try_lock() else {
+            return Ok(RenameOutcome::Conflict(
+                "another stream rename is running".to_string(),
+            ));
+        };
+        let journal = match self.load_journal().await {
+            Some(journal)
+                if journal.from == req.from
+                    && journal.to == req.to
+                    && journal.copy_objects == req.copy_objects =>
+            {
+                tracing::info!(
+                    "[rename] resuming {} -> {} ({} renamed)",
+                    journal.from,
+                    journal.to,
+                    journal.progress.renamed
+                );
+                journal
+            }
+            Some(journal) => {
+                return Ok(RenameOutcome::Conflict(format!(
+                    "rename {} -> {} is unfinished, retry it first",
+                    journal.from, journal.to
+                )));
+            }
+            None => {
+                let tenants = (
+                    self.tenancy.tenant_of(&req.from),
+                    self.tenancy.tenant_of(&req.to),
+                );
+                if tenants.0 != tenants.1 {
+                    return Ok(RenameOutcome::Conflict(format!(
+                        "{} and {} belong to different tenants",
+                        req.from, req.to
+                    )));
+                }
+                if let Some(reason) = self.collision(&req).await? {
+                    return Ok(RenameOutcome::Conflict(reason));
+                }
+                Journal {
+                    from: req.from,
+                    to: req.to,
+                    copy_objects: req.copy_objects,
+                    pending_delete: Vec::new(),
+                    progress: RenameStreamResponse::default(),
+                }
+            }
+        };
+        self.run(journal).await.map(RenameOutcome::Renamed)
+    }
+
+    /// What [`Self::rename`] would move, without moving anything
+    pub async fn preview(&self, req: &RenameStreamRequest) -> Result<DryRun> {
+        let journal = tokio::fs::read_to_string(&self.journal_path)
+            .await
+            .ok()
+            .and_then(|content| serde_json::from_str::<Journal>(&content).ok());
+        match journal {
+            Some(journal)
+                if journal.from == req.from
+                    && journal.to == req.to
+                    && journal.copy_objects == req.copy_objects => {}
+            Some(journal) => {
+                return Ok(DryRun::Conflict(format!(
+                    "rename {} -> {} is unfinished, retry it first",
+                    journal.from, journal.to
+                )));
+            }
+            None => {
+                if let Some(reason) = self.collision(req).await? {
+                    return Ok(DryRun::Conflict(reason));
+                }
+            }
+        }
+        let operator = self.operator.current();
+        let mut preview = DryRunResponse {
+            operation: AuditOperation::RenameStream,
+            keys: Vec::new(),
+            objects: 0,
+            bytes: 0,
+        };
+        for entry in self.index.entries_of(&req.from).await {
+            if req.copy_objects
+                && self
+                    .moved_path(&entry.record_dir, &req.from, &req.to)
+                    .is_some()
+            {
+                preview.objects += count_objects(&operator, &entry.record_dir).await?;
+            }
+            preview.keys.push(entry.key());
+        }
+        Ok(DryRun::Affected(preview))
+    }
+
+    /// Finish a rename interrupted by a restart, if any
+    pub async fn resume(self: &Arc<Self>) {
+        let Some(journal) = self.load_journal().await else {
+            return;
+        };
+        let this = self.clone();
+        tokio::spawn(async move {
+            let _guard = this.running.lock().await;
+            tracing::info!("[rename] resuming {} -> {}", journal.from, journal.to);
+            if let Err(e) = this.run(journal).await {
+                tracing::error!(
+                    "[rename] resumed rename failed, retry it via the API: {:#}",
+                    e
+                );
+            }
+        });
+    }
+
+    /// Recordings of `to` that the rename would overwrite
+    async fn collision(&self, req: &RenameStreamRequest) -> Result<Option<String>> {
+        let operator = self.operator.current();
+        for entry in self.index.entries_of(&req.from).await {
+            if self.index.contains(&req.to, &entry.record).await {
+                return Ok(Some(format!(
+                    "recording {}/{} already exists",
+                    req.to, entry.record
+                )));
+            }
+            if !req.copy_objects {
+                continue;
+            }
+            if let Some(dir) = self.moved_path(&entry.record_dir, &req.from, &req.to)
+                && has_objects(&operator, &dir).await?
+            {
+                return Ok(Some(format!("objects already exist under {dir}")));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn run(&self, mut journal: Journal) -> Result<RenameStreamResponse> {
+        self.save_journal(&journal).await?;
+
+        // Deletions left over from an interrupted run, their entries already moved
+        while let Some(dir) = journal.pending_delete.first().cloned() {
+            storage::delete_prefix(&self.operator.current(), &dir).await?;
+            journal.pending_delete.remove(0);
+            self.save_journal(&journal).await?;
+        }
+
+        for entry in self.index.entries_of(&journal.from).await {
+            let moved_dir = journal
+                .copy_objects
+                .then(|| self.moved_path(&entry.record_dir, &journal.from, &journal.to))
+                .flatten();
+            let Some(dir) = moved_dir else {
+                if journal.copy_objects {
+                    journal.progress.kept_in_place += 1;
+                }
+                self.index
+                    .rename_entry(
+                        &entry.stream,
+                        &entry.record,
+                        &journal.to,
+                        entry.record_dir.clone(),
+                        entry.mpd_path.clone(),
+                    )
+                    .await?;
+                journal.progress.renamed += 1;
+                self.save_journal(&journal).await?;
+                continue;
+            };
+
+            let operator = self.operator.current();
+            let copied = copy_verified(&operator, &entry.record_dir, &dir).await?;
+            let mpd_path = self
+                .moved_path(&entry.mpd_path, &journal.from, &journal.to)
+                .unwrap_or_else(|| entry.mpd_path.clone());
+            self.index
+                .rename_entry(
+                    &entry.stream,
+                    &entry.record,
+                    &journal.to,
+                    dir.clone(),
+                    mpd_path,
+                )
+                .await?;
+            journal.progress.renamed += 1;
+            journal.progress.moved_objects += copied;
+            journal.pending_delete.push(entry.record_dir.clone());
+            self.save_journal(&journal).await?;
+
+            storage::delete_prefix(&operator, &entry.record_dir).await?;
+            journal.pending_delete.retain(|d| *d != entry.record_dir);
+            self.save_journal(&journal).await?;
+            tracing::debug!(
+                "[rename] moved {} to {} ({} objects)",
+                entry.record_dir,
+                dir,
+                copied
+            );
+        }
+
+        let _ = tokio::fs::remove_file(&self.journal_path).await;
+        tracing::info!(
+            "[rename] renamed {} recordings of {} to {}, moved {} objects",
+            journal.progress.renamed,
+            journal.from,
+            journal.to,
+            journal.progress.moved_objects
+        );
+        Ok(journal.progress)
+    }
+
+    /// [`moved_path`] of a generated record dir, with or without this node's namespace
+    /// and the stream's tenant
+    fn moved_path(&self, path: &str, from: &str, to: &str) -> Option<String> {
+        if let Ok(Some(tenant)) = self.tenancy.tenant_of(from)
+            && let Some(rest) = path
+                .strip_prefix(tenant.as_str())
+                .and_then(|p| p.strip_prefix('/'))
+        {
+            return self
+                .moved_path_in_namespace(rest, from, to)
+                .map(|moved| format!("{tenant}/{moved}"));
+        }
+        self.moved_path_in_namespace(path, from, to)
+    }
+
+    fn moved_path_in_namespace(&self, path: &str, from: &str, to: &str) -> Option<String> {
+        if let Some(ns) = self.namespace.as_deref()
+            && let Some(rest) = path.strip_prefix(ns).and_then(|p| p.strip_prefix('/'))
+            && let Some(moved) = moved_path(rest, from, to)
+        {
+            return Some(format!("{ns}/{moved}"));
+        }
+        moved_path(path, from, to)
+    }
+
+    async fn load_journal(&self) -> Option<Journal> {
+        let content = tokio::fs::read_to_string(&self.journal_path).await.ok()?;
+        match serde_json::from_str(&content) {
+            Ok(journal) => Some(journal),
+            Err(e) => {
+                tracing::warn!("[rename] discarding unreadable journal: {}", e);
+                let _ = tokio::fs::remove_file(&self.journal_path).await;
+                None
+            }
+        }
+    }
+
+    async fn save_journal(&self, journal: &Journal) -> Result<()> {
+        // `with_extension` would collide with the index's own temp file
+        let mut tmp = self.journal_path.clone().into_os_string();
+        tmp.push(".tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(journal)?).await?;
+        tokio::fs::rename(&tmp, &self.journal_path).await?;
+        Ok(())
+    }
+}
+
+/// `path` under the `to` prefix, `None` when it is not under `from/` (custom `base_dir`)
+fn moved_path(path: &str, from: &str, to: &str) -> Option<String> {
+    let rest = path.strip_prefix(from)?.strip_prefix('/')?;
+    Some(format!("{to}/{rest}"))
+}
+
+async fn has_objects(operator: &Operator, dir: &str) -> opendal::Result<bool> {
+    let entries = operator
+        .list_with(&format!("{dir}/"))
+        .recursive(true)
+        .await?;
+    Ok(entries.iter().any(|e| !e.metadata().is_dir()))
+}
+
+/// Objects [`copy_verified`] would copy from `dir`
+async fn count_objects(operator: &Operator, dir: &str) -> opendal::Result<u64> {
+    let entries = operator
+        .list_with(&format!("{dir}/"))
+        .recursive(true)
+        .await?;
+    Ok(entries
+        .iter()
+        .filter(|e| !e.metadata().is_dir() && !storage::is_shared(e.path()))
+        .count() as u64)
+}
+
+/// Server-side copy every object under `src` to `dst`, checking each copy's size.
+///
+/// Returns how many objects were copied. Existing objects under `dst` are
+/// overwritten, so an interrupted copy is simply repeated.
+async fn copy_verified(operator: &Operator, src: &str, dst: &str) -> Result<usize> {
+    let src_prefix = format!("{src}/");
+    let mut copied = 0;
+    for entry in operator.list_with(&src_prefix).recursive(true).await? {
+        if entry.metadata().is_dir() || storage::is_shared(entry.path()) {
+            continue;
+        }
+        let Some(rel) = entry.path().strip_prefix(&src_prefix) else {
+            continue;
+        };
+        let target = format!("{dst}/{rel}");
+        operator.copy(entry.path(), &target).await?;
+        let (source, copy) = (
+            operator.stat(entry.path()).await?,
+            operator.stat(&target).await?,
+        );
+        anyhow::ensure!(
+            source.content_length() == copy.content_length(),
+            "copy of {} to {} has {} bytes, expected {}",
+            entry.path(),
+            target,
+            copy.content_length(),
+            source.content_length()
+        );
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::recorder::{DEFAULT_PRIORITY, RecordingIndexEntry, RecordingStatus};
+    use opendal::services::Fs;
+
+    fn entry(stream: &str, record: &str, record_dir: &str) -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            uuid: String::new(),
+            record: record.to_string(),
+            stream: stream.to_string(),
+            record_dir: record_dir.to_string(),
+            mpd_path: format!("{record_dir}/manifest.mpd"),
+            start_ts: 1,
+            end_ts: Some(2),
+            duration_ms: Some(1000),
+            status: RecordingStatus::Completed,
+            node_alias: None,
+            updated_at: 1,
+            note: None,
+            labels: Vec::new(),
+            continues: None,
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant: None,
+            size: None,
+            captions: Vec::new(),
+            trigger: None,
+            imported: false,
+            seq: 0,
+        }
+    }
+
+    async fn setup(dir: &std::path::Path) -> (Arc<StreamRenamer>, Arc<RecordingsIndex>, Operator) {
+        let mut builder = Fs::default();
+        builder.root(dir.join("storage").to_str().unwrap());
+        let op = Operator::new(builder).unwrap().finish();
+        let index = Arc::new(RecordingsIndex::load(dir.join("index.json")).await.unwrap());
+        let renamer = Arc::new(StreamRenamer::new(
+            index.clone(),
+            FailoverOperator::new(vec![(None, op.clone())]),
+            dir.join("index.json.rename"),
+            Some("edge-1".to_string()),
+            Tenancy::default(),
+        ));
+        (renamer, index, op)
+    }
+
+    fn request(copy_objects: bool) -> RenameStreamRequest {
+        RenameStreamRequest {
+            from: "cam".to_string(),
+            to: "lobby".to_string(),
+            copy_objects,
+        }
+    }
+
+    #[test]
+    fn test_moved_path() {
+        assert_eq!(
+            moved_path("cam/100/manifest.mpd", "cam", "lobby").as_deref(),
+            Some("lobby/100/manifest.mpd")
+        );
+        assert_eq!(moved_path("camera/100", "cam", "lobby"), None);
+        assert_eq!(moved_path("archive/cam/100", "cam", "lobby"), None);
+    }
+
+    #[tokio::test]
+    async fn test_moved_path_keeps_namespace() {
+        let dir = tempfile::tempdir().unwrap();
+        let (renamer, _, _) = setup(dir.path()).await;
+        assert_eq!(
+            renamer
+                .moved_path("edge-1/cam/100/manifest.mpd", "cam", "lobby")
+                .as_deref(),
+            Some("edge-1/lobby/100/manifest.mpd")
+        );
+        assert_eq!(
+            renamer.moved_path("cam/100", "cam", "lobby").as_deref(),
+            Some("lobby/100")
+        );
+        assert_eq!(renamer.moved_path("edge-2/cam/100", "cam", "lobby"), None);
+    }
+
+    #[tokio::test]
+    async fn test_rename_stays_in_tenant() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, index, op) = setup(dir.path()).await;
+        let tenancy = Tenancy::new(&crate::config::TenancyConfig {
+            tenant_separator: Some("-".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let renamer = StreamRenamer::new(
+            index,
+            FailoverOperator::new(vec![(None, op)]),
+            dir.path().join("index.json.rename"),
+            Some("edge-1".to_string()),
+            tenancy,
+        );
+        assert_eq!(
+            renamer
+                .moved_path(
+                    "acme/edge-1/acme-cam/100/manifest.mpd",
+                    "acme-cam",
+                    "acme-lobby"
+                )
+                .as_deref(),
+            Some("acme/edge-1/acme-lobby/100/manifest.mpd")
+        );
+
+        let req = RenameStreamRequest {
+            from: "acme-cam".to_string(),
+            to: "globex-cam".to_string(),
+            copy_objects: true,
+        };
+        assert!(matches!(
+            renamer.rename(req).await.unwrap(),
+            RenameOutcome::Conflict(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rename_index_only_keeps_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let (renamer, index, op) = setup(dir.path()).await;
+        index.upsert(entry("cam", "100", "cam/100")).await.unwrap();
+        op.write("cam/100/manifest.mpd", "mpd").await.unwrap();
+
+        let RenameOutcome::Renamed(resp) = renamer.rename(request(false)).await.unwrap() else {
+            panic!("unexpected conflict");
+        };
+        assert_eq!(resp.renamed, 1);
+        assert_eq!(resp.moved_objects, 0);
+
+        let renamed = index.entries_of("lobby").await;
+        assert_eq!(renamed.len(), 1);
+        assert_eq!(renamed[0].mpd_path, "cam/100/manifest.mpd");
+        assert!(index.entries_of("cam").await.is_empty());
+        assert!(op.exists("cam/100/manifest.mpd").await.unwrap());
+
+        // The old key stays gone after a reload
+        let reloaded = RecordingsIndex::load(dir.path().join("index.json"))
+            .await
+            .unwrap();
+        assert!(reloaded.entries_of("cam").await.is_empty());
+        assert!(!dir.path().join("index.json.rename").exists());
+    }
+
+    #[tokio::test]
+    async fn test_rename_copies_objects_and_deletes_old() {
+        let dir = tempfile::tempdir().unwrap();
+        let (renamer, index, op) = setup(dir.path()).await;
+        index.upsert(entry("cam", "100", "cam/100")).await.unwrap();
+        index
+            .upsert(entry("cam", "200", "archive/200"))
+            .await
+            .unwrap();
+        for path in ["cam/100/manifest.mpd", "cam/100/v_seg_0001.m4s"] {
+            op.write(path, "data").await.unwrap();
+        }
+        op.write("archive/200/manifest.mpd", "mpd").await.unwrap();
+
+        let DryRun::Affected(preview) = renamer.preview(&request(true)).await.unwrap() else {
+            panic!("unexpected conflict");
+        };
+        assert_eq!(preview.keys, ["cam/100", "cam/200"]);
+        // Objects outside `cam/` stay in place
+        assert_eq!(preview.objects, 2);
+        assert_eq!(index.entries_of("cam").await.len(), 2);
+
+        let RenameOutcome::Renamed(resp) = renamer.rename(request(true)).await.unwrap() else {
+            panic!("unexpected conflict");
+        };
+        assert_eq!(resp.renamed, 2);
+        assert_eq!(resp.moved_objects, 2);
+        assert_eq!(resp.kept_in_place, 1);
+
+        let renamed = index.entries_of("lobby").await;
+        assert_eq!(renamed[0].record_dir, "lobby/100");
+        assert_eq!(renamed[0].mpd_path, "lobby/100/manifest.mpd");
+        assert_eq!(renamed[1].record_dir, "archive/200");
+        assert!(op.exists("lobby/100/v_seg_0001.m4s").await.unwrap());
+        assert!(!op.exists("cam/100/v_seg_0001.m4s").await.unwrap());
+        assert!(op.exists("archive/200/manifest.mpd").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_rename_rejects_collisions() {
+        let dir = tempfile::tempdir().unwrap();
+        let (renamer, index, op) = setup(dir.path()).await;
+        index.upsert(entry("cam", "100", "cam/100")).await.unwrap();
+        index.upsert(entry("lobby", "100", "x/100")).await.unwrap();
+        assert!(matches!(
+            renamer.preview(&request(false)).await.unwrap(),
+            DryRun::Conflict(_)
+        ));
+        assert!(matches!(
+            renamer.rename(request(false)).await.unwrap(),
+            RenameOutcome::Conflict(_)
+        ));
+
+        // Leftover objects at the target prefix also collide when copying
+        let dir = tempfile::tempdir().unwrap();
+        let (renamer, index, op) = setup(dir.path()).await;
+        index.upsert(entry("cam", "100", "cam/100")).await.unwrap();
+        op.write("lobby/100/manifest.mpd", "other").await.unwrap();
+        assert!(matches!(
+            renamer.rename(request(true)).await.unwrap(),
+            RenameOutcome::Conflict(_)
+        ));
+        assert_eq!(index.entries_of("cam").await.len(), 1);
+        assert!(renamer.rename(request(false)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rename_resumes_pending_deletes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (renamer, index, op) = setup(dir.path()).await;
+        // Crashed after moving the entry, before deleting the old objects
+        index
+            .upsert(entry("lobby", "100", "lobby/100"))
+            .await
+            .unwrap();
+        op.write("cam/100/manifest.mpd", "mpd").await.unwrap();
+        op.write("lobby/100/manifest.mpd", "mpd").await.unwrap();
+        let journal = Journal {
+            from: "cam".to_string(),
+            to: "lobby".to_string(),
+            copy_objects: true,
+            pending_delete: vec!["cam/100".to_string()],
+            progress: RenameStreamResponse {
+                renamed: 1,
+                moved_objects: 1,
+                kept_in_place: 0,
+            },
+        };
+        renamer.save_journal(&journal).await.unwrap();
+
+        // A different rename waits for the unfinished one
+        let other = RenameStreamRequest {
+            from: "cam".to_string(),
+            to: "hall".to_string(),
+            copy_objects: true,
+        };
+        assert!(matches!(
+            renamer.rename(other).await.unwrap(),
+            RenameOutcome::Conflict(_)
+        ));
+
+        let RenameOutcome::Renamed(resp) = renamer.rename(request(true)).await.unwrap() else {
+            panic!("unexpected conflict");
+        };
+        assert_eq!(resp.renamed, 1);
+        assert!(!op.exists("cam/100/manifest.mpd").await.unwrap());
+        assert!(op.exists("lobby/100/manifest.mpd").await.unwrap());
+    }
+}
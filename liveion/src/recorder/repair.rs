@@ -0,0 +1,592 @@
+//! Rebuild the manifest of an interrupted recording from the segments actually stored.
+//!
+//! The segmenter rewrites `manifest.mpd` after each segment, so a crash or a missed
+//! shutdown deadline leaves it without the last segments even though their files made
+//! it to disk or storage. The repair lists the segments present, reads each one's span
+//! from its fragment headers and writes the timeline back, the rest of the manifest is
+//! kept as it was.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use api::recorder::{
+    RecorderEvent, RecorderEventKind, RecordingIndexEntry, RecordingStatus, RepairRecordingResponse,
+};
+use opendal::ErrorKind;
+use storage::FailoverOperator;
+use tokio::sync::broadcast;
+
+use super::index::RecordingsIndex;
+use super::probe::{probe_fragment, probe_init_segment};
+use super::verify::local_files;
+
+/// Outcome of [`Repairer::repair`]
+pub enum RepairOutcome {
+    Repaired(RepairRecordingResponse),
+    /// The manifest or an init segment is missing, the reason is kept on the entry
+    Unrepairable(String),
+    NotFound,
+    Conflict(String),
+}
+
+pub struct Repairer {
+    index: Arc<RecordingsIndex>,
+    operator: FailoverOperator,
+    /// Directories holding local copies of the objects, later roots win
+    local_roots: Vec<PathBuf>,
+}
+
+impl Repairer {
+    pub fn new(
+        index: Arc<RecordingsIndex>,
+        operator: FailoverOperator,
+        local_roots: Vec<PathBuf>,
+    ) -> Self {
+        Self {
+            index,
+            operator,
+            local_roots,
+        }
+    }
+
+    /// Repair every recording the index reports as interrupted
+    pub async fn run(self: Arc<Self>, mut events: broadcast::Receiver<RecorderEvent>) {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            if event.kind != RecorderEventKind::Status
+                || !matches!(event.entry.status, RecordingStatus::Interrupted)
+            {
+                continue;
+            }
+            let (stream, record) = (&event.entry.stream, &event.entry.record);
+            match self.repair(stream, record).await {
+                Ok(RepairOutcome::Repaired(resp)) => tracing::info!(
+                    "[recorder] repaired {}, {} segments added, {} ms",
+                    resp.mpd_path,
+                    resp.added_segments,
+                    resp.duration_ms
+                ),
+                Ok(RepairOutcome::Unrepairable(reason)) => {
+                    tracing::warn!("[recorder] {stream}/{record} is unrepairable: {reason}")
+                }
+                Ok(RepairOutcome::NotFound | RepairOutcome::Conflict(_)) => {}
+                Err(e) => tracing::error!("[recorder] repair of {stream}/{record} failed: {e:#}"),
+            }
+        }
+    }
+
+    /// Rewrite the manifest of `stream/record` from its segments and update
+    /// `duration_ms` in the index
+    pub async fn repair(&self, stream: &str, record: &str) -> Result<RepairOutcome> {
+        let Some(entry) = self.index.get(stream, record).await else {
+            return Ok(RepairOutcome::NotFound);
+        };
+        if matches!(entry.status, RecordingStatus::Active) {
+            return Ok(RepairOutcome::Conflict(format!(
+                "recording {} is still being written",
+                entry.key()
+            )));
+        }
+        match self.rebuild(&entry).await? {
+            Ok((mpd, resp)) => {
+                self.write(&entry.mpd_path, mpd.into_bytes()).await?;
+                self.index
+                    .record_repair(stream, record, Ok(resp.duration_ms))
+                    .await?;
+                Ok(RepairOutcome::Repaired(resp))
+            }
+            Err(reason) => {
+                self.index
+                    .record_repair(stream, record, Err(reason.clone()))
+                    .await?;
+                Ok(RepairOutcome::Unrepairable(reason))
+            }
+        }
+    }
+
+    /// The repaired manifest, `Err` with the reason when it cannot be repaired
+    async fn rebuild(
+        &self,
+        entry: &RecordingIndexEntry,
+    ) -> Result<std::result::Result<(String, RepairRecordingResponse), String>> {
+        let Some(mpd) = self.read(&entry.mpd_path).await? else {
+            return Ok(Err(format!("manifest {} is missing", entry.mpd_path)));
+        };
+        let mpd = String::from_utf8_lossy(&mpd).into_owned();
+        let templates = parse_templates(&mpd);
+        if templates.is_empty() {
+            return Ok(Err("manifest has no segment template".to_string()));
+        }
+        let names = self.segment_names(&entry.record_dir).await?;
+
+        let mut timelines = Vec::with_capacity(templates.len());
+        for template in &templates {
+            let init = storage::resolve_relative(&entry.record_dir, &template.initialization);
+            let Some(data) = self.read(&init).await? else {
+                return Ok(Err(format!("init segment {init} is missing")));
+            };
+            if let Err(e) = probe_init_segment(&data) {
+                return Ok(Err(format!("init segment {init} is unreadable: {e}")));
+            }
+
+            let numbers: BTreeSet<u64> = names
+                .iter()
+                .filter_map(|name| template.number_of(name))
+                .collect();
+            let mut timeline = Vec::new();
+            // `$Number$` addressing has no room for gaps, the timeline ends at the first
+            let mut number = template.start_number;
+            while numbers.contains(&number) {
+                let name = template.name_of(number);
+                let key = format!("{}/{}", entry.record_dir.trim_end_matches('/'), name);
+                let Some(data) = self.read(&key).await? else {
+                    break;
+                };
+                match probe_fragment(&data, template.timescale) {
+                    Ok(timing) => timeline.push((timing.start, timing.duration)),
+                    Err(e) => {
+                        tracing::warn!("[recorder] repair stops before {key}: {e}");
+                        break;
+                    }
+                }
+                number += 1;
+            }
+            timelines.push(timeline);
+        }
+
+        let duration_ms = templates
+            .iter()
+            .zip(&timelines)
+            .filter_map(|(template, timeline)| {
+                let (t, d) = timeline.last()?;
+                Some((t + d) * 1000 / template.timescale as u64)
+            })
+            .max()
+            .unwrap_or(0);
+        let segments: usize = timelines.iter().map(Vec::len).sum();
+        let listed: usize = templates.iter().map(|t| t.listed).sum();
+        let resp = RepairRecordingResponse {
+            stream: entry.stream.clone(),
+            record: entry.record.clone(),
+            mpd_path: entry.mpd_path.clone(),
+            segments,
+            added_segments: segments.saturating_sub(listed),
+            duration_ms: i32::try_from(duration_ms).unwrap_or(i32::MAX),
+        };
+        Ok(Ok((
+            rewrite(&mpd, &templates, &timelines, duration_ms),
+            resp,
+        )))
+    }
+
+    /// File names directly under `record_dir`, local copies and stored objects
+    async fn segment_names(&self, record_dir: &str) -> Result<BTreeSet<String>> {
+        let mut names: BTreeSet<String> = local_files(&self.local_roots, record_dir)
+            .await?
+            .into_keys()
+            .collect();
+        let prefix = format!("{}/", record_dir.trim_end_matches('/'));
+        let listed = match self.operator.current().list(&prefix).await {
+            Ok(listed) => listed,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        for object in listed {
+            if object.metadata().is_dir() {
+                continue;
+            }
+            if let Some(name) = object.path().strip_prefix(&prefix) {
+                names.insert(name.to_string());
+            }
+        }
+        names.retain(|name| !name.contains('/'));
+        Ok(names)
+    }
+
+    /// Contents of `key` from the newest local copy, then storage. `None` if absent
+    async fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        for root in self.local_roots.iter().rev() {
+            match tokio::fs::read(root.join(key)).await {
+                Ok(data) => return Ok(Some(data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        match self.operator.current().read(key).await {
+            Ok(data) => Ok(Some(data.to_vec())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store `data` and overwrite the local copies, so a queued upload of the stale
+    /// manifest does not undo the repair
+    async fn write(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        for root in &self.local_roots {
+            let path = root.join(key);
+            if tokio::fs::try_exists(&path).await? {
+                tokio::fs::write(&path, &data).await?;
+            }
+        }
+        self.operator.current().write(key, data).await?;
+        crate::recorder::invalidate(key).await;
+        Ok(())
+    }
+}
+
+/// One `<SegmentTemplate>` of a manifest, `start..end` byte range of the element
+struct Template {
+    start: usize,
+    end: usize,
+    timescale: u32,
+    start_number: u64,
+    initialization: String,
+    /// `media` before and after its `$Number$` field, and the field's zero padding
+    media: (String, String, usize),
+    /// Segments in the timeline, `r` repeats expanded
+    listed: usize,
+}
+
+impl Template {
+    fn name_of(&self, number: u64) -> String {
+        let (prefix, suffix, width) = &self.media;
+        format!("{prefix}{number:0width$}{suffix}")
+    }
+
+    fn number_of(&self, name: &str) -> Option<u64> {
+        let (prefix, suffix, _) = &self.media;
+        let digits = name
+            .strip_prefix(prefix.as_str())?
+            .strip_suffix(suffix.as_str())?;
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    }
+}
+
+fn attr<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let needle = format!(" {name}=\"");
+    let start = element.find(&needle)? + needle.len();
+    let len = element[start..].find('"')?;
+    Some(&element[start..start + len])
+}
+
+/// Split `v_seg_$Number%04d$.m4s` into `("v_seg_", ".m4s", 4)`
+fn split_media(media: &str) -> Option<(String, String, usize)> {
+    let begin = media.find("$Number")?;
+    let rest = &media[begin + "$Number".len()..];
+    let close = rest.find('$')?;
+    let width = rest[..close]
+        .strip_prefix("%0")
+        .and_then(|f| f.strip_suffix('d'))
+        .map_or(Some(0), |w| w.parse().ok())?;
+    Some((
+        media[..begin].to_string(),
+        rest[close + 1..].to_string(),
+        width,
+    ))
+}
+
+fn parse_templates(mpd: &str) -> Vec<Template> {
+    let mut templates = Vec::new();
+    let mut offset = 0;
+    while let Some(begin) = mpd[offset..].find("<SegmentTemplate") {
+        let start = offset + begin;
+        let Some(len) = mpd[start..].find("</SegmentTemplate>") else {
+            break;
+        };
+        let end = start + len + "</SegmentTemplate>".len();
+        offset = end;
+        let element = &mpd[start..end];
+        let (Some(initialization), Some(media)) = (
+            attr(element, "initialization"),
+            attr(element, "media").and_then(split_media),
+        ) else {
+            continue;
+        };
+        let listed = element
+            .match_indices("<S ")
+            .map(|(pos, _)| {
+                let s = &element[pos..];
+                let s = &s[..s.find('>').unwrap_or(s.len())];
+                1 + attr(s, "r")
+                    .and_then(|r| r.parse::<usize>().ok())
+                    .unwrap_or(0)
+            })
+            .sum();
+        templates.push(Template {
+            start,
+            end,
+            timescale: attr(element, "timescale")
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(1),
+            start_number: attr(element, "startNumber")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1),
+            initialization: initialization.to_string(),
+            media,
+            listed,
+        });
+    }
+    templates
+}
+
+/// `mpd` with each template's timeline replaced and the presentation duration updated
+fn rewrite(
+    mpd: &str,
+    templates: &[Template],
+    timelines: &[Vec<(u64, u64)>],
+    duration_ms: u64,
+) -> String {
+    let mut out = String::with_capacity(mpd.len() + timelines.len() * 64);
+    let mut offset = 0;
+    for (template, timeline) in templates.iter().zip(timelines) {
+        out.push_str(&mpd[offset..template.start]);
+        let element = &mpd[template.start..template.end];
+        let tag_end = element.find('>').map_or(element.len(), |i| i + 1);
+        out.push_str(&element[..tag_end]);
+        out.push_str("\n                    <SegmentTimeline>\n");
+        for (t, d) in timeline {
+            out.push_str(&format!(
+                "                        <S t=\"{t}\" d=\"{d}\" />\n"
+            ));
+        }
+        out.push_str("                    </SegmentTimeline>\n                </SegmentTemplate>");
+        offset = template.end;
+    }
+    out.push_str(&mpd[offset..]);
+
+    let duration = format!("PT{:.3}S", duration_ms as f64 / 1000.0);
+    match attr(&out, "mediaPresentationDuration") {
+        Some(old) => {
+            let needle = format!(" mediaPresentationDuration=\"{old}\"");
+            out.replacen(
+                &needle,
+                &format!(" mediaPresentationDuration=\"{duration}\""),
+                1,
+            )
+        }
+        None => out,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::fmp4::{Fmp4Writer, Mp4Sample};
+    use api::recorder::DEFAULT_PRIORITY;
+    use bytes::Bytes;
+    use opendal::Operator;
+    use opendal::services::Fs;
+
+    /// The manifest as the segmenter last wrote it, before the crash: one segment per
+    /// track of the three stored
+    const TRUNCATED_MPD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011"
+     profiles="urn:mpeg:dash:profile:isoff-live:2011"
+     type="static"
+     mediaPresentationDuration="PT2.000S"
+     maxSegmentDuration="PT2.000S"
+     minBufferTime="PT6.000S">
+    <Period id="0" start="PT0.0S">
+        <AdaptationSet id="0" contentType="video" startWithSAP="1" segmentAlignment="true">
+            <Representation id="0" mimeType="video/mp4" codecs="avc1.42E01E" bandwidth="0" width="640" height="360" sar="1:1">
+                <SegmentTemplate timescale="90000" initialization="v_init.m4s" media="v_seg_$Number%04d$.m4s" startNumber="1">
+                    <SegmentTimeline>
+                        <S t="0" d="180000" />
+                    </SegmentTimeline>
+                </SegmentTemplate>
+            </Representation>
+        </AdaptationSet>
+        <AdaptationSet id="1" contentType="audio" segmentAlignment="true">
+            <Representation id="1" mimeType="audio/mp4" codecs="opus" bandwidth="0" audioSamplingRate="48000" >
+                <SegmentTemplate timescale="48000" initialization="a_init.m4s" media="a_seg_$Number%04d$.m4s" startNumber="1">
+                    <SegmentTimeline>
+                        <S t="0" d="96000" />
+                    </SegmentTimeline>
+                </SegmentTemplate>
+            </Representation>
+        </AdaptationSet>
+    </Period>
+</MPD>
+"#;
+
+    const RECORD_DIR: &str = "cam/1700000000";
+
+    fn segment(
+        writer: &Fmp4Writer,
+        seq: u32,
+        base_time: u64,
+        count: u32,
+        duration: u32,
+    ) -> Vec<u8> {
+        let samples: Vec<Mp4Sample> = (0..count)
+            .map(|i| Mp4Sample {
+                duration,
+                is_sync: i == 0,
+                bytes: Bytes::from_static(&[0, 0, 0, 1, 0x65]),
+            })
+            .collect();
+        writer.build_fragment(seq, base_time, &samples)
+    }
+
+    /// Three complete two-second segments per track under `RECORD_DIR`
+    fn write_fixture(root: &std::path::Path) {
+        let dir = root.join(RECORD_DIR);
+        std::fs::create_dir_all(&dir).unwrap();
+        let video = Fmp4Writer::new(90_000, 1, 640, 360, "avc1.42E01E".to_string(), vec![]);
+        let audio = Fmp4Writer::new_audio(48_000, 2, 2, 48_000, "opus".to_string(), vec![]);
+        std::fs::write(dir.join("v_init.m4s"), video.build_init_segment()).unwrap();
+        std::fs::write(dir.join("a_init.m4s"), audio.build_init_segment()).unwrap();
+        for i in 0..3u32 {
+            std::fs::write(
+                dir.join(format!("v_seg_{:04}.m4s", i + 1)),
+                segment(&video, i + 1, i as u64 * 180_000, 60, 3_000),
+            )
+            .unwrap();
+            std::fs::write(
+                dir.join(format!("a_seg_{:04}.m4s", i + 1)),
+                segment(&audio, i + 1, i as u64 * 96_000, 100, 960),
+            )
+            .unwrap();
+        }
+        std::fs::write(dir.join("manifest.mpd"), TRUNCATED_MPD).unwrap();
+    }
+
+    async fn setup(root: &std::path::Path) -> (Arc<RecordingsIndex>, Repairer) {
+        let index = RecordingsIndex::load(root.join("index.json"))
+            .await
+            .unwrap();
+        index
+            .upsert(RecordingIndexEntry {
+                uuid: String::new(),
+                record: "1700000000".to_string(),
+                stream: "cam".to_string(),
+                record_dir: RECORD_DIR.to_string(),
+                mpd_path: format!("{RECORD_DIR}/manifest.mpd"),
+                start_ts: 1_700_000_000_000_000,
+                end_ts: None,
+                duration_ms: None,
+                status: RecordingStatus::Interrupted,
+                node_alias: None,
+                updated_at: 1,
+                note: None,
+                labels: Vec::new(),
+                continues: None,
+                media_info: Vec::new(),
+                retention_class: None,
+                trashed_at: None,
+                trashed_from: None,
+                repair_error: None,
+                source: None,
+                replicas: Vec::new(),
+                clock_skew_detected: false,
+                priority: DEFAULT_PRIORITY,
+                tenant: None,
+                size: None,
+                captions: Vec::new(),
+                trigger: None,
+                imported: false,
+                seq: 0,
+            })
+            .await
+            .unwrap();
+        let index = Arc::new(index);
+        let op = Operator::new(Fs::default().root(root.join("bucket").to_str().unwrap()))
+            .unwrap()
+            .finish();
+        let repairer = Repairer::new(index.clone(), op.into(), vec![root.to_path_buf()]);
+        (index, repairer)
+    }
+
+    #[tokio::test]
+    async fn test_repair_truncated_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture(dir.path());
+        let (index, repairer) = setup(dir.path()).await;
+
+        let RepairOutcome::Repaired(resp) = repairer.repair("cam", "1700000000").await.unwrap()
+        else {
+            panic!("recording not repaired");
+        };
+        assert_eq!(resp.segments, 6);
+        assert_eq!(resp.added_segments, 4);
+        assert_eq!(resp.duration_ms, 6_000);
+
+        // Both the local copy and the stored manifest carry every segment
+        for path in [
+            dir.path().join(RECORD_DIR).join("manifest.mpd"),
+            dir.path()
+                .join("bucket")
+                .join(RECORD_DIR)
+                .join("manifest.mpd"),
+        ] {
+            let mpd = std::fs::read_to_string(path).unwrap();
+            for s in [
+                r#"<S t="0" d="180000" />"#,
+                r#"<S t="180000" d="180000" />"#,
+                r#"<S t="360000" d="180000" />"#,
+                r#"<S t="192000" d="96000" />"#,
+            ] {
+                assert!(mpd.contains(s), "{s} missing from {mpd}");
+            }
+            assert!(mpd.contains(r#"mediaPresentationDuration="PT6.000S""#));
+            assert!(mpd.contains(r#"codecs="avc1.42E01E""#));
+            assert_eq!(mpd.matches("<SegmentTimeline>").count(), 2);
+        }
+
+        let entry = index.get("cam", "1700000000").await.unwrap();
+        assert_eq!(entry.duration_ms, Some(6_000));
+        assert!(entry.repair_error.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_repair_stops_at_truncated_segment() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture(dir.path());
+        // The crash hit while the last video segment was written
+        let last = dir.path().join(RECORD_DIR).join("v_seg_0003.m4s");
+        let data = std::fs::read(&last).unwrap();
+        std::fs::write(&last, &data[..data.len() / 2]).unwrap();
+        let (_, repairer) = setup(dir.path()).await;
+
+        let RepairOutcome::Repaired(resp) = repairer.repair("cam", "1700000000").await.unwrap()
+        else {
+            panic!("recording not repaired");
+        };
+        assert_eq!(resp.segments, 5);
+        let mpd =
+            std::fs::read_to_string(dir.path().join(RECORD_DIR).join("manifest.mpd")).unwrap();
+        assert!(!mpd.contains(r#"<S t="360000""#));
+    }
+
+    #[tokio::test]
+    async fn test_missing_init_is_unrepairable() {
+        let dir = tempfile::tempdir().unwrap();
+        write_fixture(dir.path());
+        std::fs::remove_file(dir.path().join(RECORD_DIR).join("a_init.m4s")).unwrap();
+        let (index, repairer) = setup(dir.path()).await;
+
+        let RepairOutcome::Unrepairable(reason) =
+            repairer.repair("cam", "1700000000").await.unwrap()
+        else {
+            panic!("recording repaired without its init segment");
+        };
+        assert_eq!(reason, "init segment cam/1700000000/a_init.m4s is missing");
+        let entry = index.get("cam", "1700000000").await.unwrap();
+        assert_eq!(entry.repair_error.as_deref(), Some(reason.as_str()));
+        assert_eq!(entry.duration_ms, None);
+        // The manifest is left as it was
+        let mpd =
+            std::fs::read_to_string(dir.path().join(RECORD_DIR).join("manifest.mpd")).unwrap();
+        assert_eq!(mpd, TRUNCATED_MPD);
+    }
+}
@@ -0,0 +1,354 @@
+//! `recorder.republish`: a stream published again before its last recording is done.
+//!
+//! Ending a recording flushes its last segments and manifest and updates the index,
+//! which takes as long as storage does, so it runs off the event loop. Meanwhile the
+//! stream is finalizing; what its next publisher gets depends on the stream's mode:
+//!
+//! - `queue` starts the new recording once every finalizing of the stream is done
+//! - `force_new` starts it right away, next to the ones being finalized
+//! - `resume` parks a recording whose publisher left instead of finalizing it. A
+//!   publisher back within `resume_max_gap_seconds` continues it in a new Period,
+//!   otherwise it is finalized as it was when the publisher left
+//!
+//! Record ids of a stream only ever grow, a recording started within the second of the
+//! previous one never shares its directory.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::config::{RecorderConfig, RepublishMode};
+
+/// `recorder.republish` and the rules' overrides
+#[derive(Debug, Default)]
+pub struct RepublishPolicy {
+    mode: RepublishMode,
+    rules: Vec<(Vec<String>, RepublishMode)>,
+    max_gap: Duration,
+}
+
+impl RepublishPolicy {
+    pub fn from_config(cfg: &RecorderConfig) -> Self {
+        Self {
+            mode: cfg.republish,
+            rules: cfg
+                .rules
+                .iter()
+                .filter_map(|rule| Some((rule.streams.clone(), rule.republish?)))
+                .collect(),
+            max_gap: Duration::from_secs(cfg.resume_max_gap_seconds),
+        }
+    }
+
+    /// First matching rule with a mode, then `recorder.republish`
+    pub fn mode_for(&self, stream: &str) -> RepublishMode {
+        self.rules
+            .iter()
+            .find(|(patterns, _)| super::should_record(patterns, stream))
+            .map_or(self.mode, |(_, mode)| *mode)
+    }
+
+    /// Longest absence of a publisher a parked recording waits out
+    pub fn max_gap(&self) -> Duration {
+        self.max_gap
+    }
+}
+
+/// What a stream's new publisher gets
+pub enum Decision<P> {
+    /// Record as usual
+    Start,
+    /// Record once the stream's recordings are finalized
+    Wait(Finalized),
+    /// Continue this parked recording
+    Resume(P),
+}
+
+/// Resolves once no recording of a stream is being finalized
+pub struct Finalized(watch::Receiver<usize>);
+
+impl Finalized {
+    pub async fn wait(mut self) {
+        // A dropped sender went with the stream's last finalizing
+        let _ = self.0.wait_for(|in_flight| *in_flight == 0).await;
+    }
+}
+
+struct Parked<P> {
+    recording: P,
+    /// Monotonic time the recording was parked at, it also tells parkings apart
+    since: Duration,
+}
+
+/// Streams between recordings: being finalized, or parked for their publisher
+pub struct Sessions<P> {
+    /// Recordings of the stream being finalized
+    finalizing: HashMap<String, watch::Sender<usize>>,
+    parked: HashMap<String, Parked<P>>,
+    /// Highest record id handed out per stream, kept while not in the past
+    last_ids: HashMap<String, i64>,
+}
+
+impl<P> Default for Sessions<P> {
+    fn default() -> Self {
+        Self {
+            finalizing: HashMap::new(),
+            parked: HashMap::new(),
+            last_ids: HashMap::new(),
+        }
+    }
+}
+
+impl<P> Sessions<P> {
+    /// Decide for a new publisher of `stream` at monotonic `now`. A parked recording
+    /// it resumes is taken out.
+    pub fn republish(
+        &mut self,
+        stream: &str,
+        mode: RepublishMode,
+        now: Duration,
+        max_gap: Duration,
+    ) -> Decision<P> {
+        if mode == RepublishMode::Resume
+            && self
+                .parked
+                .get(stream)
+                .is_some_and(|parked| now.saturating_sub(parked.since) <= max_gap)
+            && let Some(parked) = self.parked.remove(stream)
+        {
+            return Decision::Resume(parked.recording);
+        }
+        match self.finalizing.get(stream) {
+            Some(tx) if mode != RepublishMode::ForceNew && *tx.borrow() > 0 => {
+                Decision::Wait(Finalized(tx.subscribe()))
+            }
+            _ => Decision::Start,
+        }
+    }
+
+    /// A recording of `stream` is being finalized from now on
+    pub fn finalizing(&mut self, stream: &str) {
+        self.finalizing
+            .entry(stream.to_string())
+            .or_insert_with(|| watch::channel(0).0)
+            .send_modify(|in_flight| *in_flight += 1);
+    }
+
+    /// A recording of `stream` counted by [`Self::finalizing`] is done
+    pub fn finalized(&mut self, stream: &str) {
+        let Some(tx) = self.finalizing.get(stream) else {
+            return;
+        };
+        tx.send_modify(|in_flight| *in_flight = in_flight.saturating_sub(1));
+        if *tx.borrow() == 0 {
+            self.finalizing.remove(stream);
+        }
+    }
+
+    /// Keep `recording` for the next publisher of `stream`, returns the one it displaces
+    pub fn park(&mut self, stream: &str, recording: P, now: Duration) -> Option<P> {
+        self.parked
+            .insert(
+                stream.to_string(),
+                Parked {
+                    recording,
+                    since: now,
+                },
+            )
+            .map(|parked| parked.recording)
+    }
+
+    pub fn unpark(&mut self, stream: &str) -> Option<P> {
+        self.parked.remove(stream).map(|parked| parked.recording)
+    }
+
+    /// Take the recording of `stream` parked at `since`, once its wait is over
+    pub fn expire(&mut self, stream: &str, since: Duration) -> Option<P> {
+        if self.parked.get(stream)?.since != since {
+            return None;
+        }
+        self.unpark(stream)
+    }
+
+    pub fn drain_parked(&mut self) -> Vec<(String, P)> {
+        self.parked
+            .drain()
+            .map(|(stream, parked)| (stream, parked.recording))
+            .collect()
+    }
+
+    /// Record id of a new recording of `stream` started at unix second `now`, past every
+    /// id handed out before
+    pub fn next_record_id(&mut self, stream: &str, now: i64) -> i64 {
+        let id = self
+            .last_ids
+            .get(stream)
+            .map_or(now, |last| now.max(last + 1));
+        self.issued(stream, id, now);
+        id
+    }
+
+    /// Note an id handed out for `stream` elsewhere, by a split
+    pub fn issued(&mut self, stream: &str, id: i64, now: i64) {
+        self.last_ids.retain(|_, last| *last >= now);
+        let last = self.last_ids.entry(stream.to_string()).or_insert(id);
+        *last = (*last).max(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RecordingRule;
+
+    const NOW: i64 = 1_700_000_000;
+    const GAP: Duration = Duration::from_secs(30);
+
+    fn assert_increasing(ids: &[i64]) {
+        assert!(ids.windows(2).all(|w| w[0] < w[1]), "{ids:?}");
+    }
+
+    /// A publisher flapping within one second: every republish waits for the
+    /// recording before it, and none reuses its record id
+    #[tokio::test]
+    async fn test_queue_reconnect_loop() {
+        let mut sessions = Sessions::<()>::default();
+        let mut ids = Vec::new();
+        for _ in 0..50 {
+            assert!(matches!(
+                sessions.republish("cam", RepublishMode::Queue, Duration::ZERO, GAP),
+                Decision::Start
+            ));
+            ids.push(sessions.next_record_id("cam", NOW));
+            // The publisher leaves, its recording is finalized off the event loop
+            sessions.finalizing("cam");
+            let Decision::Wait(done) =
+                sessions.republish("cam", RepublishMode::Queue, Duration::ZERO, GAP)
+            else {
+                panic!("republish did not wait for the finalizing");
+            };
+            let waiter = tokio::spawn(done.wait());
+            tokio::task::yield_now().await;
+            assert!(!waiter.is_finished());
+            sessions.finalized("cam");
+            waiter.await.unwrap();
+        }
+        assert_increasing(&ids);
+        assert!(sessions.finalizing.is_empty());
+        // Other streams are not held up
+        assert!(matches!(
+            sessions.republish("gate", RepublishMode::Queue, Duration::ZERO, GAP),
+            Decision::Start
+        ));
+    }
+
+    /// Republishes start right away while earlier recordings are still finalizing
+    #[tokio::test]
+    async fn test_force_new_reconnect_loop() {
+        let mut sessions = Sessions::<()>::default();
+        let mut ids = Vec::new();
+        for _ in 0..50 {
+            assert!(matches!(
+                sessions.republish("cam", RepublishMode::ForceNew, Duration::ZERO, GAP),
+                Decision::Start
+            ));
+            ids.push(sessions.next_record_id("cam", NOW));
+            sessions.finalizing("cam");
+        }
+        assert_increasing(&ids);
+        assert_eq!(*sessions.finalizing["cam"].borrow(), 50);
+
+        // A queued stream republished meanwhile waits for all of them
+        let Decision::Wait(done) =
+            sessions.republish("cam", RepublishMode::Queue, Duration::ZERO, GAP)
+        else {
+            panic!("republish did not wait for the finalizing");
+        };
+        let waiter = tokio::spawn(done.wait());
+        for _ in 0..49 {
+            sessions.finalized("cam");
+        }
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+        sessions.finalized("cam");
+        waiter.await.unwrap();
+        assert!(sessions.finalizing.is_empty());
+
+        // The clock moving on lets ids follow it again
+        assert_eq!(sessions.next_record_id("cam", NOW + 3_600), NOW + 3_600);
+    }
+
+    /// Each return within the gap continues the same recording, a longer absence
+    /// leaves it to expire and starts a new one
+    #[tokio::test]
+    async fn test_resume_reconnect_loop() {
+        let mut sessions = Sessions::<i64>::default();
+        let record = sessions.next_record_id("cam", NOW);
+        let mut now = Duration::ZERO;
+        for _ in 0..50 {
+            // Parked while the segmenter is flushed, a republish meanwhile waits
+            sessions.finalizing("cam");
+            assert!(matches!(
+                sessions.republish("cam", RepublishMode::Resume, now, GAP),
+                Decision::Wait(_)
+            ));
+            assert!(sessions.park("cam", record, now).is_none());
+            sessions.finalized("cam");
+
+            now += Duration::from_millis(200);
+            let Decision::Resume(resumed) =
+                sessions.republish("cam", RepublishMode::Resume, now, GAP)
+            else {
+                panic!("republish within the gap did not resume");
+            };
+            assert_eq!(resumed, record);
+        }
+
+        sessions.park("cam", record, now);
+        let parked_at = now;
+        now += GAP + Duration::from_secs(1);
+        assert!(matches!(
+            sessions.republish("cam", RepublishMode::Resume, now, GAP),
+            Decision::Start
+        ));
+        assert!(sessions.next_record_id("cam", NOW) > record);
+        // Only the timer of this parking finalizes it
+        assert_eq!(sessions.expire("cam", Duration::ZERO), None);
+        assert_eq!(sessions.expire("cam", parked_at), Some(record));
+        assert!(sessions.drain_parked().is_empty());
+    }
+
+    #[test]
+    fn test_mode_per_rule() {
+        let cfg = RecorderConfig {
+            rules: vec![
+                RecordingRule {
+                    streams: vec!["lobby-*".to_string()],
+                    max_recording_duration_minutes: Some(60),
+                    retention_class: None,
+                    priority: None,
+                    republish: None,
+                },
+                RecordingRule {
+                    streams: vec!["lobby-*".to_string(), "gate".to_string()],
+                    max_recording_duration_minutes: None,
+                    retention_class: None,
+                    priority: None,
+                    republish: Some(RepublishMode::Resume),
+                },
+            ],
+            republish: RepublishMode::ForceNew,
+            ..Default::default()
+        };
+        let policy = RepublishPolicy::from_config(&cfg);
+        assert_eq!(policy.mode_for("lobby-1"), RepublishMode::Resume);
+        assert_eq!(policy.mode_for("gate"), RepublishMode::Resume);
+        assert_eq!(policy.mode_for("other"), RepublishMode::ForceNew);
+        assert_eq!(policy.max_gap(), Duration::from_secs(30));
+        assert_eq!(
+            RepublishPolicy::from_config(&RecorderConfig::default()).mode_for("other"),
+            RepublishMode::Queue
+        );
+    }
+}
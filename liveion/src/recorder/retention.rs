@@ -0,0 +1,850 @@
+//! Retention classes: the object tags storage lifecycle rules match on, and the
+//! optional sweep deleting recordings past their class or over the byte quota from
+//! storage and the index.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, ensure};
+use api::recorder::{
+    AuditOperation, AuditOutcome, DEFAULT_PRIORITY, DryRunResponse, RETENTION_TAG,
+    RecordingIndexEntry, RecordingStatus, RetentionClass,
+};
+use chrono::Utc;
+use opendal::Operator;
+use reqwest::Client;
+use storage::{FailoverOperator, PresignedRequest, S3Signer, StorageConfig};
+use tokio::time::{self, MissedTickBehavior};
+
+use super::audit::{self, AuditLog, DryRun, SYSTEM_RETENTION};
+use super::index::{RecordingsIndex, TrashUpdate};
+use super::segmenter::PENDING_WRITES;
+use super::uploader::UploadManager;
+use crate::config::RecorderConfig;
+
+/// Lifetime of the presigned tagging requests
+const TAGGING_TTL: Duration = Duration::from_secs(300);
+
+/// How often the trash is checked for recordings past the trash retention
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Resolves the class and priority of new recordings from the auto-record rules
+#[derive(Default)]
+pub struct RetentionPolicy {
+    rules: Vec<(Vec<String>, RetentionClass)>,
+    default_class: Option<RetentionClass>,
+    priorities: Vec<(Vec<String>, u8)>,
+}
+
+impl RetentionPolicy {
+    pub fn from_config(cfg: &RecorderConfig) -> Self {
+        Self {
+            rules: cfg
+                .rules
+                .iter()
+                .filter_map(|rule| Some((rule.streams.clone(), rule.retention_class.clone()?)))
+                .collect(),
+            default_class: cfg.retention.default_class.clone(),
+            priorities: cfg
+                .rules
+                .iter()
+                .filter_map(|rule| Some((rule.streams.clone(), rule.priority?)))
+                .collect(),
+        }
+    }
+
+    /// First matching rule with a class, then `recorder.retention.default_class`
+    pub fn class_for(&self, stream: &str) -> Option<RetentionClass> {
+        self.rules
+            .iter()
+            .find(|(patterns, _)| super::should_record(patterns, stream))
+            .map(|(_, class)| class.clone())
+            .or_else(|| self.default_class.clone())
+    }
+
+    /// First matching rule with a priority, then [`DEFAULT_PRIORITY`]
+    pub fn priority_for(&self, stream: &str) -> u8 {
+        self.priorities
+            .iter()
+            .find(|(patterns, _)| super::should_record(patterns, stream))
+            .map_or(DEFAULT_PRIORITY, |(_, priority)| *priority)
+    }
+}
+
+/// Tags recording objects with their class and sweeps expired recordings
+pub struct Retention {
+    index: Arc<RecordingsIndex>,
+    operator: FailoverOperator,
+    storage: StorageConfig,
+    uploader: Option<Arc<UploadManager>>,
+    client: Client,
+    /// Byte quota of the sweep, 0 disables it
+    max_bytes: u64,
+    audit: Option<Arc<AuditLog>>,
+}
+
+impl Retention {
+    pub fn new(
+        index: Arc<RecordingsIndex>,
+        operator: FailoverOperator,
+        storage: StorageConfig,
+        uploader: Option<Arc<UploadManager>>,
+    ) -> Self {
+        Self {
+            index,
+            operator,
+            storage,
+            uploader,
+            client: Client::new(),
+            max_bytes: 0,
+            audit: None,
+        }
+    }
+
+    /// Let the sweep also delete finished recordings while storage holds more than
+    /// `max_bytes` of them
+    pub fn with_quota(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Record every deletion in `audit`
+    pub fn with_audit(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Bring the tags of a finished recording in line with its indexed class.
+    ///
+    /// Uploads carry their tags already, so only direct writes and recordings whose class
+    /// changed while they were written (`tagged_with` differs) need a tagging pass.
+    pub async fn on_finished(
+        self: &Arc<Self>,
+        record_dir: &str,
+        tagged_with: Option<&RetentionClass>,
+    ) {
+        let Some(class) = self
+            .index
+            .find_by_dir(record_dir)
+            .await
+            .and_then(|entry| entry.retention_class)
+        else {
+            return;
+        };
+        if self.uploader.is_some() && tagged_with == Some(&class) {
+            return;
+        }
+        self.reclassify(record_dir, class).await;
+    }
+
+    /// Re-tag a recording moved to `class`: queued uploads switch to the new tags and
+    /// the objects already stored are tagged in the background
+    pub async fn reclassify(self: &Arc<Self>, record_dir: &str, class: RetentionClass) {
+        if let Some(uploader) = self.uploader.as_ref()
+            && let Err(e) = uploader.retag_pending(record_dir, &class.tagging()).await
+        {
+            tracing::warn!(
+                "[retention] failed to retag queued uploads of {}: {:#}",
+                record_dir,
+                e
+            );
+        }
+        self.spawn_tag(record_dir.to_string(), class);
+    }
+
+    fn spawn_tag(self: &Arc<Self>, record_dir: String, class: RetentionClass) {
+        // Filesystem storage has no tags, the sweep is all there is
+        if matches!(self.storage, StorageConfig::Fs { .. }) && self.uploader.is_none() {
+            return;
+        }
+        let this = self.clone();
+        tokio::spawn(async move {
+            match this.tag(&record_dir, &class).await {
+                Ok(tagged) => tracing::info!(
+                    "[retention] tagged {} objects of {} with {}",
+                    tagged,
+                    record_dir,
+                    class.tagging()
+                ),
+                Err(e) => tracing::warn!(
+                    "[retention] tagging {} with {} failed: {:#}",
+                    record_dir,
+                    class.tagging(),
+                    e
+                ),
+            }
+        });
+    }
+
+    /// Replace the tag set of every object under `record_dir`, shared objects excepted
+    async fn tag(&self, record_dir: &str, class: &RetentionClass) -> Result<usize> {
+        // Direct writes of the last segments may still be in flight
+        PENDING_WRITES.wait_idle().await;
+        let body = storage::tagging_document(&[(RETENTION_TAG, class.as_str())]);
+        let operator = self.operator.current();
+        let mut tagged = 0;
+        for entry in operator
+            .list_with(&format!("{}/", record_dir.trim_end_matches('/')))
+            .recursive(true)
+            .await?
+        {
+            if entry.metadata().is_dir() || storage::is_shared(entry.path()) {
+                continue;
+            }
+            let signed = self.presign_tagging(entry.path()).await?;
+            let mut req = self.client.put(&signed.url).body(body.clone());
+            for (name, value) in &signed.headers {
+                req = req.header(name, value);
+            }
+            let resp = req.send().await.map_err(reqwest::Error::without_url)?;
+            ensure!(
+                resp.status().is_success(),
+                "tagging {} failed: {}",
+                entry.path(),
+                resp.status()
+            );
+            tagged += 1;
+        }
+        Ok(tagged)
+    }
+
+    /// Sign locally with static credentials, otherwise have liveman sign for the uploader
+    async fn presign_tagging(&self, path: &str) -> Result<PresignedRequest> {
+        let endpoint = self.operator.selected_endpoint();
+        if let Some(signer) = S3Signer::from_config(&self.storage, endpoint.as_deref()) {
+            return Ok(signer.presign_put_tagging(path, TAGGING_TTL));
+        }
+        match self.uploader.as_ref() {
+            Some(uploader) => uploader.presign_tagging(path).await,
+            None => anyhow::bail!("object tagging needs S3 storage with static credentials"),
+        }
+    }
+
+    /// Delete finished recordings past their class, then those over the byte quota.
+    /// Returns how many were removed.
+    pub async fn sweep(&self) -> Result<usize> {
+        let mut removed = 0;
+        for entry in self.index.expired(Utc::now().timestamp_micros()).await? {
+            let (deleted, _) = self
+                .purge_as(&entry, AuditOperation::Expire, SYSTEM_RETENTION)
+                .await?;
+            removed += 1;
+            tracing::info!(
+                "[retention] {} expired ({}), deleted {} objects",
+                entry.key(),
+                entry.retention_class.as_ref().map_or("", |c| c.as_str()),
+                deleted
+            );
+        }
+        if self.max_bytes > 0 {
+            removed += self.enforce_quota().await?;
+        }
+        Ok(removed)
+    }
+
+    /// Delete finished recordings, lowest priority and oldest first, until the indexed
+    /// recordings fit in `max_bytes`. Active recordings count but are never deleted.
+    async fn enforce_quota(&self) -> Result<usize> {
+        let operator = self.operator.current();
+        let mut recordings = Vec::new();
+        let mut total = 0u64;
+        for entry in self.index.in_deletion_order().await? {
+            let (_, bytes) = recording_usage(&operator, &entry.record_dir).await?;
+            total = total.saturating_add(bytes);
+            recordings.push((entry, bytes));
+        }
+        let mut removed = 0;
+        for (entry, bytes) in recordings {
+            if total <= self.max_bytes {
+                break;
+            }
+            if matches!(entry.status, RecordingStatus::Active) {
+                continue;
+            }
+            if let Some(uploader) = self.uploader.as_ref()
+                && uploader.pending_under(&entry.record_dir).await > 0
+            {
+                continue;
+            }
+            let (deleted, _) = self
+                .purge_as(&entry, AuditOperation::Quota, SYSTEM_RETENTION)
+                .await?;
+            total = total.saturating_sub(bytes);
+            removed += 1;
+            tracing::info!(
+                "[retention] {} (priority {}) deleted over the {} byte quota, {} objects",
+                entry.key(),
+                entry.priority,
+                self.max_bytes,
+                deleted
+            );
+        }
+        Ok(removed)
+    }
+
+    /// Delete the objects and the entry of a finished recording without going through
+    /// the trash, on behalf of `actor`. With `if_match`, only if the entry's `updated_at`
+    /// is that one when the objects are about to be deleted
+    pub async fn purge_recording(
+        &self,
+        stream: &str,
+        record: &str,
+        actor: &str,
+        if_match: Option<i64>,
+    ) -> Result<TrashUpdate> {
+        let Some(entry) = self.index.get(stream, record).await else {
+            return Ok(TrashUpdate::NotFound);
+        };
+        if if_match.is_some_and(|version| version != entry.updated_at) {
+            let mut rejected = audit::record(AuditOperation::Purge, actor, vec![entry.key()]);
+            rejected.outcome = AuditOutcome::Rejected;
+            rejected.reason = Some(format!("version {} changed", entry.updated_at));
+            self.audit(rejected).await;
+            return Ok(TrashUpdate::Stale(entry));
+        }
+        if let Some(reason) = self.purge_conflict(&entry).await {
+            let mut rejected = audit::record(AuditOperation::Purge, actor, vec![entry.key()]);
+            rejected.outcome = AuditOutcome::Rejected;
+            rejected.reason = Some(reason.clone());
+            self.audit(rejected).await;
+            return Ok(TrashUpdate::Conflict(reason));
+        }
+        let (deleted, _) = self.purge_as(&entry, AuditOperation::Purge, actor).await?;
+        tracing::info!(
+            "[retention] {} purged, deleted {} objects",
+            entry.key(),
+            deleted
+        );
+        Ok(TrashUpdate::Updated(entry))
+    }
+
+    /// What [`Self::purge_recording`] would delete, without deleting it
+    pub async fn preview_purge(&self, stream: &str, record: &str) -> Result<DryRun> {
+        let Some(entry) = self.index.get(stream, record).await else {
+            return Ok(DryRun::NotFound(format!(
+                "recording {stream}/{record} not found"
+            )));
+        };
+        if let Some(reason) = self.purge_conflict(&entry).await {
+            return Ok(DryRun::Conflict(reason));
+        }
+        let (objects, bytes) = recording_usage(&self.operator.current(), &entry.record_dir).await?;
+        Ok(DryRun::Affected(DryRunResponse {
+            operation: AuditOperation::Purge,
+            keys: vec![entry.key()],
+            objects,
+            bytes,
+        }))
+    }
+
+    /// Why a recording can't be purged now
+    async fn purge_conflict(&self, entry: &RecordingIndexEntry) -> Option<String> {
+        if matches!(entry.status, RecordingStatus::Active) {
+            return Some(format!("recording {} is still being written", entry.key()));
+        }
+        if let Some(uploader) = self.uploader.as_ref()
+            && uploader.pending_under(&entry.record_dir).await > 0
+        {
+            // Queued uploads would bring the objects back
+            return Some(format!("uploads of {} are still queued", entry.key()));
+        }
+        None
+    }
+
+    /// Purge recordings trashed more than `retention` ago, returns how many were removed
+    pub async fn empty_trash(&self, retention: Duration) -> Result<usize> {
+        let before = Utc::now().timestamp_micros()
+            - i64::try_from(retention.as_micros()).unwrap_or(i64::MAX);
+        let mut purged = 0;
+        for entry in self.index.trashed_before(before).await {
+            if let Some(uploader) = self.uploader.as_ref()
+                && uploader.pending_under(&entry.record_dir).await > 0
+            {
+                continue;
+            }
+            let (deleted, _) = self
+                .purge_as(&entry, AuditOperation::EmptyTrash, SYSTEM_RETENTION)
+                .await?;
+            purged += 1;
+            tracing::info!(
+                "[retention] {} emptied from the trash, deleted {} objects",
+                entry.key(),
+                deleted
+            );
+        }
+        Ok(purged)
+    }
+
+    /// Delete a recording's objects and entry, returns the objects deleted and their
+    /// bytes
+    async fn purge(&self, entry: &RecordingIndexEntry) -> Result<(usize, u64)> {
+        let operator = self.operator.current();
+        let (_, bytes) = recording_usage(&operator, &entry.record_dir).await?;
+        let deleted = storage::delete_prefix_with(
+            &operator,
+            &entry.record_dir,
+            &storage::DeleteBatchOptions::default(),
+            |progress| {
+                // Callers log the outcome, only long deletions report how far they got
+                let handled = progress.deleted + progress.failed;
+                if handled < progress.total {
+                    tracing::info!(
+                        "[retention] {} deleting objects: {}/{}",
+                        entry.key(),
+                        handled,
+                        progress.total
+                    );
+                }
+            },
+        )
+        .await?;
+        self.index.remove(&entry.stream, &entry.record).await?;
+        Ok((deleted, bytes))
+    }
+
+    /// [`Self::purge`] recorded in the audit log as `operation` by `actor`
+    async fn purge_as(
+        &self,
+        entry: &RecordingIndexEntry,
+        operation: AuditOperation,
+        actor: &str,
+    ) -> Result<(usize, u64)> {
+        let purged = self.purge(entry).await;
+        let mut record = audit::record(operation, actor, vec![entry.key()]);
+        match &purged {
+            Ok((objects, bytes)) => {
+                record.objects = *objects as u64;
+                record.bytes = *bytes;
+            }
+            Err(e) => {
+                record.outcome = AuditOutcome::Failed;
+                record.reason = Some(format!("{e:#}"));
+            }
+        }
+        self.audit(record).await;
+        purged
+    }
+
+    async fn audit(&self, record: api::recorder::AuditRecord) {
+        if let Some(audit) = self.audit.as_ref() {
+            audit.log(record).await;
+        }
+    }
+
+    pub async fn trash_loop(self: Arc<Self>, retention: Duration) {
+        let mut ticker = time::interval(TRASH_PURGE_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.empty_trash(retention).await {
+                tracing::warn!("[retention] emptying the trash failed: {:#}", e);
+            }
+        }
+    }
+
+    pub async fn sweep_loop(self: Arc<Self>, interval: Duration) {
+        let mut ticker = time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.sweep().await {
+                tracing::warn!("[retention] sweep failed: {:#}", e);
+            }
+        }
+    }
+}
+
+/// Objects and bytes stored under `record_dir`, shared objects excepted
+async fn recording_usage(operator: &Operator, record_dir: &str) -> Result<(u64, u64)> {
+    let mut objects = 0;
+    let mut total = 0;
+    for entry in operator
+        .list_with(&format!("{}/", record_dir.trim_end_matches('/')))
+        .recursive(true)
+        .await?
+    {
+        if entry.metadata().is_dir() || storage::is_shared(entry.path()) {
+            continue;
+        }
+        let mut size = entry.metadata().content_length();
+        // Not every backend reports sizes when listing
+        if size == 0 {
+            size = operator.stat(entry.path()).await?.content_length();
+        }
+        objects += 1;
+        total += size;
+    }
+    Ok((objects, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RecordingRule, RetentionConfig};
+
+    #[test]
+    fn test_class_for_prefers_rules() {
+        let cfg = RecorderConfig {
+            rules: vec![
+                RecordingRule {
+                    streams: vec!["lobby-*".to_string()],
+                    max_recording_duration_minutes: Some(60),
+                    retention_class: None,
+                    priority: Some(20),
+                    republish: None,
+                },
+                RecordingRule {
+                    streams: vec!["lobby-*".to_string(), "gate".to_string()],
+                    max_recording_duration_minutes: None,
+                    retention_class: Some("1y".parse().unwrap()),
+                    priority: Some(200),
+                    republish: None,
+                },
+            ],
+            retention: RetentionConfig {
+                default_class: Some("30d".parse().unwrap()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let policy = RetentionPolicy::from_config(&cfg);
+        assert_eq!(policy.class_for("lobby-1").unwrap().as_str(), "1y");
+        assert_eq!(policy.class_for("gate").unwrap().as_str(), "1y");
+        assert_eq!(policy.class_for("cam").unwrap().as_str(), "30d");
+        assert!(RetentionPolicy::default().class_for("cam").is_none());
+
+        assert_eq!(policy.priority_for("lobby-1"), 20);
+        assert_eq!(policy.priority_for("gate"), 200);
+        assert_eq!(policy.priority_for("cam"), DEFAULT_PRIORITY);
+    }
+
+    #[tokio::test]
+    async fn test_sweep_deletes_expired_recordings() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("storage");
+        let storage = StorageConfig::Fs {
+            root: root.to_string_lossy().into_owned(),
+        };
+        let operator = storage::create_failover_operator(&storage).unwrap();
+        let index = Arc::new(
+            RecordingsIndex::load(dir.path().join("index.json"))
+                .await
+                .unwrap(),
+        );
+
+        let day = 86_400 * 1_000_000;
+        let now = Utc::now().timestamp_micros();
+        for (record, class, end_ts, status) in [
+            (
+                "1",
+                Some("30d"),
+                Some(now - 31 * day),
+                RecordingStatus::Completed,
+            ),
+            (
+                "2",
+                Some("30d"),
+                Some(now - day),
+                RecordingStatus::Completed,
+            ),
+            (
+                "3",
+                Some("forever"),
+                Some(now - 400 * day),
+                RecordingStatus::Completed,
+            ),
+            ("4", None, Some(now - 400 * day), RecordingStatus::Completed),
+            ("5", Some("1d"), None, RecordingStatus::Active),
+        ] {
+            let record_dir = format!("cam/{record}");
+            operator
+                .current()
+                .write(&format!("{record_dir}/manifest.mpd"), "mpd")
+                .await
+                .unwrap();
+            index
+                .upsert(RecordingIndexEntry {
+                    uuid: String::new(),
+                    record: record.to_string(),
+                    stream: "cam".to_string(),
+                    mpd_path: format!("{record_dir}/manifest.mpd"),
+                    record_dir,
+                    start_ts: now - 500 * day,
+                    end_ts,
+                    duration_ms: None,
+                    status,
+                    node_alias: None,
+                    updated_at: now,
+                    note: None,
+                    labels: Vec::new(),
+                    continues: None,
+                    media_info: Vec::new(),
+                    retention_class: class.map(|c| c.parse().unwrap()),
+                    trashed_at: None,
+                    trashed_from: None,
+                    repair_error: None,
+                    source: None,
+                    replicas: Vec::new(),
+                    clock_skew_detected: false,
+                    priority: DEFAULT_PRIORITY,
+                    tenant: None,
+                    size: None,
+                    captions: Vec::new(),
+                    trigger: None,
+                    imported: false,
+                    seq: 0,
+                })
+                .await
+                .unwrap();
+        }
+        // Indexed before end_ts was clamped, a clock step put it 40 days before its start
+        index
+            .upsert(RecordingIndexEntry {
+                uuid: String::new(),
+                record: "6".to_string(),
+                stream: "cam".to_string(),
+                record_dir: "cam/6".to_string(),
+                mpd_path: "cam/6/manifest.mpd".to_string(),
+                start_ts: now - day,
+                end_ts: Some(now - 41 * day),
+                duration_ms: Some(60_000),
+                status: RecordingStatus::Completed,
+                node_alias: None,
+                updated_at: now,
+                note: None,
+                labels: Vec::new(),
+                continues: None,
+                media_info: Vec::new(),
+                retention_class: Some("30d".parse().unwrap()),
+                trashed_at: None,
+                trashed_from: None,
+                repair_error: None,
+                source: None,
+                replicas: Vec::new(),
+                clock_skew_detected: false,
+                priority: DEFAULT_PRIORITY,
+                tenant: None,
+                size: None,
+                captions: Vec::new(),
+                trigger: None,
+                imported: false,
+                seq: 0,
+            })
+            .await
+            .unwrap();
+
+        let retention = Retention::new(index.clone(), operator.clone(), storage, None);
+        assert_eq!(retention.sweep().await.unwrap(), 1);
+        let records: Vec<String> = index
+            .entries_of("cam")
+            .await
+            .into_iter()
+            .map(|e| e.record)
+            .collect();
+        assert_eq!(records, vec!["2", "3", "4", "5", "6"]);
+        assert!(!root.join("cam/1/manifest.mpd").exists());
+        assert!(root.join("cam/2/manifest.mpd").exists());
+    }
+
+    #[tokio::test]
+    async fn test_trash_restore_and_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("storage");
+        let storage = StorageConfig::Fs {
+            root: root.to_string_lossy().into_owned(),
+        };
+        let operator = storage::create_failover_operator(&storage).unwrap();
+        let index = Arc::new(
+            RecordingsIndex::load(dir.path().join("index.json"))
+                .await
+                .unwrap(),
+        );
+        for (record, status) in [
+            ("1", RecordingStatus::Failed),
+            ("2", RecordingStatus::Completed),
+            ("3", RecordingStatus::Active),
+        ] {
+            let record_dir = format!("cam/{record}");
+            operator
+                .current()
+                .write(&format!("{record_dir}/manifest.mpd"), "mpd")
+                .await
+                .unwrap();
+            index
+                .upsert(RecordingIndexEntry {
+                    uuid: String::new(),
+                    record: record.to_string(),
+                    stream: "cam".to_string(),
+                    mpd_path: format!("{record_dir}/manifest.mpd"),
+                    record_dir,
+                    start_ts: 0,
+                    end_ts: None,
+                    duration_ms: None,
+                    status,
+                    node_alias: None,
+                    updated_at: 1,
+                    note: None,
+                    labels: Vec::new(),
+                    continues: None,
+                    media_info: Vec::new(),
+                    retention_class: None,
+                    trashed_at: None,
+                    trashed_from: None,
+                    repair_error: None,
+                    source: None,
+                    replicas: Vec::new(),
+                    clock_skew_detected: false,
+                    priority: DEFAULT_PRIORITY,
+                    tenant: None,
+                    size: None,
+                    captions: Vec::new(),
+                    trigger: None,
+                    imported: false,
+                    seq: 0,
+                })
+                .await
+                .unwrap();
+        }
+
+        assert!(matches!(
+            index.trash("cam", "3", None).await.unwrap(),
+            TrashUpdate::Conflict(_)
+        ));
+        assert!(matches!(
+            index.restore("cam", "1", None).await.unwrap(),
+            TrashUpdate::Conflict(_)
+        ));
+        let TrashUpdate::Updated(trashed) = index.trash("cam", "1", None).await.unwrap() else {
+            panic!("recording not trashed");
+        };
+        assert!(trashed.is_trashed());
+        let TrashUpdate::Updated(restored) = index.restore("cam", "1", None).await.unwrap() else {
+            panic!("recording not restored");
+        };
+        assert!(matches!(restored.status, RecordingStatus::Failed));
+        assert!(restored.trashed_at.is_none());
+
+        index.trash("cam", "1", None).await.unwrap();
+        index.trash("cam", "2", None).await.unwrap();
+        let audit = Arc::new(AuditLog::new(dir.path().join("index.json"), 0, 0));
+        let retention = Retention::new(index.clone(), operator.clone(), storage, None)
+            .with_audit(audit.clone());
+        // Nothing was trashed a day ago yet
+        assert_eq!(
+            retention
+                .empty_trash(Duration::from_secs(86_400))
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(retention.empty_trash(Duration::ZERO).await.unwrap(), 2);
+        assert!(index.get("cam", "1").await.is_none());
+        assert!(!root.join("cam/2/manifest.mpd").exists());
+        assert!(root.join("cam/3/manifest.mpd").exists());
+
+        assert!(matches!(
+            retention.preview_purge("cam", "3").await.unwrap(),
+            DryRun::Conflict(_)
+        ));
+        assert!(matches!(
+            retention
+                .purge_recording("cam", "3", "ops", None)
+                .await
+                .unwrap(),
+            TrashUpdate::Conflict(_)
+        ));
+
+        let records = audit.since(None).await.unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(records[..2].iter().all(|r| {
+            r.operation == AuditOperation::EmptyTrash
+                && r.actor == SYSTEM_RETENTION
+                && r.objects == 1
+                && r.bytes == 3
+                && r.outcome == AuditOutcome::Succeeded
+        }));
+        assert_eq!(records[2].operation, AuditOperation::Purge);
+        assert_eq!(records[2].actor, "ops");
+        assert_eq!(records[2].keys, ["cam/3"]);
+        assert_eq!(records[2].outcome, AuditOutcome::Rejected);
+    }
+
+    #[tokio::test]
+    async fn test_quota_deletes_low_priority_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("storage");
+        let storage = StorageConfig::Fs {
+            root: root.to_string_lossy().into_owned(),
+        };
+        let operator = storage::create_failover_operator(&storage).unwrap();
+        let index = Arc::new(
+            RecordingsIndex::load(dir.path().join("index.json"))
+                .await
+                .unwrap(),
+        );
+        // 100 bytes each, the oldest recording has the highest priority
+        for (record, priority, status) in [
+            ("1", 200, RecordingStatus::Completed),
+            ("2", 10, RecordingStatus::Completed),
+            ("3", 10, RecordingStatus::Completed),
+            ("4", 10, RecordingStatus::Active),
+            ("5", DEFAULT_PRIORITY, RecordingStatus::Completed),
+        ] {
+            let record_dir = format!("cam/{record}");
+            operator
+                .current()
+                .write(&format!("{record_dir}/v_seg_0001.m4s"), vec![0u8; 100])
+                .await
+                .unwrap();
+            index
+                .upsert(RecordingIndexEntry {
+                    uuid: String::new(),
+                    record: record.to_string(),
+                    stream: "cam".to_string(),
+                    mpd_path: format!("{record_dir}/manifest.mpd"),
+                    record_dir,
+                    start_ts: record.parse().unwrap(),
+                    end_ts: None,
+                    duration_ms: None,
+                    status,
+                    node_alias: None,
+                    updated_at: 1,
+                    note: None,
+                    labels: Vec::new(),
+                    continues: None,
+                    media_info: Vec::new(),
+                    retention_class: None,
+                    trashed_at: None,
+                    trashed_from: None,
+                    repair_error: None,
+                    source: None,
+                    replicas: Vec::new(),
+                    clock_skew_detected: false,
+                    priority,
+                    tenant: None,
+                    size: None,
+                    captions: Vec::new(),
+                    trigger: None,
+                    imported: false,
+                    seq: 0,
+                })
+                .await
+                .unwrap();
+        }
+
+        let retention =
+            Retention::new(index.clone(), operator.clone(), storage, None).with_quota(250);
+        assert_eq!(retention.sweep().await.unwrap(), 3);
+        let records: Vec<String> = index
+            .entries_of("cam")
+            .await
+            .into_iter()
+            .map(|e| e.record)
+            .collect();
+        // The active recording stays and still counts against the quota
+        assert_eq!(records, vec!["1", "4"]);
+        assert!(!root.join("cam/5/v_seg_0001.m4s").exists());
+        // Back under the quota, the next sweep has nothing to do
+        assert_eq!(retention.sweep().await.unwrap(), 0);
+    }
+}
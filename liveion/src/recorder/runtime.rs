@@ -0,0 +1,150 @@
+//! `recorder.runtime`: a tokio runtime of the recorder's own.
+//!
+//! Index file IO and uploads block on the disk and on storage. On the main runtime a
+//! hung S3 endpoint piles up blocked tasks until the WebRTC packet path, which shares
+//! the workers, starts to jitter. They run here instead, so a stall only ties up this
+//! runtime's threads. The handle is passed to what runs on it rather than looked up.
+
+use std::sync::OnceLock;
+
+use anyhow::Result;
+use tokio::runtime::{Builder, Handle, Runtime};
+
+use crate::config::RecorderRuntimeConfig;
+
+/// Built once and kept for the life of the process: dropping a runtime from async
+/// code panics, and recordings outlive any config reload
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Handle of the recorder's runtime, built on first use. The current runtime when
+/// `worker_threads` is 0, or when building it fails
+pub fn handle(cfg: &RecorderRuntimeConfig) -> Handle {
+    if cfg.worker_threads == 0 {
+        return Handle::current();
+    }
+    if let Some(runtime) = RUNTIME.get() {
+        return runtime.handle().clone();
+    }
+    match build(cfg) {
+        Ok(runtime) => {
+            tracing::info!(
+                "[recorder] dedicated runtime with {} workers and {} blocking threads",
+                cfg.worker_threads,
+                cfg.max_blocking_threads
+            );
+            RUNTIME.get_or_init(|| runtime).handle().clone()
+        }
+        Err(e) => {
+            tracing::error!("[recorder] failed to build its runtime, sharing the main one: {e}");
+            Handle::current()
+        }
+    }
+}
+
+fn build(cfg: &RecorderRuntimeConfig) -> Result<Runtime> {
+    Ok(Builder::new_multi_thread()
+        .worker_threads(cfg.worker_threads)
+        .max_blocking_threads(cfg.max_blocking_threads.max(1))
+        .thread_name("recorder")
+        .enable_all()
+        .build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    use api::recorder::{DEFAULT_PRIORITY, RecordingIndexEntry, RecordingStatus};
+
+    use crate::recorder::index::RecordingsIndex;
+
+    fn entry(i: usize) -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            uuid: String::new(),
+            record: (1_700_000_000 + i).to_string(),
+            stream: "cam".to_string(),
+            record_dir: format!("cam/{}", 1_700_000_000 + i),
+            mpd_path: format!("cam/{}/manifest.mpd", 1_700_000_000 + i),
+            start_ts: 0,
+            end_ts: None,
+            duration_ms: None,
+            status: RecordingStatus::Active,
+            node_alias: None,
+            updated_at: i as i64,
+            note: None,
+            labels: Vec::new(),
+            continues: None,
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant: None,
+            size: None,
+            captions: Vec::new(),
+            trigger: None,
+            imported: false,
+            seq: 0,
+        }
+    }
+
+    /// Storage hanging on every blocking thread and worker of the recorder's runtime
+    /// while the index keeps writing: a heartbeat on the main runtime stays on time
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_stalls_stay_on_the_recorder_runtime() {
+        const TICK: Duration = Duration::from_millis(10);
+        let runtime = build(&RecorderRuntimeConfig {
+            worker_threads: 1,
+            max_blocking_threads: 2,
+        })
+        .unwrap();
+        let handle = runtime.handle().clone();
+
+        let heartbeat = tokio::spawn(async move {
+            let mut worst = Duration::ZERO;
+            let mut ticker = tokio::time::interval(TICK);
+            ticker.tick().await;
+            let mut last = Instant::now();
+            for _ in 0..50 {
+                ticker.tick().await;
+                worst = worst.max(last.elapsed().saturating_sub(TICK));
+                last = Instant::now();
+            }
+            worst
+        });
+
+        // A hung storage endpoint: uploads and their blocking calls never come back
+        // in time, on the worker and on every blocking thread
+        for _ in 0..4 {
+            handle.spawn_blocking(|| std::thread::sleep(Duration::from_millis(300)));
+        }
+        handle.spawn(async { std::thread::sleep(Duration::from_millis(300)) });
+
+        let dir = tempfile::tempdir().unwrap();
+        let index = RecordingsIndex::load(dir.path().join("index.json"))
+            .await
+            .unwrap()
+            .with_runtime(handle.clone());
+        let writes = tokio::spawn(async move {
+            for i in 0..20 {
+                index.upsert(entry(i)).await.unwrap();
+            }
+            index.snapshot().await.unwrap().len()
+        });
+
+        let worst = heartbeat.await.unwrap();
+        let written = writes.await.unwrap();
+        runtime.shutdown_background();
+        assert!(
+            worst < Duration::from_millis(50),
+            "heartbeat late by {worst:?}"
+        );
+        // The index writes queue behind the stall but are not lost
+        assert_eq!(written, 20);
+    }
+}
@@ -0,0 +1,444 @@
+//! Cron-like recording schedules evaluated in local time.
+//!
+//! Expressions use the classic five fields `minute hour day-of-month month day-of-week`
+//! with `*`, lists, ranges, steps and three-letter month/day names. Windows of every
+//! schedule matching a stream are merged, so overlapping entries record as their union.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, TimeZone, Timelike, Utc};
+use serde::Serialize;
+
+use crate::config::RecordingSchedule;
+
+/// How far back to look for a window that is still open
+const LOOKBACK_DAYS: i64 = 8;
+/// How far ahead to look for the next start/stop
+const LOOKAHEAD_DAYS: i64 = 32;
+/// Upper bound on windows generated per schedule, guards against `* * * * *` with a duration
+const MAX_WINDOWS: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Field {
+    bits: u64,
+    restricted: bool,
+}
+
+impl Field {
+    fn parse(expr: &str, min: u32, max: u32, names: &[&str]) -> anyhow::Result<Self> {
+        let mut bits = 0u64;
+        for part in expr.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>()?),
+                None => (part, 1),
+            };
+            if step == 0 {
+                anyhow::bail!("step must be positive in '{part}'");
+            }
+            let (lo, hi) = if range == "*" {
+                (min, max)
+            } else if let Some((lo, hi)) = range.split_once('-') {
+                (parse_value(lo, names, min)?, parse_value(hi, names, min)?)
+            } else {
+                let value = parse_value(range, names, min)?;
+                // `5/15` means every 15 starting at 5
+                (value, if part.contains('/') { max } else { value })
+            };
+            if lo < min || hi > max || lo > hi {
+                anyhow::bail!("'{part}' is out of range {min}-{max}");
+            }
+            for v in (lo..=hi).step_by(step as usize) {
+                bits |= 1 << v;
+            }
+        }
+        Ok(Self {
+            bits,
+            restricted: !expr.starts_with('*'),
+        })
+    }
+
+    fn matches(&self, value: u32) -> bool {
+        self.bits & (1 << value) != 0
+    }
+}
+
+fn parse_value(value: &str, names: &[&str], offset: u32) -> anyhow::Result<u32> {
+    if let Some(pos) = names.iter().position(|n| n.eq_ignore_ascii_case(value)) {
+        return Ok(pos as u32 + offset);
+    }
+    value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid value '{value}'"))
+}
+
+/// Parsed five-field cron expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    minute: Field,
+    hour: Field,
+    day: Field,
+    month: Field,
+    weekday: Field,
+}
+
+impl std::str::FromStr for CronExpr {
+    type Err = anyhow::Error;
+
+    fn from_str(expr: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            anyhow::bail!("expected 5 fields in '{expr}'");
+        };
+        const MONTHS: [&str; 12] = [
+            "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+        ];
+        const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+        let mut weekday = Field::parse(weekday, 0, 7, &WEEKDAYS)?;
+        // Both 0 and 7 are Sunday
+        if weekday.matches(7) {
+            weekday.bits |= 1;
+        }
+        Ok(Self {
+            minute: Field::parse(minute, 0, 59, &[])?,
+            hour: Field::parse(hour, 0, 23, &[])?,
+            day: Field::parse(day, 1, 31, &[])?,
+            month: Field::parse(month, 1, 12, &MONTHS)?,
+            weekday,
+        })
+    }
+}
+
+impl CronExpr {
+    fn matches_day(&self, t: &NaiveDateTime) -> bool {
+        let day = self.day.matches(t.day());
+        let weekday = self.weekday.matches(t.weekday().num_days_from_sunday());
+        // Classic cron: when both are restricted either one may match
+        match (self.day.restricted, self.weekday.restricted) {
+            (true, true) => day || weekday,
+            (true, false) => day,
+            (false, true) => weekday,
+            (false, false) => true,
+        }
+    }
+
+    /// First matching local minute strictly after `after`
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut t = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let give_up = after.year() + 5;
+        while t.year() <= give_up {
+            if !self.month.matches(t.month()) {
+                let (year, month) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = chrono::NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(&t) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !self.hour.matches(t.hour()) {
+                t = t.with_minute(0)? + Duration::hours(1);
+            } else if !self.minute.matches(t.minute()) {
+                t += Duration::minutes(1);
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+/// How a schedule window ends
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum WindowEnd {
+    Stop(CronExpr),
+    Duration(Duration),
+}
+
+/// A validated schedule entry
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    streams: Vec<glob::Pattern>,
+    start: CronExpr,
+    end: WindowEnd,
+}
+
+impl Schedule {
+    pub fn compile(cfg: &RecordingSchedule) -> anyhow::Result<Self> {
+        let streams = cfg
+            .streams
+            .iter()
+            .map(|p| glob::Pattern::new(p))
+            .collect::<Result<Vec<_>, _>>()?;
+        let start = cfg.start.parse()?;
+        let end = match (&cfg.stop, cfg.duration_minutes) {
+            (Some(stop), None) => WindowEnd::Stop(stop.parse()?),
+            (None, Some(minutes)) if minutes > 0 => {
+                WindowEnd::Duration(Duration::minutes(minutes as i64))
+            }
+            _ => anyhow::bail!("schedule needs either `stop` or a positive `duration_minutes`"),
+        };
+        Ok(Self {
+            streams,
+            start,
+            end,
+        })
+    }
+
+    pub fn applies_to(&self, stream: &str) -> bool {
+        self.streams.iter().any(|p| p.matches(stream))
+    }
+
+    /// Windows starting in `[from, to)`, in UTC
+    fn windows<Tz: TimeZone>(
+        &self,
+        tz: &Tz,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        let mut windows = Vec::new();
+        let mut cursor = from.with_timezone(tz).naive_local() - Duration::minutes(1);
+        while windows.len() < MAX_WINDOWS {
+            let Some(start_local) = self.start.next_after(cursor) else {
+                break;
+            };
+            let start = resolve_local(tz, start_local);
+            if start >= to {
+                break;
+            }
+            let end = match &self.end {
+                WindowEnd::Duration(duration) => start + *duration,
+                WindowEnd::Stop(stop) => match stop.next_after(start_local) {
+                    Some(stop_local) => resolve_local(tz, stop_local),
+                    None => DateTime::<Utc>::MAX_UTC,
+                },
+            };
+            if start >= from && end > start {
+                windows.push((start, end));
+            }
+            cursor = start_local;
+        }
+        windows
+    }
+}
+
+/// Map a local wall-clock time to UTC deterministically across DST transitions.
+///
+/// Times repeated by a backward jump resolve to their first occurrence, times skipped
+/// by a forward jump resolve to the first minute that exists after the gap.
+fn resolve_local<Tz: TimeZone>(tz: &Tz, naive: NaiveDateTime) -> DateTime<Utc> {
+    let mut probe = naive;
+    for _ in 0..=24 * 60 {
+        if let Some(t) = tz.from_local_datetime(&probe).earliest() {
+            return t.with_timezone(&Utc);
+        }
+        probe += Duration::minutes(1);
+    }
+    naive.and_utc()
+}
+
+/// Merged schedule windows for one stream around `now`, sorted and non-overlapping
+pub fn merged_windows<Tz: TimeZone>(
+    schedules: &[Schedule],
+    stream: &str,
+    tz: &Tz,
+    now: DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let from = now - Duration::days(LOOKBACK_DAYS);
+    let to = now + Duration::days(LOOKAHEAD_DAYS);
+    let mut windows: Vec<_> = schedules
+        .iter()
+        .filter(|s| s.applies_to(stream))
+        .flat_map(|s| s.windows(tz, from, to))
+        .collect();
+    windows.sort();
+
+    let mut merged: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::with_capacity(windows.len());
+    for (start, end) in windows {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Schedule position of a stream, reported by the recorder status endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScheduleStatus {
+    pub in_window: bool,
+    /// Next scheduled start, UNIX microseconds
+    pub next_start: Option<i64>,
+    /// Next scheduled stop, UNIX microseconds
+    pub next_stop: Option<i64>,
+}
+
+/// Schedule status of a stream, `None` when no schedule applies to it
+pub fn status<Tz: TimeZone>(
+    schedules: &[Schedule],
+    stream: &str,
+    tz: &Tz,
+    now: DateTime<Utc>,
+) -> Option<ScheduleStatus> {
+    if !schedules.iter().any(|s| s.applies_to(stream)) {
+        return None;
+    }
+    let windows = merged_windows(schedules, stream, tz, now);
+    let current = windows
+        .iter()
+        .find(|(start, end)| *start <= now && now < *end);
+    let next = windows.iter().find(|(start, _)| *start > now);
+    let micros = |t: &DateTime<Utc>| (*t != DateTime::<Utc>::MAX_UTC).then(|| t.timestamp_micros());
+    Some(ScheduleStatus {
+        in_window: current.is_some(),
+        next_start: next.map(|(start, _)| start.timestamp_micros()),
+        next_stop: current.or(next).and_then(|(_, end)| micros(end)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, NaiveDate};
+
+    fn at(y: i32, m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap()
+    }
+
+    fn schedule(
+        streams: &[&str],
+        start: &str,
+        stop: Option<&str>,
+        minutes: Option<u64>,
+    ) -> Schedule {
+        Schedule::compile(&RecordingSchedule {
+            streams: streams.iter().map(|s| s.to_string()).collect(),
+            start: start.to_string(),
+            stop: stop.map(str::to_string),
+            duration_minutes: minutes,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        let weekdays: CronExpr = "0 8 * * mon-fri".parse().unwrap();
+        // 2024-01-05 is a Friday
+        assert_eq!(
+            weekdays.next_after(at(2024, 1, 5, 9, 0)),
+            Some(at(2024, 1, 8, 8, 0))
+        );
+        assert_eq!(
+            weekdays.next_after(at(2024, 1, 5, 7, 59)),
+            Some(at(2024, 1, 5, 8, 0))
+        );
+
+        let steps: CronExpr = "*/15 * * * *".parse().unwrap();
+        assert_eq!(
+            steps.next_after(at(2024, 1, 1, 0, 0)),
+            Some(at(2024, 1, 1, 0, 15))
+        );
+
+        let sunday: CronExpr = "30 6 * * 7".parse().unwrap();
+        assert_eq!(
+            sunday.next_after(at(2024, 1, 1, 0, 0)),
+            Some(at(2024, 1, 7, 6, 30))
+        );
+
+        assert!("0 8 * *".parse::<CronExpr>().is_err());
+        assert!("61 8 * * *".parse::<CronExpr>().is_err());
+        assert!("0 8 * * */0".parse::<CronExpr>().is_err());
+    }
+
+    #[test]
+    fn test_status_in_and_out_of_window() {
+        let tz = FixedOffset::east_opt(2 * 3600).unwrap();
+        let schedules = vec![schedule(
+            &["camera*"],
+            "0 8 * * 1-5",
+            Some("0 18 * * 1-5"),
+            None,
+        )];
+        let local = |t: NaiveDateTime| tz.from_local_datetime(&t).unwrap().with_timezone(&Utc);
+
+        // Wednesday noon local time
+        let now = local(at(2024, 1, 3, 12, 0));
+        let status = status(&schedules, "camera01", &tz, now).unwrap();
+        assert!(status.in_window);
+        assert_eq!(
+            status.next_stop,
+            Some(local(at(2024, 1, 3, 18, 0)).timestamp_micros())
+        );
+        assert_eq!(
+            status.next_start,
+            Some(local(at(2024, 1, 4, 8, 0)).timestamp_micros())
+        );
+
+        // Saturday
+        let now = local(at(2024, 1, 6, 12, 0));
+        let status = super::status(&schedules, "camera01", &tz, now).unwrap();
+        assert!(!status.in_window);
+        assert_eq!(
+            status.next_start,
+            Some(local(at(2024, 1, 8, 8, 0)).timestamp_micros())
+        );
+        assert_eq!(
+            status.next_stop,
+            Some(local(at(2024, 1, 8, 18, 0)).timestamp_micros())
+        );
+
+        assert!(super::status(&schedules, "lobby", &tz, now).is_none());
+    }
+
+    #[test]
+    fn test_overlapping_schedules_union() {
+        let schedules = vec![
+            schedule(&["cam"], "0 8 * * *", None, Some(120)),
+            schedule(&["cam"], "0 9 * * *", Some("0 12 * * *"), None),
+            schedule(&["cam"], "0 20 * * *", None, Some(60)),
+        ];
+        let now = at(2024, 1, 3, 0, 0).and_utc();
+        let windows = merged_windows(&schedules, "cam", &Utc, now);
+        let today: Vec<_> = windows
+            .iter()
+            .filter(|(start, _)| start.date_naive() == now.date_naive())
+            .collect();
+        assert_eq!(
+            today,
+            vec![
+                &(
+                    at(2024, 1, 3, 8, 0).and_utc(),
+                    at(2024, 1, 3, 12, 0).and_utc()
+                ),
+                &(
+                    at(2024, 1, 3, 20, 0).and_utc(),
+                    at(2024, 1, 3, 21, 0).and_utc()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_window_started_before_lookup_is_open() {
+        // Overnight window: 22:00 to 06:00
+        let schedules = vec![schedule(&["cam"], "0 22 * * *", Some("0 6 * * *"), None)];
+        let now = at(2024, 1, 3, 2, 0).and_utc();
+        let status = status(&schedules, "cam", &Utc, now).unwrap();
+        assert!(status.in_window);
+        assert_eq!(
+            status.next_stop,
+            Some(at(2024, 1, 3, 6, 0).and_utc().timestamp_micros())
+        );
+    }
+
+    #[test]
+    fn test_schedule_requires_single_end() {
+        let cfg = RecordingSchedule {
+            streams: vec!["cam".to_string()],
+            start: "0 8 * * *".to_string(),
+            stop: Some("0 18 * * *".to_string()),
+            duration_minutes: Some(60),
+        };
+        assert!(Schedule::compile(&cfg).is_err());
+    }
+}
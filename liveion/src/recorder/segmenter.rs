@@ -1,10 +1,25 @@
 use crate::recorder::codec::{CodecAdapter, VideoCodec, create_video_adapter};
+use crate::recorder::disk::DiskFull;
 use crate::recorder::fmp4::{Fmp4Writer, Mp4Sample};
 use crate::recorder::pli_backoff::PliBackoff;
+use crate::recorder::probe::{SampleEntry, probe_init_segment};
 use anyhow::Result;
+use api::recorder::{
+    AudioInfo, DEFAULT_PRIORITY, MediaInfo, RecordingSize, RetentionClass, SEGMENTS_FILENAME,
+    SegmentTiming, VideoInfo,
+};
 use bytes::Bytes;
-use opendal::Operator;
-use tracing::info;
+use dash::xml::{Element, Node};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use storage::{FailoverOperator, SegmentPattern};
+use tokio::sync::Notify;
+use tracing::{info, warn};
 
 /// Default duration of each segment in seconds
 const DEFAULT_SEG_DURATION: u64 = 10;
@@ -12,11 +27,9 @@ const DEFAULT_SEG_DURATION: u64 = 10;
 const MANIFEST_FILENAME: &str = "manifest.mpd";
 const VIDEO_INIT_FILENAME: &str = "v_init.m4s";
 const AUDIO_INIT_FILENAME: &str = "a_init.m4s";
-const VIDEO_SEGMENT_FILENAME_PREFIX: &str = "v_seg_";
-const AUDIO_SEGMENT_FILENAME_PREFIX: &str = "a_seg_";
-const SEGMENT_FILE_EXTENSION: &str = ".m4s";
-const VIDEO_SEGMENT_TEMPLATE: &str = "v_seg_$Number%04d$.m4s";
-const AUDIO_SEGMENT_TEMPLATE: &str = "a_seg_$Number%04d$.m4s";
+/// File name prefixes of each track's segments, ahead of the [`SegmentPattern`]
+const VIDEO_TRACK_PREFIX: &str = "v_";
+const AUDIO_TRACK_PREFIX: &str = "a_";
 
 const DEFAULT_AUDIO_SAMPLE_RATE: u32 = 48_000;
 const DEFAULT_AUDIO_CHANNELS: u16 = 2;
@@ -25,6 +38,49 @@ const DEFAULT_AUDIO_CODEC: &str = "opus";
 const VIDEO_TRACK_ID: u32 = 1;
 const AUDIO_TRACK_ID: u32 = 2;
 
+/// Detached storage writes still in flight, shutdown waits for them to land
+pub(crate) static PENDING_WRITES: Lazy<PendingWrites> = Lazy::new(PendingWrites::default);
+
+/// Shared init segment keys written (or being written) by this process
+static SHARED_INITS: Lazy<Mutex<HashSet<String>>> = Lazy::new(Default::default);
+
+#[derive(Default)]
+pub(crate) struct PendingWrites {
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+/// Counts one in-flight write until dropped
+struct PendingWrite(&'static PendingWrites);
+
+impl PendingWrites {
+    fn begin(&'static self) -> PendingWrite {
+        self.count.fetch_add(1, Ordering::AcqRel);
+        PendingWrite(self)
+    }
+
+    /// Resolve once no write is in flight
+    pub(crate) async fn wait_idle(&self) {
+        loop {
+            let notified = self.idle.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.count.load(Ordering::Acquire) == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for PendingWrite {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
 /// Represents a completed segment with its actual duration
 #[derive(Debug, Clone)]
 struct SegmentInfo {
@@ -32,12 +88,41 @@ struct SegmentInfo {
     duration: u64,   // Actual duration in timescale units
 }
 
+/// First segments of a Period after the first, as indices into the segment lists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct PeriodStart {
+    video: usize,
+    audio: usize,
+}
+
+/// A finished split: `previous_prefix` is finalized and new samples go to `next_prefix`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentSplit {
+    pub previous_prefix: String,
+    pub next_prefix: String,
+    /// What the segmenter stored for `previous_prefix`
+    pub previous_size: RecordingSize,
+}
+
 pub struct Segmenter {
-    op: Operator,
+    op: FailoverOperator,
     stream: String,
     path_prefix: String,
     uploader: Option<std::sync::Arc<crate::recorder::uploader::UploadManager>>,
     local_dir: Option<std::path::PathBuf>,
+    /// Store init segments once under a content-addressed shared key
+    dedup_init_segments: bool,
+    /// Stat objects written directly and write them again when storage kept another size
+    verify_size: bool,
+    /// `x-amz-tagging` tag set of uploaded objects, see [`RetentionClass::tagging`]
+    tagging: Option<String>,
+    /// Upload priority of the recording's objects
+    priority: u8,
+    /// Names of media segments, also written into the manifest's `SegmentTemplate`
+    segment_pattern: SegmentPattern,
+    // shared keys the manifest references instead of the per-recording init segments
+    video_init_key: Option<String>,
+    audio_init_key: Option<String>,
     timescale: u32,
     // Length of each segment (in timescale units) for fast comparison
     seg_duration_ticks: u64,
@@ -46,6 +131,10 @@ pub struct Segmenter {
     video_seg_index: u32,
     // Decode timestamp of current video segment start (in timescale units)
     video_seg_start_dts: u64,
+    /// Wall clock time of the current video segment's first sample, UNIX microseconds
+    video_seg_start_wall: i64,
+    /// `segments.jsonl` so far, stored whole after each video segment
+    segment_timings: String,
     video_track_id: Option<u32>,
 
     // Audio track id (Opus)
@@ -106,11 +195,34 @@ pub struct Segmenter {
 
     /// Audio segments with their actual durations
     audio_segments: Vec<SegmentInfo>,
+
+    /// Periods begun by publishers that came back, see [`Segmenter::begin_period`]
+    periods: Vec<PeriodStart>,
+
+    /// Path prefix to switch to at the next keyframe
+    pending_split: Option<String>,
+    // whether a PLI has been sent on behalf of the pending split
+    split_pli_sent: bool,
+
+    /// Split performed but not yet picked up by the recording task
+    completed_split: Option<SegmentSplit>,
+
+    /// Track formats read from the init segments, `None` when they could not be parsed
+    video_info: Option<VideoInfo>,
+    audio_info: Option<AudioInfo>,
+    // when a format last changed, cleared once picked up by the recording task
+    media_changed_at: Option<i64>,
+    // the publisher went away, media is dropped until the next video keyframe
+    awaiting_resume: bool,
+    /// Media segments stored for the current recording, read by the stream listing
+    segments_written: std::sync::Arc<AtomicU64>,
+    /// Bytes of those segments, read by the recorder stats
+    bytes_written: std::sync::Arc<AtomicU64>,
 }
 
 impl Segmenter {
     pub async fn new(
-        op: Operator,
+        op: FailoverOperator,
         stream: String,
         root_prefix: String,
         uploader: Option<std::sync::Arc<crate::recorder::uploader::UploadManager>>,
@@ -122,10 +234,19 @@ impl Segmenter {
             path_prefix: root_prefix,
             uploader,
             local_dir: local_dir.map(std::path::PathBuf::from),
+            dedup_init_segments: false,
+            verify_size: false,
+            tagging: None,
+            priority: DEFAULT_PRIORITY,
+            segment_pattern: SegmentPattern::default(),
+            video_init_key: None,
+            audio_init_key: None,
             timescale: 90_000,
             seg_duration_ticks: 90_000u64 * DEFAULT_SEG_DURATION,
             video_seg_index: 0,
             video_seg_start_dts: 0,
+            video_seg_start_wall: 0,
+            segment_timings: String::new(),
             video_track_id: None,
 
             audio_track_id: None,
@@ -161,9 +282,134 @@ impl Segmenter {
             video_adapter: None,
             segments: Vec::new(),
             audio_segments: Vec::new(),
+            periods: Vec::new(),
+            pending_split: None,
+            split_pli_sent: false,
+            completed_split: None,
+            video_info: None,
+            audio_info: None,
+            media_changed_at: None,
+            awaiting_resume: false,
+            segments_written: Default::default(),
+            bytes_written: Default::default(),
         })
     }
 
+    /// Count of media segments stored for the current recording, reset by a split
+    pub fn segments_written(&self) -> std::sync::Arc<AtomicU64> {
+        self.segments_written.clone()
+    }
+
+    /// Bytes of the media segments stored for the current recording, reset by a split
+    pub fn bytes_written(&self) -> std::sync::Arc<AtomicU64> {
+        self.bytes_written.clone()
+    }
+
+    /// Reference init segments by content hash under `_shared/`, see [`storage::shared_init_key`]
+    pub fn set_dedup_init_segments(&mut self, enabled: bool) {
+        self.dedup_init_segments = enabled;
+    }
+
+    /// Check the size of objects written without the uploader, which checks its own
+    pub fn set_verify_size(&mut self, enabled: bool) {
+        self.verify_size = enabled;
+    }
+
+    /// Tag uploaded objects with `class`, shared objects are never tagged
+    pub fn set_retention_class(&mut self, class: Option<&RetentionClass>) {
+        self.tagging = class.map(RetentionClass::tagging);
+    }
+
+    pub fn set_priority(&mut self, priority: u8) {
+        self.priority = priority;
+    }
+
+    /// Name segments after `pattern`, set before the first segment is written
+    pub fn set_segment_pattern(&mut self, pattern: SegmentPattern) {
+        self.segment_pattern = pattern;
+    }
+
+    /// Check the longest key of every object the recording writes against S3's
+    /// constraints, so a recording that cannot be stored fails to start rather than
+    /// failing its uploads later. Segment numbers are counted at their widest.
+    pub fn check_keys(&self) -> std::result::Result<(), storage::KeyError> {
+        let segment = self.segment_pattern.filename(VIDEO_TRACK_PREFIX, u32::MAX);
+        [
+            MANIFEST_FILENAME,
+            SEGMENTS_FILENAME,
+            VIDEO_INIT_FILENAME,
+            AUDIO_INIT_FILENAME,
+            segment.as_str(),
+        ]
+        .into_iter()
+        .try_for_each(|name| storage::check_key(&format!("{}/{}", self.path_prefix, name)))
+    }
+
+    /// Finish the current recording at the next keyframe and continue under `next_prefix`
+    pub fn request_split(&mut self, next_prefix: String) {
+        self.pending_split = Some(next_prefix);
+        self.split_pli_sent = false;
+    }
+
+    /// Close the segments in flight when the publisher goes away.
+    ///
+    /// The recording goes on with the next keyframe of whichever publisher comes back,
+    /// the timeline skips the gap. Audio waits for that keyframe too, so both tracks
+    /// resume at the same instant.
+    pub async fn publisher_gap(&mut self) -> Result<()> {
+        self.flush().await?;
+        self.awaiting_resume = self.video_track_id.is_some();
+        self.pli_backoff.hard_reset();
+        Ok(())
+    }
+
+    /// Continue a flushed recording for a publisher that came back, in a new Period from
+    /// its first video keyframe on. The timeline goes on where the last Period ended and
+    /// segment numbers keep counting, so nothing written before is touched.
+    pub fn begin_period(&mut self) {
+        let start = PeriodStart {
+            video: self.segments.len(),
+            audio: self.audio_segments.len(),
+        };
+        if start != PeriodStart::default() && self.periods.last() != Some(&start) {
+            self.periods.push(start);
+        }
+        self.awaiting_resume = self.video_track_id.is_some();
+        self.video_seg_start_wall = chrono::Utc::now().timestamp_micros();
+        self.pli_backoff.hard_reset();
+    }
+
+    /// Prefix of the recording being written, it moves on with every split
+    pub fn path_prefix(&self) -> &str {
+        &self.path_prefix
+    }
+
+    /// Take the split performed since the last call, if any
+    pub fn take_split(&mut self) -> Option<SegmentSplit> {
+        self.completed_split.take()
+    }
+
+    /// Take the track formats if they changed since the last call, with the recording
+    /// prefix they apply to
+    pub fn take_media_info(&mut self) -> Option<(String, MediaInfo)> {
+        let since_ts = self.media_changed_at.take()?;
+        if self.video_info.is_none() && self.audio_info.is_none() {
+            return None;
+        }
+        Some((
+            self.path_prefix.clone(),
+            MediaInfo {
+                since_ts,
+                video: self.video_info.clone(),
+                audio: self.audio_info.clone(),
+            },
+        ))
+    }
+
+    fn mark_media_changed(&mut self) {
+        self.media_changed_at = Some(chrono::Utc::now().timestamp_micros());
+    }
+
     /// Feed one H.264 Frame (Annex-B format, may contain multiple NALUs)
     /// `duration_ticks` – frame duration in the same timescale as self.timescale (90000 for H264)
     pub async fn push_h264(&mut self, frame: Bytes, duration_ticks: u32) -> Result<()> {
@@ -180,11 +426,20 @@ impl Segmenter {
     /// Feed Opus audio sample from RTP payload
     /// `duration_ticks` – duration in the 48 kHz time base (i.e. RTP timestamp delta)
     pub async fn push_opus(&mut self, payload: Bytes, duration_ticks: u32) -> Result<()> {
+        if self.awaiting_resume {
+            return Ok(());
+        }
+
         // Initialize writer if not yet done
         if self.audio_track_id.is_none() {
             self.init_audio_writer().await?;
         }
 
+        // Without video there is no keyframe to wait for, split between packets
+        if self.pending_split.is_some() && self.video_adapter.is_none() {
+            self.split_recording().await?;
+        }
+
         let size_bytes = payload.len();
         let sample_start = self.audio_current_pts;
         if self.audio_samples.is_empty() {
@@ -272,6 +527,13 @@ impl Segmenter {
 
         let is_sync = explicit_sync.unwrap_or(false) || adapter_sync;
 
+        if self.awaiting_resume {
+            if !is_sync {
+                return Ok(());
+            }
+            self.awaiting_resume = false;
+        }
+
         if is_sync {
             self.pli_backoff.record_keyframe();
         }
@@ -282,7 +544,11 @@ impl Segmenter {
             duration_ticks
         };
 
-        if is_sync && (self.video_current_pts - self.video_seg_start_dts >= self.seg_duration_ticks)
+        if is_sync && self.pending_split.is_some() && self.video_track_id.is_some() {
+            // The keyframe becomes the first sample of the next recording
+            self.split_recording().await?;
+        } else if is_sync
+            && (self.video_current_pts - self.video_seg_start_dts >= self.seg_duration_ticks)
         {
             self.roll_segment().await?;
         }
@@ -294,6 +560,8 @@ impl Segmenter {
             } else {
                 return Ok(());
             }
+        } else if is_sync {
+            self.check_video_format();
         }
 
         let sample_bytes = Bytes::from(payload);
@@ -350,15 +618,114 @@ impl Segmenter {
         self.video_seg_start_dts = 0;
         self.video_current_pts = 0;
         self.segments.clear();
+        self.periods.clear();
+        self.segment_timings.clear();
         self.total_bytes = 0;
         self.total_ticks = 0;
         self.frame_rate = 0;
         self.video_width = 0;
         self.video_height = 0;
         self.video_codec.clear();
+        self.video_info = None;
         self.pli_backoff.hard_reset();
     }
 
+    /// Append a new format when the stream renegotiates its resolution mid-recording.
+    ///
+    /// The init segment is written once per recording, so the new parameters come
+    /// from the parameter sets the adapter just parsed.
+    fn check_video_format(&mut self) {
+        let Some(adapter) = self.video_adapter.as_ref() else {
+            return;
+        };
+        let (width, height) = (adapter.width(), adapter.height());
+        let Some(current) = self.video_info.as_ref() else {
+            return;
+        };
+        if width == 0 || height == 0 || (current.width == width && current.height == height) {
+            return;
+        }
+        let codec = adapter
+            .codec_string()
+            .unwrap_or_else(|| current.codec.clone());
+        info!(
+            "[segmenter] {} video format changed from {}x{} to {}x{}",
+            self.stream, current.width, current.height, width, height
+        );
+        self.video_info = Some(VideoInfo {
+            codec,
+            width,
+            height,
+            framerate: None,
+        });
+        self.mark_media_changed();
+    }
+
+    fn probe_video_info(&self, init: &[u8]) -> Option<VideoInfo> {
+        match probe_init_segment(init) {
+            Ok(SampleEntry::Video {
+                fourcc,
+                width,
+                height,
+            }) => Some(VideoInfo {
+                codec: if self.video_codec.is_empty() {
+                    fourcc
+                } else {
+                    self.video_codec.clone()
+                },
+                width,
+                height,
+                framerate: None,
+            }),
+            Ok(other) => {
+                warn!(
+                    "[segmenter] {} {} has an unexpected sample entry {:?}",
+                    self.stream, VIDEO_INIT_FILENAME, other
+                );
+                None
+            }
+            Err(e) => {
+                warn!(
+                    "[segmenter] {} failed to read media info from {}: {:#}",
+                    self.stream, VIDEO_INIT_FILENAME, e
+                );
+                None
+            }
+        }
+    }
+
+    fn probe_audio_info(&self, init: &[u8]) -> Option<AudioInfo> {
+        match probe_init_segment(init) {
+            Ok(SampleEntry::Audio {
+                fourcc,
+                channels,
+                sample_rate,
+            }) => Some(AudioInfo {
+                codec: if self.audio_codec.is_empty() {
+                    fourcc
+                } else {
+                    self.audio_codec.clone()
+                },
+                channels,
+                sample_rate,
+            }),
+            Ok(other) => {
+                warn!(
+                    "[segmenter] {} {} has an unexpected sample entry {:?}",
+                    self.stream, AUDIO_INIT_FILENAME, other
+                );
+                None
+            }
+            Err(e) => {
+                warn!(
+                    "[segmenter] {} failed to read media info from {}: {:#}",
+                    self.stream, AUDIO_INIT_FILENAME, e
+                );
+                None
+            }
+        }
+    }
+
     fn refresh_video_metadata(&mut self) {
         if let Some(adapter) = self.video_adapter.as_ref() {
             let adapter = adapter.as_ref();
@@ -387,12 +754,16 @@ impl Segmenter {
 
     /// Check if we need to request a keyframe due to timeout
     pub fn should_request_keyframe(&self) -> bool {
-        self.pli_backoff.should_request()
+        // A pending split asks once right away instead of waiting for the backoff
+        (self.pending_split.is_some() && !self.split_pli_sent) || self.pli_backoff.should_request()
     }
 
     /// Record that a PLI request was sent
     pub fn record_pli_request(&mut self) {
         self.pli_backoff.record_request();
+        if self.pending_split.is_some() {
+            self.split_pli_sent = true;
+        }
     }
 
     /// Get PLI backoff statistics for logging
@@ -403,6 +774,65 @@ impl Segmenter {
     pub async fn flush(&mut self) -> Result<()> {
         self.roll_segment().await?;
         self.roll_audio_segment(true).await?;
+        self.write_final_manifest().await
+    }
+
+    /// Finalize the current recording and move all tracks to the pending prefix.
+    ///
+    /// Writers keep their codec configuration, so the next recording starts with fresh
+    /// init segments and a timeline at zero without waiting for new SPS/PPS.
+    async fn split_recording(&mut self) -> Result<()> {
+        let Some(next_prefix) = self.pending_split.take() else {
+            return Ok(());
+        };
+
+        self.roll_segment().await?;
+        self.roll_audio_segment(true).await?;
+        self.write_final_manifest().await?;
+
+        let previous_prefix = std::mem::replace(&mut self.path_prefix, next_prefix.clone());
+
+        self.video_samples.clear();
+        self.video_seg_index = 0;
+        self.video_seg_start_dts = 0;
+        self.video_current_pts = 0;
+        self.segments.clear();
+        self.periods.clear();
+        self.segment_timings.clear();
+        self.total_bytes = 0;
+        self.total_ticks = 0;
+
+        self.audio_samples.clear();
+        self.audio_seg_index = 0;
+        self.audio_seg_start_pts = 0;
+        self.audio_current_pts = 0;
+        self.audio_segments.clear();
+        self.audio_total_bytes = 0;
+        self.audio_total_ticks = 0;
+        let previous_size = RecordingSize {
+            segments: self.segments_written.swap(0, Ordering::Relaxed),
+            bytes: self.bytes_written.swap(0, Ordering::Relaxed),
+        };
+
+        if let Some(init_bytes) = self.fmp4_writer.as_ref().map(|w| w.build_init_segment()) {
+            self.video_init_key = self.store_init(VIDEO_INIT_FILENAME, init_bytes).await?;
+            self.open_new_segment().await?;
+        }
+        if let Some(init_bytes) = self.audio_writer.as_ref().map(|w| w.build_init_segment()) {
+            self.audio_init_key = self.store_init(AUDIO_INIT_FILENAME, init_bytes).await?;
+        }
+        self.write_manifest().await?;
+        self.mark_media_changed();
+
+        info!(
+            "[segmenter] {} split recording {} -> {}",
+            self.stream, previous_prefix, next_prefix
+        );
+        self.completed_split = Some(SegmentSplit {
+            previous_prefix,
+            next_prefix,
+            previous_size,
+        });
         Ok(())
     }
 
@@ -462,8 +892,11 @@ impl Segmenter {
         let init_bytes = fmp4_writer.build_init_segment();
         self.video_track_id = Some(track_id);
         self.fmp4_writer = Some(fmp4_writer);
+        self.video_info = self.probe_video_info(&init_bytes);
+        self.mark_media_changed();
 
-        self.store_file(VIDEO_INIT_FILENAME, init_bytes)
+        self.video_init_key = self
+            .store_init(VIDEO_INIT_FILENAME, init_bytes)
             .await
             .map_err(|e| {
                 tracing::error!(
@@ -514,7 +947,10 @@ impl Segmenter {
         self.audio_sample_rate = sample_rate;
         self.audio_channels = channels;
         self.audio_codec = codec_string.clone();
-        self.store_file(AUDIO_INIT_FILENAME, init_bytes)
+        self.audio_info = self.probe_audio_info(&init_bytes);
+        self.mark_media_changed();
+        self.audio_init_key = self
+            .store_init(AUDIO_INIT_FILENAME, init_bytes)
             .await
             .map_err(|e| {
                 tracing::error!(
@@ -537,6 +973,7 @@ impl Segmenter {
     async fn open_new_segment(&mut self) -> Result<()> {
         self.video_samples.clear();
         self.video_seg_start_dts = self.video_current_pts;
+        self.video_seg_start_wall = chrono::Utc::now().timestamp_micros();
         self.video_seg_index += 1;
         Ok(())
     }
@@ -551,18 +988,31 @@ impl Segmenter {
         let segment_end_time = self.video_current_pts;
         let actual_duration = segment_end_time - base_time;
 
+        // The timeline skips the dropped span, numbering continues at this index
+        if self.disk_guarded() {
+            warn!(
+                "[segmenter] {} disk nearly full, dropping video segment at {}",
+                self.stream, base_time
+            );
+            self.video_samples.clear();
+            self.video_seg_start_dts = self.video_current_pts;
+            self.video_seg_start_wall = chrono::Utc::now().timestamp_micros();
+            return Ok(());
+        }
+
         let writer = self
             .fmp4_writer
             .as_ref()
             .expect("fmp4 writer not initialized");
 
         let fragment = writer.build_fragment(self.video_seg_index, base_time, &self.video_samples);
-        let filename = format!(
-            "{prefix}{index:04}{ext}",
-            prefix = VIDEO_SEGMENT_FILENAME_PREFIX,
-            index = self.video_seg_index,
-            ext = SEGMENT_FILE_EXTENSION
-        );
+        let bytes = fragment.len() as u64;
+        let filename = self
+            .segment_pattern
+            .filename(VIDEO_TRACK_PREFIX, self.video_seg_index);
+        if self.segments.is_empty() {
+            self.expedite(format!("{}/{}", self.path_prefix, filename));
+        }
         self.store_file(&filename, fragment).await.map_err(|e| {
             tracing::error!(
                 "[segmenter] failed to store video segment {} for stream {}: {}",
@@ -573,12 +1023,36 @@ impl Segmenter {
             e
         })?;
         info!("[segmenter] {} {} written", self.stream, filename);
+        self.segments_written.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
 
         // Record the completed segment with its actual duration
         self.segments.push(SegmentInfo {
             start_time: base_time,
             duration: actual_duration,
         });
+        let ticks_per_ms = self.timescale as f64 / 1000.0;
+        self.store_timing(SegmentTiming {
+            file: filename,
+            seq: self.video_seg_index,
+            start_ts: self.video_seg_start_wall,
+            offset_ms: (base_time as f64 / ticks_per_ms).round() as u64,
+            duration_ms: (actual_duration as f64 / ticks_per_ms).round() as u64,
+            bytes,
+        })
+        .await;
+
+        // The first segment of a format gives its framerate
+        let frames = self.video_samples.len() as f64;
+        let timescale = self.timescale as f64;
+        if actual_duration > 0
+            && let Some(info) = self.video_info.as_mut()
+            && info.framerate.is_none()
+        {
+            let fps = frames * timescale / actual_duration as f64;
+            info.framerate = Some((fps * 100.0).round() / 100.0);
+            self.mark_media_changed();
+        }
 
         // Clear the cache and start the next segment
         self.open_new_segment().await?;
@@ -606,16 +1080,27 @@ impl Segmenter {
             return Ok(());
         }
 
+        if self.disk_guarded() {
+            warn!(
+                "[segmenter] {} disk nearly full, dropping audio segment at {}",
+                self.stream, segment_start
+            );
+            self.audio_samples.clear();
+            self.audio_seg_start_pts = self.audio_current_pts;
+            return Ok(());
+        }
+
         self.audio_seg_index += 1;
         let current_index = self.audio_seg_index;
 
         let fragment = writer.build_fragment(current_index, segment_start, &self.audio_samples);
-        let filename = format!(
-            "{prefix}{index:04}{ext}",
-            prefix = AUDIO_SEGMENT_FILENAME_PREFIX,
-            index = current_index,
-            ext = SEGMENT_FILE_EXTENSION
-        );
+        let bytes = fragment.len() as u64;
+        let filename = self
+            .segment_pattern
+            .filename(AUDIO_TRACK_PREFIX, current_index);
+        if self.audio_segments.is_empty() {
+            self.expedite(format!("{}/{}", self.path_prefix, filename));
+        }
         self.store_file(&filename, fragment).await.map_err(|e| {
             tracing::error!(
                 "[segmenter] failed to store audio segment {} for stream {}: {}",
@@ -626,6 +1111,8 @@ impl Segmenter {
             e
         })?;
         info!("[segmenter] {} {} written", self.stream, filename);
+        self.segments_written.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
 
         self.audio_segments.push(SegmentInfo {
             start_time: segment_start,
@@ -639,6 +1126,15 @@ impl Segmenter {
         Ok(())
     }
 
+    /// Write the manifest a finished recording keeps, uploaded without waiting for
+    /// `mpd_upload_interval_ms`
+    async fn write_final_manifest(&self) -> Result<()> {
+        if let Some(uploader) = self.uploader.as_ref() {
+            uploader.upload_final_manifest(format!("{}/{}", self.path_prefix, MANIFEST_FILENAME));
+        }
+        self.write_manifest().await
+    }
+
     async fn write_manifest(&self) -> Result<()> {
         let video_track_ready = self.video_track_id.is_some();
         let audio_track_ready = self.audio_writer.is_some();
@@ -689,114 +1185,130 @@ impl Segmenter {
             media_duration_secs = max_segment_duration_secs;
         }
 
-        let media_presentation_duration = format!("PT{media_duration_secs:.3}S");
-        let max_segment_duration = format!("PT{max_segment_duration_secs:.3}S");
-        let min_buffer_time = if max_segment_duration_secs * 3.0 > 0.0 {
-            format!("PT{:.3}S", max_segment_duration_secs * 3.0)
-        } else {
-            "PT1S".to_string()
-        };
-
-        let mut adaptation_sets = String::new();
-
-        if video_track_ready {
-            let video_bandwidth = if self.total_ticks > 0 {
-                self.total_bytes
-                    .saturating_mul(8)
-                    .saturating_mul(self.timescale as u64)
-                    / self.total_ticks.max(1)
-            } else {
-                0
-            };
-
-            let video_segment_timeline = self.generate_segment_timeline(&self.segments);
-
-            let fps_val = if self.frame_rate > 0 {
-                self.frame_rate
+        let mut mpd = dash::Mpd::new_static();
+        mpd.set_media_presentation_duration(Duration::from_secs_f64(media_duration_secs));
+        mpd.set_max_segment_duration(Duration::from_secs_f64(max_segment_duration_secs));
+        mpd.set_min_buffer_time(Duration::from_secs_f64(max_segment_duration_secs * 3.0));
+        let mut service = Element::new("ServiceDescription");
+        service.set_attr("id", 0);
+        for element in [Element::new("ProgramInformation"), service] {
+            mpd.raw.children.push((0, Node::Element(element)));
+        }
+        for (n, (video, audio)) in self.period_ranges().into_iter().enumerate() {
+            // A publisher that came back without media leaves nothing to play
+            if n > 0 && video.is_empty() && audio.is_empty() {
+                continue;
+            }
+            let start = if n == 0 {
+                Duration::ZERO
             } else {
-                30
+                self.period_start(video.start, audio.start)
             };
+            let mut period = dash::Period::new(&n.to_string(), start);
+
+            if video_track_ready {
+                let video_bandwidth = if self.total_ticks > 0 {
+                    self.total_bytes
+                        .saturating_mul(8)
+                        .saturating_mul(self.timescale as u64)
+                        / self.total_ticks.max(1)
+                } else {
+                    0
+                };
 
-            let par_str = if self.video_width > 0 && self.video_height > 0 {
-                let mut w = self.video_width;
-                let mut h = self.video_height;
-                while h != 0 {
-                    let tmp = h;
-                    h = w % h;
-                    w = tmp;
-                }
-                if w == 0 {
-                    "1:1".to_string()
+                let fps_val = if self.frame_rate > 0 {
+                    self.frame_rate
                 } else {
-                    format!("{}:{}", self.video_width / w, self.video_height / w)
-                }
-            } else {
-                "1:1".to_string()
-            };
+                    30
+                };
+
+                let par_str = if self.video_width > 0 && self.video_height > 0 {
+                    let mut w = self.video_width;
+                    let mut h = self.video_height;
+                    while h != 0 {
+                        let tmp = h;
+                        h = w % h;
+                        w = tmp;
+                    }
+                    if w == 0 {
+                        "1:1".to_string()
+                    } else {
+                        format!("{}:{}", self.video_width / w, self.video_height / w)
+                    }
+                } else {
+                    "1:1".to_string()
+                };
+
+                let mut set = dash::AdaptationSet::new(0, "video");
+                set.raw.set_attr("startWithSAP", 1);
+                set.raw.set_attr("segmentAlignment", true);
+                set.raw.set_attr("bitstreamSwitching", true);
+                set.raw.set_attr("frameRate", format!("{fps_val}/1"));
+                set.raw.set_attr("maxWidth", self.video_width);
+                set.raw.set_attr("maxHeight", self.video_height);
+                set.raw.set_attr("par", par_str);
+                let mut representation =
+                    dash::Representation::new(0, "video/mp4", &self.video_codec, video_bandwidth);
+                representation.raw.set_attr("width", self.video_width);
+                representation.raw.set_attr("height", self.video_height);
+                representation.raw.set_attr("sar", "1:1");
+                representation.segment_template = Some(self.segment_template(
+                    self.timescale as u64,
+                    &self.init_reference(self.video_init_key.as_deref(), VIDEO_INIT_FILENAME),
+                    &self.segment_pattern.template(VIDEO_TRACK_PREFIX),
+                    &self.segments[video.clone()],
+                    video.start,
+                    n > 0,
+                ));
+                set.representations.push(representation);
+                period.adaptation_sets.push(set);
+            }
 
-            let video_section = format!(
-                "        <AdaptationSet id=\"0\" contentType=\"video\" startWithSAP=\"1\" segmentAlignment=\"true\" bitstreamSwitching=\"true\" frameRate=\"{fps}/1\" maxWidth=\"{width}\" maxHeight=\"{height}\" par=\"{par}\">\n            <Representation id=\"0\" mimeType=\"video/mp4\" codecs=\"{codec}\" bandwidth=\"{bandwidth}\" width=\"{width}\" height=\"{height}\" sar=\"1:1\">\n                <SegmentTemplate timescale=\"{timescale}\" initialization=\"{video_init}\" media=\"{video_media}\" startNumber=\"1\">\n{video_timeline}\n                </SegmentTemplate>\n            </Representation>\n        </AdaptationSet>\n",
-                fps = fps_val,
-                width = self.video_width,
-                height = self.video_height,
-                par = par_str,
-                codec = self.video_codec,
-                bandwidth = video_bandwidth,
-                timescale = self.timescale,
-                video_init = VIDEO_INIT_FILENAME,
-                video_media = VIDEO_SEGMENT_TEMPLATE,
-                video_timeline = video_segment_timeline,
-            );
-            adaptation_sets.push_str(&video_section);
+            if audio_track_ready {
+                let writer = self.audio_writer.as_ref().unwrap();
+                let audio_bandwidth = if self.audio_total_ticks > 0 {
+                    self.audio_total_bytes
+                        .saturating_mul(8)
+                        .saturating_mul(writer.timescale as u64)
+                        / self.audio_total_ticks.max(1)
+                } else {
+                    0
+                };
+                let audio_id = if video_track_ready { 1 } else { 0 };
+
+                let mut set = dash::AdaptationSet::new(audio_id, "audio");
+                set.raw.set_attr("segmentAlignment", true);
+                let mut representation = dash::Representation::new(
+                    audio_id,
+                    "audio/mp4",
+                    &writer.codec_string,
+                    audio_bandwidth,
+                );
+                representation
+                    .raw
+                    .set_attr("audioSamplingRate", writer.sample_rate);
+                representation.segment_template = Some(self.segment_template(
+                    writer.timescale as u64,
+                    &self.init_reference(self.audio_init_key.as_deref(), AUDIO_INIT_FILENAME),
+                    &self.segment_pattern.template(AUDIO_TRACK_PREFIX),
+                    &self.audio_segments[audio.clone()],
+                    audio.start,
+                    n > 0,
+                ));
+                set.representations.push(representation);
+                period.adaptation_sets.push(set);
+            }
+            mpd.periods.push(period);
         }
+        for lang in crate::recorder::captions::active_languages(&self.path_prefix) {
+            mpd.set_captions(&lang);
+        }
+        let mpd_body = mpd.to_string();
 
-        if audio_track_ready {
-            let writer = self.audio_writer.as_ref().unwrap();
-            let audio_bandwidth = if self.audio_total_ticks > 0 {
-                self.audio_total_bytes
-                    .saturating_mul(8)
-                    .saturating_mul(writer.timescale as u64)
-                    / self.audio_total_ticks.max(1)
-            } else {
-                0
-            };
-            let audio_segment_timeline = self.generate_segment_timeline(&self.audio_segments);
-            let audio_adaptation_id = if video_track_ready { 1 } else { 0 };
-            let audio_representation_id = if video_track_ready { 1 } else { 0 };
-
-            let audio_section = format!(
-                "        <AdaptationSet id=\"{adapt_id}\" contentType=\"audio\" segmentAlignment=\"true\">\n            <Representation id=\"{rep_id}\" mimeType=\"audio/mp4\" codecs=\"{codec}\" bandwidth=\"{bandwidth}\" audioSamplingRate=\"{sample_rate}\" >\n                <SegmentTemplate timescale=\"{timescale}\" initialization=\"{audio_init}\" media=\"{audio_media}\" startNumber=\"1\">\n{audio_timeline}\n                </SegmentTemplate>\n            </Representation>\n        </AdaptationSet>\n",
-                adapt_id = audio_adaptation_id,
-                rep_id = audio_representation_id,
-                codec = writer.codec_string,
-                bandwidth = audio_bandwidth,
-                sample_rate = writer.sample_rate,
-                timescale = writer.timescale,
-                audio_init = AUDIO_INIT_FILENAME,
-                audio_media = AUDIO_SEGMENT_TEMPLATE,
-                audio_timeline = audio_segment_timeline,
-            );
-            adaptation_sets.push_str(&audio_section);
-        }
-
-        let mpd_body = format!(
-            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
-<MPD xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\"\n\
-     xmlns=\"urn:mpeg:dash:schema:mpd:2011\"\n\
-     xmlns:xlink=\"http://www.w3.org/1999/xlink\"\n\
-     xsi:schemaLocation=\"urn:mpeg:DASH:schema:MPD:2011 http://standards.iso.org/ittf/PubliclyAvailableStandards/MPEG-DASH_schema_files/DASH-MPD.xsd\"\n\
-     profiles=\"urn:mpeg:dash:profile:isoff-live:2011\"\n\
-     type=\"static\"\n\
-     mediaPresentationDuration=\"{media_duration}\"\n\
-     maxSegmentDuration=\"{max_seg_dur}\"\n\
-     minBufferTime=\"{min_buf}\">\n\
-    <ProgramInformation/>\n    <ServiceDescription id=\"0\"/>\n    <Period id=\"0\" start=\"PT0.0S\">\n{adapt_sets}    </Period>\n</MPD>\n",
-            media_duration = media_presentation_duration,
-            max_seg_dur = max_segment_duration,
-            min_buf = min_buffer_time,
-            adapt_sets = adaptation_sets,
-        );
-
+        // Up to the first media segments the manifest makes the recording playable
+        if self.segments.len() <= 1 && self.audio_segments.len() <= 1 {
+            self.expedite(format!("{}/{}", self.path_prefix, MANIFEST_FILENAME));
+        }
         self.store_file(MANIFEST_FILENAME, mpd_body.into_bytes())
             .await
             .map_err(|e| {
@@ -809,29 +1321,126 @@ impl Segmenter {
             })
     }
 
-    /// Generate SegmentTimeline XML from segment info
-    fn generate_segment_timeline(&self, segments: &[SegmentInfo]) -> String {
-        if segments.is_empty() {
-            return "                    <SegmentTimeline></SegmentTimeline>".to_string();
+    /// Append `timing` to `segments.jsonl`. Object stores cannot append, so the whole
+    /// file is stored again; a failed write is only logged, the next segment repeats it
+    /// and playback seeks from the manifest meanwhile.
+    async fn store_timing(&mut self, timing: SegmentTiming) {
+        let Ok(line) = serde_json::to_string(&timing) else {
+            return;
+        };
+        self.segment_timings.push_str(&line);
+        self.segment_timings.push('\n');
+        let body = self.segment_timings.clone().into_bytes();
+        if let Err(e) = self.store_file(SEGMENTS_FILENAME, body).await {
+            warn!(
+                "[segmenter] failed to store {} for stream {}: {}",
+                SEGMENTS_FILENAME, self.stream, e
+            );
+        }
+    }
+
+    /// Per Period, the video and audio segments it holds
+    fn period_ranges(&self) -> Vec<(Range<usize>, Range<usize>)> {
+        let end = PeriodStart {
+            video: self.segments.len(),
+            audio: self.audio_segments.len(),
+        };
+        let starts: Vec<PeriodStart> = std::iter::once(PeriodStart::default())
+            .chain(self.periods.iter().copied())
+            .collect();
+        let ends = starts.iter().skip(1).copied().chain(std::iter::once(end));
+        starts
+            .iter()
+            .zip(ends)
+            .map(|(start, end)| (start.video..end.video, start.audio..end.audio))
+            .collect()
+    }
+
+    /// Start of the Period whose first segments are at these indices, on the timeline
+    fn period_start(&self, video: usize, audio: usize) -> Duration {
+        if let Some(segment) = self.segments.get(video) {
+            return Duration::from_secs_f64(segment.start_time as f64 / self.timescale as f64);
+        }
+        match (self.audio_segments.get(audio), self.audio_writer.as_ref()) {
+            (Some(segment), Some(writer)) => {
+                Duration::from_secs_f64(segment.start_time as f64 / writer.timescale.max(1) as f64)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Segment template of a track, one timeline entry per segment. `first` is the
+    /// index of the first segment, those of a later Period are offset to its start
+    fn segment_template(
+        &self,
+        timescale: u64,
+        initialization: &str,
+        media: &str,
+        segments: &[SegmentInfo],
+        first: usize,
+        later_period: bool,
+    ) -> dash::SegmentTemplate {
+        let mut template = dash::SegmentTemplate::new(timescale, initialization, media);
+        template.set_start_number(first as u64 + 1);
+        if later_period && let Some(segment) = segments.first() {
+            template.set_presentation_time_offset(segment.start_time);
         }
+        if let Some(timeline) = template.timeline.as_mut() {
+            for segment in segments {
+                timeline.append(segment.start_time, segment.duration);
+            }
+        }
+        template
+    }
 
-        let mut timeline = String::from("                    <SegmentTimeline>\n");
+    /// MPD `initialization` attribute, relative to this recording's manifest
+    fn init_reference(&self, shared_key: Option<&str>, name: &str) -> String {
+        match shared_key {
+            Some(key) => storage::relative_to(&self.path_prefix, key),
+            None => name.to_string(),
+        }
+    }
+
+    /// Whether the uploader refuses new files for lack of disk space
+    fn disk_guarded(&self) -> bool {
+        self.uploader
+            .as_ref()
+            .is_some_and(|uploader| uploader.disk_guarded())
+    }
 
-        // Simply output each segment without grouping for now to avoid timeline gaps
-        // DASH players are very sensitive to timeline accuracy
-        for segment in segments {
-            timeline.push_str(&format!(
-                "                        <S t=\"{}\" d=\"{}\" />\n",
-                segment.start_time, segment.duration
-            ));
+    /// Have the uploader send `key` ahead of everything else and right away, for what
+    /// a new recording needs to be played remotely
+    fn expedite(&self, key: String) {
+        if let Some(uploader) = self.uploader.as_ref() {
+            uploader.expedite(key);
         }
+    }
 
-        timeline.push_str("                    </SegmentTimeline>");
-        timeline
+    /// Store an init segment, once per content under `_shared/` when deduplicating.
+    ///
+    /// Returns the shared key, `None` when the per-recording copy `name` was written.
+    async fn store_init(&self, name: &str, data: Vec<u8>) -> Result<Option<String>> {
+        if !self.dedup_init_segments {
+            self.expedite(format!("{}/{}", self.path_prefix, name));
+            self.store_file(name, data).await?;
+            return Ok(None);
+        }
+        let key = storage::shared_init_key(&sha256_hex(&data));
+        if SHARED_INITS.lock().unwrap().insert(key.clone()) {
+            self.expedite(key.clone());
+            self.store_object(key.clone(), data, true).await?;
+        }
+        Ok(Some(key))
     }
 
     async fn store_file(&self, name: &str, data: Vec<u8>) -> Result<()> {
         let path = format!("{}/{}", self.path_prefix, name);
+        self.store_object(path, data, false).await
+    }
+
+    /// Write `data` to `path` in the background. A `shared` object already in storage is
+    /// not rewritten, and is written again by the next recording when this write fails.
+    async fn store_object(&self, path: String, data: Vec<u8>, shared: bool) -> Result<()> {
         let data_size = data.len();
 
         tracing::debug!(
@@ -841,6 +1450,7 @@ impl Segmenter {
             self.stream
         );
 
+        let pending = PENDING_WRITES.begin();
         if let Some(uploader) = self.uploader.as_ref()
             && let Some(local_dir) = self.local_dir.as_ref()
         {
@@ -848,7 +1458,10 @@ impl Segmenter {
             let uploader = uploader.clone();
             let stream_clone = self.stream.clone();
             let path_clone = path.clone();
+            let tagging = if shared { None } else { self.tagging.clone() };
+            let priority = self.priority;
             tokio::spawn(async move {
+                let _pending = pending;
                 if let Some(parent) = local_path.parent()
                     && let Err(e) = tokio::fs::create_dir_all(parent).await
                 {
@@ -857,6 +1470,7 @@ impl Segmenter {
                         path_clone,
                         e
                     );
+                    forget_shared(shared, &path_clone);
                     return;
                 }
                 if let Err(e) = tokio::fs::write(&local_path, data).await {
@@ -866,37 +1480,94 @@ impl Segmenter {
                         stream_clone,
                         e
                     );
+                    forget_shared(shared, &path_clone);
                     return;
                 }
                 if let Err(e) = uploader
-                    .enqueue(path_clone.clone(), local_path.to_string_lossy().to_string())
+                    .stage(
+                        path_clone.clone(),
+                        &local_path,
+                        &stream_clone,
+                        tagging,
+                        priority,
+                    )
                     .await
                 {
-                    tracing::warn!("[segmenter] failed to enqueue upload {}: {}", path_clone, e);
+                    if e.downcast_ref::<DiskFull>().is_some() {
+                        // Never uploaded, don't leave it taking up the space
+                        let _ = tokio::fs::remove_file(&local_path).await;
+                    }
+                    tracing::warn!(
+                        "[segmenter] failed to enqueue upload {}: {:#}",
+                        path_clone,
+                        e
+                    );
+                    forget_shared(shared, &path_clone);
                 }
             });
         } else {
             // Clone what we need for the background task.
-            let op_clone = self.op.clone();
+            let op_clone = self.op.current();
             let stream_clone = self.stream.clone();
             let path_clone = path.clone();
+            let verify_size = self.verify_size;
+            let data = Bytes::from(data);
 
             // Spawn the actual write in a detached task so that slow/object‐storage latency does
             // not block the real‐time RTP processing loop. Any error will be logged.
             tokio::spawn(async move {
-                if let Err(e) = op_clone.write(&path_clone, data).await {
+                let _pending = pending;
+                // Held in memory until storage answers after startup
+                crate::recorder::startup_ready().await;
+                if shared && op_clone.exists(&path_clone).await.unwrap_or(false) {
+                    tracing::debug!("[segmenter] shared file {} already stored", path_clone);
+                    return;
+                }
+                let write = || {
+                    op_clone
+                        .write_with(&path_clone, data.clone())
+                        .content_type(storage::content_type_for(&path_clone))
+                };
+                let mut written = write().await.map(|_| ());
+                if written.is_ok() && verify_size {
+                    match op_clone.stat(&path_clone).await {
+                        Ok(meta) if meta.content_length() != data.len() as u64 => {
+                            crate::metrics::RECORDER_UPLOAD_MISMATCHES.inc();
+                            tracing::warn!(
+                                "[segmenter] stored {} has {} bytes instead of {}, writing it again",
+                                path_clone,
+                                meta.content_length(),
+                                data.len()
+                            );
+                            written = write().await.map(|_| ());
+                        }
+                        Ok(_) => crate::metrics::RECORDER_UPLOADS_VERIFIED.inc(),
+                        Err(e) => tracing::warn!(
+                            "[segmenter] failed to verify the size of {}: {}",
+                            path_clone,
+                            e
+                        ),
+                    }
+                }
+                if let Err(e) = written {
                     tracing::warn!(
                         "[segmenter] failed to write file {} (stream {}): {}",
                         path_clone,
                         stream_clone,
                         e
                     );
+                    forget_shared(shared, &path_clone);
                 } else {
                     tracing::debug!(
                         "[segmenter] successfully stored file {} for stream {}",
                         path_clone,
                         stream_clone
                     );
+                    if path_clone.ends_with(MANIFEST_FILENAME)
+                        || path_clone.ends_with(SEGMENTS_FILENAME)
+                    {
+                        crate::recorder::invalidate(&path_clone).await;
+                    }
                 }
             });
         }
@@ -907,6 +1578,20 @@ impl Segmenter {
     }
 }
 
+/// Let the next recording write a shared object whose write failed
+fn forget_shared(shared: bool, key: &str) {
+    if shared {
+        SHARED_INITS.lock().unwrap().remove(key);
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
 fn parse_channels_from_fmtp(fmtp: &str) -> Option<u16> {
     fmtp.split(';').map(str::trim).find_map(|part| {
         if let Some(value) = part.strip_prefix("channels=") {
@@ -929,3 +1614,362 @@ fn parse_channels_from_fmtp(fmtp: &str) -> Option<u16> {
         None
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opendal::Operator;
+    use opendal::services::Fs;
+
+    const SPS: &[u8] = &[0x67, 0x42, 0xE0, 0x1E, 0x8D, 0x68, 0x50];
+    const PPS: &[u8] = &[0x68, 0xCE, 0x3C, 0x80];
+
+    fn keyframe() -> Bytes {
+        let mut frame = Vec::new();
+        for nalu in [SPS, PPS, &[0x65, 0x88, 0x84, 0x00, 0x33]] {
+            frame.extend_from_slice(&[0, 0, 0, 1]);
+            frame.extend_from_slice(nalu);
+        }
+        Bytes::from(frame)
+    }
+
+    fn delta_frame() -> Bytes {
+        Bytes::from_static(&[0, 0, 0, 1, 0x41, 0x9A, 0x02, 0x04, 0x11])
+    }
+
+    async fn wait_for(root: &std::path::Path, path: &str, needle: &str) -> bool {
+        // Writes are detached from the push path, give them a moment to land
+        for _ in 0..50 {
+            if let Ok(body) = tokio::fs::read(root.join(path)).await
+                && String::from_utf8_lossy(&body).contains(needle)
+            {
+                return true;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn split_at_keyframe_produces_two_playable_recordings() {
+        let dir = tempfile::tempdir().unwrap();
+        let op = Operator::new(Fs::default().root(dir.path().to_str().unwrap()))
+            .unwrap()
+            .finish();
+        let mut seg = Segmenter::new(op.into(), "cam".into(), "cam/1000000000".into(), None, None)
+            .await
+            .unwrap();
+
+        seg.push_h264(keyframe(), 3_000).await.unwrap();
+        for _ in 0..29 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+
+        seg.request_split("cam/1000000001".into());
+        // Delta frames stay in the current recording until the next keyframe
+        seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        assert!(seg.take_split().is_none());
+
+        seg.push_h264(keyframe(), 3_000).await.unwrap();
+        let split = seg.take_split().unwrap();
+        assert_eq!(split.previous_prefix, "cam/1000000000");
+        assert_eq!(split.next_prefix, "cam/1000000001");
+        assert_eq!(split.previous_size.segments, 1);
+        assert_eq!(seg.segments_written().load(Ordering::Relaxed), 0);
+        for _ in 0..9 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+        seg.flush().await.unwrap();
+
+        for prefix in ["cam/1000000000", "cam/1000000001"] {
+            assert!(wait_for(dir.path(), &format!("{prefix}/{VIDEO_INIT_FILENAME}"), "").await);
+            assert!(wait_for(dir.path(), &format!("{prefix}/v_seg_0001.m4s"), "").await);
+        }
+        let stored = std::fs::metadata(dir.path().join("cam/1000000000/v_seg_0001.m4s"))
+            .unwrap()
+            .len();
+        assert_eq!(split.previous_size.bytes, stored);
+
+        // Every sample lands on exactly one side of the boundary
+        assert!(
+            wait_for(
+                dir.path(),
+                "cam/1000000000/manifest.mpd",
+                "<S t=\"0\" d=\"93000\" />"
+            )
+            .await
+        );
+        assert!(
+            wait_for(
+                dir.path(),
+                "cam/1000000001/manifest.mpd",
+                "<S t=\"0\" d=\"30000\" />"
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn publisher_gap_resumes_at_keyframe() {
+        let dir = tempfile::tempdir().unwrap();
+        let op = Operator::new(Fs::default().root(dir.path().to_str().unwrap()))
+            .unwrap()
+            .finish();
+        let mut seg = Segmenter::new(op.into(), "cam".into(), "cam/1000000000".into(), None, None)
+            .await
+            .unwrap();
+
+        seg.push_h264(keyframe(), 3_000).await.unwrap();
+        for _ in 0..9 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+        seg.publisher_gap().await.unwrap();
+        assert!(seg.should_request_keyframe());
+
+        // The new publisher's frames before its first keyframe can't be decoded
+        for _ in 0..5 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+        assert!(seg.video_samples.is_empty());
+
+        seg.push_h264(keyframe(), 3_000).await.unwrap();
+        for _ in 0..4 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+        seg.flush().await.unwrap();
+        assert!(seg.take_split().is_none());
+
+        // One recording, the gap closed one segment and the timeline skips it
+        assert!(
+            wait_for(
+                dir.path(),
+                "cam/1000000000/manifest.mpd",
+                "<S t=\"0\" d=\"30000\" />\n                        <S t=\"30000\" d=\"15000\" />"
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn resumed_recording_gets_a_period_per_publisher() {
+        let dir = tempfile::tempdir().unwrap();
+        let op = Operator::new(Fs::default().root(dir.path().to_str().unwrap()))
+            .unwrap()
+            .finish();
+        let mut seg = Segmenter::new(op.into(), "cam".into(), "cam/1000000000".into(), None, None)
+            .await
+            .unwrap();
+
+        seg.push_h264(keyframe(), 3_000).await.unwrap();
+        for _ in 0..9 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+        // Flushed as the task ends, then continued for the returning publisher
+        seg.flush().await.unwrap();
+        seg.begin_period();
+        seg.begin_period();
+        for _ in 0..3 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+        seg.push_h264(keyframe(), 3_000).await.unwrap();
+        for _ in 0..4 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+        seg.flush().await.unwrap();
+
+        // The second period numbers on and is offset to its own start
+        let mpd = "cam/1000000000/manifest.mpd";
+        assert!(wait_for(dir.path(), mpd, "<S t=\"30000\" d=\"15000\" />").await);
+        let body = std::fs::read_to_string(dir.path().join(mpd)).unwrap();
+        let parsed: dash::Mpd = body.parse().unwrap();
+        assert_eq!(parsed.periods.len(), 2, "{body}");
+        assert_eq!(parsed.periods[1].id(), Some("1"));
+        assert!(parsed.periods[1].start().unwrap() > Duration::ZERO);
+        let template = parsed.periods[1].adaptation_sets[0].representations[0]
+            .segment_template
+            .as_ref()
+            .unwrap();
+        assert_eq!(template.start_number(), 2);
+        assert_eq!(template.presentation_time_offset(), 30_000);
+        assert!(dir.path().join("cam/1000000000/v_seg_0001.m4s").exists());
+        assert!(dir.path().join("cam/1000000000/v_seg_0002.m4s").exists());
+    }
+
+    #[tokio::test]
+    async fn dedup_init_segments_share_one_object() {
+        let dir = tempfile::tempdir().unwrap();
+        let op = Operator::new(Fs::default().root(dir.path().to_str().unwrap()))
+            .unwrap()
+            .finish();
+        let mut seg = Segmenter::new(op.into(), "cam".into(), "cam/1000000000".into(), None, None)
+            .await
+            .unwrap();
+        seg.set_dedup_init_segments(true);
+
+        seg.push_h264(keyframe(), 3_000).await.unwrap();
+        for _ in 0..9 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+        seg.request_split("cam/1000000001".into());
+        seg.push_h264(keyframe(), 3_000).await.unwrap();
+        for _ in 0..9 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+        seg.flush().await.unwrap();
+
+        let key = seg.video_init_key.clone().unwrap();
+        assert!(key.starts_with("_shared/init/"), "{key}");
+        assert!(wait_for(dir.path(), &key, "").await);
+        let reference = format!("initialization=\"../../{key}\"");
+        for prefix in ["cam/1000000000", "cam/1000000001"] {
+            assert!(wait_for(dir.path(), &format!("{prefix}/manifest.mpd"), &reference).await);
+            assert!(!dir.path().join(prefix).join(VIDEO_INIT_FILENAME).exists());
+        }
+        // The manifest reference resolves back to the shared object
+        assert_eq!(
+            storage::resolve_relative("cam/1000000001", &format!("../../{key}")),
+            key
+        );
+    }
+
+    #[tokio::test]
+    async fn segment_pattern_names_agree() {
+        let dir = tempfile::tempdir().unwrap();
+        let op = Operator::new(Fs::default().root(dir.path().to_str().unwrap()))
+            .unwrap()
+            .finish();
+        let local = dir.path().join("local");
+        let staging = dir.path().join("staging");
+        let uploader =
+            crate::recorder::uploader::UploadManager::load(crate::config::UploadConfig {
+                queue_path: dir.path().join("queue.jsonl").display().to_string(),
+                local_dir: local.display().to_string(),
+                staging_dir: staging.display().to_string(),
+                local_retention_minutes: 60,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let mut seg = Segmenter::new(
+            op.into(),
+            "cam".into(),
+            "cam/1000000000".into(),
+            Some(std::sync::Arc::new(uploader)),
+            Some(local.display().to_string()),
+        )
+        .await
+        .unwrap();
+        seg.set_segment_pattern(SegmentPattern::parse("segment_%06d.m4s").unwrap());
+
+        // Two segments, the second starting at the keyframe past the segment duration
+        seg.push_h264(keyframe(), 3_000).await.unwrap();
+        for _ in 0..299 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+        seg.push_h264(keyframe(), 3_000).await.unwrap();
+        for _ in 0..9 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+        seg.flush().await.unwrap();
+
+        let media = "media=\"v_segment_$Number%06d$.m4s\"";
+        assert!(wait_for(&local, "cam/1000000000/manifest.mpd", "<S t=\"900000\"").await);
+        PENDING_WRITES.wait_idle().await;
+        let mpd = std::fs::read_to_string(local.join("cam/1000000000/manifest.mpd")).unwrap();
+        assert!(mpd.contains(media), "{mpd}");
+        assert_eq!(mpd.matches("<S ").count(), 2, "{mpd}");
+
+        // The local files and the staged objects are named like the manifest expands
+        for n in 1..=2 {
+            let name = format!("cam/1000000000/v_segment_{n:06}.m4s");
+            assert!(local.join(&name).exists(), "{name}");
+            assert!(staging.join(&name).exists(), "{name}");
+            assert_eq!(storage::content_type_for(&name), "video/mp4");
+        }
+        assert!(!local.join("cam/1000000000/v_seg_0001.m4s").exists());
+    }
+
+    #[tokio::test]
+    async fn check_keys_counts_bytes_of_widest_segment() {
+        let dir = tempfile::tempdir().unwrap();
+        let op = Operator::new(Fs::default().root(dir.path().to_str().unwrap()))
+            .unwrap()
+            .finish();
+        let segmenter = |stream: String| {
+            let prefix = storage::record_dir(Some("edge-1"), &stream, 1_700_000_000);
+            Segmenter::new(op.clone().into(), stream, prefix, None, None)
+        };
+
+        // 327 characters, 981 bytes: `v_seg_4294967295.m4s` still fits
+        let seg = segmenter("摄像头".repeat(109)).await.unwrap();
+        assert_eq!(seg.check_keys(), Ok(()));
+        let mut seg = segmenter("摄像头".repeat(109)).await.unwrap();
+        seg.set_segment_pattern(SegmentPattern::parse("segment_%06d.m4s").unwrap());
+        assert_eq!(seg.check_keys(), Ok(()));
+        seg.set_segment_pattern(SegmentPattern::parse("segments_%06d.m4s").unwrap());
+        assert_eq!(
+            seg.check_keys(),
+            Err(storage::KeyError::TooLong { len: 1025 })
+        );
+
+        let seg = segmenter("摄像头".repeat(110)).await.unwrap();
+        assert_eq!(
+            seg.check_keys(),
+            Err(storage::KeyError::TooLong { len: 1029 })
+        );
+    }
+
+    fn timings(root: &std::path::Path) -> Vec<SegmentTiming> {
+        std::fs::read_to_string(root.join("cam/1000000000").join(SEGMENTS_FILENAME))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn segment_timings_appended_per_segment() {
+        let dir = tempfile::tempdir().unwrap();
+        let op = Operator::new(Fs::default().root(dir.path().to_str().unwrap()))
+            .unwrap()
+            .finish();
+        let mut seg = Segmenter::new(op.into(), "cam".into(), "cam/1000000000".into(), None, None)
+            .await
+            .unwrap();
+
+        seg.push_h264(keyframe(), 3_000).await.unwrap();
+        for _ in 0..299 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+        // The keyframe closes the first segment, its line is there mid-recording
+        seg.push_h264(keyframe(), 3_000).await.unwrap();
+        PENDING_WRITES.wait_idle().await;
+        assert_eq!(timings(dir.path()).len(), 1);
+
+        for _ in 0..9 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+        seg.flush().await.unwrap();
+        PENDING_WRITES.wait_idle().await;
+
+        let timings = timings(dir.path());
+        assert_eq!(timings.len(), 2);
+        assert_eq!(
+            timings
+                .iter()
+                .map(|t| (t.file.as_str(), t.seq, t.offset_ms, t.duration_ms))
+                .collect::<Vec<_>>(),
+            [
+                ("v_seg_0001.m4s", 1, 0, 10_000),
+                ("v_seg_0002.m4s", 2, 10_000, 333)
+            ]
+        );
+        assert!(timings[0].start_ts > 0 && timings[0].start_ts <= timings[1].start_ts);
+        for timing in &timings {
+            let size = std::fs::metadata(dir.path().join("cam/1000000000").join(&timing.file))
+                .unwrap()
+                .len();
+            assert_eq!(timing.bytes, size);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use api::recorder::RecordingStatus;
+use tokio::task::JoinSet;
+
+use super::clock::{Clock, SessionEnd};
+use super::index::RecordingsIndex;
+use super::segmenter::PENDING_WRITES;
+use super::task::{RecordingStopOutcome, RecordingTask};
+use super::{RecordingInfo, record_key};
+
+/// Stop `tasks` and finalize their index entries.
+///
+/// Each task flushes its partial segment and writes its final manifest, entries are
+/// only marked finished once every detached write has landed. Whatever is not done
+/// by `deadline` is marked [`RecordingStatus::Interrupted`]. Returns how many were.
+pub(super) async fn finalize_tasks(
+    tasks: Vec<RecordingTask>,
+    index: Option<Arc<RecordingsIndex>>,
+    deadline: Duration,
+) -> usize {
+    let recordings: Vec<(String, RecordingInfo, Arc<dyn Clock>)> = tasks
+        .iter()
+        .map(|task| (task.stream.clone(), task.info.clone(), task.clock()))
+        .collect();
+
+    let mut stopping = JoinSet::new();
+    for task in tasks {
+        stopping.spawn(async move {
+            let stream = task.stream.clone();
+            (stream, task.stop().await)
+        });
+    }
+
+    let mut outcomes: Vec<(String, RecordingStopOutcome)> = Vec::new();
+    let flushed = tokio::time::timeout(deadline, async {
+        while let Some(joined) = stopping.join_next().await {
+            match joined {
+                Ok(outcome) => outcomes.push(outcome),
+                Err(e) => tracing::error!("[recorder] stop task failed during shutdown: {}", e),
+            }
+        }
+        PENDING_WRITES.wait_idle().await;
+    })
+    .await
+    .is_ok();
+    // Abort stops still running past the deadline
+    drop(stopping);
+
+    let mut interrupted = 0;
+    for (stream, info, clock) in recordings {
+        let outcome = outcomes
+            .iter()
+            .position(|(s, _)| *s == stream)
+            .map(|i| outcomes.swap_remove(i).1);
+        let (status, end, size) = match outcome {
+            Some(outcome) if flushed => (
+                outcome.status.clone(),
+                outcome.session_end(),
+                Some(outcome.size),
+            ),
+            _ => {
+                interrupted += 1;
+                let end = SessionEnd::measure(clock.as_ref(), info.start_ts_micros, info.started);
+                // Not every segment counted may have landed
+                (RecordingStatus::Interrupted, end, None)
+            }
+        };
+        tracing::info!(
+            "[recorder] shutdown finalized {} as {}",
+            info.record_dir,
+            status
+        );
+        if let Some(ref index) = index
+            && let Err(e) = index
+                .update_status(&stream, &record_key(&info), status, end, size)
+                .await
+        {
+            tracing::error!("[recorder] index.json update failed: {}", e);
+        }
+    }
+    interrupted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::clock::{self, manual::ManualClock};
+    use crate::recorder::segmenter::Segmenter;
+    use bytes::Bytes;
+    use opendal::Operator;
+    use opendal::services::Fs;
+    use tokio::sync::oneshot;
+
+    fn keyframe() -> Bytes {
+        let mut frame = Vec::new();
+        for nalu in [
+            &[0x67, 0x42, 0xE0, 0x1E, 0x8D, 0x68, 0x50][..],
+            &[0x68, 0xCE, 0x3C, 0x80],
+            &[0x65, 0x88, 0x84, 0x00, 0x33],
+        ] {
+            frame.extend_from_slice(&[0, 0, 0, 1]);
+            frame.extend_from_slice(nalu);
+        }
+        Bytes::from(frame)
+    }
+
+    fn delta_frame() -> Bytes {
+        Bytes::from_static(&[0, 0, 0, 1, 0x41, 0x9A, 0x02, 0x04, 0x11])
+    }
+
+    async fn index_with(
+        dir: &std::path::Path,
+        stream: &str,
+        info: &RecordingInfo,
+    ) -> Arc<RecordingsIndex> {
+        let index = RecordingsIndex::load(dir.join("index.json")).await.unwrap();
+        index
+            .upsert(api::recorder::RecordingIndexEntry {
+                uuid: String::new(),
+                record: record_key(info),
+                stream: stream.to_string(),
+                record_dir: info.record_dir.clone(),
+                mpd_path: format!("{}/manifest.mpd", info.record_dir),
+                start_ts: info.start_ts_micros,
+                end_ts: None,
+                duration_ms: None,
+                status: RecordingStatus::Active,
+                node_alias: None,
+                updated_at: info.start_ts_micros,
+                note: None,
+                labels: Vec::new(),
+                continues: None,
+                media_info: Vec::new(),
+                retention_class: None,
+                trashed_at: None,
+                trashed_from: None,
+                repair_error: None,
+                source: None,
+                replicas: Vec::new(),
+                clock_skew_detected: false,
+                priority: api::recorder::DEFAULT_PRIORITY,
+                tenant: None,
+                size: None,
+                captions: Vec::new(),
+                trigger: None,
+                imported: false,
+                seq: 0,
+            })
+            .await
+            .unwrap();
+        Arc::new(index)
+    }
+
+    fn info(record_dir: &str, clock: &dyn Clock) -> RecordingInfo {
+        RecordingInfo {
+            record_dir: record_dir.to_string(),
+            record_id: 1_000_000_000,
+            start_ts_micros: clock.wall_micros(),
+            started: clock.monotonic(),
+            retention_class: None,
+            priority: api::recorder::DEFAULT_PRIORITY,
+            source: None,
+            tenant: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_finalizes_manifest_and_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let op = Operator::new(Fs::default().root(dir.path().to_str().unwrap()))
+            .unwrap()
+            .finish();
+        let clock = clock::system();
+        let info = info("cam/1000000000", clock.as_ref());
+        let index = index_with(dir.path(), "cam", &info).await;
+
+        let mut seg = Segmenter::new(op.into(), "cam".into(), info.record_dir.clone(), None, None)
+            .await
+            .unwrap();
+        seg.push_h264(keyframe(), 3_000).await.unwrap();
+        for _ in 0..9 {
+            seg.push_h264(delta_frame(), 3_000).await.unwrap();
+        }
+
+        // The partial segment is only written once the shutdown signal arrives
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let handle = tokio::spawn(async move {
+            let _ = shutdown_rx.await;
+            seg.flush().await.unwrap();
+        });
+        let task = RecordingTask::from_parts("cam", info.clone(), handle, shutdown_tx, clock);
+
+        let interrupted =
+            finalize_tasks(vec![task], Some(index.clone()), Duration::from_secs(5)).await;
+        assert_eq!(interrupted, 0);
+
+        // No waiting: shutdown returns only after the writes landed
+        let mpd = std::fs::read_to_string(dir.path().join("cam/1000000000/manifest.mpd")).unwrap();
+        assert!(mpd.contains("<S t=\"0\" d=\"30000\" />"), "{mpd}");
+        assert!(dir.path().join("cam/1000000000/v_seg_0001.m4s").exists());
+
+        let entry = index.find_by_dir("cam/1000000000").await.unwrap();
+        assert!(matches!(entry.status, RecordingStatus::Completed));
+        assert!(entry.end_ts.unwrap() >= info.start_ts_micros);
+        assert!(entry.duration_ms.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_past_deadline_marks_interrupted() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = clock::system();
+        let info = info("cam/1000000001", clock.as_ref());
+        let index = index_with(dir.path(), "cam", &info).await;
+
+        // A recording loop that never reacts to the shutdown signal
+        let (shutdown_tx, _shutdown_rx) = oneshot::channel();
+        let handle = tokio::spawn(std::future::pending::<()>());
+        let task = RecordingTask::from_parts("cam", info.clone(), handle, shutdown_tx, clock);
+
+        let interrupted =
+            finalize_tasks(vec![task], Some(index.clone()), Duration::from_millis(50)).await;
+        assert_eq!(interrupted, 1);
+
+        let entry = index.find_by_dir("cam/1000000001").await.unwrap();
+        assert!(matches!(entry.status, RecordingStatus::Interrupted));
+        assert!(entry.end_ts.is_some());
+        assert!(!entry.clock_skew_detected);
+    }
+
+    #[tokio::test]
+    async fn test_clock_step_during_recording() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = ManualClock::new(1_700_000_000_000_000);
+        let info = info("cam/1700000000", clock.as_ref());
+        let index = index_with(dir.path(), "cam", &info).await;
+
+        // NTP steps the wall clock an hour back 30 s into the recording
+        clock.advance(Duration::from_secs(30));
+        clock.step_wall(-3_600_000_000);
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let handle = tokio::spawn(async move {
+            let _ = shutdown_rx.await;
+        });
+        let task = RecordingTask::from_parts("cam", info.clone(), handle, shutdown_tx, clock);
+        finalize_tasks(vec![task], Some(index.clone()), Duration::from_secs(5)).await;
+
+        let entry = index.find_by_dir("cam/1700000000").await.unwrap();
+        assert!(matches!(entry.status, RecordingStatus::Completed));
+        assert_eq!(entry.duration_ms, Some(30_000));
+        assert_eq!(entry.end_ts, Some(info.start_ts_micros));
+        assert!(entry.clock_skew_detected);
+    }
+}
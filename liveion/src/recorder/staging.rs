@@ -0,0 +1,224 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+/// Distinguishes temp files of concurrent stagings of the same object
+static TMP_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Place `src` at `dst` inside the upload staging area.
+///
+/// The file is hard linked next to `dst` and renamed over it, so readers only
+/// ever see a complete file and a newer manifest replaces an older one. When the
+/// staging area lives on another filesystem the link fails and the file is
+/// copied to the temp name instead. With `keep_source` false `src` is removed
+/// once staged, otherwise it stays for the local retention window.
+pub async fn stage_file(src: &Path, dst: &Path, keep_source: bool) -> io::Result<()> {
+    stage_file_with(src, dst, keep_source, |src, tmp| {
+        std::fs::hard_link(src, tmp)
+    })
+    .await
+}
+
+async fn stage_file_with(
+    src: &Path,
+    dst: &Path,
+    keep_source: bool,
+    link: impl FnOnce(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    if let Some(parent) = dst.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp = tmp_path_for(dst);
+    match link(src, &tmp) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            tracing::debug!(
+                "[staging] {} is on another filesystem, copying",
+                dst.display()
+            );
+            if let Err(e) = tokio::fs::copy(src, &tmp).await {
+                let _ = tokio::fs::remove_file(&tmp).await;
+                return Err(e);
+            }
+        }
+        Err(e) => return Err(e),
+    }
+    if let Err(e) = tokio::fs::rename(&tmp, dst).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e);
+    }
+    if !keep_source {
+        tokio::fs::remove_file(src).await?;
+    }
+    Ok(())
+}
+
+fn tmp_path_for(dst: &Path) -> PathBuf {
+    let seq = TMP_SEQ.fetch_add(1, Ordering::Relaxed);
+    let mut name = dst.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.{seq}.tmp", std::process::id()));
+    dst.with_file_name(name)
+}
+
+/// Remove segments and manifests under `dir` last modified more than `retention`
+/// ago, skipping `exclude`. Other files (queue, index) are never touched.
+///
+/// Returns how many files were removed.
+pub async fn prune_local(dir: &Path, exclude: &[PathBuf], retention: Duration) -> usize {
+    let Some(cutoff) = SystemTime::now().checked_sub(retention) else {
+        return 0;
+    };
+    let mut removed = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(mut entries) = tokio::fs::read_dir(&current).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if exclude.iter().any(|excluded| path.starts_with(excluded)) {
+                continue;
+            }
+            let Ok(meta) = entry.metadata().await else {
+                continue;
+            };
+            if meta.is_dir() {
+                pending.push(path);
+            } else if is_recording_file(&path)
+                && meta.modified().is_ok_and(|modified| modified < cutoff)
+                && tokio::fs::remove_file(&path).await.is_ok()
+            {
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
+
+fn is_recording_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("m4s" | "mpd")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn inode(path: &Path) -> u64 {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::metadata(path).unwrap().ino()
+    }
+
+    #[tokio::test]
+    async fn test_stage_same_filesystem_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("record/cam/1/v_seg_0001.m4s");
+        let dst = dir.path().join("staging/cam/1/v_seg_0001.m4s");
+        std::fs::create_dir_all(src.parent().unwrap()).unwrap();
+        std::fs::write(&src, b"segment").unwrap();
+
+        stage_file(&src, &dst, true).await.unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), b"segment");
+        assert!(src.exists());
+        #[cfg(unix)]
+        assert_eq!(inode(&src), inode(&dst));
+
+        // A newer manifest replaces the staged one in place
+        let mpd = dir.path().join("record/cam/1/manifest.mpd");
+        let staged_mpd = dir.path().join("staging/cam/1/manifest.mpd");
+        std::fs::write(&mpd, b"v1").unwrap();
+        stage_file(&mpd, &staged_mpd, false).await.unwrap();
+        std::fs::write(&mpd, b"v2").unwrap();
+        stage_file(&mpd, &staged_mpd, false).await.unwrap();
+        assert_eq!(std::fs::read(&staged_mpd).unwrap(), b"v2");
+        assert!(!mpd.exists());
+
+        let leftovers: Vec<_> = std::fs::read_dir(staged_mpd.parent().unwrap())
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stage_cross_filesystem_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("record/v_seg_0001.m4s");
+        let dst = dir.path().join("staging/v_seg_0001.m4s");
+        std::fs::create_dir_all(src.parent().unwrap()).unwrap();
+        std::fs::write(&src, b"segment").unwrap();
+
+        let cross_device = |_: &Path, _: &Path| Err(io::Error::from(io::ErrorKind::CrossesDevices));
+        stage_file_with(&src, &dst, true, cross_device)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), b"segment");
+        #[cfg(unix)]
+        assert_ne!(inode(&src), inode(&dst));
+
+        // Without retention the source is gone once the copy landed
+        std::fs::write(&src, b"segment v2").unwrap();
+        stage_file_with(&src, &dst, false, cross_device)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), b"segment v2");
+        assert!(!src.exists());
+    }
+
+    #[tokio::test]
+    async fn test_stage_failure_keeps_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("v_seg_0001.m4s");
+        let dst = dir.path().join("staging/v_seg_0001.m4s");
+        std::fs::write(&src, b"segment").unwrap();
+
+        let denied = |_: &Path, _: &Path| Err(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(stage_file_with(&src, &dst, false, denied).await.is_err());
+        assert!(src.exists());
+        assert!(!dst.exists());
+    }
+
+    #[tokio::test]
+    async fn test_prune_local_skips_staging() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("cam/1/v_seg_0001.m4s");
+        let staged = dir.path().join(".staging/cam/1/v_seg_0001.m4s");
+        for path in [&old, &staged] {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"segment").unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(SystemTime::now() - Duration::from_secs(3600))
+                .unwrap();
+        }
+        let fresh = dir.path().join("cam/1/v_seg_0002.m4s");
+        std::fs::write(&fresh, b"segment").unwrap();
+        let queue = dir.path().join("upload_queue.jsonl");
+        std::fs::write(&queue, b"").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&queue)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(3600))
+            .unwrap();
+
+        let removed = prune_local(
+            dir.path(),
+            &[dir.path().join(".staging")],
+            Duration::from_secs(60),
+        )
+        .await;
+        assert_eq!(removed, 1);
+        assert!(!old.exists());
+        assert!(fresh.exists());
+        assert!(staged.exists());
+        assert!(queue.exists());
+    }
+}
@@ -0,0 +1,224 @@
+//! Watchdog of recordings whose publisher stays connected but sends no media.
+//!
+//! A frozen encoder keeps its WHIP session up, so nothing ends its recording: it stays
+//! `Active` without new segments until someone needs the footage. The recording loop
+//! feeds [`StallWatchdog`] every sample it gets. After `stall_timeout_seconds` without
+//! one the recording is stalled: it sends a `recordingStalled` webhook, counts in
+//! `recordings_stalled_total`, shows in the status and health APIs and gets
+//! `recorder.stall_action`. A publisher gone altogether is the reconnect grace's to
+//! handle, the watchdog sits that out.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::time::Instant;
+
+/// Stall of a recording, shared between its loop and the APIs
+#[derive(Debug, Default)]
+pub struct StallState {
+    /// Last sample before the stall, UNIX microseconds, 0 while samples arrive
+    since: AtomicI64,
+}
+
+impl StallState {
+    /// Last sample of a stalled recording, `None` while it is not
+    pub fn since(&self) -> Option<i64> {
+        match self.since.load(Ordering::Relaxed) {
+            0 => None,
+            since => Some(since),
+        }
+    }
+}
+
+pub struct StallWatchdog {
+    /// Zero turns the watchdog off
+    timeout: Duration,
+    last_sample: Instant,
+    state: Arc<StallState>,
+}
+
+impl StallWatchdog {
+    pub fn new(timeout: Duration, state: Arc<StallState>) -> Self {
+        Self {
+            timeout,
+            last_sample: Instant::now(),
+            state,
+        }
+    }
+
+    /// When the recording stalls without another sample, `None` while it is stalled
+    /// already or the watchdog is off
+    pub fn deadline(&self) -> Option<Instant> {
+        (!self.timeout.is_zero() && self.state.since().is_none())
+            .then(|| self.last_sample + self.timeout)
+    }
+
+    /// Wait for [`Self::deadline`], forever without one
+    pub async fn expired(&self) {
+        match self.deadline() {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Mark the recording stalled once its deadline passed, returns how long it got no
+    /// sample
+    pub fn stall(&mut self) -> Duration {
+        let quiet = self.last_sample.elapsed();
+        let since = Utc::now().timestamp_micros() - quiet.as_micros() as i64;
+        if self.state.since.swap(since.max(1), Ordering::Relaxed) == 0 {
+            crate::metrics::RECORDINGS_STALLED.inc();
+            crate::metrics::RECORDER_STALLED.inc();
+        }
+        quiet
+    }
+
+    /// A sample arrived, returns how long the recording was without one when it was
+    /// stalled
+    pub fn sample(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        let stalled = self.clear().then(|| now - self.last_sample);
+        self.last_sample = now;
+        stalled
+    }
+
+    /// Start over, for a publisher that left or came back
+    pub fn reset(&mut self) {
+        self.clear();
+        self.last_sample = Instant::now();
+    }
+
+    /// Returns whether the recording was stalled
+    fn clear(&self) -> bool {
+        let stalled = self.state.since.swap(0, Ordering::Relaxed) != 0;
+        if stalled {
+            crate::metrics::RECORDER_STALLED.dec();
+        }
+        stalled
+    }
+}
+
+impl Drop for StallWatchdog {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::{mpsc, watch};
+
+    const TIMEOUT: Duration = Duration::from_millis(200);
+
+    /// Sends a sample every 10 ms while not paused, like an encoder that can freeze
+    struct SyntheticSource {
+        paused: watch::Sender<bool>,
+    }
+
+    impl SyntheticSource {
+        fn spawn() -> (Self, mpsc::Receiver<u64>) {
+            let (tx, rx) = mpsc::channel(16);
+            let (paused, mut paused_rx) = watch::channel(false);
+            tokio::spawn(async move {
+                let mut seq = 0;
+                loop {
+                    if *paused_rx.borrow_and_update() {
+                        if paused_rx.changed().await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                    seq += 1;
+                    if tx.send(seq).await.is_err() {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            });
+            (Self { paused }, rx)
+        }
+
+        fn freeze(&self) {
+            self.paused.send_replace(true);
+        }
+
+        fn resume(&self) {
+            self.paused.send_replace(false);
+        }
+    }
+
+    enum Seen {
+        Stalled(Duration),
+        Recovered(Duration),
+    }
+
+    /// Drive `watchdog` with `samples` the way the recording loop does, until it
+    /// stalls or recovers
+    async fn next(watchdog: &mut StallWatchdog, samples: &mut mpsc::Receiver<u64>) -> Seen {
+        loop {
+            tokio::select! {
+                _ = watchdog.expired() => return Seen::Stalled(watchdog.stall()),
+                Some(_) = samples.recv() => {
+                    if let Some(quiet) = watchdog.sample() {
+                        return Seen::Recovered(quiet);
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_frozen_source_stalls_and_recovers() {
+        let (source, mut samples) = SyntheticSource::spawn();
+        let state = Arc::new(StallState::default());
+        let mut watchdog = StallWatchdog::new(TIMEOUT, state.clone());
+
+        // Samples keep it quiet
+        let fed = tokio::time::timeout(TIMEOUT * 2, next(&mut watchdog, &mut samples)).await;
+        assert!(fed.is_err());
+        assert_eq!(state.since(), None);
+
+        let frozen_at = Utc::now().timestamp_micros();
+        source.freeze();
+        let Seen::Stalled(quiet) = next(&mut watchdog, &mut samples).await else {
+            panic!("recovered without samples");
+        };
+        assert!(quiet >= TIMEOUT);
+        let since = state.since().unwrap();
+        assert!((since - frozen_at).abs() < 50_000, "{since} vs {frozen_at}");
+        // Reported once, not again while it stays stalled
+        assert_eq!(watchdog.deadline(), None);
+
+        tokio::time::sleep(TIMEOUT).await;
+        source.resume();
+        let Seen::Recovered(quiet) = next(&mut watchdog, &mut samples).await else {
+            panic!("stalled again while samples arrive");
+        };
+        assert!(quiet >= TIMEOUT * 2);
+        assert_eq!(state.since(), None);
+        assert!(watchdog.deadline().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_reset_and_off() {
+        let state = Arc::new(StallState::default());
+        let mut watchdog = StallWatchdog::new(TIMEOUT, state.clone());
+        tokio::time::sleep(TIMEOUT).await;
+        watchdog.expired().await;
+        watchdog.stall();
+        assert!(state.since().is_some());
+
+        // A publisher gap starts it over
+        watchdog.reset();
+        assert_eq!(state.since(), None);
+        assert!(watchdog.deadline().unwrap() > Instant::now());
+
+        let off = StallWatchdog::new(Duration::ZERO, Arc::default());
+        assert_eq!(off.deadline(), None);
+        let waited = tokio::time::timeout(TIMEOUT, off.expired()).await;
+        assert!(waited.is_err());
+    }
+}
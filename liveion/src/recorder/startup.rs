@@ -0,0 +1,171 @@
+//! `recorder.startup`: hold storage writes back until storage answers.
+//!
+//! A node booting before its network is up would otherwise fail the first segments'
+//! writes, or burn through their upload retries. Recordings start right away: with
+//! uploads their files are staged locally and the queue waits, written directly they
+//! wait in memory. Past `max_wait_seconds` the gate opens regardless and writes fail
+//! and retry as they always did.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use api::recorder::{StartupState, StartupStatus};
+use tokio::sync::watch;
+
+use crate::config::StartupConfig;
+
+pub struct StartupGate {
+    status: watch::Sender<StartupStatus>,
+}
+
+impl StartupGate {
+    /// A closed gate waiting on `target`, see [`StartupStatus::target`]
+    pub fn new(target: &str) -> Self {
+        Self {
+            status: watch::Sender::new(StartupStatus {
+                state: StartupState::Waiting,
+                target: target.to_string(),
+                since: chrono::Utc::now().timestamp_micros(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn status(&self) -> StartupStatus {
+        self.status.borrow().clone()
+    }
+
+    /// Resolve once the gate is open, ready or timed out
+    pub async fn wait(&self) {
+        let mut rx = self.status.subscribe();
+        let _ = rx
+            .wait_for(|status| status.state != StartupState::Waiting)
+            .await;
+    }
+
+    /// Check the connection with `check` every `poll_interval_ms` until it succeeds or
+    /// `max_wait_seconds` pass
+    pub async fn run<F, Fut>(&self, cfg: &StartupConfig, mut check: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        let started = Instant::now();
+        let max_wait = Duration::from_secs(cfg.max_wait_seconds);
+        let poll_interval = Duration::from_millis(cfg.poll_interval_ms.max(100));
+        let target = self.status.borrow().target.clone();
+        loop {
+            let checked = check().await;
+            let state = match checked {
+                Ok(()) => StartupState::Ready,
+                Err(_) if started.elapsed() >= max_wait => StartupState::TimedOut,
+                Err(_) => StartupState::Waiting,
+            };
+            self.status.send_modify(|status| {
+                status.attempts += 1;
+                status.last_error = checked.as_ref().err().map(|e| format!("{e:#}"));
+                status.state = state;
+                if state != StartupState::Waiting {
+                    status.until = Some(chrono::Utc::now().timestamp_micros());
+                }
+            });
+            match state {
+                StartupState::Ready => {
+                    tracing::info!(
+                        "[recorder] {} reachable after {:?}, writing recordings out",
+                        target,
+                        started.elapsed()
+                    );
+                    return;
+                }
+                StartupState::TimedOut => {
+                    tracing::warn!(
+                        "[recorder] {} still unreachable after {:?}, writing recordings out anyway: {:#}",
+                        target,
+                        started.elapsed(),
+                        checked.unwrap_err()
+                    );
+                    return;
+                }
+                StartupState::Waiting => {
+                    tracing::debug!(
+                        "[recorder] waiting for {}: {:#}",
+                        target,
+                        checked.unwrap_err()
+                    );
+                }
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    fn cfg(max_wait_seconds: u64) -> StartupConfig {
+        StartupConfig {
+            max_wait_seconds,
+            poll_interval_ms: 100,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_opens_once_reachable() {
+        let gate = Arc::new(StartupGate::new("storage"));
+        let checks = Arc::new(AtomicU32::new(0));
+        let waiter = tokio::spawn({
+            let gate = gate.clone();
+            async move { gate.wait().await }
+        });
+
+        let runner = tokio::spawn({
+            let (gate, checks) = (gate.clone(), checks.clone());
+            async move {
+                gate.run(&cfg(60), || {
+                    let attempt = checks.fetch_add(1, Ordering::SeqCst) + 1;
+                    async move {
+                        if attempt < 3 {
+                            anyhow::bail!("connection refused");
+                        }
+                        Ok(())
+                    }
+                })
+                .await
+            }
+        });
+        // Still waiting after the first failed check, with the error to show for it
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let status = gate.status();
+        assert_eq!(status.state, StartupState::Waiting);
+        assert_eq!(status.attempts, 1);
+        assert_eq!(status.last_error.as_deref(), Some("connection refused"));
+        assert!(!waiter.is_finished());
+
+        runner.await.unwrap();
+        waiter.await.unwrap();
+        let status = gate.status();
+        assert_eq!(status.state, StartupState::Ready);
+        assert_eq!(status.attempts, 3);
+        assert_eq!(status.last_error, None);
+        assert!(status.until.unwrap() >= status.since);
+    }
+
+    #[tokio::test]
+    async fn test_times_out_without_aborting() {
+        let gate = StartupGate::new("liveman");
+        gate.run(&cfg(0), || async { anyhow::bail!("no route to host") })
+            .await;
+        // Opened regardless, writes go on with their usual retries
+        gate.wait().await;
+        let status = gate.status();
+        assert_eq!(status.state, StartupState::TimedOut);
+        assert_eq!(status.target, "liveman");
+        assert_eq!(status.attempts, 1);
+        assert_eq!(status.last_error.as_deref(), Some("no route to host"));
+    }
+}
@@ -0,0 +1,249 @@
+//! Recorder statistics of `GET /api/recorder/stats`, per stream and for the node.
+//!
+//! Finished recordings count with what their index entry says, running ones with their
+//! segmenter's counters. Aggregating reads the archive back, so the figures are cached
+//! for [`STATS_TTL`]; the write bitrate compares each running recording's stored bytes
+//! with those of the previous computation.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use api::recorder::{RecorderStats, RecordingIndexEntry, RecordingSize, RecordingStatus};
+
+/// How long computed figures are served before the index is aggregated again
+pub const STATS_TTL: Duration = Duration::from_secs(5);
+
+/// A running recording as [`StatsCache::compute`] counts it
+pub struct Live {
+    pub stream: String,
+    /// Recorded so far
+    pub elapsed: Duration,
+    pub written: RecordingSize,
+}
+
+/// Figures of the node and of each stream with recordings
+#[derive(Debug, Clone, Default)]
+pub struct Computed {
+    pub node: RecorderStats,
+    pub streams: HashMap<String, RecorderStats>,
+}
+
+impl Computed {
+    /// Figures of `stream`, zero for a stream without recordings
+    pub fn stream(&self, stream: &str) -> RecorderStats {
+        self.streams
+            .get(stream)
+            .copied()
+            .unwrap_or_else(|| RecorderStats {
+                computed_at: self.node.computed_at,
+                ..Default::default()
+            })
+    }
+}
+
+#[derive(Default)]
+pub struct StatsCache {
+    computed: Option<(Instant, Computed)>,
+    /// Bytes each running recording had stored at the previous computation
+    samples: HashMap<String, (Instant, u64)>,
+}
+
+impl StatsCache {
+    /// The figures computed less than [`STATS_TTL`] before `now`
+    pub fn fresh(&self, now: Instant) -> Option<&Computed> {
+        self.computed
+            .as_ref()
+            .filter(|(at, _)| now.saturating_duration_since(*at) < STATS_TTL)
+            .map(|(_, computed)| computed)
+    }
+
+    /// Aggregate `entries` and the `live` recordings, caching the result
+    pub fn compute(
+        &mut self,
+        entries: &[RecordingIndexEntry],
+        live: &[Live],
+        now: Instant,
+        now_micros: i64,
+    ) -> Computed {
+        let mut streams: HashMap<String, RecorderStats> = HashMap::new();
+        for entry in entries {
+            let stats = streams.entry(entry.stream.clone()).or_default();
+            stats.recordings += 1;
+            // A running recording counts with its live figures below
+            if matches!(entry.status, RecordingStatus::Active) {
+                continue;
+            }
+            let duration_ms = match entry.duration_ms {
+                Some(duration_ms) => i64::from(duration_ms),
+                None => entry
+                    .effective_end_ts()
+                    .map_or(0, |end_ts| (end_ts - entry.start_ts) / 1000),
+            };
+            stats.recorded_ms += duration_ms.max(0) as u64;
+            if let Some(size) = entry.size {
+                stats.segments += size.segments;
+                stats.bytes += size.bytes;
+            }
+        }
+
+        let mut samples = HashMap::new();
+        for recording in live {
+            let stats = streams.entry(recording.stream.clone()).or_default();
+            stats.active += 1;
+            stats.recorded_ms += recording.elapsed.as_millis() as u64;
+            stats.segments += recording.written.segments;
+            stats.bytes += recording.written.bytes;
+
+            let bytes = recording.written.bytes;
+            let (stored, over) = match self.samples.get(&recording.stream) {
+                Some((at, previous)) if now > *at => {
+                    // A split started the count over
+                    let stored = bytes.checked_sub(*previous).unwrap_or(bytes);
+                    (stored, now - *at)
+                }
+                _ => (bytes, recording.elapsed),
+            };
+            if !over.is_zero() {
+                stats.write_bitrate_bps += (stored as f64 * 8.0 / over.as_secs_f64()) as u64;
+            }
+            samples.insert(recording.stream.clone(), (now, bytes));
+        }
+        self.samples = samples;
+
+        let mut node = RecorderStats::default();
+        for stats in streams.values_mut() {
+            stats.avg_segment_bytes = stats.bytes.checked_div(stats.segments).unwrap_or(0);
+            stats.computed_at = now_micros;
+            node.add(stats);
+        }
+        node.computed_at = now_micros;
+
+        let computed = Computed { node, streams };
+        self.computed = Some((now, computed.clone()));
+        computed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::recorder::DEFAULT_PRIORITY;
+
+    const SECOND: i64 = 1_000_000;
+
+    fn entry(stream: &str, record: i64, status: RecordingStatus) -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            uuid: String::new(),
+            record: record.to_string(),
+            stream: stream.to_string(),
+            record_dir: format!("{stream}/{record}"),
+            mpd_path: format!("{stream}/{record}/manifest.mpd"),
+            start_ts: record * SECOND,
+            end_ts: None,
+            duration_ms: None,
+            status,
+            node_alias: None,
+            updated_at: record * SECOND,
+            note: None,
+            labels: Vec::new(),
+            continues: None,
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant: None,
+            size: None,
+            captions: Vec::new(),
+            trigger: None,
+            imported: false,
+            seq: 0,
+        }
+    }
+
+    fn finished(
+        stream: &str,
+        record: i64,
+        seconds: i32,
+        segments: u64,
+        bytes: u64,
+    ) -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            end_ts: Some((record + i64::from(seconds)) * SECOND),
+            duration_ms: Some(seconds * 1000),
+            size: Some(RecordingSize { segments, bytes }),
+            ..entry(stream, record, RecordingStatus::Completed)
+        }
+    }
+
+    fn live(stream: &str, seconds: u64, segments: u64, bytes: u64) -> Live {
+        Live {
+            stream: stream.to_string(),
+            elapsed: Duration::from_secs(seconds),
+            written: RecordingSize { segments, bytes },
+        }
+    }
+
+    #[test]
+    fn test_finished_and_running_recordings() {
+        let entries = vec![
+            finished("cam", 1000, 3600, 900, 900_000_000),
+            finished("cam", 5000, 1800, 450, 450_000_000),
+            // Recorded by a node that didn't count segments yet
+            RecordingIndexEntry {
+                end_ts: Some(9000 * SECOND),
+                ..entry("cam", 8000, RecordingStatus::Interrupted)
+            },
+            entry("cam", 9000, RecordingStatus::Active),
+            finished("lobby", 1000, 60, 15, 1_500_000),
+        ];
+        let mut cache = StatsCache::default();
+        let now = Instant::now();
+        let computed = cache.compute(&entries, &[live("cam", 40, 10, 5_000_000)], now, 42);
+
+        let cam = computed.stream("cam");
+        assert_eq!(cam.recordings, 4);
+        assert_eq!(cam.active, 1);
+        assert_eq!(cam.recorded_ms, (3600 + 1800 + 1000 + 40) * 1000);
+        assert_eq!(cam.segments, 1360);
+        assert_eq!(cam.bytes, 1_355_000_000);
+        assert_eq!(cam.avg_segment_bytes, 1_355_000_000 / 1360);
+        // 5 MB over the 40 s recorded so far
+        assert_eq!(cam.write_bitrate_bps, 1_000_000);
+        assert_eq!(cam.computed_at, 42);
+
+        assert_eq!(computed.node.recordings, 5);
+        assert_eq!(computed.node.bytes, 1_356_500_000);
+        assert_eq!(computed.node.recorded_hours(), (6440.0 + 60.0) / 3600.0);
+        assert_eq!(computed.stream("garage").recordings, 0);
+        assert_eq!(computed.stream("garage").computed_at, 42);
+        assert!(cache.fresh(now).is_some());
+        assert!(cache.fresh(now + STATS_TTL).is_none());
+    }
+
+    #[test]
+    fn test_write_bitrate_between_computations() {
+        let mut cache = StatsCache::default();
+        let start = Instant::now();
+        cache.compute(&[], &[live("cam", 10, 2, 1_000_000)], start, 0);
+
+        let later = start + Duration::from_secs(5);
+        let computed = cache.compute(&[], &[live("cam", 15, 3, 1_250_000)], later, 0);
+        assert_eq!(computed.stream("cam").write_bitrate_bps, 400_000);
+
+        // Split meanwhile, the counters started over
+        let split = later + Duration::from_secs(5);
+        let computed = cache.compute(&[], &[live("cam", 2, 1, 500_000)], split, 0);
+        assert_eq!(computed.stream("cam").write_bitrate_bps, 800_000);
+
+        // A deleted recording drops out
+        let computed = cache.compute(&[finished("cam", 1, 60, 15, 1_000)], &[], split, 0);
+        assert_eq!(computed.stream("cam").recordings, 1);
+        let computed = cache.compute(&[], &[], split, 0);
+        assert_eq!(computed.node, RecorderStats::default());
+    }
+}
@@ -1,7 +1,13 @@
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 
 use super::RecordingInfo;
+use super::clock::{Clock, SessionEnd};
+use super::stall::{StallState, StallWatchdog};
+use super::trigger::PreRoll;
+use crate::config::StallAction;
+use crate::forward::PeerForward;
 use crate::recorder::codec::Av1RtpParser;
 use crate::recorder::codec::H265RtpParser;
 use crate::recorder::codec::h264::H264RtpParser;
@@ -10,35 +16,107 @@ use crate::recorder::codec::vp9::Vp9RtpParser;
 use crate::recorder::segmenter::Segmenter;
 use crate::stream::manager::Manager;
 use anyhow::{Result, anyhow};
-use api::recorder::RecordingStatus;
+use api::recorder::{RecordingSize, RecordingStatus, RetentionClass};
 use bytes::Bytes;
 use chrono::Utc;
-use tokio::sync::oneshot;
+use tokio::sync::{mpsc, oneshot};
 use tokio::task::JoinHandle;
 use webrtc::api::media_engine::{MIME_TYPE_AV1, MIME_TYPE_H264, MIME_TYPE_HEVC, MIME_TYPE_VP9};
 
 pub struct RecordingTask {
     pub stream: String,
     pub info: RecordingInfo,
-    started_at: Instant,
+    clock: Arc<dyn Clock>,
     base_dir_override: Option<String>,
+    /// Prefix of generated record dirs, kept for the parts of a split
+    key_namespace: Option<String>,
     handle: JoinHandle<()>,
     shutdown_tx: Option<oneshot::Sender<()>>,
+    split_tx: mpsc::UnboundedSender<String>,
+    split_pending: bool,
+    /// Media segments the segmenter stored for the current recording
+    segments_written: Arc<AtomicU64>,
+    /// Their bytes
+    bytes_written: Arc<AtomicU64>,
+    /// Set by the recording loop's watchdog while no media arrives
+    stall: Arc<StallState>,
+    /// The segmenter and video codec, handed back by the recording loop once it ends
+    kept_rx: Option<oneshot::Receiver<(Segmenter, Option<String>)>>,
+    /// Continues a parked recording rather than starting one
+    resumed: bool,
+}
+
+/// A recording kept open after its publisher left, under `republish = "resume"`
+pub struct ParkedRecording {
+    pub info: RecordingInfo,
+    /// End as of the publisher leaving, indexed when it does not come back
+    pub outcome: RecordingStopOutcome,
+    segmenter: Segmenter,
+    /// Video codec of the init segment, `None` for audio-only recordings
+    codec: Option<String>,
+}
+
+impl ParkedRecording {
+    /// Whether a publisher sending `codec` fits the recording's init segments
+    fn continues_with(&self, codec: Option<&str>) -> bool {
+        match (self.codec.as_deref(), codec) {
+            (Some(parked), Some(codec)) => parked.eq_ignore_ascii_case(codec),
+            (parked, codec) => parked.is_none() && codec.is_none(),
+        }
+    }
+}
+
+/// WHEP URL the stream's publisher is cascade-pulled from, `None` for local publishers
+async fn cascade_source(forward: &PeerForward) -> Option<String> {
+    forward
+        .info()
+        .await
+        .publish_session_info?
+        .cascade?
+        .source_url
 }
 
 pub struct RecordingStopOutcome {
     pub status: RecordingStatus,
     pub end_ts: i64,
     pub duration_ms: i32,
+    pub clock_skew_detected: bool,
+    pub size: RecordingSize,
+}
+
+impl RecordingStopOutcome {
+    fn new(status: RecordingStatus, end: SessionEnd, size: RecordingSize) -> Self {
+        Self {
+            status,
+            end_ts: end.end_ts,
+            duration_ms: end.duration_ms,
+            clock_skew_detected: end.clock_skew_detected,
+            size,
+        }
+    }
+
+    pub fn session_end(&self) -> SessionEnd {
+        SessionEnd {
+            end_ts: self.end_ts,
+            duration_ms: self.duration_ms,
+            clock_skew_detected: self.clock_skew_detected,
+        }
+    }
 }
 
 impl RecordingTask {
+    /// Start recording `stream`. A `parked` recording the publisher's codec fits is taken
+    /// and continued in a new Period instead, one left in place is the caller's to end.
     pub async fn spawn(
         manager: Arc<Manager>,
         stream: &str,
         path_prefix_override: Option<String>,
         uploader: Option<Arc<crate::recorder::uploader::UploadManager>>,
-        local_dir: Option<String>,
+        retention_class: Option<RetentionClass>,
+        priority: u8,
+        tenant: Option<String>,
+        clock: Arc<dyn Clock>,
+        parked: &mut Option<ParkedRecording>,
     ) -> Result<Self> {
         let stream_name = stream.to_string();
         let base_dir_override = path_prefix_override;
@@ -63,26 +141,24 @@ impl RecordingTask {
             }
         };
 
-        // Directory prefix, allow override; default to /<stream_id>/<record_id>
-        // record_id unix timestamp(10)
-        let generated_record_id = chrono::Utc::now().timestamp();
+        // Directory prefix, allow override; default to
+        // [<tenant>/][<namespace>/]<stream_id>/<record_id>, record_id unix timestamp(10)
+        let key_namespace = crate::recorder::tenancy::key_namespace(
+            tenant.as_deref(),
+            crate::recorder::KEY_NAMESPACE.read().await.as_deref(),
+        );
+        let generated_record_id = crate::recorder::next_record_id(&stream_name).await;
         let (path_prefix, override_provided) = if let Some(ref p) = base_dir_override {
-            (p.clone(), true)
+            (storage::normalize_key(p).into_owned(), true)
         } else {
-            (format!("{}/{}", stream_name, generated_record_id), false)
+            (
+                storage::record_dir(key_namespace.as_deref(), &stream_name, generated_record_id),
+                false,
+            )
         };
 
-        let derived_record_id = path_prefix
-            .rsplit('/')
-            .find(|segment| {
-                !segment.is_empty()
-                    && segment.len() >= 10
-                    && segment.chars().all(|c| c.is_ascii_digit())
-            })
-            .and_then(|s| s.parse::<i64>().ok());
-
         let record_id = if override_provided {
-            derived_record_id.unwrap_or(0)
+            Self::record_id_from_prefix(&path_prefix).unwrap_or(0)
         } else {
             generated_record_id
         };
@@ -94,6 +170,7 @@ impl RecordingTask {
         );
 
         // Initialize Segmenter
+        let local_dir = uploader.as_ref().map(|u| u.local_dir());
         let mut segmenter = match Segmenter::new(
             op,
             stream_name.clone(),
@@ -121,6 +198,25 @@ impl RecordingTask {
             }
         };
 
+        segmenter.set_dedup_init_segments(
+            crate::recorder::DEDUP_INIT_SEGMENTS.load(std::sync::atomic::Ordering::Acquire),
+        );
+        segmenter.set_verify_size(
+            crate::recorder::VERIFY_SIZE.load(std::sync::atomic::Ordering::Acquire),
+        );
+        segmenter.set_retention_class(retention_class.as_ref());
+        segmenter.set_priority(priority);
+        segmenter.set_segment_pattern(crate::recorder::SEGMENT_PATTERN.read().await.clone());
+        if let Err(e) = segmenter.check_keys() {
+            tracing::error!(
+                "[recorder] refusing to record stream {} under {}: {}",
+                stream_name,
+                path_prefix,
+                e
+            );
+            return Err(e.into());
+        }
+
         // Obtain PeerForward from Manager
         let peer_forward_opt = manager.get_forward(&stream_name).await;
 
@@ -163,6 +259,25 @@ impl RecordingTask {
             }
         }
 
+        let resumed_info = match parked.take() {
+            Some(previous) if previous.continues_with(codec_mime_opt.as_deref()) => {
+                tracing::info!(
+                    "[recorder] stream {} continues {} in a new period",
+                    stream_name,
+                    previous.info.record_dir
+                );
+                segmenter = previous.segmenter;
+                segmenter.begin_period();
+                Some(previous.info)
+            }
+            previous => {
+                *parked = previous;
+                None
+            }
+        };
+        let segments_written = segmenter.segments_written();
+        let bytes_written = segmenter.bytes_written();
+
         if let Some(codec) = codec_mime_opt.as_ref() {
             tracing::info!(
                 "[recorder] stream {} use video codec {}",
@@ -177,6 +292,16 @@ impl RecordingTask {
             tracing::info!("[recorder] stream {} audio track detected", stream_name);
         }
 
+        // Tracks arrive after the pull's session is set, the source is known by now
+        let source = cascade_source(&forward).await;
+        if let Some(source) = source.as_ref() {
+            tracing::info!(
+                "[recorder] stream {} is cascade-pulled from {}",
+                stream_name,
+                source
+            );
+        }
+
         if audio_receiver_opt.is_some()
             && let Some(info) = forward.first_audio_track_info().await
         {
@@ -196,15 +321,44 @@ impl RecordingTask {
 
         tracing::info!("[recorder] subscribed RTP for stream {}", stream_name);
 
+        // Written ahead of the live packets, which repeat its tail
+        let mut pre_roll = crate::recorder::take_pre_roll(&stream_name).await;
+        if let Some(rx) = video_receiver_opt.as_mut() {
+            PreRoll::skip_replayed(&mut pre_roll.video, rx);
+        }
+        if let Some(rx) = audio_receiver_opt.as_mut() {
+            PreRoll::skip_replayed(&mut pre_roll.audio, rx);
+        }
+        let pre_roll_span = pre_roll.span;
+
         let stream_name_cloned = stream_name.clone();
         let forward_clone = forward.clone();
         let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+        let (split_tx, mut split_rx) = mpsc::unbounded_channel::<String>();
+        let (kept_tx, kept_rx) = oneshot::channel();
+        let reconnect_grace = Duration::from_secs(
+            crate::recorder::RECONNECT_GRACE_SECONDS.load(std::sync::atomic::Ordering::Acquire),
+        );
+        let stall_timeout = Duration::from_secs(
+            crate::recorder::STALL_TIMEOUT_SECONDS.load(std::sync::atomic::Ordering::Acquire),
+        );
+        let stall = Arc::new(StallState::default());
+        let stall_state = stall.clone();
 
         let handle = tokio::spawn(async move {
             let mut segmenter = segmenter;
+            let has_audio = audio_receiver_opt.is_some();
             let mut video_rx_opt = video_receiver_opt;
             let mut audio_rx_opt = audio_receiver_opt;
             let mut codec_mime_opt = codec_mime_opt;
+            let (mut pre_roll_video, mut pre_roll_audio) = (pre_roll.video, pre_roll.audio);
+            // Set while both tracks are gone, the recording ends unless they return by then
+            let mut reconnect_deadline: Option<tokio::time::Instant> = None;
+            // Ended by the publisher rather than a stop, the recording is finalized here
+            let mut publisher_lost = false;
+            // Ended by the stall watchdog, finalized here too
+            let mut stalled: Option<StallAction> = None;
+            let mut watchdog = StallWatchdog::new(stall_timeout, stall_state);
 
             let mut parser_h264 = H264RtpParser::new();
             let mut parser_h265 = H265RtpParser::new();
@@ -233,6 +387,37 @@ impl RecordingTask {
                         tracing::info!("[recorder] received stop signal for stream {}", stream_name_cloned);
                         break;
                     },
+                    Some(next_prefix) = split_rx.recv() => {
+                        segmenter.request_split(next_prefix);
+                    },
+                    _ = async {
+                        match reconnect_deadline {
+                            Some(deadline) => tokio::time::sleep_until(deadline).await,
+                            None => std::future::pending().await,
+                        }
+                    }, if reconnect_deadline.is_some() => {
+                        tracing::info!(
+                            "[recorder] publisher of {} did not return within {:?}",
+                            stream_name_cloned,
+                            reconnect_grace
+                        );
+                        publisher_lost = true;
+                        break;
+                    },
+                    _ = watchdog.expired(), if reconnect_deadline.is_none() && watchdog.deadline().is_some() => {
+                        let quiet = watchdog.stall();
+                        let action = crate::recorder::on_stalled(
+                            &manager,
+                            &stream_name_cloned,
+                            segmenter.path_prefix(),
+                            quiet,
+                        )
+                        .await;
+                        if action != StallAction::Wait {
+                            stalled = Some(action);
+                            break;
+                        }
+                    },
                     _ = keyframe_check_interval.tick(), if video_rx_opt.is_some() => {
                         if segmenter.should_request_keyframe()
                             && let Some(video_track) = forward_clone.first_video_track().await {
@@ -258,6 +443,9 @@ impl RecordingTask {
                     },
 
                     result = async {
+                        if let Some(packet) = pre_roll_video.pop_front() {
+                            return Some(packet);
+                        }
                         match video_rx_opt.as_mut() {
                             Some(rx) => rx.recv().await.ok(),
                             None => std::future::pending().await,
@@ -265,6 +453,9 @@ impl RecordingTask {
                     }, if video_rx_opt.is_some() => {
                         match result {
                             Some(packet) => {
+                                if let Some(quiet) = watchdog.sample() {
+                                    tracing::info!("[recorder] {} got media again after {:?}", stream_name_cloned, quiet);
+                                }
                                 let pkt_ts = packet.header.timestamp;
 
                                 if codec_mime_opt.is_none() {
@@ -324,6 +515,9 @@ impl RecordingTask {
                     },
 
                     result = async {
+                        if let Some(packet) = pre_roll_audio.pop_front() {
+                            return Some(packet);
+                        }
                         match audio_rx_opt.as_mut() {
                             Some(rx) => rx.recv().await.ok(),
                             None => std::future::pending().await,
@@ -331,6 +525,9 @@ impl RecordingTask {
                     }, if audio_rx_opt.is_some() => {
                         match result {
                             Some(packet) => {
+                                if let Some(quiet) = watchdog.sample() {
+                                    tracing::info!("[recorder] {} got media again after {:?}", stream_name_cloned, quiet);
+                                }
                                 let (payload, pkt_ts) = match parser_audio.push_packet(&packet) {
                                     Ok(v) => v,
                                     Err(_) => continue,
@@ -358,6 +555,28 @@ impl RecordingTask {
                             break;
                         }
 
+                        if let Some(current) = codec_mime_opt.as_ref()
+                            && let Some(codec) = forward_clone.first_video_codec().await
+                            && !current.eq_ignore_ascii_case(&codec)
+                        {
+                            // The init segment can't change codec, the rest goes to a new recording
+                            tracing::info!(
+                                "[recorder] stream {} came back with {} instead of {}",
+                                stream_name_cloned,
+                                codec,
+                                current
+                            );
+                            publisher_lost = true;
+                            break;
+                        }
+
+                        if has_audio
+                            && audio_rx_opt.is_none()
+                            && let Some(rx) = forward_clone.subscribe_audio_rtp().await
+                        {
+                            audio_rx_opt = Some(rx);
+                        }
+
                         if codec_mime_opt.is_none() {
                             codec_mime_opt = forward_clone.first_video_codec().await;
                             if let Some(codec) = codec_mime_opt.as_ref() {
@@ -382,8 +601,57 @@ impl RecordingTask {
                     }
                 }
 
+                if let Some(split) = segmenter.take_split() {
+                    // Re-index off the RTP path, the task map lock may be contended.
+                    // The new part's formats wait for its index entry.
+                    let media = segmenter.take_media_info().map(|(_, media)| media);
+                    tokio::spawn(crate::recorder::on_split(
+                        stream_name_cloned.clone(),
+                        split.next_prefix,
+                        split.previous_size,
+                        media,
+                    ));
+                } else if let Some((record_dir, media)) = segmenter.take_media_info() {
+                    tokio::spawn(crate::recorder::on_media_info(
+                        stream_name_cloned.clone(),
+                        record_dir,
+                        media,
+                    ));
+                }
+
                 if video_rx_opt.is_none() && audio_rx_opt.is_none() {
-                    break;
+                    if reconnect_grace.is_zero() {
+                        publisher_lost = true;
+                        break;
+                    }
+                    if reconnect_deadline.is_none() {
+                        tracing::info!(
+                            "[recorder] publisher of {} gone, waiting {:?} for it to return",
+                            stream_name_cloned,
+                            reconnect_grace
+                        );
+                        reconnect_deadline = Some(tokio::time::Instant::now() + reconnect_grace);
+                        watchdog.reset();
+                        if let Err(e) = segmenter.publisher_gap().await {
+                            tracing::warn!(
+                                "[recorder] {} failed to close segments: {}",
+                                stream_name_cloned,
+                                e
+                            );
+                        }
+                        // Partial frames of the old publisher must not merge into the new one's
+                        parser_h264 = H264RtpParser::new();
+                        parser_h265 = H265RtpParser::new();
+                        parser_av1 = Av1RtpParser::new();
+                        parser_vp9 = Vp9RtpParser::new();
+                        parser_audio = OpusRtpParser::new();
+                    }
+                } else if reconnect_deadline.take().is_some() {
+                    tracing::info!(
+                        "[recorder] publisher of {} returned, recording continues",
+                        stream_name_cloned
+                    );
+                    watchdog.reset();
                 }
 
                 if last_log.elapsed() >= Duration::from_secs(5) {
@@ -423,25 +691,82 @@ impl RecordingTask {
             if let Err(e) = segmenter.flush().await {
                 tracing::debug!("[recorder] {} flush error: {}", stream_name_cloned, e);
             }
+            // A recording shorter than one segment gets its framerate only now
+            if let Some((record_dir, media)) = segmenter.take_media_info() {
+                crate::recorder::on_media_info(stream_name_cloned.clone(), record_dir, media).await;
+            }
+            // Finalized off this task, which the finalizing waits for
+            if let Some(action) = stalled {
+                tokio::spawn(crate::recorder::on_stall_ended(
+                    manager,
+                    stream_name_cloned,
+                    segmenter.path_prefix().to_string(),
+                    action == StallAction::Restart,
+                ));
+            } else if publisher_lost {
+                tokio::spawn(crate::recorder::on_publisher_lost(
+                    manager,
+                    stream_name_cloned,
+                    segmenter.path_prefix().to_string(),
+                ));
+            }
+            let _ = kept_tx.send((segmenter, codec_mime_opt));
         });
 
-        let info = RecordingInfo {
+        let resumed = resumed_info.is_some();
+        let info = resumed_info.unwrap_or_else(|| RecordingInfo {
             record_dir: path_prefix,
             record_id,
-            start_ts_micros: Utc::now().timestamp_micros(),
-        };
+            start_ts_micros: clock.wall_micros() - pre_roll_span.as_micros() as i64,
+            started: clock.monotonic().saturating_sub(pre_roll_span),
+            retention_class,
+            priority,
+            source,
+            tenant,
+        });
 
         Ok(Self {
             stream: stream_name,
             info,
-            started_at: Instant::now(),
+            clock,
             base_dir_override,
+            key_namespace,
             handle,
             shutdown_tx: Some(shutdown_tx),
+            split_tx,
+            split_pending: false,
+            segments_written,
+            bytes_written,
+            stall,
+            kept_rx: Some(kept_rx),
+            resumed,
         })
     }
 
-    pub async fn stop(mut self) -> RecordingStopOutcome {
+    pub async fn stop(self) -> RecordingStopOutcome {
+        self.finish().await.0
+    }
+
+    /// [`Self::stop`], keeping a cleanly ended recording open for a publisher that comes
+    /// back
+    pub(crate) async fn park(self) -> std::result::Result<ParkedRecording, RecordingStopOutcome> {
+        let info = self.info.clone();
+        match self.finish().await {
+            (outcome, Some((segmenter, codec)))
+                if matches!(outcome.status, RecordingStatus::Completed) =>
+            {
+                Ok(ParkedRecording {
+                    info,
+                    outcome,
+                    segmenter,
+                    codec,
+                })
+            }
+            (outcome, _) => Err(outcome),
+        }
+    }
+
+    async fn finish(mut self) -> (RecordingStopOutcome, Option<(Segmenter, Option<String>)>) {
         let stream = std::mem::take(&mut self.stream);
         tracing::info!("[recorder] stopping recording for stream {}", stream);
 
@@ -475,32 +800,157 @@ impl RecordingTask {
                 RecordingStatus::Failed
             }
         };
+        let kept = match self.kept_rx.take() {
+            Some(rx) => rx.await.ok(),
+            None => None,
+        };
 
-        let end_ts = Utc::now().timestamp_micros();
-        let duration_ms = self.started_at.elapsed().as_millis().min(i32::MAX as u128) as i32;
-
-        RecordingStopOutcome {
-            status,
-            end_ts,
-            duration_ms,
-        }
+        (
+            RecordingStopOutcome::new(status, self.session_end(), self.written()),
+            kept,
+        )
     }
 }
 
 impl RecordingTask {
+    /// Wrap an already running recording loop, for driving shutdown without a live stream
+    #[cfg(test)]
+    pub(crate) fn from_parts(
+        stream: &str,
+        info: RecordingInfo,
+        handle: JoinHandle<()>,
+        shutdown_tx: oneshot::Sender<()>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let (split_tx, _) = mpsc::unbounded_channel();
+        Self {
+            stream: stream.to_string(),
+            info,
+            clock,
+            base_dir_override: None,
+            key_namespace: None,
+            handle,
+            shutdown_tx: Some(shutdown_tx),
+            split_tx,
+            split_pending: false,
+            segments_written: Arc::default(),
+            bytes_written: Arc::default(),
+            stall: Arc::default(),
+            kept_rx: None,
+            resumed: false,
+        }
+    }
+
+    /// Whether the task continues a parked recording, already indexed
+    pub(crate) fn resumed(&self) -> bool {
+        self.resumed
+    }
+
     pub(crate) fn has_exceeded(&self, max_duration: Duration) -> bool {
-        self.started_at.elapsed() >= max_duration
+        self.elapsed() >= max_duration
     }
 
-    pub(crate) fn next_rotation_base_dir(&self) -> Option<String> {
-        self.base_dir_override
-            .as_ref()
-            .map(|current| Self::derive_next_base_dir(current))
+    /// Time recorded in the current recording
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.clock.monotonic().saturating_sub(self.info.started)
+    }
+
+    pub(crate) fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    pub(crate) fn segments_written(&self) -> u64 {
+        self.segments_written.load(Ordering::Relaxed)
+    }
+
+    /// Last sample of the recording while it is stalled
+    pub(crate) fn stalled_since(&self) -> Option<i64> {
+        self.stall.since()
+    }
+
+    /// What the segmenter stored for the current recording so far
+    pub(crate) fn written(&self) -> RecordingSize {
+        RecordingSize {
+            segments: self.segments_written.load(Ordering::Relaxed),
+            bytes: self.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+
+    fn session_end(&self) -> SessionEnd {
+        SessionEnd::measure(
+            self.clock.as_ref(),
+            self.info.start_ts_micros,
+            self.info.started,
+        )
+    }
+
+    /// Ask the segmenter to split at the next keyframe, returns the new prefix.
+    ///
+    /// Returns `None` while a split is already pending or the task has ended.
+    pub(crate) fn request_split(&mut self) -> Option<String> {
+        if self.split_pending {
+            return None;
+        }
+        let next_prefix = self.next_split_dir();
+        if self.split_tx.send(next_prefix.clone()).is_err() {
+            return None;
+        }
+        self.split_pending = true;
+        Some(next_prefix)
+    }
+
+    /// Switch bookkeeping to the prefix the segmenter has moved to.
+    ///
+    /// Returns the finished recording and its outcome so the caller can finalize the index.
+    pub(crate) fn advance(
+        &mut self,
+        next_prefix: String,
+        size: RecordingSize,
+    ) -> (RecordingInfo, RecordingStopOutcome) {
+        let end = self.session_end();
+        let outcome = RecordingStopOutcome::new(RecordingStatus::Completed, end, size);
+
+        if self.base_dir_override.is_some() {
+            self.base_dir_override = Some(next_prefix.clone());
+        }
+        let next = RecordingInfo {
+            record_id: Self::record_id_from_prefix(&next_prefix).unwrap_or(0),
+            record_dir: next_prefix,
+            start_ts_micros: end.end_ts,
+            started: self.clock.monotonic(),
+            retention_class: self.info.retention_class.clone(),
+            priority: self.info.priority,
+            source: self.info.source.clone(),
+            tenant: self.info.tenant.clone(),
+        };
+        let previous = std::mem::replace(&mut self.info, next);
+        self.split_pending = false;
+        (previous, outcome)
+    }
+
+    fn next_split_dir(&self) -> String {
+        // Keep record ids strictly increasing even when splits happen within one second
+        let next_ts = Utc::now().timestamp().max(self.info.record_id + 1);
+        match self.base_dir_override.as_ref() {
+            Some(current) => Self::derive_next_base_dir(current, next_ts),
+            None => storage::record_dir(self.key_namespace.as_deref(), &self.stream, next_ts),
+        }
+    }
+
+    fn record_id_from_prefix(prefix: &str) -> Option<i64> {
+        prefix
+            .rsplit('/')
+            .find(|segment| {
+                !segment.is_empty()
+                    && segment.len() >= 10
+                    && segment.chars().all(|c| c.is_ascii_digit())
+            })
+            .and_then(|s| s.parse::<i64>().ok())
     }
 
-    fn derive_next_base_dir(current: &str) -> String {
+    fn derive_next_base_dir(current: &str, next_ts: i64) -> String {
         let trimmed = current.trim_end_matches('/');
-        let next_ts = chrono::Utc::now().timestamp().to_string();
+        let next_ts = next_ts.to_string();
         if trimmed.is_empty() {
             return next_ts;
         }
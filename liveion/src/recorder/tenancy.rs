@@ -0,0 +1,192 @@
+//! `recorder.tenancy`: the tenant of a stream, derived from its name.
+//!
+//! A tenant's recordings are stored under `{tenant}/[{namespace}/]{stream}/{record_id}`,
+//! the first segment of every object key, and their index entries carry it, so tokens
+//! with a `tenants` claim only presign and play back their own tenant's objects.
+
+use regex::Regex;
+
+use crate::config::TenancyConfig;
+
+#[derive(Debug, Clone, Default)]
+pub struct Tenancy {
+    rule: Option<Rule>,
+    default_tenant: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Rule {
+    Separator(String),
+    Pattern(Regex),
+}
+
+impl Tenancy {
+    pub fn new(cfg: &TenancyConfig) -> Result<Self, String> {
+        let rule = match (
+            cfg.tenant_pattern.as_deref(),
+            cfg.tenant_separator.as_deref(),
+        ) {
+            (Some(_), Some(_)) => {
+                return Err("set either tenant_separator or tenant_pattern".to_string());
+            }
+            (Some(pattern), None) => {
+                let re = Regex::new(pattern).map_err(|e| format!("tenant_pattern: {e}"))?;
+                if re.captures_len() < 2 {
+                    return Err("tenant_pattern needs a capture group".to_string());
+                }
+                Some(Rule::Pattern(re))
+            }
+            (None, Some("")) => return Err("tenant_separator is empty".to_string()),
+            (None, Some(separator)) => Some(Rule::Separator(separator.to_string())),
+            (None, None) => None,
+        };
+        if let Some(tenant) = cfg.default_tenant.as_deref() {
+            if rule.is_none() {
+                return Err("default_tenant needs tenant_separator or tenant_pattern".to_string());
+            }
+            check_tenant(tenant).map_err(|e| format!("default_tenant: {e}"))?;
+        }
+        Ok(Self {
+            rule,
+            default_tenant: cfg.default_tenant.clone(),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.rule.is_some()
+    }
+
+    /// Tenant of `stream`, `None` without tenancy. Streams that don't match fall into the
+    /// default tenant, or are refused without one
+    pub fn tenant_of(&self, stream: &str) -> Result<Option<String>, String> {
+        let Some(rule) = self.rule.as_ref() else {
+            return Ok(None);
+        };
+        let matched = match rule {
+            Rule::Separator(separator) => stream
+                .split_once(separator.as_str())
+                .filter(|(_, rest)| !rest.is_empty())
+                .map(|(tenant, _)| tenant),
+            Rule::Pattern(re) => re.captures(stream).and_then(|caps| {
+                caps.name("tenant")
+                    .or_else(|| caps.get(1))
+                    .map(|m| m.as_str())
+            }),
+        };
+        match matched.filter(|tenant| check_tenant(tenant).is_ok()) {
+            Some(tenant) => Ok(Some(tenant.to_string())),
+            None => match self.default_tenant.as_ref() {
+                Some(tenant) => Ok(Some(tenant.clone())),
+                None => Err(format!("stream {stream} does not name a tenant")),
+            },
+        }
+    }
+}
+
+/// Key namespace of a tenant's recordings, the tenant followed by the node's namespace
+pub fn key_namespace(tenant: Option<&str>, namespace: Option<&str>) -> Option<String> {
+    match (tenant, namespace) {
+        (Some(tenant), Some(ns)) => Some(format!("{tenant}/{ns}")),
+        (Some(tenant), None) => Some(tenant.to_string()),
+        (None, ns) => ns.map(str::to_string),
+    }
+}
+
+/// A tenant is a single key segment that can't be mistaken for shared objects
+fn check_tenant(tenant: &str) -> Result<(), String> {
+    if tenant.is_empty()
+        || tenant == "."
+        || tenant == ".."
+        || tenant.starts_with('_')
+        || tenant.contains('/')
+        || tenant.chars().any(char::is_control)
+    {
+        return Err(format!("invalid tenant {tenant:?}"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tenancy(separator: Option<&str>, pattern: Option<&str>, default: Option<&str>) -> Tenancy {
+        Tenancy::new(&TenancyConfig {
+            tenant_separator: separator.map(str::to_string),
+            tenant_pattern: pattern.map(str::to_string),
+            default_tenant: default.map(str::to_string),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_separator() {
+        let t = tenancy(Some("-"), None, None);
+        assert_eq!(t.tenant_of("acme-cam1").unwrap().as_deref(), Some("acme"));
+        assert_eq!(
+            t.tenant_of("acme-lobby-2").unwrap().as_deref(),
+            Some("acme")
+        );
+        for stream in ["lobby", "acme-", "-cam1", "_shared-cam1"] {
+            assert!(t.tenant_of(stream).is_err(), "{stream}");
+        }
+
+        let t = tenancy(Some("/"), None, Some("shared"));
+        assert_eq!(
+            t.tenant_of("tenantA/cam").unwrap().as_deref(),
+            Some("tenantA")
+        );
+        assert_eq!(t.tenant_of("lobby").unwrap().as_deref(), Some("shared"));
+
+        assert_eq!(Tenancy::default().tenant_of("acme-cam1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_pattern() {
+        let t = tenancy(None, Some(r"^(?P<tenant>[a-z]+)_\d+$"), None);
+        assert_eq!(t.tenant_of("acme_12").unwrap().as_deref(), Some("acme"));
+        assert!(t.tenant_of("acme_lobby").is_err());
+
+        let t = tenancy(None, Some(r"^site\.([a-z]+)\."), Some("shared"));
+        assert_eq!(
+            t.tenant_of("site.acme.cam").unwrap().as_deref(),
+            Some("acme")
+        );
+        assert_eq!(t.tenant_of("cam").unwrap().as_deref(), Some("shared"));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config = |separator: Option<&str>, pattern: Option<&str>, default: Option<&str>| {
+            Tenancy::new(&TenancyConfig {
+                tenant_separator: separator.map(str::to_string),
+                tenant_pattern: pattern.map(str::to_string),
+                default_tenant: default.map(str::to_string),
+            })
+        };
+        assert!(config(Some("-"), Some("(a)"), None).is_err());
+        assert!(config(Some(""), None, None).is_err());
+        assert!(config(None, Some("[a-z]+"), None).is_err());
+        assert!(config(None, Some("("), None).is_err());
+        assert!(config(None, None, Some("shared")).is_err());
+        assert!(config(Some("-"), None, Some("_shared")).is_err());
+        assert!(config(Some("-"), None, Some("a/b")).is_err());
+    }
+
+    #[test]
+    fn test_key_namespace() {
+        assert_eq!(
+            storage::record_dir(
+                key_namespace(Some("acme"), Some("edge-1")).as_deref(),
+                "acme-cam1",
+                1_700_000_000
+            ),
+            "acme/edge-1/acme-cam1/1700000000"
+        );
+        assert_eq!(key_namespace(Some("acme"), None).as_deref(), Some("acme"));
+        assert_eq!(
+            key_namespace(None, Some("edge-1")).as_deref(),
+            Some("edge-1")
+        );
+    }
+}
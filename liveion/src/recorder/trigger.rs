@@ -0,0 +1,611 @@
+//! Recordings started by events, e.g. motion detection, rather than by publishing.
+//!
+//! A trigger starts its stream's recording unless one runs already, and pushes the stop
+//! of a recording it started back to the post-roll after it, so a burst of events makes
+//! one recording. A recording running for another reason is left to whoever started
+//! it. Streams of `recorder.triggers.streams` keep their last `buffer_ms` of RTP in
+//! memory while published, a recording a trigger starts is fed the pre-roll of it
+//! before the live packets and begins at the first keyframe in it.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use api::recorder::{RecordingTrigger, TriggerResponse};
+use chrono::Utc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{self, MissedTickBehavior};
+use webrtc::rtp::packet::Packet;
+
+use crate::config::TriggerConfig;
+use crate::forward::PeerForward;
+use crate::stream::manager::Manager;
+
+/// Packets kept per track of a buffered stream, whatever `buffer_ms`
+const MAX_BUFFERED_PACKETS: usize = 20_000;
+/// Interval between checks for recordings past their post-roll
+const STOP_TICK: Duration = Duration::from_secs(1);
+
+type Track = VecDeque<(Instant, Arc<Packet>)>;
+
+/// Recent packets of a published stream
+#[derive(Default)]
+struct Buffer {
+    video: Track,
+    audio: Track,
+}
+
+/// Packets a recording writes before its live ones
+#[derive(Default)]
+pub struct PreRoll {
+    pub video: VecDeque<Arc<Packet>>,
+    pub audio: VecDeque<Arc<Packet>>,
+    /// From the oldest packet to the start of the recording
+    pub span: Duration,
+}
+
+impl PreRoll {
+    /// Drop the packets `rx` holds that `pending` has already, both having received
+    /// what the track sent since `rx` subscribed. The first newer packet moves to
+    /// `pending`, the rest stays in `rx`
+    pub fn skip_replayed(
+        pending: &mut VecDeque<Arc<Packet>>,
+        rx: &mut broadcast::Receiver<Arc<Packet>>,
+    ) {
+        let Some(last) = pending.back().map(|p| p.header.sequence_number) else {
+            return;
+        };
+        loop {
+            match rx.try_recv() {
+                Ok(packet) => {
+                    if packet.header.sequence_number.wrapping_sub(last) as i16 > 0 {
+                        pending.push_back(packet);
+                        return;
+                    }
+                }
+                Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+/// Keep `packet` received `at`, dropping those older than `keep`
+fn push(track: &mut Track, at: Instant, packet: Arc<Packet>, keep: Duration) {
+    while track
+        .front()
+        .is_some_and(|(t, _)| at.duration_since(*t) > keep || track.len() >= MAX_BUFFERED_PACKETS)
+    {
+        track.pop_front();
+    }
+    track.push_back((at, packet));
+}
+
+/// Packets of `track` received from `from` on
+fn since(track: &Track, from: Instant) -> impl Iterator<Item = &(Instant, Arc<Packet>)> {
+    track.iter().skip_while(move |(t, _)| *t < from)
+}
+
+impl Buffer {
+    fn pre_roll(&self, from: Instant, now: Instant) -> PreRoll {
+        let oldest = since(&self.video, from)
+            .chain(since(&self.audio, from))
+            .map(|(t, _)| *t)
+            .min();
+        PreRoll {
+            video: since(&self.video, from).map(|(_, p)| p.clone()).collect(),
+            audio: since(&self.audio, from).map(|(_, p)| p.clone()).collect(),
+            span: oldest.map_or(Duration::ZERO, |t| now.duration_since(t)),
+        }
+    }
+}
+
+/// A recording started by a trigger, stopped at `stop_at` unless triggered again
+struct Armed {
+    record: String,
+    stop_at: Instant,
+    trigger: RecordingTrigger,
+}
+
+impl Armed {
+    /// Another trigger `post_roll` before the stop, which is never brought forward
+    fn extend(&mut self, now: Instant, now_micros: i64, post_roll: Duration) {
+        self.stop_at = self.stop_at.max(now + post_roll);
+        self.trigger.last_at = now_micros;
+        self.trigger.count += 1;
+    }
+
+    fn stop_at_micros(&self, now: Instant, now_micros: i64) -> i64 {
+        now_micros + self.stop_at.saturating_duration_since(now).as_micros() as i64
+    }
+}
+
+pub struct Triggers {
+    cfg: TriggerConfig,
+    manager: Arc<Manager>,
+    buffers: Mutex<HashMap<String, Arc<Mutex<Buffer>>>>,
+    /// Per buffered stream, the task filling its buffer
+    watchers: Mutex<HashMap<String, tokio::task::JoinHandle<()>>>,
+    /// Pre-rolls of recordings being started, taken by their task
+    pending: Mutex<HashMap<String, PreRoll>>,
+    /// Held while a trigger is handled or a recording stopped, by stream
+    armed: tokio::sync::Mutex<HashMap<String, Armed>>,
+}
+
+impl Triggers {
+    pub fn new(cfg: TriggerConfig, manager: Arc<Manager>) -> Self {
+        Self {
+            cfg,
+            manager,
+            buffers: Mutex::new(HashMap::new()),
+            watchers: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            armed: tokio::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start buffering `stream`, just published, when it is one of `streams`
+    pub async fn watch(&self, stream: &str) {
+        if self.cfg.buffer_ms == 0 || !super::should_record(&self.cfg.streams, stream) {
+            return;
+        }
+        let Some(forward) = self.manager.get_forward(stream).await else {
+            return;
+        };
+        let buffer = Arc::new(Mutex::new(Buffer::default()));
+        self.buffers
+            .lock()
+            .unwrap()
+            .insert(stream.to_string(), buffer.clone());
+        let keep = Duration::from_millis(self.cfg.buffer_ms);
+        let task = tokio::spawn(fill(forward, buffer, keep));
+        if let Some(previous) = self
+            .watchers
+            .lock()
+            .unwrap()
+            .insert(stream.to_string(), task)
+        {
+            previous.abort();
+        }
+    }
+
+    /// `stream` went down, its buffer and the post-roll of its recording go with it
+    pub async fn forget(&self, stream: &str) {
+        if let Some(task) = self.watchers.lock().unwrap().remove(stream) {
+            task.abort();
+        }
+        self.buffers.lock().unwrap().remove(stream);
+        self.armed.lock().await.remove(stream);
+    }
+
+    /// Pre-roll left for the recording of `stream` being started
+    pub fn take_pre_roll(&self, stream: &str) -> PreRoll {
+        self.pending
+            .lock()
+            .unwrap()
+            .remove(stream)
+            .unwrap_or_default()
+    }
+
+    /// The recording of `stream` went on as `next` after a split, the post-roll
+    /// carries over
+    pub async fn on_split(&self, stream: &str, previous: &str, next: &str) {
+        if let Some(armed) = self.armed.lock().await.get_mut(stream)
+            && armed.record == previous
+        {
+            armed.record = next.to_string();
+        }
+    }
+
+    /// Handle a trigger of `stream` from `source`, `http` or `mqtt:{topic}`
+    pub async fn fire(
+        &self,
+        stream: &str,
+        source: String,
+        pre_roll_ms: Option<u64>,
+        post_roll_ms: Option<u64>,
+    ) -> Result<TriggerResponse> {
+        crate::metrics::RECORDER_TRIGGERS.inc();
+        let pre_roll = Duration::from_millis(
+            pre_roll_ms
+                .unwrap_or(self.cfg.pre_roll_ms)
+                .min(self.cfg.buffer_ms),
+        );
+        let post_roll = Duration::from_millis(post_roll_ms.unwrap_or(self.cfg.post_roll_ms));
+        let mut armed = self.armed.lock().await;
+        let (now, now_micros) = (Instant::now(), Utc::now().timestamp_micros());
+
+        let current = current_record(stream).await;
+        if let Some(record) = current.as_ref()
+            && let Some(a) = armed.get_mut(stream).filter(|a| &a.record == record)
+        {
+            a.extend(now, now_micros, post_roll);
+            save(stream, &a.record, a.trigger.clone()).await;
+            return Ok(TriggerResponse {
+                stream: stream.to_string(),
+                record: a.record.clone(),
+                started: false,
+                pre_roll_ms: 0,
+                stop_at: Some(a.stop_at_micros(now, now_micros)),
+            });
+        }
+        armed.remove(stream);
+        if let Some(record) = current {
+            tracing::info!(
+                "[recorder] trigger of {} from {}, recording already",
+                stream,
+                source
+            );
+            return Ok(TriggerResponse {
+                stream: stream.to_string(),
+                record,
+                started: false,
+                pre_roll_ms: 0,
+                stop_at: None,
+            });
+        }
+
+        if self.manager.get_forward(stream).await.is_none() {
+            return Err(api::recorder::RecorderError::NotFound {
+                stream: stream.to_string(),
+                record: None,
+                message: format!("stream {stream} is not published"),
+            }
+            .into());
+        }
+        let buffer = self.buffers.lock().unwrap().get(stream).cloned();
+        let pre = match buffer {
+            Some(buffer) => {
+                let from = now.checked_sub(pre_roll).unwrap_or(now);
+                buffer.lock().unwrap().pre_roll(from, now)
+            }
+            None => PreRoll::default(),
+        };
+        let pre_roll_ms = pre.span.as_millis() as u64;
+        self.pending.lock().unwrap().insert(stream.to_string(), pre);
+        let started =
+            super::start(self.manager.clone(), stream.to_string(), None, None, None).await;
+        // Left behind when the start failed or found a recording started meanwhile
+        let taken = self.pending.lock().unwrap().remove(stream).is_none();
+        let info = started?;
+        let record = super::record_key(&info);
+        if !taken {
+            return Ok(TriggerResponse {
+                stream: stream.to_string(),
+                record,
+                started: false,
+                pre_roll_ms: 0,
+                stop_at: None,
+            });
+        }
+        let a = Armed {
+            record: record.clone(),
+            stop_at: now + post_roll,
+            trigger: RecordingTrigger {
+                source: source.clone(),
+                first_at: now_micros,
+                last_at: now_micros,
+                count: 1,
+                pre_roll_ms,
+            },
+        };
+        save(stream, &record, a.trigger.clone()).await;
+        let stop_at = a.stop_at_micros(now, now_micros);
+        armed.insert(stream.to_string(), a);
+        tracing::info!(
+            "[recorder] trigger from {} started recording {}/{} with {}ms pre-roll",
+            source,
+            stream,
+            record,
+            pre_roll_ms
+        );
+        Ok(TriggerResponse {
+            stream: stream.to_string(),
+            record,
+            started: true,
+            pre_roll_ms,
+            stop_at: Some(stop_at),
+        })
+    }
+
+    /// Stop the recordings whose post-roll passed
+    pub async fn run(self: Arc<Self>) {
+        let mut ticker = time::interval(STOP_TICK);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            ticker.tick().await;
+            let mut armed = self.armed.lock().await;
+            let now = Instant::now();
+            let due: Vec<(String, String)> = armed
+                .iter()
+                .filter(|(_, a)| a.stop_at <= now)
+                .map(|(stream, a)| (stream.clone(), a.record.clone()))
+                .collect();
+            for (stream, record) in due {
+                armed.remove(&stream);
+                // Stopped meanwhile and recording again for another reason
+                if current_record(&stream).await.as_deref() != Some(record.as_str()) {
+                    continue;
+                }
+                tracing::info!("[recorder] post-roll of {} passed", stream);
+                if let Err(e) = super::stop(stream).await {
+                    tracing::error!("[recorder] stopping triggered recording failed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Record key of the running recording of `stream`
+async fn current_record(stream: &str) -> Option<String> {
+    super::TASKS
+        .read()
+        .await
+        .get(stream)
+        .map(|task| super::record_key(&task.info))
+}
+
+async fn save(stream: &str, record: &str, trigger: RecordingTrigger) {
+    let Some(index) = super::get_index().await else {
+        return;
+    };
+    if let Err(e) = index.set_trigger(stream, record, trigger).await {
+        tracing::error!("[recorder] index.json trigger update failed: {}", e);
+    }
+}
+
+/// Next packet of `rx`, `None` once its track is gone
+async fn next_packet(rx: &mut Option<broadcast::Receiver<Arc<Packet>>>) -> Option<Arc<Packet>> {
+    let Some(receiver) = rx.as_mut() else {
+        return std::future::pending().await;
+    };
+    loop {
+        match receiver.recv().await {
+            Ok(packet) => return Some(packet),
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Keep the last `keep` of the tracks of `forward` in `buffer`, following track changes
+async fn fill(forward: PeerForward, buffer: Arc<Mutex<Buffer>>, keep: Duration) {
+    let mut changes = forward.subscribe_tracks_change();
+    let mut video = forward.subscribe_video_rtp().await;
+    let mut audio = forward.subscribe_audio_rtp().await;
+    loop {
+        tokio::select! {
+            packet = next_packet(&mut video) => match packet {
+                Some(packet) => push(&mut buffer.lock().unwrap().video, Instant::now(), packet, keep),
+                None => video = None,
+            },
+            packet = next_packet(&mut audio) => match packet {
+                Some(packet) => push(&mut buffer.lock().unwrap().audio, Instant::now(), packet, keep),
+                None => audio = None,
+            },
+            change = changes.recv() => {
+                if matches!(change, Err(RecvError::Closed)) {
+                    return;
+                }
+                if video.is_none() {
+                    video = forward.subscribe_video_rtp().await;
+                }
+                if audio.is_none() {
+                    audio = forward.subscribe_audio_rtp().await;
+                }
+            }
+        }
+    }
+}
+
+/// Optional body of an MQTT trigger, other payloads take the defaults
+#[cfg(feature = "trigger-mqtt")]
+#[derive(Default, serde::Deserialize)]
+struct MqttTrigger {
+    pre_roll_ms: Option<u64>,
+    post_roll_ms: Option<u64>,
+}
+
+/// Fire the triggers `mqtt.topics` map the broker's messages to, until the process
+/// exits
+#[cfg(feature = "trigger-mqtt")]
+pub async fn subscribe(triggers: Arc<Triggers>, mqtt: crate::config::TriggerMqttConfig) {
+    use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS};
+
+    let options = match MqttOptions::parse_url(&mqtt.url) {
+        Ok(options) => options,
+        Err(e) => {
+            tracing::error!("[recorder] invalid trigger MQTT url {}: {}", mqtt.url, e);
+            return;
+        }
+    };
+    let (client, mut eventloop) = AsyncClient::new(options, 64);
+    loop {
+        match eventloop.poll().await {
+            // Subscriptions don't outlive a clean session, renewed on each connection
+            Ok(Event::Incoming(Incoming::ConnAck(_))) => {
+                for t in &mqtt.topics {
+                    if let Err(e) = client.try_subscribe(&t.topic, QoS::AtLeastOnce) {
+                        tracing::error!("[recorder] subscribing to {} failed: {}", t.topic, e);
+                    }
+                }
+                tracing::info!("[recorder] trigger MQTT connected to {}", mqtt.url);
+            }
+            Ok(Event::Incoming(Incoming::Publish(publish))) => {
+                let body: MqttTrigger =
+                    serde_json::from_slice(&publish.payload).unwrap_or_default();
+                for t in &mqtt.topics {
+                    let Some(levels) = match_topic(&t.topic, &publish.topic) else {
+                        continue;
+                    };
+                    let stream = stream_for(&t.stream, &levels);
+                    let triggers = triggers.clone();
+                    let source = format!("mqtt:{}", publish.topic);
+                    let (pre_roll_ms, post_roll_ms) = (body.pre_roll_ms, body.post_roll_ms);
+                    tokio::spawn(async move {
+                        if let Err(e) = triggers
+                            .fire(&stream, source, pre_roll_ms, post_roll_ms)
+                            .await
+                        {
+                            tracing::warn!("[recorder] MQTT trigger of {} failed: {}", stream, e);
+                        }
+                    });
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!("[recorder] trigger MQTT connection failed: {}", e);
+                time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+/// Levels of `topic` the wildcards of `filter` matched, `None` when it doesn't match.
+/// A trailing `#` matches the remaining levels as one
+#[cfg(feature = "trigger-mqtt")]
+fn match_topic(filter: &str, topic: &str) -> Option<Vec<String>> {
+    let mut levels = topic.split('/');
+    let mut matched = Vec::new();
+    for part in filter.split('/') {
+        match part {
+            "#" => {
+                matched.push(levels.by_ref().collect::<Vec<_>>().join("/"));
+                return Some(matched);
+            }
+            "+" => matched.push(levels.next()?.to_string()),
+            part if levels.next()? == part => {}
+            _ => return None,
+        }
+    }
+    levels.next().is_none().then_some(matched)
+}
+
+/// `template` with `{n}` replaced by the `n`th matched level, from 1
+#[cfg(feature = "trigger-mqtt")]
+fn stream_for(template: &str, levels: &[String]) -> String {
+    let mut stream = template.to_string();
+    for (i, level) in levels.iter().enumerate().rev() {
+        stream = stream.replace(&format!("{{{}}}", i + 1), level);
+    }
+    stream
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::rtp::header::Header;
+
+    fn packet(sequence_number: u16) -> Arc<Packet> {
+        Arc::new(Packet {
+            header: Header {
+                sequence_number,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn seqs(packets: &VecDeque<Arc<Packet>>) -> Vec<u16> {
+        packets.iter().map(|p| p.header.sequence_number).collect()
+    }
+
+    #[test]
+    fn test_pre_roll_window() {
+        let t0 = Instant::now();
+        let keep = Duration::from_millis(1_000);
+        let mut buffer = Buffer::default();
+        for i in 0..20u16 {
+            let at = t0 + Duration::from_millis(100 * i as u64);
+            push(
+                &mut buffer.video,
+                at,
+                packet(65_530u16.wrapping_add(i)),
+                keep,
+            );
+            if i % 2 == 0 {
+                push(&mut buffer.audio, at, packet(i), keep);
+            }
+        }
+        // Older than buffer_ms is gone
+        assert_eq!(buffer.video.len(), 11);
+
+        let now = t0 + Duration::from_millis(1_950);
+        let pre = buffer.pre_roll(now - Duration::from_millis(500), now);
+        assert_eq!(seqs(&pre.video), [9, 10, 11, 12, 13]);
+        assert_eq!(seqs(&pre.audio), [16, 18]);
+        assert_eq!(pre.span, Duration::from_millis(450));
+    }
+
+    #[test]
+    fn test_live_packets_after_pre_roll() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let mut pending: VecDeque<Arc<Packet>> = [65_534, 65_535, 0].map(packet).into();
+        // Subscribed before the pre-roll was taken, the last two are in both
+        for seq in [65_535, 0, 1, 2] {
+            tx.send(packet(seq)).unwrap();
+        }
+        PreRoll::skip_replayed(&mut pending, &mut rx);
+        assert_eq!(seqs(&pending), [65_534, 65_535, 0, 1]);
+        assert_eq!(rx.try_recv().unwrap().header.sequence_number, 2);
+
+        // Nothing to skip without a pre-roll
+        let mut empty = VecDeque::new();
+        tx.send(packet(3)).unwrap();
+        PreRoll::skip_replayed(&mut empty, &mut rx);
+        assert!(empty.is_empty());
+        assert_eq!(rx.try_recv().unwrap().header.sequence_number, 3);
+    }
+
+    #[test]
+    fn test_post_roll_extended() {
+        let now = Instant::now();
+        let mut armed = Armed {
+            record: "1700000000".to_string(),
+            stop_at: now + Duration::from_secs(30),
+            trigger: RecordingTrigger {
+                source: "http".to_string(),
+                first_at: 1_700_000_000_000_000,
+                last_at: 1_700_000_000_000_000,
+                count: 1,
+                pre_roll_ms: 5_000,
+            },
+        };
+        // A shorter post-roll doesn't bring the stop forward
+        armed.extend(
+            now + Duration::from_secs(10),
+            1_700_000_010_000_000,
+            Duration::from_secs(5),
+        );
+        assert_eq!(armed.stop_at, now + Duration::from_secs(30));
+        armed.extend(
+            now + Duration::from_secs(20),
+            1_700_000_020_000_000,
+            Duration::from_secs(30),
+        );
+        assert_eq!(armed.stop_at, now + Duration::from_secs(50));
+        assert_eq!(
+            (armed.trigger.count, armed.trigger.last_at),
+            (3, 1_700_000_020_000_000)
+        );
+        assert_eq!(
+            armed.stop_at_micros(now + Duration::from_secs(20), 1_700_000_020_000_000),
+            1_700_000_050_000_000
+        );
+    }
+
+    #[cfg(feature = "trigger-mqtt")]
+    #[test]
+    fn test_topic_to_stream() {
+        let levels = match_topic("cameras/+/motion", "cameras/door/motion").unwrap();
+        assert_eq!(stream_for("{1}", &levels), "door");
+        assert!(match_topic("cameras/+/motion", "cameras/door/sound").is_none());
+        assert!(match_topic("cameras/+/motion", "cameras/door/motion/zone").is_none());
+        assert!(match_topic("cameras/+", "cameras").is_none());
+
+        let levels = match_topic("site/+/cam/#", "site/a/cam/lobby/1").unwrap();
+        assert_eq!(levels, ["a", "lobby/1"]);
+        assert_eq!(stream_for("{1}-{2}", &levels), "a-lobby/1");
+    }
+}
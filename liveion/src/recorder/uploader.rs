@@ -1,16 +1,48 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::Duration;
 
 use anyhow::{Context, Result};
+use api::recorder::UploadLane;
+use bytes::Bytes;
 use http::header;
-use reqwest::Client;
+use reqwest::{Client, Method, StatusCode};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{Mutex, RwLock, Semaphore};
-use tracing::{debug, warn};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::runtime::Handle;
+use tokio::sync::{Mutex, Notify, RwLock, Semaphore, broadcast};
+use tracing::{debug, info, warn};
 
-use crate::config::UploadConfig;
+use super::disk::{self, DiskFull, FreeSpace};
+use super::durability::Durability;
+use super::lag::UploadLags;
+use super::staging;
+use crate::config::{UploadConfig, UploadTransport};
+use crate::metrics;
+
+/// `free_bytes` and `free_inodes` before the first free space check, or inodes of a
+/// filesystem that doesn't report them
+const FREE_UNKNOWN: u64 = u64::MAX;
+
+/// Smallest part S3 takes in a multipart upload, but for the last one
+const MIN_PART_BYTES: u64 = 5 << 20;
+
+/// Longest validity of a presigned URL, 7 days
+const MAX_PRESIGN_TTL_SECONDS: u64 = 604_800;
+
+/// Reads of a file streamed to liveman in `proxy` mode
+const PROXY_CHUNK_BYTES: usize = 256 << 10;
+
+/// Failed attempts kept in an entry's history
+const MAX_ATTEMPTS_KEPT: usize = 10;
+
+/// Priority of the objects a new recording needs to be played remotely, ahead of any
+/// recording's own
+const EXPEDITED_PRIORITY: u8 = u8::MAX;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct UploadEntry {
@@ -19,34 +51,346 @@ struct UploadEntry {
     local_path: String,
     retry_count: u32,
     next_retry_at: i64,
+    /// `x-amz-tagging` tag set the object is uploaded with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tagging: Option<String>,
+    /// Priority of the recording, higher uploads first
+    #[serde(default = "api::recorder::default_priority")]
+    priority: u8,
+    /// Multipart upload in progress, retries resume after its last uploaded part
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    multipart: Option<MultipartUpload>,
+    /// Times a newer file of the object replaced the queued one. An upload that read an
+    /// older revision leaves the entry queued for the newer one
+    #[serde(default)]
+    revision: u32,
+    /// Size and modification time of the queued file, the same file queued again is
+    /// left alone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stamp: Option<FileStamp>,
+    /// When the upload was given up after `max_retries`, it stays staged until the
+    /// object is queued again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dead_lettered_at: Option<i64>,
+    /// The latest failed attempts, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    attempts: Vec<UploadAttempt>,
+    /// Lane of the last dispatch, chosen from the size of the file then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lane: Option<UploadLane>,
+    /// Stream recording the object, its upload lag is counted against. Missing from
+    /// entries queued by older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stream: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A failed attempt of an upload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct UploadAttempt {
+    /// When it failed, UNIX milliseconds
+    at: i64,
+    error: String,
+    /// Set when storage accepted the upload but stored something else
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mismatch: Option<Mismatch>,
+}
+
+/// An uploaded object that differs from its local file, e.g. a body a proxy cut short
+/// while storage still answered `200`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Mismatch {
+    Size { local: u64, stored: u64 },
+    Checksum,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Size { local, stored } => write!(
+                f,
+                "stored object has {stored} bytes, the local file {local}"
+            ),
+            Self::Checksum => write!(f, "stored object differs from the local file"),
+        }
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct FileStamp {
+    size: u64,
+    modified_ns: i64,
+}
+
+impl FileStamp {
+    /// Stamp of the file at `path`, `None` when it can't be read. A file staged again
+    /// by a hard link keeps its stamp, a rewritten one doesn't
+    async fn of(path: &Path) -> Option<Self> {
+        let metadata = tokio::fs::metadata(path).await.ok()?;
+        let modified = metadata.modified().ok()?;
+        let modified_ns = modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?
+            .as_nanos()
+            .try_into()
+            .ok()?;
+        Some(Self {
+            size: metadata.len(),
+            modified_ns,
+        })
+    }
+}
+
+/// A queued upload, see [`UploadManager::queued`]
+#[derive(Debug, Clone)]
+pub struct QueuedUpload {
+    pub object_key: String,
+    /// Modification time of the queued file, UNIX microseconds
+    pub modified_at: Option<i64>,
+    pub dead_lettered: bool,
+    /// The last attempt stored something other than the local file
+    pub mismatched: bool,
+}
+
+/// Queued uploads by id, with the id of each object's entry: an object is queued once
+#[derive(Debug, Default)]
+struct Queue {
+    entries: HashMap<String, UploadEntry>,
+    by_key: HashMap<String, String>,
+}
+
+impl Queue {
+    /// Queue `entry`, replacing the entry of its object if it had another id
+    fn insert(&mut self, entry: UploadEntry) {
+        if let Some(id) = self
+            .by_key
+            .insert(entry.object_key.clone(), entry.id.clone())
+            && id != entry.id
+        {
+            self.entries.remove(&id);
+        }
+        self.entries.insert(entry.id.clone(), entry);
+    }
+
+    fn remove(&mut self, id: &str) -> Option<UploadEntry> {
+        let entry = self.entries.remove(id)?;
+        self.by_key.remove(&entry.object_key);
+        Some(entry)
+    }
+
+    fn get(&self, id: &str) -> Option<&UploadEntry> {
+        self.entries.get(id)
+    }
+
+    fn get_mut(&mut self, id: &str) -> Option<&mut UploadEntry> {
+        self.entries.get_mut(id)
+    }
+
+    fn by_key_mut(&mut self, object_key: &str) -> Option<&mut UploadEntry> {
+        let id = self.by_key.get(object_key)?;
+        self.entries.get_mut(id)
+    }
+
+    fn values(&self) -> impl Iterator<Item = &UploadEntry> {
+        self.entries.values()
+    }
+
+    fn values_mut(&mut self) -> impl Iterator<Item = &mut UploadEntry> {
+        self.entries.values_mut()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MultipartUpload {
+    upload_id: String,
+    /// Size of the file when the upload was created, a different file starts over
+    size: u64,
+    part_bytes: u64,
+    /// ETags of the parts uploaded so far, part `n` at `n - 1`
+    etags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct PresignRequest {
     method: String,
     path: String,
     ttl_seconds: u64,
+    content_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tagging: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    upload_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    part_number: Option<u32>,
+}
+
+/// Answer of storage to a presigned request
+struct Sent {
+    status: StatusCode,
+    etag: Option<String>,
+    /// `Content-Length` of the answer, the object's size for a `HEAD`
+    content_length: Option<u64>,
+    body: String,
+}
+
+/// A presigned request storage refused, retried with backoff
+#[derive(Debug)]
+struct Refused {
+    status: StatusCode,
+    code: Option<String>,
+}
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "upload failed: {} ({})", self.status, code),
+            None => write!(f, "upload failed: {}", self.status),
+        }
+    }
 }
 
+impl std::error::Error for Refused {}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct PresignResponse {
     url: String,
     headers: HashMap<String, String>,
 }
 
+/// Connection failures to liveman in a row, and the suspension they lead to
+#[derive(Default)]
+struct Outage {
+    failures: u32,
+    suspended: Option<api::recorder::UploadSuspension>,
+    /// Last probe while suspended, UNIX milliseconds
+    probed_at: i64,
+}
+
+impl Outage {
+    /// Count a failure at `now`, UNIX milliseconds. Returns true when it suspends the
+    /// queue, after `threshold` in a row (0 never suspends)
+    fn failed(&mut self, threshold: u32, now: i64, error: String) -> bool {
+        self.failures = self.failures.saturating_add(1);
+        if let Some(suspension) = self.suspended.as_mut() {
+            suspension.last_error = Some(error);
+            return false;
+        }
+        if threshold == 0 || self.failures < threshold {
+            return false;
+        }
+        self.suspended = Some(api::recorder::UploadSuspension {
+            since: now * 1000,
+            probes: 0,
+            last_error: Some(error),
+        });
+        self.probed_at = now;
+        true
+    }
+
+    /// liveman answered, returns the suspension this lifts
+    fn recovered(&mut self) -> Option<api::recorder::UploadSuspension> {
+        self.failures = 0;
+        self.suspended.take()
+    }
+
+    /// Whether a suspended queue probes liveman at `now`, counting the probe
+    fn probe_due(&mut self, interval_ms: u64, now: i64) -> bool {
+        let Some(suspension) = self.suspended.as_mut() else {
+            return false;
+        };
+        if now - self.probed_at < interval_ms as i64 {
+            return false;
+        }
+        self.probed_at = now;
+        suspension.probes += 1;
+        true
+    }
+}
+
+/// Whether `e` is liveman not answering at all, as opposed to answering with an error
+fn connection_failure(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .is_some_and(|e| e.is_connect() || e.is_timeout())
+}
+
+/// Uploads of one lane, with a concurrency of their own
+struct Lane {
+    kind: UploadLane,
+    semaphore: Arc<Semaphore>,
+    concurrency: usize,
+    /// Set when a pass left entries of the lane queued for lack of a permit, the next
+    /// upload of the lane to end wakes the loop for them
+    backlogged: AtomicBool,
+}
+
+impl Lane {
+    fn new(kind: UploadLane, concurrency: usize) -> Self {
+        let concurrency = concurrency.max(1);
+        metrics::RECORDER_UPLOAD_LANE_CONCURRENCY
+            .with_label_values(&[kind.as_str()])
+            .set(concurrency as i64);
+        Self {
+            kind,
+            semaphore: Arc::new(Semaphore::new(concurrency)),
+            concurrency,
+            backlogged: AtomicBool::new(false),
+        }
+    }
+
+    fn status(&self) -> api::recorder::UploadLaneStatus {
+        api::recorder::UploadLaneStatus {
+            lane: self.kind,
+            uploading: self.concurrency - self.semaphore.available_permits(),
+            concurrency: self.concurrency,
+        }
+    }
+}
+
 pub struct UploadManager {
     cfg: UploadConfig,
     client: Client,
-    entries: RwLock<HashMap<String, UploadEntry>>,
+    entries: RwLock<Queue>,
     write_lock: Mutex<()>,
-    semaphore: Arc<Semaphore>,
+    /// Objects below `small_object_bytes`, never held up by the large ones
+    small: Lane,
+    large: Lane,
     last_ping_fail: Mutex<i64>,
+    /// Suspends the whole queue while liveman is unreachable
+    outage: std::sync::Mutex<Outage>,
+    drained: broadcast::Sender<String>,
+    free_space: Arc<dyn FreeSpace>,
+    free_bytes: AtomicU64,
+    free_inodes: AtomicU64,
+    disk_guarded: AtomicBool,
+    /// Entries being uploaded, not dispatched again until their attempt ends
+    uploading: std::sync::Mutex<HashSet<String>>,
+    /// Held while a file is staged and queued, and while an uploaded one is removed, so
+    /// a newer version staged at the same path is never deleted with the older one
+    stage_lock: Mutex<()>,
+    /// When each manifest was last uploaded, for `mpd_upload_interval_ms`
+    manifest_uploads: std::sync::Mutex<HashMap<String, i64>>,
+    /// Manifests whose next version is final and skips `mpd_upload_interval_ms`
+    final_manifests: std::sync::Mutex<HashSet<String>>,
+    /// Objects whose next version is uploaded first and right away, see [`Self::expedite`]
+    expedited: std::sync::Mutex<HashSet<String>>,
+    /// Wakes the upload loop before its next tick
+    wake: Notify,
+    /// Runs the uploads, see [`super::runtime`]
+    runtime: Handle,
+    /// Whether the queue is rewritten on every change, see [`super::durability`]
+    durability: Arc<Durability>,
+    /// Changed since the queue file was last written, unless written through
+    queue_dirty: AtomicBool,
+    /// Upload lag within `lag_window_seconds`
+    lags: UploadLags,
 }
 
 impl UploadManager {
     pub async fn load(cfg: UploadConfig) -> Result<Self> {
         let client = Client::new();
-        let mut entries = HashMap::new();
+        let mut loaded = Vec::new();
         let path = PathBuf::from(&cfg.queue_path);
         if let Ok(content) = tokio::fs::read_to_string(&path).await {
             for line in content.lines() {
@@ -55,45 +399,447 @@ impl UploadManager {
                     continue;
                 }
                 if let Ok(entry) = serde_json::from_str::<UploadEntry>(line) {
-                    entries.insert(entry.id.clone(), entry);
+                    loaded.push(entry);
                 }
             }
         }
+        // Queues written before uploads were coalesced can hold several entries of one
+        // object, all pointing at the same staged file: the oldest is kept
+        loaded.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut entries = Queue::default();
+        for entry in loaded {
+            if !entries.by_key.contains_key(&entry.object_key) {
+                entries.insert(entry);
+            }
+        }
 
-        let concurrency = cfg.concurrency.max(1);
+        let small = Lane::new(UploadLane::Small, cfg.small_concurrency);
+        let large = Lane::new(UploadLane::Large, cfg.concurrency);
+        let lags = UploadLags::new(cfg.lag_window_seconds);
         Ok(Self {
             cfg,
             client,
             entries: RwLock::new(entries),
             write_lock: Mutex::new(()),
-            semaphore: Arc::new(Semaphore::new(concurrency)),
+            small,
+            large,
             last_ping_fail: Mutex::new(0),
+            outage: Default::default(),
+            drained: broadcast::channel(64).0,
+            free_space: disk::system(),
+            free_bytes: AtomicU64::new(FREE_UNKNOWN),
+            free_inodes: AtomicU64::new(FREE_UNKNOWN),
+            disk_guarded: AtomicBool::new(false),
+            uploading: Default::default(),
+            stage_lock: Mutex::new(()),
+            manifest_uploads: Default::default(),
+            final_manifests: Default::default(),
+            expedited: Default::default(),
+            wake: Notify::new(),
+            runtime: Handle::current(),
+            durability: Arc::default(),
+            queue_dirty: AtomicBool::new(false),
+            lags,
         })
     }
 
+    /// Run uploads on `runtime` instead of the runtime the queue was loaded on
+    pub fn with_runtime(mut self, runtime: Handle) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Write and sync the queue file as `durability` says instead of on every change
+    pub fn with_durability(mut self, durability: Arc<Durability>) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    pub fn with_free_space(mut self, free_space: Arc<dyn FreeSpace>) -> Self {
+        self.free_space = free_space;
+        self
+    }
+
+    /// Read the free space of `local_dir`, `Err` while it is below `min_free_bytes` or
+    /// `min_free_inodes`
+    pub fn check_free_space(&self) -> std::result::Result<(), DiskFull> {
+        let available = match self.free_space.available(Path::new(&self.cfg.local_dir)) {
+            Ok(available) => available,
+            Err(e) => {
+                // Not created before the first recording, nothing to guard yet
+                debug!("[uploader] free space of {}: {}", self.cfg.local_dir, e);
+                return Ok(());
+            }
+        };
+        self.free_bytes.store(available.bytes, Ordering::Relaxed);
+        metrics::RECORDER_DISK_FREE_BYTES.set(available.bytes.min(i64::MAX as u64) as i64);
+        self.free_inodes
+            .store(available.inodes.unwrap_or(FREE_UNKNOWN), Ordering::Relaxed);
+        if let Some(inodes) = available.inodes {
+            metrics::RECORDER_DISK_FREE_INODES.set(inodes.min(i64::MAX as u64) as i64);
+        }
+
+        let (min_free_bytes, min_free_inodes) = (self.cfg.min_free_bytes, self.cfg.min_free_inodes);
+        let full = if min_free_bytes > 0 && available.bytes < min_free_bytes {
+            Some(DiskFull::Bytes {
+                available: available.bytes,
+                min_free_bytes,
+            })
+        } else {
+            available
+                .inodes
+                .filter(|inodes| min_free_inodes > 0 && *inodes < min_free_inodes)
+                .map(|inodes| DiskFull::Inodes {
+                    available: inodes,
+                    min_free_inodes,
+                })
+        };
+        let guarded = full.is_some();
+        if self.disk_guarded.swap(guarded, Ordering::AcqRel) != guarded {
+            metrics::RECORDER_DISK_GUARD.set(guarded as i64);
+            match full {
+                Some(full) => warn!(
+                    "[uploader] {} in {}: refusing new uploads and dropping segments",
+                    full, self.cfg.local_dir
+                ),
+                None => info!(
+                    "[uploader] {} bytes free in {}, recording resumes",
+                    available.bytes, self.cfg.local_dir
+                ),
+            }
+        }
+        match full {
+            Some(full) => Err(full),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the last free space check was below `min_free_bytes` or `min_free_inodes`
+    pub fn disk_guarded(&self) -> bool {
+        self.disk_guarded.load(Ordering::Acquire)
+    }
+
+    pub fn disk_status(&self) -> api::recorder::DiskStatus {
+        let free_bytes = self.free_bytes.load(Ordering::Relaxed);
+        let free_inodes = self.free_inodes.load(Ordering::Relaxed);
+        api::recorder::DiskStatus {
+            free_bytes: (free_bytes != FREE_UNKNOWN).then_some(free_bytes),
+            min_free_bytes: self.cfg.min_free_bytes,
+            free_inodes: (free_inodes != FREE_UNKNOWN).then_some(free_inodes),
+            min_free_inodes: self.cfg.min_free_inodes,
+            guarded: self.disk_guarded(),
+        }
+    }
+
     pub fn local_dir(&self) -> String {
         self.cfg.local_dir.clone()
     }
 
-    pub async fn enqueue(&self, object_key: String, local_path: String) -> Result<()> {
-        let entry = UploadEntry {
-            id: format!("{}:{}", object_key, chrono::Utc::now().timestamp_millis()),
+    pub fn staging_dir(&self) -> String {
+        self.cfg.staging_dir.clone()
+    }
+
+    /// Hand a finished file of `stream` in `local_dir` over to the staging dir and queue
+    /// it.
+    ///
+    /// The queued `local_path` always points into the staging dir, so uploads never
+    /// depend on what happens to `local_dir`. Refused with [`DiskFull`] while free space
+    /// is below `min_free_bytes` or free inodes below `min_free_inodes`.
+    pub async fn stage(
+        &self,
+        object_key: String,
+        local_path: &Path,
+        stream: &str,
+        tagging: Option<String>,
+        priority: u8,
+    ) -> Result<()> {
+        self.check_free_space()?;
+        let staged = Path::new(&self.cfg.staging_dir).join(&object_key);
+        let _guard = self.stage_lock.lock().await;
+        staging::stage_file(local_path, &staged, self.cfg.local_retention_minutes > 0)
+            .await
+            .with_context(|| format!("stage {} for upload", local_path.display()))?;
+        self.enqueue(
             object_key,
-            local_path,
-            retry_count: 0,
-            next_retry_at: 0,
+            staged.to_string_lossy().into_owned(),
+            stream,
+            tagging,
+            priority,
+        )
+        .await
+    }
+
+    /// Upload the queued objects under `dir` with `tagging` instead, returns how many changed
+    pub async fn retag_pending(&self, dir: &str, tagging: &str) -> Result<usize> {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        let changed = {
+            let mut map = self.entries.write().await;
+            let mut changed = 0;
+            for entry in map.values_mut() {
+                if entry.object_key.starts_with(&prefix)
+                    && !storage::is_shared(&entry.object_key)
+                    && entry.tagging.as_deref() != Some(tagging)
+                {
+                    entry.tagging = Some(tagging.to_string());
+                    changed += 1;
+                }
+            }
+            changed
+        };
+        if changed > 0 {
+            self.persist_queue().await?;
+        }
+        Ok(changed)
+    }
+
+    /// Upload the queued objects under `dir` with `priority` instead
+    pub async fn reprioritize_pending(&self, dir: &str, priority: u8) {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        let changed = {
+            let mut map = self.entries.write().await;
+            let mut changed = false;
+            for entry in map.values_mut() {
+                if entry.object_key.starts_with(&prefix) && entry.priority != priority {
+                    entry.priority = priority;
+                    changed = true;
+                }
+            }
+            changed
+        };
+        if changed && let Err(e) = self.persist_queue().await {
+            warn!("[uploader] failed to persist reprioritized queue: {}", e);
+        }
+    }
+
+    /// URL and headers for a `PutObjectTagging` request on `object_key`, signed by liveman
+    pub async fn presign_tagging(&self, object_key: &str) -> Result<storage::PresignedRequest> {
+        let presign = self
+            .presign("TAGGING", object_key, "application/xml", None)
+            .await?;
+        Ok(storage::PresignedRequest {
+            method: "PUT".to_string(),
+            url: presign.url,
+            headers: presign.headers.into_iter().collect(),
+        })
+    }
+
+    /// Prune `local_dir` to `local_retention_minutes`, independently of uploads
+    pub async fn prune_loop(self: Arc<Self>) {
+        let retention = Duration::from_secs(self.cfg.local_retention_minutes * 60);
+        let local_dir = std::path::absolute(&self.cfg.local_dir)
+            .unwrap_or_else(|_| PathBuf::from(&self.cfg.local_dir));
+        let staging_dir = std::path::absolute(&self.cfg.staging_dir)
+            .unwrap_or_else(|_| PathBuf::from(&self.cfg.staging_dir));
+        let mut ticker = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            ticker.tick().await;
+            let removed =
+                staging::prune_local(&local_dir, std::slice::from_ref(&staging_dir), retention)
+                    .await;
+            if removed > 0 {
+                debug!(
+                    "[uploader] pruned {} files older than {} minutes from {}",
+                    removed,
+                    self.cfg.local_retention_minutes,
+                    local_dir.display()
+                );
+            }
+        }
+    }
+
+    /// Upload the next version of the manifest `object_key` as soon as it is staged,
+    /// regardless of `mpd_upload_interval_ms`. Called before the final manifest of a
+    /// recording is written
+    pub fn upload_final_manifest(&self, object_key: String) {
+        self.final_manifests.lock().unwrap().insert(object_key);
+    }
+
+    /// Upload the next version of `object_key` as soon as it is staged, ahead of every
+    /// other upload and without waiting for the loop's next tick or for
+    /// `mpd_upload_interval_ms`. Called for the init segments, the first media segments
+    /// and the manifests up to them, so a new recording plays remotely within seconds
+    pub fn expedite(&self, object_key: String) {
+        self.expedited.lock().unwrap().insert(object_key);
+    }
+
+    /// Queue `local_path` of `stream` as `object_key`.
+    ///
+    /// An object still queued, e.g. a manifest rewritten after every segment, has its
+    /// entry replaced instead of getting a second one: only the newest file is uploaded.
+    /// A manifest waits for `mpd_upload_interval_ms` since its last upload unless it
+    /// is the final one.
+    ///
+    /// Queuing the file already queued, as a restart replays what the recorder did
+    /// before it crashed, changes nothing, so the file is uploaded once. An upload given
+    /// up after `max_retries` is tried again.
+    async fn enqueue(
+        &self,
+        object_key: String,
+        local_path: String,
+        stream: &str,
+        tagging: Option<String>,
+        priority: u8,
+    ) -> Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let expedited = self.expedited.lock().unwrap().remove(&object_key);
+        let (not_before, priority) = if expedited {
+            (0, EXPEDITED_PRIORITY)
+        } else {
+            (self.manifest_not_before(&object_key), priority)
         };
+        let stamp = FileStamp::of(Path::new(&local_path)).await;
         {
             let mut map = self.entries.write().await;
-            map.insert(entry.id.clone(), entry);
+            match map.by_key_mut(&object_key) {
+                Some(entry)
+                    if entry.local_path == local_path
+                        && stamp.is_some()
+                        && entry.stamp == stamp =>
+                {
+                    if entry.dead_lettered_at.is_none() {
+                        debug!("[uploader] {} is already queued", object_key);
+                        return Ok(());
+                    }
+                    info!(
+                        "[uploader] {} queued again, retrying its upload",
+                        object_key
+                    );
+                    entry.dead_lettered_at = None;
+                    entry.retry_count = 0;
+                    entry.next_retry_at = not_before;
+                }
+                Some(entry) => {
+                    if entry.dead_lettered_at.take().is_some() {
+                        entry.retry_count = 0;
+                    }
+                    entry.local_path = local_path;
+                    entry.tagging = tagging;
+                    entry.priority = priority;
+                    entry.multipart = None;
+                    entry.revision += 1;
+                    entry.stamp = stamp;
+                    entry.attempts.clear();
+                    entry.stream = Some(stream.to_string());
+                    // A failing upload keeps its backoff
+                    if entry.retry_count == 0 {
+                        entry.next_retry_at = not_before;
+                    }
+                    metrics::RECORDER_UPLOADS_COALESCED.inc();
+                }
+                None => {
+                    let entry = UploadEntry {
+                        id: format!("{}:{}", object_key, now),
+                        object_key,
+                        local_path,
+                        retry_count: 0,
+                        next_retry_at: not_before,
+                        tagging,
+                        priority,
+                        multipart: None,
+                        revision: 0,
+                        stamp,
+                        dead_lettered_at: None,
+                        attempts: Vec::new(),
+                        lane: None,
+                        stream: Some(stream.to_string()),
+                    };
+                    map.insert(entry);
+                }
+            }
         }
-        self.persist_queue().await
+        self.persist_queue().await?;
+        if expedited {
+            self.wake.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Earliest upload of a new version of `object_key`, 0 for anything but a manifest
+    /// uploaded less than `mpd_upload_interval_ms` ago and not final
+    fn manifest_not_before(&self, object_key: &str) -> i64 {
+        let interval = self.cfg.mpd_upload_interval_ms as i64;
+        if interval == 0 || !object_key.ends_with(".mpd") {
+            return 0;
+        }
+        if self.final_manifests.lock().unwrap().contains(object_key) {
+            return 0;
+        }
+        let uploads = self.manifest_uploads.lock().unwrap();
+        uploads.get(object_key).map_or(0, |at| at + interval)
+    }
+
+    fn manifest_uploaded(&self, object_key: &str) {
+        let interval = self.cfg.mpd_upload_interval_ms as i64;
+        if interval == 0 || !object_key.ends_with(".mpd") {
+            return;
+        }
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut uploads = self.manifest_uploads.lock().unwrap();
+        // Older uploads no longer hold anything back
+        uploads.retain(|_, at| now - *at < interval);
+        uploads.insert(object_key.to_string(), now);
+    }
+
+    /// Queued uploads of objects under `dir`
+    pub async fn pending_under(&self, dir: &str) -> usize {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        let map = self.entries.read().await;
+        map.values()
+            .filter(|e| e.object_key.starts_with(&prefix))
+            .count()
+    }
+
+    /// The queued uploads as the integrity rollup weighs them
+    pub async fn queued(&self) -> Vec<QueuedUpload> {
+        let map = self.entries.read().await;
+        map.values()
+            .map(|entry| QueuedUpload {
+                object_key: entry.object_key.clone(),
+                modified_at: entry.stamp.map(|stamp| stamp.modified_ns / 1_000),
+                dead_lettered: entry.dead_lettered_at.is_some(),
+                mismatched: entry
+                    .attempts
+                    .last()
+                    .is_some_and(|attempt| attempt.mismatch.is_some()),
+            })
+            .collect()
+    }
+
+    /// Upload lag of `stream`, or of the node, within `lag_window_seconds`
+    pub async fn upload_lag(&self, stream: Option<&str>) -> api::recorder::UploadLag {
+        let oldest_pending = {
+            let map = self.entries.read().await;
+            map.values()
+                .filter(|entry| {
+                    entry.dead_lettered_at.is_none()
+                        && stream.is_none_or(|stream| entry.stream.as_deref() == Some(stream))
+                })
+                .filter_map(|entry| entry.stamp.map(|stamp| stamp.modified_ns / 1_000_000))
+                .min()
+        };
+        self.lags.summary(
+            stream,
+            oldest_pending,
+            chrono::Utc::now().timestamp_millis(),
+        )
+    }
+
+    /// Receive the object directory of each upload that left no pending uploads under it
+    pub fn subscribe_drained(&self) -> broadcast::Receiver<String> {
+        self.drained.subscribe()
     }
 
     pub async fn run(self: std::sync::Arc<Self>) {
         let interval = Duration::from_millis(self.cfg.interval_ms.max(500));
         loop {
-            tokio::time::sleep(interval).await;
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = self.wake.notified() => {}
+            }
+            // Lifts the guard once space is freed, even when nothing is staged meanwhile
+            let _ = self.check_free_space();
+            let worst = self.upload_lag(None).await.worst_ms / 1_000;
+            metrics::RECORDER_UPLOAD_LAG_WORST.set(worst.min(i64::MAX as u64) as i64);
             if let Err(e) = self.clone().process_queue().await {
                 warn!("[uploader] queue processing failed: {}", e);
             }
@@ -104,26 +850,62 @@ impl UploadManager {
         if !self.is_liveman_available().await? {
             return Ok(());
         }
-        let now = chrono::Utc::now().timestamp_millis();
-        let entries: Vec<UploadEntry> = {
-            let map = self.entries.read().await;
-            map.values()
-                .filter(|entry| entry.next_retry_at <= now)
-                .cloned()
-                .collect()
-        };
-
+        let entries = self.due(chrono::Utc::now().timestamp_millis()).await;
         if entries.is_empty() {
             return Ok(());
         }
 
-        for entry in entries {
-            let permit = self.semaphore.clone().acquire_owned().await?;
+        for mut entry in entries {
+            // Connection failures of the entries dispatched so far suspended the queue
+            if self.suspended() {
+                break;
+            }
+            // Long uploads outlast the loop interval
+            if self.uploading.lock().unwrap().contains(&entry.id) {
+                continue;
+            }
+            // Chosen anew each time, a manifest grows with its recording
+            let size = tokio::fs::metadata(&entry.local_path)
+                .await
+                .map(|metadata| metadata.len());
+            let lane = self.lane(self.lane_for(size.ok()));
+            // Entries of a busy lane wait for one of its uploads to end, without holding
+            // up the other lane
+            let Ok(permit) = lane.semaphore.clone().try_acquire_owned() else {
+                lane.backlogged.store(true, Ordering::Release);
+                continue;
+            };
+            self.uploading.lock().unwrap().insert(entry.id.clone());
+            if entry.lane != Some(lane.kind) {
+                entry.lane = Some(lane.kind);
+                if let Some(queued) = self.entries.write().await.get_mut(&entry.id) {
+                    queued.lane = entry.lane;
+                }
+            }
+            let in_flight =
+                metrics::RECORDER_UPLOADS_IN_FLIGHT.with_label_values(&[lane.kind.as_str()]);
+            in_flight.inc();
+            let kind = lane.kind;
             let this = self.clone();
-            tokio::spawn(async move {
-                let _permit = permit;
+            self.runtime.spawn(async move {
+                let id = entry.id.clone();
+                let expedited = entry.priority == EXPEDITED_PRIORITY;
                 if let Err(e) = this.try_upload(entry).await {
-                    warn!("[uploader] upload failed: {}", e);
+                    // Logged once for the whole queue
+                    if this.suspended() {
+                        debug!("[uploader] upload failed while suspended: {}", e);
+                    } else {
+                        warn!("[uploader] upload failed: {}", e);
+                    }
+                }
+                this.uploading.lock().unwrap().remove(&id);
+                drop(permit);
+                in_flight.dec();
+                // A newer version staged during the upload was skipped by the pass it woke,
+                // entries left for a free permit wait for no tick either
+                let backlogged = this.lane(kind).backlogged.swap(false, Ordering::AcqRel);
+                if expedited || backlogged {
+                    this.wake.notify_one();
                 }
             });
         }
@@ -131,117 +913,773 @@ impl UploadManager {
         Ok(())
     }
 
-    async fn try_upload(&self, mut entry: UploadEntry) -> Result<()> {
-        let presign = self.presign_put(&entry.object_key).await?;
-        let body = tokio::fs::read(&entry.local_path)
-            .await
-            .with_context(|| format!("read local file {}", entry.local_path))?;
-
-        let mut req = self.client.put(presign.url);
-        for (k, v) in presign.headers {
-            if let (Ok(name), Ok(value)) = (
-                header::HeaderName::from_bytes(k.as_bytes()),
-                header::HeaderValue::from_str(&v),
-            ) {
-                req = req.header(name, value);
-            }
+    /// Lane of a file of `size` bytes, the small one when it is unknown as it fails fast
+    fn lane_for(&self, size: Option<u64>) -> UploadLane {
+        let threshold = self.cfg.small_object_bytes;
+        if threshold == 0 || size.is_some_and(|size| size >= threshold) {
+            UploadLane::Large
+        } else {
+            UploadLane::Small
         }
+    }
 
-        let resp = req.body(body).send().await?;
-        if !resp.status().is_success() {
-            entry.retry_count += 1;
-            entry.next_retry_at = backoff_ts(entry.retry_count);
-            self.update_entry(entry).await?;
-            return Err(anyhow::anyhow!("upload failed: {}", resp.status()));
+    fn lane(&self, kind: UploadLane) -> &Lane {
+        match kind {
+            UploadLane::Small => &self.small,
+            UploadLane::Large => &self.large,
         }
+    }
 
-        debug!("[uploader] uploaded {}", entry.object_key);
-        let _ = tokio::fs::remove_file(&entry.local_path).await;
-        self.remove_entry(&entry.id).await?;
-        Ok(())
+    /// Entries ready for an attempt at `now` in dispatch order: higher priority first,
+    /// then the longest waiting retry, then by key so segments go out in order whether
+    /// their numbers are zero-padded or not
+    async fn due(&self, now: i64) -> Vec<UploadEntry> {
+        let map = self.entries.read().await;
+        let mut entries: Vec<UploadEntry> = map
+            .values()
+            .filter(|entry| entry.dead_lettered_at.is_none() && entry.next_retry_at <= now)
+            .cloned()
+            .collect();
+        entries.sort_by(|a, b| {
+            b.priority
+                .cmp(&a.priority)
+                .then(a.next_retry_at.cmp(&b.next_retry_at))
+                .then_with(|| key_order(&a.object_key, &b.object_key))
+        });
+        entries
     }
 
-    async fn presign_put(&self, object_key: &str) -> Result<PresignResponse> {
-        let url = format!(
-            "{}/api/storage/presign",
-            self.cfg.liveman_url.trim_end_matches('/')
-        );
-        let req = PresignRequest {
-            method: "PUT".to_string(),
-            path: object_key.to_string(),
-            ttl_seconds: self.cfg.presign_ttl_seconds.max(30),
-        };
-        let mut builder = self.client.post(url).json(&req);
-        if !self.cfg.liveman_token.is_empty() {
-            builder = builder.header(
-                header::AUTHORIZATION,
-                format!("Bearer {}", self.cfg.liveman_token),
+    async fn try_upload(&self, mut entry: UploadEntry) -> Result<()> {
+        let size = tokio::fs::metadata(&entry.local_path)
+            .await
+            .with_context(|| format!("read local file {}", entry.local_path))?
+            .len();
+        let threshold = self.cfg.multipart_threshold_bytes;
+        let proxy = self.cfg.mode == UploadTransport::Proxy;
+        if proxy && entry.multipart.take().is_some() {
+            warn!(
+                "[uploader] dropping the multipart upload of {}, proxied uploads send whole objects",
+                entry.object_key
             );
         }
-        let resp = builder.send().await?;
-        if !resp.status().is_success() {
-            return Err(anyhow::anyhow!("presign failed: {}", resp.status()));
+        let multipart = entry.multipart.is_some() || (threshold > 0 && size >= threshold);
+        let uploaded = if proxy {
+            self.upload_proxied(&entry).await
+        } else if multipart {
+            self.upload_multipart(&mut entry, size).await
+        } else {
+            self.upload_single(&entry, size).await
+        };
+        // The local file stays until the stored object is known to match it
+        let uploaded = match uploaded {
+            Ok(()) => self.verify_upload(&entry, size).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = uploaded {
+            let mismatch = e.downcast_ref::<Mismatch>().cloned();
+            if mismatch.is_some() {
+                // Completed, the next attempt starts a new upload
+                entry.multipart = None;
+            }
+            // Entries wait out a suspension without using up their retries
+            if (e.downcast_ref::<Refused>().is_some() || mismatch.is_some()) && !self.suspended() {
+                if entry.attempts.len() >= MAX_ATTEMPTS_KEPT {
+                    entry.attempts.remove(0);
+                }
+                entry.attempts.push(UploadAttempt {
+                    at: chrono::Utc::now().timestamp_millis(),
+                    error: format!("{e:#}"),
+                    mismatch,
+                });
+                entry.retry_count += 1;
+                let max_retries = self.cfg.max_retries;
+                if max_retries > 0 && entry.retry_count > max_retries {
+                    warn!(
+                        "[uploader] giving up on {} after {} attempts until it is queued again",
+                        entry.object_key, entry.retry_count
+                    );
+                    entry.dead_lettered_at = Some(chrono::Utc::now().timestamp_millis());
+                    metrics::RECORDER_UPLOADS_DEAD_LETTERED.inc();
+                } else {
+                    entry.next_retry_at = backoff_ts(entry.retry_count);
+                }
+                self.update_entry(entry).await?;
+            }
+            return Err(e);
         }
-        Ok(resp.json::<PresignResponse>().await?)
-    }
 
-    async fn is_liveman_available(&self) -> Result<bool> {
-        if self.cfg.liveman_url.trim().is_empty() {
-            return Ok(false);
+        if let Some(stamp) = entry.stamp {
+            self.lags.record(
+                entry.stream.as_deref(),
+                stamp.modified_ns / 1_000_000,
+                chrono::Utc::now().timestamp_millis(),
+            );
         }
-        let now = chrono::Utc::now().timestamp_millis();
-        let mut last_fail = self.last_ping_fail.lock().await;
-        if *last_fail != 0 && now - *last_fail < 5_000 {
-            return Ok(false);
+        self.manifest_uploaded(&entry.object_key);
+        {
+            let _guard = self.stage_lock.lock().await;
+            if !self.remove_uploaded(&entry).await? {
+                debug!(
+                    "[uploader] uploaded {}, a newer version is queued",
+                    entry.object_key
+                );
+                return Ok(());
+            }
+            debug!("[uploader] uploaded {}", entry.object_key);
+            let _ = tokio::fs::remove_file(&entry.local_path).await;
         }
-
-        let url = format!(
-            "{}/api/storage/ping",
-            self.cfg.liveman_url.trim_end_matches('/')
-        );
-        let mut req = self.client.get(url);
-        if !self.cfg.liveman_token.is_empty() {
-            req = req.header(
-                header::AUTHORIZATION,
-                format!("Bearer {}", self.cfg.liveman_token),
-            );
+        if let Some((dir, _)) = entry.object_key.rsplit_once('/') {
+            let prefix = format!("{dir}/");
+            let pending = {
+                let map = self.entries.read().await;
+                map.values().any(|e| e.object_key.starts_with(&prefix))
+            };
+            if !pending {
+                let _ = self.drained.send(dir.to_string());
+            }
         }
+        Ok(())
+    }
 
-        match req.send().await {
-            Ok(resp) if resp.status().is_success() => {
-                *last_fail = 0;
-                Ok(true)
+    /// Compare the object just uploaded with the local file, per `verify_size` and
+    /// `verify_checksum`. `Err` with [`Mismatch`] when they differ
+    async fn verify_upload(&self, entry: &UploadEntry, size: u64) -> Result<()> {
+        if !self.cfg.verify_size && !self.cfg.verify_checksum {
+            return Ok(());
+        }
+        let content_type = storage::content_type_for(&entry.object_key);
+        let mismatch = if self.cfg.verify_size {
+            let stored = match self.cfg.mode {
+                UploadTransport::Presign => {
+                    let req = self.presign_request("HEAD", &entry.object_key, content_type);
+                    self.send_presigned(req, Method::HEAD, None, Bytes::new())
+                        .await
+                        .map(|sent| sent.content_length)
+                }
+                UploadTransport::Proxy => self
+                    .send_proxied(self.proxy_request(Method::HEAD, &entry.object_key))
+                    .await
+                    .map(|resp| content_length(resp.headers())),
             }
-            Ok(resp) => {
-                *last_fail = now;
-                warn!("[uploader] liveman ping failed: {}", resp.status());
-                Ok(false)
+            .context("verify the uploaded size")?;
+            match stored {
+                Some(stored) if stored != size => Some(Mismatch::Size {
+                    local: size,
+                    stored,
+                }),
+                _ => None,
             }
-            Err(e) => {
-                *last_fail = now;
-                warn!("[uploader] liveman ping error: {}", e);
-                Ok(false)
+        } else {
+            None
+        };
+        let mismatch = match mismatch {
+            None if self.cfg.verify_checksum => {
+                let local = file_digest(Path::new(&entry.local_path)).await?;
+                let stored = self.stored_digest(&entry.object_key).await?;
+                (local != stored).then_some(Mismatch::Checksum)
+            }
+            mismatch => mismatch,
+        };
+        match mismatch {
+            Some(mismatch) => {
+                metrics::RECORDER_UPLOAD_MISMATCHES.inc();
+                warn!(
+                    "[uploader] {} uploaded but {}, keeping the local file to retry",
+                    entry.object_key, mismatch
+                );
+                Err(anyhow::Error::new(mismatch))
+            }
+            None => {
+                metrics::RECORDER_UPLOADS_VERIFIED.inc();
+                Ok(())
             }
         }
     }
 
-    async fn update_entry(&self, entry: UploadEntry) -> Result<()> {
-        {
-            let mut map = self.entries.write().await;
-            map.insert(entry.id.clone(), entry);
+    /// SHA-256 of the stored `object_key`, read back through a presigned `GET`, or
+    /// through liveman in `proxy` mode
+    async fn stored_digest(&self, object_key: &str) -> Result<Vec<u8>> {
+        let mut resp = match self.cfg.mode {
+            UploadTransport::Presign => {
+                let content_type = storage::content_type_for(object_key);
+                let presign = self
+                    .presign("GET", object_key, content_type, None)
+                    .await
+                    .context("verify the uploaded checksum")?;
+                // Errors carry no URL, a presigned one holds the signature and any
+                // session token
+                let resp = self
+                    .client
+                    .get(presign.url)
+                    .send()
+                    .await
+                    .map_err(reqwest::Error::without_url)?;
+                let status = resp.status();
+                if !status.is_success() {
+                    return Err(anyhow::Error::new(Refused { status, code: None }));
+                }
+                resp
+            }
+            UploadTransport::Proxy => self
+                .send_proxied(self.proxy_request(Method::GET, object_key))
+                .await
+                .context("verify the uploaded checksum")?,
+        };
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = resp.chunk().await? {
+            hasher.update(&chunk);
         }
-        self.persist_queue().await
+        Ok(hasher.finalize().to_vec())
+    }
+
+    /// Upload the file with one `PutObject`
+    async fn upload_single(&self, entry: &UploadEntry, size: u64) -> Result<()> {
+        // The content type is part of the signature, so it must match what liveman signed
+        let content_type = storage::content_type_for(&entry.object_key);
+        let body = tokio::fs::read(&entry.local_path)
+            .await
+            .with_context(|| format!("read local file {}", entry.local_path))?;
+        let req = PresignRequest {
+            ttl_seconds: self.presign_ttl(size),
+            tagging: entry.tagging.clone(),
+            ..self.presign_request("PUT", &entry.object_key, content_type)
+        };
+        self.send_presigned(req, Method::PUT, Some(content_type), body.into())
+            .await?;
+        Ok(())
+    }
+
+    /// Stream the file to liveman, which writes it to storage. Sent chunked, so it is
+    /// never read into memory whole
+    async fn upload_proxied(&self, entry: &UploadEntry) -> Result<()> {
+        let file = tokio::fs::File::open(&entry.local_path)
+            .await
+            .with_context(|| format!("read local file {}", entry.local_path))?;
+        let mut req = self
+            .proxy_request(Method::PUT, &entry.object_key)
+            .header(
+                header::CONTENT_TYPE,
+                storage::content_type_for(&entry.object_key),
+            )
+            .body(reqwest::Body::wrap_stream(file_chunks(file)));
+        if let Some(tagging) = &entry.tagging {
+            req = req.header("x-amz-tagging", tagging);
+        }
+        self.send_proxied(req).await?;
+        Ok(())
+    }
+
+    /// Request of `method` on `object_key` through liveman's
+    /// `/api/storage/objects/{path}`
+    fn proxy_request(&self, method: Method, object_key: &str) -> reqwest::RequestBuilder {
+        let url = format!(
+            "{}/api/storage/objects/{}",
+            self.cfg.liveman_url.trim_end_matches('/'),
+            storage::encode_key_for_url(object_key)
+        );
+        let builder = self.client.request(method, url);
+        if self.cfg.liveman_token.is_empty() {
+            return builder;
+        }
+        builder.header(
+            header::AUTHORIZATION,
+            format!("Bearer {}", self.cfg.liveman_token.expose_secret()),
+        )
+    }
+
+    /// Send `req` to liveman. A refusal is a [`Refused`] carrying liveman's reason, so
+    /// it is retried with backoff like one from storage
+    async fn send_proxied(&self, req: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let resp = match req.send().await {
+            Ok(resp) => resp,
+            Err(e) => {
+                let e = anyhow::Error::new(e);
+                if connection_failure(&e) {
+                    self.liveman_unreachable(&e);
+                }
+                return Err(e);
+            }
+        };
+        self.liveman_reachable();
+        let status = resp.status();
+        if !status.is_success() {
+            let reason = resp.text().await.unwrap_or_default();
+            return Err(anyhow::Error::new(Refused {
+                status,
+                code: Some(reason.trim().to_string()).filter(|r| !r.is_empty()),
+            }));
+        }
+        Ok(resp)
     }
 
-    async fn remove_entry(&self, id: &str) -> Result<()> {
+    /// Upload the file in parts of `multipart_part_bytes`, continuing the upload
+    /// `entry` already started. Each part is recorded in the queue once uploaded.
+    async fn upload_multipart(&self, entry: &mut UploadEntry, size: u64) -> Result<()> {
+        let content_type = storage::content_type_for(&entry.object_key);
+        if entry.multipart.as_ref().is_some_and(|m| m.size != size) {
+            warn!(
+                "[uploader] {} changed since its multipart upload began, starting over",
+                entry.local_path
+            );
+            entry.multipart = None;
+        }
+        if entry.multipart.is_none() {
+            let req = PresignRequest {
+                tagging: entry.tagging.clone(),
+                ..self.presign_request("CREATE_MULTIPART", &entry.object_key, content_type)
+            };
+            let sent = self
+                .send_presigned(req, Method::POST, Some(content_type), Bytes::new())
+                .await?;
+            let upload_id = xml_value(&sent.body, "UploadId").ok_or_else(|| {
+                anyhow::anyhow!(
+                    "no UploadId creating the multipart upload of {}",
+                    entry.object_key
+                )
+            })?;
+            entry.multipart = Some(MultipartUpload {
+                upload_id: upload_id.to_string(),
+                size,
+                part_bytes: self.cfg.multipart_part_bytes.max(MIN_PART_BYTES),
+                etags: Vec::new(),
+            });
+            self.update_entry(entry.clone()).await?;
+        }
+        let Some(MultipartUpload {
+            upload_id,
+            part_bytes,
+            etags,
+            ..
+        }) = entry.multipart.clone()
+        else {
+            unreachable!("multipart upload created above");
+        };
+
+        let parts = size.div_ceil(part_bytes).max(1);
+        let mut file = tokio::fs::File::open(&entry.local_path)
+            .await
+            .with_context(|| format!("read local file {}", entry.local_path))?;
+        for number in etags.len() as u64 + 1..=parts {
+            let offset = (number - 1) * part_bytes;
+            let mut body = vec![0; part_bytes.min(size - offset) as usize];
+            file.seek(std::io::SeekFrom::Start(offset)).await?;
+            file.read_exact(&mut body)
+                .await
+                .with_context(|| format!("read local file {}", entry.local_path))?;
+            let req = PresignRequest {
+                ttl_seconds: self.presign_ttl(body.len() as u64),
+                upload_id: Some(upload_id.clone()),
+                part_number: Some(number as u32),
+                ..self.presign_request("UPLOAD_PART", &entry.object_key, content_type)
+            };
+            let sent = match self
+                .send_presigned(req, Method::PUT, None, body.into())
+                .await
+            {
+                Ok(sent) => sent,
+                Err(e) => return Err(self.forget_missing_upload(entry, e)),
+            };
+            let etag = sent.etag.ok_or_else(|| {
+                anyhow::anyhow!("no ETag uploading part {} of {}", number, entry.object_key)
+            })?;
+            if let Some(multipart) = entry.multipart.as_mut() {
+                multipart.etags.push(etag);
+            }
+            self.update_entry(entry.clone()).await?;
+        }
+
+        let etags = entry
+            .multipart
+            .as_ref()
+            .map(|m| m.etags.clone())
+            .unwrap_or_default();
+        let parts: Vec<(u32, &str)> = etags
+            .iter()
+            .enumerate()
+            .map(|(i, etag)| (i as u32 + 1, etag.as_str()))
+            .collect();
+        let req = PresignRequest {
+            upload_id: Some(upload_id),
+            ..self.presign_request("COMPLETE_MULTIPART", &entry.object_key, content_type)
+        };
+        let document = storage::complete_multipart_document(&parts);
+        let sent = match self
+            .send_presigned(req, Method::POST, Some("application/xml"), document.into())
+            .await
+        {
+            Ok(sent) => sent,
+            Err(e) => return Err(self.forget_missing_upload(entry, e)),
+        };
+        // Completing can fail after the response status was sent
+        if sent.body.contains("<Error>") {
+            return Err(anyhow::Error::new(Refused {
+                status: sent.status,
+                code: xml_value(&sent.body, "Code").map(str::to_string),
+            }));
+        }
+        Ok(())
+    }
+
+    /// Drop the multipart upload of `entry` when storage no longer knows it, so the
+    /// next attempt starts a new one
+    fn forget_missing_upload(&self, entry: &mut UploadEntry, e: anyhow::Error) -> anyhow::Error {
+        if e.downcast_ref::<Refused>()
+            .is_some_and(|r| r.code.as_deref() == Some("NoSuchUpload"))
+        {
+            warn!(
+                "[uploader] multipart upload of {} is gone, starting over",
+                entry.object_key
+            );
+            entry.multipart = None;
+        }
+        e
+    }
+
+    /// TTL to presign a transfer of `bytes` with: `presign_ttl_seconds`, or as long as
+    /// the transfer takes at `min_upload_bytes_per_second`
+    fn presign_ttl(&self, bytes: u64) -> u64 {
+        let mut ttl = self.cfg.presign_ttl_seconds.max(30);
+        if self.cfg.min_upload_bytes_per_second > 0 {
+            ttl = ttl.max(bytes.div_ceil(self.cfg.min_upload_bytes_per_second));
+        }
+        ttl.min(MAX_PRESIGN_TTL_SECONDS)
+    }
+
+    fn presign_request(
+        &self,
+        method: &str,
+        object_key: &str,
+        content_type: &str,
+    ) -> PresignRequest {
+        PresignRequest {
+            method: method.to_string(),
+            path: storage::normalize_key(object_key).into_owned(),
+            ttl_seconds: self.presign_ttl(0),
+            content_type: content_type.to_string(),
+            tagging: None,
+            upload_id: None,
+            part_number: None,
+        }
+    }
+
+    /// Presign `req` and send `body` with it. A URL that expires in transit is presigned
+    /// again right away and the body sent once more, rather than after a backoff.
+    async fn send_presigned(
+        &self,
+        req: PresignRequest,
+        method: Method,
+        content_type: Option<&'static str>,
+        body: Bytes,
+    ) -> Result<Sent> {
+        let mut expired = false;
+        loop {
+            let presign = self.send_presign(req.clone()).await?;
+            let mut headers = header::HeaderMap::new();
+            if let Some(content_type) = content_type {
+                headers.insert(
+                    header::CONTENT_TYPE,
+                    header::HeaderValue::from_static(content_type),
+                );
+            }
+            for (k, v) in presign.headers {
+                if let (Ok(name), Ok(value)) = (
+                    header::HeaderName::from_bytes(k.as_bytes()),
+                    header::HeaderValue::from_str(&v),
+                ) {
+                    headers.insert(name, value);
+                }
+            }
+
+            let resp = self
+                .client
+                .request(method.clone(), presign.url)
+                .headers(headers)
+                .body(body.clone())
+                .send()
+                .await
+                .map_err(reqwest::Error::without_url)?;
+            let status = resp.status();
+            let etag = resp
+                .headers()
+                .get(header::ETAG)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+            let content_length = content_length(resp.headers());
+            let text = resp.text().await.unwrap_or_default();
+            if status.is_success() {
+                return Ok(Sent {
+                    status,
+                    etag,
+                    content_length,
+                    body: text,
+                });
+            }
+            if !expired && signature_expired(status, &text) {
+                expired = true;
+                warn!(
+                    "[uploader] presigned {} of {} expired in transit, presigning again",
+                    req.method, req.path
+                );
+                continue;
+            }
+            return Err(anyhow::Error::new(Refused {
+                status,
+                code: xml_value(&text, "Code").map(str::to_string),
+            }));
+        }
+    }
+
+    async fn presign(
+        &self,
+        method: &str,
+        object_key: &str,
+        content_type: &str,
+        tagging: Option<String>,
+    ) -> Result<PresignResponse> {
+        self.send_presign(PresignRequest {
+            tagging,
+            ..self.presign_request(method, object_key, content_type)
+        })
+        .await
+    }
+
+    async fn send_presign(&self, req: PresignRequest) -> Result<PresignResponse> {
+        let url = format!(
+            "{}/api/storage/presign",
+            self.cfg.liveman_url.trim_end_matches('/')
+        );
+        let mut builder = self.client.post(url).json(&req);
+        if !self.cfg.liveman_token.is_empty() {
+            builder = builder.header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", self.cfg.liveman_token.expose_secret()),
+            );
+        }
+        let resp = match builder.send().await {
+            Ok(resp) => resp,
+            Err(e) => {
+                let e = anyhow::Error::new(e);
+                if connection_failure(&e) {
+                    self.liveman_unreachable(&e);
+                }
+                return Err(e);
+            }
+        };
+        self.liveman_reachable();
+        if !resp.status().is_success() {
+            return Err(anyhow::anyhow!("presign failed: {}", resp.status()));
+        }
+        Ok(resp.json::<PresignResponse>().await?)
+    }
+
+    async fn is_liveman_available(&self) -> Result<bool> {
+        if self.cfg.liveman_url.trim().is_empty() {
+            return Ok(false);
+        }
+        let now = chrono::Utc::now().timestamp_millis();
+        if self.suspended() {
+            let due = self
+                .outage
+                .lock()
+                .unwrap()
+                .probe_due(self.cfg.suspend_probe_interval_ms, now);
+            if !due {
+                return Ok(false);
+            }
+            return match self.ping().await {
+                Ok(()) => Ok(true),
+                Err(e) => {
+                    debug!("[uploader] liveman probe failed: {:#}", e);
+                    Ok(false)
+                }
+            };
+        }
+        let mut last_fail = self.last_ping_fail.lock().await;
+        if *last_fail != 0 && now - *last_fail < 5_000 {
+            return Ok(false);
+        }
+
+        match self.ping().await {
+            Ok(()) => {
+                *last_fail = 0;
+                Ok(true)
+            }
+            Err(e) => {
+                *last_fail = now;
+                if !self.suspended() {
+                    warn!("[uploader] {:#}", e);
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    fn suspended(&self) -> bool {
+        self.outage.lock().unwrap().suspended.is_some()
+    }
+
+    /// Count a connection failure to liveman, suspending the queue after
+    /// `suspend_after_failures` in a row
+    fn liveman_unreachable(&self, e: &anyhow::Error) {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut outage = self.outage.lock().unwrap();
+        if outage.failed(self.cfg.suspend_after_failures, now, format!("{e:#}")) {
+            warn!(
+                "[uploader] liveman unreachable after {} attempts, suspending uploads and probing every {}ms: {:#}",
+                outage.failures, self.cfg.suspend_probe_interval_ms, e
+            );
+            metrics::RECORDER_UPLOADS_SUSPENDED.set(1);
+            metrics::RECORDER_UPLOAD_SUSPENSIONS.inc();
+        }
+    }
+
+    fn liveman_reachable(&self) {
+        let Some(suspension) = self.outage.lock().unwrap().recovered() else {
+            return;
+        };
+        let seconds = (chrono::Utc::now().timestamp_micros() - suspension.since) / 1_000_000;
+        info!(
+            "[uploader] liveman answered again, resuming uploads after {}s ({} probes)",
+            seconds, suspension.probes
+        );
+        metrics::RECORDER_UPLOADS_SUSPENDED.set(0);
+    }
+
+    /// Pending, dead-lettered and in-flight uploads, and the suspension if any
+    pub async fn queue_status(&self) -> api::recorder::UploadQueueStatus {
+        let (pending, dead_lettered) = {
+            let map = self.entries.read().await;
+            let dead = map
+                .values()
+                .filter(|entry| entry.dead_lettered_at.is_some())
+                .count();
+            (map.values().count(), dead)
+        };
+        let uploading = self.uploading.lock().unwrap().len();
+        let lag = self.upload_lag(None).await;
+        let mut lanes = vec![self.large.status()];
+        if self.cfg.small_object_bytes > 0 {
+            lanes.insert(0, self.small.status());
+        }
+        let outage = self.outage.lock().unwrap();
+        api::recorder::UploadQueueStatus {
+            pending,
+            dead_lettered,
+            uploading,
+            lanes,
+            consecutive_failures: outage.failures,
+            suspended: outage.suspended.clone(),
+            lag,
+        }
+    }
+
+    /// Check that liveman answers `/api/storage/ping`
+    pub async fn ping(&self) -> Result<()> {
+        let url = format!(
+            "{}/api/storage/ping",
+            self.cfg.liveman_url.trim_end_matches('/')
+        );
+        let mut req = self.client.get(url);
+        if !self.cfg.liveman_token.is_empty() {
+            req = req.header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", self.cfg.liveman_token.expose_secret()),
+            );
+        }
+        let resp = match req.send().await {
+            Ok(resp) => resp,
+            Err(e) => {
+                let e = anyhow::Error::new(e).context("liveman ping error");
+                if connection_failure(&e) {
+                    self.liveman_unreachable(&e);
+                }
+                return Err(e);
+            }
+        };
+        if !resp.status().is_success() {
+            return Err(anyhow::anyhow!("liveman ping failed: {}", resp.status()));
+        }
+        self.liveman_reachable();
+        Ok(())
+    }
+
+    /// Store the progress of an attempt, unless a newer file replaced the one it read
+    async fn update_entry(&self, entry: UploadEntry) -> Result<()> {
         {
             let mut map = self.entries.write().await;
-            map.remove(id);
+            if map
+                .get(&entry.id)
+                .is_some_and(|queued| queued.revision != entry.revision)
+            {
+                return Ok(());
+            }
+            map.insert(entry);
         }
         self.persist_queue().await
     }
 
+    /// Drop the entry of an upload, `false` when a newer file replaced the one uploaded
+    /// and the entry stays queued, waiting for `mpd_upload_interval_ms` if it applies
+    async fn remove_uploaded(&self, entry: &UploadEntry) -> Result<bool> {
+        let removed = {
+            let mut map = self.entries.write().await;
+            match map.get_mut(&entry.id) {
+                Some(queued) if queued.revision != entry.revision => {
+                    if queued.retry_count == 0 {
+                        queued.next_retry_at = self.manifest_not_before(&entry.object_key);
+                    }
+                    false
+                }
+                _ => {
+                    map.remove(&entry.id);
+                    self.final_manifests
+                        .lock()
+                        .unwrap()
+                        .remove(&entry.object_key);
+                    true
+                }
+            }
+        };
+        self.persist_queue().await?;
+        Ok(removed)
+    }
+
+    /// Write the queue changed since the last write every `flush_interval_ms`, unless
+    /// it is written on every change
+    pub async fn run_queue_flusher(self: Arc<Self>) {
+        if self.durability.writes_through() {
+            return;
+        }
+        let mut ticker = tokio::time::interval(self.durability.interval());
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.flush_queue().await {
+                warn!("[uploader] failed to write the queue: {:#}", e);
+            }
+        }
+    }
+
+    /// Write the queue if it changed since the last write, e.g. before exit
+    pub async fn flush_queue(&self) -> Result<()> {
+        if !self.queue_dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        let written = self.write_queue().await;
+        if written.is_err() {
+            self.queue_dirty.store(true, Ordering::Release);
+        }
+        written
+    }
+
     async fn persist_queue(&self) -> Result<()> {
+        if !self.durability.writes_through() {
+            self.queue_dirty.store(true, Ordering::Release);
+            return Ok(());
+        }
+        self.write_queue().await
+    }
+
+    async fn write_queue(&self) -> Result<()> {
         let _guard = self.write_lock.lock().await;
         let entries: Vec<UploadEntry> = {
             let map = self.entries.read().await;
@@ -253,25 +1691,101 @@ impl UploadManager {
             tokio::fs::create_dir_all(parent).await?;
         }
 
-        let tmp_path = tmp_path_for(&path);
         let mut contents = String::new();
         for entry in entries {
             let line = serde_json::to_string(&entry)?;
             contents.push_str(&line);
             contents.push('\n');
         }
-        tokio::fs::write(&tmp_path, contents).await?;
+
+        // A nearly full disk may not hold a second copy, rewrite the queue where it is.
+        // A crash mid-write can lose the queue, which beats losing every update
+        if self.disk_guarded() {
+            return write_in_place(&path, &contents).await;
+        }
+        let tmp_path = tmp_path_for(&path);
+        let sync = self.durability.syncs_before_rename();
+        if let Err(e) = write_file(&tmp_path, &contents, sync).await {
+            warn!(
+                "[uploader] failed to write {}, rewriting the queue in place: {}",
+                tmp_path.display(),
+                e
+            );
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return write_in_place(&path, &contents).await;
+        }
         if tokio::fs::metadata(&path).await.is_ok() {
             let _ = tokio::fs::remove_file(&path).await;
         }
         tokio::fs::rename(&tmp_path, &path)
             .await
             .with_context(|| format!("replace upload queue {}", path.display()))?;
+        let durability = self.durability.clone();
+        tokio::task::spawn_blocking(move || durability.renamed(&path)).await?;
 
         Ok(())
     }
 }
 
+/// Write `contents` to a new file at `path`, synced when `sync`
+async fn write_file(path: &Path, contents: &str, sync: bool) -> std::io::Result<()> {
+    let mut file = tokio::fs::File::create(path).await?;
+    file.write_all(contents.as_bytes()).await?;
+    if sync {
+        file.sync_data().await?;
+    }
+    Ok(())
+}
+
+async fn write_in_place(path: &Path, contents: &str) -> Result<()> {
+    tokio::fs::write(path, contents)
+        .await
+        .with_context(|| format!("rewrite upload queue {}", path.display()))
+}
+
+/// SHA-256 of the file at `path`, read in chunks
+async fn file_digest(path: &Path) -> Result<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("read local file {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// `Content-Length` of a response, read from the header: reqwest reports none for the
+/// empty body of a `HEAD`
+fn content_length(headers: &header::HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+/// `file` in reads of [`PROXY_CHUNK_BYTES`]
+fn file_chunks(
+    mut file: tokio::fs::File,
+) -> impl tokio_stream::Stream<Item = std::io::Result<Bytes>> + Send + 'static {
+    async_stream::try_stream! {
+        loop {
+            let mut chunk = vec![0; PROXY_CHUNK_BYTES];
+            let read = file.read(&mut chunk).await?;
+            if read == 0 {
+                break;
+            }
+            chunk.truncate(read);
+            yield Bytes::from(chunk);
+        }
+    }
+}
+
 fn backoff_ts(retry: u32) -> i64 {
     let base = 5_000i64;
     let max = 10 * 60 * 1000i64;
@@ -279,6 +1793,66 @@ fn backoff_ts(retry: u32) -> i64 {
     chrono::Utc::now().timestamp_millis() + delay
 }
 
+/// Whether storage refused a presigned request because its URL expired. S3 answers
+/// `AccessDenied` with "Request has expired", other stores `ExpiredToken` or
+/// `SignatureExpired`
+fn signature_expired(status: StatusCode, body: &str) -> bool {
+    if status != StatusCode::FORBIDDEN {
+        return false;
+    }
+    match xml_value(body, "Code") {
+        Some("ExpiredToken" | "SignatureExpired") => true,
+        Some("AccessDenied") => {
+            xml_value(body, "Message").is_some_and(|m| m.to_ascii_lowercase().contains("expired"))
+        }
+        _ => false,
+    }
+}
+
+/// Text of the first `<tag>` element of an S3 response
+fn xml_value<'a>(body: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{tag}>");
+    let start = body.find(&open)? + open.len();
+    let len = body[start..].find(&format!("</{tag}>"))?;
+    Some(&body[start..start + len])
+}
+
+/// Compare keys with runs of digits by value, `v_seg_2.m4s` before `v_seg_19.m4s`
+fn key_order(a: &str, b: &str) -> std::cmp::Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return std::cmp::Ordering::Equal,
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_len = a.iter().take_while(|c| c.is_ascii_digit()).count();
+                let b_len = b.iter().take_while(|c| c.is_ascii_digit()).count();
+                let (a_num, b_num) = (&a[..a_len], &b[..b_len]);
+                let a_value = &a_num[a_num.iter().take_while(|c| **c == b'0').count()..];
+                let b_value = &b_num[b_num.iter().take_while(|c| **c == b'0').count()..];
+                let order = a_value
+                    .len()
+                    .cmp(&b_value.len())
+                    .then_with(|| a_value.cmp(b_value))
+                    .then_with(|| a_len.cmp(&b_len));
+                if order.is_ne() {
+                    return order;
+                }
+                a = &a[a_len..];
+                b = &b[b_len..];
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+                a = &a[1..];
+                b = &b[1..];
+            }
+        }
+    }
+}
+
 fn tmp_path_for(path: &Path) -> PathBuf {
     let mut tmp = path.to_path_buf();
     if let Some(ext) = path.extension() {
@@ -290,3 +1864,1243 @@ fn tmp_path_for(path: &Path) -> PathBuf {
     }
     tmp
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_order() {
+        let mut keys = vec![
+            "cam/1/v_seg_19.m4s",
+            "cam/1/v_seg_2.m4s",
+            "cam/1/v_seg_0010.m4s",
+            "cam/1/v_seg_0003.m4s",
+            "cam/1/manifest.mpd",
+            "cam/10/v_seg_1.m4s",
+        ];
+        keys.sort_by(|a, b| key_order(a, b));
+        assert_eq!(
+            keys,
+            [
+                "cam/1/manifest.mpd",
+                "cam/1/v_seg_2.m4s",
+                "cam/1/v_seg_0003.m4s",
+                "cam/1/v_seg_0010.m4s",
+                "cam/1/v_seg_19.m4s",
+                "cam/10/v_seg_1.m4s",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_dispatches_by_priority() {
+        let dir = tempfile::tempdir().unwrap();
+        let uploader = UploadManager::load(UploadConfig {
+            queue_path: dir
+                .path()
+                .join("queue.jsonl")
+                .to_string_lossy()
+                .into_owned(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        // A backlog of low priority segments queued ahead of a high priority recording
+        for i in 0..50 {
+            let key = format!("lobby/1700000000/v_seg_{i:04}.m4s");
+            uploader
+                .enqueue(key.clone(), key, "cam", None, 10)
+                .await
+                .unwrap();
+        }
+        for (key, priority) in [
+            ("cam/1700000100/v_seg_0002.m4s", 250),
+            ("cam/1700000100/v_seg_0001.m4s", 250),
+            (
+                "gate/1700000050/v_seg_0001.m4s",
+                api::recorder::DEFAULT_PRIORITY,
+            ),
+        ] {
+            uploader
+                .enqueue(key.to_string(), key.to_string(), "cam", None, priority)
+                .await
+                .unwrap();
+        }
+
+        let keys: Vec<String> = uploader
+            .due(i64::MAX)
+            .await
+            .into_iter()
+            .map(|e| e.object_key)
+            .collect();
+        assert_eq!(keys.len(), 53);
+        assert_eq!(
+            keys[..4],
+            [
+                "cam/1700000100/v_seg_0001.m4s",
+                "cam/1700000100/v_seg_0002.m4s",
+                "gate/1700000050/v_seg_0001.m4s",
+                "lobby/1700000000/v_seg_0000.m4s",
+            ]
+        );
+
+        // Raising the backlog's priority moves it ahead, and survives a restart
+        uploader.reprioritize_pending("lobby/1700000000", 255).await;
+        let reloaded = UploadManager::load(uploader.cfg.clone()).await.unwrap();
+        let first = reloaded.due(i64::MAX).await.remove(0);
+        assert_eq!(first.object_key, "lobby/1700000000/v_seg_0000.m4s");
+        assert_eq!(first.priority, 255);
+    }
+
+    #[tokio::test]
+    async fn test_disk_guard() {
+        let dir = tempfile::tempdir().unwrap();
+        let free = disk::manual::ManualFreeSpace::new(10 << 20);
+        let cfg = UploadConfig {
+            queue_path: dir
+                .path()
+                .join("queue.jsonl")
+                .to_string_lossy()
+                .into_owned(),
+            local_dir: dir.path().join("local").to_string_lossy().into_owned(),
+            staging_dir: dir.path().join("staging").to_string_lossy().into_owned(),
+            min_free_bytes: 1 << 20,
+            ..Default::default()
+        };
+        let uploader = UploadManager::load(cfg.clone())
+            .await
+            .unwrap()
+            .with_free_space(free.clone());
+        let local = dir.path().join("local/cam/1700000000");
+        tokio::fs::create_dir_all(&local).await.unwrap();
+        for name in ["v_seg_0001.m4s", "v_seg_0002.m4s"] {
+            tokio::fs::write(local.join(name), b"segment")
+                .await
+                .unwrap();
+        }
+
+        uploader
+            .stage(
+                "cam/1700000000/v_seg_0001.m4s".to_string(),
+                &local.join("v_seg_0001.m4s"),
+                "cam",
+                None,
+                api::recorder::DEFAULT_PRIORITY,
+            )
+            .await
+            .unwrap();
+        assert!(!uploader.disk_guarded());
+
+        free.set(1000);
+        let err = uploader
+            .stage(
+                "cam/1700000000/v_seg_0002.m4s".to_string(),
+                &local.join("v_seg_0002.m4s"),
+                "cam",
+                None,
+                api::recorder::DEFAULT_PRIORITY,
+            )
+            .await
+            .unwrap_err();
+        let full = err.downcast_ref::<DiskFull>().unwrap();
+        assert_eq!(
+            *full,
+            DiskFull::Bytes {
+                available: 1000,
+                min_free_bytes: 1 << 20
+            }
+        );
+        assert!(uploader.disk_guarded());
+        assert!(
+            !dir.path()
+                .join("staging/cam/1700000000/v_seg_0002.m4s")
+                .exists()
+        );
+        let status = uploader.disk_status();
+        assert_eq!(status.free_bytes, Some(1000));
+        assert_eq!(status.free_inodes, None);
+        assert!(status.guarded);
+
+        // Queue updates still land, without a temp copy
+        uploader.reprioritize_pending("cam/1700000000", 200).await;
+        assert!(!dir.path().join("queue.jsonl.tmp").exists());
+        let reloaded = UploadManager::load(cfg).await.unwrap();
+        assert_eq!(reloaded.due(i64::MAX).await[0].priority, 200);
+
+        free.set(2 << 20);
+        assert!(uploader.check_free_space().is_ok());
+        assert!(!uploader.disk_guarded());
+    }
+
+    #[tokio::test]
+    async fn test_inode_guard() {
+        let dir = tempfile::tempdir().unwrap();
+        let free = disk::manual::ManualFreeSpace::new(10 << 30);
+        free.set_inodes(50_000);
+        let uploader = UploadManager::load(UploadConfig {
+            queue_path: dir
+                .path()
+                .join("queue.jsonl")
+                .to_string_lossy()
+                .into_owned(),
+            local_dir: dir.path().join("local").to_string_lossy().into_owned(),
+            staging_dir: dir.path().join("staging").to_string_lossy().into_owned(),
+            min_free_bytes: 1 << 20,
+            min_free_inodes: 10_000,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .with_free_space(free.clone());
+        let local = dir.path().join("local/cam/1700000000");
+        tokio::fs::create_dir_all(&local).await.unwrap();
+        tokio::fs::write(local.join("v_seg_0001.m4s"), b"segment")
+            .await
+            .unwrap();
+        assert!(uploader.check_free_space().is_ok());
+
+        // Plenty of bytes, out of inodes: guarded all the same, and told apart
+        free.set_inodes(20);
+        let err = uploader
+            .stage(
+                "cam/1700000000/v_seg_0001.m4s".to_string(),
+                &local.join("v_seg_0001.m4s"),
+                "cam",
+                None,
+                api::recorder::DEFAULT_PRIORITY,
+            )
+            .await
+            .unwrap_err();
+        let full = *err.downcast_ref::<DiskFull>().unwrap();
+        assert_eq!(
+            full,
+            DiskFull::Inodes {
+                available: 20,
+                min_free_inodes: 10_000
+            }
+        );
+        assert_eq!(
+            full.to_string(),
+            "20 inodes free, below min_free_inodes 10000"
+        );
+        assert!(uploader.disk_guarded());
+        let status = uploader.disk_status();
+        assert_eq!(status.free_bytes, Some(10 << 30));
+        assert_eq!(status.free_inodes, Some(20));
+        assert_eq!(status.min_free_inodes, 10_000);
+        assert!(status.guarded);
+
+        free.set_inodes(50_000);
+        assert!(uploader.check_free_space().is_ok());
+        assert!(!uploader.disk_guarded());
+    }
+
+    #[test]
+    fn test_signature_expired() {
+        let error = |code: &str, message: &str| {
+            format!("<Error><Code>{code}</Code><Message>{message}</Message></Error>")
+        };
+        let forbidden = StatusCode::FORBIDDEN;
+        assert!(signature_expired(
+            forbidden,
+            &error("AccessDenied", "Request has expired")
+        ));
+        assert!(signature_expired(forbidden, &error("SignatureExpired", "")));
+        assert!(signature_expired(forbidden, &error("ExpiredToken", "")));
+        assert!(!signature_expired(
+            forbidden,
+            &error("AccessDenied", "Access Denied")
+        ));
+        assert!(!signature_expired(
+            StatusCode::BAD_REQUEST,
+            &error("ExpiredToken", "")
+        ));
+        assert!(!signature_expired(forbidden, "forbidden"));
+    }
+
+    #[tokio::test]
+    async fn test_presign_ttl_scales_with_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = UploadConfig {
+            queue_path: dir.path().join("queue.jsonl").display().to_string(),
+            presign_ttl_seconds: 300,
+            ..Default::default()
+        };
+        let uploader = UploadManager::load(cfg.clone()).await.unwrap();
+        assert_eq!(uploader.presign_ttl(1 << 30), 300);
+
+        let uploader = UploadManager::load(UploadConfig {
+            min_upload_bytes_per_second: 1 << 20,
+            ..cfg
+        })
+        .await
+        .unwrap();
+        assert_eq!(uploader.presign_ttl(10 << 20), 300);
+        assert_eq!(uploader.presign_ttl(1 << 30), 1024);
+        assert_eq!(uploader.presign_ttl(u64::MAX), MAX_PRESIGN_TTL_SECONDS);
+    }
+
+    /// Liveman's presign API and an S3 endpoint in one. Operations listed in `expire`
+    /// are refused with an expired signature and those in `fail` with an error, those in
+    /// `truncate` store an empty object and those in `corrupt` a reversed one, once each.
+    /// Puts of the keys in `held` are answered once they are taken out
+    #[derive(Default)]
+    struct MockStorage {
+        /// Method and part number of each presign request
+        presigned: std::sync::Mutex<Vec<(String, Option<u32>)>>,
+        expire: std::sync::Mutex<Vec<String>>,
+        fail: std::sync::Mutex<Vec<String>>,
+        truncate: std::sync::Mutex<Vec<String>>,
+        corrupt: std::sync::Mutex<Vec<String>>,
+        held: std::sync::Mutex<HashSet<String>>,
+        /// Stored objects by key
+        objects: std::sync::Mutex<HashMap<String, Vec<u8>>>,
+        /// Operations storage took, with the size of their body
+        accepted: std::sync::Mutex<Vec<(String, usize)>>,
+        completed: std::sync::Mutex<Option<String>>,
+        /// Uploads streamed through liveman: key, body chunks received, whether it was
+        /// sent without a `Content-Length`, and its tags
+        proxied: std::sync::Mutex<Vec<(String, usize, bool, Option<String>)>>,
+    }
+
+    impl MockStorage {
+        fn accepted(&self) -> Vec<String> {
+            let accepted = self.accepted.lock().unwrap();
+            accepted.iter().map(|(op, _)| op.clone()).collect()
+        }
+    }
+
+    type MockState = axum::extract::State<(Arc<MockStorage>, String)>;
+
+    fn take_once(ops: &std::sync::Mutex<Vec<String>>, op: &str) -> bool {
+        let mut ops = ops.lock().unwrap();
+        match ops.iter().position(|o| o == op) {
+            Some(i) => {
+                ops.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn mock_presign(
+        axum::extract::State((mock, base)): MockState,
+        axum::Json(req): axum::Json<PresignRequest>,
+    ) -> axum::Json<serde_json::Value> {
+        mock.presigned
+            .lock()
+            .unwrap()
+            .push((req.method.clone(), req.part_number));
+        let op = match req.method.as_str() {
+            "CREATE_MULTIPART" => "create".to_string(),
+            "UPLOAD_PART" => format!("part-{}", req.part_number.unwrap()),
+            "COMPLETE_MULTIPART" => "complete".to_string(),
+            "HEAD" => "head".to_string(),
+            "GET" => "get".to_string(),
+            _ => "put".to_string(),
+        };
+        axum::Json(serde_json::json!({
+            "url": format!("{base}/s3/{}?op={op}", req.path),
+            "headers": {},
+        }))
+    }
+
+    async fn mock_s3(
+        axum::extract::State((mock, _)): MockState,
+        axum::extract::Query(query): axum::extract::Query<HashMap<String, String>>,
+        uri: axum::http::Uri,
+        body: Bytes,
+    ) -> axum::response::Response {
+        use axum::response::IntoResponse;
+
+        let op = query.get("op").cloned().unwrap_or_default();
+        let key = uri.path().trim_start_matches("/s3/").to_string();
+        if op == "head" || op == "get" {
+            return match mock.objects.lock().unwrap().get(&key) {
+                Some(object) => object.clone().into_response(),
+                None => axum::http::StatusCode::NOT_FOUND.into_response(),
+            };
+        }
+        while op == "put" && mock.held.lock().unwrap().contains(&key) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        if take_once(&mock.expire, &op) {
+            return (
+                axum::http::StatusCode::FORBIDDEN,
+                "<Error><Code>AccessDenied</Code><Message>Request has expired</Message></Error>",
+            )
+                .into_response();
+        }
+        if take_once(&mock.fail, &op) {
+            return (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "<Error><Code>InternalError</Code></Error>",
+            )
+                .into_response();
+        }
+        mock.accepted.lock().unwrap().push((op.clone(), body.len()));
+        let mut stored = body.to_vec();
+        if take_once(&mock.truncate, &op) {
+            stored.clear();
+        }
+        if take_once(&mock.corrupt, &op) {
+            stored.reverse();
+        }
+        let mut objects = mock.objects.lock().unwrap();
+        match op.as_str() {
+            "create" => {
+                objects.insert(key, Vec::new());
+            }
+            "put" => {
+                objects.insert(key, stored);
+            }
+            op if op.starts_with("part-") => objects.entry(key).or_default().extend(stored),
+            _ => {}
+        }
+        drop(objects);
+        match op.as_str() {
+            "create" => "<InitiateMultipartUploadResult><UploadId>up-1</UploadId></InitiateMultipartUploadResult>"
+                .into_response(),
+            "complete" => {
+                *mock.completed.lock().unwrap() = Some(String::from_utf8_lossy(&body).into_owned());
+                "<CompleteMultipartUploadResult></CompleteMultipartUploadResult>".into_response()
+            }
+            op => ([(header::ETAG, format!("\"{op}\""))], "").into_response(),
+        }
+    }
+
+    /// Liveman's `/api/storage/objects/{path}`, `fail` refuses a `proxy` once
+    async fn mock_proxy_put(
+        axum::extract::State((mock, _)): MockState,
+        axum::extract::Path(key): axum::extract::Path<String>,
+        headers: axum::http::HeaderMap,
+        body: axum::body::Body,
+    ) -> axum::response::Response {
+        use axum::response::IntoResponse;
+        use tokio_stream::StreamExt;
+
+        if take_once(&mock.fail, "proxy") {
+            return (
+                axum::http::StatusCode::TOO_MANY_REQUESTS,
+                "too many uploads of this node",
+            )
+                .into_response();
+        }
+        let chunked = !headers.contains_key(header::CONTENT_LENGTH);
+        let tagging = headers
+            .get("x-amz-tagging")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let mut stream = body.into_data_stream();
+        let (mut stored, mut chunks) = (Vec::new(), 0);
+        while let Some(chunk) = stream.next().await {
+            stored.extend_from_slice(&chunk.unwrap());
+            chunks += 1;
+        }
+        mock.accepted
+            .lock()
+            .unwrap()
+            .push(("proxy".to_string(), stored.len()));
+        mock.proxied
+            .lock()
+            .unwrap()
+            .push((key.clone(), chunks, chunked, tagging));
+        mock.objects.lock().unwrap().insert(key, stored);
+        axum::http::StatusCode::OK.into_response()
+    }
+
+    async fn mock_proxy_get(
+        axum::extract::State((mock, _)): MockState,
+        axum::extract::Path(key): axum::extract::Path<String>,
+    ) -> axum::response::Response {
+        use axum::response::IntoResponse;
+
+        match mock.objects.lock().unwrap().get(&key) {
+            Some(object) => object.clone().into_response(),
+            None => axum::http::StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    async fn serve_mock(mock: Arc<MockStorage>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let app = axum::Router::new()
+            .route("/api/storage/presign", axum::routing::post(mock_presign))
+            .route(
+                "/api/storage/objects/{*path}",
+                axum::routing::put(mock_proxy_put).get(mock_proxy_get),
+            )
+            .fallback(mock_s3)
+            .with_state((mock, base.clone()));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        base
+    }
+
+    #[tokio::test]
+    async fn test_put_presigned_again_when_expired() {
+        let mock = Arc::new(MockStorage::default());
+        mock.expire.lock().unwrap().push("put".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let uploader = UploadManager::load(UploadConfig {
+            liveman_url: serve_mock(mock.clone()).await,
+            queue_path: dir.path().join("queue.jsonl").display().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let file = dir.path().join("v_seg_0001.m4s");
+        std::fs::write(&file, b"segment").unwrap();
+        uploader
+            .enqueue(
+                "cam/1/v_seg_0001.m4s".to_string(),
+                file.display().to_string(),
+                "cam",
+                None,
+                api::recorder::DEFAULT_PRIORITY,
+            )
+            .await
+            .unwrap();
+
+        // Uploaded by the same attempt, without waiting for a retry
+        let entry = uploader.due(i64::MAX).await.remove(0);
+        uploader.try_upload(entry).await.unwrap();
+        assert_eq!(
+            *mock.presigned.lock().unwrap(),
+            [
+                ("PUT".to_string(), None),
+                ("PUT".to_string(), None),
+                ("HEAD".to_string(), None)
+            ]
+        );
+        assert_eq!(mock.accepted(), ["put"]);
+        assert!(uploader.due(i64::MAX).await.is_empty());
+    }
+
+    /// A file over the multipart threshold goes to liveman whole and chunked, checked
+    /// through liveman too, and no URL is presigned
+    #[tokio::test]
+    async fn test_proxied_upload() {
+        let mock = Arc::new(MockStorage::default());
+        mock.fail.lock().unwrap().push("proxy".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let uploader = UploadManager::load(UploadConfig {
+            liveman_url: serve_mock(mock.clone()).await,
+            queue_path: dir.path().join("queue.jsonl").display().to_string(),
+            mode: UploadTransport::Proxy,
+            multipart_threshold_bytes: 8 << 20,
+            verify_checksum: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let file = dir.path().join("v_seg_0001.m4s");
+        let content: Vec<u8> = (0..(24 << 20) + 7).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&file, &content).unwrap();
+        uploader
+            .enqueue(
+                "cam/1/v_seg_0001.m4s".to_string(),
+                file.display().to_string(),
+                "cam",
+                Some("retention=30d".to_string()),
+                api::recorder::DEFAULT_PRIORITY,
+            )
+            .await
+            .unwrap();
+
+        // Refused by liveman's per-node limit, retried like a refusal of storage
+        let entry = uploader.due(i64::MAX).await.remove(0);
+        let err = uploader.try_upload(entry).await.unwrap_err();
+        assert!(err.to_string().contains("429"), "{err}");
+        let entry = uploader.due(i64::MAX).await.remove(0);
+        assert_eq!(entry.retry_count, 1);
+        assert!(entry.next_retry_at > 0);
+
+        uploader.try_upload(entry).await.unwrap();
+        assert!(mock.presigned.lock().unwrap().is_empty());
+        assert_eq!(mock.accepted(), ["proxy"]);
+        let proxied = mock.proxied.lock().unwrap().clone();
+        let (key, chunks, chunked, tagging) = &proxied[0];
+        assert_eq!(key, "cam/1/v_seg_0001.m4s");
+        assert!(*chunks > 1 && *chunked);
+        assert_eq!(tagging.as_deref(), Some("retention=30d"));
+        assert_eq!(mock.objects.lock().unwrap()[key.as_str()], content);
+        assert!(uploader.due(i64::MAX).await.is_empty());
+        assert!(!file.exists());
+    }
+
+    #[tokio::test]
+    async fn test_multipart_resumes_after_last_part() {
+        let mock = Arc::new(MockStorage::default());
+        mock.expire.lock().unwrap().push("part-2".to_string());
+        mock.fail.lock().unwrap().push("part-3".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = UploadConfig {
+            liveman_url: serve_mock(mock.clone()).await,
+            queue_path: dir.path().join("queue.jsonl").display().to_string(),
+            multipart_threshold_bytes: 8 << 20,
+            multipart_part_bytes: 5 << 20,
+            ..Default::default()
+        };
+        let uploader = UploadManager::load(cfg.clone()).await.unwrap();
+        let file = dir.path().join("v_seg_0001.m4s");
+        std::fs::write(&file, vec![7u8; 11 << 20]).unwrap();
+        uploader
+            .enqueue(
+                "cam/1/v_seg_0001.m4s".to_string(),
+                file.display().to_string(),
+                "cam",
+                None,
+                api::recorder::DEFAULT_PRIORITY,
+            )
+            .await
+            .unwrap();
+
+        // Part 2 expires in transit and is presigned again, part 3 fails
+        let entry = uploader.due(i64::MAX).await.remove(0);
+        let err = uploader.try_upload(entry).await.unwrap_err();
+        assert!(err.downcast_ref::<Refused>().is_some(), "{err}");
+        assert_eq!(mock.accepted(), ["create", "part-1", "part-2"]);
+        let part_2 = mock
+            .presigned
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, part)| *part == Some(2))
+            .count();
+        assert_eq!(part_2, 2);
+
+        // The retry, even by a restarted uploader, goes on with part 3
+        let uploader = UploadManager::load(cfg).await.unwrap();
+        let entry = uploader.due(i64::MAX).await.remove(0);
+        assert_eq!(entry.retry_count, 1);
+        assert_eq!(
+            entry.multipart.as_ref().unwrap().etags,
+            ["\"part-1\"", "\"part-2\""]
+        );
+        uploader.try_upload(entry).await.unwrap();
+        assert_eq!(
+            *mock.accepted.lock().unwrap(),
+            [
+                ("create".to_string(), 0),
+                ("part-1".to_string(), 5 << 20),
+                ("part-2".to_string(), 5 << 20),
+                ("part-3".to_string(), 1 << 20),
+                ("complete".to_string(), 231),
+            ]
+        );
+        assert_eq!(
+            mock.completed.lock().unwrap().as_deref(),
+            Some(
+                storage::complete_multipart_document(&[
+                    (1, "\"part-1\""),
+                    (2, "\"part-2\""),
+                    (3, "\"part-3\""),
+                ])
+                .as_str()
+            )
+        );
+        assert!(uploader.due(i64::MAX).await.is_empty());
+        assert!(!file.exists());
+    }
+
+    #[tokio::test]
+    async fn test_manifest_versions_coalesce() {
+        let mock = Arc::new(MockStorage::default());
+        let dir = tempfile::tempdir().unwrap();
+        let queue_path = dir.path().join("queue.jsonl");
+        let uploader = UploadManager::load(UploadConfig {
+            liveman_url: serve_mock(mock.clone()).await,
+            queue_path: queue_path.display().to_string(),
+            staging_dir: dir.path().join("staging").display().to_string(),
+            mpd_upload_interval_ms: 60_000,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let key = "cam/1/manifest.mpd";
+        let local = dir.path().join("manifest.mpd");
+        let (uploader, local) = (&uploader, local.as_path());
+        let stage = |version: usize| async move {
+            std::fs::write(local, "x".repeat(version)).unwrap();
+            uploader
+                .stage(
+                    key.to_string(),
+                    local,
+                    "cam",
+                    None,
+                    api::recorder::DEFAULT_PRIORITY,
+                )
+                .await
+        };
+
+        // One rewrite per segment, the upload loop running every 10 segments
+        for version in 1..=50 {
+            stage(version).await.unwrap();
+            if version % 10 == 0 {
+                let now = chrono::Utc::now().timestamp_millis();
+                for entry in uploader.due(now).await {
+                    uploader.try_upload(entry).await.unwrap();
+                }
+            }
+        }
+        // The journal holds the newest version only
+        let queue = std::fs::read_to_string(&queue_path).unwrap();
+        assert_eq!(queue.lines().count(), 1, "{queue}");
+        let entry: UploadEntry = serde_json::from_str(queue.lines().next().unwrap()).unwrap();
+        assert_eq!(entry.revision, 39);
+        assert_eq!(std::fs::metadata(&entry.local_path).unwrap().len(), 50);
+
+        // The final manifest does not wait for the interval
+        uploader.upload_final_manifest(key.to_string());
+        stage(51).await.unwrap();
+        let now = chrono::Utc::now().timestamp_millis();
+        for entry in uploader.due(now).await {
+            uploader.try_upload(entry).await.unwrap();
+        }
+        assert_eq!(
+            *mock.accepted.lock().unwrap(),
+            [("put".to_string(), 10), ("put".to_string(), 51)]
+        );
+        assert!(uploader.due(i64::MAX).await.is_empty());
+    }
+
+    /// A recording starting while the upload loop waits for its next tick: its first
+    /// manifest naming a media segment is playable remotely one segment after the start,
+    /// plus little more than the upload itself
+    #[tokio::test]
+    async fn test_first_segment_playable_before_next_tick() {
+        const SEGMENT: Duration = Duration::from_millis(500);
+        let mock = Arc::new(MockStorage::default());
+        let dir = tempfile::tempdir().unwrap();
+        let uploader = Arc::new(
+            UploadManager::load(UploadConfig {
+                liveman_url: serve_mock(mock.clone()).await,
+                queue_path: dir.path().join("queue.jsonl").display().to_string(),
+                staging_dir: dir.path().join("staging").display().to_string(),
+                interval_ms: 60_000,
+                mpd_upload_interval_ms: 60_000,
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+        );
+        tokio::spawn(uploader.clone().run());
+
+        let stage = |name: &'static str, body: &'static str| {
+            let uploader = uploader.clone();
+            let local = dir.path().join(name);
+            async move {
+                std::fs::write(&local, body).unwrap();
+                let key = format!("cam/1/{name}");
+                uploader.expedite(key.clone());
+                uploader
+                    .stage(key, &local, "cam", None, api::recorder::DEFAULT_PRIORITY)
+                    .await
+                    .unwrap();
+            }
+        };
+        let started = std::time::Instant::now();
+        stage("v_init.m4s", "init").await;
+        stage("manifest.mpd", "<MPD/>").await;
+        tokio::time::sleep(SEGMENT).await;
+        stage("v_seg_0001.m4s", "segment").await;
+        stage("manifest.mpd", "<MPD>v_seg_0001</MPD>").await;
+
+        let playable = || {
+            let objects = mock.objects.lock().unwrap();
+            objects.contains_key("cam/1/v_init.m4s")
+                && objects.contains_key("cam/1/v_seg_0001.m4s")
+                && objects
+                    .get("cam/1/manifest.mpd")
+                    .is_some_and(|mpd| mpd.ends_with(b"v_seg_0001</MPD>"))
+        };
+        while !playable() {
+            assert!(
+                started.elapsed() < SEGMENT + Duration::from_secs(2),
+                "not playable after {:?}",
+                started.elapsed()
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    /// A large segment stuck on a slow endpoint, with the large lane's only permit: the
+    /// manifest and init segments queued behind it are uploaded meanwhile
+    #[tokio::test]
+    async fn test_small_objects_pass_a_slow_large_upload() {
+        let mock = Arc::new(MockStorage::default());
+        mock.held
+            .lock()
+            .unwrap()
+            .insert("cam/1/v_seg_0001.m4s".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let uploader = Arc::new(
+            UploadManager::load(UploadConfig {
+                liveman_url: serve_mock(mock.clone()).await,
+                queue_path: dir.path().join("queue.jsonl").display().to_string(),
+                interval_ms: 60_000,
+                concurrency: 1,
+                small_object_bytes: 64 << 10,
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+        );
+        // The first large segment goes first and takes the large lane
+        for (name, size, priority) in [
+            ("v_seg_0001.m4s", 1 << 20, 250),
+            ("v_seg_0002.m4s", 1 << 20, api::recorder::DEFAULT_PRIORITY),
+            ("v_init.m4s", 800, api::recorder::DEFAULT_PRIORITY),
+            ("a_init.m4s", 600, api::recorder::DEFAULT_PRIORITY),
+            ("manifest.mpd", 2_000, api::recorder::DEFAULT_PRIORITY),
+        ] {
+            let file = dir.path().join(name);
+            std::fs::write(&file, vec![7u8; size]).unwrap();
+            uploader
+                .enqueue(
+                    format!("cam/1/{name}"),
+                    file.display().to_string(),
+                    "cam",
+                    None,
+                    priority,
+                )
+                .await
+                .unwrap();
+        }
+        tokio::spawn(uploader.clone().run());
+        uploader.wake.notify_one();
+
+        let wait_for = |pending: usize| {
+            let uploader = uploader.clone();
+            async move {
+                tokio::time::timeout(Duration::from_secs(5), async {
+                    while uploader.queue_status().await.pending != pending {
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                    }
+                })
+                .await
+                .unwrap_or_else(|_| panic!("{pending} uploads not left pending in time"));
+            }
+        };
+        wait_for(2).await;
+        {
+            let objects = mock.objects.lock().unwrap();
+            assert!(objects.contains_key("cam/1/manifest.mpd"));
+            assert!(objects.contains_key("cam/1/v_init.m4s"));
+            assert!(objects.contains_key("cam/1/a_init.m4s"));
+            assert!(!objects.contains_key("cam/1/v_seg_0001.m4s"));
+        }
+        let status = uploader.queue_status().await;
+        assert_eq!(status.lanes[1].lane, UploadLane::Large);
+        assert_eq!(status.lanes[1].uploading, 1);
+        let lanes: HashMap<String, Option<UploadLane>> = uploader
+            .entries
+            .read()
+            .await
+            .values()
+            .map(|entry| (entry.object_key.clone(), entry.lane))
+            .collect();
+        assert_eq!(lanes["cam/1/v_seg_0001.m4s"], Some(UploadLane::Large));
+        // Left for a free permit of its lane
+        assert_eq!(lanes["cam/1/v_seg_0002.m4s"], None);
+
+        // The held upload ending starts the next one without waiting for a tick
+        mock.held.lock().unwrap().clear();
+        wait_for(0).await;
+        let objects = mock.objects.lock().unwrap();
+        assert!(objects.contains_key("cam/1/v_seg_0001.m4s"));
+        assert!(objects.contains_key("cam/1/v_seg_0002.m4s"));
+    }
+
+    #[tokio::test]
+    async fn test_replayed_stage_uploads_once() {
+        let mock = Arc::new(MockStorage::default());
+        let dir = tempfile::tempdir().unwrap();
+        let queue_path = dir.path().join("queue.jsonl");
+        let cfg = UploadConfig {
+            liveman_url: serve_mock(mock.clone()).await,
+            queue_path: queue_path.display().to_string(),
+            staging_dir: dir.path().join("staging").display().to_string(),
+            local_retention_minutes: 60,
+            ..Default::default()
+        };
+        let local = dir.path().join("local");
+        std::fs::create_dir_all(&local).unwrap();
+        let segments = ["v_seg_0001.m4s", "v_seg_0002.m4s"];
+        for name in segments {
+            std::fs::write(local.join(name), name).unwrap();
+        }
+        let stage_all = |uploader: Arc<UploadManager>| {
+            let local = local.clone();
+            async move {
+                for name in segments {
+                    uploader
+                        .stage(
+                            format!("cam/1/{name}"),
+                            &local.join(name),
+                            "cam",
+                            None,
+                            api::recorder::DEFAULT_PRIORITY,
+                        )
+                        .await
+                        .unwrap();
+                }
+            }
+        };
+
+        // Staged, then the recorder crashes before it notes so and stages them again
+        // after the restart, twice
+        stage_all(Arc::new(UploadManager::load(cfg.clone()).await.unwrap())).await;
+        let uploader = Arc::new(UploadManager::load(cfg.clone()).await.unwrap());
+        stage_all(uploader.clone()).await;
+        stage_all(uploader.clone()).await;
+
+        let queue = std::fs::read_to_string(&queue_path).unwrap();
+        assert_eq!(queue.lines().count(), 2, "{queue}");
+        let entries = uploader.due(i64::MAX).await;
+        assert!(entries.iter().all(|e| e.revision == 0));
+        for entry in entries {
+            uploader.try_upload(entry).await.unwrap();
+        }
+        assert_eq!(mock.accepted(), ["put", "put"]);
+        assert!(uploader.due(i64::MAX).await.is_empty());
+        let reloaded = UploadManager::load(cfg).await.unwrap();
+        assert!(reloaded.due(i64::MAX).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dead_lettered_upload_revived() {
+        let mock = Arc::new(MockStorage::default());
+        for _ in 0..2 {
+            mock.fail.lock().unwrap().push("put".to_string());
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = UploadConfig {
+            liveman_url: serve_mock(mock.clone()).await,
+            queue_path: dir.path().join("queue.jsonl").display().to_string(),
+            max_retries: 1,
+            ..Default::default()
+        };
+        let uploader = UploadManager::load(cfg.clone()).await.unwrap();
+        let key = "cam/1/v_seg_0001.m4s";
+        let file = dir.path().join("v_seg_0001.m4s");
+        std::fs::write(&file, b"segment").unwrap();
+        let local_path = file.display().to_string();
+        let priority = api::recorder::DEFAULT_PRIORITY;
+        uploader
+            .enqueue(key.to_string(), local_path.clone(), "cam", None, priority)
+            .await
+            .unwrap();
+
+        // The first attempt and its one retry fail, then the upload is given up
+        for _ in 0..2 {
+            let entry = uploader.due(i64::MAX).await.remove(0);
+            assert!(uploader.try_upload(entry).await.is_err());
+        }
+        assert!(uploader.due(i64::MAX).await.is_empty());
+        assert_eq!(uploader.pending_under("cam/1").await, 1);
+
+        // Queued again after a restart, it is revived rather than duplicated
+        let uploader = UploadManager::load(cfg).await.unwrap();
+        assert!(uploader.due(i64::MAX).await.is_empty());
+        uploader
+            .enqueue(key.to_string(), local_path, "cam", None, priority)
+            .await
+            .unwrap();
+        let entries = uploader.due(i64::MAX).await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].retry_count, 0);
+        uploader.try_upload(entries[0].clone()).await.unwrap();
+        assert_eq!(mock.accepted(), ["put"]);
+        assert_eq!(uploader.pending_under("cam/1").await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_upload_lag_per_stream() {
+        let mock = Arc::new(MockStorage::default());
+        let dir = tempfile::tempdir().unwrap();
+        let uploader = UploadManager::load(UploadConfig {
+            liveman_url: serve_mock(mock.clone()).await,
+            queue_path: dir.path().join("queue.jsonl").display().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        // Segments finished 90 s and 10 s ago, and one 400 s ago left queued
+        let files = [
+            ("lag-cam", "lag-cam/1/v_seg_0001.m4s", 90),
+            ("lag-gate", "lag-gate/1/v_seg_0001.m4s", 10),
+            ("lag-cam", "lag-cam/1/v_seg_0002.m4s", 400),
+        ];
+        for (i, (stream, key, age)) in files.into_iter().enumerate() {
+            let file = dir.path().join(format!("{i}.m4s"));
+            std::fs::write(&file, b"segment").unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&file)
+                .unwrap()
+                .set_modified(std::time::SystemTime::now() - Duration::from_secs(age))
+                .unwrap();
+            uploader
+                .enqueue(
+                    key.to_string(),
+                    file.display().to_string(),
+                    stream,
+                    None,
+                    api::recorder::DEFAULT_PRIORITY,
+                )
+                .await
+                .unwrap();
+        }
+        for entry in uploader.due(i64::MAX).await {
+            if entry.object_key != files[2].1 {
+                uploader.try_upload(entry).await.unwrap();
+            }
+        }
+
+        let within = |lag: Option<u64>, secs: u64| {
+            lag.is_some_and(|lag| (secs * 1_000..(secs + 5) * 1_000).contains(&lag))
+        };
+        let cam = uploader.upload_lag(Some("lag-cam")).await;
+        assert_eq!(cam.samples, 1);
+        assert!(within(cam.p50_ms, 90) && within(cam.p99_ms, 90), "{cam:?}");
+        // The queued segment lags the most already
+        assert!(within(cam.oldest_pending_ms, 400), "{cam:?}");
+        assert_eq!(Some(cam.worst_ms), cam.oldest_pending_ms);
+        let gate = uploader.upload_lag(Some("lag-gate")).await;
+        assert_eq!(gate.samples, 1);
+        assert!(within(gate.p99_ms, 10), "{gate:?}");
+        assert_eq!(gate.oldest_pending_ms, None);
+        assert_eq!(Some(gate.worst_ms), gate.p99_ms);
+
+        let node = uploader.queue_status().await.lag;
+        assert_eq!(node.samples, 2);
+        assert!(within(node.p99_ms, 90), "{node:?}");
+        assert!(within(node.p50_ms, 10), "{node:?}");
+        assert!(within(Some(node.worst_ms), 400), "{node:?}");
+        for (stream, secs) in [("lag-cam", 90.0), ("lag-gate", 10.0)] {
+            let histogram = metrics::RECORDER_UPLOAD_LAG.with_label_values(&[stream]);
+            assert_eq!(histogram.get_sample_count(), 1);
+            assert!((secs..secs + 5.0).contains(&histogram.get_sample_sum()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_upload_kept_and_retried() {
+        let mock = Arc::new(MockStorage::default());
+        mock.truncate.lock().unwrap().push("put".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = UploadConfig {
+            liveman_url: serve_mock(mock.clone()).await,
+            queue_path: dir.path().join("queue.jsonl").display().to_string(),
+            ..Default::default()
+        };
+        let uploader = UploadManager::load(cfg.clone()).await.unwrap();
+        let key = "cam/1/v_seg_0001.m4s";
+        let file = dir.path().join("v_seg_0001.m4s");
+        std::fs::write(&file, b"segment").unwrap();
+        uploader
+            .enqueue(
+                key.to_string(),
+                file.display().to_string(),
+                "cam",
+                None,
+                api::recorder::DEFAULT_PRIORITY,
+            )
+            .await
+            .unwrap();
+
+        // Storage answered 200 and kept nothing: the file stays, the attempt is noted
+        let entry = uploader.due(i64::MAX).await.remove(0);
+        let err = uploader.try_upload(entry).await.unwrap_err();
+        let mismatch = Mismatch::Size {
+            local: 7,
+            stored: 0,
+        };
+        assert_eq!(err.downcast_ref::<Mismatch>(), Some(&mismatch));
+        assert!(file.exists());
+        let reloaded = UploadManager::load(cfg.clone()).await.unwrap();
+        let entry = reloaded.due(i64::MAX).await.remove(0);
+        assert_eq!(entry.retry_count, 1);
+        assert_eq!(entry.attempts.len(), 1);
+        assert_eq!(entry.attempts[0].mismatch, Some(mismatch));
+
+        uploader.try_upload(entry).await.unwrap();
+        assert_eq!(mock.accepted(), ["put", "put"]);
+        assert!(!file.exists());
+
+        // Same size, other bytes: only the checksum tells
+        mock.corrupt.lock().unwrap().push("put".to_string());
+        let uploader = UploadManager::load(UploadConfig {
+            verify_checksum: true,
+            ..cfg
+        })
+        .await
+        .unwrap();
+        std::fs::write(&file, b"segment").unwrap();
+        uploader
+            .enqueue(
+                key.to_string(),
+                file.display().to_string(),
+                "cam",
+                None,
+                api::recorder::DEFAULT_PRIORITY,
+            )
+            .await
+            .unwrap();
+        let entry = uploader.due(i64::MAX).await.remove(0);
+        let err = uploader.try_upload(entry).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<Mismatch>(), Some(&Mismatch::Checksum));
+        let entry = uploader.due(i64::MAX).await.remove(0);
+        uploader.try_upload(entry).await.unwrap();
+        assert_eq!(mock.objects.lock().unwrap()[key], b"segment");
+    }
+
+    #[test]
+    fn test_outage_suspends_after_threshold() {
+        let mut outage = Outage::default();
+        assert!(!outage.failed(3, 1_000, "refused".to_string()));
+        assert!(!outage.failed(3, 2_000, "refused".to_string()));
+        assert!(outage.failed(3, 3_000, "refused".to_string()));
+        // Logged once, later failures only update the suspension
+        assert!(!outage.failed(3, 4_000, "timed out".to_string()));
+        let suspension = outage.suspended.clone().unwrap();
+        assert_eq!(suspension.since, 3_000_000);
+        assert_eq!(suspension.last_error.as_deref(), Some("timed out"));
+
+        assert!(!outage.probe_due(10_000, 12_000));
+        assert!(outage.probe_due(10_000, 13_000));
+        assert!(!outage.probe_due(10_000, 14_000));
+        assert_eq!(outage.suspended.as_ref().unwrap().probes, 1);
+
+        assert_eq!(outage.recovered().unwrap().probes, 1);
+        assert_eq!(outage.failures, 0);
+        assert!(outage.recovered().is_none());
+        assert!(!outage.probe_due(10_000, 100_000));
+
+        // 0 never suspends
+        let mut outage = Outage::default();
+        assert!((0..100).all(|_| !outage.failed(0, 0, String::new())));
+    }
+
+    #[tokio::test]
+    async fn test_suspended_while_liveman_down() {
+        // Nothing listens on the port until liveman comes back
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = UploadConfig {
+            liveman_url: format!("http://{addr}"),
+            queue_path: dir.path().join("queue.jsonl").display().to_string(),
+            max_retries: 1,
+            suspend_after_failures: 2,
+            suspend_probe_interval_ms: 0,
+            ..Default::default()
+        };
+        let uploader = UploadManager::load(cfg).await.unwrap();
+        let file = dir.path().join("v_seg_0001.m4s");
+        std::fs::write(&file, b"segment").unwrap();
+        uploader
+            .enqueue(
+                "cam/1/v_seg_0001.m4s".to_string(),
+                file.display().to_string(),
+                "cam",
+                None,
+                api::recorder::DEFAULT_PRIORITY,
+            )
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            let entry = uploader.due(i64::MAX).await.remove(0);
+            assert!(uploader.try_upload(entry).await.is_err());
+        }
+        let status = uploader.queue_status().await;
+        assert_eq!((status.pending, status.consecutive_failures), (1, 3));
+        assert!(status.suspended.is_some());
+        // Not given up after max_retries, nor backed off
+        let entries = uploader.due(0).await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].retry_count, 0);
+        assert!(!uploader.is_liveman_available().await.unwrap());
+
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        let app =
+            axum::Router::new().route("/api/storage/ping", axum::routing::get(|| async { "pong" }));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        assert!(uploader.is_liveman_available().await.unwrap());
+        let status = uploader.queue_status().await;
+        assert_eq!((status.consecutive_failures, status.suspended), (0, None));
+    }
+
+    /// Batched queue writes: a crash before the flusher's next write loses the changes
+    /// since the last one, never the queue written before them
+    #[tokio::test]
+    async fn test_batched_queue_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue_path = dir.path().join("queue.jsonl");
+        let cfg = UploadConfig {
+            queue_path: queue_path.display().to_string(),
+            staging_dir: dir.path().join("staging").display().to_string(),
+            ..Default::default()
+        };
+        let durability = Arc::new(Durability::new(&crate::config::DurabilityConfig {
+            mode: crate::config::DurabilityMode::Batched,
+            flush_interval_ms: 60_000,
+        }));
+        let uploader = UploadManager::load(cfg.clone())
+            .await
+            .unwrap()
+            .with_durability(durability);
+        let stage = |segment: usize| {
+            let local = dir.path().join(format!("{segment}.m4s"));
+            std::fs::write(&local, "x").unwrap();
+            let uploader = &uploader;
+            async move {
+                uploader
+                    .stage(
+                        format!("cam/1/{segment}.m4s"),
+                        &local,
+                        "cam",
+                        None,
+                        api::recorder::DEFAULT_PRIORITY,
+                    )
+                    .await
+                    .unwrap()
+            }
+        };
+        let cfg = &cfg;
+        let reloaded = || async move {
+            UploadManager::load(cfg.clone())
+                .await
+                .unwrap()
+                .queued()
+                .await
+        };
+
+        for segment in 0..5 {
+            stage(segment).await;
+        }
+        // Killed before the first flush
+        assert!(!queue_path.exists());
+        uploader.flush_queue().await.unwrap();
+        assert_eq!(reloaded().await.len(), 5);
+
+        for segment in 5..8 {
+            stage(segment).await;
+        }
+        // Killed within the window: the 3 changes since the flush are lost, not the queue
+        assert_eq!(reloaded().await.len(), 5);
+        uploader.flush_queue().await.unwrap();
+        assert_eq!(reloaded().await.len(), 8);
+        // Nothing changed, nothing written
+        let modified = std::fs::metadata(&queue_path).unwrap().modified().unwrap();
+        uploader.flush_queue().await.unwrap();
+        assert_eq!(
+            std::fs::metadata(&queue_path).unwrap().modified().unwrap(),
+            modified
+        );
+    }
+}
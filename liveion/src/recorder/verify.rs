@@ -0,0 +1,371 @@
+//! Compare the local files of a recording with the objects stored under its prefix,
+//! answering whether every segment written on this node made it to storage.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use api::recorder::{RecordingStatus, SizeMismatch, VerifyRecordingResponse};
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use storage::FailoverOperator;
+use tokio::sync::Mutex;
+use tokio::time::{self, MissedTickBehavior};
+
+use super::index::RecordingsIndex;
+use super::uploader::UploadManager;
+
+/// Final results are reused for this long unless the index entry changes
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+struct Cached {
+    updated_at: i64,
+    at: Instant,
+    result: VerifyRecordingResponse,
+}
+
+pub struct Verifier {
+    index: Arc<RecordingsIndex>,
+    operator: FailoverOperator,
+    uploader: Arc<UploadManager>,
+    max_checksum_reads_per_second: u32,
+    /// Serializes checksum runs so concurrent requests do not multiply the reads
+    checksum_lock: Mutex<()>,
+    cache: Mutex<HashMap<(String, bool), Cached>>,
+}
+
+impl Verifier {
+    pub fn new(
+        index: Arc<RecordingsIndex>,
+        operator: FailoverOperator,
+        uploader: Arc<UploadManager>,
+        max_checksum_reads_per_second: u32,
+    ) -> Self {
+        Self {
+            index,
+            operator,
+            uploader,
+            max_checksum_reads_per_second: max_checksum_reads_per_second.max(1),
+            checksum_lock: Mutex::new(()),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Diff of `stream/record`, `None` when the index has no such recording.
+    ///
+    /// Results of active recordings or recordings with queued uploads are provisional
+    /// and never cached.
+    pub async fn verify(
+        &self,
+        stream: &str,
+        record: &str,
+        checksum: bool,
+    ) -> Result<Option<VerifyRecordingResponse>> {
+        let Some(entry) = self.index.get(stream, record).await else {
+            return Ok(None);
+        };
+        let cache_key = (entry.key(), checksum);
+        if let Some(cached) = self.cache.lock().await.get(&cache_key)
+            && cached.updated_at == entry.updated_at
+            && cached.at.elapsed() < CACHE_TTL
+        {
+            return Ok(Some(cached.result.clone()));
+        }
+
+        let pending_uploads = self.uploader.pending_under(&entry.record_dir).await;
+        let roots = [
+            PathBuf::from(self.uploader.local_dir()),
+            PathBuf::from(self.uploader.staging_dir()),
+        ];
+        let local = local_files(&roots, &entry.record_dir).await?;
+        let remote = self.remote_objects(&entry.record_dir, &local).await?;
+
+        let local_sizes: BTreeMap<String, u64> = local
+            .iter()
+            .map(|(k, (_, size))| (k.clone(), *size))
+            .collect();
+        let (local_only, remote_only, size_mismatch) = compare(&local_sizes, &remote);
+        let checksum_mismatch = if checksum {
+            Some(
+                self.checksum_mismatch(&entry.record_dir, &local, &remote)
+                    .await?,
+            )
+        } else {
+            None
+        };
+        let complete = local_only.is_empty()
+            && size_mismatch.is_empty()
+            && checksum_mismatch.as_ref().is_none_or(|m| m.is_empty());
+        let provisional = matches!(entry.status, RecordingStatus::Active) || pending_uploads > 0;
+
+        let result = VerifyRecordingResponse {
+            stream: entry.stream.clone(),
+            record: entry.record.clone(),
+            record_dir: entry.record_dir.clone(),
+            complete,
+            provisional,
+            pending_uploads,
+            local_files: local.len(),
+            remote_objects: remote.len(),
+            local_only,
+            remote_only,
+            size_mismatch,
+            checksum_mismatch,
+            checked_at: Utc::now().timestamp_micros(),
+        };
+        if !provisional {
+            let mut cache = self.cache.lock().await;
+            cache.retain(|_, cached| cached.at.elapsed() < CACHE_TTL);
+            cache.insert(
+                cache_key,
+                Cached {
+                    updated_at: entry.updated_at,
+                    at: Instant::now(),
+                    result: result.clone(),
+                },
+            );
+        }
+        Ok(Some(result))
+    }
+
+    /// Sizes of the objects under `record_dir` keyed by their path relative to it
+    async fn remote_objects(
+        &self,
+        record_dir: &str,
+        local: &BTreeMap<String, (PathBuf, u64)>,
+    ) -> Result<BTreeMap<String, u64>> {
+        let operator = self.operator.current();
+        let prefix = format!("{}/", record_dir.trim_end_matches('/'));
+        let mut objects = BTreeMap::new();
+        for entry in operator.list_with(&prefix).recursive(true).await? {
+            if entry.metadata().is_dir() {
+                continue;
+            }
+            let Some(rel) = entry.path().strip_prefix(&prefix) else {
+                continue;
+            };
+            let mut size = entry.metadata().content_length();
+            // Not every backend reports sizes when listing
+            if size == 0 && local.get(rel).is_some_and(|(_, local)| *local > 0) {
+                size = operator.stat(entry.path()).await?.content_length();
+            }
+            objects.insert(rel.to_string(), size);
+        }
+        Ok(objects)
+    }
+
+    /// Objects present on both sides with equal size whose SHA-256 differs
+    async fn checksum_mismatch(
+        &self,
+        record_dir: &str,
+        local: &BTreeMap<String, (PathBuf, u64)>,
+        remote: &BTreeMap<String, u64>,
+    ) -> Result<Vec<String>> {
+        let _running = self.checksum_lock.lock().await;
+        let mut limiter = time::interval(Duration::from_secs_f64(
+            1.0 / self.max_checksum_reads_per_second as f64,
+        ));
+        limiter.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let operator = self.operator.current();
+        let mut mismatched = Vec::new();
+        for (rel, (path, size)) in local {
+            if remote.get(rel) != Some(size) {
+                continue;
+            }
+            limiter.tick().await;
+            let remote = operator
+                .read(&format!("{}/{}", record_dir.trim_end_matches('/'), rel))
+                .await?
+                .to_vec();
+            let local = tokio::fs::read(path).await?;
+            if Sha256::digest(&local) != Sha256::digest(&remote) {
+                mismatched.push(rel.clone());
+            }
+        }
+        Ok(mismatched)
+    }
+}
+
+/// Files under `record_dir` in each of `roots`, later roots win for the same path
+pub(super) async fn local_files(
+    roots: &[PathBuf],
+    record_dir: &str,
+) -> Result<BTreeMap<String, (PathBuf, u64)>> {
+    let mut files = BTreeMap::new();
+    for root in roots {
+        let base = root.join(record_dir);
+        let mut dirs = vec![base.clone()];
+        while let Some(dir) = dirs.pop() {
+            let mut read_dir = match tokio::fs::read_dir(&dir).await {
+                Ok(read_dir) => read_dir,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = read_dir.next_entry().await? {
+                let path = entry.path();
+                let meta = entry.metadata().await?;
+                if meta.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                // Half-staged files are renamed over their final name once complete
+                if path.extension().is_some_and(|ext| ext == "tmp") {
+                    continue;
+                }
+                if let Some(rel) = relative_key(&base, &path) {
+                    files.insert(rel, (path, meta.len()));
+                }
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn relative_key(base: &Path, path: &Path) -> Option<String> {
+    let rel = path.strip_prefix(base).ok()?;
+    let parts: Vec<_> = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    Some(parts.join("/"))
+}
+
+/// Split local and remote sizes into local only, remote only and size mismatches
+fn compare(
+    local: &BTreeMap<String, u64>,
+    remote: &BTreeMap<String, u64>,
+) -> (Vec<String>, Vec<String>, Vec<SizeMismatch>) {
+    let mut local_only = Vec::new();
+    let mut size_mismatch = Vec::new();
+    for (path, &local_size) in local {
+        match remote.get(path) {
+            None => local_only.push(path.clone()),
+            Some(&remote_size) if remote_size != local_size => size_mismatch.push(SizeMismatch {
+                path: path.clone(),
+                local_size,
+                remote_size,
+            }),
+            Some(_) => {}
+        }
+    }
+    let remote_only = remote
+        .keys()
+        .filter(|path| !local.contains_key(*path))
+        .cloned()
+        .collect();
+    (local_only, remote_only, size_mismatch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UploadConfig;
+    use api::recorder::{DEFAULT_PRIORITY, RecordingIndexEntry};
+    use storage::StorageConfig;
+
+    #[tokio::test]
+    async fn test_verify_reports_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = StorageConfig::Fs {
+            root: dir.path().join("storage").to_string_lossy().into_owned(),
+        };
+        let operator = storage::create_failover_operator(&storage).unwrap();
+        let index = Arc::new(
+            RecordingsIndex::load(dir.path().join("index.json"))
+                .await
+                .unwrap(),
+        );
+        let uploader = Arc::new(
+            UploadManager::load(UploadConfig {
+                queue_path: dir
+                    .path()
+                    .join("queue.jsonl")
+                    .to_string_lossy()
+                    .into_owned(),
+                local_dir: dir.path().join("local").to_string_lossy().into_owned(),
+                staging_dir: dir.path().join("staging").to_string_lossy().into_owned(),
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+        );
+        index
+            .upsert(RecordingIndexEntry {
+                uuid: String::new(),
+                record: "1".to_string(),
+                stream: "cam".to_string(),
+                record_dir: "cam/1".to_string(),
+                mpd_path: "cam/1/manifest.mpd".to_string(),
+                start_ts: 0,
+                end_ts: Some(1_000_000),
+                duration_ms: Some(1_000),
+                status: RecordingStatus::Completed,
+                node_alias: None,
+                updated_at: 1,
+                note: None,
+                labels: Vec::new(),
+                continues: None,
+                media_info: Vec::new(),
+                retention_class: None,
+                trashed_at: None,
+                trashed_from: None,
+                repair_error: None,
+                source: None,
+                replicas: Vec::new(),
+                clock_skew_detected: false,
+                priority: DEFAULT_PRIORITY,
+                tenant: None,
+                size: None,
+                captions: Vec::new(),
+                trigger: None,
+                imported: false,
+                seq: 0,
+            })
+            .await
+            .unwrap();
+
+        let local = dir.path().join("local/cam/1");
+        let staging = dir.path().join("staging/cam/1");
+        std::fs::create_dir_all(&local).unwrap();
+        std::fs::create_dir_all(&staging).unwrap();
+        std::fs::write(local.join("manifest.mpd"), "mpd").unwrap();
+        std::fs::write(local.join("v_seg_0001.m4s"), "same").unwrap();
+        std::fs::write(staging.join("v_seg_0002.m4s"), "queued").unwrap();
+        std::fs::write(staging.join("v_seg_0003.m4s"), "longer").unwrap();
+        std::fs::write(staging.join("v_seg_0003.m4s.1.0.tmp"), "partial").unwrap();
+        let remote = operator.current();
+        remote.write("cam/1/manifest.mpd", "mpd").await.unwrap();
+        remote.write("cam/1/v_seg_0001.m4s", "diff").await.unwrap();
+        remote.write("cam/1/v_seg_0003.m4s", "short").await.unwrap();
+        remote
+            .write("cam/1/v_seg_0000.m4s", "pruned")
+            .await
+            .unwrap();
+
+        let verifier = Verifier::new(index, operator, uploader, 1_000);
+        let result = verifier.verify("cam", "1", true).await.unwrap().unwrap();
+        assert!(!result.complete);
+        assert!(!result.provisional);
+        assert_eq!(result.local_files, 4);
+        assert_eq!(result.remote_objects, 4);
+        assert_eq!(result.local_only, vec!["v_seg_0002.m4s"]);
+        assert_eq!(result.remote_only, vec!["v_seg_0000.m4s"]);
+        assert_eq!(
+            result.size_mismatch,
+            vec![SizeMismatch {
+                path: "v_seg_0003.m4s".to_string(),
+                local_size: 6,
+                remote_size: 5,
+            }]
+        );
+        assert_eq!(result.checksum_mismatch.unwrap(), vec!["v_seg_0001.m4s"]);
+
+        // Without checksums only the listing is compared
+        let result = verifier.verify("cam", "1", false).await.unwrap().unwrap();
+        assert!(result.checksum_mismatch.is_none());
+        assert!(verifier.verify("cam", "2", false).await.unwrap().is_none());
+    }
+}
@@ -4,6 +4,7 @@ use crate::config::Config;
 use crate::stream::manager::Manager;
 
 pub mod admin;
+pub mod openapi;
 pub mod recorder;
 pub mod sdp;
 pub mod session;
@@ -20,3 +21,18 @@ pub struct AppState {
     pub config: Config,
     pub stream_manager: Arc<Manager>,
 }
+
+/// Rules of [`api::extract::NamedPath`], those the recorder starts recordings with
+#[cfg(feature = "recorder")]
+impl axum::extract::FromRef<AppState> for api::recorder::NameRules {
+    fn from_ref(_: &AppState) -> Self {
+        crate::recorder::name_rules()
+    }
+}
+
+#[cfg(not(feature = "recorder"))]
+impl axum::extract::FromRef<AppState> for api::recorder::NameRules {
+    fn from_ref(_: &AppState) -> Self {
+        Self::default()
+    }
+}
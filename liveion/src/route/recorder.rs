@@ -1,6 +1,11 @@
+use api::extract::NamedPath;
+#[cfg(feature = "recorder")]
+use auth::claims::Claims;
+#[cfg(feature = "recorder")]
+use axum::Extension;
 use axum::extract::{Path, Query, State};
 use axum::response::Response;
-use axum::routing::{get, post};
+use axum::routing::{get, patch, post, put};
 use axum::{Json, Router};
 
 #[cfg(feature = "recorder")]
@@ -15,17 +20,185 @@ pub fn route() -> Router<AppState> {
             &api::path::record("{stream}"),
             post(record_stream).get(record_status).delete(stop_record),
         )
+        .route(
+            &api::path::record_entry("{stream}", "{record}"),
+            patch(update_recording).delete(delete_recording),
+        )
+        .route(
+            &api::path::record_restore("{stream}", "{record}"),
+            post(restore_recording),
+        )
+        .route(
+            &api::path::record_captions("{stream}", "{record}", "{lang}"),
+            put(put_captions),
+        )
+        .route(&api::path::record_by_id("{uuid}"), get(recording_by_id))
+        .route(
+            &api::path::record_repair("{stream}", "{record}"),
+            post(repair_recording),
+        )
         .route(
             api::path::recordings(),
             get(pull_recordings)
                 .patch(ack_recordings)
                 .delete(delete_recordings),
         )
+        .route(api::path::recorder_events(), get(recorder_events))
+        .route(
+            api::path::recorder_reconcile(),
+            post(start_reconcile).get(reconcile_status),
+        )
+        .route(
+            api::path::recorder_capabilities(),
+            get(recorder_capabilities),
+        )
+        .route(api::path::recorder_trigger(), post(trigger_recording))
+        .route(api::path::recorder_rename_stream(), post(rename_stream))
+        .route(api::path::recorder_index_restore(), post(restore_index))
+        .route(api::path::recorder_import(), post(import_recordings))
+        .route(api::path::recorder_audit(), get(audit_log))
+        .route(&api::path::recorder_journal("{journal}"), get(read_journal))
+        .route(api::path::recorder_stats(), get(recorder_stats))
+        .route(api::path::recorder_health(), get(recorder_health))
+        .route(
+            &api::path::recorder_stream_health("{stream}"),
+            get(recorder_stream_health),
+        )
+        .route(api::path::recorder_uploads(), get(upload_queue))
+        .route(
+            &api::path::recorder_stream_stats("{stream}"),
+            get(recorder_stream_stats),
+        )
+        .route(
+            &api::path::recorder_verify("{stream}", "{record}"),
+            get(verify_recording),
+        )
+        .route(
+            api::path::storage_chaos(),
+            get(storage_chaos).put(update_storage_chaos),
+        )
+        .route(api::path::storage_diagnose(), post(diagnose_storage))
+}
+
+#[cfg(feature = "recorder")]
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(
+    record_stream,
+    record_status,
+    stop_record,
+    update_recording,
+    delete_recording,
+    restore_recording,
+    recording_by_id,
+    repair_recording,
+    put_captions,
+    pull_recordings,
+    recorder_events,
+    ack_recordings,
+    delete_recordings,
+    start_reconcile,
+    reconcile_status,
+    recorder_capabilities,
+    trigger_recording,
+    rename_stream,
+    restore_index,
+    import_recordings,
+    audit_log,
+    read_journal,
+    recorder_stats,
+    recorder_stream_stats,
+    recorder_health,
+    recorder_stream_health,
+    upload_queue,
+    verify_recording,
+    diagnose_storage,
+))]
+pub struct RecorderApi;
+
+/// Token subject recorded in the audit log and labelling event subscribers, `*` for
+/// static tokens and without auth
+#[cfg(feature = "recorder")]
+fn actor(claims: Option<Extension<Claims>>) -> String {
+    claims.map_or_else(|| auth::ANY_ID.to_string(), |Extension(claims)| claims.id)
+}
+
+/// Seconds a caller should wait before retrying against a locked index
+#[cfg(feature = "recorder")]
+const INDEX_BUSY_RETRY_SECONDS: u64 = 1;
+
+/// Types a failed recorder operation so callers can tell a busy index or unreachable
+/// storage, worth retrying, from a request that will never succeed. Anything not
+/// recognized stays a 500.
+#[cfg(feature = "recorder")]
+fn recorder_error(e: anyhow::Error) -> AppError {
+    use api::recorder::RecorderError;
+
+    if let Some(err) = e.downcast_ref::<RecorderError>() {
+        return AppError::recorder(err.clone());
+    }
+    if let Some(timeout) = e.downcast_ref::<crate::recorder::LockTimeout>() {
+        return AppError::recorder(RecorderError::IndexBusy {
+            retry_after_seconds: INDEX_BUSY_RETRY_SECONDS,
+            message: timeout.to_string(),
+        });
+    }
+    if let Some(err) = e.downcast_ref::<storage::KeyError>() {
+        return AppError::recorder(RecorderError::validation(Some("stream"), err));
+    }
+    if e.chain().any(|cause| cause.is::<opendal::Error>()) {
+        return AppError::recorder(RecorderError::StorageUnavailable {
+            message: format!("{e:#}"),
+        });
+    }
+    AppError::InternalServerError(e)
+}
+
+/// The index or storage this node records to is not set up
+#[cfg(feature = "recorder")]
+fn not_initialized() -> AppError {
+    AppError::recorder(api::recorder::RecorderError::StorageUnavailable {
+        message: "recorder index or storage not initialized".to_string(),
+    })
+}
+
+/// Response to a `dry_run=true` request
+#[cfg(feature = "recorder")]
+fn dry_run_response(
+    stream: &str,
+    dry_run: crate::recorder::DryRun,
+) -> crate::result::Result<Response> {
+    use crate::recorder::DryRun;
+    use api::recorder::RecorderError;
+    use axum::response::IntoResponse;
+
+    match dry_run {
+        DryRun::Affected(resp) => Ok(Json(resp).into_response()),
+        DryRun::NotFound(message) => Err(AppError::recorder(RecorderError::NotFound {
+            stream: stream.to_string(),
+            record: None,
+            message,
+        })),
+        DryRun::Conflict(message) => Err(AppError::recorder(RecorderError::Conflict { message })),
+    }
 }
+
 #[cfg(feature = "recorder")]
+#[utoipa::path(
+    post,
+    path = "/api/record/{stream}",
+    tag = "recorder",
+    params(("stream" = String, Path, description = "Stream id")),
+    request_body = api::recorder::StartRecordRequest,
+    responses(
+        (status = 200, description = "Recording started", body = api::recorder::StartRecordResponse),
+        (status = 400, description = "The stream name breaks a naming rule, or object keys of the recording would exceed S3's limits", body = api::recorder::RecorderError),
+        (status = 429, description = "The node records max_concurrent_recordings streams already", body = api::recorder::RecorderError),
+        (status = 500, description = "Stream missing or already recording", body = String),
+    )
+)]
 async fn record_stream(
     State(state): State<AppState>,
-    Path(stream): Path<String>,
+    NamedPath(stream): NamedPath<String>,
     Json(body): Json<api::recorder::StartRecordRequest>,
 ) -> crate::result::Result<Response<String>> {
     let base_dir = body.base_dir.clone();
@@ -33,8 +206,17 @@ async fn record_stream(
         state.stream_manager.clone(),
         stream.clone(),
         base_dir.clone(),
+        body.retention_class.clone(),
+        body.priority,
     )
-    .await?;
+    .await
+    .map_err(|e| match e.downcast_ref::<storage::KeyError>() {
+        Some(_) => AppError::recorder(api::recorder::RecorderError::validation(
+            Some("stream"),
+            format!("cannot record {stream}: {e}"),
+        )),
+        None => recorder_error(e),
+    })?;
 
     let mpd_path = format!("{}/manifest.mpd", recording.record_dir);
     let record_id_str = if recording.record_id > 0 {
@@ -48,6 +230,8 @@ async fn record_stream(
         record_id: record_id_str,
         record_dir: recording.record_dir,
         mpd_path,
+        retention_class: recording.retention_class,
+        priority: recording.priority,
     };
     match serde_json::to_string(&resp) {
         Ok(json_body) => Ok(Response::builder().status(StatusCode::OK).body(json_body)?),
@@ -61,18 +245,43 @@ async fn record_stream(
 #[cfg(not(feature = "recorder"))]
 async fn record_stream(
     _state: State<AppState>,
-    Path(_stream): Path<String>,
+    NamedPath(_stream): NamedPath<String>,
 ) -> crate::result::Result<Response<String>> {
     Err(AppError::Throw("feature recorder not enabled".into()))
 }
 
 #[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/record/{stream}",
+    tag = "recorder",
+    params(("stream" = String, Path, description = "Stream id")),
+    responses(
+        (status = 200, description = "Whether the stream is recording and its recording is stalled, its schedule, the upload spool's free space, the node's recordings against max_concurrent_recordings, the startup gate of storage writes, the durability mode of index and upload queue writes and the stream's upload lag", body = Object),
+    )
+)]
 async fn record_status(
     State(_state): State<AppState>,
-    Path(stream): Path<String>,
+    NamedPath(stream): NamedPath<String>,
 ) -> crate::result::Result<Json<serde_json::Value>> {
     let recording = crate::recorder::is_recording(&stream).await;
-    Ok(Json(serde_json::json!({ "recording": recording })))
+    let stall = crate::recorder::stall_status(&stream).await;
+    let schedule = crate::recorder::schedule_status(&stream).await;
+    let disk = crate::recorder::disk_status().await;
+    let recordings = crate::recorder::recording_capacity().await;
+    let startup = crate::recorder::startup_status().await;
+    let durability = crate::recorder::durability_status().await;
+    let upload_lag = crate::recorder::upload_lag(&stream).await;
+    Ok(Json(serde_json::json!({
+        "recording": recording,
+        "stall": stall,
+        "schedule": schedule,
+        "disk": disk,
+        "recordings": recordings,
+        "startup": startup,
+        "durability": durability,
+        "upload_lag": upload_lag,
+    })))
 }
 
 #[cfg(not(feature = "recorder"))]
@@ -84,11 +293,20 @@ async fn record_status(
 }
 
 #[cfg(feature = "recorder")]
+#[utoipa::path(
+    delete,
+    path = "/api/record/{stream}",
+    tag = "recorder",
+    params(("stream" = String, Path, description = "Stream id")),
+    responses((status = 200, description = "Recording stopped"))
+)]
 async fn stop_record(
     State(_state): State<AppState>,
-    Path(stream): Path<String>,
+    NamedPath(stream): NamedPath<String>,
 ) -> crate::result::Result<Response<String>> {
-    crate::recorder::stop(stream.clone()).await?;
+    crate::recorder::stop(stream.clone())
+        .await
+        .map_err(recorder_error)?;
     Ok(Response::builder()
         .status(StatusCode::OK)
         .body("".to_string())?)
@@ -97,31 +315,410 @@ async fn stop_record(
 #[cfg(not(feature = "recorder"))]
 async fn stop_record(
     _state: State<AppState>,
-    Path(_stream): Path<String>,
+    NamedPath(_stream): NamedPath<String>,
 ) -> crate::result::Result<Response<String>> {
     Err(AppError::Throw("feature recorder not enabled".into()))
 }
 
 #[cfg(feature = "recorder")]
+#[utoipa::path(
+    patch,
+    path = "/api/record/{stream}/{record}",
+    tag = "recorder",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+    ),
+    request_body = api::recorder::UpdateRecordingRequest,
+    responses(
+        (status = 200, description = "Updated index entry", body = api::recorder::RecordingIndexEntry),
+        (status = 400, description = "Invalid or rejected update", body = api::recorder::RecorderError),
+        (status = 404, description = "Recording not found", body = api::recorder::RecorderError),
+        (status = 409, description = "`if_match` is not the recording's `updated_at`", body = api::recorder::RecorderError),
+        (status = 503, description = "Index locked by another process or storage unavailable", body = api::recorder::RecorderError),
+    )
+)]
+async fn update_recording(
+    NamedPath((stream, record)): NamedPath<(String, String)>,
+    Json(req): Json<api::recorder::UpdateRecordingRequest>,
+) -> crate::result::Result<Json<api::recorder::RecordingIndexEntry>> {
+    use crate::recorder::MetadataUpdate;
+    use api::recorder::RecorderError;
+
+    req.validate()
+        .map_err(|e| AppError::recorder(RecorderError::validation(None, e)))?;
+    let if_match = req.if_match;
+    match crate::recorder::update_recording(&stream, &record, req)
+        .await
+        .map_err(recorder_error)?
+    {
+        MetadataUpdate::Updated(entry) => Ok(Json(entry)),
+        MetadataUpdate::NotFound => Err(AppError::recorder(RecorderError::not_found(
+            &stream, &record,
+        ))),
+        MetadataUpdate::Rejected(reason) => {
+            Err(AppError::recorder(RecorderError::validation(None, reason)))
+        }
+        MetadataUpdate::Stale(current) => Err(AppError::recorder(RecorderError::version_mismatch(
+            if_match.unwrap_or_default(),
+            current,
+        ))),
+    }
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn update_recording(
+    NamedPath(_path): NamedPath<(String, String)>,
+    Json(_req): Json<api::recorder::UpdateRecordingRequest>,
+) -> crate::result::Result<Json<api::recorder::RecordingIndexEntry>> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    delete,
+    path = "/api/record/{stream}/{record}",
+    tag = "recorder",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+        api::recorder::DeleteRecordingQuery,
+    ),
+    responses(
+        (status = 200, description = "Recording moved to the trash, or a `DryRunResponse` with `dry_run=true`", body = api::recorder::RecordingIndexEntry),
+        (status = 204, description = "Recording deleted with `permanent=true`"),
+        (status = 404, description = "Recording not found", body = api::recorder::RecorderError),
+        (status = 409, description = "Recording still being written or uploaded, or `if_match` is not its `updated_at`", body = api::recorder::RecorderError),
+        (status = 503, description = "Index locked by another process or storage unavailable", body = api::recorder::RecorderError),
+    )
+)]
+async fn delete_recording(
+    claims: Option<Extension<Claims>>,
+    NamedPath((stream, record)): NamedPath<(String, String)>,
+    Query(query): Query<api::recorder::DeleteRecordingQuery>,
+) -> crate::result::Result<Response> {
+    use crate::recorder::TrashUpdate;
+    use axum::response::IntoResponse;
+
+    if query.dry_run {
+        let Some(dry_run) =
+            crate::recorder::preview_delete_recording(&stream, &record, query.permanent).await
+        else {
+            return Err(not_initialized());
+        };
+        return dry_run_response(&stream, dry_run.map_err(recorder_error)?);
+    }
+    let actor = actor(claims);
+    let update = if query.permanent {
+        let Some(update) =
+            crate::recorder::purge_recording(&stream, &record, &actor, query.if_match).await
+        else {
+            return Err(not_initialized());
+        };
+        update
+    } else {
+        crate::recorder::trash_recording(&stream, &record, &actor, query.if_match).await
+    };
+    match update.map_err(recorder_error)? {
+        TrashUpdate::Updated(_) if query.permanent => Ok(StatusCode::NO_CONTENT.into_response()),
+        TrashUpdate::Updated(entry) => Ok(Json(entry).into_response()),
+        update => Err(trash_error(&stream, &record, query.if_match, update)),
+    }
+}
+
+/// Error of a trash, purge or restore that did not update the recording
+#[cfg(feature = "recorder")]
+fn trash_error(
+    stream: &str,
+    record: &str,
+    if_match: Option<i64>,
+    update: crate::recorder::TrashUpdate,
+) -> AppError {
+    use crate::recorder::TrashUpdate;
+    use api::recorder::RecorderError;
+
+    match update {
+        TrashUpdate::Conflict(message) => AppError::recorder(RecorderError::InvalidTransition {
+            stream: stream.to_string(),
+            record: record.to_string(),
+            message,
+        }),
+        TrashUpdate::Stale(current) => AppError::recorder(RecorderError::version_mismatch(
+            if_match.unwrap_or_default(),
+            current,
+        )),
+        _ => AppError::recorder(RecorderError::not_found(stream, record)),
+    }
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn delete_recording(
+    NamedPath(_path): NamedPath<(String, String)>,
+) -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    post,
+    path = "/api/record/{stream}/{record}/restore",
+    tag = "recorder",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+        api::recorder::RestoreRecordingQuery,
+    ),
+    responses(
+        (status = 200, description = "Recording taken out of the trash", body = api::recorder::RecordingIndexEntry),
+        (status = 404, description = "Recording not found or already purged", body = api::recorder::RecorderError),
+        (status = 409, description = "Recording is not in the trash, or `if_match` is not its `updated_at`", body = api::recorder::RecorderError),
+    )
+)]
+async fn restore_recording(
+    NamedPath((stream, record)): NamedPath<(String, String)>,
+    Query(query): Query<api::recorder::RestoreRecordingQuery>,
+) -> crate::result::Result<Json<api::recorder::RecordingIndexEntry>> {
+    use crate::recorder::TrashUpdate;
+
+    match crate::recorder::restore_recording(&stream, &record, query.if_match)
+        .await
+        .map_err(recorder_error)?
+    {
+        TrashUpdate::Updated(entry) => Ok(Json(entry)),
+        update => Err(trash_error(&stream, &record, query.if_match, update)),
+    }
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn restore_recording(
+    NamedPath(_path): NamedPath<(String, String)>,
+) -> crate::result::Result<Json<api::recorder::RecordingIndexEntry>> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/record/by-id/{uuid}",
+    tag = "recorder",
+    params(("uuid" = String, Path, description = "Recording uuid, see `RecordingIndexEntry::uuid`")),
+    responses(
+        (status = 200, description = "Index entry of the recording", body = api::recorder::RecordingIndexEntry),
+        (status = 400, description = "Not a uuid", body = api::recorder::RecorderError),
+        (status = 404, description = "Recording not found or acked", body = api::recorder::RecorderError),
+    )
+)]
+async fn recording_by_id(
+    Path(uuid): Path<String>,
+) -> crate::result::Result<Json<api::recorder::RecordingIndexEntry>> {
+    use api::recorder::RecorderError;
+
+    uuid::Uuid::parse_str(&uuid)
+        .map_err(|e| AppError::recorder(RecorderError::validation(Some("uuid"), e)))?;
+    crate::recorder::recording_by_uuid(&uuid)
+        .await
+        .map(Json)
+        .ok_or_else(|| {
+            AppError::recorder(RecorderError::NotFound {
+                stream: String::new(),
+                record: None,
+                message: format!("recording {uuid} not found"),
+            })
+        })
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn recording_by_id(
+    Path(_uuid): Path<String>,
+) -> crate::result::Result<Json<api::recorder::RecordingIndexEntry>> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/recordings",
+    tag = "recorder",
+    params(api::recorder::PullRecordingsRequest, api::jsonl::FormatQuery),
+    responses(
+        (status = 200, description = "Page of recordings. As JSON Lines one session per line with `x-next-cursor` carrying the next page, a listing that fails midway ends with an `{\"error\": ...}` line. `x-index-seq` is the index journal position the page was read at", content(
+            (api::recorder::PullRecordingsResponse = "application/json"),
+            (api::recorder::RecordingSession = "application/x-ndjson"),
+        )),
+        (status = 400, description = "Invalid cursor", body = api::recorder::RecorderError),
+        (status = 503, description = "Index locked by another process or storage unavailable", body = api::recorder::RecorderError),
+    )
+)]
 async fn pull_recordings(
+    headers: http::HeaderMap,
+    Query(format): Query<api::jsonl::FormatQuery>,
     Query(req): Query<api::recorder::PullRecordingsRequest>,
-) -> crate::result::Result<Json<api::recorder::PullRecordingsResponse>> {
-    let resp = crate::recorder::pull_recordings(req).await?;
-    Ok(Json(resp))
+) -> crate::result::Result<Response> {
+    use axum::response::IntoResponse;
+
+    let cursor = req.parsed_cursor().map_err(|e| {
+        AppError::recorder(api::recorder::RecorderError::validation(Some("cursor"), e))
+    })?;
+    // Read first: the listing holds at least every write up to it
+    let seq = crate::recorder::index_seq().await;
+    let resp = crate::recorder::pull_recordings(req, cursor)
+        .await
+        .map_err(recorder_error)?;
+    let accept = headers
+        .get(http::header::ACCEPT)
+        .and_then(|v| v.to_str().ok());
+    let mut response = if api::jsonl::requested(accept, format.format.as_deref()) {
+        let mut response = jsonl_response(resp.sessions);
+        if let Some(next) = resp.next_cursor {
+            response.headers_mut().insert(
+                api::recorder::NEXT_CURSOR_HEADER,
+                http::HeaderValue::from_str(&next)?,
+            );
+        }
+        response
+    } else {
+        Json(resp).into_response()
+    };
+    if let Some(seq) = seq {
+        response.headers_mut().insert(
+            api::recorder::INDEX_SEQ_HEADER,
+            http::HeaderValue::from(seq),
+        );
+    }
+    Ok(response)
 }
 
 #[cfg(not(feature = "recorder"))]
 async fn pull_recordings(
     Query(_req): Query<api::recorder::PullRecordingsRequest>,
-) -> crate::result::Result<Json<api::recorder::PullRecordingsResponse>> {
+) -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+/// Chunks of a JSON Lines body encoded ahead of a slow client
+#[cfg(feature = "recorder")]
+const JSONL_CHUNKS_AHEAD: usize = 4;
+
+/// `items` as JSON Lines, each serialized only once the client has read the chunks
+/// before it
+#[cfg(feature = "recorder")]
+fn jsonl_response<T>(items: Vec<T>) -> Response
+where
+    T: serde::Serialize + Send + 'static,
+{
+    let (tx, rx) =
+        tokio::sync::mpsc::channel::<Result<Vec<u8>, std::convert::Infallible>>(JSONL_CHUNKS_AHEAD);
+    tokio::spawn(async move {
+        let mut encoder = api::jsonl::Encoder::new();
+        for item in items {
+            match encoder.push(&item) {
+                Ok(Some(chunk)) => {
+                    // The client went away
+                    if tx.send(Ok(chunk)).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!("[recorder] listing failed midway: {}", e);
+                    let _ = tx.send(Ok(encoder.error(e))).await;
+                    return;
+                }
+            }
+        }
+        if let Some(chunk) = encoder.finish() {
+            let _ = tx.send(Ok(chunk)).await;
+        }
+    });
+    Response::builder()
+        .header(http::header::CONTENT_TYPE, api::jsonl::CONTENT_TYPE)
+        .body(axum::body::Body::from_stream(
+            tokio_stream::wrappers::ReceiverStream::new(rx),
+        ))
+        .unwrap()
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/recorder/events",
+    tag = "recorder",
+    params(
+        ("Last-Event-ID" = Option<i64>, Header, description = "Resume after this event id"),
+        api::recorder::EventsQuery,
+    ),
+    responses(
+        (status = 200, description = "Server-sent recorder events. A subscriber too slow to keep up has events dropped, then gets a `lagged` event with `EventsLagged` data and resynchronizes from the index", body = api::recorder::RecorderEvent, content_type = "text/event-stream"),
+        (status = 400, description = "Malformed stream glob, event type or subscriber name", body = api::recorder::RecorderError),
+    )
+)]
+async fn recorder_events(
+    headers: http::HeaderMap,
+    claims: Option<Extension<Claims>>,
+    Query(query): Query<api::recorder::EventsQuery>,
+) -> crate::result::Result<
+    axum::response::Sse<
+        impl tokio_stream::Stream<Item = Result<axum::response::sse::Event, std::convert::Infallible>>,
+    >,
+> {
+    use axum::response::sse::{Event, KeepAlive};
+    use tokio_stream::StreamExt;
+    use tokio_stream::wrappers::ReceiverStream;
+
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<i64>().ok());
+    let filter = crate::recorder::EventFilter::new(&query).map_err(AppError::recorder)?;
+    let subscriber = crate::recorder::subscriber_label(query.subscriber.as_deref(), &actor(claims))
+        .map_err(AppError::recorder)?;
+    let subscription =
+        crate::recorder::subscribe_events(last_event_id, filter, &subscriber).await?;
+    let delivered = subscription.delivered;
+    let stream = ReceiverStream::new(subscription.events).map(move |delivery| match delivery {
+        crate::recorder::Delivery::Event(event) => {
+            delivered.inc();
+            Ok(Event::default()
+                .event(event.kind.as_str())
+                .id(event.id.to_string())
+                .json_data(&event.entry)
+                .unwrap())
+        }
+        // Without an id, a reconnect resumes after the last event sent
+        crate::recorder::Delivery::Lagged(lagged) => Ok(Event::default()
+            .event(api::recorder::EVENTS_LAGGED)
+            .json_data(&lagged)
+            .unwrap()),
+    });
+    Ok(axum::response::Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn recorder_events() -> crate::result::Result<Response> {
     Err(AppError::Throw("feature recorder not enabled".into()))
 }
 
 #[cfg(feature = "recorder")]
+#[utoipa::path(
+    patch,
+    path = "/api/recordings",
+    tag = "recorder",
+    request_body = api::recorder::AckRecordingsRequest,
+    responses(
+        (status = 200, description = "Acknowledged recordings", body = api::recorder::AckRecordingsResponse),
+        (status = 400, description = "Invalid request", body = api::recorder::RecorderError),
+        (status = 409, description = "A key's `if_match` is not its recording's `updated_at`, nothing acked", body = api::recorder::RecorderError),
+        (status = 503, description = "Index locked by another process or storage unavailable", body = api::recorder::RecorderError),
+    )
+)]
 async fn ack_recordings(
     Json(req): Json<api::recorder::AckRecordingsRequest>,
 ) -> crate::result::Result<Json<api::recorder::AckRecordingsResponse>> {
-    let resp = crate::recorder::ack_recordings(req).await?;
+    req.validate()
+        .map_err(|e| AppError::recorder(api::recorder::RecorderError::validation(None, e)))?;
+    let resp = crate::recorder::ack_recordings(req)
+        .await
+        .map_err(recorder_error)?;
     Ok(Json(resp))
 }
 
@@ -133,16 +730,758 @@ async fn ack_recordings(
 }
 
 #[cfg(feature = "recorder")]
+#[utoipa::path(
+    delete,
+    path = "/api/recordings",
+    tag = "recorder",
+    params(api::recorder::DryRunQuery),
+    request_body = api::recorder::DeleteRecordingsRequest,
+    responses(
+        (status = 200, description = "Deleted recordings, or a `DryRunResponse` with `dry_run=true`", body = api::recorder::DeleteRecordingsResponse),
+        (status = 409, description = "A key's `if_match` is not its recording's `updated_at`, nothing deleted", body = api::recorder::RecorderError),
+        (status = 503, description = "Index locked by another process or storage unavailable", body = api::recorder::RecorderError),
+    )
+)]
 async fn delete_recordings(
+    claims: Option<Extension<Claims>>,
+    Query(query): Query<api::recorder::DryRunQuery>,
     Json(req): Json<api::recorder::DeleteRecordingsRequest>,
-) -> crate::result::Result<Json<api::recorder::DeleteRecordingsResponse>> {
-    let resp = crate::recorder::delete_recordings(req).await?;
-    Ok(Json(resp))
+) -> crate::result::Result<Response> {
+    use axum::response::IntoResponse;
+
+    if query.dry_run {
+        let resp = crate::recorder::preview_delete_recordings(&req)
+            .await
+            .map_err(recorder_error)?;
+        return Ok(Json(resp).into_response());
+    }
+    let resp = crate::recorder::delete_recordings(req, &actor(claims))
+        .await
+        .map_err(recorder_error)?;
+    Ok(Json(resp).into_response())
 }
 
 #[cfg(not(feature = "recorder"))]
 async fn delete_recordings(
     Json(_req): Json<api::recorder::DeleteRecordingsRequest>,
-) -> crate::result::Result<Json<api::recorder::DeleteRecordingsResponse>> {
+) -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    post,
+    path = "/api/recorder/reconcile",
+    tag = "recorder",
+    request_body(content = Option<api::recorder::ReconcileRequest>),
+    responses(
+        (status = 202, description = "Reconcile started", body = api::recorder::ReconcileStatus),
+        (status = 409, description = "A reconcile is already running", body = api::recorder::ReconcileStatus),
+    )
+)]
+async fn start_reconcile(
+    State(state): State<AppState>,
+    body: Option<Json<api::recorder::ReconcileRequest>>,
+) -> crate::result::Result<Response<String>> {
+    let sample_segments = body
+        .and_then(|Json(req)| req.sample_segments)
+        .unwrap_or(state.config.recorder.reconcile.sample_segments);
+    let Some((started, status)) = crate::recorder::reconcile(sample_segments).await else {
+        return Err(not_initialized());
+    };
+    let code = if started {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::CONFLICT
+    };
+    Ok(Response::builder()
+        .status(code)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_string(&status)?)?)
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn start_reconcile() -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/recorder/reconcile",
+    tag = "recorder",
+    responses((status = 200, description = "Last or running reconcile", body = api::recorder::ReconcileStatus))
+)]
+async fn reconcile_status() -> crate::result::Result<Json<api::recorder::ReconcileStatus>> {
+    match crate::recorder::reconcile_status().await {
+        Some(status) => Ok(Json(status)),
+        None => Err(not_initialized()),
+    }
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn reconcile_status() -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/recorder/capabilities",
+    tag = "recorder",
+    responses((status = 200, description = "Recorder features of this node", body = api::recorder::RecorderCapabilities))
+)]
+async fn recorder_capabilities(
+    State(state): State<AppState>,
+) -> crate::result::Result<Json<api::recorder::RecorderCapabilities>> {
+    Ok(Json(crate::recorder::capabilities(&state.config.recorder)))
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn recorder_capabilities() -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    post,
+    path = "/api/recorder/trigger",
+    tag = "recorder",
+    request_body = api::recorder::TriggerRequest,
+    responses(
+        (status = 200, description = "Recording started, or running already with its stop pushed back when a trigger started it", body = api::recorder::TriggerResponse),
+        (status = 400, description = "Invalid stream", body = api::recorder::RecorderError),
+        (status = 404, description = "Stream not published", body = api::recorder::RecorderError),
+        (status = 429, description = "The node records max_concurrent_recordings streams already", body = api::recorder::RecorderError),
+    )
+)]
+async fn trigger_recording(
+    Json(req): Json<api::recorder::TriggerRequest>,
+) -> crate::result::Result<Json<api::recorder::TriggerResponse>> {
+    use api::recorder::RecorderError;
+
+    if req.stream.trim().is_empty() {
+        return Err(AppError::recorder(RecorderError::validation(
+            Some("stream"),
+            "stream must not be empty",
+        )));
+    }
+    let Some(resp) = crate::recorder::trigger(req).await else {
+        return Err(not_initialized());
+    };
+    Ok(Json(resp.map_err(recorder_error)?))
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn trigger_recording(
+    Json(_req): Json<api::recorder::TriggerRequest>,
+) -> crate::result::Result<Json<api::recorder::TriggerResponse>> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    post,
+    path = "/api/recorder/rename-stream",
+    tag = "recorder",
+    params(api::recorder::DryRunQuery),
+    request_body = api::recorder::RenameStreamRequest,
+    responses(
+        (status = 200, description = "Recordings moved, or a `DryRunResponse` with `dry_run=true`", body = api::recorder::RenameStreamResponse),
+        (status = 400, description = "Invalid request", body = api::recorder::RecorderError),
+        (status = 409, description = "Target stream has recordings or is recording", body = api::recorder::RecorderError),
+    )
+)]
+async fn rename_stream(
+    claims: Option<Extension<Claims>>,
+    Query(query): Query<api::recorder::DryRunQuery>,
+    Json(req): Json<api::recorder::RenameStreamRequest>,
+) -> crate::result::Result<Response> {
+    use crate::recorder::RenameOutcome;
+    use api::recorder::RecorderError;
+    use axum::response::IntoResponse;
+
+    req.validate()
+        .map_err(|e| AppError::recorder(RecorderError::validation(None, e)))?;
+    let from = req.from.clone();
+    if query.dry_run {
+        let Some(dry_run) = crate::recorder::preview_rename_stream(&req).await else {
+            return Err(not_initialized());
+        };
+        return dry_run_response(&from, dry_run.map_err(recorder_error)?);
+    }
+    let Some(outcome) = crate::recorder::rename_stream(req, &actor(claims)).await else {
+        return Err(not_initialized());
+    };
+    match outcome.map_err(recorder_error)? {
+        RenameOutcome::Renamed(resp) => Ok(Json(resp).into_response()),
+        RenameOutcome::Conflict(message) => {
+            Err(AppError::recorder(RecorderError::Conflict { message }))
+        }
+    }
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn rename_stream(
+    Json(_req): Json<api::recorder::RenameStreamRequest>,
+) -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    post,
+    path = "/api/recorder/index/restore",
+    tag = "recorder",
+    params(api::recorder::DryRunQuery),
+    request_body(content = Option<api::recorder::RestoreIndexRequest>),
+    responses(
+        (status = 200, description = "Backup installed, or a `DryRunResponse` of the local entries it would drop or roll back with `dry_run=true`", body = api::recorder::RestoreIndexResponse),
+        (status = 404, description = "No such backup", body = String),
+        (status = 409, description = "Local index is newer than the backup, or recordings are running", body = api::recorder::RecorderError),
+    )
+)]
+async fn restore_index(
+    claims: Option<Extension<Claims>>,
+    Query(query): Query<api::recorder::DryRunQuery>,
+    body: Option<Json<api::recorder::RestoreIndexRequest>>,
+) -> crate::result::Result<Response> {
+    use crate::recorder::{DryRun, RestoreOutcome};
+    use api::recorder::RecorderError;
+    use axum::response::IntoResponse;
+
+    // A missing backup is no recording, it keeps the plain 404
+    let req = body.map(|Json(req)| req).unwrap_or_default();
+    if query.dry_run {
+        let Some(dry_run) =
+            crate::recorder::preview_restore_index(req.key.as_deref(), req.force).await
+        else {
+            return Err(not_initialized());
+        };
+        return match dry_run.map_err(recorder_error)? {
+            DryRun::NotFound(reason) => Err(AppError::recording_not_found(reason)),
+            dry_run => dry_run_response("", dry_run),
+        };
+    }
+    let Some(outcome) =
+        crate::recorder::restore_index(req.key.as_deref(), req.force, &actor(claims)).await
+    else {
+        return Err(not_initialized());
+    };
+    match outcome.map_err(recorder_error)? {
+        RestoreOutcome::Restored(resp) => Ok(Json(resp).into_response()),
+        RestoreOutcome::NotFound(reason) => Err(AppError::recording_not_found(reason)),
+        RestoreOutcome::Conflict(message) => {
+            Err(AppError::recorder(RecorderError::Conflict { message }))
+        }
+    }
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn restore_index() -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    post,
+    path = "/api/recorder/import",
+    tag = "recorder",
+    params(api::recorder::DryRunQuery),
+    request_body = api::recorder::ImportRecordingsRequest,
+    responses(
+        (status = 200, description = "Recordings added to the index and dirs skipped, only listed with `dry_run=true`", body = api::recorder::ImportRecordingsResponse),
+        (status = 400, description = "Invalid prefix", body = api::recorder::RecorderError),
+    )
+)]
+async fn import_recordings(
+    Query(query): Query<api::recorder::DryRunQuery>,
+    Json(req): Json<api::recorder::ImportRecordingsRequest>,
+) -> crate::result::Result<Json<api::recorder::ImportRecordingsResponse>> {
+    use api::recorder::RecorderError;
+
+    req.validate()
+        .map_err(|e| AppError::recorder(RecorderError::validation(Some("prefix"), e)))?;
+    let Some(resp) = crate::recorder::import_recordings(&req.prefix, query.dry_run).await else {
+        return Err(not_initialized());
+    };
+    Ok(Json(resp.map_err(recorder_error)?))
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn import_recordings(
+    Json(_req): Json<api::recorder::ImportRecordingsRequest>,
+) -> crate::result::Result<Json<api::recorder::ImportRecordingsResponse>> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/recorder/audit",
+    tag = "recorder",
+    params(api::recorder::AuditQuery),
+    responses((status = 200, description = "Destructive operations, oldest first", body = api::recorder::AuditLogResponse))
+)]
+async fn audit_log(
+    Query(query): Query<api::recorder::AuditQuery>,
+) -> crate::result::Result<Json<api::recorder::AuditLogResponse>> {
+    let Some(records) = crate::recorder::audit_log(query.since_ts).await else {
+        return Err(not_initialized());
+    };
+    Ok(Json(api::recorder::AuditLogResponse {
+        records: records.map_err(recorder_error)?,
+    }))
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn audit_log() -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/recorder/journal/{journal}",
+    tag = "recorder",
+    params(
+        ("journal" = api::recorder::JournalKind, Path, description = "`index` or `audit`"),
+        api::recorder::JournalQuery,
+    ),
+    responses(
+        (status = 200, description = "Raw journal bytes from `offset`, whole lines only. `x-journal-next-offset` is the offset to read from next, `x-journal-generation` changes when the file is compacted or rotated: read again from 0 then. `x-journal-size` is the size of the file read", body = String, content_type = "application/x-ndjson"),
+        (status = 503, description = "Index not initialized", body = api::recorder::RecorderError),
+    )
+)]
+async fn read_journal(
+    Path(journal): Path<api::recorder::JournalKind>,
+    Query(query): Query<api::recorder::JournalQuery>,
+) -> crate::result::Result<Response> {
+    let Some(chunk) = crate::recorder::read_journal(journal, query.offset, query.limit()).await
+    else {
+        return Err(not_initialized());
+    };
+    let chunk = chunk.map_err(recorder_error)?;
+    Ok(Response::builder()
+        .header(http::header::CONTENT_TYPE, api::jsonl::CONTENT_TYPE)
+        .header(http::header::CACHE_CONTROL, "no-store")
+        .header(api::recorder::JOURNAL_GENERATION_HEADER, chunk.generation)
+        .header(
+            api::recorder::JOURNAL_NEXT_OFFSET_HEADER,
+            chunk.next_offset(),
+        )
+        .header(api::recorder::JOURNAL_SIZE_HEADER, chunk.size)
+        .body(axum::body::Body::from(chunk.data))?)
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn read_journal(_path: Path<String>) -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/recorder/stats",
+    tag = "recorder",
+    responses((status = 200, description = "Recorder statistics of the node, cached for a few seconds", body = api::recorder::RecorderStats))
+)]
+async fn recorder_stats() -> crate::result::Result<Json<api::recorder::RecorderStats>> {
+    let stats = crate::recorder::recorder_stats(None)
+        .await
+        .map_err(recorder_error)?;
+    Ok(Json(stats))
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn recorder_stats() -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/recorder/stats/{stream}",
+    tag = "recorder",
+    params(("stream" = String, Path, description = "Stream id")),
+    responses((status = 200, description = "Recorder statistics of the stream, zero without recordings", body = api::recorder::RecorderStats))
+)]
+async fn recorder_stream_stats(
+    NamedPath(stream): NamedPath<String>,
+) -> crate::result::Result<Json<api::recorder::RecorderStats>> {
+    let stats = crate::recorder::recorder_stats(Some(&stream))
+        .await
+        .map_err(recorder_error)?;
+    Ok(Json(stats))
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn recorder_stream_stats() -> crate::result::Result<Response> {
     Err(AppError::Throw("feature recorder not enabled".into()))
 }
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/recorder/health",
+    tag = "recorder",
+    responses((status = 200, description = "Recording integrity of the node, with the reasons of every stream", body = api::recorder::RecorderHealth))
+)]
+async fn recorder_health() -> crate::result::Result<Json<api::recorder::RecorderHealth>> {
+    let health = crate::recorder::recorder_health(None)
+        .await
+        .map_err(recorder_error)?;
+    Ok(Json(health))
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn recorder_health() -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/recorder/health/{stream}",
+    tag = "recorder",
+    params(("stream" = String, Path, description = "Stream id")),
+    responses((status = 200, description = "Recording integrity of the stream, the node's disk included", body = api::recorder::RecorderHealth))
+)]
+async fn recorder_stream_health(
+    NamedPath(stream): NamedPath<String>,
+) -> crate::result::Result<Json<api::recorder::RecorderHealth>> {
+    let health = crate::recorder::recorder_health(Some(&stream))
+        .await
+        .map_err(recorder_error)?;
+    Ok(Json(health))
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn recorder_stream_health() -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/recorder/uploads",
+    tag = "recorder",
+    responses(
+        (status = 200, description = "Upload queue of the node, and its suspension while liveman is unreachable", body = api::recorder::UploadQueueStatus),
+        (status = 400, description = "Uploads are disabled", body = String),
+    )
+)]
+async fn upload_queue() -> crate::result::Result<Json<api::recorder::UploadQueueStatus>> {
+    let Some(status) = crate::recorder::upload_queue_status().await else {
+        return Err(AppError::bad_request(
+            "uploads are disabled, recordings are written to storage directly",
+        ));
+    };
+    Ok(Json(status))
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn upload_queue() -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    post,
+    path = "/api/record/repair/{stream}/{record}",
+    tag = "recorder",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+    ),
+    responses(
+        (status = 200, description = "Manifest rebuilt from the segments present", body = api::recorder::RepairRecordingResponse),
+        (status = 404, description = "Recording not found", body = api::recorder::RecorderError),
+        (status = 409, description = "Recording still being written, or its manifest or init segment is missing", body = api::recorder::RecorderError),
+    )
+)]
+async fn repair_recording(
+    NamedPath((stream, record)): NamedPath<(String, String)>,
+) -> crate::result::Result<Json<api::recorder::RepairRecordingResponse>> {
+    use crate::recorder::RepairOutcome;
+    use api::recorder::RecorderError;
+
+    let Some(outcome) = crate::recorder::repair_recording(&stream, &record).await else {
+        return Err(not_initialized());
+    };
+    match outcome.map_err(recorder_error)? {
+        RepairOutcome::Repaired(resp) => Ok(Json(resp)),
+        RepairOutcome::NotFound => Err(AppError::recorder(RecorderError::not_found(
+            &stream, &record,
+        ))),
+        RepairOutcome::Unrepairable(reason) => Err(AppError::recorder(RecorderError::Conflict {
+            message: format!("recording {stream}/{record} is unrepairable: {reason}"),
+        })),
+        RepairOutcome::Conflict(message) => {
+            Err(AppError::recorder(RecorderError::InvalidTransition {
+                stream,
+                record,
+                message,
+            }))
+        }
+    }
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn repair_recording(
+    NamedPath(_path): NamedPath<(String, String)>,
+) -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    put,
+    path = "/api/record/{stream}/{record}/captions/{lang}",
+    tag = "recorder",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+        ("lang" = String, Path, description = "Language tag, e.g. en or pt-BR"),
+    ),
+    request_body(content = String, description = "WebVTT or SRT captions", content_type = "text/vtt"),
+    responses(
+        (status = 200, description = "Captions stored and listed in the manifest", body = api::recorder::CaptionsResponse),
+        (status = 400, description = "Invalid language, captions neither WebVTT nor SRT, or cues after the end of the recording", body = api::recorder::RecorderError),
+        (status = 404, description = "Recording not found", body = api::recorder::RecorderError),
+    )
+)]
+async fn put_captions(
+    NamedPath((stream, record, lang)): NamedPath<(String, String, String)>,
+    body: String,
+) -> crate::result::Result<Json<api::recorder::CaptionsResponse>> {
+    use crate::recorder::CaptionsOutcome;
+    use api::recorder::RecorderError;
+
+    if !crate::recorder::valid_lang(&lang) {
+        return Err(AppError::recorder(RecorderError::validation(
+            Some("lang"),
+            format!("invalid language tag {lang:?}"),
+        )));
+    }
+    let Some(outcome) = crate::recorder::put_captions(&stream, &record, &lang, &body).await else {
+        return Err(not_initialized());
+    };
+    match outcome.map_err(recorder_error)? {
+        CaptionsOutcome::Stored(resp) => Ok(Json(resp)),
+        CaptionsOutcome::NotFound => Err(AppError::recorder(RecorderError::not_found(
+            &stream, &record,
+        ))),
+        CaptionsOutcome::Invalid(message) => Err(AppError::recorder(RecorderError::validation(
+            Some("captions"),
+            message,
+        ))),
+    }
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn put_captions(
+    NamedPath(_path): NamedPath<(String, String, String)>,
+    _body: String,
+) -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    get,
+    path = "/api/recorder/verify/{stream}/{record}",
+    tag = "recorder",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+        api::recorder::VerifyRecordingQuery,
+    ),
+    responses(
+        (status = 200, description = "Local files compared with stored objects", body = api::recorder::VerifyRecordingResponse),
+        (status = 400, description = "Uploads are disabled", body = String),
+        (status = 404, description = "Recording not found", body = api::recorder::RecorderError),
+    )
+)]
+async fn verify_recording(
+    NamedPath((stream, record)): NamedPath<(String, String)>,
+    Query(query): Query<api::recorder::VerifyRecordingQuery>,
+) -> crate::result::Result<Response> {
+    use axum::response::IntoResponse;
+
+    let Some(result) = crate::recorder::verify_recording(&stream, &record, query.checksum).await
+    else {
+        return Err(AppError::bad_request(
+            "uploads are disabled, this node keeps no local copies to verify",
+        ));
+    };
+    let Some(resp) = result.map_err(recorder_error)? else {
+        return Err(AppError::recorder(api::recorder::RecorderError::not_found(
+            &stream, &record,
+        )));
+    };
+    // Provisional results change with every segment and upload
+    let cache_control = if resp.provisional {
+        "no-store"
+    } else {
+        "private, max-age=60"
+    };
+    Ok(([(http::header::CACHE_CONTROL, cache_control)], Json(resp)).into_response())
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn verify_recording(
+    NamedPath(_path): NamedPath<(String, String)>,
+) -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+#[utoipa::path(
+    post,
+    path = "/api/storage/diagnose",
+    tag = "recorder",
+    responses(
+        (status = 200, description = "Status, latency and error of each storage stage, `ok` is false when one failed", body = Object),
+        (status = 400, description = "Uploads go through liveman, which diagnoses its storage itself", body = String),
+    )
+)]
+async fn diagnose_storage() -> crate::result::Result<Json<storage::DiagnoseReport>> {
+    match crate::recorder::diagnose_storage().await {
+        Some(report) => Ok(Json(report)),
+        None => Err(AppError::bad_request(
+            "storage is not written directly, diagnose it on liveman",
+        )),
+    }
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn diagnose_storage() -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+async fn storage_chaos() -> crate::result::Result<Json<storage::ChaosConfig>> {
+    match crate::recorder::chaos_config().await {
+        Some(config) => Ok(Json(config)),
+        None => Err(AppError::bad_request("storage chaos not enabled")),
+    }
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn storage_chaos() -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(feature = "recorder")]
+async fn update_storage_chaos(
+    Json(config): Json<storage::ChaosConfig>,
+) -> crate::result::Result<Json<storage::ChaosConfig>> {
+    if crate::recorder::set_chaos_config(config.clone()).await {
+        Ok(Json(config))
+    } else {
+        Err(AppError::bad_request("storage chaos not enabled"))
+    }
+}
+
+#[cfg(not(feature = "recorder"))]
+async fn update_storage_chaos() -> crate::result::Result<Response> {
+    Err(AppError::Throw("feature recorder not enabled".into()))
+}
+
+#[cfg(all(test, feature = "recorder"))]
+mod tests {
+    use super::*;
+    use api::recorder::RecorderError;
+    use axum::response::IntoResponse;
+
+    async fn respond(err: AppError) -> (StatusCode, Option<String>, serde_json::Value) {
+        let response = err.into_response();
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(http::header::RETRY_AFTER)
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            retry_after,
+            serde_json::from_slice(&body).unwrap_or_default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_lock_timeout_is_index_busy() {
+        let timeout = crate::recorder::LockTimeout {
+            path: "/var/lib/live777/index.lock".into(),
+            waited: std::time::Duration::from_secs(5),
+            holder: None,
+        };
+        let err = anyhow::Error::from(timeout).context("ack recordings");
+        let (status, retry_after, body) = respond(recorder_error(err)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(retry_after.as_deref(), Some("1"));
+        assert_eq!(body["error"], "index_busy");
+        assert_eq!(body["retry_after_seconds"], 1);
+        let err: RecorderError = serde_json::from_value(body).unwrap();
+        assert!(err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_storage_failure_is_unavailable() {
+        let err = anyhow::Error::from(opendal::Error::new(
+            opendal::ErrorKind::Unexpected,
+            "connection reset",
+        ))
+        .context("write index backup");
+        let (status, retry_after, body) = respond(recorder_error(err)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(retry_after, None);
+        assert_eq!(body["error"], "storage_unavailable");
+
+        let (status, _, body) = respond(not_initialized()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["error"], "storage_unavailable");
+    }
+
+    #[tokio::test]
+    async fn test_recording_errors() {
+        let (status, _, body) = respond(trash_error(
+            "cam",
+            "1700000000",
+            None,
+            crate::recorder::TrashUpdate::NotFound,
+        ))
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            serde_json::from_value::<RecorderError>(body).unwrap(),
+            RecorderError::not_found("cam", "1700000000")
+        );
+
+        let (status, _, body) = respond(trash_error(
+            "cam",
+            "1700000000",
+            None,
+            crate::recorder::TrashUpdate::Conflict("recording is active".to_string()),
+        ))
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"], "invalid_transition");
+        assert_eq!(body["record"], "1700000000");
+
+        let err = anyhow::Error::from(storage::check_key("cam/\u{7f}").unwrap_err());
+        let (status, _, body) = respond(recorder_error(err)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "validation");
+        assert_eq!(body["field"], "stream");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_error_stays_internal() {
+        let (status, _, _) = respond(recorder_error(anyhow::anyhow!("disk full"))).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
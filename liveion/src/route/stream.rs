@@ -25,18 +25,30 @@ pub fn route() -> Router<AppState> {
         .route(api::path::streams_sse(), get(sse))
 }
 
+/// Fill in whether each stream is being recorded, left absent without the recorder
+async fn with_recording(
+    forward_infos: Vec<crate::forward::message::ForwardInfo>,
+) -> Vec<api::response::Stream> {
+    #[cfg_attr(not(feature = "recorder"), allow(unused_mut))]
+    let mut streams: Vec<api::response::Stream> =
+        forward_infos.into_iter().map(Into::into).collect();
+    #[cfg(feature = "recorder")]
+    {
+        let ids: Vec<String> = streams.iter().map(|s| s.id.clone()).collect();
+        let mut recordings = crate::recorder::stream_recordings(&ids).await;
+        for stream in streams.iter_mut() {
+            stream.recording = recordings.remove(&stream.id);
+        }
+    }
+    streams
+}
+
 async fn index(
     State(state): State<AppState>,
     Query(req): Query<api::request::QueryInfo>,
 ) -> crate::result::Result<Json<Vec<api::response::Stream>>> {
     Ok(Json(
-        state
-            .stream_manager
-            .info(req.streams)
-            .await
-            .into_iter()
-            .map(|forward_info| forward_info.into())
-            .collect(),
+        with_recording(state.stream_manager.info(req.streams).await).await,
     ))
 }
 
@@ -44,16 +56,12 @@ async fn show(
     State(state): State<AppState>,
     Path(stream): Path<String>,
 ) -> crate::result::Result<Json<api::response::Stream>> {
-    match state
-        .stream_manager
-        .info(vec![stream.clone()])
+    match with_recording(state.stream_manager.info(vec![stream.clone()]).await)
         .await
         .into_iter()
-        .map(|forward_info| forward_info.into())
-        .collect::<Vec<api::response::Stream>>()
-        .first()
+        .next()
     {
-        Some(stream) => Ok(Json(stream.clone())),
+        Some(stream) => Ok(Json(stream)),
         None => Err(AppError::StreamNotFound(stream.to_string())),
     }
 }
@@ -88,20 +96,85 @@ async fn sse(
 ) -> crate::result::Result<
     Sse<impl tokio_stream::Stream<Item = Result<axum::response::sse::Event, Infallible>>>,
 > {
-    let recv = state
+    let mut recv = state
         .stream_manager
         .sse_handler(req.streams.clone())
         .await?;
-    let stream = ReceiverStream::new(recv).map(|forward_infos| {
-        Ok(Event::default()
-            .json_data(
-                forward_infos
-                    .into_iter()
-                    .map(api::response::Stream::from)
-                    .collect::<Vec<_>>(),
-            )
-            .unwrap())
+    // Looking up recordings is async, convert before the events are built
+    let (tx, streams) = tokio::sync::mpsc::channel(1);
+    tokio::spawn(async move {
+        while let Some(forward_infos) = recv.recv().await {
+            if tx.send(with_recording(forward_infos).await).await.is_err() {
+                break;
+            }
+        }
     });
+    let stream = ReceiverStream::new(streams)
+        .map(|streams| Ok(Event::default().json_data(streams).unwrap()));
     let resp = Sse::new(stream).keep_alive(KeepAlive::default());
     Ok(resp)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forward_info(id: &str) -> crate::forward::message::ForwardInfo {
+        crate::forward::message::ForwardInfo {
+            id: id.to_string(),
+            create_at: 1_700_000_000_000,
+            publish_leave_at: 0,
+            subscribe_leave_at: 0,
+            publish_session_info: None,
+            subscribe_session_infos: Vec::new(),
+            codecs: Vec::new(),
+            has_virtual_publisher: false,
+        }
+    }
+
+    async fn listed(id: &str) -> serde_json::Value {
+        let streams = with_recording(vec![forward_info(id)]).await;
+        serde_json::to_value(&streams[0]).unwrap()
+    }
+
+    #[cfg(feature = "recorder")]
+    #[tokio::test]
+    async fn test_recording_fields() {
+        let json = listed("not-recording").await;
+        assert_eq!(json["recording"], false);
+        for field in ["recordId", "recordingSinceTs", "segmentsWritten"] {
+            assert!(json.get(field).is_none(), "{field}");
+        }
+
+        let recording = api::response::StreamRecording {
+            recording: true,
+            record_id: Some("1700000000".to_string()),
+            recording_since_ts: Some(1_700_000_000_000_000),
+            segments_written: Some(12),
+        };
+        let mut stream: api::response::Stream = serde_json::from_value(json).unwrap();
+        stream.recording = Some(recording.clone());
+        let json = serde_json::to_value(&stream).unwrap();
+        assert_eq!(json["recording"], true);
+        assert_eq!(json["recordId"], "1700000000");
+        assert_eq!(json["segmentsWritten"], 12);
+        let parsed: api::response::Stream = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.recording, Some(recording));
+    }
+
+    #[cfg(not(feature = "recorder"))]
+    #[tokio::test]
+    async fn test_recording_fields_absent_without_recorder() {
+        let json = listed("cam").await;
+        for field in [
+            "recording",
+            "recordId",
+            "recordingSinceTs",
+            "segmentsWritten",
+        ] {
+            assert!(json.get(field).is_none(), "{field}");
+        }
+        let parsed: api::response::Stream = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.recording, None);
+    }
+}
@@ -458,6 +458,7 @@ impl Manager {
                 let stream = match event {
                     Event::Stream(val) => val.stream.stream,
                     Event::Forward(val) => val.stream_info.id,
+                    Event::Recording(_) => continue,
                 };
                 if streams.is_empty() || streams.contains(&stream) {
                     let stream_map = stream_map.read().await;
@@ -617,6 +618,12 @@ impl Manager {
         self.event_sender.subscribe()
     }
 
+    /// Publish an event to webhooks and other event subscribers
+    #[cfg(feature = "recorder")]
+    pub fn send_event(&self, event: Event) {
+        let _ = self.event_sender.send(event);
+    }
+
     #[cfg(feature = "recorder")]
     pub async fn get_forward(&self, stream: &str) -> Option<crate::forward::PeerForward> {
         let map = self.stream_map.read().await;
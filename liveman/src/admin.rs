@@ -30,7 +30,9 @@ pub async fn authorize(
     // Here you can check the user credentials from a database
     let mut user: Option<&Account> = None;
     for account in state.config.auth.accounts.iter() {
-        if payload.username == account.username && payload.password == account.password {
+        if payload.username == account.username
+            && payload.password == account.password.expose_secret()
+        {
             user = Some(account);
         }
     }
@@ -41,7 +43,7 @@ pub async fn authorize(
 
     debug!("User UID: {:?}", user);
 
-    let keys = Keys::new(state.config.auth.secret.as_bytes());
+    let keys = Keys::new(state.config.auth.secret.expose_secret().as_bytes());
     let token = keys
         .token(Claims {
             id: ANY_ID.to_string(),
@@ -50,6 +52,8 @@ pub async fn authorize(
                 .unwrap()
                 .as_secs(),
             mode: 7,
+            streams: None,
+            tenants: None,
         })
         .map_err(|err| {
             error!("Error while encoding: {err}");
@@ -64,7 +68,7 @@ pub async fn token(
     State(state): State<AppState>,
     Json(payload): Json<TokenPayload>,
 ) -> Result<Json<AuthBody>, AuthError> {
-    let keys = Keys::new(state.config.auth.secret.as_bytes());
+    let keys = Keys::new(state.config.auth.secret.expose_secret().as_bytes());
     let token = keys.token(payload.into()).map_err(|err| {
         error!("Error while encoding: {err}");
         AuthError::TokenCreation
@@ -95,6 +99,12 @@ pub struct TokenPayload {
     subscribe: bool,
     publish: bool,
     admin: bool,
+    /// Stream prefixes the token's recordings are limited to, see [`Claims::streams`]
+    #[serde(default)]
+    streams: Option<Vec<String>>,
+    /// Tenants the token's recordings are limited to, see [`Claims::tenants`]
+    #[serde(default)]
+    tenants: Option<Vec<String>>,
 }
 
 impl From<TokenPayload> for Claims {
@@ -111,6 +121,8 @@ impl From<TokenPayload> for Claims {
                 x: v.admin,
             })
             .into(),
+            streams: v.streams,
+            tenants: v.tenants,
         }
     }
 }
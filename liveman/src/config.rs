@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::{env, net::SocketAddr, str::FromStr};
 
+use config_loader::Secret;
 use iceserver::IceServer;
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
@@ -43,6 +44,38 @@ pub struct Config {
     #[cfg(feature = "recorder")]
     #[serde(default)]
     pub recorder: Recorder,
+
+    /// Where the config was loaded from, read again by `POST /api/admin/reload-storage`
+    #[serde(skip)]
+    pub source: Option<ConfigSource>,
+
+    /// `--rebuild-catalog`: forget the record sync cursors, pulling every node from the
+    /// start
+    #[serde(skip)]
+    pub rebuild_catalog: bool,
+}
+
+/// Name and arguments the config was loaded with
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    pub name: String,
+    pub args: config_loader::ConfigArgs,
+}
+
+impl ConfigSource {
+    pub fn new(name: &str, args: config_loader::ConfigArgs) -> Self {
+        Self {
+            name: name.to_string(),
+            args,
+        }
+    }
+
+    /// Merge the config file, environment and overrides again
+    pub fn load(&self) -> Result<Config, config_loader::Error> {
+        config_loader::Loader::new(&self.name)
+            .args(&self.args)
+            .load()
+    }
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Database {
@@ -81,7 +114,7 @@ pub struct Node {
     #[serde(default)]
     pub alias: String,
     #[serde(default)]
-    pub token: String,
+    pub token: Secret,
     #[serde(default)]
     pub url: String,
 }
@@ -103,7 +136,7 @@ pub struct Coturn {
     #[serde(default)]
     pub urls: Vec<String>,
     #[serde(default)]
-    pub secret: String,
+    pub secret: Secret,
     #[serde(default = "default_extra_ttl")]
     pub ttl: u64,
 }
@@ -113,7 +146,7 @@ pub struct Cloudflare {
     #[serde(default)]
     pub key_id: String,
     #[serde(default)]
-    pub api_token: String,
+    pub api_token: Secret,
     #[serde(default = "default_extra_ttl")]
     pub ttl: u64,
 }
@@ -172,14 +205,20 @@ pub struct Http {
     pub cors: bool,
     #[serde(default)]
     pub public: String,
+    /// Reverse proxies whose `Forwarded`/`X-Forwarded-*` headers are honored in absolute URLs
+    #[serde(default)]
+    pub trusted_proxies: forwarded::TrustedProxies,
+    /// Serve Swagger UI for `/api/openapi.json` at `/swagger-ui`
+    #[serde(default)]
+    pub swagger_ui: bool,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Auth {
     #[serde(default)]
-    pub secret: String,
+    pub secret: Secret,
     #[serde(default)]
-    pub tokens: Vec<String>,
+    pub tokens: Vec<Secret>,
     #[serde(default)]
     pub accounts: Vec<Account>,
 }
@@ -189,7 +228,7 @@ pub struct Account {
     #[serde(default)]
     pub username: String,
     #[serde(default)]
-    pub password: String,
+    pub password: Secret,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -230,6 +269,8 @@ impl Default for Http {
             listen: default_http_listen(),
             public: Default::default(),
             cors: Default::default(),
+            trusted_proxies: Default::default(),
+            swagger_ui: Default::default(),
         }
     }
 }
@@ -316,6 +357,10 @@ pub struct Playback {
     /// TTL in seconds for signed URLs (only used if signed_redirect is true)
     #[serde(default = "default_signed_ttl_seconds")]
     pub signed_ttl_seconds: u64,
+
+    /// Days a deleted recording stays in the trash before its objects and row are deleted
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u64,
 }
 
 impl Default for Playback {
@@ -323,6 +368,7 @@ impl Default for Playback {
         Self {
             signed_redirect: default_signed_redirect(),
             signed_ttl_seconds: default_signed_ttl_seconds(),
+            trash_retention_days: default_trash_retention_days(),
         }
     }
 }
@@ -335,11 +381,138 @@ fn default_signed_ttl_seconds() -> u64 {
     60
 }
 
+fn default_trash_retention_days() -> u64 {
+    7
+}
+
 #[cfg(feature = "recorder")]
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Recorder {
     #[serde(default)]
     pub storage: storage::StorageConfig,
+    /// Methods `POST /api/storage/presign` hands out URLs for
+    #[serde(default)]
+    pub presign: PresignPolicy,
+    /// Stages of `POST /api/storage/diagnose`
+    #[serde(default)]
+    pub diagnose: storage::DiagnoseConfig,
+    /// livevod instances told about manifests rewritten by finished uploads and repairs
+    #[serde(default)]
+    pub invalidate: storage::InvalidateConfig,
+    /// Record of the URLs `POST /api/storage/presign` hands out
+    #[serde(default)]
+    pub presign_audit: PresignAuditConfig,
+    /// Objects nodes stream through `PUT /api/storage/objects/{path}`
+    #[serde(default)]
+    pub proxy_upload: ProxyUploadConfig,
+}
+
+/// Limits of uploads proxied for nodes in `upload.mode = "proxy"`, which hold no
+/// presigned URLs. Presign policy and token checks apply to them as to presigns
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyUploadConfig {
+    /// Largest object accepted, bigger ones are refused with `413` (0 accepts any size)
+    #[serde(default = "default_proxy_max_object_bytes")]
+    pub max_object_bytes: u64,
+    /// Uploads of one node at a time, more are refused with `429` and retried by the
+    /// node (0 disables the limit)
+    #[serde(default = "default_proxy_node_concurrency")]
+    pub node_concurrency: usize,
+    /// Bytes per second each node uploads at most, over all its uploads (0 disables
+    /// the limit)
+    #[serde(default)]
+    pub node_bytes_per_second: u64,
+    /// Bytes buffered per write to storage, S3 gets objects larger than this as a
+    /// multipart upload of parts this size
+    #[serde(default = "default_proxy_chunk_bytes")]
+    pub chunk_bytes: usize,
+}
+
+#[cfg(feature = "recorder")]
+impl Default for ProxyUploadConfig {
+    fn default() -> Self {
+        Self {
+            max_object_bytes: default_proxy_max_object_bytes(),
+            node_concurrency: default_proxy_node_concurrency(),
+            node_bytes_per_second: 0,
+            chunk_bytes: default_proxy_chunk_bytes(),
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_proxy_max_object_bytes() -> u64 {
+    5 << 30
+}
+
+#[cfg(feature = "recorder")]
+fn default_proxy_node_concurrency() -> usize {
+    4
+}
+
+#[cfg(feature = "recorder")]
+fn default_proxy_chunk_bytes() -> usize {
+    8 << 20
+}
+
+/// Where presigns are recorded, the URLs themselves never are
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresignAuditConfig {
+    /// JSONL file appended per presign, none when empty
+    #[serde(default)]
+    pub path: String,
+    /// Size past which the file is rotated to `{stem}.1.jsonl`, 0 never rotates
+    #[serde(default = "default_presign_audit_max_bytes")]
+    pub max_bytes: u64,
+    /// Rotated files kept
+    #[serde(default = "default_presign_audit_keep")]
+    pub keep: usize,
+    /// Presigns kept in memory for `GET /api/storage/presign-audit`
+    #[serde(default = "default_presign_audit_buffer")]
+    pub buffer: usize,
+    /// Also log each presign at info level with the `presign_audit` target
+    #[serde(default)]
+    pub tracing: bool,
+}
+
+#[cfg(feature = "recorder")]
+impl Default for PresignAuditConfig {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            max_bytes: default_presign_audit_max_bytes(),
+            keep: default_presign_audit_keep(),
+            buffer: default_presign_audit_buffer(),
+            tracing: false,
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_presign_audit_max_bytes() -> u64 {
+    16 * 1024 * 1024
+}
+
+#[cfg(feature = "recorder")]
+fn default_presign_audit_keep() -> usize {
+    5
+}
+
+#[cfg(feature = "recorder")]
+fn default_presign_audit_buffer() -> usize {
+    10_000
+}
+
+/// `GET`, `HEAD`, `PUT` and `TAGGING` are always allowed, destructive methods need a
+/// grant
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct PresignPolicy {
+    /// Presign `DELETE` of recording objects, shared objects are never deleted
+    #[serde(default)]
+    pub allow_delete: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -377,6 +550,10 @@ pub struct RecordSync {
     pub tick_ms: u64,
     #[serde(default = "default_record_sync_limit")]
     pub limit: u32,
+    /// Follow each node's recorder event stream and sync on change, polling only
+    /// nodes whose stream is unavailable
+    #[serde(default = "default_record_sync_events")]
+    pub events: bool,
 }
 
 impl Default for RecordSync {
@@ -385,6 +562,7 @@ impl Default for RecordSync {
             enabled: false,
             tick_ms: default_record_sync_tick(),
             limit: default_record_sync_limit(),
+            events: default_record_sync_events(),
         }
     }
 }
@@ -397,6 +575,10 @@ fn default_record_sync_limit() -> u32 {
     200
 }
 
+fn default_record_sync_events() -> bool {
+    true
+}
+
 fn default_auto_record_tick() -> u64 {
     5_000
 }
@@ -1 +1,2 @@
+pub mod record_sync_cursors;
 pub mod recordings;
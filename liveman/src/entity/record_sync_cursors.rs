@@ -0,0 +1,19 @@
+use sea_orm::entity::prelude::*;
+
+/// Where record sync stands with each node, kept across restarts
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "record_sync_cursors")]
+pub struct Model {
+    /// Alias of the node
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub node: String,
+    /// `updated_at` of the newest entry synced and acked on the node, the next pull
+    /// starts after it
+    pub since_ts: i64,
+    pub updated_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
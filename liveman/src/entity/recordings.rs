@@ -10,6 +10,29 @@ pub struct Model {
     pub mpd_path: String,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
+    /// `updated_at` of the liveion index entry last applied by push ingest
+    pub source_updated_at: Option<i64>,
+    /// JSON array of the recording's track formats, see `api::recorder::MediaInfo`
+    pub media_info: Option<String>,
+    /// Retention class of the recording, see `api::recorder::RetentionClass`
+    pub retention_class: Option<String>,
+    /// When the recording was moved to the trash (UNIX microseconds)
+    pub trashed_at: Option<i64>,
+    /// Alias of the node that recorded it, empty for rows from before nodes were told apart
+    pub node: String,
+    /// Priority of the recording on its node, see `api::recorder::RecordingIndexEntry`
+    pub priority: i16,
+    /// Stable id the node gave the recording, see `api::recorder::RecordingIndexEntry`.
+    /// Unrelated to `id`, `None` until a pull or push brings it
+    pub recording_uuid: Option<String>,
+    /// Tenant the node derived from the stream name, see `api::recorder::RecordingIndexEntry`
+    pub tenant: Option<String>,
+    /// When the recording was archived (UNIX microseconds): its node is gone, it stays
+    /// playable from storage but node sync and fan-out leave it alone
+    pub archived_at: Option<i64>,
+    /// `seq` of the liveion index entry last applied by push ingest, `None` for rows
+    /// only pull sync wrote and for nodes predating it
+    pub source_seq: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -7,6 +7,9 @@ pub enum AppError {
     RequestProxyError,
     ResourceNotFound,
     ResourceAlreadyExists,
+    BadRequest(String),
+    /// A node's typed recorder error, or one liveman raises on its behalf
+    Recorder(api::recorder::RecorderError),
     InternalServerError(anyhow::Error),
 }
 
@@ -30,6 +33,12 @@ impl IntoResponse for AppError {
             AppError::ResourceAlreadyExists => {
                 (StatusCode::CONFLICT, "resource already exists".to_string()).into_response()
             }
+            AppError::BadRequest(reason) => (StatusCode::BAD_REQUEST, reason).into_response(),
+            AppError::Recorder(err) => {
+                let status = StatusCode::from_u16(err.status_code())
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                (status, axum::Json(err)).into_response()
+            }
         }
     }
 }
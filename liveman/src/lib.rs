@@ -1,4 +1,4 @@
-use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
+use std::{future::Future, sync::Arc, time::Duration};
 
 use auth::{AuthState, access::access_middleware, validate_middleware};
 use axum::{Router, extract::Request, middleware, response::IntoResponse, routing::post};
@@ -9,7 +9,10 @@ use tracing::{error, info, info_span};
 
 use crate::admin::{authorize, token};
 use crate::config::Config;
+use crate::service::dashboard::DashboardHub;
 use crate::service::database::DatabaseService;
+use crate::service::lease::LeaseTable;
+use crate::service::sync_cursors::SyncCursors;
 use crate::store::{Node, NodeKind, Storage};
 
 #[cfg(feature = "webui")]
@@ -39,14 +42,32 @@ where
     let database_service = DatabaseService::new(&cfg.database)
         .await
         .expect("Failed to initialize database connection");
+    let record_sync_cursors = SyncCursors::load(database_service.get_connection())
+        .await
+        .expect("Failed to load record sync cursors");
+    if cfg.rebuild_catalog {
+        match record_sync_cursors
+            .reset(database_service.get_connection())
+            .await
+        {
+            Ok(n) => info!(
+                "Rebuilding the catalog: {} nodes are synced from the start",
+                n
+            ),
+            Err(e) => error!("Failed to reset record sync cursors: {}", e),
+        }
+    }
 
     // Initialize file storage operator if recorder feature is enabled
     #[cfg(feature = "recorder")]
     let file_storage = if cfg!(feature = "recorder") {
-        match storage::init_operator(&cfg.recorder.storage).await {
+        match storage::init_failover_operator(&cfg.recorder.storage).await {
             Ok(operator) => {
                 info!("File storage initialized successfully");
-                Some(operator)
+                Some(service::file_storage::FileStorage::new(
+                    cfg.recorder.storage.clone(),
+                    operator,
+                ))
             }
             Err(e) => {
                 error!(
@@ -92,10 +113,10 @@ where
     let store = Storage::new(client_mem.build().unwrap());
     let nodes = store.get_map_nodes_mut();
     for v in cfg.nodes.clone() {
-        nodes
-            .write()
-            .unwrap()
-            .insert(v.alias, Node::new(v.token, NodeKind::Static, v.url));
+        nodes.write().unwrap().insert(
+            v.alias,
+            Node::new(v.token.expose_secret().to_string(), NodeKind::Static, v.url),
+        );
     }
 
     #[cfg(feature = "net4mqtt")]
@@ -173,9 +194,21 @@ where
         client: client_req.build().unwrap(),
         storage: store,
         database: database_service,
-        record_sync_cursor: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
+        record_sync_cursors: Arc::new(record_sync_cursors),
+        dashboard: Arc::new(DashboardHub::default()),
+        leases: Arc::new(LeaseTable::default()),
+        #[cfg(feature = "recorder")]
+        file_storage: service::file_storage::FileStorageHandle::new(file_storage),
+        #[cfg(feature = "recorder")]
+        invalidator: storage::Invalidator::spawn(cfg.recorder.invalidate.clone()),
         #[cfg(feature = "recorder")]
-        file_storage,
+        presign_audit: Arc::new(service::presign_audit::PresignAudit::new(
+            cfg.recorder.presign_audit.clone(),
+        )),
+        #[cfg(feature = "recorder")]
+        object_proxy: Arc::new(service::object_proxy::ObjectProxy::new(
+            cfg.recorder.proxy_upload.clone(),
+        )),
     };
 
     let app = Router::new()
@@ -184,9 +217,10 @@ where
                 .route("/api/token", post(token))
                 .layer(middleware::from_fn(access_middleware))
                 .layer(middleware::from_fn_with_state(
-                    AuthState::new(cfg.auth.secret, cfg.auth.tokens),
+                    AuthState::new(&cfg.auth.secret, &cfg.auth.tokens),
                     validate_middleware,
-                )),
+                ))
+                .layer(middleware::from_fn(route::recorder::ws_token_from_query)),
         )
         .layer(if cfg.http.cors {
             CorsLayer::permissive()
@@ -194,6 +228,20 @@ where
             CorsLayer::new()
         })
         .route("/api/login", post(authorize))
+        .route(
+            api::path::OPENAPI,
+            axum::routing::get(route::openapi::openapi),
+        )
+        .merge(route::recorder::ingest_route());
+    let app = if cfg.http.swagger_ui {
+        app.merge(
+            utoipa_swagger_ui::SwaggerUi::new(api::path::SWAGGER_UI)
+                .config(utoipa_swagger_ui::Config::from(api::path::OPENAPI)),
+        )
+    } else {
+        app
+    };
+    let app = app
         .with_state(app_state.clone())
         .layer(axum::middleware::from_fn(http_log::print_request_response))
         .layer(
@@ -222,10 +270,15 @@ where
 
     tokio::spawn(tick::record_sync(app_state.clone()));
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(signal)
-        .await
-        .unwrap_or_else(|e| error!("Application error: {e}"));
+    tokio::spawn(tick::trash_purge(app_state.clone()));
+
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(signal)
+    .await
+    .unwrap_or_else(|e| error!("Application error: {e}"));
 }
 
 #[cfg(feature = "webui")]
@@ -254,7 +307,30 @@ struct AppState {
     client: reqwest::Client,
     storage: Storage,
     database: DatabaseService,
-    record_sync_cursor: Arc<tokio::sync::RwLock<HashMap<String, i64>>>,
+    /// Where pull sync stands with each node, persisted in the catalog database
+    record_sync_cursors: Arc<SyncCursors>,
+    /// Recorder progress for `GET /api/ws/recorder`
+    dashboard: Arc<DashboardHub>,
+    /// Which node auto-records each stream, see `POST /api/recorder/lease`
+    leases: Arc<LeaseTable>,
+    /// Swapped by `POST /api/admin/reload-storage`, take one snapshot per request
+    #[cfg(feature = "recorder")]
+    file_storage: service::file_storage::FileStorageHandle,
+    /// Tells livevod about manifests the nodes finished uploading
+    #[cfg(feature = "recorder")]
+    invalidator: Option<storage::Invalidator>,
+    /// Presigns handed out, see `GET /api/storage/presign-audit`
     #[cfg(feature = "recorder")]
-    file_storage: Option<opendal::Operator>,
+    presign_audit: Arc<service::presign_audit::PresignAudit>,
+    /// Per-node limits of uploads streamed through `PUT /api/storage/objects/{path}`
+    #[cfg(feature = "recorder")]
+    object_proxy: Arc<service::object_proxy::ObjectProxy>,
+}
+
+/// Rules of [`api::extract::NamedPath`]. Nodes may record with tenancy, so stream names
+/// may hold `/`: a name a node records always passes, the node refuses the others
+impl axum::extract::FromRef<AppState> for api::recorder::NameRules {
+    fn from_ref(_: &AppState) -> Self {
+        Self::with_separators()
+    }
 }
@@ -0,0 +1,83 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Recordings::Table)
+                    .add_column(
+                        ColumnDef::new(Recordings::Node)
+                            .string()
+                            .not_null()
+                            .default(""),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Nodes recording the same stream name at the same second keep their own rows
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_recordings_stream_date")
+                    .table(Recordings::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_recordings_stream_record_node")
+                    .table(Recordings::Table)
+                    .col(Recordings::Stream)
+                    .col(Recordings::Record)
+                    .col(Recordings::Node)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_recordings_stream_record_node")
+                    .table(Recordings::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_recordings_stream_date")
+                    .table(Recordings::Table)
+                    .col(Recordings::Stream)
+                    .col(Recordings::Record)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Recordings::Table)
+                    .drop_column(Recordings::Node)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Recordings {
+    Table,
+    Stream,
+    Record,
+    Node,
+}
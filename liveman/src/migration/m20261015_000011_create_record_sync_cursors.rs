@@ -0,0 +1,93 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RecordSyncCursors::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RecordSyncCursors::Node)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(RecordSyncCursors::SinceTs)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RecordSyncCursors::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Listings by node, and by stream in `updated_at` order
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_recordings_node")
+                    .table(Recordings::Table)
+                    .col(Recordings::Node)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_recordings_stream_updated_at")
+                    .table(Recordings::Table)
+                    .col(Recordings::Stream)
+                    .col(Recordings::UpdatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_recordings_stream_updated_at")
+                    .table(Recordings::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_recordings_node")
+                    .table(Recordings::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(RecordSyncCursors::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum RecordSyncCursors {
+    Table,
+    Node,
+    SinceTs,
+    UpdatedAt,
+}
+
+#[derive(Iden)]
+enum Recordings {
+    Table,
+    Stream,
+    Node,
+    UpdatedAt,
+}
@@ -1,14 +1,36 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20250810_000001_create_recordings_index_table;
+mod m20251015_000002_add_recordings_source_updated_at;
+mod m20261015_000003_add_recordings_media_info;
+mod m20261015_000004_add_recordings_retention_class;
+mod m20261015_000005_add_recordings_trashed_at;
+mod m20261015_000006_add_recordings_node;
+mod m20261015_000007_add_recordings_priority;
+mod m20261015_000008_add_recordings_recording_uuid;
+mod m20261015_000009_add_recordings_tenant;
+mod m20261015_000010_add_recordings_archived_at;
+mod m20261015_000011_create_record_sync_cursors;
+mod m20261015_000012_add_recordings_source_seq;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(
-            m20250810_000001_create_recordings_index_table::Migration,
-        )]
+        vec![
+            Box::new(m20250810_000001_create_recordings_index_table::Migration),
+            Box::new(m20251015_000002_add_recordings_source_updated_at::Migration),
+            Box::new(m20261015_000003_add_recordings_media_info::Migration),
+            Box::new(m20261015_000004_add_recordings_retention_class::Migration),
+            Box::new(m20261015_000005_add_recordings_trashed_at::Migration),
+            Box::new(m20261015_000006_add_recordings_node::Migration),
+            Box::new(m20261015_000007_add_recordings_priority::Migration),
+            Box::new(m20261015_000008_add_recordings_recording_uuid::Migration),
+            Box::new(m20261015_000009_add_recordings_tenant::Migration),
+            Box::new(m20261015_000010_add_recordings_archived_at::Migration),
+            Box::new(m20261015_000011_create_record_sync_cursors::Migration),
+            Box::new(m20261015_000012_add_recordings_source_seq::Migration),
+        ]
     }
 }
@@ -0,0 +1,109 @@
+//! JSON Lines bodies of listings too large to build in memory, see [`api::jsonl`].
+//!
+//! A producer task encodes the items while the client reads them, with at most
+//! [`CHUNKS_AHEAD`] chunks waiting for a slow client.
+
+use std::convert::Infallible;
+use std::fmt::Display;
+
+use axum::body::Body;
+use axum::response::Response;
+use http::header;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+
+const CHUNKS_AHEAD: usize = 4;
+
+pub type Chunks = mpsc::Sender<Result<Vec<u8>, Infallible>>;
+
+/// Response streaming what `produce` sends, `produce` runs in its own task
+pub fn response<F, Fut>(produce: F) -> Response
+where
+    F: FnOnce(Chunks) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(CHUNKS_AHEAD);
+    tokio::spawn(produce(tx));
+    Response::builder()
+        .header(header::CONTENT_TYPE, api::jsonl::CONTENT_TYPE)
+        .body(Body::from_stream(ReceiverStream::new(rx)))
+        .unwrap()
+}
+
+/// Send `items` one per line, the first failed item ends the listing with an error line
+pub async fn send<S, T, E>(items: S, tx: Chunks)
+where
+    S: Stream<Item = std::result::Result<T, E>>,
+    T: Serialize,
+    E: Display,
+{
+    let mut encoder = api::jsonl::Encoder::new();
+    let mut items = std::pin::pin!(items);
+    while let Some(item) = items.next().await {
+        let pushed = match item {
+            Ok(item) => encoder.push(&item).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match pushed {
+            Ok(Some(chunk)) => {
+                // The client went away
+                if tx.send(Ok(chunk)).await.is_err() {
+                    return;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                tracing::warn!("listing failed midway: {}", e);
+                let _ = tx.send(Ok(encoder.error(e))).await;
+                return;
+            }
+        }
+    }
+    if let Some(chunk) = encoder.finish() {
+        let _ = tx.send(Ok(chunk)).await;
+    }
+}
+
+/// End a listing that failed before its first item
+pub async fn fail(error: impl Display, tx: Chunks) {
+    tracing::warn!("listing failed: {}", error);
+    let _ = tx.send(Ok(api::jsonl::Encoder::new().error(error))).await;
+}
+
+/// Every line of a JSON Lines response
+#[cfg(test)]
+pub async fn lines(response: Response) -> Vec<serde_json::Value> {
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    body.split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_error_midway_ends_with_error_line() {
+        let items: Vec<std::result::Result<u32, &str>> =
+            vec![Ok(1), Ok(2), Err("connection reset"), Ok(4)];
+        let response = response(move |tx| send(tokio_stream::iter(items), tx));
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            api::jsonl::CONTENT_TYPE
+        );
+        assert_eq!(
+            lines(response).await,
+            [
+                serde_json::json!(1),
+                serde_json::json!(2),
+                serde_json::json!({ "error": "connection reset" }),
+            ]
+        );
+    }
+}
@@ -1,5 +1,7 @@
 pub mod cascade;
+mod jsonl;
 pub mod node;
+pub mod openapi;
 pub mod proxy;
 pub mod recorder;
 #[cfg(feature = "recorder")]
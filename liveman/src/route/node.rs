@@ -1,6 +1,7 @@
 use axum::{Json, extract::State};
 use serde::{Deserialize, Serialize};
 
+use api::recorder::{RecorderCapabilities, RecordingCapacity};
 use api::strategy::Strategy;
 
 use crate::{AppState, result::Result};
@@ -22,6 +23,12 @@ pub struct Node {
     duration: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     strategy: Option<Strategy>,
+    /// Recordings running at the last record sync, against `max_concurrent_recordings`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recordings: Option<RecordingCapacity>,
+    /// Recorder features the node reported, absent for nodes that don't report them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capabilities: Option<RecorderCapabilities>,
 }
 
 pub async fn index(State(mut state): State<AppState>) -> Result<Json<Vec<Node>>> {
@@ -32,6 +39,7 @@ pub async fn index(State(mut state): State<AppState>) -> Result<Json<Vec<Node>>>
             .get_map_nodes()
             .into_iter()
             .map(|(alias, node)| Node {
+                recordings: state.dashboard.recording_capacity(&alias),
                 alias,
                 url: node.url,
                 status: match node.strategy {
@@ -39,6 +47,7 @@ pub async fn index(State(mut state): State<AppState>) -> Result<Json<Vec<Node>>>
                     None => NodeState::Stopped,
                 },
                 strategy: node.strategy,
+                capabilities: node.capabilities,
                 duration: match node.duration {
                     Some(s) => format!("{}ms", s.as_millis()),
                     None => "-".to_string(),
@@ -0,0 +1,54 @@
+use axum::Json;
+use utoipa::OpenApi;
+
+/// Recorder, playback and storage HTTP API, schemas are derived from the types the
+/// handlers use
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "liveman"),
+    tags(
+        (name = "recorder", description = "Recording sessions across the cluster"),
+        (name = "playback", description = "Recording catalog and stored objects"),
+        (name = "storage", description = "Presigned access to recording storage"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Spec of the routes compiled into this build
+pub fn spec() -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    doc.merge(super::recorder::RecorderApi::openapi());
+    #[cfg(feature = "recorder")]
+    doc.merge(super::storage::StorageApi::openapi());
+    doc
+}
+
+pub async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(spec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fails when the generated spec drifts from `openapi.json`,
+    /// run with `UPDATE_OPENAPI=1` to accept the change
+    #[cfg(feature = "recorder")]
+    #[test]
+    fn test_openapi_snapshot() {
+        let mut doc = spec();
+        // Version bumps alone should not touch the snapshot
+        doc.info.version = String::new();
+        let generated = doc.to_pretty_json().unwrap() + "\n";
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("openapi.json");
+        if std::env::var_os("UPDATE_OPENAPI").is_some() || !path.exists() {
+            std::fs::write(&path, &generated).unwrap();
+            return;
+        }
+        let snapshot = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            snapshot, generated,
+            "OpenAPI spec changed, run `UPDATE_OPENAPI=1 cargo test` and commit openapi.json"
+        );
+    }
+}
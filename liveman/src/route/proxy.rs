@@ -101,7 +101,7 @@ async fn extra_ice(
 
     if let Some(cfg) = cfg.coturn {
         let (username, password) = coturn::generate_credentials(
-            cfg.secret,
+            cfg.secret.expose_secret().to_string(),
             coturn::generate_expiry_timestamp(cfg.ttl),
             None,
         );
@@ -114,8 +114,12 @@ async fn extra_ice(
     }
 
     if let Some(cfg) = cfg.cloudflare {
-        let cloudflare_ice_servers =
-            cloudflare::request_iceserver(cfg.key_id, cfg.api_token, cfg.ttl).await?;
+        let cloudflare_ice_servers = cloudflare::request_iceserver(
+            cfg.key_id,
+            cfg.api_token.expose_secret().to_string(),
+            cfg.ttl,
+        )
+        .await?;
         debug!("cloudflare ice server {:?}", cloudflare_ice_servers);
 
         for link in link_header(cloudflare_ice_servers) {
@@ -1,6 +1,6 @@
 use axum::{
     Router,
-    extract::{Path, State},
+    extract::{Path, RawPathParams, State},
     http::StatusCode,
     response::{IntoResponse, Json, Response},
     routing::{get, post},
@@ -8,6 +8,12 @@ use axum::{
 use axum_extra::extract::Query;
 use http::header;
 
+use api::extract::NamedPath;
+use api::recorder::capability;
+
+use super::jsonl;
+use crate::service::dashboard::{DashboardEvent, DashboardFilter};
+use crate::service::recordings_index::RecordingsIndexService;
 use crate::{AppState, result::Result};
 
 pub fn route() -> Router<AppState> {
@@ -21,12 +27,504 @@ pub fn route() -> Router<AppState> {
                 .delete(stop_record),
         )
         .route("/api/record/object/{*path}", get(get_segment))
+        .route("/api/record/by-id/{uuid}", get(get_by_recording_uuid))
+        .route(
+            "/api/record/{stream}/{record}",
+            axum::routing::delete(delete_recording),
+        )
+        .route(
+            "/api/record/{stream}/{record}/restore",
+            post(restore_recording),
+        )
+        .route(api::path::recorder_rename_stream(), post(rename_stream))
+        .route(api::path::recorder_ws(), get(recorder_ws))
+        .route(api::path::recorder_stats(), get(recorder_stats))
+        .route(api::path::recorder_health(), get(recorder_health))
+        .route(api::path::recorder_leases(), get(list_leases))
+        .route(api::path::recordings_orphaned(), get(list_orphaned))
+        .route(
+            api::path::recordings_orphaned_archive(),
+            post(archive_orphaned),
+        )
+        .route(
+            api::path::recordings_orphaned_delete(),
+            post(delete_orphaned),
+        )
+}
+
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(
+    list_index_streams,
+    list_index_by_stream,
+    get_by_recording_uuid,
+    start_record,
+    get_record_status,
+    stop_record,
+    delete_recording,
+    restore_recording,
+    list_orphaned,
+    archive_orphaned,
+    delete_orphaned,
+    get_segment,
+    rename_stream,
+    ingest,
+    recorder_ws,
+    recorder_stats,
+    recorder_health,
+    lease,
+    list_leases,
+))]
+pub struct RecorderApi;
+
+/// Push ingest from liveion nodes, authenticated with the node token instead of
+/// liveman's own auth
+pub fn ingest_route() -> Router<AppState> {
+    Router::new()
+        .route(api::path::recorder_ingest(), post(ingest))
+        .route(api::path::recorder_lease(), post(lease))
+}
+
+/// Whether `headers` carry the token liveman has configured for the node `alias`
+fn node_authorized(state: &AppState, headers: &http::HeaderMap, alias: &str) -> bool {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    state
+        .storage
+        .get_map_nodes()
+        .get(alias)
+        .is_some_and(|node| !node.token.is_empty() && Some(node.token.as_str()) == token)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/recorder/ingest",
+    tag = "recorder",
+    request_body = api::recorder::IngestRecordingsRequest,
+    responses(
+        (status = 200, description = "Events applied to the catalog", body = api::recorder::IngestRecordingsResponse),
+        (status = 401, description = "Unknown node or token", body = String),
+    )
+)]
+async fn ingest(
+    State(state): State<AppState>,
+    headers: http::HeaderMap,
+    Json(req): Json<api::recorder::IngestRecordingsRequest>,
+) -> Result<Response> {
+    if !node_authorized(&state, &headers, &req.node_alias) {
+        tracing::warn!(node = %req.node_alias, "recorder ingest rejected");
+        return Ok((StatusCode::UNAUTHORIZED, "unknown node or token").into_response());
+    }
+
+    let db = state.database.get_connection();
+    let mut resp = api::recorder::IngestRecordingsResponse::default();
+    for event in req.events {
+        // Deleting from the node index never removes a recording from the catalog
+        if matches!(event.kind, api::recorder::RecorderEventKind::Deleted) {
+            resp.skipped += 1;
+            continue;
+        }
+        #[cfg(feature = "recorder")]
+        if let Some(invalidator) = state.invalidator.as_ref()
+            && !matches!(event.kind, api::recorder::RecorderEventKind::Created)
+        {
+            // Finalization and repairs rewrite these, segments are never rewritten
+            invalidator.notify(&event.entry.mpd_path);
+            invalidator.notify(&format!(
+                "{}/{}",
+                event.entry.record_dir,
+                api::recorder::SEGMENTS_FILENAME
+            ));
+        }
+        if matches!(event.kind, api::recorder::RecorderEventKind::Uploaded) {
+            state.dashboard.publish(DashboardEvent::Upload {
+                node: Some(req.node_alias.clone()),
+                stream: event.entry.stream.clone(),
+                record: event.entry.record.clone(),
+                objects: 0,
+                done: true,
+            });
+        }
+        if crate::service::recordings_index::RecordingsIndexService::apply_pushed(
+            db,
+            &req.node_alias,
+            &event.entry,
+        )
+        .await?
+        {
+            resp.applied += 1;
+            let entry = event.entry;
+            state.dashboard.publish(DashboardEvent::Status {
+                node: req.node_alias.clone(),
+                stream: entry.stream,
+                record: entry.record,
+                status: entry.status,
+                at: chrono::Utc::now().timestamp_micros(),
+            });
+        } else {
+            resp.skipped += 1;
+        }
+    }
+    tracing::debug!(
+        node = %req.node_alias,
+        applied = resp.applied,
+        skipped = resp.skipped,
+        "recorder ingest"
+    );
+    Ok(Json(resp).into_response())
+}
+
+/// Interval of WebSocket pings, below the idle timeout of common proxies
+const WS_HEARTBEAT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Initial subscription of `GET /api/ws/recorder`, comma-separated
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DashboardQuery {
+    /// Only events of these streams (health events always pass)
+    stream: Option<String>,
+    /// Only events of these nodes
+    node: Option<String>,
 }
 
-async fn get_segment(State(state): State<AppState>, Path(path): Path<String>) -> Result<Response> {
+/// Browsers cannot set headers on WebSocket requests, so the dashboard socket also
+/// accepts its token as `?token=`
+pub async fn ws_token_from_query(
+    mut req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    if req.uri().path() == api::path::recorder_ws()
+        && !req.headers().contains_key(header::AUTHORIZATION)
+        && let Some(token) = req.uri().query().and_then(|q| {
+            url::form_urlencoded::parse(q.as_bytes())
+                .find(|(k, _)| k == "token")
+                .map(|(_, v)| v.into_owned())
+        })
+        && let Ok(value) = http::HeaderValue::from_str(&format!("Bearer {token}"))
+    {
+        req.headers_mut().insert(header::AUTHORIZATION, value);
+    }
+    next.run(req).await
+}
+
+/// Stream recorder status, upload progress and node health to a dashboard
+#[utoipa::path(
+    get,
+    path = "/api/ws/recorder",
+    tag = "recorder",
+    params(DashboardQuery),
+    responses(
+        (status = 101, description = "WebSocket of JSON events, a text message `{\"streams\": [], \"nodes\": []}` replaces the subscription"),
+    )
+)]
+async fn recorder_ws(
+    State(state): State<AppState>,
+    Query(q): Query<DashboardQuery>,
+    ws: axum::extract::ws::WebSocketUpgrade,
+) -> Response {
+    let split = |v: Option<String>| -> Vec<String> {
+        v.map(|v| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+    };
+    let filter = DashboardFilter {
+        streams: split(q.stream),
+        nodes: split(q.node),
+    };
+    let events = state.dashboard.subscribe();
+    ws.on_upgrade(move |socket| dashboard_socket(socket, events, filter))
+}
+
+async fn dashboard_socket(
+    mut socket: axum::extract::ws::WebSocket,
+    mut events: tokio::sync::broadcast::Receiver<DashboardEvent>,
+    mut filter: DashboardFilter,
+) {
+    use axum::extract::ws::Message;
+    use tokio::sync::broadcast::error::RecvError;
+
+    let mut heartbeat = tokio::time::interval(WS_HEARTBEAT);
+    heartbeat.tick().await;
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if filter.matches(&event) => event,
+                Ok(_) => continue,
+                // The oldest events were dropped while this socket was slow
+                Err(RecvError::Lagged(dropped)) => DashboardEvent::Lagged { dropped },
+                Err(RecvError::Closed) => break,
+            },
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    match serde_json::from_str::<DashboardFilter>(text.as_str()) {
+                        Ok(f) => filter = f,
+                        Err(e) => tracing::debug!(error = ?e, "ignored dashboard subscription"),
+                    }
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            _ = heartbeat.tick() => {
+                if socket.send(Message::Ping(Default::default())).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+        let Ok(json) = serde_json::to_string(&event) else {
+            continue;
+        };
+        if socket.send(Message::Text(json.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Outcome of a rename on one node
+#[derive(serde::Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum NodeRename {
+    Renamed(api::recorder::RenameStreamResponse),
+    Failed { status: Option<u16>, error: String },
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+struct RenameStreamResponse {
+    nodes: std::collections::BTreeMap<String, NodeRename>,
+    /// Catalog rows moved, only once every node succeeded
+    catalog_renamed: usize,
+}
+
+/// Rename a stream's recordings on every node, then in the catalog.
+///
+/// Nodes that already finished have nothing left under `from`, so a failed fan-out
+/// is retried by sending the same request again.
+#[utoipa::path(
+    post,
+    path = "/api/recorder/rename-stream",
+    tag = "recorder",
+    request_body = api::recorder::RenameStreamRequest,
+    responses(
+        (status = 200, description = "Renamed on every node and in the catalog", body = RenameStreamResponse),
+        (status = 409, description = "Target stream is in use on some node", body = RenameStreamResponse),
+        (status = 502, description = "Rename failed on some node", body = RenameStreamResponse),
+    )
+)]
+async fn rename_stream(
+    State(state): State<AppState>,
+    Json(req): Json<api::recorder::RenameStreamRequest>,
+) -> Result<Response> {
+    req.validate().map_err(crate::error::AppError::BadRequest)?;
+
+    let mut nodes = std::collections::BTreeMap::new();
+    let mut code = StatusCode::OK;
+    for server in state.storage.get_cluster() {
+        if let Err(err) = require_capability(&state, &server.alias, capability::RENAME_STREAM) {
+            code = StatusCode::BAD_GATEWAY;
+            nodes.insert(
+                server.alias.clone(),
+                NodeRename::Failed {
+                    status: Some(StatusCode::NOT_IMPLEMENTED.as_u16()),
+                    error: err.message().to_string(),
+                },
+            );
+            continue;
+        }
+        let url = format!("{}{}", server.url, api::path::recorder_rename_stream());
+        let result = match state
+            .client
+            .post(url)
+            .header(header::AUTHORIZATION, format!("Bearer {}", server.token))
+            .json(&req)
+            .send()
+            .await
+        {
+            Ok(resp) if resp.status().is_success() => {
+                match resp.json::<api::recorder::RenameStreamResponse>().await {
+                    Ok(renamed) => NodeRename::Renamed(renamed),
+                    Err(e) => NodeRename::Failed {
+                        status: None,
+                        error: e.to_string(),
+                    },
+                }
+            }
+            Ok(resp) => {
+                let status = resp.status();
+                if status == StatusCode::CONFLICT {
+                    code = StatusCode::CONFLICT;
+                }
+                NodeRename::Failed {
+                    status: Some(status.as_u16()),
+                    error: resp.text().await.unwrap_or_default(),
+                }
+            }
+            Err(e) => NodeRename::Failed {
+                status: None,
+                error: e.to_string(),
+            },
+        };
+        if matches!(result, NodeRename::Failed { .. }) && code == StatusCode::OK {
+            code = StatusCode::BAD_GATEWAY;
+        }
+        nodes.insert(server.alias.clone(), result);
+    }
+
+    let catalog_renamed = if code == StatusCode::OK {
+        crate::service::recordings_index::RecordingsIndexService::rename_stream(
+            state.database.get_connection(),
+            &req.from,
+            &req.to,
+            req.copy_objects,
+        )
+        .await?
+    } else {
+        tracing::warn!(from = %req.from, to = %req.to, "stream rename failed on some nodes");
+        0
+    };
+    Ok((
+        code,
+        Json(RenameStreamResponse {
+            nodes,
+            catalog_renamed,
+        }),
+    )
+        .into_response())
+}
+
+/// Acquire, renew or release the recording lease of a stream for a node about to
+/// auto-record it
+#[utoipa::path(
+    post,
+    path = "/api/recorder/lease",
+    tag = "recorder",
+    request_body = api::recorder::RecordingLeaseRequest,
+    responses(
+        (status = 200, description = "Whether the node holds the lease, and the current lease", body = api::recorder::RecordingLeaseResponse),
+        (status = 401, description = "Unknown node or token", body = String),
+    )
+)]
+async fn lease(
+    State(state): State<AppState>,
+    headers: http::HeaderMap,
+    Json(req): Json<api::recorder::RecordingLeaseRequest>,
+) -> Result<Response> {
+    if !node_authorized(&state, &headers, &req.node_alias) {
+        tracing::warn!(node = %req.node_alias, "recording lease rejected");
+        return Ok((StatusCode::UNAUTHORIZED, "unknown node or token").into_response());
+    }
+    let resp = if req.release {
+        state.leases.release(&req.stream, &req.node_alias)
+    } else {
+        let now = chrono::Utc::now().timestamp_micros();
+        let resp = state
+            .leases
+            .acquire(&req.stream, &req.node_alias, req.ttl_seconds, now);
+        if let Some(lease) = resp.lease.as_ref().filter(|l| l.acquired_at == now) {
+            tracing::info!(stream = %lease.stream, node = %lease.holder, "recording lease granted");
+        }
+        resp
+    };
+    Ok(Json(resp).into_response())
+}
+
+/// Unexpired recording leases, which node auto-records each stream
+#[utoipa::path(
+    get,
+    path = "/api/recorder/leases",
+    tag = "recorder",
+    responses((status = 200, description = "Leases by stream", body = [api::recorder::RecordingLease]))
+)]
+async fn list_leases(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<api::recorder::RecordingLease>>> {
+    Ok(Json(
+        state.leases.list(chrono::Utc::now().timestamp_micros()),
+    ))
+}
+
+/// Recorder stats of the cluster, summed from what the nodes reported at their last
+/// record sync instead of asking every node
+#[utoipa::path(
+    get,
+    path = "/api/recorder/stats",
+    tag = "recorder",
+    responses((status = 200, description = "Stats of every node and their sum", body = api::recorder::ClusterRecorderStats))
+)]
+async fn recorder_stats(
+    State(state): State<AppState>,
+) -> Result<Json<api::recorder::ClusterRecorderStats>> {
+    let mut cluster = api::recorder::ClusterRecorderStats::default();
+    for server in state.storage.get_cluster() {
+        if let Some(stats) = state.dashboard.recorder_stats(&server.alias) {
+            cluster.total.add(&stats);
+            cluster.nodes.insert(server.alias, stats);
+        }
+    }
+    Ok(Json(cluster))
+}
+
+/// Recording integrity of the cluster, from the rollups the nodes reported at their
+/// last record sync
+#[utoipa::path(
+    get,
+    path = "/api/recorder/health",
+    tag = "recorder",
+    responses((status = 200, description = "Rollup of every node, the worst status among them and the upload lag of the cluster", body = api::recorder::ClusterRecorderHealth))
+)]
+async fn recorder_health(
+    State(state): State<AppState>,
+) -> Result<Json<api::recorder::ClusterRecorderHealth>> {
+    let mut cluster = api::recorder::ClusterRecorderHealth::default();
+    for server in state.storage.get_cluster() {
+        if let Some(health) = state.dashboard.recorder_health(&server.alias) {
+            cluster.status = cluster.status.max(health.status);
+            if let Some(lag) = &health.upload_lag {
+                cluster.upload_lag.get_or_insert_default().merge(lag);
+            }
+            cluster.nodes.insert(server.alias, health);
+        }
+    }
+    Ok(Json(cluster))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/record/object/{path}",
+    tag = "playback",
+    params(("path" = String, Path, description = "Object path in storage")),
+    responses(
+        (status = 200, description = "Object bytes", content_type = "application/octet-stream"),
+        (status = 307, description = "Presigned redirect when `playback.signed_redirect` is set"),
+        (status = 400, description = "Path is not a valid object key once percent-decoded", body = String),
+        (status = 404, description = "Object not found", body = String),
+        (status = 503, description = "Storage not configured", body = String),
+    )
+)]
+async fn get_segment(
+    State(state): State<AppState>,
+    axum::extract::ConnectInfo(peer): axum::extract::ConnectInfo<std::net::SocketAddr>,
+    claims: Option<axum::Extension<auth::claims::Claims>>,
+    headers: http::HeaderMap,
+    params: RawPathParams,
+) -> Result<Response> {
     #[cfg(feature = "recorder")]
     {
-        if let Some(ref operator) = state.file_storage {
+        // Decoded exactly once, the key as livevod and the uploader spell it
+        let raw = params
+            .iter()
+            .find_map(|(name, value)| (name == "path").then_some(value))
+            .unwrap_or_default();
+        let path = ::storage::decode_key_from_path(raw)
+            .map_err(|e| crate::error::AppError::BadRequest(e.to_string()))?;
+        if let Some(storage) = state.file_storage.get() {
+            let operator = storage.operator.current();
             // Always proxy MPD manifest itself to keep relative segment URLs under our domain
             let is_mpd = path.ends_with(".mpd");
 
@@ -37,7 +535,23 @@ async fn get_segment(State(state): State<AppState>, Path(path): Path<String>) ->
                 let ttl = StdDuration::from_secs(state.config.playback.signed_ttl_seconds.max(1));
                 match operator.presign_read(&path, ttl).await {
                     Ok(req) => {
-                        let uri = req.uri().to_string();
+                        let audit = crate::route::storage::audit_entry(
+                            &state,
+                            claims.as_deref(),
+                            &headers,
+                            peer,
+                            "GET",
+                            &path,
+                            ttl.as_secs(),
+                        );
+                        state.presign_audit.record(audit).await;
+                        let uri = crate::route::storage::public_url(
+                            &state,
+                            &storage.config,
+                            &headers,
+                            peer,
+                            req.uri().to_string(),
+                        );
                         return Ok((StatusCode::TEMPORARY_REDIRECT, [(header::LOCATION, uri)])
                             .into_response());
                     }
@@ -92,68 +606,258 @@ async fn get_segment(State(state): State<AppState>, Path(path): Path<String>) ->
     {
         // Avoid unused variable warnings
         let _ = state;
-        let _ = path;
+        let _ = params;
+        let _ = (peer, claims, headers);
         Ok((StatusCode::NOT_IMPLEMENTED, "Recorder feature not enabled").into_response())
     }
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, utoipa::ToSchema)]
 struct RecordingIndexEntry {
+    stream: String,
     record: String,
+    /// Stable id the node gave the recording, kept across stream renames
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uuid: Option<String>,
     mpd_path: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    media_info: Vec<api::recorder::MediaInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retention_class: Option<String>,
+    /// Upload and quota deletion order on its node, higher goes first and is deleted last
+    priority: u8,
+    /// UNIX microseconds the recording was moved to the trash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trashed_at: Option<i64>,
+    /// Node that recorded it, tells apart recordings of one stream started at the
+    /// same second on different nodes
+    #[serde(skip_serializing_if = "String::is_empty")]
+    node: String,
+    /// Tenant the node derived from the stream name, the first segment of the
+    /// recording's object keys
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    /// UNIX microseconds the recording was archived after its node left the cluster
+    #[serde(skip_serializing_if = "Option::is_none")]
+    archived_at: Option<i64>,
+    /// `updated_at` of the entry on its node as last synced, the `if_match` of a delete
+    /// or restore
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated_at: Option<i64>,
+}
+
+impl From<crate::entity::recordings::Model> for RecordingIndexEntry {
+    fn from(m: crate::entity::recordings::Model) -> Self {
+        Self {
+            media_info: crate::service::recordings_index::decode_media_info(&m),
+            stream: m.stream,
+            record: m.record,
+            uuid: m.recording_uuid,
+            mpd_path: m.mpd_path,
+            retention_class: m.retention_class,
+            priority: u8::try_from(m.priority).unwrap_or(api::recorder::DEFAULT_PRIORITY),
+            trashed_at: m.trashed_at,
+            node: m.node,
+            tenant: m.tenant,
+            archived_at: m.archived_at,
+            updated_at: m.source_updated_at,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Default, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListStreamsQuery {
+    /// Also count streams whose recordings are all in the trash
+    #[serde(default)]
+    include_trashed: bool,
+    /// Only streams with recordings of this node
+    node_alias: Option<String>,
 }
 
-async fn list_index_streams(State(state): State<AppState>) -> Result<Json<Vec<String>>> {
+#[utoipa::path(
+    get,
+    path = "/api/playback",
+    tag = "playback",
+    params(ListStreamsQuery),
+    responses((status = 200, description = "Streams with recordings", body = Vec<String>))
+)]
+async fn list_index_streams(
+    State(state): State<AppState>,
+    Query(q): Query<ListStreamsQuery>,
+) -> Result<Json<Vec<String>>> {
     use crate::entity::recordings::{self, Entity as Recordings};
-    use sea_orm::{EntityTrait, QuerySelect};
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QuerySelect};
     let db = state.database.get_connection();
-    let streams: Vec<String> = Recordings::find()
+    let mut query = Recordings::find()
         .select_only()
         .column(recordings::Column::Stream)
-        .distinct()
-        .into_tuple()
-        .all(db)
-        .await?;
+        .distinct();
+    if !q.include_trashed {
+        query = query.filter(recordings::Column::TrashedAt.is_null());
+    }
+    if let Some(node) = q.node_alias.as_deref() {
+        query = query.filter(recordings::Column::Node.eq(node));
+    }
+    let streams: Vec<String> = query.into_tuple().all(db).await?;
     Ok(Json(streams))
 }
 
+/// Paging parameters, listing is unpaged when none is given
+#[derive(serde::Deserialize, Default, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListIndexQuery {
+    order: Option<api::recorder::ListOrder>,
+    limit: Option<usize>,
+    cursor: Option<String>,
+    /// Also list recordings in the trash
+    #[serde(default)]
+    include_trashed: bool,
+    /// Only recordings of this node
+    node_alias: Option<String>,
+    /// `jsonl` streams one recording per line, like `Accept: application/x-ndjson`
+    format: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/playback/{stream}",
+    tag = "playback",
+    params(("stream" = String, Path, description = "Stream id"), ListIndexQuery),
+    responses(
+        (status = 200, description = "Recordings of the stream, `x-next-cursor` carries the next page. As JSON Lines one per line, a listing that fails midway ends with an `{\"error\": ...}` line", content(
+            (Vec<RecordingIndexEntry> = "application/json"),
+            (RecordingIndexEntry = "application/x-ndjson"),
+        )),
+        (status = 400, description = "Invalid cursor", body = String),
+    )
+)]
 async fn list_index_by_stream(
     State(state): State<AppState>,
-    Path(stream): Path<String>,
-) -> Result<Json<Vec<RecordingIndexEntry>>> {
+    NamedPath(stream): NamedPath<String>,
+    Query(q): Query<ListIndexQuery>,
+    headers: http::HeaderMap,
+) -> Result<Response> {
     use crate::entity::recordings::{self, Entity as Recordings};
+    use api::recorder::{ListCursor, NEXT_CURSOR_HEADER, page_by};
     use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+    let paged = q.order.is_some() || q.limit.is_some() || q.cursor.is_some();
+    let accept = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok());
+    let as_jsonl = api::jsonl::requested(accept, q.format.as_deref());
+    let order = q.order.unwrap_or_default();
+    let cursor = q
+        .cursor
+        .as_deref()
+        .map(|c| ListCursor::decode(c, order))
+        .transpose()
+        .map_err(crate::error::AppError::BadRequest)?;
+
     let db = state.database.get_connection();
-    let rows = Recordings::find()
-        .filter(recordings::Column::Stream.eq(stream))
-        .all(db)
-        .await?;
-    let entries = rows
-        .into_iter()
-        .map(|m| RecordingIndexEntry {
-            record: m.record,
-            mpd_path: m.mpd_path,
-        })
-        .collect();
-    Ok(Json(entries))
+    let mut query = Recordings::find().filter(recordings::Column::Stream.eq(stream));
+    if !q.include_trashed {
+        query = query.filter(recordings::Column::TrashedAt.is_null());
+    }
+    if let Some(node) = q.node_alias.as_deref() {
+        query = query.filter(recordings::Column::Node.eq(node));
+    }
+    if as_jsonl && !paged {
+        return Ok(stream_index(db.clone(), query));
+    }
+    let mut rows = query.all(db).await?;
+    let mut next_cursor = None;
+    if paged {
+        let limit = q.limit.filter(|l| *l > 0).unwrap_or(100);
+        (rows, next_cursor) = page_by(rows, order, cursor.as_ref(), limit, |m| {
+            (
+                m.updated_at.timestamp_micros(),
+                format!("{}/{}", m.stream, m.record),
+            )
+        });
+    }
+
+    let entries: Vec<RecordingIndexEntry> = rows.into_iter().map(Into::into).collect();
+    let mut response = if as_jsonl {
+        let entries =
+            tokio_stream::iter(entries.into_iter().map(Ok::<_, std::convert::Infallible>));
+        jsonl::response(move |tx| jsonl::send(entries, tx))
+    } else {
+        Json(entries).into_response()
+    };
+    if let Some(next) = next_cursor {
+        response.headers_mut().insert(
+            NEXT_CURSOR_HEADER,
+            header::HeaderValue::from_bytes(next.encode().as_bytes())?,
+        );
+    }
+    Ok(response)
+}
+
+/// Rows of `query` as JSON Lines, read from the database while the client consumes them
+fn stream_index(
+    db: sea_orm::DatabaseConnection,
+    query: sea_orm::Select<crate::entity::recordings::Entity>,
+) -> Response {
+    use tokio_stream::StreamExt;
+
+    jsonl::response(move |tx| async move {
+        match query.stream(&db).await {
+            Ok(rows) => jsonl::send(rows.map(|row| row.map(RecordingIndexEntry::from)), tx).await,
+            Err(e) => jsonl::fail(e, tx).await,
+        }
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/record/by-id/{uuid}",
+    tag = "playback",
+    params(("uuid" = String, Path, description = "Recording uuid given by its node")),
+    responses(
+        (status = 200, description = "Catalog row of the recording", body = RecordingIndexEntry),
+        (status = 404, description = "No recording with that uuid in the catalog"),
+    )
+)]
+async fn get_by_recording_uuid(
+    State(state): State<AppState>,
+    Path(uuid): Path<String>,
+) -> Result<Json<RecordingIndexEntry>> {
+    RecordingsIndexService::find_by_recording_uuid(state.database.get_connection(), &uuid)
+        .await?
+        .map(|row| Json(row.into()))
+        .ok_or(crate::error::AppError::ResourceNotFound)
 }
 
 // ---- Manual start & status proxy ----
 
-#[derive(serde::Deserialize, Default)]
+#[derive(serde::Deserialize, Default, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 struct StartRecordQuery {
     node: Option<String>,
+    /// Overrides the retention class the node's auto-record rules assign
+    retention_class: Option<api::recorder::RetentionClass>,
+    /// Overrides the priority the node's auto-record rules assign
+    priority: Option<u8>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, utoipa::ToSchema)]
 struct StartRecordResponse {
     started: bool,
     mpd_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retention_class: Option<api::recorder::RetentionClass>,
+    priority: u8,
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/record/{stream}",
+    tag = "recorder",
+    params(("stream" = String, Path, description = "Stream id"), StartRecordQuery),
+    responses((status = 200, description = "Recording started on a node", body = StartRecordResponse))
+)]
 async fn start_record(
     State(mut state): State<AppState>,
-    Path(stream): Path<String>,
+    NamedPath(stream): NamedPath<String>,
     Query(q): Query<StartRecordQuery>,
 ) -> Result<Json<StartRecordResponse>> {
     // Choose target server
@@ -169,6 +873,8 @@ async fn start_record(
     };
 
     let server = target_server.ok_or(crate::error::AppError::NoAvailableNode)?;
+    require_capability(&state, &server.alias, capability::RECORD)
+        .map_err(crate::error::AppError::Recorder)?;
 
     // Build base_dir using configured base_prefix + current timestamp
     let requested_ts = crate::utils::timestamp_dir();
@@ -179,7 +885,11 @@ async fn start_record(
         Some(format!("{base_prefix}/{requested_ts}"))
     };
 
-    let body = api::recorder::StartRecordRequest { base_dir };
+    let body = api::recorder::StartRecordRequest {
+        base_dir,
+        retention_class: q.retention_class,
+        priority: q.priority,
+    };
     let url = format!("{}{}", server.url, api::path::record(&stream));
     let resp = state
         .client
@@ -203,6 +913,8 @@ async fn start_record(
 
     let mut record_ts = String::new();
     let mut mpd_path = fallback_mpd_path;
+    let mut retention_class = None;
+    let mut priority = q.priority.unwrap_or(api::recorder::DEFAULT_PRIORITY);
 
     if let Ok(v) = resp.json::<api::recorder::StartRecordResponse>().await {
         if !v.mpd_path.is_empty() {
@@ -211,6 +923,8 @@ async fn start_record(
         if !v.record_id.is_empty() {
             record_ts = v.record_id;
         }
+        retention_class = v.retention_class;
+        priority = v.priority;
     }
 
     if record_ts.is_empty() {
@@ -222,6 +936,7 @@ async fn start_record(
     // Parse date from record metadata and upsert index
     if let Err(err) = crate::service::recordings_index::RecordingsIndexService::upsert(
         state.database.get_connection(),
+        &server.alias,
         &stream,
         &record_ts,
         &mpd_path,
@@ -230,21 +945,57 @@ async fn start_record(
     {
         tracing::error!("{}", err);
     };
+    if let Some(class) = retention_class.as_ref()
+        && let Err(err) =
+            crate::service::recordings_index::RecordingsIndexService::set_retention_class(
+                state.database.get_connection(),
+                &server.alias,
+                &stream,
+                &record_ts,
+                class,
+            )
+            .await
+    {
+        tracing::error!("{}", err);
+    }
+    if let Err(err) = crate::service::recordings_index::RecordingsIndexService::set_priority(
+        state.database.get_connection(),
+        &server.alias,
+        &stream,
+        &record_ts,
+        priority,
+    )
+    .await
+    {
+        tracing::error!("{}", err);
+    }
 
     Ok(Json(StartRecordResponse {
         started: true,
         mpd_path,
+        retention_class,
+        priority,
     }))
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, utoipa::ToSchema)]
 struct RecordStatusResponse {
     recording: bool,
+    /// Node allowed to auto-record the stream, absent without a lease
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lease: Option<api::recorder::RecordingLease>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/record/{stream}",
+    tag = "recorder",
+    params(("stream" = String, Path, description = "Stream id")),
+    responses((status = 200, description = "Whether any node records the stream", body = RecordStatusResponse))
+)]
 async fn get_record_status(
     State(mut state): State<AppState>,
-    Path(stream): Path<String>,
+    NamedPath(stream): NamedPath<String>,
 ) -> Result<Json<RecordStatusResponse>> {
     let streams = state.storage.stream_all().await;
     let map_server = state.storage.get_map_server();
@@ -270,12 +1021,22 @@ async fn get_record_status(
             }
         }
     }
-    Ok(Json(RecordStatusResponse { recording }))
+    let lease = state
+        .leases
+        .get(&stream, chrono::Utc::now().timestamp_micros());
+    Ok(Json(RecordStatusResponse { recording, lease }))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/record/{stream}",
+    tag = "recorder",
+    params(("stream" = String, Path, description = "Stream id")),
+    responses((status = 200, description = "Whether a recording was stopped", body = Object))
+)]
 async fn stop_record(
     State(mut state): State<AppState>,
-    Path(stream): Path<String>,
+    NamedPath(stream): NamedPath<String>,
 ) -> Result<Json<serde_json::Value>> {
     let streams = state.storage.stream_all().await;
     let map_server = state.storage.get_map_server();
@@ -317,3 +1078,453 @@ async fn stop_record(
     }
     Ok(Json(serde_json::json!({ "stopped": any_stopped })))
 }
+
+/// Refuse a call `node` reported no support for with [`RecorderError::Unsupported`]
+/// rather than forwarding it into a 404. Nodes that reported no capabilities, older
+/// ones included, are tried.
+///
+/// [`RecorderError::Unsupported`]: api::recorder::RecorderError::Unsupported
+fn require_capability(
+    state: &AppState,
+    node: &str,
+    feature: &str,
+) -> std::result::Result<(), api::recorder::RecorderError> {
+    match state
+        .storage
+        .get_map_nodes()
+        .get(node)
+        .and_then(|node| node.capabilities.as_ref())
+    {
+        Some(capabilities) => capabilities.require(feature, node),
+        None => Ok(()),
+    }
+}
+
+/// Forward a trash, purge or restore to `node`, or to every node when it is not known,
+/// returning whether some node applied it. `Err` carries the body of a node refusing
+/// with `409 Conflict`, see [`conflict`].
+///
+/// Nodes drop synced recordings from their index, so `404` is the common answer and
+/// unreachable nodes are only logged: the catalog decides for what it has synced.
+async fn fan_out(
+    state: &AppState,
+    node: Option<&str>,
+    method: reqwest::Method,
+    path: &str,
+) -> std::result::Result<bool, String> {
+    let mut applied = false;
+    let servers = state.storage.get_cluster();
+    for server in servers
+        .iter()
+        .filter(|s| node.is_none_or(|node| s.alias == node))
+    {
+        match state
+            .client
+            .request(method.clone(), format!("{}{}", server.url, path))
+            .header(header::AUTHORIZATION, format!("Bearer {}", server.token))
+            .send()
+            .await
+        {
+            Ok(resp) if resp.status().is_success() => applied = true,
+            Ok(resp) if resp.status() == StatusCode::NOT_FOUND => {}
+            Ok(resp) if resp.status() == StatusCode::CONFLICT => {
+                return Err(resp.text().await.unwrap_or_default());
+            }
+            Ok(resp) => {
+                tracing::warn!(node = %server.alias, status = %resp.status(), %path, "recording fan-out failed");
+            }
+            Err(e) => {
+                tracing::warn!(node = %server.alias, error = ?e, %path, "recording fan-out failed");
+            }
+        }
+    }
+    Ok(applied)
+}
+
+/// A node's `409 Conflict` body passed on, typed when it is a [`RecorderError`]
+///
+/// [`RecorderError`]: api::recorder::RecorderError
+fn conflict(body: String) -> Response {
+    match serde_json::from_str::<api::recorder::RecorderError>(&body) {
+        Ok(err) => crate::error::AppError::Recorder(err).into_response(),
+        Err(_) => (StatusCode::CONFLICT, body).into_response(),
+    }
+}
+
+/// `if_match` refused by the catalog for a recording no node answered for
+fn catalog_version_mismatch(
+    row: &crate::entity::recordings::Model,
+    if_match: Option<i64>,
+) -> std::result::Result<(), crate::error::AppError> {
+    match if_match {
+        Some(version) if row.source_updated_at != Some(version) => Err(
+            crate::error::AppError::Recorder(api::recorder::RecorderError::Conflict {
+                message: format!(
+                    "recording {}/{} is at version {}, not {version}",
+                    row.stream,
+                    row.record,
+                    row.source_updated_at.unwrap_or_default()
+                ),
+            }),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Delete a catalog row together with the objects under its record directory.
+///
+/// Without storage access only the row is dropped.
+pub(crate) async fn purge(
+    state: &AppState,
+    row: crate::entity::recordings::Model,
+) -> anyhow::Result<()> {
+    use crate::entity::recordings::Entity as Recordings;
+    use sea_orm::EntityTrait;
+    #[cfg(feature = "recorder")]
+    {
+        if let Some(storage) = state.file_storage.get() {
+            let record_dir = std::path::Path::new(&row.mpd_path)
+                .parent()
+                .map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let deleted = storage::delete_prefix(&storage.operator.current(), &record_dir).await?;
+            tracing::info!(stream = %row.stream, record = %row.record, deleted, "recording purged");
+        } else {
+            tracing::warn!(stream = %row.stream, record = %row.record, "storage not configured, dropping catalog row only");
+        }
+    }
+    #[cfg(not(feature = "recorder"))]
+    tracing::warn!(stream = %row.stream, record = %row.record, "recorder feature not enabled, dropping catalog row only");
+
+    Recordings::delete_by_id(row.id)
+        .exec(state.database.get_connection())
+        .await?;
+    Ok(())
+}
+
+#[derive(serde::Deserialize, Default, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RecordingQuery {
+    /// Delete the objects right away instead of moving the recording to the trash
+    #[serde(default)]
+    permanent: bool,
+    /// Node that recorded it, required when several nodes recorded the same stream
+    /// at the same second
+    node: Option<String>,
+    /// Only delete it while its `updated_at` is this one, passed on to its node
+    if_match: Option<i64>,
+}
+
+#[derive(serde::Deserialize, Default, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct NodeQuery {
+    /// Node that recorded it, required when several nodes recorded the same stream
+    /// at the same second
+    node: Option<String>,
+    /// Only restore it while its `updated_at` is this one, passed on to its node
+    if_match: Option<i64>,
+}
+
+/// Catalog row of `stream/record`, of `node` when given.
+///
+/// Fails when several nodes recorded it and `node` is not given.
+async fn select_row(
+    db: &sea_orm::DatabaseConnection,
+    stream: &str,
+    record: &str,
+    node: Option<&str>,
+) -> Result<Option<crate::entity::recordings::Model>> {
+    let mut rows = RecordingsIndexService::find_all(db, stream, record).await?;
+    if let Some(node) = node {
+        rows.retain(|row| row.node == node);
+    }
+    if rows.len() > 1 {
+        let nodes: Vec<_> = rows.iter().map(|row| row.node.as_str()).collect();
+        return Err(crate::error::AppError::BadRequest(format!(
+            "recording {stream}/{record} exists on nodes {}, pass ?node=",
+            nodes.join(", ")
+        )));
+    }
+    Ok(rows.pop())
+}
+
+/// Move a recording to the trash on its node and in the catalog, or purge it right
+/// away with `permanent=true`
+#[utoipa::path(
+    delete,
+    path = "/api/record/{stream}/{record}",
+    tag = "recorder",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+        RecordingQuery,
+    ),
+    responses(
+        (status = 200, description = "Recording moved to the trash", body = RecordingIndexEntry),
+        (status = 202, description = "Applied on a node, not in the catalog yet"),
+        (status = 204, description = "Recording and its objects deleted"),
+        (status = 400, description = "Recorded on several nodes, `node` is required", body = String),
+        (status = 404, description = "Recording not found", body = String),
+        (status = 409, description = "Recording is active on a node, or `if_match` is not its `updated_at`", body = api::recorder::RecorderError),
+    )
+)]
+async fn delete_recording(
+    State(state): State<AppState>,
+    NamedPath((stream, record)): NamedPath<(String, String)>,
+    Query(q): Query<RecordingQuery>,
+) -> Result<Response> {
+    let db = state.database.get_connection();
+    let row = select_row(db, &stream, &record, q.node.as_deref()).await?;
+    // The node of an archived recording is gone, only the catalog and storage have it
+    let archived = row.as_ref().is_some_and(|row| row.archived_at.is_some());
+    let node = q
+        .node
+        .as_deref()
+        .or(row.as_ref().map(|row| row.node.as_str()))
+        .filter(|node| !node.is_empty());
+    let applied = if archived {
+        false
+    } else {
+        if let Some(node) = node {
+            require_capability(&state, node, capability::TRASH)
+                .map_err(crate::error::AppError::Recorder)?;
+        }
+        let mut path = api::path::record_entry(&stream, &record);
+        let query: Vec<String> = [
+            q.permanent.then(|| "permanent=true".to_string()),
+            q.if_match.map(|version| format!("if_match={version}")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if !query.is_empty() {
+            path = format!("{path}?{}", query.join("&"));
+        }
+        match fan_out(&state, node, reqwest::Method::DELETE, &path).await {
+            Ok(applied) => applied,
+            Err(body) => return Ok(conflict(body)),
+        }
+    };
+    let Some(row) = row else {
+        // Not synced yet, the catalog learns about it from the node
+        if applied {
+            return Ok(StatusCode::ACCEPTED.into_response());
+        }
+        return Err(crate::error::AppError::ResourceNotFound);
+    };
+    // The node checked it when it still had the entry
+    if !applied {
+        catalog_version_mismatch(&row, q.if_match)?;
+    }
+    if q.permanent {
+        purge(&state, row).await?;
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+    let row = RecordingsIndexService::trash(db, row).await?;
+    Ok(Json(RecordingIndexEntry::from(row)).into_response())
+}
+
+/// Take a recording out of the trash on its node and in the catalog
+#[utoipa::path(
+    post,
+    path = "/api/record/{stream}/{record}/restore",
+    tag = "recorder",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+        NodeQuery,
+    ),
+    responses(
+        (status = 200, description = "Recording restored", body = RecordingIndexEntry),
+        (status = 400, description = "Recorded on several nodes, `node` is required", body = String),
+        (status = 404, description = "Recording not in the catalog", body = String),
+        (status = 409, description = "Recording is not in the trash, or `if_match` is not its `updated_at`", body = String),
+    )
+)]
+async fn restore_recording(
+    State(state): State<AppState>,
+    NamedPath((stream, record)): NamedPath<(String, String)>,
+    Query(q): Query<NodeQuery>,
+) -> Result<Response> {
+    let db = state.database.get_connection();
+    let Some(row) = select_row(db, &stream, &record, q.node.as_deref()).await? else {
+        return Err(crate::error::AppError::ResourceNotFound);
+    };
+    if row.trashed_at.is_none() {
+        return Ok((StatusCode::CONFLICT, "recording is not in the trash").into_response());
+    }
+    // A node that never trashed it answers 409 as well, only the catalog decides here
+    // unless the node's entry changed since the caller read it
+    let mut applied = false;
+    if row.archived_at.is_none() {
+        let mut path = api::path::record_restore(&stream, &record);
+        if let Some(version) = q.if_match {
+            path = format!("{path}?if_match={version}");
+        }
+        match fan_out(
+            &state,
+            Some(row.node.as_str()).filter(|node| !node.is_empty()),
+            reqwest::Method::POST,
+            &path,
+        )
+        .await
+        {
+            Ok(node_applied) => applied = node_applied,
+            Err(body) => {
+                if let Ok(err @ api::recorder::RecorderError::VersionMismatch { .. }) =
+                    serde_json::from_str(&body)
+                {
+                    return Err(crate::error::AppError::Recorder(err));
+                }
+            }
+        }
+    }
+    if !applied {
+        catalog_version_mismatch(&row, q.if_match)?;
+    }
+    let row = RecordingsIndexService::restore(db, row).await?;
+    Ok(Json(RecordingIndexEntry::from(row)).into_response())
+}
+
+// ---- Recordings of nodes no longer registered ----
+
+#[derive(serde::Deserialize, Default, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct OrphanedQuery {
+    /// Only recordings of this node
+    node_alias: Option<String>,
+    /// Also list recordings already archived
+    #[serde(default)]
+    include_archived: bool,
+}
+
+#[derive(serde::Deserialize, Default, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct OrphanedActionQuery {
+    /// Only recordings of this node
+    node_alias: Option<String>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+struct OrphanedActionResponse {
+    /// Recordings archived or deleted
+    count: usize,
+}
+
+/// Orphaned catalog rows: recorded by a node that is no longer registered
+async fn orphaned_rows(
+    state: &AppState,
+    node: Option<&str>,
+    include_archived: bool,
+) -> Result<Vec<crate::entity::recordings::Model>> {
+    let registered: Vec<String> = state.storage.get_map_nodes().into_keys().collect();
+    Ok(RecordingsIndexService::orphaned(
+        state.database.get_connection(),
+        &registered,
+        node,
+        include_archived,
+    )
+    .await?)
+}
+
+/// Recordings whose node is no longer registered, such as a decommissioned one
+#[utoipa::path(
+    get,
+    path = "/api/recordings/orphaned",
+    tag = "recorder",
+    params(OrphanedQuery),
+    responses((status = 200, description = "Orphaned recordings", body = Vec<RecordingIndexEntry>))
+)]
+async fn list_orphaned(
+    State(state): State<AppState>,
+    Query(q): Query<OrphanedQuery>,
+) -> Result<Json<Vec<RecordingIndexEntry>>> {
+    let rows = orphaned_rows(&state, q.node_alias.as_deref(), q.include_archived).await?;
+    Ok(Json(rows.into_iter().map(Into::into).collect()))
+}
+
+/// Archive the orphaned recordings: they stay playable from storage, node sync and
+/// fan-out leave them alone
+#[utoipa::path(
+    post,
+    path = "/api/recordings/orphaned/archive",
+    tag = "recorder",
+    params(OrphanedActionQuery),
+    responses((status = 200, description = "Recordings archived", body = OrphanedActionResponse))
+)]
+async fn archive_orphaned(
+    State(state): State<AppState>,
+    Query(q): Query<OrphanedActionQuery>,
+) -> Result<Json<OrphanedActionResponse>> {
+    let db = state.database.get_connection();
+    let rows = orphaned_rows(&state, q.node_alias.as_deref(), false).await?;
+    let count = rows.len();
+    for row in rows {
+        RecordingsIndexService::archive(db, row).await?;
+    }
+    tracing::info!(node = ?q.node_alias, count, "orphaned recordings archived");
+    Ok(Json(OrphanedActionResponse { count }))
+}
+
+/// Delete the orphaned recordings, archived ones included, with their objects
+#[utoipa::path(
+    post,
+    path = "/api/recordings/orphaned/delete",
+    tag = "recorder",
+    params(OrphanedActionQuery),
+    responses((status = 200, description = "Recordings deleted", body = OrphanedActionResponse))
+)]
+async fn delete_orphaned(
+    State(state): State<AppState>,
+    Query(q): Query<OrphanedActionQuery>,
+) -> Result<Json<OrphanedActionResponse>> {
+    let rows = orphaned_rows(&state, q.node_alias.as_deref(), true).await?;
+    let count = rows.len();
+    for row in rows {
+        purge(&state, row).await?;
+    }
+    Ok(Json(OrphanedActionResponse { count }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::recordings::{self, Entity as Recordings};
+    use crate::migration::Migrator;
+    use sea_orm::{ColumnTrait, Database, EntityTrait, QueryFilter};
+    use sea_orm_migration::MigratorTrait;
+
+    #[tokio::test]
+    async fn test_streamed_index_equals_buffered() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        for i in 0..3000 {
+            let record = (1_700_000_000 + i).to_string();
+            let stream = if i % 10 == 0 { "lobby" } else { "cam" };
+            RecordingsIndexService::upsert(
+                &db,
+                "edge-1",
+                stream,
+                &record,
+                &format!("{stream}/{record}/manifest.mpd"),
+            )
+            .await
+            .unwrap();
+        }
+        let query = || Recordings::find().filter(recordings::Column::Stream.eq("cam"));
+
+        let buffered: Vec<RecordingIndexEntry> = query()
+            .all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        let streamed = jsonl::lines(stream_index(db.clone(), query())).await;
+        assert_eq!(streamed.len(), 2700);
+        assert_eq!(
+            serde_json::Value::Array(streamed),
+            serde_json::to_value(&buffered).unwrap()
+        );
+    }
+}
@@ -2,23 +2,134 @@ use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::{
     Router,
-    extract::State,
+    body::Body,
+    extract::{ConnectInfo, Extension, Query, RawPathParams, State},
     response::{Json, Response},
-    routing::post,
+    routing::{post, put},
 };
+use http::HeaderMap;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::net::SocketAddr;
 
+use auth::claims::Claims;
+
+use crate::config::PresignPolicy;
+use crate::service::dashboard::DashboardEvent;
+use crate::service::file_storage::FileStorage;
+use crate::service::presign_audit::PresignAuditEntry;
 use crate::{AppState, result::Result};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
 struct PresignRequest {
+    /// `GET`, `HEAD`, `PUT`, `DELETE`, `TAGGING`, or `CREATE_MULTIPART`, `UPLOAD_PART` and
+    /// `COMPLETE_MULTIPART` for multipart uploads
     method: String,
+    /// Object key as stored, not percent-encoded. Signed in Unicode NFC
     path: String,
     ttl_seconds: u64,
+    /// Content type signed into PUT URLs, the uploader must send the same header
+    #[serde(default)]
+    content_type: Option<String>,
+    /// `x-amz-tagging` tag set signed into PUT URLs, e.g. `retention=30d`
+    #[serde(default)]
+    tagging: Option<String>,
+    /// Multipart upload of `UPLOAD_PART` and `COMPLETE_MULTIPART`
+    #[serde(default)]
+    upload_id: Option<String>,
+    /// Part of `UPLOAD_PART`, 1 to 10000
+    #[serde(default)]
+    part_number: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PresignMethod {
+    Get,
+    Head,
+    Put,
+    Delete,
+    Tagging,
+    CreateMultipart,
+    UploadPart,
+    CompleteMultipart,
+}
+
+impl PresignRequest {
+    /// Method of a request `policy` allows, otherwise the status and reason to refuse
+    /// it with
+    fn validate(
+        &self,
+        policy: &PresignPolicy,
+    ) -> std::result::Result<PresignMethod, (StatusCode, &'static str)> {
+        let method = match self.method.as_str() {
+            "GET" => PresignMethod::Get,
+            "HEAD" => PresignMethod::Head,
+            "PUT" => PresignMethod::Put,
+            "DELETE" => PresignMethod::Delete,
+            "TAGGING" => PresignMethod::Tagging,
+            "CREATE_MULTIPART" => PresignMethod::CreateMultipart,
+            "UPLOAD_PART" => PresignMethod::UploadPart,
+            "COMPLETE_MULTIPART" => PresignMethod::CompleteMultipart,
+            _ => return Err((StatusCode::BAD_REQUEST, "unsupported method")),
+        };
+        let upload_id = self.upload_id.as_deref().filter(|id| !id.is_empty());
+        match method {
+            PresignMethod::UploadPart | PresignMethod::CompleteMultipart if upload_id.is_none() => {
+                return Err((StatusCode::BAD_REQUEST, "upload_id is required"));
+            }
+            PresignMethod::UploadPart
+                if !self.part_number.is_some_and(|n| (1..=10_000).contains(&n)) =>
+            {
+                return Err((StatusCode::BAD_REQUEST, "part_number must be 1 to 10000"));
+            }
+            _ => {}
+        }
+        let path = self.path.trim_matches('/');
+        if path.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "path is required"));
+        }
+        match ::storage::check_key(path) {
+            Err(::storage::KeyError::TooLong { .. }) => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "path is longer than the 1024 bytes S3 accepts",
+                ));
+            }
+            Err(::storage::KeyError::ControlCharacter(_)) => {
+                return Err((StatusCode::BAD_REQUEST, "path contains control characters"));
+            }
+            _ => {}
+        }
+        if method == PresignMethod::Delete {
+            if !policy.allow_delete {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    "presigned DELETE is not allowed, see recorder.presign.allow_delete",
+                ));
+            }
+            // Any number of recordings may reference them
+            if ::storage::is_shared(path) || path.split('/').any(|s| s == "..") {
+                return Err((StatusCode::FORBIDDEN, "shared objects are never deleted"));
+            }
+        }
+        Ok(method)
+    }
 }
 
-#[derive(Debug, Serialize)]
+/// Whether `claims` may presign `method` on `path`, the same stream check livevod makes.
+/// Shared init segments hold codec setup only, any token may read them
+fn allows(claims: &Claims, method: PresignMethod, path: &str) -> bool {
+    let path = path.trim_start_matches('/');
+    if matches!(method, PresignMethod::Get | PresignMethod::Head)
+        && ::storage::is_shared(path)
+        && !path.split('/').any(|s| s == "..")
+    {
+        return true;
+    }
+    claims.allows_object(path)
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
 struct PresignResponse {
     url: String,
     headers: HashMap<String, String>,
@@ -28,42 +139,641 @@ pub fn route() -> Router<AppState> {
     Router::new()
         .route("/api/storage/presign", post(presign))
         .route("/api/storage/ping", axum::routing::get(ping))
+        .route("/api/storage/status", axum::routing::get(status))
+        .route("/api/storage/diagnose", post(diagnose))
+        .route(
+            "/api/storage/presign-audit",
+            axum::routing::get(presign_audit),
+        )
+        .route(
+            "/api/storage/objects/{*path}",
+            put(put_object).head(head_object).get(get_object),
+        )
+        .route("/api/admin/reload-storage", post(reload))
+}
+
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(
+    presign,
+    ping,
+    status,
+    diagnose,
+    reload,
+    presign_audit,
+    put_object,
+    head_object,
+    get_object
+))]
+pub struct StorageApi;
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct StorageStatus {
+    selected_endpoint: Option<String>,
+    #[schema(value_type = Vec<Object>)]
+    endpoints: Vec<::storage::EndpointStatus>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/storage/status",
+    tag = "storage",
+    responses(
+        (status = 200, description = "Health of the storage endpoints", body = StorageStatus),
+        (status = 503, description = "Storage not configured", body = String),
+    )
+)]
+async fn status(State(state): State<AppState>) -> Result<Response> {
+    let Some(storage) = state.file_storage.get() else {
+        return Ok((StatusCode::SERVICE_UNAVAILABLE, "storage not configured").into_response());
+    };
+    Ok(Json(StorageStatus {
+        selected_endpoint: storage.operator.selected_endpoint(),
+        endpoints: storage.operator.status(),
+    })
+    .into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/storage/diagnose",
+    tag = "storage",
+    responses(
+        (status = 200, description = "Status, latency and error of each stage, `ok` is false when one failed", body = Object),
+        (status = 403, description = "Token limited to streams or tenants by its `streams` or `tenants` claim", body = String),
+        (status = 503, description = "Storage not configured", body = String),
+    )
+)]
+async fn diagnose(
+    State(state): State<AppState>,
+    claims: Option<Extension<Claims>>,
+) -> Result<Response> {
+    let Some(storage) = state.file_storage.get() else {
+        return Ok((StatusCode::SERVICE_UNAVAILABLE, "storage not configured").into_response());
+    };
+    // Probes write outside every stream's prefix
+    if let Some(Extension(ref claims)) = claims
+        && (claims.streams.is_some() || claims.tenants.is_some())
+    {
+        return Ok((
+            StatusCode::FORBIDDEN,
+            "stream tokens cannot diagnose storage",
+        )
+            .into_response());
+    }
+    let selected = storage.operator.selected_endpoint();
+    let report = ::storage::diagnose(
+        &storage.config,
+        &storage.operator.current(),
+        selected.as_deref(),
+        &state.config.recorder.diagnose,
+    )
+    .await;
+    if !report.ok {
+        tracing::warn!("storage diagnose failed: {:?}", report.stages);
+    }
+    Ok(Json(report).into_response())
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+struct PresignAuditQuery {
+    /// Only keys under this prefix
+    path_prefix: Option<String>,
+    /// Only presigns after this time, UNIX microseconds
+    since: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/storage/presign-audit",
+    tag = "storage",
+    params(PresignAuditQuery),
+    responses(
+        (status = 200, description = "Recent presigns still in the buffer, oldest first", body = Vec<PresignAuditEntry>),
+        (status = 403, description = "Token limited to streams or tenants by its `streams` or `tenants` claim", body = String),
+    )
+)]
+async fn presign_audit(
+    State(state): State<AppState>,
+    claims: Option<Extension<Claims>>,
+    Query(query): Query<PresignAuditQuery>,
+) -> Result<Response> {
+    // Entries name other streams' objects and the clients that fetched them
+    if let Some(Extension(ref claims)) = claims
+        && (claims.streams.is_some() || claims.tenants.is_some())
+    {
+        return Ok((
+            StatusCode::FORBIDDEN,
+            "stream tokens cannot read the presign audit",
+        )
+            .into_response());
+    }
+    Ok(Json(
+        state
+            .presign_audit
+            .query(query.path_prefix.as_deref(), query.since),
+    )
+    .into_response())
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/admin/reload-storage",
+    tag = "storage",
+    responses(
+        (status = 200, description = "New storage in use, health of its endpoints", body = StorageStatus),
+        (status = 400, description = "Config not loaded from a file, or the file no longer loads", body = String),
+        (status = 502, description = "New storage failed its connection test, the current one stays in use", body = String),
+    )
+)]
+async fn reload(State(state): State<AppState>) -> Result<Response> {
+    let Some(ref source) = state.config.source else {
+        return Ok((StatusCode::BAD_REQUEST, "config was not loaded from a file").into_response());
+    };
+    let cfg = match source.load() {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            return Ok((StatusCode::BAD_REQUEST, format!("config load error: {e}")).into_response());
+        }
+    };
+    match state.file_storage.reload(cfg.recorder.storage).await {
+        Ok(storage) => Ok(Json(StorageStatus {
+            selected_endpoint: storage.operator.selected_endpoint(),
+            endpoints: storage.operator.status(),
+        })
+        .into_response()),
+        Err(e) => {
+            tracing::warn!("storage reload failed, keeping the current storage: {e:#}");
+            Ok((StatusCode::BAD_GATEWAY, format!("{e:#}")).into_response())
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/storage/ping",
+    tag = "storage",
+    responses(
+        (status = 200, description = "Storage configured", body = String),
+        (status = 503, description = "Storage not configured", body = String),
+    )
+)]
 async fn ping(State(state): State<AppState>) -> Result<Response> {
-    if state.file_storage.is_some() {
+    if state.file_storage.is_configured() {
         Ok((StatusCode::OK, "ok").into_response())
     } else {
         Ok((StatusCode::SERVICE_UNAVAILABLE, "storage not configured").into_response())
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/storage/presign",
+    tag = "storage",
+    request_body = PresignRequest,
+    responses(
+        (status = 200, description = "Presigned URL and the headers to send with it", body = PresignResponse),
+        (status = 400, description = "Unsupported method, missing path, or a multipart request without its upload_id or part_number", body = String),
+        (status = 403, description = "DELETE not allowed by the presign policy, of a shared object, or of a stream or tenant the token's `streams` or `tenants` claim excludes", body = String),
+        (status = 501, description = "Object tagging and multipart uploads need static S3 credentials", body = String),
+        (status = 503, description = "Storage not configured", body = String),
+    )
+)]
 async fn presign(
     State(state): State<AppState>,
-    Json(req): Json<PresignRequest>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    claims: Option<Extension<Claims>>,
+    headers: HeaderMap,
+    Json(mut req): Json<PresignRequest>,
 ) -> Result<Response> {
-    let Some(ref operator) = state.file_storage else {
+    req.path = ::storage::normalize_key(&req.path).into_owned();
+    // A reload mid-request does not change the backend this URL is signed for
+    let Some(storage) = state.file_storage.get() else {
         return Ok((StatusCode::SERVICE_UNAVAILABLE, "storage not configured").into_response());
     };
+    let method = match req.validate(&state.config.recorder.presign) {
+        Ok(method) => method,
+        Err(refused) => return Ok(refused.into_response()),
+    };
+    if let Some(Extension(ref claims)) = claims
+        && !allows(claims, method, &req.path)
+    {
+        return Ok((StatusCode::FORBIDDEN, "stream not allowed").into_response());
+    }
+    let audit = audit_entry(
+        &state,
+        claims.as_deref(),
+        &headers,
+        peer,
+        &req.method,
+        &req.path,
+        req.ttl_seconds.max(30),
+    );
+    let response = sign(&state, &storage, method, req, &headers, peer).await?;
+    if response.status().is_success() {
+        state.presign_audit.record(audit).await;
+    }
+    Ok(response)
+}
+
+/// What the audit keeps of a presign: never the URL nor its signed headers
+pub(crate) fn audit_entry(
+    state: &AppState,
+    claims: Option<&Claims>,
+    headers: &HeaderMap,
+    peer: SocketAddr,
+    method: &str,
+    path: &str,
+    ttl_seconds: u64,
+) -> PresignAuditEntry {
+    let ts = chrono::Utc::now().timestamp_micros();
+    let node = node_of(state, headers);
+    PresignAuditEntry {
+        ts,
+        method: method.to_string(),
+        path: path.to_string(),
+        ttl_seconds,
+        expires_at: ts + ttl_seconds as i64 * 1_000_000,
+        subject: claims.map_or(auth::ANY_ID, |c| c.id.as_str()).to_string(),
+        node,
+        client_ip: forwarded::client_ip(headers, peer.ip(), &state.config.http.trusted_proxies)
+            .to_string(),
+    }
+}
+
+/// Cluster node whose token signed the request
+fn node_of(state: &AppState, headers: &HeaderMap) -> Option<String> {
+    let token = headers
+        .get(http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    state
+        .storage
+        .get_map_nodes()
+        .into_iter()
+        .find(|(_, node)| !node.token.is_empty() && Some(node.token.as_str()) == token)
+        .map(|(alias, _)| alias)
+}
+
+/// Storage and object key of a request to `/api/storage/objects/{path}`, checked as a
+/// presign of `method` would be, otherwise the response refusing it
+fn object_request(
+    state: &AppState,
+    claims: Option<&Claims>,
+    params: &RawPathParams,
+    method: &str,
+) -> std::result::Result<(std::sync::Arc<FileStorage>, String), Response> {
+    let raw = params
+        .iter()
+        .find_map(|(name, value)| (name == "path").then_some(value))
+        .unwrap_or_default();
+    let path = ::storage::decode_key_from_path(raw)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+    let req = PresignRequest {
+        method: method.to_string(),
+        path,
+        ttl_seconds: 0,
+        content_type: None,
+        tagging: None,
+        upload_id: None,
+        part_number: None,
+    };
+    let Some(storage) = state.file_storage.get() else {
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "storage not configured").into_response());
+    };
+    let method = req
+        .validate(&state.config.recorder.presign)
+        .map_err(IntoResponse::into_response)?;
+    if let Some(claims) = claims
+        && !allows(claims, method, &req.path)
+    {
+        return Err((StatusCode::FORBIDDEN, "stream not allowed").into_response());
+    }
+    Ok((storage, req.path))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/storage/objects/{path}",
+    tag = "storage",
+    params(("path" = String, Path, description = "Object key, percent-encoded")),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream", description = "Object bytes, usually sent chunked. `Content-Type` is stored with the object, `x-amz-tagging` sets its tags"),
+    responses(
+        (status = 200, description = "Object written to storage"),
+        (status = 400, description = "Path is not a valid object key, or the body was cut short", body = String),
+        (status = 403, description = "Stream or tenant the token's `streams` or `tenants` claim excludes", body = String),
+        (status = 413, description = "Larger than `recorder.proxy_upload.max_object_bytes`", body = String),
+        (status = 429, description = "The node already runs `recorder.proxy_upload.node_concurrency` uploads", body = String),
+        (status = 502, description = "Storage failed the write, nothing is left at the key", body = String),
+        (status = 503, description = "Storage not configured", body = String),
+    )
+)]
+async fn put_object(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    claims: Option<Extension<Claims>>,
+    headers: HeaderMap,
+    params: RawPathParams,
+    body: Body,
+) -> Result<Response> {
+    let (storage, path) = match object_request(&state, claims.as_deref(), &params, "PUT") {
+        Ok(request) => request,
+        Err(refused) => return Ok(refused),
+    };
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let content_length = header("content-length").and_then(|v| v.parse().ok());
+    // Nodes share their limits however many connections they upload over
+    let node = node_of(&state, &headers)
+        .or_else(|| claims.as_ref().map(|c| c.id.clone()))
+        .unwrap_or_else(|| peer.ip().to_string());
+    let upload = match state.object_proxy.admit(&node, content_length) {
+        Ok(upload) => upload,
+        Err(e) => return Ok((e.status(), e.to_string()).into_response()),
+    };
+    if let Some(event) = DashboardEvent::upload_of(&path) {
+        state.dashboard.publish(event);
+    }
+    let content_type = header("content-type")
+        .unwrap_or_else(|| ::storage::content_type_for(&path))
+        .to_string();
+    let operator = storage.operator.current();
+    let written = match upload
+        .write(&operator, &path, &content_type, body.into_data_stream())
+        .await
+    {
+        Ok(written) => written,
+        Err(e) => {
+            tracing::warn!("proxied upload of {} from {} failed: {}", path, node, e);
+            return Ok((e.status(), e.to_string()).into_response());
+        }
+    };
+    if let Some(tagging) = header("x-amz-tagging").filter(|t| !t.is_empty()) {
+        let endpoint = storage.operator.selected_endpoint();
+        match ::storage::S3Signer::from_config(&storage.config, endpoint.as_deref()) {
+            Some(signer) => {
+                if let Err(e) = put_tagging(&state.client, &signer, &path, tagging).await {
+                    return Ok((StatusCode::BAD_GATEWAY, format!("{e:#}")).into_response());
+                }
+            }
+            None => tracing::warn!(
+                "proxied upload of {} without tags '{}': object tagging needs static S3 credentials",
+                path,
+                tagging
+            ),
+        }
+    }
+    tracing::debug!(
+        "proxied upload of {} from {}, {} bytes",
+        path,
+        node,
+        written
+    );
+    Ok(StatusCode::OK.into_response())
+}
+
+/// Set the tags of `path` to `tagging`, an `x-amz-tagging` query string
+async fn put_tagging(
+    client: &reqwest::Client,
+    signer: &::storage::S3Signer,
+    path: &str,
+    tagging: &str,
+) -> anyhow::Result<()> {
+    let tags: Vec<(String, String)> = url::form_urlencoded::parse(tagging.as_bytes())
+        .into_owned()
+        .collect();
+    let tags: Vec<(&str, &str)> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    let signed = signer.presign_put_tagging(path, std::time::Duration::from_secs(60));
+    let mut req = client
+        .put(&signed.url)
+        .body(::storage::tagging_document(&tags));
+    for (name, value) in &signed.headers {
+        req = req.header(name, value);
+    }
+    let resp = req.send().await.map_err(reqwest::Error::without_url)?;
+    anyhow::ensure!(
+        resp.status().is_success(),
+        "tagging {} failed: {}",
+        path,
+        resp.status()
+    );
+    Ok(())
+}
+
+#[utoipa::path(
+    head,
+    path = "/api/storage/objects/{path}",
+    tag = "storage",
+    params(("path" = String, Path, description = "Object key, percent-encoded")),
+    responses(
+        (status = 200, description = "Object exists, `Content-Length` is its size"),
+        (status = 404, description = "Object not found"),
+        (status = 503, description = "Storage not configured"),
+    )
+)]
+async fn head_object(
+    State(state): State<AppState>,
+    claims: Option<Extension<Claims>>,
+    params: RawPathParams,
+) -> Result<Response> {
+    let (storage, path) = match object_request(&state, claims.as_deref(), &params, "HEAD") {
+        Ok(request) => request,
+        Err(refused) => return Ok(refused),
+    };
+    match storage.operator.current().stat(&path).await {
+        Ok(meta) => Ok((
+            [(http::header::CONTENT_LENGTH, meta.content_length())],
+            Body::empty(),
+        )
+            .into_response()),
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => {
+            Ok(StatusCode::NOT_FOUND.into_response())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/storage/objects/{path}",
+    tag = "storage",
+    params(("path" = String, Path, description = "Object key, percent-encoded")),
+    responses(
+        (status = 200, description = "Object bytes, streamed from storage", content_type = "application/octet-stream"),
+        (status = 404, description = "Object not found", body = String),
+        (status = 503, description = "Storage not configured", body = String),
+    )
+)]
+async fn get_object(
+    State(state): State<AppState>,
+    claims: Option<Extension<Claims>>,
+    params: RawPathParams,
+) -> Result<Response> {
+    let (storage, path) = match object_request(&state, claims.as_deref(), &params, "GET") {
+        Ok(request) => request,
+        Err(refused) => return Ok(refused),
+    };
+    let operator = storage.operator.current();
+    let meta = match operator.stat(&path).await {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => {
+            return Ok((StatusCode::NOT_FOUND, "object not found").into_response());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let stream = operator.reader(&path).await?.into_bytes_stream(..).await?;
+    Ok((
+        [
+            (
+                http::header::CONTENT_TYPE,
+                ::storage::content_type_for(&path).to_string(),
+            ),
+            (
+                http::header::CONTENT_LENGTH,
+                meta.content_length().to_string(),
+            ),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+async fn sign(
+    state: &AppState,
+    storage: &FileStorage,
+    method: PresignMethod,
+    req: PresignRequest,
+    headers: &HeaderMap,
+    peer: SocketAddr,
+) -> Result<Response> {
+    // Presigned URLs point at whichever endpoint is healthy right now
+    let operator = storage.operator.current();
 
     let ttl = std::time::Duration::from_secs(req.ttl_seconds.max(30));
-    let result = match req.method.as_str() {
-        "GET" => operator.presign_read(&req.path, ttl).await,
-        "PUT" => operator.presign_write(&req.path, ttl).await,
-        _ => {
-            return Ok((StatusCode::BAD_REQUEST, "unsupported method").into_response());
+    // opendal cannot sign extra headers or subresources, tagged requests are signed here
+    let signer = || {
+        ::storage::S3Signer::from_config(
+            &storage.config,
+            storage.operator.selected_endpoint().as_deref(),
+        )
+    };
+    let tagging = req.tagging.filter(|t| !t.is_empty());
+    let result = match method {
+        PresignMethod::Get => operator.presign_read(&req.path, ttl).await,
+        PresignMethod::Head => operator.presign_stat(&req.path, ttl).await,
+        PresignMethod::Delete => {
+            tracing::info!("presign DELETE {} for {}", req.path, peer);
+            operator.presign_delete(&req.path, ttl).await
+        }
+        PresignMethod::Put => {
+            if let Some(event) = DashboardEvent::upload_of(&req.path) {
+                state.dashboard.publish(event);
+            }
+            let content_type = req
+                .content_type
+                .unwrap_or_else(|| ::storage::content_type_for(&req.path).to_string());
+            if let Some(tagging) = tagging {
+                match signer() {
+                    Some(signer) => {
+                        let signed =
+                            signer.presign_put(&req.path, ttl, &content_type, Some(&tagging));
+                        return Ok(Json(signed_response(
+                            &state,
+                            &storage.config,
+                            &headers,
+                            peer,
+                            signed,
+                        ))
+                        .into_response());
+                    }
+                    None => tracing::warn!(
+                        "presign {} without tags '{}': object tagging needs static S3 credentials",
+                        req.path,
+                        tagging
+                    ),
+                }
+            }
+            operator
+                .presign_write_with(&req.path, ttl)
+                .content_type(&content_type)
+                .await
+        }
+        PresignMethod::Tagging => {
+            let Some(signer) = signer() else {
+                return Ok((
+                    StatusCode::NOT_IMPLEMENTED,
+                    "object tagging needs static S3 credentials",
+                )
+                    .into_response());
+            };
+            let signed = signer.presign_put_tagging(&req.path, ttl);
+            return Ok(Json(signed_response(
+                &state,
+                &storage.config,
+                &headers,
+                peer,
+                signed,
+            ))
+            .into_response());
+        }
+        PresignMethod::CreateMultipart
+        | PresignMethod::UploadPart
+        | PresignMethod::CompleteMultipart => {
+            let Some(signer) = signer() else {
+                return Ok((
+                    StatusCode::NOT_IMPLEMENTED,
+                    "multipart uploads need static S3 credentials",
+                )
+                    .into_response());
+            };
+            let upload_id = req.upload_id.as_deref().unwrap_or_default();
+            let signed = match method {
+                PresignMethod::CreateMultipart => {
+                    if let Some(event) = DashboardEvent::upload_of(&req.path) {
+                        state.dashboard.publish(event);
+                    }
+                    let content_type = req
+                        .content_type
+                        .unwrap_or_else(|| ::storage::content_type_for(&req.path).to_string());
+                    signer.presign_create_multipart(
+                        &req.path,
+                        ttl,
+                        &content_type,
+                        tagging.as_deref(),
+                    )
+                }
+                PresignMethod::UploadPart => signer.presign_upload_part(
+                    &req.path,
+                    ttl,
+                    upload_id,
+                    req.part_number.unwrap_or(1),
+                ),
+                _ => signer.presign_complete_multipart(&req.path, ttl, upload_id),
+            };
+            return Ok(Json(signed_response(
+                &state,
+                &storage.config,
+                &headers,
+                peer,
+                signed,
+            ))
+            .into_response());
         }
     };
 
     match result {
         Ok(presigned) => {
-            let mut headers = HashMap::new();
+            let mut signed_headers = HashMap::new();
             for (name, value) in presigned.header() {
-                headers.insert(name.to_string(), value.to_str().unwrap_or("").to_string());
+                signed_headers.insert(name.to_string(), value.to_str().unwrap_or("").to_string());
             }
             let body = PresignResponse {
-                url: presigned.uri().to_string(),
-                headers,
+                url: public_url(
+                    &state,
+                    &storage.config,
+                    &headers,
+                    peer,
+                    presigned.uri().to_string(),
+                ),
+                headers: signed_headers,
             };
             Ok(Json(body).into_response())
         }
@@ -74,3 +784,229 @@ async fn presign(
             .into_response()),
     }
 }
+
+fn signed_response(
+    state: &AppState,
+    config: &::storage::StorageConfig,
+    headers: &HeaderMap,
+    peer: SocketAddr,
+    signed: ::storage::PresignedRequest,
+) -> PresignResponse {
+    PresignResponse {
+        url: public_url(state, config, headers, peer, signed.url),
+        headers: signed.headers.into_iter().collect(),
+    }
+}
+
+/// `uri` rewritten to the public endpoint of `config`, the storage it was signed for,
+/// as the client reaches it
+pub(crate) fn public_url(
+    state: &AppState,
+    config: &::storage::StorageConfig,
+    headers: &HeaderMap,
+    peer: SocketAddr,
+    uri: String,
+) -> String {
+    let Some(public) = config.public_url(&uri) else {
+        return uri;
+    };
+    let origin = forwarded::Origin::from_request(
+        headers,
+        peer.ip(),
+        &state.config.http.trusted_proxies,
+        "http",
+    );
+    forwarded::absolute(origin.as_ref(), &public)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str) -> PresignRequest {
+        PresignRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            ttl_seconds: 300,
+            content_type: None,
+            tagging: None,
+            upload_id: None,
+            part_number: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_methods() {
+        let policy = PresignPolicy::default();
+        for (method, expected) in [
+            ("GET", PresignMethod::Get),
+            ("HEAD", PresignMethod::Head),
+            ("PUT", PresignMethod::Put),
+            ("TAGGING", PresignMethod::Tagging),
+        ] {
+            assert_eq!(
+                request(method, "cam/1700000000/v_seg_0001.m4s")
+                    .validate(&policy)
+                    .unwrap(),
+                expected
+            );
+        }
+        assert_eq!(
+            request("head", "cam/1700000000/manifest.mpd")
+                .validate(&policy)
+                .unwrap_err()
+                .0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            request("GET", "/").validate(&policy).unwrap_err().0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn test_validate_multipart() {
+        let policy = PresignPolicy::default();
+        let path = "cam/1700000000/v_seg_0001.m4s";
+        assert_eq!(
+            request("CREATE_MULTIPART", path).validate(&policy).unwrap(),
+            PresignMethod::CreateMultipart
+        );
+        for method in ["UPLOAD_PART", "COMPLETE_MULTIPART"] {
+            assert_eq!(
+                request(method, path).validate(&policy).unwrap_err(),
+                (StatusCode::BAD_REQUEST, "upload_id is required")
+            );
+        }
+        let part = |part_number| PresignRequest {
+            upload_id: Some("up-1".to_string()),
+            part_number,
+            ..request("UPLOAD_PART", path)
+        };
+        assert_eq!(
+            part(Some(2)).validate(&policy).unwrap(),
+            PresignMethod::UploadPart
+        );
+        for part_number in [None, Some(0), Some(10_001)] {
+            assert_eq!(
+                part(part_number).validate(&policy).unwrap_err().0,
+                StatusCode::BAD_REQUEST
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_key_limits() {
+        let policy = PresignPolicy::default();
+        // 3 bytes per character, 1024 bytes in 364 characters
+        let dir = format!("lobby-1-{}/1700000000", "摄像头".repeat(110));
+        let at_limit = format!("{dir}/v_seg_0001.m4s");
+        assert_eq!(at_limit.len(), 1024);
+        assert_eq!(
+            request("PUT", &at_limit).validate(&policy).unwrap(),
+            PresignMethod::Put
+        );
+        assert_eq!(
+            request("PUT", &format!("{dir}/v_seg_00001.m4s"))
+                .validate(&policy)
+                .unwrap_err(),
+            (
+                StatusCode::BAD_REQUEST,
+                "path is longer than the 1024 bytes S3 accepts"
+            )
+        );
+        assert_eq!(
+            request("GET", "cam/1700000000/v_seg\r0001.m4s")
+                .validate(&policy)
+                .unwrap_err()
+                .0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn test_delete_needs_a_grant() {
+        let segment = request("DELETE", "cam/1700000000/v_seg_0001.m4s");
+        assert_eq!(
+            segment.validate(&PresignPolicy::default()).unwrap_err().0,
+            StatusCode::FORBIDDEN
+        );
+
+        let policy = PresignPolicy { allow_delete: true };
+        assert_eq!(segment.validate(&policy).unwrap(), PresignMethod::Delete);
+        for path in [
+            "_shared/init/3f2a.mp4",
+            "/_shared/init/3f2a.mp4",
+            "cam/../_shared/init/3f2a.mp4",
+        ] {
+            assert_eq!(
+                request("DELETE", path).validate(&policy).unwrap_err().0,
+                StatusCode::FORBIDDEN,
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_stream_claims() {
+        let claims = Claims {
+            id: "tenant-a".to_string(),
+            exp: 0,
+            mode: 4,
+            streams: Some(vec!["lobby".to_string()]),
+            tenants: None,
+        };
+        assert!(allows(
+            &claims,
+            PresignMethod::Get,
+            "/edge-1/lobby/1700000000/v_seg_0001.m4s"
+        ));
+        assert!(allows(
+            &claims,
+            PresignMethod::Head,
+            "_shared/init/3f2a.mp4"
+        ));
+        for (method, path) in [
+            (PresignMethod::Get, "lobbyist/1700000000/manifest.mpd"),
+            (PresignMethod::Put, "_shared/init/3f2a.mp4"),
+            (
+                PresignMethod::Get,
+                "_shared/../office/1700000000/manifest.mpd",
+            ),
+            (
+                PresignMethod::Get,
+                "lobby/1700000000/../../office/1700000000/a.m4s",
+            ),
+            (PresignMethod::Put, "index.json"),
+        ] {
+            assert!(!allows(&claims, method, path), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_tenant_claims() {
+        let claims = Claims {
+            id: "acme".to_string(),
+            exp: 0,
+            mode: 6,
+            streams: None,
+            tenants: Some(vec!["acme".to_string()]),
+        };
+        assert!(allows(
+            &claims,
+            PresignMethod::Put,
+            "acme/edge-1/acme-cam1/1700000000/v_seg_0001.m4s"
+        ));
+        assert!(allows(&claims, PresignMethod::Get, "_shared/init/3f2a.mp4"));
+        for (method, path) in [
+            (
+                PresignMethod::Get,
+                "globex/edge-1/globex-cam1/1700000000/manifest.mpd",
+            ),
+            (PresignMethod::Put, "acme-cam1/1700000000/v_seg_0001.m4s"),
+            (PresignMethod::Delete, "acme/index.json"),
+        ] {
+            assert!(!allows(&claims, method, path), "{path}");
+        }
+    }
+}
@@ -86,6 +86,12 @@ pub async fn index(
                                     },
                                 },
                                 codecs: vec![],
+                                // The node recording the stream, if any, wins
+                                recording: match (&s.recording, v.recording) {
+                                    (Some(r), _) if r.recording => Some(r.clone()),
+                                    (r, None) => r.clone(),
+                                    (_, r) => r,
+                                },
                             }
                         }
                         None => s.clone(),
@@ -0,0 +1,417 @@
+//! Recorder progress pushed to dashboards over `GET /api/ws/recorder`.
+//!
+//! Record sync, push ingest and presigned uploads publish into one broadcast channel.
+//! Every socket reads it at its own pace: a connection that falls behind loses its
+//! oldest events instead of slowing down the publishers.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use api::recorder::{
+    HealthReason, HealthStatus, RecorderHealth, RecorderStats, RecordingCapacity, RecordingStatus,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// Events buffered for the slowest connection before it starts losing the oldest
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DashboardEvent {
+    /// Status of a recording as its node last reported it
+    Status {
+        node: String,
+        stream: String,
+        record: String,
+        status: RecordingStatus,
+        /// When liveman learned of it, UNIX microseconds
+        at: i64,
+    },
+    /// Upload of a recording moved on: one more object was presigned, or with `done`
+    /// the node finished all of its uploads
+    Upload {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        node: Option<String>,
+        stream: String,
+        record: String,
+        objects: u64,
+        done: bool,
+    },
+    /// A node's recorder became reachable or unreachable for record sync
+    Health {
+        node: String,
+        healthy: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// A node reached its `max_concurrent_recordings`, or with `reached: false` has
+    /// room again
+    RecordingLimit {
+        node: String,
+        active: usize,
+        max: usize,
+        reached: bool,
+    },
+    /// The integrity rollup of a node changed status, with the reasons for it
+    Integrity {
+        node: String,
+        status: HealthStatus,
+        reasons: Vec<HealthReason>,
+    },
+    /// Sent to a connection that fell behind, `dropped` events were skipped
+    Lagged { dropped: u64 },
+}
+
+impl DashboardEvent {
+    fn node(&self) -> Option<&str> {
+        match self {
+            Self::Status { node, .. }
+            | Self::Health { node, .. }
+            | Self::RecordingLimit { node, .. }
+            | Self::Integrity { node, .. } => Some(node),
+            Self::Upload { node, .. } => node.as_deref(),
+            Self::Lagged { .. } => None,
+        }
+    }
+
+    fn stream(&self) -> Option<&str> {
+        match self {
+            Self::Status { stream, .. } | Self::Upload { stream, .. } => Some(stream),
+            Self::Health { .. }
+            | Self::RecordingLimit { .. }
+            | Self::Integrity { .. }
+            | Self::Lagged { .. } => None,
+        }
+    }
+
+    /// Upload event of an object key, `{node}/{stream}/{record}/...` with a key
+    /// namespace or `{stream}/{record}/...` without
+    pub fn upload_of(path: &str) -> Option<Self> {
+        let mut dirs: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        dirs.pop()?;
+        let record = dirs.pop()?;
+        if record.len() != 10 || !record.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let stream = dirs
+            .pop()
+            .filter(|s| !s.is_empty() && !s.starts_with('_'))?;
+        Some(Self::Upload {
+            node: dirs.pop().map(str::to_string),
+            stream: stream.to_string(),
+            record: record.to_string(),
+            objects: 1,
+            done: false,
+        })
+    }
+}
+
+/// Subscription of one connection, empty lists match everything.
+///
+/// Taken from the query (`?stream=a,b&node=edge-1`) and replaced by every JSON text
+/// message such as `{"streams": ["a"], "nodes": []}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DashboardFilter {
+    #[serde(default)]
+    pub streams: Vec<String>,
+    #[serde(default)]
+    pub nodes: Vec<String>,
+}
+
+impl DashboardFilter {
+    /// Events without a stream (health) pass the stream filter, events without a node
+    /// do not pass the node filter
+    pub fn matches(&self, event: &DashboardEvent) -> bool {
+        if matches!(event, DashboardEvent::Lagged { .. }) {
+            return true;
+        }
+        let stream_ok = self.streams.is_empty()
+            || event
+                .stream()
+                .is_none_or(|stream| self.streams.iter().any(|s| s == stream));
+        let node_ok = self.nodes.is_empty()
+            || event
+                .node()
+                .is_some_and(|node| self.nodes.iter().any(|n| n == node));
+        stream_ok && node_ok
+    }
+}
+
+pub struct DashboardHub {
+    events: broadcast::Sender<DashboardEvent>,
+    health: Mutex<HashMap<String, bool>>,
+    capacity: Mutex<HashMap<String, RecordingCapacity>>,
+    stats: Mutex<HashMap<String, RecorderStats>>,
+    integrity: Mutex<HashMap<String, RecorderHealth>>,
+}
+
+impl Default for DashboardHub {
+    fn default() -> Self {
+        Self {
+            events: broadcast::channel(CAPACITY).0,
+            health: Mutex::new(HashMap::new()),
+            capacity: Mutex::new(HashMap::new()),
+            stats: Mutex::new(HashMap::new()),
+            integrity: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl DashboardHub {
+    /// Never waits, without connections the event is dropped
+    pub fn publish(&self, event: DashboardEvent) {
+        let _ = self.events.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DashboardEvent> {
+        self.events.subscribe()
+    }
+
+    /// Record the outcome of a sync with `node`, publishing only changes
+    pub fn set_health(&self, node: &str, healthy: bool, error: Option<String>) {
+        let changed = self
+            .health
+            .lock()
+            .unwrap()
+            .insert(node.to_string(), healthy)
+            != Some(healthy);
+        if changed {
+            self.publish(DashboardEvent::Health {
+                node: node.to_string(),
+                healthy,
+                error,
+            });
+        }
+    }
+
+    /// Record the recordings a sync found running on `node`, publishing when it reaches
+    /// or leaves its limit
+    pub fn set_recording_capacity(&self, node: &str, capacity: RecordingCapacity) {
+        let previous = self
+            .capacity
+            .lock()
+            .unwrap()
+            .insert(node.to_string(), capacity);
+        let was_at_limit = previous.is_some_and(|c| c.at_limit());
+        if capacity.at_limit() != was_at_limit {
+            self.publish(DashboardEvent::RecordingLimit {
+                node: node.to_string(),
+                active: capacity.active,
+                max: capacity.max,
+                reached: capacity.at_limit(),
+            });
+        }
+    }
+
+    /// Recordings `node` ran at its last sync, `None` before one or from older nodes
+    pub fn recording_capacity(&self, node: &str) -> Option<RecordingCapacity> {
+        self.capacity.lock().unwrap().get(node).copied()
+    }
+
+    /// Record the recorder stats `node` reported at a sync
+    pub fn set_recorder_stats(&self, node: &str, stats: RecorderStats) {
+        self.stats.lock().unwrap().insert(node.to_string(), stats);
+    }
+
+    /// Stats `node` reported at its last sync, `None` before one or from older nodes
+    pub fn recorder_stats(&self, node: &str) -> Option<RecorderStats> {
+        self.stats.lock().unwrap().get(node).copied()
+    }
+
+    /// Record the integrity rollup `node` reported at a sync, publishing and logging
+    /// when its status changes. A node first seen `ok` is no change
+    pub fn set_recorder_health(&self, node: &str, health: RecorderHealth) {
+        let previous = self
+            .integrity
+            .lock()
+            .unwrap()
+            .insert(node.to_string(), health.clone())
+            .map_or(HealthStatus::Ok, |previous| previous.status);
+        if health.status == previous {
+            return;
+        }
+        match health.status {
+            HealthStatus::Ok => tracing::info!(node, "recorder integrity ok again"),
+            status => tracing::warn!(
+                node,
+                reasons = ?health.reasons.iter().map(|r| &r.message).collect::<Vec<_>>(),
+                "recorder integrity {}",
+                status.as_str()
+            ),
+        }
+        self.publish(DashboardEvent::Integrity {
+            node: node.to_string(),
+            status: health.status,
+            reasons: health.reasons,
+        });
+    }
+
+    /// Rollup `node` reported at its last sync, `None` before one or from older nodes
+    pub fn recorder_health(&self, node: &str) -> Option<RecorderHealth> {
+        self.integrity.lock().unwrap().get(node).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(node: &str, stream: &str) -> DashboardEvent {
+        DashboardEvent::Status {
+            node: node.to_string(),
+            stream: stream.to_string(),
+            record: "1700000000".to_string(),
+            status: RecordingStatus::Completed,
+            at: 1,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let all = DashboardFilter::default();
+        let by_stream = DashboardFilter {
+            streams: vec!["cam".to_string()],
+            nodes: vec![],
+        };
+        let by_node = DashboardFilter {
+            streams: vec![],
+            nodes: vec!["edge-1".to_string()],
+        };
+        let health = DashboardEvent::Health {
+            node: "edge-2".to_string(),
+            healthy: false,
+            error: None,
+        };
+
+        assert!(all.matches(&status("edge-2", "lobby")));
+        assert!(by_stream.matches(&status("edge-2", "cam")));
+        assert!(!by_stream.matches(&status("edge-1", "lobby")));
+        assert!(by_stream.matches(&health));
+        assert!(by_node.matches(&status("edge-1", "lobby")));
+        assert!(!by_node.matches(&health));
+        assert!(
+            !by_node.matches(&DashboardEvent::upload_of("cam/1700000000/v_seg_0001.m4s").unwrap())
+        );
+        assert!(by_node.matches(&DashboardEvent::Lagged { dropped: 3 }));
+    }
+
+    #[test]
+    fn test_upload_of() {
+        let Some(DashboardEvent::Upload {
+            node,
+            stream,
+            record,
+            objects: 1,
+            done: false,
+        }) = DashboardEvent::upload_of("edge-1/cam/1700000000/v_seg_0001.m4s")
+        else {
+            panic!("not an upload");
+        };
+        assert_eq!(node.as_deref(), Some("edge-1"));
+        assert_eq!(stream, "cam");
+        assert_eq!(record, "1700000000");
+        assert!(matches!(
+            DashboardEvent::upload_of("cam/1700000000/manifest.mpd"),
+            Some(DashboardEvent::Upload { node: None, .. })
+        ));
+        assert!(DashboardEvent::upload_of("cam/custom/manifest.mpd").is_none());
+        assert!(DashboardEvent::upload_of("_shared/init/ab12.mp4").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_slow_subscriber_drops_oldest() {
+        let hub = DashboardHub::default();
+        let mut slow = hub.subscribe();
+        for i in 0..CAPACITY + 10 {
+            hub.publish(DashboardEvent::Lagged { dropped: i as u64 });
+        }
+        // Publishing never waited for the subscriber, which lost the oldest events
+        assert!(matches!(
+            slow.recv().await,
+            Err(broadcast::error::RecvError::Lagged(10))
+        ));
+        assert!(matches!(
+            slow.recv().await.unwrap(),
+            DashboardEvent::Lagged { dropped: 10 }
+        ));
+
+        let mut events = hub.subscribe();
+        hub.set_health("edge-1", true, None);
+        hub.set_health("edge-1", true, None);
+        hub.set_health("edge-1", false, Some("timeout".to_string()));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            DashboardEvent::Health { healthy: true, .. }
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            DashboardEvent::Health { healthy: false, .. }
+        ));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_recording_limit_changes_are_published() {
+        let hub = DashboardHub::default();
+        let mut events = hub.subscribe();
+        let capacity = |active| RecordingCapacity { active, max: 50 };
+
+        hub.set_recording_capacity("edge-1", capacity(10));
+        hub.set_recording_capacity("edge-1", capacity(50));
+        hub.set_recording_capacity("edge-1", capacity(50));
+        hub.set_recording_capacity("edge-1", capacity(49));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            DashboardEvent::RecordingLimit {
+                active: 50,
+                reached: true,
+                ..
+            }
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            DashboardEvent::RecordingLimit {
+                active: 49,
+                reached: false,
+                ..
+            }
+        ));
+        assert!(events.try_recv().is_err());
+        assert_eq!(hub.recording_capacity("edge-1"), Some(capacity(49)));
+        assert_eq!(hub.recording_capacity("edge-2"), None);
+    }
+
+    #[tokio::test]
+    async fn test_integrity_changes_are_published() {
+        let hub = DashboardHub::default();
+        let mut events = hub.subscribe();
+        let health = |status| RecorderHealth {
+            status,
+            reasons: Vec::new(),
+            computed_at: 1,
+            upload_lag: None,
+        };
+        hub.set_recorder_health("edge-1", health(HealthStatus::Ok));
+        hub.set_recorder_health("edge-1", health(HealthStatus::Degraded));
+        hub.set_recorder_health("edge-1", health(HealthStatus::Degraded));
+        hub.set_recorder_health("edge-1", health(HealthStatus::Failing));
+        hub.set_recorder_health("edge-1", health(HealthStatus::Ok));
+        for expected in [
+            HealthStatus::Degraded,
+            HealthStatus::Failing,
+            HealthStatus::Ok,
+        ] {
+            assert!(matches!(
+                events.recv().await.unwrap(),
+                DashboardEvent::Integrity { status, .. } if status == expected
+            ));
+        }
+        assert!(events.try_recv().is_err());
+        assert_eq!(
+            hub.recorder_health("edge-1").map(|h| h.status),
+            Some(HealthStatus::Ok)
+        );
+        assert_eq!(hub.recorder_health("edge-2"), None);
+    }
+}
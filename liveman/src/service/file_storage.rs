@@ -0,0 +1,166 @@
+//! Storage backend of the recorder routes, replaced as a whole when the storage
+//! section is reloaded.
+//!
+//! Handlers take a [`FileStorageHandle::get`] snapshot per request, so presigns and
+//! proxied reads already running finish on the operator they started with while new
+//! requests see the reloaded one.
+
+use std::sync::{Arc, RwLock};
+
+use anyhow::Context;
+use storage::{FailoverOperator, StorageConfig};
+use tokio::task::JoinHandle;
+use tracing::info;
+
+/// Storage configuration and the operator built from it
+pub struct FileStorage {
+    pub config: StorageConfig,
+    pub operator: FailoverOperator,
+    /// Health probe of a failover operator, stopped with the last request using it
+    probe: Option<JoinHandle<()>>,
+}
+
+impl FileStorage {
+    /// Storage set up at startup, its probe is never stopped
+    pub fn new(config: StorageConfig, operator: FailoverOperator) -> Self {
+        Self {
+            config,
+            operator,
+            probe: None,
+        }
+    }
+}
+
+impl Drop for FileStorage {
+    fn drop(&mut self) {
+        if let Some(probe) = self.probe.take() {
+            probe.abort();
+        }
+    }
+}
+
+/// Shared, swappable [`FileStorage`], `None` while storage is not configured
+#[derive(Clone, Default)]
+pub struct FileStorageHandle {
+    inner: Arc<RwLock<Option<Arc<FileStorage>>>>,
+}
+
+impl FileStorageHandle {
+    pub fn new(storage: Option<FileStorage>) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(storage.map(Arc::new))),
+        }
+    }
+
+    /// Storage requests started now should use
+    pub fn get(&self) -> Option<Arc<FileStorage>> {
+        self.inner.read().unwrap().clone()
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.inner.read().unwrap().is_some()
+    }
+
+    /// Build an operator for `config` and swap it in once its connection test passes.
+    ///
+    /// A config that fails to build or connect leaves the current storage untouched.
+    pub async fn reload(&self, config: StorageConfig) -> anyhow::Result<Arc<FileStorage>> {
+        let operator =
+            storage::create_failover_operator(&config).context("invalid storage config")?;
+        if operator.is_failover() {
+            operator.probe().await;
+        }
+        storage::test_connection(&operator.current())
+            .await
+            .context("storage connection test failed")?;
+        let probe = operator.spawn_probe(storage::failover::DEFAULT_PROBE_INTERVAL);
+
+        let storage = Arc::new(FileStorage {
+            config,
+            operator,
+            probe,
+        });
+        // Handlers still holding the old storage keep it alive until they finish
+        *self.inner.write().unwrap() = Some(storage.clone());
+        info!(
+            "storage reloaded, selected endpoint: {:?}",
+            storage.operator.selected_endpoint()
+        );
+        Ok(storage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fs_config(name: &str) -> StorageConfig {
+        let root = std::env::temp_dir().join(format!("liveman-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        StorageConfig::Fs {
+            root: root.to_string_lossy().into_owned(),
+        }
+    }
+
+    /// No S3 service listens on port 1
+    fn unreachable_s3() -> StorageConfig {
+        StorageConfig::S3 {
+            bucket: "recordings".to_string(),
+            root: String::new(),
+            region: Some("us-east-1".to_string()),
+            endpoint: Some(storage::S3Endpoint::Single(
+                "http://127.0.0.1:1".to_string(),
+            )),
+            access_key_id: Some("key".to_string()),
+            secret_access_key: Some("secret".into()),
+            session_token: None,
+            disable_config_load: true,
+            enable_virtual_host_style: false,
+            public_endpoint: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_storage_on_failure() {
+        // What the router and the requests in flight hold
+        let handle = FileStorageHandle::new(None);
+        let router = handle.clone();
+
+        assert!(handle.reload(unreachable_s3()).await.is_err());
+        assert!(!router.is_configured());
+
+        handle.reload(fs_config("first")).await.unwrap();
+        let in_flight = router.get().unwrap();
+        in_flight
+            .operator
+            .current()
+            .write("cam/1/manifest.mpd", "<MPD />")
+            .await
+            .unwrap();
+
+        // A failed reload leaves the working storage in place
+        assert!(handle.reload(unreachable_s3()).await.is_err());
+        assert!(Arc::ptr_eq(&router.get().unwrap(), &in_flight));
+
+        handle.reload(fs_config("second")).await.unwrap();
+        let current = router.get().unwrap();
+        assert!(!Arc::ptr_eq(&current, &in_flight));
+        // The request that started before the swap still reads its own backend
+        assert!(
+            in_flight
+                .operator
+                .current()
+                .exists("cam/1/manifest.mpd")
+                .await
+                .unwrap()
+        );
+        assert!(
+            !current
+                .operator
+                .current()
+                .exists("cam/1/manifest.mpd")
+                .await
+                .unwrap()
+        );
+    }
+}
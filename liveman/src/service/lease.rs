@@ -0,0 +1,137 @@
+//! Recording leases, one node records a stream at a time.
+//!
+//! A node publishing a stream asks for its lease before auto-recording it and renews
+//! it while recording. Leases live in memory only: after a restart of liveman the
+//! holders renew theirs before any other node's retry, as long as the restart is
+//! shorter than the TTL.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use api::recorder::{RecordingLease, RecordingLeaseResponse};
+
+/// Longest TTL granted, a node asking for more gets this
+pub const MAX_TTL_SECONDS: u64 = 600;
+
+#[derive(Default)]
+pub struct LeaseTable {
+    leases: Mutex<HashMap<String, RecordingLease>>,
+}
+
+impl LeaseTable {
+    /// Grant or renew the lease of `stream` to `node` for `ttl_seconds` from `now`, UNIX
+    /// microseconds. Refused while another node holds an unexpired lease
+    pub fn acquire(
+        &self,
+        stream: &str,
+        node: &str,
+        ttl_seconds: u64,
+        now: i64,
+    ) -> RecordingLeaseResponse {
+        let ttl = ttl_seconds.clamp(1, MAX_TTL_SECONDS) as i64 * 1_000_000;
+        let mut leases = self.leases.lock().unwrap();
+        match leases.get_mut(stream) {
+            Some(lease) if lease.holder != node && lease.expires_at > now => {
+                RecordingLeaseResponse {
+                    granted: false,
+                    lease: Some(lease.clone()),
+                }
+            }
+            Some(lease) if lease.holder == node => {
+                lease.expires_at = now + ttl;
+                RecordingLeaseResponse {
+                    granted: true,
+                    lease: Some(lease.clone()),
+                }
+            }
+            _ => {
+                let lease = RecordingLease {
+                    stream: stream.to_string(),
+                    holder: node.to_string(),
+                    acquired_at: now,
+                    expires_at: now + ttl,
+                };
+                leases.insert(stream.to_string(), lease.clone());
+                RecordingLeaseResponse {
+                    granted: true,
+                    lease: Some(lease),
+                }
+            }
+        }
+    }
+
+    /// Give up `node`'s lease of `stream`, another node's lease is left alone and
+    /// returned
+    pub fn release(&self, stream: &str, node: &str) -> RecordingLeaseResponse {
+        let mut leases = self.leases.lock().unwrap();
+        match leases.get(stream) {
+            Some(lease) if lease.holder != node => RecordingLeaseResponse {
+                granted: false,
+                lease: Some(lease.clone()),
+            },
+            _ => {
+                leases.remove(stream);
+                RecordingLeaseResponse {
+                    granted: false,
+                    lease: None,
+                }
+            }
+        }
+    }
+
+    /// The unexpired lease of `stream`
+    pub fn get(&self, stream: &str, now: i64) -> Option<RecordingLease> {
+        let leases = self.leases.lock().unwrap();
+        leases
+            .get(stream)
+            .filter(|lease| lease.expires_at > now)
+            .cloned()
+    }
+
+    /// Unexpired leases by stream, expired ones are dropped on the way
+    pub fn list(&self, now: i64) -> Vec<RecordingLease> {
+        let mut leases = self.leases.lock().unwrap();
+        leases.retain(|_, lease| lease.expires_at > now);
+        let mut list: Vec<RecordingLease> = leases.values().cloned().collect();
+        list.sort_by(|a, b| a.stream.cmp(&b.stream));
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: i64 = 1_000_000;
+
+    #[test]
+    fn test_lease_failover() {
+        let table = LeaseTable::default();
+        let t0 = 1_700_000_000 * SECOND;
+        assert!(table.acquire("cam", "edge-1", 30, t0).granted);
+
+        // edge-2 publishes the same stream and is refused while edge-1 renews
+        let refused = table.acquire("cam", "edge-2", 30, t0 + SECOND);
+        assert!(!refused.granted);
+        assert_eq!(refused.lease.unwrap().holder, "edge-1");
+        let renewed = table.acquire("cam", "edge-1", 30, t0 + 20 * SECOND);
+        assert!(renewed.granted);
+        assert_eq!(renewed.lease.as_ref().unwrap().acquired_at, t0);
+        assert_eq!(renewed.lease.unwrap().expires_at, t0 + 50 * SECOND);
+        assert!(!table.acquire("cam", "edge-2", 30, t0 + 49 * SECOND).granted);
+
+        // edge-1 stops renewing, edge-2's retry after the expiry takes over
+        let taken = table.acquire("cam", "edge-2", 30, t0 + 50 * SECOND);
+        assert!(taken.granted);
+        assert_eq!(taken.lease.unwrap().acquired_at, t0 + 50 * SECOND);
+        assert!(!table.acquire("cam", "edge-1", 30, t0 + 51 * SECOND).granted);
+        assert_eq!(table.get("cam", t0 + 51 * SECOND).unwrap().holder, "edge-2");
+
+        // a late release of the old holder keeps edge-2's lease
+        assert!(table.release("cam", "edge-1").lease.is_some());
+        assert_eq!(table.list(t0 + 51 * SECOND).len(), 1);
+        assert!(table.release("cam", "edge-2").lease.is_none());
+        assert!(table.acquire("cam", "edge-1", 30, t0 + 52 * SECOND).granted);
+        assert!(table.list(t0 + 90 * SECOND).is_empty());
+    }
+}
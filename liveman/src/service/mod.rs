@@ -1,2 +1,11 @@
+pub mod dashboard;
 pub mod database;
+#[cfg(feature = "recorder")]
+pub mod file_storage;
+pub mod lease;
+#[cfg(feature = "recorder")]
+pub mod object_proxy;
+#[cfg(feature = "recorder")]
+pub mod presign_audit;
 pub mod recordings_index;
+pub mod sync_cursors;
@@ -0,0 +1,317 @@
+//! Uploads nodes stream through liveman instead of sending them to a presigned URL.
+//!
+//! On networks where nodes can't reach storage, or where presigned URLs must never
+//! leave liveman, `upload.mode = "proxy"` has the uploader `PUT` each object to
+//! `/api/storage/objects/{path}`. Its body is written to storage as it arrives, in
+//! `recorder.proxy_upload.chunk_bytes` writes, so an object is never held in memory
+//! whole. Each node gets `node_concurrency` uploads at a time and shares
+//! `node_bytes_per_second` among them, so one node catching up on a backlog can't take
+//! all of liveman's bandwidth.
+
+use std::collections::HashMap;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::Bytes;
+use axum::http::StatusCode;
+use opendal::Operator;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_stream::{Stream, StreamExt};
+
+use crate::config::ProxyUploadConfig;
+
+/// A proxied upload refused or cut short
+#[derive(Debug)]
+pub enum ProxyError {
+    /// Over `max_object_bytes`, by its `Content-Length` or once that many bytes came
+    TooLarge(u64),
+    /// The node has `node_concurrency` uploads running
+    Busy,
+    /// The body stopped before its end
+    Body(String),
+    Storage(opendal::Error),
+}
+
+impl ProxyError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Busy => StatusCode::TOO_MANY_REQUESTS,
+            Self::Body(_) => StatusCode::BAD_REQUEST,
+            Self::Storage(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+impl std::fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooLarge(max) => write!(
+                f,
+                "object is larger than recorder.proxy_upload.max_object_bytes ({max})"
+            ),
+            Self::Busy => write!(f, "too many uploads of this node"),
+            Self::Body(e) => write!(f, "upload body failed: {e}"),
+            Self::Storage(e) => write!(f, "storage write failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+/// Bytes a node may send, refilled at its rate up to one second's worth. Taking more
+/// than it holds leaves a debt later uploads of the node wait out too
+struct Bucket {
+    rate: u64,
+    tokens: f64,
+    at: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate as f64,
+            at: now,
+        }
+    }
+
+    /// How long to wait at `now` before sending `bytes`
+    fn take(&mut self, bytes: u64, now: Instant) -> Duration {
+        let rate = self.rate as f64;
+        let refill = now.saturating_duration_since(self.at).as_secs_f64() * rate;
+        self.tokens = (self.tokens + refill).min(rate) - bytes as f64;
+        self.at = now;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+struct NodeLimit {
+    uploads: Arc<Semaphore>,
+    /// `None` without `node_bytes_per_second`
+    bucket: Option<Mutex<Bucket>>,
+}
+
+pub struct ObjectProxy {
+    cfg: ProxyUploadConfig,
+    nodes: Mutex<HashMap<String, Arc<NodeLimit>>>,
+}
+
+/// An upload let in by [`ObjectProxy::admit`], counted against its node until dropped
+pub struct Upload {
+    limit: Arc<NodeLimit>,
+    _permit: OwnedSemaphorePermit,
+    max_object_bytes: u64,
+    chunk_bytes: usize,
+}
+
+impl ObjectProxy {
+    pub fn new(cfg: ProxyUploadConfig) -> Self {
+        Self {
+            cfg,
+            nodes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Let an upload of `node` in, refused when its `Content-Length` is already too
+    /// large or the node has as many uploads running as it may
+    pub fn admit(&self, node: &str, content_length: Option<u64>) -> Result<Upload, ProxyError> {
+        let max = self.cfg.max_object_bytes;
+        if max > 0 && content_length.is_some_and(|len| len > max) {
+            return Err(ProxyError::TooLarge(max));
+        }
+        let limit = self
+            .nodes
+            .lock()
+            .unwrap()
+            .entry(node.to_string())
+            .or_insert_with(|| {
+                Arc::new(NodeLimit {
+                    uploads: Arc::new(Semaphore::new(match self.cfg.node_concurrency {
+                        0 => Semaphore::MAX_PERMITS,
+                        n => n,
+                    })),
+                    bucket: (self.cfg.node_bytes_per_second > 0).then(|| {
+                        Mutex::new(Bucket::new(self.cfg.node_bytes_per_second, Instant::now()))
+                    }),
+                })
+            })
+            .clone();
+        let permit = limit
+            .uploads
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| ProxyError::Busy)?;
+        Ok(Upload {
+            limit,
+            _permit: permit,
+            max_object_bytes: max,
+            chunk_bytes: self.cfg.chunk_bytes.max(64 << 10),
+        })
+    }
+}
+
+impl Upload {
+    /// Write `body` to `path` as it arrives, returns the bytes written. Nothing is left
+    /// at `path` when the body fails or grows too large
+    pub async fn write<E: std::fmt::Display>(
+        self,
+        operator: &Operator,
+        path: &str,
+        content_type: &str,
+        body: impl Stream<Item = Result<Bytes, E>>,
+    ) -> Result<u64, ProxyError> {
+        let mut writer = operator
+            .writer_with(path)
+            .content_type(content_type)
+            .chunk(self.chunk_bytes)
+            .await
+            .map_err(ProxyError::Storage)?;
+        let mut body = pin!(body);
+        let mut written = 0u64;
+        let failed = loop {
+            let chunk = match body.next().await {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => break ProxyError::Body(e.to_string()),
+                None => match writer.close().await {
+                    Ok(_) => return Ok(written),
+                    Err(e) => break ProxyError::Storage(e),
+                },
+            };
+            written += chunk.len() as u64;
+            if self.max_object_bytes > 0 && written > self.max_object_bytes {
+                break ProxyError::TooLarge(self.max_object_bytes);
+            }
+            self.throttle(chunk.len() as u64).await;
+            if let Err(e) = writer.write(chunk).await {
+                break ProxyError::Storage(e);
+            }
+        };
+        if let Err(e) = writer.abort().await {
+            tracing::warn!("aborting the proxied upload of {} failed: {}", path, e);
+        }
+        Err(failed)
+    }
+
+    async fn throttle(&self, bytes: u64) {
+        let Some(bucket) = &self.limit.bucket else {
+            return;
+        };
+        let wait = bucket.lock().unwrap().take(bytes, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory() -> Operator {
+        Operator::new(opendal::services::Memory::default())
+            .unwrap()
+            .finish()
+    }
+
+    fn cfg() -> ProxyUploadConfig {
+        ProxyUploadConfig {
+            max_object_bytes: 64 << 20,
+            node_concurrency: 2,
+            node_bytes_per_second: 0,
+            chunk_bytes: 5 << 20,
+        }
+    }
+
+    /// `len` bytes in chunks of 64 KiB, as a chunked request body arrives
+    fn body(len: usize) -> (Vec<u8>, impl Stream<Item = Result<Bytes, std::io::Error>>) {
+        let content: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let chunks: Vec<_> = content
+            .chunks(64 << 10)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        (content, tokio_stream::iter(chunks))
+    }
+
+    #[tokio::test]
+    async fn test_large_object_streamed_to_storage() {
+        let proxy = ObjectProxy::new(cfg());
+        let operator = memory();
+        let (content, stream) = body((48 << 20) + 123);
+        let upload = proxy.admit("edge-1", None).unwrap();
+        let written = upload
+            .write(
+                &operator,
+                "cam/1/v_seg_0001.m4s",
+                "video/iso.segment",
+                stream,
+            )
+            .await
+            .unwrap();
+        assert_eq!(written, content.len() as u64);
+        let stored = operator.read("cam/1/v_seg_0001.m4s").await.unwrap();
+        assert_eq!(stored.to_vec(), content);
+    }
+
+    #[tokio::test]
+    async fn test_too_large_refused_and_aborted() {
+        let proxy = ObjectProxy::new(ProxyUploadConfig {
+            max_object_bytes: 1 << 20,
+            ..cfg()
+        });
+        assert!(matches!(
+            proxy.admit("edge-1", Some(2 << 20)),
+            Err(ProxyError::TooLarge(_))
+        ));
+
+        // Sent chunked, found out as it arrives
+        let operator = memory();
+        let (_, stream) = body(2 << 20);
+        let upload = proxy.admit("edge-1", None).unwrap();
+        let err = upload
+            .write(
+                &operator,
+                "cam/1/v_seg_0001.m4s",
+                "video/iso.segment",
+                stream,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(!operator.exists("cam/1/v_seg_0001.m4s").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_node_concurrency() {
+        let proxy = ObjectProxy::new(cfg());
+        let first = proxy.admit("edge-1", None).unwrap();
+        let _second = proxy.admit("edge-1", None).unwrap();
+        let err = proxy.admit("edge-1", None).err().unwrap();
+        assert_eq!(err.status(), StatusCode::TOO_MANY_REQUESTS);
+        // Other nodes have their own
+        assert!(proxy.admit("edge-2", None).is_ok());
+        drop(first);
+        assert!(proxy.admit("edge-1", None).is_ok());
+    }
+
+    #[test]
+    fn test_bucket() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(1 << 20, start);
+        // A second's worth goes right away
+        assert_eq!(bucket.take(1 << 20, start), Duration::ZERO);
+        assert_eq!(bucket.take(512 << 10, start), Duration::from_millis(500));
+        // Waiting it out pays the debt
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.take(0, later), Duration::ZERO);
+        // An idle node saves up one second at most
+        let idle = later + Duration::from_secs(60);
+        assert_eq!(bucket.take(1 << 20, idle), Duration::ZERO);
+        assert_eq!(bucket.take(1 << 20, idle), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,215 @@
+//! Who was handed a presigned URL for which object, and until when.
+//!
+//! Each presign is kept in a bounded in-memory buffer for
+//! `GET /api/storage/presign-audit`, appended as one JSON line to
+//! `recorder.presign_audit.path` when set, and logged with the `presign_audit` tracing
+//! target when enabled. A presigned URL is a credential for as long as it is valid, so
+//! only what was signed is recorded, never the URL or its signed headers.
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::config::PresignAuditConfig;
+
+/// One presign handed out
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct PresignAuditEntry {
+    /// When it was signed, UNIX microseconds
+    pub ts: i64,
+    /// Method of the presign request, e.g. `PUT` or `UPLOAD_PART`
+    pub method: String,
+    /// Object key
+    pub path: String,
+    pub ttl_seconds: u64,
+    /// When the URL stops working, UNIX microseconds
+    pub expires_at: i64,
+    /// `id` of the requesting JWT, `*` for static tokens and without auth
+    pub subject: String,
+    /// Cluster node whose token signed the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+    /// Behind trusted proxies, the client they forwarded for
+    pub client_ip: String,
+}
+
+pub struct PresignAudit {
+    cfg: PresignAuditConfig,
+    recent: Mutex<VecDeque<PresignAuditEntry>>,
+    /// Appends and rotations one at a time
+    file: tokio::sync::Mutex<()>,
+}
+
+impl PresignAudit {
+    pub fn new(cfg: PresignAuditConfig) -> Self {
+        Self {
+            cfg,
+            recent: Mutex::new(VecDeque::new()),
+            file: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Keep `entry`, failures to write the file are logged: the URL is already out
+    pub async fn record(&self, entry: PresignAuditEntry) {
+        if self.cfg.tracing {
+            tracing::info!(
+                target: "presign_audit",
+                method = %entry.method,
+                path = %entry.path,
+                ttl_seconds = entry.ttl_seconds,
+                expires_at = entry.expires_at,
+                subject = %entry.subject,
+                node = entry.node.as_deref().unwrap_or(""),
+                client_ip = %entry.client_ip,
+                "presigned"
+            );
+        }
+        if !self.cfg.path.is_empty()
+            && let Err(e) = self.append(&entry).await
+        {
+            tracing::error!("appending to the presign audit file failed: {:#}", e);
+        }
+        if self.cfg.buffer > 0 {
+            let mut recent = self.recent.lock().unwrap();
+            while recent.len() >= self.cfg.buffer {
+                recent.pop_front();
+            }
+            recent.push_back(entry);
+        }
+    }
+
+    /// Buffered presigns of keys under `path_prefix` signed after `since` (UNIX
+    /// microseconds), oldest first
+    pub fn query(&self, path_prefix: Option<&str>, since: Option<i64>) -> Vec<PresignAuditEntry> {
+        let path_prefix = path_prefix.map(|p| p.trim_start_matches('/'));
+        self.recent
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| path_prefix.is_none_or(|p| e.path.starts_with(p)))
+            .filter(|e| since.is_none_or(|since| e.ts > since))
+            .cloned()
+            .collect()
+    }
+
+    async fn append(&self, entry: &PresignAuditEntry) -> Result<()> {
+        let _guard = self.file.lock().await;
+        let line = serde_json::to_string(entry)?;
+        let path = PathBuf::from(&self.cfg.path);
+        let (max_bytes, keep) = (self.cfg.max_bytes, self.cfg.keep);
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            if let Some(parent) = path.parent()
+                && !parent.as_os_str().is_empty()
+            {
+                std::fs::create_dir_all(parent)?;
+            }
+            let size = std::fs::metadata(&path).map_or(0, |m| m.len());
+            if max_bytes > 0 && size >= max_bytes {
+                rotate(&path, keep)?;
+            }
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)?;
+            writeln!(file, "{}", line)?;
+            Ok(())
+        })
+        .await?
+    }
+}
+
+/// `{stem}.{n}.{ext}` of the `n`th rotated file
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut name = format!("{stem}.{n}");
+    if let Some(ext) = path.extension() {
+        name.push_str(&format!(".{}", ext.to_string_lossy()));
+    }
+    path.with_file_name(name)
+}
+
+/// Shift rotated file `n` to `n + 1` and the live file to 1, dropping the file past
+/// `keep`
+fn rotate(path: &Path, keep: usize) -> Result<()> {
+    if keep == 0 {
+        std::fs::remove_file(path)?;
+        return Ok(());
+    }
+    match std::fs::remove_file(rotated_path(path, keep)) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    for n in (1..keep).rev() {
+        let from = rotated_path(path, n);
+        if from.exists() {
+            std::fs::rename(&from, rotated_path(path, n + 1))?;
+        }
+    }
+    std::fs::rename(path, rotated_path(path, 1))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(ts: i64, path: &str) -> PresignAuditEntry {
+        PresignAuditEntry {
+            ts,
+            method: "PUT".to_string(),
+            path: path.to_string(),
+            ttl_seconds: 300,
+            expires_at: ts + 300_000_000,
+            subject: "*".to_string(),
+            node: Some("edge-1".to_string()),
+            client_ip: "10.0.0.7".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_buffer_and_rotation() {
+        let dir =
+            std::env::temp_dir().join(format!("liveman-presign-audit-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("presign-audit.jsonl");
+        let audit = PresignAudit::new(PresignAuditConfig {
+            path: path.to_string_lossy().into_owned(),
+            max_bytes: 1,
+            keep: 2,
+            buffer: 3,
+            tracing: false,
+        });
+        for (i, key) in ["cam/1/a.m4s", "cam/1/b.m4s", "door/1/a.m4s", "cam/2/a.m4s"]
+            .into_iter()
+            .enumerate()
+        {
+            audit.record(entry(i as i64, key)).await;
+        }
+
+        // The oldest left the buffer
+        let cam: Vec<String> = audit
+            .query(Some("/cam/"), None)
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        assert_eq!(cam, ["cam/1/b.m4s", "cam/2/a.m4s"]);
+        assert_eq!(audit.query(None, Some(2)).len(), 1);
+
+        // One line per file past max_bytes, the oldest file dropped past keep
+        let lines = |p: PathBuf| std::fs::read_to_string(p).unwrap();
+        assert!(lines(path.clone()).contains("cam/2/a.m4s"));
+        assert!(lines(dir.join("presign-audit.1.jsonl")).contains("door/1/a.m4s"));
+        assert!(lines(dir.join("presign-audit.2.jsonl")).contains("cam/1/b.m4s"));
+        assert!(!dir.join("presign-audit.3.jsonl").exists());
+        assert!(!lines(path).contains("http"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
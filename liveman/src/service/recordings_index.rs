@@ -1,4 +1,5 @@
 use anyhow::Result;
+use api::recorder::{DEFAULT_PRIORITY, MediaInfo, RecordingIndexEntry, RetentionClass};
 use chrono::{FixedOffset, Utc};
 use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
 use uuid::Uuid;
@@ -9,19 +10,58 @@ use crate::entity::recordings::{self, Entity as Recordings};
 pub struct RecordingsIndexService;
 
 impl RecordingsIndexService {
-    pub async fn upsert(
+    /// Row of `stream/record` recorded by `node`.
+    ///
+    /// Nodes recording the same stream name at the same second get a row each. A row
+    /// from before nodes were told apart (empty `node`) is claimed by the first node
+    /// reporting the recording.
+    async fn find_for_node(
         db: &DatabaseConnection,
+        node: &str,
         stream: &str,
         record: &str,
-        mpd_path: &str,
-    ) -> Result<recordings::Model> {
-        if let Some(existing) = Recordings::find()
+    ) -> Result<Option<recordings::Model>> {
+        let rows = Recordings::find()
             .filter(recordings::Column::Stream.eq(stream))
             .filter(recordings::Column::Record.eq(record))
-            .one(db)
+            .filter(recordings::Column::Node.is_in([node, ""]))
+            .all(db)
+            .await?;
+        let exact = rows.iter().position(|row| row.node == node);
+        Ok(match exact {
+            Some(i) => rows.into_iter().nth(i),
+            None => rows.into_iter().next(),
+        })
+    }
+
+    /// Like [`Self::find_for_node`], but `None` for an archived row: node sync leaves
+    /// archived recordings alone
+    async fn find_synced(
+        db: &DatabaseConnection,
+        node: &str,
+        stream: &str,
+        record: &str,
+    ) -> Result<Option<recordings::Model>> {
+        Ok(Self::find_for_node(db, node, stream, record)
             .await?
-        {
+            .filter(|row| row.archived_at.is_none()))
+    }
+
+    /// Write the row of a recording pull sync or a manual start reported, an archived
+    /// row is returned unchanged
+    pub async fn upsert(
+        db: &DatabaseConnection,
+        node: &str,
+        stream: &str,
+        record: &str,
+        mpd_path: &str,
+    ) -> Result<recordings::Model> {
+        if let Some(existing) = Self::find_for_node(db, node, stream, record).await? {
+            if existing.archived_at.is_some() {
+                return Ok(existing);
+            }
             let mut am: recordings::ActiveModel = existing.into();
+            am.node = Set(node.to_string());
             am.mpd_path = Set(mpd_path.to_string());
             am.updated_at = Set(Utc::now().with_timezone(&FixedOffset::east_opt(0).unwrap()));
             Ok(am.update(db).await?)
@@ -34,11 +74,327 @@ impl RecordingsIndexService {
                 mpd_path: Set(mpd_path.to_string()),
                 created_at: Set(now_fixed),
                 updated_at: Set(now_fixed),
+                source_updated_at: Set(None),
+                media_info: Set(None),
+                retention_class: Set(None),
+                trashed_at: Set(None),
+                node: Set(node.to_string()),
+                priority: Set(DEFAULT_PRIORITY as i16),
+                recording_uuid: Set(None),
+                tenant: Set(None),
+                archived_at: Set(None),
+                source_seq: Set(None),
             };
             Ok(am.insert(db).await?)
         }
     }
 
+    /// Apply an index entry pushed by the liveion node `node`.
+    ///
+    /// Idempotent on (stream, record, node, seq): an entry not past the journal position
+    /// last applied to the row is a duplicate or arrived out of order and is skipped, as
+    /// is any entry for an archived row. Entries of nodes predating `seq` are compared
+    /// by `updated_at`, where two writes within a microsecond look like one. Returns
+    /// whether the row was written.
+    pub async fn apply_pushed(
+        db: &DatabaseConnection,
+        node: &str,
+        entry: &RecordingIndexEntry,
+    ) -> Result<bool> {
+        let now_fixed = Utc::now().with_timezone(&FixedOffset::east_opt(0).unwrap());
+        match Self::find_for_node(db, node, &entry.stream, &entry.record).await? {
+            Some(existing) if already_applied(&existing, entry) => Ok(false),
+            Some(existing) if existing.archived_at.is_some() => Ok(false),
+            Some(existing) => {
+                let trashed = existing.trashed_at.is_some();
+                let mut am: recordings::ActiveModel = existing.into();
+                am.node = Set(node.to_string());
+                am.mpd_path = Set(entry.mpd_path.clone());
+                am.updated_at = Set(now_fixed);
+                am.source_updated_at = Set(Some(entry.updated_at));
+                am.source_seq = Set(source_seq(entry));
+                am.media_info = Set(encode_media_info(&entry.media_info));
+                am.retention_class = Set(entry.retention_class.as_ref().map(|c| c.to_string()));
+                am.priority = Set(entry.priority as i16);
+                if !entry.uuid.is_empty() {
+                    am.recording_uuid = Set(Some(entry.uuid.clone()));
+                }
+                am.tenant = Set(entry.tenant.clone());
+                // Only the catalog's own restore takes a row out of the trash
+                if !trashed && entry.is_trashed() {
+                    am.trashed_at = Set(entry.trashed_at);
+                }
+                am.update(db).await?;
+                Ok(true)
+            }
+            None => {
+                let am = recordings::ActiveModel {
+                    id: Set(Uuid::new_v4()),
+                    stream: Set(entry.stream.clone()),
+                    record: Set(entry.record.clone()),
+                    mpd_path: Set(entry.mpd_path.clone()),
+                    created_at: Set(now_fixed),
+                    updated_at: Set(now_fixed),
+                    source_updated_at: Set(Some(entry.updated_at)),
+                    media_info: Set(encode_media_info(&entry.media_info)),
+                    retention_class: Set(entry.retention_class.as_ref().map(|c| c.to_string())),
+                    trashed_at: Set(entry.trashed_at.filter(|_| entry.is_trashed())),
+                    node: Set(node.to_string()),
+                    priority: Set(entry.priority as i16),
+                    recording_uuid: Set(Some(entry.uuid.clone()).filter(|u| !u.is_empty())),
+                    tenant: Set(entry.tenant.clone()),
+                    archived_at: Set(None),
+                    source_seq: Set(source_seq(entry)),
+                };
+                am.insert(db).await?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Replace the track formats of a row written by pull sync, a no-op for unknown rows
+    pub async fn set_media_info(
+        db: &DatabaseConnection,
+        node: &str,
+        stream: &str,
+        record: &str,
+        media_info: &[MediaInfo],
+    ) -> Result<()> {
+        if let Some(existing) = Self::find_synced(db, node, stream, record).await? {
+            let mut am: recordings::ActiveModel = existing.into();
+            am.media_info = Set(encode_media_info(media_info));
+            am.update(db).await?;
+        }
+        Ok(())
+    }
+
+    /// Set the retention class of a row written by pull sync or a manual start, a no-op
+    /// for unknown rows
+    pub async fn set_retention_class(
+        db: &DatabaseConnection,
+        node: &str,
+        stream: &str,
+        record: &str,
+        class: &RetentionClass,
+    ) -> Result<()> {
+        if let Some(existing) = Self::find_synced(db, node, stream, record).await?
+            && existing.retention_class.as_deref() != Some(class.as_str())
+        {
+            let mut am: recordings::ActiveModel = existing.into();
+            am.retention_class = Set(Some(class.to_string()));
+            am.update(db).await?;
+        }
+        Ok(())
+    }
+
+    /// Set the priority of a row written by pull sync or a manual start, a no-op for
+    /// unknown rows
+    pub async fn set_priority(
+        db: &DatabaseConnection,
+        node: &str,
+        stream: &str,
+        record: &str,
+        priority: u8,
+    ) -> Result<()> {
+        if let Some(existing) = Self::find_synced(db, node, stream, record).await?
+            && existing.priority != priority as i16
+        {
+            let mut am: recordings::ActiveModel = existing.into();
+            am.priority = Set(priority as i16);
+            am.update(db).await?;
+        }
+        Ok(())
+    }
+
+    /// Set the uuid the node gave a row written by pull sync or a manual start, a no-op
+    /// for unknown rows
+    pub async fn set_recording_uuid(
+        db: &DatabaseConnection,
+        node: &str,
+        stream: &str,
+        record: &str,
+        uuid: &str,
+    ) -> Result<()> {
+        if let Some(existing) = Self::find_synced(db, node, stream, record).await?
+            && existing.recording_uuid.as_deref() != Some(uuid)
+        {
+            let mut am: recordings::ActiveModel = existing.into();
+            am.recording_uuid = Set(Some(uuid.to_string()));
+            am.update(db).await?;
+        }
+        Ok(())
+    }
+
+    /// Set the tenant of a row written by pull sync, a no-op for unknown rows
+    pub async fn set_tenant(
+        db: &DatabaseConnection,
+        node: &str,
+        stream: &str,
+        record: &str,
+        tenant: Option<&str>,
+    ) -> Result<()> {
+        if let Some(existing) = Self::find_synced(db, node, stream, record).await?
+            && existing.tenant.as_deref() != tenant
+        {
+            let mut am: recordings::ActiveModel = existing.into();
+            am.tenant = Set(tenant.map(str::to_string));
+            am.update(db).await?;
+        }
+        Ok(())
+    }
+
+    /// Row of the recording whose node gave it `uuid`
+    pub async fn find_by_recording_uuid(
+        db: &DatabaseConnection,
+        uuid: &str,
+    ) -> Result<Option<recordings::Model>> {
+        Ok(Recordings::find()
+            .filter(recordings::Column::RecordingUuid.eq(uuid))
+            .one(db)
+            .await?)
+    }
+
+    /// Mark a row trashed on its node, a no-op for unknown rows or rows already trashed
+    pub async fn mark_trashed(
+        db: &DatabaseConnection,
+        node: &str,
+        stream: &str,
+        record: &str,
+        trashed_at: i64,
+    ) -> Result<()> {
+        if let Some(existing) = Self::find_synced(db, node, stream, record).await?
+            && existing.trashed_at.is_none()
+        {
+            let mut am: recordings::ActiveModel = existing.into();
+            am.trashed_at = Set(Some(trashed_at));
+            am.update(db).await?;
+        }
+        Ok(())
+    }
+
+    /// Move a row to the trash, a row already there keeps its `trashed_at`
+    pub async fn trash(
+        db: &DatabaseConnection,
+        row: recordings::Model,
+    ) -> Result<recordings::Model> {
+        if row.trashed_at.is_some() {
+            return Ok(row);
+        }
+        let mut am: recordings::ActiveModel = row.into();
+        am.trashed_at = Set(Some(Utc::now().timestamp_micros()));
+        am.updated_at = Set(Utc::now().with_timezone(&FixedOffset::east_opt(0).unwrap()));
+        Ok(am.update(db).await?)
+    }
+
+    /// Take a row out of the trash
+    pub async fn restore(
+        db: &DatabaseConnection,
+        row: recordings::Model,
+    ) -> Result<recordings::Model> {
+        let mut am: recordings::ActiveModel = row.into();
+        am.trashed_at = Set(None);
+        am.updated_at = Set(Utc::now().with_timezone(&FixedOffset::east_opt(0).unwrap()));
+        Ok(am.update(db).await?)
+    }
+
+    /// Rows recorded by a node missing from `registered`, only those of `node` when
+    /// given. Rows from before nodes were told apart have no node and are never orphaned.
+    pub async fn orphaned(
+        db: &DatabaseConnection,
+        registered: &[String],
+        node: Option<&str>,
+        include_archived: bool,
+    ) -> Result<Vec<recordings::Model>> {
+        let mut query = Recordings::find()
+            .filter(recordings::Column::Node.ne(""))
+            .filter(recordings::Column::Node.is_not_in(registered.iter().map(String::as_str)));
+        if let Some(node) = node {
+            query = query.filter(recordings::Column::Node.eq(node));
+        }
+        if !include_archived {
+            query = query.filter(recordings::Column::ArchivedAt.is_null());
+        }
+        Ok(query.all(db).await?)
+    }
+
+    /// Archive a row, a row already archived keeps its `archived_at`
+    pub async fn archive(
+        db: &DatabaseConnection,
+        row: recordings::Model,
+    ) -> Result<recordings::Model> {
+        if row.archived_at.is_some() {
+            return Ok(row);
+        }
+        let mut am: recordings::ActiveModel = row.into();
+        am.archived_at = Set(Some(Utc::now().timestamp_micros()));
+        am.updated_at = Set(Utc::now().with_timezone(&FixedOffset::east_opt(0).unwrap()));
+        Ok(am.update(db).await?)
+    }
+
+    /// Rows moved to the trash at or before `before` (UNIX microseconds)
+    pub async fn trashed_before(
+        db: &DatabaseConnection,
+        before: i64,
+    ) -> Result<Vec<recordings::Model>> {
+        Ok(Recordings::find()
+            .filter(recordings::Column::TrashedAt.lte(before))
+            .all(db)
+            .await?)
+    }
+
+    /// Rows of `stream/record`, one per node that recorded it
+    pub async fn find_all(
+        db: &DatabaseConnection,
+        stream: &str,
+        record: &str,
+    ) -> Result<Vec<recordings::Model>> {
+        Ok(Recordings::find()
+            .filter(recordings::Column::Stream.eq(stream))
+            .filter(recordings::Column::Record.eq(record))
+            .all(db)
+            .await?)
+    }
+
+    /// Move catalog rows of `from` to `to` once the nodes renamed their recordings.
+    ///
+    /// A row already pushed or pulled under `to` wins over the old one. With
+    /// `moved_objects` manifests under `{from}/` are rewritten to where the nodes
+    /// copied them. Returns how many rows were moved or merged.
+    pub async fn rename_stream(
+        db: &DatabaseConnection,
+        from: &str,
+        to: &str,
+        moved_objects: bool,
+    ) -> Result<usize> {
+        let now_fixed = Utc::now().with_timezone(&FixedOffset::east_opt(0).unwrap());
+        let rows = Self::list_by_stream(db, from).await?;
+        let count = rows.len();
+        for row in rows {
+            let exists = Recordings::find()
+                .filter(recordings::Column::Stream.eq(to))
+                .filter(recordings::Column::Record.eq(&row.record))
+                .filter(recordings::Column::Node.eq(&row.node))
+                .one(db)
+                .await?
+                .is_some();
+            if exists {
+                Recordings::delete_by_id(row.id).exec(db).await?;
+                continue;
+            }
+            let mpd_path = if moved_objects {
+                moved_mpd_path(&row.mpd_path, &row.node, from, to)
+            } else {
+                row.mpd_path.clone()
+            };
+            let mut am: recordings::ActiveModel = row.into();
+            am.stream = Set(to.to_string());
+            am.mpd_path = Set(mpd_path);
+            am.updated_at = Set(now_fixed);
+            am.update(db).await?;
+        }
+        Ok(count)
+    }
+
     pub async fn list_by_stream(
         db: &DatabaseConnection,
         stream: &str,
@@ -49,3 +405,473 @@ impl RecordingsIndexService {
             .await?)
     }
 }
+
+/// Manifest of a row after its node copied the objects to `to`, generated record dirs
+/// may sit under the node's key namespace (by default its alias)
+fn moved_mpd_path(mpd_path: &str, node: &str, from: &str, to: &str) -> String {
+    if let Some(rest) = mpd_path.strip_prefix(&format!("{from}/")) {
+        return format!("{to}/{rest}");
+    }
+    if !node.is_empty()
+        && let Some(rest) = mpd_path.strip_prefix(&format!("{node}/{from}/"))
+    {
+        return format!("{node}/{to}/{rest}");
+    }
+    mpd_path.to_string()
+}
+
+/// Stored as a JSON array, `None` while no format is known
+/// Whether `row` already holds `entry` or a later write of its recording
+fn already_applied(row: &recordings::Model, entry: &RecordingIndexEntry) -> bool {
+    // A node whose index was rebuilt numbers its writes from the start again, but its
+    // recordings have new uuids
+    let same_recording = entry.uuid.is_empty()
+        || row
+            .recording_uuid
+            .as_ref()
+            .is_none_or(|uuid| *uuid == entry.uuid);
+    match (row.source_seq, source_seq(entry)) {
+        (Some(applied), Some(seq)) if same_recording => applied >= seq,
+        _ => row.source_updated_at >= Some(entry.updated_at),
+    }
+}
+
+fn source_seq(entry: &RecordingIndexEntry) -> Option<i64> {
+    Some(entry.seq as i64).filter(|seq| *seq > 0)
+}
+
+fn encode_media_info(media_info: &[MediaInfo]) -> Option<String> {
+    if media_info.is_empty() {
+        return None;
+    }
+    serde_json::to_string(media_info).ok()
+}
+
+/// Track formats of a catalog row, empty when unknown or unreadable
+pub fn decode_media_info(row: &recordings::Model) -> Vec<MediaInfo> {
+    row.media_info
+        .as_deref()
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migration::Migrator;
+    use api::recorder::RecordingStatus;
+    use sea_orm::Database;
+    use sea_orm_migration::MigratorTrait;
+
+    async fn database() -> DatabaseConnection {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        db
+    }
+
+    fn entry(mpd_path: &str, updated_at: i64) -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            uuid: String::new(),
+            record: "1700000000".to_string(),
+            stream: "cam".to_string(),
+            record_dir: "cam/1700000000".to_string(),
+            mpd_path: mpd_path.to_string(),
+            start_ts: 1_700_000_000_000_000,
+            end_ts: None,
+            duration_ms: None,
+            status: RecordingStatus::Active,
+            node_alias: Some("edge-1".to_string()),
+            updated_at,
+            note: None,
+            labels: Vec::new(),
+            continues: None,
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant: None,
+            size: None,
+            captions: Vec::new(),
+            trigger: None,
+            imported: false,
+            seq: 0,
+        }
+    }
+
+    async fn mpd_path(db: &DatabaseConnection) -> String {
+        let rows = RecordingsIndexService::list_by_stream(db, "cam")
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        rows[0].mpd_path.clone()
+    }
+
+    #[tokio::test]
+    async fn test_apply_pushed_duplicate_delivery() {
+        let db = database().await;
+        let first = entry("cam/1700000000/manifest.mpd", 10);
+        assert!(
+            RecordingsIndexService::apply_pushed(&db, "edge-1", &first)
+                .await
+                .unwrap()
+        );
+        // The same batch delivered again after a lost response
+        assert!(
+            !RecordingsIndexService::apply_pushed(&db, "edge-1", &first)
+                .await
+                .unwrap()
+        );
+        assert_eq!(mpd_path(&db).await, "cam/1700000000/manifest.mpd");
+    }
+
+    #[tokio::test]
+    async fn test_apply_pushed_out_of_order() {
+        let db = database().await;
+        let newer = entry("moved/manifest.mpd", 20);
+        let older = entry("cam/1700000000/manifest.mpd", 10);
+        assert!(
+            RecordingsIndexService::apply_pushed(&db, "edge-1", &newer)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !RecordingsIndexService::apply_pushed(&db, "edge-1", &older)
+                .await
+                .unwrap()
+        );
+        assert_eq!(mpd_path(&db).await, "moved/manifest.mpd");
+
+        let newest = entry("final/manifest.mpd", 30);
+        assert!(
+            RecordingsIndexService::apply_pushed(&db, "edge-1", &newest)
+                .await
+                .unwrap()
+        );
+        assert_eq!(mpd_path(&db).await, "final/manifest.mpd");
+    }
+
+    /// Two writes within the same microsecond are told apart by their journal position
+    #[tokio::test]
+    async fn test_apply_pushed_by_seq() {
+        let db = database().await;
+        let mut first = entry("cam/1700000000/manifest.mpd", 10);
+        first.seq = 7;
+        let mut second = entry("moved/manifest.mpd", 10);
+        second.seq = 8;
+        for (pushed, applied) in [(&first, true), (&second, true), (&first, false)] {
+            assert_eq!(
+                RecordingsIndexService::apply_pushed(&db, "edge-1", pushed)
+                    .await
+                    .unwrap(),
+                applied
+            );
+        }
+        assert_eq!(mpd_path(&db).await, "moved/manifest.mpd");
+
+        // Without `seq` the entry is compared by `updated_at`
+        let legacy = entry("legacy/manifest.mpd", 5);
+        assert!(
+            !RecordingsIndexService::apply_pushed(&db, "edge-1", &legacy)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply_pushed_after_pull_sync() {
+        let db = database().await;
+        RecordingsIndexService::upsert(
+            &db,
+            "edge-1",
+            "cam",
+            "1700000000",
+            "cam/1700000000/manifest.mpd",
+        )
+        .await
+        .unwrap();
+        // Rows from pull sync carry no source timestamp, any push is newer
+        let pushed = entry("cam/1700000000/manifest.mpd", 10);
+        assert!(
+            RecordingsIndexService::apply_pushed(&db, "edge-1", &pushed)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !RecordingsIndexService::apply_pushed(&db, "edge-1", &pushed)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply_pushed_keeps_media_info() {
+        let db = database().await;
+        let mut pushed = entry("cam/1700000000/manifest.mpd", 10);
+        pushed.media_info = vec![MediaInfo {
+            since_ts: 1_700_000_000_000_000,
+            video: Some(api::recorder::VideoInfo {
+                codec: "avc1.42E01F".to_string(),
+                width: 1280,
+                height: 720,
+                framerate: Some(30.0),
+            }),
+            audio: None,
+        }];
+        RecordingsIndexService::apply_pushed(&db, "edge-1", &pushed)
+            .await
+            .unwrap();
+        let rows = RecordingsIndexService::list_by_stream(&db, "cam")
+            .await
+            .unwrap();
+        assert_eq!(decode_media_info(&rows[0]), pushed.media_info);
+
+        // Pull sync rows start without formats
+        RecordingsIndexService::upsert(&db, "edge-1", "cam", "1", "cam/1/manifest.mpd")
+            .await
+            .unwrap();
+        let row = RecordingsIndexService::list_by_stream(&db, "cam")
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|r| r.record == "1")
+            .unwrap();
+        assert!(decode_media_info(&row).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_retention_class_from_push_and_pull() {
+        let db = database().await;
+        let mut pushed = entry("cam/1700000000/manifest.mpd", 10);
+        pushed.retention_class = Some("30d".parse().unwrap());
+        pushed.priority = 20;
+        RecordingsIndexService::apply_pushed(&db, "edge-1", &pushed)
+            .await
+            .unwrap();
+
+        RecordingsIndexService::upsert(&db, "edge-1", "cam", "1", "cam/1/manifest.mpd")
+            .await
+            .unwrap();
+        RecordingsIndexService::set_retention_class(
+            &db,
+            "edge-1",
+            "cam",
+            "1",
+            &"1y".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+        RecordingsIndexService::set_priority(&db, "edge-1", "cam", "1", 250)
+            .await
+            .unwrap();
+
+        let mut rows = RecordingsIndexService::list_by_stream(&db, "cam")
+            .await
+            .unwrap();
+        rows.sort_by(|a, b| a.record.cmp(&b.record));
+        assert_eq!(rows[0].retention_class.as_deref(), Some("1y"));
+        assert_eq!(rows[1].retention_class.as_deref(), Some("30d"));
+        assert_eq!(rows[0].priority, 250);
+        assert_eq!(rows[1].priority, 20);
+    }
+
+    #[tokio::test]
+    async fn test_rename_stream_moves_rows() {
+        let db = database().await;
+        RecordingsIndexService::upsert(&db, "edge-1", "cam", "1", "cam/1/manifest.mpd")
+            .await
+            .unwrap();
+        RecordingsIndexService::upsert(&db, "edge-1", "cam", "2", "archive/2/manifest.mpd")
+            .await
+            .unwrap();
+        // Already pushed by the node under the new name
+        RecordingsIndexService::upsert(&db, "edge-1", "lobby", "2", "archive/2/manifest.mpd")
+            .await
+            .unwrap();
+
+        let moved = RecordingsIndexService::rename_stream(&db, "cam", "lobby", true)
+            .await
+            .unwrap();
+        assert_eq!(moved, 2);
+        assert!(
+            RecordingsIndexService::list_by_stream(&db, "cam")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let mut rows = RecordingsIndexService::list_by_stream(&db, "lobby")
+            .await
+            .unwrap();
+        rows.sort_by(|a, b| a.record.cmp(&b.record));
+        let paths: Vec<_> = rows.iter().map(|r| r.mpd_path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec!["lobby/1/manifest.mpd", "archive/2/manifest.mpd"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trash_from_catalog_and_node() {
+        let db = database().await;
+        RecordingsIndexService::upsert(&db, "edge-1", "cam", "1", "cam/1/manifest.mpd")
+            .await
+            .unwrap();
+        let row = RecordingsIndexService::find_all(&db, "cam", "1")
+            .await
+            .unwrap()
+            .remove(0);
+        let trashed = RecordingsIndexService::trash(&db, row).await.unwrap();
+        let trashed_at = trashed.trashed_at.unwrap();
+        // Trashing again keeps the original time
+        let again = RecordingsIndexService::trash(&db, trashed).await.unwrap();
+        assert_eq!(again.trashed_at, Some(trashed_at));
+        assert_eq!(
+            RecordingsIndexService::trashed_before(&db, trashed_at)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        let restored = RecordingsIndexService::restore(&db, again).await.unwrap();
+        assert!(restored.trashed_at.is_none());
+
+        let mut pushed = entry("cam/1700000000/manifest.mpd", 10);
+        pushed.status = RecordingStatus::Trashed;
+        pushed.trashed_at = Some(20);
+        pushed.trashed_from = Some(RecordingStatus::Completed);
+        RecordingsIndexService::apply_pushed(&db, "edge-1", &pushed)
+            .await
+            .unwrap();
+        // A later push of the restored entry leaves the catalog's trash alone
+        let mut restored = entry("cam/1700000000/manifest.mpd", 30);
+        restored.status = RecordingStatus::Completed;
+        RecordingsIndexService::apply_pushed(&db, "edge-1", &restored)
+            .await
+            .unwrap();
+        let row = RecordingsIndexService::find_all(&db, "cam", "1700000000")
+            .await
+            .unwrap()
+            .remove(0);
+        assert_eq!(row.trashed_at, Some(20));
+    }
+
+    #[tokio::test]
+    async fn test_same_recording_on_two_nodes() {
+        let db = database().await;
+        // A row synced before nodes were told apart is claimed by the first node
+        RecordingsIndexService::upsert(&db, "", "cam", "1", "cam/1/manifest.mpd")
+            .await
+            .unwrap();
+        RecordingsIndexService::upsert(&db, "edge-1", "cam", "1", "edge-1/cam/1/manifest.mpd")
+            .await
+            .unwrap();
+        RecordingsIndexService::upsert(&db, "edge-2", "cam", "1", "edge-2/cam/1/manifest.mpd")
+            .await
+            .unwrap();
+        let mut rows = RecordingsIndexService::find_all(&db, "cam", "1")
+            .await
+            .unwrap();
+        rows.sort_by(|a, b| a.node.cmp(&b.node));
+        let nodes: Vec<_> = rows.iter().map(|r| r.node.as_str()).collect();
+        assert_eq!(nodes, vec!["edge-1", "edge-2"]);
+        assert_eq!(rows[1].mpd_path, "edge-2/cam/1/manifest.mpd");
+
+        RecordingsIndexService::rename_stream(&db, "cam", "lobby", true)
+            .await
+            .unwrap();
+        let mut rows = RecordingsIndexService::find_all(&db, "lobby", "1")
+            .await
+            .unwrap();
+        rows.sort_by(|a, b| a.node.cmp(&b.node));
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].mpd_path, "edge-1/lobby/1/manifest.mpd");
+    }
+
+    #[tokio::test]
+    async fn test_node_removed_from_registry() {
+        let db = database().await;
+        RecordingsIndexService::upsert(&db, "", "cam", "0", "cam/0/manifest.mpd")
+            .await
+            .unwrap();
+        for node in ["edge-1", "edge-2"] {
+            RecordingsIndexService::apply_pushed(
+                &db,
+                node,
+                &entry("cam/1700000000/manifest.mpd", 10),
+            )
+            .await
+            .unwrap();
+        }
+        let registered = vec!["edge-1".to_string(), "edge-2".to_string()];
+        assert!(
+            RecordingsIndexService::orphaned(&db, &registered, None, false)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        // edge-2 is decommissioned, its recording stays in the catalog
+        let registered = vec!["edge-1".to_string()];
+        let orphaned = RecordingsIndexService::orphaned(&db, &registered, None, false)
+            .await
+            .unwrap();
+        assert_eq!(orphaned.len(), 1);
+        assert_eq!(orphaned[0].node, "edge-2");
+        assert!(
+            RecordingsIndexService::orphaned(&db, &registered, Some("edge-1"), false)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let archived = RecordingsIndexService::archive(&db, orphaned.into_iter().next().unwrap())
+            .await
+            .unwrap();
+        let archived_at = archived.archived_at.unwrap();
+        assert!(
+            RecordingsIndexService::orphaned(&db, &registered, None, false)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            RecordingsIndexService::orphaned(&db, &registered, None, true)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // A node registering again under the alias doesn't sync over the archived row
+        let mut late = entry("edge-2/cam/1700000000/manifest.mpd", 20);
+        late.priority = 9;
+        assert!(
+            !RecordingsIndexService::apply_pushed(&db, "edge-2", &late)
+                .await
+                .unwrap()
+        );
+        RecordingsIndexService::upsert(&db, "edge-2", "cam", "1700000000", "edge-2/x.mpd")
+            .await
+            .unwrap();
+        RecordingsIndexService::set_priority(&db, "edge-2", "cam", "1700000000", 9)
+            .await
+            .unwrap();
+        RecordingsIndexService::mark_trashed(&db, "edge-2", "cam", "1700000000", 30)
+            .await
+            .unwrap();
+        let mut rows = RecordingsIndexService::find_all(&db, "cam", "1700000000")
+            .await
+            .unwrap();
+        rows.sort_by(|a, b| a.node.cmp(&b.node));
+        assert_eq!(rows.len(), 2);
+        let row = &rows[1];
+        assert_eq!(row.mpd_path, "cam/1700000000/manifest.mpd");
+        assert_eq!(row.priority, DEFAULT_PRIORITY as i16);
+        assert_eq!(row.trashed_at, None);
+        assert_eq!(row.archived_at, Some(archived_at));
+    }
+}
@@ -0,0 +1,246 @@
+//! Record sync cursors of the nodes, kept in the catalog database.
+//!
+//! Pull sync asks each node for the entries updated after its cursor and moves the
+//! cursor once the node acked them. Kept in memory only, a restarted liveman pulled
+//! every node from the start again before its catalog caught up. The cursors are
+//! written through to `record_sync_cursors` and loaded at startup, so sync resumes
+//! where it stopped. The catalog rows themselves always were in the database.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{FixedOffset, Utc};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use tokio::sync::RwLock;
+
+use crate::entity::record_sync_cursors::{self, Entity as Cursors};
+
+#[derive(Debug, Default)]
+pub struct SyncCursors {
+    /// Same as the table, read without a query on every pull
+    cursors: RwLock<HashMap<String, i64>>,
+}
+
+impl SyncCursors {
+    pub async fn load(db: &DatabaseConnection) -> Result<Self> {
+        let cursors = Cursors::find()
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|row| (row.node, row.since_ts))
+            .collect();
+        Ok(Self {
+            cursors: RwLock::new(cursors),
+        })
+    }
+
+    pub async fn get(&self, node: &str) -> Option<i64> {
+        self.cursors.read().await.get(node).copied()
+    }
+
+    /// Move the cursor of `node` to `since_ts`. Moved in memory even when the write
+    /// fails, a restart then pulls entries the catalog already has, which is harmless
+    pub async fn advance(&self, db: &DatabaseConnection, node: &str, since_ts: i64) -> Result<()> {
+        self.cursors
+            .write()
+            .await
+            .insert(node.to_string(), since_ts);
+        let row = record_sync_cursors::ActiveModel {
+            node: Set(node.to_string()),
+            since_ts: Set(since_ts),
+            updated_at: Set(Utc::now().with_timezone(&FixedOffset::east_opt(0).unwrap())),
+        };
+        Cursors::insert(row)
+            .on_conflict(
+                OnConflict::column(record_sync_cursors::Column::Node)
+                    .update_columns([
+                        record_sync_cursors::Column::SinceTs,
+                        record_sync_cursors::Column::UpdatedAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Drop the cursors of nodes missing from `registered`. Their rows stay in the
+    /// catalog, a node registered again is pulled from the start: it still holds every
+    /// entry it has not seen acked. Returns how many were dropped.
+    pub async fn prune(&self, db: &DatabaseConnection, registered: &[String]) -> Result<usize> {
+        let stale: Vec<String> = {
+            let cursors = self.cursors.read().await;
+            cursors
+                .keys()
+                .filter(|node| !registered.contains(node))
+                .cloned()
+                .collect()
+        };
+        if stale.is_empty() {
+            return Ok(0);
+        }
+        Cursors::delete_many()
+            .filter(record_sync_cursors::Column::Node.is_in(stale.iter().map(String::as_str)))
+            .exec(db)
+            .await?;
+        let mut cursors = self.cursors.write().await;
+        for node in &stale {
+            cursors.remove(node);
+        }
+        Ok(stale.len())
+    }
+
+    /// Forget every cursor, so the next sync pulls each node from the start
+    pub async fn reset(&self, db: &DatabaseConnection) -> Result<u64> {
+        let deleted = Cursors::delete_many().exec(db).await?.rows_affected;
+        self.cursors.write().await.clear();
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migration::Migrator;
+    use crate::service::recordings_index::RecordingsIndexService;
+    use api::recorder::{DEFAULT_PRIORITY, RecordingIndexEntry, RecordingStatus};
+    use sea_orm::Database;
+    use sea_orm_migration::MigratorTrait;
+
+    async fn database() -> DatabaseConnection {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        db
+    }
+
+    fn entry(record: &str, status: RecordingStatus, updated_at: i64) -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            uuid: String::new(),
+            record: record.to_string(),
+            stream: "cam".to_string(),
+            record_dir: format!("cam/{record}"),
+            mpd_path: format!("cam/{record}/manifest.mpd"),
+            start_ts: 1_700_000_000_000_000,
+            end_ts: None,
+            duration_ms: None,
+            status,
+            node_alias: Some("edge-1".to_string()),
+            updated_at,
+            note: None,
+            labels: Vec::new(),
+            continues: None,
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant: None,
+            size: None,
+            captions: Vec::new(),
+            trigger: None,
+            imported: false,
+            seq: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cursors_survive_restart() {
+        let db = database().await;
+        let cursors = SyncCursors::load(&db).await.unwrap();
+        assert_eq!(cursors.get("edge-1").await, None);
+        cursors.advance(&db, "edge-1", 10).await.unwrap();
+        cursors.advance(&db, "edge-1", 20).await.unwrap();
+        cursors.advance(&db, "edge-2", 5).await.unwrap();
+
+        // A restarted liveman resumes after the last acked entry of each node
+        let cursors = SyncCursors::load(&db).await.unwrap();
+        assert_eq!(cursors.get("edge-1").await, Some(20));
+        assert_eq!(cursors.get("edge-2").await, Some(5));
+
+        // edge-2 left the registry
+        let registered = vec!["edge-1".to_string()];
+        assert_eq!(cursors.prune(&db, &registered).await.unwrap(), 1);
+        assert_eq!(cursors.prune(&db, &registered).await.unwrap(), 0);
+        let cursors = SyncCursors::load(&db).await.unwrap();
+        assert_eq!(cursors.get("edge-2").await, None);
+
+        // --rebuild-catalog
+        assert_eq!(cursors.reset(&db).await.unwrap(), 1);
+        assert_eq!(
+            SyncCursors::load(&db).await.unwrap().get("edge-1").await,
+            None
+        );
+    }
+
+    /// Pushes lost while liveman was down: the pull after the restart starts at the
+    /// persisted cursor and brings the catalog to what the node holds, pushes delivered
+    /// again on top of it change nothing
+    #[tokio::test]
+    async fn test_missed_pushes_reconciled_after_restart() {
+        let db = database().await;
+        let cursors = SyncCursors::load(&db).await.unwrap();
+        let started = entry("1", RecordingStatus::Active, 10);
+        assert!(
+            RecordingsIndexService::apply_pushed(&db, "edge-1", &started)
+                .await
+                .unwrap()
+        );
+        cursors.advance(&db, "edge-1", 10).await.unwrap();
+        drop(cursors);
+
+        // Missed: the recording moved, a second one started
+        let mut moved = entry("1", RecordingStatus::Completed, 20);
+        moved.mpd_path = "archive/1/manifest.mpd".to_string();
+        let second = entry("2", RecordingStatus::Active, 30);
+        let node = [started.clone(), moved.clone(), second.clone()];
+
+        let cursors = SyncCursors::load(&db).await.unwrap();
+        let since = cursors.get("edge-1").await;
+        let pulled: Vec<&RecordingIndexEntry> = node
+            .iter()
+            .filter(|e| since.is_none_or(|since| e.updated_at > since))
+            .collect();
+        assert_eq!(pulled.len(), 2);
+        for entry in &pulled {
+            assert!(
+                RecordingsIndexService::apply_pushed(&db, "edge-1", entry)
+                    .await
+                    .unwrap()
+            );
+        }
+        cursors.advance(&db, "edge-1", 30).await.unwrap();
+
+        // Late redeliveries of the pushes
+        for entry in [&started, &moved, &second] {
+            assert!(
+                !RecordingsIndexService::apply_pushed(&db, "edge-1", entry)
+                    .await
+                    .unwrap()
+            );
+        }
+        let mut rows = RecordingsIndexService::list_by_stream(&db, "cam")
+            .await
+            .unwrap();
+        rows.sort_by(|a, b| a.record.cmp(&b.record));
+        let rows: Vec<_> = rows
+            .iter()
+            .map(|r| (r.record.as_str(), r.mpd_path.as_str(), r.source_updated_at))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("1", "archive/1/manifest.mpd", Some(20)),
+                ("2", "cam/2/manifest.mpd", Some(30)),
+            ]
+        );
+        assert_eq!(
+            SyncCursors::load(&db).await.unwrap().get("edge-1").await,
+            Some(30)
+        );
+    }
+}
@@ -8,10 +8,12 @@ use http::header;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error, trace, warn};
 
+use api::recorder::RecorderCapabilities;
 use api::response::Stream;
 use api::strategy::Strategy;
+use config_loader::Redacted;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Server {
     #[serde(default)]
     pub alias: String,
@@ -25,7 +27,7 @@ pub struct Server {
     pub sub_max: u16,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Node {
     pub token: String,
     pub kind: NodeKind,
@@ -34,6 +36,35 @@ pub struct Node {
     streams: Vec<Stream>,
     pub strategy: Option<Strategy>,
     pub duration: Option<Duration>,
+    /// Fetched with `strategy`, `None` for nodes without the recorder or older than
+    /// `GET /api/recorder/capabilities`
+    pub capabilities: Option<RecorderCapabilities>,
+}
+
+impl std::fmt::Debug for Server {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Server")
+            .field("alias", &self.alias)
+            .field("token", &Redacted(&self.token))
+            .field("url", &self.url)
+            .field("pub_max", &self.pub_max)
+            .field("sub_max", &self.sub_max)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Node")
+            .field("token", &Redacted(&self.token))
+            .field("kind", &self.kind)
+            .field("url", &self.url)
+            .field("streams", &self.streams)
+            .field("strategy", &self.strategy)
+            .field("duration", &self.duration)
+            .field("capabilities", &self.capabilities)
+            .finish()
+    }
 }
 
 impl Node {
@@ -109,6 +140,15 @@ fn u16_max_value() -> u16 {
     u16::MAX
 }
 
+/// Capabilities from the response to `GET /api/recorder/capabilities`, `None` when the
+/// node doesn't serve it
+async fn fetch_capabilities(
+    resp: impl Future<Output = reqwest::Result<reqwest::Response>>,
+) -> Option<RecorderCapabilities> {
+    let resp = resp.await.ok()?.error_for_status().ok()?;
+    resp.json().await.ok()
+}
+
 #[derive(Clone)]
 pub struct Storage {
     list: Arc<RwLock<HashMap<String, Node>>>,
@@ -275,19 +315,37 @@ impl Storage {
                     .get(format!("{}{}", server.url, &api::path::strategy()))
                     .header(header::AUTHORIZATION, format!("Bearer {}", server.token))
                     .send(),
+                self.client
+                    .get(format!(
+                        "{}{}",
+                        server.url,
+                        api::path::recorder_capabilities()
+                    ))
+                    .header(header::AUTHORIZATION, format!("Bearer {}", server.token))
+                    .send(),
             ));
         }
 
         let handles = requests
             .into_iter()
-            .map(|(alias, value)| {
-                tokio::spawn(async move { (alias, value.await, start.elapsed()) })
+            .map(|(alias, value, capabilities)| {
+                tokio::spawn(async move {
+                    let value = value.await;
+                    let duration = start.elapsed();
+                    (
+                        alias,
+                        value,
+                        duration,
+                        fetch_capabilities(capabilities).await,
+                    )
+                })
             })
             .collect::<Vec<
                 tokio::task::JoinHandle<(
                     std::string::String,
                     std::result::Result<reqwest::Response, reqwest::Error>,
                     std::time::Duration,
+                    Option<RecorderCapabilities>,
                 )>,
             >>();
 
@@ -307,7 +365,7 @@ impl Storage {
         for handle in handles {
             let result = tokio::join!(handle);
             match result {
-                (Ok((alias, Ok(res), duration)),) => {
+                (Ok((alias, Ok(res), duration, capabilities)),) => {
                     debug!(
                         "{}: spend time: [{:?}] Response: {:?}",
                         alias, duration, res
@@ -320,12 +378,13 @@ impl Storage {
                             {
                                 node.duration = Some(duration);
                                 node.strategy = Some(strategy);
+                                node.capabilities = capabilities;
                             }
                         }
                         Err(e) => error!("Error: {:?}", e),
                     };
                 }
-                (Ok((name, Err(e), duration)),) => {
+                (Ok((name, Err(e), duration, _)),) => {
                     error!("{}: spend time: [{:?}] Error: {:?}", name, duration, e);
                 }
                 _ => {}
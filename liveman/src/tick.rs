@@ -1,16 +1,25 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
 use chrono::Utc;
 use glob::Pattern;
 use http::header;
-use tracing::{error, info, warn};
+use tokio::sync::{RwLock, mpsc::UnboundedSender};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
 use url::Url;
 
+use crate::service::dashboard::DashboardEvent;
 use crate::service::recordings_index::RecordingsIndexService;
+use crate::store::Server;
 use crate::{AppState, error::AppError, result::Result, route::utils::session_delete};
 
 use api::recorder::{
-    AckRecordingsRequest, DeleteRecordingsRequest, PullRecordingsRequest, RecordingKey,
+    AckFilter, AckRecordingsRequest, AckRecordingsResponse, DeleteRecordingsRequest, ListOrder,
+    PullRecordingsRequest, RecorderError, RecordingKey,
 };
 
 pub async fn cascade_check(state: AppState) {
@@ -179,7 +188,11 @@ async fn do_auto_record_check(mut state: AppState) -> Result<()> {
                     } else {
                         Some(format!("{base_prefix}/{requested_ts}"))
                     };
-                    let body = api::recorder::StartRecordRequest { base_dir };
+                    let body = api::recorder::StartRecordRequest {
+                        base_dir,
+                        retention_class: None,
+                        priority: None,
+                    };
                     let start_url = format!("{}{}", server.url, api::path::record(&stream_id));
                     let resp = state
                         .client
@@ -217,6 +230,7 @@ async fn do_auto_record_check(mut state: AppState) -> Result<()> {
 
                             if let Err(err) = RecordingsIndexService::upsert(
                                 state.database.get_connection(),
+                                &server.alias,
                                 &stream_id,
                                 &record_ts,
                                 &mpd_path,
@@ -278,30 +292,223 @@ pub async fn record_sync(state: AppState) {
         return;
     }
 
+    let (wake_tx, mut wake_rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+    let connected: Arc<RwLock<HashSet<String>>> = Arc::new(RwLock::new(HashSet::new()));
+    let mut watchers: HashMap<String, JoinHandle<Option<String>>> = HashMap::new();
+    let mut last_event_ids: HashMap<String, String> = HashMap::new();
+
+    let mut ticker = tokio::time::interval(Duration::from_millis(state.config.record_sync.tick_ms));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ticker.tick().await;
     loop {
-        let timeout = tokio::time::sleep(Duration::from_millis(state.config.record_sync.tick_ms));
-        tokio::pin!(timeout);
-        let _ = timeout.as_mut().await;
-        let _ = do_record_sync(state.clone()).await;
+        tokio::select! {
+            _ = ticker.tick() => {
+                if state.config.record_sync.events {
+                    respawn_event_watchers(
+                        &state,
+                        &mut watchers,
+                        &mut last_event_ids,
+                        &connected,
+                        &wake_tx,
+                    )
+                    .await;
+                }
+                // Nodes with a live event stream are synced on change instead
+                let live = connected.read().await.clone();
+                let _ = do_record_sync(state.clone(), |alias| !live.contains(alias)).await;
+            }
+            Some(alias) = wake_rx.recv() => {
+                let mut woken = HashSet::from([alias]);
+                while let Ok(alias) = wake_rx.try_recv() {
+                    woken.insert(alias);
+                }
+                let _ = do_record_sync(state.clone(), |alias| woken.contains(alias)).await;
+            }
+        }
+    }
+}
+
+/// Purge catalog recordings that stayed in the trash for `playback.trash_retention_days`
+pub async fn trash_purge(state: AppState) {
+    let retention = Duration::from_secs(
+        state
+            .config
+            .playback
+            .trash_retention_days
+            .saturating_mul(86_400),
+    );
+    let mut ticker = tokio::time::interval(Duration::from_secs(3600));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let before = Utc::now().timestamp_micros() - retention.as_micros() as i64;
+        let rows =
+            match RecordingsIndexService::trashed_before(state.database.get_connection(), before)
+                .await
+            {
+                Ok(rows) => rows,
+                Err(e) => {
+                    warn!(error = ?e, "trash purge query failed");
+                    continue;
+                }
+            };
+        for row in rows {
+            let (stream, record) = (row.stream.clone(), row.record.clone());
+            if let Err(e) = crate::route::recorder::purge(&state, row).await {
+                warn!(%stream, %record, error = ?e, "trash purge failed");
+            }
+        }
+    }
+}
+
+/// Keep one recorder event watcher per node, resuming from the last event it saw
+async fn respawn_event_watchers(
+    state: &AppState,
+    watchers: &mut HashMap<String, JoinHandle<Option<String>>>,
+    last_event_ids: &mut HashMap<String, String>,
+    connected: &Arc<RwLock<HashSet<String>>>,
+    wake_tx: &UnboundedSender<String>,
+) {
+    let servers = state.storage.clone().nodes().await;
+    let aliases: HashSet<String> = servers.iter().map(|s| s.alias.clone()).collect();
+    watchers.retain(|alias, handle| {
+        let keep = aliases.contains(alias);
+        if !keep {
+            handle.abort();
+        }
+        keep
+    });
+    last_event_ids.retain(|alias, _| aliases.contains(alias));
+
+    for server in servers {
+        if let Some(handle) = watchers.get_mut(&server.alias) {
+            if !handle.is_finished() {
+                continue;
+            }
+            if let Ok(Some(id)) = handle.await {
+                last_event_ids.insert(server.alias.clone(), id);
+            }
+        }
+        let last_event_id = last_event_ids.get(&server.alias).cloned();
+        let handle = tokio::spawn(watch_recorder_events(
+            state.client.clone(),
+            server.clone(),
+            last_event_id,
+            connected.clone(),
+            wake_tx.clone(),
+        ));
+        watchers.insert(server.alias, handle);
+    }
+}
+
+/// Follow a node's recorder events until the stream ends, waking the sync for the node
+/// on every event. Returns the last event id seen so the next watcher can resume.
+///
+/// Nodes without the endpoint (or without the recorder feature) reject the request and
+/// simply stay on polling.
+async fn watch_recorder_events(
+    client: reqwest::Client,
+    server: Server,
+    mut last_event_id: Option<String>,
+    connected: Arc<RwLock<HashSet<String>>>,
+    wake_tx: UnboundedSender<String>,
+) -> Option<String> {
+    let url = format!("{}{}", server.url, api::path::recorder_events());
+    let mut req = client
+        .get(url)
+        .query(&[("subscriber", "liveman")])
+        .header(header::AUTHORIZATION, format!("Bearer {}", server.token))
+        .header(header::ACCEPT, "text/event-stream");
+    if let Some(id) = last_event_id.as_ref() {
+        req = req.header("Last-Event-ID", id);
+    }
+    let mut resp = match req.send().await {
+        Ok(resp) if resp.status().is_success() => resp,
+        Ok(resp) => {
+            debug!(node = %server.alias, status = %resp.status(), "record_sync events unavailable");
+            return last_event_id;
+        }
+        Err(e) => {
+            debug!(node = %server.alias, error = ?e, "record_sync events unavailable");
+            return last_event_id;
+        }
+    };
+
+    connected.write().await.insert(server.alias.clone());
+    // Catch up on anything that changed while the stream was down
+    let _ = wake_tx.send(server.alias.clone());
+
+    let mut buf = String::new();
+    loop {
+        match resp.chunk().await {
+            Ok(Some(chunk)) => {
+                buf.push_str(&String::from_utf8_lossy(&chunk));
+                while let Some(pos) = buf.find("\n\n") {
+                    let block: String = buf.drain(..pos + 2).collect();
+                    if let Some(id) = sse_event_id(&block) {
+                        last_event_id = Some(id);
+                        let _ = wake_tx.send(server.alias.clone());
+                    } else if sse_event_name(&block) == Some(api::recorder::EVENTS_LAGGED) {
+                        // Events were dropped, the sync pulls what they carried
+                        debug!(node = %server.alias, "record_sync events lagged");
+                        let _ = wake_tx.send(server.alias.clone());
+                    }
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                debug!(node = %server.alias, error = ?e, "record_sync events stream closed");
+                break;
+            }
+        }
     }
+
+    connected.write().await.remove(&server.alias);
+    last_event_id
 }
 
-async fn do_record_sync(mut state: AppState) -> Result<()> {
+/// `id` field of one SSE event block, `None` for keep-alive comments
+fn sse_event_id(block: &str) -> Option<String> {
+    block
+        .lines()
+        .find_map(|line| line.strip_prefix("id:"))
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+/// `event` field of one SSE event block
+fn sse_event_name(block: &str) -> Option<&str> {
+    block
+        .lines()
+        .find_map(|line| line.strip_prefix("event:"))
+        .map(str::trim)
+}
+
+async fn do_record_sync(mut state: AppState, include: impl Fn(&str) -> bool) -> Result<()> {
     let servers = state.storage.nodes().await;
     if servers.is_empty() {
         return Ok(());
     }
 
-    for server in servers {
-        let since_ts = {
-            let guard = state.record_sync_cursor.read().await;
-            guard.get(&server.alias).copied()
-        };
+    let registered: Vec<String> = servers.iter().map(|s| s.alias.clone()).collect();
+    if let Err(e) = state
+        .record_sync_cursors
+        .prune(state.database.get_connection(), &registered)
+        .await
+    {
+        warn!(error = ?e, "record_sync cursor prune failed");
+    }
+
+    for server in servers.into_iter().filter(|s| include(&s.alias)) {
+        let since_ts = state.record_sync_cursors.get(&server.alias).await;
 
         let req = PullRecordingsRequest {
             stream: None,
             since_ts,
             limit: state.config.record_sync.limit,
+            order: ListOrder::Asc,
+            cursor: None,
+            include_trashed: true,
         };
 
         let url = format!("{}{}", server.url, api::path::recordings());
@@ -316,16 +523,33 @@ async fn do_record_sync(mut state: AppState) -> Result<()> {
             Ok(resp) => resp,
             Err(e) => {
                 warn!(node = %server.alias, error = ?e, "record_sync pull failed");
+                state
+                    .dashboard
+                    .set_health(&server.alias, false, Some(e.to_string()));
                 continue;
             }
         };
 
         if !resp.status().is_success() {
-            warn!(
-                node = %server.alias,
-                status = %resp.status(),
-                "record_sync pull failed"
-            );
+            let status = resp.status();
+            match recorder_error(resp).await {
+                // A locked index or flaky storage is no reason to flag the node, the
+                // cursor stays put and the next tick pulls the same page again
+                Some(err) if err.is_retryable() => {
+                    warn!(node = %server.alias, %status, error = ?err, "record_sync pull deferred");
+                }
+                err => {
+                    warn!(node = %server.alias, %status, error = ?err, "record_sync pull failed");
+                    state.dashboard.set_health(
+                        &server.alias,
+                        false,
+                        Some(err.map_or_else(
+                            || format!("pull answered {status}"),
+                            |err| err.to_string(),
+                        )),
+                    );
+                }
+            }
             continue;
         }
 
@@ -333,19 +557,35 @@ async fn do_record_sync(mut state: AppState) -> Result<()> {
             Ok(v) => v,
             Err(e) => {
                 warn!(node = %server.alias, error = ?e, "record_sync parse failed");
+                state
+                    .dashboard
+                    .set_health(&server.alias, false, Some(e.to_string()));
                 continue;
             }
         };
+        state.dashboard.set_health(&server.alias, true, None);
+        if let Some(capacity) = pull.capacity {
+            state
+                .dashboard
+                .set_recording_capacity(&server.alias, capacity);
+        }
+        if let Some(stats) = pull.stats {
+            state.dashboard.set_recorder_stats(&server.alias, stats);
+        }
+        if let Some(health) = pull.health {
+            state.dashboard.set_recorder_health(&server.alias, health);
+        }
 
         if pull.sessions.is_empty() {
             if let Some(last_ts) = pull.last_ts {
-                let mut guard = state.record_sync_cursor.write().await;
-                guard.insert(server.alias.clone(), last_ts);
+                advance_cursor(&state, &server.alias, last_ts).await;
             }
             continue;
         }
 
         let mut ack_records: Vec<RecordingKey> = Vec::new();
+        // Whether every session of the page made it to the catalog
+        let mut synced_all = true;
 
         for session in pull.sessions.iter() {
             let record = if let Some(id) = session.id.as_ref()
@@ -359,11 +599,13 @@ async fn do_record_sync(mut state: AppState) -> Result<()> {
                     mpd_path = %session.mpd_path,
                     "record_sync missing record id"
                 );
+                synced_all = false;
                 continue;
             };
 
             if let Err(err) = RecordingsIndexService::upsert(
                 state.database.get_connection(),
+                &server.alias,
                 &session.stream,
                 &record,
                 &session.mpd_path,
@@ -371,51 +613,177 @@ async fn do_record_sync(mut state: AppState) -> Result<()> {
             .await
             {
                 error!("{}", err);
+                synced_all = false;
+                continue;
+            }
+            state.dashboard.publish(DashboardEvent::Status {
+                node: server.alias.clone(),
+                stream: session.stream.clone(),
+                record: record.clone(),
+                status: session.status.clone(),
+                at: Utc::now().timestamp_micros(),
+            });
+
+            if !session.media_info.is_empty()
+                && let Err(err) = RecordingsIndexService::set_media_info(
+                    state.database.get_connection(),
+                    &server.alias,
+                    &session.stream,
+                    &record,
+                    &session.media_info,
+                )
+                .await
+            {
+                warn!(
+                    node = %server.alias,
+                    stream = %session.stream,
+                    error = ?err,
+                    "record_sync media info update failed"
+                );
+            }
+
+            if let Some(class) = session.retention_class.as_ref()
+                && let Err(err) = RecordingsIndexService::set_retention_class(
+                    state.database.get_connection(),
+                    &server.alias,
+                    &session.stream,
+                    &record,
+                    class,
+                )
+                .await
+            {
+                warn!(
+                    node = %server.alias,
+                    stream = %session.stream,
+                    error = ?err,
+                    "record_sync retention class update failed"
+                );
+            }
+
+            if let Err(err) = RecordingsIndexService::set_priority(
+                state.database.get_connection(),
+                &server.alias,
+                &session.stream,
+                &record,
+                session.priority,
+            )
+            .await
+            {
+                warn!(
+                    node = %server.alias,
+                    stream = %session.stream,
+                    error = ?err,
+                    "record_sync priority update failed"
+                );
+            }
+
+            if let Err(err) = RecordingsIndexService::set_tenant(
+                state.database.get_connection(),
+                &server.alias,
+                &session.stream,
+                &record,
+                session.tenant.as_deref(),
+            )
+            .await
+            {
+                warn!(
+                    node = %server.alias,
+                    stream = %session.stream,
+                    error = ?err,
+                    "record_sync tenant update failed"
+                );
+            }
+
+            if let Some(uuid) = session.uuid.as_deref()
+                && let Err(err) = RecordingsIndexService::set_recording_uuid(
+                    state.database.get_connection(),
+                    &server.alias,
+                    &session.stream,
+                    &record,
+                    uuid,
+                )
+                .await
+            {
+                warn!(
+                    node = %server.alias,
+                    stream = %session.stream,
+                    error = ?err,
+                    "record_sync uuid update failed"
+                );
+            }
+
+            // Trashed entries are never acked, they stay on the node until purged there
+            if let Some(trashed_at) = session.trashed_at {
+                if let Err(err) = RecordingsIndexService::mark_trashed(
+                    state.database.get_connection(),
+                    &server.alias,
+                    &session.stream,
+                    &record,
+                    trashed_at,
+                )
+                .await
+                {
+                    warn!(
+                        node = %server.alias,
+                        stream = %session.stream,
+                        error = ?err,
+                        "record_sync trash update failed"
+                    );
+                }
                 continue;
             }
 
             ack_records.push(RecordingKey {
                 stream: session.stream.clone(),
                 record,
+                if_match: None,
             });
         }
 
-        let mut should_advance = false;
+        let mut advance_to = None;
 
         if ack_records.is_empty() {
-            should_advance = pull.last_ts.is_some();
+            advance_to = pull.last_ts;
         } else {
-            let ack_url = format!("{}{}", server.url, api::path::recordings_ack());
-            let ack_req = AckRecordingsRequest {
-                records: ack_records,
-            };
-            match state
-                .client
-                .patch(ack_url)
-                .header(header::AUTHORIZATION, format!("Bearer {}", server.token))
-                .json(&ack_req)
-                .send()
+            let full_page =
+                synced_all && req.limit > 0 && pull.sessions.len() >= req.limit as usize;
+            // A full page is acked by filter, everything updated before its newest entry
+            // in one pass on the node. Entries sharing that `updated_at` may not all be in
+            // the page, they are left for the next pull
+            if full_page && let Some(last_ts) = pull.last_ts {
+                let filter = AckRecordingsRequest {
+                    records: Vec::new(),
+                    filter: Some(AckFilter {
+                        updated_before_ts: Some(last_ts),
+                        ..Default::default()
+                    }),
+                };
+                if let Some(resp) = ack_recordings(&state, &server, &filter).await {
+                    advance_to = resp
+                        .max_updated_at
+                        .filter(|ts| since_ts.is_none_or(|since| *ts > since));
+                }
+            }
+            // Also when nothing was older, or the node does not know the filter form
+            if advance_to.is_none()
+                && ack_recordings(
+                    &state,
+                    &server,
+                    &AckRecordingsRequest {
+                        records: ack_records.clone(),
+                        filter: None,
+                    },
+                )
                 .await
+                .is_some()
             {
-                Ok(r) if r.status().is_success() => {
-                    should_advance = true;
-                }
-                Ok(r) => {
-                    warn!(
-                        node = %server.alias,
-                        status = %r.status(),
-                        "record_sync ack failed"
-                    );
-                }
-                Err(e) => {
-                    warn!(node = %server.alias, error = ?e, "record_sync ack failed");
-                }
+                advance_to = pull.last_ts;
             }
 
-            if should_advance {
+            if advance_to.is_some() {
                 let delete_url = format!("{}{}", server.url, api::path::recordings_delete());
                 let delete_req = DeleteRecordingsRequest {
-                    records: ack_req.records,
+                    records: ack_records,
                 };
                 match state
                     .client
@@ -427,11 +795,17 @@ async fn do_record_sync(mut state: AppState) -> Result<()> {
                 {
                     Ok(r) if r.status().is_success() => {}
                     Ok(r) => {
-                        warn!(
-                            node = %server.alias,
-                            status = %r.status(),
-                            "record_sync delete failed"
-                        );
+                        let status = r.status();
+                        match recorder_error(r).await {
+                            // Already gone from the node, which is what the delete was for
+                            Some(RecorderError::NotFound { .. }) => {}
+                            err => warn!(
+                                node = %server.alias,
+                                %status,
+                                error = ?err,
+                                "record_sync delete failed"
+                            ),
+                        }
                     }
                     Err(e) => {
                         warn!(node = %server.alias, error = ?e, "record_sync delete failed");
@@ -440,15 +814,67 @@ async fn do_record_sync(mut state: AppState) -> Result<()> {
             }
         }
 
-        if should_advance && let Some(last_ts) = pull.last_ts {
-            let mut guard = state.record_sync_cursor.write().await;
-            guard.insert(server.alias.clone(), last_ts);
+        if let Some(ts) = advance_to {
+            advance_cursor(&state, &server.alias, ts).await;
         }
     }
 
     Ok(())
 }
 
+/// Move the pull cursor of `node`, persisting it for the next start
+async fn advance_cursor(state: &AppState, node: &str, since_ts: i64) {
+    if let Err(e) = state
+        .record_sync_cursors
+        .advance(state.database.get_connection(), node, since_ts)
+        .await
+    {
+        warn!(node = %node, error = ?e, "record_sync cursor not persisted");
+    }
+}
+
+/// Typed error body of a failed recorder request, `None` from nodes answering plain text
+async fn recorder_error(resp: reqwest::Response) -> Option<RecorderError> {
+    resp.json::<RecorderError>().await.ok()
+}
+
+/// `PATCH /api/recordings` on `server`, `None` when it failed
+async fn ack_recordings(
+    state: &AppState,
+    server: &Server,
+    req: &AckRecordingsRequest,
+) -> Option<AckRecordingsResponse> {
+    let url = format!("{}{}", server.url, api::path::recordings_ack());
+    let resp = match state
+        .client
+        .patch(url)
+        .header(header::AUTHORIZATION, format!("Bearer {}", server.token))
+        .json(req)
+        .send()
+        .await
+    {
+        Ok(r) if r.status().is_success() => r,
+        Ok(r) => {
+            let status = r.status();
+            let err = recorder_error(r).await;
+            let retryable = err.as_ref().is_some_and(RecorderError::is_retryable);
+            warn!(node = %server.alias, %status, error = ?err, retryable, "record_sync ack failed");
+            return None;
+        }
+        Err(e) => {
+            warn!(node = %server.alias, error = ?e, "record_sync ack failed");
+            return None;
+        }
+    };
+    match resp.json::<AckRecordingsResponse>().await {
+        Ok(resp) => Some(resp),
+        Err(e) => {
+            warn!(node = %server.alias, error = ?e, "record_sync ack parse failed");
+            None
+        }
+    }
+}
+
 async fn do_auto_record_rotate(mut state: AppState) -> Result<()> {
     let patterns = state.config.auto_record.auto_streams.clone();
     if patterns.is_empty() {
@@ -515,6 +941,8 @@ async fn do_auto_record_rotate(mut state: AppState) -> Result<()> {
             let url = format!("{}{}", server.url, api::path::record(stream_id));
             let body = api::recorder::StartRecordRequest {
                 base_dir: base_dir.clone(),
+                retention_class: None,
+                priority: None,
             };
             let resp = state
                 .client
@@ -553,6 +981,7 @@ async fn do_auto_record_rotate(mut state: AppState) -> Result<()> {
                 // Upsert index
                 if let Err(err) = RecordingsIndexService::upsert(
                     state.database.get_connection(),
+                    &server.alias,
                     stream_id,
                     &record_ts,
                     &mpd_path,
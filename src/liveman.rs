@@ -7,16 +7,24 @@ mod utils;
 #[derive(Parser)]
 #[command(name = "liveman", version)]
 struct Args {
-    /// Set config file path
-    #[arg(short, long)]
-    config: Option<String>,
+    #[command(flatten)]
+    config: config_loader::ConfigArgs,
+    /// Sync the recording catalog from every node from the start, instead of resuming
+    /// after the last synced entry
+    #[arg(long)]
+    rebuild_catalog: bool,
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
-    let mut cfg: liveman::config::Config = utils::load("liveman".to_string(), args.config);
+    let mut cfg: liveman::config::Config = config_loader::load_or_exit("liveman", &args.config);
     cfg.validate().unwrap();
+    cfg.source = Some(liveman::config::ConfigSource::new(
+        "liveman",
+        args.config.clone(),
+    ));
+    cfg.rebuild_catalog = args.rebuild_catalog;
 
     #[cfg(debug_assertions)]
     log::set(format!(
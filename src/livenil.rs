@@ -27,11 +27,16 @@ pub fn parse(name: &str) -> (&str, &str, &str) {
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
-    let mut cfg: liveman::config::Config = utils::load(
-        NAME.to_string(),
-        args.config.clone().map(|s| format!("{s}/liveman.toml")),
-    );
+    let source = config_loader::ConfigArgs {
+        config: args.config.clone().map(|s| format!("{s}/liveman.toml")),
+        ..Default::default()
+    };
+    let mut cfg: liveman::config::Config = config_loader::Loader::new(NAME)
+        .args(&source)
+        .load()
+        .unwrap();
     cfg.validate().unwrap();
+    cfg.source = Some(liveman::config::ConfigSource::new(NAME, source));
 
     log::set(format!(
         "livenil={},liveman={},liveion={},http_log={},webrtc=error",
@@ -51,10 +56,10 @@ async fn main() {
         }
         let alias = alias.to_string();
 
-        let cfg: liveion::config::Config = utils::load(
-            "live777".to_string(),
-            entry.path().to_str().map(|s| s.to_string()),
-        );
+        let cfg: liveion::config::Config = config_loader::Loader::new("live777")
+            .path(entry.path().to_str().map(|s| s.to_string()))
+            .load()
+            .unwrap();
         cfg.validate().unwrap();
         debug!("config : {:?}", cfg);
         let listener = TcpListener::bind(&cfg.http.listen).await.unwrap();
@@ -1,18 +1,47 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 use anyhow::Result;
-use axum::extract::{Path, State};
+use api::extract::NamedPath;
+use api::recorder::{
+    ListCursor, ListOrder, NEXT_CURSOR_HEADER, NameRules, PREVIEW_AUDIO_ONLY_CODE,
+    RECORDING_MISSING_CODE, RecordingIndexEntry, RecordingStatus, SEGMENTS_FILENAME, page_entries,
+};
+use axum::extract::{ConnectInfo, FromRef, Path, RawPathParams, State};
 use axum::http::{StatusCode, header};
 use axum::response::{IntoResponse, Response};
-use axum::routing::get;
+use axum::routing::{get, post};
 use axum::{Json, Router};
 use axum_extra::extract::Query;
+use clap::Parser;
+use config_loader::Secret;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
 
 mod log;
 mod utils;
+mod vod;
+
+use vod::analytics::Analytics;
+use vod::index::{IndexCache, StreamSort, sort_summaries};
+use vod::limiter::ReadLimiter;
+use vod::local::LocalFiles;
+use vod::manifest::ManifestCache;
+use vod::not_found::NotFoundCache;
+use vod::preview::{JobStatus, PreviewJobs};
+use vod::redirect::{RedirectMode, StatCache};
+use vod::replica::{Destination, Replicas};
+use vod::size::SizeCache;
+use vod::tenant::{AuthMode, StreamAccess};
+use vod::timeline::TimelineSpan;
+
+#[derive(Parser)]
+#[command(name = "livevod", version)]
+struct Args {
+    #[command(flatten)]
+    config: config_loader::ConfigArgs,
+}
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 struct Config {
@@ -22,22 +51,69 @@ struct Config {
     log: Log,
     #[serde(default)]
     playback: Playback,
+    #[serde(default)]
+    preview: vod::preview::PreviewConfig,
+    #[cfg(feature = "transcode")]
+    #[serde(default)]
+    transcode: vod::transcode::TranscodeConfig,
+    #[serde(default)]
+    auth: vod::tenant::AuthConfig,
+    /// Playback sessions exported to a webhook or file
+    #[serde(default)]
+    analytics: vod::analytics::AnalyticsConfig,
+    /// Read-only S3-compatible gateway, off when absent
+    #[serde(default)]
+    s3: Option<vod::s3::S3Config>,
     #[serde(default = "default_index_path")]
     index_path: String,
+    /// Where the recordings are read from, absent to serve them from
+    /// `playback.local_fallback_dir` alone
+    #[serde(default)]
+    storage: Option<storage::StorageConfig>,
+    /// Further destinations holding copies of the recordings, read when `storage` lacks
+    /// an object or keeps failing
+    #[serde(default)]
+    replicas: Vec<vod::replica::ReplicaConfig>,
+    /// Storage failure injection, honored only in debug builds or with the `chaos` feature
+    #[serde(default)]
+    chaos: Option<storage::ChaosConfig>,
     #[serde(default)]
-    storage: storage::StorageConfig,
+    internal: Internal,
+}
+
+/// Endpoints for the other live777 services
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+struct Internal {
+    /// Shared with the senders of invalidation notices, which are refused while empty
+    #[serde(default)]
+    secret: Secret,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct Http {
     #[serde(default = "default_http_listen")]
     listen: SocketAddr,
+    /// Serve HTTPS on `listen` when present
+    #[serde(default)]
+    tls: Option<vod::tls::TlsConfig>,
+    /// Reverse proxies whose `Forwarded`/`X-Forwarded-*` headers are honored in absolute URLs
+    #[serde(default)]
+    trusted_proxies: forwarded::TrustedProxies,
+    /// Serve Swagger UI for `/api/openapi.json` at `/swagger-ui`
+    #[serde(default)]
+    swagger_ui: bool,
+    #[serde(default)]
+    security_headers: vod::headers::SecurityHeadersConfig,
 }
 
 impl Default for Http {
     fn default() -> Self {
         Self {
             listen: default_http_listen(),
+            tls: None,
+            trusted_proxies: Default::default(),
+            swagger_ui: false,
+            security_headers: Default::default(),
         }
     }
 }
@@ -76,6 +152,48 @@ struct Playback {
     signed_redirect: bool,
     #[serde(default = "default_signed_ttl_seconds")]
     signed_ttl_seconds: u64,
+    /// With `signed_redirect`, smaller objects are served inline to save the redirect
+    /// round trip
+    #[serde(default = "default_redirect_min_bytes")]
+    redirect_min_bytes: u64,
+    /// Manifests livevod parses itself are refused above this size
+    #[serde(default = "default_max_manifest_bytes")]
+    max_manifest_bytes: u64,
+    /// Maximum concurrent storage reads across all clients (0 disables the limit)
+    #[serde(default)]
+    max_concurrent_reads: usize,
+    /// Maximum concurrent storage reads per client IP (0 derives a quarter of the global limit)
+    #[serde(default)]
+    max_concurrent_reads_per_client: usize,
+    /// How long a read may wait for a permit before answering 503
+    #[serde(default = "default_read_queue_timeout_ms")]
+    read_queue_timeout_ms: u64,
+    /// Serve the built-in player UI at `/`, needs the `webui` feature
+    #[serde(default)]
+    ui_enabled: bool,
+    /// Keep served manifests this long (0 reads them every time). Invalidation notices
+    /// drop rewritten ones right away
+    #[serde(default)]
+    manifest_cache_seconds: u64,
+    /// Keep recording sizes this long (0 computes them every time), and list a record
+    /// dir without `segments.jsonl` at most once per period
+    #[serde(default = "default_size_cache_seconds")]
+    size_cache_seconds: u64,
+    /// Answer objects found missing this long from memory (0 reads storage every time),
+    /// at most 2000. Invalidation notices drop uploaded ones right away
+    #[serde(default = "default_not_found_cache_ms")]
+    not_found_cache_ms: u64,
+    /// Serve media objects of `fs` destinations from their files instead of reading
+    /// them whole through storage first
+    #[serde(default = "default_local_files")]
+    local_files: bool,
+    /// Size of each read when serving from a file
+    #[serde(default = "default_local_chunk_bytes")]
+    local_chunk_bytes: usize,
+    /// Recordings as a recorder on this host keeps them, e.g. its `upload.local_dir`.
+    /// Objects storage lacks are served from it, without `storage` every object is
+    #[serde(default)]
+    local_fallback_dir: Option<String>,
 }
 
 impl Default for Playback {
@@ -83,188 +201,930 @@ impl Default for Playback {
         Self {
             signed_redirect: false,
             signed_ttl_seconds: default_signed_ttl_seconds(),
+            redirect_min_bytes: default_redirect_min_bytes(),
+            max_manifest_bytes: default_max_manifest_bytes(),
+            max_concurrent_reads: 0,
+            max_concurrent_reads_per_client: 0,
+            read_queue_timeout_ms: default_read_queue_timeout_ms(),
+            ui_enabled: false,
+            manifest_cache_seconds: 0,
+            size_cache_seconds: default_size_cache_seconds(),
+            not_found_cache_ms: default_not_found_cache_ms(),
+            local_files: default_local_files(),
+            local_chunk_bytes: default_local_chunk_bytes(),
+            local_fallback_dir: None,
         }
     }
 }
 
+fn default_local_files() -> bool {
+    true
+}
+
+fn default_local_chunk_bytes() -> usize {
+    256 * 1024
+}
+
+fn default_size_cache_seconds() -> u64 {
+    60
+}
+
+fn default_not_found_cache_ms() -> u64 {
+    1000
+}
+
+fn default_read_queue_timeout_ms() -> u64 {
+    5_000
+}
+
 fn default_signed_ttl_seconds() -> u64 {
     60
 }
 
+fn default_redirect_min_bytes() -> u64 {
+    64 * 1024
+}
+
+fn default_max_manifest_bytes() -> u64 {
+    4 * 1024 * 1024
+}
+
 fn default_index_path() -> String {
     "./recordings/index.json".to_string()
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-struct RecordingIndexEntry {
-    record: String,
-    stream: String,
-    record_dir: String,
-    mpd_path: String,
-    start_ts: i64,
-    end_ts: Option<i64>,
-    duration_ms: Option<i32>,
-    status: api::recorder::RecordingStatus,
-    node_alias: Option<String>,
-    updated_at: i64,
+impl Config {
+    fn validate(&self) -> Result<()> {
+        if self.storage.is_none() {
+            if self.playback.local_fallback_dir.is_none() {
+                anyhow::bail!(
+                    "nothing to serve recordings from, configure [storage] or playback.local_fallback_dir"
+                );
+            }
+            if self.playback.signed_redirect {
+                anyhow::bail!(
+                    "playback.signed_redirect needs [storage], local recordings can't be presigned"
+                );
+            }
+            if !self.replicas.is_empty() {
+                anyhow::bail!("[[replicas]] need [storage] as their primary destination");
+            }
+        }
+        if self.replicas.iter().any(|r| r.name == vod::replica::LOCAL) {
+            anyhow::bail!(
+                "replica name '{}' is reserved for playback.local_fallback_dir",
+                vod::replica::LOCAL
+            );
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
 struct AppState {
     config: Config,
-    operator: opendal::Operator,
+    /// `storage` as the primary destination, the replicas, then the local fallback dir
+    replicas: Arc<Replicas>,
+    index: Arc<IndexCache>,
+    read_limiter: Arc<ReadLimiter>,
+    stat_cache: Arc<StatCache>,
+    manifests: Arc<ManifestCache>,
+    not_found: Arc<NotFoundCache>,
+    /// Files of the `fs` destinations by name, with `playback.local_files`
+    local: Arc<HashMap<String, LocalFiles>>,
+    sizes: Arc<SizeCache>,
+    previews: Arc<PreviewJobs>,
+    #[cfg(feature = "transcode")]
+    transcodes: Arc<vod::transcode::TranscodeJobs>,
+    analytics: Option<Arc<Analytics>>,
+    chaos: Option<storage::ChaosLayer>,
+}
+
+impl AppState {
+    /// Operator the manifests are read and previews written through: storage's, or the
+    /// local fallback dir's without storage
+    fn reader(&self) -> opendal::Operator {
+        self.replicas.destinations()[0].operator.current()
+    }
+}
+
+/// Rules of [`NamedPath`]. livevod serves the recordings of many nodes, some may record
+/// with tenancy: a name a node records always passes, the node refuses the others
+impl FromRef<AppState> for NameRules {
+    fn from_ref(_: &AppState) -> Self {
+        Self::with_separators()
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    let cfg: Config = utils::load("livevod".to_string(), None);
+    let args = Args::parse();
+    let cfg: Config = config_loader::load_or_exit("livevod", &args.config);
     log::set(format!("livevod={}", cfg.log.level));
     warn!("set log level : {}", cfg.log.level);
     debug!("config : {:?}", cfg);
+    if let Err(e) = cfg.validate() {
+        eprintln!("config error: {e}");
+        std::process::exit(1);
+    }
 
-    let operator = storage::init_operator(&cfg.storage)
-        .await
-        .expect("failed to init storage operator");
+    let chaos = match cfg.chaos.clone() {
+        Some(chaos) if storage::CHAOS_AVAILABLE => {
+            warn!("storage chaos enabled: {:?}", chaos);
+            Some(storage::ChaosLayer::new(chaos))
+        }
+        Some(_) => {
+            warn!("storage chaos ignored, build without the chaos feature");
+            None
+        }
+        None => None,
+    };
+    // Without storage there is nothing to connect to or to probe
+    let mut destinations = Vec::new();
+    if let Some(ref config) = cfg.storage {
+        let operator = storage::init_failover_operator(config)
+            .await
+            .expect("failed to init storage operator");
+        let operator = match chaos {
+            Some(ref layer) => operator.layer(layer.clone()),
+            None => operator,
+        };
+        destinations.push(Destination::new(
+            vod::replica::PRIMARY,
+            config.clone(),
+            operator,
+        ));
+    }
+    for replica in &cfg.replicas {
+        let operator = storage::init_failover_operator(&replica.storage)
+            .await
+            .unwrap_or_else(|e| panic!("failed to init replica '{}': {e}", replica.name));
+        let operator = match chaos {
+            Some(ref layer) => operator.layer(layer.clone()),
+            None => operator,
+        };
+        info!("storage replica '{}' configured", replica.name);
+        destinations.push(Destination::new(
+            replica.name.clone(),
+            replica.storage.clone(),
+            operator,
+        ));
+    }
+    if let Some(ref root) = cfg.playback.local_fallback_dir {
+        let config = storage::StorageConfig::Fs { root: root.clone() };
+        let operator = storage::create_failover_operator(&config)
+            .expect("failed to open playback.local_fallback_dir");
+        match cfg.storage {
+            Some(_) => info!("serving objects storage lacks from {}", root),
+            None => info!("no storage configured, serving recordings from {}", root),
+        }
+        destinations.push(Destination::new(vod::replica::LOCAL, config, operator));
+    }
+    let mut destinations = destinations.into_iter();
+    let primary = destinations
+        .next()
+        .expect("validated: storage or local_fallback_dir");
+    let replicas = Arc::new(Replicas::new(primary, destinations.collect()));
+
+    // Injected failures only reach reads through storage
+    let local: HashMap<String, LocalFiles> = if cfg.playback.local_files && chaos.is_none() {
+        replicas
+            .destinations()
+            .iter()
+            .filter_map(|d| {
+                let files = LocalFiles::new(&d.config, cfg.playback.local_chunk_bytes)?;
+                info!("serving objects of '{}' from local files", d.name);
+                Some((d.name.clone(), files))
+            })
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    vod::metrics::register();
+    let read_limiter = Arc::new(ReadLimiter::new(
+        cfg.playback.max_concurrent_reads,
+        cfg.playback.max_concurrent_reads_per_client,
+        std::time::Duration::from_millis(cfg.playback.read_queue_timeout_ms),
+    ));
+
+    if let Some(ref s3) = cfg.s3 {
+        let operator = replicas.destinations()[0].operator.clone();
+        let gateway = vod::s3::S3Gateway::new(s3.clone(), operator, read_limiter.clone());
+        info!("S3 gateway serving bucket '{}'", s3.bucket);
+        tokio::spawn(serve_plain(vod::s3::router(Arc::new(gateway)), s3.listen));
+    }
+
+    let analytics = Analytics::new(&cfg.analytics)
+        .expect("invalid analytics config")
+        .map(Arc::new);
+    if let Some(ref analytics) = analytics {
+        info!("exporting playback sessions to {:?}", cfg.analytics.sink);
+        tokio::spawn(analytics.clone().run());
+    }
 
     let state = AppState {
         config: cfg.clone(),
-        operator,
+        replicas,
+        index: Arc::new(IndexCache::new(&cfg.index_path)),
+        read_limiter,
+        stat_cache: Arc::new(StatCache::default()),
+        manifests: Arc::new(ManifestCache::new(std::time::Duration::from_secs(
+            cfg.playback.manifest_cache_seconds,
+        ))),
+        not_found: Arc::new(NotFoundCache::new(std::time::Duration::from_millis(
+            cfg.playback.not_found_cache_ms,
+        ))),
+        local: Arc::new(local),
+        sizes: Arc::new(SizeCache::new(std::time::Duration::from_secs(
+            cfg.playback.size_cache_seconds,
+        ))),
+        previews: Arc::new(PreviewJobs::new(cfg.preview.clone())),
+        #[cfg(feature = "transcode")]
+        transcodes: Arc::new(vod::transcode::TranscodeJobs::new(cfg.transcode.clone())),
+        analytics: analytics.clone(),
+        chaos,
     };
 
-    let app = Router::new()
+    let playback = Router::new()
         .route("/api/playback", get(list_streams))
         .route("/api/playback/{stream}", get(list_records))
         .route("/api/playback/{stream}/at", get(find_record_at))
+        .route("/api/playback/{stream}/timeline", get(timeline))
+        .route("/api/record/by-id/{uuid}", get(record_by_id))
         .route("/api/record/object/{*path}", get(get_object))
+        .route("/api/record/clip/{stream}/{file}", get(clip_manifest))
+        .route("/api/record/size/{stream}/{record}", get(record_size))
+        .route(
+            "/api/record/previews/{stream}/{record}",
+            get(preview_status).post(create_previews),
+        );
+    #[cfg(feature = "transcode")]
+    let playback = playback.route(
+        "/api/record/transcode/{stream}/{record}",
+        get(transcode_status).post(create_transcode),
+    );
+    let playback = match cfg.auth.mode {
+        AuthMode::None => playback,
+        AuthMode::Jwt => playback.layer(axum::middleware::from_fn_with_state(
+            auth::AuthState::new(&cfg.auth.secret, &cfg.auth.tokens),
+            vod::tenant::require_token,
+        )),
+    };
+
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .merge(playback)
+        .route(
+            api::path::storage_chaos(),
+            get(storage_chaos).put(update_storage_chaos),
+        )
+        .route(storage::invalidate::INVALIDATE_PATH, post(invalidate))
         .with_state(state);
 
-    let listener = tokio::net::TcpListener::bind(&cfg.http.listen)
+    let app = app
+        .route("/healthz", get(|| async { "ok" }))
+        .route(api::path::OPENAPI, get(vod::openapi::openapi));
+    let app = if cfg.http.swagger_ui {
+        app.merge(
+            utoipa_swagger_ui::SwaggerUi::new(api::path::SWAGGER_UI)
+                .config(utoipa_swagger_ui::Config::from(api::path::OPENAPI)),
+        )
+    } else {
+        app
+    };
+    let app = if cfg.playback.ui_enabled {
+        mount_ui(app)
+    } else {
+        app
+    };
+    let security_headers = vod::headers::SecurityHeaders::new(&cfg.http.security_headers)
+        .expect("invalid http.security_headers value");
+    let app = app.layer(axum::middleware::from_fn_with_state(
+        Arc::new(security_headers),
+        vod::headers::security_headers,
+    ));
+    match cfg.http.tls {
+        Some(ref tls) => serve_tls(app, cfg.http.listen, tls.clone()).await,
+        None => serve_plain(app, cfg.http.listen).await,
+    }
+    if let Some(analytics) = analytics {
+        analytics.shutdown().await;
+    }
+}
+
+#[cfg(feature = "webui")]
+fn mount_ui(app: Router) -> Router {
+    info!("serving the player UI at /");
+    app.fallback(vod::ui::static_handler)
+}
+
+#[cfg(not(feature = "webui"))]
+fn mount_ui(app: Router) -> Router {
+    warn!("playback.ui_enabled is set, but livevod was built without the webui feature");
+    app
+}
+
+async fn serve_plain(app: Router, listen: SocketAddr) {
+    let listener = tokio::net::TcpListener::bind(&listen)
         .await
         .expect("failed to bind listen address");
     let addr = listener.local_addr().expect("failed to read listen addr");
     info!("LiveVOD listening on {}", addr);
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(utils::shutdown_signal())
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(utils::shutdown_signal())
+    .await
+    .unwrap();
+}
+
+async fn serve_tls(app: Router, listen: SocketAddr, tls: vod::tls::TlsConfig) {
+    let rustls = vod::tls::load(&tls).expect("failed to load TLS certificate");
+    tokio::spawn(vod::tls::watch(tls.clone(), rustls.clone()));
+
+    let handle = axum_server::Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            utils::shutdown_signal().await;
+            handle.graceful_shutdown(None);
+        }
+    });
+
+    if let Some(plaintext) = tls.plaintext_listen {
+        info!("LiveVOD listening on http://{}", plaintext);
+        let app = app.clone();
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            axum_server::bind(plaintext)
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .expect("failed to serve plaintext listener");
+        });
+    }
+
+    info!("LiveVOD listening on https://{}", listen);
+    axum_server::bind_rustls(listen, rustls)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
 
-async fn list_streams(State(state): State<AppState>) -> Result<Json<Vec<String>>, Response> {
-    let entries = load_index(&state.config.index_path).await.map_err(|e| {
+async fn metrics(State(state): State<AppState>) -> String {
+    vod::metrics::observe_replicas(&state.replicas);
+    vod::metrics::encode()
+}
+
+async fn storage_chaos(
+    State(state): State<AppState>,
+) -> Result<Json<storage::ChaosConfig>, Response> {
+    match state.chaos {
+        Some(ref layer) => Ok(Json(layer.config())),
+        None => Err((StatusCode::NOT_FOUND, "storage chaos not enabled").into_response()),
+    }
+}
+
+async fn update_storage_chaos(
+    State(state): State<AppState>,
+    Json(config): Json<storage::ChaosConfig>,
+) -> Result<Json<storage::ChaosConfig>, Response> {
+    match state.chaos {
+        Some(ref layer) => {
+            warn!("storage chaos updated: {:?}", config);
+            layer.set_config(config.clone());
+            Ok(Json(config))
+        }
+        None => Err((StatusCode::NOT_FOUND, "storage chaos not enabled").into_response()),
+    }
+}
+
+/// Drop cached copies of objects a recorder or liveman rewrote, see
+/// [`storage::invalidate`]
+async fn invalidate(
+    State(state): State<AppState>,
+    headers: header::HeaderMap,
+    body: axum::body::Bytes,
+) -> Result<Json<serde_json::Value>, Response> {
+    let secret = state.config.internal.secret.expose_secret();
+    if secret.is_empty() {
+        return Err((StatusCode::NOT_FOUND, "internal.secret not configured").into_response());
+    }
+    let signature = headers
+        .get(storage::invalidate::SIGNATURE_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let now = chrono::Utc::now().timestamp();
+    if let Err(e) = storage::invalidate::verify(secret, signature, &body, now) {
+        warn!("invalidation notice refused: {}", e);
+        return Err((StatusCode::UNAUTHORIZED, e.to_string()).into_response());
+    }
+    let req: storage::InvalidateRequest = serde_json::from_slice(&body)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+
+    let mut evicted = 0;
+    for key in &req.keys {
+        let key = storage::normalize_key(key);
+        let cached = state.manifests.remove(&key)
+            | state.stat_cache.remove(&key)
+            | state.not_found.remove(&key);
+        evicted += usize::from(cached);
+    }
+    // Manifests are rewritten along with their index entry
+    state.index.invalidate().await;
+    state.sizes.invalidate();
+    vod::metrics::INVALIDATED_KEYS.inc_by(evicted as u64);
+    debug!(
+        "invalidated {} of {} keys: {:?}",
+        evicted,
+        req.keys.len(),
+        req.keys
+    );
+    Ok(Json(serde_json::json!({ "evicted": evicted })))
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct StreamsQuery {
+    /// Plain list of stream names, as returned before summaries existed
+    #[serde(default)]
+    names_only: bool,
+    #[serde(default)]
+    sort: StreamSort,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/playback",
+    tag = "playback",
+    params(StreamsQuery),
+    responses(
+        (status = 200, description = "Stream summaries, or names with `names_only`", body = Vec<vod::index::StreamSummary>),
+    )
+)]
+async fn list_streams(
+    State(state): State<AppState>,
+    access: StreamAccess,
+    Query(query): Query<StreamsQuery>,
+) -> Result<Response, Response> {
+    let summaries = state.index.summaries().await.map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("failed to load index: {e}"),
         )
             .into_response()
     })?;
-    let mut streams = HashSet::new();
-    for entry in entries {
-        streams.insert(entry.stream);
+    let mut summaries: Vec<_> = summaries
+        .iter()
+        .filter(|s| access.allows_recording(&s.stream, s.tenant.as_deref()))
+        .cloned()
+        .collect();
+    sort_summaries(&mut summaries, query.sort);
+    if query.names_only {
+        let names: Vec<String> = summaries.into_iter().map(|s| s.stream).collect();
+        return Ok(Json(names).into_response());
     }
-    let mut list: Vec<String> = streams.into_iter().collect();
-    list.sort();
-    Ok(Json(list))
+    Ok(Json(summaries).into_response())
 }
 
+/// Paging parameters, listing is unpaged and sorted by record when none is given
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListQuery {
+    order: Option<ListOrder>,
+    limit: Option<usize>,
+    cursor: Option<String>,
+    /// Also list recordings in the trash
+    #[serde(default)]
+    include_trashed: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/playback/{stream}",
+    tag = "playback",
+    params(("stream" = String, Path, description = "Stream id"), ListQuery),
+    responses(
+        (status = 200, description = "Recordings of the stream, `x-next-cursor` carries the next page", body = Vec<RecordingIndexEntry>),
+        (status = 400, description = "Invalid cursor", body = String),
+        (status = 403, description = "Stream not allowed by the token's `streams` claim", body = String),
+    )
+)]
 async fn list_records(
     State(state): State<AppState>,
-    Path(stream): Path<String>,
-) -> Result<Json<Vec<RecordingIndexEntry>>, Response> {
-    let entries = load_index(&state.config.index_path).await.map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("failed to load index: {e}"),
-        )
-            .into_response()
-    })?;
+    access: StreamAccess,
+    NamedPath(stream): NamedPath<String>,
+    Query(query): Query<ListQuery>,
+) -> Result<Response, Response> {
+    if !access.allows(&stream) {
+        return Err(vod::tenant::forbidden());
+    }
+    let paged = query.order.is_some() || query.limit.is_some() || query.cursor.is_some();
+    let order = query.order.unwrap_or_default();
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|c| ListCursor::decode(c, order))
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e).into_response())?;
+
+    let entries = vod::index::load(&state.config.index_path)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to load index: {e}"),
+            )
+                .into_response()
+        })?;
+    let entries = if query.include_trashed {
+        entries
+    } else {
+        vod::index::without_trashed(entries)
+    };
+    // Keys are stored in NFC, however the stream name was spelled when it was recorded
+    let stream = storage::normalize_key(&stream);
     let mut records: Vec<RecordingIndexEntry> = entries
         .into_iter()
-        .filter(|entry| entry.stream == stream)
+        .filter(|entry| storage::normalize_key(&entry.stream) == stream)
+        .filter(|entry| access.allows_entry(entry))
         .collect();
-    records.sort_by(|a, b| a.record.cmp(&b.record));
-    Ok(Json(records))
+    if !paged {
+        records.sort_by(|a, b| a.record.cmp(&b.record));
+        return Ok(Json(records).into_response());
+    }
+
+    // The index is append-only, keep the latest line per record before paging
+    let mut latest: HashMap<String, RecordingIndexEntry> = HashMap::new();
+    for entry in records {
+        latest.insert(entry.key(), entry);
+    }
+    let limit = query.limit.filter(|l| *l > 0).unwrap_or(100);
+    let (page, next_cursor) = page_entries(
+        latest.into_values().collect(),
+        order,
+        cursor.as_ref(),
+        limit,
+    );
+    let mut response = Json(page).into_response();
+    if let Some(next) = next_cursor
+        && let Ok(value) = header::HeaderValue::from_bytes(next.encode().as_bytes())
+    {
+        response.headers_mut().insert(NEXT_CURSOR_HEADER, value);
+    }
+    Ok(response)
 }
 
-#[derive(Deserialize)]
+#[utoipa::path(
+    get,
+    path = "/api/record/by-id/{uuid}",
+    tag = "playback",
+    params(("uuid" = String, Path, description = "Recording uuid, see `RecordingIndexEntry::uuid`")),
+    responses(
+        (status = 200, description = "Latest index entry of the recording, under its current stream", body = RecordingIndexEntry),
+        (status = 403, description = "Stream not allowed by the token's `streams` claim", body = String),
+        (status = 404, description = "No recording with that uuid", body = String),
+    )
+)]
+async fn record_by_id(
+    State(state): State<AppState>,
+    access: StreamAccess,
+    Path(uuid): Path<String>,
+) -> Result<Json<RecordingIndexEntry>, Response> {
+    let entries = vod::index::load(&state.config.index_path)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to load index: {e}"),
+            )
+                .into_response()
+        })?;
+    let entry = vod::index::find_by_uuid(entries, &uuid)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "recording not found").into_response())?;
+    if !access.allows_entry(&entry) {
+        return Err(vod::tenant::forbidden());
+    }
+    Ok(Json(entry))
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 struct TimeQuery {
+    /// UNIX seconds, milliseconds or microseconds
     ts: i64,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/playback/{stream}/at",
+    tag = "playback",
+    params(("stream" = String, Path, description = "Stream id"), TimeQuery),
+    responses(
+        (status = 200, description = "Recording covering the instant, `seek` locates the instant inside it", body = vod::seek::RecordAt),
+        (status = 403, description = "Stream not allowed by the token's `streams` claim", body = String),
+        (status = 404, description = "No recording at that instant", body = String),
+    )
+)]
 async fn find_record_at(
     State(state): State<AppState>,
-    Path(stream): Path<String>,
+    access: StreamAccess,
+    NamedPath(stream): NamedPath<String>,
     Query(query): Query<TimeQuery>,
-) -> Result<Json<RecordingIndexEntry>, Response> {
+) -> Result<Json<vod::seek::RecordAt>, Response> {
+    if !access.allows(&stream) {
+        return Err(vod::tenant::forbidden());
+    }
     let ts_micros = normalize_ts_to_micros(query.ts);
-    let entries = load_index(&state.config.index_path).await.map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("failed to load index: {e}"),
-        )
-            .into_response()
-    })?;
+    let entries = vod::index::load(&state.config.index_path)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to load index: {e}"),
+            )
+                .into_response()
+        })?;
 
-    let record = entries.into_iter().find(|entry| {
-        if entry.stream != stream {
-            return false;
-        }
-        let start = entry.start_ts;
-        let end = entry
-            .end_ts
-            .or_else(|| entry.duration_ms.map(|d| start + (d as i64) * 1000));
-        match end {
-            Some(end) => ts_micros >= start && ts_micros <= end,
-            None => ts_micros >= start,
+    let record = vod::index::without_trashed(entries)
+        .into_iter()
+        .find(|entry| {
+            if entry.stream != stream || !access.allows_entry(entry) {
+                return false;
+            }
+            let start = entry.start_ts;
+            match entry.effective_end_ts() {
+                Some(end) => ts_micros >= start && ts_micros <= end,
+                None => ts_micros >= start,
+            }
+        });
+
+    let Some(entry) = record else {
+        return Err((StatusCode::NOT_FOUND, "record not found").into_response());
+    };
+    let seek = seek_point(&state, &entry, ts_micros).await;
+    Ok(Json(vod::seek::RecordAt { entry, seek }))
+}
+
+/// Where `ts` falls inside `entry`, from its `segments.jsonl` while the recorder writes
+/// one and from the manifest otherwise
+async fn seek_point(
+    state: &AppState,
+    entry: &RecordingIndexEntry,
+    ts: i64,
+) -> Option<vod::seek::SeekPoint> {
+    if matches!(entry.status, RecordingStatus::Missing) {
+        return None;
+    }
+    let operator = state.reader();
+    let max_bytes = state.config.playback.max_manifest_bytes;
+    let segments_path = format!("{}/{}", entry.record_dir, SEGMENTS_FILENAME);
+    if let Ok(body) = vod::manifest::read(&operator, &segments_path, max_bytes).await
+        && let Some(point) = vod::seek::from_segments(&vod::seek::parse(&body), ts)
+    {
+        return Some(point);
+    }
+    match vod::manifest::read(&operator, &entry.mpd_path, max_bytes).await {
+        Ok(mpd) => vod::seek::from_manifest(&mpd, entry.start_ts, ts),
+        Err(e) => {
+            warn!("no seek position in {}: {}", entry.mpd_path, e);
+            None
         }
-    });
+    }
+}
 
-    match record {
-        Some(record) => Ok(Json(record)),
-        None => Err((StatusCode::NOT_FOUND, "record not found").into_response()),
+#[utoipa::path(
+    get,
+    path = "/api/playback/{stream}/timeline",
+    tag = "playback",
+    params(("stream" = String, Path, description = "Stream id")),
+    responses(
+        (status = 200, description = "Continuous playback spans", body = Vec<TimelineSpan>),
+        (status = 403, description = "Stream not allowed by the token's `streams` claim", body = String),
+    )
+)]
+async fn timeline(
+    State(state): State<AppState>,
+    access: StreamAccess,
+    NamedPath(stream): NamedPath<String>,
+) -> Result<Json<Vec<TimelineSpan>>, Response> {
+    if !access.allows(&stream) {
+        return Err(vod::tenant::forbidden());
     }
+    let entries = vod::index::load(&state.config.index_path)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to load index: {e}"),
+            )
+                .into_response()
+        })?;
+    let entries = vod::index::without_trashed(entries)
+        .into_iter()
+        .filter(|entry| entry.stream == stream && access.allows_entry(entry))
+        .collect();
+    Ok(Json(vod::timeline::merge(entries)))
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ObjectQuery {
+    /// Override the `playback.redirect_min_bytes` check, for debugging
+    redirect: Option<RedirectMode>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/record/object/{path}",
+    tag = "playback",
+    params(
+        ("path" = String, Path, description = "Object path in storage"),
+        ("Range" = Option<String>, Header, description = "A single `bytes=` range, others get the whole object"),
+        ObjectQuery,
+    ),
+    responses(
+        (status = 200, description = "Object bytes", content_type = "application/octet-stream"),
+        (status = 206, description = "The requested range, see `Content-Range`", content_type = "application/octet-stream"),
+        (status = 307, description = "Presigned redirect when `playback.signed_redirect` is set and the object has at least `playback.redirect_min_bytes`"),
+        (status = 400, description = "Path is not a valid object key once percent-decoded", body = String),
+        (status = 403, description = "Stream not allowed by the token's `streams` claim", body = String),
+        (status = 404, description = "Object not found, with `Retry-After` while it is remembered as missing", body = String),
+        (status = 410, description = "Recording objects are missing from storage", body = Object),
+        (status = 416, description = "Range starts past the end of the object"),
+        (status = 503, description = "Too many concurrent reads, see `Retry-After`", body = String),
+    )
+)]
 async fn get_object(
     State(state): State<AppState>,
-    Path(path): Path<String>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    access: StreamAccess,
+    headers: header::HeaderMap,
+    params: RawPathParams,
+    Query(query): Query<ObjectQuery>,
 ) -> Result<Response, Response> {
+    let path = object_key(&params)?;
+    if !access.allows_object(&path) {
+        return Err(vod::tenant::forbidden());
+    }
     let is_mpd = path.ends_with(".mpd");
+    let held = held_by(&state, &path).await;
 
-    if !is_mpd && state.config.playback.signed_redirect {
-        let ttl = std::time::Duration::from_secs(state.config.playback.signed_ttl_seconds.max(1));
-        match state.operator.presign_read(&path, ttl).await {
-            Ok(req) => {
-                let uri = req.uri().to_string();
-                return Ok(
-                    (StatusCode::TEMPORARY_REDIRECT, [(header::LOCATION, uri)]).into_response()
-                );
-            }
-            Err(e) => {
-                tracing::error!("presign read failed for '{}': {}", path, e);
+    // Playback starts at the manifest, refuse it for recordings known to be gone
+    if is_mpd && is_missing(&state.config.index_path, &path).await {
+        return Err((
+            StatusCode::GONE,
+            Json(serde_json::json!({
+                "code": RECORDING_MISSING_CODE,
+                "message": "recording objects are missing from storage",
+            })),
+        )
+            .into_response());
+    }
+
+    if is_mpd && let Some(body) = state.manifests.get(&path) {
+        let (response, served) =
+            vod::range::serve_bytes(&headers, storage::content_type_for(&path), body);
+        vod::metrics::OBJECT_BYTES
+            .with_label_values(&["inline"])
+            .inc_by(served);
+        record_playback(&state, &headers, peer, &path, served);
+        return Ok(response);
+    }
+
+    // Retries of a segment not uploaded yet, answered without a storage round trip
+    if let Some(remaining) = state.not_found.get(&path) {
+        vod::metrics::NOT_FOUND_CACHE_HITS.inc();
+        return Err(not_found_response(remaining));
+    }
+
+    if !is_mpd
+        && state.config.playback.signed_redirect
+        && query.redirect != Some(RedirectMode::Never)
+    {
+        let found = object_size(&state, &held, &path).await;
+        let size = found.map(|(_, size)| size);
+        if vod::redirect::should_redirect(
+            query.redirect,
+            size,
+            state.config.playback.redirect_min_bytes,
+        ) {
+            // Signed by the destination that has the object, the healthiest one otherwise
+            let destination = match found {
+                Some((destination, _)) => destination,
+                None => state.replicas.candidates(&held)[0],
+            };
+            let ttl =
+                std::time::Duration::from_secs(state.config.playback.signed_ttl_seconds.max(1));
+            match destination
+                .operator
+                .current()
+                .presign_read(&path, ttl)
+                .await
+            {
+                Ok(req) => {
+                    vod::metrics::OBJECT_BYTES
+                        .with_label_values(&["redirect"])
+                        .inc_by(size.unwrap_or(0));
+                    record_playback(&state, &headers, peer, &path, size.unwrap_or(0));
+                    vod::metrics::OBJECT_DESTINATION
+                        .with_label_values(&[destination.name.as_str(), "redirect"])
+                        .inc();
+                    let uri = public_url(
+                        &state,
+                        &destination.config,
+                        &headers,
+                        peer,
+                        req.uri().to_string(),
+                    );
+                    return Ok(
+                        (StatusCode::TEMPORARY_REDIRECT, [(header::LOCATION, uri)]).into_response()
+                    );
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "presign read failed for '{}' on '{}': {}",
+                        path,
+                        destination.name,
+                        e
+                    );
+                }
             }
         }
     }
 
-    match state.operator.read(&path).await {
-        Ok(bytes) => {
-            let content_type = if path.ends_with(".mpd") {
-                "application/dash+xml"
-            } else if path.ends_with(".m4s") || path.ends_with(".mp4") {
-                if path.contains("audio_") {
-                    "audio/mp4"
-                } else {
-                    "video/mp4"
-                }
-            } else {
-                "application/octet-stream"
-            };
-            Ok((
-                StatusCode::OK,
-                [("content-type", content_type)],
-                bytes.to_vec(),
+    // Redirects above don't consume operator bandwidth, only proxied reads are limited
+    let _permit = match state.read_limiter.acquire(peer.ip()).await {
+        Ok(permit) => permit,
+        Err(rejected) => {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(
+                    header::RETRY_AFTER,
+                    rejected.retry_after.as_secs().to_string(),
+                )],
+                "too many concurrent reads",
             )
-                .into_response())
+                .into_response());
+        }
+    };
+
+    // Media from the files of an `fs` destination, manifests stay on the cached path.
+    // A missing file falls through to storage, which tries the other destinations
+    if !is_mpd
+        && let Some(destination) = state.replicas.candidates(&held).first()
+        && let Some(files) = state.local.get(&destination.name)
+    {
+        match files
+            .serve(&path, &headers, storage::content_type_for(&path))
+            .await
+        {
+            Ok(Some((response, served))) => {
+                vod::metrics::OBJECT_BYTES
+                    .with_label_values(&["local"])
+                    .inc_by(served);
+                vod::metrics::OBJECT_DESTINATION
+                    .with_label_values(&[destination.name.as_str(), "local"])
+                    .inc();
+                record_playback(&state, &headers, peer, &path, served);
+                return Ok(response);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("failed to serve '{}' from its file: {}", path, e),
+        }
+    }
+
+    match state.replicas.read(&held, &path).await {
+        Ok((destination, bytes)) => {
+            let bytes = bytes.to_vec();
+            if is_mpd {
+                state.manifests.insert(&path, &bytes);
+            }
+            let (response, served) =
+                vod::range::serve_bytes(&headers, storage::content_type_for(&path), bytes);
+            vod::metrics::OBJECT_BYTES
+                .with_label_values(&["inline"])
+                .inc_by(served);
+            vod::metrics::OBJECT_DESTINATION
+                .with_label_values(&[destination.name.as_str(), "inline"])
+                .inc();
+            record_playback(&state, &headers, peer, &path, served);
+            Ok(response)
+        }
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => {
+            state.not_found.insert(&path);
+            if let Some(suppressed) = state.not_found.should_log(&path) {
+                warn!(
+                    "object '{}' not found ({} misses since last logged): {}",
+                    path, suppressed, e
+                );
+            }
+            Err(not_found_response(
+                state.not_found.get(&path).unwrap_or_default(),
+            ))
         }
         Err(e) => {
             tracing::error!("failed to read object '{}': {}", path, e);
@@ -273,28 +1133,558 @@ async fn get_object(
     }
 }
 
-async fn load_index(path: &str) -> Result<Vec<RecordingIndexEntry>> {
-    let content = tokio::fs::read_to_string(path).await.unwrap_or_default();
-    let trimmed = content.trim();
-    if trimmed.is_empty() {
-        return Ok(Vec::new());
+/// 404 of a missing object, `Retry-After` when it is cached as missing for `remaining`
+fn not_found_response(remaining: std::time::Duration) -> Response {
+    if remaining.is_zero() {
+        return (StatusCode::NOT_FOUND, "object not found").into_response();
     }
+    let retry_after = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+    (
+        StatusCode::NOT_FOUND,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        "object not found",
+    )
+        .into_response()
+}
+
+/// Object key of the `{*path}` parameter, percent-decoded here and only here
+fn object_key(params: &RawPathParams) -> Result<String, Response> {
+    let raw = params
+        .iter()
+        .find_map(|(name, value)| (name == "path").then_some(value))
+        .unwrap_or_default();
+    storage::decode_key_from_path(raw)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())
+}
 
-    if trimmed.starts_with('[') {
-        let entries: Vec<RecordingIndexEntry> = serde_json::from_str(trimmed)?;
-        return Ok(entries);
+/// Count a served object in the client's playback session, with `[analytics]`
+fn record_playback(
+    state: &AppState,
+    headers: &header::HeaderMap,
+    peer: SocketAddr,
+    path: &str,
+    bytes: u64,
+) {
+    if let Some(ref analytics) = state.analytics {
+        let user_agent = headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok());
+        analytics.record(peer.ip(), user_agent, path, bytes);
     }
+}
 
-    let mut entries = Vec::new();
-    for line in trimmed.lines() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
+/// Destinations the index lists for the recording owning `path`, empty to try all
+async fn held_by(state: &AppState, path: &str) -> Vec<String> {
+    if !state.replicas.is_replicated() {
+        return Vec::new();
+    }
+    let Some(record_dir) = storage::get_directory(path) else {
+        return Vec::new();
+    };
+    state.index.replicas(record_dir).await.unwrap_or_else(|e| {
+        warn!("no replicas of '{}' from the index: {}", record_dir, e);
+        Vec::new()
+    })
+}
+
+/// Size of the object at `path` and the destination to sign it with. Sizes from the
+/// stat cache go with the healthiest destination `held` allows
+async fn object_size<'a>(
+    state: &'a AppState,
+    held: &[String],
+    path: &str,
+) -> Option<(&'a Destination, u64)> {
+    if let Some(size) = state.stat_cache.get(path) {
+        return Some((state.replicas.candidates(held)[0], size));
+    }
+    match state.replicas.stat(held, path).await {
+        Ok((destination, meta)) => {
+            state.stat_cache.insert(path, meta.content_length());
+            Some((destination, meta.content_length()))
         }
-        let entry: RecordingIndexEntry = serde_json::from_str(line)?;
-        entries.push(entry);
+        Err(e) => {
+            debug!("stat failed for '{}': {}", path, e);
+            None
+        }
+    }
+}
+
+/// `uri` rewritten to the public endpoint of the destination `storage` as the client
+/// reaches it
+fn public_url(
+    state: &AppState,
+    storage: &storage::StorageConfig,
+    headers: &header::HeaderMap,
+    peer: SocketAddr,
+    uri: String,
+) -> String {
+    let Some(public) = storage.public_url(&uri) else {
+        return uri;
+    };
+    let proto = if state.config.http.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    let origin = forwarded::Origin::from_request(
+        headers,
+        peer.ip(),
+        &state.config.http.trusted_proxies,
+        proto,
+    );
+    forwarded::absolute(origin.as_ref(), &public)
+}
+
+fn preview_response(status: JobStatus) -> Response {
+    let code = match status {
+        JobStatus::Queued | JobStatus::Running => StatusCode::ACCEPTED,
+        JobStatus::Done { .. } | JobStatus::Failed { .. } => StatusCode::OK,
+    };
+    (code, Json(status)).into_response()
+}
+
+/// Previews written by an earlier job or process
+async fn cached_previews(state: &AppState, record_dir: &str) -> Option<String> {
+    let vtt_path = format!("{}/{}", record_dir, vod::preview::VTT_FILENAME);
+    match state.reader().exists(&vtt_path).await {
+        Ok(true) => Some(vtt_path),
+        _ => None,
+    }
+}
+
+async fn find_record(
+    state: &AppState,
+    access: &StreamAccess,
+    stream: &str,
+    record: &str,
+) -> Result<RecordingIndexEntry, Response> {
+    let entries = vod::index::load(&state.config.index_path)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to load index: {e}"),
+            )
+                .into_response()
+        })?;
+    entries
+        .into_iter()
+        .rev()
+        .find(|entry| entry.stream == stream && entry.record == record)
+        .filter(|entry| access.allows_entry(entry))
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "recording not found").into_response())
+}
+
+fn manifest_error(path: &str, e: vod::manifest::ManifestError) -> Response {
+    warn!("manifest '{}': {}", path, e);
+    match e {
+        vod::manifest::ManifestError::TooLarge { .. } => (
+            StatusCode::BAD_GATEWAY,
+            Json(serde_json::json!({
+                "code": vod::manifest::MANIFEST_TOO_LARGE_CODE,
+                "message": e.to_string(),
+            })),
+        )
+            .into_response(),
+        vod::manifest::ManifestError::Read(_) => {
+            (StatusCode::BAD_GATEWAY, "failed to read manifest").into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/record/previews/{stream}/{record}",
+    tag = "previews",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+    ),
+    responses(
+        (status = 200, description = "Previews finished or failed", body = vod::preview::JobStatus),
+        (status = 202, description = "Preview job queued or running", body = vod::preview::JobStatus),
+        (status = 403, description = "Stream not allowed by the token's `streams` claim", body = String),
+        (status = 404, description = "Recording not found", body = String),
+        (status = 422, description = "Recording has no video track", body = Object),
+        (status = 502, description = "Manifest unreadable or larger than `playback.max_manifest_bytes`", body = Object),
+    )
+)]
+async fn create_previews(
+    State(state): State<AppState>,
+    access: StreamAccess,
+    NamedPath((stream, record)): NamedPath<(String, String)>,
+) -> Result<Response, Response> {
+    if !access.allows(&stream) {
+        return Err(vod::tenant::forbidden());
+    }
+    let key = format!("{stream}/{record}");
+    if let Some(status) = state.previews.status(&key)
+        && !matches!(status, JobStatus::Failed { .. })
+    {
+        return Ok(preview_response(status));
+    }
+
+    let entry = find_record(&state, &access, &stream, &record).await?;
+    if let Some(vtt_path) = cached_previews(&state, &entry.record_dir).await {
+        return Ok(preview_response(state.previews.mark_done(&key, vtt_path)));
     }
-    Ok(entries)
+
+    let operator = state.reader();
+    let mpd = vod::manifest::read(
+        &operator,
+        &entry.mpd_path,
+        state.config.playback.max_manifest_bytes,
+    )
+    .await
+    .map_err(|e| manifest_error(&entry.mpd_path, e))?;
+    let Some(track) = vod::preview::video_track(&mpd) else {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({
+                "code": PREVIEW_AUDIO_ONLY_CODE,
+                "message": "recording has no video track",
+            })),
+        )
+            .into_response());
+    };
+
+    Ok(preview_response(state.previews.submit(
+        &key,
+        operator,
+        entry.record_dir,
+        track,
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/record/previews/{stream}/{record}",
+    tag = "previews",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+    ),
+    responses(
+        (status = 200, description = "Previews finished or failed", body = vod::preview::JobStatus),
+        (status = 202, description = "Preview job queued or running", body = vod::preview::JobStatus),
+        (status = 403, description = "Stream not allowed by the token's `streams` claim", body = String),
+        (status = 404, description = "No previews for this recording", body = String),
+    )
+)]
+async fn preview_status(
+    State(state): State<AppState>,
+    access: StreamAccess,
+    NamedPath((stream, record)): NamedPath<(String, String)>,
+) -> Result<Response, Response> {
+    if !access.allows(&stream) {
+        return Err(vod::tenant::forbidden());
+    }
+    let key = format!("{stream}/{record}");
+    if let Some(status) = state.previews.status(&key) {
+        return Ok(preview_response(status));
+    }
+    let entry = find_record(&state, &access, &stream, &record).await?;
+    match cached_previews(&state, &entry.record_dir).await {
+        Some(vtt_path) => Ok(preview_response(state.previews.mark_done(&key, vtt_path))),
+        None => Err((StatusCode::NOT_FOUND, "no previews for this recording").into_response()),
+    }
+}
+
+#[cfg(feature = "transcode")]
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TranscodeQuery {
+    /// Name of a `[transcode.profiles]` entry, e.g. `h264_720p`
+    profile: String,
+}
+
+#[cfg(feature = "transcode")]
+fn transcode_response(status: vod::transcode::TranscodeStatus) -> Response {
+    use vod::transcode::TranscodeStatus;
+    let code = match status {
+        TranscodeStatus::Queued | TranscodeStatus::Running => StatusCode::ACCEPTED,
+        TranscodeStatus::Done { .. } | TranscodeStatus::Failed { .. } => StatusCode::OK,
+    };
+    (code, Json(status)).into_response()
+}
+
+/// Rendition written by an earlier job or process
+#[cfg(feature = "transcode")]
+async fn cached_rendition(state: &AppState, record_dir: &str, profile: &str) -> Option<String> {
+    let mpd_path = vod::transcode::manifest_path(record_dir, profile);
+    match state.reader().exists(&mpd_path).await {
+        Ok(true) => Some(mpd_path),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "transcode")]
+#[utoipa::path(
+    post,
+    path = "/api/record/transcode/{stream}/{record}",
+    tag = "transcode",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+        TranscodeQuery,
+    ),
+    responses(
+        (status = 200, description = "Rendition finished or failed", body = vod::transcode::TranscodeStatus),
+        (status = 202, description = "Transcode job queued or running", body = vod::transcode::TranscodeStatus),
+        (status = 400, description = "Profile not configured", body = Object),
+        (status = 403, description = "Stream not allowed by the token's `streams` claim", body = String),
+        (status = 404, description = "Recording not found", body = String),
+        (status = 422, description = "Recording has no video track", body = Object),
+        (status = 502, description = "Manifest unreadable or larger than `playback.max_manifest_bytes`", body = Object),
+    )
+)]
+async fn create_transcode(
+    State(state): State<AppState>,
+    access: StreamAccess,
+    NamedPath((stream, record)): NamedPath<(String, String)>,
+    Query(query): Query<TranscodeQuery>,
+) -> Result<Response, Response> {
+    if !access.allows(&stream) {
+        return Err(vod::tenant::forbidden());
+    }
+    if state.transcodes.profile(&query.profile).is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "code": api::recorder::TRANSCODE_UNKNOWN_PROFILE_CODE,
+                "message": format!("no transcode profile named '{}'", query.profile),
+            })),
+        )
+            .into_response());
+    }
+    let key = format!("{stream}/{record}/{}", query.profile);
+    if let Some(status) = state.transcodes.status(&key)
+        && !matches!(status, vod::transcode::TranscodeStatus::Failed { .. })
+    {
+        return Ok(transcode_response(status));
+    }
+
+    let entry = find_record(&state, &access, &stream, &record).await?;
+    if let Some(mpd_path) = cached_rendition(&state, &entry.record_dir, &query.profile).await {
+        return Ok(transcode_response(
+            state.transcodes.mark_done(&key, mpd_path),
+        ));
+    }
+
+    let operator = state.reader();
+    let mpd = vod::manifest::read(
+        &operator,
+        &entry.mpd_path,
+        state.config.playback.max_manifest_bytes,
+    )
+    .await
+    .map_err(|e| manifest_error(&entry.mpd_path, e))?;
+    let Some(source) = vod::transcode::source(&mpd) else {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({
+                "code": PREVIEW_AUDIO_ONLY_CODE,
+                "message": "recording has no video track",
+            })),
+        )
+            .into_response());
+    };
+
+    Ok(transcode_response(state.transcodes.submit(
+        &key,
+        operator,
+        entry.record_dir,
+        query.profile,
+        source,
+    )))
+}
+
+#[cfg(feature = "transcode")]
+#[utoipa::path(
+    get,
+    path = "/api/record/transcode/{stream}/{record}",
+    tag = "transcode",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+        TranscodeQuery,
+    ),
+    responses(
+        (status = 200, description = "Rendition finished or failed", body = vod::transcode::TranscodeStatus),
+        (status = 202, description = "Transcode job queued or running", body = vod::transcode::TranscodeStatus),
+        (status = 403, description = "Stream not allowed by the token's `streams` claim", body = String),
+        (status = 404, description = "No rendition of this profile for this recording", body = String),
+    )
+)]
+async fn transcode_status(
+    State(state): State<AppState>,
+    access: StreamAccess,
+    NamedPath((stream, record)): NamedPath<(String, String)>,
+    Query(query): Query<TranscodeQuery>,
+) -> Result<Response, Response> {
+    if !access.allows(&stream) {
+        return Err(vod::tenant::forbidden());
+    }
+    let key = format!("{stream}/{record}/{}", query.profile);
+    if let Some(status) = state.transcodes.status(&key) {
+        return Ok(transcode_response(status));
+    }
+    let entry = find_record(&state, &access, &stream, &record).await?;
+    match cached_rendition(&state, &entry.record_dir, &query.profile).await {
+        Some(mpd_path) => Ok(transcode_response(
+            state.transcodes.mark_done(&key, mpd_path),
+        )),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            "no rendition of this profile for this recording",
+        )
+            .into_response()),
+    }
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ClipQuery {
+    /// Start of the window, milliseconds into the recording
+    from_ms: u64,
+    /// End of the window, milliseconds into the recording
+    to_ms: u64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/record/clip/{stream}/{record}.mpd",
+    tag = "playback",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+        ClipQuery,
+    ),
+    responses(
+        (status = 200, description = "Static manifest of the segments overlapping the window", content_type = "application/dash+xml"),
+        (status = 400, description = "Empty window or window outside the recording", body = String),
+        (status = 403, description = "Stream not allowed by the token's `streams` claim", body = String),
+        (status = 404, description = "Recording not found", body = String),
+        (status = 410, description = "Recording objects are missing from storage", body = Object),
+        (status = 502, description = "Manifest unreadable or larger than `playback.max_manifest_bytes`", body = Object),
+    )
+)]
+async fn clip_manifest(
+    State(state): State<AppState>,
+    access: StreamAccess,
+    NamedPath((stream, file)): NamedPath<(String, String)>,
+    Query(query): Query<ClipQuery>,
+) -> Result<Response, Response> {
+    if !access.allows(&stream) {
+        return Err(vod::tenant::forbidden());
+    }
+    let Some(record) = file.strip_suffix(".mpd") else {
+        return Err((StatusCode::NOT_FOUND, "recording not found").into_response());
+    };
+    if let Err(rule) = NameRules::from_ref(&state).check_record(record) {
+        return Err((StatusCode::BAD_REQUEST, Json(rule.error("record"))).into_response());
+    }
+    let entry = find_record(&state, &access, &stream, record).await?;
+    if matches!(entry.status, RecordingStatus::Missing) {
+        return Err((
+            StatusCode::GONE,
+            Json(serde_json::json!({
+                "code": RECORDING_MISSING_CODE,
+                "message": "recording objects are missing from storage",
+            })),
+        )
+            .into_response());
+    }
+
+    let mpd = vod::manifest::read(
+        &state.reader(),
+        &entry.mpd_path,
+        state.config.playback.max_manifest_bytes,
+    )
+    .await
+    .map_err(|e| manifest_error(&entry.mpd_path, e))?;
+    // Relative to the clip URL so reverse proxy prefixes carry over to segments
+    let base_url = format!(
+        "../../object/{}/",
+        storage::encode_key_for_url(&entry.record_dir)
+    );
+    let clipped = vod::clip::clip(&mpd, query.from_ms, query.to_ms, &base_url)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+    Ok((
+        StatusCode::OK,
+        [(
+            header::CONTENT_TYPE,
+            storage::content_type_for(&entry.mpd_path),
+        )],
+        clipped,
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/record/size/{stream}/{record}",
+    tag = "playback",
+    params(
+        ("stream" = String, Path, description = "Stream id"),
+        ("record" = String, Path, description = "Record id"),
+    ),
+    responses(
+        (status = 200, description = "Stored bytes of the recording by track", body = vod::size::RecordingBytes),
+        (status = 403, description = "Stream not allowed by the token's `streams` claim", body = String),
+        (status = 404, description = "Recording not found", body = String),
+        (status = 410, description = "Recording objects are missing from storage", body = Object),
+        (status = 500, description = "Listing the recording failed", body = String),
+    )
+)]
+async fn record_size(
+    State(state): State<AppState>,
+    access: StreamAccess,
+    NamedPath((stream, record)): NamedPath<(String, String)>,
+) -> Result<Json<vod::size::RecordingBytes>, Response> {
+    if !access.allows(&stream) {
+        return Err(vod::tenant::forbidden());
+    }
+    let entry = find_record(&state, &access, &stream, &record).await?;
+    if matches!(entry.status, RecordingStatus::Missing) {
+        return Err((
+            StatusCode::GONE,
+            Json(serde_json::json!({
+                "code": RECORDING_MISSING_CODE,
+                "message": "recording objects are missing from storage",
+            })),
+        )
+            .into_response());
+    }
+    state
+        .sizes
+        .size(
+            &state.reader(),
+            &entry,
+            state.config.playback.max_manifest_bytes,
+        )
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!("sizing {} failed: {}", entry.key(), e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to list recording",
+            )
+                .into_response()
+        })
+}
+
+/// Whether the latest index line for the recording owning `mpd_path` marks it missing
+async fn is_missing(index_path: &str, mpd_path: &str) -> bool {
+    let Ok(entries) = vod::index::load(index_path).await else {
+        return false;
+    };
+    entries
+        .into_iter()
+        .rev()
+        .find(|entry| entry.mpd_path == mpd_path)
+        .is_some_and(|entry| matches!(entry.status, RecordingStatus::Missing))
 }
 
 fn normalize_ts_to_micros(ts: i64) -> i64 {
@@ -306,3 +1696,90 @@ fn normalize_ts_to_micros(ts: i64) -> i64 {
         ts * 1_000_000
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_redacts_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("livevod.toml");
+        std::fs::write(
+            &path,
+            r#"
+[auth]
+mode = "jwt"
+secret = "auth-secret-0"
+tokens = ["auth-token-0"]
+
+[analytics]
+sink = "webhook"
+webhook_url = "http://127.0.0.1:9000/sessions"
+webhook_token = "webhook-token-0"
+
+[s3]
+[[s3.keys]]
+access_key_id = "LOBBYKEY"
+secret_access_key = "gateway-secret-0"
+
+[storage]
+type = "s3"
+bucket = "recordings"
+secret_access_key = "s3-secret-0"
+session_token = "s3-session-0"
+
+[internal]
+secret = "internal-secret-0"
+"#,
+        )
+        .unwrap();
+        let cfg: Config = config_loader::Loader::new("livevod")
+            .path(Some(path.display().to_string()))
+            .env([])
+            .load()
+            .unwrap();
+        let debug = format!("{cfg:?}");
+        for secret in [
+            "auth-secret-0",
+            "auth-token-0",
+            "webhook-token-0",
+            "gateway-secret-0",
+            "s3-secret-0",
+            "s3-session-0",
+            "internal-secret-0",
+        ] {
+            assert!(!debug.contains(secret), "{secret} in {debug}");
+        }
+        assert!(debug.contains("LOBBYKEY"), "{debug}");
+        // Still there where they are used
+        assert_eq!(cfg.internal.secret.expose_secret(), "internal-secret-0");
+    }
+
+    #[test]
+    fn test_validate_storage_is_optional() {
+        let mut cfg = Config::default();
+        let err = cfg.validate().unwrap_err().to_string();
+        assert!(err.contains("playback.local_fallback_dir"), "{err}");
+
+        cfg.playback.local_fallback_dir = Some("./recordings".to_string());
+        cfg.validate().unwrap();
+        cfg.playback.signed_redirect = true;
+        let err = cfg.validate().unwrap_err().to_string();
+        assert!(err.contains("signed_redirect"), "{err}");
+        cfg.playback.signed_redirect = false;
+        cfg.replicas.push(vod::replica::ReplicaConfig {
+            name: "dr".to_string(),
+            storage: storage::StorageConfig::default(),
+        });
+        assert!(cfg.validate().is_err());
+
+        // Served from storage first, the directory is the fallback
+        cfg.storage = Some(storage::StorageConfig::default());
+        cfg.playback.signed_redirect = true;
+        cfg.validate().unwrap();
+        cfg.replicas[0].name = vod::replica::LOCAL.to_string();
+        let err = cfg.validate().unwrap_err().to_string();
+        assert!(err.contains("reserved"), "{err}");
+    }
+}
@@ -7,15 +7,46 @@ mod utils;
 #[derive(Parser)]
 #[command(version)]
 struct Args {
-    #[arg(short, long)]
-    config: Option<String>,
+    #[command(flatten)]
+    config: config_loader::ConfigArgs,
+    /// Install an index backup from storage and exit, the latest one without KEY
+    #[cfg(feature = "recorder")]
+    #[arg(long, value_name = "KEY")]
+    restore_index: Option<Option<String>>,
+    /// Add entries for recordings in storage missing from the index and exit
+    #[cfg(feature = "recorder")]
+    #[arg(long, conflicts_with = "restore_index")]
+    rebuild_index: bool,
+    /// With --restore-index, replace a local index newer than the backup
+    #[cfg(feature = "recorder")]
+    #[arg(long, requires = "restore_index")]
+    force: bool,
+    /// Remove index locks left behind by a process that is no longer running on this host
+    #[cfg(feature = "recorder")]
+    #[arg(long)]
+    force_unlock: bool,
+    #[cfg(feature = "recorder")]
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[cfg(feature = "recorder")]
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Record synthetic streams through the segmenter, index and uploader, report the
+    /// rates achieved and exit
+    BenchRecorder(liveion::recorder::BenchOptions),
 }
 
 #[tokio::main]
 async fn main() {
     liveion::metrics_register();
     let args = Args::parse();
-    let cfg: liveion::config::Config = utils::load("live777".to_string(), args.config);
+    #[cfg(feature = "recorder")]
+    if let Some(Command::BenchRecorder(opts)) = &args.command {
+        std::process::exit(bench_recorder(opts).await);
+    }
+    let cfg: liveion::config::Config = config_loader::load_or_exit("live777", &args.config);
     cfg.validate().unwrap();
     log::set(format!(
         "live777={},liveion={},net4mqtt={},http_log={},webrtc=error",
@@ -23,12 +54,117 @@ async fn main() {
     ));
     warn!("set log level : {}", cfg.log.level);
     debug!("config : {:?}", cfg);
+
+    #[cfg(feature = "recorder")]
+    if args.force_unlock {
+        match liveion::recorder::force_unlock(&cfg.recorder) {
+            Ok(removed) => {
+                for path in removed {
+                    warn!("removed stale index lock {}", path.display());
+                }
+            }
+            Err(e) => {
+                tracing::error!("index locks not removed: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    #[cfg(feature = "recorder")]
+    if args.restore_index.is_some() || args.rebuild_index {
+        std::process::exit(index_tool(&args, &cfg.recorder).await);
+    }
+
     let listener = tokio::net::TcpListener::bind(&cfg.http.listen)
         .await
         .unwrap();
     let addr = listener.local_addr().unwrap();
     info!("Server listening on {}", addr);
 
+    #[cfg(all(unix, feature = "recorder"))]
+    tokio::spawn(reload_on_hangup(args.config.clone()));
+
     liveion::serve(cfg, listener, utils::shutdown_signal()).await;
     info!("Server shutdown");
 }
+
+/// Offline index restore or rebuild, returns the exit code
+#[cfg(feature = "recorder")]
+async fn index_tool(args: &Args, cfg: &liveion::config::RecorderConfig) -> i32 {
+    use liveion::recorder::RestoreOutcome;
+
+    if args.rebuild_index {
+        return match liveion::recorder::rebuild_index_offline(cfg).await {
+            Ok(added) => {
+                info!("index rebuilt, {} entries added", added);
+                0
+            }
+            Err(e) => {
+                tracing::error!("index rebuild failed: {:#}", e);
+                1
+            }
+        };
+    }
+    let key = args.restore_index.clone().flatten();
+    match liveion::recorder::restore_index_offline(cfg, key.as_deref(), args.force).await {
+        Ok(RestoreOutcome::Restored(resp)) => {
+            info!(
+                "index restored from {} ({} entries, {} replaced)",
+                resp.key, resp.entries, resp.replaced
+            );
+            0
+        }
+        Ok(RestoreOutcome::NotFound(reason) | RestoreOutcome::Conflict(reason)) => {
+            tracing::error!("index not restored: {}", reason);
+            1
+        }
+        Err(e) => {
+            tracing::error!("index restore failed: {:#}", e);
+            1
+        }
+    }
+}
+
+/// Run the recorder bench, no config needed, returns the exit code
+#[cfg(feature = "recorder")]
+async fn bench_recorder(opts: &liveion::recorder::BenchOptions) -> i32 {
+    log::set("live777=info,liveion=warn,webrtc=error".to_string());
+    match liveion::recorder::bench_recorder(opts).await {
+        Ok(report) if opts.json => {
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            0
+        }
+        Ok(report) => {
+            print!("{}", report.table());
+            0
+        }
+        Err(e) => {
+            tracing::error!("recorder bench failed: {:#}", e);
+            1
+        }
+    }
+}
+
+/// Re-read the config file on SIGHUP and apply the parts that support reloading
+#[cfg(all(unix, feature = "recorder"))]
+async fn reload_on_hangup(args: config_loader::ConfigArgs) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = signal(SignalKind::hangup()).unwrap();
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading recorder schedules");
+        let cfg: liveion::config::Config =
+            match config_loader::Loader::new("live777").args(&args).load() {
+                Ok(cfg) => cfg,
+                Err(e) => {
+                    warn!("config reload rejected: {}", e);
+                    continue;
+                }
+            };
+        if let Err(e) = cfg.validate() {
+            warn!("config reload rejected: {}", e);
+            continue;
+        }
+        liveion::recorder::reload_schedules(&cfg.recorder).await;
+    }
+}
@@ -0,0 +1,498 @@
+//! Export of playback sessions, see `[analytics]`.
+//!
+//! Sessions closed by the [`Sessionizer`] wait in a bounded buffer and are delivered
+//! every `flush_interval_seconds` and on shutdown, to a webhook in batches or to a
+//! rotating JSON Lines file. While the sink fails they stay buffered, the oldest
+//! dropped once `max_buffered_sessions` are waiting.
+
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use config_loader::Secret;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use super::metrics;
+use super::sessions::{PlaybackSession, Request, Sessionizer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    /// POST batches of sessions as a JSON array to `webhook_url`
+    Webhook,
+    /// Append one session per line to `file_path`
+    File,
+}
+
+/// Playback session export, off without a `sink`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AnalyticsConfig {
+    #[serde(default)]
+    pub sink: Option<SinkKind>,
+    #[serde(default)]
+    pub webhook_url: String,
+    /// Sent as `Authorization: Bearer` to the webhook when set
+    #[serde(default)]
+    pub webhook_token: Secret,
+    /// Sessions per webhook request
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    #[serde(default = "default_file_path")]
+    pub file_path: String,
+    /// Rotate the file once it reaches this size
+    #[serde(default = "default_max_file_bytes")]
+    pub max_file_bytes: u64,
+    /// Rotated files kept next to the current one, `.1` the newest
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    /// A client's requests of a recording further apart than this start a new session
+    #[serde(default = "default_session_idle_timeout_seconds")]
+    pub session_idle_timeout_seconds: u64,
+    #[serde(default = "default_flush_interval_seconds")]
+    pub flush_interval_seconds: u64,
+    /// Sessions kept while the sink fails, the oldest are dropped beyond
+    #[serde(default = "default_max_buffered_sessions")]
+    pub max_buffered_sessions: usize,
+    /// Mixed into client fingerprints, so they can't be matched to addresses by
+    /// hashing them
+    #[serde(default)]
+    pub fingerprint_salt: String,
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        Self {
+            sink: None,
+            webhook_url: String::new(),
+            webhook_token: Secret::default(),
+            batch_size: default_batch_size(),
+            file_path: default_file_path(),
+            max_file_bytes: default_max_file_bytes(),
+            max_files: default_max_files(),
+            session_idle_timeout_seconds: default_session_idle_timeout_seconds(),
+            flush_interval_seconds: default_flush_interval_seconds(),
+            max_buffered_sessions: default_max_buffered_sessions(),
+            fingerprint_salt: String::new(),
+        }
+    }
+}
+
+fn default_batch_size() -> usize {
+    500
+}
+
+fn default_file_path() -> String {
+    "./analytics/playback.jsonl".to_string()
+}
+
+fn default_max_file_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_max_files() -> usize {
+    5
+}
+
+fn default_session_idle_timeout_seconds() -> u64 {
+    60
+}
+
+fn default_flush_interval_seconds() -> u64 {
+    30
+}
+
+fn default_max_buffered_sessions() -> usize {
+    10_000
+}
+
+/// Hash of the client's address and user agent identifying it across its requests
+pub fn fingerprint(salt: &str, ip: IpAddr, user_agent: Option<&str>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(ip.to_string().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(user_agent.unwrap_or_default().as_bytes());
+    hasher.finalize()[..16]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+enum Sink {
+    Webhook {
+        client: reqwest::Client,
+        url: String,
+        token: String,
+        batch_size: usize,
+    },
+    File(RotatingFile),
+}
+
+impl Sink {
+    /// Deliver `sessions`, `Ok(n)` when the first `n` went out
+    async fn deliver(&self, sessions: &[PlaybackSession]) -> Result<usize> {
+        match self {
+            Sink::Webhook {
+                client,
+                url,
+                token,
+                batch_size,
+            } => {
+                let batch = &sessions[..sessions.len().min(*batch_size)];
+                let mut req = client.post(url).json(batch);
+                if !token.is_empty() {
+                    req = req.bearer_auth(token);
+                }
+                req.send()
+                    .await
+                    .and_then(|resp| resp.error_for_status())
+                    .with_context(|| format!("POST {url}"))?;
+                Ok(batch.len())
+            }
+            Sink::File(file) => {
+                file.append(sessions).await?;
+                Ok(sessions.len())
+            }
+        }
+    }
+}
+
+/// JSON Lines file rotated to `{path}.1` … `{path}.{max_files}` at `max_bytes`
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    async fn append(&self, sessions: &[PlaybackSession]) -> Result<()> {
+        let mut lines = Vec::new();
+        for session in sessions {
+            serde_json::to_writer(&mut lines, session)?;
+            lines.push(b'\n');
+        }
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let size = match tokio::fs::metadata(&self.path).await {
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        };
+        if size > 0 && size + lines.len() as u64 > self.max_bytes {
+            self.rotate().await?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .with_context(|| format!("open {}", self.path.display()))?;
+        file.write_all(&lines).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    async fn rotate(&self) -> Result<()> {
+        if self.max_files == 0 {
+            tokio::fs::remove_file(&self.path).await?;
+            return Ok(());
+        }
+        let _ = tokio::fs::remove_file(rotated(&self.path, self.max_files)).await;
+        for n in (1..self.max_files).rev() {
+            let from = rotated(&self.path, n);
+            if tokio::fs::try_exists(&from).await.unwrap_or(false) {
+                tokio::fs::rename(&from, rotated(&self.path, n + 1)).await?;
+            }
+        }
+        tokio::fs::rename(&self.path, rotated(&self.path, 1)).await?;
+        Ok(())
+    }
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+/// Sessions waiting for the sink, numbered so a delivery removes exactly what it sent
+/// even when older ones were dropped meanwhile
+#[derive(Default)]
+struct Buffer {
+    sessions: VecDeque<(u64, PlaybackSession)>,
+    next: u64,
+}
+
+pub struct Analytics {
+    salt: String,
+    sessions: Mutex<Sessionizer>,
+    buffer: Mutex<Buffer>,
+    max_buffered: usize,
+    sink: Sink,
+    /// One delivery at a time, in order
+    delivering: tokio::sync::Mutex<()>,
+    flush_interval: Duration,
+}
+
+impl Analytics {
+    /// `None` without a sink
+    pub fn new(cfg: &AnalyticsConfig) -> Result<Option<Self>> {
+        let sink = match cfg.sink {
+            None => return Ok(None),
+            Some(SinkKind::Webhook) => {
+                if cfg.webhook_url.is_empty() {
+                    anyhow::bail!("analytics.webhook_url is required with sink = \"webhook\"");
+                }
+                Sink::Webhook {
+                    client: reqwest::Client::builder()
+                        .timeout(Duration::from_secs(10))
+                        .build()?,
+                    url: cfg.webhook_url.clone(),
+                    token: cfg.webhook_token.expose_secret().to_string(),
+                    batch_size: cfg.batch_size.max(1),
+                }
+            }
+            Some(SinkKind::File) => Sink::File(RotatingFile {
+                path: PathBuf::from(&cfg.file_path),
+                max_bytes: cfg.max_file_bytes,
+                max_files: cfg.max_files,
+            }),
+        };
+        Ok(Some(Self {
+            salt: cfg.fingerprint_salt.clone(),
+            sessions: Mutex::new(Sessionizer::new(Duration::from_secs(
+                cfg.session_idle_timeout_seconds,
+            ))),
+            buffer: Mutex::default(),
+            max_buffered: cfg.max_buffered_sessions.max(1),
+            sink,
+            delivering: tokio::sync::Mutex::new(()),
+            flush_interval: Duration::from_secs(cfg.flush_interval_seconds.max(1)),
+        }))
+    }
+
+    /// Count a served object of `bytes` in the session of its client
+    pub fn record(&self, ip: IpAddr, user_agent: Option<&str>, path: &str, bytes: u64) {
+        let client = fingerprint(&self.salt, ip, user_agent);
+        let ended = self.sessions.lock().unwrap().observe(Request {
+            client: &client,
+            path,
+            bytes,
+            at_ms: chrono::Utc::now().timestamp_millis(),
+        });
+        if let Some(session) = ended {
+            self.buffer(vec![session]);
+        }
+    }
+
+    fn buffer(&self, sessions: Vec<PlaybackSession>) {
+        if sessions.is_empty() {
+            return;
+        }
+        metrics::ANALYTICS_SESSIONS.inc_by(sessions.len() as u64);
+        let mut buffer = self.buffer.lock().unwrap();
+        for session in sessions {
+            let seq = buffer.next;
+            buffer.next += 1;
+            buffer.sessions.push_back((seq, session));
+        }
+        let excess = buffer.sessions.len().saturating_sub(self.max_buffered);
+        if excess > 0 {
+            buffer.sessions.drain(..excess);
+            metrics::ANALYTICS_SESSIONS_DROPPED.inc_by(excess as u64);
+        }
+        metrics::ANALYTICS_BUFFERED_SESSIONS.set(buffer.sessions.len() as i64);
+    }
+
+    /// Deliver the buffered sessions, after closing the idle ones or, with `all`, every
+    /// open one
+    pub async fn flush(&self, all: bool) {
+        let closed = {
+            let mut sessions = self.sessions.lock().unwrap();
+            if all {
+                sessions.drain()
+            } else {
+                sessions.expire(chrono::Utc::now().timestamp_millis())
+            }
+        };
+        self.buffer(closed);
+
+        let _delivering = self.delivering.lock().await;
+        loop {
+            let pending: Vec<(u64, PlaybackSession)> = self
+                .buffer
+                .lock()
+                .unwrap()
+                .sessions
+                .iter()
+                .cloned()
+                .collect();
+            if pending.is_empty() {
+                return;
+            }
+            let sessions: Vec<PlaybackSession> = pending.iter().map(|(_, s)| s.clone()).collect();
+            match self.sink.deliver(&sessions).await {
+                Ok(sent) => {
+                    let last = pending[sent.max(1) - 1].0;
+                    let mut buffer = self.buffer.lock().unwrap();
+                    buffer.sessions.retain(|(seq, _)| *seq > last);
+                    metrics::ANALYTICS_BUFFERED_SESSIONS.set(buffer.sessions.len() as i64);
+                }
+                Err(e) => {
+                    warn!(
+                        "[analytics] delivering {} sessions failed: {:#}",
+                        sessions.len(),
+                        e
+                    );
+                    return;
+                }
+            }
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut ticker = tokio::time::interval(self.flush_interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            self.flush(false).await;
+        }
+    }
+
+    /// Close every session and deliver what is buffered, once the server stopped
+    pub async fn shutdown(&self) {
+        self.flush(true).await;
+        let left = self.buffer.lock().unwrap().sessions.len();
+        if left > 0 {
+            warn!("[analytics] {} sessions undelivered at shutdown", left);
+        } else {
+            info!("[analytics] sessions delivered");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip() -> IpAddr {
+        IpAddr::from([10, 0, 0, 1])
+    }
+
+    fn file_config(dir: &Path) -> AnalyticsConfig {
+        AnalyticsConfig {
+            sink: Some(SinkKind::File),
+            file_path: dir.join("playback.jsonl").display().to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn read_sessions(path: &Path) -> Vec<PlaybackSession> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let a = fingerprint("", ip(), Some("dash.js"));
+        assert_eq!(a.len(), 32);
+        assert_eq!(a, fingerprint("", ip(), Some("dash.js")));
+        assert_ne!(a, fingerprint("", ip(), Some("hls.js")));
+        assert_ne!(a, fingerprint("salt", ip(), Some("dash.js")));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_written_on_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let analytics = Analytics::new(&file_config(dir.path())).unwrap().unwrap();
+        for path in ["cam/1/manifest.mpd", "cam/1/v_seg_0001.m4s"] {
+            analytics.record(ip(), Some("dash.js"), path, 100);
+        }
+        analytics.record(ip(), Some("hls.js"), "cam/1/manifest.mpd", 10);
+
+        // Open sessions stay in memory until they idle out
+        analytics.flush(false).await;
+        assert!(!dir.path().join("playback.jsonl").exists());
+
+        analytics.shutdown().await;
+        let mut sessions = read_sessions(&dir.path().join("playback.jsonl"));
+        sessions.sort_by_key(|s| s.bytes);
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].bytes, 10);
+        assert_eq!(sessions[1].bytes, 200);
+        assert_eq!(sessions[1].segments, 1);
+        assert_eq!(sessions[1].client, fingerprint("", ip(), Some("dash.js")));
+    }
+
+    #[tokio::test]
+    async fn test_file_rotates() {
+        let dir = tempfile::tempdir().unwrap();
+        let analytics = Analytics::new(&AnalyticsConfig {
+            max_file_bytes: 1000,
+            max_files: 2,
+            ..file_config(dir.path())
+        })
+        .unwrap()
+        .unwrap();
+        for i in 0..40 {
+            analytics.record(ip(), None, &format!("cam/{i}/manifest.mpd"), 1);
+            analytics.flush(true).await;
+        }
+        let path = dir.path().join("playback.jsonl");
+        let current = read_sessions(&path);
+        let (one, two) = (rotated(&path, 1), rotated(&path, 2));
+        assert!(std::fs::metadata(&path).unwrap().len() <= 1000);
+        assert!(!rotated(&path, 3).exists());
+        // The newest sessions are in the current file, the older in `.1` and `.2`
+        let newest = current.last().unwrap();
+        assert_eq!(newest.record, "39");
+        let older = read_sessions(&one);
+        assert!(older.last().unwrap().record.parse::<u32>().unwrap() < 39);
+        assert!(two.exists());
+    }
+
+    #[tokio::test]
+    async fn test_failing_sink_drops_oldest() {
+        let dir = tempfile::tempdir().unwrap();
+        // A directory where the file should be: every write fails
+        let path = dir.path().join("playback.jsonl");
+        std::fs::create_dir_all(&path).unwrap();
+        let analytics = Analytics::new(&AnalyticsConfig {
+            max_buffered_sessions: 5,
+            ..file_config(dir.path())
+        })
+        .unwrap()
+        .unwrap();
+        let dropped = metrics::ANALYTICS_SESSIONS_DROPPED.get();
+        for i in 0..8 {
+            analytics.record(ip(), None, &format!("cam/{i}/manifest.mpd"), 1);
+            analytics.flush(true).await;
+        }
+        assert_eq!(metrics::ANALYTICS_SESSIONS_DROPPED.get() - dropped, 3);
+        let buffered: Vec<String> = {
+            let buffer = analytics.buffer.lock().unwrap();
+            buffer
+                .sessions
+                .iter()
+                .map(|(_, s)| s.record.clone())
+                .collect()
+        };
+        assert_eq!(buffered, ["3", "4", "5", "6", "7"]);
+
+        // Delivered in order once the sink recovers
+        std::fs::remove_dir(&path).unwrap();
+        analytics.flush(false).await;
+        let records: Vec<String> = read_sessions(&path).into_iter().map(|s| s.record).collect();
+        assert_eq!(records, ["3", "4", "5", "6", "7"]);
+        assert!(analytics.buffer.lock().unwrap().sessions.is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+//! Time-clipped manifests of a recording.
+//!
+//! A clip is the stored MPD cut down to the segments overlapping the window, with the
+//! original segment objects referenced through a `BaseURL`. Nothing is copied or
+//! re-encoded, so a window starting mid-segment plays from the segment containing it
+//! and `presentationTimeOffset` moves the player's start to the requested instant.
+
+use super::preview::attr;
+
+pub use dash::ClipError;
+pub(crate) use dash::{to_ms, to_ticks};
+
+/// One `<SegmentTemplate>` of the manifest, `start..end` byte range of the element
+pub(crate) struct Track {
+    pub start: usize,
+    pub end: usize,
+    pub timescale: u64,
+    pub start_number: u64,
+    /// `(t, d)` per segment, `r` repeats expanded
+    pub segments: Vec<(u64, u64)>,
+}
+
+pub(crate) fn parse_tracks(mpd: &str) -> Vec<Track> {
+    let mut tracks = Vec::new();
+    let mut offset = 0;
+    while let Some(begin) = mpd[offset..].find("<SegmentTemplate") {
+        let start = offset + begin;
+        let Some(len) = mpd[start..].find("</SegmentTemplate>") else {
+            break;
+        };
+        let end = start + len + "</SegmentTemplate>".len();
+        let element = &mpd[start..end];
+        let timescale = attr(element, "timescale")
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(1);
+        let start_number = attr(element, "startNumber")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1);
+
+        let mut segments = Vec::new();
+        let mut next = 0u64;
+        for (pos, _) in element.match_indices("<S ") {
+            let s = &element[pos..];
+            let s = &s[..s.find('>').unwrap_or(s.len())];
+            let t: u64 = attr(s, "t").and_then(|t| t.parse().ok()).unwrap_or(next);
+            let d: u64 = attr(s, "d").and_then(|d| d.parse().ok()).unwrap_or(0);
+            let r: u64 = attr(s, "r").and_then(|r| r.parse().ok()).unwrap_or(0);
+            for i in 0..=r {
+                segments.push((t + i * d, d));
+            }
+            next = t + (r + 1) * d;
+        }
+        tracks.push(Track {
+            start,
+            end,
+            timescale,
+            start_number,
+            segments,
+        });
+        offset = end;
+    }
+    tracks
+}
+
+/// `mpd` clipped to `from_ms..to_ms` of the recording, segments resolved against
+/// `base_url`.
+///
+/// Each track keeps the segments overlapping the window. Tracks are clipped on their
+/// own timescale, so audio and video stay aligned on the window, not on segments.
+pub fn clip(mpd: &str, from_ms: u64, to_ms: u64, base_url: &str) -> Result<String, ClipError> {
+    let mut mpd: dash::Mpd = mpd.parse().map_err(|_| ClipError::NoSegments)?;
+    mpd.clip(from_ms, to_ms)?;
+    if let Some(period) = mpd.periods.first_mut() {
+        period.set_base_url(base_url);
+    }
+    Ok(mpd.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MPD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<MPD type="static" mediaPresentationDuration="PT25.000S" minBufferTime="PT6.000S">
+    <Period id="0" start="PT0.0S">
+        <AdaptationSet id="0" contentType="video">
+            <Representation id="0" mimeType="video/mp4">
+                <SegmentTemplate timescale="90000" initialization="v_init.m4s" media="v_seg_$Number%04d$.m4s" startNumber="1">
+                    <SegmentTimeline>
+                        <S t="0" d="900000" />
+                        <S t="900000" d="900000" />
+                        <S t="1800000" d="450000" />
+                    </SegmentTimeline>
+                </SegmentTemplate>
+            </Representation>
+        </AdaptationSet>
+        <AdaptationSet id="1" contentType="audio">
+            <Representation id="1" mimeType="audio/mp4">
+                <SegmentTemplate timescale="48000" initialization="a_init.m4s" media="a_seg_$Number%04d$.m4s" startNumber="1">
+                    <SegmentTimeline>
+                        <S t="0" d="480000" r="2" />
+                    </SegmentTimeline>
+                </SegmentTemplate>
+            </Representation>
+        </AdaptationSet>
+    </Period>
+</MPD>
+"#;
+
+    const BASE: &str = "../../object/cam/1700000000/";
+
+    #[test]
+    fn test_clip_mid_segment() {
+        let clipped = clip(MPD, 12_500, 18_000, BASE).unwrap();
+        // 12.5s falls in the second video segment, which is kept whole
+        assert!(clipped.contains(
+            r#"timescale="90000" initialization="v_init.m4s" media="v_seg_$Number%04d$.m4s" startNumber="2" presentationTimeOffset="1125000">"#
+        ));
+        assert!(clipped.contains(r#"<S t="900000" d="900000" />"#));
+        assert!(!clipped.contains(r#"<S t="0" d="900000" />"#));
+        assert!(!clipped.contains(r#"<S t="1800000" d="450000" />"#));
+        // Audio is clipped on its own timescale, the repeat expanded
+        assert!(clipped.contains(r#"startNumber="2" presentationTimeOffset="600000">"#));
+        assert!(clipped.contains(r#"<S t="480000" d="480000" />"#));
+        assert!(!clipped.contains(r#"r="2""#));
+
+        assert!(clipped.contains(r#"mediaPresentationDuration="PT5.500S""#));
+        assert!(clipped.contains(
+            "<Period id=\"0\" start=\"PT0.0S\" duration=\"PT5.500S\">\n        <BaseURL>../../object/cam/1700000000/</BaseURL>\n        <AdaptationSet"
+        ));
+        assert!(clipped.contains(r#"minBufferTime="PT6.000S""#));
+    }
+
+    #[test]
+    fn test_clip_segment_boundaries() {
+        // A window ending on a boundary leaves the next segment out
+        let clipped = clip(MPD, 0, 10_000, BASE).unwrap();
+        assert!(clipped.contains(r#"startNumber="1" presentationTimeOffset="0">"#));
+        assert!(clipped.contains(r#"<S t="0" d="900000" />"#));
+        assert!(!clipped.contains(r#"<S t="900000""#));
+
+        let clipped = clip(MPD, 20_000, 25_000, BASE).unwrap();
+        assert!(clipped.contains(r#"startNumber="3" presentationTimeOffset="1800000">"#));
+        assert_eq!(clipped.matches("<S ").count(), 2);
+    }
+
+    #[test]
+    fn test_reject_windows() {
+        assert_eq!(clip(MPD, 5_000, 5_000, BASE), Err(ClipError::EmptyWindow));
+        assert_eq!(clip(MPD, 9_000, 2_000, BASE), Err(ClipError::EmptyWindow));
+        // The audio track is the longest, 30s
+        assert!(clip(MPD, 20_000, 30_000, BASE).is_ok());
+        assert_eq!(
+            clip(MPD, 20_000, 30_001, BASE),
+            Err(ClipError::OutOfRange {
+                duration_ms: 30_000
+            })
+        );
+        assert_eq!(
+            clip(MPD, 40_000, 50_000, BASE),
+            Err(ClipError::OutOfRange {
+                duration_ms: 30_000
+            })
+        );
+        assert_eq!(
+            clip("<MPD><Period></Period></MPD>", 0, 1_000, BASE),
+            Err(ClipError::NoSegments)
+        );
+    }
+}
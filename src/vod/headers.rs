@@ -0,0 +1,276 @@
+//! Security headers on livevod responses.
+//!
+//! Recorded objects are user-generated content served from the livevod origin. Media
+//! types players fetch are left as they are, anything else served as an object is
+//! sandboxed so a crafted upload cannot run script in the origin.
+
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::header::{self, HeaderMap, HeaderName, HeaderValue, InvalidHeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+
+/// Objects under this prefix get `Content-Security-Policy` unless they are media
+const OBJECT_PREFIX: &str = "/api/record/object/";
+
+/// Each header can be turned off for embedders, an empty string disables it
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SecurityHeadersConfig {
+    /// `X-Content-Type-Options: nosniff` on every response
+    #[serde(default = "default_true")]
+    pub nosniff: bool,
+    /// On objects that are not an expected media type
+    #[serde(default = "default_content_security_policy")]
+    pub content_security_policy: String,
+    /// Players on other origins load segments with `<video>` and `fetch`, keep
+    /// `cross-origin` unless they are all same-site
+    #[serde(default = "default_cross_origin_resource_policy")]
+    pub cross_origin_resource_policy: String,
+    #[serde(default = "default_referrer_policy")]
+    pub referrer_policy: String,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            nosniff: true,
+            content_security_policy: default_content_security_policy(),
+            cross_origin_resource_policy: default_cross_origin_resource_policy(),
+            referrer_policy: default_referrer_policy(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_content_security_policy() -> String {
+    "sandbox".to_string()
+}
+
+fn default_cross_origin_resource_policy() -> String {
+    "cross-origin".to_string()
+}
+
+fn default_referrer_policy() -> String {
+    "no-referrer".to_string()
+}
+
+/// Header values parsed once at startup
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    nosniff: bool,
+    content_security_policy: Option<HeaderValue>,
+    common: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl SecurityHeaders {
+    pub fn new(cfg: &SecurityHeadersConfig) -> Result<Self, InvalidHeaderValue> {
+        let mut common = Vec::new();
+        if let Some(value) = value_of(&cfg.cross_origin_resource_policy)? {
+            common.push((
+                HeaderName::from_static("cross-origin-resource-policy"),
+                value,
+            ));
+        }
+        if let Some(value) = value_of(&cfg.referrer_policy)? {
+            common.push((header::REFERRER_POLICY, value));
+        }
+        Ok(Self {
+            nosniff: cfg.nosniff,
+            content_security_policy: value_of(&cfg.content_security_policy)?,
+            common,
+        })
+    }
+
+    fn apply(&self, path: &str, headers: &mut HeaderMap) {
+        if self.nosniff {
+            headers.insert(
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            );
+        }
+        for (name, value) in &self.common {
+            headers.insert(name.clone(), value.clone());
+        }
+        if let Some(ref csp) = self.content_security_policy
+            && path.starts_with(OBJECT_PREFIX)
+            && !headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(is_media)
+        {
+            headers.insert(header::CONTENT_SECURITY_POLICY, csp.clone());
+        }
+    }
+}
+
+fn value_of(value: &str) -> Result<Option<HeaderValue>, InvalidHeaderValue> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    HeaderValue::from_str(value).map(Some)
+}
+
+/// Types DASH/HLS players and the player UI load from objects
+fn is_media(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match essence.split_once('/') {
+        Some(("video" | "audio", _)) => true,
+        // SVG can carry script
+        Some(("image", subtype)) => subtype != "svg+xml",
+        _ => matches!(
+            essence.as_str(),
+            "application/dash+xml"
+                | "application/vnd.apple.mpegurl"
+                | "application/x-mpegurl"
+                | "application/mp4"
+                | "text/vtt"
+        ),
+    }
+}
+
+/// Middleware adding the configured headers to every response
+pub async fn security_headers(
+    State(headers): State<Arc<SecurityHeaders>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let path = req.uri().path().to_string();
+    let mut response = next.run(req).await;
+    headers.apply(&path, response.headers_mut());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::extract::Path;
+    use axum::routing::get;
+
+    /// Object kinds a recording is made of, with the types they are served as
+    const SERVED: &[&str] = &[
+        "cam/1700000000/manifest.mpd",
+        "cam/1700000000/v_init.mp4",
+        "cam/1700000000/v_seg_0001.m4s",
+        "cam/1700000000/a_init.mp4",
+        "cam/1700000000/a_seg_0001.m4s",
+        "cam/1700000000/previews/sheet_0001.jpg",
+        "cam/1700000000/previews/previews.vtt",
+    ];
+
+    async fn serve(cfg: SecurityHeadersConfig) -> String {
+        let app = Router::new()
+            .route(
+                "/api/record/object/{*path}",
+                get(|Path(path): Path<String>| async move {
+                    (
+                        [(header::CONTENT_TYPE, storage::content_type_for(&path))],
+                        "",
+                    )
+                }),
+            )
+            .route("/api/playback", get(|| async { "[]" }))
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(SecurityHeaders::new(&cfg).unwrap()),
+                security_headers,
+            ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn test_served_media_stays_playable() {
+        let base = serve(SecurityHeadersConfig::default()).await;
+        let client = reqwest::Client::new();
+        for path in SERVED {
+            let res = client
+                .get(format!("{base}/api/record/object/{path}"))
+                .send()
+                .await
+                .unwrap();
+            let headers = res.headers();
+            assert_eq!(headers["x-content-type-options"], "nosniff", "{path}");
+            assert_eq!(
+                headers["cross-origin-resource-policy"], "cross-origin",
+                "{path}"
+            );
+            assert_eq!(headers["referrer-policy"], "no-referrer", "{path}");
+            // Media reaches players exactly as before
+            assert!(
+                !headers.contains_key("content-security-policy"),
+                "{path}: {:?}",
+                headers["content-type"]
+            );
+        }
+
+        for path in ["cam/1700000000/upload.bin", "cam/1700000000/meta.json"] {
+            let res = client
+                .get(format!("{base}/api/record/object/{path}"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(
+                res.headers()["content-security-policy"],
+                "sandbox",
+                "{path}"
+            );
+        }
+
+        let res = client
+            .get(format!("{base}/api/playback"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["x-content-type-options"], "nosniff");
+        assert!(!res.headers().contains_key("content-security-policy"));
+    }
+
+    #[tokio::test]
+    async fn test_headers_disabled_individually() {
+        let base = serve(SecurityHeadersConfig {
+            nosniff: false,
+            content_security_policy: "sandbox allow-scripts".to_string(),
+            cross_origin_resource_policy: String::new(),
+            referrer_policy: "strict-origin".to_string(),
+        })
+        .await;
+        let res = reqwest::get(format!("{base}/api/record/object/cam/1/upload.bin"))
+            .await
+            .unwrap();
+        let headers = res.headers();
+        assert!(!headers.contains_key("x-content-type-options"));
+        assert!(!headers.contains_key("cross-origin-resource-policy"));
+        assert_eq!(headers["content-security-policy"], "sandbox allow-scripts");
+        assert_eq!(headers["referrer-policy"], "strict-origin");
+    }
+
+    #[test]
+    fn test_is_media() {
+        assert!(is_media("application/dash+xml"));
+        assert!(is_media("Video/MP4; codecs=\"avc1.42E01E\""));
+        assert!(is_media("image/jpeg"));
+        assert!(!is_media("image/svg+xml"));
+        assert!(!is_media("text/html"));
+        assert!(!is_media("application/octet-stream"));
+        assert!(
+            SecurityHeaders::new(&SecurityHeadersConfig {
+                referrer_policy: "bad\nvalue".to_string(),
+                ..Default::default()
+            })
+            .is_err()
+        );
+    }
+}
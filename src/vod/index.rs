@@ -0,0 +1,425 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use api::recorder::{RecordingIndexEntry, index_archive_path};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+/// Read every line of the recorder index and its archive of acked entries, older
+/// lines of a record come first
+pub async fn load(path: &str) -> Result<Vec<RecordingIndexEntry>> {
+    let mut entries = load_lines(&index_archive_path(Path::new(path))).await?;
+    entries.extend(load_lines(Path::new(path)).await?);
+    // An entry acked after the last compaction also has an older line in the index
+    entries.sort_by_key(|entry| entry.updated_at);
+    Ok(entries)
+}
+
+async fn load_lines(path: &Path) -> Result<Vec<RecordingIndexEntry>> {
+    let content = tokio::fs::read_to_string(path).await.unwrap_or_default();
+    let trimmed = content.trim();
+    if trimmed.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if trimmed.starts_with('[') {
+        let entries: Vec<RecordingIndexEntry> = serde_json::from_str(trimmed)?;
+        return Ok(entries);
+    }
+
+    let mut entries = Vec::new();
+    for line in trimmed.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let entry: RecordingIndexEntry = serde_json::from_str(line)?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Recordings of one stream as listed by `GET /api/playback`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct StreamSummary {
+    pub stream: String,
+    /// Tenant of the stream's recordings, see [`RecordingIndexEntry::tenant`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub recordings: usize,
+    /// Start of the newest recording, UNIX microseconds
+    pub latest_start_ts: i64,
+    /// Sum of the known recording durations
+    pub total_duration_ms: i64,
+}
+
+/// Order of the stream listing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamSort {
+    #[default]
+    Name,
+    /// Most recently started recording first
+    Latest,
+}
+
+/// Latest line of the recording with `uuid`. A renamed recording is found under its
+/// new stream, lines written before the recording had a uuid are never matched
+pub fn find_by_uuid(entries: Vec<RecordingIndexEntry>, uuid: &str) -> Option<RecordingIndexEntry> {
+    if uuid.is_empty() {
+        return None;
+    }
+    entries.into_iter().rev().find(|entry| entry.uuid == uuid)
+}
+
+/// Drop every line of the records whose latest line is in the trash
+pub fn without_trashed(entries: Vec<RecordingIndexEntry>) -> Vec<RecordingIndexEntry> {
+    let mut latest: HashMap<String, bool> = HashMap::new();
+    for entry in &entries {
+        latest.insert(entry.key(), entry.is_trashed());
+    }
+    let trashed: HashSet<String> = latest
+        .into_iter()
+        .filter_map(|(key, trashed)| trashed.then_some(key))
+        .collect();
+    entries
+        .into_iter()
+        .filter(|entry| !trashed.contains(&entry.key()))
+        .collect()
+}
+
+/// Build per-stream summaries sorted by name, later lines win for the same record.
+/// Recordings in the trash are left out.
+pub fn summarize(entries: Vec<RecordingIndexEntry>) -> Vec<StreamSummary> {
+    let mut latest: HashMap<String, RecordingIndexEntry> = HashMap::new();
+    for entry in entries {
+        latest.insert(entry.key(), entry);
+    }
+    latest.retain(|_, entry| !entry.is_trashed());
+
+    let mut streams: BTreeMap<String, StreamSummary> = BTreeMap::new();
+    for entry in latest.into_values() {
+        let duration_ms = entry
+            .duration_ms
+            .map(i64::from)
+            .or_else(|| entry.end_ts.map(|end| (end - entry.start_ts) / 1000))
+            .unwrap_or(0)
+            .max(0);
+        let summary = streams
+            .entry(entry.stream.clone())
+            .or_insert_with(|| StreamSummary {
+                stream: entry.stream,
+                tenant: entry.tenant,
+                recordings: 0,
+                latest_start_ts: entry.start_ts,
+                total_duration_ms: 0,
+            });
+        summary.recordings += 1;
+        summary.latest_start_ts = summary.latest_start_ts.max(entry.start_ts);
+        summary.total_duration_ms += duration_ms;
+    }
+    streams.into_values().collect()
+}
+
+pub fn sort_summaries(summaries: &mut [StreamSummary], sort: StreamSort) {
+    match sort {
+        StreamSort::Name => summaries.sort_by(|a, b| a.stream.cmp(&b.stream)),
+        StreamSort::Latest => summaries.sort_by(|a, b| {
+            b.latest_start_ts
+                .cmp(&a.latest_start_ts)
+                .then(a.stream.cmp(&b.stream))
+        }),
+    }
+}
+
+/// Modification time and length identifying one version of the index file and its archive
+type FileVersion = [Option<(SystemTime, u64)>; 2];
+
+#[derive(Default)]
+struct Snapshot {
+    /// `None` until the first refresh
+    version: Option<FileVersion>,
+    summaries: Arc<Vec<StreamSummary>>,
+    /// `replicas` of the recordings that list any, by record dir
+    replicas: HashMap<String, Vec<String>>,
+}
+
+/// Per-stream summaries of the recorder index, refreshed when the file changes.
+///
+/// Only the summaries and the replicas of replicated recordings are kept between
+/// refreshes, so listing streams costs memory per stream rather than per recording.
+pub struct IndexCache {
+    path: PathBuf,
+    snapshot: Mutex<Snapshot>,
+}
+
+impl IndexCache {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            snapshot: Mutex::new(Snapshot::default()),
+        }
+    }
+
+    /// Summaries sorted by stream name, rebuilt first if the index changed since the last call
+    pub async fn summaries(&self) -> Result<Arc<Vec<StreamSummary>>> {
+        let mut snapshot = self.snapshot.lock().await;
+        self.refresh(&mut snapshot).await?;
+        Ok(snapshot.summaries.clone())
+    }
+
+    /// Destinations the latest line of the recording in `record_dir` lists, empty when
+    /// it lists none or is not in the index
+    pub async fn replicas(&self, record_dir: &str) -> Result<Vec<String>> {
+        let mut snapshot = self.snapshot.lock().await;
+        self.refresh(&mut snapshot).await?;
+        Ok(snapshot
+            .replicas
+            .get(record_dir)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    /// Rebuild on the next call even if the files look unchanged, their modification
+    /// time may not have moved within its granularity
+    pub async fn invalidate(&self) {
+        self.snapshot.lock().await.version = None;
+    }
+
+    async fn refresh(&self, snapshot: &mut Snapshot) -> Result<()> {
+        let version = [
+            file_version(&self.path).await,
+            file_version(&index_archive_path(&self.path)).await,
+        ];
+        if snapshot.version != Some(version) {
+            let entries = load(&self.path.to_string_lossy()).await?;
+            snapshot.replicas = replicas(&entries);
+            snapshot.summaries = Arc::new(summarize(entries));
+            snapshot.version = Some(version);
+        }
+        Ok(())
+    }
+}
+
+/// `replicas` of the latest line of each recording that lists any, by record dir
+fn replicas(entries: &[RecordingIndexEntry]) -> HashMap<String, Vec<String>> {
+    let mut replicas = HashMap::new();
+    for entry in entries {
+        if entry.replicas.is_empty() {
+            replicas.remove(&entry.record_dir);
+        } else {
+            replicas.insert(entry.record_dir.clone(), entry.replicas.clone());
+        }
+    }
+    replicas
+}
+
+async fn file_version(path: &Path) -> Option<(SystemTime, u64)> {
+    tokio::fs::metadata(path).await.ok().map(|meta| {
+        (
+            meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            meta.len(),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::recorder::{DEFAULT_PRIORITY, RecordingStatus};
+    use std::io::Write;
+
+    fn entry(stream: &str, record: &str, start_s: i64, duration_ms: Option<i32>) -> String {
+        serde_json::to_string(&RecordingIndexEntry {
+            uuid: String::new(),
+            record: record.to_string(),
+            stream: stream.to_string(),
+            record_dir: format!("{stream}/{record}"),
+            mpd_path: format!("{stream}/{record}/manifest.mpd"),
+            start_ts: start_s * 1_000_000,
+            end_ts: duration_ms.map(|d| start_s * 1_000_000 + d as i64 * 1000),
+            duration_ms,
+            status: if duration_ms.is_some() {
+                RecordingStatus::Completed
+            } else {
+                RecordingStatus::Active
+            },
+            node_alias: None,
+            updated_at: start_s,
+            note: None,
+            labels: Vec::new(),
+            continues: None,
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant: None,
+            size: None,
+            captions: Vec::new(),
+            trigger: None,
+            imported: false,
+            seq: 0,
+        })
+        .unwrap()
+    }
+
+    fn append(path: &std::path::Path, lines: &[String]) {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        for line in lines {
+            writeln!(file, "{line}").unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_summaries_follow_refreshes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        let cache = IndexCache::new(&path);
+        assert!(cache.summaries().await.unwrap().is_empty());
+
+        append(
+            &path,
+            &[
+                entry("cam", "100", 100, None),
+                entry("lobby", "50", 50, Some(2_000)),
+            ],
+        );
+        let summaries = cache.summaries().await.unwrap();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].stream, "cam");
+        assert_eq!(summaries[0].total_duration_ms, 0);
+
+        // The active recording finished and a new one started
+        append(
+            &path,
+            &[
+                entry("cam", "100", 100, Some(60_000)),
+                entry("cam", "200", 200, None),
+            ],
+        );
+        let summaries = cache.summaries().await.unwrap();
+        assert_eq!(
+            summaries[0],
+            StreamSummary {
+                stream: "cam".to_string(),
+                tenant: None,
+                recordings: 2,
+                latest_start_ts: 200_000_000,
+                total_duration_ms: 60_000,
+            }
+        );
+
+        // Compaction after deleting lobby's only recording
+        std::fs::write(
+            &path,
+            format!(
+                "{}\n{}\n",
+                entry("cam", "100", 100, Some(60_000)),
+                entry("cam", "200", 200, Some(30_000))
+            ),
+        )
+        .unwrap();
+        let summaries = cache.summaries().await.unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].recordings, 2);
+        assert_eq!(summaries[0].total_duration_ms, 90_000);
+
+        // Acked recordings are listed from the archive
+        let mut acked: RecordingIndexEntry =
+            serde_json::from_str(&entry("lobby", "50", 50, Some(2_000))).unwrap();
+        acked.status = RecordingStatus::Acked;
+        append(
+            &index_archive_path(&path),
+            &[serde_json::to_string(&acked).unwrap()],
+        );
+        let summaries = cache.summaries().await.unwrap();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[1].stream, "lobby");
+    }
+
+    #[tokio::test]
+    async fn test_replicas_of_the_latest_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        let cache = IndexCache::new(&path);
+        let mut active: RecordingIndexEntry =
+            serde_json::from_str(&entry("cam", "100", 100, None)).unwrap();
+        active.replicas = vec!["primary".to_string()];
+        let mut completed: RecordingIndexEntry =
+            serde_json::from_str(&entry("cam", "100", 100, Some(60_000))).unwrap();
+        completed.replicas = vec!["primary".to_string(), "dr".to_string()];
+        append(&path, &[serde_json::to_string(&active).unwrap()]);
+        assert_eq!(cache.replicas("cam/100").await.unwrap(), ["primary"]);
+
+        append(&path, &[serde_json::to_string(&completed).unwrap()]);
+        assert_eq!(cache.replicas("cam/100").await.unwrap(), ["primary", "dr"]);
+        assert!(cache.replicas("cam/200").await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_trashed_records_are_hidden() {
+        let mut trashed: RecordingIndexEntry =
+            serde_json::from_str(&entry("cam", "100", 100, Some(1_000))).unwrap();
+        trashed.trashed_from = Some(trashed.status);
+        trashed.status = RecordingStatus::Trashed;
+        trashed.trashed_at = Some(300);
+        let entries: Vec<RecordingIndexEntry> = [
+            entry("cam", "100", 100, Some(1_000)),
+            entry("cam", "200", 200, Some(2_000)),
+        ]
+        .iter()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .chain([trashed])
+        .collect();
+
+        let summaries = summarize(entries.clone());
+        assert_eq!(summaries[0].recordings, 1);
+        assert_eq!(summaries[0].total_duration_ms, 2_000);
+        let visible = without_trashed(entries);
+        assert_eq!(visible.len(), 1);
+        assert_eq!(visible[0].record, "200");
+    }
+
+    #[test]
+    fn test_find_by_uuid_follows_renames() {
+        let line = |stream: &str, uuid: &str| {
+            let mut entry: RecordingIndexEntry =
+                serde_json::from_str(&entry(stream, "100", 100, Some(1_000))).unwrap();
+            entry.uuid = uuid.to_string();
+            entry
+        };
+        let uuid = "0190b0a4-5d6e-7c1f-8a2b-3c4d5e6f7a8b";
+        let entries = vec![line("cam", ""), line("cam", uuid), line("lobby", uuid)];
+
+        let found = find_by_uuid(entries.clone(), uuid).unwrap();
+        assert_eq!(found.stream, "lobby");
+        assert!(find_by_uuid(entries, "").is_none());
+    }
+
+    #[test]
+    fn test_sort_by_latest() {
+        let summary = |stream: &str, latest_start_ts| StreamSummary {
+            stream: stream.to_string(),
+            tenant: None,
+            recordings: 1,
+            latest_start_ts,
+            total_duration_ms: 0,
+        };
+        let mut summaries = vec![summary("a", 1), summary("b", 3), summary("c", 2)];
+        sort_summaries(&mut summaries, StreamSort::Latest);
+        let order: Vec<_> = summaries.iter().map(|s| s.stream.as_str()).collect();
+        assert_eq!(order, vec!["b", "c", "a"]);
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::metrics;
+
+/// Prune idle per-client entries once the map grows beyond this size
+const MAX_IDLE_CLIENTS: usize = 1024;
+
+/// Bounds concurrent operator reads.
+///
+/// A request first takes a permit from its client's own semaphore and only then
+/// queues for a global permit, so a client issuing many parallel requests waits
+/// behind itself instead of occupying every global slot.
+pub struct ReadLimiter {
+    global: Option<Arc<Semaphore>>,
+    per_client: usize,
+    clients: Mutex<HashMap<IpAddr, Arc<Semaphore>>>,
+    timeout: Duration,
+}
+
+/// Held for the duration of a storage read
+pub struct ReadPermit {
+    _client: OwnedSemaphorePermit,
+    _global: OwnedSemaphorePermit,
+}
+
+/// The wait for a permit exceeded the configured threshold
+#[derive(Debug)]
+pub struct Rejected {
+    pub retry_after: Duration,
+}
+
+impl ReadLimiter {
+    /// `max_concurrent` of 0 disables limiting;
+    /// `per_client` of 0 derives a quarter of the global limit.
+    pub fn new(max_concurrent: usize, per_client: usize, timeout: Duration) -> Self {
+        let global = (max_concurrent > 0).then(|| Arc::new(Semaphore::new(max_concurrent)));
+        let per_client = if per_client == 0 {
+            (max_concurrent / 4).max(1)
+        } else {
+            per_client.min(max_concurrent.max(1))
+        };
+        Self {
+            global,
+            per_client,
+            clients: Mutex::new(HashMap::new()),
+            timeout,
+        }
+    }
+
+    /// Wait for a read permit, `Ok(None)` when limiting is disabled
+    pub async fn acquire(&self, client: IpAddr) -> Result<Option<ReadPermit>, Rejected> {
+        let Some(global) = self.global.clone() else {
+            return Ok(None);
+        };
+        let client_sem = self.client_semaphore(client);
+
+        metrics::READ_QUEUE_DEPTH.inc();
+        let result = tokio::time::timeout(self.timeout, async move {
+            let client_permit = client_sem.acquire_owned().await.ok()?;
+            let global_permit = global.acquire_owned().await.ok()?;
+            Some(ReadPermit {
+                _client: client_permit,
+                _global: global_permit,
+            })
+        })
+        .await;
+        metrics::READ_QUEUE_DEPTH.dec();
+
+        match result {
+            Ok(Some(permit)) => Ok(Some(permit)),
+            _ => {
+                metrics::READ_REJECTED.inc();
+                Err(Rejected {
+                    retry_after: self.timeout.max(Duration::from_secs(1)),
+                })
+            }
+        }
+    }
+
+    fn client_semaphore(&self, client: IpAddr) -> Arc<Semaphore> {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() > MAX_IDLE_CLIENTS {
+            let per_client = self.per_client;
+            clients.retain(|_, sem| {
+                Arc::strong_count(sem) > 1 || sem.available_permits() < per_client
+            });
+        }
+        clients
+            .entry(client)
+            .or_insert_with(|| Arc::new(Semaphore::new(self.per_client)))
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last])
+    }
+
+    #[tokio::test]
+    async fn test_disabled_limiter() {
+        let limiter = ReadLimiter::new(0, 0, Duration::from_millis(10));
+        assert!(limiter.acquire(ip(1)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_greedy_client_does_not_starve_others() {
+        let limiter = ReadLimiter::new(4, 2, Duration::from_millis(50));
+        let _a1 = limiter.acquire(ip(1)).await.unwrap();
+        let _a2 = limiter.acquire(ip(1)).await.unwrap();
+        // Third request from the same client queues behind its own slots
+        assert!(limiter.acquire(ip(1)).await.is_err());
+        // Another client still gets a permit
+        assert!(limiter.acquire(ip(2)).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_when_global_exhausted() {
+        let limiter = ReadLimiter::new(2, 1, Duration::from_millis(20));
+        let _a = limiter.acquire(ip(1)).await.unwrap();
+        let _b = limiter.acquire(ip(2)).await.unwrap();
+        let err = limiter.acquire(ip(3)).await.unwrap_err();
+        assert!(err.retry_after >= Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,232 @@
+//! Objects of an `fs` destination served straight from their files.
+//!
+//! Read through the storage operator, an object is buffered whole and copied again into
+//! the response before the first byte goes out, which for multi-GB recordings keeps a
+//! core busy and holds the object in memory. Served from its file it is streamed in
+//! `playback.local_chunk_bytes` reads, a `Range` request reads only its part. Status and
+//! headers are those of [`super::range::serve_bytes`]. hyper writes bodies through its
+//! own buffers, so this is no `sendfile`: the copy left is the one into the socket.
+
+use std::io::{self, SeekFrom};
+use std::path::PathBuf;
+
+use axum::body::Body;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use storage::StorageConfig;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::range::Requested;
+
+/// Chunks read ahead of a slow client
+const READ_AHEAD: usize = 4;
+
+pub struct LocalFiles {
+    root: PathBuf,
+    chunk_bytes: usize,
+}
+
+impl LocalFiles {
+    /// Files of `config`, `None` unless it is an `fs` destination
+    pub fn new(config: &StorageConfig, chunk_bytes: usize) -> Option<Self> {
+        match config {
+            StorageConfig::Fs { root } => Some(Self {
+                root: PathBuf::from(root),
+                chunk_bytes: chunk_bytes.max(4096),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Response of the object `key`, a checked object key, with the bytes it serves.
+    /// `None` when there is no such file
+    pub async fn serve(
+        &self,
+        key: &str,
+        headers: &HeaderMap,
+        content_type: &str,
+    ) -> io::Result<Option<(Response, u64)>> {
+        let mut file = match File::open(self.root.join(key)).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let metadata = file.metadata().await?;
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+        let len = metadata.len();
+        let requested = Requested::of(headers, len);
+        let start = match requested {
+            Requested::Part { start, .. } => start,
+            _ => 0,
+        };
+        let body_len = requested.body_len(len);
+        if start > 0 {
+            file.seek(SeekFrom::Start(start)).await?;
+        }
+        let body = self.stream(file, body_len);
+        Ok(Some((
+            requested.response(len, content_type, body),
+            body_len,
+        )))
+    }
+
+    /// Body of the next `remaining` bytes of `file`. A read error cuts the response
+    /// short, the client sees less than `Content-Length`
+    fn stream(&self, mut file: File, mut remaining: u64) -> Body {
+        let chunk_bytes = self.chunk_bytes;
+        let (tx, rx) = mpsc::channel::<io::Result<Vec<u8>>>(READ_AHEAD);
+        tokio::spawn(async move {
+            while remaining > 0 {
+                let mut chunk = vec![0; chunk_bytes.min(remaining as usize)];
+                let read = match file.read(&mut chunk).await {
+                    Ok(0) => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                    Ok(read) => {
+                        chunk.truncate(read);
+                        remaining -= read as u64;
+                        Ok(chunk)
+                    }
+                    Err(e) => Err(e),
+                };
+                let failed = read.is_err();
+                // The client went away
+                if tx.send(read).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Body::from_stream(ReceiverStream::new(rx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderValue, header};
+
+    use crate::vod::range::serve_bytes;
+
+    const KEY: &str = "cam/1760486400/v_seg_0001.m4s";
+    const CONTENT_TYPE: &str = "video/iso.segment";
+
+    /// Status, headers and body of a response
+    async fn parts(response: Response) -> (u16, Vec<(String, String)>, Vec<u8>) {
+        let (parts, body) = response.into_parts();
+        let mut headers: Vec<_> = parts
+            .headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_str().unwrap().to_string()))
+            .collect();
+        headers.sort();
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        (parts.status.as_u16(), headers, body.to_vec())
+    }
+
+    /// Serve `content` for `range` through both paths, which have to agree
+    async fn both(
+        files: &LocalFiles,
+        content: &[u8],
+        range: Option<&str>,
+    ) -> (u16, Vec<(String, String)>, Vec<u8>) {
+        tokio::fs::write(files.root.join(KEY), content)
+            .await
+            .unwrap();
+        let mut headers = HeaderMap::new();
+        if let Some(range) = range {
+            headers.insert(header::RANGE, HeaderValue::from_str(range).unwrap());
+        }
+        let (portable, portable_bytes) = serve_bytes(&headers, CONTENT_TYPE, content.to_vec());
+        let (local, local_bytes) = files
+            .serve(KEY, &headers, CONTENT_TYPE)
+            .await
+            .unwrap()
+            .unwrap();
+        let portable = parts(portable).await;
+        assert_eq!(portable, parts(local).await, "range {range:?}");
+        assert_eq!(portable_bytes, local_bytes);
+        assert_eq!(portable.2.len() as u64, portable_bytes);
+        portable
+    }
+
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[tokio::test]
+    async fn test_local_and_portable_paths_agree() {
+        let dir = tempfile::tempdir().unwrap();
+        tokio::fs::create_dir_all(dir.path().join("cam/1760486400"))
+            .await
+            .unwrap();
+        let files = LocalFiles::new(
+            &StorageConfig::Fs {
+                root: dir.path().to_string_lossy().into_owned(),
+            },
+            4096,
+        )
+        .unwrap();
+        // Spans several chunks and ends in a partial one
+        let content: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+
+        let (status, headers, body) = both(&files, &content, None).await;
+        assert_eq!(status, 200);
+        assert_eq!(body, content);
+        assert_eq!(header(&headers, "content-length"), Some("10000"));
+        assert_eq!(header(&headers, "content-type"), Some(CONTENT_TYPE));
+        assert_eq!(header(&headers, "accept-ranges"), Some("bytes"));
+
+        let (status, headers, body) = both(&files, &content, Some("bytes=4000-8999")).await;
+        assert_eq!(status, 206);
+        assert_eq!(body, content[4000..9000]);
+        assert_eq!(
+            header(&headers, "content-range"),
+            Some("bytes 4000-8999/10000")
+        );
+
+        let (_, _, body) = both(&files, &content, Some("bytes=9990-")).await;
+        assert_eq!(body, content[9990..]);
+        let (_, _, body) = both(&files, &content, Some("bytes=-5")).await;
+        assert_eq!(body, content[9995..]);
+
+        let (status, headers, body) = both(&files, &content, Some("bytes=10000-")).await;
+        assert_eq!(status, 416);
+        assert!(body.is_empty());
+        assert_eq!(header(&headers, "content-range"), Some("bytes */10000"));
+
+        let (status, _, body) = both(&files, &content, Some("bytes=0-1,5-6")).await;
+        assert_eq!(status, 200);
+        assert_eq!(body, content);
+
+        let (status, headers, body) = both(&files, &[], None).await;
+        assert_eq!(status, 200);
+        assert!(body.is_empty());
+        assert_eq!(header(&headers, "content-length"), Some("0"));
+
+        // Missing files and directories are left to the storage path
+        assert!(
+            files
+                .serve(
+                    "cam/1760486400/v_seg_0002.m4s",
+                    &HeaderMap::new(),
+                    CONTENT_TYPE
+                )
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            files
+                .serve("cam/1760486400", &HeaderMap::new(), CONTENT_TYPE)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
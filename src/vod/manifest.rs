@@ -0,0 +1,182 @@
+//! Bounded reads of recording manifests that livevod parses itself.
+//!
+//! Manifests are a few KB even for day-long recordings, a corrupted or hostile object
+//! of several GB must not be buffered whole. Plain object serving is not affected.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use opendal::Operator;
+
+/// Error code returned when a manifest exceeds `playback.max_manifest_bytes`
+pub const MANIFEST_TOO_LARGE_CODE: &str = "manifest_too_large";
+
+#[derive(Debug)]
+pub enum ManifestError {
+    /// The object holds more than `limit` bytes
+    TooLarge {
+        limit: u64,
+    },
+    Read(opendal::Error),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge { limit } => write!(f, "manifest exceeds {limit} bytes"),
+            Self::Read(e) => write!(f, "failed to read manifest: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+/// Manifest at `path` as text, refused when it has more than `max_bytes` bytes.
+///
+/// At most `max_bytes + 1` bytes are fetched, whatever size storage reports.
+pub async fn read(
+    operator: &Operator,
+    path: &str,
+    max_bytes: u64,
+) -> Result<String, ManifestError> {
+    if let Ok(meta) = operator.stat(path).await
+        && meta.content_length() > max_bytes
+    {
+        return Err(ManifestError::TooLarge { limit: max_bytes });
+    }
+    read_bounded(operator, path, max_bytes).await
+}
+
+/// Some backends report no length, the ranged read bounds the buffer either way
+async fn read_bounded(
+    operator: &Operator,
+    path: &str,
+    max_bytes: u64,
+) -> Result<String, ManifestError> {
+    let buffer = operator
+        .read_with(path)
+        .range(0..max_bytes.saturating_add(1))
+        .await
+        .map_err(ManifestError::Read)?;
+    if buffer.len() as u64 > max_bytes {
+        return Err(ManifestError::TooLarge { limit: max_bytes });
+    }
+    Ok(String::from_utf8_lossy(&buffer.to_vec()).into_owned())
+}
+
+/// Drop expired entries once the map grows beyond this size
+const MAX_CACHED: usize = 1_000;
+
+/// Manifests served by `GET /api/record/object`, kept for `playback.manifest_cache_seconds`.
+///
+/// Unlike segments a manifest is rewritten when its recording is finalized or repaired,
+/// the writer's invalidation notice drops it before it expires.
+pub struct ManifestCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, Vec<u8>)>>,
+}
+
+impl ManifestCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Option<Vec<u8>> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(path)
+            .filter(|(at, _)| at.elapsed() < self.ttl)
+            .map(|(_, body)| body.clone())
+    }
+
+    /// Keep `body`, unless caching is off
+    pub fn insert(&self, path: &str, body: &[u8]) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_CACHED {
+            entries.retain(|_, (at, _)| at.elapsed() < self.ttl);
+            if entries.len() >= MAX_CACHED {
+                entries.clear();
+            }
+        }
+        entries.insert(path.to_string(), (Instant::now(), body.to_vec()));
+    }
+
+    /// Returns whether `path` was cached
+    pub fn remove(&self, path: &str) -> bool {
+        self.entries.lock().unwrap().remove(path).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operator(root: &std::path::Path) -> Operator {
+        storage::create_operator(&storage::StorageConfig::Fs {
+            root: root.to_string_lossy().into_owned(),
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_read_enforces_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let operator = operator(dir.path());
+        operator
+            .write("cam/1/manifest.mpd", "<MPD></MPD>")
+            .await
+            .unwrap();
+        // A sparse file reports 8 GiB without taking the space
+        let file = std::fs::File::create(dir.path().join("huge.mpd")).unwrap();
+        file.set_len(8 << 30).unwrap();
+
+        assert_eq!(
+            read(&operator, "cam/1/manifest.mpd", 1024).await.unwrap(),
+            "<MPD></MPD>"
+        );
+        assert!(matches!(
+            read(&operator, "huge.mpd", 1024).await,
+            Err(ManifestError::TooLarge { limit: 1024 })
+        ));
+        // Without a reported length only one byte past the limit is fetched
+        assert!(matches!(
+            read_bounded(&operator, "huge.mpd", 1024).await,
+            Err(ManifestError::TooLarge { limit: 1024 })
+        ));
+        // Exactly at the limit is still accepted
+        assert!(read(&operator, "cam/1/manifest.mpd", 11).await.is_ok());
+        assert!(matches!(
+            read(&operator, "cam/1/manifest.mpd", 10).await,
+            Err(ManifestError::TooLarge { limit: 10 })
+        ));
+        assert!(matches!(
+            read(&operator, "missing.mpd", 1024).await,
+            Err(ManifestError::Read(_))
+        ));
+    }
+
+    #[test]
+    fn test_manifest_cache() {
+        let cache = ManifestCache::new(Duration::from_secs(60));
+        cache.insert("cam/1/manifest.mpd", b"<MPD />");
+        assert_eq!(
+            cache.get("cam/1/manifest.mpd").as_deref(),
+            Some(&b"<MPD />"[..])
+        );
+        assert!(cache.remove("cam/1/manifest.mpd"));
+        assert!(!cache.remove("cam/1/manifest.mpd"));
+        assert_eq!(cache.get("cam/1/manifest.mpd"), None);
+
+        let off = ManifestCache::new(Duration::ZERO);
+        off.insert("cam/1/manifest.mpd", b"<MPD />");
+        assert_eq!(off.get("cam/1/manifest.mpd"), None);
+    }
+}
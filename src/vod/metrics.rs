@@ -0,0 +1,193 @@
+use std::sync::LazyLock;
+
+use prometheus::{IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use storage::EndpointStatus;
+
+use super::replica::Replicas;
+
+pub static REGISTRY: LazyLock<Registry> =
+    LazyLock::new(|| Registry::new_custom(Some("livevod".to_string()), None).unwrap());
+
+pub static READ_QUEUE_DEPTH: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new(
+        "read_queue_depth",
+        "storage reads waiting for a concurrency permit",
+    )
+    .unwrap()
+});
+
+pub static READ_REJECTED: LazyLock<IntCounter> = LazyLock::new(|| {
+    IntCounter::new(
+        "read_rejected_total",
+        "storage reads rejected after waiting too long for a permit",
+    )
+    .unwrap()
+});
+
+/// Object bytes by `delivery`: `inline` proxied through livevod, `redirect` sent to a
+/// presigned URL (as sized by stat)
+pub static OBJECT_BYTES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new("object_bytes_total", "object bytes served by delivery"),
+        &["delivery"],
+    )
+    .unwrap()
+});
+
+/// Object requests by the replica `destination` that served them and `delivery`
+pub static OBJECT_DESTINATION: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "object_destination_total",
+            "object requests served by storage destination and delivery",
+        ),
+        &["destination", "delivery"],
+    )
+    .unwrap()
+});
+
+pub static ANALYTICS_SESSIONS: LazyLock<IntCounter> = LazyLock::new(|| {
+    IntCounter::new(
+        "analytics_sessions_total",
+        "playback sessions closed for the analytics sink",
+    )
+    .unwrap()
+});
+
+pub static ANALYTICS_SESSIONS_DROPPED: LazyLock<IntCounter> = LazyLock::new(|| {
+    IntCounter::new(
+        "analytics_sessions_dropped_total",
+        "playback sessions dropped while the analytics sink failed",
+    )
+    .unwrap()
+});
+
+pub static ANALYTICS_BUFFERED_SESSIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new(
+        "analytics_buffered_sessions",
+        "playback sessions waiting for the analytics sink",
+    )
+    .unwrap()
+});
+
+pub static INVALIDATED_KEYS: LazyLock<IntCounter> = LazyLock::new(|| {
+    IntCounter::new(
+        "invalidated_keys_total",
+        "keys named by invalidation notices that were cached",
+    )
+    .unwrap()
+});
+
+pub static SIZE_LISTINGS: LazyLock<IntCounter> = LazyLock::new(|| {
+    IntCounter::new(
+        "size_listings_total",
+        "record dirs listed to size recordings without segments.jsonl",
+    )
+    .unwrap()
+});
+
+pub static NOT_FOUND_CACHE_HITS: LazyLock<IntCounter> = LazyLock::new(|| {
+    IntCounter::new(
+        "not_found_cache_hits_total",
+        "object requests answered 404 from recent misses without reading storage",
+    )
+    .unwrap()
+});
+
+pub static STORAGE_DESTINATION_SCORE: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "storage_destination_score",
+            "health score of the storage destination, 0 to 100",
+        ),
+        &["destination"],
+    )
+    .unwrap()
+});
+
+pub static STORAGE_ENDPOINT_SELECTED: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "storage_endpoint_selected",
+            "1 for the storage endpoint currently serving requests",
+        ),
+        &["endpoint"],
+    )
+    .unwrap()
+});
+
+pub static STORAGE_ENDPOINT_HEALTHY: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "storage_endpoint_healthy",
+            "1 if the last probe of the storage endpoint succeeded",
+        ),
+        &["endpoint"],
+    )
+    .unwrap()
+});
+
+pub fn register() {
+    REGISTRY
+        .register(Box::new(READ_QUEUE_DEPTH.clone()))
+        .unwrap();
+    REGISTRY.register(Box::new(READ_REJECTED.clone())).unwrap();
+    REGISTRY.register(Box::new(OBJECT_BYTES.clone())).unwrap();
+    REGISTRY
+        .register(Box::new(OBJECT_DESTINATION.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(ANALYTICS_SESSIONS.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(ANALYTICS_SESSIONS_DROPPED.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(ANALYTICS_BUFFERED_SESSIONS.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(INVALIDATED_KEYS.clone()))
+        .unwrap();
+    REGISTRY.register(Box::new(SIZE_LISTINGS.clone())).unwrap();
+    REGISTRY
+        .register(Box::new(NOT_FOUND_CACHE_HITS.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(STORAGE_DESTINATION_SCORE.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(STORAGE_ENDPOINT_SELECTED.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(STORAGE_ENDPOINT_HEALTHY.clone()))
+        .unwrap();
+}
+
+/// Refresh destination and endpoint gauges, called on scrape
+pub fn observe_replicas(replicas: &Replicas) {
+    for destination in replicas.destinations() {
+        STORAGE_DESTINATION_SCORE
+            .with_label_values(&[destination.name.as_str()])
+            .set(destination.score() as i64);
+        observe_storage(&destination.operator.status());
+    }
+}
+
+/// Refresh endpoint gauges from the failover state
+pub fn observe_storage(status: &[EndpointStatus]) {
+    for endpoint in status {
+        let label = endpoint.endpoint.as_deref().unwrap_or("default");
+        STORAGE_ENDPOINT_SELECTED
+            .with_label_values(&[label])
+            .set(endpoint.selected as i64);
+        STORAGE_ENDPOINT_HEALTHY
+            .with_label_values(&[label])
+            .set(endpoint.healthy as i64);
+    }
+}
+
+pub fn encode() -> String {
+    TextEncoder::new()
+        .encode_to_string(&REGISTRY.gather())
+        .unwrap_or_default()
+}
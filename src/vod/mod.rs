@@ -0,0 +1,25 @@
+pub mod analytics;
+pub mod clip;
+pub mod headers;
+pub mod index;
+pub mod limiter;
+pub mod local;
+pub mod manifest;
+pub mod metrics;
+pub mod not_found;
+pub mod openapi;
+pub mod preview;
+pub mod range;
+pub mod redirect;
+pub mod replica;
+pub mod s3;
+pub mod seek;
+pub mod sessions;
+pub mod size;
+pub mod tenant;
+pub mod timeline;
+pub mod tls;
+#[cfg(feature = "transcode")]
+pub mod transcode;
+#[cfg(feature = "webui")]
+pub mod ui;
@@ -0,0 +1,120 @@
+//! Objects recently found missing, so retried requests skip storage.
+//!
+//! Players retry a segment of a running recording that is not uploaded yet several
+//! times a second. Each miss is kept for `playback.not_found_cache_ms`, short enough
+//! that a segment uploaded meanwhile is served on the next retry, and an invalidation
+//! notice for the key drops it right away.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Longer would hold back segments of running recordings noticeably
+pub const MAX_TTL: Duration = Duration::from_secs(2);
+
+/// A key's misses are logged at most once per interval
+const LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Drop expired entries once a map grows beyond this size
+const MAX_ENTRIES: usize = 10_000;
+
+pub struct NotFoundCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Instant>>,
+    /// When each key's miss was last logged, and the misses not logged since
+    logged: Mutex<HashMap<String, (Instant, u64)>>,
+}
+
+impl NotFoundCache {
+    /// Misses kept for `ttl`, at most [`MAX_TTL`], zero turns the cache off
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl: ttl.min(MAX_TTL),
+            entries: Mutex::new(HashMap::new()),
+            logged: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long `path` stays known missing, `None` when it is not
+    pub fn get(&self, path: &str) -> Option<Duration> {
+        let entries = self.entries.lock().unwrap();
+        let remaining = self.ttl.checked_sub(entries.get(path)?.elapsed())?;
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    pub fn insert(&self, path: &str) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, at| at.elapsed() < self.ttl);
+            if entries.len() >= MAX_ENTRIES {
+                entries.clear();
+            }
+        }
+        entries.insert(path.to_string(), Instant::now());
+    }
+
+    /// Returns whether `path` was cached
+    pub fn remove(&self, path: &str) -> bool {
+        self.entries.lock().unwrap().remove(path).is_some()
+    }
+
+    /// Whether to log a miss of `path` now, with the misses left unlogged before it
+    pub fn should_log(&self, path: &str) -> Option<u64> {
+        let mut logged = self.logged.lock().unwrap();
+        if let Some((at, suppressed)) = logged.get_mut(path)
+            && at.elapsed() < LOG_INTERVAL
+        {
+            *suppressed += 1;
+            return None;
+        }
+        if logged.len() >= MAX_ENTRIES {
+            logged.retain(|_, (at, _)| at.elapsed() < LOG_INTERVAL);
+            if logged.len() >= MAX_ENTRIES {
+                logged.clear();
+            }
+        }
+        let (_, suppressed) = logged
+            .insert(path.to_string(), (Instant::now(), 0))
+            .unwrap_or_default();
+        Some(suppressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_misses_expire_and_are_invalidated() {
+        let cache = NotFoundCache::new(Duration::from_millis(50));
+        assert!(cache.get("cam/1/v_seg_0001.m4s").is_none());
+        cache.insert("cam/1/v_seg_0001.m4s");
+        cache.insert("cam/1/v_seg_0002.m4s");
+        let remaining = cache.get("cam/1/v_seg_0001.m4s").unwrap();
+        assert!(remaining <= Duration::from_millis(50));
+
+        // The uploader's notice makes the segment readable before the miss expires
+        assert!(cache.remove("cam/1/v_seg_0001.m4s"));
+        assert!(cache.get("cam/1/v_seg_0001.m4s").is_none());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cache.get("cam/1/v_seg_0002.m4s").is_none());
+
+        // Never longer than MAX_TTL, never at all with a zero TTL
+        assert_eq!(NotFoundCache::new(Duration::from_secs(30)).ttl, MAX_TTL);
+        let off = NotFoundCache::new(Duration::ZERO);
+        off.insert("cam/1/v_seg_0001.m4s");
+        assert!(off.get("cam/1/v_seg_0001.m4s").is_none());
+    }
+
+    #[test]
+    fn test_logging_rate_limited_per_key() {
+        let cache = NotFoundCache::new(Duration::from_secs(1));
+        assert_eq!(cache.should_log("cam/1/a.m4s"), Some(0));
+        assert_eq!(cache.should_log("cam/1/a.m4s"), None);
+        assert_eq!(cache.should_log("cam/1/a.m4s"), None);
+        assert_eq!(cache.should_log("cam/1/b.m4s"), Some(0));
+    }
+}
@@ -0,0 +1,70 @@
+use axum::Json;
+use utoipa::OpenApi;
+
+/// Playback HTTP API, schemas are derived from the `api::recorder` types the handlers use
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "livevod"),
+    paths(
+        crate::list_streams,
+        crate::list_records,
+        crate::record_by_id,
+        crate::find_record_at,
+        crate::timeline,
+        crate::get_object,
+        crate::clip_manifest,
+        crate::record_size,
+        crate::create_previews,
+        crate::preview_status,
+    ),
+    tags(
+        (name = "playback", description = "Recording index and stored objects"),
+        (name = "previews", description = "Thumbnail tracks of recordings"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Routes of the `transcode` feature, merged into [`ApiDoc`] when built with it
+#[cfg(feature = "transcode")]
+#[derive(OpenApi)]
+#[openapi(
+    paths(crate::create_transcode, crate::transcode_status),
+    tags((name = "transcode", description = "Browser-compatible renditions of recordings"))
+)]
+struct TranscodeApiDoc;
+
+pub async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    let doc = ApiDoc::openapi();
+    #[cfg(feature = "transcode")]
+    let doc = {
+        let mut doc = doc;
+        doc.merge(TranscodeApiDoc::openapi());
+        doc
+    };
+    Json(doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fails when the generated spec drifts from `openapi.json`,
+    /// run with `UPDATE_OPENAPI=1` to accept the change
+    #[test]
+    fn test_openapi_snapshot() {
+        let mut doc = ApiDoc::openapi();
+        // Version bumps alone should not touch the snapshot
+        doc.info.version = String::new();
+        let generated = doc.to_pretty_json().unwrap() + "\n";
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("openapi.json");
+        if std::env::var_os("UPDATE_OPENAPI").is_some() || !path.exists() {
+            std::fs::write(&path, &generated).unwrap();
+            return;
+        }
+        let snapshot = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            snapshot, generated,
+            "OpenAPI spec changed, run `UPDATE_OPENAPI=1 cargo test` and commit openapi.json"
+        );
+    }
+}
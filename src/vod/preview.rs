@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use opendal::Operator;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+/// Written last, its presence marks a finished set of previews
+pub const VTT_FILENAME: &str = "previews.vtt";
+const SPRITE_PREFIX: &str = "previews_";
+
+/// Seek bar preview generation, see `[preview]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PreviewConfig {
+    /// ffmpeg binary used to decode frames and tile them into sheets
+    #[serde(default = "default_ffmpeg")]
+    pub ffmpeg: String,
+    /// One preview frame every this many seconds
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+    /// Width of one preview, the height follows the video aspect ratio
+    #[serde(default = "default_tile_width")]
+    pub tile_width: u32,
+    #[serde(default = "default_grid")]
+    pub columns: u32,
+    #[serde(default = "default_grid")]
+    pub rows: u32,
+    /// Jobs decoding at the same time across all recordings, further jobs queue
+    #[serde(default = "default_max_concurrent_jobs")]
+    pub max_concurrent_jobs: usize,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self {
+            ffmpeg: default_ffmpeg(),
+            interval_seconds: default_interval_seconds(),
+            tile_width: default_tile_width(),
+            columns: default_grid(),
+            rows: default_grid(),
+            max_concurrent_jobs: default_max_concurrent_jobs(),
+        }
+    }
+}
+
+fn default_ffmpeg() -> String {
+    "ffmpeg".to_string()
+}
+
+fn default_interval_seconds() -> u64 {
+    10
+}
+
+fn default_tile_width() -> u32 {
+    160
+}
+
+fn default_grid() -> u32 {
+    5
+}
+
+fn default_max_concurrent_jobs() -> usize {
+    1
+}
+
+/// Video representation of a recording manifest, or its audio one without dimensions
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoTrack {
+    pub init: String,
+    pub segments: Vec<String>,
+    pub duration_secs: f64,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub(crate) fn attr<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let needle = format!(" {name}=\"");
+    let start = element.find(&needle)? + needle.len();
+    let len = element[start..].find('"')?;
+    Some(&element[start..start + len])
+}
+
+/// Expand `$Number$` or `$Number%0Nd$` in a DASH segment template
+pub(crate) fn expand_number(template: &str, number: u64) -> String {
+    let Some(begin) = template.find("$Number") else {
+        return template.to_string();
+    };
+    let rest = &template[begin + "$Number".len()..];
+    let Some(end) = rest.find('$') else {
+        return template.to_string();
+    };
+    let width: usize = rest[..end]
+        .strip_prefix("%0")
+        .and_then(|f| f.strip_suffix('d'))
+        .and_then(|w| w.parse().ok())
+        .unwrap_or(0);
+    format!(
+        "{}{:0width$}{}",
+        &template[..begin],
+        number,
+        &rest[end + 1..],
+        width = width
+    )
+}
+
+/// Locate the video track of a manifest, `None` for audio-only recordings
+pub fn video_track(mpd: &str) -> Option<VideoTrack> {
+    track(mpd, "video")
+}
+
+/// Locate the audio track of a manifest, `None` for video-only recordings
+pub fn audio_track(mpd: &str) -> Option<VideoTrack> {
+    track(mpd, "audio")
+}
+
+fn track(mpd: &str, content_type: &str) -> Option<VideoTrack> {
+    let begin = mpd.find(&format!("contentType=\"{content_type}\""))?;
+    let block = &mpd[begin..];
+    let block = &block[..block.find("</AdaptationSet>").unwrap_or(block.len())];
+
+    let representation = &block[block.find("<Representation")?..];
+    let width = attr(representation, "width")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let height = attr(representation, "height")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+
+    let template = &block[block.find("<SegmentTemplate")?..];
+    let init = attr(template, "initialization")?.to_string();
+    let media = attr(template, "media")?;
+    let timescale: u64 = attr(template, "timescale")
+        .and_then(|v| v.parse().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(1);
+    let start_number: u64 = attr(template, "startNumber")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1);
+
+    let mut count = 0u64;
+    let mut ticks = 0u64;
+    for (pos, _) in template.match_indices("<S ") {
+        let element = &template[pos..];
+        let element = &element[..element.find('>').unwrap_or(element.len())];
+        let duration: u64 = attr(element, "d").and_then(|d| d.parse().ok()).unwrap_or(0);
+        let repeat: u64 = attr(element, "r").and_then(|r| r.parse().ok()).unwrap_or(0);
+        count += 1 + repeat;
+        ticks += duration * (1 + repeat);
+    }
+    if count == 0 {
+        return None;
+    }
+
+    Some(VideoTrack {
+        init,
+        segments: (0..count)
+            .map(|i| expand_number(media, start_number + i))
+            .collect(),
+        duration_secs: ticks as f64 / timescale as f64,
+        width,
+        height,
+    })
+}
+
+/// Preview height for `tile_width` keeping the video aspect ratio, even for the encoder
+fn tile_height(track: &VideoTrack, tile_width: u32) -> u32 {
+    if track.width == 0 || track.height == 0 {
+        return (tile_width * 9 / 16) & !1;
+    }
+    ((tile_width as u64 * track.height as u64 / track.width as u64) as u32).max(2) & !1
+}
+
+fn vtt_timestamp(secs: f64) -> String {
+    let millis = (secs * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// WebVTT cues mapping each interval of the recording to its cell in a sprite sheet
+fn build_vtt(cfg: &PreviewConfig, duration_secs: f64, tile: (u32, u32), sheets: usize) -> String {
+    let interval = cfg.interval_seconds.max(1) as f64;
+    let columns = cfg.columns.max(1);
+    let per_sheet = (columns * cfg.rows.max(1)) as usize;
+    let frames = ((duration_secs / interval).ceil() as usize)
+        .max(1)
+        .min(sheets * per_sheet);
+
+    let mut vtt = String::from("WEBVTT\n");
+    for i in 0..frames {
+        let start = i as f64 * interval;
+        let end = ((i + 1) as f64 * interval).min(duration_secs.max(start + 0.001));
+        let cell = i % per_sheet;
+        let x = (cell as u32 % columns) * tile.0;
+        let y = (cell as u32 / columns) * tile.1;
+        vtt.push_str(&format!(
+            "\n{} --> {}\n{}{:03}.jpg#xywh={},{},{},{}\n",
+            vtt_timestamp(start),
+            vtt_timestamp(end),
+            SPRITE_PREFIX,
+            i / per_sheet + 1,
+            x,
+            y,
+            tile.0,
+            tile.1
+        ));
+    }
+    vtt
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done { vtt_path: String },
+    Failed { error: String },
+}
+
+/// Preview jobs by `stream/record`, finished ones are kept so requests stay idempotent
+pub struct PreviewJobs {
+    cfg: PreviewConfig,
+    jobs: Mutex<HashMap<String, JobStatus>>,
+    permits: Arc<Semaphore>,
+}
+
+impl PreviewJobs {
+    pub fn new(cfg: PreviewConfig) -> Self {
+        let permits = Arc::new(Semaphore::new(cfg.max_concurrent_jobs.max(1)));
+        Self {
+            cfg,
+            jobs: Mutex::new(HashMap::new()),
+            permits,
+        }
+    }
+
+    pub fn status(&self, key: &str) -> Option<JobStatus> {
+        self.jobs.lock().unwrap().get(key).cloned()
+    }
+
+    /// Record previews found in storage from an earlier run
+    pub fn mark_done(&self, key: &str, vtt_path: String) -> JobStatus {
+        let status = JobStatus::Done { vtt_path };
+        self.set(key, status.clone());
+        status
+    }
+
+    /// Queue a job unless one is pending or finished, failed jobs are retried
+    pub fn submit(
+        self: &Arc<Self>,
+        key: &str,
+        operator: Operator,
+        record_dir: String,
+        track: VideoTrack,
+    ) -> JobStatus {
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            match jobs.get(key) {
+                Some(JobStatus::Failed { .. }) | None => {
+                    jobs.insert(key.to_string(), JobStatus::Queued);
+                }
+                Some(status) => return status.clone(),
+            }
+        }
+
+        let this = self.clone();
+        let key = key.to_string();
+        tokio::spawn(async move {
+            let Ok(_permit) = this.permits.clone().acquire_owned().await else {
+                return;
+            };
+            this.set(&key, JobStatus::Running);
+            let status = match this.generate(&operator, &record_dir, &track).await {
+                Ok(vtt_path) => {
+                    info!("previews for {} written to {}", key, vtt_path);
+                    JobStatus::Done { vtt_path }
+                }
+                Err(e) => {
+                    warn!("preview job for {} failed: {:#}", key, e);
+                    JobStatus::Failed {
+                        error: format!("{e:#}"),
+                    }
+                }
+            };
+            this.set(&key, status);
+        });
+        JobStatus::Queued
+    }
+
+    fn set(&self, key: &str, status: JobStatus) {
+        self.jobs.lock().unwrap().insert(key.to_string(), status);
+    }
+
+    async fn generate(
+        &self,
+        operator: &Operator,
+        record_dir: &str,
+        track: &VideoTrack,
+    ) -> Result<String> {
+        let workdir = WorkDir::create().await?;
+        let input = workdir.path().join("input.mp4");
+
+        // Init segment followed by media segments is a playable fragmented MP4
+        let mut file = tokio::fs::File::create(&input).await?;
+        for name in std::iter::once(&track.init).chain(&track.segments) {
+            // Deduplicated init segments are referenced as `../../_shared/...`
+            let path = storage::resolve_relative(record_dir, name);
+            let data = operator
+                .read(&path)
+                .await
+                .with_context(|| format!("failed to read {path}"))?;
+            file.write_all(&data.to_vec()).await?;
+        }
+        file.flush().await?;
+        drop(file);
+
+        let tile = (
+            self.cfg.tile_width.max(2) & !1,
+            tile_height(track, self.cfg.tile_width),
+        );
+        let filter = format!(
+            "fps=1/{},scale={}:{},tile={}x{}",
+            self.cfg.interval_seconds.max(1),
+            tile.0,
+            tile.1,
+            self.cfg.columns.max(1),
+            self.cfg.rows.max(1)
+        );
+        let output = tokio::process::Command::new(&self.cfg.ffmpeg)
+            .args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+            .arg(&input)
+            .args(["-vf", &filter, "-q:v", "5"])
+            .arg(workdir.path().join(format!("{SPRITE_PREFIX}%03d.jpg")))
+            .output()
+            .await
+            .with_context(|| format!("failed to run {}", self.cfg.ffmpeg))?;
+        anyhow::ensure!(
+            output.status.success(),
+            "ffmpeg exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+
+        let mut sheets = Vec::new();
+        let mut dir = tokio::fs::read_dir(workdir.path()).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(SPRITE_PREFIX) && name.ends_with(".jpg") {
+                sheets.push(name);
+            }
+        }
+        sheets.sort();
+        anyhow::ensure!(!sheets.is_empty(), "ffmpeg produced no preview frames");
+
+        for name in &sheets {
+            let data = tokio::fs::read(workdir.path().join(name)).await?;
+            let path = format!("{record_dir}/{name}");
+            operator
+                .write_with(&path, data)
+                .content_type(storage::content_type_for(&path))
+                .await
+                .with_context(|| format!("failed to write {path}"))?;
+        }
+
+        let vtt_path = format!("{record_dir}/{VTT_FILENAME}");
+        let vtt = build_vtt(&self.cfg, track.duration_secs, tile, sheets.len());
+        operator
+            .write_with(&vtt_path, vtt.into_bytes())
+            .content_type(storage::content_type_for(&vtt_path))
+            .await
+            .with_context(|| format!("failed to write {vtt_path}"))?;
+        Ok(vtt_path)
+    }
+}
+
+/// Scratch directory removed when dropped
+struct WorkDir(PathBuf);
+
+impl WorkDir {
+    async fn create() -> Result<Self> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "livevod-preview-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::create_dir_all(&path).await?;
+        Ok(Self(path))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MPD: &str = r#"<MPD><Period>
+        <AdaptationSet id="0" contentType="video" frameRate="30/1">
+            <Representation id="0" mimeType="video/mp4" width="1280" height="720">
+                <SegmentTemplate timescale="90000" initialization="v_init.m4s" media="v_seg_$Number%04d$.m4s" startNumber="1">
+                    <SegmentTimeline>
+                        <S t="0" d="900000" r="1" />
+                        <S t="1800000" d="450000" />
+                    </SegmentTimeline>
+                </SegmentTemplate>
+            </Representation>
+        </AdaptationSet>
+        <AdaptationSet id="1" contentType="audio">
+            <Representation id="1" mimeType="audio/mp4">
+                <SegmentTemplate timescale="48000" initialization="a_init.m4s" media="a_seg_$Number%04d$.m4s" startNumber="1">
+                    <SegmentTimeline><S t="0" d="480000" /></SegmentTimeline>
+                </SegmentTemplate>
+            </Representation>
+        </AdaptationSet>
+    </Period></MPD>"#;
+
+    #[test]
+    fn test_video_track() {
+        let track = video_track(MPD).unwrap();
+        assert_eq!(track.init, "v_init.m4s");
+        assert_eq!(
+            track.segments,
+            vec!["v_seg_0001.m4s", "v_seg_0002.m4s", "v_seg_0003.m4s"]
+        );
+        assert_eq!(track.duration_secs, 25.0);
+        assert_eq!((track.width, track.height), (1280, 720));
+        assert_eq!(tile_height(&track, 160), 90);
+
+        let audio_only = MPD.replace("contentType=\"video\"", "contentType=\"text\"");
+        assert!(video_track(&audio_only).is_none());
+
+        let audio = audio_track(MPD).unwrap();
+        assert_eq!(audio.segments, vec!["a_seg_0001.m4s"]);
+        assert_eq!((audio.duration_secs, audio.width), (10.0, 0));
+    }
+
+    #[test]
+    fn test_build_vtt() {
+        let cfg = PreviewConfig {
+            columns: 2,
+            rows: 1,
+            ..Default::default()
+        };
+        let vtt = build_vtt(&cfg, 25.0, (160, 90), 2);
+        assert_eq!(
+            vtt,
+            "WEBVTT\n\
+             \n00:00:00.000 --> 00:00:10.000\npreviews_001.jpg#xywh=0,0,160,90\n\
+             \n00:00:10.000 --> 00:00:20.000\npreviews_001.jpg#xywh=160,0,160,90\n\
+             \n00:00:20.000 --> 00:00:25.000\npreviews_002.jpg#xywh=0,0,160,90\n"
+        );
+        // Never point past the sheets ffmpeg produced
+        assert_eq!(
+            build_vtt(&cfg, 25.0, (160, 90), 1).matches("-->").count(),
+            2
+        );
+        assert_eq!(vtt_timestamp(3_723.5), "01:02:03.500");
+    }
+}
@@ -0,0 +1,163 @@
+//! `Range` requests of objects.
+//!
+//! Objects read from storage and objects served from local files answer with the same
+//! status and headers, both build them here. A single `bytes=` range is honored, a
+//! malformed header or several ranges get the whole object as RFC 9110 allows.
+
+use axum::body::Body;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::Response;
+
+/// Part of an object a request asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Requested {
+    Full,
+    /// Inclusive byte offsets, within the object
+    Part {
+        start: u64,
+        end: u64,
+    },
+    /// The range starts past the end of the object
+    Unsatisfiable,
+}
+
+impl Requested {
+    /// Part of an object of `len` bytes the `Range` header of `headers` asks for
+    pub fn of(headers: &HeaderMap, len: u64) -> Self {
+        let Some(spec) = headers
+            .get(header::RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().strip_prefix("bytes="))
+        else {
+            return Self::Full;
+        };
+        if spec.contains(',') {
+            return Self::Full;
+        }
+        let Some((start, end)) = spec.trim().split_once('-') else {
+            return Self::Full;
+        };
+        let (start, end) = (start.trim(), end.trim());
+        if start.is_empty() {
+            // The last `end` bytes
+            return match end.parse::<u64>() {
+                Ok(0) => Self::Unsatisfiable,
+                Ok(_) if len == 0 => Self::Unsatisfiable,
+                Ok(suffix) => Self::Part {
+                    start: len.saturating_sub(suffix),
+                    end: len - 1,
+                },
+                Err(_) => Self::Full,
+            };
+        }
+        let Ok(start) = start.parse::<u64>() else {
+            return Self::Full;
+        };
+        let end = match end {
+            "" => u64::MAX,
+            end => match end.parse::<u64>() {
+                Ok(end) if end >= start => end,
+                _ => return Self::Full,
+            },
+        };
+        if start >= len {
+            return Self::Unsatisfiable;
+        }
+        Self::Part {
+            start,
+            end: end.min(len - 1),
+        }
+    }
+
+    /// Bytes of the response body
+    pub fn body_len(&self, len: u64) -> u64 {
+        match *self {
+            Self::Full => len,
+            Self::Part { start, end } => end - start + 1,
+            Self::Unsatisfiable => 0,
+        }
+    }
+
+    /// Response for an object of `len` bytes carrying `body`, which has to hold exactly
+    /// [`Self::body_len`] bytes
+    pub fn response(&self, len: u64, content_type: &str, body: Body) -> Response {
+        let mut builder = Response::builder()
+            .header(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"))
+            .header(header::CONTENT_LENGTH, self.body_len(len));
+        builder = match *self {
+            Self::Full => builder
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, content_type),
+            Self::Part { start, end } => builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}")),
+            Self::Unsatisfiable => builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{len}")),
+        };
+        let body = match self {
+            Self::Unsatisfiable => Body::empty(),
+            _ => body,
+        };
+        builder.body(body).unwrap()
+    }
+}
+
+/// Response of an object read into memory, with the bytes it serves
+pub fn serve_bytes(headers: &HeaderMap, content_type: &str, mut bytes: Vec<u8>) -> (Response, u64) {
+    let len = bytes.len() as u64;
+    let requested = Requested::of(headers, len);
+    if let Requested::Part { start, end } = requested {
+        bytes.truncate(end as usize + 1);
+        bytes.drain(..start as usize);
+    }
+    (
+        requested.response(len, content_type, Body::from(bytes)),
+        requested.body_len(len),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requested(range: &str, len: u64) -> Requested {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_str(range).unwrap());
+        Requested::of(&headers, len)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Requested::of(&HeaderMap::new(), 10), Requested::Full);
+        assert_eq!(
+            requested("bytes=0-3", 10),
+            Requested::Part { start: 0, end: 3 }
+        );
+        assert_eq!(
+            requested("bytes=4-", 10),
+            Requested::Part { start: 4, end: 9 }
+        );
+        assert_eq!(
+            requested("bytes=4-100", 10),
+            Requested::Part { start: 4, end: 9 }
+        );
+        assert_eq!(
+            requested("bytes=-3", 10),
+            Requested::Part { start: 7, end: 9 }
+        );
+        assert_eq!(
+            requested("bytes=-30", 10),
+            Requested::Part { start: 0, end: 9 }
+        );
+        assert_eq!(requested("bytes=10-", 10), Requested::Unsatisfiable);
+        assert_eq!(requested("bytes=-0", 10), Requested::Unsatisfiable);
+        assert_eq!(requested("bytes=0-", 0), Requested::Unsatisfiable);
+        // Ignored rather than refused
+        assert_eq!(requested("bytes=5-2", 10), Requested::Full);
+        assert_eq!(requested("bytes=0-1,4-5", 10), Requested::Full);
+        assert_eq!(requested("items=0-1", 10), Requested::Full);
+        assert_eq!(requested("bytes=a-", 10), Requested::Full);
+    }
+}
@@ -0,0 +1,109 @@
+//! Whether an object is served inline or redirected to a presigned URL.
+//!
+//! The redirect costs the client an extra round trip, which outweighs the offloaded
+//! bytes for small objects such as init segments.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+/// Recorded objects are written once, sizes only go stale when a recording is purged
+const STAT_TTL: Duration = Duration::from_secs(300);
+
+/// Drop expired entries once the map grows beyond this size
+const MAX_ENTRIES: usize = 10_000;
+
+/// Object sizes from recent `stat` calls, so the redirect decision does not add a
+/// storage round trip to every segment request
+pub struct StatCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, u64)>>,
+}
+
+impl Default for StatCache {
+    fn default() -> Self {
+        Self::new(STAT_TTL)
+    }
+}
+
+impl StatCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Option<u64> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(path)
+            .filter(|(at, _)| at.elapsed() < self.ttl)
+            .map(|(_, size)| *size)
+    }
+
+    pub fn insert(&self, path: &str, size: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, (at, _)| at.elapsed() < self.ttl);
+            // Everything is fresh, start over rather than growing without bound
+            if entries.len() >= MAX_ENTRIES {
+                entries.clear();
+            }
+        }
+        entries.insert(path.to_string(), (Instant::now(), size));
+    }
+
+    /// Returns whether `path` was cached
+    pub fn remove(&self, path: &str) -> bool {
+        self.entries.lock().unwrap().remove(path).is_some()
+    }
+}
+
+/// Per-request override of the size check, `?redirect=always|never`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RedirectMode {
+    Always,
+    Never,
+}
+
+/// Redirect objects of at least `min_bytes`. Objects whose stat failed are served
+/// inline, where a missing object answers 404 instead of a redirect to one.
+pub fn should_redirect(mode: Option<RedirectMode>, size: Option<u64>, min_bytes: u64) -> bool {
+    match mode {
+        Some(RedirectMode::Always) => true,
+        Some(RedirectMode::Never) => false,
+        None => size.is_some_and(|size| size >= min_bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_redirect() {
+        assert!(!should_redirect(None, Some(4_096), 65_536));
+        assert!(should_redirect(None, Some(65_536), 65_536));
+        assert!(!should_redirect(None, None, 65_536));
+        assert!(should_redirect(Some(RedirectMode::Always), Some(1), 65_536));
+        assert!(!should_redirect(
+            Some(RedirectMode::Never),
+            Some(1 << 20),
+            65_536
+        ));
+    }
+
+    #[test]
+    fn test_entries_expire() {
+        let cache = StatCache::new(Duration::from_millis(20));
+        assert_eq!(cache.get("cam/1/v_seg_0001.m4s"), None);
+        cache.insert("cam/1/v_seg_0001.m4s", 1_024);
+        assert_eq!(cache.get("cam/1/v_seg_0001.m4s"), Some(1_024));
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(cache.get("cam/1/v_seg_0001.m4s"), None);
+    }
+}
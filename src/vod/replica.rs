@@ -0,0 +1,320 @@
+//! Storage destinations holding copies of the recordings.
+//!
+//! `storage` is the primary destination, `[[replicas]]` add named ones. Reads go to the
+//! healthiest destination first and fall through to the others when it lacks the object
+//! or fails. Each failure costs a destination part of its health score and each success
+//! earns some back, so a backend that keeps failing drops behind the others until it
+//! recovers.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use opendal::ErrorKind;
+use serde::{Deserialize, Serialize};
+use storage::{FailoverOperator, StorageConfig};
+use tracing::{debug, warn};
+
+/// Name of the `storage` destination, as listed in an index entry's `replicas`
+pub const PRIMARY: &str = "primary";
+
+/// Name of the `playback.local_fallback_dir` destination, which no index entry lists
+pub const LOCAL: &str = "local";
+
+const MAX_SCORE: u32 = 100;
+/// Score regained per successful operation
+const SUCCESS_GAIN: u32 = 5;
+/// Score lost per failed operation, a missing object is not a failure
+const FAILURE_COST: u32 = 25;
+
+/// `[[replicas]]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReplicaConfig {
+    /// Name the uploader and the index's `replicas` know the destination by
+    pub name: String,
+    pub storage: StorageConfig,
+}
+
+pub struct Destination {
+    pub name: String,
+    pub config: StorageConfig,
+    pub operator: FailoverOperator,
+    score: AtomicU32,
+}
+
+impl Destination {
+    pub fn new(name: impl Into<String>, config: StorageConfig, operator: FailoverOperator) -> Self {
+        Self {
+            name: name.into(),
+            config,
+            operator,
+            score: AtomicU32::new(MAX_SCORE),
+        }
+    }
+
+    /// Health from 0 to 100, 100 until operations fail
+    pub fn score(&self) -> u32 {
+        self.score.load(Ordering::Relaxed)
+    }
+
+    fn succeeded(&self) {
+        let _ = self
+            .score
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |score| {
+                Some((score + SUCCESS_GAIN).min(MAX_SCORE))
+            });
+    }
+
+    fn failed(&self) {
+        let _ = self
+            .score
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |score| {
+                Some(score.saturating_sub(FAILURE_COST))
+            });
+    }
+}
+
+/// The primary destination first, then the replicas in configuration order
+pub struct Replicas {
+    destinations: Vec<Destination>,
+}
+
+impl Replicas {
+    pub fn new(primary: Destination, replicas: Vec<Destination>) -> Self {
+        let mut destinations = vec![primary];
+        destinations.extend(replicas);
+        Self { destinations }
+    }
+
+    pub fn destinations(&self) -> &[Destination] {
+        &self.destinations
+    }
+
+    /// Whether reads can fall back to another destination
+    pub fn is_replicated(&self) -> bool {
+        self.destinations.len() > 1
+    }
+
+    /// Destinations to try for a recording held by `replicas`, healthiest first with
+    /// configuration order breaking ties. Every destination when `replicas` is empty or
+    /// names none that is configured. The local fallback dir is always one: it holds
+    /// what the recorder on the same host has not uploaded yet.
+    pub fn candidates(&self, replicas: &[String]) -> Vec<&Destination> {
+        let mut candidates: Vec<&Destination> = self
+            .destinations
+            .iter()
+            .filter(|d| replicas.contains(&d.name) || d.name == LOCAL)
+            .collect();
+        if candidates.iter().all(|d| d.name == LOCAL) {
+            candidates = self.destinations.iter().collect();
+        }
+        candidates.sort_by_key(|d| std::cmp::Reverse(d.score()));
+        candidates
+    }
+
+    /// Read `path` from the first candidate that has it
+    pub async fn read(
+        &self,
+        replicas: &[String],
+        path: &str,
+    ) -> opendal::Result<(&Destination, opendal::Buffer)> {
+        self.first(replicas, path, |operator| async move {
+            operator.read(path).await
+        })
+        .await
+    }
+
+    /// Metadata of `path` from the first candidate that has it
+    pub async fn stat(
+        &self,
+        replicas: &[String],
+        path: &str,
+    ) -> opendal::Result<(&Destination, opendal::Metadata)> {
+        self.first(replicas, path, |operator| async move {
+            operator.stat(path).await
+        })
+        .await
+    }
+
+    async fn first<T, F, Fut>(
+        &self,
+        replicas: &[String],
+        path: &str,
+        mut op: F,
+    ) -> opendal::Result<(&Destination, T)>
+    where
+        F: FnMut(opendal::Operator) -> Fut,
+        Fut: Future<Output = opendal::Result<T>>,
+    {
+        let mut last = None;
+        for destination in self.candidates(replicas) {
+            match op(destination.operator.current()).await {
+                Ok(value) => {
+                    destination.succeeded();
+                    return Ok((destination, value));
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    debug!("'{}' not found on destination '{}'", path, destination.name);
+                    last = Some(e);
+                }
+                Err(e) => {
+                    destination.failed();
+                    warn!(
+                        "'{}' failed on destination '{}' (score {}): {}",
+                        path,
+                        destination.name,
+                        destination.score(),
+                        e
+                    );
+                    last = Some(e);
+                }
+            }
+        }
+        Err(last.expect("at least the primary destination is configured"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn destination(name: &str, root: &std::path::Path) -> Destination {
+        let config = StorageConfig::Fs {
+            root: root.to_string_lossy().into_owned(),
+        };
+        let operator = storage::create_failover_operator(&config).unwrap();
+        Destination::new(name, config, operator)
+    }
+
+    fn failing(name: &str, root: &std::path::Path) -> Destination {
+        let mut destination = destination(name, root);
+        let chaos = storage::ChaosConfig {
+            read: storage::FaultConfig {
+                failure_rate: 1.0,
+                latency_ms: 0,
+            },
+            ..Default::default()
+        };
+        destination.operator = destination.operator.layer(storage::ChaosLayer::new(chaos));
+        destination
+    }
+
+    fn write(root: &std::path::Path, path: &str, body: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, body).unwrap();
+    }
+
+    fn names(candidates: Vec<&Destination>) -> Vec<&str> {
+        candidates.iter().map(|d| d.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_local_fallback_is_always_a_candidate() {
+        let (primary, dr, local) = (
+            tempfile::tempdir().unwrap(),
+            tempfile::tempdir().unwrap(),
+            tempfile::tempdir().unwrap(),
+        );
+        write(local.path(), "cam/1/v_seg_0002.m4s", "not uploaded yet");
+        let replicas = Replicas::new(
+            destination(PRIMARY, primary.path()),
+            vec![
+                destination("dr", dr.path()),
+                destination(LOCAL, local.path()),
+            ],
+        );
+
+        let held = [PRIMARY.to_string()];
+        assert_eq!(names(replicas.candidates(&held)), [PRIMARY, LOCAL]);
+        assert_eq!(names(replicas.candidates(&[])), [PRIMARY, "dr", LOCAL]);
+        let unknown = ["gone".to_string()];
+        assert_eq!(names(replicas.candidates(&unknown)), [PRIMARY, "dr", LOCAL]);
+        let (served, body) = replicas.read(&held, "cam/1/v_seg_0002.m4s").await.unwrap();
+        assert_eq!(served.name, LOCAL);
+        assert_eq!(body.to_vec(), b"not uploaded yet");
+
+        // Without storage it is the only destination
+        let only = Replicas::new(destination(LOCAL, local.path()), Vec::new());
+        assert_eq!(names(only.candidates(&held)), [LOCAL]);
+    }
+
+    #[tokio::test]
+    async fn test_read_falls_back_to_a_replica() {
+        let (primary, dr) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        write(dr.path(), "cam/1/v_seg_0001.m4s", "segment");
+        write(primary.path(), "cam/1/manifest.mpd", "<MPD />");
+        let replicas = Replicas::new(
+            destination(PRIMARY, primary.path()),
+            vec![destination("dr", dr.path())],
+        );
+
+        let (served, body) = replicas.read(&[], "cam/1/v_seg_0001.m4s").await.unwrap();
+        assert_eq!(served.name, "dr");
+        assert_eq!(body.to_vec(), b"segment");
+        let (served, _) = replicas.read(&[], "cam/1/manifest.mpd").await.unwrap();
+        assert_eq!(served.name, PRIMARY);
+
+        // A missing object is no reason to demote anyone
+        let err = replicas
+            .read(&[], "cam/1/v_seg_0002.m4s")
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(replicas.destinations().iter().all(|d| d.score() == 100));
+
+        // The index narrows the destinations tried
+        let only_primary = [PRIMARY.to_string()];
+        let err = replicas
+            .read(&only_primary, "cam/1/v_seg_0001.m4s")
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_candidates_follow_the_index() {
+        let root = tempfile::tempdir().unwrap();
+        let replicas = Replicas::new(
+            destination(PRIMARY, root.path()),
+            vec![
+                destination("dr", root.path()),
+                destination("archive", root.path()),
+            ],
+        );
+        assert_eq!(names(replicas.candidates(&[])), [PRIMARY, "dr", "archive"]);
+        assert_eq!(
+            names(replicas.candidates(&["archive".to_string(), "dr".to_string()])),
+            ["dr", "archive"]
+        );
+        // Destinations this livevod does not know leave every one it does
+        assert_eq!(
+            names(replicas.candidates(&["gone".to_string()])),
+            [PRIMARY, "dr", "archive"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failing_destination_is_demoted() {
+        let (primary, dr) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        for root in [primary.path(), dr.path()] {
+            write(root, "cam/1/v_seg_0001.m4s", "segment");
+        }
+        let replicas = Replicas::new(
+            failing(PRIMARY, primary.path()),
+            vec![destination("dr", dr.path())],
+        );
+
+        let (served, _) = replicas.read(&[], "cam/1/v_seg_0001.m4s").await.unwrap();
+        assert_eq!(served.name, "dr");
+        let primary = &replicas.destinations()[0];
+        assert_eq!(primary.score(), 75);
+        assert_eq!(names(replicas.candidates(&[])), ["dr", PRIMARY]);
+
+        // Later reads go to the healthy replica without trying the primary again
+        for _ in 0..3 {
+            let (served, _) = replicas.read(&[], "cam/1/v_seg_0001.m4s").await.unwrap();
+            assert_eq!(served.name, "dr");
+        }
+        assert_eq!(primary.score(), 75);
+    }
+}
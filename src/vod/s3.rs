@@ -0,0 +1,571 @@
+//! Read-only S3-compatible gateway to the recordings.
+//!
+//! Tools that only speak S3 get `GetObject`, `HeadObject` and `ListObjectsV2` on a
+//! virtual bucket backed by livevod's storage, path-style only. Requests are verified
+//! with SigV4 against the keys in `[s3]`, each limited to stream prefixes like a JWT's
+//! `streams` claim, so a tenant lists and reads its own recordings and nothing else.
+//! Every other operation answers `403 AccessDenied`.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::Router;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{Method, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use chrono::Utc;
+use config_loader::Secret;
+use serde::{Deserialize, Serialize};
+use storage::sigv4::{self, SignedRequest, VerifyError};
+
+use super::limiter::ReadLimiter;
+use super::tenant::StreamAccess;
+
+/// `MaxKeys` of a listing without `max-keys`, and the largest accepted
+const MAX_KEYS: usize = 1000;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct S3Config {
+    /// Separate from `http.listen`, S3 clients sign the request path
+    #[serde(default = "default_listen")]
+    pub listen: SocketAddr,
+    /// Name of the virtual bucket
+    #[serde(default = "default_bucket")]
+    pub bucket: String,
+    /// Region clients sign for
+    #[serde(default = "default_region")]
+    pub region: String,
+    #[serde(default)]
+    pub keys: Vec<S3Key>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct S3Key {
+    pub access_key_id: String,
+    pub secret_access_key: Secret,
+    /// Stream prefixes the key may list and read, every stream when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub streams: Option<Vec<String>>,
+}
+
+fn default_listen() -> SocketAddr {
+    "0.0.0.0:8900".parse().expect("invalid listen address")
+}
+
+fn default_bucket() -> String {
+    "recordings".to_string()
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+pub struct S3Gateway {
+    config: S3Config,
+    operator: storage::FailoverOperator,
+    read_limiter: Arc<ReadLimiter>,
+}
+
+impl S3Gateway {
+    pub fn new(
+        config: S3Config,
+        operator: storage::FailoverOperator,
+        read_limiter: Arc<ReadLimiter>,
+    ) -> Self {
+        Self {
+            config,
+            operator,
+            read_limiter,
+        }
+    }
+}
+
+pub fn router(gateway: Arc<S3Gateway>) -> Router {
+    Router::new().fallback(handle).with_state(gateway)
+}
+
+/// An S3 error document
+struct S3Error {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl S3Error {
+    fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn access_denied(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "AccessDenied", message)
+    }
+
+    fn no_such_key() -> Self {
+        Self::new(
+            StatusCode::NOT_FOUND,
+            "NoSuchKey",
+            "The specified key does not exist.",
+        )
+    }
+}
+
+impl From<VerifyError> for S3Error {
+    fn from(e: VerifyError) -> Self {
+        let (status, code) = match e {
+            VerifyError::Unsigned | VerifyError::Expired => (StatusCode::FORBIDDEN, "AccessDenied"),
+            VerifyError::Malformed(_) | VerifyError::WrongRegion(_) => {
+                (StatusCode::BAD_REQUEST, "AuthorizationHeaderMalformed")
+            }
+            VerifyError::UnknownKey(_) => (StatusCode::FORBIDDEN, "InvalidAccessKeyId"),
+            VerifyError::Skewed => (StatusCode::FORBIDDEN, "RequestTimeTooSkewed"),
+            VerifyError::Mismatch => (StatusCode::FORBIDDEN, "SignatureDoesNotMatch"),
+        };
+        Self::new(status, code, e.to_string())
+    }
+}
+
+impl IntoResponse for S3Error {
+    fn into_response(self) -> Response {
+        let body = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Error><Code>{}</Code><Message>{}</Message></Error>",
+            self.code,
+            xml_escape(&self.message)
+        );
+        (
+            self.status,
+            [(header::CONTENT_TYPE, "application/xml")],
+            body,
+        )
+            .into_response()
+    }
+}
+
+async fn handle(
+    State(gateway): State<Arc<S3Gateway>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+) -> Response {
+    match dispatch(&gateway, peer, req).await {
+        Ok(response) => response,
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn dispatch(
+    gateway: &S3Gateway,
+    peer: SocketAddr,
+    req: Request,
+) -> Result<Response, S3Error> {
+    let invalid = || S3Error::new(StatusCode::BAD_REQUEST, "InvalidURI", "invalid URI");
+    let path = sigv4::uri_decode(req.uri().path()).ok_or_else(invalid)?;
+    let query = sigv4::decode_query(req.uri().query().unwrap_or_default()).ok_or_else(invalid)?;
+    let headers: Vec<(String, String)> = req
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| {
+            Some((name.as_str().to_string(), value.to_str().ok()?.to_string()))
+        })
+        .collect();
+    let signed = SignedRequest {
+        method: req.method().as_str(),
+        path: &path,
+        query: &query,
+        headers: &headers,
+    };
+    let keys = &gateway.config.keys;
+    let access_key = sigv4::verify(
+        &signed,
+        &gateway.config.region,
+        |id| {
+            keys.iter()
+                .find(|key| key.access_key_id == id)
+                .map(|key| key.secret_access_key.expose_secret())
+        },
+        Utc::now(),
+    )?;
+    let access = StreamAccess::prefixes(
+        keys.iter()
+            .find(|key| key.access_key_id == access_key)
+            .and_then(|key| key.streams.clone()),
+    );
+
+    let head = match *req.method() {
+        Method::GET => false,
+        Method::HEAD => true,
+        _ => return Err(S3Error::access_denied("the gateway is read-only")),
+    };
+    let param = |name: &str| {
+        query
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    };
+
+    let path = path.trim_start_matches('/');
+    if path.is_empty() {
+        return Ok(list_buckets(&gateway.config.bucket));
+    }
+    let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+    if bucket != gateway.config.bucket {
+        return Err(S3Error::new(
+            StatusCode::NOT_FOUND,
+            "NoSuchBucket",
+            "The specified bucket does not exist.",
+        ));
+    }
+    if !key.is_empty() {
+        if !access.allows_object(key) {
+            return Err(S3Error::access_denied("stream not allowed"));
+        }
+        return get_object(gateway, peer, key, req.headers(), head).await;
+    }
+
+    if head {
+        return Ok(StatusCode::OK.into_response());
+    }
+    if param("location").is_some() {
+        return Ok(xml(format!(
+            "<LocationConstraint xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">{}</LocationConstraint>",
+            xml_escape(&gateway.config.region)
+        )));
+    }
+    if param("list-type") != Some("2") {
+        return Err(S3Error::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "NotImplemented",
+            "only ListObjectsV2 is supported",
+        ));
+    }
+    let max_keys = match param("max-keys") {
+        Some(max) => max
+            .parse::<usize>()
+            .map_err(|_| {
+                S3Error::new(
+                    StatusCode::BAD_REQUEST,
+                    "InvalidArgument",
+                    "invalid max-keys",
+                )
+            })?
+            .min(MAX_KEYS),
+        None => MAX_KEYS,
+    };
+    let list = ListRequest {
+        prefix: param("prefix").unwrap_or_default(),
+        delimiter: param("delimiter").filter(|d| !d.is_empty()),
+        max_keys,
+        continuation_token: param("continuation-token"),
+        start_after: param("start-after"),
+        url_encoded: param("encoding-type") == Some("url"),
+    };
+    list_objects(gateway, &access, &list).await
+}
+
+fn xml(body: String) -> Response {
+    (
+        [(header::CONTENT_TYPE, "application/xml")],
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{body}"),
+    )
+        .into_response()
+}
+
+fn list_buckets(bucket: &str) -> Response {
+    xml(format!(
+        "<ListAllMyBucketsResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+         <Owner><ID>livevod</ID><DisplayName>livevod</DisplayName></Owner>\
+         <Buckets><Bucket><Name>{}</Name></Bucket></Buckets></ListAllMyBucketsResult>",
+        xml_escape(bucket)
+    ))
+}
+
+/// `GetObject` with a single `Range`, `HeadObject` with `head`
+async fn get_object(
+    gateway: &S3Gateway,
+    peer: SocketAddr,
+    key: &str,
+    headers: &header::HeaderMap,
+    head: bool,
+) -> Result<Response, S3Error> {
+    let operator = gateway.operator.current();
+    let meta = match operator.stat(key).await {
+        Ok(meta) if !meta.is_dir() => meta,
+        Ok(_) => return Err(S3Error::no_such_key()),
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => return Err(S3Error::no_such_key()),
+        Err(e) => {
+            tracing::error!("[s3] stat of '{}' failed: {}", key, e);
+            return Err(internal_error());
+        }
+    };
+    let size = meta.content_length();
+    let range = match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(range) => Some(parse_range(range, size).ok_or_else(|| {
+            S3Error::new(
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "InvalidRange",
+                "The requested range is not satisfiable",
+            )
+        })?),
+        None => None,
+    };
+
+    let mut builder = Response::builder()
+        .header(header::CONTENT_TYPE, storage::content_type_for(key))
+        .header(header::ACCEPT_RANGES, "bytes");
+    if let Some(modified) = meta.last_modified() {
+        builder = builder.header(header::LAST_MODIFIED, modified.format_http_date());
+    }
+    if let Some(etag) = meta.etag() {
+        builder = builder.header(header::ETAG, etag);
+    }
+    if head {
+        return builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, size)
+            .body(Body::empty())
+            .map_err(|_| internal_error());
+    }
+
+    let _permit = gateway
+        .read_limiter
+        .acquire(peer.ip())
+        .await
+        .map_err(|rejected| {
+            S3Error::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "SlowDown",
+                format!(
+                    "too many concurrent reads, retry after {}s",
+                    rejected.retry_after.as_secs()
+                ),
+            )
+        })?;
+    let read = match range {
+        Some((start, end)) => operator.read_with(key).range(start..end + 1).await,
+        None => operator.read(key).await,
+    };
+    let bytes = read
+        .map_err(|e| {
+            tracing::error!("[s3] read of '{}' failed: {}", key, e);
+            internal_error()
+        })?
+        .to_vec();
+    super::metrics::OBJECT_BYTES
+        .with_label_values(&["s3"])
+        .inc_by(bytes.len() as u64);
+    let builder = match range {
+        Some((start, end)) => builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{size}")),
+        None => builder.status(StatusCode::OK),
+    };
+    builder
+        .header(header::CONTENT_LENGTH, bytes.len())
+        .body(Body::from(bytes))
+        .map_err(|_| internal_error())
+}
+
+fn internal_error() -> S3Error {
+    S3Error::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "InternalError",
+        "We encountered an internal error. Please try again.",
+    )
+}
+
+/// Inclusive byte range of `bytes=start-end`, `bytes=start-` or `bytes=-suffix` within
+/// an object of `size` bytes
+fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.strip_prefix("bytes=")?.trim().split_once('-')?;
+    let (start, end) = match (start, end) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (size.checked_sub(suffix.min(size))?, size.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, size.checked_sub(1)?),
+        (start, end) => (
+            start.parse().ok()?,
+            end.parse::<u64>().ok()?.min(size.checked_sub(1)?),
+        ),
+    };
+    (start <= end && start < size).then_some((start, end))
+}
+
+struct ListRequest<'a> {
+    prefix: &'a str,
+    delimiter: Option<&'a str>,
+    max_keys: usize,
+    continuation_token: Option<&'a str>,
+    start_after: Option<&'a str>,
+    url_encoded: bool,
+}
+
+/// One listed key, or a common prefix when `meta` is `None`
+struct Listed {
+    key: String,
+    meta: Option<opendal::Metadata>,
+}
+
+/// `ListObjectsV2`. Objects the key may not read are left out before common prefixes
+/// are folded, so a prefix is only listed when it holds something readable
+async fn list_objects(
+    gateway: &S3Gateway,
+    access: &StreamAccess,
+    list: &ListRequest<'_>,
+) -> Result<Response, S3Error> {
+    let operator = gateway.operator.current();
+    let dir = match list.prefix.rfind('/') {
+        Some(i) => &list.prefix[..=i],
+        None => "/",
+    };
+    let entries = match operator.list_with(dir).recursive(true).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            tracing::error!("[s3] listing '{}' failed: {}", dir, e);
+            return Err(internal_error());
+        }
+    };
+
+    let mut listed: BTreeMap<String, Option<opendal::Metadata>> = BTreeMap::new();
+    for entry in entries {
+        let path = entry.path().trim_start_matches('/');
+        if entry.metadata().is_dir()
+            || !path.starts_with(list.prefix)
+            || !access.allows_object(path)
+        {
+            continue;
+        }
+        let folded = list.delimiter.and_then(|delimiter| {
+            let rest = &path[list.prefix.len()..];
+            rest.find(delimiter)
+                .map(|i| path[..list.prefix.len() + i + delimiter.len()].to_string())
+        });
+        match folded {
+            Some(prefix) => {
+                listed.insert(prefix, None);
+            }
+            None => {
+                listed.insert(path.to_string(), Some(entry.metadata().clone()));
+            }
+        }
+    }
+
+    let after = [list.continuation_token, list.start_after]
+        .into_iter()
+        .flatten()
+        .max()
+        .unwrap_or_default();
+    let mut page: Vec<Listed> = listed
+        .into_iter()
+        .filter(|(key, _)| key.as_str() > after)
+        .map(|(key, meta)| Listed { key, meta })
+        .take(list.max_keys + 1)
+        .collect();
+    let truncated = page.len() > list.max_keys;
+    page.truncate(list.max_keys);
+
+    let encode = |s: &str| {
+        let s = if list.url_encoded {
+            sigv4::uri_encode(s, false)
+        } else {
+            s.to_string()
+        };
+        xml_escape(&s)
+    };
+    let mut contents = String::new();
+    let mut prefixes = String::new();
+    for item in &page {
+        let Some(ref meta) = item.meta else {
+            prefixes.push_str(&format!(
+                "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                encode(&item.key)
+            ));
+            continue;
+        };
+        // Not every backend reports sizes and times when listing
+        let meta = if meta.content_length() == 0 || meta.last_modified().is_none() {
+            operator
+                .stat(&item.key)
+                .await
+                .unwrap_or_else(|_| meta.clone())
+        } else {
+            meta.clone()
+        };
+        contents.push_str(&format!("<Contents><Key>{}</Key>", encode(&item.key)));
+        if let Some(modified) = meta.last_modified() {
+            contents.push_str(&format!("<LastModified>{modified}</LastModified>"));
+        }
+        if let Some(etag) = meta.etag() {
+            contents.push_str(&format!("<ETag>{}</ETag>", xml_escape(etag)));
+        }
+        contents.push_str(&format!(
+            "<Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+            meta.content_length()
+        ));
+    }
+
+    let mut body = format!(
+        "<ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+         <Name>{}</Name><Prefix>{}</Prefix><KeyCount>{}</KeyCount><MaxKeys>{}</MaxKeys>\
+         <IsTruncated>{truncated}</IsTruncated>",
+        xml_escape(&gateway.config.bucket),
+        encode(list.prefix),
+        page.len(),
+        list.max_keys,
+    );
+    if let Some(delimiter) = list.delimiter {
+        body.push_str(&format!("<Delimiter>{}</Delimiter>", encode(delimiter)));
+    }
+    if list.url_encoded {
+        body.push_str("<EncodingType>url</EncodingType>");
+    }
+    if let Some(token) = list.continuation_token {
+        body.push_str(&format!(
+            "<ContinuationToken>{}</ContinuationToken>",
+            xml_escape(token)
+        ));
+    }
+    if let Some(start_after) = list.start_after {
+        body.push_str(&format!("<StartAfter>{}</StartAfter>", encode(start_after)));
+    }
+    if truncated && let Some(last) = page.last() {
+        body.push_str(&format!(
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            xml_escape(&last.key)
+        ));
+    }
+    body.push_str(&contents);
+    body.push_str(&prefixes);
+    body.push_str("</ListBucketResult>");
+    Ok(xml(body))
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-9", 100), Some((0, 9)));
+        assert_eq!(parse_range("bytes=90-", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=-10", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=-200", 100), Some((0, 99)));
+        assert_eq!(parse_range("bytes=50-500", 100), Some((50, 99)));
+        assert_eq!(parse_range("bytes=100-", 100), None);
+        assert_eq!(parse_range("bytes=9-0", 100), None);
+        assert_eq!(parse_range("bytes=0-9", 0), None);
+        assert_eq!(parse_range("items=0-9", 100), None);
+    }
+}
@@ -0,0 +1,260 @@
+//! Where a wall clock instant falls inside a recording.
+//!
+//! The recorder appends one [`SegmentTiming`] line per video segment to the recording's
+//! `segments.jsonl`, pairing the wall clock start of each segment with its media offset.
+//! Without the file (older recordings, or a write that failed) the offset is counted
+//! from the recording's start through the manifest's segment timeline, which only has
+//! media time and drifts from the wall clock over long recordings.
+
+use api::recorder::{RecordingIndexEntry, SegmentTiming};
+use serde::Serialize;
+
+use super::clip::{parse_tracks, to_ms, to_ticks};
+use super::preview::{attr, expand_number};
+
+/// What a [`SeekPoint`] was derived from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SeekSource {
+    /// The recording's `segments.jsonl`
+    Segments,
+    /// The manifest's segment timeline, counted from the recording's start
+    Manifest,
+}
+
+/// Position of an instant inside a recording
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct SeekPoint {
+    /// Segment holding the instant, relative to the recording directory
+    pub segment: String,
+    /// Segment number, as in the manifest's `$Number$`
+    pub seq: u64,
+    /// Media time to seek the player to, from the start of the recording
+    pub offset_ms: u64,
+    pub source: SeekSource,
+}
+
+/// Recording covering an instant, and where the instant falls inside it
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct RecordAt {
+    #[serde(flatten)]
+    pub entry: RecordingIndexEntry,
+    /// Missing when neither `segments.jsonl` nor the manifest could be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seek: Option<SeekPoint>,
+}
+
+/// Timings of a `segments.jsonl`, unreadable lines skipped
+pub fn parse(body: &str) -> Vec<SegmentTiming> {
+    body.lines()
+        .filter_map(|line| serde_json::from_str(line.trim()).ok())
+        .collect()
+}
+
+/// Position of `ts` (UNIX microseconds) from the recording's segment timings.
+///
+/// An instant between two segments seeks to the start of the later one, an instant
+/// before the first or past the last segment to the nearest end of the recording.
+pub fn from_segments(timings: &[SegmentTiming], ts: i64) -> Option<SeekPoint> {
+    let point = |timing: &SegmentTiming, offset_ms: u64| SeekPoint {
+        segment: timing.file.clone(),
+        seq: timing.seq as u64,
+        offset_ms,
+        source: SeekSource::Segments,
+    };
+    let next = timings.partition_point(|timing| timing.start_ts <= ts);
+    let Some(timing) = next.checked_sub(1).map(|i| &timings[i]) else {
+        let first = timings.first()?;
+        return Some(point(first, first.offset_ms));
+    };
+    let elapsed_ms = ((ts - timing.start_ts) / 1000) as u64;
+    match timings.get(next) {
+        Some(later) if elapsed_ms >= timing.duration_ms => Some(point(later, later.offset_ms)),
+        _ => Some(point(
+            timing,
+            timing.offset_ms + elapsed_ms.min(timing.duration_ms),
+        )),
+    }
+}
+
+/// Position of `ts` in the video track of `mpd`, taking the recording's `start_ts` as
+/// media time zero. Audio-only manifests seek in their audio track.
+pub fn from_manifest(mpd: &str, start_ts: i64, ts: i64) -> Option<SeekPoint> {
+    let tracks = parse_tracks(mpd);
+    let video = mpd.find("contentType=\"video\"");
+    let track = tracks
+        .iter()
+        .find(|track| video.is_some_and(|v| track.start > v))
+        .or(tracks.first())?;
+    let media = attr(&mpd[track.start..track.end], "media")?;
+
+    let elapsed_ms = ((ts - start_ts).max(0) / 1000) as u64;
+    let ticks = to_ticks(elapsed_ms, track.timescale);
+    let next = track.segments.partition_point(|(t, _)| *t <= ticks);
+    let (index, offset_ms) = match next.checked_sub(1) {
+        None => (0, to_ms(track.segments.first()?.0, track.timescale)),
+        Some(i) => {
+            let (t, d) = track.segments[i];
+            match track.segments.get(next) {
+                Some((later, _)) if ticks >= t + d => (next, to_ms(*later, track.timescale)),
+                _ => (i, elapsed_ms.min(to_ms(t + d, track.timescale))),
+            }
+        }
+    };
+    let seq = track.start_number + index as u64;
+    Some(SeekPoint {
+        segment: expand_number(media, seq),
+        seq,
+        offset_ms,
+        source: SeekSource::Manifest,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START_TS: i64 = 1_718_200_000_000_000;
+    const TIMESCALE: u64 = 90_000;
+
+    /// Six hours of segments around 10 s long, jittered by up to ±1.5 s. With `drift_ms`
+    /// the wall clock gains up to that much per segment on the media clock, and every
+    /// 500th segment is dropped as on a full disk: the timeline skips its span and the
+    /// next segment takes its number, like the recorder does.
+    fn timeline(drift_ms: u64) -> Vec<SegmentTiming> {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut random = |below: u64| {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) % below
+        };
+        let mut timings = Vec::new();
+        let (mut wall, mut offset_ms, mut seq) = (START_TS, 0u64, 1u32);
+        for n in 1..=2_160 {
+            let duration_ms = 8_500 + random(3_001);
+            let drift = if drift_ms > 0 {
+                random(drift_ms + 1)
+            } else {
+                0
+            };
+            if drift_ms == 0 || n % 500 != 0 {
+                timings.push(SegmentTiming {
+                    file: format!("v_seg_{seq:04}.m4s"),
+                    seq,
+                    start_ts: wall,
+                    offset_ms,
+                    duration_ms,
+                    bytes: 1_000_000 + random(100_000),
+                });
+                seq += 1;
+            }
+            wall += ((duration_ms + drift) * 1000) as i64;
+            offset_ms += duration_ms;
+        }
+        timings
+    }
+
+    fn manifest(timings: &[SegmentTiming]) -> String {
+        let timeline: String = timings
+            .iter()
+            .map(|t| {
+                format!(
+                    "<S t=\"{}\" d=\"{}\" />\n",
+                    to_ticks(t.offset_ms, TIMESCALE),
+                    to_ticks(t.duration_ms, TIMESCALE)
+                )
+            })
+            .collect();
+        format!(
+            r#"<MPD>
+    <Period id="0" start="PT0.0S">
+        <AdaptationSet id="0" contentType="video">
+            <Representation id="0" mimeType="video/mp4">
+                <SegmentTemplate timescale="{TIMESCALE}" initialization="v_init.m4s" media="v_seg_$Number%04d$.m4s" startNumber="1">
+                    <SegmentTimeline>
+{timeline}                    </SegmentTimeline>
+                </SegmentTemplate>
+            </Representation>
+        </AdaptationSet>
+    </Period>
+</MPD>"#
+        )
+    }
+
+    /// Instants every 7.3 s over the timeline and a little past its end
+    fn probes(timings: &[SegmentTiming]) -> impl Iterator<Item = i64> {
+        let last = timings.last().unwrap();
+        let end = last.start_ts + (last.duration_ms * 1000) as i64;
+        (START_TS..end + 5_000_000).step_by(7_300_000)
+    }
+
+    #[test]
+    fn test_parse_skips_broken_lines() {
+        let timings = timeline(0);
+        let mut body = String::new();
+        for timing in &timings[..3] {
+            body.push_str(&serde_json::to_string(timing).unwrap());
+            body.push('\n');
+        }
+        body.push_str("{\"file\":\"v_seg_0004.m4s\",\"se");
+        assert_eq!(parse(&body), timings[..3]);
+        assert_eq!(from_segments(&[], START_TS), None);
+    }
+
+    #[test]
+    fn test_segments_seek_within_one_segment() {
+        let timings = timeline(40);
+        for ts in probes(&timings) {
+            let point = from_segments(&timings, ts).unwrap();
+            assert_eq!(point.source, SeekSource::Segments);
+            let i = timings
+                .iter()
+                .position(|t| t.seq as u64 == point.seq)
+                .unwrap();
+            let timing = &timings[i];
+            assert_eq!(point.segment, timing.file);
+            assert!(point.offset_ms >= timing.offset_ms);
+            assert!(point.offset_ms <= timing.offset_ms + timing.duration_ms);
+
+            let end = timing.start_ts + (timing.duration_ms * 1000) as i64;
+            if ts < timing.start_ts {
+                // Between segments, the later one starts right after the instant
+                assert_eq!(point.offset_ms, timing.offset_ms, "{ts}");
+                assert!(i == 0 || ts >= timings[i - 1].start_ts, "{ts}");
+            } else if ts < end {
+                // Inside the segment, to the millisecond
+                let at = timing.start_ts + ((point.offset_ms - timing.offset_ms) * 1000) as i64;
+                assert!((ts - at).abs() < 1000, "{ts} seeks to {at}");
+            } else {
+                assert_eq!(i, timings.len() - 1, "{ts}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_manifest_fallback() {
+        // Without drift the manifest math lands in the same segment as the timings
+        let timings = timeline(0);
+        let mpd = manifest(&timings);
+        for ts in probes(&timings) {
+            let exact = from_segments(&timings, ts).unwrap();
+            let point = from_manifest(&mpd, START_TS, ts).unwrap();
+            assert_eq!(point.source, SeekSource::Manifest);
+            assert_eq!((&point.segment, point.seq), (&exact.segment, exact.seq));
+            assert!(point.offset_ms.abs_diff(exact.offset_ms) <= 1, "{ts}");
+        }
+
+        // With drift it lands segments too late after hours, the timings do not
+        let timings = timeline(40);
+        let mpd = manifest(&timings);
+        let timing = &timings[1_500];
+        let point = from_manifest(&mpd, START_TS, timing.start_ts).unwrap();
+        assert!(point.seq >= timing.seq as u64 + 2, "{point:?}");
+        let point = from_segments(&timings, timing.start_ts).unwrap();
+        assert_eq!(
+            (point.seq, point.offset_ms),
+            (timing.seq as u64, timing.offset_ms)
+        );
+    }
+}
@@ -0,0 +1,277 @@
+//! Playback sessions derived from object requests, for `[analytics]`.
+//!
+//! Requests of one client for objects of one recording belong to the same session
+//! until the client stays away for the idle timeout. How long the client watched is
+//! approximated from its segment requests: a player fetches segments at about the
+//! rate it plays them, so the time between the first and the last segment request,
+//! plus one average interval for the last segment, is close to the time watched.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// One object request, as counted by [`Sessionizer::observe`]
+#[derive(Debug, Clone, Copy)]
+pub struct Request<'a> {
+    /// Fingerprint of the client, see [`super::analytics::fingerprint`]
+    pub client: &'a str,
+    /// Object path, `{record_dir}/{file}`
+    pub path: &'a str,
+    pub bytes: u64,
+    /// UNIX milliseconds
+    pub at_ms: i64,
+}
+
+/// A client's playback of one recording
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlaybackSession {
+    pub stream: String,
+    pub record: String,
+    pub record_dir: String,
+    pub client: String,
+    /// First request, UNIX milliseconds
+    pub started_at_ms: i64,
+    /// Last request, UNIX milliseconds
+    pub ended_at_ms: i64,
+    /// Bytes of every object served, manifests included
+    pub bytes: u64,
+    pub requests: u64,
+    /// Media segments requested
+    pub segments: u64,
+    /// Approximate time watched, from the segment requests
+    pub watched_ms: u64,
+}
+
+#[derive(Debug)]
+struct Open {
+    session: PlaybackSession,
+    /// Segment requests by track: count, first and last request
+    tracks: HashMap<String, (u64, i64, i64)>,
+}
+
+impl Open {
+    fn close(mut self) -> PlaybackSession {
+        // The track with the most requests paces playback, the video one if any
+        if let Some((count, first, last)) = self.tracks.into_values().max_by_key(|t| t.0)
+            && count > 1
+        {
+            let span = (last - first).max(0) as u64;
+            self.session.watched_ms = span + span / (count - 1);
+        }
+        self.session
+    }
+}
+
+/// Groups requests into [`PlaybackSession`]s by client and recording
+#[derive(Debug)]
+pub struct Sessionizer {
+    idle_timeout_ms: i64,
+    open: HashMap<(String, String), Open>,
+}
+
+impl Sessionizer {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self {
+            idle_timeout_ms: idle_timeout.as_millis() as i64,
+            open: HashMap::new(),
+        }
+    }
+
+    /// Count `req` in its session, returns the earlier session of the client and
+    /// recording when `req` comes after the idle timeout. Objects outside a recording
+    /// directory are ignored
+    pub fn observe(&mut self, req: Request) -> Option<PlaybackSession> {
+        let (record_dir, file) = req.path.trim_start_matches('/').rsplit_once('/')?;
+        let key = (req.client.to_string(), record_dir.to_string());
+        let ended = match self.open.get(&key) {
+            Some(open) if req.at_ms - open.session.ended_at_ms > self.idle_timeout_ms => {
+                self.open.remove(&key).map(Open::close)
+            }
+            _ => None,
+        };
+        let open = self.open.entry(key).or_insert_with(|| {
+            let (stream, record) = match record_dir.rsplit_once('/') {
+                Some((parent, record)) => (
+                    parent.rsplit('/').next().unwrap_or(parent).to_string(),
+                    record.to_string(),
+                ),
+                None => (String::new(), record_dir.to_string()),
+            };
+            Open {
+                session: PlaybackSession {
+                    stream,
+                    record,
+                    record_dir: record_dir.to_string(),
+                    client: req.client.to_string(),
+                    started_at_ms: req.at_ms,
+                    ended_at_ms: req.at_ms,
+                    bytes: 0,
+                    requests: 0,
+                    segments: 0,
+                    watched_ms: 0,
+                },
+                tracks: HashMap::new(),
+            }
+        });
+        let session = &mut open.session;
+        session.ended_at_ms = session.ended_at_ms.max(req.at_ms);
+        session.bytes += req.bytes;
+        session.requests += 1;
+        if let Some(track) = segment_track(file) {
+            session.segments += 1;
+            let (count, first, last) = open
+                .tracks
+                .entry(track.to_string())
+                .or_insert((0, req.at_ms, req.at_ms));
+            *count += 1;
+            *first = (*first).min(req.at_ms);
+            *last = (*last).max(req.at_ms);
+        }
+        ended
+    }
+
+    /// Close the sessions idle since before `now_ms` minus the idle timeout
+    pub fn expire(&mut self, now_ms: i64) -> Vec<PlaybackSession> {
+        let idle: Vec<(String, String)> = self
+            .open
+            .iter()
+            .filter(|(_, open)| now_ms - open.session.ended_at_ms > self.idle_timeout_ms)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut sessions: Vec<PlaybackSession> = idle
+            .into_iter()
+            .filter_map(|key| self.open.remove(&key))
+            .map(Open::close)
+            .collect();
+        sessions.sort_by_key(|s| s.started_at_ms);
+        sessions
+    }
+
+    /// Close every session, on shutdown
+    pub fn drain(&mut self) -> Vec<PlaybackSession> {
+        let mut sessions: Vec<PlaybackSession> =
+            self.open.drain().map(|(_, open)| open.close()).collect();
+        sessions.sort_by_key(|s| s.started_at_ms);
+        sessions
+    }
+}
+
+/// Track of a media segment like `v_seg_0042.m4s`, `None` for manifests and init segments
+fn segment_track(file: &str) -> Option<&str> {
+    let name = file.strip_suffix(".m4s")?;
+    if name.contains("init") {
+        return None;
+    }
+    Some(name.split_once("_seg").map_or("", |(track, _)| track))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T0: i64 = 1_700_000_000_000;
+
+    fn sessionizer() -> Sessionizer {
+        Sessionizer::new(Duration::from_secs(60))
+    }
+
+    fn get(s: &mut Sessionizer, client: &str, path: &str, at_ms: i64) -> Option<PlaybackSession> {
+        s.observe(Request {
+            client,
+            path,
+            bytes: 1000,
+            at_ms,
+        })
+    }
+
+    /// A player fetching the manifest, init segments, then one video and one audio
+    /// segment every 4 seconds
+    fn play(s: &mut Sessionizer, client: &str, record_dir: &str, start: i64, segments: i64) {
+        assert!(get(s, client, &format!("{record_dir}/manifest.mpd"), start).is_none());
+        get(s, client, &format!("{record_dir}/v_init.m4s"), start + 10);
+        get(s, client, &format!("{record_dir}/a_init.m4s"), start + 10);
+        for i in 0..segments {
+            let at = start + 20 + i * 4000;
+            get(s, client, &format!("{record_dir}/v_seg_{i:04}.m4s"), at);
+            get(s, client, &format!("{record_dir}/a_seg_{i:04}.m4s"), at + 5);
+        }
+    }
+
+    #[test]
+    fn test_requests_grouped_by_client_and_record() {
+        let mut s = sessionizer();
+        play(&mut s, "alice", "cam/1700000000", T0, 30);
+        play(&mut s, "bob", "cam/1700000000", T0 + 1000, 5);
+        play(&mut s, "alice", "lobby/1700000100", T0 + 2000, 1);
+        assert_eq!(s.open.len(), 3);
+        assert!(s.expire(T0 + 60_000).is_empty());
+
+        let sessions = s.expire(T0 + 200_000);
+        assert_eq!(s.open.len(), 0);
+        assert_eq!(sessions.len(), 3);
+        let alice = &sessions[0];
+        assert_eq!(
+            (alice.stream.as_str(), alice.record.as_str()),
+            ("cam", "1700000000")
+        );
+        assert_eq!(alice.client, "alice");
+        assert_eq!(alice.started_at_ms, T0);
+        assert_eq!(alice.ended_at_ms, T0 + 20 + 29 * 4000 + 5);
+        assert_eq!(alice.requests, 3 + 60);
+        assert_eq!(alice.bytes, 63_000);
+        assert_eq!(alice.segments, 60);
+        // 30 segments of 4 seconds
+        assert_eq!(alice.watched_ms, 120_000);
+
+        assert_eq!(sessions[1].client, "bob");
+        assert_eq!(sessions[1].watched_ms, 20_000);
+        // A single segment can't be timed
+        assert_eq!(sessions[2].stream, "lobby");
+        assert_eq!(sessions[2].watched_ms, 0);
+    }
+
+    #[test]
+    fn test_idle_gap_starts_a_new_session() {
+        let mut s = sessionizer();
+        play(&mut s, "alice", "cam/1", T0, 10);
+        // Back after a break: the first visit ends with this request
+        let first = get(&mut s, "alice", "cam/1/manifest.mpd", T0 + 300_000).unwrap();
+        assert_eq!(first.segments, 20);
+        assert_eq!(first.watched_ms, 40_000);
+        // Pausing shorter than the timeout stays in the session
+        get(&mut s, "alice", "cam/1/v_seg_0010.m4s", T0 + 340_000);
+        get(&mut s, "alice", "cam/1/v_seg_0011.m4s", T0 + 344_000);
+
+        let second = s.drain();
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].started_at_ms, T0 + 300_000);
+        assert_eq!(second[0].requests, 3);
+        assert_eq!(second[0].watched_ms, 8_000);
+    }
+
+    #[test]
+    fn test_namespaced_record_dir() {
+        let mut s = sessionizer();
+        get(
+            &mut s,
+            "alice",
+            "acme/edge-1/cam/1700000000/manifest.mpd",
+            T0,
+        );
+        get(&mut s, "alice", "shared.m4s", T0);
+        let sessions = s.drain();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].stream, "cam");
+        assert_eq!(sessions[0].record, "1700000000");
+        assert_eq!(sessions[0].record_dir, "acme/edge-1/cam/1700000000");
+    }
+
+    #[test]
+    fn test_segment_track() {
+        assert_eq!(segment_track("v_seg_0001.m4s"), Some("v"));
+        assert_eq!(segment_track("a_seg_12.m4s"), Some("a"));
+        assert_eq!(segment_track("v_init.m4s"), None);
+        assert_eq!(segment_track("manifest.mpd"), None);
+    }
+}
@@ -0,0 +1,423 @@
+//! Stored size of a recording, for clients estimating a download.
+//!
+//! Video bytes are summed from the recording's `segments.jsonl`, audio bytes from the
+//! manifest's audio timeline at its declared bandwidth, Opus being close to constant
+//! bitrate. Recordings without the file, or whose manifest lacks what the audio
+//! estimate needs, have their directory listed instead. A listing is reused for
+//! `playback.size_cache_seconds` however often the recording is asked for, and across
+//! invalidation notices.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use api::recorder::{RecordingIndexEntry, RecordingStatus, SEGMENTS_FILENAME, SegmentTiming};
+use chrono::Utc;
+use opendal::Operator;
+use serde::Serialize;
+
+/// What a [`RecordingBytes`] was derived from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeSource {
+    /// `segments.jsonl` for video, the manifest's audio timeline and bandwidth for audio
+    Segments,
+    /// Object sizes of a listing of the record dir
+    Listing,
+}
+
+/// Bytes of the media segments of each track
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct TrackBytes {
+    pub video: u64,
+    pub audio: u64,
+}
+
+/// Media segments a recording stored, init segments, manifests and sidecars not counted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct RecordingBytes {
+    pub stream: String,
+    pub record: String,
+    pub status: RecordingStatus,
+    pub bytes: u64,
+    pub segments: u64,
+    pub tracks: TrackBytes,
+    /// Media time the segments cover, missing when the manifest can't be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    pub source: SizeSource,
+    /// Running recordings only: `bytes` extrapolated at the recording's bitrate to the
+    /// time elapsed since it started, segments still on their way to storage included
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_final_bytes: Option<u64>,
+    /// When the figures were computed, UNIX microseconds
+    pub computed_at: i64,
+}
+
+/// Media segments by track, before they are tied to a recording
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Split {
+    segments: u64,
+    tracks: TrackBytes,
+}
+
+/// Drop idle recordings once the map grows beyond this size
+const MAX_CACHED: usize = 1_000;
+
+#[derive(Default)]
+struct Slot {
+    /// Last answer, with the cache generation and index line it was computed for
+    cached: Option<(Instant, u64, i64, RecordingBytes)>,
+    /// Last listing of the record dir, kept through invalidations
+    listed: Option<(Instant, Split)>,
+}
+
+/// Sizes served by `GET /api/record/size`, kept for `playback.size_cache_seconds`.
+///
+/// An answer is dropped early when the recording's index line changes or an
+/// invalidation notice arrives. Requests for one recording wait for each other, so a
+/// burst of them computes, and lists, once.
+pub struct SizeCache {
+    ttl: Duration,
+    generation: AtomicU64,
+    slots: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Slot>>>>,
+}
+
+impl SizeCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            generation: AtomicU64::new(0),
+            slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Recompute every recording on its next request, listings are kept
+    pub fn invalidate(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn slot(&self, key: &str) -> Arc<tokio::sync::Mutex<Slot>> {
+        let mut slots = self.slots.lock().unwrap();
+        if slots.len() >= MAX_CACHED && !slots.contains_key(key) {
+            let ttl = self.ttl;
+            slots.retain(|_, slot| {
+                Arc::strong_count(slot) > 1
+                    || slot
+                        .try_lock()
+                        .is_ok_and(|slot| slot.listed.is_some_and(|(at, _)| at.elapsed() < ttl))
+            });
+        }
+        slots.entry(key.to_string()).or_default().clone()
+    }
+
+    /// Size of `entry`, cached unless `playback.size_cache_seconds` is 0
+    pub async fn size(
+        &self,
+        operator: &Operator,
+        entry: &RecordingIndexEntry,
+        max_manifest_bytes: u64,
+    ) -> Result<RecordingBytes, opendal::Error> {
+        let slot = self.slot(&entry.key());
+        let mut slot = slot.lock().await;
+        let generation = self.generation.load(Ordering::Relaxed);
+        if let Some((at, cached_generation, updated_at, ref bytes)) = slot.cached
+            && at.elapsed() < self.ttl
+            && cached_generation == generation
+            && updated_at == entry.updated_at
+        {
+            return Ok(bytes.clone());
+        }
+
+        let mpd = match super::manifest::read(operator, &entry.mpd_path, max_manifest_bytes).await {
+            Ok(mpd) => mpd.parse::<dash::Mpd>().ok(),
+            Err(e) => {
+                tracing::debug!("sizing {} without its manifest: {}", entry.key(), e);
+                None
+            }
+        };
+        let segments_path = format!("{}/{}", entry.record_dir, SEGMENTS_FILENAME);
+        let timings =
+            match super::manifest::read(operator, &segments_path, max_manifest_bytes).await {
+                Ok(body) => super::seek::parse(&body),
+                Err(_) => Vec::new(),
+            };
+
+        let (split, source) = match mpd.as_ref().and_then(|mpd| from_segments(&timings, mpd)) {
+            Some(split) => (split, SizeSource::Segments),
+            None => {
+                let split = match slot.listed {
+                    Some((at, split)) if at.elapsed() < self.ttl => split,
+                    _ => {
+                        let split = list(operator, &entry.record_dir).await?;
+                        if !self.ttl.is_zero() {
+                            slot.listed = Some((Instant::now(), split));
+                        }
+                        split
+                    }
+                };
+                (split, SizeSource::Listing)
+            }
+        };
+
+        let now = Utc::now().timestamp_micros();
+        let duration_ms = mpd
+            .as_ref()
+            .map(media_end_ms)
+            .or_else(|| entry.duration_ms.and_then(|d| u64::try_from(d).ok()));
+        let bytes = split.tracks.video + split.tracks.audio;
+        let estimated_final_bytes = match (&entry.status, duration_ms) {
+            (RecordingStatus::Active, Some(duration_ms)) => {
+                let elapsed_ms = u64::try_from((now - entry.start_ts) / 1000).unwrap_or(0);
+                Some(extrapolate(bytes, duration_ms, elapsed_ms))
+            }
+            _ => None,
+        };
+        let bytes = RecordingBytes {
+            stream: entry.stream.clone(),
+            record: entry.record.clone(),
+            status: entry.status.clone(),
+            bytes,
+            segments: split.segments,
+            tracks: split.tracks,
+            duration_ms,
+            source,
+            estimated_final_bytes,
+            computed_at: now,
+        };
+        if !self.ttl.is_zero() {
+            slot.cached = Some((Instant::now(), generation, entry.updated_at, bytes.clone()));
+        }
+        Ok(bytes)
+    }
+}
+
+/// Video from `timings`, audio from the audio sets of `mpd`. `None` without timings, or
+/// when an audio representation declares no bandwidth
+fn from_segments(timings: &[SegmentTiming], mpd: &dash::Mpd) -> Option<Split> {
+    if timings.is_empty() {
+        return None;
+    }
+    let mut split = Split {
+        segments: timings.len() as u64,
+        tracks: TrackBytes {
+            video: timings.iter().map(|timing| timing.bytes).sum(),
+            audio: 0,
+        },
+    };
+    for set in mpd
+        .adaptation_sets()
+        .filter(|set| set.content_type() == Some("audio"))
+    {
+        for representation in &set.representations {
+            let Some(template) = set.template_of(representation) else {
+                continue;
+            };
+            let bandwidth = representation.bandwidth()?;
+            let segments = template.segments();
+            let ticks: u64 = segments.iter().map(|(_, d)| d).sum();
+            let duration_ms = dash::to_ms(ticks, template.timescale());
+            split.segments += segments.len() as u64;
+            split.tracks.audio += bandwidth * duration_ms / 8_000;
+        }
+    }
+    Some(split)
+}
+
+/// Sizes of the media segments under `record_dir`, audio told apart by file name
+async fn list(operator: &Operator, record_dir: &str) -> Result<Split, opendal::Error> {
+    let prefix = format!("{}/", record_dir.trim_end_matches('/'));
+    let entries = match operator.list_with(&prefix).recursive(true).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == opendal::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    super::metrics::SIZE_LISTINGS.inc();
+    let mut split = Split::default();
+    for entry in entries {
+        let path = entry.path();
+        if entry.metadata().is_dir() || !is_media_segment(path) {
+            continue;
+        }
+        let mut size = entry.metadata().content_length();
+        // Some backends list without sizes
+        if size == 0 {
+            size = operator.stat(path).await?.content_length();
+        }
+        split.segments += 1;
+        match storage::content_type_for(path) {
+            "audio/mp4" => split.tracks.audio += size,
+            _ => split.tracks.video += size,
+        }
+    }
+    Ok(split)
+}
+
+/// Segments of the recording itself, not its init segments or preview tracks
+fn is_media_segment(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    (name.ends_with(".m4s") || name.ends_with(".mp4"))
+        && !name.contains("init")
+        && !path.contains("/previews/")
+}
+
+/// End of the longest track of `mpd`
+fn media_end_ms(mpd: &dash::Mpd) -> u64 {
+    mpd.adaptation_sets()
+        .flat_map(|set| {
+            set.representations
+                .iter()
+                .filter_map(|representation| set.template_of(representation))
+        })
+        .map(|template| template.end_ms())
+        .max()
+        .unwrap_or(0)
+}
+
+/// `bytes` covering `recorded_ms` of media, scaled to `elapsed_ms`
+fn extrapolate(bytes: u64, recorded_ms: u64, elapsed_ms: u64) -> u64 {
+    if recorded_ms == 0 || elapsed_ms <= recorded_ms {
+        return bytes;
+    }
+    (bytes as u128 * elapsed_ms as u128 / recorded_ms as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::recorder::DEFAULT_PRIORITY;
+    use opendal::services::Fs;
+
+    const RECORD_DIR: &str = "cam/1700000000";
+
+    fn timing(seq: u32, bytes: u64) -> SegmentTiming {
+        SegmentTiming {
+            file: format!("v_seg_{seq:04}.m4s"),
+            seq,
+            start_ts: 1_700_000_000_000_000 + (seq as i64 - 1) * 10_000_000,
+            offset_ms: (seq as u64 - 1) * 10_000,
+            duration_ms: 10_000,
+            bytes,
+        }
+    }
+
+    fn entry(status: RecordingStatus, updated_at: i64) -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            uuid: String::new(),
+            record: "1700000000".to_string(),
+            stream: "cam".to_string(),
+            record_dir: RECORD_DIR.to_string(),
+            mpd_path: format!("{RECORD_DIR}/manifest.mpd"),
+            start_ts: Utc::now().timestamp_micros() - 50_000_000,
+            end_ts: None,
+            duration_ms: None,
+            status,
+            node_alias: None,
+            updated_at,
+            note: None,
+            labels: Vec::new(),
+            continues: None,
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant: None,
+            size: None,
+            captions: Vec::new(),
+            trigger: None,
+            imported: false,
+            seq: 0,
+        }
+    }
+
+    #[test]
+    fn test_split_from_segments() {
+        let mpd: dash::Mpd = include_str!("../../libs/dash/fixtures/recorder.mpd")
+            .parse()
+            .unwrap();
+        let timings = [
+            timing(1, 1_800_000),
+            timing(2, 1_900_000),
+            timing(3, 900_000),
+        ];
+        let split = from_segments(&timings, &mpd).unwrap();
+        // 25s of audio at 64 kbit/s
+        assert_eq!(
+            split,
+            Split {
+                segments: 6,
+                tracks: TrackBytes {
+                    video: 4_600_000,
+                    audio: 200_000,
+                },
+            }
+        );
+        assert_eq!(media_end_ms(&mpd), 25_000);
+        assert!(from_segments(&[], &mpd).is_none());
+
+        assert_eq!(extrapolate(4_800_000, 25_000, 50_000), 9_600_000);
+        assert_eq!(extrapolate(4_800_000, 25_000, 20_000), 4_800_000);
+    }
+
+    #[tokio::test]
+    async fn test_listing_once_per_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let record_dir = dir.path().join(RECORD_DIR);
+        std::fs::create_dir_all(record_dir.join("previews")).unwrap();
+        for (name, len) in [
+            ("v_init.m4s", 800),
+            ("v_seg_0001.m4s", 3_000),
+            ("v_seg_0002.m4s", 2_000),
+            ("a_init.m4s", 500),
+            ("a_seg_0001.m4s", 700),
+            ("previews/thumbs.jpg", 9_000),
+        ] {
+            std::fs::write(record_dir.join(name), vec![0u8; len]).unwrap();
+        }
+        let operator = Operator::new(Fs::default().root(dir.path().to_str().unwrap()))
+            .unwrap()
+            .finish();
+        let cache = SizeCache::new(Duration::from_secs(60));
+
+        let first = cache
+            .size(&operator, &entry(RecordingStatus::Completed, 1), 4096)
+            .await
+            .unwrap();
+        assert_eq!(first.source, SizeSource::Listing);
+        assert_eq!((first.bytes, first.segments), (5_700, 3));
+        assert_eq!(
+            first.tracks,
+            TrackBytes {
+                video: 5_000,
+                audio: 700,
+            }
+        );
+        assert_eq!(first.estimated_final_bytes, None);
+
+        // A new segment shows once the index line changes or a notice arrives, the
+        // directory isn't listed again within the TTL either way
+        std::fs::write(record_dir.join("v_seg_0003.m4s"), vec![0u8; 1_000]).unwrap();
+        let listings = crate::vod::metrics::SIZE_LISTINGS.get();
+        cache.invalidate();
+        let second = cache
+            .size(&operator, &entry(RecordingStatus::Completed, 2), 4096)
+            .await
+            .unwrap();
+        assert_eq!(second.bytes, 5_700);
+        assert!(second.computed_at >= first.computed_at);
+        assert_eq!(crate::vod::metrics::SIZE_LISTINGS.get(), listings);
+
+        let uncached = SizeCache::new(Duration::ZERO);
+        let third = uncached
+            .size(&operator, &entry(RecordingStatus::Completed, 2), 4096)
+            .await
+            .unwrap();
+        assert_eq!((third.bytes, third.segments), (6_700, 4));
+    }
+}
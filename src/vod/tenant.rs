@@ -0,0 +1,176 @@
+//! Stream access control for multi-tenant deployments.
+//!
+//! With `auth.mode = "jwt"` every playback request carries a bearer token, the
+//! `streams` claim lists the stream prefixes it may see and the `tenants` claim the
+//! tenants. Object paths are attributed to their stream with
+//! [`RecordingKey::from_path`](api::recorder::RecordingKey::from_path) and to their
+//! tenant by their first segment, so segment URLs are checked like the listings that
+//! lead to them.
+
+use std::convert::Infallible;
+
+use auth::AuthState;
+use auth::claims::Claims;
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use config_loader::Secret;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// Every stream is served to everyone
+    #[default]
+    None,
+    /// A static token or a JWT signed with `secret` is required
+    Jwt,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub mode: AuthMode,
+    /// JSON Web Token secret, liveman's `auth.secret` to accept the tokens it issues
+    #[serde(default)]
+    pub secret: Secret,
+    /// Static tokens with access to every stream
+    #[serde(default)]
+    pub tokens: Vec<Secret>,
+}
+
+/// Middleware rejecting requests without a valid token
+pub async fn require_token(
+    State(auth): State<AuthState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    match auth.claims(req.headers()) {
+        Some(claims) => {
+            req.extensions_mut().insert(claims);
+            next.run(req).await
+        }
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Streams the request may see, every stream without authentication
+#[derive(Debug, Clone)]
+pub struct StreamAccess(Option<Claims>);
+
+impl StreamAccess {
+    /// Access limited to the stream prefixes `streams` like a token's claim, every
+    /// stream when `None`
+    pub fn prefixes(streams: Option<Vec<String>>) -> Self {
+        Self(streams.map(|streams| Claims {
+            id: String::new(),
+            exp: 0,
+            mode: 4,
+            streams: Some(streams),
+            tenants: None,
+        }))
+    }
+
+    pub fn allows(&self, stream: &str) -> bool {
+        self.0
+            .as_ref()
+            .is_none_or(|claims| claims.allows_stream(stream))
+    }
+
+    /// Whether a recording of `stream` in `tenant` is visible, see
+    /// [`RecordingIndexEntry::tenant`](api::recorder::RecordingIndexEntry::tenant)
+    pub fn allows_recording(&self, stream: &str, tenant: Option<&str>) -> bool {
+        self.0
+            .as_ref()
+            .is_none_or(|claims| claims.allows_recording(stream, tenant))
+    }
+
+    pub fn allows_entry(&self, entry: &api::recorder::RecordingIndexEntry) -> bool {
+        self.allows_recording(&entry.stream, entry.tenant.as_deref())
+    }
+
+    /// Shared init segments hold codec setup only and are referenced by the manifests
+    /// of every stream, any token may read them. Other objects that don't parse into a
+    /// recording are denied
+    pub fn allows_object(&self, path: &str) -> bool {
+        let Some(ref claims) = self.0 else {
+            return true;
+        };
+        (storage::is_shared(path) && !path.split('/').any(|s| s == ".."))
+            || claims.allows_object(path)
+    }
+}
+
+pub fn forbidden() -> Response {
+    (StatusCode::FORBIDDEN, "stream not allowed").into_response()
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for StreamAccess {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(parts.extensions.get::<Claims>().cloned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tenant(streams: &[&str]) -> StreamAccess {
+        StreamAccess(Some(Claims {
+            id: "tenant-a".to_string(),
+            exp: 0,
+            mode: 4,
+            streams: Some(streams.iter().map(|s| s.to_string()).collect()),
+            tenants: None,
+        }))
+    }
+
+    #[test]
+    fn test_objects() {
+        let access = tenant(&["lobby"]);
+        assert!(access.allows("lobby"));
+        assert!(!access.allows("lobbyist"));
+        assert!(access.allows_object("lobby/1700000000/manifest.mpd"));
+        assert!(access.allows_object("_shared/init/3f2a.mp4"));
+        for path in [
+            "lobbyist/1700000000/manifest.mpd",
+            "_shared/../office/1700000000/manifest.mpd",
+            "lobby/1700000000/../../office/1700000000/v_seg_0001.m4s",
+            "custom/base/dir/manifest.mpd",
+            "index.json",
+        ] {
+            assert!(!access.allows_object(path), "{path}");
+        }
+
+        let open = StreamAccess(None);
+        assert!(open.allows("office"));
+        assert!(open.allows_object("custom/base/dir/manifest.mpd"));
+
+        let key = StreamAccess::prefixes(Some(vec!["lobby".to_string()]));
+        assert!(key.allows_object("lobby/1700000000/manifest.mpd"));
+        assert!(!key.allows_object("office/1700000000/manifest.mpd"));
+        assert!(StreamAccess::prefixes(None).allows_object("index.json"));
+    }
+
+    #[test]
+    fn test_tenants() {
+        let access = StreamAccess(Some(Claims {
+            id: "acme".to_string(),
+            exp: 0,
+            mode: 4,
+            streams: None,
+            tenants: Some(vec!["acme".to_string()]),
+        }));
+        assert!(access.allows_recording("acme-cam1", Some("acme")));
+        assert!(!access.allows_recording("globex-cam1", Some("globex")));
+        assert!(!access.allows_recording("lobby", None));
+        assert!(access.allows_object("acme/acme-cam1/1700000000/manifest.mpd"));
+        assert!(access.allows_object("_shared/init/3f2a.mp4"));
+        assert!(!access.allows_object("globex/globex-cam1/1700000000/manifest.mpd"));
+        assert!(StreamAccess(None).allows_recording("globex-cam1", Some("globex")));
+    }
+}
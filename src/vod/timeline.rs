@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use api::recorder::RecordingIndexEntry;
+use serde::Serialize;
+
+/// Continuous playback span built from recordings linked via `continues`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct TimelineSpan {
+    pub start_ts: i64,
+    pub end_ts: Option<i64>,
+    /// Record ids in playback order
+    pub records: Vec<String>,
+}
+
+/// Merge the entries of one stream into spans, later index lines win for the same record
+pub fn merge(entries: Vec<RecordingIndexEntry>) -> Vec<TimelineSpan> {
+    let mut latest: HashMap<String, RecordingIndexEntry> = HashMap::new();
+    for entry in entries {
+        latest.insert(entry.record.clone(), entry);
+    }
+
+    let mut ordered: Vec<RecordingIndexEntry> = latest.into_values().collect();
+    ordered.sort_by(|a, b| a.start_ts.cmp(&b.start_ts).then(a.record.cmp(&b.record)));
+
+    let mut spans: Vec<TimelineSpan> = Vec::new();
+    for entry in ordered {
+        let end_ts = entry.effective_end_ts();
+        if let Some(span) = spans.last_mut()
+            && entry.continues.is_some()
+            && span.records.last() == entry.continues.as_ref()
+        {
+            span.records.push(entry.record);
+            span.end_ts = end_ts;
+            continue;
+        }
+        spans.push(TimelineSpan {
+            start_ts: entry.start_ts,
+            end_ts,
+            records: vec![entry.record],
+        });
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::recorder::{DEFAULT_PRIORITY, RecordingStatus};
+
+    fn entry(
+        record: &str,
+        start_ts: i64,
+        end_ts: i64,
+        continues: Option<&str>,
+    ) -> RecordingIndexEntry {
+        RecordingIndexEntry {
+            uuid: String::new(),
+            record: record.to_string(),
+            stream: "cam".to_string(),
+            record_dir: format!("cam/{record}"),
+            mpd_path: format!("cam/{record}/manifest.mpd"),
+            start_ts,
+            end_ts: Some(end_ts),
+            duration_ms: None,
+            status: RecordingStatus::Completed,
+            node_alias: None,
+            updated_at: 0,
+            note: None,
+            labels: Vec::new(),
+            continues: continues.map(str::to_string),
+            media_info: Vec::new(),
+            retention_class: None,
+            trashed_at: None,
+            trashed_from: None,
+            repair_error: None,
+            source: None,
+            replicas: Vec::new(),
+            clock_skew_detected: false,
+            priority: DEFAULT_PRIORITY,
+            tenant: None,
+            size: None,
+            captions: Vec::new(),
+            trigger: None,
+            imported: false,
+            seq: 0,
+        }
+    }
+
+    #[test]
+    fn chained_parts_merge_into_one_span() {
+        let spans = merge(vec![
+            entry("1000000060", 60, 120, Some("1000000000")),
+            entry("1000000000", 0, 60, None),
+            entry("1000000500", 500, 560, None),
+        ]);
+        assert_eq!(
+            spans,
+            vec![
+                TimelineSpan {
+                    start_ts: 0,
+                    end_ts: Some(120),
+                    records: vec!["1000000000".to_string(), "1000000060".to_string()],
+                },
+                TimelineSpan {
+                    start_ts: 500,
+                    end_ts: Some(560),
+                    records: vec!["1000000500".to_string()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn dangling_continues_starts_new_span() {
+        let spans = merge(vec![entry("1000000060", 60, 120, Some("1000000000"))]);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].records, vec!["1000000060".to_string()]);
+    }
+}
@@ -0,0 +1,161 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::RootCertStore;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// HTTPS termination settings, see `[http.tls]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first
+    pub cert_path: String,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1)
+    pub key_path: String,
+    /// PEM CA bundle, when set clients must present a certificate it signed
+    #[serde(default)]
+    pub client_ca_path: Option<String>,
+    /// Additional plaintext listener, keeps HTTP clients working during a migration
+    #[serde(default)]
+    pub plaintext_listen: Option<std::net::SocketAddr>,
+    /// How often the certificate files are checked for changes
+    #[serde(default = "default_reload_interval_seconds")]
+    pub reload_interval_seconds: u64,
+}
+
+fn default_reload_interval_seconds() -> u64 {
+    10
+}
+
+impl TlsConfig {
+    fn paths(&self) -> Vec<&str> {
+        let mut paths = vec![self.cert_path.as_str(), self.key_path.as_str()];
+        paths.extend(self.client_ca_path.as_deref());
+        paths
+    }
+}
+
+/// Load the certificate, key and optional client CA into a rustls server config
+pub fn load(cfg: &TlsConfig) -> Result<RustlsConfig> {
+    Ok(RustlsConfig::from_config(Arc::new(server_config(cfg)?)))
+}
+
+fn server_config(cfg: &TlsConfig) -> Result<rustls::ServerConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let certs = CertificateDer::pem_file_iter(&cfg.cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("failed to read certificate '{}'", cfg.cert_path))?;
+    anyhow::ensure!(!certs.is_empty(), "no certificate in '{}'", cfg.cert_path);
+    let key = PrivateKeyDer::from_pem_file(&cfg.key_path)
+        .with_context(|| format!("failed to read private key '{}'", cfg.key_path))?;
+
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match cfg.client_ca_path {
+        Some(ref ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(ca_path)
+                .with_context(|| format!("failed to read client CA '{ca_path}'"))?
+            {
+                roots.add(cert.with_context(|| format!("invalid client CA '{ca_path}'"))?)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .with_context(|| format!("invalid client CA '{ca_path}'"))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder.with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// Modification times of every configured file, `None` for unreadable ones
+fn fingerprint(cfg: &TlsConfig) -> Vec<Option<SystemTime>> {
+    cfg.paths()
+        .into_iter()
+        .map(|path| Path::new(path).metadata().and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Reload `rustls` whenever one of the configured files changes.
+///
+/// A file replaced in several steps (certificate before key) may fail to load
+/// in between, the previous config stays active and the next change retries.
+pub async fn watch(cfg: TlsConfig, rustls: RustlsConfig) {
+    let mut last = fingerprint(&cfg);
+    let mut ticker = tokio::time::interval(Duration::from_secs(cfg.reload_interval_seconds.max(1)));
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let current = fingerprint(&cfg);
+        if current == last {
+            continue;
+        }
+        match server_config(&cfg) {
+            Ok(config) => {
+                rustls.reload_from_config(Arc::new(config));
+                info!("reloaded TLS certificate '{}'", cfg.cert_path);
+                last = current;
+            }
+            Err(e) => warn!("keeping previous TLS certificate: {:#}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(dir: &Path) -> TlsConfig {
+        TlsConfig {
+            cert_path: dir.join("cert.pem").to_string_lossy().into_owned(),
+            key_path: dir.join("key.pem").to_string_lossy().into_owned(),
+            client_ca_path: None,
+            plaintext_listen: None,
+            reload_interval_seconds: default_reload_interval_seconds(),
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_tracks_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = config(dir.path());
+        assert_eq!(fingerprint(&cfg), vec![None, None]);
+
+        std::fs::write(&cfg.cert_path, "a").unwrap();
+        std::fs::write(&cfg.key_path, "b").unwrap();
+        let first = fingerprint(&cfg);
+        assert!(first.iter().all(Option::is_some));
+
+        let later = SystemTime::now() + Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&cfg.cert_path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_ne!(fingerprint(&cfg), first);
+    }
+
+    #[test]
+    fn test_invalid_files_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = config(dir.path());
+        let err = server_config(&cfg).unwrap_err();
+        assert!(format!("{err:#}").contains("cert.pem"));
+
+        std::fs::write(&cfg.cert_path, "not a certificate").unwrap();
+        std::fs::write(&cfg.key_path, "not a key").unwrap();
+        assert!(server_config(&cfg).is_err());
+    }
+}
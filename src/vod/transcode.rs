@@ -0,0 +1,575 @@
+//! Browser-compatible renditions of recordings, see `[transcode]`.
+//!
+//! H.265 records fine but most browsers can't decode it. A job re-encodes a recording
+//! to H.264 and AAC with ffmpeg and writes the DASH rendition next to the original
+//! under `{record_dir}/transcode/{profile}/`, its manifest last. The rendition plays
+//! like any recording, through its own manifest.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use opendal::Operator;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+use super::preview::{self, VideoTrack};
+
+/// Directory of the renditions under a recording's `record_dir`
+pub const TRANSCODE_DIR: &str = "transcode";
+/// Written last, its presence marks a finished rendition
+const MANIFEST: &str = "manifest.mpd";
+/// Left in the scratch directory once the source is downloaded
+const FETCHED: &str = ".fetched";
+/// Left in the scratch directory once ffmpeg finished
+const ENCODED: &str = ".encoded";
+const OUTPUT_DIR: &str = "out";
+
+/// Transcode jobs, see `[transcode]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TranscodeConfig {
+    /// ffmpeg binary, built with libx264
+    #[serde(default = "default_ffmpeg")]
+    pub ffmpeg: String,
+    /// Jobs encoding at the same time across all recordings, further jobs queue
+    #[serde(default = "default_max_concurrent_jobs")]
+    pub max_concurrent_jobs: usize,
+    /// Sources and encodes of unfinished jobs. A failed or interrupted job keeps its
+    /// files here and resumes from them when requested again
+    #[serde(default = "default_scratch_dir")]
+    pub scratch_dir: String,
+    /// Jobs fail instead of starting while the scratch directory holds more, 0 is
+    /// unlimited
+    #[serde(default)]
+    pub max_scratch_bytes: u64,
+    /// Renditions by name, the `profile` of a request
+    #[serde(default = "default_profiles")]
+    pub profiles: BTreeMap<String, TranscodeProfile>,
+}
+
+impl Default for TranscodeConfig {
+    fn default() -> Self {
+        Self {
+            ffmpeg: default_ffmpeg(),
+            max_concurrent_jobs: default_max_concurrent_jobs(),
+            scratch_dir: default_scratch_dir(),
+            max_scratch_bytes: 0,
+            profiles: default_profiles(),
+        }
+    }
+}
+
+/// H.264 and AAC rendition
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TranscodeProfile {
+    /// The width follows the aspect ratio, sources below it keep their height
+    pub height: u32,
+    pub video_bitrate_kbps: u32,
+    #[serde(default = "default_audio_bitrate_kbps")]
+    pub audio_bitrate_kbps: u32,
+    #[serde(default = "default_segment_seconds")]
+    pub segment_seconds: u32,
+}
+
+fn default_ffmpeg() -> String {
+    "ffmpeg".to_string()
+}
+
+fn default_max_concurrent_jobs() -> usize {
+    1
+}
+
+fn default_scratch_dir() -> String {
+    std::env::temp_dir()
+        .join("livevod-transcode")
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn default_audio_bitrate_kbps() -> u32 {
+    128
+}
+
+fn default_segment_seconds() -> u32 {
+    4
+}
+
+fn default_profiles() -> BTreeMap<String, TranscodeProfile> {
+    [
+        ("h264_480p", 480, 1_200),
+        ("h264_720p", 720, 2_500),
+        ("h264_1080p", 1080, 5_000),
+    ]
+    .into_iter()
+    .map(|(name, height, video_bitrate_kbps)| {
+        let profile = TranscodeProfile {
+            height,
+            video_bitrate_kbps,
+            audio_bitrate_kbps: default_audio_bitrate_kbps(),
+            segment_seconds: default_segment_seconds(),
+        };
+        (name.to_string(), profile)
+    })
+    .collect()
+}
+
+/// Tracks of the recording to transcode
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub video: VideoTrack,
+    pub audio: Option<VideoTrack>,
+}
+
+/// Tracks of a manifest, `None` for audio-only recordings
+pub fn source(mpd: &str) -> Option<Source> {
+    Some(Source {
+        video: preview::video_track(mpd)?,
+        audio: preview::audio_track(mpd),
+    })
+}
+
+/// Manifest of the `profile` rendition of the recording in `record_dir`
+pub fn manifest_path(record_dir: &str, profile: &str) -> String {
+    format!("{record_dir}/{TRANSCODE_DIR}/{profile}/{MANIFEST}")
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum TranscodeStatus {
+    Queued,
+    Running,
+    Done { mpd_path: String },
+    Failed { error: String },
+}
+
+/// Transcode jobs by `stream/record/profile`, finished ones are kept so requests stay
+/// idempotent
+pub struct TranscodeJobs {
+    cfg: TranscodeConfig,
+    jobs: Mutex<HashMap<String, TranscodeStatus>>,
+    permits: Arc<Semaphore>,
+}
+
+impl TranscodeJobs {
+    pub fn new(cfg: TranscodeConfig) -> Self {
+        let permits = Arc::new(Semaphore::new(cfg.max_concurrent_jobs.max(1)));
+        Self {
+            cfg,
+            jobs: Mutex::new(HashMap::new()),
+            permits,
+        }
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&TranscodeProfile> {
+        self.cfg.profiles.get(name)
+    }
+
+    pub fn status(&self, key: &str) -> Option<TranscodeStatus> {
+        self.jobs.lock().unwrap().get(key).cloned()
+    }
+
+    /// Record a rendition found in storage from an earlier run
+    pub fn mark_done(&self, key: &str, mpd_path: String) -> TranscodeStatus {
+        let status = TranscodeStatus::Done { mpd_path };
+        self.set(key, status.clone());
+        status
+    }
+
+    /// Queue a job unless one is pending or finished, failed jobs are retried from
+    /// where they stopped
+    pub fn submit(
+        self: &Arc<Self>,
+        key: &str,
+        operator: Operator,
+        record_dir: String,
+        profile: String,
+        source: Source,
+    ) -> TranscodeStatus {
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            match jobs.get(key) {
+                Some(TranscodeStatus::Failed { .. }) | None => {
+                    jobs.insert(key.to_string(), TranscodeStatus::Queued);
+                }
+                Some(status) => return status.clone(),
+            }
+        }
+
+        let this = self.clone();
+        let key = key.to_string();
+        tokio::spawn(async move {
+            let Ok(_permit) = this.permits.clone().acquire_owned().await else {
+                return;
+            };
+            this.set(&key, TranscodeStatus::Running);
+            let status = match this
+                .generate(&operator, &record_dir, &profile, &source)
+                .await
+            {
+                Ok(mpd_path) => {
+                    info!("{} rendition of {} written to {}", profile, key, mpd_path);
+                    TranscodeStatus::Done { mpd_path }
+                }
+                Err(e) => {
+                    warn!("transcode job for {} failed: {:#}", key, e);
+                    TranscodeStatus::Failed {
+                        error: format!("{e:#}"),
+                    }
+                }
+            };
+            this.set(&key, status);
+        });
+        TranscodeStatus::Queued
+    }
+
+    fn set(&self, key: &str, status: TranscodeStatus) {
+        self.jobs.lock().unwrap().insert(key.to_string(), status);
+    }
+
+    fn scratch_path(&self, record_dir: &str, profile: &str) -> PathBuf {
+        Path::new(&self.cfg.scratch_dir)
+            .join(record_dir.trim_start_matches('/'))
+            .join(profile)
+    }
+
+    /// Download, encode and upload, each step skipped when an earlier run of the job
+    /// finished it
+    async fn generate(
+        &self,
+        operator: &Operator,
+        record_dir: &str,
+        name: &str,
+        source: &Source,
+    ) -> Result<String> {
+        let profile = self
+            .profile(name)
+            .with_context(|| format!("unknown profile {name}"))?;
+        let scratch = self.scratch_path(record_dir, name);
+        if self.cfg.max_scratch_bytes > 0 {
+            let root = PathBuf::from(&self.cfg.scratch_dir);
+            let used = tokio::task::spawn_blocking(move || dir_size(&root)).await?;
+            anyhow::ensure!(
+                used <= self.cfg.max_scratch_bytes,
+                "scratch directory {} holds {} bytes, more than max_scratch_bytes",
+                self.cfg.scratch_dir,
+                used
+            );
+        }
+        tokio::fs::create_dir_all(&scratch).await?;
+
+        if !scratch.join(FETCHED).exists() {
+            fetch(
+                operator,
+                record_dir,
+                &source.video,
+                &scratch.join("video.mp4"),
+            )
+            .await?;
+            if let Some(audio) = &source.audio {
+                fetch(operator, record_dir, audio, &scratch.join("audio.mp4")).await?;
+            }
+            tokio::fs::write(scratch.join(FETCHED), b"").await?;
+        }
+
+        let out = scratch.join(OUTPUT_DIR);
+        // Objects uploaded by an interrupted run are kept, they came from this encode
+        let resumed = scratch.join(ENCODED).exists();
+        if !resumed {
+            let _ = tokio::fs::remove_dir_all(&out).await;
+            tokio::fs::create_dir_all(&out).await?;
+            let output = tokio::process::Command::new(&self.cfg.ffmpeg)
+                .args(ffmpeg_args(profile, &scratch, source.audio.is_some()))
+                .output()
+                .await
+                .with_context(|| format!("failed to run {}", self.cfg.ffmpeg))?;
+            anyhow::ensure!(
+                output.status.success(),
+                "ffmpeg exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            tokio::fs::write(scratch.join(ENCODED), b"").await?;
+        }
+
+        let mut files = Vec::new();
+        let mut dir = tokio::fs::read_dir(&out).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let file = entry.file_name().to_string_lossy().into_owned();
+            if file != MANIFEST {
+                files.push(file);
+            }
+        }
+        files.sort();
+        anyhow::ensure!(
+            out.join(MANIFEST).exists() && !files.is_empty(),
+            "ffmpeg produced no rendition"
+        );
+        let mpd_path = manifest_path(record_dir, name);
+        let rendition_dir = &mpd_path[..mpd_path.len() - MANIFEST.len() - 1];
+        for file in files.iter().map(String::as_str).chain([MANIFEST]) {
+            let path = format!("{rendition_dir}/{file}");
+            if resumed && file != MANIFEST && operator.exists(&path).await.unwrap_or(false) {
+                continue;
+            }
+            let data = tokio::fs::read(out.join(file)).await?;
+            operator
+                .write_with(&path, data)
+                .content_type(storage::content_type_for(&path))
+                .await
+                .with_context(|| format!("failed to write {path}"))?;
+        }
+
+        if let Err(e) = tokio::fs::remove_dir_all(&scratch).await {
+            warn!("failed to remove {}: {}", scratch.display(), e);
+        }
+        Ok(mpd_path)
+    }
+}
+
+/// Write the init segment of `track` followed by its media segments to `file`, a
+/// playable fragmented MP4
+async fn fetch(
+    operator: &Operator,
+    record_dir: &str,
+    track: &VideoTrack,
+    file: &Path,
+) -> Result<()> {
+    let mut out = tokio::fs::File::create(file).await?;
+    for name in std::iter::once(&track.init).chain(&track.segments) {
+        // Deduplicated init segments are referenced as `../../_shared/...`
+        let path = storage::resolve_relative(record_dir, name);
+        let data = operator
+            .read(&path)
+            .await
+            .with_context(|| format!("failed to read {path}"))?;
+        out.write_all(&data.to_vec()).await?;
+    }
+    out.flush().await?;
+    Ok(())
+}
+
+/// Arguments encoding the sources in `scratch` to a DASH rendition in its output dir
+fn ffmpeg_args(profile: &TranscodeProfile, scratch: &Path, audio: bool) -> Vec<OsString> {
+    let mut args: Vec<OsString> = ["-hide_banner", "-loglevel", "error", "-y", "-i"]
+        .map(OsString::from)
+        .into();
+    args.push(scratch.join("video.mp4").into());
+    if audio {
+        args.push("-i".into());
+        args.push(scratch.join("audio.mp4").into());
+    }
+    args.extend(["-map", "0:v:0"].map(OsString::from));
+    if audio {
+        args.extend(["-map", "1:a:0"].map(OsString::from));
+    }
+    let video = profile.video_bitrate_kbps;
+    let segment = profile.segment_seconds.max(1);
+    args.extend(
+        [
+            "-c:v".to_string(),
+            "libx264".to_string(),
+            "-preset".to_string(),
+            "veryfast".to_string(),
+            "-pix_fmt".to_string(),
+            "yuv420p".to_string(),
+            "-vf".to_string(),
+            format!("scale=-2:'min({},ih)'", profile.height),
+            "-b:v".to_string(),
+            format!("{video}k"),
+            "-maxrate".to_string(),
+            format!("{video}k"),
+            "-bufsize".to_string(),
+            format!("{}k", video * 2),
+            // A keyframe at every segment boundary
+            "-force_key_frames".to_string(),
+            format!("expr:gte(t,n_forced*{segment})"),
+        ]
+        .map(OsString::from),
+    );
+    if audio {
+        args.extend(
+            [
+                "-c:a".to_string(),
+                "aac".to_string(),
+                "-b:a".to_string(),
+                format!("{}k", profile.audio_bitrate_kbps),
+            ]
+            .map(OsString::from),
+        );
+    }
+    args.extend(
+        [
+            "-f".to_string(),
+            "dash".to_string(),
+            "-seg_duration".to_string(),
+            segment.to_string(),
+            "-use_template".to_string(),
+            "1".to_string(),
+            "-use_timeline".to_string(),
+            "1".to_string(),
+            "-init_seg_name".to_string(),
+            "init_$RepresentationID$.m4s".to_string(),
+            "-media_seg_name".to_string(),
+            "seg_$RepresentationID$_$Number%05d$.m4s".to_string(),
+        ]
+        .map(OsString::from),
+    );
+    args.push(scratch.join(OUTPUT_DIR).join(MANIFEST).into());
+    args
+}
+
+/// Bytes of the files under `path`, 0 when it does not exist
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => dir_size(&entry.path()),
+            _ => entry.metadata().map(|m| m.len()).unwrap_or(0),
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MPD: &str = r#"<MPD><Period>
+        <AdaptationSet id="0" contentType="video">
+            <Representation id="0" mimeType="video/mp4" codecs="hev1.1.6.L93.B0" width="1920" height="1080">
+                <SegmentTemplate timescale="90000" initialization="v_init.m4s" media="v_seg_$Number%04d$.m4s" startNumber="1">
+                    <SegmentTimeline><S t="0" d="900000" r="1" /></SegmentTimeline>
+                </SegmentTemplate>
+            </Representation>
+        </AdaptationSet>
+        <AdaptationSet id="1" contentType="audio">
+            <Representation id="1" mimeType="audio/mp4">
+                <SegmentTemplate timescale="48000" initialization="a_init.m4s" media="a_seg_$Number%04d$.m4s" startNumber="1">
+                    <SegmentTimeline><S t="0" d="960000" /></SegmentTimeline>
+                </SegmentTemplate>
+            </Representation>
+        </AdaptationSet>
+    </Period></MPD>"#;
+
+    #[test]
+    fn test_ffmpeg_args() {
+        let profiles = default_profiles();
+        let profile = &profiles["h264_720p"];
+        let args: Vec<String> = ffmpeg_args(profile, Path::new("/scratch"), true)
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect();
+        let after = |flag: &str| {
+            let at = args.iter().position(|arg| arg == flag).unwrap();
+            args[at + 1].as_str()
+        };
+        assert_eq!(after("-vf"), "scale=-2:'min(720,ih)'");
+        assert_eq!(after("-b:v"), "2500k");
+        assert_eq!(after("-force_key_frames"), "expr:gte(t,n_forced*4)");
+        assert_eq!(after("-c:a"), "aac");
+        assert_eq!(args.last().unwrap(), "/scratch/out/manifest.mpd");
+        assert_eq!(args.iter().filter(|arg| *arg == "-i").count(), 2);
+
+        let silent = ffmpeg_args(profile, Path::new("/scratch"), false);
+        assert!(!silent.iter().any(|arg| arg == "-c:a" || arg == "1:a:0"));
+    }
+
+    /// An encode failing after the download resumes from the downloaded source, the
+    /// rendition lands next to the recording with its manifest and the scratch is freed
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_job_resumes_and_writes_rendition() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = dir.path().join("storage");
+        let operator = storage::create_operator(&storage::StorageConfig::Fs {
+            root: storage.to_string_lossy().into_owned(),
+        })
+        .unwrap();
+        for name in ["v_init.m4s", "v_seg_0001.m4s", "v_seg_0002.m4s"] {
+            operator
+                .write(&format!("cam/1/{name}"), name)
+                .await
+                .unwrap();
+        }
+        for name in ["a_init.m4s", "a_seg_0001.m4s"] {
+            operator
+                .write(&format!("cam/1/{name}"), name)
+                .await
+                .unwrap();
+        }
+
+        // Writes what ffmpeg's dash muxer would into the directory of its last argument
+        let ffmpeg = dir.path().join("ffmpeg");
+        let script = |body: &str| {
+            std::fs::write(&ffmpeg, format!("#!/bin/sh\n{body}\n")).unwrap();
+            std::fs::set_permissions(&ffmpeg, std::fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        script("echo 'Unknown encoder libx264' >&2; exit 1");
+        let jobs = Arc::new(TranscodeJobs::new(TranscodeConfig {
+            ffmpeg: ffmpeg.to_string_lossy().into_owned(),
+            scratch_dir: dir.path().join("scratch").to_string_lossy().into_owned(),
+            ..Default::default()
+        }));
+        let (jobs, operator, source) = (&jobs, &operator, source(MPD).unwrap());
+        let wait = || async move {
+            loop {
+                match jobs.status("cam/1/h264_720p").unwrap() {
+                    TranscodeStatus::Queued | TranscodeStatus::Running => {
+                        tokio::time::sleep(std::time::Duration::from_millis(10)).await
+                    }
+                    status => return status,
+                }
+            }
+        };
+        let submit = || {
+            jobs.submit(
+                "cam/1/h264_720p",
+                operator.clone(),
+                "cam/1".to_string(),
+                "h264_720p".to_string(),
+                source.clone(),
+            )
+        };
+
+        assert!(matches!(submit(), TranscodeStatus::Queued));
+        let TranscodeStatus::Failed { error } = wait().await else {
+            panic!("ffmpeg failed");
+        };
+        assert!(error.contains("Unknown encoder libx264"), "{error}");
+        let scratch = dir.path().join("scratch/cam/1/h264_720p");
+        assert_eq!(
+            std::fs::read_to_string(scratch.join("video.mp4")).unwrap(),
+            "v_init.m4sv_seg_0001.m4sv_seg_0002.m4s"
+        );
+
+        // Not downloaded again
+        operator.delete("cam/1/v_seg_0002.m4s").await.unwrap();
+        script(
+            r#"for last; do :; done
+out=$(dirname "$last")
+echo init > "$out/init_0.m4s"
+echo seg > "$out/seg_0_00001.m4s"
+echo mpd > "$last""#,
+        );
+        assert!(matches!(submit(), TranscodeStatus::Queued));
+        let TranscodeStatus::Done { mpd_path } = wait().await else {
+            panic!("transcode failed");
+        };
+        assert_eq!(mpd_path, "cam/1/transcode/h264_720p/manifest.mpd");
+        for name in ["init_0.m4s", "seg_0_00001.m4s", "manifest.mpd"] {
+            let path = format!("cam/1/transcode/h264_720p/{name}");
+            assert!(operator.exists(&path).await.unwrap(), "{path}");
+        }
+        assert!(!scratch.exists());
+        // Finished jobs are returned as is
+        assert!(matches!(submit(), TranscodeStatus::Done { .. }));
+    }
+}
@@ -0,0 +1,53 @@
+use axum::http::{StatusCode, Uri, header};
+use axum::response::{IntoResponse, Response};
+
+#[derive(rust_embed::RustEmbed)]
+#[folder = "assets/livevod/"]
+struct Assets;
+
+/// Built-in player UI, mounted as the router fallback when `playback.ui_enabled` is set
+pub async fn static_handler(uri: Uri) -> Response {
+    let mut path = uri.path().trim_start_matches('/');
+    if path.is_empty() {
+        path = "index.html";
+    }
+    match Assets::get(path) {
+        Some(content) => {
+            let mime = mime_guess::from_path(path).first_or_octet_stream();
+            (
+                [
+                    (header::CONTENT_TYPE, mime.as_ref()),
+                    (header::CACHE_CONTROL, cache_control(path)),
+                ],
+                content.data,
+            )
+                .into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "not found").into_response(),
+    }
+}
+
+/// Bundles under `assets/` carry a content hash in their name and never change,
+/// pages referencing them are revalidated so a new build is picked up right away
+fn cache_control(path: &str) -> &'static str {
+    if path.starts_with("assets/") {
+        "public, max-age=31536000, immutable"
+    } else if path.ends_with(".html") {
+        "no-cache"
+    } else {
+        "public, max-age=3600"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_control() {
+        assert_eq!(cache_control("index.html"), "no-cache");
+        assert_eq!(cache_control("tools/dash.html"), "no-cache");
+        assert!(cache_control("assets/index-3f2a1b.js").contains("immutable"));
+        assert_eq!(cache_control("logo.svg"), "public, max-age=3600");
+    }
+}
@@ -0,0 +1,224 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Merged config of a binary as printed by `--print-effective-config`.
+///
+/// Only `PATH` is inherited so defaults read from the environment (`LOG_LEVEL`,
+/// `PORT`, `DATABASE_URL`) are stable, `env` adds the variables under test.
+fn effective_config(bin: &str, args: &[&str], env: &[(&str, &str)]) -> String {
+    let output = Command::new(bin)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env_clear()
+        .envs(std::env::var_os("PATH").map(|p| ("PATH", p)))
+        .env("LOG_LEVEL", "info")
+        .envs(env.iter().copied())
+        .args(args)
+        .arg("--print-effective-config")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{bin} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Compare with `tests/snapshots/{name}.toml`, written when missing or with `UPDATE_SNAPSHOTS=1`
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{name}.toml"));
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() || !path.exists() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(
+        expected,
+        actual,
+        "{} changed, rerun with UPDATE_SNAPSHOTS=1 if intended",
+        path.display()
+    );
+}
+
+#[test]
+fn test_live777_effective_config() {
+    let merged = effective_config(
+        env!("CARGO_BIN_EXE_live777"),
+        &[
+            "--config",
+            "conf/live777.toml",
+            "--set",
+            "auth.secret=from-cli",
+        ],
+        &[("LIVE777__HTTP__LISTEN", "127.0.0.1:17777")],
+    );
+    assert!(merged.contains("listen = \"127.0.0.1:17777\""), "{merged}");
+    assert!(!merged.contains("from-cli"), "{merged}");
+    assert_snapshot(&format!("live777{}", feature_suffix()), &merged);
+}
+
+#[test]
+fn test_liveman_effective_config() {
+    let merged = effective_config(
+        env!("CARGO_BIN_EXE_liveman"),
+        &["--config", "conf/liveman.toml", "--set", "http.cors=true"],
+        &[("LIVEMAN__LOG__LEVEL", "warn")],
+    );
+    assert!(merged.contains("level = \"warn\""), "{merged}");
+    assert_snapshot(&format!("liveman{}", feature_suffix()), &merged);
+}
+
+#[test]
+fn test_livevod_effective_config() {
+    let merged = effective_config(
+        env!("CARGO_BIN_EXE_livevod"),
+        &[
+            "--config",
+            "conf/livevod.toml",
+            "--set",
+            "playback.max_concurrent_reads=8",
+        ],
+        &[("LIVEVOD__HTTP__LISTEN", "127.0.0.1:18899")],
+    );
+    assert!(merged.contains("max_concurrent_reads = 8"), "{merged}");
+    assert!(merged.contains("listen = \"127.0.0.1:18899\""), "{merged}");
+    assert_snapshot(&format!("livevod{}", feature_suffix()), &merged);
+}
+
+/// Optional features add config sections, keep one snapshot per feature set
+fn feature_suffix() -> String {
+    let mut suffix = String::new();
+    if cfg!(feature = "recorder") {
+        suffix.push_str(".recorder");
+    }
+    if cfg!(feature = "net4mqtt") {
+        suffix.push_str(".net4mqtt");
+    }
+    suffix
+}
+
+/// Every secret a config can hold, each a distinct marker its `Debug` output must lack
+const SECRETS: &[&str] = &[
+    "auth-secret-0",
+    "auth-token-0",
+    "auth-token-1",
+    "s3-secret-0",
+    "s3-session-0",
+    "upload-token-0",
+    "push-token-0",
+    "lease-token-0",
+    "invalidate-secret-0",
+    "turn-credential-0",
+    "node-token-0",
+    "account-password-0",
+    "coturn-secret-0",
+    "cloudflare-token-0",
+];
+
+const STORAGE: &str = r#"
+type = "s3"
+bucket = "recordings"
+access_key_id = "AKIDEXAMPLE"
+secret_access_key = "s3-secret-0"
+session_token = "s3-session-0"
+"#;
+
+/// `{:?}` of the config `toml` loads as, without the process environment
+fn debug_of<T: serde::de::DeserializeOwned + std::fmt::Debug>(name: &str, toml: &str) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(format!("{name}.toml"));
+    std::fs::write(&path, toml).unwrap();
+    let cfg: T = config_loader::Loader::new(name)
+        .path(Some(path.display().to_string()))
+        .env([])
+        .load()
+        .unwrap();
+    format!("{cfg:?}")
+}
+
+fn assert_no_secrets(debug: &str) {
+    for secret in SECRETS {
+        assert!(!debug.contains(secret), "{secret} in {debug}");
+    }
+    assert!(debug.contains("***"), "{debug}");
+}
+
+#[test]
+fn test_live777_debug_redacts_secrets() {
+    let toml = format!(
+        r#"
+[auth]
+secret = "auth-secret-0"
+tokens = ["auth-token-0", "auth-token-1"]
+
+[[ice_servers]]
+urls = ["turn:turn.example.com:3478"]
+username = "live777"
+credential = "turn-credential-0"
+
+[recorder.storage]
+{STORAGE}
+
+[recorder.upload]
+enabled = true
+liveman_url = "http://127.0.0.1:8888"
+liveman_token = "upload-token-0"
+
+[recorder.push]
+liveman_url = "http://127.0.0.1:8888"
+token = "push-token-0"
+
+[recorder.lease]
+liveman_url = "http://127.0.0.1:8888"
+token = "lease-token-0"
+
+[recorder.invalidate]
+urls = ["http://127.0.0.1:8899"]
+secret = "invalidate-secret-0"
+"#
+    );
+    let debug = debug_of::<liveion::config::Config>("live777", &toml);
+    assert_no_secrets(&debug);
+    assert!(debug.contains("turn:turn.example.com:3478"), "{debug}");
+}
+
+#[test]
+fn test_liveman_debug_redacts_secrets() {
+    let toml = format!(
+        r#"
+[auth]
+secret = "auth-secret-0"
+tokens = ["auth-token-0", "auth-token-1"]
+
+[[auth.accounts]]
+username = "admin"
+password = "account-password-0"
+
+[[nodes]]
+alias = "edge-1"
+token = "node-token-0"
+url = "http://127.0.0.1:7777"
+
+[extra_ice.coturn]
+urls = ["turn:turn.example.com:3478"]
+secret = "coturn-secret-0"
+
+[extra_ice.cloudflare]
+key_id = "key"
+api_token = "cloudflare-token-0"
+
+[recorder.storage]
+{STORAGE}
+
+[recorder.invalidate]
+urls = ["http://127.0.0.1:8899"]
+secret = "invalidate-secret-0"
+"#
+    );
+    let debug = debug_of::<liveman::config::Config>("liveman", &toml);
+    assert_no_secrets(&debug);
+    assert!(debug.contains("edge-1"), "{debug}");
+}
@@ -0,0 +1,598 @@
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Child, Command};
+
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use aws_sdk_s3::primitives::ByteStream;
+use reqwest::Method;
+use tokio::time::{Duration, sleep};
+
+const BUCKET: &str = "recordings";
+const MANIFEST: &str = "lobby/1718200000/manifest.mpd";
+const INTERNAL_SECRET: &str = "internal-secret";
+
+/// livevod, killed on drop
+struct Livevod(Child);
+
+impl Drop for Livevod {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> SocketAddr {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// livevod with `config` written to `conf`, once it listens on every one of `addrs`
+async fn spawn(conf: &Path, config: &str, addrs: &[SocketAddr]) -> Livevod {
+    let path = conf.join("livevod.toml");
+    std::fs::write(&path, config).unwrap();
+    let child = Command::new(env!("CARGO_BIN_EXE_livevod"))
+        .arg("--config")
+        .arg(&path)
+        .env("LOG_LEVEL", "info")
+        .spawn()
+        .unwrap();
+    let livevod = Livevod(child);
+    for _ in 0..100 {
+        if addrs.iter().all(|addr| TcpStream::connect(addr).is_ok()) {
+            return livevod;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    panic!("livevod never listened on {addrs:?}");
+}
+
+/// livevod serving `root`, with the addresses of its S3 gateway and of its HTTP API
+async fn spawn_livevod(conf: &Path, root: &Path) -> (Livevod, SocketAddr, SocketAddr) {
+    let s3 = free_port();
+    let http = free_port();
+    let livevod = spawn(
+        conf,
+        &format!(
+            r#"index_path = "{root}/index.json"
+
+[http]
+listen = "{http}"
+
+[playback]
+manifest_cache_seconds = 60
+
+[internal]
+secret = "{INTERNAL_SECRET}"
+
+[storage]
+type = "fs"
+root = "{root}"
+
+[s3]
+listen = "{s3}"
+bucket = "{BUCKET}"
+
+[[s3.keys]]
+access_key_id = "LOBBYKEY"
+secret_access_key = "lobby-secret"
+streams = ["lobby"]
+
+[[s3.keys]]
+access_key_id = "OPERATORKEY"
+secret_access_key = "operator-secret"
+"#,
+            root = root.display(),
+        ),
+        &[s3, http],
+    )
+    .await;
+    (livevod, s3, http)
+}
+
+fn client(addr: SocketAddr, access_key_id: &str, secret_access_key: &str) -> aws_sdk_s3::Client {
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .endpoint_url(format!("http://{addr}"))
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new(
+            access_key_id,
+            secret_access_key,
+            None,
+            None,
+            "livevod-test",
+        ))
+        .force_path_style(true)
+        .build();
+    aws_sdk_s3::Client::from_conf(config)
+}
+
+async fn keys(client: &aws_sdk_s3::Client, delimiter: Option<&str>) -> (Vec<String>, Vec<String>) {
+    let list = client
+        .list_objects_v2()
+        .bucket(BUCKET)
+        .set_delimiter(delimiter.map(str::to_string))
+        .send()
+        .await
+        .unwrap();
+    let keys = list
+        .contents()
+        .iter()
+        .filter_map(|o| o.key().map(str::to_string))
+        .collect();
+    let prefixes = list
+        .common_prefixes()
+        .iter()
+        .filter_map(|p| p.prefix().map(str::to_string))
+        .collect();
+    (keys, prefixes)
+}
+
+#[tokio::test]
+async fn test_s3_gateway_lists_and_reads_a_tenants_recordings() {
+    let storage = tempfile::tempdir().unwrap();
+    for (path, body) in [
+        (MANIFEST, "<MPD />"),
+        ("lobby/1718200000/v_seg_0001.m4s", "segment-1"),
+        ("office/1718200001/manifest.mpd", "<MPD />"),
+    ] {
+        let path = storage.path().join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, body).unwrap();
+    }
+    let conf = tempfile::tempdir().unwrap();
+    let (_livevod, addr, _) = spawn_livevod(conf.path(), storage.path()).await;
+
+    // A tenant key only sees its streams
+    let lobby = client(addr, "LOBBYKEY", "lobby-secret");
+    let (keys_seen, _) = keys(&lobby, None).await;
+    assert_eq!(keys_seen, [MANIFEST, "lobby/1718200000/v_seg_0001.m4s"]);
+    let (keys_seen, prefixes) = keys(&lobby, Some("/")).await;
+    assert!(keys_seen.is_empty());
+    assert_eq!(prefixes, ["lobby/"]);
+
+    let object = lobby
+        .get_object()
+        .bucket(BUCKET)
+        .key(MANIFEST)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(object.content_length(), Some(7));
+    let body = object.body.collect().await.unwrap().into_bytes();
+    assert_eq!(&body[..], b"<MPD />");
+
+    let range = lobby
+        .get_object()
+        .bucket(BUCKET)
+        .key("lobby/1718200000/v_seg_0001.m4s")
+        .range("bytes=8-")
+        .send()
+        .await
+        .unwrap();
+    let body = range.body.collect().await.unwrap().into_bytes();
+    assert_eq!(&body[..], b"1");
+
+    let denied = lobby
+        .get_object()
+        .bucket(BUCKET)
+        .key("office/1718200001/manifest.mpd")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(denied.raw_response().unwrap().status().as_u16(), 403);
+
+    // Read-only
+    let put = lobby
+        .put_object()
+        .bucket(BUCKET)
+        .key("lobby/1718200000/v_seg_0002.m4s")
+        .body(ByteStream::from_static(b"segment-2"))
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(put.raw_response().unwrap().status().as_u16(), 403);
+    assert!(
+        !storage
+            .path()
+            .join("lobby/1718200000/v_seg_0002.m4s")
+            .exists()
+    );
+
+    // The operator key sees everything
+    let operator = client(addr, "OPERATORKEY", "operator-secret");
+    let (_, prefixes) = keys(&operator, Some("/")).await;
+    assert_eq!(prefixes, ["lobby/", "office/"]);
+
+    let forged = client(addr, "LOBBYKEY", "operator-secret");
+    let err = forged
+        .list_objects_v2()
+        .bucket(BUCKET)
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.raw_response().unwrap().status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn test_reserved_characters_in_stream_names_round_trip() {
+    let storage = tempfile::tempdir().unwrap();
+    let streams = ["📷 lobby", "50%", "a+b", "door#2", "cafe\u{301}"];
+    let mut index = String::new();
+    for (n, stream) in streams.iter().enumerate() {
+        // As the recorder stores them: NFC, not encoded
+        let record = 1_718_200_000 + n as i64;
+        let dir = storage::record_dir(None, stream, record);
+        std::fs::create_dir_all(storage.path().join(&dir)).unwrap();
+        std::fs::write(storage.path().join(&dir).join("manifest.mpd"), "<MPD />").unwrap();
+        std::fs::write(storage.path().join(&dir).join("v_seg_0001.m4s"), *stream).unwrap();
+        let entry = serde_json::json!({
+            "record": record.to_string(),
+            "stream": stream,
+            "record_dir": dir,
+            "mpd_path": format!("{dir}/manifest.mpd"),
+            "start_ts": record * 1_000_000,
+            "end_ts": (record + 60) * 1_000_000,
+            "duration_ms": 60_000,
+            "status": "Completed",
+            "node_alias": null,
+            "updated_at": record * 1_000_000,
+        });
+        index.push_str(&format!("{entry}\n"));
+    }
+    std::fs::write(storage.path().join("index.json"), index).unwrap();
+    let conf = tempfile::tempdir().unwrap();
+    let (_livevod, _, http) = spawn_livevod(conf.path(), storage.path()).await;
+
+    for stream in streams {
+        let res = reqwest::get(format!(
+            "http://{http}/api/playback/{}",
+            storage::encode_key_for_url(stream)
+        ))
+        .await
+        .unwrap();
+        assert_eq!(res.status(), http::StatusCode::OK, "{stream}");
+        let records: Vec<api::recorder::RecordingIndexEntry> = res.json().await.unwrap();
+        assert_eq!(records.len(), 1, "{stream}");
+
+        // The listed keys play back once encoded, and only that way
+        let mpd = format!("http://{http}/api/record/object/");
+        let res = reqwest::get(format!(
+            "{mpd}{}",
+            storage::encode_key_for_url(&records[0].mpd_path)
+        ))
+        .await
+        .unwrap();
+        assert_eq!(res.status(), http::StatusCode::OK, "{stream}");
+        assert_eq!(res.text().await.unwrap(), "<MPD />");
+        let segment = format!("{}/v_seg_0001.m4s", records[0].record_dir);
+        let res = reqwest::get(format!("{mpd}{}", storage::encode_key_for_url(&segment)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), http::StatusCode::OK, "{stream}");
+        assert_eq!(res.text().await.unwrap(), stream);
+    }
+
+    // Decomposed and composed spellings reach the same object, a double encoding doesn't
+    let decomposed = format!(
+        "http://{http}/api/record/object/cafe%CC%81/{}/v_seg_0001.m4s",
+        1_718_200_004
+    );
+    assert_eq!(
+        reqwest::get(decomposed).await.unwrap().status(),
+        http::StatusCode::OK
+    );
+    let twice = format!(
+        "http://{http}/api/record/object/50%2525/{}/v_seg_0001.m4s",
+        1_718_200_001
+    );
+    assert_eq!(
+        reqwest::get(twice).await.unwrap().status(),
+        http::StatusCode::NOT_FOUND
+    );
+    let invalid = format!("http://{http}/api/record/object/50%zz/1/v_seg_0001.m4s");
+    assert_eq!(
+        reqwest::get(invalid).await.unwrap().status(),
+        http::StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+async fn test_invalidation_replaces_a_cached_manifest() {
+    let storage = tempfile::tempdir().unwrap();
+    let path = storage.path().join(MANIFEST);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, "<MPD id=\"1\" />").unwrap();
+    let conf = tempfile::tempdir().unwrap();
+    let (_livevod, _, http) = spawn_livevod(conf.path(), storage.path()).await;
+    let url = format!("http://{http}/api/record/object/{MANIFEST}");
+    let get = || async { reqwest::get(&url).await.unwrap().text().await.unwrap() };
+
+    assert_eq!(get().await, "<MPD id=\"1\" />");
+    // Finalized meanwhile, the cached copy is still served
+    std::fs::write(&path, "<MPD id=\"2\" />").unwrap();
+    assert_eq!(get().await, "<MPD id=\"1\" />");
+
+    let notice = format!("http://{http}{}", storage::invalidate::INVALIDATE_PATH);
+    let body = serde_json::to_vec(&storage::InvalidateRequest {
+        keys: vec![MANIFEST.to_string()],
+    })
+    .unwrap();
+    let now = chrono::Utc::now().timestamp();
+    let client = reqwest::Client::new();
+    for signature in [
+        None,
+        Some(storage::invalidate::sign("wrong", now, &body)),
+        Some(storage::invalidate::sign(
+            INTERNAL_SECRET,
+            now - 3600,
+            &body,
+        )),
+    ] {
+        let mut req = client.post(&notice).body(body.clone());
+        if let Some(signature) = signature {
+            req = req.header(storage::invalidate::SIGNATURE_HEADER, signature);
+        }
+        let res = req.send().await.unwrap();
+        assert_eq!(res.status(), http::StatusCode::UNAUTHORIZED);
+    }
+    assert_eq!(get().await, "<MPD id=\"1\" />");
+
+    let res = client
+        .post(&notice)
+        .header(
+            storage::invalidate::SIGNATURE_HEADER,
+            storage::invalidate::sign(INTERNAL_SECRET, now, &body),
+        )
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), http::StatusCode::OK);
+    let evicted: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(evicted["evicted"], 1);
+    assert_eq!(get().await, "<MPD id=\"2\" />");
+
+    // As a recorder sends them, several rewrites batched into one notice
+    let invalidator = storage::Invalidator::spawn(storage::InvalidateConfig {
+        urls: vec![format!("http://{http}")],
+        secret: INTERNAL_SECRET.into(),
+        debounce_ms: 50,
+    })
+    .unwrap();
+    std::fs::write(&path, "<MPD id=\"3\" />").unwrap();
+    invalidator.notify(MANIFEST);
+    invalidator.notify(MANIFEST);
+    for _ in 0..50 {
+        if get().await == "<MPD id=\"3\" />" {
+            return;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    panic!("the notice never reached livevod");
+}
+
+#[tokio::test]
+async fn test_malformed_names_are_refused_before_lookup() {
+    let storage = tempfile::tempdir().unwrap();
+    std::fs::write(storage.path().join("index.json"), "").unwrap();
+    let conf = tempfile::tempdir().unwrap();
+    let (_livevod, _, http) = spawn_livevod(conf.path(), storage.path()).await;
+
+    // As one percent-encoded segment, with the rule broken. A whole `.` or `..` segment
+    // is resolved by the client
+    let malformed = [
+        ("x".repeat(10_000), "too_long"),
+        ("cam%0A1".to_string(), "control_character"),
+        ("cam%00".to_string(), "control_character"),
+        ("cam%5C1".to_string(), "backslash"),
+        ("a%2E%2Eb".to_string(), "dot_segment"),
+        ("%2E%2E%2Fetc".to_string(), "dot_segment"),
+    ];
+    // Streams may hold tenant segments, records never
+    let streams = malformed.iter().cloned().chain([
+        ("%2Flobby".to_string(), "empty_segment"),
+        ("acme%2F%2Flobby".to_string(), "empty_segment"),
+    ]);
+    let records = malformed
+        .iter()
+        .cloned()
+        .chain([("acme%2Flobby".to_string(), "separator")]);
+
+    let client = reqwest::Client::new();
+    let refused = |method: Method, path: String, field: &'static str, rule: &'static str| {
+        let req = client.request(method.clone(), format!("http://{http}{path}"));
+        async move {
+            let res = req.send().await.unwrap();
+            assert_eq!(
+                res.status(),
+                http::StatusCode::BAD_REQUEST,
+                "{method} {path:.80}"
+            );
+            let body = res.text().await.unwrap();
+            match serde_json::from_str(&body) {
+                Ok(api::recorder::RecorderError::Validation {
+                    field: Some(f),
+                    message,
+                }) if f == field && message.contains(rule) => {}
+                _ => panic!("{method} {path:.80}: expected {field} {rule}, got {body}"),
+            }
+        }
+    };
+
+    for (stream, rule) in streams {
+        for (method, path) in [
+            (Method::GET, format!("/api/playback/{stream}")),
+            (
+                Method::GET,
+                format!("/api/playback/{stream}/at?ts=1718200000"),
+            ),
+            (Method::GET, format!("/api/playback/{stream}/timeline")),
+            (
+                Method::GET,
+                format!("/api/record/clip/{stream}/1718200000.mpd"),
+            ),
+            (Method::GET, format!("/api/record/size/{stream}/1718200000")),
+            (
+                Method::GET,
+                format!("/api/record/previews/{stream}/1718200000"),
+            ),
+            (
+                Method::POST,
+                format!("/api/record/previews/{stream}/1718200000"),
+            ),
+        ] {
+            refused(method, path, "stream", rule).await;
+        }
+    }
+    for (record, rule) in records {
+        for (method, path) in [
+            (Method::GET, format!("/api/record/clip/lobby/{record}.mpd")),
+            (Method::GET, format!("/api/record/size/lobby/{record}")),
+            (Method::GET, format!("/api/record/previews/lobby/{record}")),
+            (Method::POST, format!("/api/record/previews/lobby/{record}")),
+        ] {
+            refused(method, path, "record", rule).await;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_local_recordings_play_without_storage() {
+    let local = tempfile::tempdir().unwrap();
+    let mpd = r#"<?xml version="1.0" encoding="utf-8"?>
+<MPD type="static" mediaPresentationDuration="PT20.000S" minBufferTime="PT6.000S">
+    <Period id="0" start="PT0.0S">
+        <AdaptationSet id="0" contentType="video">
+            <Representation id="0" mimeType="video/mp4">
+                <SegmentTemplate timescale="90000" initialization="v_init.m4s" media="v_seg_$Number%04d$.m4s" startNumber="1">
+                    <SegmentTimeline>
+                        <S t="0" d="900000" r="1" />
+                    </SegmentTimeline>
+                </SegmentTemplate>
+            </Representation>
+        </AdaptationSet>
+    </Period>
+</MPD>
+"#;
+    for (path, body) in [
+        (MANIFEST, mpd),
+        ("lobby/1718200000/v_init.m4s", "init"),
+        ("lobby/1718200000/v_seg_0001.m4s", "segment-1"),
+        ("lobby/1718200000/v_seg_0002.m4s", "segment-2"),
+    ] {
+        let path = local.path().join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, body).unwrap();
+    }
+    let entry = serde_json::json!({
+        "record": "1718200000",
+        "stream": "lobby",
+        "record_dir": "lobby/1718200000",
+        "mpd_path": MANIFEST,
+        "start_ts": 1_718_200_000_000_000i64,
+        "end_ts": 1_718_200_020_000_000i64,
+        "duration_ms": 20_000,
+        "status": "Completed",
+        "node_alias": null,
+        "updated_at": 1_718_200_020_000_000i64,
+    });
+    std::fs::write(local.path().join("index.json"), format!("{entry}\n")).unwrap();
+
+    // No [storage] at all, only the recorder's local directory
+    let conf = tempfile::tempdir().unwrap();
+    let http = free_port();
+    let _livevod = spawn(
+        conf.path(),
+        &format!(
+            r#"index_path = "{root}/index.json"
+
+[http]
+listen = "{http}"
+
+[playback]
+local_fallback_dir = "{root}"
+"#,
+            root = local.path().display(),
+        ),
+        &[http],
+    )
+    .await;
+    let base = format!("http://{http}/api");
+    let get = |path: String| reqwest::get(format!("{base}{path}"));
+
+    let streams: serde_json::Value = get("/playback".into()).await.unwrap().json().await.unwrap();
+    assert_eq!(streams[0]["stream"], "lobby");
+    assert_eq!(streams[0]["recordings"], 1);
+    let records: Vec<api::recorder::RecordingIndexEntry> = get("/playback/lobby".into())
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 1);
+
+    let res = get(format!("/record/object/{}", records[0].mpd_path))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), http::StatusCode::OK);
+    assert_eq!(res.text().await.unwrap(), mpd);
+    let res = reqwest::Client::new()
+        .get(format!(
+            "{base}/record/object/lobby/1718200000/v_seg_0002.m4s"
+        ))
+        .header(http::header::RANGE, "bytes=8-")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), http::StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.text().await.unwrap(), "2");
+    let res = get("/record/object/lobby/1718200000/v_seg_0003.m4s".into())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), http::StatusCode::NOT_FOUND);
+
+    // Manifests are parsed from the directory too
+    let res = get("/record/clip/lobby/1718200000.mpd?from_ms=12000&to_ms=15000".into())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), http::StatusCode::OK);
+    let clip = res.text().await.unwrap();
+    assert!(
+        clip.contains("<BaseURL>../../object/lobby/1718200000/</BaseURL>"),
+        "{clip}"
+    );
+    assert!(clip.contains(r#"startNumber="2""#), "{clip}");
+    let size: serde_json::Value = get("/record/size/lobby/1718200000".into())
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(size["bytes"], 18);
+    assert_eq!(size["segments"], 2);
+}
+
+#[test]
+fn test_config_without_storage_needs_a_local_dir() {
+    let conf = tempfile::tempdir().unwrap();
+    for (config, error) in [
+        ("", "configure [storage] or playback.local_fallback_dir"),
+        (
+            "[playback]\nlocal_fallback_dir = \"./recordings\"\nsigned_redirect = true\n",
+            "playback.signed_redirect needs [storage]",
+        ),
+    ] {
+        let path = conf.path().join("livevod.toml");
+        std::fs::write(&path, config).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_livevod"))
+            .arg("--config")
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "{config}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(error), "{config}: {stderr}");
+    }
+}
@@ -0,0 +1,128 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use api::recorder::RecorderError;
+use reqwest::Method;
+use tokio::net::TcpListener;
+
+mod common;
+use common::shutdown_signal;
+
+/// Malformed names as one percent-encoded path segment, with the rule they break
+fn malformed() -> Vec<(String, &'static str)> {
+    vec![
+        ("x".repeat(10_000), "too_long"),
+        ("cam%0A1".to_string(), "control_character"),
+        ("cam%00".to_string(), "control_character"),
+        ("%1B%5B31m".to_string(), "control_character"),
+        ("cam%5C1".to_string(), "backslash"),
+        // A whole `.` or `..` segment is resolved by the client, see the api tests
+        ("a%2E%2Eb".to_string(), "dot_segment"),
+        ("%2E%2E%2Fetc".to_string(), "dot_segment"),
+    ]
+}
+
+async fn listener() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+        .await
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    (listener, addr)
+}
+
+/// `method url` is refused with 400 naming `rule` on `field`
+async fn assert_refused(method: Method, url: &str, field: &str, rule: &str) {
+    let res = reqwest::Client::new()
+        .request(method.clone(), url)
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        res.status(),
+        http::StatusCode::BAD_REQUEST,
+        "{method} {url:.80}"
+    );
+    let body = res.text().await.unwrap();
+    match serde_json::from_str(&body) {
+        Ok(RecorderError::Validation {
+            field: Some(f),
+            message,
+        }) if f == field && message.contains(rule) => {}
+        _ => panic!("{method} {url:.80}: expected {field} {rule}, got {body}"),
+    }
+}
+
+/// Each route with malformed `{stream}`s, then with malformed `{record}`s
+async fn assert_routes_refuse(
+    addr: SocketAddr,
+    streams: &[(String, &str)],
+    routes: &[(Method, fn(&str, &str) -> String)],
+) {
+    for (method, path) in routes {
+        for (name, rule) in streams {
+            let url = format!("http://{addr}{}", path(name, "1760486400"));
+            assert_refused(method.clone(), &url, "stream", rule).await;
+        }
+        // Routes without a `{record}` ignore the one passed
+        if path("cam", "{record}").contains("{record}") {
+            let mut records = malformed();
+            records.push(("acme%2Fcam".to_string(), "separator"));
+            for (name, rule) in &records {
+                let url = format!("http://{addr}{}", path("cam", name));
+                assert_refused(method.clone(), &url, "record", rule).await;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_liveion_refuses_malformed_names() {
+    let (listener, addr) = listener().await;
+    tokio::spawn(liveion::serve(
+        liveion::config::Config::default(),
+        listener,
+        shutdown_signal(),
+    ));
+
+    let routes: &[(Method, fn(&str, &str) -> String)] = &[
+        (Method::POST, |s, _| api::path::record(s)),
+        (Method::GET, |s, _| api::path::record(s)),
+        (Method::DELETE, |s, _| api::path::record(s)),
+        (Method::PATCH, api::path::record_entry),
+        (Method::DELETE, api::path::record_entry),
+        (Method::POST, api::path::record_restore),
+        (Method::PUT, |s, r| api::path::record_captions(s, r, "en")),
+        (Method::POST, api::path::record_repair),
+        (Method::GET, |s, _| api::path::recorder_stream_health(s)),
+        (Method::GET, |s, _| api::path::recorder_stream_stats(s)),
+        (Method::GET, api::path::recorder_verify),
+    ];
+    // Streams are single segments without tenancy
+    let mut streams = malformed();
+    streams.push(("acme%2Fcam".to_string(), "separator"));
+    streams.push(("%2Facme".to_string(), "separator"));
+    assert_routes_refuse(addr, &streams, routes).await;
+}
+
+#[tokio::test]
+async fn test_liveman_refuses_malformed_names() {
+    let (listener, addr) = listener().await;
+    let mut cfg = liveman::config::Config::default();
+    cfg.database.url = "sqlite::memory:".to_string();
+    cfg.database.max_connections = 1;
+    tokio::spawn(liveman::serve(cfg, listener, shutdown_signal()));
+
+    let routes: &[(Method, fn(&str, &str) -> String)] = &[
+        (Method::GET, |s, _| format!("/api/playback/{s}")),
+        (Method::POST, |s, _| api::path::record(s)),
+        (Method::GET, |s, _| api::path::record(s)),
+        (Method::DELETE, |s, _| api::path::record(s)),
+        (Method::DELETE, api::path::record_entry),
+        (Method::POST, api::path::record_restore),
+    ];
+    // Nodes may record with tenancy, `/` only separates non-empty segments
+    let mut streams = malformed();
+    streams.push(("%2Facme".to_string(), "empty_segment"));
+    streams.push(("acme%2F%2Fcam".to_string(), "empty_segment"));
+    assert_routes_refuse(addr, &streams, routes).await;
+}
@@ -1,2 +1,181 @@
 #![cfg(feature = "recorder")]
-// Recorder integration tests removed in S3-only mode.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+
+use api::recorder::{PullRecordingsResponse, RecordingSession, RecordingStatus};
+use tokio::net::TcpListener;
+use tokio::time::{Duration, sleep};
+
+mod common;
+use common::shutdown_signal;
+
+const STREAM: &str = "cascade";
+
+async fn serve(cfg: liveion::config::Config) -> SocketAddr {
+    let listener = TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+        .await
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(liveion::serve(cfg, listener, shutdown_signal()));
+    addr
+}
+
+async fn stream(addr: SocketAddr) -> Option<api::response::Stream> {
+    let res = reqwest::get(format!("http://{addr}{}", api::path::streams("")))
+        .await
+        .unwrap();
+    assert_eq!(http::StatusCode::OK, res.status());
+    res.json::<Vec<api::response::Stream>>()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|s| s.id == STREAM)
+}
+
+/// Wait for the stream's publisher on `addr` to connect, or to leave when `connected` is false
+async fn wait_publish(addr: SocketAddr, connected: bool) {
+    for _ in 0..300 {
+        let up = stream(addr).await.is_some_and(|s| {
+            s.publish
+                .sessions
+                .iter()
+                .any(|s| s.state == api::response::RTCPeerConnectionState::Connected)
+        });
+        if up == connected {
+            return;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    panic!("publisher of {STREAM} on {addr} never became connected={connected}");
+}
+
+async fn recordings(addr: SocketAddr) -> Vec<RecordingSession> {
+    let res = reqwest::get(format!(
+        "http://{addr}{}?stream={STREAM}&limit=100",
+        api::path::recordings()
+    ))
+    .await
+    .unwrap();
+    assert_eq!(http::StatusCode::OK, res.status());
+    res.json::<PullRecordingsResponse>().await.unwrap().sessions
+}
+
+async fn cascade_pull(puller: SocketAddr, source_url: &str) {
+    let res = reqwest::Client::new()
+        .post(format!("http://{puller}{}", api::path::cascade(STREAM)))
+        .json(&serde_json::json!({ "source_url": source_url }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(http::StatusCode::OK, res.status());
+    wait_publish(puller, true).await;
+}
+
+/// Drop the pull from the source's side, as a restarting upstream would
+async fn drop_pull(source: SocketAddr, puller: SocketAddr) {
+    let sessions = stream(source).await.unwrap().subscribe.sessions;
+    for session in sessions {
+        let res = reqwest::Client::new()
+            .delete(format!(
+                "http://{source}{}",
+                api::path::session(STREAM, &session.id)
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(http::StatusCode::NO_CONTENT, res.status());
+    }
+    wait_publish(puller, false).await;
+}
+
+/// The manifest and every segment it lists are in storage
+fn assert_playable(root: &Path, session: &RecordingSession) {
+    let mpd = std::fs::read_to_string(root.join(&session.mpd_path)).unwrap();
+    let segments = mpd.matches("<S ").count();
+    assert!(segments > 0, "{mpd}");
+    let dir = root.join(session.mpd_path.trim_end_matches("manifest.mpd"));
+    assert!(dir.join("v_init.m4s").exists());
+    for n in 1..=segments {
+        assert!(
+            dir.join(format!("v_seg_{n:04}.m4s")).exists(),
+            "{n} of {mpd}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_record_cascade_pull_across_reconnects() {
+    let source = serve(liveion::config::Config::default()).await;
+
+    let storage = tempfile::tempdir().unwrap();
+    let mut cfg = liveion::config::Config::default();
+    cfg.recorder.auto_streams = vec!["*".to_string()];
+    cfg.recorder.storage = storage::StorageConfig::Fs {
+        root: storage.path().to_str().unwrap().to_string(),
+    };
+    cfg.recorder.index_path = Some(storage.path().join("index.json").display().to_string());
+    cfg.recorder.namespace_keys = false;
+    cfg.recorder.reconnect_grace_seconds = 10;
+    let puller = serve(cfg).await;
+
+    let sdp = tempfile::tempdir().unwrap();
+    let sdp_file = sdp.path().join("whip.sdp").display().to_string();
+    let vcodec = "-profile:v baseline -level 3.0 -pix_fmt yuv420p -g 30 -keyint_min 30 -preset ultrafast -tune zerolatency";
+    tokio::spawn(livetwo::whip::into(
+        sdp_file.clone(),
+        format!("http://{source}{}", api::path::whip(STREAM)),
+        None,
+        Some(format!(
+            "ffmpeg -re -f lavfi -i testsrc=size=640x480:rate=30 -vcodec libx264 {vcodec} -f rtp 'rtp://{}' -sdp_file {sdp_file}",
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 5110)
+        )),
+    ));
+    wait_publish(source, true).await;
+
+    // Auto-record fires on the pull like on a local publisher
+    let source_url = format!("http://{source}{}", api::path::whep(STREAM));
+    cascade_pull(puller, &source_url).await;
+    sleep(Duration::from_secs(3)).await;
+    let sessions = recordings(puller).await;
+    assert_eq!(sessions.len(), 1);
+    let first = sessions[0].clone();
+    assert!(matches!(first.status, RecordingStatus::Active));
+    assert_eq!(first.source.as_deref(), Some(source_url.as_str()));
+
+    // Back within the grace, the same recording goes on
+    drop_pull(source, puller).await;
+    cascade_pull(puller, &source_url).await;
+    sleep(Duration::from_secs(3)).await;
+    let sessions = recordings(puller).await;
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].id, first.id);
+    assert!(matches!(sessions[0].status, RecordingStatus::Active));
+
+    // Back after the grace, a new recording continues the first
+    drop_pull(source, puller).await;
+    sleep(Duration::from_secs(12)).await;
+    let sessions = recordings(puller).await;
+    assert_eq!(sessions.len(), 1);
+    assert!(matches!(sessions[0].status, RecordingStatus::Completed));
+    cascade_pull(puller, &source_url).await;
+    sleep(Duration::from_secs(3)).await;
+
+    let res = reqwest::Client::new()
+        .delete(format!("http://{puller}{}", api::path::record(STREAM)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(http::StatusCode::OK, res.status());
+
+    let mut sessions = recordings(puller).await;
+    sessions.sort_by_key(|s| s.start_ts);
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0].id, first.id);
+    assert_eq!(sessions[1].continues, first.id);
+    for session in &sessions {
+        assert!(matches!(session.status, RecordingStatus::Completed));
+        assert_eq!(session.source.as_deref(), Some(source_url.as_str()));
+        assert_playable(storage.path(), session);
+    }
+}
@@ -3,6 +3,7 @@ import QueryStringAddon from 'wretch/addons/queryString';
 
 import { type Stream } from '../shared/api';
 import { makeAuthorizationMiddleware } from '../shared/authorization-middleware';
+import { readJsonLines } from '../shared/jsonl';
 
 const authMiddleware = makeAuthorizationMiddleware();
 
@@ -89,7 +90,7 @@ export interface RecordingSession {
     end_ts: number | null;
     duration_ms: number | null;
     mpd_path: string;
-    status: 'Active' | 'Completed' | 'Failed' | 'Acked';
+    status: 'Active' | 'Completed' | 'Failed' | 'Acked' | 'Missing' | 'Interrupted' | 'Trashed';
 }
 
 export interface RecordingSessionsResponse {
@@ -107,13 +108,26 @@ export interface RecordingSessionQuery {
 }
 
 export function getSegmentUrl(path: string) {
-    // Use encodeURI so that "/" remains as path separators (server expects wildcard path)
-    return `/api/record/object/${encodeURI(path)}`;
+    // Each segment encoded on its own so "/" remains a separator (server expects a wildcard path)
+    // while "#", "?", "+" and "%" in stream names stay part of the key
+    return `/api/record/object/${path.split('/').map(encodeURIComponent).join('/')}`;
 }
 
 export interface RecordingIndexEntry {
     record: string;
     mpd_path: string;
+    status?: RecordingSession['status'];
+    retention_class?: string;
+    /** Upload and quota deletion order on its node, higher goes first and is deleted last */
+    priority?: number;
+    /** Alias of the node that recorded it */
+    node?: string;
+    /** UNIX microseconds, only listed with `include_trashed` */
+    trashed_at?: number;
+    /** UNIX microseconds the recording was archived after its node left the cluster */
+    archived_at?: number;
+    /** The wall clock stepped while recording, `duration_ms` is the reliable length */
+    clock_skew_detected?: boolean;
 }
 
 export function getRecordingIndexStreams() {
@@ -123,3 +137,9 @@ export function getRecordingIndexStreams() {
 export function getRecordingIndexByStream(stream: string) {
     return w.url(`/api/playback/${encodeURIComponent(stream)}`).get().json<RecordingIndexEntry[]>();
 }
+
+/** Like `getRecordingIndexByStream`, yielding recordings as the server reads them */
+export async function* streamRecordingIndexByStream(stream: string) {
+    const res = await w.url(`/api/playback/${encodeURIComponent(stream)}`).query({ format: 'jsonl' }).get().res();
+    yield* readJsonLines<RecordingIndexEntry>(res);
+}
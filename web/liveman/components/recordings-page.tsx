@@ -190,11 +190,15 @@ export function RecordingsPage() {
                         {list.map(e => (
                             <div key={ e.record } className="border border-base-200 rounded-lg p-3 flex flex-col gap-2">
                                 <div className="flex items-center justify-between">
-                                    <span className="font-medium">{ formatDateTime(e.record)}</span>
+                                    <span className="font-medium flex items-center gap-2">
+                                        { formatDateTime(e.record)}
+                                        {e.status === 'Missing' && <Badge color="error" size="sm">Missing</Badge>}
+                                        {e.retention_class && <Badge color="ghost" size="sm" title="Retention class">{e.retention_class}</Badge>}
+                                    </span>
                                     <span className="text-xs opacity-70 font-mono truncate" title={e.mpd_path}>{getFileName(e.mpd_path)}</span>
                                 </div>
                                 <div className="flex items-center gap-2">
-                                    <Button size="sm" color="primary" className="flex-1" onClick={() => playMpd(e.mpd_path)}>
+                                    <Button size="sm" color="primary" className="flex-1" disabled={e.status === 'Missing'} onClick={() => playMpd(e.mpd_path)}>
                                         <Play className="w-4 h-4" />
                                         Play
                                     </Button>
@@ -0,0 +1,55 @@
+import wretch from 'wretch';
+import QueryStringAddon from 'wretch/addons/queryString';
+
+import { makeAuthorizationMiddleware } from '@/shared/authorization-middleware';
+
+const authMiddleware = makeAuthorizationMiddleware();
+
+const w = wretch().addon(QueryStringAddon).middlewares([authMiddleware]);
+
+export const setAuthToken = authMiddleware.setAuthorization;
+
+export interface StreamSummary {
+    stream: string;
+    recordings: number;
+    latest_start_ts: number;
+    total_duration_ms: number;
+}
+
+export interface RecordingIndexEntry {
+    record: string;
+    stream: string;
+    mpd_path: string;
+    /** UNIX microseconds */
+    start_ts: number;
+    end_ts?: number;
+    duration_ms?: number;
+    status: 'Active' | 'Completed' | 'Failed' | 'Acked' | 'Missing' | 'Interrupted' | 'Trashed';
+    note?: string;
+    labels?: string[];
+    /** Upload and quota deletion order, higher goes first and is deleted last */
+    priority?: number;
+    /** The wall clock stepped while recording, `duration_ms` is the reliable length */
+    clock_skew_detected?: boolean;
+    /** WHEP URL the stream was cascade-pulled from */
+    source?: string;
+}
+
+export function getStreams(sort: 'name' | 'latest' = 'latest') {
+    return w.url('/api/playback').query({ sort }).get().json<StreamSummary[]>();
+}
+
+export function getRecordings(stream: string, limit = 100) {
+    return w
+        .url(`/api/playback/${encodeURIComponent(stream)}`)
+        .query({ order: 'desc', limit })
+        .get()
+        .json<RecordingIndexEntry[]>();
+}
+
+/** Player page for `mpdPath`, passing the token on so segment requests are authorized too */
+export function playerUrl(mpdPath: string, token: string) {
+    const params = new URLSearchParams({ mpd: mpdPath });
+    if (token) params.set('token', token);
+    return `/tools/dash.html?${params}`;
+}
@@ -0,0 +1,127 @@
+import { useCallback, useEffect, useMemo, useState } from 'preact/hooks';
+import { Badge, Button, Card, Loading } from 'react-daisyui';
+import { Play, RefreshCw } from 'lucide-react';
+
+import * as api from './api';
+
+function formatDateTime(micros: number): string {
+    const date = new Date(micros / 1000);
+    const pad = (n: number) => String(n).padStart(2, '0');
+    return `${date.getFullYear()}-${pad(date.getMonth() + 1)}-${pad(date.getDate())} ${pad(date.getHours())}:${pad(date.getMinutes())}:${pad(date.getSeconds())}`;
+}
+
+function formatDuration(ms?: number): string {
+    if (ms === undefined) return '-';
+    const s = Math.max(0, Math.floor(ms / 1000));
+    const pad = (n: number) => String(n).padStart(2, '0');
+    return `${pad(Math.floor(s / 3600))}:${pad(Math.floor((s % 3600) / 60))}:${pad(s % 60)}`;
+}
+
+export function Livevod() {
+    // `?token=` is sent as a bearer token and handed on to the player
+    const token = useMemo(() => new URLSearchParams(location.search).get('token') ?? '', []);
+    const [streams, setStreams] = useState<api.StreamSummary[]>([]);
+    const [selected, setSelected] = useState('');
+    const [recordings, setRecordings] = useState<api.RecordingIndexEntry[]>([]);
+    const [loading, setLoading] = useState(true);
+    const [error, setError] = useState('');
+
+    useEffect(() => {
+        if (token) api.setAuthToken(`Bearer ${token}`);
+    }, [token]);
+
+    const fetchStreams = useCallback(async () => {
+        try {
+            setLoading(true);
+            setError('');
+            const res = await api.getStreams();
+            setStreams(res);
+            setSelected(current => current || (res[0]?.stream ?? ''));
+        } catch {
+            setError('Failed to fetch streams');
+        } finally {
+            setLoading(false);
+        }
+    }, []);
+
+    useEffect(() => {
+        fetchStreams();
+    }, [fetchStreams]);
+
+    useEffect(() => {
+        if (!selected) return;
+        api.getRecordings(selected)
+            .then(setRecordings)
+            .catch(() => setError(`Failed to fetch recordings of ${selected}`));
+    }, [selected]);
+
+    return (
+        <div className="container mx-auto p-4 flex flex-col gap-4">
+            <div className="flex items-center justify-between">
+                <h1 className="text-2xl font-bold">LiveVOD</h1>
+                <Button size="sm" onClick={fetchStreams} disabled={loading}>
+                    <RefreshCw size={16} />
+                    Refresh
+                </Button>
+            </div>
+            {error && <div className="alert alert-error">{error}</div>}
+            {loading && <Loading />}
+            <div className="flex flex-col md:flex-row gap-4">
+                <Card className="md:w-64 shrink-0">
+                    <Card.Body className="p-2">
+                        <ul className="menu">
+                            {streams.map(s => (
+                                <li key={s.stream}>
+                                    <a
+                                        className={s.stream === selected ? 'active' : ''}
+                                        onClick={() => setSelected(s.stream)}
+                                    >
+                                        <span className="truncate">{s.stream}</span>
+                                        <Badge size="sm">{s.recordings}</Badge>
+                                    </a>
+                                </li>
+                            ))}
+                        </ul>
+                        {!loading && streams.length === 0 && <p className="p-2 opacity-70">No recordings yet</p>}
+                    </Card.Body>
+                </Card>
+                <Card className="grow">
+                    <Card.Body className="p-2 overflow-x-auto">
+                        <table className="table table-sm">
+                            <thead>
+                                <tr>
+                                    <th>Record</th>
+                                    <th>Start</th>
+                                    <th>Duration</th>
+                                    <th>Status</th>
+                                    <th></th>
+                                </tr>
+                            </thead>
+                            <tbody>
+                                {recordings.map(r => (
+                                    <tr key={r.record}>
+                                        <td className="font-mono">{r.record}</td>
+                                        <td>{formatDateTime(r.start_ts)}</td>
+                                        <td>{formatDuration(r.duration_ms)}</td>
+                                        <td><Badge size="sm">{r.status}</Badge></td>
+                                        <td>
+                                            <a
+                                                className="btn btn-xs btn-primary"
+                                                href={api.playerUrl(r.mpd_path, token)}
+                                                target="_blank"
+                                                rel="noreferrer"
+                                            >
+                                                <Play size={12} />
+                                                Play
+                                            </a>
+                                        </td>
+                                    </tr>
+                                ))}
+                            </tbody>
+                        </table>
+                    </Card.Body>
+                </Card>
+            </div>
+        </div>
+    );
+}
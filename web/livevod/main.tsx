@@ -0,0 +1,7 @@
+import { render } from 'preact';
+
+import '@/shared/tailwind.css';
+
+import { Livevod } from './livevod';
+
+render(<Livevod />, document.getElementById('app')!);
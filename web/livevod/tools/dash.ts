@@ -0,0 +1,3 @@
+import '../../shared/tools/dash-player/main.tsx';
+
+
@@ -0,0 +1,27 @@
+import { resolve } from 'node:path';
+
+import { defineConfig, mergeConfig } from 'vite';
+
+import CommonConfig, { ProjectRoot } from '../vite.config';
+
+// directory name of the current module (web/livevod)
+const configDir = import.meta.dirname;
+
+// https://vitejs.dev/config/
+export default mergeConfig(CommonConfig, defineConfig({
+    root: configDir,
+    server: {
+        proxy: {
+            '^/api/.*': 'http://localhost:8899',
+        },
+    },
+    build: {
+        outDir: resolve(ProjectRoot, 'assets/livevod'),
+        rollupOptions: {
+            input: {
+                index: resolve(configDir, 'index.html'),
+                dash: resolve(configDir, 'tools/dash.html'),
+            }
+        }
+    }
+}), /* isRoot = */ false);
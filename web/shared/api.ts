@@ -39,6 +39,11 @@ export interface Stream {
         leaveAt: number;
         sessions: Session[];
     };
+    /** absent when the node is built without the recorder */
+    recording?: boolean;
+    recordId?: string;
+    recordingSinceTs?: number;
+    segmentsWritten?: number;
 }
 
 export interface Session {
@@ -0,0 +1,31 @@
+/**
+ * Reads a JSON Lines (`application/x-ndjson`) body one item at a time.
+ * A listing that failed midway ends with an `{"error": ...}` line, which is thrown.
+ */
+export async function* readJsonLines<T>(res: Response): AsyncGenerator<T> {
+    if (!res.body) return;
+    const reader = res.body.pipeThrough(new TextDecoderStream()).getReader();
+    let pending = '';
+    const parse = (line: string) => {
+        const item = JSON.parse(line);
+        if (item && typeof item === 'object' && !Array.isArray(item) && Object.keys(item).length === 1 && typeof item.error === 'string') {
+            throw new Error(item.error);
+        }
+        return item as T;
+    };
+    try {
+        for (;;) {
+            const { done, value } = await reader.read();
+            if (done) break;
+            pending += value;
+            const lines = pending.split('\n');
+            pending = lines.pop() ?? '';
+            for (const line of lines) {
+                if (line) yield parse(line);
+            }
+        }
+        if (pending) yield parse(pending);
+    } finally {
+        reader.releaseLock();
+    }
+}
@@ -16,7 +16,8 @@ const workspaceContentRoots = [
 const defaultAppContentRoots = [
     'web/liveion',
     'web/liveman',
-    'web/livecam'
+    'web/livecam',
+    'web/livevod'
 ];
 
 const packageName = process.env.npm_package_name;